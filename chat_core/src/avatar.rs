@@ -0,0 +1,108 @@
+//! Avatars for users who haven't set one: their initials on a color picked
+//! from their MXID, so the same user looks the same everywhere and in every
+//! release.
+
+use crate::{hash, localpart};
+
+/// Backgrounds for generated avatars, as RGB. A user's color is a hash of
+/// their MXID modulo the length, so changing the list recolors everyone.
+pub const PALETTE: [(u8, u8, u8); 8] = [
+    (0x58, 0x65, 0xf2), // blurple
+    (0x3b, 0xa5, 0x5d), // green
+    (0xfa, 0xa6, 0x1a), // amber
+    (0xed, 0x42, 0x45), // red
+    (0xeb, 0x45, 0x9e), // pink
+    (0x9b, 0x59, 0xb6), // purple
+    (0x1a, 0xbc, 0x9c), // teal
+    (0xe6, 0x7e, 0x22), // orange
+];
+
+/// The background of `user_id`'s generated avatar.
+pub fn color(user_id: &str) -> (u8, u8, u8) {
+    PALETTE[hash::fnv1a_32(user_id) as usize % PALETTE.len()]
+}
+
+/// `c` in uppercase, unless that takes more than one letter, as "ß" would.
+fn upper(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+/// The first letter or digit of the first and last of `words`, uppercase.
+fn initials_of<'a>(words: impl Iterator<Item = &'a str>) -> String {
+    let letters: Vec<char> = words
+        .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+        .collect();
+    let picked = match letters.as_slice() {
+        [] => return String::new(),
+        [only] => vec![*only],
+        [first, .., last] => vec![*first, *last],
+    };
+    picked.into_iter().map(upper).collect()
+}
+
+/// Up to two letters for `user_id`'s generated avatar: from the first and
+/// last words of `display_name`, skipping emoji and other symbols, else
+/// from the localpart, e.g. "JS" for "john.smith". "?" when neither has a
+/// letter or digit.
+pub fn initials(display_name: &str, user_id: &str) -> String {
+    let from_name = initials_of(display_name.split_whitespace());
+    if !from_name.is_empty() {
+        return from_name;
+    }
+    let from_id = initials_of(
+        localpart(user_id)
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty()),
+    );
+    if from_id.is_empty() {
+        "?".to_string()
+    } else {
+        from_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_are_pinned() {
+        // Changing these recolors users between releases.
+        assert_eq!(hash::fnv1a_32("@alice:example.org"), 0xf7d6ffc5);
+        assert_eq!(color("@alice:example.org"), (0x9b, 0x59, 0xb6));
+        assert_eq!(color("@bob:example.org"), (0xfa, 0xa6, 0x1a));
+        assert_eq!(color("@carol:matrix.org"), (0xe6, 0x7e, 0x22));
+        assert_eq!(color(""), (0x9b, 0x59, 0xb6));
+    }
+
+    #[test]
+    fn test_colors_spread_over_the_palette() {
+        let mut used = [0; PALETTE.len()];
+        for i in 0..800 {
+            let (r, g, b) = color(&format!("@user{}:example.org", i));
+            let index = PALETTE.iter().position(|&c| c == (r, g, b)).unwrap();
+            used[index] += 1;
+        }
+        assert!(used.iter().all(|&n| n > 50), "{:?}", used);
+    }
+
+    #[test]
+    fn test_initials() {
+        assert_eq!(initials("Alice", "@alice:x"), "A");
+        assert_eq!(initials("alice cooper", "@alice:x"), "AC");
+        assert_eq!(initials("Mary Jane Watson", "@mj:x"), "MW");
+        assert_eq!(initials("  Bob  ", "@bob:x"), "B");
+        // Emoji and symbols are skipped.
+        assert_eq!(initials("🎮 Gamer Joe", "@joe:x"), "GJ");
+        assert_eq!(initials("🔥blaze", "@b:x"), "B");
+        assert_eq!(initials("Élodie ßtrauss", "@e:x"), "Éß");
+        // Nothing to go on in the name: the localpart.
+        assert_eq!(initials("", "@john.smith:x"), "JS");
+        assert_eq!(initials("🔥🔥", "@zed:x"), "Z");
+        assert_eq!(initials("", "@___:x"), "?");
+    }
+}
@@ -0,0 +1,215 @@
+//! Messages relayed into a room by a bridge's bot, with their real author
+//! written into the body, e.g. "[irc] <alice> hello". They are shown as
+//! the author's, with the name taken off the body. Bridges that give each
+//! remote user a ghost account of their own, as the mautrix ones do, need
+//! none of this: their messages already come from the author.
+//!
+//! Only two formats are read, and only from senders that look like bots,
+//! so that text of our own in angle brackets is left as it was written:
+//!
+//! - matterbridge's default, `[protocol] <nick> body`, in which the nick
+//!   may have spaces, as Discord names do;
+//! - the IRC style of Heisenbridge and matterbridge without a protocol,
+//!   `<nick> body`, the nick perhaps behind a channel mode such as `@`.
+
+use crate::mention::escape_html;
+use crate::{localpart, Message, MessageType};
+
+/// Longest nick read; longer is more likely a sentence in brackets.
+const MAX_NICK: usize = 64;
+
+/// A relayed message, as its author sent it.
+#[derive(Debug, PartialEq)]
+pub struct Relayed<'a> {
+    pub author: &'a str,
+    /// Where it was relayed from, e.g. "irc", when the bridge says.
+    pub network: Option<&'a str>,
+    pub body: &'a str,
+    /// The part of the relayed body that named the author.
+    prefix: &'a str,
+}
+
+/// Whether `user_id` looks like a bridge's bot, e.g. `@matterbridge:…` or
+/// `@heisenbridge:…`, rather than a person.
+pub fn is_relay_bot(user_id: &str) -> bool {
+    let name = localpart(user_id).to_lowercase();
+    name.contains("bridge") || name.contains("relay") || name.ends_with("bot")
+}
+
+/// `[protocol] `, and what follows it.
+fn network(body: &str) -> Option<(&str, &str)> {
+    let rest = body.strip_prefix('[')?;
+    let end = rest.find(']')?;
+    let network = &rest[..end];
+    let valid = !network.is_empty()
+        && network.len() <= 20
+        && network
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+    let rest = rest[end + 1..].strip_prefix(' ')?;
+    valid.then_some((network, rest))
+}
+
+/// `<nick> `, and what follows it. An IRC nick has no spaces, and its
+/// channel mode is dropped; after a protocol, spaces are allowed.
+fn nick(text: &str, spaces: bool) -> Option<(&str, &str)> {
+    let rest = text.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let nick = &rest[..end];
+    let body = rest[end + 1..].strip_prefix(' ')?;
+    let nick = if spaces {
+        nick.trim()
+    } else {
+        nick.trim_start_matches(['@', '+', '%', '~', '&'])
+    };
+    let valid = !nick.is_empty()
+        && nick.chars().count() <= MAX_NICK
+        && !nick.contains('<')
+        && (spaces || !nick.contains(char::is_whitespace))
+        // Not a tag or a link, e.g. "<b>" quoted, or "</b>".
+        && !nick.starts_with('/')
+        && !nick.contains(['=', ':', '"'])
+        && nick.chars().any(char::is_alphanumeric);
+    (valid && !body.trim().is_empty()).then_some((nick, body))
+}
+
+/// Read the author out of a relayed `body`, if it names one.
+pub fn parse(body: &str) -> Option<Relayed<'_>> {
+    let (network, text) = match network(body) {
+        Some((network, rest)) => (Some(network), rest),
+        None => (None, body),
+    };
+    let (author, rest) = nick(text, network.is_some())?;
+    Some(Relayed {
+        author,
+        network,
+        body: rest,
+        prefix: &body[..body.len() - rest.len()],
+    })
+}
+
+/// `message` as its author sent it, when a bridge's bot relayed it:
+/// named after them, without their name in the body. The HTML loses the
+/// name too, or is dropped when it doesn't start with it.
+pub fn attribute(message: &Message) -> Option<Message> {
+    if message.schema != MessageType::Text || !is_relay_bot(&message.sender) {
+        return None;
+    }
+    let relayed = parse(&message.content)?;
+    let mut escaped = String::new();
+    escape_html(relayed.prefix, &mut escaped);
+    let formatted = message
+        .formatted
+        .as_deref()
+        .and_then(|html| html.strip_prefix(escaped.as_str()))
+        .map(str::to_string);
+    Some(Message {
+        sender_name: Some(relayed.author.to_string()),
+        content: relayed.body.to_string(),
+        formatted,
+        ..message.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Relayed bodies, and the author and body each should be read as.
+    const RELAYED: &[(&str, &str, Option<&str>, &str)] = &[
+        // matterbridge
+        ("[irc] <alice> hello", "alice", Some("irc"), "hello"),
+        (
+            "[discord] <Bob The Great> gg wp",
+            "Bob The Great",
+            Some("discord"),
+            "gg wp",
+        ),
+        (
+            "[telegram] <carol> <3 see you\nsecond line",
+            "carol",
+            Some("telegram"),
+            "<3 see you\nsecond line",
+        ),
+        // Heisenbridge, and matterbridge without the protocol
+        (
+            "<dave> anyone up for a raid?",
+            "dave",
+            None,
+            "anyone up for a raid?",
+        ),
+        ("<@op> welcome to #lobby", "op", None, "welcome to #lobby"),
+        ("<+voiced> <b> is bold", "voiced", None, "<b> is bold"),
+    ];
+
+    /// Bodies that only look relayed, left as written.
+    const NOT_RELAYED: &[&str] = &[
+        "<b>bold</b> text",
+        "<3 you all",
+        "<https://example.org> is down",
+        "</b> closes it",
+        "<a href=\"x\"> link",
+        "<two words> no protocol",
+        "<alice>",
+        "<alice> ",
+        "< > nothing",
+        "[not a protocol] <alice> hi",
+        "[irc]<alice> hi",
+        "x < y > z",
+        "plain text",
+    ];
+
+    fn message(sender: &str, body: &str) -> Message {
+        Message {
+            id: "$1".to_string(),
+            sender: sender.to_string(),
+            content: body.to_string(),
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn test_relayed_formats() {
+        for &(body, author, network, text) in RELAYED {
+            let relayed = parse(body).unwrap_or_else(|| panic!("not read: {:?}", body));
+            assert_eq!(
+                (relayed.author, relayed.network, relayed.body),
+                (author, network, text),
+                "{:?}",
+                body
+            );
+        }
+        for body in NOT_RELAYED {
+            assert_eq!(parse(body), None, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn test_only_bots_are_attributed() {
+        assert!(is_relay_bot("@matterbridge:example.org"));
+        assert!(is_relay_bot("@heisenbridge:example.org"));
+        assert!(is_relay_bot("@IRCBot:example.org"));
+        // A mautrix ghost is already its author.
+        assert!(!is_relay_bot("@discord_123456:example.org"));
+        assert!(!is_relay_bot("@alice:example.org"));
+
+        let mut relayed = message("@matterbridge:example.org", "[irc] <alice> <i>hi</i>");
+        relayed.formatted = Some("[irc] &lt;alice&gt; <em>hi</em>".to_string());
+        let shown = attribute(&relayed).unwrap();
+        assert_eq!(shown.sender_display(), "alice");
+        assert_eq!(shown.sender, "@matterbridge:example.org");
+        assert_eq!(shown.content, "<i>hi</i>");
+        assert_eq!(shown.formatted.as_deref(), Some("<em>hi</em>"));
+
+        relayed.formatted = Some("<strong>alice</strong>: <em>hi</em>".to_string());
+        assert_eq!(attribute(&relayed).unwrap().formatted, None);
+
+        assert_eq!(
+            attribute(&message("@alice:example.org", "<bob> said hi")),
+            None
+        );
+        let mut emote = message("@heisenbridge:example.org", "<alice> waves");
+        emote.schema = MessageType::Emote;
+        assert_eq!(attribute(&emote), None);
+    }
+}
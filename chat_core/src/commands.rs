@@ -0,0 +1,260 @@
+//! Slash commands typed into the composer, e.g. "/me waves" or
+//! "/invite @bob:example.org". A message starting with "//" is sent as
+//! text, with one slash taken off.
+
+use thiserror::Error;
+
+/// Appended by `/shrug`.
+pub const SHRUG: &str = r"¯\_(ツ)_/¯";
+
+/// The commands there are, with what they take, for the help text.
+pub const USAGE: [&str; 6] = [
+    "/me <action>",
+    "/shrug [message]",
+    "/join <#alias:server>",
+    "/msg <@user:server> <message>",
+    "/topic <topic>",
+    "/invite <@user:server>",
+];
+
+/// What a composer message asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Send as is: not a command, or escaped with "//".
+    Say(String),
+    /// Send as an emote, e.g. "* alice waves".
+    Me(String),
+    Join(String),
+    Msg {
+        user_id: String,
+        text: String,
+    },
+    Topic(String),
+    Invite(String),
+}
+
+/// Why a command can't be run. Shown in place of sending it.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CommandError {
+    #[error("/{0} isn't a command. Start with // to send it as a message.")]
+    Unknown(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("{0} isn't a user id, like @name:server")]
+    NotAUser(String),
+    #[error("{0} isn't a room, like #alias:server")]
+    NotARoom(String),
+    #[error("A quote isn't closed")]
+    UnclosedQuote,
+}
+
+/// The first argument of `rest`, which may be in double quotes with `\"`
+/// inside, and what follows it, trimmed.
+fn split_arg(rest: &str) -> Result<(String, &str), CommandError> {
+    let rest = rest.trim_start();
+    let Some(quoted) = rest.strip_prefix('"') else {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        return Ok((rest[..end].to_string(), rest[end..].trim()));
+    };
+    let mut arg = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((arg, quoted[i + 1..].trim())),
+            '\\' => match chars.next() {
+                Some((_, escaped)) => arg.push(escaped),
+                None => break,
+            },
+            c => arg.push(c),
+        }
+    }
+    Err(CommandError::UnclosedQuote)
+}
+
+/// The single argument of a command, which must be there.
+fn one_arg(rest: &str, usage: &'static str) -> Result<String, CommandError> {
+    let (arg, more) = split_arg(rest)?;
+    if arg.is_empty() || !more.is_empty() {
+        return Err(CommandError::Usage(usage));
+    }
+    Ok(arg)
+}
+
+fn user_id(arg: String) -> Result<String, CommandError> {
+    match arg.strip_prefix('@').and_then(|id| id.split_once(':')) {
+        Some((name, server)) if !name.is_empty() && !server.is_empty() => Ok(arg),
+        _ => Err(CommandError::NotAUser(arg)),
+    }
+}
+
+fn room(arg: String) -> Result<String, CommandError> {
+    let valid = arg.starts_with(['#', '!'])
+        && arg[1..]
+            .split_once(':')
+            .is_some_and(|(name, server)| !name.is_empty() && !server.is_empty());
+    if valid {
+        Ok(arg)
+    } else {
+        Err(CommandError::NotARoom(arg))
+    }
+}
+
+/// What `text`, as typed in the composer, asks for.
+pub fn parse(text: &str) -> Result<Command, CommandError> {
+    let text = text.trim();
+    let Some(command) = text.strip_prefix('/') else {
+        return Ok(Command::Say(text.to_string()));
+    };
+    if command.starts_with('/') {
+        return Ok(Command::Say(command.to_string()));
+    }
+    let (name, rest) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    let rest = rest.trim();
+    match name.to_lowercase().as_str() {
+        "me" if rest.is_empty() => Err(CommandError::Usage(USAGE[0])),
+        "me" => Ok(Command::Me(rest.to_string())),
+        "shrug" if rest.is_empty() => Ok(Command::Say(SHRUG.to_string())),
+        "shrug" => Ok(Command::Say(format!("{} {}", rest, SHRUG))),
+        "join" => Ok(Command::Join(room(one_arg(rest, USAGE[2])?)?)),
+        "msg" => {
+            let (user, text) = split_arg(rest)?;
+            if user.is_empty() || text.is_empty() {
+                return Err(CommandError::Usage(USAGE[3]));
+            }
+            Ok(Command::Msg {
+                user_id: user_id(user)?,
+                text: text.to_string(),
+            })
+        }
+        "topic" if rest.is_empty() => Err(CommandError::Usage(USAGE[4])),
+        "topic" => Ok(Command::Topic(rest.to_string())),
+        "invite" => Ok(Command::Invite(user_id(one_arg(rest, USAGE[5])?)?)),
+        _ => Err(CommandError::Unknown(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_and_escapes() {
+        assert_eq!(parse("hello"), Ok(Command::Say("hello".to_string())));
+        assert_eq!(
+            parse("//shrug is a command"),
+            Ok(Command::Say("/shrug is a command".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_me() {
+        assert_eq!(parse("/me waves"), Ok(Command::Me("waves".to_string())));
+        assert_eq!(parse("/ME  waves "), Ok(Command::Me("waves".to_string())));
+        assert_eq!(parse("/me"), Err(CommandError::Usage("/me <action>")));
+    }
+
+    #[test]
+    fn test_shrug() {
+        assert_eq!(parse("/shrug"), Ok(Command::Say(SHRUG.to_string())));
+        assert_eq!(
+            parse("/shrug no idea"),
+            Ok(Command::Say(format!("no idea {}", SHRUG)))
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(
+            parse("/join #games:example.org"),
+            Ok(Command::Join("#games:example.org".to_string()))
+        );
+        assert_eq!(
+            parse("/join !abc:example.org"),
+            Ok(Command::Join("!abc:example.org".to_string()))
+        );
+        assert_eq!(
+            parse("/join"),
+            Err(CommandError::Usage("/join <#alias:server>"))
+        );
+        assert_eq!(
+            parse("/join games"),
+            Err(CommandError::NotARoom("games".to_string()))
+        );
+        assert_eq!(
+            parse("/join #a:x #b:x"),
+            Err(CommandError::Usage("/join <#alias:server>"))
+        );
+    }
+
+    #[test]
+    fn test_msg() {
+        assert_eq!(
+            parse("/msg @bob:example.org see you at 8"),
+            Ok(Command::Msg {
+                user_id: "@bob:example.org".to_string(),
+                text: "see you at 8".to_string(),
+            })
+        );
+        assert_eq!(
+            parse(r#"/msg "@bob:example.org" "quoted" stays"#),
+            Ok(Command::Msg {
+                user_id: "@bob:example.org".to_string(),
+                text: r#""quoted" stays"#.to_string(),
+            })
+        );
+        assert_eq!(
+            parse("/msg @bob:example.org"),
+            Err(CommandError::Usage("/msg <@user:server> <message>"))
+        );
+        assert_eq!(
+            parse("/msg bob hi"),
+            Err(CommandError::NotAUser("bob".to_string()))
+        );
+        assert_eq!(
+            parse(r#"/msg "@bob:example.org hi"#),
+            Err(CommandError::UnclosedQuote)
+        );
+    }
+
+    #[test]
+    fn test_topic() {
+        assert_eq!(
+            parse("/topic Raid night: Friday 8pm"),
+            Ok(Command::Topic("Raid night: Friday 8pm".to_string()))
+        );
+        assert_eq!(
+            parse("/topic  "),
+            Err(CommandError::Usage("/topic <topic>"))
+        );
+    }
+
+    #[test]
+    fn test_invite() {
+        assert_eq!(
+            parse(r#"/invite "@bob:example.org""#),
+            Ok(Command::Invite("@bob:example.org".to_string()))
+        );
+        assert_eq!(
+            parse("/invite"),
+            Err(CommandError::Usage("/invite <@user:server>"))
+        );
+        assert_eq!(
+            parse("/invite @bob"),
+            Err(CommandError::NotAUser("@bob".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_unknown_commands() {
+        assert_eq!(
+            parse("/usr/bin/env"),
+            Err(CommandError::Unknown("usr/bin/env".to_string()))
+        );
+        assert_eq!(
+            CommandError::Unknown("dance".to_string()).to_string(),
+            "/dance isn't a command. Start with // to send it as a message."
+        );
+    }
+}
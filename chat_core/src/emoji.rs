@@ -0,0 +1,637 @@
+//! Emoji for the composer and reactions: a table searchable by shortcode,
+//! `:shortcode:` completion at the caret, skin tones, and usage counts that
+//! put the user's favourites first.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Characters typed after ':' before completions are offered.
+pub const MIN_QUERY_LEN: usize = 2;
+
+/// Emoji kept in the recently used list.
+const RECENT_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    People,
+    Nature,
+    Food,
+    Activities,
+    Travel,
+    Objects,
+    Symbols,
+}
+
+impl Category {
+    pub const ALL: [Category; 7] = [
+        Category::People,
+        Category::Nature,
+        Category::Food,
+        Category::Activities,
+        Category::Travel,
+        Category::Objects,
+        Category::Symbols,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::People => "People",
+            Category::Nature => "Nature",
+            Category::Food => "Food & Drink",
+            Category::Activities => "Activities",
+            Category::Travel => "Travel & Places",
+            Category::Objects => "Objects",
+            Category::Symbols => "Symbols",
+        }
+    }
+
+    /// The emoji shown on the category's tab.
+    pub fn icon(self) -> &'static str {
+        match self {
+            Category::People => "😀",
+            Category::Nature => "🐻",
+            Category::Food => "🍔",
+            Category::Activities => "⚽",
+            Category::Travel => "🚗",
+            Category::Objects => "💡",
+            Category::Symbols => "❤️",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Emoji {
+    pub emoji: &'static str,
+    /// Without the colons, e.g. "thumbsup".
+    pub shortcode: &'static str,
+    /// Other words it is found by.
+    pub keywords: &'static [&'static str],
+    pub category: Category,
+    /// Takes a skin tone modifier.
+    pub toned: bool,
+}
+
+const fn emoji(
+    emoji: &'static str,
+    shortcode: &'static str,
+    keywords: &'static [&'static str],
+    category: Category,
+) -> Emoji {
+    Emoji {
+        emoji,
+        shortcode,
+        keywords,
+        category,
+        toned: false,
+    }
+}
+
+const fn toned(
+    emoji: &'static str,
+    shortcode: &'static str,
+    keywords: &'static [&'static str],
+) -> Emoji {
+    Emoji {
+        emoji,
+        shortcode,
+        keywords,
+        category: Category::People,
+        toned: true,
+    }
+}
+
+use Category::*;
+
+/// Every emoji offered, in picker order.
+pub static EMOJI: &[Emoji] = &[
+    emoji("😀", "grinning", &["happy", "smile"], People),
+    emoji("😃", "smiley", &["happy", "joy"], People),
+    emoji("😄", "smile", &["happy", "laugh"], People),
+    emoji("😁", "grin", &["happy", "teeth"], People),
+    emoji("😆", "laughing", &["happy", "lol"], People),
+    emoji("😅", "sweat_smile", &["relief", "nervous"], People),
+    emoji("🤣", "rofl", &["lol", "laugh", "floor"], People),
+    emoji("😂", "joy", &["lol", "tears", "laugh"], People),
+    emoji("🙂", "slight_smile", &["smile"], People),
+    emoji("🙃", "upside_down", &["silly", "sarcasm"], People),
+    emoji("😉", "wink", &["flirt"], People),
+    emoji("😊", "blush", &["smile", "happy"], People),
+    emoji("😇", "innocent", &["angel", "halo"], People),
+    emoji("🥰", "smiling_face_with_hearts", &["love", "crush"], People),
+    emoji("😍", "heart_eyes", &["love", "crush"], People),
+    emoji("🤩", "star_struck", &["wow", "stars"], People),
+    emoji("😘", "kissing_heart", &["kiss", "love"], People),
+    emoji("😋", "yum", &["tasty", "delicious"], People),
+    emoji("😛", "stuck_out_tongue", &["tongue", "silly"], People),
+    emoji(
+        "😜",
+        "stuck_out_tongue_winking_eye",
+        &["tongue", "silly"],
+        People,
+    ),
+    emoji("🤪", "zany_face", &["crazy", "silly"], People),
+    emoji("🤗", "hugging", &["hug"], People),
+    emoji("🤔", "thinking", &["hmm", "think"], People),
+    emoji("🤐", "zipper_mouth", &["secret", "quiet"], People),
+    emoji("🤨", "raised_eyebrow", &["skeptical", "doubt"], People),
+    emoji("😐", "neutral_face", &["meh"], People),
+    emoji("😑", "expressionless", &["blank"], People),
+    emoji("😶", "no_mouth", &["silent"], People),
+    emoji("😏", "smirk", &["smug"], People),
+    emoji("😒", "unamused", &["meh", "annoyed"], People),
+    emoji("🙄", "rolling_eyes", &["eyeroll", "whatever"], People),
+    emoji("😬", "grimacing", &["awkward", "oops"], People),
+    emoji("😌", "relieved", &["calm"], People),
+    emoji("😔", "pensive", &["sad"], People),
+    emoji("😪", "sleepy", &["tired"], People),
+    emoji("😴", "sleeping", &["zzz", "tired"], People),
+    emoji("😷", "mask", &["sick", "ill"], People),
+    emoji("🤒", "thermometer_face", &["sick", "ill"], People),
+    emoji("🤢", "nauseated_face", &["sick", "gross"], People),
+    emoji(
+        "🤯",
+        "exploding_head",
+        &["mind", "blown", "shocked"],
+        People,
+    ),
+    emoji("🥳", "partying_face", &["party", "celebrate"], People),
+    emoji("😎", "sunglasses", &["cool"], People),
+    emoji("🤓", "nerd", &["geek", "glasses"], People),
+    emoji("😕", "confused", &["puzzled"], People),
+    emoji("😟", "worried", &["nervous"], People),
+    emoji("😮", "open_mouth", &["wow", "surprised"], People),
+    emoji("😲", "astonished", &["shocked", "wow"], People),
+    emoji("😳", "flushed", &["embarrassed", "blush"], People),
+    emoji("🥺", "pleading_face", &["please", "puppy"], People),
+    emoji("😢", "cry", &["sad", "tear"], People),
+    emoji("😭", "sob", &["sad", "crying"], People),
+    emoji("😱", "scream", &["scared", "horror"], People),
+    emoji("😤", "triumph", &["huff", "proud"], People),
+    emoji("😡", "rage", &["angry", "mad"], People),
+    emoji("😠", "angry", &["mad", "annoyed"], People),
+    emoji("🤬", "cursing_face", &["swear", "angry"], People),
+    emoji("💀", "skull", &["dead", "dying"], People),
+    emoji("💩", "poop", &["poo", "shit"], People),
+    emoji("🤡", "clown", &["silly"], People),
+    emoji("👻", "ghost", &["spooky", "halloween"], People),
+    emoji("👽", "alien", &["ufo"], People),
+    emoji("🤖", "robot", &["bot"], People),
+    toned("👋", "wave", &["hello", "hi", "bye"]),
+    toned("🤚", "raised_back_of_hand", &["stop"]),
+    toned("✋", "raised_hand", &["stop", "high_five"]),
+    toned("👌", "ok_hand", &["okay", "perfect"]),
+    toned("🤌", "pinched_fingers", &["italian"]),
+    toned("✌️", "v", &["peace", "victory"]),
+    toned("🤞", "crossed_fingers", &["luck", "hope"]),
+    toned("🤟", "love_you_gesture", &["ily"]),
+    toned("🤘", "metal", &["rock", "horns"]),
+    toned("🤙", "call_me", &["shaka"]),
+    toned("👈", "point_left", &["left"]),
+    toned("👉", "point_right", &["right"]),
+    toned("👆", "point_up", &["up"]),
+    toned("👇", "point_down", &["down"]),
+    toned("👍", "thumbsup", &["+1", "yes", "like", "approve"]),
+    toned("👎", "thumbsdown", &["-1", "no", "dislike"]),
+    toned("✊", "fist", &["power"]),
+    toned("👊", "punch", &["fist_bump"]),
+    toned("👏", "clap", &["applause", "bravo"]),
+    toned("🙌", "raised_hands", &["hooray", "praise"]),
+    toned("👐", "open_hands", &["hug"]),
+    toned("🤲", "palms_up_together", &["pray"]),
+    toned("🙏", "pray", &["please", "thanks", "hope"]),
+    toned("💪", "muscle", &["strong", "flex"]),
+    toned("🤦", "facepalm", &["disbelief", "ugh"]),
+    toned("🤷", "shrug", &["whatever", "idk"]),
+    emoji("👀", "eyes", &["look", "see"], People),
+    emoji("🧠", "brain", &["smart"], People),
+    emoji("🐶", "dog", &["puppy", "pet"], Nature),
+    emoji("🐱", "cat", &["kitten", "pet"], Nature),
+    emoji("🐭", "mouse", &["rodent"], Nature),
+    emoji("🦊", "fox", &["animal"], Nature),
+    emoji("🐻", "bear", &["animal"], Nature),
+    emoji("🐼", "panda", &["animal"], Nature),
+    emoji("🐸", "frog", &["toad"], Nature),
+    emoji("🐵", "monkey_face", &["monkey"], Nature),
+    emoji("🐧", "penguin", &["bird"], Nature),
+    emoji("🦄", "unicorn", &["magic"], Nature),
+    emoji("🐝", "bee", &["honeybee"], Nature),
+    emoji("🐢", "turtle", &["slow"], Nature),
+    emoji("🐍", "snake", &["python"], Nature),
+    emoji("🦀", "crab", &["rust", "ferris"], Nature),
+    emoji("🐙", "octopus", &["tentacles"], Nature),
+    emoji("🌵", "cactus", &["desert"], Nature),
+    emoji("🌲", "evergreen_tree", &["tree", "forest"], Nature),
+    emoji("🌸", "cherry_blossom", &["flower", "spring"], Nature),
+    emoji("🌹", "rose", &["flower", "love"], Nature),
+    emoji("🌻", "sunflower", &["flower"], Nature),
+    emoji("🍀", "four_leaf_clover", &["luck"], Nature),
+    emoji("🌈", "rainbow", &["pride"], Nature),
+    emoji("☀️", "sunny", &["sun", "weather"], Nature),
+    emoji("🌙", "crescent_moon", &["moon", "night"], Nature),
+    emoji("⭐", "star", &["favourite"], Nature),
+    emoji("⚡", "zap", &["lightning", "fast"], Nature),
+    emoji("❄️", "snowflake", &["cold", "winter"], Nature),
+    emoji("🔥", "fire", &["lit", "hot", "flame"], Nature),
+    emoji("💧", "droplet", &["water"], Nature),
+    emoji("🍎", "apple", &["fruit"], Food),
+    emoji("🍌", "banana", &["fruit"], Food),
+    emoji("🍉", "watermelon", &["fruit"], Food),
+    emoji("🍓", "strawberry", &["fruit"], Food),
+    emoji("🥑", "avocado", &["fruit"], Food),
+    emoji("🌶️", "hot_pepper", &["spicy", "chili"], Food),
+    emoji("🍞", "bread", &["toast"], Food),
+    emoji("🧀", "cheese", &["dairy"], Food),
+    emoji("🍔", "hamburger", &["burger"], Food),
+    emoji("🍟", "fries", &["chips"], Food),
+    emoji("🍕", "pizza", &["slice"], Food),
+    emoji("🌮", "taco", &["mexican"], Food),
+    emoji("🍣", "sushi", &["fish", "japanese"], Food),
+    emoji("🍜", "ramen", &["noodles"], Food),
+    emoji("🍩", "doughnut", &["donut"], Food),
+    emoji("🍪", "cookie", &["biscuit"], Food),
+    emoji("🎂", "birthday", &["cake", "party"], Food),
+    emoji("🍫", "chocolate_bar", &["chocolate"], Food),
+    emoji("🍿", "popcorn", &["movie"], Food),
+    emoji("☕", "coffee", &["cafe", "espresso"], Food),
+    emoji("🍵", "tea", &["green"], Food),
+    emoji("🍺", "beer", &["drink", "pub"], Food),
+    emoji("🍻", "beers", &["cheers", "drinks"], Food),
+    emoji("🍷", "wine_glass", &["wine", "drink"], Food),
+    emoji("🥤", "cup_with_straw", &["soda", "drink"], Food),
+    emoji("⚽", "soccer", &["football", "ball"], Activities),
+    emoji("🏀", "basketball", &["ball"], Activities),
+    emoji("🏈", "football", &["american"], Activities),
+    emoji("🎾", "tennis", &["ball"], Activities),
+    emoji("🏆", "trophy", &["win", "award"], Activities),
+    emoji("🥇", "first_place", &["gold", "medal"], Activities),
+    emoji("🎮", "video_game", &["controller", "gaming"], Activities),
+    emoji("🕹️", "joystick", &["arcade", "gaming"], Activities),
+    emoji("🎲", "game_die", &["dice", "random"], Activities),
+    emoji("♟️", "chess_pawn", &["chess"], Activities),
+    emoji("🎯", "dart", &["target", "bullseye"], Activities),
+    emoji("🎸", "guitar", &["music", "rock"], Activities),
+    emoji("🎧", "headphones", &["music", "audio"], Activities),
+    emoji("🎤", "microphone", &["sing", "karaoke"], Activities),
+    emoji("🎨", "art", &["paint", "palette"], Activities),
+    emoji("🎉", "tada", &["party", "celebrate", "hooray"], Activities),
+    emoji("🎊", "confetti_ball", &["party", "celebrate"], Activities),
+    emoji("🎁", "gift", &["present", "birthday"], Activities),
+    emoji("🚗", "car", &["drive"], Travel),
+    emoji("🚕", "taxi", &["cab"], Travel),
+    emoji("🚌", "bus", &["transit"], Travel),
+    emoji("🚲", "bike", &["bicycle", "cycle"], Travel),
+    emoji("🚀", "rocket", &["launch", "ship", "space"], Travel),
+    emoji("✈️", "airplane", &["flight", "travel"], Travel),
+    emoji("🚂", "train", &["rail"], Travel),
+    emoji("⛵", "sailboat", &["boat"], Travel),
+    emoji("🏠", "house", &["home"], Travel),
+    emoji("🏰", "castle", &["fortress"], Travel),
+    emoji("🗺️", "map", &["world"], Travel),
+    emoji("🌍", "earth_africa", &["globe", "world"], Travel),
+    emoji("🏖️", "beach", &["holiday", "vacation"], Travel),
+    emoji("⛰️", "mountain", &["hike"], Travel),
+    emoji("🌃", "night_with_stars", &["city", "night"], Travel),
+    emoji("💡", "bulb", &["idea", "light"], Objects),
+    emoji("💻", "computer", &["laptop", "pc"], Objects),
+    emoji("🖥️", "desktop", &["monitor", "pc"], Objects),
+    emoji("⌨️", "keyboard", &["typing"], Objects),
+    emoji("🖱️", "mouse_three_button", &["click"], Objects),
+    emoji("📱", "iphone", &["phone", "mobile"], Objects),
+    emoji("📷", "camera", &["photo"], Objects),
+    emoji("🔋", "battery", &["power"], Objects),
+    emoji("🔌", "electric_plug", &["power"], Objects),
+    emoji("💾", "floppy_disk", &["save"], Objects),
+    emoji("📁", "file_folder", &["folder", "directory"], Objects),
+    emoji("📎", "paperclip", &["attachment"], Objects),
+    emoji("📌", "pushpin", &["pin"], Objects),
+    emoji("✏️", "pencil", &["write", "edit"], Objects),
+    emoji("📚", "books", &["read", "library"], Objects),
+    emoji("🔒", "lock", &["secure", "private"], Objects),
+    emoji("🔑", "key", &["password"], Objects),
+    emoji("🔨", "hammer", &["tool", "build"], Objects),
+    emoji("🛠️", "tools", &["fix", "build"], Objects),
+    emoji("⚙️", "gear", &["settings"], Objects),
+    emoji("💣", "bomb", &["boom"], Objects),
+    emoji("💰", "moneybag", &["money", "rich"], Objects),
+    emoji("⏰", "alarm_clock", &["time", "wake"], Objects),
+    emoji("⌛", "hourglass", &["time", "wait"], Objects),
+    emoji("🔔", "bell", &["notification", "ring"], Objects),
+    emoji("❤️", "heart", &["love", "like"], Symbols),
+    emoji("🧡", "orange_heart", &["love"], Symbols),
+    emoji("💛", "yellow_heart", &["love"], Symbols),
+    emoji("💚", "green_heart", &["love"], Symbols),
+    emoji("💙", "blue_heart", &["love"], Symbols),
+    emoji("💜", "purple_heart", &["love"], Symbols),
+    emoji("🖤", "black_heart", &["love"], Symbols),
+    emoji("💔", "broken_heart", &["sad", "heartbreak"], Symbols),
+    emoji("💯", "100", &["hundred", "perfect", "score"], Symbols),
+    emoji("💥", "boom", &["explosion", "collision"], Symbols),
+    emoji("💤", "zzz", &["sleep"], Symbols),
+    emoji("✨", "sparkles", &["shiny", "new"], Symbols),
+    emoji("✅", "white_check_mark", &["done", "yes", "check"], Symbols),
+    emoji("☑️", "ballot_box_with_check", &["done", "check"], Symbols),
+    emoji("❌", "x", &["no", "cross", "wrong"], Symbols),
+    emoji("❗", "exclamation", &["bang", "important"], Symbols),
+    emoji("❓", "question", &["what", "confused"], Symbols),
+    emoji("⚠️", "warning", &["caution"], Symbols),
+    emoji("🚫", "no_entry_sign", &["forbidden", "block"], Symbols),
+    emoji("♻️", "recycle", &["green"], Symbols),
+    emoji("➕", "heavy_plus_sign", &["plus", "add"], Symbols),
+    emoji("➖", "heavy_minus_sign", &["minus"], Symbols),
+    emoji("🔴", "red_circle", &["record", "live"], Symbols),
+    emoji("🟢", "green_circle", &["online", "go"], Symbols),
+    emoji("🆗", "ok", &["okay"], Symbols),
+    emoji("🆕", "new", &["fresh"], Symbols),
+];
+
+/// The emoji with exactly `shortcode`.
+pub fn find(shortcode: &str) -> Option<&'static Emoji> {
+    EMOJI.iter().find(|e| e.shortcode == shortcode)
+}
+
+/// The table entry for `emoji`, with or without a skin tone.
+pub fn lookup(emoji: &str) -> Option<&'static Emoji> {
+    let base = SkinTone::strip(emoji);
+    EMOJI.iter().find(|e| e.emoji == base)
+}
+
+/// Emoji matching `query`: shortcodes starting with it first, then those
+/// containing it, then keyword matches, each in table order.
+pub fn search(query: &str) -> Vec<&'static Emoji> {
+    let query = query.trim().trim_matches(':').to_lowercase();
+    if query.is_empty() {
+        return EMOJI.iter().collect();
+    }
+    let mut ranked: Vec<(u8, &'static Emoji)> = EMOJI
+        .iter()
+        .filter_map(|e| {
+            let rank = if e.shortcode.starts_with(&query) {
+                0
+            } else if e.shortcode.contains(&query) {
+                1
+            } else if e.keywords.iter().any(|k| k.starts_with(&query)) {
+                2
+            } else {
+                return None;
+            };
+            Some((rank, e))
+        })
+        .collect();
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked.into_iter().map(|(_, e)| e).collect()
+}
+
+/// In the picker's category order.
+pub fn in_category(category: Category) -> impl Iterator<Item = &'static Emoji> {
+    EMOJI.iter().filter(move |e| e.category == category)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkinTone {
+    #[default]
+    Default,
+    Light,
+    MediumLight,
+    Medium,
+    MediumDark,
+    Dark,
+}
+
+impl SkinTone {
+    pub const ALL: [SkinTone; 6] = [
+        SkinTone::Default,
+        SkinTone::Light,
+        SkinTone::MediumLight,
+        SkinTone::Medium,
+        SkinTone::MediumDark,
+        SkinTone::Dark,
+    ];
+
+    fn modifier(self) -> Option<char> {
+        match self {
+            SkinTone::Default => None,
+            SkinTone::Light => Some('\u{1F3FB}'),
+            SkinTone::MediumLight => Some('\u{1F3FC}'),
+            SkinTone::Medium => Some('\u{1F3FD}'),
+            SkinTone::MediumDark => Some('\u{1F3FE}'),
+            SkinTone::Dark => Some('\u{1F3FF}'),
+        }
+    }
+
+    /// `emoji` in this tone, if it takes one.
+    pub fn apply(self, emoji: &Emoji) -> String {
+        let Some(modifier) = self.modifier().filter(|_| emoji.toned) else {
+            return emoji.emoji.to_string();
+        };
+        // The modifier replaces the emoji presentation selector.
+        let base = emoji.emoji.trim_end_matches('\u{FE0F}');
+        let mut chars = base.chars();
+        let mut toned: String = chars.next().into_iter().collect();
+        toned.push(modifier);
+        toned.extend(chars);
+        toned
+    }
+
+    /// `emoji` without a tone modifier.
+    pub fn strip(emoji: &str) -> String {
+        let stripped: String = emoji
+            .chars()
+            .filter(|c| !('\u{1F3FB}'..='\u{1F3FF}').contains(c))
+            .collect();
+        match EMOJI
+            .iter()
+            .find(|e| e.emoji.trim_end_matches('\u{FE0F}') == stripped)
+        {
+            Some(e) => e.emoji.to_string(),
+            None => stripped,
+        }
+    }
+
+    /// A hand in this tone, for the tone selector.
+    pub fn swatch(self) -> String {
+        match find("raised_hand") {
+            Some(hand) => self.apply(hand),
+            None => String::new(),
+        }
+    }
+}
+
+/// A `:shortcode` being typed, ending at the caret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion<'a> {
+    /// Byte offset of the ':'.
+    pub start: usize,
+    pub query: &'a str,
+}
+
+/// The `:shortcode` typed just before byte offset `caret` in `text`, if it
+/// is long enough to complete. The ':' must start a word, so times like
+/// "10:30" are left alone.
+pub fn completion_query(text: &str, caret: usize) -> Option<Completion<'_>> {
+    let before = text.get(..caret)?;
+    let start = before.rfind(':')?;
+    let query = &before[start + 1..];
+    let is_shortcode_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-');
+    if query.len() < MIN_QUERY_LEN || !query.chars().all(is_shortcode_char) {
+        return None;
+    }
+    let starts_word = before[..start]
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace);
+    starts_word.then_some(Completion { start, query })
+}
+
+/// `text` with bytes `start..end` replaced by `insert`, and the byte offset
+/// just after the insertion.
+pub fn replace_range(text: &str, start: usize, end: usize, insert: &str) -> (String, usize) {
+    let start = start.min(text.len());
+    let end = end.clamp(start, text.len());
+    if !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+        let caret = text.len() + insert.len();
+        return (format!("{}{}", text, insert), caret);
+    }
+    let mut result = String::with_capacity(text.len() + insert.len());
+    result.push_str(&text[..start]);
+    result.push_str(insert);
+    let caret = result.len();
+    result.push_str(&text[end..]);
+    (result, caret)
+}
+
+/// How often, and how lately, each emoji was used; persisted with the
+/// settings so favourites come first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmojiUsage {
+    /// Uses by shortcode.
+    counts: BTreeMap<String, u32>,
+    /// Shortcodes, most recent first.
+    recent: Vec<String>,
+}
+
+impl EmojiUsage {
+    pub fn record(&mut self, emoji: &Emoji) {
+        let count = self.counts.entry(emoji.shortcode.to_string()).or_default();
+        *count = count.saturating_add(1);
+        self.recent.retain(|s| s != emoji.shortcode);
+        self.recent.insert(0, emoji.shortcode.to_string());
+        self.recent.truncate(RECENT_LEN);
+    }
+
+    pub fn count(&self, emoji: &Emoji) -> u32 {
+        self.counts.get(emoji.shortcode).copied().unwrap_or(0)
+    }
+
+    /// Recently used emoji, most recent first.
+    pub fn recent(&self) -> Vec<&'static Emoji> {
+        self.recent.iter().filter_map(|s| find(s)).collect()
+    }
+
+    /// Up to `limit` emoji, most used first.
+    pub fn frequent(&self, limit: usize) -> Vec<&'static Emoji> {
+        let mut used: Vec<&'static Emoji> = self.counts.keys().filter_map(|s| find(s)).collect();
+        self.rank(&mut used);
+        used.truncate(limit);
+        used
+    }
+
+    /// Move the most used of `emoji` to the front, keeping the order of
+    /// equally used ones.
+    pub fn rank(&self, emoji: &mut [&'static Emoji]) {
+        emoji.sort_by_key(|e| std::cmp::Reverse(self.count(e)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn shortcodes(emoji: &[&Emoji]) -> Vec<&'static str> {
+        emoji.iter().map(|e| e.shortcode).collect()
+    }
+
+    #[test]
+    fn test_table_has_unique_shortcodes() {
+        let mut seen = HashSet::new();
+        for e in EMOJI {
+            assert!(seen.insert(e.shortcode), "duplicate :{}:", e.shortcode);
+            assert!(!e.emoji.is_empty());
+        }
+        for category in Category::ALL {
+            assert!(in_category(category).next().is_some());
+        }
+    }
+
+    #[test]
+    fn test_search_ranks_prefixes_first() {
+        let found = search("ta");
+        assert_eq!(shortcodes(&found[..3]), ["taco", "tada", "taxi"]);
+        assert!(shortcodes(&found).contains(&"star"));
+        assert_eq!(search(":thumbs")[0].shortcode, "thumbsup");
+        let by_keyword = search("party");
+        assert!(shortcodes(&by_keyword).contains(&"tada"));
+        assert!(search("zzzzz").is_empty());
+        assert_eq!(search("").len(), EMOJI.len());
+    }
+
+    #[test]
+    fn test_completion_query() {
+        assert_eq!(
+            completion_query("hi :ta", 6),
+            Some(Completion {
+                start: 3,
+                query: "ta"
+            })
+        );
+        assert_eq!(completion_query(":thumbsup", 9).unwrap().query, "thumbsup");
+        assert_eq!(completion_query("hi :t", 5), None);
+        assert_eq!(completion_query("at 10:30", 8), None);
+        assert_eq!(completion_query("hi :ta there", 12), None);
+        assert_eq!(completion_query("hi :ta there", 6).unwrap().query, "ta");
+        assert_eq!(completion_query("é :ta", 6).unwrap().start, 3);
+        assert_eq!(completion_query("hi", 10), None);
+    }
+
+    #[test]
+    fn test_replace_range() {
+        assert_eq!(
+            replace_range("hi :ta", 3, 6, "🎉"),
+            ("hi 🎉".to_string(), 7)
+        );
+        assert_eq!(replace_range("a b", 1, 1, "😀"), ("a😀 b".to_string(), 5));
+        assert_eq!(replace_range("ab", 9, 9, "!"), ("ab!".to_string(), 3));
+    }
+
+    #[test]
+    fn test_skin_tones() {
+        let thumbs = find("thumbsup").unwrap();
+        assert_eq!(SkinTone::Default.apply(thumbs), "👍");
+        assert_eq!(SkinTone::Medium.apply(thumbs), "👍🏽");
+        let peace = find("v").unwrap();
+        assert_eq!(SkinTone::Dark.apply(peace), "✌🏿");
+        assert_eq!(SkinTone::strip("✌🏿"), "✌️");
+        assert_eq!(lookup("👍🏽"), Some(thumbs));
+        let heart = find("heart").unwrap();
+        assert_eq!(SkinTone::Dark.apply(heart), "❤️");
+    }
+
+    #[test]
+    fn test_usage_orders_favourites_first() {
+        let mut usage = EmojiUsage::default();
+        let (tada, fire) = (find("tada").unwrap(), find("fire").unwrap());
+        usage.record(tada);
+        usage.record(fire);
+        usage.record(fire);
+        assert_eq!(shortcodes(&usage.recent()), ["fire", "tada"]);
+        assert_eq!(shortcodes(&usage.frequent(1)), ["fire"]);
+
+        let mut found = search("ta");
+        usage.rank(&mut found);
+        assert_eq!(found[0].shortcode, "tada");
+
+        let json = serde_json::to_string(&usage).unwrap();
+        assert_eq!(serde_json::from_str::<EmojiUsage>(&json).unwrap(), usage);
+    }
+}
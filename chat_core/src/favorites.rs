@@ -0,0 +1,103 @@
+//! Rooms pinned to the top of the sidebar, in the order the user put them.
+//! A room is in the list at most once; new ones go at the end.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Favorites {
+    rooms: Vec<String>,
+}
+
+impl Favorites {
+    /// `rooms` in order, with repeats dropped.
+    pub fn new(rooms: Vec<String>) -> Self {
+        let mut favorites = Self::default();
+        for room_id in rooms {
+            favorites.add(&room_id);
+        }
+        favorites
+    }
+
+    pub fn rooms(&self) -> &[String] {
+        &self.rooms
+    }
+
+    pub fn contains(&self, room_id: &str) -> bool {
+        self.rooms.iter().any(|id| id == room_id)
+    }
+
+    /// Pin `room_id` after the others. Returns whether it wasn't already.
+    pub fn add(&mut self, room_id: &str) -> bool {
+        if self.contains(room_id) {
+            return false;
+        }
+        self.rooms.push(room_id.to_string());
+        true
+    }
+
+    /// Returns whether `room_id` was pinned.
+    pub fn remove(&mut self, room_id: &str) -> bool {
+        let before = self.rooms.len();
+        self.rooms.retain(|id| id != room_id);
+        self.rooms.len() != before
+    }
+
+    /// Swap `room_id` with the one above it. Returns whether it moved.
+    pub fn move_up(&mut self, room_id: &str) -> bool {
+        match self.position(room_id) {
+            Some(index) if index > 0 => {
+                self.rooms.swap(index, index - 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Swap `room_id` with the one below it. Returns whether it moved.
+    pub fn move_down(&mut self, room_id: &str) -> bool {
+        match self.position(room_id) {
+            Some(index) if index + 1 < self.rooms.len() => {
+                self.rooms.swap(index, index + 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn position(&self, room_id: &str) -> Option<usize> {
+        self.rooms.iter().position(|id| id == room_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn favorites(rooms: &[&str]) -> Favorites {
+        Favorites::new(rooms.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[test]
+    fn test_rooms_are_pinned_once() {
+        let mut pinned = favorites(&["!a", "!b", "!a"]);
+        assert_eq!(pinned.rooms(), ["!a", "!b"]);
+        assert!(pinned.add("!c"));
+        assert!(!pinned.add("!b"));
+        assert_eq!(pinned.rooms(), ["!a", "!b", "!c"]);
+        assert!(pinned.remove("!b"));
+        assert!(!pinned.remove("!b"));
+        assert_eq!(pinned.rooms(), ["!a", "!c"]);
+    }
+
+    #[test]
+    fn test_rooms_move_within_the_list() {
+        let mut pinned = favorites(&["!a", "!b", "!c"]);
+        assert!(pinned.move_up("!c"));
+        assert_eq!(pinned.rooms(), ["!a", "!c", "!b"]);
+        assert!(pinned.move_down("!a"));
+        assert_eq!(pinned.rooms(), ["!c", "!a", "!b"]);
+        // Nowhere further to go, or not pinned at all.
+        assert!(!pinned.move_up("!c"));
+        assert!(!pinned.move_down("!b"));
+        assert!(!pinned.move_up("!z"));
+        assert_eq!(pinned.rooms(), ["!c", "!a", "!b"]);
+    }
+}
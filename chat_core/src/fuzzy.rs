@@ -0,0 +1,172 @@
+//! Fuzzy matching for finders, scored the way skim and fzf score: the
+//! query's characters have to appear in the text in order, and a match
+//! scores higher where they run together or start words.
+
+const SCORE_MATCH: i32 = 16;
+const GAP_START: i32 = -3;
+const GAP_EXTENSION: i32 = -1;
+/// A match at the start of a word.
+const BONUS_BOUNDARY: i32 = SCORE_MATCH / 2;
+/// A match on punctuation or a space, which is rarely typed by accident.
+const BONUS_NON_WORD: i32 = SCORE_MATCH / 2;
+/// A match at a lower-to-upper case or letter-to-digit change.
+const BONUS_CAMEL: i32 = BONUS_BOUNDARY + GAP_EXTENSION;
+/// The least a match right after another one adds.
+const BONUS_CONSECUTIVE: i32 = -(GAP_START + GAP_EXTENSION);
+/// The query's first character counts its bonus this many times.
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+    NonWord,
+    Lower,
+    Upper,
+    Digit,
+}
+
+fn class(c: char) -> Class {
+    if c.is_lowercase() {
+        Class::Lower
+    } else if c.is_uppercase() {
+        Class::Upper
+    } else if c.is_numeric() {
+        Class::Digit
+    } else if c.is_alphabetic() {
+        // Scripts without case.
+        Class::Lower
+    } else {
+        Class::NonWord
+    }
+}
+
+fn bonus(prev: Class, current: Class) -> i32 {
+    match (prev, current) {
+        (_, Class::NonWord) => BONUS_NON_WORD,
+        (Class::NonWord, _) => BONUS_BOUNDARY,
+        (Class::Lower, Class::Upper) => BONUS_CAMEL,
+        (Class::Lower | Class::Upper, Class::Digit) => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+/// What a query is matched with: lowercased, without spaces.
+pub fn query(text: &str) -> Vec<char> {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Text prepared once to be matched against many queries.
+#[derive(Debug, Clone)]
+pub struct Target {
+    chars: Vec<char>,
+    /// What matching each character adds beyond `SCORE_MATCH`.
+    bonus: Vec<i32>,
+}
+
+impl Target {
+    pub fn new(text: &str) -> Self {
+        let mut chars = Vec::with_capacity(text.len());
+        let mut bonuses = Vec::with_capacity(text.len());
+        let mut prev = Class::NonWord;
+        for c in text.chars() {
+            let current = class(c);
+            let bonus = bonus(prev, current);
+            // One character for each, so they line up with the bonuses.
+            chars.push(c.to_lowercase().next().unwrap_or(c));
+            bonuses.push(bonus);
+            prev = current;
+        }
+        Self {
+            chars,
+            bonus: bonuses,
+        }
+    }
+
+    /// How well `query` (from [`query`]) matches, or `None` if it doesn't.
+    /// An empty query matches everything, scoring 0.
+    pub fn score(&self, query: &[char]) -> Option<i32> {
+        let n = self.chars.len();
+        if query.is_empty() {
+            return Some(0);
+        }
+        if query.len() > n {
+            return None;
+        }
+        // For the query so far, the best score with its last character
+        // matched at each position of the text, and the bonus the run of
+        // consecutive matches ending there started with.
+        let mut prev: Vec<Option<(i32, i32)>> = vec![None; n];
+        let mut row: Vec<Option<(i32, i32)>> = vec![None; n];
+        for (i, &q) in query.iter().enumerate() {
+            // The best score of the previous character matched at least two
+            // positions back, less the gap to here.
+            let mut gapped: Option<i32> = None;
+            for j in 0..n {
+                if i > 0 && j >= 2 {
+                    let opened = prev[j - 2].map(|(score, _)| score + GAP_START);
+                    let extended = gapped.map(|score| score + GAP_EXTENSION);
+                    gapped = opened.max(extended);
+                }
+                row[j] = None;
+                if self.chars[j] != q {
+                    continue;
+                }
+                let bonus = self.bonus[j];
+                if i == 0 {
+                    // Where the match starts doesn't cost anything.
+                    let score = SCORE_MATCH + bonus * BONUS_FIRST_CHAR_MULTIPLIER;
+                    row[j] = Some((score, bonus));
+                    continue;
+                }
+                let after_gap = gapped.map(|score| (score + SCORE_MATCH + bonus, bonus));
+                let consecutive = j.checked_sub(1).and_then(|k| prev[k]).map(|(score, run)| {
+                    // A new word starts a new run.
+                    let run = if bonus >= BONUS_BOUNDARY {
+                        bonus
+                    } else {
+                        run.max(BONUS_CONSECUTIVE).max(bonus)
+                    };
+                    (score + SCORE_MATCH + run, run)
+                });
+                row[j] = match (after_gap, consecutive) {
+                    (Some(a), Some(c)) => Some(if c.0 >= a.0 { c } else { a }),
+                    (a, c) => a.or(c),
+                };
+            }
+            std::mem::swap(&mut prev, &mut row);
+        }
+        prev.into_iter().flatten().map(|(score, _)| score).max()
+    }
+}
+
+/// How well `query` matches `text`; see [`Target::score`].
+pub fn score(query_text: &str, text: &str) -> Option<i32> {
+    Target::new(text).score(&query(query_text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_in_order_in_any_case() {
+        assert!(score("gnrl", "General").is_some());
+        assert!(score("GEN", "general").is_some());
+        assert!(score("lrg", "General").is_none());
+        assert!(score("generals", "General").is_none());
+        assert_eq!(score("", "General"), Some(0));
+        assert!(score("off top", "off-topic").is_some());
+    }
+
+    #[test]
+    fn test_prefers_word_starts_and_runs() {
+        // Starting words beats landing mid-word.
+        assert!(score("gc", "general-chat") > score("gc", "magic"));
+        assert!(score("gc", "gameChat") > score("gc", "magic"));
+        // Together beats scattered.
+        assert!(score("lob", "lobby") > score("lob", "leaderboard"));
+        assert!(score("voice", "Voice Chat") > score("voice", "invoice"));
+    }
+}
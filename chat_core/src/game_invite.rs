@@ -0,0 +1,307 @@
+//! Game invites: a message asking the room to join a game, with where to
+//! connect, how many can come and until when. It is sent as a message of
+//! its own type whose body reads as plain text, so clients that don't know
+//! the type still show what it is about.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The `msgtype` of an invite.
+pub const MSGTYPE: &str = "io.gamechat.game_invite";
+
+/// The longest an invite can stay open.
+pub const MAX_LIFETIME_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Schemes never launched, whatever the settings say: they open files or
+/// run script rather than start a game.
+const NEVER_LAUNCH: [&str; 5] = ["file", "javascript", "vbscript", "data", "ms-msdt"];
+
+/// Schemes launched when the settings don't say otherwise.
+pub fn default_launch_schemes() -> Vec<String> {
+    vec!["steam".to_string()]
+}
+
+/// The fields an invite carries beside its body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameInvite {
+    pub game: String,
+    /// Where to connect: a server address, a lobby code, or a link such
+    /// as `steam://connect/203.0.113.7:27015`.
+    pub connect: String,
+    /// How many players the party is for, counting the one inviting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub party_size: Option<u32>,
+    /// When it stops being open, in ms since the Unix epoch.
+    pub expires_ts: u64,
+}
+
+/// Why an invite can't be sent, or is shown as its body alone.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum InviteError {
+    #[error("Name the game")]
+    NoGame,
+    #[error("The game's name is too long")]
+    GameTooLong,
+    #[error("Say where to connect")]
+    NoConnect,
+    #[error("The connection string is too long")]
+    ConnectTooLong,
+    #[error("The connection string can't contain spaces")]
+    BadConnect,
+    #[error("A party is from 1 to 100 players")]
+    BadPartySize,
+    #[error("An invite must expire within a day")]
+    BadExpiry,
+}
+
+/// What joining an invite does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Join {
+    /// Open this link with the application registered for its scheme,
+    /// once the user confirms.
+    Launch(String),
+    /// Copy this to the clipboard, to paste into the game.
+    Copy(String),
+}
+
+impl GameInvite {
+    /// Check an invite sent, or to be sent, at `sent_ts`.
+    pub fn validate(&self, sent_ts: u64) -> Result<(), InviteError> {
+        let game = self.game.trim();
+        if game.is_empty() {
+            return Err(InviteError::NoGame);
+        }
+        if game.chars().count() > 64 {
+            return Err(InviteError::GameTooLong);
+        }
+        if self.connect.is_empty() {
+            return Err(InviteError::NoConnect);
+        }
+        if self.connect.len() > 256 {
+            return Err(InviteError::ConnectTooLong);
+        }
+        if self
+            .connect
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(InviteError::BadConnect);
+        }
+        if matches!(self.party_size, Some(size) if !(1..=100).contains(&size)) {
+            return Err(InviteError::BadPartySize);
+        }
+        if self.expires_ts <= sent_ts || self.expires_ts - sent_ts > MAX_LIFETIME_MS {
+            return Err(InviteError::BadExpiry);
+        }
+        Ok(())
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_ts
+    }
+
+    /// The body sent along, for clients that show only that, e.g. "Game
+    /// invite: Valorant, party of 5", then "Join: steam://connect/..." and
+    /// "Expires in 30 min." on lines of their own, so that the connection
+    /// string can be copied whole.
+    pub fn fallback_body(&self, now: u64) -> String {
+        let party = match self.party_size {
+            Some(size) => format!(", party of {}", size),
+            None => String::new(),
+        };
+        format!(
+            "Game invite: {}{}\nJoin: {}\nExpires in {}.",
+            self.game.trim(),
+            party,
+            self.connect,
+            format_remaining(self.expires_ts.saturating_sub(now)),
+        )
+    }
+}
+
+/// How long is left, to the minute and rounded up, e.g. "45 min", "2 h" or
+/// "1 h 30 min".
+pub fn format_remaining(ms: u64) -> String {
+    let minutes = ms.div_ceil(60 * 1000);
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{} min", minutes),
+        (hours, 0) => format!("{} h", hours),
+        (hours, minutes) => format!("{} h {} min", hours, minutes),
+    }
+}
+
+/// The scheme `connect` starts with, in lower case, if it reads as a link.
+/// "203.0.113.7:27015" and "localhost:27015" are addresses, not links.
+fn scheme(connect: &str) -> Option<String> {
+    let (scheme, rest) = connect.split_once(':')?;
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    (valid && rest.starts_with("//")).then(|| scheme.to_ascii_lowercase())
+}
+
+/// What joining an invite to `connect` does: launch it if it is a link of
+/// one of `launch_schemes`, otherwise copy it.
+pub fn join_action(connect: &str, launch_schemes: &[String]) -> Join {
+    match scheme(connect) {
+        Some(scheme)
+            if !NEVER_LAUNCH.contains(&scheme.as_str())
+                && launch_schemes
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(&scheme)) =>
+        {
+            Join::Launch(connect.to_string())
+        }
+        _ => Join::Copy(connect.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1000;
+    const SENT: u64 = 1_710_504_000_000;
+
+    fn invite() -> GameInvite {
+        GameInvite {
+            game: "Valorant".to_string(),
+            connect: "steam://connect/203.0.113.7:27015".to_string(),
+            party_size: Some(5),
+            expires_ts: SENT + 30 * MINUTE,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(invite().validate(SENT), Ok(()));
+
+        let check = |change: fn(&mut GameInvite)| {
+            let mut invite = invite();
+            change(&mut invite);
+            invite.validate(SENT)
+        };
+        assert_eq!(check(|i| i.game = "  ".into()), Err(InviteError::NoGame));
+        assert_eq!(
+            check(|i| i.game = "x".repeat(65)),
+            Err(InviteError::GameTooLong)
+        );
+        assert_eq!(check(|i| i.connect.clear()), Err(InviteError::NoConnect));
+        assert_eq!(
+            check(|i| i.connect = "a".repeat(257)),
+            Err(InviteError::ConnectTooLong)
+        );
+        assert_eq!(
+            check(|i| i.connect = "lobby 42".into()),
+            Err(InviteError::BadConnect)
+        );
+        assert_eq!(
+            check(|i| i.connect = "lobby\n42".into()),
+            Err(InviteError::BadConnect)
+        );
+        assert_eq!(
+            check(|i| i.party_size = Some(0)),
+            Err(InviteError::BadPartySize)
+        );
+        assert_eq!(
+            check(|i| i.party_size = Some(101)),
+            Err(InviteError::BadPartySize)
+        );
+        assert_eq!(check(|i| i.party_size = None), Ok(()));
+        assert_eq!(check(|i| i.expires_ts = SENT), Err(InviteError::BadExpiry));
+        assert_eq!(
+            check(|i| i.expires_ts = SENT + MAX_LIFETIME_MS + 1),
+            Err(InviteError::BadExpiry)
+        );
+        assert_eq!(check(|i| i.expires_ts = SENT + MAX_LIFETIME_MS), Ok(()));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let invite = invite();
+        assert!(!invite.is_expired(SENT));
+        assert!(!invite.is_expired(SENT + 30 * MINUTE - 1));
+        assert!(invite.is_expired(SENT + 30 * MINUTE));
+    }
+
+    #[test]
+    fn test_fallback_body() {
+        assert_eq!(
+            invite().fallback_body(SENT),
+            "Game invite: Valorant, party of 5\n\
+             Join: steam://connect/203.0.113.7:27015\n\
+             Expires in 30 min."
+        );
+        let invite = GameInvite {
+            party_size: None,
+            connect: "ABCD-1234".to_string(),
+            expires_ts: SENT + 90 * MINUTE,
+            ..invite()
+        };
+        assert_eq!(
+            invite.fallback_body(SENT),
+            "Game invite: Valorant\nJoin: ABCD-1234\nExpires in 1 h 30 min."
+        );
+    }
+
+    #[test]
+    fn test_format_remaining() {
+        assert_eq!(format_remaining(1), "1 min");
+        assert_eq!(format_remaining(45 * MINUTE), "45 min");
+        assert_eq!(format_remaining(120 * MINUTE), "2 h");
+        assert_eq!(format_remaining(89 * MINUTE + 1), "1 h 30 min");
+    }
+
+    #[test]
+    fn test_join_action() {
+        let steam = default_launch_schemes();
+        let url = "steam://connect/203.0.113.7:27015";
+        assert_eq!(join_action(url, &steam), Join::Launch(url.to_string()));
+        assert_eq!(
+            join_action("STEAM://run/730", &steam),
+            Join::Launch("STEAM://run/730".to_string())
+        );
+
+        // Addresses and codes are copied, as are links of other schemes.
+        for connect in [
+            "203.0.113.7:27015",
+            "localhost:27015",
+            "ABCD-1234",
+            "minecraft://play.example.org",
+        ] {
+            assert_eq!(
+                join_action(connect, &steam),
+                Join::Copy(connect.to_string())
+            );
+        }
+
+        // Never launched, even when allowed.
+        let risky = vec!["file".to_string(), "javascript".to_string()];
+        assert_eq!(
+            join_action("file:///etc/passwd", &risky),
+            Join::Copy("file:///etc/passwd".to_string())
+        );
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let json = serde_json::to_value(invite()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "game": "Valorant",
+                "connect": "steam://connect/203.0.113.7:27015",
+                "party_size": 5,
+                "expires_ts": SENT + 30 * MINUTE,
+            })
+        );
+        let without_party: GameInvite = serde_json::from_value(serde_json::json!({
+            "game": "Valorant",
+            "connect": "ABCD-1234",
+            "expires_ts": SENT,
+        }))
+        .unwrap();
+        assert_eq!(without_party.party_size, None);
+    }
+}
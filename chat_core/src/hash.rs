@@ -0,0 +1,31 @@
+//! Hashes that are the same on every build and platform, unlike
+//! `DefaultHasher`, for anything kept on disk or shown to the user.
+
+/// 64-bit FNV-1a of `text`'s bytes.
+pub fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// 32-bit FNV-1a of `text`'s bytes, where a small hash will do.
+pub fn fnv1a_32(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_matches_the_reference() {
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a("foobar"), 0x85944171f73967e8);
+        assert_eq!(fnv1a_32(""), 0x811c9dc5);
+        assert_eq!(fnv1a_32("a"), 0xe40c292c);
+        assert_eq!(fnv1a_32("foobar"), 0xbf9cf968);
+    }
+}
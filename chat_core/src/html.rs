@@ -0,0 +1,1205 @@
+//! Formatted message bodies: the HTML other clients send, cut down to the
+//! tags and attributes Matrix allows and turned into blocks of styled text
+//! the timeline can draw. Disallowed tags are dropped but their text kept,
+//! except for scripts and the like, which go with everything in them, as do
+//! the reply fallbacks quoting the message replied to. Nothing here loads
+//! anything: images become their alt text, and links keep only safe schemes.
+
+/// How deep elements may nest; tags deeper than this are ignored and only
+/// their text is kept.
+pub const MAX_DEPTH: usize = 100;
+
+/// Tags Matrix allows in formatted bodies.
+const ALLOWED_TAGS: &[&str] = &[
+    "font",
+    "del",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "p",
+    "a",
+    "ul",
+    "ol",
+    "sup",
+    "sub",
+    "li",
+    "b",
+    "i",
+    "u",
+    "strong",
+    "em",
+    "s",
+    "strike",
+    "code",
+    "hr",
+    "br",
+    "div",
+    "table",
+    "thead",
+    "tbody",
+    "tr",
+    "th",
+    "td",
+    "caption",
+    "pre",
+    "span",
+    "img",
+    "details",
+    "summary",
+];
+
+/// Tags dropped together with everything inside them.
+const DROPPED_TAGS: &[&str] = &[
+    "mx-reply", "script", "style", "head", "title", "template", "textarea", "select", "option",
+    "iframe", "object", "applet", "embed", "svg", "math", "noscript", "noembed", "noframes",
+    "audio", "video", "canvas", "xmp",
+];
+
+/// Tags whose content is not markup, so a `<` in it starts no tag.
+const RAW_TEXT_TAGS: &[&str] = &[
+    "script", "style", "title", "textarea", "xmp", "iframe", "noembed", "noframes", "noscript",
+];
+
+/// Tags that never have content or an end tag.
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Link schemes kept; `javascript:`, `data:` and relative links are not.
+const LINK_SCHEMES: &[&str] = &["https", "http", "ftp", "mailto", "magnet", "matrix"];
+
+/// How a run of text is drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// Inline code, in a monospace font.
+    pub code: bool,
+    /// Hidden until clicked.
+    pub spoiler: bool,
+}
+
+/// A run of text drawn the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+    /// Where the text links to.
+    pub link: Option<String>,
+    /// The sender's text color, as "#rrggbb".
+    pub color: Option<String>,
+}
+
+/// A block of a formatted message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    /// Text; line breaks within it are '\n'.
+    Paragraph(Vec<Span>),
+    /// A heading, `level` 1 to 6.
+    Heading {
+        level: u8,
+        spans: Vec<Span>,
+    },
+    Quote(Vec<Block>),
+    /// Preformatted text, whitespace kept. `language` is the one the sender
+    /// named for highlighting, e.g. "rust".
+    Code {
+        language: Option<String>,
+        text: String,
+    },
+    /// A list, numbered from `start` or bulleted when `None`.
+    List {
+        start: Option<u32>,
+        items: Vec<Vec<Block>>,
+    },
+    /// A horizontal line.
+    Rule,
+}
+
+/// The text of `spans`, without their styling.
+pub fn plain_text(spans: &[Span]) -> String {
+    spans.iter().map(|span| span.text.as_str()).collect()
+}
+
+/// `html` as blocks, sanitized.
+pub fn parse(html: &str) -> Vec<Block> {
+    let tree = Tree::build(html);
+    let mut out = Builder::default();
+    for &child in &tree.nodes[0].children {
+        walk(&tree, child, Context::default(), &mut out);
+    }
+    out.finish()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    End(String),
+    Text(String),
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => number.parse().ok(),
+        };
+        // Out of range, surrogates and NUL become the replacement character.
+        return Some(
+            code.filter(|&c| c != 0)
+                .and_then(char::from_u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+        );
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bull" => '•',
+        "middot" => '·',
+        "times" => '×',
+        "deg" => '°',
+        _ => return None,
+    })
+}
+
+/// `text` with its character references decoded. Unknown ones are kept as
+/// they were.
+fn decode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 32)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+/// The attributes of a start tag from just after its name, and where the
+/// tag ends: just past its '>', or `None` if it never does.
+fn attributes(html: &str, mut pos: usize) -> (Vec<(String, String)>, bool, Option<usize>) {
+    let bytes = html.as_bytes();
+    let mut attrs = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+            self_closing = bytes[pos] == b'/';
+            pos += 1;
+        }
+        if pos >= bytes.len() {
+            return (attrs, self_closing, None);
+        }
+        if bytes[pos] == b'>' {
+            return (attrs, self_closing, Some(pos + 1));
+        }
+        self_closing = false;
+        let start = pos;
+        while pos < bytes.len()
+            && !matches!(bytes[pos], b'=' | b'>' | b'/')
+            && !bytes[pos].is_ascii_whitespace()
+        {
+            pos += 1;
+        }
+        let name = html[start..pos].to_ascii_lowercase();
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos >= bytes.len() || bytes[pos] != b'=' {
+            attrs.push((name, String::new()));
+            continue;
+        }
+        pos += 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let value = match bytes.get(pos) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                let Some(len) = html[pos + 1..].find(quote as char) else {
+                    return (attrs, false, None);
+                };
+                let value = &html[pos + 1..pos + 1 + len];
+                pos += len + 2;
+                value
+            }
+            _ => {
+                let start = pos;
+                while pos < bytes.len() && bytes[pos] != b'>' && !bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                &html[start..pos]
+            }
+        };
+        attrs.push((name, decode(value)));
+    }
+}
+
+/// Split `html` into tags and text. Comments, doctypes and tags left open
+/// at the end are dropped; a '<' that starts no tag is text.
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut text_start = 0;
+    let flush = |tokens: &mut Vec<Token>, text: &str| {
+        if !text.is_empty() {
+            tokens.push(Token::Text(decode(text)));
+        }
+    };
+    while let Some(offset) = html[pos..].find('<') {
+        let lt = pos + offset;
+        let rest = &html[lt + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            flush(&mut tokens, &html[text_start..lt]);
+            pos = match comment.find("-->") {
+                Some(end) => lt + 4 + end + 3,
+                None => html.len(),
+            };
+            text_start = pos;
+        } else if rest.starts_with(['!', '?']) {
+            flush(&mut tokens, &html[text_start..lt]);
+            pos = rest.find('>').map_or(html.len(), |end| lt + 1 + end + 1);
+            text_start = pos;
+        } else if let Some(after) = rest.strip_prefix('/') {
+            flush(&mut tokens, &html[text_start..lt]);
+            let name_len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            let name = after[..name_len].to_ascii_lowercase();
+            pos = after.find('>').map_or(html.len(), |end| lt + 2 + end + 1);
+            text_start = pos;
+            if !name.is_empty() {
+                tokens.push(Token::End(name));
+            }
+        } else if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            flush(&mut tokens, &html[text_start..lt]);
+            let name_len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            let name = rest[..name_len].to_ascii_lowercase();
+            let (attrs, self_closing, end) = attributes(html, lt + 1 + name_len);
+            let Some(end) = end else {
+                pos = html.len();
+                text_start = pos;
+                break;
+            };
+            pos = end;
+            text_start = pos;
+            let raw = RAW_TEXT_TAGS.contains(&name.as_str()) && !self_closing;
+            tokens.push(Token::Start {
+                name: name.clone(),
+                attrs,
+                self_closing,
+            });
+            if raw {
+                // Skip to the matching end tag; nothing in between is markup.
+                let closing = format!("</{}", name);
+                let lower = html[pos..].to_ascii_lowercase();
+                let content_end = lower.find(&closing).map_or(html.len(), |i| pos + i);
+                tokens.push(Token::Text(html[pos..content_end].to_string()));
+                pos = html[content_end..]
+                    .find('>')
+                    .map_or(html.len(), |i| content_end + i + 1);
+                text_start = pos;
+                tokens.push(Token::End(name));
+            }
+        } else {
+            pos = lt + 1;
+        }
+    }
+    flush(&mut tokens, &html[text_start..]);
+    tokens
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    /// The root, and allowed elements with their allowed attributes.
+    Element {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    Text(String),
+}
+
+#[derive(Debug)]
+struct Node {
+    kind: NodeKind,
+    children: Vec<usize>,
+}
+
+impl Node {
+    fn name(&self) -> &str {
+        match &self.kind {
+            NodeKind::Element { name, .. } => name,
+            NodeKind::Text(_) => "",
+        }
+    }
+
+    fn attr(&self, key: &str) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Element { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str()),
+            NodeKind::Text(_) => None,
+        }
+    }
+}
+
+/// Attributes of `tag` that Matrix allows and we use.
+fn allowed_attribute(tag: &str, attr: &str) -> bool {
+    matches!(
+        (tag, attr),
+        ("font", "color" | "data-mx-color")
+            | ("span", "data-mx-color" | "data-mx-spoiler")
+            | ("a", "href")
+            | ("img", "alt" | "title" | "src")
+            | ("ol", "start")
+            | ("code", "class")
+    )
+}
+
+/// Tags that end an open paragraph, as they would in a browser.
+fn closes_paragraph(tag: &str) -> bool {
+    matches!(
+        tag,
+        "p" | "div"
+            | "blockquote"
+            | "pre"
+            | "ul"
+            | "ol"
+            | "table"
+            | "hr"
+            | "details"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+    )
+}
+
+/// The sanitized element tree, root first.
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn build(html: &str) -> Self {
+        let mut nodes = vec![Node {
+            kind: NodeKind::Element {
+                name: String::new(),
+                attrs: Vec::new(),
+            },
+            children: Vec::new(),
+        }];
+        // Open elements, the root first.
+        let mut open = vec![0];
+        // While dropping: the tag, and how many of it are open inside.
+        let mut dropping: Option<(String, usize)> = None;
+        // Start tags ignored for depth whose end tags are still to come.
+        let mut too_deep: Vec<String> = Vec::new();
+
+        for token in tokenize(html) {
+            if let Some((tag, nested)) = &mut dropping {
+                match &token {
+                    Token::Start {
+                        name, self_closing, ..
+                    } if name == tag && !self_closing => *nested += 1,
+                    Token::End(name) if name == tag => {
+                        *nested -= 1;
+                        if *nested == 0 {
+                            dropping = None;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            let parent = *open.last().unwrap();
+            match token {
+                Token::Text(text) => {
+                    let id = nodes.len();
+                    nodes.push(Node {
+                        kind: NodeKind::Text(text),
+                        children: Vec::new(),
+                    });
+                    nodes[parent].children.push(id);
+                }
+                Token::Start {
+                    name,
+                    attrs,
+                    self_closing,
+                } => {
+                    let void = VOID_TAGS.contains(&name.as_str());
+                    if DROPPED_TAGS.contains(&name.as_str()) {
+                        if !self_closing && !void {
+                            dropping = Some((name, 1));
+                        }
+                        continue;
+                    }
+                    if !ALLOWED_TAGS.contains(&name.as_str()) {
+                        continue;
+                    }
+                    if closes_paragraph(&name) {
+                        close_last(&nodes, &mut open, "p", &[]);
+                    }
+                    if name == "li" {
+                        close_last(&nodes, &mut open, "li", &["ul", "ol"]);
+                    }
+                    if matches!(name.as_str(), "td" | "th") {
+                        close_last(&nodes, &mut open, "td", &["tr", "table"]);
+                        close_last(&nodes, &mut open, "th", &["tr", "table"]);
+                    }
+                    if name == "tr" {
+                        close_last(&nodes, &mut open, "tr", &["table"]);
+                    }
+                    let parent = *open.last().unwrap();
+                    let container = !void && !self_closing;
+                    if container && open.len() > MAX_DEPTH {
+                        too_deep.push(name);
+                        continue;
+                    }
+                    let attrs = attrs
+                        .into_iter()
+                        .filter(|(attr, _)| allowed_attribute(&name, attr))
+                        .collect();
+                    let id = nodes.len();
+                    nodes.push(Node {
+                        kind: NodeKind::Element { name, attrs },
+                        children: Vec::new(),
+                    });
+                    nodes[parent].children.push(id);
+                    if container {
+                        open.push(id);
+                    }
+                }
+                Token::End(name) => {
+                    if let Some(i) = too_deep.iter().rposition(|tag| *tag == name) {
+                        too_deep.remove(i);
+                        continue;
+                    }
+                    if let Some(i) = open.iter().rposition(|&id| nodes[id].name() == name) {
+                        if i > 0 {
+                            open.truncate(i);
+                        }
+                    }
+                }
+            }
+        }
+        Self { nodes }
+    }
+}
+
+/// Close the innermost open `tag`, unless one of `scope` is open inside it.
+fn close_last(nodes: &[Node], open: &mut Vec<usize>, tag: &str, scope: &[&str]) {
+    for i in (1..open.len()).rev() {
+        let name = nodes[open[i]].name();
+        if name == tag {
+            open.truncate(i);
+            return;
+        }
+        if scope.contains(&name) {
+            return;
+        }
+    }
+}
+
+/// `value` if it is a "#rrggbb" color.
+fn color(value: &str) -> Option<String> {
+    let hex = value.strip_prefix('#')?;
+    (hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| value.to_ascii_lowercase())
+}
+
+/// `href` if it has a scheme we let users open.
+fn safe_link(href: &str) -> Option<String> {
+    let href = href.trim();
+    let (scheme, _) = href.split_once(':')?;
+    LINK_SCHEMES
+        .iter()
+        .any(|s| s.eq_ignore_ascii_case(scheme))
+        .then(|| href.to_string())
+}
+
+/// The language named by a `<code class="language-rust">`.
+fn code_language(class: &str) -> Option<String> {
+    class
+        .split_whitespace()
+        .find_map(|class| class.strip_prefix("language-"))
+        .filter(|lang| {
+            !lang.is_empty()
+                && lang.len() <= 32
+                && lang
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '#' | '_' | '.'))
+        })
+        .map(str::to_ascii_lowercase)
+}
+
+/// The style, link and color text inherits from the elements around it.
+#[derive(Debug, Clone, Default)]
+struct Context {
+    style: Style,
+    link: Option<String>,
+    color: Option<String>,
+}
+
+/// Blocks, and the paragraph being filled.
+#[derive(Default)]
+struct Builder {
+    blocks: Vec<Block>,
+    spans: Vec<Span>,
+}
+
+impl Builder {
+    fn ends_in_space(&self) -> bool {
+        self.spans
+            .last()
+            .and_then(|span| span.text.chars().next_back())
+            .is_none_or(|c| c == ' ' || c == '\n')
+    }
+
+    fn push(&mut self, text: &str, context: &Context) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(last) = self.spans.last_mut() {
+            if last.style == context.style
+                && last.link == context.link
+                && last.color == context.color
+            {
+                last.text.push_str(text);
+                return;
+            }
+        }
+        self.spans.push(Span {
+            text: text.to_string(),
+            style: context.style,
+            link: context.link.clone(),
+            color: context.color.clone(),
+        });
+    }
+
+    /// Add `text` with its runs of whitespace collapsed to one space, as
+    /// a browser would.
+    fn text(&mut self, text: &str, context: &Context) {
+        let mut collapsed = String::with_capacity(text.len());
+        let mut space = self.ends_in_space();
+        for c in text.chars() {
+            if c.is_ascii_whitespace() {
+                if !space {
+                    collapsed.push(' ');
+                    space = true;
+                }
+            } else {
+                collapsed.push(c);
+                space = false;
+            }
+        }
+        self.push(&collapsed, context);
+    }
+
+    fn line_break(&mut self, context: &Context) {
+        if let Some(last) = self.spans.last_mut() {
+            let trimmed = last.text.trim_end_matches(' ').len();
+            last.text.truncate(trimmed);
+        }
+        self.push("\n", context);
+    }
+
+    /// End the paragraph being filled, if it has any text.
+    fn paragraph(&mut self) -> Option<Vec<Span>> {
+        let mut spans = std::mem::take(&mut self.spans);
+        while let Some(last) = spans.last_mut() {
+            let trimmed = last.text.trim_end_matches([' ', '\n']).len();
+            last.text.truncate(trimmed);
+            if !last.text.is_empty() {
+                break;
+            }
+            spans.pop();
+        }
+        spans.retain(|span| !span.text.is_empty());
+        let blank = spans.iter().all(|span| span.text.trim().is_empty());
+        (!blank).then_some(spans)
+    }
+
+    fn flush(&mut self) {
+        if let Some(spans) = self.paragraph() {
+            self.blocks.push(Block::Paragraph(spans));
+        }
+    }
+
+    fn block(&mut self, block: Block) {
+        self.flush();
+        self.blocks.push(block);
+    }
+
+    fn finish(mut self) -> Vec<Block> {
+        self.flush();
+        self.blocks
+    }
+}
+
+/// The blocks of `id`'s children, in a builder of their own.
+fn children(tree: &Tree, id: usize, context: &Context) -> Vec<Block> {
+    let mut inner = Builder::default();
+    for &child in &tree.nodes[id].children {
+        walk(tree, child, context.clone(), &mut inner);
+    }
+    inner.finish()
+}
+
+/// The text of a `<pre>`, whitespace and all.
+fn raw_text(tree: &Tree, id: usize, out: &mut String) {
+    let node = &tree.nodes[id];
+    match &node.kind {
+        NodeKind::Text(text) => out.push_str(text),
+        NodeKind::Element { name, .. } if name == "br" => out.push('\n'),
+        NodeKind::Element { .. } => {
+            for &child in &node.children {
+                raw_text(tree, child, out);
+            }
+        }
+    }
+}
+
+fn walk(tree: &Tree, id: usize, mut context: Context, out: &mut Builder) {
+    let node = &tree.nodes[id];
+    let name = match &node.kind {
+        NodeKind::Text(text) => return out.text(text, &context),
+        NodeKind::Element { name, .. } => name.as_str(),
+    };
+    match name {
+        "b" | "strong" => context.style.bold = true,
+        "i" | "em" => context.style.italic = true,
+        "u" => context.style.underline = true,
+        "del" | "s" | "strike" => context.style.strikethrough = true,
+        "code" => context.style.code = true,
+        "a" => context.link = node.attr("href").and_then(safe_link),
+        "font" | "span" => {
+            let value = node
+                .attr("data-mx-color")
+                .or_else(|| node.attr("color"))
+                .and_then(color);
+            if value.is_some() {
+                context.color = value;
+            }
+            if node.attr("data-mx-spoiler").is_some() {
+                context.style.spoiler = true;
+            }
+        }
+        "th" => {
+            context.style.bold = true;
+        }
+        _ => {}
+    }
+    match name {
+        "br" => out.line_break(&context),
+        "hr" => out.block(Block::Rule),
+        "img" => {
+            // Only the alt text: images are never loaded from a message.
+            if let Some(alt) = node.attr("alt").or_else(|| node.attr("title")) {
+                out.text(alt, &context);
+            }
+        }
+        "pre" => {
+            let mut text = String::new();
+            raw_text(tree, id, &mut text);
+            let text = text.strip_prefix('\n').unwrap_or(&text);
+            let language = node
+                .children
+                .iter()
+                .map(|&child| &tree.nodes[child])
+                .find(|child| child.name() == "code")
+                .and_then(|code| code.attr("class"))
+                .and_then(code_language);
+            out.block(Block::Code {
+                language,
+                text: text.trim_end_matches('\n').to_string(),
+            });
+        }
+        "blockquote" => {
+            let blocks = children(tree, id, &context);
+            out.flush();
+            if !blocks.is_empty() {
+                out.blocks.push(Block::Quote(blocks));
+            }
+        }
+        "ul" | "ol" => {
+            let start = (name == "ol").then(|| {
+                node.attr("start")
+                    .and_then(|start| start.trim().parse().ok())
+                    .unwrap_or(1)
+            });
+            out.flush();
+            let mut items: Vec<Vec<Block>> = Vec::new();
+            let mut stray = Builder::default();
+            for &child in &node.children {
+                if tree.nodes[child].name() == "li" {
+                    items.extend(Some(stray.finish()).filter(|b| !b.is_empty()));
+                    stray = Builder::default();
+                    items.push(children(tree, child, &context));
+                } else {
+                    walk(tree, child, context.clone(), &mut stray);
+                }
+            }
+            items.extend(Some(stray.finish()).filter(|b| !b.is_empty()));
+            if !items.is_empty() {
+                out.blocks.push(Block::List { start, items });
+            }
+        }
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name.as_bytes()[1] - b'0';
+            out.flush();
+            for block in children(tree, id, &context) {
+                out.blocks.push(match block {
+                    Block::Paragraph(spans) => Block::Heading { level, spans },
+                    block => block,
+                });
+            }
+        }
+        "p" | "div" | "li" | "table" | "thead" | "tbody" | "tr" | "caption" | "details"
+        | "summary" => {
+            out.flush();
+            for &child in &node.children {
+                walk(tree, child, context.clone(), out);
+            }
+            out.flush();
+        }
+        "td" | "th" => {
+            if !out.spans.is_empty() {
+                out.push(" | ", &Context::default());
+            }
+            for &child in &node.children {
+                walk(tree, child, context.clone(), out);
+            }
+        }
+        _ => {
+            for &child in &node.children {
+                walk(tree, child, context.clone(), out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Span {
+        Span {
+            text: text.to_string(),
+            style: Style::default(),
+            link: None,
+            color: None,
+        }
+    }
+
+    fn styled(text: &str, style: Style) -> Span {
+        Span {
+            style,
+            ..self::text(text)
+        }
+    }
+
+    fn paragraph(spans: &[&str]) -> Block {
+        Block::Paragraph(spans.iter().map(|s| text(s)).collect())
+    }
+
+    /// Everything said in `blocks`, one line per paragraph.
+    fn all_text(blocks: &[Block]) -> String {
+        let mut lines = Vec::new();
+        for block in blocks {
+            match block {
+                Block::Paragraph(spans) | Block::Heading { spans, .. } => {
+                    lines.push(plain_text(spans))
+                }
+                Block::Quote(inner) => lines.push(all_text(inner)),
+                Block::Code { text, .. } => lines.push(text.clone()),
+                Block::List { items, .. } => lines.extend(items.iter().map(|item| all_text(item))),
+                Block::Rule => lines.push("---".to_string()),
+            }
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_inline_styles() {
+        let bold = Style {
+            bold: true,
+            ..Style::default()
+        };
+        let bold_italic = Style {
+            italic: true,
+            ..bold
+        };
+        let code = Style {
+            code: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            parse("a <b>bold <i>both</i></b> <code>x()</code>"),
+            vec![Block::Paragraph(vec![
+                text("a "),
+                styled("bold ", bold),
+                styled("both", bold_italic),
+                text(" "),
+                styled("x()", code),
+            ])]
+        );
+        assert_eq!(
+            parse("<del>old</del><s>x</s>"),
+            vec![Block::Paragraph(vec![styled(
+                "oldx",
+                Style {
+                    strikethrough: true,
+                    ..Style::default()
+                }
+            )])]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_and_breaks() {
+        assert_eq!(
+            parse("  one \n\t two  <br>  three  "),
+            vec![paragraph(&["one two\nthree"])]
+        );
+        assert_eq!(
+            parse("<p> a </p>\n<p>b</p><p>  </p>"),
+            vec![paragraph(&["a"]), paragraph(&["b"])]
+        );
+        assert_eq!(parse("&nbsp;x"), vec![paragraph(&["\u{a0}x"])]);
+        assert_eq!(parse(""), vec![]);
+        assert_eq!(parse("<br><br>"), vec![]);
+    }
+
+    #[test]
+    fn test_entities() {
+        assert_eq!(
+            parse("&lt;b&gt; &amp;amp; &#65;&#x42; &unknown; & &#0; &#xD800;"),
+            vec![paragraph(&["<b> &amp; AB &unknown; & \u{fffd} \u{fffd}"])]
+        );
+        // A number too big for a char.
+        assert_eq!(parse("&#99999999999;"), vec![paragraph(&["\u{fffd}"])]);
+    }
+
+    #[test]
+    fn test_links() {
+        let blocks = parse(r#"<a href="https://example.org/?a=1&amp;b=2">site</a>"#);
+        let Block::Paragraph(spans) = &blocks[0] else {
+            panic!("{:?}", blocks);
+        };
+        assert_eq!(
+            spans[0].link.as_deref(),
+            Some("https://example.org/?a=1&b=2")
+        );
+        assert_eq!(spans[0].text, "site");
+
+        let link = |html: &str| match &parse(html)[0] {
+            Block::Paragraph(spans) => spans[0].link.clone(),
+            block => panic!("{:?}", block),
+        };
+        assert_eq!(
+            link("<a href='matrix:r/room:example.org'>r</a>").as_deref(),
+            Some("matrix:r/room:example.org")
+        );
+        assert_eq!(
+            link("<a href=mailto:a@b.org>mail</a>").as_deref(),
+            Some("mailto:a@b.org")
+        );
+        for hostile in [
+            r#"<a href="javascript:alert(1)">x</a>"#,
+            r#"<a href="JavaScript:alert(1)">x</a>"#,
+            r#"<a href=" javascript:alert(1)">x</a>"#,
+            r#"<a href="jav&#x61;script:alert(1)">x</a>"#,
+            "<a href=\"java\tscript:alert(1)\">x</a>",
+            r#"<a href="data:text/html;base64,PHNjcmlwdD4=">x</a>"#,
+            r#"<a href="vbscript:msgbox">x</a>"#,
+            r#"<a href="/relative/path">x</a>"#,
+            r#"<a href="file:///etc/passwd">x</a>"#,
+            r#"<a>x</a>"#,
+        ] {
+            assert_eq!(link(hostile), None, "{}", hostile);
+        }
+    }
+
+    #[test]
+    fn test_scripts_and_unknown_tags() {
+        assert_eq!(
+            parse("a<script>alert('<b>hi</b>')</script>b"),
+            vec![paragraph(&["ab"])]
+        );
+        assert_eq!(
+            parse("<SCRIPT>x</sCrIpT>ok<style>p{color:red}</style>"),
+            vec![paragraph(&["ok"])]
+        );
+        // Never closed: everything after it goes.
+        assert_eq!(parse("ok<script>alert(1)"), vec![paragraph(&["ok"])]);
+        assert_eq!(
+            parse("<scr<script>ipt>x</script>"),
+            vec![paragraph(&["ipt>x"])]
+        );
+        // Unknown tags go, their text stays.
+        assert_eq!(
+            parse("<marquee><blink>hi</blink></marquee> <form><input value=x>there</form>"),
+            vec![paragraph(&["hi there"])]
+        );
+        assert_eq!(
+            parse("<svg><a href='https://x.org'>in svg</a></svg>after"),
+            vec![paragraph(&["after"])]
+        );
+        assert_eq!(
+            parse("<iframe src='https://evil.example'><b>x</b></iframe>y"),
+            vec![paragraph(&["y"])]
+        );
+    }
+
+    #[test]
+    fn test_attributes_are_filtered() {
+        let tree = Tree::build(
+            r##"<span data-mx-color="#ff0000" onclick="steal()" style="position:fixed">x</span>
+               <img src="mxc://a/b" onerror="alert(1)" alt="cat">"##,
+        );
+        for node in &tree.nodes {
+            if let NodeKind::Element { attrs, .. } = &node.kind {
+                for (name, _) in attrs {
+                    assert!(!name.starts_with("on") && name != "style", "{}", name);
+                }
+            }
+        }
+        // Colors must be plain hex, not anything CSS might read.
+        let colored = |html: &str| match &parse(html)[0] {
+            Block::Paragraph(spans) => spans[0].color.clone(),
+            block => panic!("{:?}", block),
+        };
+        assert_eq!(
+            colored(r##"<font color="#00FF00">g</font>"##).as_deref(),
+            Some("#00ff00")
+        );
+        assert_eq!(
+            colored(r##"<span data-mx-color="#123abc">c</span>"##).as_deref(),
+            Some("#123abc")
+        );
+        assert_eq!(colored(r#"<font color="red">r</font>"#), None);
+        assert_eq!(
+            colored(r##"<font color="#fff;background:url(x)">r</font>"##),
+            None
+        );
+        assert_eq!(colored(r##"<p data-mx-color="#ff0000">p</p>"##), None);
+    }
+
+    #[test]
+    fn test_images_are_never_loaded() {
+        assert_eq!(
+            parse(r#"hi <img src="https://tracker.example/pixel.gif" alt=":wave:"> there"#),
+            vec![paragraph(&["hi :wave: there"])]
+        );
+        assert_eq!(
+            parse(r#"<img src="mxc://example.org/emote" title="party">"#),
+            vec![paragraph(&["party"])]
+        );
+        assert_eq!(parse(r#"<img src="https://x.org/a.png">"#), vec![]);
+    }
+
+    #[test]
+    fn test_reply_fallback_is_removed() {
+        let html = "<mx-reply><blockquote><a href=\"https://matrix.to/#/!r/$e\">In reply to</a> \
+                    <a href=\"https://matrix.to/#/@bob:x\">@bob:x</a><br>original \
+                    <mx-reply>nested</mx-reply></blockquote></mx-reply>the answer";
+        assert_eq!(parse(html), vec![paragraph(&["the answer"])]);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let blocks = parse(
+            "<h2>Title</h2><blockquote><p>quoted</p><blockquote>deeper</blockquote></blockquote>\
+             <ol start=\"3\"><li>three</li><li><p>four</p><ul><li>sub</li></ul></li></ol><hr>end",
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Heading {
+                    level: 2,
+                    spans: vec![text("Title")]
+                },
+                Block::Quote(vec![
+                    paragraph(&["quoted"]),
+                    Block::Quote(vec![paragraph(&["deeper"])]),
+                ]),
+                Block::List {
+                    start: Some(3),
+                    items: vec![
+                        vec![paragraph(&["three"])],
+                        vec![
+                            paragraph(&["four"]),
+                            Block::List {
+                                start: None,
+                                items: vec![vec![paragraph(&["sub"])]],
+                            },
+                        ],
+                    ],
+                },
+                Block::Rule,
+                paragraph(&["end"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_blocks_keep_whitespace() {
+        assert_eq!(
+            parse("<pre><code class=\"language-Rust\">\nfn main() {\n    <b>x</b> &lt; 1;\n}\n</code></pre>"),
+            vec![Block::Code {
+                language: Some("rust".to_string()),
+                text: "fn main() {\n    x < 1;\n}".to_string(),
+            }]
+        );
+        assert_eq!(
+            parse(r#"<pre><code class='language-"><script>'>a  b</code></pre>"#),
+            vec![Block::Code {
+                language: None,
+                text: "a  b".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_and_misnested_tags() {
+        assert_eq!(
+            all_text(&parse("<p>one<p>two<ul><li>a<li>b</ul>after")),
+            "one\ntwo\na\nb\nafter"
+        );
+        assert_eq!(all_text(&parse("<b><i>x</b>y</i>z</p></div></li>")), "xyz");
+        // A tag left open at the end is dropped with the rest of the input.
+        assert_eq!(
+            parse("ok <a href=\"https://x.org"),
+            vec![paragraph(&["ok"])]
+        );
+        assert_eq!(parse("ok <b"), vec![paragraph(&["ok"])]);
+        assert_eq!(parse("1 < 2 <> 3 </>"), vec![paragraph(&["1 < 2 <> 3"])]);
+        assert_eq!(
+            parse("a<!-- <b>hidden</b> -->b<!-- never closed"),
+            vec![paragraph(&["ab"])]
+        );
+        assert_eq!(parse("<!DOCTYPE html><?xml?>x"), vec![paragraph(&["x"])]);
+    }
+
+    #[test]
+    fn test_tables() {
+        assert_eq!(
+            all_text(&parse(
+                "<table><tr><th>name</th><th>score</th></tr><tr><td>ann</td><td>3</td></tr></table>"
+            )),
+            "name | score\nann | 3"
+        );
+    }
+
+    #[test]
+    fn test_spoilers() {
+        let blocks = parse("<span data-mx-spoiler=\"plot\">he dies</span>");
+        let Block::Paragraph(spans) = &blocks[0] else {
+            panic!("{:?}", blocks);
+        };
+        assert!(spans[0].style.spoiler);
+    }
+
+    #[test]
+    fn test_depth_is_limited() {
+        let deep = "<b>".repeat(10_000) + "x" + &"</b>".repeat(10_000);
+        assert_eq!(all_text(&parse(&deep)), "x");
+
+        let quotes = "<blockquote>".repeat(5_000) + "deep" + &"</blockquote>".repeat(5_000) + "out";
+        let blocks = parse(&quotes);
+        let mut depth = 0;
+        let mut block = &blocks[0];
+        while let Block::Quote(inner) = block {
+            depth += 1;
+            block = &inner[0];
+        }
+        assert!(depth <= MAX_DEPTH, "{}", depth);
+        assert_eq!(block, &paragraph(&["deep"]));
+        assert_eq!(blocks.last(), Some(&paragraph(&["out"])));
+
+        let lists = "<ul><li>".repeat(3_000) + "item";
+        assert!(all_text(&parse(&lists)).ends_with("item"));
+    }
+
+    #[test]
+    fn test_hostile_input_does_not_panic() {
+        for html in [
+            "<",
+            "</",
+            "<a",
+            "<a href",
+            "<a href=",
+            "<a href='",
+            "<a b c d e=f g='h' i=\"j\">",
+            "&",
+            "&#",
+            "&#x;",
+            "&#xffffffffffffffff;",
+            "<pre>",
+            "<ol start=-5><li>x</ol>",
+            "<ol start=99999999999999><li>x</ol>",
+            "</b></i></p></blockquote></ul>",
+            "<td>cell</td>",
+            "<li>loose</li>",
+            "<h7>no</h7><h1></h1>",
+            "<é>unicode tag</é>ü<b>ä</b>",
+            "<mx-reply>",
+            "<mx-reply/>after",
+            "<script/>after",
+            "\u{0}<b>\u{0}</b>",
+        ] {
+            parse(html);
+        }
+        assert_eq!(parse("<mx-reply/>after"), vec![paragraph(&["after"])]);
+        assert_eq!(
+            parse("<ol start=-5><li>x</ol>"),
+            vec![Block::List {
+                start: Some(1),
+                items: vec![vec![paragraph(&["x"])]],
+            }]
+        );
+    }
+}
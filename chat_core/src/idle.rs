@@ -0,0 +1,115 @@
+//! Automatic away status: Online becomes Idle after a while without input,
+//! and Online again on the next input. A status the user picked themselves
+//! is never changed.
+
+use crate::UserStatus;
+use std::time::Duration;
+
+/// Decides when to go away and come back, from how long input has been
+/// idle at each check.
+#[derive(Debug, Clone, Default)]
+pub struct AutoAway {
+    /// We set Idle, and put Online back on activity.
+    away: bool,
+}
+
+impl AutoAway {
+    /// Whether we are showing Idle because of no input.
+    pub fn is_away(&self) -> bool {
+        self.away
+    }
+
+    /// Input has been idle for `idle` while `current` is shown. Returns the
+    /// status to switch to, if any. Only Online goes away, so Do Not
+    /// Disturb, a chosen Idle and Offline stay as they are; a status
+    /// picked while away is kept too. A zero `threshold` never goes away.
+    pub fn update(
+        &mut self,
+        current: UserStatus,
+        idle: Duration,
+        threshold: Duration,
+    ) -> Option<UserStatus> {
+        if self.away {
+            if current != UserStatus::Idle {
+                self.away = false;
+                return None;
+            }
+            if idle < threshold || threshold.is_zero() {
+                self.away = false;
+                return Some(UserStatus::Online);
+            }
+            return None;
+        }
+        if current == UserStatus::Online && !threshold.is_zero() && idle >= threshold {
+            self.away = true;
+            return Some(UserStatus::Idle);
+        }
+        None
+    }
+
+    /// Forget going away, e.g. as the account signs out.
+    pub fn reset(&mut self) {
+        self.away = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_goes_away_and_comes_back() {
+        let mut away = AutoAway::default();
+        let threshold = 10 * MINUTE;
+        assert_eq!(away.update(UserStatus::Online, 9 * MINUTE, threshold), None);
+        assert_eq!(
+            away.update(UserStatus::Online, 10 * MINUTE, threshold),
+            Some(UserStatus::Idle)
+        );
+        assert!(away.is_away());
+        assert_eq!(away.update(UserStatus::Idle, 30 * MINUTE, threshold), None);
+        assert_eq!(
+            away.update(UserStatus::Idle, Duration::from_secs(2), threshold),
+            Some(UserStatus::Online)
+        );
+        assert!(!away.is_away());
+    }
+
+    #[test]
+    fn test_keeps_chosen_status() {
+        let mut away = AutoAway::default();
+        let threshold = MINUTE;
+        for status in [
+            UserStatus::DoNotDisturb,
+            UserStatus::Idle,
+            UserStatus::Offline,
+        ] {
+            assert_eq!(away.update(status, 60 * MINUTE, threshold), None);
+        }
+
+        // Switched to Do Not Disturb while away: not undone on activity.
+        away.update(UserStatus::Online, MINUTE, threshold);
+        assert_eq!(
+            away.update(UserStatus::DoNotDisturb, MINUTE, threshold),
+            None
+        );
+        assert_eq!(
+            away.update(UserStatus::DoNotDisturb, Duration::ZERO, threshold),
+            None
+        );
+        assert!(!away.is_away());
+
+        // Turned off: never away, and back if it was.
+        assert_eq!(
+            away.update(UserStatus::Online, 60 * MINUTE, Duration::ZERO),
+            None
+        );
+        away.update(UserStatus::Online, MINUTE, threshold);
+        assert_eq!(
+            away.update(UserStatus::Idle, MINUTE, Duration::ZERO),
+            Some(UserStatus::Online)
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Messages we sent, kept per room so the composer can bring them back
+//! with the arrow keys, as a shell does with commands.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Messages kept per room; older ones are dropped.
+pub const LIMIT: usize = 50;
+
+/// Our own sent messages, by room id, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputHistory {
+    rooms: BTreeMap<String, Vec<String>>,
+}
+
+impl InputHistory {
+    /// Remember `body`, sent to `room_id`. Blank messages and repeats of
+    /// the last one are not kept.
+    pub fn push(&mut self, room_id: &str, body: &str) {
+        if body.trim().is_empty() {
+            return;
+        }
+        let sent = self.rooms.entry(room_id.to_string()).or_default();
+        if sent.last().is_some_and(|last| last == body) {
+            return;
+        }
+        sent.push(body.to_string());
+        if sent.len() > LIMIT {
+            sent.drain(..sent.len() - LIMIT);
+        }
+    }
+
+    /// What was sent to `room_id`, oldest first.
+    pub fn sent(&self, room_id: &str) -> &[String] {
+        self.rooms.get(room_id).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// A walk back through a room's history from the composer, keeping what
+/// was typed before it started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recall {
+    draft: String,
+    /// How many messages back we are; 0 is the draft.
+    back: usize,
+}
+
+impl Recall {
+    pub fn start(draft: &str) -> Self {
+        Self {
+            draft: draft.to_string(),
+            back: 0,
+        }
+    }
+
+    /// The message before the one shown; `None` at the oldest.
+    pub fn older<'a>(&mut self, sent: &'a [String]) -> Option<&'a str> {
+        if self.back >= sent.len() {
+            return None;
+        }
+        self.back += 1;
+        Some(&sent[sent.len() - self.back])
+    }
+
+    /// The message after the one shown, or the draft past the newest.
+    /// `None` once back at the draft, which ends the walk.
+    pub fn newer<'a>(&mut self, sent: &'a [String]) -> Option<&'a str> {
+        if self.back <= 1 {
+            self.back = 0;
+            return None;
+        }
+        self.back -= 1;
+        sent.get(sent.len().checked_sub(self.back)?)
+            .map(String::as_str)
+    }
+
+    /// What was typed before the walk started.
+    pub fn draft(&self) -> &str {
+        &self.draft
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_keeps_the_latest_per_room() {
+        let mut history = InputHistory::default();
+        for i in 0..LIMIT + 5 {
+            history.push("!a", &i.to_string());
+        }
+        history.push("!a", &(LIMIT + 4).to_string());
+        history.push("!a", "  ");
+        history.push("!b", "hi");
+
+        let sent = history.sent("!a");
+        assert_eq!(sent.len(), LIMIT);
+        assert_eq!(sent[0], "5");
+        assert_eq!(sent[LIMIT - 1], (LIMIT + 4).to_string());
+        assert_eq!(history.sent("!b"), ["hi"]);
+        assert!(history.sent("!c").is_empty());
+
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(
+            serde_json::from_str::<InputHistory>(&json).unwrap(),
+            history
+        );
+    }
+
+    #[test]
+    fn test_recall_walks_back_and_returns_to_the_draft() {
+        let sent = vec!["one".to_string(), "two".to_string(), "three".to_string()];
+        let mut recall = Recall::start("typing");
+
+        assert_eq!(recall.older(&sent), Some("three"));
+        assert_eq!(recall.older(&sent), Some("two"));
+        assert_eq!(recall.older(&sent), Some("one"));
+        assert_eq!(recall.older(&sent), None);
+        assert_eq!(recall.newer(&sent), Some("two"));
+        assert_eq!(recall.newer(&sent), Some("three"));
+        assert_eq!(recall.newer(&sent), None);
+        assert_eq!(recall.draft(), "typing");
+
+        assert_eq!(Recall::start("").older(&[]), None);
+    }
+}
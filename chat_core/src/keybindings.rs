@@ -0,0 +1,283 @@
+//! Keyboard shortcuts: the actions that can be bound, the key combinations
+//! bound to them, and which bindings clash.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    QuickSwitcher,
+    SearchMessages,
+    ToggleMute,
+    ToggleDeafen,
+    /// Transmit only while held; unbound means voice activity.
+    PushToTalk,
+    NextUnread,
+    PreviousUnread,
+    /// Only while the composer is focused and empty.
+    EditLastMessage,
+    /// Show or hide the overlay, wherever the focus is.
+    ToggleOverlay,
+}
+
+impl Action {
+    /// In the order the settings list them.
+    pub const ALL: [Action; 9] = [
+        Self::QuickSwitcher,
+        Self::SearchMessages,
+        Self::ToggleMute,
+        Self::ToggleDeafen,
+        Self::PushToTalk,
+        Self::NextUnread,
+        Self::PreviousUnread,
+        Self::EditLastMessage,
+        Self::ToggleOverlay,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::QuickSwitcher => "Quick switcher",
+            Self::SearchMessages => "Search messages",
+            Self::ToggleMute => "Toggle mute",
+            Self::ToggleDeafen => "Toggle deafen",
+            Self::PushToTalk => "Push to talk",
+            Self::NextUnread => "Next unread room",
+            Self::PreviousUnread => "Previous unread room",
+            Self::EditLastMessage => "Edit last message",
+            Self::ToggleOverlay => "Toggle overlay",
+        }
+    }
+
+    /// The binding until another is chosen.
+    pub fn default_shortcut(self) -> Option<Shortcut> {
+        let keys = match self {
+            Self::QuickSwitcher => "Ctrl+KeyK",
+            Self::SearchMessages => "Ctrl+Shift+KeyF",
+            Self::ToggleMute => "Ctrl+Shift+KeyM",
+            Self::ToggleDeafen => "Ctrl+Shift+KeyD",
+            Self::PushToTalk => return None,
+            Self::NextUnread => "Alt+Shift+ArrowDown",
+            Self::PreviousUnread => "Alt+Shift+ArrowUp",
+            Self::EditLastMessage => "Ctrl+ArrowUp",
+            Self::ToggleOverlay => "Ctrl+Shift+KeyO",
+        };
+        keys.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{0:?} is not a shortcut")]
+pub struct ParseShortcutError(String);
+
+/// A key and the modifiers held with it. Keys are named by where they are
+/// on the keyboard, as W3C `code` values ("KeyK", "Digit1", "ArrowUp"), so
+/// a binding stays on the same key in any layout.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Shortcut {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// The Windows, Command or Super key.
+    pub meta: bool,
+    pub key: String,
+}
+
+impl Shortcut {
+    /// `key` on its own.
+    pub fn new(key: &str) -> Self {
+        Self {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            meta: false,
+            key: key.to_string(),
+        }
+    }
+
+    fn modifiers(&self) -> impl Iterator<Item = &'static str> {
+        [
+            (self.ctrl, "Ctrl"),
+            (self.alt, "Alt"),
+            (self.shift, "Shift"),
+            (self.meta, "Super"),
+        ]
+        .into_iter()
+        .filter_map(|(held, name)| held.then_some(name))
+    }
+
+    /// How the settings show it, e.g. "Ctrl+Shift+K".
+    pub fn label(&self) -> String {
+        let key = &self.key;
+        let key = key
+            .strip_prefix("Key")
+            .or_else(|| key.strip_prefix("Digit"))
+            .or_else(|| key.strip_prefix("Arrow"))
+            .unwrap_or(key);
+        let mut parts: Vec<&str> = self.modifiers().collect();
+        parts.push(key);
+        parts.join("+")
+    }
+}
+
+/// "Ctrl+Alt+Shift+Super+KeyK", modifiers in that order.
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for modifier in self.modifiers() {
+            write!(f, "{}+", modifier)?;
+        }
+        f.write_str(&self.key)
+    }
+}
+
+impl FromStr for Shortcut {
+    type Err = ParseShortcutError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseShortcutError(s.to_string());
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|k| !k.is_empty()).ok_or_else(error)?;
+        let mut shortcut = Shortcut::new(key);
+        for modifier in parts {
+            let held = match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => &mut shortcut.ctrl,
+                "alt" => &mut shortcut.alt,
+                "shift" => &mut shortcut.shift,
+                "super" | "meta" | "cmd" => &mut shortcut.meta,
+                _ => return Err(error()),
+            };
+            *held = true;
+        }
+        Ok(shortcut)
+    }
+}
+
+impl TryFrom<String> for Shortcut {
+    type Error = ParseShortcutError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Shortcut> for String {
+    fn from(shortcut: Shortcut) -> Self {
+        shortcut.to_string()
+    }
+}
+
+/// The shortcuts chosen in the settings. Actions not listed keep their
+/// default, so new actions come bound.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keybindings {
+    /// `None` for an action deliberately left unbound.
+    chosen: BTreeMap<Action, Option<Shortcut>>,
+}
+
+impl Keybindings {
+    pub fn get(&self, action: Action) -> Option<Shortcut> {
+        match self.chosen.get(&action) {
+            Some(chosen) => chosen.clone(),
+            None => action.default_shortcut(),
+        }
+    }
+
+    /// Bind `action` to `shortcut`, or unbind it.
+    pub fn set(&mut self, action: Action, shortcut: Option<Shortcut>) {
+        if shortcut == action.default_shortcut() {
+            self.chosen.remove(&action);
+        } else {
+            self.chosen.insert(action, shortcut);
+        }
+    }
+
+    /// The action `shortcut` is bound to. Where bindings clash, the first
+    /// action listed wins.
+    pub fn action(&self, shortcut: &Shortcut) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|&action| self.get(action).as_ref() == Some(shortcut))
+    }
+
+    /// The other actions bound to the same shortcut as `action`.
+    pub fn conflicts(&self, action: Action) -> Vec<Action> {
+        let Some(shortcut) = self.get(action) else {
+            return Vec::new();
+        };
+        Action::ALL
+            .into_iter()
+            .filter(|&other| other != action && self.get(other) == Some(shortcut.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortcut_text() {
+        let shortcut: Shortcut = "shift+CTRL+KeyK".parse().unwrap();
+        assert!(shortcut.ctrl && shortcut.shift && !shortcut.alt);
+        assert_eq!(shortcut.to_string(), "Ctrl+Shift+KeyK");
+        assert_eq!(shortcut.label(), "Ctrl+Shift+K");
+        assert_eq!(Shortcut::new("ArrowUp").label(), "Up");
+        assert_eq!(Shortcut::new("F5").to_string(), "F5");
+        assert!("Ctrl+".parse::<Shortcut>().is_err());
+        assert!("Hyper+KeyK".parse::<Shortcut>().is_err());
+    }
+
+    #[test]
+    fn test_bindings_fall_back_to_defaults() {
+        let mut bindings = Keybindings::default();
+        assert_eq!(
+            bindings.get(Action::QuickSwitcher),
+            "Ctrl+KeyK".parse().ok()
+        );
+        assert_eq!(bindings.get(Action::PushToTalk), None);
+
+        bindings.set(Action::PushToTalk, Some(Shortcut::new("KeyV")));
+        bindings.set(Action::QuickSwitcher, None);
+        assert_eq!(bindings.get(Action::QuickSwitcher), None);
+        assert_eq!(
+            bindings.action(&Shortcut::new("KeyV")),
+            Some(Action::PushToTalk)
+        );
+
+        let json = serde_json::to_string(&bindings).unwrap();
+        assert_eq!(json, r#"{"quick_switcher":null,"push_to_talk":"KeyV"}"#);
+        assert_eq!(
+            serde_json::from_str::<Keybindings>(&json).unwrap(),
+            bindings
+        );
+
+        // Choosing the default again forgets the choice.
+        bindings.set(
+            Action::QuickSwitcher,
+            Action::QuickSwitcher.default_shortcut(),
+        );
+        assert!(!serde_json::to_string(&bindings)
+            .unwrap()
+            .contains("quick_switcher"));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut bindings = Keybindings::default();
+        assert!(Action::ALL
+            .iter()
+            .all(|&a| bindings.conflicts(a).is_empty()));
+
+        let mute = bindings.get(Action::ToggleMute);
+        bindings.set(Action::PushToTalk, mute.clone());
+        assert_eq!(bindings.conflicts(Action::ToggleMute), [Action::PushToTalk]);
+        assert_eq!(bindings.conflicts(Action::PushToTalk), [Action::ToggleMute]);
+        assert_eq!(bindings.action(&mute.unwrap()), Some(Action::ToggleMute));
+        assert!(bindings.conflicts(Action::QuickSwitcher).is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+//! Notification keywords: words or phrases that make a message notify and
+//! stand out as a mention does, wherever they appear as whole words.
+
+use serde::{Deserialize, Serialize};
+
+/// A word or phrase to be alerted about.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyword {
+    pub text: String,
+    /// Play a sound along with the notification.
+    #[serde(default = "default_sound")]
+    pub sound: bool,
+}
+
+fn default_sound() -> bool {
+    true
+}
+
+impl Keyword {
+    /// A keyword from what was typed: trimmed, with runs of whitespace made
+    /// one space. `None` if nothing is left.
+    pub fn new(text: &str) -> Option<Self> {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        (!text.is_empty()).then_some(Self { text, sound: true })
+    }
+}
+
+/// Scripts written without spaces between words, where any position can
+/// start a word: Thai, kana and CJK ideographs.
+fn unspaced(c: char) -> bool {
+    matches!(c,
+        '\u{0E00}'..='\u{0E7F}'
+        | '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}')
+}
+
+/// Part of a word in a script that separates words with spaces.
+fn word_char(c: char) -> bool {
+    (c.is_alphanumeric() || c == '_') && !unspaced(c)
+}
+
+/// Whether `keyword` appears in `body` as a whole word or phrase, ignoring
+/// case. Only edges of the keyword that are letters or digits need a
+/// boundary, so "c++" is found in "c++, anyone?" and "@team" in "hi @team".
+pub fn matches(keyword: &str, body: &str) -> bool {
+    let keyword = keyword.to_lowercase();
+    let Some(first) = keyword.chars().next() else {
+        return false;
+    };
+    let last = keyword.chars().next_back().unwrap_or(first);
+    let body = body.to_lowercase();
+    body.match_indices(&keyword).any(|(start, found)| {
+        let before = body[..start].chars().next_back();
+        let after = body[start + found.len()..].chars().next();
+        let joined_before = word_char(first) && before.is_some_and(word_char);
+        let joined_after = word_char(last) && after.is_some_and(word_char);
+        !joined_before && !joined_after
+    })
+}
+
+/// The first of `keywords` found in `body`.
+pub fn find<'a>(keywords: &'a [Keyword], body: &str) -> Option<&'a Keyword> {
+    keywords.iter().find(|keyword| matches(&keyword.text, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_whole_words_ignoring_case() {
+        assert!(matches("raid", "Raid tonight?"));
+        assert!(matches("raid", "who's up for a RAID."));
+        assert!(matches("raid night", "(raid night) at 9"));
+        assert!(!matches("raid", "raiders of the lost ark"));
+        assert!(!matches("raid", "a_raid"));
+        assert!(!matches("", "anything"));
+
+        // Punctuation at the keyword's own edges needs no boundary.
+        assert!(matches("c++", "c++, anyone?"));
+        assert!(matches("@team", "hey@team"));
+        assert!(!matches("c++", "abc++"));
+
+        // A later occurrence can match where an earlier one does not.
+        assert!(matches("gg", "eggs then gg"));
+    }
+
+    #[test]
+    fn test_matches_unicode_boundaries() {
+        assert!(matches("café", "Le CAFÉ est ouvert"));
+        assert!(matches("naïve", "how «naïve»!"));
+        assert!(!matches("über", "überall"));
+        assert!(matches("Ωmega", "ωmega—now"));
+        assert!(matches("ゲーム", "今日はゲームする"));
+        assert!(matches("raid", "🔥raid🔥"));
+        assert!(!matches("raid", "raidé"));
+    }
+
+    #[test]
+    fn test_find_and_new() {
+        assert_eq!(Keyword::new("  raid \t night "), Keyword::new("raid night"));
+        assert_eq!(Keyword::new("   "), None);
+
+        let keywords = vec![
+            Keyword {
+                text: "deploy".to_string(),
+                sound: false,
+            },
+            Keyword::new("raid").unwrap(),
+        ];
+        assert_eq!(find(&keywords, "RAID at 9").map(|k| k.sound), Some(true));
+        assert_eq!(find(&keywords, "deployed it"), None);
+
+        let json = r#"[{"text":"raid"}]"#;
+        let parsed: Vec<Keyword> = serde_json::from_str(json).unwrap();
+        assert!(parsed[0].sound);
+    }
+}
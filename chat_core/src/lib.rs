@@ -1,6 +1,30 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub mod avatar;
+pub mod bridge;
+pub mod commands;
+pub mod emoji;
+pub mod favorites;
+pub mod fuzzy;
+pub mod game_invite;
+pub mod hash;
+pub mod html;
+pub mod idle;
+pub mod input_history;
+pub mod keybindings;
+pub mod keywords;
+pub mod link_preview;
+pub mod mention;
+pub mod outbox;
+pub mod permalink;
+pub mod quiet_hours;
+pub mod rich_presence;
+pub mod schedule;
+pub mod send_guard;
+pub mod spelling;
+pub mod time;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum UserStatus {
     Online,
     Idle,
@@ -8,6 +32,25 @@ pub enum UserStatus {
     Offline,
 }
 
+impl UserStatus {
+    pub const ALL: [UserStatus; 4] = [Self::Online, Self::Idle, Self::DoNotDisturb, Self::Offline];
+
+    /// The label shown in the status picker.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Online => "Online",
+            Self::Idle => "Idle",
+            Self::DoNotDisturb => "Do Not Disturb",
+            Self::Offline => "Offline",
+        }
+    }
+
+    /// Parse the label shown in the status picker.
+    pub fn from_label(label: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.label() == label)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
@@ -16,36 +59,376 @@ pub struct User {
     pub status: UserStatus,
 }
 
+/// A member's standing in a room, from its power levels. Ordered highest
+/// first, the order the member list groups by.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemberRole {
+    Admin,
+    Moderator,
+    Member,
+}
+
+impl MemberRole {
+    /// The role for a power level, using the levels Matrix clients
+    /// conventionally give admins (100) and moderators (50).
+    pub fn from_power_level(level: i64) -> Self {
+        match level {
+            100.. => Self::Admin,
+            50.. => Self::Moderator,
+            _ => Self::Member,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Admin => "Admin",
+            Self::Moderator => "Moderator",
+            Self::Member => "Member",
+        }
+    }
+}
+
+/// A user joined to a room.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Member {
+    pub user_id: String,
+    /// Display name in the room, when set.
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub role: MemberRole,
+    pub status: UserStatus,
+    /// Free text shown with the status, e.g. "Playing Valorant".
+    #[serde(default)]
+    pub status_message: Option<String>,
+}
+
+impl Member {
+    /// Name to show: display name, else the MXID localpart.
+    pub fn name(&self) -> &str {
+        self.display_name
+            .as_deref()
+            .unwrap_or_else(|| localpart(&self.user_id))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum RoomType {
     Direct,
     Group,
     Public,
+    Space,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Room {
     pub id: String,
     pub name: String,
     pub topic: Option<String>,
     pub room_type: RoomType,
     pub avatar_url: Option<String>,
+    /// Timestamp (ms) of the latest event we know about, used for ordering.
+    #[serde(default)]
+    pub last_activity: u64,
+    #[serde(default)]
+    pub unread: UnreadCounts,
+    /// Notifications are off for this room; it still shows its own count.
+    #[serde(default)]
+    pub muted: bool,
+    /// The room's published address, e.g. `#lobby:example.org`.
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// For a direct chat, the user ids of who it is with.
+    #[serde(default)]
+    pub direct_targets: Vec<String>,
+    /// Messages are end-to-end encrypted.
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Joined members, as last synced.
+    #[serde(default)]
+    pub member_count: u64,
+}
+
+/// Unread counters for a room, as computed by the homeserver.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct UnreadCounts {
+    /// Messages that would notify.
+    pub notifications: u64,
+    /// Mentions and keyword hits, a subset of `notifications`.
+    pub highlights: u64,
+}
+
+impl std::ops::Add for UnreadCounts {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            notifications: self.notifications + other.notifications,
+            highlights: self.highlights + other.highlights,
+        }
+    }
 }
 
+impl std::iter::Sum for UnreadCounts {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |a, b| a + b)
+    }
+}
+
+/// A space groups rooms together; shown as a server in the rail.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Space {
+    pub id: String,
+    pub name: String,
+    pub avatar_url: Option<String>,
+    /// Room ids of the space's children, in `m.space.child` order.
+    pub children: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub enum MessageType {
+    #[default]
     Text,
+    /// An action, shown as "* alice waves", as sent with `/me`.
+    Emote,
     Image,
     File,
+    /// A call that rang out unanswered; `sender` is who missed it.
+    MissedCall,
+    /// A line the client made up rather than received, e.g. "alice joined
+    /// voice". Its id is never an event id, so it is never marked read.
+    SystemNotice(Notice),
+    /// An invite to join a game; `content` is the body sent along for
+    /// other clients.
+    GameInvite(game_invite::GameInvite),
+    /// `sender`'s membership of the room changed, whoever changed it.
+    Membership(MembershipChange),
+    /// A run of membership changes folded into one row. `content` is
+    /// empty; the interface words the counts in its language.
+    MembershipSummary(MembershipSummary),
+    /// `sender` took another display name; `content` tells which.
+    DisplayName,
+    /// `sender` changed their avatar.
+    Avatar,
+    /// `sender` reacted to `reply_to` with `content`, shown as a row of
+    /// its own when the timeline filter asks for that.
+    Reaction,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// What a `SystemNotice` is about.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Notice {
+    /// Someone joined or left the room's voice channel.
+    #[default]
+    Voice,
+    /// The room's topic changed.
+    Topic,
+    /// Someone's keys changed since we verified them.
+    Security,
+}
+
+/// How someone's membership of a room changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MembershipChange {
+    Joined,
+    Left,
+    Invited,
+    /// Kicked or banned.
+    Removed,
+}
+
+/// How many people's membership changed each way in a run of changes.
+/// Each person counts once per kind of change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipSummary {
+    pub joined: usize,
+    pub left: usize,
+    pub invited: usize,
+    pub removed: usize,
+}
+
+impl MessageType {
+    /// A line about the room rather than something said in it: a notice,
+    /// or a row for a membership, profile or reaction event.
+    pub fn is_notice(&self) -> bool {
+        matches!(
+            self,
+            MessageType::SystemNotice(_)
+                | MessageType::Membership(_)
+                | MessageType::MembershipSummary(_)
+                | MessageType::DisplayName
+                | MessageType::Avatar
+                | MessageType::Reaction
+        )
+    }
+
+    /// Shown on a row of its own, never grouped with what is around it.
+    pub fn stands_alone(&self) -> bool {
+        *self == MessageType::MissedCall || self.is_notice()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct Message {
     pub id: String,
     pub sender: String,
+    /// Sender's display name in the room, when known.
+    #[serde(default)]
+    pub sender_name: Option<String>,
     pub content: String,
     pub schema: MessageType,
+    /// Origin server timestamp in milliseconds since the Unix epoch.
     pub timestamp: u64,
+    /// Event id of the message this one replies to.
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    /// The body was replaced by an edit.
+    #[serde(default)]
+    pub edited: bool,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    /// The image or file of an `Image` or `File` message.
+    #[serde(default)]
+    pub attachment: Option<Attachment>,
+    /// Members mentioned in the body, for messages we send.
+    #[serde(default)]
+    pub mentions: Vec<mention::Mention>,
+    /// The body as HTML, when the sender formatted it. `content` holds the
+    /// same message as plain text.
+    #[serde(default)]
+    pub formatted: Option<String>,
+    /// How far the message can be trusted to be from `sender`, as the
+    /// crypto store told on decrypting it. `None` when it wasn't encrypted.
+    #[serde(default)]
+    pub trust: Option<MessageTrust>,
+}
+
+/// How far an encrypted message can be trusted to be from its sender.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SenderTrust {
+    /// Sent from a device of theirs they signed, and we verified them.
+    Verified,
+    /// From a device we can't tie to a sender we verified.
+    #[default]
+    Unverified,
+    /// Their keys aren't the ones they had when we verified them: their
+    /// account was reset, or someone else holds it.
+    KeysChanged,
+}
+
+/// Who vouches for an encrypted message.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageTrust {
+    /// The device it was sent from, when known.
+    pub device_id: Option<String>,
+    pub trust: SenderTrust,
+}
+
+/// Media sent as a message. Sources are opaque to everything but the
+/// network crate, which fetches them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Attachment {
+    pub source: String,
+    /// A smaller rendition the sender uploaded, if any.
+    pub thumbnail_source: Option<String>,
+    pub name: String,
+    pub mimetype: Option<String>,
+    /// Size in bytes, when the sender gave it.
+    pub size: Option<u64>,
+    /// Pixel dimensions of an image, when the sender gave them.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl Attachment {
+    /// A GIF or APNG, which may be animated.
+    pub fn is_animated(&self) -> bool {
+        matches!(self.mimetype.as_deref(), Some("image/gif" | "image/apng"))
+    }
+}
+
+/// A byte count for display: "512 B", "1.5 KB", "12.0 MB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// One user's reaction to a message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+    /// The emoji, or whatever text the client reacted with.
+    pub key: String,
+    pub sender: String,
+    /// The reaction's own event id, which redacting it removes it by.
+    pub event_id: String,
+}
+
+impl Message {
+    /// Name to show for the sender: display name, else the MXID localpart.
+    pub fn sender_display(&self) -> &str {
+        self.sender_name
+            .as_deref()
+            .unwrap_or_else(|| localpart(&self.sender))
+    }
+
+    /// Whether this message continues a group started by `prev`: same sender
+    /// within `time::GROUP_WINDOW_MS`, and not earlier than it. Missed calls
+    /// and system notices stand on their own.
+    pub fn continues(&self, prev: &Message) -> bool {
+        !self.schema.stands_alone()
+            && !prev.schema.stands_alone()
+            && self.sender == prev.sender
+            && self.timestamp >= prev.timestamp
+            && self.timestamp - prev.timestamp <= time::GROUP_WINDOW_MS
+            && time::same_day(prev.timestamp, self.timestamp)
+    }
+
+    /// Reactions grouped by key in the order first seen: the key, how many
+    /// reacted with it, and whether `user_id` is one of them.
+    pub fn reaction_counts(&self, user_id: &str) -> Vec<(&str, usize, bool)> {
+        let mut counts: Vec<(&str, usize, bool)> = Vec::new();
+        for reaction in &self.reactions {
+            let own = reaction.sender == user_id;
+            match counts.iter_mut().find(|(key, _, _)| *key == reaction.key) {
+                Some(entry) => {
+                    entry.1 += 1;
+                    entry.2 |= own;
+                }
+                None => counts.push((&reaction.key, 1, own)),
+            }
+        }
+        counts
+    }
+}
+
+/// The body of a reply without the quoted fallback other clients put in
+/// front of it: `> <@alice:example.org> quoted` lines and a blank line.
+pub fn strip_reply_fallback(body: &str) -> &str {
+    if !body.starts_with("> ") {
+        return body;
+    }
+    let mut rest = body;
+    while let Some(line) = rest.strip_prefix('>') {
+        rest = match line.find('\n') {
+            Some(end) => &line[end + 1..],
+            None => "",
+        };
+    }
+    rest.strip_prefix('\n').unwrap_or(rest)
+}
+
+/// The localpart of a Matrix user id: `@alice:example.org` -> `alice`.
+pub fn localpart(user_id: &str) -> &str {
+    let id = user_id.strip_prefix('@').unwrap_or(user_id);
+    id.split(':').next().unwrap_or(id)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -98,14 +481,31 @@ mod tests {
         assert_eq!(user.status, deserialized.status);
     }
 
+    #[test]
+    fn test_status_labels_round_trip() {
+        for status in UserStatus::ALL {
+            assert_eq!(UserStatus::from_label(status.label()), Some(status));
+        }
+        assert_eq!(UserStatus::from_label("Invisible"), None);
+    }
+
+    #[test]
+    fn test_roles_from_power_levels() {
+        assert_eq!(MemberRole::from_power_level(100), MemberRole::Admin);
+        assert_eq!(MemberRole::from_power_level(75), MemberRole::Moderator);
+        assert_eq!(MemberRole::from_power_level(0), MemberRole::Member);
+        assert_eq!(MemberRole::from_power_level(-1), MemberRole::Member);
+        assert!(MemberRole::Admin < MemberRole::Member);
+    }
+
     #[test]
     fn test_message_serialization() {
         let message = Message {
             id: "msg1".to_string(),
             sender: "user123".to_string(),
             content: "Hello World".to_string(),
-            schema: MessageType::Text,
             timestamp: 1678888888,
+            ..Message::default()
         };
 
         let json = serde_json::to_string(&message).unwrap();
@@ -114,4 +514,92 @@ mod tests {
         assert_eq!(message.content, deserialized.content);
         assert_eq!(message.schema, deserialized.schema);
     }
+
+    fn message_at(sender: &str, timestamp: u64) -> Message {
+        Message {
+            id: format!("${}", timestamp),
+            sender: sender.to_string(),
+            content: "hi".to_string(),
+            timestamp,
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(12 * 1024 * 1024), "12.0 MB");
+    }
+
+    #[test]
+    fn test_message_grouping() {
+        let first = message_at("@alice:example.org", 1_000_000);
+        let soon = message_at("@alice:example.org", 1_000_000 + 60_000);
+        let late = message_at("@alice:example.org", 1_000_000 + 10 * 60_000);
+        let other = message_at("@bob:example.org", 1_000_000 + 1_000);
+
+        assert!(soon.continues(&first));
+        assert!(!late.continues(&first));
+        assert!(!other.continues(&first));
+        assert!(!first.continues(&soon));
+
+        let mut missed = soon.clone();
+        missed.schema = MessageType::MissedCall;
+        assert!(!missed.continues(&first));
+        assert!(!soon.continues(&missed));
+
+        let mut notice = soon.clone();
+        notice.schema = MessageType::SystemNotice(Notice::Voice);
+        assert!(!notice.continues(&first));
+        assert!(!soon.continues(&notice));
+
+        let mut joined = soon.clone();
+        joined.schema = MessageType::Membership(MembershipChange::Joined);
+        assert!(!joined.continues(&first));
+        assert!(!soon.continues(&joined));
+    }
+
+    #[test]
+    fn test_sender_display() {
+        let mut message = message_at("@alice:example.org", 0);
+        assert_eq!(message.sender_display(), "alice");
+        message.sender_name = Some("Alice".to_string());
+        assert_eq!(message.sender_display(), "Alice");
+        assert_eq!(localpart("bob"), "bob");
+    }
+
+    #[test]
+    fn test_reactions_grouped_by_key() {
+        let mut message = message_at("@alice:example.org", 0);
+        for (key, sender) in [
+            ("👍", "@bob:example.org"),
+            ("🎉", "@alice:example.org"),
+            ("👍", "@alice:example.org"),
+        ] {
+            message.reactions.push(Reaction {
+                key: key.to_string(),
+                sender: sender.to_string(),
+                event_id: format!("${}{}", key, sender),
+            });
+        }
+        assert_eq!(
+            message.reaction_counts("@alice:example.org"),
+            [("👍", 2, true), ("🎉", 1, true)]
+        );
+        assert_eq!(
+            message.reaction_counts("@bob:example.org"),
+            [("👍", 2, true), ("🎉", 1, false)]
+        );
+    }
+
+    #[test]
+    fn test_reply_fallback_is_stripped() {
+        assert_eq!(
+            strip_reply_fallback("> <@bob:example.org> hi\n> there\n\nhello"),
+            "hello"
+        );
+        assert_eq!(strip_reply_fallback("hello"), "hello");
+        assert_eq!(strip_reply_fallback(">no space"), ">no space");
+    }
 }
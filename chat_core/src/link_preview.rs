@@ -0,0 +1,93 @@
+//! Link previews: the card shown under a message for the first web link in
+//! it, made from the page's title, description and image.
+
+use crate::Attachment;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// What the homeserver found on a linked page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// e.g. "YouTube".
+    pub site_name: Option<String>,
+    /// The page's image, as stored on the homeserver.
+    pub image: Option<Attachment>,
+}
+
+/// Punctuation that ends a sentence around a link rather than the link.
+fn trailing(c: char) -> bool {
+    matches!(
+        c,
+        '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | '>' | ')' | ']'
+    )
+}
+
+/// Where the http(s) link in `word` is, without the punctuation around it.
+/// A closing parenthesis is kept when the link opened one, as Wikipedia
+/// links do.
+pub(crate) fn link_in(word: &str) -> Option<Range<usize>> {
+    let start = word.find(|c: char| !matches!(c, '<' | '(' | '[' | '"' | '\''))?;
+    let word = &word[start..];
+    let scheme = word.get(..8)?.to_ascii_lowercase();
+    let rest = if scheme == "https://" {
+        &word[8..]
+    } else if scheme.starts_with("http://") {
+        &word[7..]
+    } else {
+        return None;
+    };
+    let mut url = word;
+    while let Some(c) = url.chars().next_back().filter(|&c| trailing(c)) {
+        if c == ')' && url.matches('(').count() >= url.matches(')').count() {
+            break;
+        }
+        url = &url[..url.len() - 1];
+    }
+    let host_len = rest.len() - (word.len() - url.len());
+    (host_len > 0 && !rest.starts_with('/')).then_some(start..start + url.len())
+}
+
+/// The first http(s) link in `body`, without the punctuation around it.
+/// Quoted lines of a reply are skipped, as their links are previewed on
+/// the message they quote.
+pub fn first_url(body: &str) -> Option<&str> {
+    body.lines()
+        .filter(|line| !line.starts_with('>'))
+        .flat_map(str::split_whitespace)
+        .find_map(|word| link_in(word).map(|link| &word[link]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_url() {
+        assert_eq!(
+            first_url("check https://example.org/a?b=c out"),
+            Some("https://example.org/a?b=c")
+        );
+        assert_eq!(
+            first_url("see (http://example.org/x), then"),
+            Some("http://example.org/x")
+        );
+        assert_eq!(
+            first_url("HTTPS://Example.org."),
+            Some("HTTPS://Example.org")
+        );
+        assert_eq!(
+            first_url("<https://en.wikipedia.org/wiki/Rust_(video_game)>"),
+            Some("https://en.wikipedia.org/wiki/Rust_(video_game)")
+        );
+        assert_eq!(
+            first_url("> https://quoted.org\n\nhttps://reply.org"),
+            Some("https://reply.org")
+        );
+        assert_eq!(first_url("a https:// b http:///x c"), None);
+        assert_eq!(first_url("ftp://example.org and httpsfoo"), None);
+        assert_eq!(first_url("no links here"), None);
+    }
+}
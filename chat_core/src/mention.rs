@@ -0,0 +1,237 @@
+//! Mentions of room members: the `@name` typed at the caret, the pills that
+//! keep a completed mention attached to its name while the text around it
+//! is edited, and the HTML that links each one to its member on sending.
+
+use serde::{Deserialize, Serialize};
+
+/// A member mentioned at bytes `start..end` of a message body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mention {
+    pub user_id: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The `@query` typed just before byte offset `caret` in `text`, as the
+/// byte offset of the '@' and what follows it. The '@' must start a word,
+/// so e-mail addresses are left alone; the query may still be empty.
+pub fn query(text: &str, caret: usize) -> Option<(usize, &str)> {
+    let before = text.get(..caret)?;
+    let start = before.rfind('@')?;
+    let query = &before[start + 1..];
+    if query.chars().any(char::is_whitespace) {
+        return None;
+    }
+    let starts_word = before[..start]
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace);
+    starts_word.then_some((start, query))
+}
+
+/// Where members link to.
+pub fn permalink(user_id: &str) -> String {
+    format!("https://matrix.to/#/{}", user_id)
+}
+
+/// Append `text` to `out` as HTML, line breaks included.
+pub fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            '\n' => out.push_str("<br>"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// `body` as HTML with each mention a link to its member. Mentions that do
+/// not fit the body, or overlap an earlier one, stay plain text.
+pub fn html_body(body: &str, mentions: &[Mention]) -> String {
+    let mut sorted: Vec<&Mention> = mentions.iter().collect();
+    sorted.sort_by_key(|m| m.start);
+    let mut html = String::with_capacity(body.len());
+    let mut done = 0;
+    for mention in sorted {
+        let fits = done <= mention.start
+            && mention.start < mention.end
+            && body.get(mention.start..mention.end).is_some();
+        if !fits {
+            continue;
+        }
+        escape_html(&body[done..mention.start], &mut html);
+        html.push_str("<a href=\"");
+        escape_html(&permalink(&mention.user_id), &mut html);
+        html.push_str("\">");
+        escape_html(&body[mention.start..mention.end], &mut html);
+        html.push_str("</a>");
+        done = mention.end;
+    }
+    escape_html(&body[done..], &mut html);
+    html
+}
+
+/// Length in bytes of the longest common prefix of `a` and `b`.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars()
+        .zip(b.chars())
+        .take_while(|(x, y)| x == y)
+        .map(|(c, _)| c.len_utf8())
+        .sum()
+}
+
+/// Length in bytes of the longest common suffix of `a` and `b`, up to `max`.
+fn common_suffix(a: &str, b: &str, max: usize) -> usize {
+    let mut len = 0;
+    for (x, y) in a.chars().rev().zip(b.chars().rev()) {
+        if x != y || len + x.len_utf8() > max {
+            break;
+        }
+        len += x.len_utf8();
+    }
+    len
+}
+
+/// The mentions in a message being written. Each covers the name it was
+/// completed to; edits before or after it move it along, and an edit
+/// inside it turns it back into plain text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pills {
+    /// Sorted by position, not overlapping.
+    mentions: Vec<Mention>,
+}
+
+impl Pills {
+    pub fn mentions(&self) -> &[Mention] {
+        &self.mentions
+    }
+
+    /// The pill covering byte offset `at`, not counting its ends.
+    pub fn covering(&self, at: usize) -> Option<&Mention> {
+        self.mentions.iter().find(|m| m.start < at && at < m.end)
+    }
+
+    /// Add a pill over text just completed. It replaces any it overlaps.
+    pub fn insert(&mut self, mention: Mention) {
+        self.mentions
+            .retain(|m| m.end <= mention.start || m.start >= mention.end);
+        let at = self.mentions.partition_point(|m| m.start < mention.start);
+        self.mentions.insert(at, mention);
+    }
+
+    /// The text changed from `old` to `new`: move pills after the change and
+    /// drop those it touched.
+    pub fn edited(&mut self, old: &str, new: &str) {
+        if old == new {
+            return;
+        }
+        let prefix = common_prefix(old, new);
+        let suffix = common_suffix(old, new, old.len().min(new.len()) - prefix);
+        let changed_end = old.len() - suffix;
+        self.mentions.retain_mut(|m| {
+            if m.end <= prefix {
+                true
+            } else if m.start >= changed_end {
+                m.start = m.start + new.len() - old.len();
+                m.end = m.end + new.len() - old.len();
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.mentions.clear();
+    }
+
+    /// The mentions of `text` once sent, which trims it, leaving none
+    /// behind.
+    pub fn take(&mut self, text: &str) -> Vec<Mention> {
+        let trimmed = text.trim();
+        let offset = text.len() - text.trim_start().len();
+        let mut mentions = std::mem::take(&mut self.mentions);
+        mentions.retain_mut(|m| {
+            let Some(start) = m.start.checked_sub(offset) else {
+                return false;
+            };
+            m.start = start;
+            m.end -= offset;
+            trimmed.get(m.start..m.end).is_some()
+        });
+        mentions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention(user_id: &str, start: usize, end: usize) -> Mention {
+        Mention {
+            user_id: user_id.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        assert_eq!(query("hi @al", 6), Some((3, "al")));
+        assert_eq!(query("@", 1), Some((0, "")));
+        assert_eq!(query("mail me@example.org", 19), None);
+        assert_eq!(query("hi @al there", 12), None);
+        assert_eq!(query("hi @al there", 6), Some((3, "al")));
+        assert_eq!(query("hi", 10), None);
+    }
+
+    #[test]
+    fn test_html_body_links_mentions() {
+        let body = "@Alice & @Bob <3";
+        let mentions = [mention("@bob:x.org", 9, 13), mention("@alice:x.org", 0, 6)];
+        assert_eq!(
+            html_body(body, &mentions),
+            "<a href=\"https://matrix.to/#/@alice:x.org\">@Alice</a> &amp; \
+             <a href=\"https://matrix.to/#/@bob:x.org\">@Bob</a> &lt;3"
+        );
+        assert_eq!(html_body("hi", &[mention("@a:x.org", 1, 9)]), "hi");
+    }
+
+    #[test]
+    fn test_pills_survive_edits_around_them() {
+        let mut pills = Pills::default();
+        pills.insert(mention("@alice:x.org", 3, 9));
+        // "hi @Alice" -> "oh hi @Alice": moved along.
+        pills.edited("hi @Alice", "oh hi @Alice");
+        assert_eq!(pills.mentions(), [mention("@alice:x.org", 6, 12)]);
+        // Typing after it keeps it.
+        pills.edited("oh hi @Alice", "oh hi @Alice!");
+        assert_eq!(pills.mentions(), [mention("@alice:x.org", 6, 12)]);
+        // Replacing text before it drops the pill there.
+        pills.insert(mention("@bob:x.org", 0, 2));
+        pills.edited("oh hi @Alice!", "é hi @Alice!");
+        assert_eq!(pills.mentions(), [mention("@alice:x.org", 6, 12)]);
+        // Editing inside it drops it.
+        pills.edited("é hi @Alice!", "é hi @Alce!");
+        assert!(pills.mentions().is_empty());
+    }
+
+    #[test]
+    fn test_pills_take_what_fits() {
+        let mut pills = Pills::default();
+        pills.insert(mention("@bob:x.org", 7, 11));
+        pills.insert(mention("@alice:x.org", 0, 6));
+        assert_eq!(pills.covering(3).unwrap().user_id, "@alice:x.org");
+        assert_eq!(pills.covering(6), None);
+        let taken = pills.take("@Alice");
+        assert_eq!(taken, [mention("@alice:x.org", 0, 6)]);
+        pills.insert(mention("@alice:x.org", 3, 9));
+        let taken = pills.take("   @Alice  ");
+        assert_eq!(taken, [mention("@alice:x.org", 0, 6)]);
+        assert!(pills.mentions().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+//! Messages written but not yet delivered. They are kept until they are,
+//! so that ones left behind by a crash can be offered again on the next
+//! start: sent as they were, under their transaction ids so none arrives
+//! twice, or discarded. Messages scheduled for later wait here too, until
+//! they are due.
+
+use crate::mention::Mention;
+use serde::{Deserialize, Serialize};
+
+/// Unsent messages older than this start out unticked for sending again.
+pub const DEFAULT_MAX_AGE_HOURS: u64 = 24;
+
+/// A text message waiting to be delivered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unsent {
+    pub room_id: String,
+    /// As the room was named when the message was written.
+    #[serde(default)]
+    pub room_name: String,
+    pub txn_id: String,
+    pub body: String,
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    /// When it was written, in ms since the epoch.
+    pub timestamp: u64,
+    /// When to send it, in ms since the epoch, if it was scheduled.
+    #[serde(default)]
+    pub send_at: Option<u64>,
+}
+
+impl Unsent {
+    /// Whether it was written more than `max_age_hours` before `now` (ms),
+    /// and so is likely not worth sending any more.
+    pub fn is_stale(&self, now: u64, max_age_hours: u64) -> bool {
+        now.saturating_sub(self.timestamp) > max_age_hours.saturating_mul(60 * 60 * 1000)
+    }
+
+    /// Whether it waits to be sent later than `now` (ms).
+    pub fn is_scheduled_after(&self, now: u64) -> bool {
+        self.send_at.is_some_and(|at| at > now)
+    }
+}
+
+/// Messages of one room, oldest first.
+#[derive(Debug, PartialEq)]
+pub struct RoomGroup<'a> {
+    pub room_id: &'a str,
+    pub room_name: &'a str,
+    pub messages: Vec<&'a Unsent>,
+}
+
+/// `messages` by room, the rooms in the order their first message was
+/// written and the messages in theirs.
+pub fn by_room(messages: &[Unsent]) -> Vec<RoomGroup<'_>> {
+    let mut sorted: Vec<&Unsent> = messages.iter().collect();
+    sorted.sort_by_key(|m| m.timestamp);
+    let mut groups: Vec<RoomGroup> = Vec::new();
+    for message in sorted {
+        match groups.iter_mut().find(|g| g.room_id == message.room_id) {
+            Some(group) => group.messages.push(message),
+            None => groups.push(RoomGroup {
+                room_id: &message.room_id,
+                room_name: &message.room_name,
+                messages: vec![message],
+            }),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    fn unsent(room: &str, txn_id: &str, timestamp: u64) -> Unsent {
+        Unsent {
+            room_id: format!("!{}:example.org", room),
+            room_name: room.to_string(),
+            txn_id: txn_id.to_string(),
+            body: format!("message {}", txn_id),
+            mentions: Vec::new(),
+            reply_to: None,
+            timestamp,
+            send_at: None,
+        }
+    }
+
+    #[test]
+    fn test_grouped_by_room_in_writing_order() {
+        let messages = vec![
+            unsent("lobby", "t3", 30),
+            unsent("raids", "t2", 20),
+            unsent("lobby", "t1", 10),
+        ];
+        let groups = by_room(&messages);
+        let shown: Vec<(&str, Vec<&str>)> = groups
+            .iter()
+            .map(|g| {
+                let txn_ids = g.messages.iter().map(|m| m.txn_id.as_str()).collect();
+                (g.room_name, txn_ids)
+            })
+            .collect();
+        assert_eq!(
+            shown,
+            vec![("lobby", vec!["t1", "t3"]), ("raids", vec!["t2"])]
+        );
+        assert!(by_room(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_staleness() {
+        let message = unsent("lobby", "t1", 100 * HOUR);
+        assert!(!message.is_stale(110 * HOUR, DEFAULT_MAX_AGE_HOURS));
+        assert!(!message.is_stale(124 * HOUR, DEFAULT_MAX_AGE_HOURS));
+        assert!(message.is_stale(125 * HOUR, DEFAULT_MAX_AGE_HOURS));
+        // A clock set back doesn't make it stale.
+        assert!(!message.is_stale(50 * HOUR, DEFAULT_MAX_AGE_HOURS));
+    }
+
+    #[test]
+    fn test_scheduled() {
+        let mut message = unsent("lobby", "t1", 100 * HOUR);
+        assert!(!message.is_scheduled_after(100 * HOUR));
+        message.send_at = Some(102 * HOUR);
+        assert!(message.is_scheduled_after(101 * HOUR));
+        assert!(!message.is_scheduled_after(102 * HOUR));
+    }
+}
@@ -0,0 +1,462 @@
+//! Links to rooms, messages and users, in both forms the spec gives:
+//! matrix.to permalinks, e.g. `https://matrix.to/#/%23lobby%3Aexample.org`,
+//! and `matrix:` URIs, e.g. `matrix:r/lobby:example.org`. Ids may come
+//! percent-encoded or not; anything that doesn't make a whole link is
+//! refused rather than guessed at.
+
+use crate::{Member, MemberRole};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+const MATRIX_TO: &str = "https://matrix.to/#/";
+
+/// What a matrix.to link escapes in an id: all but `!` and the unreserved
+/// characters, as in the spec's examples.
+const MATRIX_TO_ID: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'!');
+
+/// What a `matrix:` URI escapes in a path segment.
+const URI_SEGMENT: &AsciiSet = &MATRIX_TO_ID
+    .remove(b':')
+    .remove(b'@')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=');
+
+/// What either escapes in a `via` server name, which may have a port.
+const QUERY_VALUE: &AsciiSet = &MATRIX_TO_ID.remove(b':');
+
+/// Most servers a link names to join through, as the spec suggests.
+const MAX_VIA: usize = 3;
+
+/// What a link points at.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Permalink {
+    /// A room by id or alias, with servers to join it through.
+    Room {
+        id_or_alias: String,
+        via: Vec<String>,
+    },
+    /// A message in a room.
+    Event {
+        id_or_alias: String,
+        event_id: String,
+        via: Vec<String>,
+    },
+    User(String),
+}
+
+impl Permalink {
+    /// The link as `https://matrix.to/#/…`.
+    pub fn matrix_to(&self) -> String {
+        let mut link = String::from(MATRIX_TO);
+        let via = match self {
+            Permalink::Room { id_or_alias, via } => {
+                link.extend(utf8_percent_encode(id_or_alias, MATRIX_TO_ID));
+                via.as_slice()
+            }
+            Permalink::Event {
+                id_or_alias,
+                event_id,
+                via,
+            } => {
+                link.extend(utf8_percent_encode(id_or_alias, MATRIX_TO_ID));
+                link.push('/');
+                link.extend(utf8_percent_encode(event_id, MATRIX_TO_ID));
+                via.as_slice()
+            }
+            Permalink::User(user_id) => {
+                link.extend(utf8_percent_encode(user_id, MATRIX_TO_ID));
+                &[]
+            }
+        };
+        push_via(&mut link, via);
+        link
+    }
+
+    /// The link as a `matrix:` URI.
+    pub fn matrix_uri(&self) -> String {
+        let mut uri = String::from("matrix:");
+        let (id, event_id, via) = match self {
+            Permalink::Room { id_or_alias, via } => (id_or_alias, None, via.as_slice()),
+            Permalink::Event {
+                id_or_alias,
+                event_id,
+                via,
+            } => (id_or_alias, Some(event_id), via.as_slice()),
+            Permalink::User(user_id) => (user_id, None, &[][..]),
+        };
+        let (kind, bare) = match id.split_at(id.chars().next().map_or(0, char::len_utf8)) {
+            ("#", bare) => ("r", bare),
+            ("@", bare) => ("u", bare),
+            (_, bare) => ("roomid", bare),
+        };
+        uri.push_str(kind);
+        uri.push('/');
+        uri.extend(utf8_percent_encode(bare, URI_SEGMENT));
+        if let Some(event_id) = event_id {
+            uri.push_str("/e/");
+            let bare = event_id.strip_prefix('$').unwrap_or(event_id);
+            uri.extend(utf8_percent_encode(bare, URI_SEGMENT));
+        }
+        push_via(&mut uri, via);
+        uri
+    }
+}
+
+fn push_via(link: &mut String, via: &[String]) {
+    for (i, server) in via.iter().enumerate() {
+        link.push_str(if i == 0 { "?via=" } else { "&via=" });
+        link.extend(utf8_percent_encode(server, QUERY_VALUE));
+    }
+}
+
+/// Read a matrix.to link or a `matrix:` URI.
+pub fn parse(url: &str) -> Option<Permalink> {
+    let url = url.trim();
+    let (scheme, rest) = url.split_once(':')?;
+    if scheme.eq_ignore_ascii_case("matrix") {
+        return parse_uri(rest);
+    }
+    if !scheme.eq_ignore_ascii_case("https") && !scheme.eq_ignore_ascii_case("http") {
+        return None;
+    }
+    let fragment = rest.strip_prefix("//matrix.to/#/")?;
+    let (path, query) = fragment.split_once('?').unwrap_or((fragment, ""));
+    let mut segments = path.split('/');
+    let id = decode(segments.next()?)?;
+    let event_id = segments.next().map(decode);
+    if segments.any(|s| !s.is_empty()) {
+        return None;
+    }
+    let event_id = match event_id {
+        Some(Some(event_id)) if !event_id.is_empty() => Some(event_id),
+        Some(None) => return None,
+        _ => None,
+    };
+    target(id, event_id, via(query))
+}
+
+/// The part of a `matrix:` URI after the scheme, e.g. `r/lobby:example.org`.
+fn parse_uri(rest: &str) -> Option<Permalink> {
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let segments: Vec<&str> = path.split('/').collect();
+    let (kind, id, event_id) = match segments.as_slice() {
+        [kind, id] => (*kind, *id, None),
+        [kind, id, "e" | "event", event_id] => (*kind, *id, Some(*event_id)),
+        _ => return None,
+    };
+    let sigil = match kind {
+        "u" | "user" => '@',
+        "r" | "room" => '#',
+        "roomid" => '!',
+        _ => return None,
+    };
+    let id = format!("{}{}", sigil, decode(id)?);
+    let event_id = match event_id {
+        Some(event_id) => Some(format!("${}", decode(event_id)?)),
+        None => None,
+    };
+    target(id, event_id, via(query))
+}
+
+fn decode(segment: &str) -> Option<String> {
+    percent_decode_str(segment)
+        .decode_utf8()
+        .ok()
+        .map(|s| s.into_owned())
+}
+
+/// The `via` servers of a query; other parameters, e.g. `action`, are
+/// ignored.
+fn via(query: &str) -> Vec<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("via="))
+        .filter_map(decode)
+        .filter(|server| !server.is_empty())
+        .collect()
+}
+
+/// Whether `id` is one with `sigil`: a server name after the localpart,
+/// except for room and event ids, which newer room versions leave without.
+fn is_id(id: &str, sigil: char) -> bool {
+    let Some(rest) = id.strip_prefix(sigil) else {
+        return false;
+    };
+    let needs_server = matches!(sigil, '@' | '#');
+    let whole = match rest.split_once(':') {
+        Some((local, server)) => !local.is_empty() && !server.is_empty(),
+        None => !needs_server && !rest.is_empty(),
+    };
+    whole && id.len() <= 255 && !id.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn target(id: String, event_id: Option<String>, via: Vec<String>) -> Option<Permalink> {
+    if let Some(event_id) = &event_id {
+        if !is_id(event_id, '$') {
+            return None;
+        }
+    }
+    match (id.chars().next()?, event_id) {
+        ('@', None) if is_id(&id, '@') => Some(Permalink::User(id)),
+        (sigil @ ('!' | '#'), None) if is_id(&id, sigil) => Some(Permalink::Room {
+            id_or_alias: id,
+            via,
+        }),
+        (sigil @ ('!' | '#'), Some(event_id)) if is_id(&id, sigil) => Some(Permalink::Event {
+            id_or_alias: id,
+            event_id,
+            via,
+        }),
+        _ => None,
+    }
+}
+
+/// The server part of a Matrix id.
+fn server_name(id: &str) -> Option<&str> {
+    id.split_once(':').map(|(_, server)| server)
+}
+
+/// An IP address rather than a name, which the spec leaves out of `via`
+/// as it is unlikely to last.
+fn is_ip_literal(server: &str) -> bool {
+    let host = match server.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => server,
+    };
+    host.starts_with('[') || host.parse::<IpAddr>().is_ok()
+}
+
+/// Up to three servers to join `room_id` through, chosen from `members`
+/// as the spec suggests: the server of a moderator or admin, the highest
+/// there is, then those most members are on. With no members to go by,
+/// the server that made the room.
+pub fn via_servers(room_id: &str, members: &[Member]) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for member in members {
+        if let Some(server) = server_name(&member.user_id).filter(|s| !is_ip_literal(s)) {
+            *counts.entry(server).or_default() += 1;
+        }
+    }
+    let mut by_size: Vec<(&str, usize)> = counts.into_iter().collect();
+    by_size.sort_by_key(|&(server, count)| (Reverse(count), server));
+
+    // Of the highest ranked, the one on the server most members are on.
+    let rank = |server: &str| by_size.iter().position(|&(s, _)| s == server);
+    let powerful = members
+        .iter()
+        .filter(|m| m.role <= MemberRole::Moderator)
+        .filter_map(|m| Some((m.role, rank(server_name(&m.user_id)?)?)))
+        .min()
+        .map(|(_, i)| by_size[i].0);
+
+    let mut servers: Vec<String> = Vec::new();
+    for server in powerful
+        .into_iter()
+        .chain(by_size.iter().map(|&(server, _)| server))
+    {
+        if servers.len() == MAX_VIA {
+            break;
+        }
+        if !servers.iter().any(|s| s == server) {
+            servers.push(server.to_string());
+        }
+    }
+    if servers.is_empty() {
+        servers.extend(server_name(room_id).map(str::to_string));
+    }
+    servers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserStatus;
+
+    fn room(id_or_alias: &str, via: &[&str]) -> Permalink {
+        Permalink::Room {
+            id_or_alias: id_or_alias.to_string(),
+            via: via.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn event(id_or_alias: &str, event_id: &str, via: &[&str]) -> Permalink {
+        Permalink::Event {
+            id_or_alias: id_or_alias.to_string(),
+            event_id: event_id.to_string(),
+            via: via.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn user(user_id: &str) -> Permalink {
+        Permalink::User(user_id.to_string())
+    }
+
+    #[test]
+    fn test_spec_matrix_to_examples() {
+        let examples = [
+            (
+                "https://matrix.to/#/%23somewhere%3Aexample.org",
+                room("#somewhere:example.org", &[]),
+            ),
+            (
+                "https://matrix.to/#/!somewhere%3Aexample.org?via=elsewhere.ca",
+                room("!somewhere:example.org", &["elsewhere.ca"]),
+            ),
+            (
+                "https://matrix.to/#/!somewhere%3Aexample.org/%24event%3Aexample.org?via=elsewhere.ca",
+                event(
+                    "!somewhere:example.org",
+                    "$event:example.org",
+                    &["elsewhere.ca"],
+                ),
+            ),
+            (
+                "https://matrix.to/#/%23somewhere:example.org/%24event:example.org",
+                event("#somewhere:example.org", "$event:example.org", &[]),
+            ),
+            (
+                "https://matrix.to/#/%40alice%3Aexample.org",
+                user("@alice:example.org"),
+            ),
+        ];
+        for (link, target) in &examples {
+            assert_eq!(parse(link).as_ref(), Some(target), "{}", link);
+        }
+        // Generated as the spec writes them.
+        assert_eq!(examples[0].1.matrix_to(), examples[0].0);
+        assert_eq!(examples[1].1.matrix_to(), examples[1].0);
+        assert_eq!(examples[2].1.matrix_to(), examples[2].0);
+        assert_eq!(examples[4].1.matrix_to(), examples[4].0);
+    }
+
+    #[test]
+    fn test_spec_matrix_uri_examples() {
+        let examples = [
+            (
+                "matrix:r/somewhere:example.org",
+                room("#somewhere:example.org", &[]),
+            ),
+            (
+                "matrix:roomid/somewhere:example.org?via=elsewhere.ca",
+                room("!somewhere:example.org", &["elsewhere.ca"]),
+            ),
+            (
+                "matrix:r/somewhere:example.org/e/event:example.org",
+                event("#somewhere:example.org", "$event:example.org", &[]),
+            ),
+            (
+                "matrix:roomid/somewhere:example.org/e/event:example.org?via=elsewhere.ca",
+                event(
+                    "!somewhere:example.org",
+                    "$event:example.org",
+                    &["elsewhere.ca"],
+                ),
+            ),
+            ("matrix:u/alice:example.org", user("@alice:example.org")),
+        ];
+        for (uri, target) in &examples {
+            assert_eq!(parse(uri).as_ref(), Some(target), "{}", uri);
+            assert_eq!(target.matrix_uri(), *uri);
+        }
+        assert_eq!(
+            parse("matrix:u/alice:example.org?action=chat"),
+            Some(user("@alice:example.org"))
+        );
+    }
+
+    #[test]
+    fn test_tolerant_but_strict() {
+        // Unencoded, and an event id of a newer room version.
+        assert_eq!(
+            parse("  https://matrix.to/#/!abc:example.org/$Rqnc-F-dvnEYJTyHq_iKxU2bZ1CI92-kuZq3a5lr5Zg?via=example.org&via=other.org:8448 "),
+            Some(event(
+                "!abc:example.org",
+                "$Rqnc-F-dvnEYJTyHq_iKxU2bZ1CI92-kuZq3a5lr5Zg",
+                &["example.org", "other.org:8448"],
+            ))
+        );
+        assert_eq!(
+            parse("MATRIX:r/caf%C3%A9:example.org"),
+            Some(room("#café:example.org", &[]))
+        );
+        let id = room("#a/b?c:example.org", &["example.org"]);
+        assert_eq!(parse(&id.matrix_to()), Some(id.clone()));
+        assert_eq!(parse(&id.matrix_uri()), Some(id));
+
+        for bad in [
+            "",
+            "https://matrix.to/#/",
+            "https://matrix.to/#/nonsense",
+            "https://matrix.to/#/%23lobby",
+            "https://matrix.to/#/@alice:example.org/$event",
+            "https://matrix.to/#/!room:example.org/event",
+            "https://matrix.to/#/!room:example.org/$e/extra",
+            "https://matrix.to/#/%FF%FE:example.org",
+            "https://matrix.to/#/%23lobby%20room:example.org",
+            "https://example.org/#/%23lobby:example.org",
+            "matrix:",
+            "matrix:x/lobby:example.org",
+            "matrix:r/lobby",
+            "matrix:r/:example.org",
+            "matrix:u/alice:example.org/e/event",
+            "matrix:r/lobby:example.org/e/",
+            "matrix:roomid/abc:example.org/e",
+            "mailto:alice@example.org",
+        ] {
+            assert_eq!(parse(bad), None, "{:?}", bad);
+        }
+    }
+
+    fn member(user_id: &str, role: MemberRole) -> Member {
+        Member {
+            user_id: user_id.to_string(),
+            display_name: None,
+            avatar_url: None,
+            role,
+            status: UserStatus::Offline,
+            status_message: None,
+        }
+    }
+
+    #[test]
+    fn test_via_servers() {
+        let members = [
+            member("@a:big.org", MemberRole::Member),
+            member("@b:big.org", MemberRole::Member),
+            member("@c:big.org", MemberRole::Member),
+            member("@d:mid.org", MemberRole::Member),
+            member("@e:mid.org", MemberRole::Member),
+            member("@f:small.org", MemberRole::Moderator),
+            member("@g:tiny.org", MemberRole::Member),
+            member("@h:10.0.0.1", MemberRole::Admin),
+            member("@i:10.0.0.1", MemberRole::Member),
+        ];
+        // The moderator's server first, an admin on an IP address aside.
+        assert_eq!(
+            via_servers("!room:big.org", &members),
+            vec!["small.org", "big.org", "mid.org"]
+        );
+        assert_eq!(
+            via_servers("!room:big.org", &members[..5]),
+            vec!["big.org", "mid.org"]
+        );
+        assert_eq!(via_servers("!room:home.org", &[]), vec!["home.org"]);
+        assert!(is_ip_literal("[::1]:8448"));
+        assert!(!is_ip_literal("example.org:8448"));
+    }
+}
@@ -0,0 +1,311 @@
+//! Quiet hours: a daily window, on chosen days, during which presence goes
+//! to Do Not Disturb by itself and notifications stay silent. The status
+//! shown before comes back when it ends, unless another was picked in
+//! between; that one stands until the window's next start or end.
+
+use crate::UserStatus;
+use chrono::{Datelike, Duration, Local, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// When quiet hours are, in local time. A window ending at or before its
+/// start crosses midnight, and belongs to the day it starts on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Schedule {
+    pub enabled: bool,
+    /// Minutes after midnight.
+    pub start: u16,
+    pub end: u16,
+    /// Monday first.
+    pub days: [bool; 7],
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: 22 * 60,
+            end: 8 * 60,
+            days: [true; 7],
+        }
+    }
+}
+
+impl Schedule {
+    /// Whether `at` falls in a window.
+    pub fn is_quiet(&self, at: NaiveDateTime) -> bool {
+        if !self.enabled || self.start == self.end {
+            return false;
+        }
+        let minute = (at.hour() * 60 + at.minute()) as u16;
+        let today = at.weekday().num_days_from_monday() as usize;
+        let yesterday = (today + 6) % 7;
+        if self.start < self.end {
+            self.days[today] && (self.start..self.end).contains(&minute)
+        } else {
+            (self.days[today] && minute >= self.start)
+                || (self.days[yesterday] && minute < self.end)
+        }
+    }
+
+    /// The first time after `at` that a window starts or ends, or `None`
+    /// when none ever will.
+    pub fn next_boundary(&self, at: NaiveDateTime) -> Option<NaiveDateTime> {
+        let quiet = self.is_quiet(at);
+        let mut candidates: Vec<NaiveDateTime> = (0..=7)
+            .flat_map(|days| {
+                let date = at.date() + Duration::days(days);
+                [self.start, self.end].map(|minute| date.and_time(time_of_day(minute)))
+            })
+            .filter(|candidate| *candidate > at)
+            .collect();
+        candidates.sort();
+        candidates
+            .into_iter()
+            .find(|candidate| self.is_quiet(*candidate) != quiet)
+    }
+}
+
+/// The wall clock now, in the time zone set now, to check a schedule
+/// against.
+pub fn local_now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+fn time_of_day(minute: u16) -> NaiveTime {
+    let minute = u32::from(minute.min(MINUTES_PER_DAY - 1));
+    NaiveTime::from_hms_opt(minute / 60, minute % 60, 0).unwrap_or_default()
+}
+
+/// "22:00" as minutes after midnight.
+pub fn parse_time(text: &str) -> Option<u16> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    let hours: u16 = hours.parse().ok()?;
+    let minutes: u16 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Minutes after midnight as "22:00".
+pub fn format_time(minute: u16) -> String {
+    format!("{:02}:{:02}", minute / 60 % 24, minute % 60)
+}
+
+/// What a check changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    /// Quiet hours began; switch to the status, if any.
+    Started(Option<UserStatus>),
+    /// A status was picked during them. It stands, and notifications make
+    /// their sounds again, until the next boundary.
+    Overridden,
+    /// They ended; put back the status, if any.
+    Ended(Option<UserStatus>),
+}
+
+/// Decides when to switch presence, from whether it is quiet hours at
+/// each check and the status shown then.
+#[derive(Debug, Clone, Default)]
+pub struct QuietHours {
+    /// Inside a window as of the last check.
+    quiet: bool,
+    /// What was shown as the window started, to put back at its end.
+    before: Option<UserStatus>,
+    /// What the window shows: Do Not Disturb, or Offline left as it was.
+    held: Option<UserStatus>,
+    /// A status was picked during the window.
+    overridden: bool,
+}
+
+impl QuietHours {
+    /// Whether notifications should stay silent: inside a window, with no
+    /// status picked since it started.
+    pub fn is_silencing(&self) -> bool {
+        self.quiet && !self.overridden
+    }
+
+    /// Whether the last check was inside a window.
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// `current` is shown and it is, or isn't, `quiet` hours.
+    pub fn update(&mut self, current: UserStatus, quiet: bool) -> Option<Transition> {
+        match (self.quiet, quiet) {
+            (false, true) => {
+                // Appearing offline isn't given away by going Do Not Disturb.
+                let held = match current {
+                    UserStatus::Offline => UserStatus::Offline,
+                    _ => UserStatus::DoNotDisturb,
+                };
+                *self = Self {
+                    quiet: true,
+                    before: Some(current),
+                    held: Some(held),
+                    overridden: false,
+                };
+                Some(Transition::Started((held != current).then_some(held)))
+            }
+            (true, true) => {
+                if self.overridden || self.held == Some(current) {
+                    return None;
+                }
+                self.overridden = true;
+                Some(Transition::Overridden)
+            }
+            (true, false) => {
+                let restore = self.before.filter(|before| {
+                    !self.overridden && self.held == Some(current) && *before != current
+                });
+                *self = Self::default();
+                Some(Transition::Ended(restore))
+            }
+            (false, false) => None,
+        }
+    }
+
+    /// Forget the window, e.g. as the account signs out or quiet hours are
+    /// turned off; returns the status to put back, as at its end.
+    pub fn reset(&mut self, current: UserStatus) -> Option<UserStatus> {
+        match self.update(current, false) {
+            Some(Transition::Ended(restore)) => restore,
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// 2024-03-11 was a Monday.
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 11 + day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn weeknights() -> Schedule {
+        Schedule {
+            enabled: true,
+            start: 22 * 60,
+            end: 7 * 60 + 30,
+            days: [true, true, true, true, true, false, false],
+        }
+    }
+
+    #[test]
+    fn test_windows_crossing_midnight() {
+        let schedule = weeknights();
+        assert!(!schedule.is_quiet(at(0, 21, 59)));
+        assert!(schedule.is_quiet(at(0, 22, 0)));
+        assert!(schedule.is_quiet(at(1, 7, 29)));
+        assert!(!schedule.is_quiet(at(1, 7, 30)));
+        // Friday night runs into Saturday morning; Saturday night is free.
+        assert!(schedule.is_quiet(at(5, 3, 0)));
+        assert!(!schedule.is_quiet(at(5, 23, 0)));
+        assert!(!schedule.is_quiet(at(0, 3, 0)));
+        assert!(!Schedule {
+            enabled: false,
+            ..schedule
+        }
+        .is_quiet(at(0, 23, 0)));
+
+        let afternoon = Schedule {
+            enabled: true,
+            start: 13 * 60,
+            end: 14 * 60,
+            days: [true; 7],
+        };
+        assert!(afternoon.is_quiet(at(2, 13, 30)));
+        assert!(!afternoon.is_quiet(at(2, 14, 0)));
+    }
+
+    #[test]
+    fn test_next_boundary() {
+        let schedule = weeknights();
+        assert_eq!(schedule.next_boundary(at(0, 12, 0)), Some(at(0, 22, 0)));
+        assert_eq!(schedule.next_boundary(at(0, 23, 0)), Some(at(1, 7, 30)));
+        // From Saturday morning, past the free weekend to Monday night.
+        assert_eq!(schedule.next_boundary(at(5, 7, 30)), Some(at(7, 22, 0)));
+        let never = Schedule {
+            days: [false; 7],
+            ..schedule
+        };
+        assert_eq!(never.next_boundary(at(0, 12, 0)), None);
+    }
+
+    #[test]
+    fn test_parse_and_format_times() {
+        assert_eq!(parse_time("22:00"), Some(1320));
+        assert_eq!(parse_time(" 7:05 "), Some(425));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("noon"), None);
+        assert_eq!(format_time(425), "07:05");
+    }
+
+    #[test]
+    fn test_switches_and_restores() {
+        let mut quiet = QuietHours::default();
+        assert_eq!(quiet.update(UserStatus::Online, false), None);
+        assert_eq!(
+            quiet.update(UserStatus::Online, true),
+            Some(Transition::Started(Some(UserStatus::DoNotDisturb)))
+        );
+        assert!(quiet.is_silencing());
+        assert_eq!(quiet.update(UserStatus::DoNotDisturb, true), None);
+        assert_eq!(
+            quiet.update(UserStatus::DoNotDisturb, false),
+            Some(Transition::Ended(Some(UserStatus::Online)))
+        );
+        assert!(!quiet.is_quiet());
+
+        // Already Do Not Disturb: nothing to switch or put back.
+        assert_eq!(
+            quiet.update(UserStatus::DoNotDisturb, true),
+            Some(Transition::Started(None))
+        );
+        assert_eq!(
+            quiet.update(UserStatus::DoNotDisturb, false),
+            Some(Transition::Ended(None))
+        );
+
+        // Appearing offline stays so.
+        assert_eq!(
+            quiet.update(UserStatus::Offline, true),
+            Some(Transition::Started(None))
+        );
+        assert!(quiet.is_silencing());
+        assert_eq!(quiet.reset(UserStatus::Offline), None);
+    }
+
+    #[test]
+    fn test_picked_status_stands_until_the_boundary() {
+        let mut quiet = QuietHours::default();
+        quiet.update(UserStatus::Idle, true);
+        assert_eq!(
+            quiet.update(UserStatus::Online, true),
+            Some(Transition::Overridden)
+        );
+        assert!(!quiet.is_silencing());
+        assert_eq!(quiet.update(UserStatus::DoNotDisturb, true), None);
+        assert_eq!(
+            quiet.update(UserStatus::DoNotDisturb, false),
+            Some(Transition::Ended(None))
+        );
+
+        // The next window switches again.
+        assert_eq!(
+            quiet.update(UserStatus::DoNotDisturb, true),
+            Some(Transition::Started(None))
+        );
+        quiet.update(UserStatus::Online, true);
+        assert_eq!(
+            quiet.update(UserStatus::Online, false),
+            Some(Transition::Ended(None))
+        );
+    }
+}
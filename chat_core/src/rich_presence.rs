@@ -0,0 +1,109 @@
+//! Rich presence: the game being played, found among the running processes
+//! by its executable, and shown to others as our status message.
+
+use serde::{Deserialize, Serialize};
+
+/// A game to look for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Game {
+    /// As shown to others: "Playing {name}".
+    pub name: String,
+    /// The process's executable file name, e.g. `VALORANT-Win64-Shipping.exe`.
+    pub executable: String,
+}
+
+impl Game {
+    pub fn new(name: &str, executable: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            executable: executable.to_string(),
+        }
+    }
+
+    /// The status message published while it runs.
+    pub fn status_message(&self) -> String {
+        format!("Playing {}", self.name)
+    }
+}
+
+/// Games looked for until the user changes the list. Executable names are
+/// those of the Windows builds.
+pub fn default_games() -> Vec<Game> {
+    [
+        ("Valorant", "VALORANT-Win64-Shipping.exe"),
+        ("League of Legends", "League of Legends.exe"),
+        ("Counter-Strike 2", "cs2.exe"),
+        ("Dota 2", "dota2.exe"),
+        ("Fortnite", "FortniteClient-Win64-Shipping.exe"),
+        ("Apex Legends", "r5apex.exe"),
+        ("Overwatch 2", "Overwatch.exe"),
+        ("Rocket League", "RocketLeague.exe"),
+        ("Minecraft", "Minecraft.Windows.exe"),
+        ("Grand Theft Auto V", "GTA5.exe"),
+    ]
+    .into_iter()
+    .map(|(name, executable)| Game::new(name, executable))
+    .collect()
+}
+
+/// The game a status message says someone is playing, if it is one
+/// published by `Game::status_message`.
+pub fn game_in(status_message: &str) -> Option<&str> {
+    status_message
+        .strip_prefix("Playing ")
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// `name` without a trailing ".exe", in any case.
+fn stem(name: &str) -> &str {
+    let len = name.len();
+    match name.get(len.saturating_sub(4)..) {
+        Some(ext) if len > 4 && ext.eq_ignore_ascii_case(".exe") => &name[..len - 4],
+        _ => name,
+    }
+}
+
+/// Whether process `process` runs `executable`, ignoring case and an
+/// ".exe" on either, as other platforms name the same program without it.
+pub fn runs(executable: &str, process: &str) -> bool {
+    stem(executable).eq_ignore_ascii_case(stem(process))
+}
+
+/// The first of `games` among the `processes` running, by executable name.
+pub fn detect<'a, S: AsRef<str>>(games: &'a [Game], processes: &[S]) -> Option<&'a Game> {
+    games.iter().find(|game| {
+        processes
+            .iter()
+            .any(|process| runs(&game.executable, process.as_ref()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_matches_executables_loosely() {
+        let games = default_games();
+        let running = ["explorer.exe", "valorant-win64-shipping.exe", "cs2.exe"];
+        let found = detect(&games, &running).unwrap();
+        assert_eq!(found.name, "Valorant");
+        assert_eq!(found.status_message(), "Playing Valorant");
+
+        assert!(runs("cs2.exe", "cs2"));
+        assert!(runs("dota2", "DOTA2.EXE"));
+        assert!(!runs("cs2.exe", "cs2.exe.bak"));
+        assert!(!runs(".exe", "anything"));
+        assert_eq!(detect(&games, &["code.exe", "bash"]), None);
+        assert_eq!(detect::<&str>(&games, &[]), None);
+    }
+
+    #[test]
+    fn test_game_in_status_message() {
+        let game = Game::new("Rocket League", "RocketLeague.exe");
+        assert_eq!(game_in(&game.status_message()), Some("Rocket League"));
+        assert_eq!(game_in("Playing "), None);
+        assert_eq!(game_in("Away for lunch"), None);
+    }
+}
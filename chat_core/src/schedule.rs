@@ -0,0 +1,165 @@
+//! When a message scheduled from the composer goes out: "30m", "1h30m" or
+//! "in 2h" from now, "18:30" at its next occurrence, "tomorrow 9:00", or
+//! "2025-03-15 18:30". Times are read on the user's wall clock and kept as
+//! UTC ms, so that a later change of time zone or clock doesn't move the
+//! moment they picked.
+
+use crate::quiet_hours::parse_time;
+use chrono::{
+    DateTime, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+};
+
+/// A message sent more than this after its time was held up, e.g. with
+/// the app closed, and says so.
+pub const LATE_AFTER_MS: u64 = 60 * 1000;
+
+/// The moment `text` names, in ms since the epoch, if it is still to come.
+pub fn parse(text: &str) -> Option<u64> {
+    parse_at(text, Local::now())
+}
+
+/// `parse` relative to an explicit "now", in `now`'s time zone.
+pub fn parse_at<Tz: TimeZone>(text: &str, now: DateTime<Tz>) -> Option<u64> {
+    let text = text.trim().to_lowercase();
+    let at = match parse_delay(text.strip_prefix("in ").unwrap_or(&text)) {
+        Some(delay) => now.clone() + delay,
+        None => parse_wall_clock(&text, &now)?,
+    };
+    (at > now).then(|| at.timestamp_millis().max(0) as u64)
+}
+
+/// "1h30m": days, hours and minutes, in that order, each at most once.
+fn parse_delay(text: &str) -> Option<Duration> {
+    let mut rest = text.trim();
+    let mut total = Duration::zero();
+    for (unit, minutes) in [('d', 24 * 60), ('h', 60), ('m', 1)] {
+        let Some((count, after)) = rest.split_once(unit) else {
+            continue;
+        };
+        let count: i64 = count.trim().parse().ok()?;
+        total += Duration::minutes(count.checked_mul(minutes)?);
+        rest = after.trim_start();
+    }
+    (rest.is_empty() && total > Duration::zero()).then_some(total)
+}
+
+/// "18:30", "tomorrow 18:30" or "2025-03-15 18:30" in `now`'s time zone.
+fn parse_wall_clock<Tz: TimeZone>(text: &str, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let today = now.date_naive();
+    let (date, time) = match text.split_once(' ') {
+        Some(("tomorrow", time)) => (today.succ_opt()?, time),
+        Some((date, time)) => (NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?, time),
+        None => (today, text),
+    };
+    let minute = u32::from(parse_time(time)?);
+    let time = NaiveTime::from_hms_opt(minute / 60, minute % 60, 0)?;
+    let at = resolve(&now.timezone(), date.and_time(time))?;
+    // A bare time already gone today is tomorrow's.
+    if !text.contains(' ') && at <= *now {
+        return resolve(&now.timezone(), today.succ_opt()?.and_time(time));
+    }
+    Some(at)
+}
+
+/// `at` on the wall clock of `tz`. An hour a clock change repeats is taken
+/// the first time round; one it skips is read an hour later.
+fn resolve<Tz: TimeZone>(tz: &Tz, at: NaiveDateTime) -> Option<DateTime<Tz>> {
+    match tz.from_local_datetime(&at) {
+        LocalResult::Single(at) => Some(at),
+        LocalResult::Ambiguous(first, _) => Some(first),
+        LocalResult::None => tz
+            .from_local_datetime(&(at + Duration::hours(1)))
+            .earliest(),
+    }
+}
+
+/// `at_ms` as `parse` reads it back: "18:30" today, "tomorrow 18:30", or
+/// with the date further on.
+pub fn format(at_ms: u64) -> String {
+    format_at(at_ms, Local::now())
+}
+
+/// `format` relative to an explicit "now", in `now`'s time zone.
+pub fn format_at<Tz: TimeZone>(at_ms: u64, now: DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let Some(at) = now.timezone().timestamp_millis_opt(at_ms as i64).single() else {
+        return String::new();
+    };
+    match (at.date_naive() - now.date_naive()).num_days() {
+        0 => at.format("%H:%M").to_string(),
+        1 => at.format("tomorrow %H:%M").to_string(),
+        _ => at.format("%Y-%m-%d %H:%M").to_string(),
+    }
+}
+
+/// Whether a message due at `send_at` and sent at `now` (both ms) went
+/// out late.
+pub fn is_late(send_at: u64, now: u64) -> bool {
+    now.saturating_sub(send_at) > LATE_AFTER_MS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, Utc};
+
+    const MINUTE: u64 = 60 * 1000;
+
+    /// 2024-03-15 12:00 UTC, 14:00 on a wall clock two hours ahead.
+    const NOW_MS: i64 = 1_710_504_000_000;
+
+    fn now() -> DateTime<FixedOffset> {
+        let tz = FixedOffset::east_opt(2 * 60 * 60).unwrap();
+        tz.timestamp_millis_opt(NOW_MS).unwrap()
+    }
+
+    fn parsed(text: &str) -> Option<u64> {
+        parse_at(text, now()).map(|at| (at - NOW_MS as u64) / MINUTE)
+    }
+
+    #[test]
+    fn test_delays_from_now() {
+        assert_eq!(parsed("30m"), Some(30));
+        assert_eq!(parsed("in 2h"), Some(120));
+        assert_eq!(parsed("1h 30m"), Some(90));
+        assert_eq!(parsed("1d"), Some(24 * 60));
+        assert_eq!(parsed("0m"), None);
+        assert_eq!(parsed("30m 1h"), None);
+        assert_eq!(parsed("soon"), None);
+    }
+
+    #[test]
+    fn test_wall_clock_times() {
+        // Read on the local clock, not UTC.
+        assert_eq!(parsed("18:30"), Some(4 * 60 + 30));
+        // Gone today, so tomorrow.
+        assert_eq!(parsed("9:00"), Some(19 * 60));
+        assert_eq!(parsed("Tomorrow 14:00"), Some(24 * 60));
+        assert_eq!(parsed("2024-03-17 14:00"), Some(2 * 24 * 60));
+        // An explicit moment that has passed isn't moved on.
+        assert_eq!(parsed("2024-03-14 14:00"), None);
+        assert_eq!(parsed("25:00"), None);
+    }
+
+    #[test]
+    fn test_formatted_as_parsed() {
+        for text in ["18:30", "tomorrow 09:00", "2024-03-20 08:15"] {
+            let at = parse_at(text, now()).unwrap();
+            assert_eq!(format_at(at, now()), text);
+        }
+        // The same moment reads differently elsewhere.
+        let at = parse_at("18:30", now()).unwrap();
+        let utc = Utc.timestamp_millis_opt(NOW_MS).unwrap();
+        assert_eq!(format_at(at, utc), "16:30");
+    }
+
+    #[test]
+    fn test_lateness() {
+        assert!(!is_late(100 * MINUTE, 100 * MINUTE + 30 * 1000));
+        assert!(is_late(100 * MINUTE, 105 * MINUTE));
+        // Sent early after the clock was put forward.
+        assert!(!is_late(100 * MINUTE, 90 * MINUTE));
+    }
+}
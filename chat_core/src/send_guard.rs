@@ -0,0 +1,127 @@
+//! Holding a message back before it goes out: a big room asks first, so
+//! that half a sentence doesn't reach thousands of people, and a room in
+//! slow mode takes one message per so many seconds from each member.
+
+use crate::{Room, RoomType};
+use std::collections::HashMap;
+
+/// Rooms with more members than this ask before sending, unless set
+/// otherwise.
+pub const DEFAULT_CONFIRM_ABOVE: u64 = 1000;
+
+/// Whether a message to `room` asks first: more than `threshold` members,
+/// where 0 never asks. Direct chats never ask, however many are in them.
+pub fn needs_confirmation(room: &Room, threshold: u64) -> bool {
+    threshold > 0 && room.room_type != RoomType::Direct && room.member_count > threshold
+}
+
+/// When each room in slow mode takes our next message.
+#[derive(Debug, Clone, Default)]
+pub struct Cooldowns {
+    /// Milliseconds since the epoch, by room id.
+    until: HashMap<String, u64>,
+}
+
+impl Cooldowns {
+    /// A message went to `room_id` at `now_ms`, in a room taking one per
+    /// `interval_secs`; 0 is no slow mode.
+    pub fn sent(&mut self, room_id: &str, now_ms: u64, interval_secs: u64) {
+        if interval_secs == 0 {
+            self.until.remove(room_id);
+        } else {
+            let until = now_ms.saturating_add(interval_secs.saturating_mul(1000));
+            self.until.insert(room_id.to_string(), until);
+        }
+    }
+
+    /// Book `room_id`'s next turn for a message ready at `now_ms`, in a room
+    /// taking one per `interval_secs`: milliseconds to wait before sending
+    /// it. Messages booked together, like a queue sent on reconnecting, go
+    /// one interval apart.
+    pub fn book(&mut self, room_id: &str, now_ms: u64, interval_secs: u64) -> u64 {
+        if interval_secs == 0 {
+            self.until.remove(room_id);
+            return 0;
+        }
+        let turn = self
+            .until
+            .get(room_id)
+            .map_or(now_ms, |&until| until.max(now_ms));
+        self.sent(room_id, turn, interval_secs);
+        turn - now_ms
+    }
+
+    /// Whole seconds, rounded up, until `room_id` takes another message.
+    pub fn remaining_secs(&self, room_id: &str, now_ms: u64) -> u64 {
+        self.until
+            .get(room_id)
+            .map_or(0, |until| until.saturating_sub(now_ms).div_ceil(1000))
+    }
+
+    /// Drop rooms that are ready again.
+    pub fn expire(&mut self, now_ms: u64) {
+        self.until.retain(|_, until| *until > now_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnreadCounts;
+
+    fn room(room_type: RoomType, member_count: u64) -> Room {
+        Room {
+            id: "!r:example.org".to_string(),
+            name: "lobby".to_string(),
+            topic: None,
+            room_type,
+            avatar_url: None,
+            last_activity: 0,
+            unread: UnreadCounts::default(),
+            muted: false,
+            alias: None,
+            direct_targets: Vec::new(),
+            encrypted: false,
+            member_count,
+        }
+    }
+
+    #[test]
+    fn test_big_rooms_ask_but_direct_chats_never() {
+        assert!(needs_confirmation(&room(RoomType::Public, 5000), 1000));
+        assert!(needs_confirmation(&room(RoomType::Group, 1001), 1000));
+        assert!(!needs_confirmation(&room(RoomType::Group, 1000), 1000));
+        assert!(!needs_confirmation(&room(RoomType::Public, 5000), 0));
+        assert!(!needs_confirmation(&room(RoomType::Direct, 5000), 1));
+    }
+
+    #[test]
+    fn test_cooldown_counts_down_by_room() {
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.sent("!a", 10_000, 30);
+        cooldowns.sent("!b", 10_000, 0);
+        assert_eq!(cooldowns.remaining_secs("!a", 10_000), 30);
+        assert_eq!(cooldowns.remaining_secs("!a", 10_001), 30);
+        assert_eq!(cooldowns.remaining_secs("!a", 39_000), 1);
+        assert_eq!(cooldowns.remaining_secs("!a", 40_000), 0);
+        assert_eq!(cooldowns.remaining_secs("!b", 10_000), 0);
+
+        cooldowns.expire(40_000);
+        assert!(cooldowns.until.is_empty());
+    }
+
+    #[test]
+    fn test_booked_messages_go_an_interval_apart() {
+        let mut cooldowns = Cooldowns::default();
+        assert_eq!(cooldowns.book("!a", 10_000, 30), 0);
+        assert_eq!(cooldowns.book("!a", 10_000, 30), 30_000);
+        assert_eq!(cooldowns.book("!a", 10_500, 30), 59_500);
+        assert_eq!(cooldowns.remaining_secs("!a", 10_000), 90);
+        // Other rooms, and those out of slow mode, don't wait.
+        assert_eq!(cooldowns.book("!b", 10_000, 30), 0);
+        assert_eq!(cooldowns.book("!a", 20_000, 0), 0);
+        assert_eq!(cooldowns.remaining_secs("!a", 20_000), 0);
+        // A turn long past doesn't hold the next message.
+        assert_eq!(cooldowns.book("!b", 100_000, 30), 0);
+    }
+}
@@ -0,0 +1,182 @@
+//! What the composer's spell checker looks at: the words of a message, less
+//! what no dictionary knows. Code, between backticks or fences, links,
+//! Matrix ids such as @alice:example.org, emoji shortcodes, words with
+//! digits in them and all-caps abbreviations are left out.
+
+use crate::link_preview;
+use std::ops::Range;
+
+/// Runs of backticks in `line`, as byte ranges.
+fn backtick_runs(line: &str) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (i, _) in line.match_indices('`') {
+        match runs.last_mut() {
+            Some(run) if run.end == i => run.end += 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    runs
+}
+
+/// Code spans in `line`: from a run of backticks to the next run of as
+/// many. A run without one to close it is plain text.
+fn code_spans(line: &str) -> Vec<Range<usize>> {
+    let runs = backtick_runs(line);
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while let Some(open) = runs.get(i) {
+        let close = runs[i + 1..].iter().position(|run| run.len() == open.len());
+        match close {
+            Some(j) => {
+                spans.push(open.start..runs[i + 1 + j].end);
+                i += j + 2;
+            }
+            None => i += 1,
+        }
+    }
+    spans
+}
+
+/// Whether `line` opens or closes a fenced code block: three or more
+/// backticks and no others after them.
+fn is_fence(line: &str) -> bool {
+    let line = line.trim();
+    let info = line.trim_start_matches('`');
+    line.len() - info.len() >= 3 && !info.contains('`')
+}
+
+/// The code in `text`, as byte ranges: fenced blocks, fences included, and
+/// spans within other lines. A fence left open runs to the end.
+fn code(text: &str) -> Vec<Range<usize>> {
+    let mut code = Vec::new();
+    let mut fence = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        match fence {
+            Some(open) if is_fence(line) => {
+                code.push(open..offset);
+                fence = None;
+            }
+            Some(_) => {}
+            None if is_fence(line) => fence = Some(start),
+            None => {
+                let spans = code_spans(line).into_iter();
+                code.extend(spans.map(|s| s.start + start..s.end + start));
+            }
+        }
+    }
+    if let Some(open) = fence {
+        code.push(open..text.len());
+    }
+    code
+}
+
+/// Whether a whitespace-separated token is left out whole: a link, a
+/// Matrix id or email address, or an emoji shortcode like `:wave:`.
+fn is_skipped_token(token: &str) -> bool {
+    let bare = token.trim_matches(|c: char| !c.is_alphanumeric() && !"@#!+:".contains(c));
+    link_preview::link_in(token).is_some()
+        || token.contains('@')
+        || (bare.starts_with(['#', '!', '+']) && bare.contains(':'))
+        || (bare.len() > 2 && bare.starts_with(':') && bare.ends_with(':'))
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '’'
+}
+
+/// Whether a word is an abbreviation, like "GG" or "AFK", or has digits.
+fn is_skipped_word(word: &str) -> bool {
+    let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+    let capitals = letters > 1 && !word.chars().any(char::is_lowercase);
+    capitals || word.chars().any(char::is_numeric)
+}
+
+/// The words of `text` to spell check, as byte ranges in order. A word is
+/// a run of letters and digits, with apostrophes inside it as in "don't".
+pub fn words(text: &str) -> Vec<Range<usize>> {
+    let mut skipped = code(text);
+    skipped.extend(
+        text.split_whitespace()
+            .filter(|token| is_skipped_token(token))
+            .map(|token| {
+                let start = token.as_ptr() as usize - text.as_ptr() as usize;
+                start..start + token.len()
+            }),
+    );
+
+    let mut words = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if !c.is_alphanumeric() {
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, c)) = chars.peek() {
+            let mut after = text[i + c.len_utf8()..].chars();
+            let joins = is_apostrophe(c) && after.next().is_some_and(char::is_alphanumeric);
+            if !c.is_alphanumeric() && !joins {
+                break;
+            }
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        let overlaps = skipped.iter().any(|s| s.start < end && start < s.end);
+        if !overlaps && !is_skipped_word(&text[start..end]) {
+            words.push(start..end);
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checked(text: &str) -> Vec<&str> {
+        words(text).into_iter().map(|word| &text[word]).collect()
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            checked("Teh quick, brwon fox!"),
+            vec!["Teh", "quick", "brwon", "fox"]
+        );
+        assert_eq!(
+            checked("don't I’m rock'n'roll 'quoted' é-t-é"),
+            vec!["don't", "I’m", "rock'n'roll", "quoted", "é", "t", "é"]
+        );
+        assert_eq!(checked("GG gg2 3rd AFK a I"), vec!["a", "I"]);
+        assert!(checked("").is_empty());
+    }
+
+    #[test]
+    fn test_code_is_skipped() {
+        assert_eq!(checked("run `cargo biuld` now"), vec!["run", "now"]);
+        assert_eq!(checked("``a ` mispel`` ok"), vec!["ok"]);
+        // A backtick nothing closes is just a backtick.
+        assert_eq!(checked("one ` two"), vec!["one", "two"]);
+        assert_eq!(
+            checked("look\n```rust\nlet x = teh;\n```\nok"),
+            vec!["look", "ok"]
+        );
+        assert_eq!(checked("open\n```\nnevr closed"), vec!["open"]);
+        assert_eq!(checked("```inline fense``` after"), vec!["after"]);
+    }
+
+    #[test]
+    fn test_links_and_ids_are_skipped() {
+        assert_eq!(
+            checked("see https://exampel.org/teh, (http://a.org/b_(c)) ok"),
+            vec!["see", "ok"]
+        );
+        assert_eq!(
+            checked("ask @alise:example.org in #lobbby:example.org, or bob@exmaple.org"),
+            vec!["ask", "in", "or"]
+        );
+        assert_eq!(checked("hi :wavey: #hashtag"), vec!["hi", "hashtag"]);
+    }
+}
@@ -0,0 +1,264 @@
+use chrono::{DateTime, Local, TimeZone, Utc};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Messages from the same sender closer together than this are grouped.
+pub const GROUP_WINDOW_MS: u64 = 2 * 60 * 1000;
+
+/// How timestamps read in a language, as `strftime` patterns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimestampFormat {
+    pub today: &'static str,
+    pub yesterday: &'static str,
+    pub older: &'static str,
+    pub time_of_day: &'static str,
+    /// Headings of the timeline's date separators.
+    pub day_today: &'static str,
+    pub day_yesterday: &'static str,
+    pub day_older: &'static str,
+}
+
+impl TimestampFormat {
+    pub const ENGLISH: Self = Self {
+        today: "Today at %H:%M",
+        yesterday: "Yesterday at %H:%M",
+        older: "%d/%m/%Y %H:%M",
+        time_of_day: "%H:%M",
+        day_today: "Today",
+        day_yesterday: "Yesterday",
+        day_older: "%A, %-d %B %Y",
+    };
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self::ENGLISH
+    }
+}
+
+static FORMAT: RwLock<TimestampFormat> = RwLock::new(TimestampFormat::ENGLISH);
+
+/// Format timestamps from now on as `format` says, e.g. on switching
+/// language.
+pub fn set_format(format: TimestampFormat) {
+    *FORMAT.write().unwrap_or_else(|e| e.into_inner()) = format;
+}
+
+fn format() -> TimestampFormat {
+    *FORMAT.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The current time in ms since the Unix epoch.
+pub fn now_ms() -> u64 {
+    Utc::now().timestamp_millis().max(0) as u64
+}
+
+/// Format a message timestamp (ms since epoch) for the timeline header, e.g.
+/// "Today at 14:02", "Yesterday at 09:15" or "03/11/2025 18:40".
+pub fn format_timestamp(ts_ms: u64) -> String {
+    format_timestamp_at(ts_ms, Local::now())
+}
+
+/// `format_timestamp` relative to an explicit "now", in `now`'s time zone.
+pub fn format_timestamp_at<Tz: TimeZone>(ts_ms: u64, now: DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    format_timestamp_with(ts_ms, now, &format())
+}
+
+/// `format_timestamp_at` in an explicit format rather than the one set.
+pub fn format_timestamp_with<Tz: TimeZone>(
+    ts_ms: u64,
+    now: DateTime<Tz>,
+    format: &TimestampFormat,
+) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let Some(ts) = now.timezone().timestamp_millis_opt(ts_ms as i64).single() else {
+        return String::new();
+    };
+
+    let days_ago = (now.date_naive() - ts.date_naive()).num_days();
+    let pattern = match days_ago {
+        0 => format.today,
+        1 => format.yesterday,
+        _ => format.older,
+    };
+    ts.format(pattern).to_string()
+}
+
+/// Whether `a` and `b` (ms since epoch) fall on the same day in the local
+/// time zone. The timeline puts a date separator between days and doesn't
+/// group messages across one.
+pub fn same_day(a: u64, b: u64) -> bool {
+    same_day_in(a, b, &Local)
+}
+
+/// `same_day` in an explicit time zone.
+pub fn same_day_in<Tz: TimeZone>(a: u64, b: u64, zone: &Tz) -> bool {
+    let day = |ts: u64| {
+        zone.timestamp_millis_opt(ts as i64)
+            .single()
+            .map(|ts| ts.date_naive())
+    };
+    day(a) == day(b)
+}
+
+/// The heading of the date separator for the day `ts_ms` falls on, e.g.
+/// "Today", "Yesterday" or "Friday, 8 March 2024".
+pub fn format_day(ts_ms: u64) -> String {
+    format_day_at(ts_ms, Local::now())
+}
+
+/// `format_day` relative to an explicit "now", in `now`'s time zone.
+pub fn format_day_at<Tz: TimeZone>(ts_ms: u64, now: DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    format_day_with(ts_ms, now, &format())
+}
+
+/// `format_day_at` in an explicit format rather than the one set.
+pub fn format_day_with<Tz: TimeZone>(
+    ts_ms: u64,
+    now: DateTime<Tz>,
+    format: &TimestampFormat,
+) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let Some(ts) = now.timezone().timestamp_millis_opt(ts_ms as i64).single() else {
+        return String::new();
+    };
+
+    match (now.date_naive() - ts.date_naive()).num_days() {
+        0 => format.day_today.to_string(),
+        1 => format.day_yesterday.to_string(),
+        _ => ts.format(format.day_older).to_string(),
+    }
+}
+
+/// Short time-of-day form used beside grouped messages, e.g. "14:02".
+pub fn format_time_of_day(ts_ms: u64) -> String {
+    Local
+        .timestamp_millis_opt(ts_ms as i64)
+        .single()
+        .map(|ts| ts.format(format().time_of_day).to_string())
+        .unwrap_or_default()
+}
+
+/// The exact time of `ts_ms` in the local time zone, to the millisecond and
+/// with the zone's offset, e.g. "2025-03-11 18:40:12.345 +01:00".
+pub fn format_exact(ts_ms: u64) -> String {
+    format_exact_in(ts_ms, &Local)
+}
+
+/// `format_exact` in an explicit time zone.
+pub fn format_exact_in<Tz: TimeZone>(ts_ms: u64, zone: &Tz) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    zone.timestamp_millis_opt(ts_ms as i64)
+        .single()
+        .map(|ts| ts.format("%Y-%m-%d %H:%M:%S%.3f %:z").to_string())
+        .unwrap_or_default()
+}
+
+/// A length of time as minutes and seconds, e.g. "4:07" for a voice clip.
+pub fn format_length(length: Duration) -> String {
+    let secs = length.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-15 12:00:00 UTC
+    const NOW_MS: i64 = 1_710_504_000_000;
+
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(NOW_MS).unwrap()
+    }
+
+    #[test]
+    fn test_today_and_yesterday() {
+        let ten_past_nine = (NOW_MS - (2 * 60 + 50) * 60 * 1000) as u64;
+        assert_eq!(format_timestamp_at(ten_past_nine, now()), "Today at 09:10");
+
+        let yesterday = (NOW_MS - 24 * 60 * 60 * 1000) as u64;
+        assert_eq!(format_timestamp_at(yesterday, now()), "Yesterday at 12:00");
+    }
+
+    #[test]
+    fn test_lengths() {
+        assert_eq!(format_length(Duration::from_millis(7_900)), "0:07");
+        assert_eq!(format_length(Duration::from_secs(300)), "5:00");
+        assert_eq!(format_length(Duration::from_secs(61 * 60)), "61:00");
+    }
+
+    #[test]
+    fn test_older_dates() {
+        let last_week = (NOW_MS - 7 * 24 * 60 * 60 * 1000) as u64;
+        assert_eq!(format_timestamp_at(last_week, now()), "08/03/2024 12:00");
+    }
+
+    #[test]
+    fn test_exact_times() {
+        let ts = (NOW_MS + 12_345) as u64;
+        assert_eq!(format_exact_in(ts, &Utc), "2024-03-15 12:00:12.345 +00:00");
+        let paris = chrono::FixedOffset::east_opt(3600).unwrap();
+        assert_eq!(
+            format_exact_in(ts, &paris),
+            "2024-03-15 13:00:12.345 +01:00"
+        );
+    }
+
+    #[test]
+    fn test_days() {
+        assert_eq!(format_day_at(NOW_MS as u64 - 60_000, now()), "Today");
+        let yesterday = (NOW_MS - 24 * 60 * 60 * 1000) as u64;
+        assert_eq!(format_day_at(yesterday, now()), "Yesterday");
+        let last_week = (NOW_MS - 7 * 24 * 60 * 60 * 1000) as u64;
+        assert_eq!(format_day_at(last_week, now()), "Friday, 8 March 2024");
+    }
+
+    #[test]
+    fn test_same_day_is_local() {
+        // 23:30 and 00:30 UTC, an hour apart across midnight.
+        let before = (NOW_MS + 11 * 60 * 60 * 1000 + 30 * 60 * 1000) as u64;
+        let after = before + 60 * 60 * 1000;
+        assert!(!same_day_in(before, after, &Utc));
+        assert!(same_day_in(before, before - 60_000, &Utc));
+        // In New York both are on the evening of the 15th.
+        let new_york = chrono::FixedOffset::west_opt(4 * 3600).unwrap();
+        assert!(same_day_in(before, after, &new_york));
+    }
+
+    #[test]
+    fn test_other_formats() {
+        let french = TimestampFormat {
+            today: "Aujourd’hui à %H:%M",
+            yesterday: "Hier à %H:%M",
+            older: "%d/%m/%Y %H:%M",
+            time_of_day: "%H:%M",
+            day_today: "Aujourd’hui",
+            day_yesterday: "Hier",
+            day_older: "%d/%m/%Y",
+        };
+        let ten_past_nine = (NOW_MS - (2 * 60 + 50) * 60 * 1000) as u64;
+        assert_eq!(
+            format_timestamp_with(ten_past_nine, now(), &french),
+            "Aujourd’hui à 09:10"
+        );
+        let yesterday = (NOW_MS - 24 * 60 * 60 * 1000) as u64;
+        assert_eq!(
+            format_timestamp_with(yesterday, now(), &french),
+            "Hier à 12:00"
+        );
+        let last_week = (NOW_MS - 7 * 24 * 60 * 60 * 1000) as u64;
+        assert_eq!(format_day_with(last_week, now(), &french), "08/03/2024");
+    }
+}
@@ -0,0 +1,178 @@
+//! The command line: global flags anywhere, then a subcommand and its
+//! arguments.
+
+use anyhow::{bail, Context, Result};
+
+pub const USAGE: &str = "\
+Usage: gamechat-cli [--json] [--account <user id>] <command>
+
+Commands:
+  login <homeserver> <username>   Log in and save the session, as the app does.
+                                  The password is read from GAMECHAT_PASSWORD,
+                                  or from standard input.
+  rooms                           List joined rooms.
+  send <room> <message...>        Send a message to a room, by id, alias or name.
+  watch <room>                    Print messages sent to a room until Ctrl+C.
+  doctor [<homeserver>]           Check the homeserver, the session, sync,
+                                  audio devices, UDP and free disk space.
+
+Options:
+  --json                 Print JSON instead of text.
+  --account <user id>    Use this saved session instead of the first one.
+  --room <room>          With doctor, also show the room's server ACL and
+                         which of its members' servers it shuts out.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Login {
+        homeserver: String,
+        username: String,
+    },
+    Rooms,
+    Send {
+        room: String,
+        message: String,
+    },
+    Watch {
+        room: String,
+    },
+    Doctor {
+        homeserver: Option<String>,
+        /// A joined room whose server ACL to show.
+        room: Option<String>,
+    },
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+    pub json: bool,
+    /// The saved session to use; the first one when `None`.
+    pub account: Option<String>,
+    pub command: Command,
+}
+
+/// Parse the arguments after the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut json = false;
+    let mut account = None;
+    let mut doctor_room = None;
+    let mut help = false;
+    let mut words = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--account" => account = Some(args.next().context("--account needs a user id")?),
+            "--room" => doctor_room = Some(args.next().context("--room needs a room")?),
+            "-h" | "--help" => help = true,
+            _ if arg.starts_with("--") => bail!("Unknown option {}", arg),
+            _ => words.push(arg),
+        }
+    }
+
+    let mut words = words.into_iter();
+    let command = match words.next().as_deref() {
+        _ if help => {
+            return Ok(Args {
+                json,
+                account,
+                command: Command::Help,
+            })
+        }
+        None | Some("help") => Command::Help,
+        Some("login") => {
+            let (Some(homeserver), Some(username)) = (words.next(), words.next()) else {
+                bail!("login needs a homeserver and a username");
+            };
+            Command::Login {
+                homeserver,
+                username,
+            }
+        }
+        Some("rooms") => Command::Rooms,
+        Some("send") => {
+            let room = words.next().context("send needs a room and a message")?;
+            let message = words.by_ref().collect::<Vec<_>>().join(" ");
+            if message.is_empty() {
+                bail!("send needs a message");
+            }
+            Command::Send { room, message }
+        }
+        Some("watch") => Command::Watch {
+            room: words.next().context("watch needs a room")?,
+        },
+        Some("doctor") => Command::Doctor {
+            homeserver: words.next(),
+            room: doctor_room.take(),
+        },
+        Some(other) => bail!("Unknown command {}", other),
+    };
+    if let Some(extra) = words.next() {
+        bail!("Unexpected argument {}", extra);
+    }
+    if doctor_room.is_some() {
+        bail!("--room only goes with doctor");
+    }
+    Ok(Args {
+        json,
+        account,
+        command,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(line: &str) -> Result<Args> {
+        parse(line.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn test_parse_commands_and_flags() {
+        let args = parse_str("send --json #lobby:x.org hello there").unwrap();
+        assert!(args.json);
+        assert_eq!(
+            args.command,
+            Command::Send {
+                room: "#lobby:x.org".to_string(),
+                message: "hello there".to_string(),
+            }
+        );
+
+        let args = parse_str("--account @a:x.org rooms").unwrap();
+        assert_eq!(args.account.as_deref(), Some("@a:x.org"));
+        assert_eq!(args.command, Command::Rooms);
+
+        assert_eq!(
+            parse_str("doctor").unwrap().command,
+            Command::Doctor {
+                homeserver: None,
+                room: None,
+            }
+        );
+        assert_eq!(
+            parse_str("doctor --room #lobby:x.org x.org")
+                .unwrap()
+                .command,
+            Command::Doctor {
+                homeserver: Some("x.org".to_string()),
+                room: Some("#lobby:x.org".to_string()),
+            }
+        );
+        assert_eq!(parse_str("").unwrap().command, Command::Help);
+        assert_eq!(parse_str("rooms --help").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        assert!(parse_str("send !room:x.org").is_err());
+        assert!(parse_str("login matrix.org").is_err());
+        assert!(parse_str("rooms extra").is_err());
+        assert!(parse_str("frobnicate").is_err());
+        assert!(parse_str("rooms --verbose").is_err());
+        assert!(parse_str("rooms --account").is_err());
+        assert!(parse_str("rooms --room #lobby:x.org").is_err());
+    }
+}
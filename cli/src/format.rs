@@ -0,0 +1,81 @@
+//! Rooms and messages as the command line prints them, and finding a room
+//! from what was typed.
+
+use chat_core::{time, Message, MessageType, Room, RoomType};
+
+/// The room `query` names: its id, its alias, or else its name, ignoring
+/// case.
+pub fn find_room<'a>(rooms: &'a [Room], query: &str) -> Option<&'a Room> {
+    rooms
+        .iter()
+        .find(|room| room.id == query || room.alias.as_deref() == Some(query))
+        .or_else(|| {
+            rooms
+                .iter()
+                .find(|room| room.name.eq_ignore_ascii_case(query))
+        })
+}
+
+/// One line per room: id, kind and name, with the alias if it has one.
+pub fn room_line(room: &Room) -> String {
+    let kind = match room.room_type {
+        RoomType::Direct => "dm",
+        _ => "room",
+    };
+    let mut line = format!("{}\t{}\t{}", room.id, kind, room.name);
+    if let Some(alias) = &room.alias {
+        line.push_str(&format!(" ({})", alias));
+    }
+    line
+}
+
+/// "[14:02] Alice: hello", with the file name for attachments and
+/// "[14:02] * Alice waves" for emotes.
+pub fn message_line(message: &Message) -> String {
+    let sender = message.sender_name.as_deref().unwrap_or(&message.sender);
+    let time = time::format_time_of_day(message.timestamp);
+    match (&message.schema, &message.attachment) {
+        (MessageType::Image | MessageType::File, Some(attachment)) => {
+            format!("[{}] {}: [{}]", time, sender, attachment.name)
+        }
+        (MessageType::Emote, _) => format!("[{}] * {} {}", time, sender, message.content),
+        _ => format!("[{}] {}: {}", time, sender, message.content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(id: &str, name: &str, alias: Option<&str>) -> Room {
+        Room {
+            id: id.to_string(),
+            name: name.to_string(),
+            topic: None,
+            room_type: RoomType::Group,
+            avatar_url: None,
+            last_activity: 0,
+            unread: Default::default(),
+            muted: false,
+            alias: alias.map(str::to_owned),
+            direct_targets: Vec::new(),
+            encrypted: false,
+            member_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_find_room_by_id_alias_or_name() {
+        let rooms = vec![
+            room("!a:x.org", "Lobby", Some("#lobby:x.org")),
+            room("!b:x.org", "Raid night", None),
+        ];
+        let found = |query| find_room(&rooms, query).map(|r| r.id.as_str());
+        assert_eq!(found("!b:x.org"), Some("!b:x.org"));
+        assert_eq!(found("#lobby:x.org"), Some("!a:x.org"));
+        assert_eq!(found("raid NIGHT"), Some("!b:x.org"));
+        assert_eq!(found("#nope:x.org"), None);
+
+        assert_eq!(room_line(&rooms[0]), "!a:x.org\troom\tLobby (#lobby:x.org)");
+    }
+}
@@ -0,0 +1,326 @@
+//! Gamechat without a window: log in, list rooms, send and watch messages
+//! from scripts, and check what stands between the app and the homeserver.
+//! Sessions are shared with the app through `~/.gamechat/sessions.json`.
+//! Diagnostics go to stderr so stdout is only what was asked for.
+
+mod args;
+mod format;
+
+use anyhow::{Context, Result};
+use args::{Args, Command};
+use chat_core::time;
+use network::config::ConfigManager;
+use network::events::ChatEvent;
+use network::federation::ServerAcl;
+use network::register::Registration;
+use network::session::{Session, SessionManager};
+use network::voice::VoiceManager;
+use network::MatrixClient;
+use serde_json::json;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Round trips timed by `doctor`.
+const PING_SAMPLES: usize = 3;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    network::crash::log_to_stderr();
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, args::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    match &args.command {
+        Command::Help => {
+            println!("{}", args::USAGE);
+            Ok(())
+        }
+        Command::Login {
+            homeserver,
+            username,
+        } => login(&args, homeserver, username).await,
+        Command::Rooms => rooms(&args).await,
+        Command::Send { room, message } => send(&args, room, message).await,
+        Command::Watch { room } => watch(&args, room).await,
+        Command::Doctor { homeserver, room } => {
+            doctor(&args, homeserver.as_deref(), room.as_deref()).await
+        }
+    }
+}
+
+/// The saved session `--account` names, or the first one.
+fn saved_session(args: &Args) -> Result<Session> {
+    let sessions = SessionManager::get_remembered_profiles();
+    match &args.account {
+        Some(user_id) => sessions
+            .into_iter()
+            .find(|s| s.user_id == *user_id)
+            .with_context(|| format!("No saved session for {}", user_id)),
+        None => sessions
+            .into_iter()
+            .next()
+            .context("No saved session; log in first"),
+    }
+}
+
+async fn connect(args: &Args) -> Result<MatrixClient> {
+    let saved = saved_session(args)?;
+    let mut client = MatrixClient::restore_session(&saved)
+        .await
+        .with_context(|| format!("Could not restore the session for {}", saved.user_id))?;
+    client.start_sync().await?;
+    Ok(client)
+}
+
+fn read_password() -> Result<String> {
+    if let Ok(password) = std::env::var("GAMECHAT_PASSWORD") {
+        return Ok(password);
+    }
+    eprint!("Password: ");
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("Failed to read the password")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn login(args: &Args, homeserver: &str, username: &str) -> Result<()> {
+    let password = read_password()?;
+    let mut client = MatrixClient::new(homeserver).await?;
+    let (user_id, display_name) = client.login(username, &password).await?;
+    if args.json {
+        println!(
+            "{}",
+            json!({ "user_id": user_id, "display_name": display_name })
+        );
+    } else {
+        println!("Logged in as {} ({})", display_name, user_id);
+    }
+    Ok(())
+}
+
+async fn rooms(args: &Args) -> Result<()> {
+    let client = connect(args).await?;
+    let mut rooms = client
+        .get_joined_rooms(ConfigManager::load().startup.room_concurrency)
+        .await?;
+    rooms.sort_by_key(|room| std::cmp::Reverse(room.last_activity));
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&rooms)?);
+    } else {
+        for room in &rooms {
+            println!("{}", format::room_line(room));
+        }
+    }
+    Ok(())
+}
+
+async fn find_room(client: &MatrixClient, query: &str) -> Result<String> {
+    let rooms = client
+        .get_joined_rooms(ConfigManager::load().startup.room_concurrency)
+        .await?;
+    format::find_room(&rooms, query)
+        .map(|room| room.id.clone())
+        .with_context(|| format!("No joined room matches {}", query))
+}
+
+async fn send(args: &Args, room: &str, message: &str) -> Result<()> {
+    let client = connect(args).await?;
+    let room_id = find_room(&client, room).await?;
+    let txn_id = format!("cli-{}", time::now_ms());
+    let event_id = client.send_message(&room_id, message, &[], &txn_id).await?;
+    if args.json {
+        println!("{}", json!({ "room_id": room_id, "event_id": event_id }));
+    } else {
+        println!("{}", event_id);
+    }
+    Ok(())
+}
+
+async fn watch(args: &Args, room: &str) -> Result<()> {
+    let client = connect(args).await?;
+    let room_id = find_room(&client, room).await?;
+    let mut events = client.subscribe();
+    eprintln!("Watching {}; Ctrl+C to stop.", room_id);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            event = events.recv() => match event {
+                Ok(ChatEvent::Message { room_id: id, message, .. }) if id == room_id => {
+                    if args.json {
+                        println!("{}", serde_json::to_string(&message)?);
+                    } else {
+                        println!("{}", format::message_line(&message));
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    eprintln!("Fell behind; {} events skipped", missed);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+fn registration_name(registration: Registration) -> &'static str {
+    match registration {
+        Registration::InApp => "in-app",
+        Registration::NeedsBrowser => "browser",
+        Registration::Disabled => "disabled",
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+async fn doctor(args: &Args, homeserver: Option<&str>, room: Option<&str>) -> Result<()> {
+    let session = saved_session(args).ok();
+    // Checks run signed in, unless another homeserver was asked about.
+    let signed_in = session.clone().filter(|_| homeserver.is_none());
+    let homeserver = homeserver
+        .map(str::to_owned)
+        .or_else(|| session.as_ref().map(|s| s.homeserver.clone()))
+        .or_else(|| ConfigManager::load().login.homeserver)
+        .unwrap_or_else(|| "matrix.org".to_string());
+
+    let client = MatrixClient::new(&homeserver)
+        .await
+        .with_context(|| format!("Could not discover a homeserver for {}", homeserver))?;
+    let support = client.server_support().await?;
+    let mut pings = Vec::with_capacity(PING_SAMPLES);
+    for _ in 0..PING_SAMPLES {
+        pings.push(client.ping().await?);
+    }
+    let fastest = pings.iter().min().copied().unwrap_or_default();
+    let average = pings.iter().sum::<Duration>() / pings.len() as u32;
+    let sessions: Vec<String> = SessionManager::get_remembered_profiles()
+        .into_iter()
+        .map(|s| s.user_id)
+        .collect();
+    let inputs = VoiceManager::get_input_devices();
+    let outputs = VoiceManager::get_output_devices();
+    let diagnostics = match signed_in {
+        Some(saved) => {
+            // A session that can't sync is still checked, to say why.
+            let checked = match connect(args).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    MatrixClient::restore_session(&saved).await?
+                }
+            };
+            checked.run_diagnostics().await
+        }
+        None => client.run_diagnostics().await,
+    };
+    let acl = match room {
+        Some(room) => {
+            let client = connect(args).await?;
+            let room_id = find_room(&client, room).await?;
+            Some((room_id.clone(), client.server_acl(&room_id).await?))
+        }
+        None => None,
+    };
+
+    if args.json {
+        let mut report = json!({
+            "homeserver": support.homeserver,
+            "password_login": support.password_login,
+            "registration": registration_name(support.registration),
+            "latency_ms": { "min": ms(fastest), "average": ms(average) },
+            "sessions": sessions,
+            "input_devices": inputs,
+            "output_devices": outputs,
+            "status": diagnostics.status(),
+            "checks": diagnostics.checks,
+        });
+        if let Some((room_id, acl)) = &acl {
+            report["room"] = json!({ "room_id": room_id, "server_acl": acl });
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("Homeserver:      {}", support.homeserver);
+    println!(
+        "Password login:  {}",
+        if support.password_login { "yes" } else { "no" }
+    );
+    println!(
+        "Registration:    {}",
+        registration_name(support.registration)
+    );
+    println!(
+        "Latency:         {:.0} ms (min), {:.0} ms (average of {})",
+        ms(fastest),
+        ms(average),
+        PING_SAMPLES
+    );
+    println!(
+        "Saved sessions:  {}",
+        if sessions.is_empty() {
+            "none".to_string()
+        } else {
+            sessions.join(", ")
+        }
+    );
+    for (label, devices) in [("Microphones:", &inputs), ("Speakers:", &outputs)] {
+        if devices.is_empty() {
+            println!("{:<17}none found", label);
+        } else {
+            println!("{:<17}{}", label, devices.join(", "));
+        }
+    }
+    if let Some((room_id, acl)) = &acl {
+        println!("Room:            {}", room_id);
+        print_server_acl(acl.as_ref());
+    }
+    println!();
+    print!("{}", diagnostics);
+    Ok(())
+}
+
+fn print_server_acl(acl: Option<&ServerAcl>) {
+    let Some(acl) = acl else {
+        println!("Server ACL:      none");
+        return;
+    };
+    let list = |servers: &[String]| {
+        if servers.is_empty() {
+            "none".to_string()
+        } else {
+            servers.join(", ")
+        }
+    };
+    println!("ACL allows:      {}", list(&acl.allow));
+    println!("ACL denies:      {}", list(&acl.deny));
+    println!(
+        "IP literals:     {}",
+        if acl.allow_ip_literals {
+            "allowed"
+        } else {
+            "denied"
+        }
+    );
+    println!("Servers let in:  {}", list(&acl.allowed_servers));
+    println!("Servers blocked: {}", list(&acl.denied_servers));
+}
@@ -0,0 +1,35 @@
+//! Resolving 500 rooms at a simulated 5ms round trip each, as after signing
+//! in to a busy account. One at a time that takes 2.5s; bounded it should
+//! take about a concurrency-th of that.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use network::startup::{resolve_bounded, DEFAULT_CONCURRENCY};
+use std::time::Duration;
+
+const ROOMS: usize = 500;
+const LATENCY: Duration = Duration::from_millis(5);
+
+async fn room_name(room: usize) -> String {
+    tokio::time::sleep(LATENCY).await;
+    format!("room {}", room)
+}
+
+fn resolve_rooms(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("resolve_rooms");
+    group.sample_size(10);
+    for concurrency in [1, DEFAULT_CONCURRENCY, 32] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime)
+                    .iter(|| resolve_bounded((0..ROOMS).collect(), concurrency, room_name))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, resolve_rooms);
+criterion_main!(benches);
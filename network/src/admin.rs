@@ -0,0 +1,121 @@
+//! Server administration: channels are rooms listed in a space, and roles
+//! are names for power levels. Custom roles are kept in a
+//! `com.gamechat.roles` state event in the space, next to the built-in
+//! Admin, Moderator and Member levels.
+
+use matrix_sdk::deserialized_responses::RawSyncOrStrippedState;
+use matrix_sdk::ruma::events::macros::EventContent;
+use matrix_sdk::ruma::events::{EmptyStateKey, SyncStateEvent};
+use matrix_sdk::Room;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A role: a name for a power level.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Role {
+    pub name: String,
+    pub level: i64,
+}
+
+impl Role {
+    fn new(name: &str, level: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            level,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.gamechat.roles", kind = State, state_key_type = EmptyStateKey)]
+pub struct RolesEventContent {
+    /// Custom roles; the built-in ones are not stored.
+    #[serde(default)]
+    pub roles: Vec<Role>,
+}
+
+/// Custom roles followed by the built-in ones, highest level first. A
+/// custom role named like a built-in one is ignored.
+pub fn with_builtin_roles(custom: Vec<Role>) -> Vec<Role> {
+    let builtin = [
+        Role::new("Admin", 100),
+        Role::new("Moderator", 50),
+        Role::new("Member", 0),
+    ];
+    let mut roles: Vec<Role> = custom
+        .into_iter()
+        .filter(|r| !builtin.iter().any(|b| b.name.eq_ignore_ascii_case(&r.name)))
+        .collect();
+    roles.extend(builtin);
+    roles.sort_by(|a, b| b.level.cmp(&a.level).then_with(|| a.name.cmp(&b.name)));
+    roles
+}
+
+/// Custom roles defined in `room`.
+pub(crate) async fn custom_roles(room: &Room) -> matrix_sdk::Result<Vec<Role>> {
+    let Some(RawSyncOrStrippedState::Sync(raw)) =
+        room.get_state_event_static::<RolesEventContent>().await?
+    else {
+        return Ok(Vec::new());
+    };
+    Ok(match raw.deserialize() {
+        Ok(SyncStateEvent::Original(ev)) => ev.content.roles,
+        _ => Vec::new(),
+    })
+}
+
+/// How to take a channel off a server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelRemoval {
+    /// Unlist it from the space; members keep the room.
+    FromSpace,
+    /// Leave the room ourselves.
+    Leave,
+}
+
+/// Admin actions the server refused, shown inline in the admin panel.
+/// Other failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminError {
+    /// We lack the power level for the action, e.g. "create roles".
+    Forbidden(&'static str),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Forbidden(action) => write!(f, "You don't have permission to {}", action),
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+/// Turn a refusal of `action` into `AdminError::Forbidden`.
+pub(crate) fn admin_error(e: matrix_sdk::Error, action: &'static str) -> anyhow::Error {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    match e.client_api_error_kind() {
+        Some(ErrorKind::Forbidden) => AdminError::Forbidden(action).into(),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_roles_rank_among_builtin_ones() {
+        let roles = with_builtin_roles(vec![
+            Role::new("Raid Lead", 75),
+            Role::new("admin", 90),
+            Role::new("Guest", -1),
+        ]);
+        let names: Vec<_> = roles.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["Admin", "Raid Lead", "Moderator", "Member", "Guest"]
+        );
+    }
+}
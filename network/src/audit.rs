@@ -0,0 +1,474 @@
+//! A room's audit log: who changed its state and how, read from the state
+//! events in its history. Events come in as their JSON, as the server sent
+//! them, with the content they replaced in `unsigned.prev_content`.
+
+use serde_json::{Map, Value};
+
+/// The state changes the audit log covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    Membership,
+    PowerLevels,
+    Name,
+    Topic,
+    Avatar,
+    ServerAcl,
+}
+
+impl AuditKind {
+    pub const ALL: [AuditKind; 6] = [
+        AuditKind::Membership,
+        AuditKind::PowerLevels,
+        AuditKind::Name,
+        AuditKind::Topic,
+        AuditKind::Avatar,
+        AuditKind::ServerAcl,
+    ];
+
+    pub fn event_type(self) -> &'static str {
+        match self {
+            AuditKind::Membership => "m.room.member",
+            AuditKind::PowerLevels => "m.room.power_levels",
+            AuditKind::Name => "m.room.name",
+            AuditKind::Topic => "m.room.topic",
+            AuditKind::Avatar => "m.room.avatar",
+            AuditKind::ServerAcl => "m.room.server_acl",
+        }
+    }
+
+    fn from_event_type(event_type: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.event_type() == event_type)
+    }
+}
+
+/// What a membership event did to its member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MembershipAction {
+    Joined,
+    Left,
+    Invited,
+    /// Removed by someone else, or their invite withdrawn.
+    Kicked,
+    Banned,
+    Unbanned,
+    Knocked,
+}
+
+/// What `actor` did to `target`'s membership, going from `old` to `new`;
+/// `None` if the membership stayed as it was, as when a member renames.
+pub fn membership_action(
+    actor: &str,
+    target: &str,
+    old: Option<&str>,
+    new: &str,
+) -> Option<MembershipAction> {
+    let by_themselves = actor == target;
+    Some(match (old, new) {
+        (Some(old), new) if old == new => return None,
+        (_, "join") => MembershipAction::Joined,
+        (_, "invite") => MembershipAction::Invited,
+        (_, "ban") => MembershipAction::Banned,
+        (_, "knock") => MembershipAction::Knocked,
+        (Some("ban"), "leave") => MembershipAction::Unbanned,
+        (_, "leave") if by_themselves => MembershipAction::Left,
+        (_, "leave") => MembershipAction::Kicked,
+        _ => return None,
+    })
+}
+
+/// One value of the room's state, before and after; `None` for unset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl Change {
+    fn new(field: impl Into<String>, old: Option<String>, new: Option<String>) -> Option<Self> {
+        (old != new).then(|| Change {
+            field: field.into(),
+            old,
+            new,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub event_id: String,
+    pub kind: AuditKind,
+    pub actor: String,
+    pub actor_name: Option<String>,
+    /// The member a membership change is about.
+    pub target: Option<String>,
+    pub target_name: Option<String>,
+    pub membership: Option<MembershipAction>,
+    pub changes: Vec<Change>,
+    pub reason: Option<String>,
+    pub timestamp: u64,
+}
+
+impl AuditEntry {
+    /// Whether `query` is part of who made the change, by id or name,
+    /// ignoring case. An empty query matches everyone.
+    pub fn matches_actor(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        std::iter::once(&self.actor)
+            .chain(self.actor_name.as_ref())
+            .any(|name| name.to_lowercase().contains(&query))
+    }
+}
+
+/// A page of the audit log, newest first.
+#[derive(Debug, Clone, Default)]
+pub struct StateHistoryPage {
+    pub entries: Vec<AuditEntry>,
+    /// Where the next, older, page starts; `None` at the start of the room.
+    pub next: Option<String>,
+}
+
+/// Power levels the spec gives settings that are left out.
+const POWER_DEFAULTS: [(&str, i64); 7] = [
+    ("ban", 50),
+    ("kick", 50),
+    ("redact", 50),
+    ("invite", 0),
+    ("events_default", 0),
+    ("state_default", 50),
+    ("users_default", 0),
+];
+
+/// A power level, which older rooms may have as a string.
+fn level(value: Option<&Value>) -> Option<i64> {
+    match value? {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn object<'a>(content: &'a Value, key: &str) -> Option<&'a Map<String, Value>> {
+    content.get(key)?.as_object()
+}
+
+/// The levels under `key` that either side sets, sorted, as changes.
+fn level_map_changes(old: &Value, new: &Value, key: &str) -> Vec<Change> {
+    let old_map = object(old, key);
+    let new_map = object(new, key);
+    let mut names: Vec<&String> = old_map
+        .into_iter()
+        .chain(new_map)
+        .flat_map(Map::keys)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = level(old_map.and_then(|m| m.get(name)));
+            let new = level(new_map.and_then(|m| m.get(name)));
+            Change::new(
+                format!("{}.{}", key, name),
+                old.map(|l| l.to_string()),
+                new.map(|l| l.to_string()),
+            )
+        })
+        .collect()
+}
+
+/// What changed from the power levels `old` to `new`: the settings, with
+/// the spec's defaults for those left out, then the levels of event types
+/// and of users either side names.
+pub fn power_level_changes(old: &Value, new: &Value) -> Vec<Change> {
+    let setting = |content: &Value, key: &str, default: i64| {
+        level(content.get(key)).unwrap_or(default).to_string()
+    };
+    let notifications = |content: &Value| {
+        let room = object(content, "notifications").and_then(|n| level(n.get("room")));
+        room.unwrap_or(50).to_string()
+    };
+    let mut changes: Vec<Change> = POWER_DEFAULTS
+        .iter()
+        .filter_map(|&(key, default)| {
+            let old = setting(old, key, default);
+            let new = setting(new, key, default);
+            Change::new(key, Some(old), Some(new))
+        })
+        .collect();
+    changes.extend(Change::new(
+        "notifications.room",
+        Some(notifications(old)),
+        Some(notifications(new)),
+    ));
+    changes.extend(level_map_changes(old, new, "events"));
+    changes.extend(level_map_changes(old, new, "users"));
+    changes
+}
+
+fn text(content: &Value, key: &str) -> Option<String> {
+    let text = content.get(key)?.as_str()?;
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn strings(content: &Value, key: &str) -> Vec<String> {
+    let Some(list) = content.get(key).and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    list.iter()
+        .filter_map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Servers added to and taken off each list of a server ACL, each a change
+/// of its own, then whether IP addresses may take part.
+fn acl_changes(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for key in ["allow", "deny"] {
+        let old = strings(old, key);
+        let new = strings(new, key);
+        for server in old.iter().filter(|s| !new.contains(s)) {
+            changes.extend(Change::new(key, Some(server.clone()), None));
+        }
+        for server in new.iter().filter(|s| !old.contains(s)) {
+            changes.extend(Change::new(key, None, Some(server.clone())));
+        }
+    }
+    // Left out, IP addresses are allowed.
+    let literals = |content: &Value| {
+        let allowed = content.get("allow_ip_literals").and_then(Value::as_bool);
+        allowed.unwrap_or(true).to_string()
+    };
+    changes.extend(Change::new(
+        "allow_ip_literals",
+        Some(literals(old)),
+        Some(literals(new)),
+    ));
+    changes
+}
+
+/// The audit log entry for a state `event`, or `None` if it isn't one the
+/// log covers or changed nothing.
+pub fn entry(event: &Value) -> Option<AuditEntry> {
+    let kind = AuditKind::from_event_type(event.get("type")?.as_str()?)?;
+    let state_key = event.get("state_key")?.as_str()?;
+    let actor = event.get("sender")?.as_str()?.to_string();
+    let content = event.get("content").unwrap_or(&Value::Null);
+    // Older servers put the replaced content beside the new.
+    let old = event
+        .pointer("/unsigned/prev_content")
+        .or_else(|| event.get("prev_content"))
+        .unwrap_or(&Value::Null);
+
+    let mut target = None;
+    let mut membership = None;
+    let changes = match kind {
+        AuditKind::Membership => {
+            let new = text(content, "membership")?;
+            let previous = text(old, "membership");
+            membership = Some(membership_action(
+                &actor,
+                state_key,
+                previous.as_deref(),
+                &new,
+            )?);
+            target = Some(state_key.to_string());
+            Change::new("membership", previous, Some(new))
+                .into_iter()
+                .collect()
+        }
+        AuditKind::PowerLevels => power_level_changes(old, content),
+        AuditKind::Name => Change::new("name", text(old, "name"), text(content, "name"))
+            .into_iter()
+            .collect(),
+        AuditKind::Topic => Change::new("topic", text(old, "topic"), text(content, "topic"))
+            .into_iter()
+            .collect(),
+        AuditKind::Avatar => Change::new("url", text(old, "url"), text(content, "url"))
+            .into_iter()
+            .collect(),
+        AuditKind::ServerAcl => acl_changes(old, content),
+    };
+    if changes.is_empty() {
+        return None;
+    }
+    Some(AuditEntry {
+        event_id: event.get("event_id")?.as_str()?.to_string(),
+        kind,
+        actor,
+        actor_name: None,
+        target,
+        target_name: None,
+        membership,
+        changes,
+        reason: text(content, "reason"),
+        timestamp: event
+            .get("origin_server_ts")
+            .and_then(Value::as_u64)
+            .unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn change(field: &str, old: Option<&str>, new: Option<&str>) -> Change {
+        Change {
+            field: field.to_string(),
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_power_levels_diff_with_defaults() {
+        let old = json!({
+            "users": {"@alice:x": 100, "@bob:x": 50, "@carol:x": 0},
+            "events": {"m.room.name": 50},
+            "kick": 50,
+        });
+        let new = json!({
+            "users": {"@alice:x": 100, "@bob:x": "75", "@dave:x": 50},
+            "events": {"m.room.name": 50, "m.room.topic": 25},
+            "ban": 50,
+            "kick": 75,
+            "notifications": {"room": 100},
+        });
+        assert_eq!(
+            power_level_changes(&old, &new),
+            vec![
+                change("kick", Some("50"), Some("75")),
+                change("notifications.room", Some("50"), Some("100")),
+                change("events.m.room.topic", None, Some("25")),
+                change("users.@bob:x", Some("50"), Some("75")),
+                change("users.@carol:x", Some("0"), None),
+                change("users.@dave:x", None, Some("50")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_power_levels_unchanged() {
+        let content = json!({"users": {"@alice:x": 100}, "state_default": 50});
+        assert!(power_level_changes(&content, &content).is_empty());
+        // Setting a default explicitly changes nothing.
+        let explicit = json!({"users": {"@alice:x": 100}, "invite": 0, "redact": 50});
+        assert!(power_level_changes(&content, &explicit).is_empty());
+    }
+
+    #[test]
+    fn test_power_levels_of_a_new_room() {
+        let new = json!({"users": {"@alice:x": 100}, "invite": 50});
+        assert_eq!(
+            power_level_changes(&Value::Null, &new),
+            vec![
+                change("invite", Some("0"), Some("50")),
+                change("users.@alice:x", None, Some("100")),
+            ]
+        );
+    }
+
+    fn member_event(sender: &str, target: &str, old: Option<&str>, new: &str) -> Value {
+        let mut event = json!({
+            "type": "m.room.member",
+            "event_id": "$m",
+            "sender": sender,
+            "state_key": target,
+            "origin_server_ts": 1700,
+            "content": {"membership": new, "reason": "spam"},
+        });
+        if let Some(old) = old {
+            event["unsigned"] = json!({"prev_content": {"membership": old}});
+        }
+        event
+    }
+
+    #[test]
+    fn test_membership_entries() {
+        let kick = entry(&member_event("@mod:x", "@bob:x", Some("join"), "leave")).unwrap();
+        assert_eq!(kick.kind, AuditKind::Membership);
+        assert_eq!(kick.membership, Some(MembershipAction::Kicked));
+        assert_eq!(kick.target.as_deref(), Some("@bob:x"));
+        assert_eq!(kick.reason.as_deref(), Some("spam"));
+        assert_eq!(kick.timestamp, 1700);
+        assert_eq!(
+            kick.changes,
+            vec![change("membership", Some("join"), Some("leave"))]
+        );
+
+        let action = |sender, old, new| {
+            entry(&member_event(sender, "@bob:x", old, new)).and_then(|e| e.membership)
+        };
+        use MembershipAction::*;
+        for (sender, old, new, expected) in [
+            ("@bob:x", Some("join"), "leave", Some(Left)),
+            ("@mod:x", Some("join"), "ban", Some(Banned)),
+            ("@mod:x", Some("ban"), "leave", Some(Unbanned)),
+            ("@bob:x", None, "join", Some(Joined)),
+            ("@mod:x", None, "invite", Some(Invited)),
+            ("@mod:x", Some("invite"), "leave", Some(Kicked)),
+            ("@bob:x", Some("leave"), "knock", Some(Knocked)),
+            // A new display name is no membership change.
+            ("@bob:x", Some("join"), "join", None),
+        ] {
+            assert_eq!(action(sender, old, new), expected, "{:?} to {}", old, new);
+        }
+    }
+
+    #[test]
+    fn test_other_entries() {
+        let name = json!({
+            "type": "m.room.name",
+            "event_id": "$n",
+            "sender": "@mod:x",
+            "state_key": "",
+            "origin_server_ts": 5,
+            "content": {"name": "Raids"},
+            "prev_content": {"name": "Lobby"},
+        });
+        let name = entry(&name).unwrap();
+        assert_eq!(name.kind, AuditKind::Name);
+        assert_eq!(name.target, None);
+        assert_eq!(
+            name.changes,
+            vec![change("name", Some("Lobby"), Some("Raids"))]
+        );
+
+        let acl = json!({
+            "type": "m.room.server_acl",
+            "event_id": "$a",
+            "sender": "@mod:x",
+            "state_key": "",
+            "content": {"allow": ["*"], "deny": ["evil.example", "spam.example"]},
+            "unsigned": {"prev_content": {"allow": ["*"], "deny": ["spam.example"]}},
+        });
+        assert_eq!(
+            entry(&acl).unwrap().changes,
+            vec![change("deny", None, Some("evil.example"))]
+        );
+
+        let unchanged_topic = json!({
+            "type": "m.room.topic",
+            "event_id": "$t",
+            "sender": "@mod:x",
+            "state_key": "",
+            "content": {"topic": "Raid night"},
+            "unsigned": {"prev_content": {"topic": "Raid night"}},
+        });
+        assert_eq!(entry(&unchanged_topic), None);
+        let message = json!({"type": "m.room.message", "event_id": "$x", "sender": "@a:x"});
+        assert_eq!(entry(&message), None);
+    }
+
+    #[test]
+    fn test_actor_filter() {
+        let mut kick = entry(&member_event("@mod:x", "@bob:x", Some("join"), "leave")).unwrap();
+        kick.actor_name = Some("Moderator Mia".to_string());
+        assert!(kick.matches_actor(""));
+        assert!(kick.matches_actor("@MOD"));
+        assert!(kick.matches_actor(" mia "));
+        assert!(!kick.matches_actor("bob"));
+    }
+}
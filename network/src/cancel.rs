@@ -0,0 +1,91 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Error returned by `CancelToken::run` when the request was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Request cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Shared flag for abandoning an in-flight request, e.g. a history fetch for
+/// a room the user has already switched away from. Clones share state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` has been called.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Drive `fut` to completion unless the token is cancelled first, in
+    /// which case `fut` is dropped.
+    pub async fn run<F: Future>(&self, fut: F) -> Result<F::Output, Cancelled> {
+        tokio::select! {
+            biased;
+            _ = self.cancelled() => Err(Cancelled),
+            out = fut => Ok(out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_interrupts_pending_request() {
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move { canceller.cancel() });
+
+        let result = token.run(std::future::pending::<()>()).await;
+        assert_eq!(result, Err(Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_uncancelled_request_completes() {
+        let token = CancelToken::new();
+        assert_eq!(token.run(async { 7 }).await, Ok(7));
+
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.run(async { 7 }).await, Err(Cancelled));
+    }
+}
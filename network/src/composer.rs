@@ -0,0 +1,58 @@
+//! What the composer keeps between launches, per account, in
+//! `~/.gamechat/composer.json`: the messages sent, to recall them with the
+//! arrow keys.
+
+use crate::session::app_dir;
+use anyhow::{Context, Result};
+use chat_core::input_history::InputHistory;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+pub struct ComposerStore;
+
+impl ComposerStore {
+    fn path() -> Result<PathBuf> {
+        Ok(app_dir()?.join("composer.json"))
+    }
+
+    fn load_all() -> Result<BTreeMap<String, InputHistory>> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let data = fs::read_to_string(&path).context("Failed to read composer file")?;
+        serde_json::from_str(&data).context("Failed to parse composer file")
+    }
+
+    fn save_all(all: &BTreeMap<String, InputHistory>) -> Result<()> {
+        let data = serde_json::to_string_pretty(all)?;
+        fs::write(Self::path()?, data).context("Failed to write composer file")
+    }
+
+    /// The messages `user_id` sent, or none if unreadable.
+    pub fn load(user_id: &str) -> InputHistory {
+        match Self::load_all() {
+            Ok(mut all) => all.remove(user_id).unwrap_or_default(),
+            Err(e) => {
+                tracing::warn!("Starting with no sent messages: {:#}", e);
+                InputHistory::default()
+            }
+        }
+    }
+
+    pub fn save(user_id: &str, history: &InputHistory) -> Result<()> {
+        let mut all = Self::load_all().unwrap_or_default();
+        all.insert(user_id.to_string(), history.clone());
+        Self::save_all(&all)
+    }
+
+    /// Forget what `user_id` sent, e.g. as they log out.
+    pub fn delete(user_id: &str) -> Result<()> {
+        let mut all = Self::load_all().unwrap_or_default();
+        if all.remove(user_id).is_some() {
+            Self::save_all(&all)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,704 @@
+//! Application settings, persisted in `~/.gamechat/config.json`.
+
+use crate::cue_sound::RoomSound;
+use crate::session::app_dir;
+use crate::timeline::TimelineFilter;
+use crate::voice_clip;
+use anyhow::{bail, Context, Result};
+use chat_core::emoji::{EmojiUsage, SkinTone};
+use chat_core::game_invite;
+use chat_core::keybindings::Keybindings;
+use chat_core::keywords::Keyword;
+use chat_core::quiet_hours::Schedule;
+use chat_core::rich_presence::{self, Game};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// Where the ringtone and notification chimes play.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub enum CueOutput {
+    /// On the voice output device.
+    #[default]
+    Voice,
+    /// On a device of their own; `None` is the system default.
+    Device(Option<String>),
+    Off,
+}
+
+/// Voice capture and playback settings.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Device names as reported by the system; `None` uses the default.
+    pub input_device: Option<String>,
+    pub output_device: Option<String>,
+    pub cue_output: CueOutput,
+    /// Multiplier applied to the microphone, from 0.0 to 2.0.
+    pub input_gain: f32,
+    /// Input level (0.0–1.0, the scale of the mic meter) below which voice
+    /// activity detection stops transmitting.
+    pub vad_threshold: f32,
+    pub noise_suppression: bool,
+    /// Milliseconds of audio per packet: 10, 20 or 40. Shorter frames cut
+    /// latency, longer ones send fewer packets.
+    pub frame_ms: u32,
+    /// How much incoming audio is held before it plays, in milliseconds, to
+    /// ride out packets arriving unevenly.
+    pub jitter_ms: u32,
+    /// Longest voice clip recorded from the composer, in seconds.
+    pub max_clip_secs: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            input_device: None,
+            output_device: None,
+            cue_output: CueOutput::Voice,
+            input_gain: 1.0,
+            vad_threshold: 0.02,
+            noise_suppression: true,
+            frame_ms: 20,
+            jitter_ms: 60,
+            max_clip_secs: voice_clip::DEFAULT_MAX_SECS,
+        }
+    }
+}
+
+/// Where the main window was and how big, in physical pixels.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    /// The size when not maximized, so un-maximizing goes back to it.
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+/// Settings kept apart for each account signed in on this machine.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AccountConfig {
+    /// Keyboard shortcuts, push-to-talk among them.
+    pub keybindings: Keybindings,
+    /// The window as this account left it; `None` opens it at its default
+    /// size.
+    pub window: Option<WindowGeometry>,
+    /// Words that notify like a mention of us.
+    pub keywords: Vec<Keyword>,
+    /// Our own status message, published again as we sign in.
+    pub status_message: Option<String>,
+    /// Messages we reported, so their menu says so.
+    pub reported: Vec<String>,
+    /// Rooms we knocked on and haven't been let into yet.
+    pub knocks: Vec<PendingKnock>,
+    /// Where the homeserver tells us of mentions while the app is closed.
+    pub pushers: PusherConfig,
+    /// Rooms whose bridged messages are shown as the bridge's bot sent
+    /// them, rather than as their author's.
+    pub unbridged_rooms: Vec<String>,
+    /// Rooms pinned to the top of the sidebar, top first, as last known;
+    /// used until account data says otherwise, or when it can't be reached.
+    pub favorites: Vec<String>,
+    /// Words added to the spell checker's dictionary, in lower case.
+    pub dictionary: Vec<String>,
+    /// Rooms notifying with a sound of their own rather than the system's,
+    /// by room id.
+    pub room_sounds: BTreeMap<String, RoomSound>,
+    /// Each user's master key as it was when we verified them, by user
+    /// id, to tell when it changes.
+    pub verified_keys: BTreeMap<String, String>,
+}
+
+/// Pushers registered for the account as it signs in. Empty fields are
+/// off.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PusherConfig {
+    /// Mail mentions to this address, which must be on the account.
+    pub email: Option<String>,
+    pub gateway: Option<GatewayConfig>,
+}
+
+/// A push gateway, e.g. a companion app's, that the homeserver posts our
+/// notifications to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GatewayConfig {
+    /// Its `/_matrix/push/v1/notify` URL.
+    pub url: String,
+    /// Which app the gateway forwards to, e.g. `org.example.companion`.
+    pub app_id: String,
+}
+
+/// A knock of ours waiting for an answer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PendingKnock {
+    pub room_id: String,
+    /// What we knocked on, e.g. `#lobby:example.org`, to list it by.
+    pub address: String,
+}
+
+/// Publishing the game being played as our status message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RichPresenceConfig {
+    /// Off unless asked for: it looks at every running process.
+    pub enabled: bool,
+    pub games: Vec<Game>,
+}
+
+impl Default for RichPresenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            games: rich_presence::default_games(),
+        }
+    }
+}
+
+/// Preview cards for links in messages.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LinkPreviewConfig {
+    pub enabled: bool,
+    /// Also in encrypted rooms, which tells the homeserver what was linked.
+    pub encrypted_rooms: bool,
+}
+
+impl Default for LinkPreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            encrypted_rooms: false,
+        }
+    }
+}
+
+/// Spell checking in the composer.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SpellingConfig {
+    pub enabled: bool,
+    /// The dictionary's language, e.g. "en_GB"; empty for the interface's.
+    pub language: String,
+    /// A folder of Hunspell dictionaries looked in first; empty for none.
+    pub dictionary_dir: String,
+}
+
+impl Default for SpellingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            language: String::new(),
+            dictionary_dir: String::new(),
+        }
+    }
+}
+
+/// What is done to images before they are uploaded.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// Scale images down and encode them again, unless the upload prompt
+    /// asks for the original.
+    pub compress_images: bool,
+    /// Longest side, in pixels, of a compressed image.
+    pub max_dimension: u32,
+    /// JPEG quality, from 1 to 100.
+    pub quality: u8,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            compress_images: true,
+            max_dimension: 2048,
+            quality: 80,
+        }
+    }
+}
+
+/// Going Idle by itself after a while without input.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Minutes without input before going Idle; 0 never does.
+    pub after_minutes: u32,
+    /// Keep notifications silent while away.
+    pub mute_sounds: bool,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            after_minutes: 10,
+            mute_sounds: false,
+        }
+    }
+}
+
+/// Loading the account after signing in.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StartupConfig {
+    /// Rooms resolved at once; more is faster on a quick homeserver but
+    /// can trip its rate limits.
+    pub room_concurrency: usize,
+    /// Messages left unsent by the last run that are older than this start
+    /// out unticked in the dialog offering to send them.
+    pub unsent_max_age_hours: u64,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            room_concurrency: crate::startup::DEFAULT_CONCURRENCY,
+            unsent_max_age_hours: chat_core::outbox::DEFAULT_MAX_AGE_HOURS,
+        }
+    }
+}
+
+/// Joining game invites from the timeline.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GameInviteConfig {
+    /// Link schemes that Join opens, after asking, e.g. "steam"; other
+    /// connection strings are copied instead.
+    pub launch_schemes: Vec<String>,
+}
+
+impl Default for GameInviteConfig {
+    fn default() -> Self {
+        Self {
+            launch_schemes: game_invite::default_launch_schemes(),
+        }
+    }
+}
+
+/// The overlay: a small window kept above games, showing who is in voice
+/// and the latest messages.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct OverlayConfig {
+    /// Show it on joining a voice channel.
+    pub show_on_voice: bool,
+    /// 0.2–1.0; the game shows through the rest.
+    pub opacity: f32,
+    /// Let clicks through to the game; it can't be moved meanwhile.
+    pub click_through: bool,
+    /// Where it was last moved to, in physical pixels; `None` until then.
+    pub position: Option<OverlayPosition>,
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        Self {
+            show_on_voice: false,
+            opacity: 0.85,
+            click_through: false,
+            position: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct OverlayPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Sending in encrypted rooms.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Fail sends into rooms where someone has a device they haven't
+    /// verified, rather than only warning.
+    pub never_send_to_unverified: bool,
+}
+
+/// Checks before a message goes out.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SendConfig {
+    /// Ask before sending to rooms with more members than this; 0 never
+    /// asks. Direct chats never ask.
+    pub confirm_above_members: u64,
+}
+
+impl Default for SendConfig {
+    fn default() -> Self {
+        Self {
+            confirm_above_members: chat_core::send_guard::DEFAULT_CONFIRM_ABOVE,
+        }
+    }
+}
+
+/// What timelines show besides messages.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TimelineConfig {
+    pub filter: TimelineFilter,
+    /// Rooms that filter differently, by room id.
+    pub rooms: BTreeMap<String, TimelineFilter>,
+    /// Most messages a room keeps on screen while scrolled to the bottom;
+    /// older ones are brought back as they are scrolled up to.
+    pub window: usize,
+}
+
+impl Default for TimelineConfig {
+    fn default() -> Self {
+        Self {
+            filter: TimelineFilter::default(),
+            rooms: BTreeMap::new(),
+            window: 1000,
+        }
+    }
+}
+
+impl TimelineConfig {
+    /// The filter for `room_id`: its own, or the default.
+    pub fn for_room(&self, room_id: &str) -> TimelineFilter {
+        self.rooms.get(room_id).copied().unwrap_or(self.filter)
+    }
+}
+
+/// Translating messages through a LibreTranslate server. Messages go to it
+/// only when the user asks, or from the rooms in `rooms`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TranslationConfig {
+    /// The server, e.g. "https://libretranslate.example.org"; empty for
+    /// none.
+    pub url: String,
+    /// Empty for servers that don't want one.
+    pub api_key: String,
+    /// The language to translate into, e.g. "en"; empty for the interface's.
+    pub target: String,
+    /// Rooms whose messages are translated as they are shown, with the
+    /// language they are translated from, by room id.
+    pub rooms: BTreeMap<String, String>,
+}
+
+/// How long what was seen is kept on this machine: cached media and link
+/// previews, and the messages we sent for the composer to recall. Nothing
+/// on the server is touched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Retention {
+    #[default]
+    Forever,
+    Days30,
+    Days7,
+    /// Kept in memory while signed in, never written to disk.
+    SessionOnly,
+}
+
+impl Retention {
+    const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+    /// How old, in ms, what is kept on disk may get; `None` for no limit.
+    pub fn max_age_ms(self) -> Option<u64> {
+        match self {
+            Retention::Forever => None,
+            Retention::Days30 => Some(30 * Self::DAY_MS),
+            Retention::Days7 => Some(7 * Self::DAY_MS),
+            Retention::SessionOnly => Some(0),
+        }
+    }
+
+    /// Whether anything is written to disk at all.
+    pub fn on_disk(self) -> bool {
+        self != Retention::SessionOnly
+    }
+
+    /// Things last used before this, in ms since the epoch, are forgotten.
+    pub fn cutoff(self, now: u64) -> Option<u64> {
+        self.max_age_ms().map(|age| now.saturating_sub(age))
+    }
+}
+
+/// Media kept on disk.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Past this, the least recently used files are deleted.
+    pub max_size_mb: u64,
+    pub retention: Retention,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: crate::media_cache::DEFAULT_MAX_MB,
+            retention: Retention::default(),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// What the login screen starts with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LoginPreferences {
+    /// The homeserver picked when first run; `None` offers matrix.org.
+    pub homeserver: Option<String>,
+}
+
+/// The shape of the config this build writes. Bump it, with a step in
+/// `MIGRATIONS`, when a change needs older files rewritten to be read.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Steps bringing a config written at version `i` up to `i + 1`, run on
+/// its JSON before it is read.
+const MIGRATIONS: [fn(&mut Value); CONFIG_VERSION as usize] = [unversioned];
+
+/// Files from before versioning have the same shape as version 1.
+fn unversioned(_: &mut Value) {}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    /// `CONFIG_VERSION` as of the last save; 0 for files older than that.
+    pub version: u32,
+    pub audio: AudioConfig,
+    /// Show message text in desktop notifications.
+    pub notification_previews: bool,
+    /// Hide to the tray instead of quitting when the window is closed.
+    pub close_to_tray: bool,
+    /// Tone for emoji that take one, as last picked.
+    pub skin_tone: SkinTone,
+    /// Emoji used so far, to offer them first.
+    pub emoji_usage: EmojiUsage,
+    /// By user id.
+    pub accounts: BTreeMap<String, AccountConfig>,
+    pub login: LoginPreferences,
+    pub rich_presence: RichPresenceConfig,
+    pub idle: IdleConfig,
+    /// Going Do Not Disturb by itself at set times.
+    pub quiet_hours: Schedule,
+    pub link_previews: LinkPreviewConfig,
+    /// Show animated images still.
+    pub reduce_motion: bool,
+    pub uploads: UploadConfig,
+    pub startup: StartupConfig,
+    pub cache: CacheConfig,
+    pub encryption: EncryptionConfig,
+    pub sending: SendConfig,
+    pub timeline: TimelineConfig,
+    pub translation: TranslationConfig,
+    pub spelling: SpellingConfig,
+    pub game_invites: GameInviteConfig,
+    pub overlay: OverlayConfig,
+    /// The interface's language, e.g. "fr"; `None` is English.
+    pub language: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            audio: AudioConfig::default(),
+            notification_previews: true,
+            close_to_tray: false,
+            skin_tone: SkinTone::default(),
+            emoji_usage: EmojiUsage::default(),
+            accounts: BTreeMap::new(),
+            login: LoginPreferences::default(),
+            rich_presence: RichPresenceConfig::default(),
+            idle: IdleConfig::default(),
+            quiet_hours: Schedule::default(),
+            link_previews: LinkPreviewConfig::default(),
+            reduce_motion: false,
+            uploads: UploadConfig::default(),
+            startup: StartupConfig::default(),
+            cache: CacheConfig::default(),
+            encryption: EncryptionConfig::default(),
+            sending: SendConfig::default(),
+            timeline: TimelineConfig::default(),
+            translation: TranslationConfig::default(),
+            spelling: SpellingConfig::default(),
+            game_invites: GameInviteConfig::default(),
+            overlay: OverlayConfig::default(),
+            language: None,
+        }
+    }
+}
+
+impl Config {
+    /// The settings of `user_id`, or the defaults for an account not seen
+    /// before.
+    pub fn account(&self, user_id: &str) -> AccountConfig {
+        self.accounts.get(user_id).cloned().unwrap_or_default()
+    }
+}
+
+/// Read `value`, a config as written by this or an older version, running
+/// the migrations it needs. One from a newer version is refused, rather
+/// than read with what it added thrown away.
+pub fn from_value(mut value: Value) -> Result<Config> {
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    let version = usize::try_from(version).unwrap_or(usize::MAX);
+    let Some(steps) = MIGRATIONS.get(version..) else {
+        bail!("These settings are from a newer version of GameChat");
+    };
+    for step in steps {
+        step(&mut value);
+    }
+    if let Some(object) = value.as_object_mut() {
+        object.insert("version".to_string(), CONFIG_VERSION.into());
+    }
+    serde_json::from_value(value).context("The settings are damaged")
+}
+
+/// Loads and saves the config file. Missing settings take their defaults,
+/// so files written by older versions keep working.
+pub struct ConfigManager;
+
+/// The config as this process last loaded or saved it.
+static CACHED: RwLock<Option<Config>> = RwLock::new(None);
+
+impl ConfigManager {
+    fn config_path() -> Result<PathBuf> {
+        Ok(app_dir()?.join("config.json"))
+    }
+
+    /// Whether a config has been saved, i.e. this is not the first run.
+    pub fn exists() -> bool {
+        Self::config_path().is_ok_and(|path| path.exists())
+    }
+
+    /// The saved config, or the defaults if there is none or it is unreadable.
+    pub fn load() -> Config {
+        let config = match Self::try_load() {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Using default settings: {:#}", e);
+                Config::default()
+            }
+        };
+        *CACHED.write().unwrap() = Some(config.clone());
+        config
+    }
+
+    /// `f` of the config as this process last loaded or saved it, read
+    /// from disk only the first time; for checks made often, such as on
+    /// every send.
+    pub fn cached<R>(f: impl FnOnce(&Config) -> R) -> R {
+        if let Some(config) = CACHED.read().unwrap().as_ref() {
+            return f(config);
+        }
+        f(&Self::load())
+    }
+
+    fn try_load() -> Result<Config> {
+        let path = Self::config_path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let data = fs::read_to_string(&path).context("Failed to read config file")?;
+        from_value(serde_json::from_str(&data).context("Failed to parse config file")?)
+    }
+
+    pub fn save(config: &Config) -> Result<()> {
+        let path = Self::config_path()?;
+        let data = serde_json::to_string_pretty(config)?;
+        fs::write(&path, data).context("Failed to write config file")?;
+        *CACHED.write().unwrap() = Some(config.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::keybindings::{Action, Shortcut};
+
+    #[test]
+    fn test_missing_settings_take_defaults() {
+        let config: Config =
+            serde_json::from_str(r#"{"audio": {"input_device": "USB Mic"}}"#).unwrap();
+        assert_eq!(config.audio.input_device.as_deref(), Some("USB Mic"));
+        assert_eq!(config.audio.input_gain, 1.0);
+        assert!(config.notification_previews);
+        assert!(!config.rich_presence.enabled);
+        assert!(!config.rich_presence.games.is_empty());
+        assert_eq!(config.idle.after_minutes, 10);
+        assert!(config.link_previews.enabled && !config.link_previews.encrypted_rooms);
+        assert!(config.uploads.compress_images);
+        assert_eq!(config.uploads.quality, 80);
+        assert_eq!(config.startup.room_concurrency, 8);
+        assert_eq!(config.sending.confirm_above_members, 1000);
+        assert_eq!(config.cache.max_bytes(), 500 * 1024 * 1024);
+        assert_eq!(config.game_invites.launch_schemes, ["steam"]);
+        assert!(!config.overlay.show_on_voice);
+        assert_eq!(config.overlay.position, None);
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn test_older_configs_are_migrated_and_newer_refused() {
+        let unversioned = serde_json::json!({"close_to_tray": true});
+        let config = from_value(unversioned).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.close_to_tray);
+
+        let newer = serde_json::json!({"version": CONFIG_VERSION + 1});
+        assert!(from_value(newer).is_err());
+    }
+
+    #[test]
+    fn test_accounts_keep_their_own_settings() {
+        let config: Config = serde_json::from_str(
+            r#"{"accounts": {"@a:x.org": {"keybindings": {"push_to_talk": "KeyV"}}}}"#,
+        )
+        .unwrap();
+        let ptt = |user_id| config.account(user_id).keybindings.get(Action::PushToTalk);
+        assert_eq!(ptt("@a:x.org"), Some(Shortcut::new("KeyV")));
+        assert_eq!(ptt("@b:x.org"), None);
+
+        assert_eq!(config.account("@a:x.org").window, None);
+        assert!(config.account("@a:x.org").keywords.is_empty());
+    }
+
+    #[test]
+    fn test_rooms_can_filter_their_own_timeline() {
+        let config: Config = serde_json::from_str(
+            r#"{"timeline": {"filter": {"membership": false}, "rooms": {"!a:x.org": {}}}}"#,
+        )
+        .unwrap();
+        assert!(!config.timeline.for_room("!b:x.org").membership);
+        assert!(config.timeline.filter.group_membership);
+        assert!(config.timeline.for_room("!a:x.org").membership);
+        assert!(config.timeline.for_room("!b:x.org").voice);
+        assert!(config.timeline.for_room("!b:x.org").topic);
+    }
+
+    #[test]
+    fn test_retention() {
+        let config: Config = serde_json::from_str(r#"{"cache": {"max_size_mb": 100}}"#).unwrap();
+        assert_eq!(config.cache.retention, Retention::Forever);
+        assert_eq!(Retention::Forever.cutoff(1_000), None);
+
+        let week = Retention::Days7.max_age_ms().unwrap();
+        assert_eq!(Retention::Days7.cutoff(week + 5), Some(5));
+        assert_eq!(Retention::Days30.cutoff(5), Some(0));
+        assert_eq!(Retention::SessionOnly.cutoff(5), Some(5));
+        assert!(!Retention::SessionOnly.on_disk());
+        assert!(Retention::Days7.on_disk());
+    }
+}
@@ -0,0 +1,96 @@
+//! Whether the sync loop is reaching the homeserver, and how long it waits
+//! before trying again when it is not.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+/// Wait after the first failed sync, doubled with each further failure.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The last `attempt` syncs in a row failed.
+    Reconnecting {
+        attempt: u32,
+    },
+}
+
+impl ConnectionState {
+    pub fn is_connected(self) -> bool {
+        self == ConnectionState::Connected
+    }
+
+    /// Failed syncs in a row; 0 when connected.
+    pub fn attempt(self) -> u32 {
+        match self {
+            ConnectionState::Connected => 0,
+            ConnectionState::Reconnecting { attempt } => attempt,
+        }
+    }
+
+    /// The state after a sync failed.
+    pub fn failed(self) -> Self {
+        ConnectionState::Reconnecting {
+            attempt: self.attempt().saturating_add(1),
+        }
+    }
+}
+
+/// Wakes the sync loop from its backoff to try again at once. Clones share
+/// state.
+#[derive(Debug, Clone, Default)]
+pub struct Retry {
+    notify: Arc<Notify>,
+}
+
+impl Retry {
+    pub fn now(&self) {
+        self.notify.notify_one();
+    }
+
+    pub(crate) async fn requested(&self) {
+        self.notify.notified().await
+    }
+}
+
+/// The sync loop's state, and a way to hurry it along.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub state: watch::Receiver<ConnectionState>,
+    pub retry: Retry,
+}
+
+/// How long to wait before sync attempt `attempt + 1`.
+pub fn backoff(attempt: u32) -> Duration {
+    let doublings = attempt.saturating_sub(1).min(16);
+    FIRST_BACKOFF
+        .saturating_mul(1 << doublings)
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_count_attempts() {
+        let state = ConnectionState::Connected.failed();
+        assert_eq!(state, ConnectionState::Reconnecting { attempt: 1 });
+        assert_eq!(state.failed(), ConnectionState::Reconnecting { attempt: 2 });
+        assert_eq!(state.failed().attempt(), 2);
+        assert!(!state.is_connected());
+        assert_eq!(ConnectionState::Connected.attempt(), 0);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(16));
+        assert_eq!(backoff(6), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+}
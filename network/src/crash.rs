@@ -0,0 +1,496 @@
+//! Crash reports: a panic writes one to `~/.gamechat/crashes/` with the
+//! panic, a backtrace, the version and OS, the last log lines and the last
+//! diagnostics, so there is something to send us. The log lines come from
+//! a tracing layer keeping the newest in memory, beside the one printing
+//! warnings to stderr. Everything written is redacted: access tokens as in
+//! diagnostics, and message bodies, which the SDK's logs can carry.
+
+use crate::diagnostics;
+use crate::session::app_dir;
+use anyhow::{Context, Result};
+use chat_core::time;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::{Context as LayerContext, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// How many log lines a report ends with.
+pub const LOG_LINES: usize = 200;
+/// Fields whose values are message text.
+const BODY_FIELDS: [&str; 2] = ["body", "formatted_body"];
+/// Where our own crates log from: their module paths, and `startup`'s
+/// timings.
+const APP_TARGETS: [&str; 5] = ["ui", "network", "chat_core", "gamechat_cli", "startup"];
+
+/// What gets logged: our own crates from `app` up, the SDK's from `sdk`
+/// up, and the HTTP and TLS stacks beneath it, like anything else, only
+/// from warnings up.
+fn targets(app: LevelFilter, sdk: LevelFilter) -> Targets {
+    APP_TARGETS
+        .iter()
+        .fold(Targets::new(), |targets, target| {
+            targets.with_target(*target, app)
+        })
+        .with_target("matrix_sdk", sdk)
+        .with_target("hyper", LevelFilter::WARN)
+        .with_target("rustls", LevelFilter::WARN)
+        .with_default(LevelFilter::WARN)
+}
+
+/// Our own info, warnings and errors, as lines on stderr.
+fn stderr_layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(targets(LevelFilter::INFO, LevelFilter::WARN))
+}
+
+/// Print what is logged to stderr, without keeping any for crash reports,
+/// e.g. in the command line client.
+pub fn log_to_stderr() {
+    if let Err(e) = tracing_subscriber::registry()
+        .with(stderr_layer())
+        .try_init()
+    {
+        eprintln!("Failed to print log lines: {}", e);
+    }
+}
+
+/// The newest log lines, oldest first, dropping the oldest past capacity;
+/// with a capacity of 0 it keeps none. A tracing layer: each event becomes
+/// one redacted line.
+#[derive(Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        // A panic while logging must not take the log with it.
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() >= self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
+/// An event's fields as ` key=value`, the message first without its key.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).ok();
+        } else if BODY_FIELDS.contains(&field.name()) {
+            write!(self.rest, " {}=<redacted>", field.name()).ok();
+        } else {
+            write!(self.rest, " {}={:?}", field.name(), value).ok();
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        let line = format!(
+            "{} {:>5} {}: {}{}",
+            time::format_exact(time::now_ms()),
+            meta.level().as_str(),
+            meta.target(),
+            fields.message,
+            fields.rest
+        );
+        self.push(redact(&line));
+    }
+}
+
+/// `text` with access tokens and message bodies blanked. Bodies are the
+/// values of `body` and `formatted_body`, whether as JSON, JSON quoted in
+/// a log line, or `body=…` fields.
+pub fn redact(text: &str) -> String {
+    let text = diagnostics::redact(text);
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(at) = rest.find("body") {
+        let after = at + "body".len();
+        // A key ends with "body", e.g. formatted_body, but isn't "nobody".
+        let key = !rest[..at].ends_with(|c: char| c.is_alphanumeric())
+            && !rest[after..].starts_with(is_word);
+        let value = key.then(|| body_value(&rest[after..])).flatten();
+        let Some((start, end)) = value else {
+            out.push_str(&rest[..after]);
+            rest = &rest[after..];
+            continue;
+        };
+        out.push_str(&rest[..after + start]);
+        if end > start {
+            out.push_str("<redacted>");
+        }
+        rest = &rest[after + end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Where the value of a key starts and ends in `text`, which follows the
+/// key: past a closing quote, a `:` or `=`, and an opening quote when it
+/// is quoted.
+fn body_value(text: &str) -> Option<(usize, usize)> {
+    let is_space = |c: char| c == ' ' || c == '\\' || c == '"';
+    let sep = text.find(|c: char| !is_space(c))?;
+    if !text[sep..].starts_with([':', '=']) {
+        return None;
+    }
+    let value = sep + 1;
+    let start = value + text[value..].find(|c: char| c != ' ')?;
+    let quoted = text[start..].starts_with('"');
+    let escaped = text[start..].starts_with("\\\"");
+    if escaped {
+        // JSON inside a quoted string: ends at the next \" not escaped
+        // again, i.e. not \\\".
+        let start = start + 2;
+        let mut end = start;
+        while let Some(at) = text[end..].find("\\\"") {
+            let at = end + at;
+            if !text[..at].ends_with("\\\\") {
+                return Some((start, at));
+            }
+            end = at + 2;
+        }
+        return Some((start, text.len()));
+    }
+    if quoted {
+        let start = start + 1;
+        let mut escape = false;
+        for (i, c) in text[start..].char_indices() {
+            match c {
+                '\\' if !escape => escape = true,
+                '"' if !escape => return Some((start, start + i)),
+                _ => escape = false,
+            }
+        }
+        return Some((start, text.len()));
+    }
+    let end = text[start..]
+        .find(|c: char| c.is_whitespace() || ",;})]".contains(c))
+        .map_or(text.len(), |len| start + len);
+    Some((start, end))
+}
+
+/// What a crash report says, written out by `to_text`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub at_ms: u64,
+    pub version: String,
+    pub os: String,
+    pub thread: String,
+    pub message: String,
+    /// Where in the source it panicked, e.g. "ui/src/main.rs:12:5".
+    pub location: Option<String>,
+    pub backtrace: String,
+    /// The last diagnostics run, if any were this session.
+    pub diagnostics: Option<String>,
+    pub log: Vec<String>,
+}
+
+impl Report {
+    /// The report as written, redacted.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "gamechat crash report").unwrap();
+        writeln!(text, "Time: {}", time::format_exact(self.at_ms)).unwrap();
+        writeln!(text, "Version: {}", self.version).unwrap();
+        writeln!(text, "OS: {}", self.os).unwrap();
+        writeln!(text, "Thread: {}", self.thread).unwrap();
+        writeln!(text).unwrap();
+        writeln!(text, "Panic: {}", self.message).unwrap();
+        if let Some(location) = &self.location {
+            writeln!(text, "At: {}", location).unwrap();
+        }
+        writeln!(text, "\nBacktrace:\n{}", self.backtrace.trim_end()).unwrap();
+        let diagnostics = self.diagnostics.as_deref().unwrap_or("(not run)\n");
+        write!(text, "\nDiagnostics:\n{}", diagnostics).unwrap();
+        writeln!(text, "\nLast {} log lines:", self.log.len()).unwrap();
+        for line in &self.log {
+            writeln!(text, "{}", line).unwrap();
+        }
+        redact(&text)
+    }
+
+    /// Write the report into `dir`, named after its time. Returns where.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir).context("Failed to create the crashes directory")?;
+        let path = dir.join(format!("crash-{}.txt", self.at_ms));
+        fs::write(&path, self.to_text())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Where crash reports are written.
+pub fn crashes_dir() -> Result<PathBuf> {
+    Ok(app_dir()?.join("crashes"))
+}
+
+/// The reports in `dir` not yet shown, oldest first.
+pub fn pending(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with("crash-")
+                        && name.ends_with(".txt")
+                        && !name.ends_with(".seen.txt")
+                })
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Keep the report at `path`, but don't offer it again.
+pub fn mark_seen(path: &Path) -> Result<()> {
+    let seen = path.with_extension("seen.txt");
+    fs::rename(path, &seen).with_context(|| format!("Failed to rename {}", path.display()))
+}
+
+/// What the panic hook reads: the log, and the last diagnostics.
+struct HookState {
+    log: LogBuffer,
+    version: String,
+    diagnostics: Mutex<Option<String>>,
+}
+
+static STATE: OnceLock<HookState> = OnceLock::new();
+
+/// Keep the diagnostics just run for a crash report.
+pub fn set_diagnostics(text: String) {
+    if let Some(state) = STATE.get() {
+        *state.diagnostics.lock().unwrap_or_else(|e| e.into_inner()) = Some(text);
+    }
+}
+
+/// Keep the last log lines in memory, and write a crash report on any
+/// panic before the default hook prints it. Once per process; `version`
+/// is the app's.
+pub fn install(version: &str) {
+    let log = LogBuffer::new(LOG_LINES);
+    let state = HookState {
+        log: log.clone(),
+        version: version.to_string(),
+        diagnostics: Mutex::new(None),
+    };
+    if STATE.set(state).is_err() {
+        return;
+    }
+    let layer = log.with_filter(targets(LevelFilter::DEBUG, LevelFilter::INFO));
+    let subscriber = tracing_subscriber::registry()
+        .with(layer)
+        .with(stderr_layer());
+    if let Err(e) = subscriber.try_init() {
+        eprintln!("Failed to keep log lines for crash reports: {}", e);
+    }
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_report(info) {
+            Ok(path) => eprintln!("Crash report written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write a crash report: {:#}", e),
+        }
+        previous(info);
+    }));
+}
+
+fn write_report(info: &PanicHookInfo<'_>) -> Result<PathBuf> {
+    let state = STATE.get().context("Crash reports aren't set up")?;
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "(not a string)".to_string());
+    let thread = std::thread::current();
+    let report = Report {
+        at_ms: time::now_ms(),
+        version: state.version.clone(),
+        os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        thread: thread.name().unwrap_or("unnamed").to_string(),
+        message,
+        location: info.location().map(|l| l.to_string()),
+        backtrace: Backtrace::force_capture().to_string(),
+        diagnostics: state
+            .diagnostics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone(),
+        log: state.log.lines(),
+    };
+    report.write(&crashes_dir()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_keeps_the_newest() {
+        let buffer = LogBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(format!("line {}", i));
+        }
+        assert_eq!(buffer.lines(), ["line 2", "line 3", "line 4"]);
+
+        let buffer = LogBuffer::new(0);
+        buffer.push("line 0".to_string());
+        assert!(buffer.lines().is_empty());
+    }
+
+    #[test]
+    fn test_layer_records_events() {
+        let buffer = LogBuffer::new(LOG_LINES);
+        let subscriber = tracing_subscriber::registry().with(buffer.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "sync", room = "!r:x", "joined");
+            tracing::debug!(target: "send", body = "gg wp", "sent");
+        });
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].ends_with(" INFO sync: joined room=\"!r:x\""),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].ends_with("DEBUG send: sent body=<redacted>"),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn test_app_logs_more_than_its_dependencies() {
+        let buffer = LogBuffer::new(LOG_LINES);
+        let layer = buffer
+            .clone()
+            .with_filter(targets(LevelFilter::DEBUG, LevelFilter::INFO));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!(target: "network::sync", "kept");
+            tracing::debug!(target: "matrix_sdk_crypto::machine", "dropped");
+            tracing::info!(target: "matrix_sdk::http_client", "kept");
+            tracing::info!(target: "hyper::client", "dropped");
+            tracing::warn!(target: "rustls::conn", "kept");
+        });
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert!(lines.iter().all(|line| line.ends_with("kept")));
+    }
+
+    #[test]
+    fn test_redact_bodies() {
+        assert_eq!(
+            redact(r#"{"msgtype":"m.text","body":"gg wp","formatted_body":"<b>gg</b>"}"#),
+            r#"{"msgtype":"m.text","body":"<redacted>","formatted_body":"<redacted>"}"#
+        );
+        // JSON quoted in a log line, with a quote in the body.
+        assert_eq!(
+            redact(r#"content: "{\"body\": \"say \\\"hi\\\"\", \"msgtype\": \"m.text\"}""#),
+            r#"content: "{\"body\": \"<redacted>\", \"msgtype\": \"m.text\"}""#
+        );
+        assert_eq!(
+            redact("sent body=hello, to=!r:x"),
+            "sent body=<redacted>, to=!r:x"
+        );
+        assert_eq!(redact(r#"body: "a \"b\"" ok"#), r#"body: "<redacted>" ok"#);
+        assert_eq!(redact(r#"body="" ok"#), r#"body="" ok"#);
+        // Not keys.
+        assert_eq!(
+            redact("nobody=3 the body was empty"),
+            "nobody=3 the body was empty"
+        );
+    }
+
+    #[test]
+    fn test_redact_tokens() {
+        assert_eq!(
+            redact("GET /sync?access_token=syt_abc_123 Bearer xyz"),
+            "GET /sync?access_token=<redacted> Bearer <redacted>"
+        );
+    }
+
+    #[test]
+    fn test_report_is_redacted() {
+        let report = Report {
+            version: "1.2.3".to_string(),
+            message: "bad event {\"body\":\"secret\"}".to_string(),
+            log: vec!["token syt_abc".to_string()],
+            ..Default::default()
+        };
+        let text = report.to_text();
+        assert!(text.contains("Version: 1.2.3"));
+        assert!(text.contains("Diagnostics:\n(not run)"));
+        assert!(!text.contains("secret"));
+        assert!(!text.contains("syt_abc"));
+    }
+
+    #[test]
+    fn test_pending_and_seen() {
+        let dir = std::env::temp_dir().join(format!("crashes-{}", time::now_ms()));
+        let older = Report {
+            at_ms: 1,
+            ..Default::default()
+        };
+        let newer = Report {
+            at_ms: 2,
+            ..Default::default()
+        };
+        let older = older.write(&dir).unwrap();
+        let newer = newer.write(&dir).unwrap();
+        assert_eq!(pending(&dir), [older.clone(), newer.clone()]);
+
+        mark_seen(&older).unwrap();
+        assert_eq!(pending(&dir), [newer]);
+        assert!(dir.join("crash-1.seen.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+//! Notification sounds: the tones built in, and short WAV or Ogg Vorbis
+//! files the user picks. Either way a sound ends up as cues are played, one
+//! channel of `f32` samples at `RATE`, so files of any sample rate and
+//! channel count are mixed down and resampled as they are loaded.
+
+use crate::voice_clip;
+use anyhow::{bail, Context, Result};
+use lewton::inside_ogg::OggStreamReader;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The sample rate sounds are kept at.
+pub const RATE: u32 = 48_000;
+/// The longest a file can play, in seconds; a notification is a ping, not
+/// a song.
+pub const MAX_SECS: u32 = 5;
+/// Larger files are turned down before they are read.
+const MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+/// Loud files are brought down to this peak, near the built-in tones.
+const MAX_PEAK: f32 = 0.5;
+
+/// A room's own notification sound.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomSound {
+    Tone(Tone),
+    /// A WAV or Ogg Vorbis file of the user's.
+    File(PathBuf),
+}
+
+/// The sounds built in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tone {
+    /// Two rising notes, as keywords chime.
+    Chime,
+    /// One high note, fading.
+    Ping,
+    /// A low note with its octave, ringing on.
+    Bell,
+    /// Two soft knocks.
+    Knock,
+}
+
+const CHIME_HZ: [f32; 2] = [880.0, 1320.0];
+const CHIME_NOTE_SECS: f32 = 0.12;
+const CHIME_VOLUME: f32 = 0.15;
+
+impl Tone {
+    /// In the order the room settings list them.
+    pub const ALL: [Tone; 4] = [Tone::Chime, Tone::Ping, Tone::Bell, Tone::Knock];
+
+    fn secs(self) -> f32 {
+        match self {
+            Tone::Chime => CHIME_NOTE_SECS * CHIME_HZ.len() as f32,
+            Tone::Ping => 0.3,
+            Tone::Bell => 0.9,
+            Tone::Knock => 0.3,
+        }
+    }
+
+    /// The sample `t` seconds in.
+    fn wave(self, t: f32) -> f32 {
+        let sine = |hz: f32, t: f32| (t * hz * std::f32::consts::TAU).sin();
+        match self {
+            Tone::Chime => {
+                let note = (t / CHIME_NOTE_SECS) as usize;
+                match CHIME_HZ.get(note) {
+                    Some(&hz) => sine(hz, t) * CHIME_VOLUME,
+                    None => 0.0,
+                }
+            }
+            Tone::Ping => sine(1760.0, t) * (-t * 12.0).exp() * 0.2,
+            Tone::Bell => (sine(660.0, t) + sine(1320.0, t) * 0.4) * (-t * 4.0).exp() * 0.12,
+            Tone::Knock => {
+                // Each knock is a short, low thump.
+                let since = if t >= 0.15 { t - 0.15 } else { t };
+                sine(150.0, since) * (-since * 40.0).exp() * 0.3
+            }
+        }
+    }
+
+    pub fn sound(self) -> Sound {
+        let len = (self.secs() * RATE as f32) as usize;
+        Sound::new(
+            (0..len)
+                .map(|i| self.wave(i as f32 / RATE as f32))
+                .collect(),
+        )
+    }
+}
+
+/// A sound ready to play.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sound {
+    /// One channel at `RATE`.
+    samples: Arc<[f32]>,
+}
+
+impl Sound {
+    fn new(samples: Vec<f32>) -> Self {
+        Self {
+            samples: samples.into(),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / f64::from(RATE))
+    }
+
+    /// The sample `t` seconds in; silence past the end.
+    pub fn sample(&self, t: f32) -> f32 {
+        let at = (t * RATE as f32) as usize;
+        self.samples.get(at).copied().unwrap_or(0.0)
+    }
+}
+
+/// Read and decode the sound file at `path`.
+pub fn load(path: &Path) -> Result<Sound> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    if size > MAX_FILE_BYTES {
+        bail!("{} is too large for a notification sound", path.display());
+    }
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    decode(&data).with_context(|| format!("Can't play {}", path.display()))
+}
+
+/// Samples as a file holds them: interleaved over `channels`, at `rate`.
+struct Pcm {
+    rate: u32,
+    channels: u16,
+    samples: Vec<f32>,
+}
+
+/// Decode a WAV or Ogg Vorbis file to a sound of at most `MAX_SECS`.
+pub fn decode(data: &[u8]) -> Result<Sound> {
+    let pcm = if data.starts_with(b"RIFF") {
+        decode_wav(data)?
+    } else if data.starts_with(b"OggS") {
+        decode_vorbis(data)?
+    } else {
+        bail!("Only WAV and Ogg Vorbis files can be notification sounds");
+    };
+    if pcm.rate == 0 || pcm.channels == 0 {
+        bail!("The file has no sample rate or no channels");
+    }
+    let frames = pcm.samples.len() / usize::from(pcm.channels);
+    if frames == 0 {
+        bail!("The file is silent");
+    }
+    if frames as u64 > u64::from(pcm.rate) * u64::from(MAX_SECS) {
+        bail!("Notification sounds can be up to {} seconds", MAX_SECS);
+    }
+    let mono: Vec<f32> = voice_clip::downmix(&pcm.samples, pcm.channels).collect();
+    let mut samples = resample(&mono, pcm.rate, RATE);
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak > MAX_PEAK {
+        let gain = MAX_PEAK / peak;
+        samples.iter_mut().for_each(|s| *s *= gain);
+    }
+    Ok(Sound::new(samples))
+}
+
+/// The chunks of a RIFF file after its header, as (id, body).
+fn chunks(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = data.get(12..).unwrap_or_default();
+    std::iter::from_fn(move || {
+        let id = rest.get(..4)?;
+        let len = u32::from_le_bytes(rest.get(4..8)?.try_into().ok()?) as usize;
+        let body = rest.get(8..)?;
+        let body = &body[..len.min(body.len())];
+        // Bodies are padded to an even length.
+        rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+        Some((id, body))
+    })
+}
+
+fn decode_wav(data: &[u8]) -> Result<Pcm> {
+    if data.get(8..12) != Some(b"WAVE") {
+        bail!("The file is not a WAV file");
+    }
+    let mut format = None;
+    let mut samples = None;
+    for (id, body) in chunks(data) {
+        match id {
+            b"fmt " => format = Some(body),
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+    }
+    let (Some(format), Some(samples)) = (format, samples) else {
+        bail!("The WAV file is missing its format or its samples");
+    };
+    if format.len() < 16 {
+        bail!("The WAV file's format is cut short");
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([format[at], format[at + 1]]);
+    let mut tag = u16_at(0);
+    // Extensible: the real format starts the sub-format's GUID.
+    if tag == 0xFFFE && format.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let channels = u16_at(2);
+    let rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+    let bits = u16_at(14);
+    let samples = match (tag, bits) {
+        (1, 8) => samples
+            .iter()
+            .map(|&s| (f32::from(s) - 128.0) / 128.0)
+            .collect(),
+        (1, 16) => samples
+            .chunks_exact(2)
+            .map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / 32768.0)
+            .collect(),
+        (1, 24) => samples
+            .chunks_exact(3)
+            .map(|s| i32::from_le_bytes([0, s[0], s[1], s[2]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (1, 32) => samples
+            .chunks_exact(4)
+            .map(|s| i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (3, 32) => samples
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]).clamp(-1.0, 1.0))
+            .collect(),
+        _ => bail!(
+            "WAV files of {}-bit samples in format {} aren't supported",
+            bits,
+            tag
+        ),
+    };
+    Ok(Pcm {
+        rate,
+        channels,
+        samples,
+    })
+}
+
+fn decode_vorbis(data: &[u8]) -> Result<Pcm> {
+    let mut reader =
+        OggStreamReader::new(Cursor::new(data)).context("The file is not Ogg Vorbis")?;
+    let rate = reader.ident_hdr.audio_sample_rate;
+    let channels = u16::from(reader.ident_hdr.audio_channels);
+    // Stop reading once it is too long, rather than decode all of it.
+    let limit = rate as usize * usize::from(channels) * (MAX_SECS as usize + 1);
+    let mut samples = Vec::new();
+    while let Some(packet) = reader
+        .read_dec_packet_itl()
+        .context("The Ogg Vorbis file is damaged")?
+    {
+        samples.extend(packet.into_iter().map(|s| f32::from(s) / 32768.0));
+        if samples.len() > limit {
+            break;
+        }
+    }
+    Ok(Pcm {
+        rate,
+        channels,
+        samples,
+    })
+}
+
+/// `samples` at `from` Hz, as they would be at `to` Hz, each new sample a
+/// straight line between the two old ones around it.
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || from == 0 || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * u64::from(to) / u64::from(from)) as usize;
+    let step = f64::from(from) / f64::from(to);
+    (0..len)
+        .map(|i| {
+            let at = i as f64 * step;
+            let before = at as usize;
+            let a = samples[before];
+            let b = samples.get(before + 1).copied().unwrap_or(a);
+            a + (b - a) * (at - before as f64) as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A WAV file of 16-bit samples.
+    fn wav(rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut file = voice_clip::wav_header(rate, data.len() as u32).to_vec();
+        // The header is mono; say how many channels there are.
+        file[22..24].copy_from_slice(&channels.to_le_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn test_wav_is_mixed_down_and_resampled() {
+        // Stereo at 24 kHz: left and right average to a quarter.
+        let frames = 2400;
+        let samples: Vec<i16> = (0..frames).flat_map(|_| [16384, 0]).collect();
+        let sound = decode(&wav(24_000, 2, &samples)).unwrap();
+        assert_eq!(sound.duration(), Duration::from_millis(100));
+        assert!((sound.sample(0.05) - 0.25).abs() < 0.001);
+        assert_eq!(sound.sample(1.0), 0.0);
+    }
+
+    #[test]
+    fn test_loud_files_are_brought_down() {
+        let sound = decode(&wav(RATE, 1, &[i16::MAX, i16::MIN, 0])).unwrap();
+        assert!((sound.sample(0.0) - MAX_PEAK).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_files_that_cant_be_sounds() {
+        assert!(decode(b"ID3 not a wav").is_err());
+        assert!(decode(&wav(RATE, 1, &[])).is_err());
+        let too_long = vec![0; (8_000 * (MAX_SECS + 1)) as usize];
+        let error = decode(&wav(8_000, 1, &too_long)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Notification sounds can be up to 5 seconds"
+        );
+        let mut twelve_bit = wav(RATE, 1, &[0; 4]);
+        twelve_bit[34] = 12;
+        assert!(decode(&twelve_bit).is_err());
+        assert!(decode(b"OggS but not really").is_err());
+    }
+
+    #[test]
+    fn test_resample() {
+        assert_eq!(resample(&[0.0, 1.0], 1, 2), [0.0, 0.5, 1.0, 1.0]);
+        assert_eq!(resample(&[0.0, 0.5, 1.0, 0.5], 2, 1), [0.0, 1.0]);
+        assert_eq!(resample(&[0.3], RATE, RATE), [0.3]);
+    }
+
+    #[test]
+    fn test_chime_ends_after_its_notes() {
+        let chime = |t| Tone::Chime.wave(t);
+        assert!(chime(CHIME_NOTE_SECS / 3.0) != 0.0);
+        assert!(chime(CHIME_NOTE_SECS * 1.5).abs() <= CHIME_VOLUME);
+        assert_eq!(chime(CHIME_NOTE_SECS * CHIME_HZ.len() as f32), 0.0);
+    }
+
+    #[test]
+    fn test_tones_are_short_and_quiet() {
+        for tone in Tone::ALL {
+            let sound = tone.sound();
+            assert!(sound.duration() <= Duration::from_secs(1));
+            assert!(sound.samples.iter().all(|s| s.abs() <= MAX_PEAK));
+            assert!(sound.samples.iter().any(|&s| s != 0.0));
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! What there is to know about one event beyond what the timeline shows:
+//! when exactly it was sent, when it was last edited and how it was
+//! encrypted. Read from the event as the server gives it, so it holds up
+//! when a screenshot is questioned. The edit time comes from the server's
+//! aggregation of the latest `m.replace` in `unsigned`.
+
+use matrix_sdk::deserialized_responses::{EncryptionInfo, TimelineEvent, VerificationState};
+use serde::Deserialize;
+
+/// The megolm algorithm, as named in `m.room.encrypted` events.
+const MEGOLM: &str = "m.megolm.v1.aes-sha2";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventDetails {
+    pub event_id: String,
+    pub sender: String,
+    /// Origin server timestamp in milliseconds since the Unix epoch.
+    pub sent_at: u64,
+    /// When the latest edit was sent, if the message was edited and the
+    /// server said so.
+    pub edited_at: Option<u64>,
+    /// How it was encrypted; `None` if it was sent in the clear.
+    pub encryption: Option<EncryptionDetails>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptionDetails {
+    pub algorithm: String,
+    /// The megolm session that encrypted it, when the event still says.
+    pub session_id: Option<String>,
+    /// The device it was sent from.
+    pub sender_device: Option<String>,
+    /// Whether that device is verified, i.e. cross-signed by the sender;
+    /// `None` when it couldn't be told.
+    pub verified: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawEvent {
+    event_id: String,
+    sender: String,
+    origin_server_ts: u64,
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    content: EncryptedContent,
+    #[serde(default)]
+    unsigned: Unsigned,
+}
+
+/// The cleartext part of an `m.room.encrypted` event.
+#[derive(Debug, Default, Deserialize)]
+struct EncryptedContent {
+    algorithm: Option<String>,
+    session_id: Option<String>,
+    device_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Unsigned {
+    #[serde(rename = "m.relations", default)]
+    relations: Relations,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Relations {
+    #[serde(rename = "m.replace")]
+    replace: Option<LatestEdit>,
+}
+
+/// The latest edit, in full or as the summary newer servers send.
+#[derive(Debug, Deserialize)]
+struct LatestEdit {
+    origin_server_ts: Option<u64>,
+}
+
+/// The details of `event` as JSON, with `encryption_info` when the SDK
+/// decrypted it. `None` if it lacks what every event has.
+pub(crate) fn from_json(
+    event: serde_json::Value,
+    encryption_info: Option<&EncryptionInfo>,
+) -> Option<EventDetails> {
+    let raw: RawEvent = serde_json::from_value(event).ok()?;
+    let encryption = match encryption_info {
+        Some(info) => Some(EncryptionDetails {
+            algorithm: MEGOLM.to_string(),
+            session_id: raw.content.session_id,
+            sender_device: info.sender_device.as_ref().map(|d| d.to_string()),
+            verified: Some(info.verification_state == VerificationState::Verified),
+        }),
+        None if raw.event_type == "m.room.encrypted" => Some(EncryptionDetails {
+            algorithm: raw.content.algorithm.unwrap_or_else(|| MEGOLM.to_string()),
+            session_id: raw.content.session_id,
+            sender_device: raw.content.device_id,
+            verified: None,
+        }),
+        None => None,
+    };
+    Some(EventDetails {
+        event_id: raw.event_id,
+        sender: raw.sender,
+        sent_at: raw.origin_server_ts,
+        edited_at: raw
+            .unsigned
+            .relations
+            .replace
+            .and_then(|edit| edit.origin_server_ts),
+        encryption,
+    })
+}
+
+/// The details of `event`, as loaded from the store or the server.
+pub(crate) fn event_details(event: &TimelineEvent) -> Option<EventDetails> {
+    let json = event.event.deserialize_as::<serde_json::Value>().ok()?;
+    from_json(json, event.encryption_info.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_plain_event() {
+        let details = from_json(
+            json!({
+                "type": "m.room.message",
+                "event_id": "$a",
+                "sender": "@alice:example.org",
+                "origin_server_ts": 1_710_504_000_000u64,
+                "content": { "msgtype": "m.text", "body": "hi" },
+            }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            details,
+            EventDetails {
+                event_id: "$a".to_string(),
+                sender: "@alice:example.org".to_string(),
+                sent_at: 1_710_504_000_000,
+                edited_at: None,
+                encryption: None,
+            }
+        );
+        assert_eq!(from_json(json!({ "event_id": "$a" }), None), None);
+    }
+
+    #[test]
+    fn test_edit_time_comes_from_the_aggregation() {
+        // Newer servers send a summary, older ones the whole edit.
+        for edit in [
+            json!({ "event_id": "$edit", "origin_server_ts": 1_710_504_060_000u64, "sender": "@alice:example.org" }),
+            json!({ "type": "m.room.message", "origin_server_ts": 1_710_504_060_000u64, "content": {} }),
+        ] {
+            let details = from_json(
+                json!({
+                    "type": "m.room.message",
+                    "event_id": "$a",
+                    "sender": "@alice:example.org",
+                    "origin_server_ts": 1_710_504_000_000u64,
+                    "content": { "msgtype": "m.text", "body": "hi" },
+                    "unsigned": { "m.relations": { "m.replace": edit } },
+                }),
+                None,
+            )
+            .unwrap();
+            assert_eq!(details.edited_at, Some(1_710_504_060_000));
+        }
+    }
+
+    #[test]
+    fn test_undecrypted_event_names_its_session() {
+        let details = from_json(
+            json!({
+                "type": "m.room.encrypted",
+                "event_id": "$a",
+                "sender": "@alice:example.org",
+                "origin_server_ts": 1_710_504_000_000u64,
+                "content": {
+                    "algorithm": "m.megolm.v1.aes-sha2",
+                    "ciphertext": "AwgAEn...",
+                    "device_id": "ALICEPHONE",
+                    "sender_key": "abc",
+                    "session_id": "SESSION",
+                },
+            }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            details.encryption,
+            Some(EncryptionDetails {
+                algorithm: MEGOLM.to_string(),
+                session_id: Some("SESSION".to_string()),
+                sender_device: Some("ALICEPHONE".to_string()),
+                verified: None,
+            })
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Health checks run on demand, from the diagnostics panel or `doctor`:
+//! each one is ok, a warning or a failure, with a line saying why. The
+//! report can be copied for a bug report, so anything that looks like an
+//! access token is taken out of it first.
+
+use crate::config::AudioConfig;
+use crate::connection::ConnectionState;
+use crate::housekeeping::Counters;
+use serde::Serialize;
+use std::fmt::{self, Write};
+use std::time::Duration;
+
+/// Round trips slower than this are worth a warning.
+const SLOW_PING: Duration = Duration::from_secs(1);
+/// A sync loop silent for longer than this has likely stalled; long polls
+/// return every 30 seconds even when nothing happens.
+const STALE_SYNC_MS: u64 = 2 * 60 * 1000;
+/// Free space below which the cache warns, then fails.
+const LOW_DISK: u64 = 1024 * 1024 * 1024;
+const FULL_DISK: u64 = 100 * 1024 * 1024;
+
+/// Ordered so that the worst of several is their maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    pub fn new(name: &str, status: Status, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            message: redact(&message.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// The worst of the checks; ok when there are none.
+    pub fn status(&self) -> Status {
+        self.checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(Status::Ok)
+    }
+
+    /// One line per check, to paste into a bug report.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for check in &self.checks {
+            let status = format!("[{}]", check.status.label());
+            writeln!(text, "{:<6} {}: {}", status, check.name, check.message).unwrap();
+        }
+        redact(&text)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_text())
+    }
+}
+
+/// `text` with access tokens blanked: Synapse's `syt_…` tokens, and
+/// whatever follows `access_token=` or `Bearer `.
+pub fn redact(text: &str) -> String {
+    const MARKERS: [&str; 3] = ["syt_", "access_token=", "Bearer "];
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "-._~+/=".contains(c);
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((at, marker)) = MARKERS
+        .iter()
+        .filter_map(|marker| rest.find(marker).map(|at| (at, *marker)))
+        .min()
+    {
+        let start = at + marker.len();
+        let end = rest[start..]
+            .find(|c: char| !is_token(c))
+            .map_or(rest.len(), |len| start + len);
+        out.push_str(&rest[..start]);
+        if end > start {
+            out.push_str("<redacted>");
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The homeserver as a few pings found it: reachable and how quickly.
+pub fn homeserver(homeserver: &str, pings: &[Result<Duration, String>]) -> Check {
+    const NAME: &str = "Homeserver";
+    let mut times: Vec<Duration> = pings
+        .iter()
+        .filter_map(|p| p.as_ref().ok())
+        .copied()
+        .collect();
+    times.sort();
+    let Some(median) = times.get(times.len() / 2) else {
+        let error = pings.iter().find_map(|p| p.as_ref().err());
+        let message = format!(
+            "{} can't be reached: {}",
+            homeserver,
+            error.map_or("not tried", String::as_str)
+        );
+        return Check::new(NAME, Status::Fail, message);
+    };
+    let lost = pings.len() - times.len();
+    let message = format!("{} ms to {}", median.as_millis(), homeserver);
+    if lost > 0 {
+        let message = format!("{}, {} of {} pings lost", message, lost, pings.len());
+        Check::new(NAME, Status::Warn, message)
+    } else if *median > SLOW_PING {
+        Check::new(NAME, Status::Warn, format!("{}, which is slow", message))
+    } else {
+        Check::new(NAME, Status::Ok, message)
+    }
+}
+
+/// The sync loop: whether it runs, reaches the server, and last finished
+/// `age_ms` ago, if ever.
+pub fn sync(running: bool, state: ConnectionState, age_ms: Option<u64>) -> Check {
+    const NAME: &str = "Sync";
+    if !running {
+        return Check::new(NAME, Status::Fail, "Not running");
+    }
+    if !state.is_connected() {
+        let message = format!("Reconnecting, {} failed in a row", state.attempt());
+        return Check::new(NAME, Status::Warn, message);
+    }
+    match age_ms {
+        None => Check::new(NAME, Status::Warn, "Running, but no sync has finished yet"),
+        Some(age) if age > STALE_SYNC_MS => {
+            let message = format!("Last synced {} s ago; it may have stalled", age / 1000);
+            Check::new(NAME, Status::Warn, message)
+        }
+        Some(age) => Check::new(
+            NAME,
+            Status::Ok,
+            format!("Last synced {} s ago", age / 1000),
+        ),
+    }
+}
+
+/// Audio devices present, and the ones `config` picks among them.
+pub fn audio(inputs: &[String], outputs: &[String], config: &AudioConfig) -> Check {
+    const NAME: &str = "Audio";
+    let missing = [
+        (&config.input_device, inputs, "Microphone"),
+        (&config.output_device, outputs, "Speaker"),
+    ]
+    .into_iter()
+    .find_map(|(chosen, present, kind)| {
+        let chosen = chosen.as_ref()?;
+        (!present.contains(chosen)).then(|| format!("{} \"{}\" is missing", kind, chosen))
+    });
+    let counts = format!("{} inputs, {} outputs", inputs.len(), outputs.len());
+    if inputs.is_empty() || outputs.is_empty() {
+        let message = format!("{}; voice needs at least one of each", counts);
+        Check::new(NAME, Status::Fail, message)
+    } else if let Some(missing) = missing {
+        let message = format!("{}; the default is used instead ({})", missing, counts);
+        Check::new(NAME, Status::Warn, message)
+    } else {
+        Check::new(NAME, Status::Ok, counts)
+    }
+}
+
+/// Free space where the cache lives.
+pub fn disk(available: u64) -> Check {
+    const NAME: &str = "Disk";
+    let message = format!("{} MiB free for the cache", available / (1024 * 1024));
+    let status = if available < FULL_DISK {
+        Status::Fail
+    } else if available < LOW_DISK {
+        Status::Warn
+    } else {
+        Status::Ok
+    };
+    Check::new(NAME, status, message)
+}
+
+/// Receipts, typing notices and presence: how many were asked for and how
+/// many went out, and whether the server rate-limited us.
+pub fn housekeeping(counters: &Counters) -> Check {
+    const NAME: &str = "Background";
+    let message = format!(
+        "{} of {} receipts, {} of {} typing notices and {} of {} presence updates sent",
+        counters.receipts_sent,
+        counters.receipts_asked,
+        counters.typing_sent,
+        counters.typing_asked,
+        counters.presence_sent,
+        counters.presence_asked,
+    );
+    match counters.rate_limited {
+        0 => Check::new(NAME, Status::Ok, message),
+        times => {
+            let message = format!("{}; rate limited {} times", message, times);
+            Check::new(NAME, Status::Warn, message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_redacted() {
+        assert_eq!(
+            redact("GET /sync?since=s1&access_token=syt_abc_123 failed"),
+            "GET /sync?since=s1&access_token=<redacted> failed"
+        );
+        assert_eq!(
+            redact("token syt_YWxp_xyz, expired"),
+            "token syt_<redacted>, expired"
+        );
+        assert_eq!(
+            redact("Authorization: Bearer abc.def"),
+            "Authorization: Bearer <redacted>"
+        );
+        assert_eq!(redact("nothing secret"), "nothing secret");
+
+        let report = Report {
+            checks: vec![
+                Check::new("Sign-in", Status::Fail, "Bearer abc rejected"),
+                Check::new("Disk", Status::Warn, "low"),
+            ],
+        };
+        assert_eq!(report.status(), Status::Fail);
+        assert_eq!(
+            report.to_text(),
+            "[fail] Sign-in: Bearer <redacted> rejected\n[warn] Disk: low\n"
+        );
+        assert_eq!(Report::default().status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_verdicts() {
+        let ms = Duration::from_millis;
+        let ok = homeserver("hs", &[Ok(ms(40)), Ok(ms(60)), Ok(ms(50))]);
+        assert_eq!(
+            (ok.status, ok.message.as_str()),
+            (Status::Ok, "50 ms to hs")
+        );
+        let lossy = homeserver("hs", &[Ok(ms(40)), Err("timed out".into())]);
+        assert_eq!(lossy.status, Status::Warn);
+        assert_eq!(homeserver("hs", &[Ok(ms(1500))]).status, Status::Warn);
+        let down = homeserver("hs", &[Err("refused".into())]);
+        assert_eq!(
+            (down.status, down.message.as_str()),
+            (Status::Fail, "hs can't be reached: refused")
+        );
+
+        let connected = ConnectionState::Connected;
+        assert_eq!(sync(false, connected, Some(0)).status, Status::Fail);
+        assert_eq!(sync(true, connected, Some(10_000)).status, Status::Ok);
+        assert_eq!(
+            sync(true, connected, Some(STALE_SYNC_MS + 1)).status,
+            Status::Warn
+        );
+        assert_eq!(sync(true, connected, None).status, Status::Warn);
+        assert_eq!(sync(true, connected.failed(), Some(0)).status, Status::Warn);
+
+        let devices = vec!["USB Mic".to_string()];
+        let mut config = AudioConfig::default();
+        assert_eq!(audio(&devices, &devices, &config).status, Status::Ok);
+        assert_eq!(audio(&[], &devices, &config).status, Status::Fail);
+        config.output_device = Some("Headset".to_string());
+        let missing = audio(&devices, &devices, &config);
+        assert_eq!(missing.status, Status::Warn);
+        assert!(missing
+            .message
+            .starts_with("Speaker \"Headset\" is missing"));
+
+        assert_eq!(disk(50 * 1024 * 1024).status, Status::Fail);
+        assert_eq!(disk(500 * 1024 * 1024).status, Status::Warn);
+        assert_eq!(disk(5 * LOW_DISK).status, Status::Ok);
+
+        let mut counters = Counters {
+            receipts_asked: 12,
+            receipts_sent: 3,
+            typing_asked: 40,
+            typing_sent: 2,
+            presence_asked: 1,
+            presence_sent: 1,
+            rate_limited: 0,
+        };
+        let quiet = housekeeping(&counters);
+        assert_eq!(
+            (quiet.status, quiet.message.as_str()),
+            (
+                Status::Ok,
+                "3 of 12 receipts, 2 of 40 typing notices and 1 of 1 presence updates sent"
+            )
+        );
+        counters.rate_limited = 2;
+        assert_eq!(housekeeping(&counters).status, Status::Warn);
+    }
+}
@@ -0,0 +1,250 @@
+//! Finding out whether a homeserver is there before signing in: why it
+//! could not be reached, and when it can, how quickly it answers and what
+//! it runs.
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde_json::Value;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Round trips timed for the latency shown.
+pub const PINGS: usize = 3;
+/// How long one request to the server may take.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a homeserver could not be reached, for errors the login screen
+/// names. Anything else counts as unreachable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscoveryError {
+    /// The name does not resolve.
+    Dns,
+    /// The connection's TLS failed, e.g. on a bad certificate.
+    Tls,
+    Timeout,
+    /// Something answered, but not as a Matrix homeserver.
+    NotMatrix,
+    Unreachable(String),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dns => f.write_str("No server found at this address"),
+            Self::Tls => f.write_str("The server's secure connection failed"),
+            Self::Timeout => f.write_str("The server took too long to answer"),
+            Self::NotMatrix => f.write_str("This is not a Matrix server"),
+            Self::Unreachable(reason) => write!(f, "Could not reach the server: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+impl DiscoveryError {
+    /// Sort a failure to look up or reach a server by its cause.
+    pub fn classify(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<DiscoveryError>() {
+            return e.clone();
+        }
+        if e.chain()
+            .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+            .any(reqwest::Error::is_timeout)
+        {
+            return Self::Timeout;
+        }
+        let text = e
+            .chain()
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>()
+            .join(": ");
+        Self::from_text(&text)
+    }
+
+    /// Judge from the text of an error and its causes.
+    fn from_text(text: &str) -> Self {
+        let lower = text.to_lowercase();
+        let any = |patterns: &[&str]| patterns.iter().any(|p| lower.contains(p));
+        if any(&[
+            "dns error",
+            "failed to lookup address",
+            "name or service not known",
+            "no such host",
+        ]) {
+            Self::Dns
+        } else if any(&["certificate", "tls", "handshake"]) {
+            Self::Tls
+        } else if any(&["timed out", "timeout"]) {
+            Self::Timeout
+        } else if any(&[
+            "expected value",
+            "error decoding",
+            "m_unrecognized",
+            "auto-discovery",
+            "well-known",
+            "does not look like a matrix",
+        ]) {
+            Self::NotMatrix
+        } else {
+            let reason = text.rsplit(": ").next().unwrap_or(text).trim();
+            Self::Unreachable(reason.to_string())
+        }
+    }
+}
+
+/// How a reachable homeserver is doing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerStatus {
+    /// The client API address the server name led to.
+    pub homeserver: String,
+    /// The middle of the round trips timed.
+    pub latency: Duration,
+    /// The newest Matrix version it speaks, e.g. "v1.9".
+    pub version: Option<String>,
+    /// The software it runs, when it says, e.g. "Synapse 1.98.0".
+    pub server: Option<String>,
+}
+
+/// The middle of `samples`.
+pub fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples.get(samples.len() / 2).copied().unwrap_or_default()
+}
+
+/// The newest of the versions a `/versions` answer lists. Legacy "r0"
+/// versions rank below every "v1".
+pub fn latest_version(versions: &Value) -> Option<String> {
+    let key = |version: &str| -> Option<(u32, u32, u32)> {
+        if let Some(rest) = version.strip_prefix('v') {
+            let (major, minor) = rest.split_once('.')?;
+            Some((major.parse::<u32>().ok()? + 1, minor.parse().ok()?, 0))
+        } else {
+            let mut parts = version.strip_prefix("r0.")?.split('.');
+            Some((0, parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+        }
+    };
+    versions
+        .get("versions")?
+        .as_array()?
+        .iter()
+        .filter_map(Value::as_str)
+        .filter_map(|version| Some((key(version)?, version)))
+        .max()
+        .map(|(_, version)| version.to_string())
+}
+
+/// The name and version a federation `/version` answer gives.
+pub fn software(answer: &Value) -> Option<String> {
+    let server = answer.get("server")?;
+    let name = server.get("name")?.as_str()?;
+    Some(match server.get("version").and_then(Value::as_str) {
+        Some(version) => format!("{} {}", name, version),
+        None => name.to_string(),
+    })
+}
+
+fn endpoint(homeserver: &Url, path: &[&str]) -> Result<Url> {
+    let mut url = homeserver.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid homeserver URL"))?
+        .pop_if_empty()
+        .extend(path);
+    Ok(url)
+}
+
+/// Time [`PINGS`] requests for the versions `homeserver` speaks, and ask
+/// what software it runs.
+pub async fn server_status(homeserver: Url) -> Result<ServerStatus> {
+    let http = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let versions_url = endpoint(&homeserver, &["_matrix", "client", "versions"])?;
+    let mut samples = Vec::with_capacity(PINGS);
+    let mut versions = Value::Null;
+    for _ in 0..PINGS {
+        let started = Instant::now();
+        let response = http.get(versions_url.clone()).send().await?;
+        if !response.status().is_success() {
+            return Err(DiscoveryError::NotMatrix.into());
+        }
+        versions = response
+            .json()
+            .await
+            .map_err(|_| DiscoveryError::NotMatrix)?;
+        samples.push(started.elapsed());
+    }
+    if versions.get("versions").is_none() {
+        return Err(DiscoveryError::NotMatrix.into());
+    }
+
+    // Only servers that federate from this address say; that's fine.
+    let version_url = endpoint(&homeserver, &["_matrix", "federation", "v1", "version"])?;
+    let server = match http.get(version_url).send().await {
+        Ok(response) if response.status().is_success() => response
+            .json::<Value>()
+            .await
+            .ok()
+            .as_ref()
+            .and_then(software),
+        _ => None,
+    };
+    Ok(ServerStatus {
+        homeserver: homeserver.to_string(),
+        latency: median(samples),
+        version: latest_version(&versions),
+        server,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_classify_failures() {
+        let classify = |text: &str| DiscoveryError::classify(&anyhow!(text.to_string()));
+        assert_eq!(
+            classify("error sending request: dns error: failed to lookup address information"),
+            DiscoveryError::Dns
+        );
+        assert_eq!(
+            classify("invalid peer certificate: UnknownIssuer"),
+            DiscoveryError::Tls
+        );
+        assert_eq!(classify("operation timed out"), DiscoveryError::Timeout);
+        assert_eq!(
+            classify("error decoding response body: expected value at line 1"),
+            DiscoveryError::NotMatrix
+        );
+        assert_eq!(
+            classify("error sending request: Connection refused (os error 111)"),
+            DiscoveryError::Unreachable("Connection refused (os error 111)".to_string())
+        );
+        let typed = anyhow::Error::from(DiscoveryError::NotMatrix).context("Checking the server");
+        assert_eq!(DiscoveryError::classify(&typed), DiscoveryError::NotMatrix);
+    }
+
+    #[test]
+    fn test_median() {
+        let ms = Duration::from_millis;
+        assert_eq!(median(vec![ms(120), ms(80), ms(95)]), ms(95));
+        assert_eq!(median(vec![ms(40)]), ms(40));
+        assert_eq!(median(Vec::new()), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_latest_version_and_software() {
+        let versions = json!({ "versions": ["r0.6.1", "v1.1", "v1.10", "v1.9", "nonsense"] });
+        assert_eq!(latest_version(&versions).as_deref(), Some("v1.10"));
+        let legacy = json!({ "versions": ["r0.5.0", "r0.6.1"] });
+        assert_eq!(latest_version(&legacy).as_deref(), Some("r0.6.1"));
+        assert_eq!(latest_version(&json!({})), None);
+
+        let answer = json!({ "server": { "name": "Synapse", "version": "1.98.0" } });
+        assert_eq!(software(&answer).as_deref(), Some("Synapse 1.98.0"));
+        let unversioned = json!({ "server": { "name": "conduit" } });
+        assert_eq!(software(&unversioned).as_deref(), Some("conduit"));
+        assert_eq!(software(&json!({})), None);
+    }
+}
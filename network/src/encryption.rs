@@ -0,0 +1,553 @@
+//! Whether a room is encrypted, and whether anyone in it has a device they
+//! haven't verified, which could read what is sent there unnoticed. A
+//! device is verified as the SDK's crypto store holds it: trusted by hand,
+//! or signed by its owner's cross-signing keys, signatures checked, with
+//! the owner verified by us. The store keeps up with everyone's devices as
+//! sync reports them changed, so checks make no requests of their own.
+//!
+//! How far each message can be trusted comes from the SDK's crypto store,
+//! which tells on decrypting it which device sent it and whether that
+//! device and its owner are verified. On top of that, each verified user's
+//! master key is remembered, and their messages are flagged once it is no
+//! longer the one they had when we verified them.
+
+use crate::config::ConfigManager;
+use crate::events::ChatEvent;
+use anyhow::{bail, Result};
+use chat_core::{Message, MessageTrust, SenderTrust};
+use matrix_sdk::deserialized_responses::{EncryptionInfo, VerificationState};
+use matrix_sdk::ruma::events::room::member::MembershipState;
+use matrix_sdk::ruma::UserId;
+use matrix_sdk::{Client, Room, RoomMemberships};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoomSecurity {
+    pub encrypted: bool,
+    /// Members with a device they haven't verified, sorted. Always empty in
+    /// rooms that aren't encrypted.
+    pub unverified_users: Vec<String>,
+}
+
+impl RoomSecurity {
+    pub fn has_unverified_devices(&self) -> bool {
+        !self.unverified_users.is_empty()
+    }
+}
+
+/// A message was not sent, as the config says never to send to unverified
+/// devices and these members of the room have some.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnverifiedDevices {
+    pub users: Vec<String>,
+}
+
+impl fmt::Display for UnverifiedDevices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Not sent: {} {} devices that aren't verified",
+            self.users.join(", "),
+            if self.users.len() == 1 { "has" } else { "have" }
+        )
+    }
+}
+
+impl std::error::Error for UnverifiedDevices {}
+
+/// Those of `users` with a device the crypto store doesn't hold verified,
+/// sorted. The device we are using is left out.
+async fn unverified_users(client: &Client, users: &[&UserId]) -> Result<Vec<String>> {
+    let encryption = client.encryption();
+    let own_device = client.user_id().zip(client.device_id());
+    let mut unverified = Vec::new();
+    for &user_id in users {
+        let devices = encryption.get_user_devices(user_id).await?;
+        let any = devices.devices().any(|device| {
+            own_device != Some((device.user_id(), device.device_id())) && !device.is_verified()
+        });
+        if any {
+            unverified.push(user_id.to_string());
+        }
+    }
+    unverified.sort();
+    Ok(unverified)
+}
+
+/// Whether `room` is encrypted, and who in it, joined or invited, has
+/// unverified devices.
+pub(crate) async fn room_security(client: &Client, room: &Room) -> Result<RoomSecurity> {
+    if !room.is_encrypted().await? {
+        return Ok(RoomSecurity::default());
+    }
+    let members = room
+        .members(RoomMemberships::JOIN | RoomMemberships::INVITE)
+        .await?;
+    let users: Vec<&UserId> = members.iter().map(|m| m.user_id()).collect();
+    Ok(RoomSecurity {
+        encrypted: true,
+        unverified_users: unverified_users(client, &users).await?,
+    })
+}
+
+/// The encrypted rooms `user_id` is in with us, and the name they go by
+/// there, if they set one.
+pub(crate) async fn shared_rooms(client: &Client, user_id: &str) -> (Vec<String>, Option<String>) {
+    let Ok(user_id) = <&UserId>::try_from(user_id) else {
+        return (Vec::new(), None);
+    };
+    let mut rooms = Vec::new();
+    let mut name = None;
+    for room in client.joined_rooms() {
+        if !room.is_encrypted().await.unwrap_or(false) {
+            continue;
+        }
+        let member = room.get_member_no_sync(user_id).await.ok().flatten();
+        let Some(member) = member.filter(|m| *m.membership() == MembershipState::Join) else {
+            continue;
+        };
+        rooms.push(room.room_id().to_string());
+        name = name.or_else(|| member.display_name().map(str::to_string));
+    }
+    (rooms, name)
+}
+
+/// How far a message decrypted with `info` can be trusted to be from its
+/// sender, given whether their keys changed since we verified them.
+pub(crate) fn message_trust(info: &EncryptionInfo, keys_changed: bool) -> MessageTrust {
+    let trust = if keys_changed {
+        SenderTrust::KeysChanged
+    } else if info.verification_state == VerificationState::Verified {
+        SenderTrust::Verified
+    } else {
+        SenderTrust::Unverified
+    };
+    MessageTrust {
+        device_id: info.sender_device.as_ref().map(|d| d.to_string()),
+        trust,
+    }
+}
+
+/// Flag the encrypted ones of `messages` whose sender is in `changed`, the
+/// users whose keys changed since we verified them.
+pub(crate) fn mark_keys_changed(messages: &mut [Message], changed: &HashSet<String>) {
+    for message in messages {
+        if !changed.contains(&message.sender) {
+            continue;
+        }
+        if let Some(trust) = &mut message.trust {
+            trust.trust = SenderTrust::KeysChanged;
+        }
+    }
+}
+
+/// Whether a user's master key is no longer `recorded`, the one they had
+/// when we verified them. Users we never verified have nothing to change.
+fn keys_changed(recorded: Option<&str>, current: Option<&str>) -> bool {
+    recorded.is_some_and(|recorded| current != Some(recorded))
+}
+
+/// What the crypto store makes of a user.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UserTrust {
+    /// How far messages from each of their devices can be trusted, by
+    /// device id, leaving their keys changing aside.
+    pub devices: BTreeMap<String, SenderTrust>,
+    /// Their master key, once they set up cross-signing.
+    pub master_key: Option<String>,
+    /// Whether we verified them.
+    pub verified: bool,
+    /// Whether their master key is no longer the one recorded.
+    pub keys_changed: bool,
+}
+
+/// How far `user_id` and their devices can be trusted, as the crypto store
+/// knows them, given `recorded`, their master key when we verified them.
+/// A device is verified when it is and so are they.
+pub(crate) async fn user_trust(
+    client: &Client,
+    user_id: &UserId,
+    recorded: Option<&str>,
+) -> Result<UserTrust> {
+    let encryption = client.encryption();
+    let identity = encryption.get_user_identity(user_id).await?;
+    let master_key = identity
+        .as_ref()
+        .and_then(|identity| identity.master_key().get_first_key())
+        .map(|key| key.to_base64());
+    let verified = identity.is_some_and(|identity| identity.is_verified());
+    let devices = encryption
+        .get_user_devices(user_id)
+        .await?
+        .devices()
+        .map(|device| {
+            let trust = if verified && device.is_verified() {
+                SenderTrust::Verified
+            } else {
+                SenderTrust::Unverified
+            };
+            (device.device_id().to_string(), trust)
+        })
+        .collect();
+    Ok(UserTrust {
+        devices,
+        keys_changed: keys_changed(recorded, master_key.as_deref()),
+        master_key,
+        verified,
+    })
+}
+
+/// Work out `user_id`'s trust again and tell subscribers. Their master key
+/// is recorded once we have verified them, and `changed_keys` kept up with
+/// whether it changed since. When `quiet`, subscribers only hear of users
+/// whose keys changed.
+pub(crate) async fn recheck_user(
+    client: &Client,
+    tx: &broadcast::Sender<ChatEvent>,
+    changed_keys: &Mutex<HashSet<String>>,
+    user_id: &UserId,
+    quiet: bool,
+) {
+    let Some(account) = client.user_id().map(|user_id| user_id.to_string()) else {
+        return;
+    };
+    let mut config = ConfigManager::load();
+    let recorded = config
+        .account(&account)
+        .verified_keys
+        .remove(user_id.as_str());
+    let trust = match user_trust(client, user_id, recorded.as_deref()).await {
+        Ok(trust) => trust,
+        Err(e) => {
+            tracing::warn!("Failed to check {}'s keys: {:#}", user_id, e);
+            return;
+        }
+    };
+    if let (None, true, Some(key)) = (&recorded, trust.verified, &trust.master_key) {
+        config
+            .accounts
+            .entry(account)
+            .or_default()
+            .verified_keys
+            .insert(user_id.to_string(), key.clone());
+        if let Err(e) = ConfigManager::save(&config) {
+            tracing::warn!("Failed to save {}'s keys: {:#}", user_id, e);
+        }
+    }
+    {
+        let mut changed_keys = changed_keys.lock().unwrap();
+        if trust.keys_changed {
+            changed_keys.insert(user_id.to_string());
+        } else {
+            changed_keys.remove(user_id.as_str());
+        }
+    }
+    if quiet && !trust.keys_changed {
+        return;
+    }
+    let (rooms, display_name) = shared_rooms(client, user_id.as_str()).await;
+    let _ = tx.send(ChatEvent::SenderTrustChanged {
+        user_id: user_id.to_string(),
+        display_name,
+        devices: trust.devices,
+        keys_changed: trust.keys_changed,
+        rooms,
+    });
+}
+
+/// Keep subscribers up with how far everyone can be trusted as the crypto
+/// store learns of their devices and identities. First the users we
+/// verified are checked, for keys that changed while we were away. Runs
+/// until aborted.
+pub(crate) async fn watch_trust(
+    client: Client,
+    tx: broadcast::Sender<ChatEvent>,
+    changed_keys: Arc<Mutex<HashSet<String>>>,
+) {
+    use futures_util::{stream, StreamExt};
+    use matrix_sdk::ruma::OwnedUserId;
+
+    let encryption = client.encryption();
+    let (identities, devices) = match (
+        encryption.user_identities_stream().await,
+        encryption.devices_stream().await,
+    ) {
+        (Ok(identities), Ok(devices)) => (identities, devices),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!("Not watching anyone's keys: {}", e);
+            return;
+        }
+    };
+    let identities = identities.map(|updates| {
+        updates
+            .new
+            .into_keys()
+            .chain(updates.changed.into_keys())
+            .collect::<Vec<_>>()
+    });
+    let devices = devices.map(|updates| {
+        updates
+            .new
+            .into_keys()
+            .chain(updates.changed.into_keys())
+            .collect::<Vec<_>>()
+    });
+    let mut updates = std::pin::pin!(stream::select(identities, devices));
+
+    let verified: Vec<OwnedUserId> = client
+        .user_id()
+        .map(|account| {
+            ConfigManager::load()
+                .account(account.as_str())
+                .verified_keys
+        })
+        .unwrap_or_default()
+        .into_keys()
+        .filter_map(|user_id| OwnedUserId::try_from(user_id).ok())
+        .collect();
+    for user_id in verified {
+        recheck_user(&client, &tx, &changed_keys, &user_id, true).await;
+    }
+    while let Some(users) = updates.next().await {
+        for user_id in users {
+            recheck_user(&client, &tx, &changed_keys, &user_id, false).await;
+        }
+    }
+}
+
+/// Whether the device `client` is signed in on is verified.
+pub(crate) async fn own_device_verified(client: &Client) -> Result<bool> {
+    let (Some(user_id), Some(device_id)) = (client.user_id(), client.device_id()) else {
+        return Ok(false);
+    };
+    device_verified(client, user_id.as_str(), device_id.as_str()).await
+}
+
+/// Whether the crypto store holds `user_id`'s device `device_id` verified.
+/// Unknown devices are not.
+pub(crate) async fn device_verified(
+    client: &Client,
+    user_id: &str,
+    device_id: &str,
+) -> Result<bool> {
+    let user_id = <&UserId>::try_from(user_id)?;
+    let device = client
+        .encryption()
+        .get_device(user_id, device_id.into())
+        .await?;
+    Ok(device.is_some_and(|device| device.is_verified()))
+}
+
+/// Refuse to send into `room` if it is encrypted and we can't encrypt,
+/// e.g. while there is no crypto store, rather than send in the clear; or
+/// while it has unverified devices, if the config says never to.
+pub(crate) async fn check_send(client: &Client, room: &Room) -> Result<()> {
+    if !room.is_encrypted().await? {
+        return Ok(());
+    }
+    if client.encryption().ed25519_key().await.is_none() {
+        bail!("Not sent: this room is encrypted, and encryption isn't set up here");
+    }
+    if !ConfigManager::cached(|config| config.encryption.never_send_to_unverified) {
+        return Ok(());
+    }
+    let security = room_security(client, room).await?;
+    if security.has_unverified_devices() {
+        return Err(UnverifiedDevices {
+            users: security.unverified_users,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::deserialized_responses::{AlgorithmInfo, VerificationLevel};
+    use matrix_sdk::matrix_auth::{MatrixSession, MatrixSessionTokens};
+    use matrix_sdk::ruma::api::client::keys::get_keys::v3::Response as KeysResponse;
+    use matrix_sdk::ruma::api::IncomingResponse;
+    use matrix_sdk::ruma::{device_id, user_id, TransactionId};
+    use matrix_sdk::SessionMeta;
+    use matrix_sdk_test::{response_from_file, test_json};
+
+    /// The master key of `@web2:localhost:8482` in the SDK's fixtures.
+    const WEB2_MASTER_KEY: &str = "Ct4QR+aXrzW4iYIgH1B/56NkPEtSPoN+h2TGoQ0xxYI";
+
+    fn decrypted(verification_state: VerificationState) -> EncryptionInfo {
+        EncryptionInfo {
+            sender: user_id!("@bob:x").to_owned(),
+            sender_device: Some(device_id!("BOBPHONE").to_owned()),
+            algorithm_info: AlgorithmInfo::MegolmV1AesSha2 {
+                curve25519_key: "key".to_string(),
+                sender_claimed_keys: BTreeMap::new(),
+            },
+            verification_state,
+        }
+    }
+
+    #[test]
+    fn test_message_trust_is_what_decrypting_said() {
+        assert_eq!(
+            message_trust(&decrypted(VerificationState::Verified), false),
+            MessageTrust {
+                device_id: Some("BOBPHONE".to_string()),
+                trust: SenderTrust::Verified,
+            }
+        );
+        for level in [
+            VerificationLevel::UnverifiedIdentity,
+            VerificationLevel::UnsignedDevice,
+        ] {
+            let info = decrypted(VerificationState::Unverified(level));
+            assert_eq!(message_trust(&info, false).trust, SenderTrust::Unverified);
+        }
+        // New keys outweigh how verified the device was when it sent.
+        assert_eq!(
+            message_trust(&decrypted(VerificationState::Verified), true).trust,
+            SenderTrust::KeysChanged
+        );
+    }
+
+    #[test]
+    fn test_keys_change_only_once_verified() {
+        assert!(!keys_changed(None, Some("NEW")));
+        assert!(!keys_changed(Some("OLD"), Some("OLD")));
+        assert!(keys_changed(Some("OLD"), Some("NEW")));
+        // Cross-signing was reset and not set up again.
+        assert!(keys_changed(Some("OLD"), None));
+    }
+
+    #[test]
+    fn test_only_encrypted_messages_are_flagged() {
+        let message = |sender: &str, trust| Message {
+            id: "$a".to_string(),
+            sender: sender.to_string(),
+            trust,
+            ..Message::default()
+        };
+        let mut messages = [
+            message("@eve:x", Some(MessageTrust::default())),
+            message("@eve:x", None),
+            message("@bob:x", Some(MessageTrust::default())),
+        ];
+        mark_keys_changed(&mut messages, &HashSet::from(["@eve:x".to_string()]));
+        let trust: Vec<_> = messages
+            .iter()
+            .map(|m| m.trust.as_ref().map(|t| t.trust))
+            .collect();
+        assert_eq!(
+            trust,
+            [
+                Some(SenderTrust::KeysChanged),
+                None,
+                Some(SenderTrust::Unverified)
+            ]
+        );
+    }
+
+    /// A client signed in as `@me:localhost` whose crypto store has the keys
+    /// of `@web2:localhost:8482`, who has two devices and signed one.
+    async fn client_knowing_web2() -> Client {
+        let client = Client::builder()
+            .homeserver_url("http://localhost:1")
+            .build()
+            .await
+            .unwrap();
+        let session = MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@me:localhost").to_owned(),
+                device_id: device_id!("MYDEVICE").to_owned(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: "token".to_string(),
+                refresh_token: None,
+            },
+        };
+        client.matrix_auth().restore_session(session).await.unwrap();
+        let response = KeysResponse::try_from_http_response(response_from_file(
+            &test_json::KEYS_QUERY_TWO_DEVICES_ONE_SIGNED,
+        ))
+        .unwrap();
+        let machine = client.olm_machine_for_testing().await;
+        machine
+            .as_ref()
+            .unwrap()
+            .mark_request_as_sent(&TransactionId::new(), &response)
+            .await
+            .unwrap();
+        drop(machine);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_user_trust_comes_from_the_crypto_store() {
+        let client = client_knowing_web2().await;
+        let web2 = user_id!("@web2:localhost:8482");
+
+        // Signing a device doesn't make it verified until we verify them.
+        let trust = user_trust(&client, web2, None).await.unwrap();
+        assert_eq!(
+            trust,
+            UserTrust {
+                devices: BTreeMap::from([
+                    ("AVXFQWJUQA".to_string(), SenderTrust::Unverified),
+                    ("JERTCKWUWG".to_string(), SenderTrust::Unverified),
+                ]),
+                master_key: Some(WEB2_MASTER_KEY.to_string()),
+                verified: false,
+                keys_changed: false,
+            }
+        );
+        let trust = user_trust(&client, web2, Some(WEB2_MASTER_KEY))
+            .await
+            .unwrap();
+        assert!(!trust.keys_changed);
+        let trust = user_trust(&client, web2, Some("an older key"))
+            .await
+            .unwrap();
+        assert!(trust.keys_changed);
+
+        // Nothing is known of anyone else.
+        let stranger = user_trust(&client, user_id!("@eve:localhost"), None)
+            .await
+            .unwrap();
+        assert!(stranger.devices.is_empty() && stranger.master_key.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_devices_are_unverified_until_the_store_says_so() {
+        let client = client_knowing_web2().await;
+        let web2 = user_id!("@web2:localhost:8482");
+
+        // Signed or not, their devices count, as we haven't verified them.
+        let users = [web2, user_id!("@eve:localhost"), user_id!("@me:localhost")];
+        assert_eq!(
+            unverified_users(&client, &users).await.unwrap(),
+            vec![web2.to_string()]
+        );
+        for device_id in ["AVXFQWJUQA", "JERTCKWUWG", "UNKNOWN"] {
+            assert!(!device_verified(&client, web2.as_str(), device_id)
+                .await
+                .unwrap());
+        }
+    }
+
+    #[test]
+    fn test_error_names_the_users() {
+        let one = UnverifiedDevices {
+            users: vec!["@bob:x".to_string()],
+        };
+        assert_eq!(
+            one.to_string(),
+            "Not sent: @bob:x has devices that aren't verified"
+        );
+        let error: anyhow::Error = one.clone().into();
+        assert_eq!(error.downcast_ref::<UnverifiedDevices>(), Some(&one));
+    }
+}
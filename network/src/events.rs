@@ -0,0 +1,238 @@
+use crate::signaling::HangupReason;
+use chat_core::{Message, Reaction, Room, SenderTrust, Space, UnreadCounts, UserStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Updates pushed from the sync loop to subscribers of `MatrixClient::subscribe`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChatEvent {
+    /// We joined a room, or its metadata changed enough to re-send it.
+    RoomJoined(Room),
+    /// We joined a space, or its metadata changed.
+    SpaceJoined(Space),
+    /// We left (or were removed from) a room or space.
+    RoomLeft {
+        room_id: String,
+    },
+    RoomRenamed {
+        room_id: String,
+        name: String,
+    },
+    /// A room's published address changed, or it lost it.
+    RoomAliasChanged {
+        room_id: String,
+        alias: Option<String>,
+    },
+    /// A room's topic changed, or it was cleared.
+    RoomTopicChanged {
+        room_id: String,
+        topic: Option<String>,
+        /// Who changed it, and their display name there if known.
+        sender: String,
+        sender_name: Option<String>,
+    },
+    /// Encryption was turned on in a room, which can't be undone.
+    RoomEncrypted {
+        room_id: String,
+    },
+    /// The crypto store learned something new of `user_id`'s devices or
+    /// identity, or their keys changed since we verified them. Messages of
+    /// theirs already handed out should be trusted as far as `devices` says
+    /// for the device that sent them. `rooms` are the encrypted rooms we
+    /// share with them.
+    SenderTrustChanged {
+        user_id: String,
+        /// Their display name in a room we share, if known.
+        display_name: Option<String>,
+        /// How far messages from each of their devices can be trusted now,
+        /// by device id, leaving `keys_changed` aside.
+        devices: BTreeMap<String, SenderTrust>,
+        /// Whether their master key is no longer the one they had when we
+        /// verified them, which makes all their messages suspect.
+        keys_changed: bool,
+        rooms: Vec<String>,
+    },
+    /// Messages were pinned or unpinned in a room. `pinned` holds them all,
+    /// oldest pin first.
+    PinsChanged {
+        room_id: String,
+        pinned: Vec<String>,
+    },
+    /// A space's `m.space.child` set changed.
+    SpaceChildrenChanged {
+        space_id: String,
+        children: Vec<String>,
+    },
+    /// The server's unread counters for a room changed.
+    UnreadChanged {
+        room_id: String,
+        unread: UnreadCounts,
+    },
+    Message {
+        room_id: String,
+        message: Box<Message>,
+        /// Set on the remote echo of a message sent from this device.
+        transaction_id: Option<String>,
+    },
+    /// `sender` replaced the body of message `event_id`.
+    MessageEdited {
+        room_id: String,
+        event_id: String,
+        sender: String,
+        body: String,
+        /// The new body as HTML, when formatted.
+        formatted: Option<String>,
+    },
+    Reacted {
+        room_id: String,
+        message_id: String,
+        reaction: Reaction,
+    },
+    /// A message or reaction was deleted.
+    Redacted {
+        room_id: String,
+        event_id: String,
+    },
+    /// Someone joined or left a room, changed their name or avatar there, or
+    /// the power levels or slow mode changed.
+    MembersChanged {
+        room_id: String,
+    },
+    /// A room's welcome message was set, changed or removed.
+    WelcomeChanged {
+        room_id: String,
+    },
+    /// Sticker packs changed: the user's own or which rooms' they enabled
+    /// everywhere if `room_id` is `None`, else those in that room's state.
+    StickerPacksChanged {
+        room_id: Option<String>,
+    },
+    /// The friends list changed, on another device or through a handshake.
+    RosterChanged,
+    /// A user's presence changed.
+    PresenceChanged {
+        user_id: String,
+        status: UserStatus,
+        /// Their status message, e.g. "Playing Valorant".
+        message: Option<String>,
+    },
+    /// Someone joined, left or re-announced a room's voice channel.
+    VoiceMembership {
+        room_id: String,
+        member: VoiceMember,
+    },
+    /// We were invited to a room, e.g. as our knock was let in.
+    Invited {
+        room_id: String,
+    },
+    /// Someone asks to join a room we can let them into.
+    Knocked(KnockRequest),
+    /// A knock was let in, turned away or withdrawn.
+    KnockResolved {
+        room_id: String,
+        user_id: String,
+    },
+    /// Someone is ringing us. Never sent for our own calls.
+    IncomingCall(CallInvite),
+    /// `user_id` answered call `call_id`, possibly us on another device.
+    CallAnswered {
+        room_id: String,
+        call_id: String,
+        user_id: String,
+    },
+    CallHungUp {
+        room_id: String,
+        call_id: String,
+        user_id: String,
+        reason: HangupReason,
+    },
+}
+
+/// A participant of a room's voice channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoiceMember {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    /// Addresses to reach them at, best first. Empty if they left.
+    pub candidates: Vec<String>,
+}
+
+impl VoiceMember {
+    pub fn name(&self) -> &str {
+        self.display_name
+            .as_deref()
+            .unwrap_or_else(|| chat_core::localpart(&self.user_id))
+    }
+}
+
+/// Someone knocking on a room, with why they want in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnockRequest {
+    pub room_id: String,
+    pub room_name: String,
+    pub user_id: String,
+    pub display_name: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl KnockRequest {
+    pub fn name(&self) -> &str {
+        self.display_name
+            .as_deref()
+            .unwrap_or_else(|| chat_core::localpart(&self.user_id))
+    }
+}
+
+/// A call ringing us into `room_id`'s voice channel until `expires_ts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallInvite {
+    pub room_id: String,
+    /// The invite's event id, which answers and hangups refer to.
+    pub call_id: String,
+    pub caller: String,
+    pub caller_name: Option<String>,
+    pub expires_ts: u64,
+}
+
+impl CallInvite {
+    pub fn caller_name(&self) -> &str {
+        self.caller_name
+            .as_deref()
+            .unwrap_or_else(|| chat_core::localpart(&self.caller))
+    }
+}
+
+/// Who is typing in a room, pushed to subscribers of
+/// `MatrixClient::subscribe_typing`. Replaces the previous list for the room.
+#[derive(Debug, Clone, Default)]
+pub struct TypingEvent {
+    pub room_id: String,
+    pub users: Vec<TypingUser>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypingUser {
+    pub user_id: String,
+    pub display_name: Option<String>,
+}
+
+impl TypingUser {
+    pub fn name(&self) -> &str {
+        self.display_name
+            .as_deref()
+            .unwrap_or_else(|| chat_core::localpart(&self.user_id))
+    }
+}
+
+/// A message our push rules say to alert about, pushed to subscribers of
+/// `MatrixClient::subscribe_notifications`. Own messages are never sent.
+#[derive(Debug, Clone)]
+pub struct NotificationEvent {
+    pub room_id: String,
+    pub room_name: String,
+    pub sender_name: String,
+    pub body: String,
+    pub is_direct: bool,
+    /// A push rule asked for a highlight, e.g. our name is in the body.
+    pub is_mention: bool,
+}
@@ -0,0 +1,15 @@
+//! Rooms pinned to the top of the sidebar, kept in our own account data so
+//! the order follows the account to other devices.
+
+use serde::{Deserialize, Serialize};
+
+/// Account data event type holding the pinned rooms.
+pub const FAVORITES_EVENT_TYPE: &str = "com.gamechat.favorites";
+
+/// Content of the `com.gamechat.favorites` account data event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FavoritesContent {
+    /// Room ids, top first.
+    #[serde(default)]
+    pub rooms: Vec<String>,
+}
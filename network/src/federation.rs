@@ -0,0 +1,186 @@
+//! Failures that come from other servers in a room rather than our own:
+//! the room's server ACL shutting our homeserver out, remote servers too
+//! slow to answer, and none of them able to let us join. Servers answer
+//! these with generic codes, so they are told apart by the error text too.
+
+use matrix_sdk::deserialized_responses::RawSyncOrStrippedState;
+use matrix_sdk::ruma::api::client::error::{Error as ApiError, ErrorBody};
+use matrix_sdk::ruma::events::room::server_acl::RoomServerAclEventContent;
+use matrix_sdk::ruma::events::SyncStateEvent;
+use matrix_sdk::{Room, RoomMemberships};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Why a join or send failed elsewhere in the federation, for the user to
+/// be told. Other failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FederationError {
+    /// The room's server ACL denies our homeserver.
+    BlockedByAcl,
+    /// The servers in the room didn't answer in time, or at all.
+    Timeout,
+    /// No server in the room could sign us in, e.g. to a room restricted
+    /// to a space's members, as none has a member who may invite.
+    UnableToGrantJoin,
+}
+
+impl fmt::Display for FederationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BlockedByAcl => f.write_str("This room blocks your homeserver"),
+            Self::Timeout => {
+                f.write_str("The servers in this room didn't answer in time; try again later")
+            }
+            Self::UnableToGrantJoin => f.write_str(
+                "None of the servers in this room can let you in right now; try again later",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FederationError {}
+
+/// The body of a Matrix error response.
+#[derive(Debug, Default, Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    errcode: String,
+    #[serde(default)]
+    error: String,
+}
+
+/// Which federation failure a response with `status`, `errcode` and
+/// `message` is, if any.
+fn classify(status: u16, errcode: &str, message: &str) -> Option<FederationError> {
+    let message = message.to_lowercase();
+    match errcode {
+        "M_UNABLE_TO_GRANT_JOIN" => Some(FederationError::UnableToGrantJoin),
+        "M_FORBIDDEN" if message.contains("acl") || message.contains("banned from room") => {
+            Some(FederationError::BlockedByAcl)
+        }
+        _ if status == 504
+            || message.contains("timed out")
+            || message.contains("via any server") =>
+        {
+            Some(FederationError::Timeout)
+        }
+        _ => None,
+    }
+}
+
+/// Like `classify`, from a response body, which a proxy in between may
+/// have replaced with something that isn't JSON.
+fn from_body(status: u16, body: &[u8]) -> Option<FederationError> {
+    let response: ErrorResponse = serde_json::from_slice(body).unwrap_or_default();
+    classify(status, &response.errcode, &response.error)
+}
+
+impl FederationError {
+    /// The federation failure `error` reports, if any.
+    pub(crate) fn from_api(error: &ApiError) -> Option<Self> {
+        let status = error.status_code.as_u16();
+        match &error.body {
+            ErrorBody::Standard { kind, message } => classify(status, kind.as_ref(), message),
+            ErrorBody::Json(json) => from_body(status, json.to_string().as_bytes()),
+            ErrorBody::NotJson { bytes, .. } => from_body(status, bytes),
+        }
+    }
+}
+
+/// `e` as a `FederationError` if it is one, else as it is.
+pub(crate) fn federation_error(e: matrix_sdk::Error) -> anyhow::Error {
+    match e.as_client_api_error().and_then(FederationError::from_api) {
+        Some(error) => error.into(),
+        None => e.into(),
+    }
+}
+
+/// A room's server ACL, and what it makes of the servers in the room.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerAcl {
+    /// Server name globs, as set in the room.
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub allow_ip_literals: bool,
+    /// Servers of joined members the ACL lets in, sorted.
+    pub allowed_servers: Vec<String>,
+    /// Servers of joined members the ACL shuts out, sorted. Their members
+    /// neither see nor are seen by the rest of the room.
+    pub denied_servers: Vec<String>,
+}
+
+/// `room`'s server ACL, or `None` if it has none.
+pub(crate) async fn server_acl(room: &Room) -> matrix_sdk::Result<Option<ServerAcl>> {
+    let Some(RawSyncOrStrippedState::Sync(raw)) = room
+        .get_state_event_static::<RoomServerAclEventContent>()
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Ok(SyncStateEvent::Original(ev)) = raw.deserialize() else {
+        return Ok(None);
+    };
+    let servers: BTreeSet<_> = room
+        .members(RoomMemberships::JOIN)
+        .await?
+        .iter()
+        .map(|member| member.user_id().server_name().to_owned())
+        .collect();
+    let (allowed, denied): (Vec<_>, Vec<_>) = servers
+        .into_iter()
+        .partition(|server| ev.content.is_allowed(server));
+    Ok(Some(ServerAcl {
+        allow: ev.content.allow,
+        deny: ev.content.deny,
+        allow_ip_literals: ev.content.allow_ip_literals,
+        allowed_servers: allowed.iter().map(ToString::to_string).collect(),
+        denied_servers: denied.iter().map(ToString::to_string).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acl_refusals() {
+        // Synapse, passing on the resident server's refusal.
+        let synapse = br#"{"errcode":"M_FORBIDDEN","error":"Server is banned from room"}"#;
+        assert_eq!(from_body(403, synapse), Some(FederationError::BlockedByAcl));
+        // Conduit.
+        let conduit = br#"{"errcode":"M_FORBIDDEN","error":"Server was denied by room ACL"}"#;
+        assert_eq!(from_body(403, conduit), Some(FederationError::BlockedByAcl));
+        // Other refusals are not federation's doing.
+        let power = br#"{"errcode":"M_FORBIDDEN","error":"You don't have permission to post that to the room. user_level (0) < send_level (50)"}"#;
+        assert_eq!(from_body(403, power), None);
+        let banned = br#"{"errcode":"M_FORBIDDEN","error":"You are banned from this room"}"#;
+        assert_eq!(from_body(403, banned), None);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let make_join = br#"{"errcode":"M_UNKNOWN","error":"Failed to make_join via any server"}"#;
+        assert_eq!(from_body(502, make_join), Some(FederationError::Timeout));
+        let timed_out = br#"{"errcode":"M_UNKNOWN","error":"Request timed out"}"#;
+        assert_eq!(from_body(500, timed_out), Some(FederationError::Timeout));
+        // A reverse proxy giving up on a slow join.
+        let proxy = b"<html><body><h1>504 Gateway Time-out</h1></body></html>";
+        assert_eq!(from_body(504, proxy), Some(FederationError::Timeout));
+        let bad_gateway = b"<html><body><h1>502 Bad Gateway</h1></body></html>";
+        assert_eq!(from_body(502, bad_gateway), None);
+    }
+
+    #[test]
+    fn test_unable_to_grant_join() {
+        let body = br#"{"errcode":"M_UNABLE_TO_GRANT_JOIN","error":"No resident server could sign the join"}"#;
+        assert_eq!(
+            from_body(400, body),
+            Some(FederationError::UnableToGrantJoin)
+        );
+        assert_eq!(
+            FederationError::BlockedByAcl.to_string(),
+            "This room blocks your homeserver"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Forwarding a message to another room: its content is sent there again,
+//! out of the reply, thread or edit it was part of. Media already on the
+//! server is sent again as is when both rooms read it the same way; across
+//! an encryption boundary it is downloaded and uploaded anew, so that it
+//! ends up encrypted exactly when the destination is.
+
+use chat_core::mention::escape_html;
+use matrix_sdk::ruma::events::room::message::{
+    FormattedBody, MessageFormat, MessageType, Relation, RoomMessageEventContent,
+};
+use matrix_sdk::ruma::events::room::MediaSource;
+
+/// How a message gets to its destination.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    /// Send the content as it is: text, or media the destination can read
+    /// where it already is.
+    Resend,
+    /// Download the media from `source`, encoded as in `Attachment`, and
+    /// upload it again as `name`.
+    Reupload {
+        source: String,
+        name: String,
+        mimetype: String,
+    },
+}
+
+fn media(msgtype: &MessageType) -> Option<(&MediaSource, &str, Option<&str>)> {
+    match msgtype {
+        MessageType::Image(c) => Some((
+            &c.source,
+            c.body.as_str(),
+            c.info.as_deref().and_then(|i| i.mimetype.as_deref()),
+        )),
+        MessageType::File(c) => Some((
+            &c.source,
+            c.filename.as_deref().unwrap_or(&c.body),
+            c.info.as_deref().and_then(|i| i.mimetype.as_deref()),
+        )),
+        MessageType::Video(c) => Some((
+            &c.source,
+            c.body.as_str(),
+            c.info.as_deref().and_then(|i| i.mimetype.as_deref()),
+        )),
+        MessageType::Audio(c) => Some((
+            &c.source,
+            c.body.as_str(),
+            c.info.as_deref().and_then(|i| i.mimetype.as_deref()),
+        )),
+        _ => None,
+    }
+}
+
+/// How to send `msgtype` to a room that is, or isn't, `encrypted`. An
+/// encrypted file's key travels in the content, so another encrypted room
+/// can be sent it as is; a plain `mxc://` URL is only sent again where it
+/// stays plain.
+pub fn plan(msgtype: &MessageType, encrypted: bool) -> Plan {
+    let Some((source, name, mimetype)) = media(msgtype) else {
+        return Plan::Resend;
+    };
+    if matches!(source, MediaSource::Encrypted(_)) == encrypted {
+        return Plan::Resend;
+    }
+    Plan::Reupload {
+        source: crate::media::encode(source),
+        name: name.to_string(),
+        mimetype: mimetype
+            .map(str::to_string)
+            .unwrap_or_else(|| crate::media::mimetype(name).to_string()),
+    }
+}
+
+/// The HTML of a reply without the quote of its parent in front.
+fn strip_html_reply_fallback(html: &str) -> &str {
+    match html.rfind("</mx-reply>") {
+        Some(end) if html.starts_with("<mx-reply>") => &html[end + "</mx-reply>".len()..],
+        _ => html,
+    }
+}
+
+/// Text `body` and `formatted` without a reply's fallback, and with
+/// `annotation` on a line of its own above.
+fn forwarded_text(
+    body: &mut String,
+    formatted: &mut Option<FormattedBody>,
+    annotation: Option<&str>,
+) {
+    *body = chat_core::strip_reply_fallback(body).to_string();
+    if let Some(formatted) = formatted
+        .as_mut()
+        .filter(|f| f.format == MessageFormat::Html)
+    {
+        formatted.body = strip_html_reply_fallback(&formatted.body).to_string();
+    }
+    let Some(annotation) = annotation else {
+        return;
+    };
+    *body = format!("{}\n{}", annotation, body);
+    if let Some(formatted) = formatted {
+        let mut html = String::from("<em>");
+        escape_html(annotation, &mut html);
+        html.push_str("</em><br>");
+        html.push_str(&formatted.body);
+        formatted.body = html;
+    }
+}
+
+/// `content` as it is forwarded: the newest body when it is an edit, and
+/// with no reply, thread, edit or mentions, as those belong to the room it
+/// came from. `annotation`, e.g. "Forwarded from Alice", goes above text;
+/// media is sent without one.
+pub fn prepare(
+    content: RoomMessageEventContent,
+    annotation: Option<&str>,
+) -> RoomMessageEventContent {
+    let mut msgtype = match content.relates_to {
+        Some(Relation::Replacement(replacement)) => replacement.new_content.msgtype,
+        _ => content.msgtype,
+    };
+    match &mut msgtype {
+        MessageType::Text(c) => forwarded_text(&mut c.body, &mut c.formatted, annotation),
+        MessageType::Notice(c) => forwarded_text(&mut c.body, &mut c.formatted, annotation),
+        MessageType::Emote(c) => forwarded_text(&mut c.body, &mut c.formatted, annotation),
+        _ => {}
+    }
+    RoomMessageEventContent::new(msgtype)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::events::relation::InReplyTo;
+    use matrix_sdk::ruma::events::room::message::ImageMessageEventContent;
+    use matrix_sdk::ruma::events::Mentions;
+    use matrix_sdk::ruma::{owned_event_id, owned_user_id, OwnedMxcUri};
+    use serde_json::json;
+
+    fn plain_image() -> MessageType {
+        MessageType::Image(ImageMessageEventContent::plain(
+            "cat.png".to_string(),
+            OwnedMxcUri::from("mxc://example.org/cat"),
+        ))
+    }
+
+    fn encrypted_image() -> MessageType {
+        serde_json::from_value(json!({
+            "msgtype": "m.image",
+            "body": "cat.png",
+            "info": { "mimetype": "image/png" },
+            "file": {
+                "url": "mxc://example.org/secret",
+                "key": {
+                    "kty": "oct",
+                    "key_ops": ["encrypt", "decrypt"],
+                    "alg": "A256CTR",
+                    "k": "qcHVMSgYg-71CauWBezXI5qkaRb0LuIy-Wx5kIaHMIA",
+                    "ext": true
+                },
+                "iv": "X85+XgHN+HEAAAAAAAAAAA",
+                "hashes": { "sha256": "5qG4fFnbbVdlAW9ZuSTpRlmSTeRHPz3QxDEc4C2FJBU" },
+                "v": "v2"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_media_is_reuploaded_across_encryption() {
+        assert_eq!(plan(&plain_image(), false), Plan::Resend);
+        assert_eq!(plan(&encrypted_image(), true), Plan::Resend);
+
+        let Plan::Reupload { name, mimetype, .. } = plan(&plain_image(), true) else {
+            panic!("expected a reupload into the encrypted room");
+        };
+        assert_eq!((name.as_str(), mimetype.as_str()), ("cat.png", "image/png"));
+        let Plan::Reupload {
+            source, mimetype, ..
+        } = plan(&encrypted_image(), false)
+        else {
+            panic!("expected a reupload out of the encrypted room");
+        };
+        assert!(source.contains("mxc://example.org/secret"));
+        assert_eq!(mimetype, "image/png");
+
+        let text = RoomMessageEventContent::text_plain("gg").msgtype;
+        assert_eq!(plan(&text, true), Plan::Resend);
+    }
+
+    #[test]
+    fn test_prepare_leaves_the_original_room_behind() {
+        let mut reply = RoomMessageEventContent::text_html(
+            "> <@bob:example.org> ready?\n\nready",
+            "<mx-reply><blockquote>ready?</blockquote></mx-reply>ready",
+        );
+        reply.relates_to = Some(Relation::Reply {
+            in_reply_to: InReplyTo::new(owned_event_id!("$parent")),
+        });
+        reply.mentions = Some(Mentions::with_user_ids([owned_user_id!(
+            "@bob:example.org"
+        )]));
+
+        let forwarded = prepare(reply.clone(), None);
+        assert!(forwarded.relates_to.is_none());
+        assert!(forwarded.mentions.is_none());
+        let MessageType::Text(text) = &forwarded.msgtype else {
+            panic!("expected text");
+        };
+        assert_eq!(text.body, "ready");
+        assert_eq!(text.formatted.as_ref().unwrap().body, "ready");
+
+        let annotated = prepare(reply, Some("Forwarded from <Alice>"));
+        let MessageType::Text(text) = &annotated.msgtype else {
+            panic!("expected text");
+        };
+        assert_eq!(text.body, "Forwarded from <Alice>\nready");
+        assert_eq!(
+            text.formatted.as_ref().unwrap().body,
+            "<em>Forwarded from &lt;Alice&gt;</em><br>ready"
+        );
+
+        let image = prepare(
+            RoomMessageEventContent::new(plain_image()),
+            Some("Forwarded"),
+        );
+        assert_eq!(image.msgtype.body(), "cat.png");
+    }
+}
@@ -0,0 +1,61 @@
+//! Keys registered as system-wide hotkeys, so they work while a game has
+//! focus: push-to-talk, and the key showing or hiding the overlay. Platforms
+//! want hotkeys registered from the thread running the event loop, and some
+//! managers can't leave it, so this is per thread.
+
+use anyhow::{Context, Result};
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::GlobalHotKeyManager;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// What a hotkey is registered for; each has at most one key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Slot {
+    PushToTalk,
+    Overlay,
+}
+
+#[derive(Default)]
+struct Hotkeys {
+    manager: Option<GlobalHotKeyManager>,
+    registered: HashMap<Slot, HotKey>,
+}
+
+thread_local! {
+    static HOTKEYS: RefCell<Hotkeys> = RefCell::new(Hotkeys::default());
+}
+
+/// Register `keys` ("Ctrl+KeyV") for `slot` in place of the key registered
+/// for it before, or just drop that one. Returns the id the new key's
+/// events carry.
+pub(crate) fn register(slot: Slot, keys: Option<&str>) -> Result<Option<u32>> {
+    HOTKEYS.with(|h| {
+        let mut hotkeys = h.borrow_mut();
+        if hotkeys.manager.is_none() {
+            if keys.is_none() {
+                return Ok(None);
+            }
+            hotkeys.manager =
+                Some(GlobalHotKeyManager::new().context("Global hotkeys are unavailable")?);
+        }
+        let hotkeys = &mut *hotkeys;
+        let manager = hotkeys.manager.as_ref().unwrap();
+        if let Some(old) = hotkeys.registered.remove(&slot) {
+            if let Err(e) = manager.unregister(old) {
+                tracing::warn!("Failed to unregister hotkey: {}", e);
+            }
+        }
+        let Some(keys) = keys else {
+            return Ok(None);
+        };
+        let hotkey: HotKey = keys
+            .parse()
+            .with_context(|| format!("{} can't be a global hotkey", keys))?;
+        manager
+            .register(hotkey)
+            .with_context(|| format!("Failed to register {}", keys))?;
+        hotkeys.registered.insert(slot, hotkey);
+        Ok(Some(hotkey.id()))
+    })
+}
@@ -0,0 +1,415 @@
+//! Read receipts, typing notices and presence: housekeeping a busy account
+//! sends a lot of, coalesced so it doesn't run into the server's rate
+//! limits and hold up real messages. Receipts go at most once a second per
+//! room, for the furthest event asked for; typing notices only when we
+//! start or stop typing, renewed before the server forgets them; presence
+//! no more often than `PRESENCE_SPACING`, or what the server asks for when
+//! it rate-limits us. Messages never go through here.
+//!
+//! `Batcher` decides what is due; each request comes with a waiter, e.g. a
+//! reply channel, answered once what covers it was sent.
+
+use chat_core::UserStatus;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Receipts for a room go no more often than this.
+pub const RECEIPT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the server is told to show us typing.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(30);
+/// Still typing this long after saying so: say it again before it runs out.
+const TYPING_RENEW: Duration = Duration::from_secs(25);
+/// Presence goes no more often than this unless the server asks for longer.
+pub const PRESENCE_SPACING: Duration = Duration::from_secs(5);
+
+/// Something to send.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chore {
+    /// Move the read receipt and fully-read marker.
+    Receipt {
+        room_id: String,
+        event_id: String,
+    },
+    Typing {
+        room_id: String,
+        typing: bool,
+    },
+    Presence {
+        status: UserStatus,
+        message: Option<String>,
+    },
+}
+
+/// A chore due, with the waiters of every request it covers.
+pub struct Batch<W> {
+    pub chore: Chore,
+    pub waiters: Vec<W>,
+}
+
+/// How much was asked for and how much went out, for the diagnostics.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counters {
+    pub receipts_asked: u64,
+    pub receipts_sent: u64,
+    pub typing_asked: u64,
+    pub typing_sent: u64,
+    pub presence_asked: u64,
+    pub presence_sent: u64,
+    /// Times the server turned us away for sending too much.
+    pub rate_limited: u64,
+}
+
+struct Receipt<W> {
+    event_id: String,
+    ts: u64,
+    waiters: Vec<W>,
+}
+
+struct Presence<W> {
+    status: UserStatus,
+    message: Option<String>,
+    waiters: Vec<W>,
+}
+
+pub struct Batcher<W> {
+    receipts: BTreeMap<String, Receipt<W>>,
+    /// When each room's receipt last went, and the timestamp of its event.
+    receipts_sent: HashMap<String, (Instant, u64)>,
+    /// Typing states to send, by room.
+    typing: BTreeMap<String, (bool, Vec<W>)>,
+    /// Rooms we said we are typing in, and when.
+    announced: HashMap<String, Instant>,
+    presence: Option<Presence<W>>,
+    presence_sent: Option<(Instant, UserStatus, Option<String>)>,
+    presence_spacing: Duration,
+    /// Nothing goes before this, as the server asked.
+    held_until: Option<Instant>,
+    counters: Counters,
+}
+
+impl<W> Default for Batcher<W> {
+    fn default() -> Self {
+        Self {
+            receipts: BTreeMap::new(),
+            receipts_sent: HashMap::new(),
+            typing: BTreeMap::new(),
+            announced: HashMap::new(),
+            presence: None,
+            presence_sent: None,
+            presence_spacing: PRESENCE_SPACING,
+            held_until: None,
+            counters: Counters::default(),
+        }
+    }
+}
+
+impl<W> Batcher<W> {
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Move `room_id`'s receipt to `event_id`, sent at `ts`. Returns the
+    /// waiters answered at once: those for a receipt no further than one
+    /// already sent or waiting.
+    pub fn receipt(&mut self, room_id: &str, event_id: &str, ts: u64, waiter: W) -> Vec<W> {
+        self.counters.receipts_asked += 1;
+        let sent_ts = self.receipts_sent.get(room_id).map(|(_, ts)| *ts);
+        let pending_ts = self.receipts.get(room_id).map(|r| r.ts);
+        if sent_ts.is_some_and(|sent| ts <= sent) && pending_ts.is_none() {
+            return vec![waiter];
+        }
+        let receipt = self
+            .receipts
+            .entry(room_id.to_string())
+            .or_insert_with(|| Receipt {
+                event_id: event_id.to_string(),
+                ts,
+                waiters: Vec::new(),
+            });
+        if ts > receipt.ts {
+            receipt.event_id = event_id.to_string();
+            receipt.ts = ts;
+        }
+        receipt.waiters.push(waiter);
+        Vec::new()
+    }
+
+    /// Say whether we are typing in `room_id`. Returns the waiters answered
+    /// at once: those that change nothing the server knows.
+    pub fn typing(&mut self, room_id: &str, typing: bool, waiter: W, now: Instant) -> Vec<W> {
+        self.counters.typing_asked += 1;
+        let renew = self
+            .announced
+            .get(room_id)
+            .is_some_and(|since| now.saturating_duration_since(*since) >= TYPING_RENEW);
+        let told = self.announced.contains_key(room_id) && !renew;
+        if typing == told {
+            // Back to what the server knows: what was waiting is moot.
+            let mut answered = self
+                .typing
+                .remove(room_id)
+                .map(|(_, waiters)| waiters)
+                .unwrap_or_default();
+            answered.push(waiter);
+            return answered;
+        }
+        let pending = self
+            .typing
+            .entry(room_id.to_string())
+            .or_insert((typing, Vec::new()));
+        pending.0 = typing;
+        pending.1.push(waiter);
+        Vec::new()
+    }
+
+    /// Publish `status` with `message`. Returns the waiter if that is what
+    /// was last published and nothing else is waiting.
+    pub fn presence(&mut self, status: UserStatus, message: Option<String>, waiter: W) -> Vec<W> {
+        self.counters.presence_asked += 1;
+        let published = self
+            .presence_sent
+            .as_ref()
+            .is_some_and(|(_, s, m)| *s == status && *m == message);
+        if published && self.presence.is_none() {
+            return vec![waiter];
+        }
+        let mut waiters = self.presence.take().map(|p| p.waiters).unwrap_or_default();
+        waiters.push(waiter);
+        self.presence = Some(Presence {
+            status,
+            message,
+            waiters,
+        });
+        Vec::new()
+    }
+
+    /// The server turned us away; hold everything for `retry_after`, and
+    /// space presence out at least as much from now on.
+    pub fn rate_limited(&mut self, retry_after: Duration, now: Instant) {
+        self.counters.rate_limited += 1;
+        self.held_until = Some(now + retry_after);
+        self.presence_spacing = self.presence_spacing.max(retry_after);
+    }
+
+    /// Put back a batch that couldn't go, unless something newer took its
+    /// place meanwhile; then its waiters wait on that.
+    pub fn retry(&mut self, batch: Batch<W>, now: Instant) {
+        let Batch { chore, mut waiters } = batch;
+        match chore {
+            Chore::Receipt { room_id, event_id } => {
+                let sent = self.receipts_sent.remove(&room_id);
+                match self.receipts.get_mut(&room_id) {
+                    Some(newer) => newer.waiters.append(&mut waiters),
+                    None => {
+                        let ts = sent.map_or(0, |(_, ts)| ts);
+                        self.receipts.insert(
+                            room_id,
+                            Receipt {
+                                event_id,
+                                ts,
+                                waiters,
+                            },
+                        );
+                    }
+                }
+            }
+            Chore::Typing { room_id, typing } => {
+                if typing {
+                    self.announced.remove(&room_id);
+                } else {
+                    self.announced.insert(room_id.clone(), now);
+                }
+                let pending = self.typing.entry(room_id).or_insert((typing, Vec::new()));
+                pending.1.append(&mut waiters);
+            }
+            Chore::Presence { status, message } => {
+                self.presence_sent = None;
+                match &mut self.presence {
+                    Some(newer) => newer.waiters.append(&mut waiters),
+                    None => {
+                        self.presence = Some(Presence {
+                            status,
+                            message,
+                            waiters,
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    /// When the next chore is due; `None` when nothing is waiting.
+    pub fn next_due(&self, now: Instant) -> Option<Instant> {
+        let receipts = self.receipts.keys().map(|room_id| {
+            self.receipts_sent
+                .get(room_id)
+                .map_or(now, |(sent, _)| *sent + RECEIPT_INTERVAL)
+        });
+        let typing = self.typing.keys().map(|_| now);
+        let presence = self.presence.iter().map(|_| {
+            self.presence_sent
+                .as_ref()
+                .map_or(now, |(sent, _, _)| *sent + self.presence_spacing)
+        });
+        let due = receipts.chain(typing).chain(presence).min()?;
+        Some(self.held_until.map_or(due, |held| due.max(held)))
+    }
+
+    /// Take what is due at `now`, counting it as sent.
+    pub fn take_due(&mut self, now: Instant) -> Vec<Batch<W>> {
+        if self.held_until.is_some_and(|held| now < held) {
+            return Vec::new();
+        }
+        self.held_until = None;
+        let mut due = Vec::new();
+
+        let rooms: Vec<String> = self
+            .receipts
+            .keys()
+            .filter(|room_id| {
+                self.receipts_sent
+                    .get(*room_id)
+                    .is_none_or(|(sent, _)| now >= *sent + RECEIPT_INTERVAL)
+            })
+            .cloned()
+            .collect();
+        for room_id in rooms {
+            let Some(receipt) = self.receipts.remove(&room_id) else {
+                continue;
+            };
+            self.receipts_sent
+                .insert(room_id.clone(), (now, receipt.ts));
+            self.counters.receipts_sent += 1;
+            due.push(Batch {
+                chore: Chore::Receipt {
+                    room_id,
+                    event_id: receipt.event_id,
+                },
+                waiters: receipt.waiters,
+            });
+        }
+
+        for (room_id, (typing, waiters)) in std::mem::take(&mut self.typing) {
+            if typing {
+                self.announced.insert(room_id.clone(), now);
+            } else {
+                self.announced.remove(&room_id);
+            }
+            self.counters.typing_sent += 1;
+            due.push(Batch {
+                chore: Chore::Typing { room_id, typing },
+                waiters,
+            });
+        }
+
+        let presence_due = self
+            .presence_sent
+            .as_ref()
+            .is_none_or(|(sent, _, _)| now >= *sent + self.presence_spacing);
+        if let Some(presence) = self.presence.take_if(|_| presence_due) {
+            self.presence_sent = Some((now, presence.status, presence.message.clone()));
+            self.counters.presence_sent += 1;
+            due.push(Batch {
+                chore: Chore::Presence {
+                    status: presence.status,
+                    message: presence.message,
+                },
+                waiters: presence.waiters,
+            });
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chores(batches: &[Batch<u32>]) -> Vec<Chore> {
+        batches.iter().map(|b| b.chore.clone()).collect()
+    }
+
+    fn receipt(room_id: &str, event_id: &str) -> Chore {
+        Chore::Receipt {
+            room_id: room_id.to_string(),
+            event_id: event_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_receipts_go_once_a_second_for_the_furthest_event() {
+        let start = Instant::now();
+        let mut batcher = Batcher::default();
+        assert!(batcher.receipt("!a", "$1", 10, 1).is_empty());
+        assert!(batcher.receipt("!a", "$3", 30, 2).is_empty());
+        assert!(batcher.receipt("!a", "$2", 20, 3).is_empty());
+        let due = batcher.take_due(start);
+        assert_eq!(chores(&due), vec![receipt("!a", "$3")]);
+        assert_eq!(due[0].waiters, vec![1, 2, 3]);
+
+        // Behind what was sent: answered at once.
+        assert_eq!(batcher.receipt("!a", "$2", 20, 4), vec![4]);
+        // Further: waits out the second.
+        assert!(batcher.receipt("!a", "$4", 40, 5).is_empty());
+        assert_eq!(batcher.next_due(start), Some(start + RECEIPT_INTERVAL));
+        assert!(batcher
+            .take_due(start + Duration::from_millis(500))
+            .is_empty());
+        let due = batcher.take_due(start + RECEIPT_INTERVAL);
+        assert_eq!(chores(&due), vec![receipt("!a", "$4")]);
+
+        let counters = batcher.counters();
+        assert_eq!((counters.receipts_asked, counters.receipts_sent), (5, 2));
+    }
+
+    #[test]
+    fn test_typing_goes_on_changes_only() {
+        let start = Instant::now();
+        let mut batcher = Batcher::default();
+        assert!(batcher.typing("!a", true, 1, start).is_empty());
+        assert_eq!(batcher.take_due(start).len(), 1);
+
+        // Still typing: nothing to say until the notice needs renewing.
+        assert_eq!(batcher.typing("!a", true, 2, start), vec![2]);
+        let later = start + TYPING_RENEW;
+        assert!(batcher.typing("!a", true, 3, later).is_empty());
+        assert_eq!(batcher.take_due(later).len(), 1);
+
+        // Stopping and starting again before anything went is no change.
+        assert!(batcher.typing("!a", false, 4, later).is_empty());
+        assert_eq!(batcher.typing("!a", true, 5, later), vec![4, 5]);
+        assert!(batcher.take_due(later).is_empty());
+        assert_eq!(batcher.next_due(later), None);
+
+        let counters = batcher.counters();
+        assert_eq!((counters.typing_asked, counters.typing_sent), (5, 2));
+    }
+
+    #[test]
+    fn test_presence_is_spaced_and_backs_off_when_rate_limited() {
+        let start = Instant::now();
+        let mut batcher = Batcher::default();
+        batcher.presence(UserStatus::Idle, None, 1);
+        assert_eq!(batcher.take_due(start).len(), 1);
+        assert_eq!(batcher.presence(UserStatus::Idle, None, 2), vec![2]);
+
+        batcher.presence(UserStatus::Online, None, 3);
+        batcher.presence(UserStatus::DoNotDisturb, None, 4);
+        assert_eq!(batcher.next_due(start), Some(start + PRESENCE_SPACING));
+        let due = batcher.take_due(start + PRESENCE_SPACING);
+        assert_eq!(due[0].waiters, vec![3, 4]);
+
+        // Turned away: retried once the server says, and spaced as much.
+        let now = start + PRESENCE_SPACING;
+        let batch = due.into_iter().next().unwrap();
+        batcher.rate_limited(Duration::from_secs(20), now);
+        batcher.retry(batch, now);
+        assert!(batcher.take_due(now + Duration::from_secs(10)).is_empty());
+        let retried = batcher.take_due(now + Duration::from_secs(20));
+        assert_eq!(retried[0].waiters, vec![3, 4]);
+        batcher.presence(UserStatus::Online, None, 5);
+        let spaced = now + Duration::from_secs(40);
+        assert_eq!(batcher.next_due(now), Some(spaced));
+        assert_eq!(batcher.counters().rate_limited, 1);
+    }
+}
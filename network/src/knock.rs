@@ -0,0 +1,81 @@
+//! Knocking: asking to join a room whose join rule is "knock". Members who
+//! can invite see the request and let the knocker in with an invite, or
+//! turn them away with a kick. Rooms and servers that don't take knocks
+//! answer with `KnockError::Unsupported` rather than the bare refusal.
+
+use crate::events::KnockRequest;
+use crate::federation::FederationError;
+use matrix_sdk::ruma::UserId;
+use matrix_sdk::{HttpError, Room, RoomMemberships};
+use std::fmt;
+
+/// Why a knock could not be made, for the knock prompt to show. Other
+/// failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KnockError {
+    /// The room doesn't take knocks, by its join rule or room version, or
+    /// the server has no knocking at all.
+    Unsupported,
+}
+
+impl fmt::Display for KnockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => f.write_str("This room doesn't take requests to join"),
+        }
+    }
+}
+
+impl std::error::Error for KnockError {}
+
+/// Turn a refused knock into `KnockError::Unsupported`, unless federation
+/// refused it. Knocking on a room that doesn't allow it is a plain 403, and
+/// servers without the endpoint don't know it.
+pub(crate) fn knock_error(e: HttpError) -> anyhow::Error {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    if let Some(error) = e.as_client_api_error().and_then(FederationError::from_api) {
+        return error.into();
+    }
+    let unknown_endpoint = e
+        .as_client_api_error()
+        .is_some_and(|error| matches!(error.status_code.as_u16(), 404 | 405));
+    match e.client_api_error_kind() {
+        Some(
+            ErrorKind::Forbidden
+            | ErrorKind::Unrecognized
+            | ErrorKind::IncompatibleRoomVersion { .. },
+        ) => KnockError::Unsupported.into(),
+        None if unknown_endpoint => KnockError::Unsupported.into(),
+        _ => e.into(),
+    }
+}
+
+/// Knocks waiting in `room`, if `user_id` can let people in there.
+pub(crate) async fn requests(
+    room: &Room,
+    user_id: &UserId,
+) -> matrix_sdk::Result<Vec<KnockRequest>> {
+    if !room.can_user_invite(user_id).await? {
+        return Ok(Vec::new());
+    }
+    let room_name = room
+        .display_name()
+        .await
+        .map(|n| n.to_string())
+        .unwrap_or_else(|_| room.room_id().to_string());
+    let members = room.members_no_sync(RoomMemberships::KNOCK).await?;
+    Ok(members
+        .iter()
+        .map(|member| KnockRequest {
+            room_id: room.room_id().to_string(),
+            room_name: room_name.clone(),
+            user_id: member.user_id().to_string(),
+            display_name: member.display_name().map(str::to_string),
+            reason: member
+                .event()
+                .original_content()
+                .and_then(|content| content.reason.clone()),
+        })
+        .collect())
+}
@@ -1,15 +1,361 @@
 use anyhow::{Context, Result};
-use matrix_sdk::{ruma::events::room::message::RoomMessageEventContent, Client};
+use chat_core::game_invite::GameInvite;
+use chat_core::link_preview::LinkPreview;
+use chat_core::mention::Mention;
+use chat_core::{Attachment, Room, Space, UserStatus};
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk::matrix_auth::MatrixSession;
+use matrix_sdk::room::{MessagesOptions, Receipts};
+use matrix_sdk::ruma::TransactionId;
+use matrix_sdk::Client;
+use matrix_sdk::LoopCtrl;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, oneshot, watch, Notify};
+use tokio::task::JoinHandle;
 
+pub mod admin;
+pub mod audit;
+pub mod cancel;
+pub mod composer;
+pub mod config;
+pub mod connection;
+pub mod crash;
+pub mod cue_sound;
+pub mod details;
+pub mod diagnostics;
+pub mod discovery;
+pub mod encryption;
+pub mod events;
+pub mod favorites;
+pub mod federation;
+pub mod forward;
+mod hotkey;
+pub mod housekeeping;
+pub mod knock;
+pub mod link_preview;
+pub mod media;
+pub mod media_cache;
+pub mod members;
+pub mod outbox;
+pub mod password_reset;
+pub mod peek;
+pub mod pins;
+pub mod profile;
+pub mod purge;
+pub mod pusher;
+pub mod recorder;
+pub mod register;
+pub mod report;
+pub mod room_settings;
+pub mod rooms;
+pub mod roster;
+pub mod search;
 pub mod session;
+pub mod settings_transfer;
+pub mod signaling;
+pub mod spelling;
+pub mod startup;
+pub mod stickers;
+pub mod stun;
+mod sync;
+pub mod threepid;
+pub mod timeline;
+pub mod translate;
 pub mod voice;
+pub mod voice_clip;
+pub mod welcome;
 
-use session::{Session, SessionManager};
+use admin::{admin_error, AdminError, ChannelRemoval, Role, RolesEventContent};
+use audit::{AuditKind, StateHistoryPage};
+use composer::ComposerStore;
+use config::{ConfigManager, PusherConfig, Retention};
+use connection::{Connection, ConnectionState, Retry};
+use details::EventDetails;
+use diagnostics::{Check, Report, Status};
+use discovery::{DiscoveryError, ServerStatus};
+use encryption::RoomSecurity;
+use events::{ChatEvent, KnockRequest, NotificationEvent, TypingEvent, VoiceMember};
+use favorites::{FavoritesContent, FAVORITES_EVENT_TYPE};
+use federation::{federation_error, FederationError, ServerAcl};
+use housekeeping::{Batch, Batcher, Chore, Counters};
+use knock::knock_error;
+use link_preview::PreviewCache;
+use media::{Upload, UploadProgress};
+use media_cache::{CacheCategory, CacheUsage};
+use members::{MemberList, Presences};
+use password_reset::PasswordReset;
+use peek::RoomPreview;
+use profile::{Profile, ProfileContent, AVATAR_THUMBNAIL_SIZE, PROFILE_EVENT_TYPE};
+use purge::{PurgeProgress, PurgeReport, PurgeWindow};
+use pusher::{pusher_error, Pusher};
+use register::{Availability, RegisterError, Registration, ServerSupport};
+use report::Reported;
+use room_settings::{alias_error, AliasError, HistoryVisibility, JoinRule};
+use room_settings::{RoomAliases, RoomSettings};
+use rooms::{game_invite_content, text_content, ContextPage, MessagePage, NewerPage, Permissions};
+use roster::{Friend, FriendEventContent, HandshakeError, Roster};
+use search::{SearchHit, SearchIndex};
+use session::{DeviceDetails, Session, SessionManager};
+use signaling::{
+    CallAnswerEventContent, CallHangupEventContent, HangupReason, VoiceMemberEventContent,
+};
+use stickers::{Sticker, StickerPack, EMOTE_ROOMS_TYPE, USER_PACK_NAME, USER_PACK_TYPE};
+use threepid::{threepid_error, EmailValidation, Threepid, ThreepidError};
+use welcome::{Seen, Welcome, WelcomeSeenContent, WELCOME_SEEN_EVENT_TYPE};
+
+/// Capacity of the event broadcast; slow subscribers lag rather than block sync.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+/// Events per room a background sync asks for. Enough for the mentions
+/// that arrive between two syncs, without keeping whole timelines.
+const BACKGROUND_TIMELINE_LIMIT: u32 = 10;
+/// Messages per room fetched when the search index is rebuilt.
+const REINDEX_PAGE_SIZE: u32 = 100;
+/// Round trips timed by `run_diagnostics`.
+const DIAGNOSTIC_PINGS: usize = 3;
+/// How long a STUN server gets to answer.
+const STUN_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub struct MatrixClient {
     client: Client,
     user_id: Option<String>,
     display_name: Option<String>,
+    event_tx: broadcast::Sender<ChatEvent>,
+    notification_tx: broadcast::Sender<NotificationEvent>,
+    typing_tx: broadcast::Sender<TypingEvent>,
+    connection_tx: Arc<watch::Sender<ConnectionState>>,
+    retry_sync: Retry,
+    sync_task: Option<JoinHandle<()>>,
+    /// When a sync last finished, in milliseconds since the epoch; 0 if
+    /// none has.
+    last_sync: Arc<AtomicU64>,
+    /// The token of the last sync, to pick the loop up there on restart.
+    next_batch: Arc<Mutex<Option<String>>>,
+    /// Signed in but not shown; synced only for notifications.
+    background: bool,
+    /// What we last published, so status and message can change apart.
+    presence: Mutex<(UserStatus, Option<String>)>,
+    previews: Arc<PreviewCache>,
+    search: Arc<Mutex<SearchIndex>>,
+    /// The friends list, as last merged with what other devices stored.
+    roster: Arc<Mutex<Roster>>,
+    /// Everyone's presence as last seen in sync, for the member list.
+    presences: Arc<Mutex<Presences>>,
+    /// Users whose keys changed since we verified them, to flag their
+    /// messages.
+    changed_keys: Arc<Mutex<HashSet<String>>>,
+    /// Follows the crypto store's news of everyone's keys once synced.
+    trust_watch: Option<JoinHandle<()>>,
+    housekeeping: Housekeeping,
+}
+
+/// Answers a request queued with the housekeeping once what covers it went.
+type ChoreReply = oneshot::Sender<std::result::Result<(), String>>;
+
+/// Sends the receipts, typing notices and presence the `Batcher` lets
+/// through, on a task of its own, so queueing them never waits on the
+/// network, nor holds up the messages sent after.
+struct Housekeeping {
+    batcher: Arc<Mutex<Batcher<ChoreReply>>>,
+    wake: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl Housekeeping {
+    fn start(client: Client) -> Self {
+        let batcher = Arc::new(Mutex::new(Batcher::default()));
+        let wake = Arc::new(Notify::new());
+        let task = tokio::spawn(run_housekeeping(client, batcher.clone(), wake.clone()));
+        Self {
+            batcher,
+            wake,
+            task,
+        }
+    }
+
+    /// Queue a request through `queue`, then wait for what covers it to go.
+    fn queue(
+        &self,
+        queue: impl FnOnce(&mut Batcher<ChoreReply>, ChoreReply) -> Vec<ChoreReply>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let answered = queue(&mut self.batcher.lock().unwrap(), reply);
+        for reply in answered {
+            let _ = reply.send(Ok(()));
+        }
+        self.wake.notify_one();
+        async move {
+            match rx.await {
+                Ok(result) => result.map_err(anyhow::Error::msg),
+                Err(_) => Err(anyhow::anyhow!("Signed out before it was sent")),
+            }
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        self.batcher.lock().unwrap().counters()
+    }
+}
+
+impl Drop for Housekeeping {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run_housekeeping(
+    client: Client,
+    batcher: Arc<Mutex<Batcher<ChoreReply>>>,
+    wake: Arc<Notify>,
+) {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    loop {
+        let due = batcher.lock().unwrap().next_due(Instant::now());
+        let Some(due) = due else {
+            wake.notified().await;
+            continue;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(due.into()) => {}
+            // Something new may be due sooner.
+            _ = wake.notified() => continue,
+        }
+        let batches = batcher.lock().unwrap().take_due(Instant::now());
+        for batch in batches {
+            let result = send_chore(&client, &batch.chore).await;
+            let Err(e) = result else {
+                for reply in batch.waiters {
+                    let _ = reply.send(Ok(()));
+                }
+                continue;
+            };
+            if let Some(ErrorKind::LimitExceeded { retry_after_ms }) = e.client_api_error_kind() {
+                let retry_after = retry_after_ms.unwrap_or(housekeeping::PRESENCE_SPACING);
+                tracing::warn!(
+                    "Rate limited; holding receipts, typing and presence for {:?}",
+                    retry_after
+                );
+                let mut batcher = batcher.lock().unwrap();
+                batcher.rate_limited(retry_after, Instant::now());
+                batcher.retry(batch, Instant::now());
+                continue;
+            }
+            tracing::warn!("Failed to send {:?}: {}", batch.chore, e);
+            let Batch { waiters, .. } = batch;
+            for reply in waiters {
+                let _ = reply.send(Err(e.to_string()));
+            }
+        }
+    }
+}
+
+async fn send_chore(client: &Client, chore: &Chore) -> matrix_sdk::Result<()> {
+    use matrix_sdk::ruma::api::client::presence::set_presence::v3::Request as PresenceRequest;
+    use matrix_sdk::ruma::api::client::typing::create_typing_event::v3::{
+        Request as TypingRequest, Typing,
+    };
+    use matrix_sdk::ruma::presence::PresenceState;
+    use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId};
+
+    let Some(user_id) = client.user_id() else {
+        return Err(matrix_sdk::Error::AuthenticationRequired);
+    };
+    match chore {
+        Chore::Receipt { room_id, event_id } => {
+            let (Ok(room_id), Ok(event_id)) = (
+                OwnedRoomId::try_from(room_id.as_str()),
+                OwnedEventId::try_from(event_id.as_str()),
+            ) else {
+                return Ok(());
+            };
+            let Some(room) = client.get_room(&room_id) else {
+                return Ok(());
+            };
+            let receipts = Receipts::new()
+                .fully_read_marker(event_id.clone())
+                .public_read_receipt(event_id);
+            room.send_multiple_receipts(receipts).await
+        }
+        Chore::Typing { room_id, typing } => {
+            let Ok(room_id) = OwnedRoomId::try_from(room_id.as_str()) else {
+                return Ok(());
+            };
+            let typing = if *typing {
+                Typing::Yes(housekeeping::TYPING_TIMEOUT)
+            } else {
+                Typing::No
+            };
+            let request = TypingRequest::new(user_id.to_owned(), room_id, typing);
+            client.send(request, None).await?;
+            Ok(())
+        }
+        Chore::Presence { status, message } => {
+            let presence = match status {
+                UserStatus::Online => PresenceState::Online,
+                UserStatus::Idle | UserStatus::DoNotDisturb => PresenceState::Unavailable,
+                UserStatus::Offline => PresenceState::Offline,
+            };
+            let mut request = PresenceRequest::new(user_id.to_owned(), presence);
+            request.status_msg = members::status_msg(*status, message.as_deref());
+            client.send(request, None).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Free space where media is cached. The cache's directory may not exist
+/// yet, so the nearest folder above it that does is asked.
+fn disk_check() -> Check {
+    let dir = media_cache::shared().dir();
+    let available = dir
+        .ancestors()
+        .find(|dir| dir.exists())
+        .map(fs2::available_space);
+    match available {
+        Some(Ok(available)) => diagnostics::disk(available),
+        Some(Err(e)) => Check::new(
+            "Disk",
+            Status::Warn,
+            format!("Couldn't check free space: {}", e),
+        ),
+        None => Check::new("Disk", Status::Warn, "The cache folder can't be found"),
+    }
+}
+
+/// Remember the name and verification of the device `client` is signed in
+/// on, and that its token still works, for the saved profile card. Runs in
+/// the background; failures leave what was remembered.
+fn refresh_device_details(client: Client) {
+    tokio::spawn(async move {
+        let (Some(user_id), Some(device_id)) = (client.user_id(), client.device_id()) else {
+            return;
+        };
+        let devices = match client.devices().await {
+            Ok(response) => response.devices,
+            Err(e) => {
+                tracing::warn!("Failed to load device details: {}", e);
+                return;
+            }
+        };
+        let device_name = devices
+            .into_iter()
+            .find(|device| device.device_id.as_str() == device_id.as_str())
+            .and_then(|device| device.display_name);
+        let verified = encryption::own_device_verified(&client).await.ok();
+        let details = DeviceDetails {
+            device_name,
+            verified,
+            validated_at: Some(session::now_secs()),
+        };
+        if let Err(e) = SessionManager::update_details(user_id.as_str(), |d| *d = details) {
+            tracing::warn!("Failed to save device details: {}", e);
+        }
+    });
 }
 
 impl MatrixClient {
@@ -20,37 +366,32 @@ impl MatrixClient {
             .trim_start_matches("http://")
             .trim_end_matches('/');
 
-        println!("[MatrixClient] Connecting to server: {}", server_name);
+        tracing::info!("Connecting to server: {}", server_name);
 
         // Try server_name discovery first (does .well-known lookup), fall back to homeserver_url
         let client = if let Ok(name) = <&matrix_sdk::ruma::ServerName>::try_from(server_name) {
-            Client::builder().server_name(name).build().await?
+            Client::builder().server_name(name).build().await
         } else {
             Client::builder()
                 .homeserver_url(homeserver_url)
                 .build()
-                .await?
-        };
-        println!(
-            "[MatrixClient] Connected. Homeserver resolved to: {}",
-            client.homeserver()
-        );
-        Ok(Self {
-            client,
-            user_id: None,
-            display_name: None,
-        })
+                .await
+        }
+        .map_err(|e| DiscoveryError::classify(&e.into()))?;
+        tracing::warn!("Connected. Homeserver resolved to: {}", client.homeserver());
+        Ok(Self::from_client(client, None, None))
     }
 
     /// Login with username/password. Returns (user_id, display_name).
     pub async fn login(&mut self, username: &str, password: &str) -> Result<(String, String)> {
-        println!("[MatrixClient] Logging in as '{}'", username);
+        tracing::info!("Logging in as '{}'", username);
         let response = self
             .client
             .matrix_auth()
             .login_username(username, password)
             .send()
             .await?;
+        self.use_device_store().await?;
 
         let user_id = response.user_id.to_string();
 
@@ -75,56 +416,374 @@ impl MatrixClient {
                 homeserver: self.client.homeserver().to_string(),
                 access_token: mat_session.tokens.access_token.to_string(),
                 device_id: mat_session.meta.device_id.to_string(),
+                details: DeviceDetails::default(),
             };
             let _ = SessionManager::save_session(saved);
+            refresh_device_details(self.client.clone());
         }
 
         Ok((user_id, display_name))
     }
 
     /// Register a new account. Returns (user_id, display_name).
+    ///
+    /// Servers that only ask for the dummy auth stage are handled here;
+    /// anything more fails with `RegisterError::NeedsBrowser`.
     pub async fn register(&mut self, username: &str, password: &str) -> Result<(String, String)> {
         use matrix_sdk::ruma::api::client::account::register::v3::Request as RegistrationRequest;
+        use matrix_sdk::ruma::api::client::uiaa::{AuthData, AuthType, Dummy};
 
         let mut request = RegistrationRequest::new();
         request.username = Some(username.to_string());
         request.password = Some(password.to_string());
+        request.initial_device_display_name = Some("GameChat".to_string());
+
+        let response = match self.client.matrix_auth().register(request.clone()).await {
+            Ok(response) => response,
+            Err(e) => {
+                let Some(info) = e.as_uiaa_response() else {
+                    return Err(register_error(e));
+                };
+                let dummy_only = info
+                    .flows
+                    .iter()
+                    .any(|flow| flow.stages.iter().all(|stage| *stage == AuthType::Dummy));
+                if !dummy_only {
+                    return Err(RegisterError::NeedsBrowser.into());
+                }
+                let mut dummy = Dummy::new();
+                dummy.session = info.session.clone();
+                request.auth = Some(AuthData::Dummy(dummy));
+                self.client
+                    .matrix_auth()
+                    .register(request)
+                    .await
+                    .map_err(register_error)?
+            }
+        };
+
+        self.use_device_store().await?;
+        let user_id = response.user_id.to_string();
+        let display_name = username.to_string();
+        self.user_id = Some(user_id.clone());
+        self.display_name = Some(display_name.clone());
+
+        if let Some(mat_session) = self.client.matrix_auth().session() {
+            let saved = Session {
+                user_id: user_id.clone(),
+                display_name: display_name.clone(),
+                homeserver: self.client.homeserver().to_string(),
+                access_token: mat_session.tokens.access_token.to_string(),
+                device_id: mat_session.meta.device_id.to_string(),
+                details: DeviceDetails::default(),
+            };
+            let _ = SessionManager::save_session(saved);
+            refresh_device_details(self.client.clone());
+        }
+
+        tracing::info!("Registered {}", user_id);
+        Ok((user_id, display_name))
+    }
+
+    /// Check that the server answers as a Matrix homeserver, and find out
+    /// whether it takes passwords and lets new users register.
+    pub async fn server_support(&self) -> Result<ServerSupport> {
+        use matrix_sdk::ruma::api::client::account::register::v3::Request as RegistrationRequest;
+        use matrix_sdk::ruma::api::client::error::ErrorKind;
+        use matrix_sdk::ruma::api::client::session::get_login_types::v3::LoginType;
+
+        let login_types = self
+            .client
+            .matrix_auth()
+            .get_login_types()
+            .await
+            .context("This does not look like a Matrix server")?;
+        let password_login = login_types
+            .flows
+            .iter()
+            .any(|flow| matches!(flow, LoginType::Password(_)));
+
+        // Registering without credentials only asks the server for its
+        // flows; none of them is started.
+        let registration = match self
+            .client
+            .matrix_auth()
+            .register(RegistrationRequest::new())
+            .await
+        {
+            Ok(_) => Registration::InApp,
+            Err(e) => match e.as_uiaa_response() {
+                Some(info) => {
+                    let flows: Vec<Vec<String>> = info
+                        .flows
+                        .iter()
+                        .map(|flow| flow.stages.iter().map(|s| s.to_string()).collect())
+                        .collect();
+                    Registration::from_flows(&flows)
+                }
+                None if matches!(e.client_api_error_kind(), Some(ErrorKind::Forbidden)) => {
+                    Registration::Disabled
+                }
+                None => return Err(e.into()),
+            },
+        };
+        Ok(ServerSupport {
+            homeserver: self.client.homeserver().to_string(),
+            password_login,
+            registration,
+        })
+    }
+
+    /// Time one round trip to the homeserver.
+    pub async fn ping(&self) -> Result<Duration> {
+        let started = Instant::now();
+        self.client.matrix_auth().get_login_types().await?;
+        Ok(started.elapsed())
+    }
+
+    /// Time a few round trips to the homeserver and ask what it runs.
+    pub async fn server_status(&self) -> Result<ServerStatus> {
+        discovery::server_status(self.client.homeserver()).await
+    }
+
+    /// Check what voice and chat depend on, one after another: the
+    /// homeserver, the token, the sync loop, this device's keys, audio
+    /// devices, UDP and disk space. Checks that need an account are left
+    /// out when signed out. Takes a few seconds; nothing here fails, as a
+    /// failure is a check's result.
+    pub async fn run_diagnostics(&self) -> Report {
+        let mut pings = Vec::with_capacity(DIAGNOSTIC_PINGS);
+        for _ in 0..DIAGNOSTIC_PINGS {
+            pings.push(self.ping().await.map_err(|e| e.to_string()));
+        }
+        let mut checks = vec![diagnostics::homeserver(
+            self.client.homeserver().as_str(),
+            &pings,
+        )];
+        if self.client.logged_in() {
+            checks.push(self.token_check().await);
+            let running = self
+                .sync_task
+                .as_ref()
+                .is_some_and(|task| !task.is_finished());
+            let last_sync = self.last_sync.load(Ordering::Relaxed);
+            let age = (last_sync > 0).then(|| chat_core::time::now_ms().saturating_sub(last_sync));
+            checks.push(diagnostics::sync(
+                running,
+                *self.connection_tx.borrow(),
+                age,
+            ));
+            checks.push(diagnostics::housekeeping(&self.housekeeping.counters()));
+            checks.push(match encryption::own_device_verified(&self.client).await {
+                Ok(true) => Check::new("Encryption", Status::Ok, "This device is cross-signed"),
+                Ok(false) => Check::new(
+                    "Encryption",
+                    Status::Warn,
+                    "This device isn't verified, so others may not trust its messages",
+                ),
+                Err(e) => Check::new(
+                    "Encryption",
+                    Status::Fail,
+                    format!("Couldn't load this device's keys: {}", e),
+                ),
+            });
+        } else {
+            checks.push(Check::new("Sign-in", Status::Warn, "Not signed in"));
+        }
+        let devices = tokio::task::spawn_blocking(|| {
+            (
+                voice::VoiceManager::get_input_devices(),
+                voice::VoiceManager::get_output_devices(),
+            )
+        })
+        .await
+        .unwrap_or_default();
+        checks.push(diagnostics::audio(
+            &devices.0,
+            &devices.1,
+            &ConfigManager::load().audio,
+        ));
+        checks.push(self.udp_check().await);
+        checks.push(disk_check());
+        Report { checks }
+    }
+
+    /// Whether the homeserver still takes our access token.
+    async fn token_check(&self) -> Check {
+        use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+        const NAME: &str = "Sign-in";
+        match self.client.whoami().await {
+            Ok(response) => Check::new(
+                NAME,
+                Status::Ok,
+                format!("Token valid for {}", response.user_id),
+            ),
+            Err(e) => match e.client_api_error_kind() {
+                Some(
+                    ErrorKind::UnknownToken { .. }
+                    | ErrorKind::MissingToken
+                    | ErrorKind::UserDeactivated,
+                ) => Check::new(NAME, Status::Fail, "Signed out; sign in again"),
+                _ => Check::new(
+                    NAME,
+                    Status::Warn,
+                    format!("Couldn't check the token: {}", e),
+                ),
+            },
+        }
+    }
 
-        match self.client.matrix_auth().register(request).await {
-            Ok(response) => {
-                let user_id = response.user_id.to_string();
-                let display_name = username.to_string();
-                self.user_id = Some(user_id.clone());
-                self.display_name = Some(display_name.clone());
-
-                if let Some(mat_session) = self.client.matrix_auth().session() {
-                    let saved = Session {
-                        user_id: user_id.clone(),
-                        display_name: display_name.clone(),
-                        homeserver: self.client.homeserver().to_string(),
-                        access_token: mat_session.tokens.access_token.to_string(),
-                        device_id: mat_session.meta.device_id.to_string(),
-                    };
-                    let _ = SessionManager::save_session(saved);
+    /// Bind a UDP port as voice does, and ask the homeserver's STUN or TURN
+    /// servers which address it is seen at from outside.
+    async fn udp_check(&self) -> Check {
+        const NAME: &str = "UDP";
+        let socket = match tokio::net::UdpSocket::bind("0.0.0.0:0").await {
+            Ok(socket) => socket,
+            Err(e) => {
+                return Check::new(NAME, Status::Fail, format!("Can't bind a UDP port: {}", e))
+            }
+        };
+        let port = socket
+            .local_addr()
+            .map(|address| address.port())
+            .unwrap_or(0);
+        let uris = self.turn_uris().await;
+        let mut last_error = None;
+        for server in uris.iter().filter_map(|uri| stun::server_address(uri)) {
+            let address = match tokio::net::lookup_host(&server).await {
+                Ok(mut addresses) => addresses.find(|address| address.is_ipv4()),
+                Err(e) => {
+                    last_error = Some(format!("{} can't be resolved: {}", server, e));
+                    continue;
                 }
+            };
+            let Some(address) = address else {
+                continue;
+            };
+            match stun::query(&socket, address, STUN_TIMEOUT).await {
+                Ok(mapped) => {
+                    let message = format!("Port {} bound; {} sees it as {}", port, server, mapped);
+                    return Check::new(NAME, Status::Ok, message);
+                }
+                Err(e) => last_error = Some(e.to_string()),
+            }
+        }
+        let message = match last_error {
+            Some(e) => format!("Port {} bound, but STUN failed: {}", port, e),
+            None => format!(
+                "Port {} bound; the homeserver offers no STUN server to try",
+                port
+            ),
+        };
+        Check::new(NAME, Status::Warn, message)
+    }
+
+    /// The homeserver's STUN and TURN server URIs; none when signed out.
+    async fn turn_uris(&self) -> Vec<String> {
+        use matrix_sdk::ruma::api::client::voip::get_turn_server_info::v3::Request;
 
-                Ok((user_id, display_name))
+        if !self.client.logged_in() {
+            return Vec::new();
+        }
+        match self.client.send(Request::new(), None).await {
+            Ok(response) => response.uris,
+            Err(e) => {
+                tracing::warn!("Failed to get TURN servers: {}", e);
+                Vec::new()
             }
-            Err(e) => Err(anyhow::anyhow!(
-                "Registration failed: {}. Many homeservers require email verification or have registration disabled.",
-                e
-            )),
         }
     }
 
+    /// The homeserver's STUN and TURN servers that voice can ask for its
+    /// public address, resolved to IPv4 as the voice socket is.
+    pub async fn stun_servers(&self) -> Vec<SocketAddr> {
+        let mut servers = Vec::new();
+        for uri in self.turn_uris().await {
+            let Some(server) = stun::server_address(&uri) else {
+                continue;
+            };
+            match tokio::net::lookup_host(&server).await {
+                Ok(mut addresses) => servers.extend(addresses.find(|address| address.is_ipv4())),
+                Err(e) => tracing::warn!("{} can't be resolved: {}", server, e),
+            };
+        }
+        servers
+    }
+
+    /// Ask the server whether `username` can be registered.
+    pub async fn check_username(&self, username: &str) -> Result<Availability> {
+        use matrix_sdk::ruma::api::client::account::get_username_availability::v3::Request;
+        use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+        match self
+            .client
+            .send(Request::new(username.to_string()), None)
+            .await
+        {
+            Ok(response) if response.available => Ok(Availability::Available),
+            Ok(_) => Ok(Availability::Taken),
+            Err(e) => match e.client_api_error_kind() {
+                Some(ErrorKind::UserInUse) => Ok(Availability::Taken),
+                Some(ErrorKind::InvalidUsername | ErrorKind::Exclusive) => {
+                    Ok(Availability::Invalid)
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    /// Have the server mail `email` a link to reset the password of the
+    /// account it is on. Addresses on no account look mailed too.
+    pub async fn request_password_reset(&self, email: &str) -> Result<PasswordReset> {
+        use matrix_sdk::ruma::ClientSecret;
+
+        let validation = EmailValidation {
+            email: email.trim().to_string(),
+            client_secret: ClientSecret::new().to_string(),
+            sid: String::new(),
+            send_attempt: 1,
+        };
+        password_reset::request_token(&self.client, validation).await
+    }
+
+    /// Mail the reset link again. Either mail's link works.
+    pub async fn resend_password_reset(&self, reset: &PasswordReset) -> Result<PasswordReset> {
+        let validation = EmailValidation {
+            send_attempt: reset.validation.send_attempt + 1,
+            ..reset.validation.clone()
+        };
+        password_reset::request_token(&self.client, validation).await
+    }
+
+    /// Submit the code from the reset mail, for servers that give
+    /// `reset.submit_url`, instead of clicking the link.
+    pub async fn submit_reset_token(&self, reset: &PasswordReset, token: &str) -> Result<()> {
+        let validation = &reset.validation;
+        let submit_url = reset.submit_url.as_deref().context("No code to submit")?;
+        password_reset::submit_token(
+            submit_url,
+            &validation.sid,
+            &validation.client_secret,
+            token,
+        )
+        .await
+    }
+
+    /// Set a new password once the reset link for session `sid` was
+    /// clicked. Every session of the account is signed out.
+    pub async fn complete_password_reset(
+        &self,
+        sid: &str,
+        client_secret: &str,
+        new_password: &str,
+    ) -> Result<()> {
+        password_reset::set_password(&self.client, sid, client_secret, new_password).await
+    }
+
     /// Restore a session from a saved token.
     pub async fn restore_session(saved: &Session) -> Result<Self> {
-        let client = Client::builder()
-            .homeserver_url(&saved.homeserver)
-            .build()
-            .await?;
-
-        use matrix_sdk::matrix_auth::{MatrixSession, MatrixSessionTokens};
+        use matrix_sdk::matrix_auth::MatrixSessionTokens;
         use matrix_sdk::ruma::{OwnedDeviceId, OwnedUserId};
         use matrix_sdk::SessionMeta;
 
@@ -140,13 +799,58 @@ impl MatrixClient {
             },
         };
 
-        client.matrix_auth().restore_session(mat_session).await?;
+        let client = session_client(&saved.homeserver, mat_session).await?;
+        refresh_device_details(client.clone());
 
-        Ok(Self {
+        Ok(Self::from_client(
             client,
-            user_id: Some(saved.user_id.clone()),
-            display_name: Some(saved.display_name.clone()),
-        })
+            Some(saved.user_id.clone()),
+            Some(saved.display_name.clone()),
+        ))
+    }
+
+    /// Carry on the session just signed in with a client keeping its state
+    /// and encryption keys in the device's store, where they outlive this
+    /// run. The one signing in only ever has them in memory.
+    async fn use_device_store(&mut self) -> Result<()> {
+        let session = self
+            .client
+            .matrix_auth()
+            .session()
+            .context("Not logged in")?;
+        let client = session_client(self.client.homeserver().as_str(), session).await?;
+        *self = Self::from_client(client, None, None);
+        Ok(())
+    }
+
+    fn from_client(client: Client, user_id: Option<String>, display_name: Option<String>) -> Self {
+        let (event_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let (notification_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let (typing_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let (connection_tx, _) = watch::channel(ConnectionState::Connected);
+        let housekeeping = Housekeeping::start(client.clone());
+        Self {
+            client,
+            user_id,
+            display_name,
+            event_tx,
+            notification_tx,
+            typing_tx,
+            connection_tx: Arc::new(connection_tx),
+            retry_sync: Retry::default(),
+            sync_task: None,
+            last_sync: Arc::new(AtomicU64::new(0)),
+            next_batch: Arc::new(Mutex::new(None)),
+            background: false,
+            presence: Mutex::new((UserStatus::Online, None)),
+            previews: Arc::new(PreviewCache::load(ConfigManager::load().cache.retention)),
+            search: Arc::new(Mutex::new(SearchIndex::default())),
+            roster: Arc::new(Mutex::new(Roster::default())),
+            presences: Arc::new(Mutex::new(Presences::default())),
+            changed_keys: Arc::new(Mutex::new(HashSet::new())),
+            trust_watch: None,
+            housekeeping,
+        }
     }
 
     pub fn get_display_name(&self) -> Option<&str> {
@@ -160,31 +864,2241 @@ impl MatrixClient {
     pub async fn set_display_name(&mut self, name: &str) -> Result<()> {
         self.client.account().set_display_name(Some(name)).await?;
         self.display_name = Some(name.to_string());
+        if let Some(user_id) = &self.user_id {
+            let _ = SessionManager::update_display_name(user_id, name);
+        }
+        Ok(())
+    }
+
+    /// Load our display name, avatar thumbnail and bio from the server.
+    pub async fn get_profile(&self) -> Result<Profile> {
+        use matrix_sdk::media::{MediaFormat, MediaThumbnailSize};
+        use matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method;
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+
+        let account = self.client.account();
+        let display_name = account.get_display_name().await?;
+        let avatar = account
+            .get_avatar(MediaFormat::Thumbnail(MediaThumbnailSize {
+                method: Method::Crop,
+                width: AVATAR_THUMBNAIL_SIZE.into(),
+                height: AVATAR_THUMBNAIL_SIZE.into(),
+            }))
+            .await?;
+        let bio = match account
+            .account_data_raw(GlobalAccountDataEventType::from(PROFILE_EVENT_TYPE))
+            .await?
+        {
+            Some(raw) => raw.deserialize_as::<ProfileContent>()?.bio,
+            None => String::new(),
+        };
+        Ok(Profile {
+            display_name,
+            avatar,
+            bio,
+        })
+    }
+
+    /// Upload `data` as our avatar.
+    pub async fn set_avatar(&self, data: Vec<u8>, mime_type: &str) -> Result<()> {
+        let mime_type: mime::Mime = mime_type.parse()?;
+        self.client
+            .account()
+            .upload_avatar(&mime_type, data)
+            .await?;
+        Ok(())
+    }
+
+    /// Store our bio in account data, where other GameChat clients read it.
+    pub async fn set_bio(&self, bio: &str) -> Result<()> {
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+        use matrix_sdk::ruma::serde::Raw;
+
+        let content = Raw::new(&ProfileContent {
+            bio: bio.to_string(),
+        })?
+        .cast();
+        self.client
+            .account()
+            .set_account_data_raw(
+                GlobalAccountDataEventType::from(PROFILE_EVENT_TYPE),
+                content,
+            )
+            .await?;
         Ok(())
     }
 
-    pub async fn sync(&self) -> Result<()> {
+    /// The pinned rooms, top first; `None` if no device has stored them yet.
+    pub async fn get_favorites(&self) -> Result<Option<Vec<String>>> {
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+
+        let raw = self
+            .client
+            .account()
+            .account_data_raw(GlobalAccountDataEventType::from(FAVORITES_EVENT_TYPE))
+            .await?;
+        match raw {
+            Some(raw) => Ok(Some(raw.deserialize_as::<FavoritesContent>()?.rooms)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the pinned rooms in account data, for our other devices.
+    pub async fn set_favorites(&self, rooms: Vec<String>) -> Result<()> {
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+        use matrix_sdk::ruma::serde::Raw;
+
+        let content = Raw::new(&FavoritesContent { rooms })?.cast();
+        self.client
+            .account()
+            .set_account_data_raw(
+                GlobalAccountDataEventType::from(FAVORITES_EVENT_TYPE),
+                content,
+            )
+            .await?;
         Ok(())
     }
 
-    pub async fn send_message(&self, room_id: &str, content: &str) -> Result<()> {
+    /// The sticker packs to offer in `room_id`: the user's own, then those
+    /// of rooms they enabled everywhere, then the room's own. Packs that
+    /// can't be read are left out.
+    pub async fn sticker_packs(&self, room_id: &str) -> Result<Vec<StickerPack>> {
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+
         let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let account = self.client.account();
+        let user_pack = account
+            .account_data_raw(GlobalAccountDataEventType::from(USER_PACK_TYPE))
+            .await?
+            .and_then(|raw| raw.deserialize_as::<serde_json::Value>().ok());
+        let mut packs: Vec<StickerPack> = user_pack
+            .and_then(|content| stickers::parse_pack(&content, USER_PACK_NAME))
+            .into_iter()
+            .collect();
+        let enabled = account
+            .account_data_raw(GlobalAccountDataEventType::from(EMOTE_ROOMS_TYPE))
+            .await?
+            .and_then(|raw| raw.deserialize_as::<serde_json::Value>().ok())
+            .map(|content| stickers::enabled_rooms(&content))
+            .unwrap_or_default();
+        for (pack_room_id, state_key) in enabled {
+            // The room's own packs come last, all of them.
+            if pack_room_id == room_id.as_str() {
+                continue;
+            }
+            let Some(room) = <&matrix_sdk::ruma::RoomId>::try_from(pack_room_id.as_str())
+                .ok()
+                .and_then(|id| self.client.get_room(id))
+            else {
+                continue;
+            };
+            match rooms::sticker_packs(&room, Some(&state_key)).await {
+                Ok(found) => packs.extend(found),
+                Err(e) => tracing::warn!("Failed to read sticker pack in {}: {}", pack_room_id, e),
+            }
+        }
         if let Some(room) = self.client.get_room(room_id) {
-            let content = RoomMessageEventContent::text_plain(content);
-            room.send(content).await?;
+            packs.extend(rooms::sticker_packs(&room, None).await?);
         }
-        Ok(())
+        Ok(packs)
     }
 
-    pub async fn logout(&mut self) -> Result<()> {
-        if let Some(user_id) = &self.user_id {
-            let _ = SessionManager::delete_session(user_id);
+    /// Subscribe to room and message updates from the sync loop.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Subscribe to messages that should raise a desktop notification.
+    pub fn subscribe_notifications(&self) -> broadcast::Receiver<NotificationEvent> {
+        self.notification_tx.subscribe()
+    }
+
+    /// Subscribe to who is typing in each room. Our own typing is included.
+    pub fn subscribe_typing(&self) -> broadcast::Receiver<TypingEvent> {
+        self.typing_tx.subscribe()
+    }
+
+    /// Follow whether the sync loop reaches the homeserver. The returned
+    /// `Retry` works without going through the client.
+    pub fn subscribe_connection(&self) -> Connection {
+        Connection {
+            state: self.connection_tx.subscribe(),
+            retry: self.retry_sync.clone(),
         }
-        let _ = self.client.matrix_auth().logout().await;
-        self.user_id = None;
-        self.display_name = None;
+    }
+
+    /// Run an initial sync so joined rooms are known, then keep syncing in
+    /// the background, backing off while the homeserver can't be reached.
+    /// Calling this again while running is a no-op.
+    pub async fn start_sync(&mut self) -> Result<()> {
+        if self.sync_task.is_some() {
+            return Ok(());
+        }
+
+        sync::register_handlers(
+            &self.client,
+            self.event_tx.clone(),
+            self.roster.clone(),
+            self.presences.clone(),
+            self.changed_keys.clone(),
+        );
+        sync::register_typing(&self.client, self.typing_tx.clone());
+        self.spawn_indexer();
+        let response = self
+            .client
+            .sync_once(self.sync_settings())
+            .await
+            .context("Initial sync failed")?;
+        self.last_sync
+            .store(chat_core::time::now_ms(), Ordering::Relaxed);
+        sync::register_notifications(&self.client, self.notification_tx.clone()).await;
+
+        *self.next_batch.lock().unwrap() = Some(response.next_batch.clone());
+        self.trust_watch = Some(tokio::spawn(encryption::watch_trust(
+            self.client.clone(),
+            self.event_tx.clone(),
+            self.changed_keys.clone(),
+        )));
+        self.spawn_sync(self.sync_settings().token(response.next_batch));
         Ok(())
     }
+
+    /// Sync only what notifications need while `background`: a few recent
+    /// events per room, no typing or presence, and members loaded lazily,
+    /// so an account that is not shown stays small. Switching restarts the
+    /// sync loop where it left off.
+    pub async fn set_background(&mut self, background: bool) {
+        if self.background == background || self.sync_task.is_none() {
+            self.background = background;
+            return;
+        }
+        self.background = background;
+        self.stop_sync();
+        let mut settings = self.sync_settings();
+        if let Some(token) = self.next_batch.lock().unwrap().clone() {
+            settings = settings.token(token);
+        }
+        self.spawn_sync(settings);
+    }
+
+    fn sync_settings(&self) -> SyncSettings {
+        use matrix_sdk::ruma::api::client::filter::{
+            Filter, FilterDefinition, LazyLoadOptions, RoomEventFilter,
+        };
+        use matrix_sdk::ruma::api::client::sync::sync_events::v3::Filter as SyncFilter;
+
+        if !self.background {
+            return SyncSettings::default();
+        }
+        let mut filter = FilterDefinition::default();
+        filter.presence = Filter::ignore_all();
+        filter.room.ephemeral = RoomEventFilter::ignore_all();
+        filter.room.timeline.limit = Some(BACKGROUND_TIMELINE_LIMIT.into());
+        filter.room.state.lazy_load_options = LazyLoadOptions::Enabled {
+            include_redundant_members: false,
+        };
+        SyncSettings::default().filter(SyncFilter::FilterDefinition(filter))
+    }
+
+    /// Keep the search index up with what sync brings in. Ends with the
+    /// client, when the event channel closes.
+    fn spawn_indexer(&self) {
+        let mut events = self.event_tx.subscribe();
+        let index = self.search.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => index.lock().unwrap().apply(&event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Search index missed {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn spawn_sync(&mut self, settings: SyncSettings) {
+        let client = self.client.clone();
+        let tx = self.event_tx.clone();
+        let connection_tx = self.connection_tx.clone();
+        let retry = self.retry_sync.clone();
+        let last_sync = self.last_sync.clone();
+        let next_batch = self.next_batch.clone();
+        self.sync_task = Some(tokio::spawn(async move {
+            let result = client
+                .sync_with_result_callback(settings, move |response| {
+                    let tx = tx.clone();
+                    let connection_tx = connection_tx.clone();
+                    let retry = retry.clone();
+                    let last_sync = last_sync.clone();
+                    let next_batch = next_batch.clone();
+                    async move {
+                        let response = match response {
+                            Ok(response) => response,
+                            Err(e) => {
+                                let state = connection_tx.borrow().failed();
+                                tracing::warn!(
+                                    "Sync failed (attempt {}): {}",
+                                    state.attempt(),
+                                    e
+                                );
+                                connection_tx.send_replace(state);
+                                tokio::select! {
+                                    _ = tokio::time::sleep(connection::backoff(state.attempt())) => {}
+                                    _ = retry.requested() => {}
+                                }
+                                return Ok(LoopCtrl::Continue);
+                            }
+                        };
+                        last_sync.store(chat_core::time::now_ms(), Ordering::Relaxed);
+                        *next_batch.lock().unwrap() = Some(response.next_batch.clone());
+                        connection_tx.send_if_modified(|state| {
+                            let reconnected = !state.is_connected();
+                            *state = ConnectionState::Connected;
+                            reconnected
+                        });
+                        for event in sync::unread_events(&response) {
+                            let _ = tx.send(event);
+                        }
+                        Ok(LoopCtrl::Continue)
+                    }
+                })
+                .await;
+            if let Err(e) = result {
+                tracing::error!("Sync loop stopped: {}", e);
+            }
+        }));
+    }
+
+    fn stop_sync(&mut self) {
+        if let Some(task) = self.sync_task.take() {
+            task.abort();
+        }
+    }
+
+    /// Joined rooms that are not spaces, with `last_activity` resolved,
+    /// `concurrency` rooms at a time.
+    pub async fn get_joined_rooms(&self, concurrency: usize) -> Result<Vec<Room>> {
+        let _span = startup::Span::new("rooms");
+        let joined: Vec<_> = self
+            .client
+            .joined_rooms()
+            .into_iter()
+            .filter(|room| !room.is_space())
+            .collect();
+        let result = startup::resolve_bounded(joined, concurrency, |room| async move {
+            let mut options = MessagesOptions::backward();
+            options.limit = 1u32.into();
+            let last_activity = room
+                .messages(options)
+                .await
+                .ok()
+                .and_then(|page| page.chunk.first().and_then(rooms::timeline_message))
+                .map(|m| m.timestamp)
+                .unwrap_or(0);
+            rooms::room_to_core(&room, last_activity).await
+        })
+        .await;
+        Ok(result)
+    }
+
+    /// Joined spaces with their child room ids, `concurrency` at a time.
+    pub async fn get_spaces(&self, concurrency: usize) -> Result<Vec<Space>> {
+        let _span = startup::Span::new("spaces");
+        let joined: Vec<_> = self
+            .client
+            .joined_rooms()
+            .into_iter()
+            .filter(|room| room.is_space())
+            .collect();
+        let result = startup::resolve_bounded(joined, concurrency, |room| async move {
+            rooms::space_to_core(&room).await
+        })
+        .await;
+        Ok(result)
+    }
+
+    /// Fetch a page of history ending at `from` (or the latest event when `None`).
+    pub async fn get_messages(
+        &self,
+        room_id: &str,
+        from: Option<&str>,
+        limit: u32,
+    ) -> Result<MessagePage> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let mut page = fetch_page(&room, from, limit).await?;
+        encryption::mark_keys_changed(&mut page.messages, &self.changed_keys.lock().unwrap());
+        self.search
+            .lock()
+            .unwrap()
+            .insert_page(room_id.as_str(), &page.messages);
+        Ok(page)
+    }
+
+    /// A page of `room_id`'s audit log going back from `from`, or from the
+    /// latest event: the changes of `kinds` among up to `limit` state
+    /// events, which the server picks out of the history. An empty page
+    /// with a `next` token only means none of the events it went through
+    /// were of those kinds.
+    pub async fn get_state_history(
+        &self,
+        room_id: &str,
+        kinds: &[AuditKind],
+        from: Option<&str>,
+        limit: u32,
+    ) -> Result<StateHistoryPage> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+
+        let mut options = MessagesOptions::backward();
+        options.from = from.map(str::to_owned);
+        options.limit = limit.into();
+        options.filter.types = Some(kinds.iter().map(|k| k.event_type().to_string()).collect());
+        let response = room.messages(options).await?;
+
+        let mut entries: Vec<_> = response
+            .chunk
+            .iter()
+            .filter_map(|event| event.event.deserialize_as::<serde_json::Value>().ok())
+            .filter_map(|event| audit::entry(&event))
+            .filter(|entry| kinds.contains(&entry.kind))
+            .collect();
+        let mut names: HashMap<String, Option<String>> = HashMap::new();
+        for entry in &mut entries {
+            for user_id in std::iter::once(&entry.actor).chain(&entry.target) {
+                if names.contains_key(user_id) {
+                    continue;
+                }
+                let name = match <&matrix_sdk::ruma::UserId>::try_from(user_id.as_str()) {
+                    Ok(user_id) => rooms::member_name(&room, user_id).await,
+                    Err(_) => None,
+                };
+                names.insert(user_id.clone(), name);
+            }
+            entry.actor_name = names[&entry.actor].clone();
+            entry.target_name = entry.target.as_ref().and_then(|t| names[t].clone());
+        }
+        Ok(StateHistoryPage {
+            entries,
+            next: response.end,
+        })
+    }
+
+    /// A page of `room_id`'s history going forwards from `from`, for
+    /// catching up from a message jumped to.
+    pub async fn get_newer_messages(
+        &self,
+        room_id: &str,
+        from: &str,
+        limit: u32,
+    ) -> Result<NewerPage> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+
+        let mut options = MessagesOptions::forward();
+        options.from = Some(from.to_string());
+        options.limit = limit.into();
+        let response = room.messages(options).await?;
+
+        // Forwards, the chunk comes oldest first.
+        let chunk: Vec<_> = response.chunk.into_iter().rev().collect();
+        let mut messages = rooms::page_messages(&chunk);
+        rooms::resolve_sender_names(&room, &mut messages).await;
+        encryption::mark_keys_changed(&mut messages, &self.changed_keys.lock().unwrap());
+        self.search
+            .lock()
+            .unwrap()
+            .insert_page(room_id.as_str(), &messages);
+
+        Ok(NewerPage {
+            messages,
+            next_batch: response.end,
+        })
+    }
+
+    /// `event_id` with up to `limit` messages around it, for jumping to a
+    /// message that is not loaded.
+    pub async fn get_context(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        limit: u32,
+    ) -> Result<ContextPage> {
+        use matrix_sdk::deserialized_responses::TimelineEvent;
+        use matrix_sdk::ruma::api::client::context::get_context::v3::Request;
+        use matrix_sdk::ruma::EventId;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let event_id = <&EventId>::try_from(event_id)?;
+
+        let mut request = Request::new(room_id.to_owned(), event_id.to_owned());
+        request.limit = limit.into();
+        let response = self
+            .client
+            .send(request, None)
+            .await
+            .context("Could not load the message")?;
+
+        // Newest first, as `page_messages` takes them.
+        let events: Vec<TimelineEvent> = response
+            .events_after
+            .into_iter()
+            .rev()
+            .chain(response.event)
+            .chain(response.events_before)
+            .map(TimelineEvent::new)
+            .collect();
+        let mut messages = rooms::page_messages(&events);
+        rooms::resolve_sender_names(&room, &mut messages).await;
+
+        Ok(ContextPage {
+            messages,
+            prev_batch: response.start,
+            next_batch: response.end,
+        })
+    }
+
+    /// Messages loaded this session that match `query`, best first, in
+    /// `room_id` if given. Answers at once and covers encrypted rooms.
+    pub fn search_local(&self, query: &str, room_id: Option<&str>) -> Vec<SearchHit> {
+        self.search
+            .lock()
+            .unwrap()
+            .search(query, room_id, search::MAX_RESULTS)
+    }
+
+    /// The homeserver's matches for `query`, in `room_id` if given. Slower
+    /// than `search_local` and blind to encrypted rooms, but it reaches
+    /// history never loaded here. Not every homeserver supports it.
+    pub async fn search_server(
+        &self,
+        query: &str,
+        room_id: Option<&str>,
+    ) -> Result<Vec<SearchHit>> {
+        use matrix_sdk::deserialized_responses::TimelineEvent;
+        use matrix_sdk::ruma::api::client::filter::RoomEventFilter;
+        use matrix_sdk::ruma::api::client::search::search_events::v3::{
+            Categories, Criteria, Request,
+        };
+        use matrix_sdk::ruma::OwnedRoomId;
+
+        let mut criteria = Criteria::new(query.to_string());
+        if let Some(room_id) = room_id {
+            let mut filter = RoomEventFilter::default();
+            filter.rooms = Some(vec![OwnedRoomId::try_from(room_id)?]);
+            criteria.filter = filter;
+        }
+        let mut categories = Categories::new();
+        categories.room_events = Some(criteria);
+        let response = self
+            .client
+            .send(Request::new(categories), None)
+            .await
+            .context("The homeserver could not search")?;
+
+        let mut hits = Vec::new();
+        for result in response.search_categories.room_events.results {
+            let Some(raw) = result.result else {
+                continue;
+            };
+            // Rooms we are no longer in can't be opened to show the hit.
+            let Some(room) = raw
+                .get_field::<OwnedRoomId>("room_id")
+                .ok()
+                .flatten()
+                .and_then(|room_id| self.client.get_room(&room_id))
+            else {
+                continue;
+            };
+            let Some(message) = rooms::timeline_message(&TimelineEvent::new(raw)) else {
+                continue;
+            };
+            let mut messages = [message];
+            rooms::resolve_sender_names(&room, &mut messages).await;
+            let [message] = messages;
+            hits.push(SearchHit {
+                room_id: room.room_id().to_string(),
+                message,
+            });
+        }
+        Ok(hits)
+    }
+
+    /// Index the latest messages of every joined room afresh, in the
+    /// background; search covers each room as its page comes in.
+    pub fn rebuild_search_index(&self) {
+        let client = self.client.clone();
+        let index = self.search.clone();
+        index.lock().unwrap().clear();
+        tokio::spawn(async move {
+            for room in client.joined_rooms() {
+                match fetch_page(&room, None, REINDEX_PAGE_SIZE).await {
+                    Ok(page) => index
+                        .lock()
+                        .unwrap()
+                        .insert_page(room.room_id().as_str(), &page.messages),
+                    Err(e) => tracing::warn!("Could not index {}: {}", room.room_id(), e),
+                }
+            }
+        });
+    }
+
+    /// Send a text message under `txn_id`, returning the new event id.
+    /// Retrying with the same transaction id will not duplicate the message.
+    /// Fails with `UnverifiedDevices` if the config says never to send to
+    /// unverified devices and the room has some, and with `FederationError`
+    /// if the room's other servers turned it away.
+    pub async fn send_message(
+        &self,
+        room_id: &str,
+        content: &str,
+        mentions: &[Mention],
+        txn_id: &str,
+    ) -> Result<String> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        encryption::check_send(&self.client, &room).await?;
+        let content = text_content(content, mentions);
+        let response = room
+            .send(content)
+            .with_transaction_id(<&TransactionId>::from(txn_id))
+            .await
+            .map_err(federation_error)?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Send `content` as a reply to `reply_to`, under `txn_id` as with
+    /// `send_message`.
+    pub async fn send_reply(
+        &self,
+        room_id: &str,
+        content: &str,
+        mentions: &[Mention],
+        reply_to: &str,
+        txn_id: &str,
+    ) -> Result<String> {
+        use matrix_sdk::ruma::events::relation::InReplyTo;
+        use matrix_sdk::ruma::events::room::message::Relation;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let reply_to = matrix_sdk::ruma::OwnedEventId::try_from(reply_to)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        encryption::check_send(&self.client, &room).await?;
+        let mut content = text_content(content, mentions);
+        content.relates_to = Some(Relation::Reply {
+            in_reply_to: InReplyTo::new(reply_to),
+        });
+        let response = room
+            .send(content)
+            .with_transaction_id(<&TransactionId>::from(txn_id))
+            .await
+            .map_err(federation_error)?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Send `action` as an emote, e.g. "waves" for "* alice waves",
+    /// returning the new event id. Fails as `send_message` does.
+    pub async fn send_emote(&self, room_id: &str, action: &str) -> Result<String> {
+        use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        encryption::check_send(&self.client, &room).await?;
+        let response = room
+            .send(RoomMessageEventContent::emote_plain(action))
+            .await
+            .map_err(federation_error)?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Send `sticker` from a pack, returning the new event id. Fails as
+    /// `send_message` does.
+    pub async fn send_sticker(&self, room_id: &str, sticker: &Sticker) -> Result<String> {
+        use matrix_sdk::ruma::events::room::ImageInfo;
+        use matrix_sdk::ruma::events::sticker::StickerEventContent;
+        use matrix_sdk::ruma::{OwnedMxcUri, UInt};
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        encryption::check_send(&self.client, &room).await?;
+        let mut info = ImageInfo::new();
+        info.mimetype = sticker.mimetype.clone();
+        info.size = sticker.size.and_then(UInt::new);
+        info.width = sticker.width.map(UInt::from);
+        info.height = sticker.height.map(UInt::from);
+        let url = OwnedMxcUri::from(sticker.url.as_str());
+        let response = room
+            .send(StickerEventContent::new(sticker.body.clone(), info, url))
+            .await
+            .map_err(federation_error)?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Invite the room to a game, returning the new event id. Fails as
+    /// `send_message` does, or when the invite isn't valid.
+    pub async fn send_game_invite(&self, room_id: &str, invite: &GameInvite) -> Result<String> {
+        let now = chat_core::time::now_ms();
+        invite.validate(now)?;
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        encryption::check_send(&self.client, &room).await?;
+        let response = room
+            .send(game_invite_content(invite, now)?)
+            .await
+            .map_err(federation_error)?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Replace the body of our message `event_id`, returning the edit's id.
+    pub async fn edit_message(&self, room_id: &str, event_id: &str, body: &str) -> Result<String> {
+        use matrix_sdk::ruma::events::room::message::{
+            ReplacementMetadata, RoomMessageEventContent,
+        };
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let event_id = matrix_sdk::ruma::OwnedEventId::try_from(event_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let content = RoomMessageEventContent::text_plain(body)
+            .make_replacement(ReplacementMetadata::new(event_id, None), None);
+        let response = room.send(content).await?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Redact a message or reaction.
+    pub async fn delete_message(&self, room_id: &str, event_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let event_id = <&matrix_sdk::ruma::EventId>::try_from(event_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.redact(event_id, None, None)
+            .await
+            .map_err(|e| admin_error(e.into(), "delete this message"))?;
+        Ok(())
+    }
+
+    /// React to `event_id` with `key`, returning the reaction's event id.
+    pub async fn react_to_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        key: &str,
+    ) -> Result<String> {
+        use matrix_sdk::ruma::events::reaction::ReactionEventContent;
+        use matrix_sdk::ruma::events::relation::Annotation;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let event_id = matrix_sdk::ruma::OwnedEventId::try_from(event_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let content = ReactionEventContent::new(Annotation::new(event_id, key.to_string()));
+        let response = room.send(content).await?;
+        Ok(response.event_id.to_string())
+    }
+
+    /// Report `event_id` to the homeserver's admins. `score` runs from -100,
+    /// most offensive, to 0.
+    pub async fn report_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        score: Option<i64>,
+        reason: Option<&str>,
+    ) -> Result<Reported> {
+        use matrix_sdk::ruma::api::client::error::ErrorKind;
+        use matrix_sdk::ruma::api::client::room::report_content::v3::Request;
+
+        let room_id = matrix_sdk::ruma::OwnedRoomId::try_from(room_id)?;
+        let event_id = matrix_sdk::ruma::OwnedEventId::try_from(event_id)?;
+        let score = score.and_then(|score| matrix_sdk::ruma::Int::new(score.clamp(-100, 0)));
+        let request = Request::new(room_id, event_id, score, reason.map(str::to_string));
+        match self.client.send(request, None).await {
+            Ok(_) => Ok(Reported::Sent),
+            Err(e) if matches!(e.client_api_error_kind(), Some(ErrorKind::Unrecognized)) => {
+                Ok(Reported::Unsupported)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Report `room_id` itself, e.g. as spam.
+    pub async fn report_room(&self, room_id: &str, reason: Option<&str>) -> Result<Reported> {
+        report::report_room(&self.client, room_id, reason).await
+    }
+
+    /// Remove `user_id`'s messages in `room_id` within `window` of now,
+    /// banning them first if `ban`, reporting on `progress` as redactions
+    /// go. Redactions that fail are listed in the report, not returned.
+    pub fn purge_user_messages(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        window: PurgeWindow,
+        ban: bool,
+        progress: watch::Sender<PurgeProgress>,
+    ) -> impl Future<Output = Result<PurgeReport>> + Send + 'static {
+        let room = <&matrix_sdk::ruma::RoomId>::try_from(room_id)
+            .map_err(anyhow::Error::from)
+            .and_then(|room_id| self.client.get_room(room_id).context("Room not found"));
+        let user_id = user_id.to_string();
+        async move {
+            let room = purge::ServerRoom(room?);
+            purge::purge(
+                &room,
+                &user_id,
+                window,
+                ban,
+                chat_core::time::now_ms(),
+                purge::REDACTION_SPACING,
+                |p| {
+                    let _ = progress.send(p);
+                },
+            )
+            .await
+        }
+    }
+
+    /// Stop receiving anything from `user_id`, on every device.
+    pub async fn ignore_user(&self, user_id: &str) -> Result<()> {
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        self.client.account().ignore_user(user_id).await?;
+        Ok(())
+    }
+
+    /// Whether `room_id` is encrypted and who there has unverified devices.
+    pub async fn room_security(&self, room_id: &str) -> Result<RoomSecurity> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        encryption::room_security(&self.client, &room).await
+    }
+
+    /// Take `user_id`'s keys as they are now as the ones we verified,
+    /// forgetting those they had, and tell subscribers how far they can be
+    /// trusted with them.
+    pub async fn trust_new_keys(&self, user_id: &str) -> Result<()> {
+        let account = self.user_id.as_deref().context("Not logged in")?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let mut config = ConfigManager::load();
+        let forgotten = config
+            .accounts
+            .get_mut(account)
+            .and_then(|account| account.verified_keys.remove(user_id.as_str()));
+        if forgotten.is_some() {
+            ConfigManager::save(&config)?;
+        }
+        encryption::recheck_user(
+            &self.client,
+            &self.event_tx,
+            &self.changed_keys,
+            user_id,
+            false,
+        )
+        .await;
+        Ok(())
+    }
+
+    /// `room_id`'s server ACL and which of its members' servers it shuts
+    /// out, or `None` if it has none.
+    pub async fn server_acl(&self, room_id: &str) -> Result<Option<ServerAcl>> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        Ok(federation::server_acl(&room).await?)
+    }
+
+    /// What our power level lets us do with messages in `room_id`, and
+    /// how long its slow mode has us wait between them.
+    pub async fn get_permissions(&self, room_id: &str) -> Result<Permissions> {
+        use matrix_sdk::ruma::events::{MessageLikeEventType, StateEventType};
+
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let redact_others = room.can_user_redact(user_id).await?;
+        Ok(Permissions {
+            send: room
+                .can_user_send_message(user_id, MessageLikeEventType::RoomMessage)
+                .await?,
+            react: room
+                .can_user_send_message(user_id, MessageLikeEventType::Reaction)
+                .await?,
+            redact_others,
+            topic: room
+                .can_user_send_state(user_id, StateEventType::RoomTopic)
+                .await?,
+            pin: room
+                .can_user_send_state(user_id, StateEventType::RoomPinnedEvents)
+                .await?,
+            slow_mode_secs: if redact_others {
+                0
+            } else {
+                rooms::slow_mode_secs(&room).await?
+            },
+        })
+    }
+
+    /// The ids of the messages pinned in `room_id`, oldest pin first.
+    pub async fn get_pinned(&self, room_id: &str) -> Result<Vec<String>> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        Ok(pins::pinned_events(&room).await?)
+    }
+
+    /// Pin message `event_id` in `room_id`, or unpin it. Nothing is sent if
+    /// it already is as asked.
+    pub async fn pin_message(&self, room_id: &str, event_id: &str, pin: bool) -> Result<()> {
+        use matrix_sdk::ruma::events::room::pinned_events::RoomPinnedEventsEventContent;
+        use matrix_sdk::ruma::OwnedEventId;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let pinned = pins::pinned_events(&room).await?;
+        let Some(pinned) = pins::with_pin(&pinned, event_id, pin) else {
+            return Ok(());
+        };
+        let pinned = pinned
+            .iter()
+            .map(|id| OwnedEventId::try_from(id.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let verb = if pin {
+            "pin messages"
+        } else {
+            "unpin messages"
+        };
+        room.send_state_event(RoomPinnedEventsEventContent::new(pinned))
+            .await
+            .map_err(|e| admin_error(e, verb))?;
+        Ok(())
+    }
+
+    /// Message `event_id` of `room_id`, from the store or the server, e.g.
+    /// a pinned one that isn't loaded.
+    pub async fn get_event(&self, room_id: &str, event_id: &str) -> Result<chat_core::Message> {
+        use matrix_sdk::ruma::EventId;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let event = room
+            .event(<&EventId>::try_from(event_id)?)
+            .await
+            .context("Could not load the message")?;
+        let message = rooms::timeline_message(&event).context("Not a message")?;
+        let mut messages = [message];
+        rooms::resolve_sender_names(&room, &mut messages).await;
+        encryption::mark_keys_changed(&mut messages, &self.changed_keys.lock().unwrap());
+        let [message] = messages;
+        Ok(message)
+    }
+
+    /// What there is to know about message `event_id` of `room_id` beyond
+    /// what the timeline shows: its exact times, and how it was encrypted
+    /// and whether the device that sent it is verified.
+    pub async fn get_event_details(&self, room_id: &str, event_id: &str) -> Result<EventDetails> {
+        use matrix_sdk::ruma::EventId;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let event = room
+            .event(<&EventId>::try_from(event_id)?)
+            .await
+            .context("Could not load the message")?;
+        let mut details = details::event_details(&event).context("Not an event")?;
+        if let Some(encryption) = &mut details.encryption {
+            if let (None, Some(device_id)) = (encryption.verified, &encryption.sender_device) {
+                encryption.verified =
+                    encryption::device_verified(&self.client, &details.sender, device_id)
+                        .await
+                        .ok();
+            }
+        }
+        Ok(details)
+    }
+
+    /// Tell `room_id` we started or stopped typing. Only changes go out,
+    /// through the housekeeping.
+    pub fn set_typing(
+        &self,
+        room_id: &str,
+        typing: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let room_id = room_id.to_string();
+        self.housekeeping
+            .queue(move |batcher, reply| batcher.typing(&room_id, typing, reply, Instant::now()))
+    }
+
+    /// Move our read receipt and fully-read marker in `room_id` to `event_id`,
+    /// sent at `ts`. Goes through the housekeeping, which sends only the
+    /// furthest of those asked for in a second.
+    pub fn mark_read(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        ts: u64,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.housekeeping
+            .queue(move |batcher, reply| batcher.receipt(&room_id, &event_id, ts, reply))
+    }
+
+    /// The event our fully-read marker in `room_id` is on, as last synced:
+    /// what came after it is new to us.
+    pub async fn fully_read(&self, room_id: &str) -> Result<Option<String>> {
+        use matrix_sdk::ruma::events::fully_read::FullyReadEventContent;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let Some(event) = room.account_data_static::<FullyReadEventContent>().await? else {
+            return Ok(None);
+        };
+        Ok(Some(event.deserialize()?.content.event_id.to_string()))
+    }
+
+    /// Join a room by id or alias, trying the `via` servers. Returns the room
+    /// id. Fails with `FederationError` if the room's servers turned us away
+    /// or didn't answer.
+    pub async fn join_room(&self, id_or_alias: &str, via: &[String]) -> Result<String> {
+        let id_or_alias = <&matrix_sdk::ruma::RoomOrAliasId>::try_from(id_or_alias)?;
+        let via = via
+            .iter()
+            .map(|s| matrix_sdk::ruma::OwnedServerName::try_from(s.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let room = self
+            .client
+            .join_room_by_id_or_alias(id_or_alias, &via)
+            .await
+            .map_err(
+                |e| match e.as_client_api_error().and_then(FederationError::from_api) {
+                    Some(error) => error.into(),
+                    None => admin_error(e, "join this room"),
+                },
+            )?;
+        Ok(room.room_id().to_string())
+    }
+
+    /// A look into a room not joined, by id or alias, through the `via`
+    /// servers: its latest `limit` messages if its history is
+    /// world-readable, otherwise what the directory says about it.
+    pub async fn preview_room(
+        &self,
+        id_or_alias: &str,
+        via: &[String],
+        limit: u32,
+    ) -> Result<RoomPreview> {
+        peek::preview(&self.client, id_or_alias, via, limit).await
+    }
+
+    /// Ask to join a room we can't just join, trying the `via` servers.
+    /// Returns the room id.
+    pub async fn knock_on_room(
+        &self,
+        id_or_alias: &str,
+        via: &[String],
+        reason: Option<&str>,
+    ) -> Result<String> {
+        use matrix_sdk::ruma::api::client::knock::knock_room::v3::Request;
+
+        let id_or_alias = matrix_sdk::ruma::OwnedRoomOrAliasId::try_from(id_or_alias)?;
+        let mut request = Request::new(id_or_alias);
+        request.reason = reason.map(str::to_string);
+        request.server_name = via
+            .iter()
+            .map(|s| matrix_sdk::ruma::OwnedServerName::try_from(s.as_str()))
+            .collect::<Result<_, _>>()?;
+        let response = self.client.send(request, None).await.map_err(knock_error)?;
+        Ok(response.room_id.to_string())
+    }
+
+    /// Take back our knock on `room_id`. The SDK keeps no room for knocks,
+    /// so this leaves by hand.
+    pub async fn retract_knock(&self, room_id: &str) -> Result<()> {
+        use matrix_sdk::ruma::api::client::membership::leave_room::v3::Request;
+
+        let room_id = matrix_sdk::ruma::OwnedRoomId::try_from(room_id)?;
+        self.client.send(Request::new(room_id), None).await?;
+        Ok(())
+    }
+
+    /// Knocks waiting in the rooms we can let people into.
+    pub async fn knock_requests(&self) -> Result<Vec<KnockRequest>> {
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let mut requests = Vec::new();
+        for room in self.client.joined_rooms() {
+            requests.extend(knock::requests(&room, user_id).await?);
+        }
+        Ok(requests)
+    }
+
+    /// Let `user_id`'s knock on `room_id` in, with an invite.
+    pub async fn approve_knock(&self, room_id: &str, user_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.invite_user_by_id(user_id)
+            .await
+            .map_err(|e| admin_error(e, "let people in"))?;
+        Ok(())
+    }
+
+    /// Invite `user_id` into `room_id`.
+    pub async fn invite_user(&self, room_id: &str, user_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.invite_user_by_id(user_id)
+            .await
+            .map_err(|e| admin_error(e, "invite people"))?;
+        Ok(())
+    }
+
+    /// Turn `user_id`'s knock on `room_id` away.
+    pub async fn deny_knock(&self, room_id: &str, user_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.kick_user(user_id, None)
+            .await
+            .map_err(|e| admin_error(e, "turn people away"))?;
+        Ok(())
+    }
+
+    /// The direct message room with `user_id`, created if there is none yet.
+    pub async fn open_dm(&self, user_id: &str) -> Result<String> {
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        // The first joined room `m.direct` lists for them.
+        let existing = self
+            .client
+            .joined_rooms()
+            .into_iter()
+            .find(|room| room.direct_targets().contains(user_id));
+        let room = match existing {
+            Some(room) => room,
+            None => self.client.create_dm(user_id).await?,
+        };
+        Ok(room.room_id().to_string())
+    }
+
+    /// Everyone on the friends list, and those asking to be on it, with
+    /// what their direct chat knows of them.
+    pub async fn get_friends(&self) -> Result<Vec<Friend>> {
+        if let Some(stored) = roster::load(&self.client).await? {
+            self.roster.lock().unwrap().merge(&stored);
+        }
+        let entries: Vec<(String, roster::RosterEntry)> = {
+            let roster = self.roster.lock().unwrap();
+            roster
+                .entries()
+                .map(|(user_id, entry)| (user_id.to_string(), entry.clone()))
+                .collect()
+        };
+        let mut friends = Vec::with_capacity(entries.len());
+        for (user_id, entry) in entries {
+            // Entries come from other devices too; one with bad ids is
+            // listed without what the room knows.
+            let member = self
+                .dm_member(&entry.room_id, &user_id)
+                .await
+                .ok()
+                .flatten();
+            friends.push(Friend {
+                user_id,
+                display_name: member.as_ref().and_then(|m| m.display_name.clone()),
+                state: entry.state,
+                room_id: entry.room_id,
+                status: member.as_ref().map_or(UserStatus::Offline, |m| m.status),
+                status_message: member.and_then(|m| m.status_message),
+            });
+        }
+        Ok(friends)
+    }
+
+    /// `user_id` as the direct chat `room_id` knows them, if it does.
+    async fn dm_member(&self, room_id: &str, user_id: &str) -> Result<Option<chat_core::Member>> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let Some(room) = self.client.get_room(room_id) else {
+            return Ok(None);
+        };
+        let member = room.get_member_no_sync(user_id).await?;
+        let presences = self.presences.lock().unwrap();
+        Ok(member.map(|member| members::member_to_core(&member, &presences)))
+    }
+
+    /// Ask `user_id` to be friends, in the direct chat with them. If they
+    /// asked first, this accepts them.
+    pub async fn add_friend(&self, user_id: &str) -> Result<()> {
+        use rand::distributions::{Alphanumeric, DistString};
+
+        let user = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        if self.client.user_id() == Some(user) {
+            return Err(HandshakeError::FromSelf.into());
+        }
+        let room_id = self.open_dm(user_id).await?;
+        let request_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let content = self.roster.lock().unwrap().request(
+            user_id,
+            &room_id,
+            &request_id,
+            chat_core::time::now_ms(),
+        );
+        self.send_handshake(user_id, content).await
+    }
+
+    /// Accept `user_id`'s friend request, or turn it down.
+    pub async fn respond_to_friend(&self, user_id: &str, accept: bool) -> Result<()> {
+        let content = {
+            let mut roster = self.roster.lock().unwrap();
+            if accept {
+                roster.accept(user_id, chat_core::time::now_ms())
+            } else {
+                roster.decline(user_id, chat_core::time::now_ms())
+            }
+        };
+        self.send_handshake(user_id, content).await
+    }
+
+    /// Take `user_id` off the friends list, or withdraw our request to them.
+    pub async fn remove_friend(&self, user_id: &str) -> Result<()> {
+        let content = self
+            .roster
+            .lock()
+            .unwrap()
+            .remove(user_id, chat_core::time::now_ms());
+        self.send_handshake(user_id, content).await
+    }
+
+    /// Send `content` to `user_id` in the direct chat their entry names,
+    /// and store the list that led to it. Nothing to send means the list
+    /// had nothing to change.
+    async fn send_handshake(
+        &self,
+        user_id: &str,
+        content: Option<FriendEventContent>,
+    ) -> Result<()> {
+        let Some(content) = content else {
+            return Ok(());
+        };
+        let room_id = self
+            .roster
+            .lock()
+            .unwrap()
+            .friends
+            .get(user_id)
+            .map(|entry| entry.room_id.clone())
+            .context("Not on the friends list")?;
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id.as_str())?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        roster::save(&self.client, &self.roster).await?;
+        room.send(content).await?;
+        Ok(())
+    }
+
+    /// Publish our presence. Matrix has no "do not disturb", so it is sent as
+    /// unavailable with a status message.
+    pub fn set_presence(
+        &self,
+        status: UserStatus,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let message = {
+            let mut presence = self.presence.lock().unwrap();
+            presence.0 = status;
+            presence.1.clone()
+        };
+        self.send_presence(status, message)
+    }
+
+    /// Show `text` with our status, e.g. "Playing Valorant"; blank clears it.
+    pub fn set_status_message(
+        &self,
+        text: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let text = text.trim();
+        let message = (!text.is_empty()).then(|| text.to_string());
+        let status = {
+            let mut presence = self.presence.lock().unwrap();
+            presence.1 = message.clone();
+            presence.0
+        };
+        self.send_presence(status, message)
+    }
+
+    /// Publish presence through the housekeeping, which spaces it out.
+    fn send_presence(
+        &self,
+        status: UserStatus,
+        message: Option<String>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        self.housekeeping
+            .queue(move |batcher, reply| batcher.presence(status, message, reply))
+    }
+
+    /// Mirror our notification keywords into the account's push rules, so
+    /// other clients and mobile push alert on them too.
+    pub async fn set_keywords(&self, keywords: &[String]) -> Result<()> {
+        let settings = self.client.notification_settings().await;
+        let enabled = settings.enabled_keywords().await;
+        for old in enabled.iter().filter(|k| !keywords.contains(k)) {
+            settings.remove_keyword(old).await?;
+        }
+        for new in keywords.iter().filter(|k| !enabled.contains(*k)) {
+            settings.add_keyword(new.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Joined members of `room_id`. Large rooms list only the members already
+    /// known unless `full`, which fetches them all from the server.
+    pub async fn get_room_members(&self, room_id: &str, full: bool) -> Result<MemberList> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        Ok(members::joined_members(&room, full, &self.presences).await?)
+    }
+
+    /// A thumbnail of `user_id`'s avatar in `room_id`, if they have one.
+    pub async fn get_member_avatar(&self, room_id: &str, user_id: &str) -> Result<Option<Vec<u8>>> {
+        use matrix_sdk::media::{MediaFormat, MediaThumbnailSize};
+        use matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let member = room
+            .get_member_no_sync(user_id)
+            .await?
+            .context("Not a member of this room")?;
+        let Some(url) = member.avatar_url() else {
+            return Ok(None);
+        };
+        let key = media_cache::avatar_key(url.as_str());
+        let avatar = media_cache::shared()
+            .fetch(&key, CacheCategory::Avatars, async {
+                let format = MediaFormat::Thumbnail(MediaThumbnailSize {
+                    method: Method::Crop,
+                    width: AVATAR_THUMBNAIL_SIZE.into(),
+                    height: AVATAR_THUMBNAIL_SIZE.into(),
+                });
+                member.avatar(format).await?.context("No avatar")
+            })
+            .await?;
+        Ok(Some(avatar))
+    }
+
+    /// The whole content of an attachment. The download does not borrow
+    /// the client, so it can run alongside other requests.
+    pub fn download_media(
+        &self,
+        source: &str,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + 'static {
+        let client = self.client.clone();
+        let request = media::content_request(source);
+        let key = media_cache::media_key(source);
+        async move { download_cached(&client, request?, &key, false).await }
+    }
+
+    /// What of `attachment` to show inline; see `media::thumbnail_request`.
+    pub fn download_thumbnail(
+        &self,
+        attachment: &Attachment,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + 'static {
+        let client = self.client.clone();
+        let request = media::thumbnail_request(attachment);
+        let key = media_cache::thumbnail_key(attachment);
+        async move { download_cached(&client, request?, &key, true).await }
+    }
+
+    /// Bytes cached on disk, by category.
+    pub fn cache_size(&self) -> CacheUsage {
+        CacheUsage {
+            timeline: self.previews.size(),
+            ..media_cache::shared().usage()
+        }
+    }
+
+    /// Delete what is cached of `category`, but for downloads still going.
+    pub async fn clear_cache(&self, category: CacheCategory) -> Result<()> {
+        let previews = self.previews.clone();
+        if category == CacheCategory::Timeline {
+            self.search.lock().unwrap().clear();
+        }
+        tokio::task::spawn_blocking(move || match category {
+            CacheCategory::Timeline => previews.clear(),
+            _ => media_cache::shared().clear(category),
+        })
+        .await?
+    }
+
+    /// Forget cached media and link previews older than `retention` keeps,
+    /// and keep to it from now on.
+    pub async fn prune_cache(&self, retention: Retention) -> Result<()> {
+        let previews = self.previews.clone();
+        tokio::task::spawn_blocking(move || {
+            previews.prune(retention)?;
+            media_cache::shared().prune(retention)
+        })
+        .await?
+    }
+
+    /// Delete what this machine keeps of what was seen and sent: cached
+    /// media, avatars and link previews, and the messages the composer
+    /// recalls. The media cache is shared by every account signed in here,
+    /// so it goes for all of them. The search index goes too. Nothing on
+    /// the server is touched.
+    pub async fn clear_local_history(&self) -> Result<()> {
+        self.search.lock().unwrap().clear();
+        let previews = self.previews.clone();
+        let user_id = self.user_id.clone();
+        tokio::task::spawn_blocking(move || {
+            previews.clear()?;
+            let cache = media_cache::shared();
+            for category in [
+                CacheCategory::Media,
+                CacheCategory::Avatars,
+                CacheCategory::Timeline,
+            ] {
+                cache.clear(category)?;
+            }
+            match user_id {
+                Some(user_id) => ComposerStore::delete(&user_id),
+                None => Ok(()),
+            }
+        })
+        .await?
+    }
+
+    /// The preview of `url` as of `ts` (ms), made by the homeserver so the
+    /// linked site never sees us; `None` if the page has nothing to show.
+    /// Cached, and like `download_media` it does not borrow the client.
+    pub fn get_url_preview(
+        &self,
+        url: &str,
+        ts: u64,
+    ) -> impl Future<Output = Result<Option<LinkPreview>>> + Send + 'static {
+        let client = self.client.clone();
+        let previews = self.previews.clone();
+        let url = url.to_string();
+        async move {
+            if let Some(cached) = previews.get(&url) {
+                return Ok(cached);
+            }
+            let preview = link_preview::fetch(&client, &url, ts).await?;
+            previews.insert(&url, preview.clone());
+            Ok(preview)
+        }
+    }
+
+    /// Largest upload the homeserver accepts, in bytes.
+    pub async fn upload_limit(&self) -> Result<u64> {
+        use matrix_sdk::ruma::api::client::media::get_media_config;
+
+        let response = self
+            .client
+            .send(get_media_config::v3::Request::new(), None)
+            .await?;
+        Ok(response.upload_size.into())
+    }
+
+    /// Send `upload` to `room_id` as an image or file message under
+    /// `txn_id`, reporting bytes sent to `progress`. Returns the event id.
+    /// Like `download_media`, the upload does not borrow the client.
+    pub fn send_attachment(
+        &self,
+        room_id: &str,
+        upload: Upload,
+        txn_id: &str,
+        progress: watch::Sender<UploadProgress>,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        use matrix_sdk::attachment::AttachmentConfig;
+        use matrix_sdk::TransmissionProgress;
+
+        let room = <&matrix_sdk::ruma::RoomId>::try_from(room_id)
+            .map_err(anyhow::Error::from)
+            .and_then(|room_id| self.client.get_room(room_id).context("Room not found"));
+        let txn_id = matrix_sdk::ruma::OwnedTransactionId::from(txn_id);
+        let client = self.client.clone();
+        async move {
+            let room = room?;
+            encryption::check_send(&client, &room).await?;
+            let mimetype: mime::Mime = upload.mimetype.parse()?;
+            let observable = eyeball::SharedObservable::new(TransmissionProgress::default());
+            let mut updates = observable.subscribe();
+            let forward = tokio::spawn(async move {
+                while let Some(update) = updates.next().await {
+                    let _ = progress.send(UploadProgress {
+                        sent: update.current as u64,
+                        total: update.total as u64,
+                    });
+                }
+            });
+            let config = AttachmentConfig::new().txn_id(&txn_id);
+            let response = room
+                .send_attachment(&upload.name, &mimetype, upload.data, config)
+                .with_send_progress_observable(observable)
+                .await;
+            forward.abort();
+            Ok(response.map_err(federation_error)?.event_id.to_string())
+        }
+    }
+
+    /// Send a voice clip, the WAV file `data` lasting `duration`, as an
+    /// audio message under `txn_id`, returning its event id. Like
+    /// `send_attachment`, this does not borrow the client.
+    pub fn send_voice_message(
+        &self,
+        room_id: &str,
+        data: Vec<u8>,
+        duration: Duration,
+        txn_id: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        use matrix_sdk::attachment::{AttachmentConfig, AttachmentInfo, BaseAudioInfo};
+
+        let room = <&matrix_sdk::ruma::RoomId>::try_from(room_id)
+            .map_err(anyhow::Error::from)
+            .and_then(|room_id| self.client.get_room(room_id).context("Room not found"));
+        let txn_id = matrix_sdk::ruma::OwnedTransactionId::from(txn_id);
+        let client = self.client.clone();
+        async move {
+            let room = room?;
+            encryption::check_send(&client, &room).await?;
+            let mimetype: mime::Mime = voice_clip::MIMETYPE.parse()?;
+            let info = AttachmentInfo::Audio(BaseAudioInfo {
+                duration: Some(duration),
+                size: matrix_sdk::ruma::UInt::new(data.len() as u64),
+            });
+            let config = AttachmentConfig::new().txn_id(&txn_id).info(info);
+            let response = room
+                .send_attachment(voice_clip::FILE_NAME, &mimetype, data, config)
+                .await;
+            Ok(response.map_err(federation_error)?.event_id.to_string())
+        }
+    }
+
+    /// Send `event_id` from `src_room` again in `dst_room`, returning the
+    /// new event id; see `forward`. `annotation` goes above text. Media
+    /// crossing into or out of encryption is downloaded and uploaded again,
+    /// so like `send_attachment` this does not borrow the client. Fails as
+    /// `send_message` does.
+    pub fn forward_message(
+        &self,
+        src_room: &str,
+        event_id: &str,
+        dst_room: &str,
+        annotation: Option<&str>,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        use matrix_sdk::attachment::AttachmentConfig;
+        use matrix_sdk::ruma::events::{AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent};
+
+        let room = |room_id: &str| {
+            <&matrix_sdk::ruma::RoomId>::try_from(room_id)
+                .map_err(anyhow::Error::from)
+                .and_then(|room_id| self.client.get_room(room_id).context("Room not found"))
+        };
+        let rooms = room(src_room).and_then(|src| Ok((src, room(dst_room)?)));
+        let event_id = matrix_sdk::ruma::OwnedEventId::try_from(event_id);
+        let annotation = annotation.map(str::to_string);
+        let client = self.client.clone();
+        async move {
+            let (src, dst) = rooms?;
+            encryption::check_send(&client, &dst).await?;
+            let event = src.event(&event_id?).await?;
+            let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(ev),
+            )) = event.event.deserialize()?
+            else {
+                anyhow::bail!("Only messages can be forwarded");
+            };
+            let content = forward::prepare(ev.content, annotation.as_deref());
+            let response = match forward::plan(&content.msgtype, dst.is_encrypted().await?) {
+                forward::Plan::Resend => dst.send(content).await,
+                forward::Plan::Reupload {
+                    source,
+                    name,
+                    mimetype,
+                } => {
+                    let request = media::content_request(&source)?;
+                    let key = media_cache::media_key(&source);
+                    let data = download_cached(&client, request, &key, false).await?;
+                    let mimetype: mime::Mime = mimetype.parse()?;
+                    dst.send_attachment(&name, &mimetype, data, AttachmentConfig::new())
+                        .await
+                }
+            };
+            Ok(response.map_err(federation_error)?.event_id.to_string())
+        }
+    }
+
+    /// Announce that we are in `room_id`'s voice channel, reachable at
+    /// `candidates`. Calling it again refreshes the announcement.
+    pub async fn join_voice(&self, room_id: &str, candidates: Vec<String>) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let content = VoiceMemberEventContent::joined(candidates, chat_core::time::now_ms());
+        room.send_state_event_for_key(user_id, content).await?;
+        Ok(())
+    }
+
+    /// Withdraw our announcement from `room_id`'s voice channel.
+    pub async fn leave_voice(&self, room_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let user_id = self.client.user_id().context("Not logged in")?;
+        room.send_state_event_for_key(user_id, VoiceMemberEventContent::default())
+            .await?;
+        Ok(())
+    }
+
+    /// Everyone currently in `room_id`'s voice channel, ourselves included.
+    pub async fn voice_members(&self, room_id: &str) -> Result<Vec<VoiceMember>> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        Ok(signaling::voice_members(&room, chat_core::time::now_ms()).await?)
+    }
+
+    /// Tell the caller, and our other devices, that we took call `call_id`.
+    /// Joining the voice channel is separate.
+    pub async fn answer_call(&self, room_id: &str, call_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let content = CallAnswerEventContent {
+            call_id: call_id.to_string(),
+        };
+        room.send(content).await?;
+        Ok(())
+    }
+
+    /// End call `call_id`, or turn it down while it rings.
+    pub async fn hangup_call(
+        &self,
+        room_id: &str,
+        call_id: &str,
+        reason: HangupReason,
+    ) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let content = CallHangupEventContent {
+            call_id: call_id.to_string(),
+            reason,
+        };
+        room.send(content).await?;
+        Ok(())
+    }
+
+    /// Create a channel named `name`, listed in `space_id` if given.
+    /// Returns the room id. The channel list picks it up from sync.
+    pub async fn create_channel(&self, space_id: Option<&str>, name: &str) -> Result<String> {
+        use matrix_sdk::ruma::api::client::room::create_room::v3::Request;
+        use matrix_sdk::ruma::events::space::child::SpaceChildEventContent;
+        use matrix_sdk::ruma::events::space::parent::SpaceParentEventContent;
+        use matrix_sdk::ruma::events::StateEventType;
+
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let space = match space_id {
+            Some(space_id) => {
+                let space_id = <&matrix_sdk::ruma::RoomId>::try_from(space_id)?;
+                let space = self.client.get_room(space_id).context("Space not found")?;
+                // Checked first so a refusal leaves no stray room behind.
+                if !space
+                    .can_user_send_state(user_id, StateEventType::SpaceChild)
+                    .await?
+                {
+                    return Err(AdminError::Forbidden("add channels to this server").into());
+                }
+                Some(space)
+            }
+            None => None,
+        };
+
+        let mut request = Request::new();
+        request.name = Some(name.to_string());
+        let room = self
+            .client
+            .create_room(request)
+            .await
+            .map_err(|e| admin_error(e, "create channels"))?;
+
+        if let Some(space) = space {
+            let via = vec![user_id.server_name().to_owned()];
+            space
+                .send_state_event_for_key(room.room_id(), SpaceChildEventContent::new(via.clone()))
+                .await
+                .map_err(|e| admin_error(e, "add channels to this server"))?;
+            room.send_state_event_for_key(space.room_id(), SpaceParentEventContent::new(via))
+                .await?;
+        }
+        Ok(room.room_id().to_string())
+    }
+
+    /// Take `room_id` off a server: unlist it from `space_id`, or leave it.
+    pub async fn remove_channel(
+        &self,
+        space_id: Option<&str>,
+        room_id: &str,
+        removal: ChannelRemoval,
+    ) -> Result<()> {
+        use matrix_sdk::ruma::events::space::child::SpaceChildEventContent;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        match removal {
+            ChannelRemoval::FromSpace => {
+                let space_id = space_id.context("Channel is not in a server")?;
+                let space_id = <&matrix_sdk::ruma::RoomId>::try_from(space_id)?;
+                let space = self.client.get_room(space_id).context("Space not found")?;
+                // An empty `via` is the tombstone for a removed child.
+                space
+                    .send_state_event_for_key(room_id, SpaceChildEventContent::new(Vec::new()))
+                    .await
+                    .map_err(|e| admin_error(e, "remove channels from this server"))?;
+            }
+            ChannelRemoval::Leave => {
+                let room = self.client.get_room(room_id).context("Room not found")?;
+                room.leave().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Roles defined in `room_id`, built-in ones included, highest first.
+    pub async fn get_roles(&self, room_id: &str) -> Result<Vec<Role>> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        Ok(admin::with_builtin_roles(admin::custom_roles(&room).await?))
+    }
+
+    /// Define a custom role in `room_id`, replacing one of the same name.
+    pub async fn create_role(&self, room_id: &str, role: Role) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let mut roles = admin::custom_roles(&room).await?;
+        roles.retain(|r| r.name != role.name);
+        roles.push(role);
+        room.send_state_event(RolesEventContent { roles })
+            .await
+            .map_err(|e| admin_error(e, "create roles"))?;
+        Ok(())
+    }
+
+    /// Give `user_id` power level `level` in `room_id`.
+    pub async fn set_power_level(&self, room_id: &str, user_id: &str, level: i64) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let user_id = <&matrix_sdk::ruma::UserId>::try_from(user_id)?;
+        let level = matrix_sdk::ruma::Int::new(level).context("Power level out of range")?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.update_power_levels(vec![(user_id, level)])
+            .await
+            .map_err(|e| admin_error(e, "change roles"))?;
+        Ok(())
+    }
+
+    /// Who can join and read `room_id`, whether it is encrypted, and which
+    /// of that we may change.
+    pub async fn get_room_settings(&self, room_id: &str) -> Result<RoomSettings> {
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        Ok(room_settings::load(&room, user_id).await?)
+    }
+
+    pub async fn set_join_rule(&self, room_id: &str, rule: JoinRule) -> Result<()> {
+        use matrix_sdk::ruma::events::room::join_rules::RoomJoinRulesEventContent;
+
+        let rule = rule.to_sdk().context("This join rule can't be set")?;
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.send_state_event(RoomJoinRulesEventContent::new(rule))
+            .await
+            .map_err(|e| admin_error(e, "change who can join"))?;
+        Ok(())
+    }
+
+    pub async fn set_history_visibility(
+        &self,
+        room_id: &str,
+        visibility: HistoryVisibility,
+    ) -> Result<()> {
+        use matrix_sdk::ruma::events::room::history_visibility::RoomHistoryVisibilityEventContent;
+
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.send_state_event(RoomHistoryVisibilityEventContent::new(visibility.to_sdk()))
+            .await
+            .map_err(|e| admin_error(e, "change who can read the history"))?;
+        Ok(())
+    }
+
+    pub async fn set_guest_access(&self, room_id: &str, allowed: bool) -> Result<()> {
+        use matrix_sdk::ruma::events::room::guest_access::{
+            GuestAccess, RoomGuestAccessEventContent,
+        };
+
+        let access = if allowed {
+            GuestAccess::CanJoin
+        } else {
+            GuestAccess::Forbidden
+        };
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.send_state_event(RoomGuestAccessEventContent::new(access))
+            .await
+            .map_err(|e| admin_error(e, "change guest access"))?;
+        Ok(())
+    }
+
+    /// Have each member who can't delete others' messages wait `seconds`
+    /// between messages in `room_id`, up to `MAX_SLOW_MODE_SECS`; 0 turns
+    /// slow mode off.
+    pub async fn set_slow_mode(&self, room_id: &str, seconds: u64) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let content = rooms::SlowModeEventContent {
+            seconds: seconds.min(rooms::MAX_SLOW_MODE_SECS),
+        };
+        room.send_state_event(content)
+            .await
+            .map_err(|e| admin_error(e, "change slow mode"))?;
+        Ok(())
+    }
+
+    /// The welcome set in `room_id`, and what we did with it.
+    pub async fn get_welcome(&self, room_id: &str) -> Result<Option<(Welcome, Seen)>> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let Some(welcome) = rooms::welcome(&room).await? else {
+            return Ok(None);
+        };
+        let seen = self
+            .welcomes_seen()
+            .await?
+            .seen(room_id.as_str(), &welcome.event_id);
+        Ok(Some((welcome, seen)))
+    }
+
+    /// Set `room_id`'s welcome to `body`, as plain text; an empty one
+    /// removes it. We take our own welcome as accepted.
+    pub async fn set_welcome(&self, room_id: &str, body: &str, must_accept: bool) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let response = room
+            .send_state_event(rooms::WelcomeEventContent::plain(body, must_accept))
+            .await
+            .map_err(|e| admin_error(e, "change the welcome message"))?;
+        if !body.trim().is_empty() {
+            let event_id = response.event_id.to_string();
+            self.mark_welcome(room_id.as_str(), &event_id, Seen::Accepted)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn welcomes_seen(&self) -> Result<WelcomeSeenContent> {
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+
+        let raw = self
+            .client
+            .account()
+            .account_data_raw(GlobalAccountDataEventType::from(WELCOME_SEEN_EVENT_TYPE))
+            .await?;
+        match raw {
+            Some(raw) => Ok(raw.deserialize_as::<WelcomeSeenContent>()?),
+            None => Ok(WelcomeSeenContent::default()),
+        }
+    }
+
+    /// Note in account data that we dismissed or accepted welcome
+    /// `event_id` of `room_id`, for our other devices. Rooms we left are
+    /// forgotten meanwhile.
+    pub async fn mark_welcome(&self, room_id: &str, event_id: &str, seen: Seen) -> Result<()> {
+        use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+        use matrix_sdk::ruma::serde::Raw;
+
+        let mut content = self.welcomes_seen().await?;
+        content.mark(room_id, event_id, seen);
+        let joined: Vec<String> = self
+            .client
+            .joined_rooms()
+            .iter()
+            .map(|room| room.room_id().to_string())
+            .collect();
+        content.retain_rooms(&joined);
+        let content = Raw::new(&content)?.cast();
+        self.client
+            .account()
+            .set_account_data_raw(
+                GlobalAccountDataEventType::from(WELCOME_SEEN_EVENT_TYPE),
+                content,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_topic(&self, room_id: &str, topic: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.set_room_topic(topic)
+            .await
+            .map_err(|e| admin_error(e, "change the topic"))?;
+        Ok(())
+    }
+
+    /// Turn on end-to-end encryption in `room_id`, for good. Returns once
+    /// sync has seen it, so messages sent after are encrypted.
+    pub async fn enable_encryption(&self, room_id: &str) -> Result<()> {
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        room.enable_encryption()
+            .await
+            .map_err(|e| admin_error(e, "enable encryption"))?;
+        Ok(())
+    }
+
+    /// The aliases of `room_id`, the main one first.
+    pub async fn get_room_aliases(&self, room_id: &str) -> Result<RoomAliases> {
+        use matrix_sdk::ruma::api::client::room::aliases::v3::Request;
+
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let room_id = matrix_sdk::ruma::OwnedRoomId::try_from(room_id)?;
+        let room = self.client.get_room(&room_id).context("Room not found")?;
+        let response = self.client.send(Request::new(room_id), None).await?;
+        Ok(room_settings::load_aliases(&room, user_id, response.aliases).await?)
+    }
+
+    /// Publish `alias` for `room_id` on our server. `alias` may leave out
+    /// the `#` and the server; the full alias is returned.
+    pub async fn add_room_alias(&self, room_id: &str, alias: &str) -> Result<String> {
+        use matrix_sdk::ruma::api::client::alias::create_alias::v3::Request;
+
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let alias = room_settings::parse_alias(alias, user_id.server_name().as_str())?;
+        let alias_id = matrix_sdk::ruma::OwnedRoomAliasId::try_from(alias.as_str())
+            .map_err(|_| AliasError::Invalid)?;
+        let room_id = matrix_sdk::ruma::OwnedRoomId::try_from(room_id)?;
+        self.client
+            .send(Request::new(alias_id, room_id), None)
+            .await
+            .map_err(|e| alias_error(e.into(), "add aliases"))?;
+        Ok(alias)
+    }
+
+    pub async fn remove_room_alias(&self, alias: &str) -> Result<()> {
+        use matrix_sdk::ruma::api::client::alias::delete_alias::v3::Request;
+
+        let alias = matrix_sdk::ruma::OwnedRoomAliasId::try_from(alias)?;
+        self.client
+            .send(Request::new(alias), None)
+            .await
+            .map_err(|e| alias_error(e.into(), "remove this alias"))?;
+        Ok(())
+    }
+
+    /// Make `alias` the one `room_id` is shown with. The previous main alias
+    /// stays listed as another.
+    pub async fn set_canonical_alias(&self, room_id: &str, alias: &str) -> Result<()> {
+        use matrix_sdk::ruma::events::room::canonical_alias::RoomCanonicalAliasEventContent;
+
+        let alias = matrix_sdk::ruma::OwnedRoomAliasId::try_from(alias)?;
+        let room_id = <&matrix_sdk::ruma::RoomId>::try_from(room_id)?;
+        let room = self.client.get_room(room_id).context("Room not found")?;
+        let mut content = RoomCanonicalAliasEventContent::new();
+        content.alt_aliases = room.alt_aliases();
+        content.alt_aliases.extend(room.canonical_alias());
+        content.alt_aliases.retain(|a| *a != alias);
+        content.alias = Some(alias);
+        room.send_state_event(content)
+            .await
+            .map_err(|e| alias_error(e, "change the main alias"))?;
+        Ok(())
+    }
+
+    /// The email addresses and phone numbers on the account.
+    pub async fn get_3pids(&self) -> Result<Vec<Threepid>> {
+        let response = self.client.account().get_3pids().await?;
+        Ok(response
+            .threepids
+            .iter()
+            .filter_map(Threepid::from_sdk)
+            .collect())
+    }
+
+    /// Have the homeserver mail a link to `email`, to prove it is ours
+    /// before it is added with `add_3pid`.
+    pub async fn request_email_token(&self, email: &str) -> Result<EmailValidation> {
+        use matrix_sdk::ruma::ClientSecret;
+
+        let validation = EmailValidation {
+            email: email.trim().to_string(),
+            client_secret: ClientSecret::new().to_string(),
+            sid: String::new(),
+            send_attempt: 1,
+        };
+        self.send_email_token(validation).await
+    }
+
+    /// Mail the link for `validation` again, e.g. when the first didn't
+    /// arrive.
+    pub async fn resend_email_token(
+        &self,
+        validation: &EmailValidation,
+    ) -> Result<EmailValidation> {
+        let validation = EmailValidation {
+            send_attempt: validation.send_attempt + 1,
+            ..validation.clone()
+        };
+        self.send_email_token(validation).await
+    }
+
+    async fn send_email_token(&self, mut validation: EmailValidation) -> Result<EmailValidation> {
+        use matrix_sdk::ruma::{ClientSecret, UInt};
+
+        let secret = <&ClientSecret>::try_from(validation.client_secret.as_str())?;
+        let response = self
+            .client
+            .account()
+            .request_3pid_email_token(
+                secret,
+                &validation.email,
+                UInt::from(validation.send_attempt),
+            )
+            .await
+            .map_err(threepid_error)?;
+        validation.sid = response.sid.to_string();
+        Ok(validation)
+    }
+
+    /// Add the address of `validation` to the account, once its link was
+    /// clicked. The server asks for `password` to confirm.
+    pub async fn add_3pid(&self, validation: &EmailValidation, password: &str) -> Result<()> {
+        use matrix_sdk::ruma::api::client::uiaa::{AuthData, Password, UserIdentifier};
+        use matrix_sdk::ruma::{ClientSecret, SessionId};
+
+        let secret = <&ClientSecret>::try_from(validation.client_secret.as_str())?;
+        let sid = <&SessionId>::try_from(validation.sid.as_str())?;
+        let account = self.client.account();
+        let Err(e) = account.add_3pid(secret, sid, None).await else {
+            return Ok(());
+        };
+        let Some(info) = e.as_uiaa_response() else {
+            return Err(threepid_error(e));
+        };
+        let user_id = self.client.user_id().context("Not logged in")?;
+        let mut auth = Password::new(
+            UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
+            password.to_string(),
+        );
+        auth.session = info.session.clone();
+        match account
+            .add_3pid(secret, sid, Some(AuthData::Password(auth)))
+            .await
+        {
+            Ok(_) => Ok(()),
+            // Asked again: the password was refused.
+            Err(e) if e.as_uiaa_response().is_some() => Err(ThreepidError::WrongPassword.into()),
+            Err(e) => Err(threepid_error(e)),
+        }
+    }
+
+    /// Take `email` off the account.
+    pub async fn remove_3pid(&self, email: &str) -> Result<()> {
+        use matrix_sdk::ruma::thirdparty::Medium;
+
+        self.client
+            .account()
+            .delete_3pid(email, Medium::Email, None)
+            .await?;
+        Ok(())
+    }
+
+    /// The pushers on the account, ours and other apps'.
+    pub async fn list_pushers(&self) -> Result<Vec<Pusher>> {
+        use matrix_sdk::ruma::api::client::push::get_pushers::v3::Request;
+
+        let response = self
+            .client
+            .send(Request::new(), None)
+            .await
+            .map_err(pusher_error)?;
+        Ok(response.pushers.iter().map(Pusher::from_sdk).collect())
+    }
+
+    async fn set_pusher(&self, pusher: &Pusher) -> Result<()> {
+        use matrix_sdk::ruma::api::client::push::set_pusher::v3::Request;
+
+        let pusher = pusher.to_sdk().context("Unknown pusher kind")?;
+        self.client
+            .send(Request::post(pusher), None)
+            .await
+            .map_err(pusher_error)?;
+        Ok(())
+    }
+
+    fn device_id(&self) -> Result<String> {
+        let device_id = self.client.device_id().context("Not logged in")?;
+        Ok(device_id.to_string())
+    }
+
+    /// Have our mentions mailed to `email`, which must be on the account.
+    pub async fn set_email_pusher(&self, email: &str) -> Result<()> {
+        self.set_pusher(&Pusher::email(email, &self.device_id()?))
+            .await
+    }
+
+    /// Have our notifications posted to the push gateway at `url`, for app
+    /// `app_id`. This device's id is the push key.
+    pub async fn add_http_pusher(
+        &self,
+        url: &str,
+        app_id: &str,
+        device_display_name: &str,
+    ) -> Result<()> {
+        let pusher = Pusher::gateway(url, app_id, &self.device_id()?, device_display_name);
+        self.set_pusher(&pusher).await
+    }
+
+    pub async fn remove_pusher(&self, app_id: &str, pushkey: &str) -> Result<()> {
+        use matrix_sdk::ruma::api::client::push::set_pusher::v3::Request;
+        use matrix_sdk::ruma::api::client::push::PusherIds;
+
+        let ids = PusherIds::new(pushkey.to_string(), app_id.to_string());
+        self.client
+            .send(Request::delete(ids), None)
+            .await
+            .map_err(pusher_error)?;
+        Ok(())
+    }
+
+    /// Bring our pushers in line with `config`: register those it asks for
+    /// that aren't in place, and remove ours it no longer asks for. Other
+    /// apps' are left alone. Run on every sign in, so it changes nothing
+    /// when nothing changed.
+    pub async fn register_pushers(&self, config: &PusherConfig) -> Result<()> {
+        let device_id = self.device_id()?;
+        let wanted = pusher::wanted(config, &device_id);
+        let existing = self.list_pushers().await?;
+        for old in &existing {
+            if pusher::is_ours(old, &device_id) && !wanted.iter().any(|p| p.same_as(old)) {
+                self.remove_pusher(&old.app_id, &old.pushkey).await?;
+            }
+        }
+        for pusher in &wanted {
+            if !existing.iter().any(|p| p.same_as(pusher)) {
+                self.set_pusher(pusher).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the pushers this device registered, so nothing is pushed for
+    /// a session that is gone.
+    async fn remove_own_pushers(&self) -> Result<()> {
+        let device_id = self.device_id()?;
+        for pusher in self.list_pushers().await? {
+            if pusher::is_ours(&pusher, &device_id) {
+                self.remove_pusher(&pusher.app_id, &pusher.pushkey).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn logout(&mut self) -> Result<()> {
+        self.stop_sync();
+        if let Some(task) = self.trust_watch.take() {
+            task.abort();
+        }
+        if let Some(user_id) = &self.user_id {
+            let _ = SessionManager::delete_session(user_id);
+        }
+        if let Err(e) = self.remove_own_pushers().await {
+            tracing::warn!("Failed to remove our pushers: {:#}", e);
+        }
+        let device_id = self.client.device_id().map(|d| d.to_string());
+        let _ = self.client.matrix_auth().logout().await;
+        // The device is gone, and its keys with it.
+        if let Some(dir) = device_id.and_then(|d| session::store_dir(&d).ok()) {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                tracing::warn!("Failed to remove {}: {}", dir.display(), e);
+            }
+        }
+        self.user_id = None;
+        self.display_name = None;
+        Ok(())
+    }
+}
+
+/// A page of `room`'s history going back from `from`, or from the latest
+/// message, with senders' names filled in.
+async fn fetch_page(
+    room: &matrix_sdk::Room,
+    from: Option<&str>,
+    limit: u32,
+) -> Result<MessagePage> {
+    let mut options = MessagesOptions::backward();
+    options.from = from.map(str::to_owned);
+    options.limit = limit.into();
+    let response = room.messages(options).await?;
+
+    let mut messages = rooms::page_messages(&response.chunk);
+    rooms::resolve_sender_names(room, &mut messages).await;
+
+    Ok(MessagePage {
+        messages,
+        prev_batch: response.end,
+    })
+}
+
+/// A client for `session` on `homeserver`, keeping its state and
+/// encryption keys in the device's store.
+async fn session_client(homeserver: &str, session: MatrixSession) -> Result<Client> {
+    let store = session::store_dir(session.meta.device_id.as_str())?;
+    let client = Client::builder()
+        .homeserver_url(homeserver)
+        .sqlite_store(&store, None)
+        .build()
+        .await?;
+    client.matrix_auth().restore_session(session).await?;
+    Ok(client)
+}
+
+/// The content `request` fetches, from the media cache when there. Encrypted
+/// media is never cached; `use_cache` is for the SDK's own store.
+async fn download_cached(
+    client: &Client,
+    request: matrix_sdk::media::MediaRequest,
+    key: &str,
+    use_cache: bool,
+) -> Result<Vec<u8>> {
+    let download = async {
+        Ok(client
+            .media()
+            .get_media_content(&request, use_cache)
+            .await?)
+    };
+    if !media::cacheable(&request) {
+        return download.await;
+    }
+    media_cache::shared()
+        .fetch(key, CacheCategory::Media, download)
+        .await
+}
+
+/// Map a failed registration onto the errors the register form handles.
+fn register_error(e: matrix_sdk::HttpError) -> anyhow::Error {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    match e.client_api_error_kind() {
+        Some(ErrorKind::UserInUse) => RegisterError::UsernameTaken.into(),
+        Some(ErrorKind::InvalidUsername | ErrorKind::Exclusive) => {
+            RegisterError::InvalidUsername.into()
+        }
+        Some(ErrorKind::WeakPassword) => RegisterError::WeakPassword.into(),
+        Some(ErrorKind::Forbidden) => RegisterError::Disabled.into(),
+        _ => anyhow::anyhow!("Registration failed: {}", e),
+    }
+}
+
+impl Drop for MatrixClient {
+    fn drop(&mut self) {
+        self.stop_sync();
+        if let Some(task) = self.trust_watch.take() {
+            task.abort();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,307 @@
+//! Link previews, asked of the homeserver's `preview_url` endpoint so the
+//! linked site only ever sees the server, never us. Previews are kept in
+//! memory and in `~/.gamechat/link_previews.json` for `CACHE_TTL_MS`, so
+//! scrolling back or starting again does not ask twice. A shorter
+//! `Retention` forgets them sooner; `SessionOnly` keeps them in memory only.
+
+use crate::config::Retention;
+use crate::media;
+use crate::session::app_dir;
+use anyhow::{Context, Result};
+use chat_core::link_preview::LinkPreview;
+use chat_core::{time, Attachment};
+use matrix_sdk::ruma::events::room::MediaSource;
+use matrix_sdk::ruma::{MilliSecondsSinceUnixEpoch, OwnedMxcUri, UInt};
+use matrix_sdk::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// How long a preview is used before the server is asked again: a week.
+pub const CACHE_TTL_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+/// Most previews kept; the oldest go first.
+pub const MAX_CACHED: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cached {
+    /// `None` when the page had nothing to show.
+    preview: Option<LinkPreview>,
+    /// When it was fetched, in ms since the epoch.
+    fetched_at: u64,
+}
+
+/// Previews by URL.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Previews {
+    entries: HashMap<String, Cached>,
+}
+
+impl Previews {
+    /// The preview of `url` if one was fetched in the last `CACHE_TTL_MS`;
+    /// `Some(None)` when the page had none.
+    fn get(&self, url: &str, now: u64) -> Option<Option<LinkPreview>> {
+        self.entries
+            .get(url)
+            .filter(|cached| now.saturating_sub(cached.fetched_at) < CACHE_TTL_MS)
+            .map(|cached| cached.preview.clone())
+    }
+
+    fn insert(&mut self, url: &str, preview: Option<LinkPreview>, now: u64) {
+        self.entries.insert(
+            url.to_string(),
+            Cached {
+                preview,
+                fetched_at: now,
+            },
+        );
+        self.entries
+            .retain(|_, cached| now.saturating_sub(cached.fetched_at) < CACHE_TTL_MS);
+        while self.entries.len() > MAX_CACHED {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.fetched_at)
+                .map(|(url, _)| url.clone())
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Forget previews fetched before `cutoff`. Returns whether any were.
+    fn forget_before(&mut self, cutoff: u64) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|_, cached| cached.fetched_at >= cutoff);
+        self.entries.len() != before
+    }
+}
+
+/// The previews of one client, read from disk when it is created and
+/// written back as new ones arrive.
+#[derive(Debug, Default)]
+pub struct PreviewCache {
+    previews: Mutex<Previews>,
+    /// Cleared under `Retention::SessionOnly`.
+    on_disk: AtomicBool,
+}
+
+impl PreviewCache {
+    fn path() -> Result<PathBuf> {
+        Ok(app_dir()?.join("link_previews.json"))
+    }
+
+    fn read() -> Result<Previews> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Previews::default());
+        }
+        let data = fs::read_to_string(&path).context("Failed to read link preview cache")?;
+        serde_json::from_str(&data).context("Failed to parse link preview cache")
+    }
+
+    fn write(previews: &Previews) -> Result<()> {
+        let data = serde_json::to_string(previews).context("Failed to encode link previews")?;
+        fs::write(Self::path()?, data).context("Failed to write link preview cache")
+    }
+
+    fn remove_file() -> Result<()> {
+        let path = Self::path()?;
+        if path.exists() {
+            fs::remove_file(path).context("Failed to delete link preview cache")?;
+        }
+        Ok(())
+    }
+
+    /// The cache on disk as far as `retention` keeps it, or an empty one if
+    /// unreadable.
+    pub fn load(retention: Retention) -> Self {
+        let cache = Self::default();
+        if retention.on_disk() {
+            let previews = Self::read().unwrap_or_else(|e| {
+                tracing::warn!("Starting with no link previews: {:#}", e);
+                Previews::default()
+            });
+            *cache.previews.lock().unwrap() = previews;
+        }
+        if let Err(e) = cache.prune(retention) {
+            tracing::warn!("{:#}", e);
+        }
+        cache
+    }
+
+    /// Forget what `retention` no longer keeps. Under `SessionOnly` the
+    /// file goes and previews are kept in memory only from then on.
+    pub fn prune(&self, retention: Retention) -> Result<()> {
+        self.on_disk.store(retention.on_disk(), Ordering::Relaxed);
+        if !retention.on_disk() {
+            return Self::remove_file();
+        }
+        let Some(cutoff) = retention.cutoff(time::now_ms()) else {
+            return Ok(());
+        };
+        let mut previews = self.previews.lock().unwrap();
+        if previews.forget_before(cutoff) {
+            Self::write(&previews)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes the previews take on disk.
+    pub fn size(&self) -> u64 {
+        Self::path()
+            .and_then(|path| Ok(fs::metadata(path)?.len()))
+            .unwrap_or(0)
+    }
+
+    /// Forget every preview, so they are asked for again.
+    pub fn clear(&self) -> Result<()> {
+        self.previews.lock().unwrap().entries.clear();
+        Self::remove_file()
+    }
+
+    pub fn get(&self, url: &str) -> Option<Option<LinkPreview>> {
+        self.previews.lock().unwrap().get(url, time::now_ms())
+    }
+
+    pub fn insert(&self, url: &str, preview: Option<LinkPreview>) {
+        let mut previews = self.previews.lock().unwrap();
+        previews.insert(url, preview, time::now_ms());
+        if !self.on_disk.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(e) = Self::write(&previews) {
+            tracing::warn!("{:#}", e);
+        }
+    }
+}
+
+/// A pixel size given as a number or a string, as OpenGraph allows both.
+fn pixels(value: Option<&Value>) -> Option<u32> {
+    match value? {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A preview from the OpenGraph properties the server returns, or `None`
+/// if there is neither a title nor a description. Images are only taken
+/// from the server's media repository, so showing them touches no other
+/// site.
+pub(crate) fn from_open_graph(url: &str, data: &Value) -> Option<LinkPreview> {
+    let text = |key: &str| {
+        data.get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+    let title = text("og:title");
+    let description = text("og:description");
+    if title.is_none() && description.is_none() {
+        return None;
+    }
+    let image = text("og:image")
+        .filter(|uri| uri.starts_with("mxc://"))
+        .map(|uri| Attachment {
+            source: media::encode(&MediaSource::Plain(OwnedMxcUri::from(uri))),
+            thumbnail_source: None,
+            name: String::new(),
+            mimetype: text("og:image:type"),
+            size: data.get("matrix:image:size").and_then(Value::as_u64),
+            width: pixels(data.get("og:image:width")),
+            height: pixels(data.get("og:image:height")),
+        });
+    Some(LinkPreview {
+        url: url.to_string(),
+        title,
+        description,
+        site_name: text("og:site_name"),
+        image,
+    })
+}
+
+/// Ask the homeserver for the preview of `url` as of `ts`, in ms.
+pub(crate) async fn fetch(client: &Client, url: &str, ts: u64) -> Result<Option<LinkPreview>> {
+    use matrix_sdk::ruma::api::client::media::get_media_preview::v3::Request;
+
+    let ts = UInt::new(ts)
+        .map(MilliSecondsSinceUnixEpoch)
+        .unwrap_or_else(MilliSecondsSinceUnixEpoch::now);
+    let request = Request::new(url.to_string(), ts);
+    let response = client.send(request, None).await?;
+    let Some(data) = response.data else {
+        return Ok(None);
+    };
+    let data: Value = serde_json::from_str(data.get()).context("Invalid link preview")?;
+    Ok(from_open_graph(url, &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_open_graph() {
+        let data = json!({
+            "og:title": " Rust ",
+            "og:description": "A language",
+            "og:site_name": "rust-lang.org",
+            "og:image": "mxc://example.org/logo",
+            "og:image:width": "1200",
+            "og:image:height": 630,
+            "matrix:image:size": 4096,
+        });
+        let preview = from_open_graph("https://rust-lang.org", &data).unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Rust"));
+        assert_eq!(preview.site_name.as_deref(), Some("rust-lang.org"));
+        let image = preview.image.unwrap();
+        assert_eq!((image.width, image.height), (Some(1200), Some(630)));
+        assert_eq!(image.size, Some(4096));
+
+        // An image elsewhere would have us fetch from that site.
+        let data = json!({"og:title": "Page", "og:image": "https://tracker.example/i.png"});
+        assert_eq!(from_open_graph("https://a.org", &data).unwrap().image, None);
+
+        assert_eq!(
+            from_open_graph("https://a.org", &json!({"og:title": " "})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cache_expires_and_keeps_the_newest() {
+        let mut previews = Previews::default();
+        previews.insert("https://a.org", None, 1_000);
+        assert_eq!(previews.get("https://a.org", 2_000), Some(None));
+        assert_eq!(previews.get("https://a.org", 1_000 + CACHE_TTL_MS), None);
+        assert_eq!(previews.get("https://b.org", 2_000), None);
+
+        for i in 0..=MAX_CACHED as u64 {
+            previews.insert(&format!("https://{}.org", i), None, 2_000 + i);
+        }
+        assert_eq!(previews.entries.len(), MAX_CACHED);
+        assert_eq!(previews.get("https://0.org", 3_000), None);
+        assert!(previews
+            .get(&format!("https://{}.org", MAX_CACHED), 3_000)
+            .is_some());
+    }
+
+    #[test]
+    fn test_forget_before_cutoff() {
+        let mut previews = Previews::default();
+        previews.insert("https://old.org", None, 1_000);
+        previews.insert("https://new.org", None, 5_000);
+        assert!(previews.forget_before(2_000));
+        assert!(!previews.forget_before(2_000));
+        assert_eq!(previews.get("https://old.org", 6_000), None);
+        assert_eq!(previews.get("https://new.org", 6_000), Some(None));
+    }
+}
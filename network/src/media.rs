@@ -0,0 +1,232 @@
+//! Images and files sent as messages. The SDK's media source, plain or
+//! encrypted, travels through the app as JSON in `Attachment::source`.
+
+use crate::profile::avatar_mime;
+use crate::stickers::Sticker;
+use anyhow::{Context, Result};
+use chat_core::Attachment;
+use matrix_sdk::media::{MediaFormat, MediaRequest, MediaThumbnailSize};
+use matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method;
+use matrix_sdk::ruma::events::room::message::MessageType;
+use matrix_sdk::ruma::events::room::MediaSource;
+use matrix_sdk::ruma::events::sticker::StickerEventContent;
+use matrix_sdk::ruma::{OwnedMxcUri, UInt};
+
+/// Largest inline image shown in the timeline, in pixels.
+pub const THUMBNAIL_WIDTH: u32 = 400;
+pub const THUMBNAIL_HEIGHT: u32 = 300;
+
+/// A file to send as a message.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub name: String,
+    pub mimetype: String,
+    pub data: Vec<u8>,
+}
+
+/// The mimetype a file named `name` is sent with: images by their
+/// extension, anything else as opaque bytes.
+pub fn mimetype(name: &str) -> &'static str {
+    avatar_mime(name).unwrap_or("application/octet-stream")
+}
+
+impl Upload {
+    /// `data` named `name`; images are sent as image messages, anything else
+    /// as a file.
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            mimetype: mimetype(name).to_string(),
+            data,
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.mimetype.starts_with("image/")
+    }
+}
+
+/// How much of an upload has been sent, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UploadProgress {
+    pub sent: u64,
+    pub total: u64,
+}
+
+pub(crate) fn encode(source: &MediaSource) -> String {
+    serde_json::to_string(source).unwrap_or_default()
+}
+
+fn decode(source: &str) -> Result<MediaSource> {
+    serde_json::from_str(source).context("Invalid media source")
+}
+
+fn pixels(value: Option<UInt>) -> Option<u32> {
+    u32::try_from(u64::from(value?)).ok()
+}
+
+/// The attachment of an image, file or audio message.
+pub(crate) fn attachment(msgtype: &MessageType) -> Option<Attachment> {
+    match msgtype {
+        MessageType::Image(image) => {
+            let info = image.info.as_deref();
+            Some(Attachment {
+                source: encode(&image.source),
+                thumbnail_source: info.and_then(|i| i.thumbnail_source.as_ref()).map(encode),
+                name: image.body.clone(),
+                mimetype: info.and_then(|i| i.mimetype.clone()),
+                size: info.and_then(|i| i.size).map(u64::from),
+                width: pixels(info.and_then(|i| i.width)),
+                height: pixels(info.and_then(|i| i.height)),
+            })
+        }
+        MessageType::File(file) => {
+            let info = file.info.as_deref();
+            Some(Attachment {
+                source: encode(&file.source),
+                thumbnail_source: info.and_then(|i| i.thumbnail_source.as_ref()).map(encode),
+                name: file.filename.clone().unwrap_or_else(|| file.body.clone()),
+                mimetype: info.and_then(|i| i.mimetype.clone()),
+                size: info.and_then(|i| i.size).map(u64::from),
+                width: None,
+                height: None,
+            })
+        }
+        MessageType::Audio(audio) => {
+            let info = audio.info.as_deref();
+            Some(Attachment {
+                source: encode(&audio.source),
+                thumbnail_source: None,
+                name: audio.body.clone(),
+                mimetype: info.and_then(|i| i.mimetype.clone()),
+                size: info.and_then(|i| i.size).map(u64::from),
+                width: None,
+                height: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The image of a sticker event, as an image message's attachment.
+pub(crate) fn sticker_event(content: &StickerEventContent) -> Attachment {
+    let info = &content.info;
+    Attachment {
+        source: encode(&MediaSource::Plain(content.url.clone())),
+        thumbnail_source: info.thumbnail_source.as_ref().map(encode),
+        name: content.body.clone(),
+        mimetype: info.mimetype.clone(),
+        size: info.size.map(u64::from),
+        width: pixels(info.width),
+        height: pixels(info.height),
+    }
+}
+
+/// A sticker of a pack as an attachment, to show it in the picker.
+pub fn sticker_attachment(sticker: &Sticker) -> Attachment {
+    Attachment {
+        source: encode(&MediaSource::Plain(OwnedMxcUri::from(sticker.url.as_str()))),
+        thumbnail_source: None,
+        name: sticker.body.clone(),
+        mimetype: sticker.mimetype.clone(),
+        size: sticker.size,
+        width: sticker.width,
+        height: sticker.height,
+    }
+}
+
+/// The whole content of `source`.
+pub(crate) fn content_request(source: &str) -> Result<MediaRequest> {
+    Ok(MediaRequest {
+        source: decode(source)?,
+        format: MediaFormat::File,
+    })
+}
+
+/// Whether what `request` fetches may be cached on disk: not encrypted
+/// media, which would be kept there decrypted.
+pub(crate) fn cacheable(request: &MediaRequest) -> bool {
+    matches!(request.source, MediaSource::Plain(_))
+}
+
+/// What to fetch to show `attachment` inline: the sender's thumbnail, else
+/// one the server scales down, else the content itself. The server can't
+/// scale encrypted media, and GIFs and APNGs are fetched whole so they can
+/// be animated.
+pub(crate) fn thumbnail_request(attachment: &Attachment) -> Result<MediaRequest> {
+    if attachment.is_animated() {
+        return content_request(&attachment.source);
+    }
+    if let Some(thumbnail) = &attachment.thumbnail_source {
+        return content_request(thumbnail);
+    }
+    let source = decode(&attachment.source)?;
+    let format = match source {
+        MediaSource::Plain(_) => MediaFormat::Thumbnail(MediaThumbnailSize {
+            method: Method::Scale,
+            width: THUMBNAIL_WIDTH.into(),
+            height: THUMBNAIL_HEIGHT.into(),
+        }),
+        MediaSource::Encrypted(_) => MediaFormat::File,
+    };
+    Ok(MediaRequest { source, format })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(mimetype: &str, thumbnail: bool) -> Attachment {
+        let source = |id: &str| {
+            encode(&MediaSource::Plain(OwnedMxcUri::from(format!(
+                "mxc://example.org/{}",
+                id
+            ))))
+        };
+        Attachment {
+            source: source("full"),
+            thumbnail_source: thumbnail.then(|| source("thumb")),
+            name: "cat".to_string(),
+            mimetype: Some(mimetype.to_string()),
+            size: None,
+            width: None,
+            height: None,
+        }
+    }
+
+    fn fetched(request: &MediaRequest) -> (String, bool) {
+        let MediaSource::Plain(uri) = &request.source else {
+            panic!("expected a plain source");
+        };
+        (
+            uri.to_string(),
+            matches!(request.format, MediaFormat::Thumbnail(_)),
+        )
+    }
+
+    #[test]
+    fn test_uploads_are_typed_by_extension() {
+        let image = Upload::new("screenshot.PNG", Vec::new());
+        assert_eq!(image.mimetype, "image/png");
+        assert!(image.is_image());
+        let file = Upload::new("notes.txt", Vec::new());
+        assert_eq!(file.mimetype, "application/octet-stream");
+        assert!(!file.is_image());
+    }
+
+    #[test]
+    fn test_thumbnail_requests() {
+        let request = thumbnail_request(&image("image/png", true)).unwrap();
+        assert_eq!(fetched(&request), ("mxc://example.org/thumb".into(), false));
+
+        let request = thumbnail_request(&image("image/png", false)).unwrap();
+        assert_eq!(fetched(&request), ("mxc://example.org/full".into(), true));
+
+        let request = thumbnail_request(&image("image/gif", true)).unwrap();
+        assert_eq!(fetched(&request), ("mxc://example.org/full".into(), false));
+        let request = thumbnail_request(&image("image/apng", true)).unwrap();
+        assert_eq!(fetched(&request), ("mxc://example.org/full".into(), false));
+
+        assert!(content_request("not json").is_err());
+    }
+}
@@ -0,0 +1,686 @@
+//! Media kept on disk so it is not downloaded again: attachments and
+//! avatars under `~/.gamechat/cache`, up to a size cap. An index file keeps
+//! each file's size and when it was last used; past the cap the least
+//! recently used go first. Files on screen are pinned and never evicted,
+//! and files still downloading are marked in the index so nothing deletes
+//! them mid-way. Files unused for longer than the `Retention` setting are
+//! pruned; under `SessionOnly` nothing is cached at all.
+
+use crate::config::{ConfigManager, Retention};
+use crate::session::app_dir;
+use anyhow::{Context, Result};
+use chat_core::{hash, time, Attachment};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Size cap when the config doesn't say, in megabytes.
+pub const DEFAULT_MAX_MB: u64 = 500;
+/// A download marked longer ago than this was cut off, e.g. by a crash,
+/// and what it wrote is thrown away: ten minutes.
+const STALE_DOWNLOAD_MS: u64 = 10 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CacheCategory {
+    /// Images and files from messages.
+    Media,
+    Avatars,
+    /// What the timeline keeps besides media, i.e. link previews.
+    Timeline,
+}
+
+impl CacheCategory {
+    fn dir_name(self) -> &'static str {
+        match self {
+            CacheCategory::Media => "media",
+            CacheCategory::Avatars => "avatars",
+            CacheCategory::Timeline => "timeline",
+        }
+    }
+}
+
+/// Bytes cached, by category.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheUsage {
+    pub media: u64,
+    pub avatars: u64,
+    pub timeline: u64,
+}
+
+impl CacheUsage {
+    pub fn total(&self) -> u64 {
+        self.media + self.avatars + self.timeline
+    }
+
+    fn add(&mut self, category: CacheCategory, bytes: u64) {
+        match category {
+            CacheCategory::Media => self.media += bytes,
+            CacheCategory::Avatars => self.avatars += bytes,
+            CacheCategory::Timeline => self.timeline += bytes,
+        }
+    }
+}
+
+/// The cache key of attachment content from `source`.
+pub fn media_key(source: &str) -> String {
+    source.to_string()
+}
+
+/// The cache key of what is shown inline for `attachment`.
+pub fn thumbnail_key(attachment: &Attachment) -> String {
+    format!("{}#thumbnail", attachment.source)
+}
+
+/// The cache key of the avatar thumbnail at `url`.
+pub fn avatar_key(url: &str) -> String {
+    format!("{}#avatar", url)
+}
+
+/// The file a key is kept in. Keys are hashed so the index doesn't list
+/// what was viewed.
+fn file_name(key: &str) -> String {
+    format!("{:016x}", hash::fnv1a(key))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    category: CacheCategory,
+    size: u64,
+    /// When it was last read or written, in ms since the epoch.
+    accessed: u64,
+    /// When its download started, while it is downloading.
+    #[serde(default)]
+    downloading: Option<u64>,
+}
+
+/// Entries by file name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Index {
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    index: Index,
+    /// How many times each file is on screen.
+    pinned: HashMap<String, usize>,
+    /// The latest access time handed out, so two in the same millisecond
+    /// still come in order.
+    clock: u64,
+    retention: Retention,
+}
+
+impl State {
+    fn tick(&mut self) -> u64 {
+        self.clock = time::now_ms().max(self.clock + 1);
+        self.clock
+    }
+}
+
+pub struct MediaCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+/// The cache shared by every account on this machine, capped as the config
+/// says.
+pub fn shared() -> &'static MediaCache {
+    static CACHE: OnceLock<MediaCache> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        let dir = match app_dir() {
+            Ok(dir) => dir.join("cache"),
+            Err(e) => {
+                tracing::warn!("Caching media in the temp directory: {:#}", e);
+                std::env::temp_dir().join("gamechat-cache")
+            }
+        };
+        let config = ConfigManager::load().cache;
+        let cache = MediaCache::open(dir, config.max_bytes());
+        if let Err(e) = cache.prune(config.retention) {
+            tracing::warn!("{:#}", e);
+        }
+        cache
+    })
+}
+
+impl MediaCache {
+    /// The cache in `dir`, holding at most `max_bytes`. Downloads cut off
+    /// before they finished are thrown away, as are entries whose files are
+    /// gone.
+    pub fn open(dir: PathBuf, max_bytes: u64) -> Self {
+        let cache = Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(State::default()),
+        };
+        let mut index = cache.read_index().unwrap_or_else(|e| {
+            tracing::warn!("Starting with an empty media cache: {:#}", e);
+            Index::default()
+        });
+        let now = time::now_ms();
+        index.entries.retain(|name, entry| {
+            let kept = match entry.downloading {
+                Some(started) => now.saturating_sub(started) < STALE_DOWNLOAD_MS,
+                None => cache.path(entry.category, name).exists(),
+            };
+            if !kept {
+                fs::remove_file(cache.path(entry.category, name)).ok();
+            }
+            kept
+        });
+        {
+            let mut state = cache.state.lock().unwrap();
+            state.clock = index
+                .entries
+                .values()
+                .map(|e| e.accessed)
+                .max()
+                .unwrap_or(0);
+            state.index = index;
+            cache.evict(&mut state, None);
+            cache.save(&state.index);
+        }
+        cache
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+
+    fn path(&self, category: CacheCategory, name: &str) -> PathBuf {
+        self.dir.join(category.dir_name()).join(name)
+    }
+
+    fn read_index(&self) -> Result<Index> {
+        let path = self.index_path();
+        if !path.exists() {
+            return Ok(Index::default());
+        }
+        let data = fs::read_to_string(&path).context("Failed to read the media cache index")?;
+        serde_json::from_str(&data).context("Failed to parse the media cache index")
+    }
+
+    fn save(&self, index: &Index) {
+        let saved = serde_json::to_string(index)
+            .context("Failed to encode the media cache index")
+            .and_then(|data| {
+                fs::create_dir_all(&self.dir).context("Failed to create the cache directory")?;
+                fs::write(self.index_path(), data).context("Failed to write the media cache index")
+            });
+        if let Err(e) = saved {
+            tracing::warn!("{:#}", e);
+        }
+    }
+
+    /// The cached content of `key`, which then counts as just used.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let name = file_name(key);
+        let mut state = self.state.lock().unwrap();
+        if !state.retention.on_disk() {
+            return None;
+        }
+        let entry = state.index.entries.get(&name)?;
+        if entry.downloading.is_some() {
+            return None;
+        }
+        let category = entry.category;
+        match fs::read(self.path(category, &name)) {
+            Ok(data) => {
+                let now = state.tick();
+                if let Some(entry) = state.index.entries.get_mut(&name) {
+                    entry.accessed = now;
+                }
+                self.save(&state.index);
+                Some(data)
+            }
+            Err(_) => {
+                state.index.entries.remove(&name);
+                self.save(&state.index);
+                None
+            }
+        }
+    }
+
+    /// Mark `key` as downloading, so it is kept until the download finishes
+    /// or is dropped.
+    pub fn begin(&self, key: &str, category: CacheCategory) -> Download<'_> {
+        let name = file_name(key);
+        let mut state = self.state.lock().unwrap();
+        let now = state.tick();
+        state.index.entries.insert(
+            name.clone(),
+            Entry {
+                category,
+                size: 0,
+                accessed: now,
+                downloading: Some(time::now_ms()),
+            },
+        );
+        self.save(&state.index);
+        Download {
+            cache: self,
+            name,
+            category,
+            finished: false,
+        }
+    }
+
+    /// The cached content of `key`, else `download`ed and cached.
+    pub async fn fetch(
+        &self,
+        key: &str,
+        category: CacheCategory,
+        download: impl Future<Output = Result<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
+        if !self.state.lock().unwrap().retention.on_disk() {
+            return download.await;
+        }
+        if let Some(data) = self.get(key) {
+            return Ok(data);
+        }
+        let marker = self.begin(key, category);
+        let data = download.await?;
+        if let Err(e) = marker.finish(&data) {
+            tracing::warn!("Failed to cache media: {:#}", e);
+        }
+        Ok(data)
+    }
+
+    /// Keep `key` from being evicted while the returned guard lives, e.g.
+    /// while it is on screen.
+    pub fn pin(&self, key: &str) -> Pinned<'_> {
+        let name = file_name(key);
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .pinned
+            .entry(name.clone())
+            .or_default() += 1;
+        Pinned { cache: self, name }
+    }
+
+    /// Bytes cached, by category. Downloads count once they finish.
+    pub fn usage(&self) -> CacheUsage {
+        let state = self.state.lock().unwrap();
+        let mut usage = CacheUsage::default();
+        for entry in state.index.entries.values() {
+            usage.add(entry.category, entry.size);
+        }
+        usage
+    }
+
+    /// Delete everything cached of `category` but what is still
+    /// downloading. What is on screen stays there, as it is in memory.
+    pub fn clear(&self, category: CacheCategory) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let cleared: Vec<String> = state
+            .index
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.category == category && entry.downloading.is_none())
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut result = Ok(());
+        for name in cleared {
+            match fs::remove_file(self.path(category, &name)) {
+                Ok(()) => {
+                    state.index.entries.remove(&name);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    state.index.entries.remove(&name);
+                }
+                Err(e) => result = Err(e).context("Failed to delete a cached file"),
+            }
+        }
+        self.save(&state.index);
+        result
+    }
+
+    /// Keep files only as long as `retention` says, from now on too: delete
+    /// those last used before its cutoff, or all of them under
+    /// `SessionOnly`. Files pinned or downloading are kept.
+    pub fn prune(&self, retention: Retention) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.retention = retention;
+        let Some(cutoff) = retention.cutoff(time::now_ms()) else {
+            return Ok(());
+        };
+        let pruned: Vec<String> = state
+            .index
+            .entries
+            .iter()
+            .filter(|(name, entry)| {
+                entry.downloading.is_none()
+                    && !state.pinned.contains_key(*name)
+                    && (!retention.on_disk() || entry.accessed < cutoff)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        if pruned.is_empty() {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for name in pruned {
+            let category = state.index.entries[&name].category;
+            match fs::remove_file(self.path(category, &name)) {
+                Ok(()) => {
+                    state.index.entries.remove(&name);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    state.index.entries.remove(&name);
+                }
+                Err(e) => result = Err(e).context("Failed to delete a cached file"),
+            }
+        }
+        self.save(&state.index);
+        result
+    }
+
+    /// Delete the least recently used files until the cache fits its cap.
+    /// Files pinned or downloading are skipped, as is `fresh`, just written
+    /// to be shown.
+    fn evict(&self, state: &mut State, fresh: Option<&str>) {
+        let mut total: u64 = state.index.entries.values().map(|e| e.size).sum();
+        if total <= self.max_bytes {
+            return;
+        }
+        let mut candidates: Vec<(u64, String)> = state
+            .index
+            .entries
+            .iter()
+            .filter(|(name, entry)| {
+                entry.downloading.is_none()
+                    && !state.pinned.contains_key(*name)
+                    && fresh != Some(name.as_str())
+            })
+            .map(|(name, entry)| (entry.accessed, name.clone()))
+            .collect();
+        candidates.sort();
+        for (_, name) in candidates {
+            if total <= self.max_bytes {
+                break;
+            }
+            let Some(entry) = state.index.entries.remove(&name) else {
+                continue;
+            };
+            fs::remove_file(self.path(entry.category, &name)).ok();
+            total -= entry.size;
+        }
+    }
+}
+
+/// A download in progress, marked in the index until it is finished or
+/// dropped.
+pub struct Download<'a> {
+    cache: &'a MediaCache,
+    name: String,
+    category: CacheCategory,
+    finished: bool,
+}
+
+impl Download<'_> {
+    /// Write the downloaded `data` to the cache, evicting older files if it
+    /// no longer fits.
+    pub fn finish(mut self, data: &[u8]) -> Result<()> {
+        let path = self.cache.path(self.category, &self.name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context("Failed to create the cache directory")?;
+        }
+        fs::write(&path, data).context("Failed to write a cached file")?;
+
+        let mut state = self.cache.state.lock().unwrap();
+        let now = state.tick();
+        state.index.entries.insert(
+            self.name.clone(),
+            Entry {
+                category: self.category,
+                size: data.len() as u64,
+                accessed: now,
+                downloading: None,
+            },
+        );
+        self.finished = true;
+        self.cache.evict(&mut state, Some(&self.name));
+        self.cache.save(&state.index);
+        Ok(())
+    }
+}
+
+impl Drop for Download<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let mut state = self.cache.state.lock().unwrap();
+        if state
+            .index
+            .entries
+            .get(&self.name)
+            .is_some_and(|entry| entry.downloading.is_some())
+        {
+            state.index.entries.remove(&self.name);
+            fs::remove_file(self.cache.path(self.category, &self.name)).ok();
+            self.cache.save(&state.index);
+        }
+    }
+}
+
+/// Keeps a file from being evicted while it lives.
+pub struct Pinned<'a> {
+    cache: &'a MediaCache,
+    name: String,
+}
+
+impl Drop for Pinned<'_> {
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock().unwrap();
+        if let Some(count) = state.pinned.get_mut(&self.name) {
+            *count -= 1;
+            if *count == 0 {
+                state.pinned.remove(&self.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "media-cache-{}-{}-{}",
+            std::process::id(),
+            time::now_ms(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
+
+    fn store(cache: &MediaCache, key: &str, category: CacheCategory, size: usize) {
+        cache.begin(key, category).finish(&vec![0; size]).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_and_usage() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 1000);
+        assert_eq!(cache.get("mxc://x/a"), None);
+        store(&cache, "mxc://x/a", CacheCategory::Media, 10);
+        store(&cache, "mxc://x/b#avatar", CacheCategory::Avatars, 3);
+
+        assert_eq!(cache.get("mxc://x/a"), Some(vec![0; 10]));
+        let usage = cache.usage();
+        assert_eq!((usage.media, usage.avatars, usage.total()), (10, 3, 13));
+
+        // The index outlives the process.
+        drop(cache);
+        let cache = MediaCache::open(dir.clone(), 1000);
+        assert_eq!(cache.usage().total(), 13);
+        assert_eq!(cache.get("mxc://x/b#avatar"), Some(vec![0; 3]));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_least_recently_used_are_evicted_first() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 10);
+        store(&cache, "a", CacheCategory::Media, 4);
+        store(&cache, "b", CacheCategory::Media, 4);
+        // Reading `a` makes `b` the oldest.
+        cache.get("a").unwrap();
+        store(&cache, "c", CacheCategory::Media, 4);
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.usage().total(), 8);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_pinned_and_downloading_files_are_kept() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 10);
+        store(&cache, "shown", CacheCategory::Media, 6);
+        let pinned = cache.pin("shown");
+        let downloading = cache.begin("coming", CacheCategory::Media);
+        store(&cache, "new", CacheCategory::Media, 6);
+
+        // Over the cap, but nothing else could go.
+        assert!(cache.get("shown").is_some());
+        assert!(cache.get("new").is_some());
+        cache.clear(CacheCategory::Media).unwrap();
+        assert!(cache.get("shown").is_none());
+        downloading.finish(&[1, 2, 3]).unwrap();
+        assert_eq!(cache.get("coming"), Some(vec![1, 2, 3]));
+
+        drop(pinned);
+        store(&cache, "big", CacheCategory::Media, 9);
+        assert!(cache.get("coming").is_none());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_clear_keeps_other_categories_and_downloads() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 1000);
+        store(&cache, "image", CacheCategory::Media, 5);
+        store(&cache, "face", CacheCategory::Avatars, 5);
+        let downloading = cache.begin("half", CacheCategory::Media);
+        fs::write(cache.path(CacheCategory::Media, &file_name("half")), [0; 2]).unwrap();
+
+        cache.clear(CacheCategory::Media).unwrap();
+        assert!(cache.get("image").is_none());
+        assert!(cache.get("face").is_some());
+        assert!(cache
+            .path(CacheCategory::Media, &file_name("half"))
+            .exists());
+        drop(downloading);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_dropped_and_stale_downloads_are_thrown_away() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 1000);
+        drop(cache.begin("cancelled", CacheCategory::Media));
+        assert!(cache.state.lock().unwrap().index.entries.is_empty());
+
+        // As left by a crash an hour ago.
+        let marker = cache.begin("crashed", CacheCategory::Media);
+        cache
+            .state
+            .lock()
+            .unwrap()
+            .index
+            .entries
+            .values_mut()
+            .for_each(|entry| entry.downloading = Some(time::now_ms() - 60 * 60 * 1000));
+        cache.save(&cache.state.lock().unwrap().index);
+        std::mem::forget(marker);
+
+        let cache = MediaCache::open(dir.clone(), 1000);
+        assert!(cache.state.lock().unwrap().index.entries.is_empty());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_prune_by_age_and_session_only() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 1000);
+        store(&cache, "old", CacheCategory::Media, 4);
+        store(&cache, "old face", CacheCategory::Avatars, 4);
+        store(&cache, "new", CacheCategory::Media, 4);
+        let week_ago = time::now_ms() - 8 * 24 * 60 * 60 * 1000;
+        for name in [file_name("old"), file_name("old face")] {
+            cache
+                .state
+                .lock()
+                .unwrap()
+                .index
+                .entries
+                .get_mut(&name)
+                .unwrap()
+                .accessed = week_ago;
+        }
+        let _pinned = cache.pin("old face");
+
+        cache.prune(Retention::Forever).unwrap();
+        assert_eq!(cache.usage().total(), 12);
+        cache.prune(Retention::Days7).unwrap();
+        assert!(cache.get("old").is_none());
+        assert!(cache.get("old face").is_some());
+        assert!(cache.get("new").is_some());
+
+        cache.prune(Retention::SessionOnly).unwrap();
+        assert_eq!(cache.usage().media, 0);
+        // Nothing new is written, but downloads still work.
+        let data = cache
+            .fetch("mxc://x/a", CacheCategory::Media, async { Ok(vec![1]) })
+            .await
+            .unwrap();
+        assert_eq!(data, vec![1]);
+        assert_eq!(cache.usage().media, 0);
+        assert!(cache.get("old face").is_none());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_downloads_once() {
+        let dir = temp_dir();
+        let cache = MediaCache::open(dir.clone(), 1000);
+        let downloads = AtomicUsize::new(0);
+        for _ in 0..2 {
+            let data = cache
+                .fetch("mxc://x/a", CacheCategory::Media, async {
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                    Ok(vec![7; 4])
+                })
+                .await
+                .unwrap();
+            assert_eq!(data, vec![7; 4]);
+        }
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+        let failed = cache
+            .fetch("mxc://x/b", CacheCategory::Media, async {
+                Err(anyhow::anyhow!("offline"))
+            })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(cache.state.lock().unwrap().index.entries.len(), 1);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,198 @@
+//! Joined members of a room for the member list, with their role from the
+//! power levels and their presence.
+
+use chat_core::{Member, MemberRole, UserStatus};
+use matrix_sdk::room::RoomMember;
+use matrix_sdk::ruma::presence::PresenceState;
+use matrix_sdk::{Room, RoomMemberships};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Joined members of a room, as far as they are loaded.
+#[derive(Debug, Clone, Default)]
+pub struct MemberList {
+    pub members: Vec<Member>,
+    /// Joined members in the room, counting any not loaded yet.
+    pub total: u64,
+}
+
+/// Between do not disturb's label and a status message after it.
+const DND_SEPARATOR: &str = " · ";
+
+/// The presence status message for `status` and our own `message`. Do not
+/// disturb travels as unavailable with its label as the status message,
+/// followed by `message` if there is one.
+pub fn status_msg(status: UserStatus, message: Option<&str>) -> Option<String> {
+    match (status, message) {
+        (UserStatus::DoNotDisturb, Some(message)) => {
+            Some(format!("{}{}{}", status.label(), DND_SEPARATOR, message))
+        }
+        (UserStatus::DoNotDisturb, None) => Some(status.label().to_string()),
+        (_, message) => message.map(str::to_owned),
+    }
+}
+
+/// `status_msg` without the do not disturb label, if it has one.
+fn without_dnd(status_msg: &str) -> Option<&str> {
+    let rest = status_msg.strip_prefix(UserStatus::DoNotDisturb.label())?;
+    if rest.is_empty() {
+        Some(rest)
+    } else {
+        rest.strip_prefix(DND_SEPARATOR)
+    }
+}
+
+/// The status a presence update shows; see `status_msg`.
+pub fn status_from_presence(presence: &PresenceState, status_msg: Option<&str>) -> UserStatus {
+    match presence {
+        PresenceState::Online => UserStatus::Online,
+        PresenceState::Unavailable if status_msg.and_then(without_dnd).is_some() => {
+            UserStatus::DoNotDisturb
+        }
+        PresenceState::Unavailable => UserStatus::Idle,
+        _ => UserStatus::Offline,
+    }
+}
+
+/// The free-text message of a presence update, if any; see `status_msg`.
+pub fn message_from_presence(presence: &PresenceState, status_msg: Option<&str>) -> Option<String> {
+    let status_msg = status_msg?;
+    let message = match presence {
+        PresenceState::Unavailable => without_dnd(status_msg).unwrap_or(status_msg),
+        _ => status_msg,
+    };
+    let message = message.trim();
+    (!message.is_empty()).then(|| message.to_string())
+}
+
+/// The presence each user was last seen with in sync. The SDK doesn't
+/// hand out what it stores of presence, so the sync handler keeps it here.
+#[derive(Debug, Default)]
+pub(crate) struct Presences {
+    by_user: HashMap<String, (UserStatus, Option<String>)>,
+}
+
+impl Presences {
+    pub(crate) fn update(
+        &mut self,
+        user_id: &str,
+        presence: &PresenceState,
+        status_msg: Option<&str>,
+    ) {
+        let status = status_from_presence(presence, status_msg);
+        let message = message_from_presence(presence, status_msg);
+        self.by_user.insert(user_id.to_string(), (status, message));
+    }
+
+    /// `user_id`'s status and message; offline for anyone not seen yet.
+    pub(crate) fn get(&self, user_id: &str) -> (UserStatus, Option<String>) {
+        self.by_user
+            .get(user_id)
+            .cloned()
+            .unwrap_or((UserStatus::Offline, None))
+    }
+}
+
+pub(crate) fn member_to_core(member: &RoomMember, presences: &Presences) -> Member {
+    let (status, status_message) = presences.get(member.user_id().as_str());
+    Member {
+        user_id: member.user_id().to_string(),
+        display_name: member.display_name().map(str::to_owned),
+        avatar_url: member.avatar_url().map(|u| u.to_string()),
+        role: MemberRole::from_power_level(member.power_level()),
+        status,
+        status_message,
+    }
+}
+
+/// The room's joined members. Without `full` only those already in the
+/// store are listed, which for large rooms with lazy loading is a subset;
+/// `full` fetches the whole list from the server first.
+pub(crate) async fn joined_members(
+    room: &Room,
+    full: bool,
+    presences: &Mutex<Presences>,
+) -> matrix_sdk::Result<MemberList> {
+    let members = if full {
+        room.members(RoomMemberships::JOIN).await?
+    } else {
+        room.members_no_sync(RoomMemberships::JOIN).await?
+    };
+    let presences = presences.lock().unwrap();
+    Ok(MemberList {
+        members: members
+            .iter()
+            .map(|member| member_to_core(member, &presences))
+            .collect(),
+        total: room.joined_members_count(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_do_not_disturb_round_trips_through_presence() {
+        let dnd = UserStatus::DoNotDisturb.label();
+        assert_eq!(
+            status_from_presence(&PresenceState::Unavailable, Some(dnd)),
+            UserStatus::DoNotDisturb
+        );
+        assert_eq!(
+            status_from_presence(&PresenceState::Unavailable, Some("brb")),
+            UserStatus::Idle
+        );
+        assert_eq!(
+            status_from_presence(&PresenceState::Online, None),
+            UserStatus::Online
+        );
+        assert_eq!(
+            status_from_presence(&PresenceState::Offline, None),
+            UserStatus::Offline
+        );
+    }
+
+    #[test]
+    fn test_presences_keep_the_latest() {
+        let mut presences = Presences::default();
+        assert_eq!(presences.get("@a:x"), (UserStatus::Offline, None));
+        presences.update("@a:x", &PresenceState::Online, Some("raiding"));
+        presences.update("@a:x", &PresenceState::Unavailable, None);
+        assert_eq!(presences.get("@a:x"), (UserStatus::Idle, None));
+        assert_eq!(presences.get("@b:x"), (UserStatus::Offline, None));
+    }
+
+    #[test]
+    fn test_status_message_travels_with_do_not_disturb() {
+        let playing = Some("Playing Valorant");
+        let sent = status_msg(UserStatus::DoNotDisturb, playing);
+        assert_eq!(sent.as_deref(), Some("Do Not Disturb · Playing Valorant"));
+        assert_eq!(
+            status_from_presence(&PresenceState::Unavailable, sent.as_deref()),
+            UserStatus::DoNotDisturb
+        );
+        assert_eq!(
+            message_from_presence(&PresenceState::Unavailable, sent.as_deref()).as_deref(),
+            playing
+        );
+
+        let dnd = status_msg(UserStatus::DoNotDisturb, None);
+        assert_eq!(
+            message_from_presence(&PresenceState::Unavailable, dnd.as_deref()),
+            None
+        );
+        assert_eq!(
+            status_from_presence(&PresenceState::Unavailable, Some("Do Not Disturbing")),
+            UserStatus::Idle
+        );
+
+        let online = status_msg(UserStatus::Online, playing);
+        assert_eq!(online.as_deref(), playing);
+        assert_eq!(
+            message_from_presence(&PresenceState::Online, online.as_deref()).as_deref(),
+            playing
+        );
+        assert_eq!(status_msg(UserStatus::Idle, None), None);
+    }
+}
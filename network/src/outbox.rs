@@ -0,0 +1,146 @@
+//! Messages not yet delivered, per account, in `~/.gamechat/outbox.json`,
+//! so that they outlive a crash. The file says which version of its format
+//! it is in: older ones are brought up to date as they are read, and one
+//! written by a newer build is left alone rather than overwritten.
+
+use crate::session::app_dir;
+use anyhow::{bail, Context, Result};
+use chat_core::outbox::Unsent;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// The version of the format written. Version 2 added scheduled messages,
+/// which a build reading version 1 would send at once.
+pub const FORMAT_VERSION: u64 = 2;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct OutboxFile {
+    version: u64,
+    /// By user id.
+    accounts: BTreeMap<String, Vec<Unsent>>,
+}
+
+/// Read a file in any format up to [`FORMAT_VERSION`].
+fn parse(data: &str) -> Result<OutboxFile> {
+    let value: Value = serde_json::from_str(data)?;
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .context("No format version")?;
+    match version {
+        // Each later version gets an arm turning the one before into it.
+        FORMAT_VERSION => Ok(serde_json::from_value(value)?),
+        // Nothing was scheduled yet.
+        1 => Ok(OutboxFile {
+            version: FORMAT_VERSION,
+            ..serde_json::from_value(value)?
+        }),
+        newer if newer > FORMAT_VERSION => {
+            bail!("Written by a newer version (format {})", newer)
+        }
+        older => bail!("Unknown format {}", older),
+    }
+}
+
+pub struct OutboxStore;
+
+impl OutboxStore {
+    fn path() -> Result<PathBuf> {
+        Ok(app_dir()?.join("outbox.json"))
+    }
+
+    fn load_all() -> Result<OutboxFile> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(OutboxFile {
+                version: FORMAT_VERSION,
+                accounts: BTreeMap::new(),
+            });
+        }
+        let data = fs::read_to_string(&path).context("Failed to read outbox file")?;
+        parse(&data).context("Failed to parse outbox file")
+    }
+
+    /// The messages `user_id` left unsent, or none if unreadable.
+    pub fn load(user_id: &str) -> Vec<Unsent> {
+        match Self::load_all() {
+            Ok(mut all) => all.accounts.remove(user_id).unwrap_or_default(),
+            Err(e) => {
+                tracing::warn!("Starting with no unsent messages: {:#}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Keep `messages` as what `user_id` has unsent. Fails rather than
+    /// overwrite a file that could not be read.
+    pub fn save(user_id: &str, messages: &[Unsent]) -> Result<()> {
+        let mut all = Self::load_all()?;
+        all.version = FORMAT_VERSION;
+        if messages.is_empty() {
+            if all.accounts.remove(user_id).is_none() {
+                return Ok(());
+            }
+        } else {
+            all.accounts.insert(user_id.to_string(), messages.to_vec());
+        }
+        let data = serde_json::to_string_pretty(&all)?;
+        fs::write(Self::path()?, data).context("Failed to write outbox file")
+    }
+
+    /// Forget what `user_id` left unsent, e.g. as they log out.
+    pub fn delete(user_id: &str) -> Result<()> {
+        Self::save(user_id, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_formats_are_versioned() {
+        let file = OutboxFile {
+            version: FORMAT_VERSION,
+            accounts: BTreeMap::from([(
+                "@alice:example.org".to_string(),
+                vec![Unsent {
+                    room_id: "!lobby:example.org".to_string(),
+                    room_name: "lobby".to_string(),
+                    txn_id: "t1".to_string(),
+                    body: "gg".to_string(),
+                    mentions: Vec::new(),
+                    reply_to: None,
+                    timestamp: 1,
+                    send_at: Some(2),
+                }],
+            )]),
+        };
+        let data = serde_json::to_string(&file).unwrap();
+        assert!(data.contains("\"version\":2"));
+        assert_eq!(parse(&data).unwrap(), file);
+
+        let first = json!({
+            "version": 1,
+            "accounts": { "@alice:example.org": [{
+                "room_id": "!lobby:example.org",
+                "txn_id": "t1",
+                "body": "gg",
+                "timestamp": 1,
+            }] },
+        })
+        .to_string();
+        let upgraded = parse(&first).unwrap();
+        assert_eq!(upgraded.version, FORMAT_VERSION);
+        assert_eq!(upgraded.accounts["@alice:example.org"][0].send_at, None);
+
+        let newer = json!({ "version": FORMAT_VERSION + 1, "messages": [] }).to_string();
+        assert!(parse(&newer).is_err());
+        let unversioned = json!({ "accounts": {} }).to_string();
+        assert!(parse(&unversioned).is_err());
+    }
+}
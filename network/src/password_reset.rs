@@ -0,0 +1,213 @@
+//! Resetting a forgotten password, before signing in. The homeserver mails
+//! a link to an address on the account, and once it is clicked the new
+//! password is set with the mail's session as proof. Servers that mail a
+//! code as well give an address to submit it to. Nothing we answer depends
+//! on whether the address is on an account, so the form can't be used to
+//! find out: unknown addresses look mailed, and their link never clicked.
+
+use crate::report::is_unknown_endpoint;
+use crate::threepid::EmailValidation;
+use anyhow::{anyhow, bail, Result};
+use matrix_sdk::ruma::{ClientSecret, UInt};
+use matrix_sdk::Client;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// A reset waiting for its link to be clicked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordReset {
+    /// Empty `sid` when the address is on no account.
+    pub validation: EmailValidation,
+    /// Where the code in the mail can be submitted, on servers that mail
+    /// one.
+    pub submit_url: Option<String>,
+}
+
+/// Why a reset didn't go through, worded the same whether or not the
+/// address is on an account. Other failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetError {
+    /// The link wasn't clicked, or the session ran out.
+    NotValidated,
+    WrongCode,
+    WeakPassword,
+    /// The server doesn't reset passwords by email.
+    Disabled,
+}
+
+impl fmt::Display for ResetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotValidated => "The link hasn't been clicked yet, or it has expired",
+            Self::WrongCode => "That code isn't right, or it has expired",
+            Self::WeakPassword => "This password is too weak",
+            Self::Disabled => "This server can't reset passwords by email",
+        })
+    }
+}
+
+impl std::error::Error for ResetError {}
+
+/// What a refused password change meant, if it is one we word ourselves.
+fn reset_error(status: u16, errcode: Option<&str>) -> Option<ResetError> {
+    match errcode {
+        Some("M_WEAK_PASSWORD") => Some(ResetError::WeakPassword),
+        // A session that was never validated, ran out, or is for an
+        // address on no account.
+        Some("M_UNAUTHORIZED" | "M_THREEPID_AUTH_FAILED" | "M_THREEPID_NOT_FOUND") => {
+            Some(ResetError::NotValidated)
+        }
+        Some("M_FORBIDDEN") if status == 401 => Some(ResetError::NotValidated),
+        _ if is_unknown_endpoint(status, errcode) => Some(ResetError::Disabled),
+        _ => None,
+    }
+}
+
+fn refusal(status: u16, body: &Value) -> anyhow::Error {
+    let errcode = body.get("errcode").and_then(Value::as_str);
+    if let Some(e) = reset_error(status, errcode) {
+        return e.into();
+    }
+    match body.get("error").and_then(Value::as_str) {
+        Some(error) => anyhow!("{}", error),
+        None => anyhow!("The server answered {}", status),
+    }
+}
+
+/// Have the server mail `validation`'s address a link to reset the
+/// password of the account it is on.
+pub(crate) async fn request_token(
+    client: &Client,
+    mut validation: EmailValidation,
+) -> Result<PasswordReset> {
+    use matrix_sdk::ruma::api::client::account::request_password_change_token_via_email::v3::Request;
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    let secret = <&ClientSecret>::try_from(validation.client_secret.as_str())?;
+    let request = Request::new(
+        secret.to_owned(),
+        validation.email.clone(),
+        UInt::from(validation.send_attempt),
+    );
+    match client.send(request, None).await {
+        Ok(response) => {
+            validation.sid = response.sid.to_string();
+            Ok(PasswordReset {
+                validation,
+                submit_url: response.submit_url,
+            })
+        }
+        Err(e) => {
+            let unknown_endpoint = e
+                .as_client_api_error()
+                .is_some_and(|error| matches!(error.status_code.as_u16(), 404 | 405));
+            match e.client_api_error_kind() {
+                // Answered as if mailed; its link is never clicked.
+                Some(ErrorKind::ThreepidNotFound) => Ok(PasswordReset {
+                    validation,
+                    submit_url: None,
+                }),
+                Some(ErrorKind::Unrecognized) => Err(ResetError::Disabled.into()),
+                None if unknown_endpoint => Err(ResetError::Disabled.into()),
+                _ => Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Submit the code mailed for session `sid`, to `submit_url`.
+pub(crate) async fn submit_token(
+    submit_url: &str,
+    sid: &str,
+    client_secret: &str,
+    token: &str,
+) -> Result<()> {
+    if sid.is_empty() {
+        return Err(ResetError::WrongCode.into());
+    }
+    let response = reqwest::Client::new()
+        .post(submit_url)
+        .json(&json!({ "sid": sid, "client_secret": client_secret, "token": token.trim() }))
+        .send()
+        .await?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if status.is_success() && body.get("success").and_then(Value::as_bool) == Some(true) {
+        return Ok(());
+    }
+    if status.is_server_error() {
+        bail!("The server answered {}", status);
+    }
+    Err(ResetError::WrongCode.into())
+}
+
+/// Set the password of the account session `sid` was validated for. Its
+/// other sessions are signed out. The SDK's request needs an access
+/// token, so this one is made by hand.
+pub(crate) async fn set_password(
+    client: &Client,
+    sid: &str,
+    client_secret: &str,
+    new_password: &str,
+) -> Result<()> {
+    if sid.is_empty() {
+        return Err(ResetError::NotValidated.into());
+    }
+    let mut url = client.homeserver();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid homeserver URL"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "account", "password"]);
+    let http = reqwest::Client::new();
+
+    // The first ask is refused with the session to authenticate in.
+    let mut body = json!({ "new_password": new_password, "logout_devices": true });
+    let response = http.post(url.clone()).json(&body).send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let answer: Value = response.json().await.unwrap_or_default();
+    if status.as_u16() != 401 || answer.get("errcode").is_some() {
+        return Err(refusal(status.as_u16(), &answer));
+    }
+
+    body["auth"] = json!({
+        "type": "m.login.email.identity",
+        "threepid_creds": { "sid": sid, "client_secret": client_secret },
+        "session": answer.get("session"),
+    });
+    let response = http.post(url).json(&body).send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let answer: Value = response.json().await.unwrap_or_default();
+    Err(refusal(status.as_u16(), &answer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_errors() {
+        assert_eq!(
+            reset_error(401, Some("M_UNAUTHORIZED")),
+            Some(ResetError::NotValidated)
+        );
+        // An address on no account reads like an unclicked link.
+        assert_eq!(
+            reset_error(400, Some("M_THREEPID_NOT_FOUND")),
+            reset_error(401, Some("M_THREEPID_AUTH_FAILED"))
+        );
+        assert_eq!(
+            reset_error(400, Some("M_WEAK_PASSWORD")),
+            Some(ResetError::WeakPassword)
+        );
+        assert_eq!(reset_error(404, None), Some(ResetError::Disabled));
+        assert_eq!(reset_error(429, Some("M_LIMIT_EXCEEDED")), None);
+        // Refused for something other than the session.
+        assert_eq!(reset_error(403, Some("M_FORBIDDEN")), None);
+    }
+}
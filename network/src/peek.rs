@@ -0,0 +1,265 @@
+//! A look into a room before joining it. A room whose history anyone can
+//! read is peeked at: its state and latest messages are fetched without
+//! joining, and the page is handed over to the timeline if we join. Any
+//! other room only shows what the public room directory says about it,
+//! when the directory lists it.
+
+use crate::rooms::{self, MessagePage};
+use anyhow::{Context, Result};
+use matrix_sdk::deserialized_responses::TimelineEvent;
+use matrix_sdk::ruma::directory::PublicRoomsChunk;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedServerName, RoomOrAliasId};
+use matrix_sdk::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How many directory entries to look through for a room.
+const DIRECTORY_LIMIT: u32 = 50;
+
+/// What can be seen of a room from outside.
+#[derive(Debug, Clone, Default)]
+pub struct RoomPreview {
+    pub room_id: String,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub alias: Option<String>,
+    pub member_count: u64,
+    /// Whether its history could be read. If not, `page` is empty.
+    pub peekable: bool,
+    /// The latest messages, with senders' names filled in.
+    pub page: MessagePage,
+}
+
+/// A room's name, topic, alias and members, from its current state as the
+/// server sends it.
+#[derive(Debug, Default, PartialEq)]
+struct Summary {
+    name: Option<String>,
+    topic: Option<String>,
+    alias: Option<String>,
+    member_count: u64,
+    /// Display names of those joined.
+    names: HashMap<String, String>,
+}
+
+fn content_str(event: &Value, key: &str) -> Option<String> {
+    event["content"][key]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+fn summary(state: &[Value]) -> Summary {
+    let mut summary = Summary::default();
+    for event in state {
+        match event["type"].as_str() {
+            Some("m.room.name") => summary.name = content_str(event, "name"),
+            Some("m.room.topic") => summary.topic = content_str(event, "topic"),
+            Some("m.room.canonical_alias") => summary.alias = content_str(event, "alias"),
+            Some("m.room.member") if event["content"]["membership"] == "join" => {
+                summary.member_count += 1;
+                if let (Some(user_id), Some(name)) = (
+                    event["state_key"].as_str(),
+                    content_str(event, "displayname"),
+                ) {
+                    summary.names.insert(user_id.to_string(), name);
+                }
+            }
+            _ => {}
+        }
+    }
+    summary
+}
+
+/// The entry of `chunk` for the room `room_id`, also known as `alias`.
+fn directory_entry<'a>(
+    chunk: &'a [PublicRoomsChunk],
+    room_id: Option<&str>,
+    alias: Option<&str>,
+) -> Option<&'a PublicRoomsChunk> {
+    chunk.iter().find(|entry| {
+        room_id == Some(entry.room_id.as_str())
+            || (alias.is_some() && entry.canonical_alias.as_deref().map(|a| a.as_str()) == alias)
+    })
+}
+
+/// The servers to ask about `id_or_alias`: those given, or else the one
+/// its name is on.
+fn servers(id_or_alias: &RoomOrAliasId, via: &[String]) -> Result<Vec<OwnedServerName>> {
+    let mut servers = via
+        .iter()
+        .map(|s| OwnedServerName::try_from(s.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if servers.is_empty() {
+        servers.extend(id_or_alias.server_name().map(ToOwned::to_owned));
+    }
+    Ok(servers)
+}
+
+/// Peek into `room_id`: its state, then its latest `limit` messages.
+/// Fails if its history can't be read from outside.
+async fn peek(client: &Client, room_id: &OwnedRoomId, limit: u32) -> Result<RoomPreview> {
+    use matrix_sdk::ruma::api::client::message::get_message_events::v3::Request as Messages;
+    use matrix_sdk::ruma::api::client::state::get_state_events::v3::Request as State;
+
+    let state = client.send(State::new(room_id.clone()), None).await?;
+    let state: Vec<Value> = state
+        .room_state
+        .iter()
+        .filter_map(|event| event.deserialize_as::<Value>().ok())
+        .collect();
+    let summary = summary(&state);
+
+    let mut request = Messages::backward(room_id.clone());
+    request.limit = limit.into();
+    let response = client.send(request, None).await?;
+    let events: Vec<TimelineEvent> = response.chunk.into_iter().map(TimelineEvent::new).collect();
+    let mut messages = rooms::page_messages(&events);
+    for message in &mut messages {
+        message.sender_name = summary.names.get(&message.sender).cloned();
+    }
+
+    Ok(RoomPreview {
+        room_id: room_id.to_string(),
+        name: summary.name,
+        topic: summary.topic,
+        alias: summary.alias,
+        member_count: summary.member_count,
+        peekable: true,
+        page: MessagePage {
+            messages,
+            prev_batch: response.end,
+        },
+    })
+}
+
+/// What the public room directory of `server` says about the room.
+async fn from_directory(
+    client: &Client,
+    server: Option<OwnedServerName>,
+    room_id: Option<&str>,
+    alias: Option<&str>,
+) -> Result<RoomPreview> {
+    use matrix_sdk::ruma::api::client::directory::get_public_rooms_filtered::v3::Request;
+
+    let mut request = Request::new();
+    request.server = server;
+    request.limit = Some(DIRECTORY_LIMIT.into());
+    request.filter.generic_search_term = alias.map(str::to_string);
+    let response = client.send(request, None).await?;
+    let entry =
+        directory_entry(&response.chunk, room_id, alias).context("This room can't be previewed")?;
+    Ok(RoomPreview {
+        room_id: entry.room_id.to_string(),
+        name: entry.name.clone(),
+        topic: entry.topic.clone(),
+        alias: entry.canonical_alias.as_ref().map(ToString::to_string),
+        member_count: entry.num_joined_members.into(),
+        peekable: false,
+        page: MessagePage::default(),
+    })
+}
+
+/// A look into the room `id_or_alias`, through the `via` servers: peeked
+/// at if its history is world-readable, otherwise from the directory.
+pub(crate) async fn preview(
+    client: &Client,
+    id_or_alias: &str,
+    via: &[String],
+    limit: u32,
+) -> Result<RoomPreview> {
+    let id_or_alias = <&RoomOrAliasId>::try_from(id_or_alias)?;
+    let servers = servers(id_or_alias, via)?;
+    let alias = id_or_alias.is_room_alias_id().then(|| id_or_alias.as_str());
+    let room_id = match <&matrix_sdk::ruma::RoomAliasId>::try_from(id_or_alias.as_str()) {
+        Ok(alias) => client
+            .resolve_room_alias(alias)
+            .await
+            .ok()
+            .map(|r| r.room_id),
+        Err(_) => Some(OwnedRoomId::try_from(id_or_alias.as_str())?),
+    };
+
+    if let Some(room_id) = &room_id {
+        match peek(client, room_id, limit).await {
+            Ok(preview) => return Ok(preview),
+            Err(e) => tracing::warn!("Could not peek into {}: {:#}", room_id, e),
+        }
+    }
+    from_directory(
+        client,
+        servers.into_iter().next(),
+        room_id.as_ref().map(|id| id.as_str()),
+        alias,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::directory::PublicRoomsChunkInit;
+    use matrix_sdk::ruma::{owned_room_alias_id, owned_room_id, owned_server_name, uint};
+    use serde_json::json;
+
+    #[test]
+    fn test_summary_of_state() {
+        let state = [
+            json!({"type": "m.room.name", "state_key": "", "content": {"name": "Raid night"}}),
+            json!({"type": "m.room.topic", "state_key": "", "content": {"topic": ""}}),
+            json!({"type": "m.room.canonical_alias", "state_key": "",
+                "content": {"alias": "#raid:x.org"}}),
+            json!({"type": "m.room.member", "state_key": "@a:x.org",
+                "content": {"membership": "join", "displayname": "Alice"}}),
+            json!({"type": "m.room.member", "state_key": "@b:x.org",
+                "content": {"membership": "join"}}),
+            json!({"type": "m.room.member", "state_key": "@c:x.org",
+                "content": {"membership": "leave", "displayname": "Carol"}}),
+        ];
+        let summary = summary(&state);
+        assert_eq!(summary.name.as_deref(), Some("Raid night"));
+        // An empty topic is no topic.
+        assert_eq!(summary.topic, None);
+        assert_eq!(summary.alias.as_deref(), Some("#raid:x.org"));
+        assert_eq!(summary.member_count, 2);
+        assert_eq!(
+            summary.names,
+            HashMap::from([("@a:x.org".to_string(), "Alice".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_directory_entry_by_id_or_alias() {
+        let mut raid: PublicRoomsChunk = PublicRoomsChunkInit {
+            num_joined_members: uint!(12),
+            room_id: owned_room_id!("!raid:x.org"),
+            world_readable: false,
+            guest_can_join: false,
+        }
+        .into();
+        raid.canonical_alias = Some(owned_room_alias_id!("#raid:x.org"));
+        let lobby: PublicRoomsChunk = PublicRoomsChunkInit {
+            num_joined_members: uint!(3),
+            room_id: owned_room_id!("!lobby:x.org"),
+            world_readable: false,
+            guest_can_join: false,
+        }
+        .into();
+        let chunk = [lobby, raid];
+
+        let found =
+            |room_id, alias| directory_entry(&chunk, room_id, alias).map(|e| e.room_id.as_str());
+        assert_eq!(found(Some("!raid:x.org"), None), Some("!raid:x.org"));
+        assert_eq!(found(None, Some("#raid:x.org")), Some("!raid:x.org"));
+        assert_eq!(found(Some("!other:x.org"), Some("#other:x.org")), None);
+        assert_eq!(found(None, None), None);
+    }
+
+    #[test]
+    fn test_servers_default_to_the_rooms_own() {
+        let alias = <&RoomOrAliasId>::try_from("#raid:x.org").unwrap();
+        assert_eq!(servers(alias, &[]).unwrap(), [owned_server_name!("x.org")]);
+        let via = ["y.org".to_string()];
+        assert_eq!(servers(alias, &via).unwrap(), [owned_server_name!("y.org")]);
+    }
+}
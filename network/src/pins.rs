@@ -0,0 +1,62 @@
+//! Messages pinned in a room, kept in its `m.room.pinned_events` state in
+//! the order they were pinned. Changing it needs the power level to send
+//! that state event.
+
+use matrix_sdk::deserialized_responses::RawSyncOrStrippedState;
+use matrix_sdk::ruma::events::room::pinned_events::RoomPinnedEventsEventContent;
+use matrix_sdk::ruma::events::SyncStateEvent;
+use matrix_sdk::Room;
+
+/// The ids of the messages pinned in `room`, oldest pin first.
+pub(crate) async fn pinned_events(room: &Room) -> matrix_sdk::Result<Vec<String>> {
+    let Some(RawSyncOrStrippedState::Sync(raw)) = room
+        .get_state_event_static::<RoomPinnedEventsEventContent>()
+        .await?
+    else {
+        return Ok(Vec::new());
+    };
+    Ok(match raw.deserialize() {
+        Ok(SyncStateEvent::Original(ev)) => {
+            ev.content.pinned.iter().map(|id| id.to_string()).collect()
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// `pinned` with `event_id` pinned after the others, or unpinned, or
+/// `None` if that changes nothing.
+pub fn with_pin(pinned: &[String], event_id: &str, pin: bool) -> Option<Vec<String>> {
+    let present = pinned.iter().any(|id| id == event_id);
+    if present == pin {
+        return None;
+    }
+    let mut pinned = pinned.to_vec();
+    if pin {
+        pinned.push(event_id.to_string());
+    } else {
+        pinned.retain(|id| id != event_id);
+    }
+    Some(pinned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pins_are_added_last_and_removed() {
+        let pinned = ids(&["$a", "$b"]);
+        assert_eq!(
+            with_pin(&pinned, "$c", true),
+            Some(ids(&["$a", "$b", "$c"]))
+        );
+        assert_eq!(with_pin(&pinned, "$a", false), Some(ids(&["$b"])));
+        // Already as asked.
+        assert_eq!(with_pin(&pinned, "$b", true), None);
+        assert_eq!(with_pin(&pinned, "$c", false), None);
+    }
+}
@@ -0,0 +1,70 @@
+//! The user's own profile. Display name and avatar are Matrix profile
+//! fields; Matrix has no bio, so it is kept in our own account data.
+
+use serde::{Deserialize, Serialize};
+
+/// Account data event type holding the fields Matrix has no place for.
+pub const PROFILE_EVENT_TYPE: &str = "com.gamechat.profile";
+
+/// Edge length, in pixels, of the avatar thumbnail fetched for display.
+pub const AVATAR_THUMBNAIL_SIZE: u32 = 96;
+
+/// Largest avatar we upload; homeservers commonly reject bigger media.
+pub const MAX_AVATAR_BYTES: usize = 5 * 1024 * 1024;
+
+/// The profile as loaded from the server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub display_name: Option<String>,
+    /// Encoded thumbnail of the avatar, if one is set.
+    pub avatar: Option<Vec<u8>>,
+    pub bio: String,
+}
+
+/// Content of the `com.gamechat.profile` account data event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProfileContent {
+    #[serde(default)]
+    pub bio: String,
+}
+
+/// MIME type of an avatar image, judged by its file extension. `None` for
+/// formats we do not upload.
+pub fn avatar_mime(file_name: &str) -> Option<&'static str> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avatar_mime() {
+        assert_eq!(avatar_mime("me.PNG"), Some("image/png"));
+        assert_eq!(avatar_mime("/home/me/photo.final.jpeg"), Some("image/jpeg"));
+        assert_eq!(avatar_mime("notes.txt"), None);
+        assert_eq!(avatar_mime("png"), None);
+    }
+
+    #[test]
+    fn test_profile_content_tolerates_missing_fields() {
+        let content: ProfileContent = serde_json::from_str("{}").unwrap();
+        assert_eq!(content, ProfileContent::default());
+
+        let content = ProfileContent {
+            bio: "Tank main".to_string(),
+        };
+        let json = serde_json::to_string(&content).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ProfileContent>(&json).unwrap(),
+            content
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Removing a user's recent messages from a room in one go, as spam
+//! attacks call for. The room's recent timeline is paged back over a
+//! window, the user's events in it collected, then redacted one after
+//! another, paced so the server's rate limits are not run into, and held
+//! for as long as the server asks when they are anyway. A redaction that
+//! fails is noted and the rest go on; the report says which failed.
+
+use crate::admin::admin_error;
+use anyhow::Result;
+use async_trait::async_trait;
+use matrix_sdk::room::MessagesOptions;
+use matrix_sdk::ruma::api::client::error::ErrorKind;
+use matrix_sdk::ruma::{EventId, UserId};
+use matrix_sdk::Room;
+use std::time::Duration;
+
+/// Time left between two redactions.
+pub const REDACTION_SPACING: Duration = Duration::from_millis(200);
+/// How long to hold when rate-limited without being told.
+const RATE_LIMIT_WAIT: Duration = Duration::from_secs(5);
+/// Times a redaction is tried again after being rate-limited.
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// Events asked for per page.
+const PAGE_SIZE: u32 = 50;
+
+/// How far back to look for the user's messages: whichever of the two
+/// limits is hit first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PurgeWindow {
+    pub max_age: Duration,
+    /// Events of the room looked at, anyone's.
+    pub max_events: usize,
+}
+
+impl Default for PurgeWindow {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(60 * 60),
+            max_events: 100,
+        }
+    }
+}
+
+/// How far the redactions have got.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PurgeProgress {
+    /// Redactions tried, whether or not they went through.
+    pub done: usize,
+    pub total: usize,
+}
+
+/// How a purge went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PurgeReport {
+    pub banned: bool,
+    /// The user's events found in the window.
+    pub found: usize,
+    pub redacted: usize,
+    /// Event ids that couldn't be redacted, with why.
+    pub failed: Vec<(String, String)>,
+}
+
+/// One event of the timeline, as far as the purge cares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScannedEvent {
+    pub event_id: String,
+    pub sender: String,
+    pub ts: u64,
+    /// State events, e.g. the user's membership, are left alone.
+    pub state: bool,
+    /// Redacted already.
+    pub redacted: bool,
+}
+
+impl ScannedEvent {
+    /// Read from an event as JSON; `None` if it lacks what every event has.
+    pub(crate) fn from_json(event: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            event_id: event.get("event_id")?.as_str()?.to_string(),
+            sender: event.get("sender")?.as_str()?.to_string(),
+            ts: event.get("origin_server_ts")?.as_u64()?,
+            state: event.get("state_key").is_some(),
+            redacted: event
+                .pointer("/unsigned/redacted_because")
+                .is_some_and(|r| !r.is_null()),
+        })
+    }
+}
+
+/// A page of the timeline, newest first, and where the next one starts.
+pub struct ScannedPage {
+    pub events: Vec<ScannedEvent>,
+    pub next: Option<String>,
+}
+
+/// Why a redaction failed.
+#[derive(Debug)]
+pub enum RedactError {
+    /// Too many requests; try again after this long, if the server said.
+    RateLimited(Option<Duration>),
+    Other(anyhow::Error),
+}
+
+/// The room as the purge sees it, so it runs the same against a mock.
+#[async_trait]
+pub(crate) trait Moderation: Sync {
+    /// The page of the timeline before `from`, or the latest.
+    async fn page(&self, from: Option<&str>, limit: u32) -> Result<ScannedPage>;
+    async fn ban(&self, user_id: &str) -> Result<()>;
+    async fn redact(&self, event_id: &str) -> std::result::Result<(), RedactError>;
+}
+
+/// A room on the homeserver.
+pub(crate) struct ServerRoom(pub Room);
+
+#[async_trait]
+impl Moderation for ServerRoom {
+    async fn page(&self, from: Option<&str>, limit: u32) -> Result<ScannedPage> {
+        let mut options = MessagesOptions::backward();
+        options.from = from.map(str::to_owned);
+        options.limit = limit.into();
+        let response = self.0.messages(options).await?;
+        let events = response
+            .chunk
+            .iter()
+            .filter_map(|event| event.event.deserialize_as::<serde_json::Value>().ok())
+            .filter_map(|event| ScannedEvent::from_json(&event))
+            .collect();
+        Ok(ScannedPage {
+            events,
+            next: response.end,
+        })
+    }
+
+    async fn ban(&self, user_id: &str) -> Result<()> {
+        let user_id = <&UserId>::try_from(user_id)?;
+        self.0
+            .ban_user(user_id, None)
+            .await
+            .map_err(|e| admin_error(e, "ban members"))?;
+        Ok(())
+    }
+
+    async fn redact(&self, event_id: &str) -> std::result::Result<(), RedactError> {
+        let event_id = <&EventId>::try_from(event_id).map_err(|e| RedactError::Other(e.into()))?;
+        let Err(e) = self.0.redact(event_id, None, None).await else {
+            return Ok(());
+        };
+        let e = matrix_sdk::Error::from(e);
+        if let Some(ErrorKind::LimitExceeded { retry_after_ms }) = e.client_api_error_kind() {
+            return Err(RedactError::RateLimited(*retry_after_ms));
+        }
+        Err(RedactError::Other(admin_error(e, "delete messages")))
+    }
+}
+
+/// The ids of `user_id`'s events within `window` of `now_ms`, newest
+/// first, paging back through `room`.
+pub(crate) async fn collect(
+    room: &impl Moderation,
+    user_id: &str,
+    window: PurgeWindow,
+    now_ms: u64,
+) -> Result<Vec<String>> {
+    let oldest = now_ms.saturating_sub(window.max_age.as_millis() as u64);
+    let mut found = Vec::new();
+    let mut scanned = 0;
+    let mut from: Option<String> = None;
+    loop {
+        let page = room.page(from.as_deref(), PAGE_SIZE).await?;
+        for event in &page.events {
+            if scanned == window.max_events || event.ts < oldest {
+                return Ok(found);
+            }
+            scanned += 1;
+            if event.sender == user_id && !event.state && !event.redacted {
+                found.push(event.event_id.clone());
+            }
+        }
+        match page.next {
+            Some(next) if !page.events.is_empty() => from = Some(next),
+            _ => return Ok(found),
+        }
+    }
+}
+
+/// Remove `user_id`'s events within `window` of `now_ms` from `room`,
+/// banning them first if asked; a ban that fails stops it all. `progress`
+/// hears after each redaction, and `spacing` is left between them.
+pub(crate) async fn purge(
+    room: &impl Moderation,
+    user_id: &str,
+    window: PurgeWindow,
+    ban: bool,
+    now_ms: u64,
+    spacing: Duration,
+    mut progress: impl FnMut(PurgeProgress) + Send,
+) -> Result<PurgeReport> {
+    let mut report = PurgeReport::default();
+    if ban {
+        room.ban(user_id).await?;
+        report.banned = true;
+    }
+    let events = collect(room, user_id, window, now_ms).await?;
+    report.found = events.len();
+    progress(PurgeProgress {
+        done: 0,
+        total: events.len(),
+    });
+    for (i, event_id) in events.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(spacing).await;
+        }
+        let mut retries = 0;
+        loop {
+            match room.redact(event_id).await {
+                Ok(()) => report.redacted += 1,
+                Err(RedactError::RateLimited(wait)) if retries < RATE_LIMIT_RETRIES => {
+                    retries += 1;
+                    tokio::time::sleep(wait.unwrap_or(RATE_LIMIT_WAIT)).await;
+                    continue;
+                }
+                Err(RedactError::RateLimited(_)) => report
+                    .failed
+                    .push((event_id.clone(), "Rate limited".to_string())),
+                Err(RedactError::Other(e)) => report.failed.push((event_id.clone(), e.to_string())),
+            }
+            break;
+        }
+        progress(PurgeProgress {
+            done: i + 1,
+            total: events.len(),
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    const SPAMMER: &str = "@spam:example.org";
+    const NOW: u64 = 1_710_504_000_000;
+    const MINUTE: u64 = 60 * 1000;
+
+    /// Stands in for a homeserver: holds a room's timeline, oldest first,
+    /// rate-limits the first `limited` redactions and refuses some.
+    #[derive(Default)]
+    struct MockServer {
+        events: Vec<ScannedEvent>,
+        limited: Mutex<usize>,
+        refused: HashSet<String>,
+        redacted: Mutex<Vec<String>>,
+        banned: Mutex<Vec<String>>,
+        pages: Mutex<usize>,
+    }
+
+    impl MockServer {
+        fn new(events: Vec<ScannedEvent>) -> Self {
+            Self {
+                events,
+                ..Self::default()
+            }
+        }
+    }
+
+    fn message(event_id: &str, sender: &str, ts: u64) -> ScannedEvent {
+        ScannedEvent {
+            event_id: event_id.to_string(),
+            sender: sender.to_string(),
+            ts,
+            state: false,
+            redacted: false,
+        }
+    }
+
+    #[async_trait]
+    impl Moderation for MockServer {
+        async fn page(&self, from: Option<&str>, limit: u32) -> Result<ScannedPage> {
+            *self.pages.lock().unwrap() += 1;
+            let end = from.map_or(self.events.len(), |f| f.parse().unwrap());
+            let start = end.saturating_sub(limit as usize);
+            Ok(ScannedPage {
+                events: self.events[start..end].iter().rev().cloned().collect(),
+                next: (start > 0).then(|| start.to_string()),
+            })
+        }
+
+        async fn ban(&self, user_id: &str) -> Result<()> {
+            self.banned.lock().unwrap().push(user_id.to_string());
+            Ok(())
+        }
+
+        async fn redact(&self, event_id: &str) -> std::result::Result<(), RedactError> {
+            let mut limited = self.limited.lock().unwrap();
+            if *limited > 0 {
+                *limited -= 1;
+                return Err(RedactError::RateLimited(Some(Duration::from_millis(1))));
+            }
+            if self.refused.contains(event_id) {
+                return Err(RedactError::Other(anyhow::anyhow!("Forbidden")));
+            }
+            self.redacted.lock().unwrap().push(event_id.to_string());
+            Ok(())
+        }
+    }
+
+    /// 120 messages a minute apart up to now, every third the spammer's.
+    fn busy_room() -> MockServer {
+        MockServer::new(
+            (0..120)
+                .map(|i| {
+                    let sender = if i % 3 == 0 {
+                        SPAMMER
+                    } else {
+                        "@alice:example.org"
+                    };
+                    message(&format!("${}", i), sender, NOW - (119 - i) * MINUTE)
+                })
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_window_stops_at_age_or_count() {
+        let room = busy_room();
+        let hour = PurgeWindow::default();
+        let found = collect(&room, SPAMMER, hour, NOW).await.unwrap();
+        // The last 61 messages are within the hour, 20 of them the spammer's.
+        assert_eq!(found.len(), 20);
+        assert_eq!(found[0], "$117");
+
+        let few = PurgeWindow {
+            max_events: 10,
+            ..hour
+        };
+        assert_eq!(collect(&room, SPAMMER, few, NOW).await.unwrap().len(), 3);
+
+        let all = PurgeWindow {
+            max_age: Duration::from_secs(24 * 60 * 60),
+            max_events: 1000,
+        };
+        assert_eq!(collect(&room, SPAMMER, all, NOW).await.unwrap().len(), 40);
+        assert!(*room.pages.lock().unwrap() >= 3);
+    }
+
+    #[tokio::test]
+    async fn test_state_and_redacted_events_are_skipped() {
+        let event = |json: serde_json::Value| ScannedEvent::from_json(&json).unwrap();
+        let base = json!({ "event_id": "$a", "sender": SPAMMER, "origin_server_ts": NOW });
+        let mut membership = base.clone();
+        membership["state_key"] = json!(SPAMMER);
+        let mut gone = base.clone();
+        gone["unsigned"] = json!({ "redacted_because": { "event_id": "$r" } });
+
+        assert!(!event(base.clone()).state && !event(base).redacted);
+        assert!(event(membership.clone()).state);
+        assert!(event(gone.clone()).redacted);
+        assert_eq!(ScannedEvent::from_json(&json!({ "event_id": "$a" })), None);
+
+        let room = MockServer {
+            events: vec![event(membership), event(gone)],
+            ..MockServer::default()
+        };
+        let found = collect(&room, SPAMMER, PurgeWindow::default(), NOW)
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_purge_goes_on_past_failures() {
+        let mut room = busy_room();
+        room.refused.insert("$105".to_string());
+        *room.limited.lock().unwrap() = 2;
+        let mut seen = Vec::new();
+        let report = purge(
+            &room,
+            SPAMMER,
+            PurgeWindow::default(),
+            true,
+            NOW,
+            Duration::ZERO,
+            |p| seen.push(p),
+        )
+        .await
+        .unwrap();
+
+        assert!(report.banned);
+        assert_eq!(*room.banned.lock().unwrap(), [SPAMMER]);
+        assert_eq!(report.found, 20);
+        assert_eq!(report.redacted, 19);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "$105");
+        // Rate-limited redactions were tried again, not given up on.
+        assert_eq!(room.redacted.lock().unwrap().len(), 19);
+        assert_eq!(seen.first(), Some(&PurgeProgress { done: 0, total: 20 }));
+        assert_eq!(
+            seen.last(),
+            Some(&PurgeProgress {
+                done: 20,
+                total: 20
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_that_last_fail_the_event() {
+        let room = MockServer::new(vec![
+            message("$b", SPAMMER, NOW),
+            message("$a", SPAMMER, NOW),
+        ]);
+        *room.limited.lock().unwrap() = RATE_LIMIT_RETRIES as usize + 1;
+        let report = purge(
+            &room,
+            SPAMMER,
+            PurgeWindow::default(),
+            false,
+            NOW,
+            Duration::ZERO,
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert!(!report.banned);
+        // The newest, tried first, used up the retries.
+        assert_eq!(
+            report.failed,
+            [("$a".to_string(), "Rate limited".to_string())]
+        );
+        assert_eq!(*room.redacted.lock().unwrap(), ["$b"]);
+    }
+}
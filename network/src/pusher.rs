@@ -0,0 +1,242 @@
+//! Pushers: the homeserver telling us of mentions while the app is closed,
+//! by mail or through a push gateway. Each is keyed by its app id and push
+//! key; registering one again only updates it, so they are registered on
+//! every sign in. Logging out removes the ones this session registered and
+//! leaves those of other apps alone.
+
+use crate::config::PusherConfig;
+use matrix_sdk::ruma::api::client::push::{EmailPusherData, PusherIds, PusherInit};
+use matrix_sdk::ruma::api::client::push::{Pusher as SdkPusher, PusherKind as SdkPusherKind};
+use matrix_sdk::ruma::push::HttpPusherData;
+use matrix_sdk::HttpError;
+use std::fmt;
+
+/// The app id the spec gives email pushers. Their push key is the address.
+pub const EMAIL_APP_ID: &str = "m.email";
+
+/// Shown as the pusher's app, e.g. in other clients' lists of them.
+pub(crate) const APP_DISPLAY_NAME: &str = "gamechat";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PusherKind {
+    Email,
+    /// Posted to a push gateway at `url`.
+    Http {
+        url: String,
+    },
+    /// A kind we don't know, e.g. a server extension.
+    Other,
+}
+
+/// A pusher registered on the account, by any app.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pusher {
+    pub app_id: String,
+    /// The address for email pushers; a device token for gateways.
+    pub pushkey: String,
+    pub kind: PusherKind,
+    pub app_display_name: String,
+    pub device_display_name: String,
+}
+
+impl Pusher {
+    /// Mail mentions to `address`. Every device shares the one pusher, as
+    /// the address is its push key.
+    pub(crate) fn email(address: &str, device_id: &str) -> Self {
+        Self {
+            app_id: EMAIL_APP_ID.to_string(),
+            pushkey: address.to_string(),
+            kind: PusherKind::Email,
+            app_display_name: APP_DISPLAY_NAME.to_string(),
+            device_display_name: device_id.to_string(),
+        }
+    }
+
+    /// Post notifications to the gateway at `url`, for app `app_id`. The
+    /// push key is this device's id, so each device has its own.
+    pub(crate) fn gateway(url: &str, app_id: &str, device_id: &str, device_name: &str) -> Self {
+        Self {
+            app_id: app_id.to_string(),
+            pushkey: device_id.to_string(),
+            kind: PusherKind::Http {
+                url: url.to_string(),
+            },
+            app_display_name: APP_DISPLAY_NAME.to_string(),
+            device_display_name: device_name.to_string(),
+        }
+    }
+
+    pub(crate) fn from_sdk(pusher: &SdkPusher) -> Self {
+        let kind = match &pusher.kind {
+            SdkPusherKind::Email(_) => PusherKind::Email,
+            SdkPusherKind::Http(data) => PusherKind::Http {
+                url: data.url.clone(),
+            },
+            _ => PusherKind::Other,
+        };
+        Self {
+            app_id: pusher.ids.app_id.clone(),
+            pushkey: pusher.ids.pushkey.clone(),
+            kind,
+            app_display_name: pusher.app_display_name.clone(),
+            device_display_name: pusher.device_display_name.clone(),
+        }
+    }
+
+    /// `None` for kinds we don't know.
+    pub(crate) fn to_sdk(&self) -> Option<SdkPusher> {
+        let kind = match &self.kind {
+            PusherKind::Email => SdkPusherKind::Email(EmailPusherData::new()),
+            PusherKind::Http { url } => SdkPusherKind::Http(HttpPusherData::new(url.clone())),
+            PusherKind::Other => return None,
+        };
+        let pusher = PusherInit {
+            ids: PusherIds::new(self.pushkey.clone(), self.app_id.clone()),
+            kind,
+            app_display_name: self.app_display_name.clone(),
+            device_display_name: self.device_display_name.clone(),
+            profile_tag: None,
+            lang: "en".to_string(),
+        };
+        Some(pusher.into())
+    }
+
+    /// Whether `other` is the same pusher, sending to the same place.
+    pub(crate) fn same_as(&self, other: &Pusher) -> bool {
+        self.app_id == other.app_id && self.pushkey == other.pushkey && self.kind == other.kind
+    }
+}
+
+/// The pushers `config` asks for on device `device_id`.
+pub(crate) fn wanted(config: &PusherConfig, device_id: &str) -> Vec<Pusher> {
+    let mut pushers = Vec::new();
+    if let Some(email) = config.email.as_deref().map(str::trim) {
+        if !email.is_empty() {
+            pushers.push(Pusher::email(email, device_id));
+        }
+    }
+    if let Some(gateway) = &config.gateway {
+        if !gateway.url.trim().is_empty() && !gateway.app_id.trim().is_empty() {
+            pushers.push(Pusher::gateway(
+                gateway.url.trim(),
+                gateway.app_id.trim(),
+                device_id,
+                device_id,
+            ));
+        }
+    }
+    pushers
+}
+
+/// Whether `pusher` was registered by this app on device `device_id`:
+/// ours to replace, and to remove as it logs out.
+pub(crate) fn is_ours(pusher: &Pusher, device_id: &str) -> bool {
+    pusher.app_display_name == APP_DISPLAY_NAME
+        && match pusher.kind {
+            PusherKind::Email => true,
+            PusherKind::Http { .. } => pusher.pushkey == device_id,
+            PusherKind::Other => false,
+        }
+}
+
+/// Why a pusher could not be registered, for the settings to show. Other
+/// failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PusherError {
+    /// The server sends no notifications elsewhere, or none of this kind.
+    Disabled,
+    /// Email pushers need the address on the account first.
+    EmailNotOnAccount,
+}
+
+impl fmt::Display for PusherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Disabled => "Your server doesn't send notifications outside the app",
+            Self::EmailNotOnAccount => "Add this email to your account first",
+        })
+    }
+}
+
+impl std::error::Error for PusherError {}
+
+/// Turn a refused pusher into `PusherError`. Servers with pushers off
+/// refuse them or don't know the endpoint.
+pub(crate) fn pusher_error(e: HttpError) -> anyhow::Error {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    let unknown_endpoint = e
+        .as_client_api_error()
+        .is_some_and(|error| matches!(error.status_code.as_u16(), 404 | 405));
+    match e.client_api_error_kind() {
+        Some(ErrorKind::Forbidden | ErrorKind::Unrecognized) => PusherError::Disabled.into(),
+        Some(ErrorKind::ThreepidNotFound) => PusherError::EmailNotOnAccount.into(),
+        None if unknown_endpoint => PusherError::Disabled.into(),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GatewayConfig;
+
+    #[test]
+    fn test_only_our_pushers_are_ours() {
+        let config = PusherConfig {
+            email: Some(" me@example.org ".to_string()),
+            gateway: Some(GatewayConfig {
+                url: "https://push.example.org/_matrix/push/v1/notify".to_string(),
+                app_id: String::new(),
+            }),
+        };
+        // A gateway without an app id is left off.
+        let wanted = wanted(&config, "DEVICEID");
+        assert_eq!(wanted, [Pusher::email("me@example.org", "DEVICEID")]);
+        assert!(is_ours(&wanted[0], "OTHERDEVICE"));
+
+        let gateway = Pusher::gateway("https://push.example.org", "org.example", "DEVICEID", "");
+        assert!(is_ours(&gateway, "DEVICEID"));
+        assert!(!is_ours(&gateway, "OTHERDEVICE"));
+        // The same address, mailed for another app.
+        let theirs = Pusher {
+            app_display_name: "Element".to_string(),
+            ..Pusher::email("me@example.org", "PHONE")
+        };
+        assert!(!is_ours(&theirs, "DEVICEID"));
+    }
+
+    #[test]
+    fn test_pushers_round_trip() {
+        let gateway = Pusher {
+            app_id: "org.example.companion".to_string(),
+            pushkey: "DEVICEID".to_string(),
+            kind: PusherKind::Http {
+                url: "https://push.example.org/_matrix/push/v1/notify".to_string(),
+            },
+            app_display_name: APP_DISPLAY_NAME.to_string(),
+            device_display_name: "Desktop".to_string(),
+        };
+        let back = Pusher::from_sdk(&gateway.to_sdk().unwrap());
+        assert_eq!(back, gateway);
+
+        let moved = Pusher {
+            kind: PusherKind::Http {
+                url: "https://other.example.org/notify".to_string(),
+            },
+            ..gateway.clone()
+        };
+        assert!(!moved.same_as(&gateway));
+        let renamed = Pusher {
+            device_display_name: "Laptop".to_string(),
+            ..gateway.clone()
+        };
+        assert!(renamed.same_as(&gateway));
+
+        let unknown = Pusher {
+            kind: PusherKind::Other,
+            ..gateway
+        };
+        assert!(unknown.to_sdk().is_none());
+    }
+}
@@ -0,0 +1,247 @@
+//! Recording a session's event stream for bug reports, and reading it
+//! back to replay. A recording is gzipped JSON lines: a header with the
+//! rooms as loaded, then each event with when it came. Message bodies can
+//! be left out, each replaced by its length and a hash, so a recording
+//! still shows which bodies were the same without what they said.
+
+use crate::events::ChatEvent;
+use crate::session::app_dir;
+use anyhow::{Context, Result};
+use chat_core::{hash, time, Message, Room, Space};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Who was signed in and what they had joined when recording started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub user_id: String,
+    pub display_name: String,
+    pub spaces: Vec<Space>,
+    pub rooms: Vec<Room>,
+    /// Message bodies were left out.
+    pub redacted: bool,
+}
+
+/// An event, `at_ms` after recording started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub at_ms: u64,
+    pub event: ChatEvent,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub header: Header,
+    pub events: Vec<Recorded>,
+}
+
+/// Writes one session's events to a recording as they come.
+pub struct Recorder {
+    out: GzEncoder<BufWriter<File>>,
+    started: Instant,
+    redact: bool,
+}
+
+impl Recorder {
+    /// Start a recording at `path`.
+    pub fn create(path: &Path, header: &Header) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut recorder = Self {
+            out: GzEncoder::new(BufWriter::new(file), Compression::default()),
+            started: Instant::now(),
+            redact: header.redacted,
+        };
+        recorder.write_line(header)?;
+        Ok(recorder)
+    }
+
+    /// Start a recording in the recordings directory, named after `header`'s
+    /// user and the time. Returns where it is.
+    pub fn start(header: &Header) -> Result<(Self, PathBuf)> {
+        let dir = app_dir()?.join("recordings");
+        fs::create_dir_all(&dir).context("Failed to create the recordings directory")?;
+        let name: String = header
+            .user_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{}-{}.jsonl.gz", name, time::now_ms()));
+        Ok((Self::create(&path, header)?, path))
+    }
+
+    /// Append `event`. Each event is flushed, so a recording cut short by a
+    /// crash still reads up to it.
+    pub fn record(&mut self, event: &ChatEvent) -> Result<()> {
+        let event = if self.redact {
+            redact(event)
+        } else {
+            event.clone()
+        };
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.write_line(&Recorded { at_ms, event })
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<()> {
+        serde_json::to_writer(&mut self.out, value)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// End the recording.
+    pub fn finish(self) -> Result<()> {
+        self.out.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Read the recording at `path`. A recording cut short ends at the last
+/// event written whole.
+pub fn read(path: &Path) -> Result<Recording> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut lines = BufReader::new(GzDecoder::new(file)).lines();
+    let header = lines.next().context("The recording is empty")??;
+    let header: Header = serde_json::from_str(&header).context("Not a recording")?;
+    let events = lines
+        .map_while(|line| line.ok())
+        .map_while(|line| serde_json::from_str(&line).ok())
+        .collect();
+    Ok(Recording { header, events })
+}
+
+/// `body` as its length and a hash, e.g. "[12 chars, 5d41402a]".
+fn redact_body(body: &str) -> String {
+    let hash = hash::fnv1a(body) as u32;
+    format!("[{} chars, {:08x}]", body.chars().count(), hash)
+}
+
+fn redact_message(message: &Message) -> Message {
+    Message {
+        content: redact_body(&message.content),
+        formatted: message.formatted.as_deref().map(redact_body),
+        // Their ranges are into the body.
+        mentions: Vec::new(),
+        ..message.clone()
+    }
+}
+
+/// `event` with message bodies left out.
+pub fn redact(event: &ChatEvent) -> ChatEvent {
+    match event {
+        ChatEvent::Message {
+            room_id,
+            message,
+            transaction_id,
+        } => ChatEvent::Message {
+            room_id: room_id.clone(),
+            message: Box::new(redact_message(message)),
+            transaction_id: transaction_id.clone(),
+        },
+        ChatEvent::MessageEdited {
+            room_id,
+            event_id,
+            sender,
+            body,
+            formatted,
+        } => ChatEvent::MessageEdited {
+            room_id: room_id.clone(),
+            event_id: event_id.clone(),
+            sender: sender.clone(),
+            body: redact_body(body),
+            formatted: formatted.as_deref().map(redact_body),
+        },
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, body: &str) -> Message {
+        Message {
+            id: id.to_string(),
+            sender: "@bob:x".to_string(),
+            content: body.to_string(),
+            ..Message::default()
+        }
+    }
+
+    fn header(redacted: bool) -> Header {
+        Header {
+            user_id: "@me:x".to_string(),
+            display_name: "me".to_string(),
+            spaces: Vec::new(),
+            rooms: Vec::new(),
+            redacted,
+        }
+    }
+
+    fn body(event: &ChatEvent) -> &str {
+        match event {
+            ChatEvent::Message { message, .. } => &message.content,
+            other => panic!("not a message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl.gz", time::now_ms()));
+        let mut recorder = Recorder::create(&path, &header(false)).unwrap();
+        for (id, text) in [("$1", "hello"), ("$2", "there")] {
+            let event = ChatEvent::Message {
+                room_id: "!r:x".to_string(),
+                message: Box::new(message(id, text)),
+                transaction_id: None,
+            };
+            recorder.record(&event).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let recording = read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(recording.header.user_id, "@me:x");
+        let bodies: Vec<&str> = recording.events.iter().map(|r| body(&r.event)).collect();
+        assert_eq!(bodies, vec!["hello", "there"]);
+        assert!(recording.events[0].at_ms <= recording.events[1].at_ms);
+    }
+
+    #[test]
+    fn test_cut_short_recording_reads_up_to_the_cut() {
+        let path = std::env::temp_dir().join(format!("cut-{}.jsonl.gz", time::now_ms()));
+        let mut recorder = Recorder::create(&path, &header(true)).unwrap();
+        recorder
+            .record(&ChatEvent::RoomLeft {
+                room_id: "!r:x".to_string(),
+            })
+            .unwrap();
+        // Never finished, as after a crash.
+        std::mem::forget(recorder);
+
+        let recording = read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(recording.events.len(), 1);
+    }
+
+    #[test]
+    fn test_redacted_bodies() {
+        let event = |text: &str| ChatEvent::Message {
+            room_id: "!r:x".to_string(),
+            message: Box::new(message("$1", text)),
+            transaction_id: None,
+        };
+        let hidden = redact(&event("secret plans"));
+        assert!(!body(&hidden).contains("secret"));
+        assert!(body(&hidden).starts_with("[12 chars, "));
+        // The same body hashes the same; another doesn't.
+        assert_eq!(body(&hidden), body(&redact(&event("secret plans"))));
+        assert_ne!(body(&hidden), body(&redact(&event("secret plant"))));
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+
+/// Why the server refused a registration, for errors the register form
+/// shows next to a field or acts on. Other failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterError {
+    UsernameTaken,
+    InvalidUsername,
+    WeakPassword,
+    /// The server does not allow registration.
+    Disabled,
+    /// The server requires steps we cannot complete in-app, such as email
+    /// verification, a captcha or accepting terms.
+    NeedsBrowser,
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UsernameTaken => "This username is already taken",
+            Self::InvalidUsername => "This username is not allowed on this server",
+            Self::WeakPassword => "This password is too weak",
+            Self::Disabled => "Registration is disabled on this server",
+            Self::NeedsBrowser => "This server requires registering in a browser",
+        })
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+/// Result of checking a username before registering.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Availability {
+    Available,
+    Taken,
+    Invalid,
+}
+
+/// How a homeserver lets new users sign up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Registration {
+    /// With just a username and password, so in the app.
+    InApp,
+    /// Only with steps we cannot complete in-app, like a captcha.
+    NeedsBrowser,
+    Disabled,
+}
+
+impl Registration {
+    /// Judge from the stages of each flow the server offers.
+    pub fn from_flows<S: AsRef<str>>(flows: &[Vec<S>]) -> Self {
+        if flows.is_empty() {
+            Self::Disabled
+        } else if flows
+            .iter()
+            .any(|flow| flow.iter().all(|stage| stage.as_ref() == "m.login.dummy"))
+        {
+            Self::InApp
+        } else {
+            Self::NeedsBrowser
+        }
+    }
+}
+
+/// What a homeserver offers someone without a session yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSupport {
+    /// The client API address the server name led to.
+    pub homeserver: String,
+    pub password_login: bool,
+    pub registration: Registration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registration_from_flows() {
+        let no_flows: [Vec<&str>; 0] = [];
+        assert_eq!(Registration::from_flows(&no_flows), Registration::Disabled);
+        assert_eq!(
+            Registration::from_flows(&[vec!["m.login.recaptcha"], vec!["m.login.dummy"]]),
+            Registration::InApp
+        );
+        // A flow with no stages at all needs nothing more either.
+        assert_eq!(
+            Registration::from_flows(&[Vec::<&str>::new()]),
+            Registration::InApp
+        );
+        assert_eq!(
+            Registration::from_flows(&[vec!["m.login.email.identity", "m.login.dummy"]]),
+            Registration::NeedsBrowser
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Reporting messages and rooms to the homeserver's admins. Servers that
+//! predate an endpoint answer that they do not know it, which is told
+//! apart from a failed report so ignoring the sender can still be offered.
+
+use anyhow::{anyhow, bail, Context, Result};
+use matrix_sdk::Client;
+use serde_json::{json, Value};
+
+/// What became of a report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reported {
+    /// The server took it.
+    Sent,
+    /// The server has no endpoint for it.
+    Unsupported,
+}
+
+/// Whether an error answer means the server does not know the endpoint,
+/// rather than refusing the report itself.
+pub(crate) fn is_unknown_endpoint(status: u16, errcode: Option<&str>) -> bool {
+    status == 405 || errcode == Some("M_UNRECOGNIZED") || (status == 404 && errcode.is_none())
+}
+
+/// Report `room_id`, e.g. as spam. Room reports are newer than the Matrix
+/// version our SDK speaks, so the request is made by hand.
+pub(crate) async fn report_room(
+    client: &Client,
+    room_id: &str,
+    reason: Option<&str>,
+) -> Result<Reported> {
+    let session = client.matrix_auth().session().context("Not logged in")?;
+    let mut url = client.homeserver();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid homeserver URL"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "rooms", room_id, "report"]);
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(session.tokens.access_token)
+        .json(&json!({ "reason": reason.unwrap_or("") }))
+        .send()
+        .await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(Reported::Sent);
+    }
+    let body: Value = response.json().await.unwrap_or_default();
+    let errcode = body.get("errcode").and_then(Value::as_str);
+    if is_unknown_endpoint(status.as_u16(), errcode) {
+        return Ok(Reported::Unsupported);
+    }
+    match body.get("error").and_then(Value::as_str) {
+        Some(error) => bail!("{}", error),
+        None => bail!("The server answered {}", status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_unknown_endpoint() {
+        assert!(is_unknown_endpoint(404, Some("M_UNRECOGNIZED")));
+        assert!(is_unknown_endpoint(400, Some("M_UNRECOGNIZED")));
+        assert!(is_unknown_endpoint(405, None));
+        assert!(is_unknown_endpoint(404, None));
+        // The room itself is unknown: a real failure.
+        assert!(!is_unknown_endpoint(404, Some("M_NOT_FOUND")));
+        assert!(!is_unknown_endpoint(403, Some("M_FORBIDDEN")));
+    }
+}
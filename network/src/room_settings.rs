@@ -0,0 +1,286 @@
+//! Room settings admins change from the room settings dialog: who can
+//! join, who can read the history, guest access, encryption, slow mode, the
+//! welcome message and aliases. Matrix has no way to turn encryption off
+//! once it is on.
+
+use crate::admin::admin_error;
+use crate::rooms::{self, SlowModeEventContent, WelcomeEventContent};
+use crate::welcome::Welcome;
+use matrix_sdk::ruma::events::room::guest_access::GuestAccess;
+use matrix_sdk::ruma::events::room::history_visibility::HistoryVisibility as SdkHistoryVisibility;
+use matrix_sdk::ruma::events::room::join_rules::JoinRule as SdkJoinRule;
+use matrix_sdk::ruma::events::{EventContent, StateEventType};
+use matrix_sdk::ruma::{OwnedRoomAliasId, UserId};
+use matrix_sdk::Room;
+use std::fmt;
+
+/// Who can join a room.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinRule {
+    /// Anyone who finds it.
+    Public,
+    /// Only those invited.
+    Invite,
+    /// Anyone can ask to, and members who can invite let them in.
+    Knock,
+    /// A rule we don't offer, e.g. members of a space; shown but not set.
+    Other,
+}
+
+impl JoinRule {
+    fn from_sdk(rule: &SdkJoinRule) -> Self {
+        match rule {
+            SdkJoinRule::Public => Self::Public,
+            SdkJoinRule::Invite => Self::Invite,
+            SdkJoinRule::Knock => Self::Knock,
+            _ => Self::Other,
+        }
+    }
+
+    /// `None` for `Other`.
+    pub(crate) fn to_sdk(self) -> Option<SdkJoinRule> {
+        match self {
+            Self::Public => Some(SdkJoinRule::Public),
+            Self::Invite => Some(SdkJoinRule::Invite),
+            Self::Knock => Some(SdkJoinRule::Knock),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Who can read a room's messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryVisibility {
+    /// Anyone, members or not.
+    WorldReadable,
+    /// Members, including what was sent before they joined.
+    Shared,
+    /// Members, from when they were invited.
+    Invited,
+    /// Members, from when they joined.
+    Joined,
+}
+
+impl HistoryVisibility {
+    fn from_sdk(visibility: &SdkHistoryVisibility) -> Self {
+        match visibility {
+            SdkHistoryVisibility::WorldReadable => Self::WorldReadable,
+            SdkHistoryVisibility::Invited => Self::Invited,
+            SdkHistoryVisibility::Joined => Self::Joined,
+            // Unknown values read as shared, as the spec says.
+            _ => Self::Shared,
+        }
+    }
+
+    pub(crate) fn to_sdk(self) -> SdkHistoryVisibility {
+        match self {
+            Self::WorldReadable => SdkHistoryVisibility::WorldReadable,
+            Self::Shared => SdkHistoryVisibility::Shared,
+            Self::Invited => SdkHistoryVisibility::Invited,
+            Self::Joined => SdkHistoryVisibility::Joined,
+        }
+    }
+}
+
+/// Which settings our power level lets us change.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SettingsPermissions {
+    pub join_rule: bool,
+    pub history_visibility: bool,
+    pub guest_access: bool,
+    /// False once the room is encrypted.
+    pub encryption: bool,
+    pub slow_mode: bool,
+    pub welcome: bool,
+}
+
+/// A room's settings, as the dialog shows them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomSettings {
+    pub join_rule: JoinRule,
+    pub history_visibility: HistoryVisibility,
+    /// Guests, accounts made without a password, may join.
+    pub guest_access: bool,
+    pub encrypted: bool,
+    /// Seconds between each member's messages; 0 for no slow mode.
+    pub slow_mode_secs: u64,
+    pub welcome: Option<Welcome>,
+    pub can_change: SettingsPermissions,
+}
+
+/// The settings of `room`, and which of them `user_id` may change.
+pub(crate) async fn load(room: &Room, user_id: &UserId) -> matrix_sdk::Result<RoomSettings> {
+    let encrypted = room.is_encrypted().await?;
+    let can_change = SettingsPermissions {
+        join_rule: room
+            .can_user_send_state(user_id, StateEventType::RoomJoinRules)
+            .await?,
+        history_visibility: room
+            .can_user_send_state(user_id, StateEventType::RoomHistoryVisibility)
+            .await?,
+        guest_access: room
+            .can_user_send_state(user_id, StateEventType::RoomGuestAccess)
+            .await?,
+        encryption: !encrypted
+            && room
+                .can_user_send_state(user_id, StateEventType::RoomEncryption)
+                .await?,
+        slow_mode: room
+            .can_user_send_state(user_id, SlowModeEventContent::default().event_type())
+            .await?,
+        welcome: room
+            .can_user_send_state(user_id, WelcomeEventContent::default().event_type())
+            .await?,
+    };
+    Ok(RoomSettings {
+        join_rule: JoinRule::from_sdk(&room.join_rule()),
+        history_visibility: HistoryVisibility::from_sdk(&room.history_visibility()),
+        guest_access: room.guest_access() == GuestAccess::CanJoin,
+        encrypted,
+        slow_mode_secs: rooms::slow_mode_secs(room).await?,
+        welcome: rooms::welcome(room).await?,
+        can_change,
+    })
+}
+
+/// Why an alias was turned down, shown under the alias field. Other
+/// failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AliasError {
+    Invalid,
+    /// Aliases are made on our own homeserver only.
+    OtherServer,
+    /// M_ROOM_IN_USE: another room has it.
+    Taken,
+}
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Invalid => "Aliases are a name without spaces, like #lobby",
+            Self::OtherServer => "Aliases can only be added on your own server",
+            Self::Taken => "This alias is already taken",
+        })
+    }
+}
+
+impl std::error::Error for AliasError {}
+
+/// The full alias for what was typed in the alias field: `lobby`, `#lobby`
+/// or `#lobby:example.org`, on our server `our_server`.
+pub fn parse_alias(input: &str, our_server: &str) -> Result<String, AliasError> {
+    let input = input.trim();
+    let input = input.strip_prefix('#').unwrap_or(input);
+    let (localpart, server) = input.split_once(':').unwrap_or((input, our_server));
+    if localpart.is_empty()
+        || localpart
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '#')
+    {
+        return Err(AliasError::Invalid);
+    }
+    if !server.eq_ignore_ascii_case(our_server) {
+        return Err(AliasError::OtherServer);
+    }
+    let alias = format!("#{}:{}", localpart, our_server);
+    // The spec's limit for any identifier.
+    if alias.len() > 255 {
+        return Err(AliasError::Invalid);
+    }
+    Ok(alias)
+}
+
+/// Turn a refusal of `action` into `AdminError::Forbidden`, and an alias
+/// another room has into `AliasError::Taken`.
+pub(crate) fn alias_error(e: matrix_sdk::Error, action: &'static str) -> anyhow::Error {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    match e.client_api_error_kind() {
+        Some(ErrorKind::RoomInUse) => AliasError::Taken.into(),
+        _ => admin_error(e, action),
+    }
+}
+
+/// A room's addresses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomAliases {
+    /// The main one, which the room is shown with.
+    pub canonical: Option<String>,
+    /// The main one first, then the others it lists and those published on
+    /// our server.
+    pub aliases: Vec<String>,
+    pub can_set_canonical: bool,
+}
+
+/// The aliases of `room`, with `published` from our server's directory.
+pub(crate) async fn load_aliases(
+    room: &Room,
+    user_id: &UserId,
+    published: Vec<OwnedRoomAliasId>,
+) -> matrix_sdk::Result<RoomAliases> {
+    let canonical = room.canonical_alias();
+    let mut aliases: Vec<String> = Vec::new();
+    for alias in canonical
+        .iter()
+        .chain(&room.alt_aliases())
+        .chain(&published)
+    {
+        let alias = alias.to_string();
+        if !aliases.contains(&alias) {
+            aliases.push(alias);
+        }
+    }
+    Ok(RoomAliases {
+        canonical: canonical.map(|a| a.to_string()),
+        aliases,
+        can_set_canonical: room
+            .can_user_send_state(user_id, StateEventType::RoomCanonicalAlias)
+            .await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_we_dont_offer_are_not_set() {
+        for rule in [JoinRule::Public, JoinRule::Invite, JoinRule::Knock] {
+            assert_eq!(JoinRule::from_sdk(&rule.to_sdk().unwrap()), rule);
+        }
+        assert_eq!(JoinRule::from_sdk(&SdkJoinRule::Private), JoinRule::Other);
+        assert_eq!(JoinRule::Other.to_sdk(), None);
+
+        let unknown = SdkHistoryVisibility::from("org.example.custom");
+        assert_eq!(
+            HistoryVisibility::from_sdk(&unknown),
+            HistoryVisibility::Shared
+        );
+        let joined = HistoryVisibility::Joined;
+        assert_eq!(HistoryVisibility::from_sdk(&joined.to_sdk()), joined);
+    }
+
+    #[test]
+    fn test_aliases_are_checked_before_sending() {
+        let ours = "example.org";
+        for input in [
+            "lobby",
+            "#lobby",
+            " #lobby:example.org ",
+            "#lobby:EXAMPLE.org",
+        ] {
+            assert_eq!(parse_alias(input, ours).unwrap(), "#lobby:example.org");
+        }
+        for input in ["", "#", "#lobby night", "##lobby", "#:example.org"] {
+            assert_eq!(parse_alias(input, ours), Err(AliasError::Invalid));
+        }
+        assert_eq!(
+            parse_alias("#lobby:matrix.org", ours),
+            Err(AliasError::OtherServer)
+        );
+        assert_eq!(
+            parse_alias(&"a".repeat(250), ours),
+            Err(AliasError::Invalid)
+        );
+    }
+}
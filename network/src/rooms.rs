@@ -0,0 +1,817 @@
+use crate::encryption;
+use crate::media;
+use crate::signaling::{CallHangupEventContent, HangupReason};
+use crate::stickers::{self, StickerPack, ROOM_PACK_TYPE};
+use crate::welcome::Welcome;
+use chat_core::game_invite::{self, GameInvite};
+use chat_core::mention::{self, Mention};
+use chat_core::{
+    MembershipChange, Message, MessageType, Reaction, Room, RoomType, Space, UnreadCounts,
+};
+use matrix_sdk::deserialized_responses::{
+    RawAnySyncOrStrippedState, RawSyncOrStrippedState, TimelineEvent,
+};
+use matrix_sdk::notification_settings::RoomNotificationMode;
+use matrix_sdk::ruma::events::macros::EventContent;
+use matrix_sdk::ruma::events::room::member;
+use matrix_sdk::ruma::events::room::message::{self, Relation, RoomMessageEventContent};
+use matrix_sdk::ruma::events::space::child::SpaceChildEventContent;
+use matrix_sdk::ruma::events::sticker::StickerEventContent;
+use matrix_sdk::ruma::events::{
+    AnyMessageLikeEvent, AnyStateEvent, AnyTimelineEvent, EmptyStateKey, Mentions,
+    MessageLikeEvent, OriginalMessageLikeEvent, StateEvent, StateEventType, StaticEventContent,
+    SyncStateEvent,
+};
+use matrix_sdk::ruma::serde::Raw;
+use matrix_sdk::ruma::{EventId, MilliSecondsSinceUnixEpoch, OwnedUserId, UserId};
+use matrix_sdk::sync::UnreadNotificationsCount;
+use matrix_sdk::{deserialized_responses::SyncOrStrippedState, Room as SdkRoom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// One page of room history, oldest message first.
+#[derive(Debug, Clone, Default)]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    /// Pagination token for the next (older) page; `None` at the start of the room.
+    pub prev_batch: Option<String>,
+}
+
+/// A page of newer history, fetched going forwards, oldest message first.
+#[derive(Debug, Clone, Default)]
+pub struct NewerPage {
+    pub messages: Vec<Message>,
+    /// Pagination token for the next (newer) page; `None` once the latest
+    /// message is reached.
+    pub next_batch: Option<String>,
+}
+
+/// The messages around one event, oldest first, and where to paginate
+/// from on either side of them.
+#[derive(Debug, Clone, Default)]
+pub struct ContextPage {
+    pub messages: Vec<Message>,
+    /// Token for the page before the oldest; `None` at the start of the room.
+    pub prev_batch: Option<String>,
+    /// Token for the page after the newest.
+    pub next_batch: Option<String>,
+}
+
+/// What we may do with messages in a room, from its power levels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Permissions {
+    pub send: bool,
+    pub react: bool,
+    /// Delete messages sent by others; our own can always be deleted.
+    pub redact_others: bool,
+    /// Change the room's topic.
+    pub topic: bool,
+    /// Pin and unpin messages.
+    pub pin: bool,
+    /// Seconds to wait after each message, when the room is in slow mode
+    /// and holds us to it; 0 otherwise.
+    pub slow_mode_secs: u64,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            send: true,
+            react: true,
+            redact_others: false,
+            topic: false,
+            pin: false,
+            slow_mode_secs: 0,
+        }
+    }
+}
+
+/// Longest slow mode taken from a room's state, six hours; longer ones are
+/// cut down to it rather than silencing a room for good.
+pub const MAX_SLOW_MODE_SECS: u64 = 6 * 60 * 60;
+
+/// A room's slow mode: each member may send one message per `seconds`.
+/// Moderators, who may delete others' messages, are not held to it.
+/// Nothing in the protocol enforces this; clients honour it, and others
+/// than ours don't. The state event, with an empty state key:
+///
+/// ```json
+/// { "type": "com.gamechat.slow_mode", "state_key": "", "content": { "seconds": 30 } }
+/// ```
+///
+/// 0, or the event missing, is no slow mode. Anyone with the power level
+/// for the event may send it, so what it holds is read leniently.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.gamechat.slow_mode", kind = State, state_key_type = EmptyStateKey)]
+pub struct SlowModeEventContent {
+    #[serde(default, deserialize_with = "lenient_seconds")]
+    pub seconds: u64,
+}
+
+/// `seconds` as sent by any client: a whole number up to
+/// `MAX_SLOW_MODE_SECS`, fractions dropped and longer ones cut down.
+/// Anything else, a negative number or a string, is no slow mode.
+fn lenient_seconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let seconds = match value.as_u64() {
+        Some(seconds) => seconds,
+        None => value
+            .as_f64()
+            .filter(|s| s.is_finite() && *s > 0.0)
+            .map_or(0, |s| s.min(MAX_SLOW_MODE_SECS as f64) as u64),
+    };
+    Ok(seconds.min(MAX_SLOW_MODE_SECS))
+}
+
+/// The slow mode set in `room`, in seconds; 0 for none.
+pub(crate) async fn slow_mode_secs(room: &SdkRoom) -> matrix_sdk::Result<u64> {
+    let Some(RawSyncOrStrippedState::Sync(raw)) = room
+        .get_state_event_static::<SlowModeEventContent>()
+        .await?
+    else {
+        return Ok(0);
+    };
+    Ok(match raw.deserialize() {
+        Ok(SyncStateEvent::Original(ev)) => ev.content.seconds,
+        _ => 0,
+    })
+}
+
+/// A room's welcome message, shown to members the first time they open it:
+///
+/// ```json
+/// { "type": "io.gamechat.welcome", "state_key": "", "content": {
+///     "body": "Be nice.", "format": "org.matrix.custom.html",
+///     "formatted_body": "<p>Be <b>nice</b>.</p>", "must_accept": true } }
+/// ```
+///
+/// The body and its formatting are as in a message. An empty body is no
+/// welcome; with `must_accept`, members accept it before sending.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "io.gamechat.welcome", kind = State, state_key_type = EmptyStateKey)]
+pub struct WelcomeEventContent {
+    #[serde(default)]
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted_body: Option<String>,
+    #[serde(default)]
+    pub must_accept: bool,
+}
+
+impl WelcomeEventContent {
+    /// A welcome as typed in the room settings, which is plain text.
+    pub fn plain(body: &str, must_accept: bool) -> Self {
+        Self {
+            body: body.trim().to_string(),
+            must_accept,
+            ..Self::default()
+        }
+    }
+
+    /// The welcome set by event `event_id`; formatting other than HTML is
+    /// left out.
+    pub fn into_welcome(self, event_id: String) -> Welcome {
+        let html = self.format.as_deref() == Some("org.matrix.custom.html");
+        Welcome {
+            event_id,
+            body: self.body,
+            formatted: self.formatted_body.filter(|_| html),
+            must_accept: self.must_accept,
+        }
+    }
+}
+
+/// The welcome set in `room`, if any.
+pub(crate) async fn welcome(room: &SdkRoom) -> matrix_sdk::Result<Option<Welcome>> {
+    let Some(RawSyncOrStrippedState::Sync(raw)) =
+        room.get_state_event_static::<WelcomeEventContent>().await?
+    else {
+        return Ok(None);
+    };
+    Ok(match raw.deserialize() {
+        Ok(SyncStateEvent::Original(ev)) => {
+            let welcome = ev.content.into_welcome(ev.event_id.to_string());
+            (!welcome.is_empty()).then_some(welcome)
+        }
+        _ => None,
+    })
+}
+
+/// The sticker packs in `room`'s state, or just the one under `state_key`.
+/// Packs without a name take the room's.
+pub(crate) async fn sticker_packs(
+    room: &SdkRoom,
+    state_key: Option<&str>,
+) -> matrix_sdk::Result<Vec<StickerPack>> {
+    let event_type = StateEventType::from(ROOM_PACK_TYPE);
+    let events = match state_key {
+        Some(key) => room
+            .get_state_event(event_type, key)
+            .await?
+            .into_iter()
+            .collect(),
+        None => room.get_state_events(event_type).await?,
+    };
+    let name = room
+        .display_name()
+        .await
+        .map(|n| n.to_string())
+        .unwrap_or_else(|_| room.room_id().to_string());
+    Ok(events
+        .into_iter()
+        .filter_map(|event| match event {
+            RawAnySyncOrStrippedState::Sync(raw) => raw.get_field("content").ok()?,
+            RawAnySyncOrStrippedState::Stripped(_) => None,
+        })
+        .filter_map(|content| stickers::parse_pack(&content, &name))
+        .collect())
+}
+
+/// Convert an SDK room into the shared `Room` model.
+pub(crate) async fn room_to_core(room: &SdkRoom, last_activity: u64) -> Room {
+    let name = room
+        .display_name()
+        .await
+        .map(|n| n.to_string())
+        .unwrap_or_else(|_| room.room_id().to_string());
+
+    let room_type = if room.is_space() {
+        RoomType::Space
+    } else if room.is_direct().await.unwrap_or(false) {
+        RoomType::Direct
+    } else if room.is_public() {
+        RoomType::Public
+    } else {
+        RoomType::Group
+    };
+
+    Room {
+        id: room.room_id().to_string(),
+        name,
+        topic: room.topic(),
+        room_type,
+        avatar_url: room.avatar_url().map(|u| u.to_string()),
+        last_activity,
+        unread: unread_counts(&room.unread_notification_counts()),
+        muted: matches!(
+            room.notification_mode().await,
+            Some(RoomNotificationMode::Mute)
+        ),
+        alias: room.canonical_alias().map(|a| a.to_string()),
+        direct_targets: room
+            .direct_targets()
+            .iter()
+            .map(|u| u.to_string())
+            .collect(),
+        encrypted: room.is_encrypted().await.unwrap_or(false),
+        member_count: room.joined_members_count(),
+    }
+}
+
+pub(crate) fn unread_counts(counts: &UnreadNotificationsCount) -> UnreadCounts {
+    UnreadCounts {
+        notifications: counts.notification_count,
+        highlights: counts.highlight_count,
+    }
+}
+
+/// Convert an SDK space room into a `Space`, resolving its children.
+pub(crate) async fn space_to_core(room: &SdkRoom) -> Space {
+    let core = room_to_core(room, 0).await;
+    Space {
+        id: core.id,
+        name: core.name,
+        avatar_url: core.avatar_url,
+        children: space_children(room).await,
+    }
+}
+
+/// Room ids listed as children of a space. Entries with an empty `via`
+/// are tombstones for removed children and are skipped.
+pub(crate) async fn space_children(room: &SdkRoom) -> Vec<String> {
+    let Ok(events) = room
+        .get_state_events_static::<SpaceChildEventContent>()
+        .await
+    else {
+        return Vec::new();
+    };
+
+    events
+        .into_iter()
+        .filter_map(|raw| match raw.deserialize().ok()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(ev))
+                if !ev.content.via.is_empty() =>
+            {
+                Some(ev.state_key.to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// What kind of message `msgtype` is, for one sent at `sent_ts`.
+pub(crate) fn message_type(msgtype: &message::MessageType, sent_ts: u64) -> MessageType {
+    if let Some(invite) = parse_game_invite(msgtype, sent_ts) {
+        return MessageType::GameInvite(invite);
+    }
+    match msgtype {
+        message::MessageType::Image(_) => MessageType::Image,
+        // Voice messages among them, offered as a file to play elsewhere.
+        message::MessageType::File(_) | message::MessageType::Audio(_) => MessageType::File,
+        message::MessageType::Emote(_) => MessageType::Emote,
+        _ => MessageType::Text,
+    }
+}
+
+/// The invite a message of `msgtype` sent at `sent_ts` carries, if it is
+/// one and a valid one. Invalid invites are shown as their body alone.
+fn parse_game_invite(msgtype: &message::MessageType, sent_ts: u64) -> Option<GameInvite> {
+    if msgtype.msgtype() != game_invite::MSGTYPE {
+        return None;
+    }
+    let data = serde_json::Value::Object(msgtype.data().into_owned());
+    let invite: GameInvite = serde_json::from_value(data).ok()?;
+    invite.validate(sent_ts).ok()?;
+    Some(invite)
+}
+
+/// A message carrying `invite`, with a plain text body for other clients.
+pub(crate) fn game_invite_content(
+    invite: &GameInvite,
+    now: u64,
+) -> anyhow::Result<RoomMessageEventContent> {
+    let serde_json::Value::Object(data) = serde_json::to_value(invite)? else {
+        anyhow::bail!("A game invite must serialize to an object");
+    };
+    let msgtype = message::MessageType::new(game_invite::MSGTYPE, invite.fallback_body(now), data)?;
+    Ok(RoomMessageEventContent::new(msgtype))
+}
+
+/// The HTML body of a text, notice or emote message, if it has one.
+pub(crate) fn formatted_body(msgtype: &message::MessageType) -> Option<String> {
+    let formatted = match msgtype {
+        message::MessageType::Text(c) => c.formatted.as_ref(),
+        message::MessageType::Notice(c) => c.formatted.as_ref(),
+        message::MessageType::Emote(c) => c.formatted.as_ref(),
+        _ => None,
+    }?;
+    (formatted.format == message::MessageFormat::Html).then(|| formatted.body.clone())
+}
+
+pub(crate) fn message_to_core(
+    event_id: &EventId,
+    sender: &UserId,
+    ts: MilliSecondsSinceUnixEpoch,
+    content: &RoomMessageEventContent,
+) -> Message {
+    let reply_to = match &content.relates_to {
+        Some(Relation::Reply { in_reply_to }) => Some(in_reply_to.event_id.to_string()),
+        _ => None,
+    };
+    let body = if reply_to.is_some() {
+        chat_core::strip_reply_fallback(content.body())
+    } else {
+        content.body()
+    };
+    Message {
+        id: event_id.to_string(),
+        sender: sender.to_string(),
+        sender_name: None,
+        content: body.to_string(),
+        schema: message_type(&content.msgtype, ts.0.into()),
+        timestamp: ts.0.into(),
+        reply_to,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: media::attachment(&content.msgtype),
+        mentions: Vec::new(),
+        formatted: formatted_body(&content.msgtype),
+        trust: None,
+    }
+}
+
+/// The timeline entry for a call `sender` let ring out.
+pub(crate) fn missed_call(
+    event_id: &EventId,
+    sender: &UserId,
+    ts: MilliSecondsSinceUnixEpoch,
+) -> Message {
+    Message {
+        id: event_id.to_string(),
+        sender: sender.to_string(),
+        sender_name: None,
+        content: "Missed call".to_string(),
+        schema: MessageType::MissedCall,
+        timestamp: ts.0.into(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    }
+}
+
+/// A sticker, shown as the image it is.
+pub(crate) fn sticker_to_core(
+    event_id: &EventId,
+    sender: &UserId,
+    ts: MilliSecondsSinceUnixEpoch,
+    content: &StickerEventContent,
+) -> Message {
+    Message {
+        id: event_id.to_string(),
+        sender: sender.to_string(),
+        sender_name: None,
+        content: content.body.clone(),
+        schema: MessageType::Image,
+        timestamp: ts.0.into(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: Some(media::sticker_event(content)),
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    }
+}
+
+/// The timeline row for a change to `user_id`'s membership or profile,
+/// unless the timeline has none for it, e.g. a knock. `name` is their
+/// display name as the event left it, or as it was before.
+pub(crate) fn member_change_to_core(
+    event_id: &EventId,
+    user_id: &UserId,
+    name: Option<&str>,
+    ts: MilliSecondsSinceUnixEpoch,
+    change: &member::MembershipChange<'_>,
+) -> Option<Message> {
+    use member::MembershipChange as Change;
+    let who = name.unwrap_or_else(|| chat_core::localpart(user_id.as_str()));
+    let membership = |change, verb| (MessageType::Membership(change), format!("{} {}", who, verb));
+    let (schema, content) = match change {
+        Change::Joined | Change::InvitationAccepted => {
+            membership(MembershipChange::Joined, "joined")
+        }
+        Change::Left | Change::InvitationRejected | Change::KnockRetracted => {
+            membership(MembershipChange::Left, "left")
+        }
+        Change::Invited | Change::KnockAccepted => {
+            membership(MembershipChange::Invited, "was invited")
+        }
+        Change::Kicked
+        | Change::Banned
+        | Change::KickedAndBanned
+        | Change::InvitationRevoked
+        | Change::KnockDenied => membership(MembershipChange::Removed, "was removed"),
+        Change::ProfileChanged {
+            displayname_change: Some(names),
+            ..
+        } => {
+            let old = names
+                .old
+                .unwrap_or_else(|| chat_core::localpart(user_id.as_str()));
+            let content = match names.new {
+                Some(new) => format!("{} is now {}", old, new),
+                None => format!("{} removed their display name", old),
+            };
+            (MessageType::DisplayName, content)
+        }
+        Change::ProfileChanged {
+            avatar_url_change: Some(_),
+            ..
+        } => (MessageType::Avatar, format!("{} changed their avatar", who)),
+        _ => return None,
+    };
+    Some(Message {
+        id: event_id.to_string(),
+        sender: user_id.to_string(),
+        sender_name: name.map(str::to_owned),
+        content,
+        schema,
+        timestamp: ts.0.into(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    })
+}
+
+/// `reaction` to message `target` as a row of its own, for timelines that
+/// show reactions so.
+pub(crate) fn reaction_row(
+    target: &str,
+    reaction: &Reaction,
+    ts: MilliSecondsSinceUnixEpoch,
+) -> Message {
+    Message {
+        id: reaction.event_id.clone(),
+        sender: reaction.sender.clone(),
+        sender_name: None,
+        content: reaction.key.clone(),
+        schema: MessageType::Reaction,
+        timestamp: ts.0.into(),
+        reply_to: Some(target.to_string()),
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    }
+}
+
+/// A hangup of a call that rang out, as its timeline entry. Other hangups
+/// and call events don't show.
+fn missed_call_event(raw: &Raw<AnyTimelineEvent>) -> Option<Message> {
+    let event_type: String = raw.get_field("type").ok()??;
+    if event_type != CallHangupEventContent::TYPE {
+        return None;
+    }
+    let ev = raw
+        .deserialize_as::<OriginalMessageLikeEvent<CallHangupEventContent>>()
+        .ok()?;
+    (ev.content.reason == HangupReason::Missed)
+        .then(|| missed_call(&ev.event_id, &ev.sender, ev.origin_server_ts))
+}
+
+/// A text message, with an HTML body linking any members it mentions so
+/// their clients highlight it.
+pub(crate) fn text_content(body: &str, mentions: &[Mention]) -> RoomMessageEventContent {
+    if mentions.is_empty() {
+        return RoomMessageEventContent::text_plain(body);
+    }
+    let mut content = RoomMessageEventContent::text_html(body, mention::html_body(body, mentions));
+    let user_ids = mentions
+        .iter()
+        .filter_map(|m| OwnedUserId::try_from(m.user_id.as_str()).ok());
+    content.mentions = Some(Mentions::with_user_ids(user_ids));
+    content
+}
+
+/// A message's new body, from an edit.
+pub(crate) struct Replacement {
+    /// The edited message.
+    pub event_id: String,
+    pub body: String,
+    pub formatted: Option<String>,
+}
+
+/// What `content` changes, if it is an edit.
+pub(crate) fn replacement(content: &RoomMessageEventContent) -> Option<Replacement> {
+    match &content.relates_to {
+        Some(Relation::Replacement(r)) => Some(Replacement {
+            event_id: r.event_id.to_string(),
+            body: r.new_content.msgtype.body().to_string(),
+            formatted: formatted_body(&r.new_content.msgtype),
+        }),
+        _ => None,
+    }
+}
+
+/// The member's display name in `room`, from the local store only.
+pub(crate) async fn member_name(room: &SdkRoom, user_id: &UserId) -> Option<String> {
+    room.get_member_no_sync(user_id)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|m| m.display_name().map(str::to_owned))
+}
+
+/// Fill in `sender_name` for a page of messages, looking each sender up once.
+pub(crate) async fn resolve_sender_names(room: &SdkRoom, messages: &mut [Message]) {
+    let mut names: HashMap<String, Option<String>> = HashMap::new();
+    for message in messages.iter_mut() {
+        if !names.contains_key(&message.sender) {
+            let name = match <&UserId>::try_from(message.sender.as_str()) {
+                Ok(user_id) => member_name(room, user_id).await,
+                Err(_) => None,
+            };
+            names.insert(message.sender.clone(), name);
+        }
+        message.sender_name = names[&message.sender].clone();
+    }
+}
+
+/// What a paginated timeline event does to a page of messages.
+enum PageEvent {
+    Message(Box<Message>),
+    Edit {
+        sender: String,
+        replacement: Replacement,
+    },
+    Reaction {
+        target: String,
+        reaction: Reaction,
+        ts: MilliSecondsSinceUnixEpoch,
+    },
+}
+
+fn page_event(event: &TimelineEvent) -> Option<PageEvent> {
+    let page_event = match event.event.deserialize().ok()? {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(ev),
+        )) => Some(match replacement(&ev.content) {
+            Some(replacement) => PageEvent::Edit {
+                sender: ev.sender.to_string(),
+                replacement,
+            },
+            None => PageEvent::Message(Box::new(message_to_core(
+                &ev.event_id,
+                &ev.sender,
+                ev.origin_server_ts,
+                &ev.content,
+            ))),
+        }),
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::Sticker(
+            MessageLikeEvent::Original(ev),
+        )) => Some(PageEvent::Message(Box::new(sticker_to_core(
+            &ev.event_id,
+            &ev.sender,
+            ev.origin_server_ts,
+            &ev.content,
+        )))),
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::Reaction(
+            MessageLikeEvent::Original(ev),
+        )) => Some(PageEvent::Reaction {
+            target: ev.content.relates_to.event_id.to_string(),
+            reaction: Reaction {
+                key: ev.content.relates_to.key,
+                sender: ev.sender.to_string(),
+                event_id: ev.event_id.to_string(),
+            },
+            ts: ev.origin_server_ts,
+        }),
+        AnyTimelineEvent::State(AnyStateEvent::RoomMember(StateEvent::Original(ev))) => {
+            let name = ev
+                .content
+                .displayname
+                .as_deref()
+                .or_else(|| ev.prev_content()?.displayname.as_deref());
+            member_change_to_core(
+                &ev.event_id,
+                &ev.state_key,
+                name,
+                ev.origin_server_ts,
+                &ev.membership_change(),
+            )
+            .map(|message| PageEvent::Message(Box::new(message)))
+        }
+        _ => missed_call_event(&event.event).map(|message| PageEvent::Message(Box::new(message))),
+    };
+    match (page_event, &event.encryption_info) {
+        (Some(PageEvent::Message(mut message)), Some(info)) => {
+            message.trust = Some(encryption::message_trust(info, false));
+            Some(PageEvent::Message(message))
+        }
+        (page_event, _) => page_event,
+    }
+}
+
+/// Extract a `Message` from a paginated timeline event, if it is one.
+pub(crate) fn timeline_message(event: &TimelineEvent) -> Option<Message> {
+    match page_event(event)? {
+        PageEvent::Message(message) => Some(*message),
+        _ => None,
+    }
+}
+
+/// The messages of a page of timeline events given newest first, as
+/// backward pagination returns them, oldest first. Edits and reactions to
+/// messages in the page are applied; ones to older messages are dropped.
+pub(crate) fn page_messages(events: &[TimelineEvent]) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    for event in events.iter().rev().filter_map(page_event) {
+        match event {
+            PageEvent::Message(message) => messages.push(*message),
+            PageEvent::Edit {
+                sender,
+                replacement,
+            } => {
+                // Only the sender may edit a message.
+                if let Some(m) = messages
+                    .iter_mut()
+                    .find(|m| m.id == replacement.event_id && m.sender == sender)
+                {
+                    m.content = replacement.body;
+                    m.formatted = replacement.formatted;
+                    m.edited = true;
+                }
+            }
+            PageEvent::Reaction {
+                target,
+                reaction,
+                ts,
+            } => {
+                let row = reaction_row(&target, &reaction, ts);
+                if let Some(m) = messages.iter_mut().find(|m| m.id == target) {
+                    m.reactions.push(reaction);
+                }
+                messages.push(row);
+            }
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn seconds(content: serde_json::Value) -> u64 {
+        serde_json::from_value::<SlowModeEventContent>(content)
+            .unwrap()
+            .seconds
+    }
+
+    #[test]
+    fn test_welcome_is_read_and_written_as_state() {
+        let content = WelcomeEventContent::plain("  Be nice.\n", true);
+        assert_eq!(
+            serde_json::to_value(&content).unwrap(),
+            json!({ "body": "Be nice.", "must_accept": true })
+        );
+        let welcome = content.into_welcome("$rules".to_string());
+        assert_eq!(welcome.body, "Be nice.");
+        assert!(welcome.must_accept);
+
+        let formatted: WelcomeEventContent = serde_json::from_value(json!({
+            "body": "Be nice.",
+            "format": "org.matrix.custom.html",
+            "formatted_body": "<p>Be <b>nice</b>.</p>",
+        }))
+        .unwrap();
+        let welcome = formatted.into_welcome("$rules".to_string());
+        assert_eq!(welcome.formatted.as_deref(), Some("<p>Be <b>nice</b>.</p>"));
+        assert!(!welcome.must_accept);
+
+        let other: WelcomeEventContent = serde_json::from_value(json!({
+            "body": "Be nice.",
+            "format": "org.example.markdown",
+            "formatted_body": "Be **nice**.",
+        }))
+        .unwrap();
+        assert_eq!(other.into_welcome("$rules".to_string()).formatted, None);
+
+        let empty: WelcomeEventContent = serde_json::from_value(json!({})).unwrap();
+        assert!(empty.into_welcome("$removed".to_string()).is_empty());
+    }
+
+    #[test]
+    fn test_slow_mode_is_read_leniently() {
+        assert_eq!(seconds(json!({"seconds": 30})), 30);
+        assert_eq!(seconds(json!({})), 0);
+        assert_eq!(seconds(json!({"seconds": 12.9})), 12);
+        assert_eq!(seconds(json!({"seconds": 10_000_000})), MAX_SLOW_MODE_SECS);
+        assert_eq!(seconds(json!({"seconds": 1e300})), MAX_SLOW_MODE_SECS);
+        for junk in [
+            json!(-5),
+            json!("30"),
+            json!(null),
+            json!(true),
+            json!([30]),
+        ] {
+            assert_eq!(seconds(json!({ "seconds": junk })), 0, "{}", junk);
+        }
+    }
+
+    #[test]
+    fn test_game_invite_round_trips_with_a_fallback_body() {
+        let sent = 1_710_504_000_000;
+        let invite = GameInvite {
+            game: "Valorant".to_string(),
+            connect: "steam://connect/203.0.113.7:27015".to_string(),
+            party_size: Some(5),
+            expires_ts: sent + 30 * 60 * 1000,
+        };
+        let content = game_invite_content(&invite, sent).unwrap();
+        let json = serde_json::to_value(&content).unwrap();
+        assert_eq!(json["msgtype"], game_invite::MSGTYPE);
+        assert_eq!(json["body"], invite.fallback_body(sent));
+        assert_eq!(json["connect"], "steam://connect/203.0.113.7:27015");
+
+        let received: RoomMessageEventContent = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(
+            message_type(&received.msgtype, sent),
+            MessageType::GameInvite(invite)
+        );
+
+        // Sent already expired, or missing fields: shown as text.
+        let late = sent + 31 * 60 * 1000;
+        assert_eq!(message_type(&received.msgtype, late), MessageType::Text);
+        let broken: RoomMessageEventContent = serde_json::from_value(json!({
+            "msgtype": game_invite::MSGTYPE,
+            "body": "Game invite: Valorant",
+            "game": "Valorant",
+        }))
+        .unwrap();
+        assert_eq!(message_type(&broken.msgtype, sent), MessageType::Text);
+    }
+}
@@ -0,0 +1,621 @@
+//! The friends list. Friends are kept in our own account data, so the list
+//! follows the account to other devices, and each friendship is agreed on
+//! through a handshake of `io.gamechat.friend` events in the direct chat
+//! with them: a request, then an accept, a decline or later a removal.
+//!
+//! Devices may change the list at once. Each entry carries when it last
+//! changed, and lists are merged entry by entry with the later change
+//! winning, so two devices adding different friends both keep theirs. A
+//! removal stays in the list as such for a while, so it wins over the
+//! entry an older copy still has.
+
+use chat_core::{time, UserStatus};
+use matrix_sdk::ruma::events::macros::EventContent;
+use matrix_sdk::ruma::UserId;
+use matrix_sdk::{Client, Room};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Account data event type holding the friends list.
+pub const ROSTER_EVENT_TYPE: &str = "io.gamechat.roster";
+
+/// How long a removal is remembered before it is dropped from the list.
+const REMOVAL_KEPT_MS: u64 = 30 * 24 * 60 * 60 * 1000;
+
+/// Handshake ids are ours to make, so anything else is refused.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Where a friendship stands. Ordered so that of two changes made in the
+/// same millisecond the further along wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendState {
+    /// Removed, declined or withdrawn.
+    Removed,
+    /// We asked; waiting for them.
+    Requested,
+    /// They asked; waiting for us.
+    Incoming,
+    Accepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RosterEntry {
+    pub state: FriendState,
+    /// The handshake the entry follows.
+    pub request_id: String,
+    /// The direct chat the handshake goes through.
+    pub room_id: String,
+    /// When it last changed, in milliseconds since the Unix epoch.
+    pub updated_ts: u64,
+}
+
+impl RosterEntry {
+    /// Whether this change wins over `other`: the later one does.
+    fn supersedes(&self, other: &RosterEntry) -> bool {
+        (self.updated_ts, self.state, &self.request_id)
+            > (other.updated_ts, other.state, &other.request_id)
+    }
+}
+
+/// Content of the `io.gamechat.roster` account data event.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Roster {
+    /// By user id.
+    #[serde(default)]
+    pub friends: BTreeMap<String, RosterEntry>,
+}
+
+impl Roster {
+    /// `user_id`'s entry, unless they were removed.
+    pub fn entry(&self, user_id: &str) -> Option<&RosterEntry> {
+        self.friends
+            .get(user_id)
+            .filter(|entry| entry.state != FriendState::Removed)
+    }
+
+    /// Everyone on the list, removals left out.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &RosterEntry)> {
+        self.friends
+            .iter()
+            .filter(|(_, entry)| entry.state != FriendState::Removed)
+            .map(|(user_id, entry)| (user_id.as_str(), entry))
+    }
+
+    /// Set `user_id`'s entry, unless the one there changed later. False
+    /// if it was kept.
+    pub fn set(&mut self, user_id: &str, entry: RosterEntry) -> bool {
+        match self.friends.get(user_id) {
+            Some(current) if !entry.supersedes(current) => false,
+            _ => {
+                self.friends.insert(user_id.to_string(), entry);
+                true
+            }
+        }
+    }
+
+    /// Take in `other`'s entries where they changed later than ours.
+    /// False if nothing changed.
+    pub fn merge(&mut self, other: &Roster) -> bool {
+        let mut changed = false;
+        for (user_id, entry) in &other.friends {
+            changed |= self.set(user_id, entry.clone());
+        }
+        changed
+    }
+
+    /// Forget removals older than a month, so the list doesn't grow
+    /// forever.
+    pub fn prune(&mut self, now_ms: u64) {
+        self.friends.retain(|_, entry| {
+            entry.state != FriendState::Removed
+                || now_ms.saturating_sub(entry.updated_ts) < REMOVAL_KEPT_MS
+        });
+    }
+
+    /// When a change made locally at `now_ms` is taken to be: after the
+    /// last one, as that came with the server's time and our clock may be
+    /// behind it.
+    fn change_ts(&self, user_id: &str, now_ms: u64) -> u64 {
+        match self.friends.get(user_id) {
+            Some(entry) => now_ms.max(entry.updated_ts + 1),
+            None => now_ms,
+        }
+    }
+
+    fn change(&mut self, user_id: &str, state: FriendState, now_ms: u64) -> Option<RosterEntry> {
+        let mut entry = self.entry(user_id)?.clone();
+        entry.state = state;
+        entry.updated_ts = self.change_ts(user_id, now_ms);
+        self.set(user_id, entry.clone()).then_some(entry)
+    }
+
+    /// Ask `user_id` to be friends through `room_id`, as handshake
+    /// `request_id`. If they asked first, this accepts instead. `None`
+    /// if we are friends already.
+    pub fn request(
+        &mut self,
+        user_id: &str,
+        room_id: &str,
+        request_id: &str,
+        now_ms: u64,
+    ) -> Option<FriendEventContent> {
+        match self.entry(user_id).map(|entry| entry.state) {
+            Some(FriendState::Accepted) => None,
+            Some(FriendState::Incoming) => self.accept(user_id, now_ms),
+            _ => {
+                let entry = RosterEntry {
+                    state: FriendState::Requested,
+                    request_id: request_id.to_string(),
+                    room_id: room_id.to_string(),
+                    updated_ts: self.change_ts(user_id, now_ms),
+                };
+                self.set(user_id, entry);
+                Some(FriendEventContent::new(
+                    HandshakeAction::Request,
+                    request_id,
+                    user_id,
+                ))
+            }
+        }
+    }
+
+    /// Accept `user_id`'s request.
+    pub fn accept(&mut self, user_id: &str, now_ms: u64) -> Option<FriendEventContent> {
+        if self.entry(user_id)?.state != FriendState::Incoming {
+            return None;
+        }
+        let entry = self.change(user_id, FriendState::Accepted, now_ms)?;
+        Some(FriendEventContent::new(
+            HandshakeAction::Accept,
+            &entry.request_id,
+            user_id,
+        ))
+    }
+
+    /// Turn `user_id`'s request down.
+    pub fn decline(&mut self, user_id: &str, now_ms: u64) -> Option<FriendEventContent> {
+        if self.entry(user_id)?.state != FriendState::Incoming {
+            return None;
+        }
+        let entry = self.change(user_id, FriendState::Removed, now_ms)?;
+        Some(FriendEventContent::new(
+            HandshakeAction::Decline,
+            &entry.request_id,
+            user_id,
+        ))
+    }
+
+    /// Remove `user_id`, or withdraw our request to them.
+    pub fn remove(&mut self, user_id: &str, now_ms: u64) -> Option<FriendEventContent> {
+        if self.entry(user_id)?.state == FriendState::Incoming {
+            return self.decline(user_id, now_ms);
+        }
+        let entry = self.change(user_id, FriendState::Removed, now_ms)?;
+        Some(FriendEventContent::new(
+            HandshakeAction::Remove,
+            &entry.request_id,
+            user_id,
+        ))
+    }
+
+    /// Take in handshake `content` that `sender` sent in `room_id` at
+    /// `sent_ts`, already validated. Returns whether the list changed, and
+    /// what to answer: a request from a friend, or one crossing ours, is
+    /// accepted at once.
+    pub fn receive(
+        &mut self,
+        sender: &str,
+        room_id: &str,
+        content: &FriendEventContent,
+        sent_ts: u64,
+    ) -> (bool, Option<FriendEventContent>) {
+        let current = self.entry(sender).cloned();
+        let matches = |entry: &RosterEntry| entry.request_id == content.request_id;
+        let (state, request_id, answer) = match (content.action, &current) {
+            // Seen already, e.g. as the room's history is loaded again.
+            (HandshakeAction::Request, Some(entry)) if matches(entry) => return (false, None),
+            (HandshakeAction::Request, Some(entry))
+                if matches!(entry.state, FriendState::Accepted | FriendState::Requested) =>
+            {
+                let answer =
+                    FriendEventContent::new(HandshakeAction::Accept, &content.request_id, sender);
+                (FriendState::Accepted, &content.request_id, Some(answer))
+            }
+            (HandshakeAction::Request, _) => (FriendState::Incoming, &content.request_id, None),
+            (HandshakeAction::Accept, Some(entry))
+                if entry.state == FriendState::Requested && matches(entry) =>
+            {
+                (FriendState::Accepted, &entry.request_id, None)
+            }
+            (HandshakeAction::Decline, Some(entry))
+                if entry.state == FriendState::Requested && matches(entry) =>
+            {
+                (FriendState::Removed, &entry.request_id, None)
+            }
+            (HandshakeAction::Remove, Some(entry)) if matches(entry) => {
+                (FriendState::Removed, &entry.request_id, None)
+            }
+            _ => return (false, None),
+        };
+        let entry = RosterEntry {
+            state,
+            request_id: request_id.clone(),
+            room_id: room_id.to_string(),
+            updated_ts: sent_ts,
+        };
+        let changed = self.set(sender, entry);
+        (changed, answer.filter(|_| changed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandshakeAction {
+    Request,
+    Accept,
+    Decline,
+    Remove,
+}
+
+/// One step of the handshake, sent in the direct chat.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "io.gamechat.friend", kind = MessageLike)]
+pub struct FriendEventContent {
+    pub action: HandshakeAction,
+    /// The request the step answers, or that it makes.
+    pub request_id: String,
+    /// Who the step is for, as a direct chat may hold others.
+    pub to: String,
+}
+
+/// Why a handshake event was ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandshakeError {
+    /// The request id is empty, too long or has characters ids never have.
+    BadRequestId,
+    /// It isn't addressed to a valid user id.
+    BadRecipient,
+    /// It is for someone else.
+    NotForUs,
+    /// Sent to oneself.
+    FromSelf,
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::BadRequestId => "The friend request has an invalid id",
+            Self::BadRecipient => "The friend request isn't addressed to a user",
+            Self::NotForUs => "The friend request is for someone else",
+            Self::FromSelf => "You can't be friends with yourself",
+        })
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl FriendEventContent {
+    pub fn new(action: HandshakeAction, request_id: &str, to: &str) -> Self {
+        Self {
+            action,
+            request_id: request_id.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    /// Check what serde can't: the request id's form, and that `sender`
+    /// sent it to `own_user_id`.
+    pub fn validate(&self, sender: &str, own_user_id: &str) -> Result<(), HandshakeError> {
+        let id_ok = !self.request_id.is_empty()
+            && self.request_id.len() <= MAX_REQUEST_ID_LEN
+            && self
+                .request_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !id_ok {
+            return Err(HandshakeError::BadRequestId);
+        }
+        if <&UserId>::try_from(self.to.as_str()).is_err() {
+            return Err(HandshakeError::BadRecipient);
+        }
+        if self.to != own_user_id {
+            return Err(HandshakeError::NotForUs);
+        }
+        if sender == own_user_id {
+            return Err(HandshakeError::FromSelf);
+        }
+        Ok(())
+    }
+}
+
+/// Someone on the list, as shown in the friends panel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Friend {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    pub state: FriendState,
+    /// The direct chat with them.
+    pub room_id: String,
+    pub status: UserStatus,
+    /// E.g. "Playing Valorant".
+    pub status_message: Option<String>,
+}
+
+impl Friend {
+    pub fn name(&self) -> &str {
+        self.display_name
+            .as_deref()
+            .unwrap_or_else(|| chat_core::localpart(&self.user_id))
+    }
+}
+
+/// The list as another device last stored it; `None` if none has yet.
+pub(crate) async fn load(client: &Client) -> anyhow::Result<Option<Roster>> {
+    use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+
+    let raw = client
+        .account()
+        .account_data_raw(GlobalAccountDataEventType::from(ROSTER_EVENT_TYPE))
+        .await?;
+    match raw {
+        Some(raw) => Ok(Some(raw.deserialize_as::<Roster>()?)),
+        None => Ok(None),
+    }
+}
+
+/// Store `roster` for our other devices.
+pub(crate) async fn store(client: &Client, roster: &Roster) -> anyhow::Result<()> {
+    use matrix_sdk::ruma::events::GlobalAccountDataEventType;
+    use matrix_sdk::ruma::serde::Raw;
+
+    let content = Raw::new(roster)?.cast();
+    client
+        .account()
+        .set_account_data_raw(GlobalAccountDataEventType::from(ROSTER_EVENT_TYPE), content)
+        .await?;
+    Ok(())
+}
+
+/// Store our list, after taking in what other devices stored since, so
+/// their changes aren't written over.
+pub(crate) async fn save(client: &Client, roster: &Mutex<Roster>) -> anyhow::Result<()> {
+    let stored = load(client).await?.unwrap_or_default();
+    let merged = {
+        let mut roster = roster.lock().unwrap();
+        roster.merge(&stored);
+        roster.prune(time::now_ms());
+        roster.clone()
+    };
+    if merged != stored {
+        store(client, &merged).await?;
+    }
+    Ok(())
+}
+
+/// Take in the list another device stored. If ours has changes it lacks,
+/// e.g. as both changed it at once, the merged list is stored back, so
+/// every device ends up with the same. False if ours didn't change.
+pub(crate) async fn stored_elsewhere(
+    client: &Client,
+    roster: &Mutex<Roster>,
+    mut stored: Roster,
+) -> anyhow::Result<bool> {
+    let now_ms = time::now_ms();
+    stored.prune(now_ms);
+    let (changed, merged) = {
+        let mut roster = roster.lock().unwrap();
+        let changed = roster.merge(&stored);
+        roster.prune(now_ms);
+        (changed, roster.clone())
+    };
+    if stored.merge(&merged) {
+        store(client, &merged).await?;
+    }
+    Ok(changed)
+}
+
+/// Take in handshake `content` that `sender` sent in `room` at `sent_ts`,
+/// answer it if it calls for that, and store the list. False if it changed
+/// nothing.
+pub(crate) async fn handshake_received(
+    client: &Client,
+    roster: &Mutex<Roster>,
+    room: &Room,
+    sender: &UserId,
+    content: &FriendEventContent,
+    sent_ts: u64,
+) -> anyhow::Result<bool> {
+    let own_user_id = client
+        .user_id()
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+    content.validate(sender.as_str(), &own_user_id)?;
+    let (changed, answer) =
+        roster
+            .lock()
+            .unwrap()
+            .receive(sender.as_str(), room.room_id().as_str(), content, sent_ts);
+    if !changed {
+        return Ok(false);
+    }
+    if let Some(answer) = answer {
+        room.send(answer).await?;
+    }
+    save(client, roster).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ME: &str = "@alice:example.org";
+    const BOB: &str = "@bob:example.org";
+    const CAROL: &str = "@carol:example.org";
+    const DM: &str = "!dm:example.org";
+
+    fn entry(state: FriendState, request_id: &str, updated_ts: u64) -> RosterEntry {
+        RosterEntry {
+            state,
+            request_id: request_id.to_string(),
+            room_id: DM.to_string(),
+            updated_ts,
+        }
+    }
+
+    #[test]
+    fn test_merge_is_per_entry() {
+        // Two devices, each adding a friend from the same list.
+        let mut phone = Roster::default();
+        phone.set(BOB, entry(FriendState::Accepted, "r1", 100));
+        let mut desktop = phone.clone();
+        phone.set(CAROL, entry(FriendState::Requested, "r2", 200));
+        desktop.remove(BOB, 300);
+
+        let mut merged = phone.clone();
+        assert!(merged.merge(&desktop));
+        assert_eq!(merged.entry(BOB), None);
+        assert_eq!(merged.entry(CAROL).unwrap().state, FriendState::Requested);
+        // Merging either way round agrees.
+        assert!(desktop.merge(&phone));
+        assert_eq!(desktop, merged);
+        assert!(!merged.merge(&desktop));
+    }
+
+    #[test]
+    fn test_older_changes_lose() {
+        let mut roster = Roster::default();
+        roster.set(BOB, entry(FriendState::Removed, "r1", 300));
+        assert!(!roster.set(BOB, entry(FriendState::Accepted, "r1", 100)));
+        // In the same millisecond, the further along wins.
+        assert!(roster.set(BOB, entry(FriendState::Accepted, "r1", 300)));
+        roster.prune(300 + REMOVAL_KEPT_MS);
+        assert_eq!(roster.friends.len(), 1);
+        roster.remove(BOB, 400);
+        roster.prune(400 + REMOVAL_KEPT_MS);
+        assert!(roster.friends.is_empty());
+    }
+
+    #[test]
+    fn test_handshake() {
+        // Alice asks Bob; Bob's side takes the request in and accepts.
+        let mut alice = Roster::default();
+        let request = alice.request(BOB, DM, "r1", 100).unwrap();
+        assert_eq!(request.to, BOB);
+        let mut bob = Roster::default();
+        assert_eq!(bob.receive(ME, DM, &request, 100), (true, None));
+        assert_eq!(bob.entry(ME).unwrap().state, FriendState::Incoming);
+        let accept = bob.accept(ME, 200).unwrap();
+        assert_eq!(accept.action, HandshakeAction::Accept);
+        assert_eq!(accept.request_id, "r1");
+
+        assert_eq!(alice.receive(BOB, DM, &accept, 200), (true, None));
+        assert_eq!(alice.entry(BOB).unwrap().state, FriendState::Accepted);
+        // The request again, as history loads: nothing changes.
+        assert_eq!(bob.receive(ME, DM, &request, 100), (false, None));
+
+        // Bob removes Alice; she hears of it.
+        let remove = bob.remove(ME, 300).unwrap();
+        assert_eq!(alice.receive(BOB, DM, &remove, 300), (true, None));
+        assert_eq!(alice.entry(BOB), None);
+    }
+
+    #[test]
+    fn test_local_changes_follow_the_last() {
+        // Bob's acceptance came with the server's time, ahead of our clock.
+        let mut alice = Roster::default();
+        alice.request(BOB, DM, "r1", 100).unwrap();
+        let accept = FriendEventContent::new(HandshakeAction::Accept, "r1", ME);
+        alice.receive(BOB, DM, &accept, 5_000);
+        assert!(alice.remove(BOB, 4_000).is_some());
+        assert_eq!(alice.friends[BOB].updated_ts, 5_001);
+        assert_eq!(alice.entry(BOB), None);
+    }
+
+    #[test]
+    fn test_crossing_requests_become_friends() {
+        let mut alice = Roster::default();
+        alice.request(BOB, DM, "r1", 100).unwrap();
+        let from_bob = FriendEventContent::new(HandshakeAction::Request, "r2", ME);
+        let (changed, answer) = alice.receive(BOB, DM, &from_bob, 110);
+        assert!(changed);
+        assert_eq!(
+            answer,
+            Some(FriendEventContent::new(HandshakeAction::Accept, "r2", BOB))
+        );
+        assert_eq!(alice.entry(BOB).unwrap().state, FriendState::Accepted);
+
+        // Asking someone who asked first accepts them.
+        let mut carol = Roster::default();
+        carol.receive(
+            ME,
+            DM,
+            &FriendEventContent::new(HandshakeAction::Request, "r3", CAROL),
+            100,
+        );
+        let answer = carol.request(ME, DM, "r4", 200).unwrap();
+        assert_eq!(answer.action, HandshakeAction::Accept);
+        assert_eq!(answer.request_id, "r3");
+    }
+
+    #[test]
+    fn test_stray_answers_are_ignored() {
+        let mut alice = Roster::default();
+        alice.request(BOB, DM, "r1", 100).unwrap();
+        let wrong = FriendEventContent::new(HandshakeAction::Accept, "other", ME);
+        assert_eq!(alice.receive(BOB, DM, &wrong, 200), (false, None));
+        let unasked = FriendEventContent::new(HandshakeAction::Accept, "r1", ME);
+        assert_eq!(alice.receive(CAROL, DM, &unasked, 200), (false, None));
+        assert_eq!(alice.entry(BOB).unwrap().state, FriendState::Requested);
+    }
+
+    #[test]
+    fn test_handshake_schema() {
+        let content: FriendEventContent = serde_json::from_value(json!({
+            "action": "request",
+            "request_id": "gc1710504000000.3",
+            "to": ME,
+        }))
+        .unwrap();
+        assert_eq!(content.validate(BOB, ME), Ok(()));
+        assert_eq!(content.validate(ME, ME), Err(HandshakeError::FromSelf));
+        assert_eq!(content.validate(BOB, CAROL), Err(HandshakeError::NotForUs));
+
+        let bad_id = FriendEventContent::new(HandshakeAction::Accept, "r 1<script>", ME);
+        assert_eq!(bad_id.validate(BOB, ME), Err(HandshakeError::BadRequestId));
+        let long_id = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        let too_long = FriendEventContent::new(HandshakeAction::Accept, &long_id, ME);
+        assert_eq!(
+            too_long.validate(BOB, ME),
+            Err(HandshakeError::BadRequestId)
+        );
+        let bad_to = FriendEventContent::new(HandshakeAction::Accept, "r1", "alice");
+        assert_eq!(bad_to.validate(BOB, ME), Err(HandshakeError::BadRecipient));
+
+        // Unknown actions and missing fields don't parse at all.
+        for content in [
+            json!({ "action": "poke", "request_id": "r1", "to": ME }),
+            json!({ "action": "request", "to": ME }),
+            json!({ "action": "request", "request_id": 7, "to": ME }),
+        ] {
+            assert!(serde_json::from_value::<FriendEventContent>(content).is_err());
+        }
+    }
+
+    #[test]
+    fn test_roster_round_trips() {
+        let mut roster = Roster::default();
+        roster.set(BOB, entry(FriendState::Accepted, "r1", 100));
+        let json = serde_json::to_value(&roster).unwrap();
+        assert_eq!(json["friends"][BOB]["state"], "accepted");
+        assert_eq!(serde_json::from_value::<Roster>(json).unwrap(), roster);
+        assert_eq!(
+            serde_json::from_value::<Roster>(json!({})).unwrap(),
+            Roster::default()
+        );
+    }
+}
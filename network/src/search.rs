@@ -0,0 +1,348 @@
+//! A local index over the messages seen this session, so search answers
+//! as you type and covers encrypted rooms, which the server can't search.
+//! It is fed from sync and from every page of history loaded.
+
+use crate::events::ChatEvent;
+use chat_core::Message;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Messages kept in the index; past this the oldest are dropped.
+pub const MAX_INDEXED: usize = 20_000;
+/// Hits a search returns.
+pub const MAX_RESULTS: usize = 50;
+
+/// A message matching a search, and the room it is in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub room_id: String,
+    pub message: Message,
+}
+
+#[derive(Debug)]
+struct Document {
+    room_id: String,
+    message: Message,
+    terms: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub struct SearchIndex {
+    documents: HashMap<u64, Document>,
+    /// Document ids by event id.
+    events: HashMap<String, u64>,
+    /// Document ids by the terms they contain.
+    postings: HashMap<String, HashSet<u64>>,
+    /// Document ids oldest first, for dropping the oldest.
+    by_age: BTreeSet<(u64, u64)>,
+    next_id: u64,
+    capacity: usize,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self::with_capacity(MAX_INDEXED)
+    }
+}
+
+/// The words of `text`, lowercased.
+fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl SearchIndex {
+    /// An index holding at most `capacity` messages.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            documents: HashMap::new(),
+            events: HashMap::new(),
+            postings: HashMap::new(),
+            by_age: BTreeSet::new(),
+            next_id: 0,
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Index `message`, in place of any earlier copy of it. Notices and
+    /// missed calls have nothing to find.
+    pub fn insert(&mut self, room_id: &str, message: &Message) {
+        if message.schema.stands_alone() {
+            return;
+        }
+        self.remove(&message.id);
+        let terms: HashSet<String> = terms(&message.content).collect();
+        if terms.is_empty() {
+            return;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        for term in &terms {
+            self.postings.entry(term.clone()).or_default().insert(id);
+        }
+        self.events.insert(message.id.clone(), id);
+        self.by_age.insert((message.timestamp, id));
+        self.documents.insert(
+            id,
+            Document {
+                room_id: room_id.to_string(),
+                message: message.clone(),
+                terms,
+            },
+        );
+        while self.documents.len() > self.capacity {
+            let Some(&(_, oldest)) = self.by_age.first() else {
+                break;
+            };
+            self.remove_document(oldest);
+        }
+    }
+
+    /// Index a page of history from `room_id`.
+    pub fn insert_page(&mut self, room_id: &str, messages: &[Message]) {
+        for message in messages {
+            self.insert(room_id, message);
+        }
+    }
+
+    pub fn remove(&mut self, event_id: &str) {
+        if let Some(&id) = self.events.get(event_id) {
+            self.remove_document(id);
+        }
+    }
+
+    fn remove_document(&mut self, id: u64) {
+        let Some(document) = self.documents.remove(&id) else {
+            return;
+        };
+        for term in &document.terms {
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        self.events.remove(&document.message.id);
+        self.by_age.remove(&(document.message.timestamp, id));
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::with_capacity(self.capacity);
+    }
+
+    /// Keep up with sync: new messages, edits, deletions and rooms left.
+    pub fn apply(&mut self, event: &ChatEvent) {
+        match event {
+            ChatEvent::Message {
+                room_id, message, ..
+            } => self.insert(room_id, message),
+            ChatEvent::MessageEdited {
+                room_id,
+                event_id,
+                sender,
+                body,
+                formatted,
+            } => {
+                let Some(document) = self
+                    .events
+                    .get(event_id)
+                    .and_then(|id| self.documents.get(id))
+                else {
+                    return;
+                };
+                // Only the sender may edit a message.
+                if document.message.sender != *sender {
+                    return;
+                }
+                let message = Message {
+                    content: body.clone(),
+                    formatted: formatted.clone(),
+                    edited: true,
+                    ..document.message.clone()
+                };
+                self.insert(room_id, &message);
+            }
+            ChatEvent::Redacted { event_id, .. } => self.remove(event_id),
+            ChatEvent::RoomLeft { room_id } => {
+                let ids: Vec<u64> = self
+                    .documents
+                    .iter()
+                    .filter(|(_, document)| document.room_id == *room_id)
+                    .map(|(&id, _)| id)
+                    .collect();
+                for id in ids {
+                    self.remove_document(id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Messages containing every word of `query`, in `room_id` if given,
+    /// best first. The last word also matches the start of a longer one, so
+    /// results follow typing. Whole-word matches rank above those, then
+    /// newer above older.
+    pub fn search(&self, query: &str, room_id: Option<&str>, limit: usize) -> Vec<SearchHit> {
+        let words: Vec<String> = terms(query).collect();
+        let Some((last, whole)) = words.split_last() else {
+            return Vec::new();
+        };
+        let mut found: Option<HashSet<u64>> = None;
+        for word in whole {
+            let ids = self.postings.get(word).cloned().unwrap_or_default();
+            found = Some(match found {
+                Some(found) => &found & &ids,
+                None => ids,
+            });
+        }
+        let prefixed: HashSet<u64> = self
+            .postings
+            .iter()
+            .filter(|(term, _)| term.starts_with(last.as_str()))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect();
+        let found = match found {
+            Some(found) => &found & &prefixed,
+            None => prefixed,
+        };
+
+        let mut hits: Vec<&Document> = found
+            .iter()
+            .filter_map(|id| self.documents.get(id))
+            .filter(|document| room_id.is_none_or(|room_id| document.room_id == room_id))
+            .collect();
+        hits.sort_by_key(|document| {
+            let exact = document.terms.contains(last);
+            (
+                std::cmp::Reverse(exact),
+                std::cmp::Reverse(document.message.timestamp),
+            )
+        });
+        hits.into_iter()
+            .take(limit)
+            .map(|document| SearchHit {
+                room_id: document.room_id.clone(),
+                message: document.message.clone(),
+            })
+            .collect()
+    }
+}
+
+/// `local` hits followed by those of `server` that are not among them.
+pub fn merge_hits(mut local: Vec<SearchHit>, server: Vec<SearchHit>) -> Vec<SearchHit> {
+    let seen: HashSet<String> = local.iter().map(|hit| hit.message.id.clone()).collect();
+    local.extend(
+        server
+            .into_iter()
+            .filter(|hit| !seen.contains(&hit.message.id)),
+    );
+    local
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::{MessageType, Notice};
+
+    fn message(id: &str, sender: &str, content: &str, timestamp: u64) -> Message {
+        Message {
+            id: id.to_string(),
+            sender: sender.to_string(),
+            content: content.to_string(),
+            timestamp,
+            ..Message::default()
+        }
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.message.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_search_matches_every_word_and_ranks() {
+        let mut index = SearchIndex::default();
+        index.insert("!a", &message("$1", "@bob", "Raid tonight at nine?", 1));
+        index.insert("!a", &message("$2", "@amy", "raiding is fun", 2));
+        index.insert("!b", &message("$3", "@bob", "No raid, tonight is off", 3));
+
+        assert_eq!(
+            ids(&index.search("raid tonight", None, 10)),
+            vec!["$3", "$1"]
+        );
+        // The last word matches as a prefix, whole words first.
+        assert_eq!(ids(&index.search("RAID", None, 10)), vec!["$3", "$1", "$2"]);
+        assert_eq!(ids(&index.search("rai", Some("!a"), 10)), vec!["$2", "$1"]);
+        assert_eq!(ids(&index.search("raid", None, 1)), vec!["$3"]);
+        assert!(index.search("  ", None, 10).is_empty());
+        assert!(index.search("dungeon", None, 10).is_empty());
+    }
+
+    #[test]
+    fn test_index_follows_edits_deletions_and_rooms_left() {
+        let mut index = SearchIndex::default();
+        index.insert("!a", &message("$1", "@bob", "see you at the lan", 1));
+        index.insert("!b", &message("$2", "@amy", "lan party", 2));
+
+        let edit = |sender: &str| ChatEvent::MessageEdited {
+            room_id: "!a".to_string(),
+            event_id: "$1".to_string(),
+            sender: sender.to_string(),
+            body: "see you online".to_string(),
+            formatted: None,
+        };
+        index.apply(&edit("@mallory"));
+        assert_eq!(ids(&index.search("lan", None, 10)), vec!["$2", "$1"]);
+        index.apply(&edit("@bob"));
+        assert_eq!(ids(&index.search("lan", None, 10)), vec!["$2"]);
+        let hits = index.search("online", None, 10);
+        assert!(hits[0].message.edited);
+
+        index.apply(&ChatEvent::Redacted {
+            room_id: "!b".to_string(),
+            event_id: "$2".to_string(),
+        });
+        assert!(index.search("lan", None, 10).is_empty());
+        index.apply(&ChatEvent::RoomLeft {
+            room_id: "!a".to_string(),
+        });
+        assert!(index.is_empty());
+        assert!(index.postings.is_empty());
+    }
+
+    #[test]
+    fn test_index_is_bounded_and_skips_notices() {
+        let mut index = SearchIndex::with_capacity(2);
+        index.insert("!a", &message("$2", "@bob", "gg", 2));
+        index.insert("!a", &message("$1", "@bob", "gg", 1));
+        index.insert("!a", &message("$3", "@bob", "gg", 3));
+        assert_eq!(ids(&index.search("gg", None, 10)), vec!["$3", "$2"]);
+
+        let notice = Message {
+            schema: MessageType::SystemNotice(Notice::Voice),
+            ..message("~1", "@bob", "gg joined voice", 4)
+        };
+        index.insert("!a", &notice);
+        assert_eq!(index.len(), 2);
+        index.clear();
+        assert!(index.search("gg", None, 10).is_empty());
+    }
+
+    #[test]
+    fn test_merge_hits_drops_duplicates() {
+        let hit = |id: &str| SearchHit {
+            room_id: "!a".to_string(),
+            message: message(id, "@bob", "gg", 1),
+        };
+        let merged = merge_hits(vec![hit("$1"), hit("$2")], vec![hit("$2"), hit("$3")]);
+        assert_eq!(ids(&merged), vec!["$1", "$2", "$3"]);
+    }
+}
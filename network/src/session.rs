@@ -1,7 +1,13 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::join_all;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fs;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long checking each saved session may take at startup.
+pub const VALIDATE_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Represents a saved user session that can be restored on next launch.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,24 +17,132 @@ pub struct Session {
     pub homeserver: String,
     pub access_token: String,
     pub device_id: String,
+    /// What we last learned of the device, for the saved profile card.
+    #[serde(default)]
+    pub details: DeviceDetails,
+}
+
+/// What the server last told us of a session's device, refreshed after
+/// each sign in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceDetails {
+    /// The name the device goes by in the account's device list.
+    pub device_name: Option<String>,
+    /// Whether the device is cross-signed; `None` until checked.
+    pub verified: Option<bool>,
+    /// When the server last took the session's token, in seconds since the
+    /// Unix epoch.
+    pub validated_at: Option<u64>,
+}
+
+/// What the server makes of a saved session's token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionValidity {
+    Valid,
+    /// Signed out or revoked: signing in again is the only way back.
+    Expired,
+    /// The server couldn't be reached, or answered something else. Offline
+    /// sessions are this, never `Expired`.
+    Unknown,
+}
+
+/// What a `/whoami` answer with `status` and `errcode` says of the token.
+fn validity(status: u16, errcode: Option<&str>) -> SessionValidity {
+    match (status, errcode) {
+        (200..=299, _) => SessionValidity::Valid,
+        (401, Some("M_UNKNOWN_TOKEN" | "M_MISSING_TOKEN")) => SessionValidity::Expired,
+        (403, Some("M_USER_DEACTIVATED")) => SessionValidity::Expired,
+        _ => SessionValidity::Unknown,
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Ask `session`'s homeserver who its token belongs to.
+async fn whoami(session: &Session) -> Result<SessionValidity> {
+    let mut url = reqwest::Url::parse(&session.homeserver)?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid homeserver URL"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "account", "whoami"]);
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(&session.access_token)
+        .send()
+        .await?;
+    let status = response.status().as_u16();
+    let body: Value = response.json().await.unwrap_or_default();
+    Ok(validity(
+        status,
+        body.get("errcode").and_then(Value::as_str),
+    ))
+}
+
+/// Check each of `sessions` with its homeserver, all at once and each for
+/// at most `VALIDATE_TIMEOUT`. Results are in the order of `sessions`;
+/// ones that can't be reached are `Unknown`. Valid sessions are saved as
+/// validated now.
+pub async fn validate_sessions(sessions: &[Session]) -> Vec<SessionValidity> {
+    let checks = sessions.iter().map(|session| async move {
+        match tokio::time::timeout(VALIDATE_TIMEOUT, whoami(session)).await {
+            Ok(Ok(validity)) => validity,
+            Ok(Err(e)) => {
+                tracing::warn!("Could not check the session of {}: {}", session.user_id, e);
+                SessionValidity::Unknown
+            }
+            Err(_) => SessionValidity::Unknown,
+        }
+    });
+    let results = join_all(checks).await;
+    let now = now_secs();
+    for (session, validity) in sessions.iter().zip(&results) {
+        if *validity == SessionValidity::Valid {
+            let _ = SessionManager::update_details(&session.user_id, |details| {
+                details.validated_at = Some(now)
+            });
+        }
+    }
+    results
 }
 
 /// Manages persistent session storage in `~/.gamechat/sessions.json`.
 pub struct SessionManager;
 
+/// The `.gamechat` directory our files live in, created if missing.
+pub(crate) fn app_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_local_dir()
+        .or_else(dirs::home_dir)
+        .context("Could not determine home directory")?;
+
+    let app_dir = data_dir.join(".gamechat");
+    if !app_dir.exists() {
+        fs::create_dir_all(&app_dir).context("Failed to create .gamechat directory")?;
+    }
+    Ok(app_dir)
+}
+
+/// Where the SDK keeps the state and encryption keys of our device
+/// `device_id`. Each signed in device has its own, as its keys can't be
+/// made again.
+pub(crate) fn store_dir(device_id: &str) -> Result<PathBuf> {
+    let name: String = device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    Ok(app_dir()?.join("stores").join(name))
+}
+
 impl SessionManager {
     /// Get the path to the sessions file.
     fn sessions_path() -> Result<PathBuf> {
-        let data_dir = dirs::data_local_dir()
-            .or_else(|| dirs::home_dir())
-            .context("Could not determine home directory")?;
-
-        let app_dir = data_dir.join(".gamechat");
-        if !app_dir.exists() {
-            fs::create_dir_all(&app_dir).context("Failed to create .gamechat directory")?;
-        }
-
-        Ok(app_dir.join("sessions.json"))
+        Ok(app_dir()?.join("sessions.json"))
     }
 
     /// Load all saved sessions from disk.
@@ -61,6 +175,30 @@ impl SessionManager {
         Ok(())
     }
 
+    /// Update the display name remembered for `user_id`, if it has a session.
+    pub fn update_display_name(user_id: &str, display_name: &str) -> Result<()> {
+        let Some(mut session) = Self::load_sessions()?
+            .into_iter()
+            .find(|s| s.user_id == user_id)
+        else {
+            return Ok(());
+        };
+        session.display_name = display_name.to_string();
+        Self::save_session(session)
+    }
+
+    /// Change what is remembered of `user_id`'s device, if it has a session.
+    pub fn update_details(user_id: &str, change: impl FnOnce(&mut DeviceDetails)) -> Result<()> {
+        let Some(mut session) = Self::load_sessions()?
+            .into_iter()
+            .find(|s| s.user_id == user_id)
+        else {
+            return Ok(());
+        };
+        change(&mut session.details);
+        Self::save_session(session)
+    }
+
     /// Delete a session by user_id.
     pub fn delete_session(user_id: &str) -> Result<()> {
         let mut sessions = Self::load_sessions().unwrap_or_default();
@@ -90,6 +228,7 @@ mod tests {
             homeserver: "https://matrix.org".to_string(),
             access_token: "syt_token_123".to_string(),
             device_id: "DEVICEABC".to_string(),
+            details: DeviceDetails::default(),
         };
 
         let json = serde_json::to_string(&session).unwrap();
@@ -99,4 +238,31 @@ mod tests {
         assert_eq!(session.display_name, parsed.display_name);
         assert_eq!(session.access_token, parsed.access_token);
     }
+
+    #[test]
+    fn test_sessions_saved_before_details_load() {
+        let json = r#"{"user_id":"@a:x","display_name":"A","homeserver":"https://x","access_token":"t","device_id":"D"}"#;
+        let parsed: Session = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed.details, DeviceDetails::default());
+    }
+
+    #[test]
+    fn test_validity() {
+        assert_eq!(validity(200, None), SessionValidity::Valid);
+        assert_eq!(
+            validity(401, Some("M_UNKNOWN_TOKEN")),
+            SessionValidity::Expired
+        );
+        assert_eq!(
+            validity(403, Some("M_USER_DEACTIVATED")),
+            SessionValidity::Expired
+        );
+        // Rate limits, proxies and outages say nothing of the token.
+        assert_eq!(
+            validity(429, Some("M_LIMIT_EXCEEDED")),
+            SessionValidity::Unknown
+        );
+        assert_eq!(validity(502, None), SessionValidity::Unknown);
+        assert_eq!(validity(401, None), SessionValidity::Unknown);
+    }
 }
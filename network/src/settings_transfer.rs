@@ -0,0 +1,522 @@
+//! Settings exported to a file, to bring to another machine, and imported
+//! from one. The export is the config minus what mustn't or needn't travel:
+//! the translation server's API key, the overlay's position, and each
+//! account's window position, pending knocks, registered pushers and the
+//! keys of the users it verified. Sign-ins and encryption keys live outside
+//! the config and are never part of it.
+//!
+//! An import is read through the config's migrations, then merged section
+//! by section: a section that is the same, or that we left at its default,
+//! takes the file's; one we changed that the file has otherwise is a
+//! conflict, kept unless the user picks the file's.
+
+use crate::config::{self, AccountConfig, Config};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+/// Marks a file as settings exported by us.
+const FORMAT: &str = "gamechat-settings";
+
+/// Paths into the config that are never exported, and kept as they are on
+/// import. `*` is any account.
+const LEFT_OUT: [&[&str]; 6] = [
+    &["translation", "api_key"],
+    &["overlay", "position"],
+    &["accounts", "*", "window"],
+    &["accounts", "*", "knocks"],
+    &["accounts", "*", "pushers"],
+    &["accounts", "*", "verified_keys"],
+];
+
+/// The settings in `config`, as a file to import elsewhere.
+pub fn export(config: &Config) -> Result<String> {
+    let mut settings = serde_json::to_value(config)?;
+    strip(&mut settings);
+    let file = json!({ "format": FORMAT, "settings": settings });
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+/// Read a file written by `export`, by this or an older version.
+pub fn parse(data: &str) -> Result<Config> {
+    let file: Value = serde_json::from_str(data).context("This isn't a settings file")?;
+    if file.get("format").and_then(Value::as_str) != Some(FORMAT) {
+        bail!("This isn't a GameChat settings file");
+    }
+    let mut settings = file
+        .get("settings")
+        .cloned()
+        .context("The settings file has no settings")?;
+    strip(&mut settings);
+    config::from_value(settings)
+}
+
+/// Remove the `LEFT_OUT` paths from a config's JSON.
+fn strip(config: &mut Value) {
+    for pattern in LEFT_OUT {
+        for path in expand(config, pattern) {
+            let Some((last, parents)) = path.split_last() else {
+                continue;
+            };
+            let parent = parents
+                .iter()
+                .try_fold(&mut *config, |value, key| value.get_mut(key));
+            if let Some(object) = parent.and_then(Value::as_object_mut) {
+                object.remove(last);
+            }
+        }
+    }
+}
+
+/// The paths in `value` that `pattern` matches.
+fn expand(value: &Value, pattern: &[&str]) -> Vec<Vec<String>> {
+    let Some((first, rest)) = pattern.split_first() else {
+        return vec![Vec::new()];
+    };
+    let Some(object) = value.as_object() else {
+        return Vec::new();
+    };
+    let children: Vec<(&String, &Value)> = match *first {
+        "*" => object.iter().collect(),
+        key => object.get_key_value(key).into_iter().collect(),
+    };
+    let mut paths = Vec::new();
+    for (key, child) in children {
+        for mut path in expand(child, rest) {
+            path.insert(0, key.clone());
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// A part of the settings merged as a whole: a top-level one like
+/// `audio`, or one account's, like `accounts/@a:x.org/keywords`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub key: String,
+    /// As shown when asking about a conflict.
+    pub label: String,
+    path: Vec<String>,
+}
+
+/// How importing a file would go.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Sections the file changes, as ours were at their defaults.
+    pub changes: Vec<Section>,
+    /// Sections we changed that the file has otherwise.
+    pub conflicts: Vec<Section>,
+}
+
+/// Where each section is, as a path into the config's JSON.
+fn sections(config: &Value) -> Vec<Vec<String>> {
+    let Some(object) = config.as_object() else {
+        return Vec::new();
+    };
+    let mut sections = Vec::new();
+    for (key, value) in object {
+        match key.as_str() {
+            "version" => {}
+            "accounts" => {
+                let accounts = value.as_object().into_iter().flatten();
+                for (user_id, account) in accounts {
+                    for field in account.as_object().into_iter().flat_map(|a| a.keys()) {
+                        sections.push(vec![key.clone(), user_id.clone(), field.clone()]);
+                    }
+                }
+            }
+            _ => sections.push(vec![key.clone()]),
+        }
+    }
+    sections
+}
+
+fn get<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+fn set(value: &mut Value, path: &[String], new: Value) {
+    let Some((last, parents)) = path.split_last() else {
+        return;
+    };
+    let mut value = value;
+    for key in parents {
+        let Some(object) = value.as_object_mut() else {
+            return;
+        };
+        value = object.entry(key.clone()).or_insert_with(|| json!({}));
+    }
+    if let Some(object) = value.as_object_mut() {
+        object.insert(last.clone(), new);
+    }
+}
+
+/// What the section at `path` is by default, as exported.
+fn default_of(path: &[String]) -> Result<Option<Value>> {
+    let mut defaults = serde_json::to_value(Config::default())?;
+    // An account's section: under `accounts/<user>/`.
+    if path.len() == 3 {
+        let account = serde_json::to_value(AccountConfig::default())?;
+        set(&mut defaults, &path[..2], account);
+    }
+    strip(&mut defaults);
+    Ok(get(&defaults, path).cloned())
+}
+
+/// The sections of `imported` that would change `current`, and which of
+/// them conflict.
+pub fn plan(current: &Config, imported: &Config) -> Result<Plan> {
+    let mut ours = serde_json::to_value(current)?;
+    let mut theirs = serde_json::to_value(imported)?;
+    strip(&mut ours);
+    strip(&mut theirs);
+
+    let mut plan = Plan::default();
+    for path in sections(&theirs) {
+        let default = default_of(&path)?;
+        let ours = get(&ours, &path).or(default.as_ref());
+        if ours == get(&theirs, &path) {
+            continue;
+        }
+        let section = Section {
+            key: path.join("/"),
+            label: label(&path),
+            path,
+        };
+        if ours == default.as_ref() {
+            plan.changes.push(section);
+        } else {
+            plan.conflicts.push(section);
+        }
+    }
+    Ok(plan)
+}
+
+/// `current` with the sections of `imported` that change it without
+/// conflict, and the conflicting ones whose keys are in `taken`.
+pub fn merge(current: &Config, imported: &Config, taken: &[String]) -> Result<Config> {
+    let plan = plan(current, imported)?;
+    let mut merged = serde_json::to_value(current)?;
+    let mut theirs = serde_json::to_value(imported)?;
+    strip(&mut theirs);
+    let take = plan.changes.iter().chain(
+        plan.conflicts
+            .iter()
+            .filter(|section| taken.contains(&section.key)),
+    );
+    for section in take {
+        if let Some(value) = get(&theirs, &section.path) {
+            set(&mut merged, &section.path, value.clone());
+        }
+    }
+    // Taking the translation section mustn't lose our API key.
+    let ours = serde_json::to_value(current)?;
+    for pattern in LEFT_OUT {
+        for path in expand(&ours, pattern) {
+            if let Some(value) = get(&ours, &path) {
+                set(&mut merged, &path, value.clone());
+            }
+        }
+    }
+    Ok(serde_json::from_value(merged)?)
+}
+
+/// How the section at `path` is named when asking about a conflict.
+fn label(path: &[String]) -> String {
+    let name = |key: &str| -> String {
+        match key {
+            "audio" => "Voice and audio",
+            "notification_previews" => "Notification previews",
+            "close_to_tray" => "Close to tray",
+            "skin_tone" => "Emoji skin tone",
+            "emoji_usage" => "Frequently used emoji",
+            "login" => "Sign-in homeserver",
+            "rich_presence" => "Game activity",
+            "idle" => "Going idle",
+            "quiet_hours" => "Quiet hours",
+            "link_previews" => "Link previews",
+            "reduce_motion" => "Reduced motion",
+            "uploads" => "Image uploads",
+            "startup" => "Startup",
+            "cache" => "Storage",
+            "encryption" => "Encryption",
+            "sending" => "Sending",
+            "timeline" => "Timeline",
+            "translation" => "Translation",
+            "spelling" => "Spell check",
+            "game_invites" => "Game invites",
+            "overlay" => "Overlay",
+            "language" => "Language",
+            "keybindings" => "Keyboard shortcuts",
+            "keywords" => "Notification keywords",
+            "status_message" => "Status message",
+            "reported" => "Reported messages",
+            "unbridged_rooms" => "Bridged authors",
+            "favorites" => "Favorite rooms",
+            "dictionary" => "Spelling dictionary",
+            "room_sounds" => "Room notification sounds",
+            other => return other.replace('_', " "),
+        }
+        .to_string()
+    };
+    match path {
+        [_, user_id, field] => format!("{} of {}", name(field), user_id),
+        _ => name(&path[0]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        AudioConfig, CacheConfig, CueOutput, EncryptionConfig, GameInviteConfig, GatewayConfig,
+        IdleConfig, LinkPreviewConfig, LoginPreferences, OverlayConfig, OverlayPosition,
+        PendingKnock, PusherConfig, Retention, RichPresenceConfig, SendConfig, SpellingConfig,
+        StartupConfig, TimelineConfig, TranslationConfig, UploadConfig, WindowGeometry,
+    };
+    use crate::cue_sound::{RoomSound, Tone};
+    use crate::timeline::TimelineFilter;
+    use chat_core::emoji::{self, EmojiUsage, SkinTone};
+    use chat_core::keybindings::{Action, Keybindings, Shortcut};
+    use chat_core::keywords::Keyword;
+    use chat_core::quiet_hours::Schedule;
+    use chat_core::rich_presence::Game;
+    use std::collections::BTreeMap;
+
+    const USER: &str = "@a:x.org";
+
+    /// A config with every setting off its default. Fields are named one
+    /// by one so a new one can't be missed here.
+    fn changed() -> Config {
+        let mut keybindings = Keybindings::default();
+        keybindings.set(Action::PushToTalk, Some(Shortcut::new("KeyV")));
+        let mut emoji_usage = EmojiUsage::default();
+        emoji_usage.record(emoji::find("smile").unwrap());
+        let filter = TimelineFilter {
+            membership: false,
+            group_membership: false,
+            display_names: false,
+            avatars: false,
+            reactions_as_rows: true,
+            voice: false,
+            topic: false,
+        };
+        let account = AccountConfig {
+            keybindings,
+            window: Some(WindowGeometry {
+                x: 10,
+                y: 20,
+                width: 800,
+                height: 600,
+                maximized: true,
+            }),
+            keywords: vec![Keyword::new("raid").unwrap()],
+            status_message: Some("Raiding".to_string()),
+            reported: vec!["$reported".to_string()],
+            knocks: vec![PendingKnock {
+                room_id: "!knock:x.org".to_string(),
+                address: "#knock:x.org".to_string(),
+            }],
+            pushers: PusherConfig {
+                email: Some("a@x.org".to_string()),
+                gateway: Some(GatewayConfig {
+                    url: "https://push.x.org".to_string(),
+                    app_id: "org.x.push".to_string(),
+                }),
+            },
+            unbridged_rooms: vec!["!bridged:x.org".to_string()],
+            favorites: vec!["!favorite:x.org".to_string()],
+            dictionary: vec!["zerg".to_string()],
+            room_sounds: BTreeMap::from([("!loud:x.org".to_string(), RoomSound::Tone(Tone::Bell))]),
+            verified_keys: BTreeMap::from([("@b:x.org".to_string(), "SSK".to_string())]),
+        };
+        Config {
+            version: config::CONFIG_VERSION,
+            audio: AudioConfig {
+                input_device: Some("Mic".to_string()),
+                output_device: Some("Speakers".to_string()),
+                cue_output: CueOutput::Device(Some("Headset".to_string())),
+                input_gain: 1.5,
+                vad_threshold: 0.2,
+                noise_suppression: false,
+                frame_ms: 40,
+                jitter_ms: 120,
+                max_clip_secs: 60,
+            },
+            notification_previews: false,
+            close_to_tray: true,
+            skin_tone: SkinTone::Dark,
+            emoji_usage,
+            accounts: BTreeMap::from([(USER.to_string(), account)]),
+            login: LoginPreferences {
+                homeserver: Some("https://x.org".to_string()),
+            },
+            rich_presence: RichPresenceConfig {
+                enabled: false,
+                games: vec![Game::new("Game", "game.exe")],
+            },
+            idle: IdleConfig {
+                after_minutes: 3,
+                mute_sounds: true,
+            },
+            quiet_hours: Schedule {
+                enabled: true,
+                start: 60,
+                end: 120,
+                days: [true, false, true, false, true, false, true],
+            },
+            link_previews: LinkPreviewConfig {
+                enabled: false,
+                encrypted_rooms: true,
+            },
+            reduce_motion: true,
+            uploads: UploadConfig {
+                compress_images: false,
+                max_dimension: 1024,
+                quality: 50,
+            },
+            startup: StartupConfig {
+                room_concurrency: 2,
+                unsent_max_age_hours: 12,
+            },
+            cache: CacheConfig {
+                max_size_mb: 100,
+                retention: Retention::Days7,
+            },
+            encryption: EncryptionConfig {
+                never_send_to_unverified: true,
+            },
+            sending: SendConfig {
+                confirm_above_members: 50,
+            },
+            timeline: TimelineConfig {
+                filter,
+                rooms: BTreeMap::from([("!a:x.org".to_string(), filter)]),
+                window: 500,
+            },
+            translation: TranslationConfig {
+                url: "https://translate.x.org".to_string(),
+                api_key: "secret".to_string(),
+                target: "fr".to_string(),
+                rooms: BTreeMap::from([("!a:x.org".to_string(), "de".to_string())]),
+            },
+            spelling: SpellingConfig {
+                enabled: false,
+                language: "en_GB".to_string(),
+                dictionary_dir: "/home/x/dictionaries".to_string(),
+            },
+            game_invites: GameInviteConfig {
+                launch_schemes: vec!["steam".to_string(), "minecraft".to_string()],
+            },
+            overlay: OverlayConfig {
+                show_on_voice: true,
+                opacity: 0.5,
+                click_through: true,
+                position: Some(OverlayPosition { x: 20, y: 40 }),
+            },
+            language: Some("fr".to_string()),
+        }
+    }
+
+    /// `config` as it travels: without what is left out of exports.
+    fn without_left_out(mut config: Config) -> Config {
+        config.translation.api_key = String::new();
+        config.overlay.position = None;
+        for account in config.accounts.values_mut() {
+            account.window = None;
+            account.knocks = Vec::new();
+            account.pushers = PusherConfig::default();
+            account.verified_keys = BTreeMap::new();
+        }
+        config
+    }
+
+    #[test]
+    fn test_every_setting_survives_export_and_import() {
+        let config = changed();
+        let imported = parse(&export(&config).unwrap()).unwrap();
+        assert_eq!(imported, without_left_out(config.clone()));
+
+        // Onto a fresh install, nothing conflicts and everything is taken.
+        let plan = plan(&Config::default(), &imported).unwrap();
+        assert!(plan.conflicts.is_empty());
+        let merged = merge(&Config::default(), &imported, &[]).unwrap();
+        assert_eq!(merged, without_left_out(config));
+    }
+
+    #[test]
+    fn test_secrets_and_machine_state_stay_behind() {
+        let config = changed();
+        let file = export(&config).unwrap();
+        assert!(!file.contains("secret"));
+        assert!(!file.contains("knock:x.org") && !file.contains("push.x.org"));
+
+        // Importing keeps ours, even when taking the sections holding them.
+        let mut imported = parse(&file).unwrap();
+        imported.translation.target = "es".to_string();
+        let taken: Vec<String> = plan(&config, &imported)
+            .unwrap()
+            .conflicts
+            .into_iter()
+            .map(|section| section.key)
+            .collect();
+        assert_eq!(taken, vec!["translation".to_string()]);
+        let merged = merge(&config, &imported, &taken).unwrap();
+        assert_eq!(merged.translation.target, "es");
+        assert_eq!(merged.translation.api_key, "secret");
+        assert_eq!(merged.accounts[USER], config.accounts[USER]);
+    }
+
+    #[test]
+    fn test_changed_settings_conflict_and_are_kept_unless_taken() {
+        let mut current = Config {
+            close_to_tray: true,
+            ..Config::default()
+        };
+        current.idle.after_minutes = 20;
+        let mut imported = Config {
+            close_to_tray: false,
+            ..Config::default()
+        };
+        imported.idle.after_minutes = 20;
+        imported.reduce_motion = true;
+        imported.accounts.insert(
+            USER.to_string(),
+            AccountConfig {
+                keywords: vec![Keyword::new("raid").unwrap()],
+                ..AccountConfig::default()
+            },
+        );
+
+        let plan = plan(&current, &imported).unwrap();
+        let keys = |sections: &[Section]| -> Vec<String> {
+            sections.iter().map(|s| s.key.clone()).collect()
+        };
+        assert_eq!(keys(&plan.conflicts), vec!["close_to_tray"]);
+        assert_eq!(plan.conflicts[0].label, "Close to tray");
+        assert_eq!(
+            keys(&plan.changes),
+            vec!["accounts/@a:x.org/keywords", "reduce_motion"]
+        );
+        assert_eq!(plan.changes[0].label, "Notification keywords of @a:x.org");
+
+        let kept = merge(&current, &imported, &[]).unwrap();
+        assert!(kept.close_to_tray && kept.reduce_motion);
+        assert_eq!(kept.account(USER).keywords.len(), 1);
+        let taken = merge(&current, &imported, &keys(&plan.conflicts)).unwrap();
+        assert!(!taken.close_to_tray);
+    }
+
+    #[test]
+    fn test_other_files_and_newer_versions_are_refused() {
+        assert!(parse("not json").is_err());
+        assert!(parse(r#"{"audio": {}}"#).is_err());
+        let newer = json!({
+            "format": FORMAT,
+            "settings": {"version": config::CONFIG_VERSION + 1},
+        });
+        assert!(parse(&newer.to_string()).is_err());
+        let older = json!({"format": FORMAT, "settings": {"reduce_motion": true}});
+        assert!(parse(&older.to_string()).unwrap().reduce_motion);
+    }
+}
@@ -0,0 +1,160 @@
+//! Voice channel signaling over room state. Each participant publishes a
+//! `com.gamechat.voice.member` state event, keyed by their user id, listing
+//! the UDP addresses peers can reach them on. Leaving replaces it with an
+//! empty event.
+//!
+//! Calls ring the other members of a room into its voice channel: a
+//! `com.gamechat.call.invite` event rings, and answers and hangups name the
+//! call by the invite's event id. Answering is joining the voice channel.
+
+use crate::events::VoiceMember;
+use crate::rooms;
+use matrix_sdk::deserialized_responses::RawSyncOrStrippedState;
+use matrix_sdk::ruma::events::macros::EventContent;
+use matrix_sdk::ruma::events::SyncStateEvent;
+use matrix_sdk::ruma::{OwnedUserId, UserId};
+use matrix_sdk::Room;
+use serde::{Deserialize, Serialize};
+
+/// A membership lapses after this long, in case its owner quit without
+/// leaving. Participants re-publish well before then.
+pub const MEMBERSHIP_TTL_MS: u64 = 60 * 60 * 1000;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.gamechat.voice.member", kind = State, state_key_type = OwnedUserId)]
+pub struct VoiceMemberEventContent {
+    /// `ip:port` addresses, best first. Empty once the user has left.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<String>,
+    /// When the membership lapses, in ms since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_ts: Option<u64>,
+}
+
+impl VoiceMemberEventContent {
+    /// Announce being in the channel at `candidates` until `now + TTL`.
+    pub fn joined(candidates: Vec<String>, now: u64) -> Self {
+        Self {
+            candidates,
+            expires_ts: Some(now + MEMBERSHIP_TTL_MS),
+        }
+    }
+
+    /// The user is in the channel: they announced addresses that have not
+    /// lapsed.
+    pub fn is_active(&self, now: u64) -> bool {
+        !self.candidates.is_empty() && self.expires_ts.is_none_or(|ts| ts > now)
+    }
+}
+
+/// The member described by a state event; no candidates if they are not
+/// (or no longer) in the channel.
+pub(crate) async fn voice_member(
+    room: &Room,
+    user_id: &UserId,
+    content: &VoiceMemberEventContent,
+    now: u64,
+) -> VoiceMember {
+    VoiceMember {
+        user_id: user_id.to_string(),
+        display_name: rooms::member_name(room, user_id).await,
+        candidates: if content.is_active(now) {
+            content.candidates.clone()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+/// Rings `lifetime` ms before it counts as missed.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.gamechat.call.invite", kind = MessageLike)]
+pub struct CallInviteEventContent {
+    pub lifetime: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.gamechat.call.answer", kind = MessageLike)]
+pub struct CallAnswerEventContent {
+    /// The invite's event id.
+    pub call_id: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HangupReason {
+    /// The callee turned the call down.
+    Declined,
+    /// The caller gave up before it was answered.
+    Cancelled,
+    /// It rang out. Sent by the callee, and shown in the room's timeline.
+    Missed,
+    /// Someone hung up after it was answered.
+    Ended,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.gamechat.call.hangup", kind = MessageLike)]
+pub struct CallHangupEventContent {
+    /// The invite's event id.
+    pub call_id: String,
+    pub reason: HangupReason,
+}
+
+/// An invite sent at `sent_ts` still rings at `now`.
+pub fn is_ringing(invite: &CallInviteEventContent, sent_ts: u64, now: u64) -> bool {
+    sent_ts.saturating_add(invite.lifetime) > now
+}
+
+/// Everyone currently in `room`'s voice channel.
+pub(crate) async fn voice_members(room: &Room, now: u64) -> matrix_sdk::Result<Vec<VoiceMember>> {
+    let mut members = Vec::new();
+    for raw in room
+        .get_state_events_static::<VoiceMemberEventContent>()
+        .await?
+    {
+        let RawSyncOrStrippedState::Sync(raw) = raw else {
+            continue;
+        };
+        let Ok(SyncStateEvent::Original(ev)) = raw.deserialize() else {
+            continue;
+        };
+        if ev.content.is_active(now) {
+            members.push(voice_member(room, &ev.state_key, &ev.content, now).await);
+        }
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership_lapses() {
+        let joined = VoiceMemberEventContent::joined(vec!["192.0.2.1:4000".to_string()], 1_000);
+        assert!(joined.is_active(1_000));
+        assert!(!joined.is_active(1_000 + MEMBERSHIP_TTL_MS));
+        assert!(!VoiceMemberEventContent::default().is_active(0));
+
+        // Leaving publishes an empty event.
+        let left = serde_json::to_string(&VoiceMemberEventContent::default()).unwrap();
+        assert_eq!(left, "{}");
+    }
+
+    #[test]
+    fn test_call_events() {
+        let invite = CallInviteEventContent { lifetime: 30_000 };
+        assert!(is_ringing(&invite, 1_000, 30_999));
+        assert!(!is_ringing(&invite, 1_000, 31_000));
+
+        let hangup = CallHangupEventContent {
+            call_id: "$invite".to_string(),
+            reason: HangupReason::Missed,
+        };
+        assert_eq!(
+            serde_json::to_string(&hangup).unwrap(),
+            r#"{"call_id":"$invite","reason":"missed"}"#
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Spell checking for the composer, with Hunspell dictionaries: a `.dic`
+//! and `.aff` pair per language, named for it as in `en_US.dic`. They are
+//! looked for in the folder picked in the settings, then in the app's own
+//! `dictionaries` folder, then where the system keeps them for other
+//! programs. Words the user adds are kept per account, in the config.
+
+use crate::session::app_dir;
+use anyhow::{anyhow, Context, Result};
+use chat_core::spelling;
+use spellbook::Dictionary;
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// Dictionaries kept apart from the system's, in the app's folder.
+const DICTIONARY_DIR: &str = "dictionaries";
+
+/// Where Linux distributions and macOS put dictionaries.
+const SYSTEM_DIRS: [&str; 4] = [
+    "/usr/share/hunspell",
+    "/usr/share/myspell",
+    "/usr/share/myspell/dicts",
+    "/Library/Spelling",
+];
+
+/// Most suggestions offered for a word.
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DictionaryFile {
+    /// As the files are named, e.g. "en_US".
+    pub language: String,
+    pub dic: PathBuf,
+    pub aff: PathBuf,
+}
+
+/// The app's own dictionary folder, where to put dictionaries the system
+/// doesn't have.
+pub fn own_dir() -> Result<PathBuf> {
+    Ok(app_dir()?.join(DICTIONARY_DIR))
+}
+
+/// The folders dictionaries are looked for in, in order: `custom` unless
+/// empty, ours, then the system's.
+pub fn search_dirs(custom: &str) -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    if !custom.trim().is_empty() {
+        folders.push(PathBuf::from(custom.trim()));
+    }
+    if let Ok(dir) = own_dir() {
+        folders.push(dir);
+    }
+    if let Some(data) = dirs::data_dir() {
+        folders.push(data.join("hunspell"));
+    }
+    if let Some(home) = dirs::home_dir() {
+        folders.push(home.join("Library").join("Spelling"));
+    }
+    folders.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+    folders
+}
+
+/// The dictionaries in `folders`, by language. A language's first one
+/// found is used; a `.dic` without its `.aff` isn't one.
+pub fn find(folders: &[PathBuf]) -> Vec<DictionaryFile> {
+    let mut found: Vec<DictionaryFile> = Vec::new();
+    for folder in folders {
+        let Ok(entries) = fs::read_dir(folder) else {
+            continue;
+        };
+        let mut here: Vec<DictionaryFile> = entries
+            .filter_map(|entry| {
+                let dic = entry.ok()?.path();
+                if dic.extension()? != "dic" {
+                    return None;
+                }
+                let aff = dic.with_extension("aff");
+                let language = dic.file_stem()?.to_str()?.to_string();
+                aff.is_file()
+                    .then_some(DictionaryFile { language, dic, aff })
+            })
+            .collect();
+        here.retain(|file| !found.iter().any(|f| f.language == file.language));
+        found.extend(here);
+    }
+    found.sort_by(|a, b| a.language.cmp(&b.language));
+    found
+}
+
+/// "en-us" as dictionaries are named: "en_US".
+fn normalize(language: &str) -> String {
+    match language.trim().replace('-', "_").split_once('_') {
+        Some((code, region)) => format!("{}_{}", code.to_lowercase(), region.to_uppercase()),
+        None => language.trim().to_lowercase(),
+    }
+}
+
+/// The dictionary for `language`, like "fr" or "en-GB": the one named for
+/// it, then for its language in the country of the same name ("fr_FR"),
+/// then in the US, then in any country.
+pub fn pick<'a>(dictionaries: &'a [DictionaryFile], language: &str) -> Option<&'a DictionaryFile> {
+    let language = normalize(language);
+    let code = language.split('_').next().unwrap_or_default();
+    let preferred = [
+        language.clone(),
+        format!("{}_{}", code, code.to_uppercase()),
+        format!("{}_US", code),
+    ];
+    preferred
+        .iter()
+        .find_map(|name| {
+            dictionaries
+                .iter()
+                .find(|d| normalize(&d.language) == *name)
+        })
+        .or_else(|| {
+            let prefix = format!("{}_", code);
+            dictionaries
+                .iter()
+                .find(|d| normalize(&d.language).starts_with(&prefix))
+        })
+}
+
+/// A dictionary loaded, with the words the user added to it, which are
+/// correct in any case.
+pub struct SpellChecker {
+    language: String,
+    dictionary: Dictionary,
+    personal: RwLock<HashSet<String>>,
+}
+
+impl SpellChecker {
+    /// Read `file`, which may take a moment for large dictionaries.
+    pub fn load(file: &DictionaryFile) -> Result<Self> {
+        let aff = fs::read_to_string(&file.aff)
+            .with_context(|| format!("Failed to read {}", file.aff.display()))?;
+        let dic = fs::read_to_string(&file.dic)
+            .with_context(|| format!("Failed to read {}", file.dic.display()))?;
+        let dictionary = Dictionary::new(&aff, &dic)
+            .map_err(|e| anyhow!("The {} dictionary is damaged: {}", file.language, e))?;
+        Ok(Self {
+            language: file.language.clone(),
+            dictionary,
+            personal: RwLock::new(HashSet::new()),
+        })
+    }
+
+    /// e.g. "en_US".
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn is_correct(&self, word: &str) -> bool {
+        let personal = self.personal.read().unwrap_or_else(|e| e.into_inner());
+        personal.contains(&word.to_lowercase()) || self.dictionary.check(word)
+    }
+
+    /// The words of `text` not in the dictionary, as byte ranges.
+    pub fn misspelled(&self, text: &str) -> Vec<Range<usize>> {
+        spelling::words(text)
+            .into_iter()
+            .filter(|word| !self.is_correct(&text[word.clone()]))
+            .collect()
+    }
+
+    /// What `word` may have been meant to be, likeliest first.
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let mut suggestions = Vec::new();
+        self.dictionary.suggest(word, &mut suggestions);
+        suggestions.truncate(MAX_SUGGESTIONS);
+        suggestions
+    }
+
+    /// Take `word` as correct from now on, in any case.
+    pub fn add(&self, word: &str) {
+        let mut personal = self.personal.write().unwrap_or_else(|e| e.into_inner());
+        personal.insert(word.to_lowercase());
+    }
+
+    /// Replace the words the user added, as another account signs in.
+    pub fn set_personal(&self, words: &[String]) {
+        let mut personal = self.personal.write().unwrap_or_else(|e| e.into_inner());
+        *personal = words.iter().map(|w| w.to_lowercase()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::time;
+
+    fn file(language: &str) -> DictionaryFile {
+        DictionaryFile {
+            language: language.to_string(),
+            dic: PathBuf::from(format!("{}.dic", language)),
+            aff: PathBuf::from(format!("{}.aff", language)),
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let installed = [
+            file("de_AT"),
+            file("en_GB"),
+            file("en_US"),
+            file("fr_FR"),
+            file("pt_BR"),
+        ];
+        let picked = |language| pick(&installed, language).map(|d| d.language.as_str());
+        assert_eq!(picked("en-gb"), Some("en_GB"));
+        assert_eq!(picked("en"), Some("en_US"));
+        assert_eq!(picked("en_AU"), Some("en_US"));
+        assert_eq!(picked("fr"), Some("fr_FR"));
+        assert_eq!(picked("de"), Some("de_AT"));
+        assert_eq!(picked("PT"), Some("pt_BR"));
+        assert_eq!(picked("es"), None);
+        assert_eq!(pick(&[], "en"), None);
+    }
+
+    #[test]
+    fn test_find() {
+        let root = std::env::temp_dir().join(format!(
+            "dictionaries-{}-{}",
+            std::process::id(),
+            time::now_ms()
+        ));
+        let ours = root.join("ours");
+        let system = root.join("system");
+        for dir in [&ours, &system] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        for path in [
+            ours.join("fr_FR.dic"),
+            ours.join("fr_FR.aff"),
+            // No .aff, so not a dictionary.
+            ours.join("de_DE.dic"),
+            system.join("fr_FR.dic"),
+            system.join("fr_FR.aff"),
+            system.join("en_US.dic"),
+            system.join("en_US.aff"),
+            system.join("README.txt"),
+        ] {
+            fs::write(path, "").unwrap();
+        }
+        let missing = root.join("missing");
+        let found = find(&[missing, ours.clone(), system.clone()]);
+        assert_eq!(
+            found,
+            vec![
+                DictionaryFile {
+                    language: "en_US".to_string(),
+                    dic: system.join("en_US.dic"),
+                    aff: system.join("en_US.aff"),
+                },
+                DictionaryFile {
+                    language: "fr_FR".to_string(),
+                    dic: ours.join("fr_FR.dic"),
+                    aff: ours.join("fr_FR.aff"),
+                },
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
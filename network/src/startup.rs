@@ -0,0 +1,119 @@
+//! Loading the joined rooms after signing in. Each room costs a round trip
+//! or two to resolve, so rooms are resolved a few at a time rather than one
+//! after another, and each step is timed so a slow start shows in the debug
+//! log. How fast resolving is gets measured in benches/startup.rs.
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::future::Future;
+use std::time::Instant;
+
+/// Rooms resolved at once when the config doesn't say.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// `resolve` each of `items`, at most `concurrency` at a time. Results are
+/// in the order of `items`, whichever finished first.
+pub async fn resolve_bounded<I, T, F, Fut>(
+    items: Vec<I>,
+    concurrency: usize,
+    mut resolve: F,
+) -> Vec<T>
+where
+    F: FnMut(I) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let mut results: Vec<Option<T>> = items.iter().map(|_| None).collect();
+    let mut pending = items.into_iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    loop {
+        while running.len() < concurrency.max(1) {
+            let Some((index, item)) = pending.next() else {
+                break;
+            };
+            let resolving = resolve(item);
+            running.push(async move { (index, resolving.await) });
+        }
+        match running.next().await {
+            Some((index, result)) => results[index] = Some(result),
+            None => break,
+        }
+    }
+    results.into_iter().flatten().collect()
+}
+
+/// Logs how long it lived at debug level when dropped, e.g. "rooms:
+/// 412.30ms" with the target `startup`.
+pub struct Span {
+    name: &'static str,
+    started: Instant,
+}
+
+impl Span {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        tracing::debug!(target: "startup", "{}: {:.2?}", self.name, self.started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Stands in for a homeserver: every request takes `latency` and the
+    /// most requests ever in flight at once are counted.
+    struct MockServer {
+        latency: Duration,
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl MockServer {
+        fn new(latency: Duration) -> Self {
+            Self {
+                latency,
+                in_flight: AtomicUsize::new(0),
+                peak: AtomicUsize::new(0),
+            }
+        }
+
+        async fn room_name(&self, room: usize) -> String {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            // Later rooms answer sooner, so finishing order differs from
+            // asking order.
+            tokio::time::sleep(self.latency + Duration::from_micros(((500 - room) * 4) as u64))
+                .await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            format!("room {}", room)
+        }
+    }
+
+    // How long this takes is measured in benches/startup.rs.
+    #[tokio::test]
+    async fn test_500_rooms_resolve_bounded_and_in_order() {
+        let server = MockServer::new(Duration::from_millis(5));
+        let rooms: Vec<usize> = (0..500).collect();
+
+        let names =
+            resolve_bounded(rooms, DEFAULT_CONCURRENCY, |room| server.room_name(room)).await;
+
+        let expected: Vec<String> = (0..500).map(|room| format!("room {}", room)).collect();
+        assert_eq!(names, expected);
+        assert_eq!(server.peak.load(Ordering::SeqCst), DEFAULT_CONCURRENCY);
+    }
+
+    #[tokio::test]
+    async fn test_zero_concurrency_still_resolves() {
+        let doubled = resolve_bounded(vec![1, 2, 3], 0, |n| async move { n * 2 }).await;
+        assert_eq!(doubled, vec![2, 4, 6]);
+    }
+}
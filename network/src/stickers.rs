@@ -0,0 +1,266 @@
+//! Sticker packs in the image pack format Element and others use
+//! (MSC2545): the user's own pack in account data, packs in rooms' state,
+//! and the rooms whose packs the user enabled everywhere. Packs in the wild
+//! are loosely shaped, from older clients and hand-written JSON alike, so
+//! entries that make no sense are skipped rather than failing their pack.
+
+use serde_json::{Map, Value};
+
+/// Account data holding the user's own pack.
+pub const USER_PACK_TYPE: &str = "im.ponies.user_emotes";
+/// Room state holding a room's packs, one per state key.
+pub const ROOM_PACK_TYPE: &str = "im.ponies.room_emotes";
+/// Account data listing the room packs the user enabled in every room.
+pub const EMOTE_ROOMS_TYPE: &str = "im.ponies.emote_rooms";
+
+/// The name of the user's own pack when it doesn't give one.
+pub const USER_PACK_NAME: &str = "Your stickers";
+
+/// One image of a pack, sent as an `m.sticker` event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sticker {
+    pub shortcode: String,
+    /// Text sent along, for clients that can't show the image.
+    pub body: String,
+    /// `mxc://` URI of the image.
+    pub url: String,
+    pub mimetype: Option<String>,
+    /// Size in bytes, when the pack gives it.
+    pub size: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StickerPack {
+    pub name: String,
+    /// `mxc://` URI of the pack's icon; the first sticker stands in without
+    /// one.
+    pub avatar_url: Option<String>,
+    pub stickers: Vec<Sticker>,
+}
+
+impl StickerPack {
+    /// The image to show for the pack in the picker's tabs.
+    pub fn icon_url(&self) -> Option<&str> {
+        self.avatar_url
+            .as_deref()
+            .or_else(|| self.stickers.first().map(|s| s.url.as_str()))
+    }
+}
+
+/// An `mxc://server/media` URI, or `None` for anything else.
+fn mxc(value: Option<&Value>) -> Option<String> {
+    let url = value?.as_str()?.trim();
+    let (server, media) = url.strip_prefix("mxc://")?.split_once('/')?;
+    (!server.is_empty() && !media.is_empty() && !media.contains('/')).then(|| url.to_string())
+}
+
+fn text(value: Option<&Value>) -> Option<String> {
+    let text = value?.as_str()?.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// A count given as a number, or as a number in a string as some packs do.
+fn count(value: Option<&Value>) -> Option<u64> {
+    match value? {
+        Value::Number(n) => n.as_u64().or_else(|| {
+            let n = n.as_f64()?;
+            (n >= 0.0 && n.is_finite()).then(|| n.round() as u64)
+        }),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Whether a `usage` list allows stickers: `None` if there is no list.
+fn for_stickers(usage: Option<&Value>) -> Option<bool> {
+    let usage = usage?.as_array()?;
+    Some(usage.iter().any(|u| u.as_str() == Some("sticker")))
+}
+
+/// The image `shortcode` stands for, if it is usable as a sticker. Images
+/// without a `usage` of their own take the pack's.
+fn sticker(shortcode: &str, image: &Value, pack_usage: Option<bool>) -> Option<Sticker> {
+    let shortcode = shortcode.trim().trim_matches(':');
+    if shortcode.is_empty() {
+        return None;
+    }
+    // Older packs map shortcodes straight to URIs.
+    if image.is_string() {
+        if pack_usage == Some(false) {
+            return None;
+        }
+        return Some(Sticker {
+            shortcode: shortcode.to_string(),
+            body: shortcode.to_string(),
+            url: mxc(Some(image))?,
+            mimetype: None,
+            size: None,
+            width: None,
+            height: None,
+        });
+    }
+    let image = image.as_object()?;
+    if for_stickers(image.get("usage")).or(pack_usage) == Some(false) {
+        return None;
+    }
+    let info = image.get("info").and_then(Value::as_object);
+    let info = |key: &str| info.and_then(|info| info.get(key));
+    Some(Sticker {
+        shortcode: shortcode.to_string(),
+        body: text(image.get("body")).unwrap_or_else(|| shortcode.to_string()),
+        url: mxc(image.get("url"))?,
+        mimetype: text(info("mimetype")),
+        size: count(info("size")),
+        width: count(info("w")).and_then(|w| u32::try_from(w).ok()),
+        height: count(info("h")).and_then(|h| u32::try_from(h).ok()),
+    })
+}
+
+/// The stickers of a pack's `images`, as a map by shortcode or a list of
+/// images carrying theirs.
+fn stickers(images: &Value, pack_usage: Option<bool>) -> Vec<Sticker> {
+    match images {
+        Value::Object(images) => images
+            .iter()
+            .filter_map(|(shortcode, image)| sticker(shortcode, image, pack_usage))
+            .collect(),
+        Value::Array(images) => images
+            .iter()
+            .filter_map(|image| {
+                let shortcode = image.get("shortcode").and_then(Value::as_str)?;
+                sticker(shortcode, image, pack_usage)
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The pack in an image pack event's `content`, named `fallback_name` if
+/// it has no name of its own. `None` if it holds no usable stickers.
+pub fn parse_pack(content: &Value, fallback_name: &str) -> Option<StickerPack> {
+    let empty = Map::new();
+    let pack = content
+        .get("pack")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let usage = for_stickers(pack.get("usage"));
+    let mut stickers = match content.get("images") {
+        Some(images) => stickers(images, usage),
+        None => Vec::new(),
+    };
+    // The format before `images`: shortcodes to URIs, for emoticons and
+    // stickers alike.
+    if let Some(short) = content.get("short") {
+        for sticker in self::stickers(short, usage) {
+            if !stickers.iter().any(|s| s.shortcode == sticker.shortcode) {
+                stickers.push(sticker);
+            }
+        }
+    }
+    if stickers.is_empty() {
+        return None;
+    }
+    Some(StickerPack {
+        name: text(pack.get("display_name")).unwrap_or_else(|| fallback_name.to_string()),
+        avatar_url: mxc(pack.get("avatar_url")),
+        stickers,
+    })
+}
+
+/// The packs the user enabled in every room, from the content of their
+/// `im.ponies.emote_rooms` account data, as room ids and state keys.
+pub fn enabled_rooms(content: &Value) -> Vec<(String, String)> {
+    let Some(rooms) = content.get("rooms").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    rooms
+        .iter()
+        .filter(|(room_id, _)| room_id.starts_with('!'))
+        .flat_map(|(room_id, packs)| {
+            let packs = packs.as_object().into_iter().flat_map(Map::keys);
+            packs.map(move |state_key| (room_id.clone(), state_key.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn shortcodes(pack: &StickerPack) -> Vec<&str> {
+        pack.stickers.iter().map(|s| s.shortcode.as_str()).collect()
+    }
+
+    #[test]
+    fn test_element_pack() {
+        let content = fixture(include_str!("../fixtures/image_packs/element.json"));
+        let pack = parse_pack(&content, "fallback").unwrap();
+        assert_eq!(pack.name, "Cats");
+        assert_eq!(pack.avatar_url.as_deref(), Some("mxc://example.org/cats"));
+        // The emoticon-only image is left out.
+        assert_eq!(shortcodes(&pack), vec!["cat_happy", "cat_sleep"]);
+        assert_eq!(
+            pack.stickers[0],
+            Sticker {
+                shortcode: "cat_happy".to_string(),
+                body: "Happy cat".to_string(),
+                url: "mxc://example.org/happy".to_string(),
+                mimetype: Some("image/png".to_string()),
+                size: Some(40213),
+                width: Some(256),
+                height: Some(256),
+            }
+        );
+        assert_eq!(pack.stickers[1].body, "cat_sleep");
+    }
+
+    #[test]
+    fn test_broken_entries_are_skipped() {
+        let content = fixture(include_str!("../fixtures/image_packs/broken.json"));
+        let pack = parse_pack(&content, "Room pack").unwrap();
+        assert_eq!(pack.name, "Room pack");
+        assert_eq!(pack.avatar_url, None);
+        assert_eq!(pack.icon_url(), Some("mxc://example.org/good"));
+        assert_eq!(shortcodes(&pack), vec!["good", "string_sizes"]);
+        let sizes = &pack.stickers[1];
+        assert_eq!((sizes.width, sizes.height), (Some(128), Some(96)));
+        assert_eq!((sizes.size, sizes.mimetype.as_deref()), (None, None));
+    }
+
+    #[test]
+    fn test_older_shapes() {
+        let content = fixture(include_str!("../fixtures/image_packs/legacy.json"));
+        let pack = parse_pack(&content, USER_PACK_NAME).unwrap();
+        assert_eq!(pack.name, USER_PACK_NAME);
+        assert_eq!(shortcodes(&pack), vec!["wave", "blob", "party"]);
+        assert_eq!(pack.stickers[2].url, "mxc://example.org/party");
+    }
+
+    #[test]
+    fn test_packs_without_stickers() {
+        let content = fixture(include_str!("../fixtures/image_packs/emoticons.json"));
+        assert_eq!(parse_pack(&content, "Emoji"), None);
+        assert_eq!(parse_pack(&Value::Null, "Nothing"), None);
+        assert_eq!(parse_pack(&fixture(r#"{"images": 3}"#), "Odd"), None);
+    }
+
+    #[test]
+    fn test_enabled_rooms() {
+        let content = fixture(include_str!("../fixtures/image_packs/emote_rooms.json"));
+        assert_eq!(
+            enabled_rooms(&content),
+            vec![
+                ("!games:example.org".to_string(), String::new()),
+                ("!games:example.org".to_string(), "memes".to_string()),
+                ("!raids:example.org".to_string(), "tanks".to_string()),
+            ]
+        );
+        assert!(enabled_rooms(&fixture(r#"{"rooms": []}"#)).is_empty());
+    }
+}
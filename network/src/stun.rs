@@ -0,0 +1,190 @@
+//! Just enough STUN (RFC 5389) to ask a server which address our UDP
+//! packets arrive from: one Binding request, and the XOR-MAPPED-ADDRESS
+//! of its answer. TURN servers answer these too, so the homeserver's own
+//! can be asked without telling anyone else we are here.
+
+use anyhow::{bail, Context, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+/// Where STUN and TURN listen unless told otherwise.
+const DEFAULT_PORT: u16 = 3478;
+
+/// A Binding request under transaction id `txn`.
+pub fn binding_request(txn: [u8; 12]) -> [u8; HEADER_LEN] {
+    let mut request = [0; HEADER_LEN];
+    request[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // No attributes, so the length stays 0.
+    request[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request[8..20].copy_from_slice(&txn);
+    request
+}
+
+/// The address a Binding success answer to `txn` says we were seen from.
+pub fn mapped_address(response: &[u8], txn: [u8; 12]) -> Option<SocketAddr> {
+    let header = response.get(..HEADER_LEN)?;
+    if u16::from_be_bytes([header[0], header[1]]) != BINDING_SUCCESS
+        || header[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || header[8..20] != txn
+    {
+        return None;
+    }
+    let length = usize::from(u16::from_be_bytes([header[2], header[3]]));
+    let mut attributes = response.get(HEADER_LEN..HEADER_LEN + length)?;
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = usize::from(u16::from_be_bytes([attributes[2], attributes[3]]));
+        let value = attributes.get(4..4 + len)?;
+        if kind == XOR_MAPPED_ADDRESS {
+            return xor_address(value, txn);
+        }
+        // Attributes are padded to four bytes.
+        let padded = (4 + len).next_multiple_of(4);
+        attributes = attributes.get(padded..).unwrap_or_default();
+    }
+    None
+}
+
+fn xor_address(value: &[u8], txn: [u8; 12]) -> Option<SocketAddr> {
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let port = u16::from_be_bytes([value.get(2)? ^ cookie[0], value.get(3)? ^ cookie[1]]);
+    let ip = match value.get(1)? {
+        0x01 => {
+            let mut octets = [0; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value.get(4 + i)? ^ cookie[i];
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        0x02 => {
+            let key: Vec<u8> = cookie.iter().chain(&txn).copied().collect();
+            let mut octets = [0; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value.get(4 + i)? ^ key[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// The `host:port` a `stun:` or `turn:` URI points at, e.g.
+/// "turn:turn.example.org?transport=udp". `turns:` runs over TLS and is
+/// left out, as are URIs only for TCP.
+pub fn server_address(uri: &str) -> Option<String> {
+    let (scheme, rest) = uri.split_once(':')?;
+    if !matches!(scheme, "stun" | "turn") {
+        return None;
+    }
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if query.split('&').any(|param| param == "transport=tcp") {
+        return None;
+    }
+    // An IPv6 host is bracketed, so its own colons aren't taken for a port.
+    let has_port = address.rsplit_once(':').is_some_and(|(host, port)| {
+        port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+    });
+    if address.is_empty() {
+        None
+    } else if has_port {
+        Some(address.to_string())
+    } else {
+        Some(format!("{}:{}", address, DEFAULT_PORT))
+    }
+}
+
+/// Ask `server` from `socket` which address we are seen from, giving up
+/// after `wait`.
+pub async fn query(socket: &UdpSocket, server: SocketAddr, wait: Duration) -> Result<SocketAddr> {
+    let txn: [u8; 12] = rand::random();
+    socket
+        .send_to(&binding_request(txn), server)
+        .await
+        .with_context(|| format!("Could not send to {}", server))?;
+    let mut buf = [0; 512];
+    let answer = tokio::time::timeout(wait, async {
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            if from == server {
+                if let Some(address) = mapped_address(&buf[..len], txn) {
+                    return Ok::<_, std::io::Error>(address);
+                }
+            }
+        }
+    })
+    .await;
+    match answer {
+        Ok(result) => Ok(result?),
+        Err(_) => bail!("No answer from {} within {:?}", server, wait),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TXN: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    /// A Binding success answer carrying `attributes`.
+    fn response(attributes: &[u8]) -> Vec<u8> {
+        let mut response = Vec::new();
+        response.extend(BINDING_SUCCESS.to_be_bytes());
+        response.extend((attributes.len() as u16).to_be_bytes());
+        response.extend(MAGIC_COOKIE.to_be_bytes());
+        response.extend(TXN);
+        response.extend(attributes);
+        response
+    }
+
+    #[test]
+    fn test_mapped_address_is_read() {
+        let request = binding_request(TXN);
+        assert_eq!(&request[..4], &[0, 1, 0, 0]);
+        assert_eq!(&request[8..], &TXN);
+
+        // 192.0.2.1:32853 from RFC 5769, behind a SOFTWARE attribute that
+        // takes padding.
+        let mut attributes = vec![0x80, 0x22, 0x00, 0x03, b'a', b'b', b'c', 0];
+        attributes.extend([0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47]);
+        attributes.extend([0xe1, 0x12, 0xa6, 0x43]);
+        assert_eq!(
+            mapped_address(&response(&attributes), TXN),
+            Some("192.0.2.1:32853".parse().unwrap())
+        );
+        // Someone else's answer, or a cut off one.
+        assert_eq!(mapped_address(&response(&attributes), [0; 12]), None);
+        assert_eq!(mapped_address(&response(&attributes)[..30], TXN), None);
+        assert_eq!(mapped_address(&response(&[]), TXN), None);
+    }
+
+    #[test]
+    fn test_server_addresses_from_uris() {
+        let cases = [
+            (
+                "turn:turn.example.org?transport=udp",
+                Some("turn.example.org:3478"),
+            ),
+            (
+                "turn:turn.example.org:5349?transport=udp",
+                Some("turn.example.org:5349"),
+            ),
+            ("stun:203.0.113.5", Some("203.0.113.5:3478")),
+            ("stun:[2001:db8::1]:3479", Some("[2001:db8::1]:3479")),
+            ("stun:[2001:db8::1]", Some("[2001:db8::1]:3478")),
+            ("turn:turn.example.org?transport=tcp", None),
+            ("turns:turn.example.org:5349", None),
+            ("https://example.org", None),
+            ("turn:", None),
+        ];
+        for (uri, address) in cases {
+            assert_eq!(server_address(uri).as_deref(), address, "{}", uri);
+        }
+    }
+}
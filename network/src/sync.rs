@@ -0,0 +1,628 @@
+use crate::encryption;
+use crate::events::{
+    CallInvite, ChatEvent, KnockRequest, NotificationEvent, TypingEvent, TypingUser,
+};
+use crate::members::Presences;
+use crate::rooms;
+use crate::roster::{self, FriendEventContent, Roster, ROSTER_EVENT_TYPE};
+use crate::signaling::{
+    self, CallAnswerEventContent, CallHangupEventContent, CallInviteEventContent, HangupReason,
+    VoiceMemberEventContent,
+};
+use crate::stickers::{EMOTE_ROOMS_TYPE, ROOM_PACK_TYPE, USER_PACK_TYPE};
+use chat_core::{time, MessageTrust, Reaction};
+use matrix_sdk::deserialized_responses::EncryptionInfo;
+use matrix_sdk::ruma::api::client::push::get_notifications::v3::Notification;
+use matrix_sdk::ruma::events::presence::PresenceEvent;
+use matrix_sdk::ruma::events::reaction::OriginalSyncReactionEvent;
+use matrix_sdk::ruma::events::room::canonical_alias::OriginalSyncRoomCanonicalAliasEvent;
+use matrix_sdk::ruma::events::room::encryption::OriginalSyncRoomEncryptionEvent;
+use matrix_sdk::ruma::events::room::member::{
+    MembershipState, OriginalSyncRoomMemberEvent, StrippedRoomMemberEvent,
+};
+use matrix_sdk::ruma::events::room::message::OriginalSyncRoomMessageEvent;
+use matrix_sdk::ruma::events::room::name::OriginalSyncRoomNameEvent;
+use matrix_sdk::ruma::events::room::pinned_events::OriginalSyncRoomPinnedEventsEvent;
+use matrix_sdk::ruma::events::room::power_levels::OriginalSyncRoomPowerLevelsEvent;
+use matrix_sdk::ruma::events::room::redaction::OriginalSyncRoomRedactionEvent;
+use matrix_sdk::ruma::events::room::topic::OriginalSyncRoomTopicEvent;
+use matrix_sdk::ruma::events::space::child::OriginalSyncSpaceChildEvent;
+use matrix_sdk::ruma::events::sticker::OriginalSyncStickerEvent;
+use matrix_sdk::ruma::events::typing::SyncTypingEvent;
+use matrix_sdk::ruma::events::{
+    AnyGlobalAccountDataEvent, AnySyncMessageLikeEvent, AnySyncStateEvent, AnySyncTimelineEvent,
+    OriginalSyncMessageLikeEvent, OriginalSyncStateEvent, SyncMessageLikeEvent,
+};
+use matrix_sdk::ruma::push::Action;
+use matrix_sdk::ruma::serde::Raw;
+use matrix_sdk::ruma::UserId;
+use matrix_sdk::sync::SyncResponse;
+use matrix_sdk::{Client, Room};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// How far a message from `sender` decrypted with `info` can be trusted;
+/// `None` if it came in the clear.
+fn trust(
+    info: Option<&EncryptionInfo>,
+    sender: &UserId,
+    changed_keys: &Mutex<HashSet<String>>,
+) -> Option<MessageTrust> {
+    let keys_changed = changed_keys.lock().unwrap().contains(sender.as_str());
+    info.map(|info| encryption::message_trust(info, keys_changed))
+}
+
+/// Register the event handlers that translate sync responses into `ChatEvent`s.
+pub(crate) fn register_handlers(
+    client: &Client,
+    tx: broadcast::Sender<ChatEvent>,
+    roster: Arc<Mutex<Roster>>,
+    presences: Arc<Mutex<Presences>>,
+    changed_keys: Arc<Mutex<HashSet<String>>>,
+) {
+    let msg_tx = tx.clone();
+    let msg_changed_keys = changed_keys.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncRoomMessageEvent, room: Room, info: Option<EncryptionInfo>| {
+            let tx = msg_tx.clone();
+            let changed_keys = msg_changed_keys.clone();
+            async move {
+                if let Some(replacement) = rooms::replacement(&ev.content) {
+                    let _ = tx.send(ChatEvent::MessageEdited {
+                        room_id: room.room_id().to_string(),
+                        event_id: replacement.event_id,
+                        sender: ev.sender.to_string(),
+                        body: replacement.body,
+                        formatted: replacement.formatted,
+                    });
+                    return;
+                }
+                let mut message = rooms::message_to_core(
+                    &ev.event_id,
+                    &ev.sender,
+                    ev.origin_server_ts,
+                    &ev.content,
+                );
+                message.sender_name = rooms::member_name(&room, &ev.sender).await;
+                message.trust = trust(info.as_ref(), &ev.sender, &changed_keys);
+                let _ = tx.send(ChatEvent::Message {
+                    room_id: room.room_id().to_string(),
+                    message: Box::new(message),
+                    transaction_id: ev.unsigned.transaction_id.map(|t| t.to_string()),
+                });
+            }
+        },
+    );
+
+    let sticker_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncStickerEvent, room: Room, info: Option<EncryptionInfo>| {
+            let tx = sticker_tx.clone();
+            let changed_keys = changed_keys.clone();
+            async move {
+                let mut message = rooms::sticker_to_core(
+                    &ev.event_id,
+                    &ev.sender,
+                    ev.origin_server_ts,
+                    &ev.content,
+                );
+                message.sender_name = rooms::member_name(&room, &ev.sender).await;
+                message.trust = trust(info.as_ref(), &ev.sender, &changed_keys);
+                let _ = tx.send(ChatEvent::Message {
+                    room_id: room.room_id().to_string(),
+                    message: Box::new(message),
+                    transaction_id: ev.unsigned.transaction_id.map(|t| t.to_string()),
+                });
+            }
+        },
+    );
+
+    let reaction_tx = tx.clone();
+    client.add_event_handler(move |ev: OriginalSyncReactionEvent, room: Room| {
+        let tx = reaction_tx.clone();
+        async move {
+            let message_id = ev.content.relates_to.event_id.to_string();
+            let reaction = Reaction {
+                key: ev.content.relates_to.key,
+                sender: ev.sender.to_string(),
+                event_id: ev.event_id.to_string(),
+            };
+            let mut row = rooms::reaction_row(&message_id, &reaction, ev.origin_server_ts);
+            row.sender_name = rooms::member_name(&room, &ev.sender).await;
+            let _ = tx.send(ChatEvent::Message {
+                room_id: room.room_id().to_string(),
+                message: Box::new(row),
+                transaction_id: None,
+            });
+            let _ = tx.send(ChatEvent::Reacted {
+                room_id: room.room_id().to_string(),
+                message_id,
+                reaction,
+            });
+        }
+    });
+
+    let redaction_tx = tx.clone();
+    client.add_event_handler(move |ev: OriginalSyncRoomRedactionEvent, room: Room| {
+        let tx = redaction_tx.clone();
+        async move {
+            // Newer rooms carry the target in the content, older ones beside it.
+            let Some(event_id) = ev.content.redacts.or(ev.redacts) else {
+                return;
+            };
+            let _ = tx.send(ChatEvent::Redacted {
+                room_id: room.room_id().to_string(),
+                event_id: event_id.to_string(),
+            });
+        }
+    });
+
+    let name_tx = tx.clone();
+    client.add_event_handler(move |_: OriginalSyncRoomNameEvent, room: Room| {
+        let tx = name_tx.clone();
+        async move {
+            let name = room
+                .display_name()
+                .await
+                .map(|n| n.to_string())
+                .unwrap_or_else(|_| room.room_id().to_string());
+            let _ = tx.send(ChatEvent::RoomRenamed {
+                room_id: room.room_id().to_string(),
+                name,
+            });
+        }
+    });
+
+    let alias_tx = tx.clone();
+    client.add_event_handler(move |ev: OriginalSyncRoomCanonicalAliasEvent, room: Room| {
+        let tx = alias_tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::RoomAliasChanged {
+                room_id: room.room_id().to_string(),
+                alias: ev.content.alias.map(|a| a.to_string()),
+            });
+        }
+    });
+
+    let topic_tx = tx.clone();
+    client.add_event_handler(move |ev: OriginalSyncRoomTopicEvent, room: Room| {
+        let tx = topic_tx.clone();
+        async move {
+            let topic = ev.content.topic.trim();
+            let _ = tx.send(ChatEvent::RoomTopicChanged {
+                room_id: room.room_id().to_string(),
+                topic: (!topic.is_empty()).then(|| topic.to_string()),
+                sender: ev.sender.to_string(),
+                sender_name: rooms::member_name(&room, &ev.sender).await,
+            });
+        }
+    });
+
+    let pins_tx = tx.clone();
+    client.add_event_handler(move |ev: OriginalSyncRoomPinnedEventsEvent, room: Room| {
+        let tx = pins_tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::PinsChanged {
+                room_id: room.room_id().to_string(),
+                pinned: ev.content.pinned.iter().map(|id| id.to_string()).collect(),
+            });
+        }
+    });
+
+    let encryption_tx = tx.clone();
+    client.add_event_handler(move |_: OriginalSyncRoomEncryptionEvent, room: Room| {
+        let tx = encryption_tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::RoomEncrypted {
+                room_id: room.room_id().to_string(),
+            });
+        }
+    });
+
+    let member_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncRoomMemberEvent, room: Room, client: Client| {
+            let tx = member_tx.clone();
+            async move {
+                let name = ev
+                    .content
+                    .displayname
+                    .as_deref()
+                    .or_else(|| ev.prev_content()?.displayname.as_deref());
+                if let Some(message) = rooms::member_change_to_core(
+                    &ev.event_id,
+                    &ev.state_key,
+                    name,
+                    ev.origin_server_ts,
+                    &ev.membership_change(),
+                ) {
+                    let _ = tx.send(ChatEvent::Message {
+                        room_id: room.room_id().to_string(),
+                        message: Box::new(message),
+                        transaction_id: None,
+                    });
+                }
+                let _ = tx.send(ChatEvent::MembersChanged {
+                    room_id: room.room_id().to_string(),
+                });
+                let Some(own_id) = client.user_id() else {
+                    return;
+                };
+                if own_id != ev.state_key {
+                    knock_changed(&tx, &ev, &room, own_id).await;
+                    return;
+                }
+                let event = match ev.content.membership {
+                    MembershipState::Join if room.is_space() => {
+                        ChatEvent::SpaceJoined(rooms::space_to_core(&room).await)
+                    }
+                    MembershipState::Join => ChatEvent::RoomJoined(
+                        rooms::room_to_core(&room, ev.origin_server_ts.0.into()).await,
+                    ),
+                    MembershipState::Leave | MembershipState::Ban => ChatEvent::RoomLeft {
+                        room_id: room.room_id().to_string(),
+                    },
+                    _ => return,
+                };
+                let _ = tx.send(event);
+            }
+        },
+    );
+
+    let invited_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: StrippedRoomMemberEvent, room: Room, client: Client| {
+            let tx = invited_tx.clone();
+            async move {
+                if ev.content.membership == MembershipState::Invite
+                    && client.user_id() == Some(&*ev.state_key)
+                {
+                    let _ = tx.send(ChatEvent::Invited {
+                        room_id: room.room_id().to_string(),
+                    });
+                }
+            }
+        },
+    );
+
+    let power_tx = tx.clone();
+    client.add_event_handler(move |_: OriginalSyncRoomPowerLevelsEvent, room: Room| {
+        let tx = power_tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::MembersChanged {
+                room_id: room.room_id().to_string(),
+            });
+        }
+    });
+
+    let slow_mode_tx = tx.clone();
+    client.add_event_handler(move |_: rooms::OriginalSyncSlowModeEvent, room: Room| {
+        let tx = slow_mode_tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::MembersChanged {
+                room_id: room.room_id().to_string(),
+            });
+        }
+    });
+
+    let welcome_tx = tx.clone();
+    client.add_event_handler(move |_: rooms::OriginalSyncWelcomeEvent, room: Room| {
+        let tx = welcome_tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::WelcomeChanged {
+                room_id: room.room_id().to_string(),
+            });
+        }
+    });
+
+    // Image packs have no event types in the SDK, so they are told apart by
+    // their type alone.
+    let account_packs_tx = tx.clone();
+    client.add_event_handler(move |raw: Raw<AnyGlobalAccountDataEvent>| {
+        let tx = account_packs_tx.clone();
+        async move {
+            let event_type: Option<String> = raw.get_field("type").ok().flatten();
+            if matches!(
+                event_type.as_deref(),
+                Some(USER_PACK_TYPE | EMOTE_ROOMS_TYPE)
+            ) {
+                let _ = tx.send(ChatEvent::StickerPacksChanged { room_id: None });
+            }
+        }
+    });
+
+    let stored_roster = roster.clone();
+    let stored_roster_tx = tx.clone();
+    client.add_event_handler(move |raw: Raw<AnyGlobalAccountDataEvent>, client: Client| {
+        let roster = stored_roster.clone();
+        let tx = stored_roster_tx.clone();
+        async move {
+            let event_type: Option<String> = raw.get_field("type").ok().flatten();
+            if event_type.as_deref() != Some(ROSTER_EVENT_TYPE) {
+                return;
+            }
+            let Ok(Some(stored)) = raw.get_field::<Roster>("content") else {
+                tracing::warn!("Ignoring a friends list that can't be read");
+                return;
+            };
+            match roster::stored_elsewhere(&client, &roster, stored).await {
+                Ok(true) => {
+                    let _ = tx.send(ChatEvent::RosterChanged);
+                }
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Failed to merge the friends list: {}", e),
+            }
+        }
+    });
+
+    let handshake_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncMessageLikeEvent<FriendEventContent>, room: Room, client: Client| {
+            let roster = roster.clone();
+            let tx = handshake_tx.clone();
+            async move {
+                let sent_ts: u64 = ev.origin_server_ts.0.into();
+                match roster::handshake_received(
+                    &client,
+                    &roster,
+                    &room,
+                    &ev.sender,
+                    &ev.content,
+                    sent_ts,
+                )
+                .await
+                {
+                    Ok(true) => {
+                        let _ = tx.send(ChatEvent::RosterChanged);
+                    }
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("Ignoring a friend request from {}: {}", ev.sender, e),
+                }
+            }
+        },
+    );
+
+    let room_packs_tx = tx.clone();
+    client.add_event_handler(move |raw: Raw<AnySyncStateEvent>, room: Room| {
+        let tx = room_packs_tx.clone();
+        async move {
+            let event_type: Option<String> = raw.get_field("type").ok().flatten();
+            if event_type.as_deref() == Some(ROOM_PACK_TYPE) {
+                let _ = tx.send(ChatEvent::StickerPacksChanged {
+                    room_id: Some(room.room_id().to_string()),
+                });
+            }
+        }
+    });
+
+    let presence_tx = tx.clone();
+    client.add_event_handler(move |ev: PresenceEvent| {
+        let tx = presence_tx.clone();
+        let presences = presences.clone();
+        async move {
+            let (status, message) = {
+                let mut presences = presences.lock().unwrap();
+                let user_id = ev.sender.as_str();
+                presences.update(
+                    user_id,
+                    &ev.content.presence,
+                    ev.content.status_msg.as_deref(),
+                );
+                presences.get(user_id)
+            };
+            let _ = tx.send(ChatEvent::PresenceChanged {
+                user_id: ev.sender.to_string(),
+                status,
+                message,
+            });
+        }
+    });
+
+    let voice_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncStateEvent<VoiceMemberEventContent>, room: Room| {
+            let tx = voice_tx.clone();
+            async move {
+                let member =
+                    signaling::voice_member(&room, &ev.state_key, &ev.content, time::now_ms())
+                        .await;
+                let _ = tx.send(ChatEvent::VoiceMembership {
+                    room_id: room.room_id().to_string(),
+                    member,
+                });
+            }
+        },
+    );
+
+    let invite_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncMessageLikeEvent<CallInviteEventContent>,
+              room: Room,
+              client: Client| {
+            let tx = invite_tx.clone();
+            async move {
+                let sent_ts: u64 = ev.origin_server_ts.0.into();
+                // Our own calls, and ones that rang out before we synced.
+                if client.user_id() == Some(&*ev.sender)
+                    || !signaling::is_ringing(&ev.content, sent_ts, time::now_ms())
+                {
+                    return;
+                }
+                let _ = tx.send(ChatEvent::IncomingCall(CallInvite {
+                    room_id: room.room_id().to_string(),
+                    call_id: ev.event_id.to_string(),
+                    caller: ev.sender.to_string(),
+                    caller_name: rooms::member_name(&room, &ev.sender).await,
+                    expires_ts: sent_ts.saturating_add(ev.content.lifetime),
+                }));
+            }
+        },
+    );
+
+    let answer_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncMessageLikeEvent<CallAnswerEventContent>, room: Room| {
+            let tx = answer_tx.clone();
+            async move {
+                let _ = tx.send(ChatEvent::CallAnswered {
+                    room_id: room.room_id().to_string(),
+                    call_id: ev.content.call_id,
+                    user_id: ev.sender.to_string(),
+                });
+            }
+        },
+    );
+
+    let hangup_tx = tx.clone();
+    client.add_event_handler(
+        move |ev: OriginalSyncMessageLikeEvent<CallHangupEventContent>, room: Room| {
+            let tx = hangup_tx.clone();
+            async move {
+                let room_id = room.room_id().to_string();
+                if ev.content.reason == HangupReason::Missed {
+                    let mut message =
+                        rooms::missed_call(&ev.event_id, &ev.sender, ev.origin_server_ts);
+                    message.sender_name = rooms::member_name(&room, &ev.sender).await;
+                    let _ = tx.send(ChatEvent::Message {
+                        room_id: room_id.clone(),
+                        message: Box::new(message),
+                        transaction_id: None,
+                    });
+                }
+                let _ = tx.send(ChatEvent::CallHungUp {
+                    room_id,
+                    call_id: ev.content.call_id,
+                    user_id: ev.sender.to_string(),
+                    reason: ev.content.reason,
+                });
+            }
+        },
+    );
+
+    client.add_event_handler(move |_: OriginalSyncSpaceChildEvent, room: Room| {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(ChatEvent::SpaceChildrenChanged {
+                space_id: room.room_id().to_string(),
+                children: rooms::space_children(&room).await,
+            });
+        }
+    });
+}
+
+/// Tell about a knock on `room` coming in, or going away as it is answered
+/// or withdrawn, if `own_id` can answer it.
+async fn knock_changed(
+    tx: &broadcast::Sender<ChatEvent>,
+    ev: &OriginalSyncRoomMemberEvent,
+    room: &Room,
+    own_id: &UserId,
+) {
+    let was_knocking = ev
+        .unsigned
+        .prev_content
+        .as_ref()
+        .is_some_and(|prev| prev.membership == MembershipState::Knock);
+    let event = match ev.content.membership {
+        MembershipState::Knock => {
+            if !room.can_user_invite(own_id).await.unwrap_or(false) {
+                return;
+            }
+            let room_name = room
+                .display_name()
+                .await
+                .map(|n| n.to_string())
+                .unwrap_or_else(|_| room.room_id().to_string());
+            ChatEvent::Knocked(KnockRequest {
+                room_id: room.room_id().to_string(),
+                room_name,
+                user_id: ev.state_key.to_string(),
+                display_name: ev.content.displayname.clone(),
+                reason: ev.content.reason.clone(),
+            })
+        }
+        _ if was_knocking => ChatEvent::KnockResolved {
+            room_id: room.room_id().to_string(),
+            user_id: ev.state_key.to_string(),
+        },
+        _ => return,
+    };
+    let _ = tx.send(event);
+}
+
+/// Forward typing notifications, with the typists' names resolved.
+pub(crate) fn register_typing(client: &Client, tx: broadcast::Sender<TypingEvent>) {
+    client.add_event_handler(move |ev: SyncTypingEvent, room: Room| {
+        let tx = tx.clone();
+        async move {
+            let mut users = Vec::new();
+            for user_id in &ev.content.user_ids {
+                users.push(TypingUser {
+                    user_id: user_id.to_string(),
+                    display_name: rooms::member_name(&room, user_id).await,
+                });
+            }
+            let _ = tx.send(TypingEvent {
+                room_id: room.room_id().to_string(),
+                users,
+            });
+        }
+    });
+}
+
+/// Forward the messages our push rules ask to notify about. Registered after
+/// the initial sync so the backlog does not raise a burst of notifications.
+pub(crate) async fn register_notifications(
+    client: &Client,
+    tx: broadcast::Sender<NotificationEvent>,
+) {
+    client
+        .register_notification_handler(
+            move |notification: Notification, room: Room, client: Client| {
+                let tx = tx.clone();
+                async move {
+                    let Ok(AnySyncTimelineEvent::MessageLike(
+                        AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Original(ev)),
+                    )) = notification.event.deserialize()
+                    else {
+                        return;
+                    };
+                    if client.user_id() == Some(&*ev.sender) {
+                        return;
+                    }
+
+                    let room_name = room
+                        .display_name()
+                        .await
+                        .map(|n| n.to_string())
+                        .unwrap_or_else(|_| room.room_id().to_string());
+                    let sender_name = rooms::member_name(&room, &ev.sender)
+                        .await
+                        .unwrap_or_else(|| chat_core::localpart(ev.sender.as_str()).to_string());
+                    let _ = tx.send(NotificationEvent {
+                        room_id: room.room_id().to_string(),
+                        room_name,
+                        sender_name,
+                        body: ev.content.body().to_string(),
+                        is_direct: room.is_direct().await.unwrap_or(false),
+                        is_mention: notification.actions.iter().any(Action::is_highlight),
+                    });
+                }
+            },
+        )
+        .await;
+}
+
+/// Unread counter updates for the rooms in one sync response.
+pub(crate) fn unread_events(response: &SyncResponse) -> Vec<ChatEvent> {
+    response
+        .rooms
+        .join
+        .iter()
+        .map(|(room_id, update)| ChatEvent::UnreadChanged {
+            room_id: room_id.to_string(),
+            unread: rooms::unread_counts(&update.unread_notifications),
+        })
+        .collect()
+}
@@ -0,0 +1,120 @@
+//! Email addresses on the account, where password resets are mailed. An
+//! address is proven before it is added: the homeserver mails a link, the
+//! user clicks it, and the address is then added with their password to
+//! confirm. Asking for the mail again keeps the same session, so either
+//! mail's link works.
+
+use matrix_sdk::ruma::thirdparty::{Medium as SdkMedium, ThirdPartyIdentifier};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Medium {
+    Email,
+    /// A phone number, added in another client.
+    Phone,
+}
+
+/// An address on the account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threepid {
+    pub medium: Medium,
+    pub address: String,
+}
+
+impl Threepid {
+    /// `None` for mediums we don't know.
+    pub(crate) fn from_sdk(threepid: &ThirdPartyIdentifier) -> Option<Self> {
+        let medium = match threepid.medium {
+            SdkMedium::Email => Medium::Email,
+            SdkMedium::Msisdn => Medium::Phone,
+            _ => return None,
+        };
+        Some(Self {
+            medium,
+            address: threepid.address.clone(),
+        })
+    }
+}
+
+/// An email address waiting for its link to be clicked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailValidation {
+    pub email: String,
+    /// Ours, made up as the first mail is asked for; it ties the mails and
+    /// the add together.
+    pub client_secret: String,
+    /// The homeserver's id for the validation.
+    pub sid: String,
+    /// Counts the mails asked for; the server only sends one again for a
+    /// higher count.
+    pub send_attempt: u32,
+}
+
+/// Why an address couldn't be added, each with its own message. Other
+/// failures are plain errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreepidError {
+    /// M_THREEPID_IN_USE: another account has it.
+    InUse,
+    /// M_THREEPID_DENIED: the server doesn't take addresses like it.
+    Denied,
+    /// The link in the mail wasn't clicked yet.
+    NotValidated,
+    WrongPassword,
+}
+
+impl fmt::Display for ThreepidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InUse => "This email is already on another account",
+            Self::Denied => "Your server doesn't accept this email address",
+            Self::NotValidated => "The link in the email hasn't been clicked yet",
+            Self::WrongPassword => "That password isn't right",
+        })
+    }
+}
+
+impl std::error::Error for ThreepidError {}
+
+/// Turn the refusals the email settings tell apart into `ThreepidError`.
+pub(crate) fn threepid_error(e: matrix_sdk::Error) -> anyhow::Error {
+    use matrix_sdk::ruma::api::client::error::ErrorKind;
+
+    match e.client_api_error_kind() {
+        Some(ErrorKind::ThreepidInUse) => ThreepidError::InUse.into(),
+        Some(ErrorKind::ThreepidDenied) => ThreepidError::Denied.into(),
+        Some(ErrorKind::ThreepidAuthFailed) => ThreepidError::NotValidated.into(),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::ruma::thirdparty::ThirdPartyIdentifierInit;
+    use matrix_sdk::ruma::MilliSecondsSinceUnixEpoch;
+
+    fn threepid(address: &str, medium: SdkMedium) -> ThirdPartyIdentifier {
+        ThirdPartyIdentifierInit {
+            address: address.to_string(),
+            medium,
+            validated_at: MilliSecondsSinceUnixEpoch::now(),
+            added_at: MilliSecondsSinceUnixEpoch::now(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_unknown_mediums_are_skipped() {
+        let email = threepid("alice@example.org", SdkMedium::Email);
+        assert_eq!(
+            Threepid::from_sdk(&email),
+            Some(Threepid {
+                medium: Medium::Email,
+                address: "alice@example.org".to_string(),
+            })
+        );
+        let carrier_pigeon = threepid("loft 7", SdkMedium::from("org.example.pigeon"));
+        assert_eq!(Threepid::from_sdk(&carrier_pigeon), None);
+    }
+}
@@ -0,0 +1,1515 @@
+//! One room's timeline, put together from sync and pagination. Events can
+//! arrive more than once and out of turn: a page fetched while sync runs
+//! overlaps what sync already delivered, and a backwards page overlaps the
+//! oldest events held. Each chunk the server sends is in the room's order,
+//! so a chunk is placed by the events it shares with the buffer rather
+//! than by timestamps, which can tie or go backwards across servers.
+//! Jumping to an event far back holds what surrounds it apart from the
+//! rest, until pagination closes the gap.
+//!
+//! What is held is not all shown: a `TimelineFilter` hides state noise like
+//! membership changes, and can fold runs of them into one summary row. The
+//! diffs are in terms of the rows shown.
+
+use chat_core::{MembershipSummary, Message, MessageType, Notice};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub use chat_core::MembershipChange;
+
+/// What an event is, as far as the filter cares.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    Message,
+    Membership {
+        user_id: String,
+        change: MembershipChange,
+    },
+    DisplayName,
+    Avatar,
+    /// A reaction shown as a row of its own rather than on its message.
+    Reaction,
+    /// Someone joined or left the room's voice channel. Made up locally
+    /// from voice membership, never sent as a message.
+    Voice,
+    /// The room's topic changed. Also made up locally.
+    Topic,
+}
+
+/// Anything the buffer can hold, identified by its event id.
+pub trait TimelineItem: Clone + PartialEq {
+    fn event_id(&self) -> &str;
+
+    fn kind(&self) -> EventKind {
+        EventKind::Message
+    }
+
+    /// A row standing for `run`, two or more membership changes back to
+    /// back, that `summary` counts.
+    fn summarize(run: &[Self], summary: MembershipSummary) -> Self;
+}
+
+impl TimelineItem for Message {
+    fn event_id(&self) -> &str {
+        &self.id
+    }
+
+    fn kind(&self) -> EventKind {
+        match &self.schema {
+            MessageType::Membership(change) => EventKind::Membership {
+                user_id: self.sender.clone(),
+                change: *change,
+            },
+            MessageType::DisplayName => EventKind::DisplayName,
+            MessageType::Avatar => EventKind::Avatar,
+            MessageType::Reaction => EventKind::Reaction,
+            MessageType::SystemNotice(Notice::Voice) => EventKind::Voice,
+            MessageType::SystemNotice(Notice::Topic) => EventKind::Topic,
+            _ => EventKind::Message,
+        }
+    }
+
+    fn summarize(run: &[Self], summary: MembershipSummary) -> Self {
+        Message {
+            content: String::new(),
+            schema: MessageType::MembershipSummary(summary),
+            formatted: None,
+            reply_to: None,
+            edited: false,
+            reactions: Vec::new(),
+            attachment: None,
+            mentions: Vec::new(),
+            ..run[0].clone()
+        }
+    }
+}
+
+/// Which events the timeline shows besides messages. Hidden events are
+/// still held, so changing the filter brings them back.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimelineFilter {
+    /// Joins, leaves, invites, kicks and bans.
+    pub membership: bool,
+    /// Fold membership changes that follow each other into one row.
+    pub group_membership: bool,
+    pub display_names: bool,
+    pub avatars: bool,
+    pub reactions_as_rows: bool,
+    /// Joins and leaves of the room's voice channel.
+    pub voice: bool,
+    /// Changes to the room's topic, as a notice where they happened.
+    pub topic: bool,
+}
+
+impl Default for TimelineFilter {
+    fn default() -> Self {
+        Self {
+            membership: true,
+            group_membership: true,
+            display_names: true,
+            avatars: true,
+            reactions_as_rows: false,
+            voice: true,
+            topic: true,
+        }
+    }
+}
+
+impl TimelineFilter {
+    pub fn shows(&self, kind: &EventKind) -> bool {
+        match kind {
+            EventKind::Message => true,
+            EventKind::Membership { .. } => self.membership,
+            EventKind::DisplayName => self.display_names,
+            EventKind::Avatar => self.avatars,
+            EventKind::Reaction => self.reactions_as_rows,
+            EventKind::Voice => self.voice,
+            EventKind::Topic => self.topic,
+        }
+    }
+}
+
+/// How many people `changes` saw join, leave, be invited and be removed.
+/// Each person counts once per kind of change.
+pub fn membership_summary(changes: &[(&str, MembershipChange)]) -> MembershipSummary {
+    let people = |change| {
+        changes
+            .iter()
+            .filter(|(_, c)| *c == change)
+            .map(|(user_id, _)| *user_id)
+            .collect::<HashSet<&str>>()
+            .len()
+    };
+    MembershipSummary {
+        joined: people(MembershipChange::Joined),
+        left: people(MembershipChange::Left),
+        invited: people(MembershipChange::Invited),
+        removed: people(MembershipChange::Removed),
+    }
+}
+
+/// A row shown: one held event, or a run of membership changes folded
+/// into one.
+#[derive(Debug, Clone, PartialEq)]
+enum Row {
+    Item(usize),
+    Group(Vec<usize>),
+}
+
+/// The rows `filter` shows of `items`, from position `from` on.
+fn rows(items: &[impl TimelineItem], from: usize, filter: &TimelineFilter) -> Vec<Row> {
+    let mut rows = Vec::new();
+    // Shown membership changes not yet given a row.
+    let mut run: Vec<usize> = Vec::new();
+    let flush = |run: &mut Vec<usize>, rows: &mut Vec<Row>| {
+        if run.len() > 1 && filter.group_membership {
+            rows.push(Row::Group(std::mem::take(run)));
+        } else {
+            rows.extend(run.drain(..).map(Row::Item));
+        }
+    };
+    for (i, item) in items.iter().enumerate().skip(from) {
+        let kind = item.kind();
+        if !filter.shows(&kind) {
+            continue;
+        }
+        if matches!(kind, EventKind::Membership { .. }) {
+            run.push(i);
+        } else {
+            flush(&mut run, &mut rows);
+            rows.push(Row::Item(i));
+        }
+    }
+    flush(&mut run, &mut rows);
+    rows
+}
+
+impl Row {
+    /// Position of the row's first event.
+    fn first(&self) -> usize {
+        match self {
+            Self::Item(i) => *i,
+            Self::Group(run) => run[0],
+        }
+    }
+
+    /// Position of the row's last event.
+    fn last(&self) -> usize {
+        match self {
+            Self::Item(i) => *i,
+            Self::Group(run) => run[run.len() - 1],
+        }
+    }
+
+    /// The row's id: its event's, or its first event's.
+    fn event_id<'a>(&self, items: &'a [impl TimelineItem]) -> &'a str {
+        items[self.first()].event_id()
+    }
+
+    fn build<T: TimelineItem>(&self, items: &[T]) -> T {
+        match self {
+            Self::Item(i) => items[*i].clone(),
+            Self::Group(run) => {
+                let run: Vec<T> = run.iter().map(|&i| items[i].clone()).collect();
+                let kinds: Vec<EventKind> = run.iter().map(TimelineItem::kind).collect();
+                let changes: Vec<(&str, MembershipChange)> = kinds
+                    .iter()
+                    .filter_map(|kind| match kind {
+                        EventKind::Membership { user_id, change } => {
+                            Some((user_id.as_str(), *change))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                T::summarize(&run, membership_summary(&changes))
+            }
+        }
+    }
+}
+
+/// Where a chunk that shares no events with the buffer goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// After everything held: live events, or the latest page.
+    Newer,
+    /// Before everything held: a page paginated backwards from the oldest.
+    Older,
+}
+
+/// One change to the buffer, in terms of rows, for the UI to repeat on its
+/// own model. Indices are as of when the change is made, so changes are
+/// applied in the order given.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimelineDiff<T> {
+    InsertAt(usize, T),
+    /// A held event came again with new content, e.g. an edit.
+    Update(usize, T),
+    Remove(usize),
+    /// Every row at once, e.g. after the filter changed.
+    Reset(Vec<T>),
+}
+
+impl<T> TimelineDiff<T> {
+    /// Make this change to `rows`.
+    pub fn apply(self, rows: &mut Vec<T>) {
+        match self {
+            Self::InsertAt(index, item) => rows.insert(index, item),
+            Self::Update(index, item) => rows[index] = item,
+            Self::Remove(index) => {
+                rows.remove(index);
+            }
+            Self::Reset(items) => *rows = items,
+        }
+    }
+}
+
+/// A stretch of a room's events with no gaps, oldest first.
+#[derive(Debug)]
+struct Segment<T> {
+    items: Vec<T>,
+    /// Position of each event in `items`.
+    index: HashMap<String, usize>,
+}
+
+impl<T> Default for Segment<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<T: TimelineItem> Segment<T> {
+    fn position(&self, event_id: &str) -> Option<usize> {
+        self.index.get(event_id).copied()
+    }
+
+    /// Renumber the items from `from` on, after an insert or removal there.
+    fn reindex(&mut self, from: usize) {
+        for (row, item) in self.items.iter().enumerate().skip(from) {
+            self.index.insert(item.event_id().to_string(), row);
+        }
+    }
+
+    /// Fold in `chunk`, as `TimelineBuffer::merge` does, returning the
+    /// position of the first event that changed, if any did.
+    fn merge(&mut self, chunk: Vec<T>, direction: Direction) -> Option<usize> {
+        // New events before the first held one go right above it.
+        let mut at = match chunk.iter().find_map(|item| self.position(item.event_id())) {
+            Some(row) => row,
+            None if direction == Direction::Older => 0,
+            None => self.items.len(),
+        };
+        let mut changed: Option<usize> = None;
+        let mut touch = |row: usize| changed = Some(changed.map_or(row, |first| first.min(row)));
+        for item in chunk {
+            match self.position(item.event_id()) {
+                Some(row) => {
+                    if self.items[row] != item {
+                        self.items[row] = item;
+                        touch(row);
+                    }
+                    // A held event never moves, even if the chunk has it
+                    // out of turn; what follows goes below both.
+                    at = at.max(row + 1);
+                }
+                None => {
+                    self.items.insert(at, item);
+                    self.reindex(at);
+                    touch(at);
+                    at += 1;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Change the event `event_id` with `f`, which may give it a new id.
+    /// Returns its position.
+    fn update(&mut self, event_id: &str, f: impl FnOnce(&mut T)) -> Option<usize> {
+        let row = self.index.remove(event_id)?;
+        f(&mut self.items[row]);
+        self.index
+            .insert(self.items[row].event_id().to_string(), row);
+        Some(row)
+    }
+
+    /// Drop `event_id`, returning where it was.
+    fn remove(&mut self, event_id: &str) -> Option<usize> {
+        let row = self.index.remove(event_id)?;
+        self.items.remove(row);
+        self.reindex(row);
+        Some(row)
+    }
+}
+
+/// A room's events, oldest first, each held once. They are held in
+/// segments with gaps between them: the live one, which sync extends, and
+/// any loaded around an event jumped to, far back in the room. Paginating
+/// a segment until it reaches another joins the two. One segment is shown
+/// at a time, and the rows and diffs are of it.
+#[derive(Debug)]
+pub struct TimelineBuffer<T> {
+    /// The live segment last, the others in the order they were made.
+    segments: Vec<Segment<T>>,
+    /// The segment shown.
+    shown: usize,
+    filter: TimelineFilter,
+    /// What the filter shows of the shown segment, as last diffed.
+    rows: Vec<T>,
+    /// The events of the shown segment behind each of `rows`.
+    layout: Vec<Row>,
+}
+
+impl<T> Default for TimelineBuffer<T> {
+    fn default() -> Self {
+        Self {
+            segments: vec![Segment::default()],
+            shown: 0,
+            filter: TimelineFilter::default(),
+            rows: Vec::new(),
+            layout: Vec::new(),
+        }
+    }
+}
+
+impl<T: TimelineItem> TimelineBuffer<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_filter(filter: TimelineFilter) -> Self {
+        Self {
+            filter,
+            ..Self::default()
+        }
+    }
+
+    /// Every held event of the segment shown, shown or not.
+    pub fn items(&self) -> &[T] {
+        &self.segments[self.shown].items
+    }
+
+    /// Every held event of the live segment, shown or not.
+    pub fn live_items(&self) -> &[T] {
+        &self.segments[self.live()].items
+    }
+
+    /// The rows shown, which the diffs build.
+    pub fn rows(&self) -> &[T] {
+        &self.rows
+    }
+
+    pub fn filter(&self) -> TimelineFilter {
+        self.filter
+    }
+
+    /// Show what `filter` lets through, as one `Reset`, or nothing if it
+    /// is the filter already in use.
+    pub fn set_filter(&mut self, filter: TimelineFilter) -> Vec<TimelineDiff<T>> {
+        if filter == self.filter {
+            return Vec::new();
+        }
+        self.filter = filter;
+        self.reset()
+    }
+
+    /// All the rows of the segment shown, as one `Reset`.
+    fn reset(&mut self) -> Vec<TimelineDiff<T>> {
+        let items = &self.segments[self.shown].items;
+        self.layout = rows(items, 0, &self.filter);
+        self.rows = self.layout.iter().map(|row| row.build(items)).collect();
+        vec![TimelineDiff::Reset(self.rows.clone())]
+    }
+
+    /// Bring the rows up to date with the segment shown, whose events from
+    /// position `from` on changed, returning the changes. The rows before
+    /// stay, all but the last, which a run of membership changes may grow,
+    /// so sync adding to a long timeline only looks at its end. Held events
+    /// never move, so the rows both have keep their order, and a walk down
+    /// both finds what was inserted, updated and removed.
+    fn refresh(&mut self, from: usize) -> Vec<TimelineDiff<T>> {
+        let keep = self
+            .layout
+            .partition_point(|row| row.last() < from)
+            .saturating_sub(1);
+        let start = match keep {
+            0 => 0,
+            _ => self.layout[keep - 1].last() + 1,
+        };
+        let items = &self.segments[self.shown].items;
+        let fresh = rows(items, start, &self.filter);
+        let old_ids: HashSet<&str> = self.rows[keep..].iter().map(|row| row.event_id()).collect();
+        let new_ids: HashSet<&str> = fresh.iter().map(|row| row.event_id(items)).collect();
+        let mut diffs = Vec::new();
+        let (mut old, mut new, mut at) = (keep, 0, keep);
+        loop {
+            match (self.rows.get(old), fresh.get(new)) {
+                // An event that took a new id, e.g. once sent, keeps its row.
+                (Some(shown), Some(row))
+                    if !new_ids.contains(shown.event_id())
+                        && !old_ids.contains(row.event_id(items)) =>
+                {
+                    diffs.push(TimelineDiff::Update(at, row.build(items)));
+                    old += 1;
+                    new += 1;
+                    at += 1;
+                }
+                (Some(row), _) if !new_ids.contains(row.event_id()) => {
+                    diffs.push(TimelineDiff::Remove(at));
+                    old += 1;
+                }
+                (_, Some(row)) if !old_ids.contains(row.event_id(items)) => {
+                    diffs.push(TimelineDiff::InsertAt(at, row.build(items)));
+                    new += 1;
+                    at += 1;
+                }
+                (Some(shown), Some(row)) => {
+                    let changed = match row {
+                        Row::Item(i) => *shown != items[*i],
+                        Row::Group(_) => *shown != row.build(items),
+                    };
+                    if changed {
+                        diffs.push(TimelineDiff::Update(at, row.build(items)));
+                    }
+                    old += 1;
+                    new += 1;
+                    at += 1;
+                }
+                _ => break,
+            }
+        }
+        for diff in diffs.iter().cloned() {
+            diff.apply(&mut self.rows);
+        }
+        self.layout.truncate(keep);
+        self.layout.extend(fresh);
+        diffs
+    }
+
+    /// Events held, in every segment.
+    pub fn len(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| segment.items.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.iter().all(|segment| segment.items.is_empty())
+    }
+
+    /// Whether `event_id` is held, in any segment.
+    pub fn contains(&self, event_id: &str) -> bool {
+        self.segment_of(event_id).is_some()
+    }
+
+    /// Whether the live segment holds `event_id`.
+    pub fn live_contains(&self, event_id: &str) -> bool {
+        self.segments[self.live()].position(event_id).is_some()
+    }
+
+    /// The held event `event_id`, in any segment.
+    pub fn get(&self, event_id: &str) -> Option<&T> {
+        let segment = &self.segments[self.segment_of(event_id)?];
+        segment.position(event_id).map(|row| &segment.items[row])
+    }
+
+    /// Position of `event_id` in `items`, if the segment shown holds it.
+    pub fn position(&self, event_id: &str) -> Option<usize> {
+        self.segments[self.shown].position(event_id)
+    }
+
+    /// The row showing `event_id`, if the filter shows it in the segment
+    /// shown. Each event of a run folded into one row is on that row.
+    pub fn row(&self, event_id: &str) -> Option<usize> {
+        let position = self.position(event_id)?;
+        let row = self.layout.partition_point(|row| row.last() < position);
+        let shows = match self.layout.get(row)? {
+            Row::Item(i) => *i == position,
+            Row::Group(run) => run.binary_search(&position).is_ok(),
+        };
+        shows.then_some(row)
+    }
+
+    fn segment_of(&self, event_id: &str) -> Option<usize> {
+        self.segments
+            .iter()
+            .position(|segment| segment.position(event_id).is_some())
+    }
+
+    fn live(&self) -> usize {
+        self.segments.len() - 1
+    }
+
+    /// Whether the live segment is shown, so newer events come from sync
+    /// rather than from paginating forwards.
+    pub fn is_live(&self) -> bool {
+        self.shown == self.live()
+    }
+
+    /// A live event from sync.
+    pub fn push_live(&mut self, item: T) -> Vec<TimelineDiff<T>> {
+        self.merge(vec![item], Direction::Newer)
+    }
+
+    /// Fold in `chunk`, oldest first, as the server ordered it, to the live
+    /// segment. New events go next to the held events they neighbour in
+    /// the chunk; held events keep their places and take the chunk's
+    /// content if it changed. A chunk sharing no events goes at the end
+    /// `direction` names.
+    pub fn merge(&mut self, chunk: Vec<T>, direction: Direction) -> Vec<TimelineDiff<T>> {
+        let live = self.live();
+        self.fold(live, chunk, direction)
+    }
+
+    /// Fold in a page fetched going `direction` from the end of the segment
+    /// shown, as `merge` does for the live one.
+    pub fn paginate(&mut self, chunk: Vec<T>, direction: Direction) -> Vec<TimelineDiff<T>> {
+        self.fold(self.shown, chunk, direction)
+    }
+
+    /// Change the held event `event_id` where it is, e.g. once it is sent;
+    /// `f` may give it a new id. A segment not shown changes unseen.
+    pub fn update(&mut self, event_id: &str, f: impl FnOnce(&mut T)) -> Vec<TimelineDiff<T>> {
+        let Some(segment) = self.segment_of(event_id) else {
+            return Vec::new();
+        };
+        match self.segments[segment].update(event_id, f) {
+            Some(row) if segment == self.shown => self.refresh(row),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Change every held event `f` returns true for, in every segment,
+    /// keeping their ids.
+    pub fn update_each(&mut self, mut f: impl FnMut(&mut T) -> bool) -> Vec<TimelineDiff<T>> {
+        let mut first = None;
+        for (segment, held) in self.segments.iter_mut().enumerate() {
+            for (row, item) in held.items.iter_mut().enumerate() {
+                if f(item) && segment == self.shown && first.is_none() {
+                    first = Some(row);
+                }
+            }
+        }
+        match first {
+            Some(row) => self.refresh(row),
+            None => Vec::new(),
+        }
+    }
+
+    /// Show `chunk`, the events around one jumped to. It joins a segment it
+    /// shares events with, or else is held as a segment of its own.
+    /// Showing another segment is one `Reset`.
+    pub fn jump(&mut self, chunk: Vec<T>) -> Vec<TimelineDiff<T>> {
+        if chunk.is_empty() {
+            return Vec::new();
+        }
+        let target = match chunk
+            .iter()
+            .find_map(|item| self.segment_of(item.event_id()))
+        {
+            Some(segment) => segment,
+            None => {
+                let at = self.live();
+                self.segments.insert(at, Segment::default());
+                if self.shown >= at {
+                    self.shown += 1;
+                }
+                at
+            }
+        };
+        let (target, changed) = self.join(target, chunk, Direction::Newer);
+        if target == self.shown {
+            return match changed {
+                Some(from) => self.refresh(from),
+                None => Vec::new(),
+            };
+        }
+        self.shown = target;
+        self.reset()
+    }
+
+    /// Fold the segment shown into the live one and show that, as when
+    /// paginating forwards from an event jumped to reaches the latest
+    /// events, but the live segment shares none of them: they go before
+    /// everything it holds. The rows shown stay, and the live segment's
+    /// are added below.
+    pub fn join_live(&mut self) -> Vec<TimelineDiff<T>> {
+        if self.is_live() {
+            return Vec::new();
+        }
+        let chunk = self.segments[self.shown].items.clone();
+        self.merge(chunk, Direction::Older)
+    }
+
+    /// Fold `chunk` into segment `target` and return the diffs to the rows
+    /// shown.
+    fn fold(&mut self, target: usize, chunk: Vec<T>, direction: Direction) -> Vec<TimelineDiff<T>> {
+        match self.join(target, chunk, direction) {
+            (target, Some(from)) if target == self.shown => self.refresh(from),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Fold `chunk` into segment `target`. Any other segment sharing events
+    /// with the chunk covers the same stretch of the room, so it is folded
+    /// in too, and the one segment left is live if either was. Returns
+    /// where that segment is now, and the position of its first event that
+    /// changed, if any did.
+    fn join(
+        &mut self,
+        mut target: usize,
+        chunk: Vec<T>,
+        direction: Direction,
+    ) -> (usize, Option<usize>) {
+        let mut seen = HashSet::new();
+        let chunk: Vec<T> = chunk
+            .into_iter()
+            .filter(|item| seen.insert(item.event_id().to_string()))
+            .collect();
+        let others: Vec<usize> = (0..self.segments.len())
+            .filter(|&other| {
+                other != target
+                    && chunk
+                        .iter()
+                        .any(|item| self.segments[other].position(item.event_id()).is_some())
+            })
+            .collect();
+
+        let mut changed = self.segments[target].merge(chunk, direction);
+        let live_segment = self.live();
+        let mut live = target == live_segment;
+        // Highest first, so the rest keep their places.
+        for other in others.into_iter().rev() {
+            live |= other == live_segment;
+            let joined = self.segments.remove(other);
+            if target > other {
+                target -= 1;
+            }
+            if self.shown == other {
+                self.shown = target;
+            } else if self.shown > other {
+                self.shown -= 1;
+            }
+            // They share events now, which places the rest. The rows of
+            // either may have moved.
+            self.segments[target].merge(joined.items, Direction::Newer);
+            changed = Some(0);
+        }
+        if live && target != self.live() {
+            let segment = self.segments.remove(target);
+            self.segments.push(segment);
+            if self.shown == target {
+                self.shown = self.live();
+            } else if self.shown > target {
+                self.shown -= 1;
+            }
+            target = self.live();
+        }
+        (target, changed)
+    }
+
+    /// Show the live segment again, dropping the others; jumping back
+    /// fetches their events again.
+    pub fn show_live(&mut self) -> Vec<TimelineDiff<T>> {
+        if self.segments.len() == 1 {
+            return Vec::new();
+        }
+        let was_live = self.is_live();
+        let live = self.segments.pop().unwrap_or_default();
+        self.segments = vec![live];
+        self.shown = 0;
+        if was_live {
+            return Vec::new();
+        }
+        self.reset()
+    }
+
+    /// Drop `event_id`, e.g. once redacted. A later chunk holding it again
+    /// brings it back. A segment left empty goes, unless it is the live one.
+    pub fn remove(&mut self, event_id: &str) -> Vec<TimelineDiff<T>> {
+        let Some(segment) = self.segment_of(event_id) else {
+            return Vec::new();
+        };
+        let Some(row) = self.segments[segment].remove(event_id) else {
+            return Vec::new();
+        };
+        if self.segments[segment].items.is_empty() && segment != self.live() {
+            self.segments.remove(segment);
+            if self.shown == segment {
+                self.shown = self.live();
+                return self.reset();
+            }
+            if self.shown > segment {
+                self.shown -= 1;
+            }
+            return Vec::new();
+        }
+        if segment != self.shown {
+            return Vec::new();
+        }
+        self.refresh(row)
+    }
+
+    /// Drop everything, e.g. after a gap too wide to paginate across.
+    pub fn clear(&mut self) -> Vec<TimelineDiff<T>> {
+        let diffs = (0..self.rows.len())
+            .rev()
+            .map(TimelineDiff::Remove)
+            .collect();
+        self.segments = vec![Segment::default()];
+        self.shown = 0;
+        self.rows.clear();
+        self.layout.clear();
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Event {
+        id: String,
+        /// Position in the room, which the buffer never sees.
+        order: usize,
+        /// Bumped when the event is delivered again with new content.
+        revision: u32,
+        kind: EventKind,
+        /// Set on rows standing for a run of membership changes.
+        summary: Option<MembershipSummary>,
+    }
+
+    impl TimelineItem for Event {
+        fn event_id(&self) -> &str {
+            &self.id
+        }
+
+        fn kind(&self) -> EventKind {
+            self.kind.clone()
+        }
+
+        fn summarize(run: &[Self], summary: MembershipSummary) -> Self {
+            Event {
+                summary: Some(summary),
+                ..run[0].clone()
+            }
+        }
+    }
+
+    fn event(order: usize) -> Event {
+        Event {
+            id: format!("$e{}", order),
+            order,
+            revision: 0,
+            kind: EventKind::Message,
+            summary: None,
+        }
+    }
+
+    fn membership(order: usize, user_id: &str, change: MembershipChange) -> Event {
+        Event {
+            kind: EventKind::Membership {
+                user_id: user_id.to_string(),
+                change,
+            },
+            ..event(order)
+        }
+    }
+
+    /// The rows as order, or the summary's counts for grouped ones, e.g.
+    /// "3 joined, 1 left".
+    fn shown(rows: &[Event]) -> Vec<String> {
+        let counts = |s: MembershipSummary| {
+            [
+                (s.joined, "joined"),
+                (s.left, "left"),
+                (s.invited, "invited"),
+                (s.removed, "removed"),
+            ]
+            .into_iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, kind)| format!("{} {}", n, kind))
+            .collect::<Vec<_>>()
+            .join(", ")
+        };
+        rows.iter()
+            .map(|e| e.summary.map_or_else(|| e.order.to_string(), counts))
+            .collect()
+    }
+
+    fn chunk(range: std::ops::Range<usize>) -> Vec<Event> {
+        range.map(event).collect()
+    }
+
+    fn orders(buffer: &TimelineBuffer<Event>) -> Vec<usize> {
+        buffer.items().iter().map(|e| e.order).collect()
+    }
+
+    /// A small deterministic generator, so failures reproduce by seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 33) % n as u64) as usize
+        }
+
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, self.below(i + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_live_and_latest_page_overlap() {
+        let mut buffer = TimelineBuffer::new();
+        buffer.push_live(event(8));
+        buffer.push_live(event(9));
+        // The latest page was fetched while 9 was on its way.
+        let diffs = buffer.merge(chunk(5..9), Direction::Newer);
+        assert_eq!(orders(&buffer), vec![5, 6, 7, 8, 9]);
+        assert_eq!(
+            diffs,
+            vec![
+                TimelineDiff::InsertAt(0, event(5)),
+                TimelineDiff::InsertAt(1, event(6)),
+                TimelineDiff::InsertAt(2, event(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pages_with_no_overlap() {
+        let mut buffer = TimelineBuffer::new();
+        buffer.merge(chunk(5..8), Direction::Newer);
+        buffer.merge(chunk(2..5), Direction::Older);
+        buffer.merge(chunk(8..10), Direction::Newer);
+        assert_eq!(orders(&buffer), (2..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ties_and_backwards_timestamps_are_ignored() {
+        // The server's order is what counts, whatever the timestamps say.
+        let message = |id: &str, timestamp| Message {
+            id: id.to_string(),
+            sender: "@a:x".to_string(),
+            content: "hi".to_string(),
+            timestamp,
+            ..Message::default()
+        };
+        let oldest = message("$a", 200);
+        let older = message("$b", 100);
+        let newer = message("$c", 100);
+
+        let mut buffer = TimelineBuffer::new();
+        buffer.merge(vec![older.clone(), newer.clone()], Direction::Newer);
+        buffer.merge(vec![oldest, older], Direction::Older);
+        let ids: Vec<&str> = buffer.items().iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["$a", "$b", "$c"]);
+    }
+
+    #[test]
+    fn test_updates_and_removals() {
+        let mut buffer = TimelineBuffer::new();
+        buffer.merge(chunk(0..3), Direction::Newer);
+        // The same content again changes nothing.
+        assert!(buffer.merge(chunk(0..3), Direction::Older).is_empty());
+
+        let mut edited = event(1);
+        edited.revision = 1;
+        assert_eq!(
+            buffer.push_live(edited.clone()),
+            vec![TimelineDiff::Update(1, edited)]
+        );
+
+        assert_eq!(buffer.remove("$e1"), vec![TimelineDiff::Remove(1)]);
+        assert!(buffer.remove("$e1").is_empty());
+        assert_eq!(buffer.position("$e2"), Some(1));
+        assert_eq!(orders(&buffer), vec![0, 2]);
+
+        let diffs = buffer.clear();
+        assert_eq!(
+            diffs,
+            vec![TimelineDiff::Remove(1), TimelineDiff::Remove(0)]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    /// Throw overlapping, repeated and reordered chunks of a room at the
+    /// buffer, as sync and pagination racing each other would, and check
+    /// it always holds each event once, in the room's order, and that its
+    /// diffs rebuild the same rows.
+    #[test]
+    fn test_shuffled_and_duplicated_chunks() {
+        const ROOM_LEN: usize = 60;
+
+        for seed in 0..300 {
+            let mut rng = Rng(seed);
+            let mut chunks: Vec<Vec<Event>> = (0..rng.below(12) + 4)
+                .map(|_| {
+                    let start = rng.below(ROOM_LEN);
+                    let end = (start + rng.below(12) + 1).min(ROOM_LEN);
+                    let mut chunk = chunk(start..end);
+                    // Deliveries of the same event, side by side.
+                    if rng.below(4) == 0 {
+                        let at = rng.below(chunk.len());
+                        chunk.insert(at, chunk[at].clone());
+                    }
+                    // Delivered again with new content.
+                    if rng.below(3) == 0 {
+                        let at = rng.below(chunk.len());
+                        chunk[at].revision = rng.below(3) as u32 + 1;
+                    }
+                    chunk
+                })
+                .collect();
+            // Every chunk twice, in any order.
+            chunks.extend(chunks.clone());
+            rng.shuffle(&mut chunks);
+
+            let mut buffer = TimelineBuffer::new();
+            let mut rows = Vec::new();
+            let mut held: Option<(usize, usize)> = None;
+            let mut pending = std::collections::VecDeque::from(chunks);
+            let mut deferred = 0;
+            while let Some(chunk) = pending.pop_front() {
+                let first = chunk[0].order;
+                let last = chunk[chunk.len() - 1].order;
+                // Like the real server, only hand over chunks that touch
+                // what is held: the latest page and live events continue
+                // it, backwards pages end where it starts.
+                let direction = match held {
+                    None => Direction::Newer,
+                    Some((lo, _)) if last + 1 == lo => Direction::Older,
+                    Some((_, hi)) if first == hi + 1 => Direction::Newer,
+                    Some((lo, hi)) if first <= hi && last >= lo => {
+                        if rng.below(2) == 0 {
+                            Direction::Older
+                        } else {
+                            Direction::Newer
+                        }
+                    }
+                    Some(_) => {
+                        // Not reachable yet; try again later.
+                        pending.push_back(chunk);
+                        deferred += 1;
+                        if deferred > pending.len() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                deferred = 0;
+                held = Some(match held {
+                    None => (first, last),
+                    Some((lo, hi)) => (lo.min(first), hi.max(last)),
+                });
+
+                for diff in buffer.merge(chunk, direction) {
+                    diff.apply(&mut rows);
+                }
+                let (lo, hi) = held.unwrap();
+                assert_eq!(
+                    orders(&buffer),
+                    (lo..=hi).collect::<Vec<_>>(),
+                    "seed {}",
+                    seed
+                );
+                assert_eq!(rows, buffer.items(), "seed {}", seed);
+                for (row, item) in buffer.items().iter().enumerate() {
+                    assert_eq!(buffer.position(&item.id), Some(row), "seed {}", seed);
+                }
+            }
+        }
+    }
+
+    /// Every event is held once, and each segment is a run of the room
+    /// with no gaps.
+    fn check_segments(buffer: &TimelineBuffer<Event>, seed: u64) {
+        let mut held = HashSet::new();
+        for segment in &buffer.segments {
+            let orders: Vec<usize> = segment.items.iter().map(|e| e.order).collect();
+            if let (Some(&first), Some(&last)) = (orders.first(), orders.last()) {
+                assert_eq!(orders, (first..=last).collect::<Vec<_>>(), "seed {}", seed);
+            }
+            for (row, item) in segment.items.iter().enumerate() {
+                assert!(held.insert(item.order), "seed {}", seed);
+                assert_eq!(segment.position(&item.id), Some(row), "seed {}", seed);
+            }
+        }
+        assert_eq!(buffer.len(), held.len(), "seed {}", seed);
+    }
+
+    #[test]
+    fn test_jump_holds_a_detached_segment_until_it_joins() {
+        let mut buffer = TimelineBuffer::new();
+        let mut rows = Vec::new();
+        apply(buffer.merge(chunk(50..60), Direction::Newer), &mut rows);
+
+        let diffs = buffer.jump(chunk(10..15));
+        assert!(matches!(diffs.as_slice(), [TimelineDiff::Reset(_)]));
+        apply(diffs, &mut rows);
+        assert_eq!(orders(&buffer), (10..15).collect::<Vec<_>>());
+        assert!(!buffer.is_live());
+        assert!(buffer.contains("$e55"));
+        assert_eq!(buffer.position("$e55"), None);
+
+        // Sync goes on out of sight.
+        assert!(buffer.push_live(event(60)).is_empty());
+        apply(buffer.paginate(chunk(5..10), Direction::Older), &mut rows);
+        assert_eq!(orders(&buffer), (5..15).collect::<Vec<_>>());
+        assert_eq!(rows, buffer.rows());
+
+        // Paginating forwards into the live segment joins the two.
+        apply(buffer.paginate(chunk(15..30), Direction::Newer), &mut rows);
+        assert!(!buffer.is_live());
+        apply(buffer.paginate(chunk(30..52), Direction::Newer), &mut rows);
+        assert!(buffer.is_live());
+        assert_eq!(buffer.segments.len(), 1);
+        assert_eq!(orders(&buffer), (5..61).collect::<Vec<_>>());
+        assert_eq!(rows, buffer.rows());
+        check_segments(&buffer, 0);
+    }
+
+    #[test]
+    fn test_jump_to_held_events() {
+        let mut buffer = TimelineBuffer::new();
+        let mut rows = Vec::new();
+        apply(buffer.merge(chunk(0..10), Direction::Newer), &mut rows);
+
+        // Context already held changes nothing.
+        assert!(buffer.jump(chunk(3..6)).is_empty());
+        // Context reaching past the live segment extends it.
+        assert_eq!(
+            buffer.jump(chunk(8..11)),
+            vec![TimelineDiff::InsertAt(10, event(10))]
+        );
+        assert!(buffer.is_live());
+
+        apply(buffer.jump(chunk(40..45)), &mut rows);
+        assert_eq!(buffer.segments.len(), 2);
+        // Back to an event of the live segment, which is shown again.
+        let diffs = buffer.jump(chunk(4..7));
+        assert!(matches!(diffs.as_slice(), [TimelineDiff::Reset(_)]));
+        apply(diffs, &mut rows);
+        assert!(buffer.is_live());
+        assert_eq!(rows, chunk(0..11));
+        assert_eq!(buffer.segments.len(), 2);
+
+        assert!(buffer.show_live().is_empty());
+        assert_eq!(buffer.segments.len(), 1);
+        assert!(!buffer.contains("$e40"));
+    }
+
+    #[test]
+    fn test_chunk_bridging_segments_joins_them() {
+        let mut buffer = TimelineBuffer::new();
+        let mut rows = Vec::new();
+        apply(buffer.merge(chunk(40..50), Direction::Newer), &mut rows);
+        apply(buffer.jump(chunk(10..15)), &mut rows);
+        apply(buffer.jump(chunk(25..30)), &mut rows);
+        assert_eq!(buffer.segments.len(), 3);
+        assert_eq!(orders(&buffer), (25..30).collect::<Vec<_>>());
+
+        // A page overlapping both ends of a gap closes it.
+        apply(buffer.paginate(chunk(12..27), Direction::Older), &mut rows);
+        assert_eq!(buffer.segments.len(), 2);
+        assert_eq!(orders(&buffer), (10..30).collect::<Vec<_>>());
+        assert_eq!(rows, buffer.rows());
+        check_segments(&buffer, 0);
+
+        // Removing all of a segment shown drops it and shows the live one.
+        let mut diffs = Vec::new();
+        for order in 10..30 {
+            diffs = buffer.remove(&format!("$e{}", order));
+        }
+        assert!(matches!(diffs.as_slice(), [TimelineDiff::Reset(_)]));
+        assert_eq!(buffer.segments.len(), 1);
+        assert!(buffer.is_live());
+        assert_eq!(orders(&buffer), (40..50).collect::<Vec<_>>());
+
+        apply(buffer.jump(chunk(0..5)), &mut rows);
+        apply(buffer.show_live(), &mut rows);
+        assert_eq!(rows, chunk(40..50));
+    }
+
+    /// Jump about a room and paginate from wherever is shown, as a user
+    /// following search results and replies would, and check that events
+    /// are never held twice, segments have no gaps, and the diffs rebuild
+    /// the rows shown.
+    #[test]
+    fn test_random_jumps_and_pagination() {
+        const ROOM_LEN: usize = 80;
+
+        for seed in 0..300 {
+            let mut rng = Rng(seed);
+            let mut buffer = TimelineBuffer::new();
+            let mut rows = Vec::new();
+            apply(
+                buffer.merge(chunk(ROOM_LEN - 10..ROOM_LEN), Direction::Newer),
+                &mut rows,
+            );
+            for _ in 0..30 {
+                let (first, last) = match (buffer.items().first(), buffer.items().last()) {
+                    (Some(first), Some(last)) => (first.order, last.order),
+                    _ => (0, 0),
+                };
+                let size = rng.below(12) + 1;
+                let diffs = match rng.below(3) {
+                    0 => {
+                        let at = rng.below(ROOM_LEN);
+                        let start = at.saturating_sub(size / 2);
+                        buffer.jump(chunk(start..(at + size / 2 + 1).min(ROOM_LEN)))
+                    }
+                    // Pages overlap the end they start from, as the server's
+                    // may when events arrive meanwhile.
+                    1 => {
+                        let end = (first + rng.below(2)).min(ROOM_LEN);
+                        buffer.paginate(chunk(end.saturating_sub(size)..end), Direction::Older)
+                    }
+                    _ => {
+                        let start = (last + 1).saturating_sub(rng.below(2));
+                        let end = (start + size).min(ROOM_LEN);
+                        buffer.paginate(chunk(start..end), Direction::Newer)
+                    }
+                };
+                apply(diffs, &mut rows);
+                assert_eq!(rows, buffer.items(), "seed {}", seed);
+                check_segments(&buffer, seed);
+                // The newest events are only ever in the live segment.
+                let live = &buffer.segments[buffer.live()];
+                assert_eq!(
+                    live.items.last().map(|e| e.order),
+                    Some(ROOM_LEN - 1),
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+    }
+
+    /// Change a timeline full of membership runs every way the buffer
+    /// allows, and check the diffs build the rows a fresh look at all its
+    /// events would show, though each change only looks at the end of them.
+    #[test]
+    fn test_diffs_match_a_full_rebuild() {
+        use MembershipChange::*;
+
+        for seed in 0..300 {
+            let mut rng = Rng(seed);
+            let filter = TimelineFilter {
+                group_membership: rng.below(4) != 0,
+                display_names: rng.below(2) == 0,
+                ..TimelineFilter::default()
+            };
+            let room: Vec<Event> = (0..40)
+                .map(|order| match rng.below(3) {
+                    0 => event(order),
+                    1 => {
+                        let change = if rng.below(2) == 0 { Joined } else { Left };
+                        membership(order, &format!("@u{}:x", rng.below(4)), change)
+                    }
+                    _ => Event {
+                        kind: EventKind::DisplayName,
+                        ..event(order)
+                    },
+                })
+                .collect();
+
+            let mut buffer = TimelineBuffer::with_filter(filter);
+            let mut rows = Vec::new();
+            for _ in 0..60 {
+                let picked = room[rng.below(room.len())].clone();
+                let diffs = match rng.below(5) {
+                    0 => buffer.push_live(picked),
+                    1 => {
+                        let start = rng.below(room.len());
+                        let end = (start + rng.below(8) + 1).min(room.len());
+                        let direction = if rng.below(2) == 0 {
+                            Direction::Older
+                        } else {
+                            Direction::Newer
+                        };
+                        buffer.merge(room[start..end].to_vec(), direction)
+                    }
+                    2 => buffer.remove(&picked.id),
+                    3 => buffer.update(&picked.id, |e| e.revision += 1),
+                    _ => buffer.update_each(|e| {
+                        let bump = e.order % 7 == 3;
+                        e.revision += u32::from(bump);
+                        bump
+                    }),
+                };
+                apply(diffs, &mut rows);
+
+                let items = buffer.items();
+                let layout = super::rows(items, 0, &filter);
+                let fresh: Vec<Event> = layout.iter().map(|row| row.build(items)).collect();
+                assert_eq!(rows, fresh, "seed {}", seed);
+                assert_eq!(buffer.rows(), fresh, "seed {}", seed);
+                for (at, row) in layout.iter().enumerate() {
+                    let run = match row {
+                        Row::Item(i) => vec![*i],
+                        Row::Group(run) => run.clone(),
+                    };
+                    for i in run {
+                        assert_eq!(buffer.row(&items[i].id), Some(at), "seed {}", seed);
+                    }
+                }
+                if !filter.display_names {
+                    for item in items.iter().filter(|e| e.kind == EventKind::DisplayName) {
+                        assert_eq!(buffer.row(&item.id), None, "seed {}", seed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_membership_summary() {
+        use MembershipChange::*;
+
+        let summary = |joined, left, invited, removed| MembershipSummary {
+            joined,
+            left,
+            invited,
+            removed,
+        };
+        let joins: Vec<(String, MembershipChange)> =
+            (0..5).map(|i| (format!("@u{}:x", i), Joined)).collect();
+        let joins: Vec<(&str, MembershipChange)> =
+            joins.iter().map(|(u, c)| (u.as_str(), *c)).collect();
+        assert_eq!(membership_summary(&joins), summary(5, 0, 0, 0));
+        // Rejoining counts once.
+        assert_eq!(
+            membership_summary(&[("@a:x", Joined), ("@a:x", Left), ("@a:x", Joined)]),
+            summary(1, 1, 0, 0)
+        );
+        assert_eq!(
+            membership_summary(&[
+                ("@a:x", Joined),
+                ("@b:x", Invited),
+                ("@c:x", Invited),
+                ("@d:x", Removed),
+            ]),
+            summary(1, 0, 2, 1)
+        );
+    }
+
+    #[test]
+    fn test_filter_hides_and_groups() {
+        use MembershipChange::*;
+
+        let mut name_change = event(4);
+        name_change.kind = EventKind::DisplayName;
+        let events = vec![
+            event(0),
+            membership(1, "@a:x", Joined),
+            membership(2, "@b:x", Joined),
+            membership(3, "@c:x", Joined),
+            name_change,
+            membership(5, "@d:x", Left),
+            event(6),
+        ];
+        let mut buffer = TimelineBuffer::new();
+        let mut rows = Vec::new();
+        for diff in buffer.merge(events, Direction::Newer) {
+            diff.apply(&mut rows);
+        }
+        assert_eq!(shown(&rows), ["0", "3 joined", "4", "5", "6"]);
+        assert_eq!(rows, buffer.rows());
+        assert_eq!(buffer.items().len(), 7);
+
+        // With name changes hidden, the leave joins the run.
+        let filter = TimelineFilter {
+            display_names: false,
+            ..TimelineFilter::default()
+        };
+        let diffs = buffer.set_filter(filter);
+        assert!(matches!(diffs.as_slice(), [TimelineDiff::Reset(_)]));
+        for diff in diffs {
+            diff.apply(&mut rows);
+        }
+        assert_eq!(shown(&rows), ["0", "3 joined, 1 left", "6"]);
+        assert!(buffer.set_filter(filter).is_empty());
+
+        let ungrouped = TimelineFilter {
+            group_membership: false,
+            ..filter
+        };
+        for diff in buffer.set_filter(ungrouped) {
+            diff.apply(&mut rows);
+        }
+        assert_eq!(shown(&rows), ["0", "1", "2", "3", "5", "6"]);
+
+        let quiet = TimelineFilter {
+            membership: false,
+            ..filter
+        };
+        for diff in buffer.set_filter(quiet) {
+            diff.apply(&mut rows);
+        }
+        assert_eq!(shown(&rows), ["0", "6"]);
+        // Hidden events are still held and placed.
+        assert!(buffer
+            .merge(vec![membership(7, "@e:x", Joined)], Direction::Newer)
+            .is_empty());
+        assert_eq!(buffer.position("$e7"), Some(7));
+    }
+
+    #[test]
+    fn test_voice_rows_filter() {
+        let mut voice = event(1);
+        voice.kind = EventKind::Voice;
+        let mut buffer = TimelineBuffer::new();
+        let mut rows = Vec::new();
+        apply(
+            buffer.merge(vec![event(0), voice, event(2)], Direction::Newer),
+            &mut rows,
+        );
+        assert_eq!(shown(&rows), ["0", "1", "2"]);
+
+        let no_voice = TimelineFilter {
+            voice: false,
+            ..TimelineFilter::default()
+        };
+        apply(buffer.set_filter(no_voice), &mut rows);
+        assert_eq!(shown(&rows), ["0", "2"]);
+
+        let mut notice = Message {
+            id: "~voice".to_string(),
+            sender: "@a:x".to_string(),
+            content: "a joined voice".to_string(),
+            schema: MessageType::SystemNotice(Notice::Voice),
+            ..Message::default()
+        };
+        assert_eq!(notice.kind(), EventKind::Voice);
+        notice.schema = MessageType::Text;
+        assert_eq!(notice.kind(), EventKind::Message);
+    }
+
+    #[test]
+    fn test_messages_are_classified() {
+        let kind = |schema| {
+            Message {
+                id: "$1".to_string(),
+                sender: "@a:x".to_string(),
+                schema,
+                ..Message::default()
+            }
+            .kind()
+        };
+        assert_eq!(
+            kind(MessageType::Membership(MembershipChange::Left)),
+            EventKind::Membership {
+                user_id: "@a:x".to_string(),
+                change: MembershipChange::Left,
+            }
+        );
+        assert_eq!(kind(MessageType::DisplayName), EventKind::DisplayName);
+        assert_eq!(kind(MessageType::Avatar), EventKind::Avatar);
+        assert_eq!(kind(MessageType::Reaction), EventKind::Reaction);
+        assert_eq!(
+            kind(MessageType::SystemNotice(Notice::Topic)),
+            EventKind::Topic
+        );
+        // Nobody filters out a warning about someone's keys.
+        assert_eq!(
+            kind(MessageType::SystemNotice(Notice::Security)),
+            EventKind::Message
+        );
+        assert_eq!(kind(MessageType::MissedCall), EventKind::Message);
+
+        let quiet = TimelineFilter {
+            membership: false,
+            display_names: false,
+            avatars: false,
+            voice: false,
+            topic: false,
+            ..TimelineFilter::default()
+        };
+        let mut buffer = TimelineBuffer::with_filter(quiet);
+        let messages = [
+            MessageType::Text,
+            MessageType::Membership(MembershipChange::Joined),
+            MessageType::DisplayName,
+            MessageType::Avatar,
+            MessageType::Reaction,
+            MessageType::SystemNotice(Notice::Topic),
+            MessageType::SystemNotice(Notice::Voice),
+            MessageType::Emote,
+        ];
+        for (n, schema) in messages.into_iter().enumerate() {
+            buffer.push_live(Message {
+                id: format!("${}", n),
+                schema,
+                ..Message::default()
+            });
+        }
+        let ids: Vec<&str> = buffer.rows().iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["$0", "$7"]);
+        buffer.set_filter(TimelineFilter::default());
+        assert_eq!(buffer.rows().len(), 7);
+    }
+
+    fn apply(diffs: Vec<TimelineDiff<Event>>, rows: &mut Vec<Event>) {
+        for diff in diffs {
+            diff.apply(rows);
+        }
+    }
+
+    #[test]
+    fn test_groups_grow_and_split() {
+        use MembershipChange::*;
+
+        let mut buffer = TimelineBuffer::new();
+        let mut rows = Vec::new();
+        apply(buffer.push_live(event(0)), &mut rows);
+        apply(buffer.push_live(membership(1, "@a:x", Joined)), &mut rows);
+        assert_eq!(shown(&rows), ["0", "1"]);
+
+        // A second join turns the row into a group, in place.
+        let diffs = buffer.push_live(membership(2, "@b:x", Joined));
+        assert!(matches!(diffs.as_slice(), [TimelineDiff::Update(1, _)]));
+        apply(diffs, &mut rows);
+        assert_eq!(shown(&rows), ["0", "2 joined"]);
+
+        // A join that turns up in between grows it.
+        apply(buffer.push_live(event(4)), &mut rows);
+        let chunk = vec![
+            membership(2, "@b:x", Joined),
+            membership(3, "@c:x", Joined),
+            event(4),
+        ];
+        apply(buffer.merge(chunk, Direction::Newer), &mut rows);
+        assert_eq!(shown(&rows), ["0", "3 joined", "4"]);
+
+        // A message in between splits it.
+        let mut late = event(5);
+        late.id = "$late".to_string();
+        let chunk = vec![
+            membership(1, "@a:x", Joined),
+            late,
+            membership(2, "@b:x", Joined),
+        ];
+        apply(buffer.merge(chunk, Direction::Newer), &mut rows);
+        assert_eq!(shown(&rows), ["0", "1", "5", "2 joined", "4"]);
+        assert_eq!(rows, buffer.rows());
+
+        apply(buffer.remove("$e2"), &mut rows);
+        assert_eq!(shown(&rows), ["0", "1", "5", "3", "4"]);
+        // Without the message, the two joins left are grouped again.
+        apply(buffer.remove("$late"), &mut rows);
+        assert_eq!(shown(&rows), ["0", "2 joined", "4"]);
+        assert_eq!(rows, buffer.rows());
+    }
+}
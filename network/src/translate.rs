@@ -0,0 +1,181 @@
+//! Translating messages through a provider behind [`Translator`]; the one
+//! there is asks a LibreTranslate server. Nothing is sent to it unless the
+//! user asks for a message to be translated, or turned translation on for
+//! its room: callers are trusted with that, so this module only talks to
+//! the server.
+
+use crate::config::TranslationConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// How long the provider gets to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A message in another language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translation {
+    pub text: String,
+    /// The language it was translated from, e.g. "fr": as asked for, or as
+    /// the provider detected it. `None` if it didn't say.
+    pub source_lang: Option<String>,
+}
+
+#[async_trait]
+pub trait Translator: Send + Sync {
+    /// `text` in `target_lang`, from `source_lang` if given or else from
+    /// whatever language the provider detects.
+    async fn translate(
+        &self,
+        text: &str,
+        source_lang: Option<&str>,
+        target_lang: &str,
+    ) -> Result<Translation>;
+}
+
+/// A LibreTranslate server, with the API key it may want.
+pub struct LibreTranslate {
+    http: reqwest::Client,
+    endpoint: Url,
+    api_key: Option<String>,
+}
+
+impl LibreTranslate {
+    /// The server `config` points at; an error when none is set.
+    pub fn new(config: &TranslationConfig) -> Result<Self> {
+        let url = config.url.trim();
+        if url.is_empty() {
+            bail!("No translation server is set");
+        }
+        let mut endpoint =
+            Url::parse(url).with_context(|| format!("{} isn't a server address", url))?;
+        endpoint
+            .path_segments_mut()
+            .map_err(|_| anyhow!("{} isn't a server address", url))?
+            .pop_if_empty()
+            .push("translate");
+        let api_key = Some(config.api_key.trim().to_string()).filter(|key| !key.is_empty());
+        Ok(Self {
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            endpoint,
+            api_key,
+        })
+    }
+}
+
+#[async_trait]
+impl Translator for LibreTranslate {
+    async fn translate(
+        &self,
+        text: &str,
+        source_lang: Option<&str>,
+        target_lang: &str,
+    ) -> Result<Translation> {
+        let body = request_body(text, source_lang, target_lang, self.api_key.as_deref());
+        let response = self
+            .http
+            .post(self.endpoint.clone())
+            .json(&body)
+            .send()
+            .await?;
+        let status = response.status();
+        let answer: Value = response.json().await.unwrap_or_default();
+        read_answer(status.as_u16(), &answer, source_lang)
+    }
+}
+
+/// What LibreTranslate's `/translate` takes.
+fn request_body(
+    text: &str,
+    source_lang: Option<&str>,
+    target_lang: &str,
+    api_key: Option<&str>,
+) -> Value {
+    let mut body = json!({
+        "q": text,
+        "source": source_lang.unwrap_or("auto"),
+        "target": target_lang,
+        "format": "text",
+    });
+    if let Some(api_key) = api_key {
+        body["api_key"] = json!(api_key);
+    }
+    body
+}
+
+/// The translation in LibreTranslate's answer, or the error it gave.
+fn read_answer(status: u16, answer: &Value, source_lang: Option<&str>) -> Result<Translation> {
+    if !(200..300).contains(&status) {
+        match answer.get("error").and_then(Value::as_str) {
+            Some(error) => bail!("{}", error),
+            None => bail!("The translation server answered {}", status),
+        }
+    }
+    let text = answer
+        .get("translatedText")
+        .and_then(Value::as_str)
+        .context("The translation server sent no translation")?;
+    let detected = answer
+        .pointer("/detectedLanguage/language")
+        .and_then(Value::as_str);
+    Ok(Translation {
+        text: text.to_string(),
+        source_lang: source_lang.or(detected).map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_body() {
+        assert_eq!(
+            request_body("bonjour", None, "en", None),
+            json!({"q": "bonjour", "source": "auto", "target": "en", "format": "text"})
+        );
+        let body = request_body("hallo", Some("de"), "fr", Some("key"));
+        assert_eq!(body["source"], "de");
+        assert_eq!(body["api_key"], "key");
+    }
+
+    #[test]
+    fn test_answers_are_read() {
+        let detected = json!({
+            "translatedText": "hello",
+            "detectedLanguage": {"confidence": 90.0, "language": "fr"},
+        });
+        assert_eq!(
+            read_answer(200, &detected, None).unwrap(),
+            Translation {
+                text: "hello".to_string(),
+                source_lang: Some("fr".to_string()),
+            }
+        );
+        let given = json!({"translatedText": "hello"});
+        assert_eq!(
+            read_answer(200, &given, Some("de"))
+                .unwrap()
+                .source_lang
+                .as_deref(),
+            Some("de")
+        );
+
+        let refused = json!({"error": "Invalid API key"});
+        assert_eq!(
+            read_answer(403, &refused, None).unwrap_err().to_string(),
+            "Invalid API key"
+        );
+        assert_eq!(
+            read_answer(502, &Value::Null, None)
+                .unwrap_err()
+                .to_string(),
+            "The translation server answered 502"
+        );
+        assert!(read_answer(200, &json!({}), None).is_err());
+    }
+}
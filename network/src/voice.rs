@@ -1,16 +1,854 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 
+use crate::config::{AudioConfig, CueOutput};
+use crate::cue_sound::{Sound, Tone};
+use crate::hotkey;
+use crate::stun;
+use crate::voice_clip::{self, ClipWriter, RecordedClip};
+use chat_core::keybindings::Shortcut;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use global_hotkey::{GlobalHotKeyEvent, HotKeyState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// With noise suppression on, buffers quieter than this are silenced.
+const NOISE_FLOOR: f32 = 0.01;
+/// How often the audio loop rechecks whether it should stop.
+const LOOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const TEST_TONE_HZ: f32 = 440.0;
+const TEST_TONE_VOLUME: f32 = 0.2;
+const TEST_TONE_DURATION: Duration = Duration::from_millis(600);
+/// The ringtone's two tones, as a telephone rings.
+const RING_HZ: [f32; 2] = [440.0, 480.0];
+const RING_VOLUME: f32 = 0.15;
+/// Rings for `RING_ON_SECS` of every `RING_PERIOD_SECS`.
+const RING_ON_SECS: f32 = 1.2;
+const RING_PERIOD_SECS: f32 = 3.0;
+/// How often we greet peers, to find an address that reaches them and to
+/// keep it open.
+const HELLO_INTERVAL: Duration = Duration::from_secs(2);
+/// Someone stops counting as speaking this long after their last audio.
+const SPEAKING_HOLD: Duration = Duration::from_millis(300);
+/// Sends failing for this long means the network went away under us.
+const SEND_FAILURE_LIMIT: Duration = Duration::from_secs(3);
+/// How long a STUN server gets to answer. Short, as the audio loop waits.
+const STUN_WAIT: Duration = Duration::from_secs(1);
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+/// Frame sizes offered in settings, in milliseconds.
+pub const FRAME_SIZES_MS: [u32; 3] = [10, 20, 40];
+/// Bounds of the jitter buffer target, in milliseconds.
+pub const JITTER_MIN_MS: u32 = 20;
+pub const JITTER_MAX_MS: u32 = 200;
+/// Once a peer's jitter buffer holds this many times its target it has
+/// fallen behind, and the oldest audio is dropped to catch up.
+const JITTER_MAX_FACTOR: usize = 4;
+/// Room for the largest frame: 40 ms of 48 kHz stereo is 15 KB.
+const MAX_DATAGRAM: usize = 65536;
+
+/// First byte of each datagram: what follows it.
+const PACKET_AUDIO: u8 = 0;
+const PACKET_HELLO: u8 = 1;
+const PACKET_FORMAT: u8 = 2;
+
+/// Sounds played outside of calls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cue {
+    /// An incoming call; loops until stopped.
+    Ringtone,
+}
+
+impl Cue {
+    /// The sample `t` seconds in.
+    fn sample(self, t: f32) -> f32 {
+        match self {
+            Cue::Ringtone => {
+                if t % RING_PERIOD_SECS >= RING_ON_SECS {
+                    return 0.0;
+                }
+                let [a, b] = RING_HZ.map(|hz| (t * hz * std::f32::consts::TAU).sin());
+                (a + b) / 2.0 * RING_VOLUME
+            }
+        }
+    }
+}
+
+fn test_tone(t: f32) -> f32 {
+    (t * TEST_TONE_HZ * std::f32::consts::TAU).sin() * TEST_TONE_VOLUME
+}
+
+/// Something that happened in the call, pushed to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoiceEvent {
+    /// `user_id` started or stopped speaking. Covers us as well as peers.
+    Speaking { user_id: String, speaking: bool },
+    /// Our network changed and the call moved to a new socket; peers can't
+    /// hear us until they learn its address.
+    Reconnecting,
+    /// A peer got through to the new socket.
+    Reconnected,
+    /// The overlay's global hotkey was pressed.
+    OverlayKey,
+}
+
+#[derive(Debug, PartialEq)]
+enum Packet<'a> {
+    /// Captured samples as native-endian `f32`s.
+    Audio(&'a [u8]),
+    /// A peer introducing itself by user id.
+    Hello(&'a str),
+    /// The sender's frames are this many milliseconds from now on.
+    Format(u32),
+}
+
+impl<'a> Packet<'a> {
+    fn encode(&self) -> Vec<u8> {
+        let frame_ms;
+        let (kind, payload) = match self {
+            Packet::Audio(samples) => (PACKET_AUDIO, *samples),
+            Packet::Hello(user_id) => (PACKET_HELLO, user_id.as_bytes()),
+            Packet::Format(ms) => {
+                frame_ms = ms.to_le_bytes();
+                (PACKET_FORMAT, &frame_ms[..])
+            }
+        };
+        let mut data = Vec::with_capacity(payload.len() + 1);
+        data.push(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn decode(data: &'a [u8]) -> Option<Self> {
+        let (&kind, payload) = data.split_first()?;
+        match kind {
+            PACKET_AUDIO => Some(Packet::Audio(payload)),
+            PACKET_HELLO => std::str::from_utf8(payload).ok().map(Packet::Hello),
+            PACKET_FORMAT => payload
+                .try_into()
+                .ok()
+                .map(|ms| Packet::Format(u32::from_le_bytes(ms))),
+            _ => None,
+        }
+    }
+}
+
+/// What the capture callback hands the network loop.
+#[derive(Debug, PartialEq)]
+enum Captured {
+    Frame(Vec<f32>),
+    /// The frame size changed; peers are told before the next frame.
+    FrameSize(u32),
+}
+
+/// Samples in `ms` of audio, across all channels.
+fn samples_for(ms: u32, sample_rate: u32, channels: u16) -> usize {
+    (sample_rate as u64 * ms as u64 / 1000) as usize * channels as usize
+}
+
+/// How long `samples` samples last, in milliseconds.
+fn ms_for(samples: usize, sample_rate: u32, channels: u16) -> f32 {
+    let frames = samples as f32 / channels.max(1) as f32;
+    frames * 1000.0 / sample_rate.max(1) as f32
+}
+
+/// Cuts captured audio into frames of one length, however the device
+/// sizes its buffers. Frames go out as raw PCM: there is no Opus encoder
+/// (see Cargo.toml), so the frame size sets only how much audio each
+/// packet carries, and the encoder state flushed on a resize is just the
+/// partial frame pending.
+#[derive(Debug, Default)]
+struct Framer {
+    /// Samples per frame, across all channels.
+    frame_len: usize,
+    pending: Vec<f32>,
+}
+
+impl Framer {
+    /// Add captured samples. Returns the frames they complete.
+    fn push(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        self.pending.extend_from_slice(samples);
+        let frame_len = self.frame_len.max(1);
+        let mut frames = Vec::new();
+        while self.pending.len() >= frame_len {
+            frames.push(self.pending.drain(..frame_len).collect());
+        }
+        frames
+    }
+
+    /// Switch to frames of `frame_len` samples. Returns what was pending,
+    /// padded with silence to a whole frame of the old length, so no frame
+    /// mixes the two sizes.
+    fn resize(&mut self, frame_len: usize) -> Option<Vec<f32>> {
+        let mut rest = std::mem::take(&mut self.pending);
+        let old = std::mem::replace(&mut self.frame_len, frame_len);
+        if rest.is_empty() {
+            return None;
+        }
+        rest.resize(old.max(rest.len()), 0.0);
+        Some(rest)
+    }
+
+    /// Drop a partial frame, when transmitting stops partway through one.
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Audio received from one peer, held until enough has built up to play
+/// through packets arriving unevenly.
+#[derive(Debug, Default)]
+struct JitterBuffer {
+    samples: VecDeque<f32>,
+    /// Whether playback has started. After running dry it waits for the
+    /// target again.
+    playing: bool,
+}
+
+impl JitterBuffer {
+    /// Queue received samples. Past `JITTER_MAX_FACTOR` times `target`, the
+    /// oldest are dropped back down to `target` to keep the delay bounded.
+    fn push(&mut self, samples: &[f32], target: usize) {
+        self.samples.extend(samples);
+        if self.samples.len() > target.max(1) * JITTER_MAX_FACTOR {
+            let excess = self.samples.len() - target;
+            self.samples.drain(..excess);
+        }
+    }
+
+    /// Add queued samples into `out`. Nothing plays until `target` samples
+    /// are queued.
+    fn mix_into(&mut self, out: &mut [f32], target: usize) {
+        if !self.playing {
+            if self.samples.len() < target {
+                return;
+            }
+            self.playing = true;
+        }
+        let len = out.len().min(self.samples.len());
+        for (out, sample) in out.iter_mut().zip(self.samples.drain(..len)) {
+            *out += sample;
+        }
+        if len < out.len() {
+            self.playing = false;
+        }
+    }
+
+    /// Wait for the target again before playing on, as when the peer's
+    /// frame size changes and its packets start arriving at a new pace.
+    /// What is queued still plays, so nothing is cut off.
+    fn reprime(&mut self) {
+        self.playing = false;
+    }
+}
+
+/// Where the delay between a peer speaking and us hearing them comes from,
+/// in milliseconds. The network's own delay is not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyEstimate {
+    /// The input device's buffer.
+    pub capture_ms: f32,
+    /// Waiting for a frame to fill before it is sent.
+    pub frame_ms: f32,
+    /// Incoming audio held back by the jitter buffer.
+    pub jitter_ms: f32,
+    /// The output device's buffer.
+    pub playback_ms: f32,
+}
+
+impl LatencyEstimate {
+    pub fn total_ms(&self) -> f32 {
+        self.capture_ms + self.frame_ms + self.jitter_ms + self.playback_ms
+    }
+}
+
+impl fmt::Display for LatencyEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "~{:.0} ms: capture {:.0} + frame {:.0} + jitter {:.0} + playback {:.0}",
+            self.total_ms(),
+            self.capture_ms,
+            self.frame_ms,
+            self.jitter_ms,
+            self.playback_ms
+        )
+    }
+}
+
+/// A sound mixed into the voice output instead of played on a stream of
+/// its own, when cues and voice share a device.
+struct MixedSound {
+    wave: Box<dyn Fn(f32) -> f32 + Send + Sync>,
+    playing: Box<dyn Fn(Duration) -> bool + Send>,
+    /// Seconds played so far.
+    played: f64,
+}
+
+impl MixedSound {
+    /// Add the sound's next samples to `out`. Returns false once it is over.
+    fn mix_into(&mut self, out: &mut [f32], sample_rate: u32, channels: u16) -> bool {
+        if !(self.playing)(Duration::from_secs_f64(self.played)) {
+            return false;
+        }
+        let step = 1.0 / sample_rate.max(1) as f64;
+        for frame in out.chunks_mut(channels.max(1) as usize) {
+            let sample = (self.wave)(self.played as f32);
+            for channel in frame {
+                *channel += sample;
+            }
+            self.played += step;
+        }
+        true
+    }
+
+    /// Play the rest of the sound on a stream of its own, as when the call
+    /// it was mixed into ends.
+    fn play_on(self, device: Option<String>) {
+        let (wave, playing, played) = (self.wave, self.playing, self.played);
+        let offset = Duration::from_secs_f64(played);
+        play_wave(
+            device,
+            move |t| wave(t + played as f32),
+            move |elapsed| playing(elapsed + offset),
+        );
+    }
+}
+
+/// What the voice output stream plays from, kept apart from the stream so
+/// it can be reopened on another device.
+#[derive(Clone)]
+struct VoiceOutput {
+    /// Received audio, by peer, waiting to be played.
+    jitter: Arc<Mutex<HashMap<String, JitterBuffer>>>,
+    /// Jitter buffer target in milliseconds.
+    jitter_ms: Arc<AtomicU32>,
+    buffers: Arc<DeviceBuffers>,
+    sounds: Arc<Mutex<Vec<MixedSound>>>,
+}
+
+impl VoiceOutput {
+    /// Open and start a stream on `device` playing from here. Returns it
+    /// with its sample rate and channel count.
+    fn open(
+        &self,
+        device: &cpal::Device,
+        lost: Arc<AtomicBool>,
+    ) -> Result<(cpal::Stream, u32, u16)> {
+        let config: cpal::StreamConfig = device.default_output_config()?.into();
+        let (rate, channels) = (config.sample_rate.0, config.channels);
+        let output = self.clone();
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &_| output.fill(data, rate, channels),
+            output_error(lost),
+            None,
+        )?;
+        stream.play()?;
+        Ok((stream, rate, channels))
+    }
+
+    fn fill(&self, data: &mut [f32], rate: u32, channels: u16) {
+        data.fill(0.0);
+        self.buffers
+            .playback_ms
+            .store(ms_for(data.len(), rate, channels));
+        // Never wait on the network loop here: a buffer of silence is better
+        // than a stalled device.
+        if let Ok(mut peers) = self.jitter.try_lock() {
+            let target = samples_for(self.jitter_ms.load(Ordering::Relaxed), rate, channels);
+            for buffer in peers.values_mut() {
+                buffer.mix_into(data, target);
+            }
+        }
+        if let Ok(mut sounds) = self.sounds.try_lock() {
+            sounds.retain_mut(|sound| sound.mix_into(data, rate, channels));
+        }
+        for sample in data.iter_mut() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// An output stream's error callback. Sets `lost` if the device went away,
+/// for whoever plays on it to move to the default.
+fn output_error(lost: Arc<AtomicBool>) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    move |err| {
+        if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+            lost.store(true, Ordering::SeqCst);
+        }
+        tracing::error!("Output stream error: {}", err);
+    }
+}
+
+/// How much audio the devices buffer, measured from their callbacks.
+#[derive(Debug, Default)]
+struct DeviceBuffers {
+    capture_ms: AtomicF32,
+    playback_ms: AtomicF32,
+}
+
+/// Someone else in the call.
+#[derive(Debug, Default)]
+struct Peer {
+    /// Addresses they announced, best first.
+    candidates: Vec<SocketAddr>,
+    /// The address their hello came from, once one has.
+    addr: Option<SocketAddr>,
+    last_voice: Option<Instant>,
+}
+
+/// The call's participants and who is speaking.
+#[derive(Debug, Default)]
+struct Call {
+    local_user: String,
+    peers: HashMap<String, Peer>,
+    last_voice: HashMap<String, Instant>,
+    speaking: HashSet<String>,
+}
+
+impl Call {
+    /// Replace the peers, keeping the confirmed address of anyone still
+    /// reachable at it. Returns speaking changes for those who left.
+    fn set_peers(&mut self, peers: Vec<(String, Vec<SocketAddr>)>) -> Vec<VoiceEvent> {
+        let mut old = std::mem::take(&mut self.peers);
+        for (user_id, candidates) in peers {
+            if user_id == self.local_user {
+                continue;
+            }
+            let previous = old.remove(&user_id).unwrap_or_default();
+            let addr = previous.addr.filter(|addr| candidates.contains(addr));
+            self.peers.insert(
+                user_id,
+                Peer {
+                    candidates,
+                    addr,
+                    last_voice: previous.last_voice,
+                },
+            );
+        }
+        old.into_keys()
+            .filter_map(|user_id| self.stop_speaking(&user_id))
+            .collect()
+    }
+
+    /// The peer whose confirmed address is `addr`.
+    fn peer_at(&self, addr: SocketAddr) -> Option<&str> {
+        self.peers
+            .iter()
+            .find(|(_, peer)| peer.addr == Some(addr))
+            .map(|(user_id, _)| user_id.as_str())
+    }
+
+    /// Record audio from `user_id`. Returns a change if they just started.
+    fn heard(&mut self, user_id: &str, now: Instant) -> Option<VoiceEvent> {
+        match self.peers.get_mut(user_id) {
+            Some(peer) => peer.last_voice = Some(now),
+            None if user_id == self.local_user => {
+                self.last_voice.insert(user_id.to_string(), now);
+            }
+            None => return None,
+        }
+        self.speaking
+            .insert(user_id.to_string())
+            .then(|| VoiceEvent::Speaking {
+                user_id: user_id.to_string(),
+                speaking: true,
+            })
+    }
+
+    /// Stop showing as speaking anyone silent for `SPEAKING_HOLD`.
+    fn expire(&mut self, now: Instant) -> Vec<VoiceEvent> {
+        let quiet: Vec<String> = self
+            .speaking
+            .iter()
+            .filter(|user_id| {
+                let last = match self.peers.get(*user_id) {
+                    Some(peer) => peer.last_voice,
+                    None => self.last_voice.get(*user_id).copied(),
+                };
+                last.is_none_or(|last| now.duration_since(last) >= SPEAKING_HOLD)
+            })
+            .cloned()
+            .collect();
+        quiet
+            .iter()
+            .filter_map(|user_id| self.stop_speaking(user_id))
+            .collect()
+    }
+
+    fn stop_speaking(&mut self, user_id: &str) -> Option<VoiceEvent> {
+        self.speaking.remove(user_id).then(|| VoiceEvent::Speaking {
+            user_id: user_id.to_string(),
+            speaking: false,
+        })
+    }
+
+    /// Addresses to greet: every candidate of every peer.
+    fn hello_addrs(&self) -> Vec<SocketAddr> {
+        self.peers
+            .values()
+            .flat_map(|peer| peer.candidates.iter().copied())
+            .collect()
+    }
+
+    /// Addresses to send audio to: peers that have answered.
+    fn audio_addrs(&self) -> Vec<SocketAddr> {
+        self.peers.values().filter_map(|peer| peer.addr).collect()
+    }
+}
+
+/// Watches for the network changing under the call: our LAN address
+/// moving, as on a switch from Wi-Fi to Ethernet, or sends failing.
+#[derive(Debug, Default)]
+struct Link {
+    ip: Option<IpAddr>,
+    /// When sends started failing, if the last one did.
+    failing_since: Option<Instant>,
+    /// The socket still has to be replaced; a failed rebind is retried.
+    rebind: bool,
+    /// From noticing a change until a peer is heard on the new socket.
+    reconnecting: bool,
+}
+
+impl Link {
+    fn new(ip: Option<IpAddr>) -> Self {
+        Self {
+            ip,
+            ..Default::default()
+        }
+    }
+
+    fn sent(&mut self, ok: bool, now: Instant) {
+        if ok {
+            self.failing_since = None;
+        } else {
+            self.failing_since.get_or_insert(now);
+        }
+    }
+
+    /// Note our LAN address is now `ip`. Returns `Reconnecting` if it moved,
+    /// or sends have failed for `SEND_FAILURE_LIMIT`, and we were not
+    /// already reconnecting.
+    fn check(&mut self, ip: Option<IpAddr>, now: Instant) -> Option<VoiceEvent> {
+        let moved = std::mem::replace(&mut self.ip, ip) != ip;
+        let failing = self
+            .failing_since
+            .is_some_and(|since| now.duration_since(since) >= SEND_FAILURE_LIMIT);
+        if !moved && !failing {
+            return None;
+        }
+        self.failing_since = None;
+        self.rebind = true;
+        (!std::mem::replace(&mut self.reconnecting, true)).then_some(VoiceEvent::Reconnecting)
+    }
+
+    /// The socket was replaced. With nobody in the call to hear from, that
+    /// is the end of it.
+    fn rebound(&mut self, peers: usize) -> Option<VoiceEvent> {
+        self.rebind = false;
+        if peers == 0 {
+            self.heard()
+        } else {
+            None
+        }
+    }
+
+    /// A peer got through. Returns `Reconnected` if that ends a
+    /// reconnection; packets still arriving at the old socket don't.
+    fn heard(&mut self) -> Option<VoiceEvent> {
+        if self.rebind {
+            return None;
+        }
+        std::mem::take(&mut self.reconnecting).then_some(VoiceEvent::Reconnected)
+    }
+}
+
+/// A new socket in place of `old`, on the same interface and a new port.
+async fn rebind(old: &UdpSocket) -> std::io::Result<Arc<UdpSocket>> {
+    let local = old.local_addr()?;
+    let socket = UdpSocket::bind(SocketAddr::new(local.ip(), 0)).await?;
+    Ok(Arc::new(socket))
+}
+
+/// Where STUN servers see the voice socket from outside, for peers behind
+/// another NAT.
+#[derive(Debug, Default)]
+struct PublicAddress {
+    servers: Vec<SocketAddr>,
+    seen: Option<SocketAddr>,
+    /// The servers changed; ask them on the next tick.
+    stale: bool,
+}
+
+/// Ask the STUN servers in turn where `socket` is seen from, forgetting
+/// what they saw of any socket before it.
+async fn discover(socket: &UdpSocket, public: &Mutex<PublicAddress>) {
+    let servers = {
+        let mut public = public.lock().unwrap();
+        public.stale = false;
+        public.servers.clone()
+    };
+    let mut seen = None;
+    for server in servers {
+        match stun::query(socket, server, STUN_WAIT).await {
+            Ok(address) => {
+                seen = Some(address);
+                break;
+            }
+            Err(e) => tracing::debug!("STUN failed: {}", e),
+        }
+    }
+    public.lock().unwrap().seen = seen;
+}
+
+/// The LAN address of this machine: the one a UDP socket picks to reach
+/// the internet. Connecting a UDP socket sends nothing.
+fn lan_ip() -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// An `f32` that can be shared with the audio callbacks.
+#[derive(Debug, Default)]
+struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Capture settings, read by the audio thread on every buffer so changes
+/// apply immediately.
+#[derive(Debug)]
+struct InputSettings {
+    gain: AtomicF32,
+    vad_threshold: AtomicF32,
+    noise_suppression: AtomicBool,
+    push_to_talk: AtomicBool,
+    ptt_pressed: AtomicBool,
+    /// Id of the push-to-talk key's global hotkey, if it could be registered.
+    ptt_hotkey: Mutex<Option<u32>>,
+    /// Level of the latest captured buffer, after gain, for the mic meter.
+    level: AtomicF32,
+    /// Milliseconds per frame sent, one of `FRAME_SIZES_MS`.
+    frame_ms: AtomicU32,
+}
+
+impl InputSettings {
+    fn new(config: &AudioConfig) -> Self {
+        Self {
+            gain: AtomicF32::new(config.input_gain),
+            vad_threshold: AtomicF32::new(config.vad_threshold),
+            noise_suppression: AtomicBool::new(config.noise_suppression),
+            push_to_talk: AtomicBool::new(false),
+            ptt_pressed: AtomicBool::new(false),
+            ptt_hotkey: Mutex::new(None),
+            level: AtomicF32::default(),
+            frame_ms: AtomicU32::new(frame_size(config.frame_ms)),
+        }
+    }
+
+    /// Process a captured buffer and record its level. Returns the samples
+    /// to transmit, or `None` if this buffer should not be sent.
+    fn capture(&self, data: &[f32]) -> Option<Vec<f32>> {
+        let mut samples = data.to_vec();
+        let level = process_input(
+            &mut samples,
+            self.gain.load(),
+            self.noise_suppression.load(Ordering::Relaxed),
+        );
+        self.level.store(level);
+        let ptt = self
+            .push_to_talk
+            .load(Ordering::Relaxed)
+            .then(|| self.ptt_pressed.load(Ordering::Relaxed));
+        transmits(level, self.vad_threshold.load(), ptt).then_some(samples)
+    }
+
+    /// A global hotkey went down or up; the push-to-talk one is held while
+    /// it is down.
+    fn hotkey_event(&self, event: GlobalHotKeyEvent) {
+        if *self.ptt_hotkey.lock().unwrap() == Some(event.id) {
+            self.ptt_pressed
+                .store(event.state == HotKeyState::Pressed, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `ms` if it is one of `FRAME_SIZES_MS`, otherwise the default.
+fn frame_size(ms: u32) -> u32 {
+    if FRAME_SIZES_MS.contains(&ms) {
+        ms
+    } else {
+        AudioConfig::default().frame_ms
+    }
+}
+
+/// Apply `gain` to `samples` in place, silencing them if noise suppression is
+/// on and they are below the noise floor. Returns their RMS level, 0.0–1.0.
+fn process_input(samples: &mut [f32], gain: f32, noise_suppression: bool) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    for sample in samples.iter_mut() {
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+    let level = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+    if noise_suppression && level < NOISE_FLOOR {
+        samples.fill(0.0);
+        return 0.0;
+    }
+    level
+}
+
+/// Whether a buffer at `level` is sent: while the key is held in
+/// push-to-talk mode (`ptt` is whether it is pressed), otherwise when the
+/// level reaches the voice activity threshold.
+fn transmits(level: f32, vad_threshold: f32, ptt: Option<bool>) -> bool {
+    match ptt {
+        Some(pressed) => pressed,
+        None => level > 0.0 && level >= vad_threshold,
+    }
+}
+
+/// The input device called `name`, or the default if there is no such
+/// device (it may have been unplugged since it was chosen).
+fn input_device(host: &cpal::Host, name: Option<&str>) -> Option<cpal::Device> {
+    if let Some(name) = name {
+        let found = host
+            .input_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().ok().as_deref() == Some(name)));
+        if found.is_some() {
+            return found;
+        }
+        tracing::warn!("Input device {} is unavailable, using the default", name);
+    }
+    host.default_input_device()
+}
+
+/// The output device called `name`, or the default; see `input_device`.
+fn output_device(host: &cpal::Host, name: Option<&str>) -> Option<cpal::Device> {
+    if let Some(name) = name {
+        let found = host
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().ok().as_deref() == Some(name)));
+        if found.is_some() {
+            return found;
+        }
+        tracing::warn!("Output device {} is unavailable, using the default", name);
+    }
+    host.default_output_device()
+}
+
+/// Chosen device names, `None` for the system default.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Devices {
+    input: Option<String>,
+    output: Option<String>,
+    cue: CueOutput,
+}
+
+impl Devices {
+    /// Where cues play, or `None` if they are off.
+    fn cue_device(&self) -> Option<Option<String>> {
+        match &self.cue {
+            CueOutput::Voice => Some(self.output.clone()),
+            CueOutput::Device(name) => Some(name.clone()),
+            CueOutput::Off => None,
+        }
+    }
+}
+
+/// A voice clip being recorded, fed by the call's capture stream while in
+/// voice or by a stream of its own otherwise.
+struct Clip {
+    writer: ClipWriter,
+    from_call: bool,
+}
+
+/// How the voice clip being recorded is coming along.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipStatus {
+    pub elapsed: Duration,
+    /// Level of the latest buffer, 0.0–1.0.
+    pub level: f32,
+    /// It reached the longest allowed, so nothing more is kept.
+    pub full: bool,
+}
+
+/// Add a captured buffer, with the microphone's gain applied, to the clip
+/// being recorded if it is fed from the call (`from_call`) or not.
+fn record_clip(
+    clip: &Mutex<Option<Clip>>,
+    input: &InputSettings,
+    from_call: bool,
+    data: &[f32],
+    rate: u32,
+    channels: u16,
+) {
+    let mut clip = clip.lock().unwrap();
+    let Some(clip) = clip.as_mut().filter(|clip| clip.from_call == from_call) else {
+        return;
+    };
+    let mut samples = data.to_vec();
+    process_input(
+        &mut samples,
+        input.gain.load(),
+        input.noise_suppression.load(Ordering::Relaxed),
+    );
+    clip.writer.push(&samples, rate, channels);
+}
 
 pub struct VoiceManager {
-    socket: Arc<UdpSocket>,
+    /// Replaced by the audio loop when the network changes.
+    socket: Arc<Mutex<Arc<UdpSocket>>>,
+    /// Found again by the audio loop whenever the socket is replaced.
+    public: Arc<Mutex<PublicAddress>>,
     is_recording: Arc<AtomicBool>,
-    target_addr: Arc<Mutex<Option<SocketAddr>>>,
+    /// Bumped to retire the running audio thread, e.g. on a device switch.
+    generation: Arc<AtomicU64>,
+    /// Stop sending our microphone.
+    muted: Arc<AtomicBool>,
+    /// Stop playing incoming audio; implies muted.
+    deafened: Arc<AtomicBool>,
+    input: Arc<InputSettings>,
+    /// Jitter buffer target in milliseconds, read by the audio thread on
+    /// every buffer.
+    jitter_ms: Arc<AtomicU32>,
+    buffers: Arc<DeviceBuffers>,
+    /// Cues mixed into the call, while it plays where they do.
+    sounds: Arc<Mutex<Vec<MixedSound>>>,
+    devices: Arc<Mutex<Devices>>,
+    /// The settings screen's microphone test is running.
+    mic_test: Arc<AtomicBool>,
+    /// Bumped to stop the cue playing.
+    cue_generation: Arc<AtomicU64>,
+    clip: Arc<Mutex<Option<Clip>>>,
+    /// Bumped to stop playing back a recorded clip.
+    preview_generation: Arc<AtomicU64>,
+    call: Arc<Mutex<Call>>,
+    events: broadcast::Sender<VoiceEvent>,
+    /// Id of the overlay key's global hotkey, if it could be registered.
+    overlay_hotkey: Arc<Mutex<Option<u32>>>,
+    /// The latest audio thread, so quitting can wait for it to end.
+    audio_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
     // In a real app, we'd store the streams here to keep them alive,
     // but cpal streams rely on `std::marker::Send` which isn't always trivial.
     // For this prototype, we'll spawn a blocking thread for the audio loop.
@@ -19,16 +857,186 @@ pub struct VoiceManager {
 impl VoiceManager {
     pub async fn new(bind_addr: &str) -> Result<Self> {
         let socket = UdpSocket::bind(bind_addr).await?;
+        let defaults = AudioConfig::default();
+        let input = Arc::new(InputSettings::new(&defaults));
+        let events = broadcast::channel(EVENT_CHANNEL_CAPACITY).0;
+        let overlay_hotkey = Arc::new(Mutex::new(None));
+        let hotkey_input = input.clone();
+        let hotkey_overlay = overlay_hotkey.clone();
+        let hotkey_events = events.clone();
+        GlobalHotKeyEvent::set_event_handler(Some(move |event: GlobalHotKeyEvent| {
+            if event.state == HotKeyState::Pressed
+                && *hotkey_overlay.lock().unwrap() == Some(event.id)
+            {
+                hotkey_events.send(VoiceEvent::OverlayKey).ok();
+            }
+            hotkey_input.hotkey_event(event)
+        }));
+        voice_clip::sweep();
         Ok(Self {
-            socket: Arc::new(socket),
+            socket: Arc::new(Mutex::new(Arc::new(socket))),
+            public: Arc::new(Mutex::new(PublicAddress::default())),
             is_recording: Arc::new(AtomicBool::new(false)),
-            target_addr: Arc::new(Mutex::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
+            muted: Arc::new(AtomicBool::new(false)),
+            deafened: Arc::new(AtomicBool::new(false)),
+            input,
+            jitter_ms: Arc::new(AtomicU32::new(defaults.jitter_ms)),
+            buffers: Arc::new(DeviceBuffers::default()),
+            sounds: Arc::new(Mutex::new(Vec::new())),
+            devices: Arc::new(Mutex::new(Devices::default())),
+            mic_test: Arc::new(AtomicBool::new(false)),
+            cue_generation: Arc::new(AtomicU64::new(0)),
+            clip: Arc::new(Mutex::new(None)),
+            preview_generation: Arc::new(AtomicU64::new(0)),
+            call: Arc::new(Mutex::new(Call::default())),
+            events,
+            overlay_hotkey,
+            audio_thread: Mutex::new(None),
         })
     }
 
-    pub async fn set_target(&self, addr: SocketAddr) {
-        let mut target = self.target_addr.lock().await;
-        *target = Some(addr);
+    /// Apply audio settings. Gain, thresholds, frame size and the jitter
+    /// target take effect on the next buffer; a device change restarts a
+    /// running audio loop on the new devices.
+    pub fn apply_config(&self, config: &AudioConfig) {
+        self.input.gain.store(config.input_gain);
+        self.input.vad_threshold.store(config.vad_threshold);
+        self.input
+            .noise_suppression
+            .store(config.noise_suppression, Ordering::Relaxed);
+        self.input
+            .frame_ms
+            .store(frame_size(config.frame_ms), Ordering::Relaxed);
+        self.jitter_ms.store(
+            config.jitter_ms.clamp(JITTER_MIN_MS, JITTER_MAX_MS),
+            Ordering::Relaxed,
+        );
+
+        let devices = Devices {
+            input: config.input_device.clone(),
+            output: config.output_device.clone(),
+            cue: config.cue_output.clone(),
+        };
+        // Cues open a stream per sound, so only voice devices need a restart.
+        let changed = {
+            let mut current = self.devices.lock().unwrap();
+            let changed = current.input != devices.input || current.output != devices.output;
+            *current = devices;
+            changed
+        };
+        if changed && self.is_active() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            self.spawn_audio_thread();
+        }
+    }
+
+    /// Transmit only while `key` is held, or on voice activity if `None`.
+    /// The key is registered as a global hotkey; where that fails it still
+    /// works through `set_ptt_pressed` while our window has focus. Call from
+    /// the UI thread.
+    pub fn set_ptt_key(&self, key: Option<&Shortcut>) {
+        self.input
+            .push_to_talk
+            .store(key.is_some(), Ordering::Relaxed);
+        self.input.ptt_pressed.store(false, Ordering::Relaxed);
+        let keys = key.map(Shortcut::to_string);
+        let id = hotkey::register(hotkey::Slot::PushToTalk, keys.as_deref()).unwrap_or_else(|e| {
+            tracing::warn!("Push to talk only works in the window: {:#}", e);
+            None
+        });
+        *self.input.ptt_hotkey.lock().unwrap() = id;
+    }
+
+    /// Send `VoiceEvent::OverlayKey` whenever `key` is pressed, wherever
+    /// the focus is, or stop if `None`. Returns false where it couldn't be
+    /// registered, leaving the key to the window. Call from the UI thread.
+    pub fn set_overlay_key(&self, key: Option<&Shortcut>) -> bool {
+        let keys = key.map(Shortcut::to_string);
+        let id = hotkey::register(hotkey::Slot::Overlay, keys.as_deref()).unwrap_or_else(|e| {
+            tracing::warn!("The overlay key only works in the window: {:#}", e);
+            None
+        });
+        *self.overlay_hotkey.lock().unwrap() = id;
+        id.is_some()
+    }
+
+    /// Report whether the push-to-talk key is held.
+    pub fn set_ptt_pressed(&self, pressed: bool) {
+        self.input.ptt_pressed.store(pressed, Ordering::Relaxed);
+    }
+
+    /// Level of the microphone (0.0–1.0) while in voice or testing it.
+    pub fn input_level(&self) -> f32 {
+        self.input.level.load()
+    }
+
+    /// How far behind a peer we hear them, not counting the network, while
+    /// in voice.
+    pub fn latency(&self) -> Option<LatencyEstimate> {
+        self.is_active().then(|| LatencyEstimate {
+            capture_ms: self.buffers.capture_ms.load(),
+            frame_ms: self.input.frame_ms.load(Ordering::Relaxed) as f32,
+            jitter_ms: self.jitter_ms.load(Ordering::Relaxed) as f32,
+            playback_ms: self.buffers.playback_ms.load(),
+        })
+    }
+
+    /// Addresses peers can reach us at, best first: our LAN address, the
+    /// one STUN sees us at, then loopback for other clients on this machine.
+    pub fn local_candidates(&self) -> Vec<String> {
+        let Ok(local) = self.socket.lock().unwrap().local_addr() else {
+            return Vec::new();
+        };
+        let ips = if local.ip().is_unspecified() {
+            lan_ip()
+                .into_iter()
+                .chain([IpAddr::V4(Ipv4Addr::LOCALHOST)])
+                .collect()
+        } else {
+            vec![local.ip()]
+        };
+        let mut candidates: Vec<SocketAddr> = ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, local.port()))
+            .collect();
+        let public = self.public.lock().unwrap().seen;
+        if let Some(public) = public.filter(|public| !candidates.contains(public)) {
+            candidates.insert(candidates.len().min(1), public);
+        }
+        candidates.iter().map(ToString::to_string).collect()
+    }
+
+    /// The STUN servers to find our public address with. They are asked on
+    /// the audio loop's next tick, and again whenever it rebinds.
+    pub fn set_stun_servers(&self, servers: Vec<SocketAddr>) {
+        let mut public = self.public.lock().unwrap();
+        public.servers = servers;
+        public.stale = true;
+    }
+
+    /// Who we are in calls, so our own announcement is not taken for a
+    /// peer and our speaking is reported under our id.
+    pub fn set_local_user(&self, user_id: &str) {
+        self.call.lock().unwrap().local_user = user_id.to_string();
+    }
+
+    /// The other participants and the addresses they announced. Peers not
+    /// listed are hung up on.
+    pub fn set_peers(&self, peers: Vec<(String, Vec<SocketAddr>)>) {
+        let changes = self.call.lock().unwrap().set_peers(peers);
+        self.notify(changes);
+    }
+
+    /// Speaking changes from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<VoiceEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, events: Vec<VoiceEvent>) {
+        for event in events {
+            let _ = self.events.send(event);
+        }
     }
 
     pub fn start_audio_loop(&self) -> Result<()> {
@@ -37,19 +1045,52 @@ impl VoiceManager {
         }
 
         self.is_recording.store(true, Ordering::SeqCst);
-        let socket = self.socket.clone();
-        let is_running = self.is_recording.clone();
-        let target_addr_mutex = self.target_addr.clone();
+        // A clip being recorded is fed from the call's stream from now on.
+        if let Some(clip) = self.clip.lock().unwrap().as_mut() {
+            clip.from_call = true;
+        }
+        self.spawn_audio_thread();
+        Ok(())
+    }
+
+    fn spawn_audio_thread(&self) {
+        let socket_slot = self.socket.clone();
+        let public = self.public.clone();
+        let is_recording = self.is_recording.clone();
+        let generation = self.generation.clone();
+        let own_generation = generation.load(Ordering::SeqCst);
+        let is_running = move || {
+            is_recording.load(Ordering::SeqCst)
+                && generation.load(Ordering::SeqCst) == own_generation
+        };
+        let muted = self.muted.clone();
+        let deafened = self.deafened.clone();
+        let input = self.input.clone();
+        let clip = self.clip.clone();
+        let output = VoiceOutput {
+            jitter: Arc::new(Mutex::new(HashMap::new())),
+            jitter_ms: self.jitter_ms.clone(),
+            buffers: self.buffers.clone(),
+            sounds: self.sounds.clone(),
+        };
+        let devices = self.devices.lock().unwrap().clone();
+        let call = self.call.clone();
+        let events = self.events.clone();
+        let notify = move |changes: Vec<VoiceEvent>| {
+            for event in changes {
+                let _ = events.send(event);
+            }
+        };
 
         // Spawn a dedicated thread for audio input/output to avoid blocking async runtime
-        std::thread::spawn(move || {
+        let thread = std::thread::spawn(move || {
             let host = cpal::default_host();
 
             // Setup Input
-            let input_device = match host.default_input_device() {
+            let input_device = match input_device(&host, devices.input.as_deref()) {
                 Some(d) => d,
                 None => {
-                    eprintln!("No input device available");
+                    tracing::error!("No input device available");
                     return;
                 }
             };
@@ -58,21 +1099,43 @@ impl VoiceManager {
 
             // Channel to bridge sync audio callback to async network sender
             // Use Unbounded channel to allow sending from sync code without blocking
-            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Captured>();
 
-            // Input Stream
+            // Input Stream. Samples go out raw rather than through a codec,
+            // so the frame size only decides how they are packetized.
+            let (rate, channels) = (config.sample_rate.0, config.channels);
+            let capture_buffers = output.buffers.clone();
+            let mut framer = Framer::default();
+            let mut frame_ms = 0;
             let input_stream = input_device
                 .build_input_stream(
                     &config,
                     move |data: &[f32], _: &_| {
-                        // Simple f32 to u8 (byte dump)
-                        let mut bytes = Vec::with_capacity(data.len() * 4);
-                        for sample in data {
-                            bytes.extend_from_slice(&sample.to_ne_bytes());
+                        capture_buffers
+                            .capture_ms
+                            .store(ms_for(data.len(), rate, channels));
+                        record_clip(&clip, &input, true, data, rate, channels);
+                        // A new frame size: finish the frame in progress at
+                        // the old size, then tell peers before sending any
+                        // at the new one.
+                        let wanted = input.frame_ms.load(Ordering::Relaxed);
+                        if wanted != frame_ms {
+                            frame_ms = wanted;
+                            let rest = framer.resize(samples_for(wanted, rate, channels));
+                            if let Some(rest) = rest {
+                                let _ = tx.send(Captured::Frame(rest));
+                            }
+                            let _ = tx.send(Captured::FrameSize(wanted));
+                        }
+                        let Some(samples) = input.capture(data) else {
+                            framer.clear();
+                            return;
+                        };
+                        for frame in framer.push(&samples) {
+                            let _ = tx.send(Captured::Frame(frame));
                         }
-                        let _ = tx.send(bytes);
                     },
-                    |err| eprintln!("Input stream error: {}", err),
+                    |err| tracing::error!("Input stream error: {}", err),
                     None,
                 )
                 .unwrap();
@@ -80,43 +1143,24 @@ impl VoiceManager {
             input_stream.play().unwrap();
 
             // Setup Output
-            let output_device = match host.default_output_device() {
+            let out_dev = match output_device(&host, devices.output.as_deref()) {
                 Some(d) => d,
                 None => {
-                    eprintln!("No output device available");
+                    tracing::error!("No output device available");
                     return;
                 }
             };
-            let output_config: cpal::StreamConfig =
-                output_device.default_output_config().unwrap().into();
-
-            // Channel for received audio to be played
-            let (play_tx, play_rx) = std::sync::mpsc::channel::<Vec<f32>>();
-
-            let output_stream = output_device
-                .build_output_stream(
-                    &output_config,
-                    move |data: &mut [f32], _: &_| {
-                        if let Ok(incoming) = play_rx.try_recv() {
-                            let len = std::cmp::min(data.len(), incoming.len());
-                            data[..len].copy_from_slice(&incoming[..len]);
-                            if len < data.len() {
-                                for sample in &mut data[len..] {
-                                    *sample = 0.0;
-                                }
-                            }
-                        } else {
-                            for sample in data.iter_mut() {
-                                *sample = 0.0;
-                            }
-                        }
-                    },
-                    |err| eprintln!("Output stream error: {}", err),
-                    None,
-                )
-                .unwrap();
-
-            output_stream.play().unwrap();
+            let output_lost = Arc::new(AtomicBool::new(false));
+            let (mut _output_stream, mut out_rate, mut out_channels) =
+                match output.open(&out_dev, output_lost.clone()) {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        tracing::warn!("Failed to open voice output: {}", e);
+                        return;
+                    }
+                };
+            let jitter = output.jitter.clone();
+            let jitter_ms = output.jitter_ms.clone();
 
             // Network Sender/Receiver Loop
             let rt = tokio::runtime::Builder::new_current_thread()
@@ -124,56 +1168,467 @@ impl VoiceManager {
                 .build()
                 .unwrap();
 
-            let socket_recv = socket.clone();
             rt.block_on(async {
-                let mut buf = [0u8; 4096];
+                let mut socket = socket_slot.lock().unwrap().clone();
+                let mut buf = vec![0u8; MAX_DATAGRAM];
+                let mut tick = tokio::time::interval(LOOP_CHECK_INTERVAL);
+                let mut last_hello: Option<Instant> = None;
+                let mut link = Link::new(lan_ip());
 
                 loop {
-                    if !is_running.load(Ordering::SeqCst) {
+                    if !is_running() {
                         break;
                     }
 
                     tokio::select! {
                         // SEND: Input audio -> UDP
-                        Some(data) = rx.recv() => {
-                             let target = target_addr_mutex.lock().await;
-                             if let Some(addr) = *target {
-                                 let _ = socket.send_to(&data, addr).await;
+                        Some(captured) = rx.recv() => {
+                             let samples = match captured {
+                                 Captured::FrameSize(frame_ms) => {
+                                     let addrs = call.lock().unwrap().audio_addrs();
+                                     let packet = Packet::Format(frame_ms).encode();
+                                     for addr in addrs {
+                                         let sent = socket.send_to(&packet, addr).await;
+                                         link.sent(sent.is_ok(), Instant::now());
+                                     }
+                                     continue;
+                                 }
+                                 Captured::Frame(samples) => samples,
+                             };
+                             if muted.load(Ordering::SeqCst) || deafened.load(Ordering::SeqCst) {
+                                 continue;
+                             }
+                             let (addrs, changes) = {
+                                 let mut call = call.lock().unwrap();
+                                 let local_user = call.local_user.clone();
+                                 let change = call.heard(&local_user, Instant::now());
+                                 (call.audio_addrs(), change.into_iter().collect())
+                             };
+                             notify(changes);
+                             // Simple f32 to u8 (byte dump)
+                             let mut data = Vec::with_capacity(samples.len() * 4);
+                             for sample in samples {
+                                 data.extend_from_slice(&sample.to_ne_bytes());
+                             }
+                             let packet = Packet::Audio(&data).encode();
+                             for addr in addrs {
+                                 let sent = socket.send_to(&packet, addr).await;
+                                 link.sent(sent.is_ok(), Instant::now());
                              }
                         }
 
                         // RECEIVE: UDP -> Output Audio
-                        res = socket_recv.recv_from(&mut buf) => {
+                        res = socket.recv_from(&mut buf) => {
                             match res {
-                                Ok((len, _addr)) => {
-                                    let mut samples = Vec::with_capacity(len / 4);
-                                    for chunk in buf[..len].chunks_exact(4) {
-                                        let val = f32::from_ne_bytes(chunk.try_into().unwrap());
-                                        samples.push(val);
+                                Ok((len, addr)) => match Packet::decode(&buf[..len]) {
+                                    Some(Packet::Hello(user_id)) => {
+                                        if let Some(peer) = call.lock().unwrap().peers.get_mut(user_id) {
+                                            peer.addr = Some(addr);
+                                            notify(link.heard().into_iter().collect());
+                                        }
                                     }
-                                    let _ = play_tx.send(samples);
-                                }
+                                    Some(Packet::Format(_)) => {
+                                        let Some(user_id) = call.lock().unwrap().peer_at(addr).map(str::to_string) else {
+                                            continue;
+                                        };
+                                        notify(link.heard().into_iter().collect());
+                                        if let Some(buffer) = jitter.lock().unwrap().get_mut(&user_id) {
+                                            buffer.reprime();
+                                        }
+                                    }
+                                    Some(Packet::Audio(data)) => {
+                                        // Only play audio from peers in the call.
+                                        let (user_id, change) = {
+                                            let mut call = call.lock().unwrap();
+                                            let Some(user_id) = call.peer_at(addr).map(str::to_string) else {
+                                                continue;
+                                            };
+                                            let change = call.heard(&user_id, Instant::now());
+                                            (user_id, change)
+                                        };
+                                        notify(change.into_iter().chain(link.heard()).collect());
+                                        if deafened.load(Ordering::SeqCst) {
+                                            continue;
+                                        }
+                                        let mut samples = Vec::with_capacity(data.len() / 4);
+                                        for chunk in data.chunks_exact(4) {
+                                            let val = f32::from_ne_bytes(chunk.try_into().unwrap());
+                                            samples.push(val);
+                                        }
+                                        let target = samples_for(jitter_ms.load(Ordering::Relaxed), out_rate, out_channels);
+                                        jitter
+                                            .lock()
+                                            .unwrap()
+                                            .entry(user_id)
+                                            .or_default()
+                                            .push(&samples, target);
+                                    }
+                                    None => {}
+                                },
                                 Err(_) => {
                                     // Ignore errors to keep loop alive
                                 }
                             }
                         }
+
+                        // Nothing to send while silent; wake up to check for a
+                        // stop, time out speakers, greet peers and watch for
+                        // the network changing.
+                        _ = tick.tick() => {
+                            // The voice device went away: carry on on the
+                            // default. Cues on another device don't notice.
+                            if output_lost.swap(false, Ordering::SeqCst) {
+                                tracing::warn!("Voice output device is gone, using the default");
+                                let reopened = output_device(&host, None)
+                                    .ok_or_else(|| anyhow!("No output device available"))
+                                    .and_then(|device| output.open(&device, output_lost.clone()));
+                                match reopened {
+                                    Ok((stream, rate, channels)) => {
+                                        _output_stream = stream;
+                                        (out_rate, out_channels) = (rate, channels);
+                                    }
+                                    Err(e) => tracing::warn!("Failed to reopen voice output: {}", e),
+                                }
+                            }
+                            let now = Instant::now();
+                            let (changes, hello, peers) = {
+                                let mut call = call.lock().unwrap();
+                                let hello_due = last_hello
+                                    .is_none_or(|last| now.duration_since(last) >= HELLO_INTERVAL);
+                                let hello = hello_due
+                                    .then(|| (call.local_user.clone(), call.hello_addrs()));
+                                jitter
+                                    .lock()
+                                    .unwrap()
+                                    .retain(|user_id, _| call.peers.contains_key(user_id));
+                                (call.expire(now), hello, call.peers.len())
+                            };
+                            notify(changes);
+                            if let Some((local_user, addrs)) = hello {
+                                last_hello = Some(now);
+                                // Move to a new socket, then greet peers at
+                                // every address they announced so they learn
+                                // where we are now. The NAT maps the new port
+                                // afresh, so STUN is asked again before peers
+                                // hear of it: our candidates go out anew on
+                                // `Reconnecting`, and the reconnection is not
+                                // over until a peer answers on the new socket.
+                                let mut changes: Vec<VoiceEvent> =
+                                    link.check(lan_ip(), now).into_iter().collect();
+                                if link.rebind {
+                                    match rebind(&socket).await {
+                                        Ok(rebound) => {
+                                            socket = rebound;
+                                            *socket_slot.lock().unwrap() = socket.clone();
+                                            discover(&socket, &public).await;
+                                            changes.extend(link.rebound(peers));
+                                        }
+                                        Err(e) => tracing::warn!("Failed to rebind the voice socket: {}", e),
+                                    }
+                                } else if public.lock().unwrap().stale {
+                                    discover(&socket, &public).await;
+                                }
+                                notify(changes);
+                                let packet = Packet::Hello(&local_user).encode();
+                                for addr in addrs {
+                                    let sent = socket.send_to(&packet, addr).await;
+                                    link.sent(sent.is_ok(), now);
+                                }
+                            }
+                        }
                     }
                 }
             });
         });
-        Ok(())
+        *self.audio_thread.lock().unwrap() = Some(thread);
     }
 
+    /// Stop the audio loop and hang up on every peer.
     pub fn stop(&self) {
         self.is_recording.store(false, Ordering::SeqCst);
+        self.stop_mic_test();
+        let recording_clip = match self.clip.lock().unwrap().as_mut() {
+            Some(clip) if clip.from_call => {
+                clip.from_call = false;
+                true
+            }
+            _ => false,
+        };
+        if recording_clip {
+            self.spawn_clip_capture();
+        }
+        let sounds = std::mem::take(&mut *self.sounds.lock().unwrap());
+        let device = self.devices.lock().unwrap().output.clone();
+        for sound in sounds {
+            sound.play_on(device.clone());
+        }
+        let changes = {
+            let mut call = self.call.lock().unwrap();
+            let mut changes = call.set_peers(Vec::new());
+            let local_user = call.local_user.clone();
+            changes.extend(call.stop_speaking(&local_user));
+            changes
+        };
+        self.notify(changes);
+    }
+
+    /// Capture from the chosen input device only to measure its level, for
+    /// the mic meter in settings. Runs until `stop_mic_test`.
+    pub fn start_mic_test(&self) {
+        if self.mic_test.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let mic_test = self.mic_test.clone();
+        let input = self.input.clone();
+        let name = self.devices.lock().unwrap().input.clone();
+        std::thread::spawn(move || {
+            let host = cpal::default_host();
+            let Some(device) = input_device(&host, name.as_deref()) else {
+                tracing::error!("No input device available");
+                mic_test.store(false, Ordering::SeqCst);
+                return;
+            };
+            let stream = device
+                .default_input_config()
+                .map_err(anyhow::Error::from)
+                .and_then(|config| {
+                    let level = input.clone();
+                    let stream = device.build_input_stream(
+                        &config.into(),
+                        move |data: &[f32], _: &_| {
+                            level.capture(data);
+                        },
+                        |err| tracing::error!("Input stream error: {}", err),
+                        None,
+                    )?;
+                    stream.play()?;
+                    Ok(stream)
+                });
+            let _stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to start mic test: {}", e);
+                    mic_test.store(false, Ordering::SeqCst);
+                    return;
+                }
+            };
+            while mic_test.load(Ordering::SeqCst) {
+                std::thread::sleep(LOOP_CHECK_INTERVAL);
+            }
+            input.level.store(0.0);
+        });
+    }
+
+    pub fn stop_mic_test(&self) {
+        self.mic_test.store(false, Ordering::SeqCst);
+    }
+
+    /// Start recording a voice clip of up to `max` from the chosen input
+    /// device. During a call it is taken from the call's capture, which
+    /// goes on being sent as usual.
+    pub fn start_clip(&self, max: Duration) -> Result<()> {
+        let from_call = {
+            let mut clip = self.clip.lock().unwrap();
+            if clip.is_some() {
+                bail!("A voice clip is already being recorded");
+            }
+            let from_call = self.is_active();
+            *clip = Some(Clip {
+                writer: ClipWriter::start(max)?,
+                from_call,
+            });
+            from_call
+        };
+        if !from_call {
+            self.spawn_clip_capture();
+        }
+        Ok(())
+    }
+
+    /// Capture for the clip being recorded from a stream of its own, until
+    /// the clip ends or a call takes over feeding it.
+    fn spawn_clip_capture(&self) {
+        let clip = self.clip.clone();
+        let input = self.input.clone();
+        let name = self.devices.lock().unwrap().input.clone();
+        let capturing = {
+            let clip = clip.clone();
+            move || matches!(&*clip.lock().unwrap(), Some(clip) if !clip.from_call)
+        };
+        std::thread::spawn(move || {
+            let host = cpal::default_host();
+            let stream = input_device(&host, name.as_deref())
+                .ok_or_else(|| anyhow!("No input device available"))
+                .and_then(|device| {
+                    let config: cpal::StreamConfig = device.default_input_config()?.into();
+                    let (rate, channels) = (config.sample_rate.0, config.channels);
+                    let clip = clip.clone();
+                    let stream = device.build_input_stream(
+                        &config,
+                        move |data: &[f32], _: &_| {
+                            record_clip(&clip, &input, false, data, rate, channels);
+                        },
+                        |err| tracing::error!("Input stream error: {}", err),
+                        None,
+                    )?;
+                    stream.play()?;
+                    Ok(stream)
+                });
+            let _stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // Dropped, so the composer sees the recording end.
+                    tracing::warn!("Failed to record a voice clip: {}", e);
+                    if let Some(clip) = clip.lock().unwrap().take() {
+                        clip.writer.discard();
+                    }
+                    return;
+                }
+            };
+            while capturing() {
+                std::thread::sleep(LOOP_CHECK_INTERVAL);
+            }
+        });
+    }
+
+    /// The clip being recorded, `None` if there is none or it failed.
+    pub fn clip_status(&self) -> Option<ClipStatus> {
+        self.clip.lock().unwrap().as_ref().map(|clip| ClipStatus {
+            elapsed: clip.writer.elapsed(),
+            level: clip.writer.level(),
+            full: clip.writer.is_full(),
+        })
+    }
+
+    /// Stop recording and keep the clip, to play back or send.
+    pub fn finish_clip(&self) -> Result<RecordedClip> {
+        let clip = self.clip.lock().unwrap().take();
+        clip.context("No voice clip is being recorded")?
+            .writer
+            .finish()
+    }
+
+    /// Stop recording and throw the clip away.
+    pub fn discard_clip(&self) {
+        if let Some(clip) = self.clip.lock().unwrap().take() {
+            clip.writer.discard();
+        }
+    }
+
+    /// Play `clip` back on the chosen output device, in place of any clip
+    /// playing, until it ends or `stop_clip_preview`.
+    pub fn play_clip(&self, clip: &RecordedClip) -> Result<()> {
+        let (rate, samples) = voice_clip::read(&clip.path)?;
+        let generation = self.preview_generation.clone();
+        let mine = generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let length = clip.duration;
+        let name = self.devices.lock().unwrap().output.clone();
+        play_wave(
+            name,
+            move |t| {
+                let at = (t * rate as f32) as usize;
+                samples.get(at).copied().unwrap_or(0.0)
+            },
+            move |played| played < length && generation.load(Ordering::SeqCst) == mine,
+        );
+        Ok(())
+    }
+
+    pub fn stop_clip_preview(&self) {
+        self.preview_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Play a short tone on the chosen output device.
+    pub fn play_test_sound(&self) {
+        let name = self.devices.lock().unwrap().output.clone();
+        play_wave(name, test_tone, |played| played < TEST_TONE_DURATION);
+    }
+
+    /// Play the short chime for a notification keyword.
+    pub fn play_chime(&self) {
+        self.play(&Tone::Chime.sound());
+    }
+
+    /// Play `sound` where cues go, e.g. a room's notification sound.
+    pub fn play(&self, sound: &Sound) {
+        let sound = sound.clone();
+        let length = sound.duration();
+        self.play_sound(move |t| sound.sample(t), move |played| played < length);
+    }
+
+    /// Play `cue` where cues go, in place of any cue playing, until
+    /// `stop_cue`.
+    pub fn play_cue(&self, cue: Cue) {
+        let generation = self.cue_generation.clone();
+        let mine = generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.play_sound(
+            move |t| cue.sample(t),
+            move |_| generation.load(Ordering::SeqCst) == mine,
+        );
+    }
+
+    /// Play a sound on the cue device. During a call on that same device it
+    /// is mixed into the call's stream rather than opening a second one.
+    fn play_sound(
+        &self,
+        wave: impl Fn(f32) -> f32 + Send + Sync + 'static,
+        playing: impl Fn(Duration) -> bool + Send + 'static,
+    ) {
+        let devices = self.devices.lock().unwrap().clone();
+        let Some(device) = devices.cue_device() else {
+            return;
+        };
+        if self.is_active() && device == devices.output {
+            self.sounds.lock().unwrap().push(MixedSound {
+                wave: Box::new(wave),
+                playing: Box::new(playing),
+                played: 0.0,
+            });
+        } else {
+            play_wave(device, wave, playing);
+        }
+    }
+
+    pub fn stop_cue(&self) {
+        self.cue_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Whether the audio loop is running.
+    pub fn is_active(&self) -> bool {
+        self.is_recording.load(Ordering::SeqCst)
+    }
+
+    /// Whether the audio thread has ended, as it does a moment after
+    /// `stop`; true if there never was one.
+    pub fn is_stopped(&self) -> bool {
+        self.audio_thread
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::SeqCst);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::SeqCst)
+    }
+
+    pub fn set_deafened(&self, deafened: bool) {
+        self.deafened.store(deafened, Ordering::SeqCst);
+    }
+
+    pub fn is_deafened(&self) -> bool {
+        self.deafened.load(Ordering::SeqCst)
     }
 
     pub fn get_input_devices() -> Vec<String> {
         let host = cpal::default_host();
         match host.input_devices() {
             Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
-            Err(_) => vec!["Default Input".to_string()],
+            Err(_) => Vec::new(),
         }
     }
 
@@ -181,7 +1636,349 @@ impl VoiceManager {
         let host = cpal::default_host();
         match host.output_devices() {
             Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
-            Err(_) => vec!["Default Output".to_string()],
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Play `wave`, the sample at each time in seconds, on output device
+/// `name` from a thread of its own, while `playing` holds for the time
+/// played so far. If the device goes away partway through, the rest plays
+/// on the default.
+fn play_wave(
+    name: Option<String>,
+    wave: impl Fn(f32) -> f32 + Send + Sync + 'static,
+    playing: impl Fn(Duration) -> bool + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        let host = cpal::default_host();
+        let wave = Arc::new(wave);
+        let started = Instant::now();
+        let mut name = name;
+        loop {
+            let lost = Arc::new(AtomicBool::new(false));
+            let from = started.elapsed().as_secs_f32();
+            let _stream = match open_wave(&host, name.as_deref(), wave.clone(), from, lost.clone())
+            {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to play sound: {}", e);
+                    return;
+                }
+            };
+            while playing(started.elapsed()) && !lost.load(Ordering::SeqCst) {
+                std::thread::sleep(LOOP_CHECK_INTERVAL);
+            }
+            if !lost.load(Ordering::SeqCst) || name.is_none() {
+                return;
+            }
+            tracing::warn!("Output device is gone, finishing the sound on the default");
+            name = None;
         }
+    });
+}
+
+/// Open a stream on output device `name` playing `wave` from `from`
+/// seconds in. `lost` is set if the device goes away.
+fn open_wave(
+    host: &cpal::Host,
+    name: Option<&str>,
+    wave: Arc<impl Fn(f32) -> f32 + Send + Sync + 'static>,
+    from: f32,
+    lost: Arc<AtomicBool>,
+) -> Result<cpal::Stream> {
+    let device = output_device(host, name).ok_or_else(|| anyhow!("No output device available"))?;
+    let config: cpal::StreamConfig = device.default_output_config()?.into();
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let mut frame = (from * sample_rate) as u64;
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _: &_| {
+            for out in data.chunks_mut(channels) {
+                out.fill(wave(frame as f32 / sample_rate));
+                frame += 1;
+            }
+        },
+        output_error(lost),
+        None,
+    )?;
+    stream.play()?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ringtone_rings_then_pauses() {
+        let ringing = (0..100).map(|i| Cue::Ringtone.sample(i as f32 / 8000.0));
+        assert!(ringing.clone().any(|s| s != 0.0));
+        assert!(ringing.map(f32::abs).all(|s| s <= RING_VOLUME));
+        assert_eq!(Cue::Ringtone.sample(RING_ON_SECS + 0.1), 0.0);
+        assert!(Cue::Ringtone.sample(RING_PERIOD_SECS + 0.01) != 0.0);
+    }
+
+    #[test]
+    fn test_process_input_applies_gain_and_gate() {
+        let mut samples = vec![0.25, -0.25, 0.75];
+        let level = process_input(&mut samples, 2.0, false);
+        assert_eq!(samples, vec![0.5, -0.5, 1.0]);
+        assert!((level - 0.5_f32.sqrt()).abs() < 1e-6);
+
+        let mut hiss = vec![0.005, -0.004];
+        assert!(process_input(&mut hiss.clone(), 1.0, false) > 0.0);
+        assert_eq!(process_input(&mut hiss, 1.0, true), 0.0);
+        assert_eq!(hiss, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_packets_round_trip() {
+        let hello = Packet::Hello("@alice:example.org").encode();
+        assert_eq!(hello[0], PACKET_HELLO);
+        assert_eq!(
+            Packet::decode(&hello),
+            Some(Packet::Hello("@alice:example.org"))
+        );
+        let audio = Packet::Audio(&[1, 2, 3, 4]).encode();
+        assert_eq!(Packet::decode(&audio), Some(Packet::Audio(&[1, 2, 3, 4])));
+        let format = Packet::Format(40).encode();
+        assert_eq!(format[0], PACKET_FORMAT);
+        assert_eq!(Packet::decode(&format), Some(Packet::Format(40)));
+        assert_eq!(Packet::decode(&format[..3]), None);
+        assert_eq!(Packet::decode(&[]), None);
+        assert_eq!(Packet::decode(&[9, 1]), None);
+    }
+
+    #[test]
+    fn test_framer_cuts_fixed_frames_and_flushes_on_resize() {
+        // 10 ms of 48 kHz stereo.
+        assert_eq!(samples_for(10, 48_000, 2), 960);
+        assert_eq!(ms_for(960, 48_000, 2), 10.0);
+
+        let mut framer = Framer::default();
+        assert_eq!(framer.resize(4), None);
+        assert!(framer.push(&[0.1, 0.2, 0.3]).is_empty());
+        let frames = framer.push(&[0.4, 0.5, 0.6, 0.7, 0.8, 0.9]);
+        assert_eq!(
+            frames,
+            vec![vec![0.1, 0.2, 0.3, 0.4], vec![0.5, 0.6, 0.7, 0.8]]
+        );
+
+        // The frame in progress goes out whole at the old size.
+        assert_eq!(framer.resize(2), Some(vec![0.9, 0.0, 0.0, 0.0]));
+        assert_eq!(framer.push(&[1.0, 1.0, 1.0]), vec![vec![1.0, 1.0]]);
+        framer.clear();
+        assert_eq!(framer.push(&[0.5]), Vec::<Vec<f32>>::new());
+    }
+
+    #[test]
+    fn test_jitter_buffer_waits_for_target_and_bounds_delay() {
+        let mut buffer = JitterBuffer::default();
+        let mut out = [0.0; 2];
+        buffer.push(&[0.5, 0.5], 4);
+        buffer.mix_into(&mut out, 4);
+        assert_eq!(out, [0.0, 0.0]);
+
+        buffer.push(&[0.25, 0.25], 4);
+        buffer.mix_into(&mut out, 4);
+        assert_eq!(out, [0.5, 0.5]);
+        // Playing on once started, adding to what is already there.
+        buffer.mix_into(&mut out, 4);
+        assert_eq!(out, [0.75, 0.75]);
+
+        // Running dry waits for the target again.
+        let mut out = [0.0; 3];
+        buffer.push(&[0.1], 4);
+        buffer.mix_into(&mut out, 4);
+        assert_eq!(out, [0.1, 0.0, 0.0]);
+        buffer.push(&[0.1], 4);
+        buffer.mix_into(&mut out, 4);
+        assert_eq!(out, [0.1, 0.0, 0.0]);
+
+        // Far behind, the oldest audio is dropped down to the target.
+        buffer.push(&[0.0; 20], 4);
+        assert_eq!(buffer.samples.len(), 4);
+
+        // A new frame size waits for the target but keeps what is queued.
+        buffer.playing = true;
+        buffer.reprime();
+        buffer.push(&[0.0; 2], 8);
+        buffer.mix_into(&mut out, 8);
+        assert_eq!(buffer.samples.len(), 6);
+    }
+
+    #[test]
+    fn test_latency_estimate_adds_up() {
+        let latency = LatencyEstimate {
+            capture_ms: 10.0,
+            frame_ms: 20.0,
+            jitter_ms: 60.0,
+            playback_ms: 5.3,
+        };
+        assert!((latency.total_ms() - 95.3).abs() < 1e-4);
+        assert_eq!(
+            latency.to_string(),
+            "~95 ms: capture 10 + frame 20 + jitter 60 + playback 5"
+        );
+        assert_eq!(frame_size(40), 40);
+        assert_eq!(frame_size(30), 20);
+    }
+
+    #[test]
+    fn test_speaking_follows_audio_and_membership() {
+        let bob: SocketAddr = "192.0.2.2:4000".parse().unwrap();
+        let mut call = Call {
+            local_user: "@alice:example.org".to_string(),
+            ..Default::default()
+        };
+        call.set_peers(vec![
+            (
+                "@alice:example.org".to_string(),
+                vec!["192.0.2.1:4000".parse().unwrap()],
+            ),
+            ("@bob:example.org".to_string(), vec![bob]),
+        ]);
+        assert_eq!(call.peers.len(), 1);
+        assert_eq!(call.peer_at(bob), None);
+        call.peers.get_mut("@bob:example.org").unwrap().addr = Some(bob);
+        assert_eq!(call.peer_at(bob), Some("@bob:example.org"));
+
+        let start = Instant::now();
+        let started = VoiceEvent::Speaking {
+            user_id: "@bob:example.org".to_string(),
+            speaking: true,
+        };
+        assert_eq!(call.heard("@bob:example.org", start), Some(started));
+        assert_eq!(call.heard("@bob:example.org", start), None);
+        assert!(call.heard("@carol:example.org", start).is_none());
+        assert!(call.heard("@alice:example.org", start).is_some());
+        assert!(call.expire(start + SPEAKING_HOLD / 2).is_empty());
+        assert_eq!(call.expire(start + SPEAKING_HOLD).len(), 2);
+
+        // Leaving stops them speaking; a confirmed address survives a refresh.
+        call.heard("@bob:example.org", start);
+        call.set_peers(vec![("@bob:example.org".to_string(), vec![bob])]);
+        assert_eq!(call.peer_at(bob), Some("@bob:example.org"));
+        let stopped = call.set_peers(Vec::new());
+        assert_eq!(
+            stopped,
+            vec![VoiceEvent::Speaking {
+                user_id: "@bob:example.org".to_string(),
+                speaking: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mixed_sound_adds_to_each_channel_until_over() {
+        let mut sound = MixedSound {
+            wave: Box::new(|t| if t < 1.0 { 0.5 } else { 0.25 }),
+            playing: Box::new(|played| played < Duration::from_secs(2)),
+            played: 0.0,
+        };
+        let mut out = [0.25; 4];
+        assert!(sound.mix_into(&mut out, 1, 2));
+        assert_eq!(out, [0.75, 0.75, 0.5, 0.5]);
+        assert!(!sound.mix_into(&mut out, 1, 2));
+    }
+
+    #[test]
+    fn test_cues_follow_voice_unless_routed_elsewhere() {
+        let mut devices = Devices {
+            output: Some("Headset".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(devices.cue_device(), Some(Some("Headset".to_string())));
+        devices.cue = CueOutput::Device(None);
+        assert_eq!(devices.cue_device(), Some(None));
+        devices.cue = CueOutput::Off;
+        assert_eq!(devices.cue_device(), None);
+    }
+
+    #[test]
+    fn test_link_reconnects_on_address_change_or_failing_sends() {
+        let wifi: IpAddr = "192.168.1.20".parse().unwrap();
+        let ethernet: IpAddr = "192.168.1.30".parse().unwrap();
+        let start = Instant::now();
+        let mut link = Link::new(Some(wifi));
+        assert_eq!(link.check(Some(wifi), start), None);
+
+        assert_eq!(
+            link.check(Some(ethernet), start),
+            Some(VoiceEvent::Reconnecting)
+        );
+        assert!(link.rebind);
+        // Still on the old socket, and a failed rebind is retried quietly.
+        assert_eq!(link.heard(), None);
+        assert_eq!(link.check(None, start), None);
+        assert!(link.rebind);
+        assert_eq!(link.rebound(2), None);
+        assert_eq!(link.heard(), Some(VoiceEvent::Reconnected));
+        assert_eq!(link.heard(), None);
+
+        // Sends failing for long enough count too; a success resets it.
+        link.sent(false, start);
+        assert_eq!(link.check(None, start + SEND_FAILURE_LIMIT / 2), None);
+        link.sent(true, start);
+        link.sent(false, start + SEND_FAILURE_LIMIT);
+        assert_eq!(link.check(None, start + SEND_FAILURE_LIMIT * 3 / 2), None);
+        assert_eq!(
+            link.check(None, start + SEND_FAILURE_LIMIT * 2),
+            Some(VoiceEvent::Reconnecting)
+        );
+        // Alone in the call there is nobody to wait for.
+        assert_eq!(link.rebound(0), Some(VoiceEvent::Reconnected));
+    }
+
+    /// A STUN server on loopback that sees every client at `ip`, on the
+    /// client's own port as a full-cone NAT would.
+    async fn stun_server(ip: Ipv4Addr) -> SocketAddr {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let cookie: u32 = 0x2112_A442;
+            let mut buf = [0; 512];
+            while let Ok((len, from)) = server.recv_from(&mut buf).await {
+                let mut answer = buf[..len].to_vec();
+                answer[0..2].copy_from_slice(&0x0101u16.to_be_bytes());
+                answer[2..4].copy_from_slice(&12u16.to_be_bytes());
+                answer.extend([0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+                answer.extend((from.port() ^ (cookie >> 16) as u16).to_be_bytes());
+                answer.extend((u32::from(ip) ^ cookie).to_be_bytes());
+                server.send_to(&answer, from).await.ok();
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_public_address_is_found_again_after_rebinding() {
+        let ip = Ipv4Addr::new(203, 0, 113, 7);
+        let public = Mutex::new(PublicAddress {
+            servers: vec![stun_server(ip).await],
+            stale: true,
+            ..Default::default()
+        });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        discover(&socket, &public).await;
+        let port = socket.local_addr().unwrap().port();
+        assert_eq!(public.lock().unwrap().seen, Some((ip, port).into()));
+        assert!(!public.lock().unwrap().stale);
+
+        let socket = rebind(&socket).await.unwrap();
+        discover(&socket, &public).await;
+        let port = socket.local_addr().unwrap().port();
+        assert_eq!(public.lock().unwrap().seen, Some((ip, port).into()));
+    }
+
+    #[test]
+    fn test_transmit_decision() {
+        assert!(transmits(0.1, 0.05, None));
+        assert!(!transmits(0.01, 0.05, None));
+        assert!(!transmits(0.0, 0.0, None));
+        assert!(transmits(0.0, 0.05, Some(true)));
+        assert!(!transmits(0.5, 0.05, Some(false)));
     }
 }
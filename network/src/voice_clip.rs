@@ -0,0 +1,276 @@
+//! Voice clips recorded from the composer. What the microphone captures is
+//! mixed down to mono and spooled to a 16-bit WAV file in the clips
+//! directory as it comes, so a long clip doesn't sit in memory. The file
+//! goes once the clip is sent or discarded; any a crash left behind are
+//! swept on the next start.
+
+use crate::session::app_dir;
+use anyhow::{bail, Context, Result};
+use chat_core::time;
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longest clip recorded unless the settings say otherwise, in seconds.
+pub const DEFAULT_MAX_SECS: u32 = 300;
+/// Name a clip is sent under.
+pub const FILE_NAME: &str = "Voice message.wav";
+pub const MIMETYPE: &str = "audio/wav";
+
+const HEADER_LEN: usize = 44;
+
+/// Where clips are spooled while recorded and until sent.
+fn dir() -> Result<PathBuf> {
+    let dir = app_dir()?.join("clips");
+    fs::create_dir_all(&dir).context("Failed to create the clips directory")?;
+    Ok(dir)
+}
+
+/// Remove the clips a crash left behind. Call at startup, before any is
+/// recorded.
+pub fn sweep() {
+    let Ok(entries) = dir().and_then(|dir| Ok(fs::read_dir(dir)?)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        remove(&entry.path());
+    }
+}
+
+/// The header of a mono 16-bit WAV file of `data_len` bytes at `rate`.
+pub fn wav_header(rate: u32, data_len: u32) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    let fields: [&[u8]; 13] = [
+        b"RIFF",
+        &(36 + data_len).to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        &16u32.to_le_bytes(),
+        // PCM, one channel.
+        &1u16.to_le_bytes(),
+        &1u16.to_le_bytes(),
+        &rate.to_le_bytes(),
+        &(rate * 2).to_le_bytes(),
+        &2u16.to_le_bytes(),
+        &16u16.to_le_bytes(),
+        b"data",
+        &data_len.to_le_bytes(),
+    ];
+    let mut at = 0;
+    for field in fields {
+        header[at..at + field.len()].copy_from_slice(field);
+        at += field.len();
+    }
+    header
+}
+
+/// `data`, interleaved over `channels`, as one channel: the average of
+/// each frame.
+pub fn downmix(data: &[f32], channels: u16) -> impl Iterator<Item = f32> + '_ {
+    let channels = usize::from(channels.max(1));
+    data.chunks(channels)
+        .map(move |frame| frame.iter().sum::<f32>() / channels as f32)
+}
+
+fn to_pcm16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+}
+
+/// The sample rate and samples of a clip written by `ClipWriter`, for
+/// playing it back.
+pub fn read(path: &Path) -> Result<(u32, Vec<f32>)> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if data.len() < HEADER_LEN || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        bail!("{} is not a voice clip", path.display());
+    }
+    let rate = u32::from_le_bytes([data[24], data[25], data[26], data[27]]);
+    let samples = data[HEADER_LEN..]
+        .chunks_exact(2)
+        .map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / f32::from(i16::MAX))
+        .collect();
+    Ok((rate, samples))
+}
+
+/// A clip done recording, waiting to be sent or discarded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedClip {
+    pub path: PathBuf,
+    pub duration: Duration,
+}
+
+impl RecordedClip {
+    /// Remove the clip's file, once sent or no longer wanted.
+    pub fn remove(&self) {
+        remove(&self.path);
+    }
+}
+
+fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        tracing::warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+/// Spools a clip being recorded to its file. The sample rate is taken from
+/// the first buffer; the header is written with the sizes at `finish`.
+pub struct ClipWriter {
+    out: BufWriter<File>,
+    path: PathBuf,
+    max: Duration,
+    rate: u32,
+    /// Samples written, one per frame.
+    written: u64,
+    /// RMS level of the latest buffer, 0.0–1.0.
+    level: f32,
+    failed: bool,
+}
+
+impl ClipWriter {
+    /// Start a clip at `path` that takes no more than `max`.
+    pub fn create(path: &Path, max: Duration) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(&wav_header(0, 0))?;
+        Ok(Self {
+            out,
+            path: path.to_path_buf(),
+            max,
+            rate: 0,
+            written: 0,
+            level: 0.0,
+            failed: false,
+        })
+    }
+
+    /// Start a clip in the clips directory.
+    pub fn start(max: Duration) -> Result<Self> {
+        let path = dir()?.join(format!("clip-{}.wav", time::now_ms()));
+        Self::create(&path, max)
+    }
+
+    /// Append a captured buffer, `rate` frames a second over `channels`.
+    /// What goes past the longest clip allowed is dropped.
+    pub fn push(&mut self, data: &[f32], rate: u32, channels: u16) {
+        if self.rate == 0 {
+            self.rate = rate;
+        }
+        let mono: Vec<f32> = downmix(data, channels).collect();
+        self.level = if mono.is_empty() {
+            0.0
+        } else {
+            (mono.iter().map(|s| s * s).sum::<f32>() / mono.len() as f32).sqrt()
+        };
+        let max = (self.max.as_secs_f64() * f64::from(self.rate)) as u64;
+        let room = max.saturating_sub(self.written);
+        let take = mono.len().min(usize::try_from(room).unwrap_or(usize::MAX));
+        if take == 0 || self.failed {
+            return;
+        }
+        let bytes: Vec<u8> = mono[..take]
+            .iter()
+            .flat_map(|s| to_pcm16(*s).to_le_bytes())
+            .collect();
+        if let Err(e) = self.out.write_all(&bytes) {
+            tracing::warn!("Failed to write the voice clip: {}", e);
+            self.failed = true;
+            return;
+        }
+        self.written += take as u64;
+    }
+
+    /// How long the clip is so far.
+    pub fn elapsed(&self) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.written as f64 / f64::from(self.rate))
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Whether the clip reached the longest allowed.
+    pub fn is_full(&self) -> bool {
+        self.elapsed() >= self.max
+    }
+
+    /// End the clip, writing its header.
+    pub fn finish(mut self) -> Result<RecordedClip> {
+        let clip = RecordedClip {
+            path: self.path.clone(),
+            duration: self.elapsed(),
+        };
+        if let Err(e) = self.write_header() {
+            clip.remove();
+            return Err(e);
+        }
+        Ok(clip)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if self.failed {
+            bail!("Failed to write the voice clip");
+        }
+        let data_len = u32::try_from(self.written * 2).context("The voice clip is too long")?;
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&wav_header(self.rate, data_len))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// End the clip and remove its file.
+    pub fn discard(self) {
+        let path = self.path.clone();
+        drop(self);
+        remove(&path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix_averages_each_frame() {
+        let mono: Vec<f32> = downmix(&[0.5, 0.25, -1.0, 1.0], 2).collect();
+        assert_eq!(mono, vec![0.375, 0.0]);
+        let same: Vec<f32> = downmix(&[0.5, -0.5], 1).collect();
+        assert_eq!(same, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_clip_is_written_capped_and_read_back() {
+        let path = std::env::temp_dir().join(format!("clip-{}.wav", time::now_ms()));
+        let mut writer = ClipWriter::create(&path, Duration::from_millis(500)).unwrap();
+        // Two channels at 8 frames a second: each buffer is half a second.
+        writer.push(&[0.5; 8], 8, 2);
+        assert_eq!(writer.elapsed(), Duration::from_millis(500));
+        assert!(writer.is_full());
+        writer.push(&[0.25; 8], 8, 2);
+        assert_eq!(writer.elapsed(), Duration::from_millis(500));
+
+        let clip = writer.finish().unwrap();
+        assert_eq!(clip.duration, Duration::from_millis(500));
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data[..HEADER_LEN], wav_header(8, 8));
+        let (rate, samples) = read(&path).unwrap();
+        assert_eq!(rate, 8);
+        assert_eq!(samples.len(), 4);
+        assert!(samples.iter().all(|s| (s - 0.5).abs() < 0.001));
+
+        clip.remove();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_discarded_clip_leaves_nothing() {
+        let path = std::env::temp_dir().join(format!("discard-{}.wav", time::now_ms()));
+        let mut writer = ClipWriter::create(&path, Duration::from_secs(1)).unwrap();
+        writer.push(&[0.1; 4], 8, 1);
+        writer.discard();
+        assert!(!path.exists());
+    }
+}
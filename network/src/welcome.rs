@@ -0,0 +1,182 @@
+//! A room's welcome message: its rules or a greeting, set in the room's
+//! state by those allowed to and shown to each member the first time they
+//! open the room. A room may ask that it be accepted before anything is
+//! sent there. Which welcomes the user dismissed or accepted is kept in
+//! our own account data, so it follows the account to other devices. A
+//! changed welcome is a new one, shown to everyone again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Account data event type holding the welcomes dismissed or accepted.
+pub const WELCOME_SEEN_EVENT_TYPE: &str = "io.gamechat.welcome_seen";
+
+/// A room's welcome, as set in its state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Welcome {
+    /// The state event that set it.
+    pub event_id: String,
+    pub body: String,
+    /// The body as HTML, when set by a client that formats it.
+    pub formatted: Option<String>,
+    /// Nothing is sent in the room until it is accepted.
+    pub must_accept: bool,
+}
+
+/// What the user did with a room's welcome.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Seen {
+    /// Not shown yet, or changed since.
+    #[default]
+    New,
+    /// Closed without accepting it.
+    Dismissed,
+    Accepted,
+}
+
+impl Welcome {
+    /// Whether there's anything to show; an empty welcome is none.
+    pub fn is_empty(&self) -> bool {
+        self.body.trim().is_empty()
+    }
+
+    /// Whether to show it as the room is opened.
+    pub fn shows(&self, seen: Seen) -> bool {
+        !self.is_empty() && seen == Seen::New
+    }
+
+    /// Whether the composer waits for it to be accepted.
+    pub fn holds_composer(&self, seen: Seen) -> bool {
+        !self.is_empty() && self.must_accept && seen != Seen::Accepted
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct SeenWelcome {
+    event_id: String,
+    #[serde(default)]
+    accepted: bool,
+}
+
+/// Content of the `io.gamechat.welcome_seen` account data event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct WelcomeSeenContent {
+    /// By room id, the last welcome dismissed or accepted there.
+    #[serde(default)]
+    rooms: BTreeMap<String, SeenWelcome>,
+}
+
+impl WelcomeSeenContent {
+    /// What the user did with welcome `event_id` of `room_id`.
+    pub fn seen(&self, room_id: &str, event_id: &str) -> Seen {
+        match self.rooms.get(room_id) {
+            Some(seen) if seen.event_id == event_id && seen.accepted => Seen::Accepted,
+            Some(seen) if seen.event_id == event_id => Seen::Dismissed,
+            _ => Seen::New,
+        }
+    }
+
+    /// Note what the user did with welcome `event_id` of `room_id`. Once
+    /// accepted it stays so, though dismissed again.
+    pub fn mark(&mut self, room_id: &str, event_id: &str, seen: Seen) {
+        let accepted = match (seen, self.seen(room_id, event_id)) {
+            (Seen::New, _) => {
+                self.rooms.remove(room_id);
+                return;
+            }
+            (Seen::Accepted, _) | (_, Seen::Accepted) => true,
+            (Seen::Dismissed, _) => false,
+        };
+        let event_id = event_id.to_string();
+        self.rooms
+            .insert(room_id.to_string(), SeenWelcome { event_id, accepted });
+    }
+
+    /// Forget the rooms not in `joined`, so the event doesn't grow forever.
+    pub fn retain_rooms(&mut self, joined: &[String]) {
+        self.rooms.retain(|room_id, _| joined.contains(room_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn welcome(must_accept: bool) -> Welcome {
+        Welcome {
+            event_id: "$rules".to_string(),
+            body: "Be nice.".to_string(),
+            formatted: None,
+            must_accept,
+        }
+    }
+
+    #[test]
+    fn test_welcome_shows_until_dismissed() {
+        let welcome = welcome(false);
+        assert!(welcome.shows(Seen::New));
+        assert!(!welcome.shows(Seen::Dismissed));
+        assert!(!welcome.shows(Seen::Accepted));
+        let empty = Welcome {
+            body: " \n".to_string(),
+            ..welcome.clone()
+        };
+        assert!(!empty.shows(Seen::New));
+    }
+
+    #[test]
+    fn test_composer_waits_for_acceptance() {
+        let rules = welcome(true);
+        assert!(rules.holds_composer(Seen::New));
+        assert!(rules.holds_composer(Seen::Dismissed));
+        assert!(!rules.holds_composer(Seen::Accepted));
+        assert!(!welcome(false).holds_composer(Seen::New));
+        let empty = Welcome {
+            body: String::new(),
+            ..rules
+        };
+        assert!(!empty.holds_composer(Seen::New));
+    }
+
+    #[test]
+    fn test_seen_welcomes_are_per_room_and_event() {
+        let mut seen = WelcomeSeenContent::default();
+        assert_eq!(seen.seen("!a:x", "$1"), Seen::New);
+        seen.mark("!a:x", "$1", Seen::Dismissed);
+        assert_eq!(seen.seen("!a:x", "$1"), Seen::Dismissed);
+        assert_eq!(seen.seen("!b:x", "$1"), Seen::New);
+        seen.mark("!a:x", "$1", Seen::Accepted);
+        seen.mark("!a:x", "$1", Seen::Dismissed);
+        assert_eq!(seen.seen("!a:x", "$1"), Seen::Accepted);
+        // The welcome changed.
+        assert_eq!(seen.seen("!a:x", "$2"), Seen::New);
+        seen.mark("!a:x", "$2", Seen::Dismissed);
+        assert_eq!(seen.seen("!a:x", "$2"), Seen::Dismissed);
+        seen.mark("!a:x", "$2", Seen::New);
+        assert_eq!(seen.seen("!a:x", "$2"), Seen::New);
+    }
+
+    #[test]
+    fn test_seen_welcomes_roam_as_account_data() {
+        let mut seen = WelcomeSeenContent::default();
+        seen.mark("!a:x", "$1", Seen::Accepted);
+        seen.mark("!b:x", "$2", Seen::Dismissed);
+        let stored = serde_json::to_value(&seen).unwrap();
+        assert_eq!(
+            stored,
+            json!({ "rooms": {
+                "!a:x": { "event_id": "$1", "accepted": true },
+                "!b:x": { "event_id": "$2", "accepted": false },
+            }})
+        );
+        let read: WelcomeSeenContent = serde_json::from_value(stored).unwrap();
+        assert_eq!(read, seen);
+        let empty: WelcomeSeenContent = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(empty, WelcomeSeenContent::default());
+
+        seen.retain_rooms(&["!b:x".to_string()]);
+        assert_eq!(seen.seen("!a:x", "$1"), Seen::New);
+        assert_eq!(seen.seen("!b:x", "$2"), Seen::Dismissed);
+    }
+}
@@ -1,3 +1,4 @@
 fn main() {
-    slint_build::compile("ui/app-window.slint").unwrap();
+    let config = slint_build::CompilerConfiguration::new().with_bundled_translations("lang");
+    slint_build::compile_with_config("ui/app-window.slint", config).unwrap();
 }
@@ -0,0 +1,207 @@
+# Text the interface puts together in code, in English. The markup's own
+# strings are in the .po files Slint bundles.
+
+## Typing
+
+typing-one = { $name } is typing…
+typing-two = { $first } and { $second } are typing…
+typing-many =
+    { $count ->
+        [one] { $count } person is typing…
+       *[other] { $count } people are typing…
+    }
+
+## The homeserver on the login screen
+
+latency-ms = { $ms } ms
+latency-s = { $seconds } s
+matrix-version = Matrix { $version }
+error-dns = No server found at this address
+error-tls = The server's secure connection failed
+error-timeout = The server took too long to answer
+error-not-matrix = This is not a Matrix server
+error-unreachable = Could not reach the server: { $reason }
+
+## Registering
+
+username-available = Username is available
+username-taken = This username is already taken
+username-invalid = This username is not allowed on this server
+
+## Read out by screen readers as messages arrive
+
+announce-text = { $sender }: { $body }
+announce-emote = { $sender } { $body }
+announce-image = { $sender } sent an image, { $name }
+announce-file = { $sender } sent a file, { $name }
+announce-missed-call = Missed call from { $sender }
+announce-game-invite = { $sender } invites you to play { $game }
+announce-reaction = { $sender } reacted with { $body }
+timeline-reaction = { $sender } reacted with { $key }
+
+## Quiet hours, beside the status
+
+quiet-until = Quiet hours until { $time }
+quiet-paused-until = Quiet hours paused until { $time }
+
+## Forwarding messages
+
+forward-title =
+    { $count ->
+        [one] Forward a message to…
+       *[other] Forward { $count } messages to…
+    }
+forwarded-from = Forwarded from { $sender }
+forwarded-to =
+    { $count ->
+        [one] Message forwarded to { $room }
+       *[other] { $count } messages forwarded to { $room }
+    }
+
+## Scheduled messages
+
+scheduled-sent-late = Scheduled for { $time }, sent late as GameChat was closed
+
+## A room's audit log
+
+audit-joined = { $actor } joined
+audit-left = { $actor } left
+audit-invited = { $actor } invited { $target }
+audit-kicked = { $actor } removed { $target }
+audit-banned = { $actor } banned { $target }
+audit-unbanned = { $actor } unbanned { $target }
+audit-knocked = { $actor } asked to join
+audit-membership = { $actor } changed the membership of { $target }
+audit-power-levels = { $actor } changed power levels
+audit-name = { $actor } renamed the room
+audit-topic = { $actor } changed the topic
+audit-avatar = { $actor } changed the room's avatar
+audit-server-acl = { $actor } changed the server ACL
+audit-reason = Reason: { $reason }
+audit-unset = (unset)
+
+## Spell check
+
+spelling-same-as-app = Same as the app
+spelling-using = Checking spelling in { $language }.
+spelling-missing = No { $language } dictionary was found. Put its .dic and .aff files in { $folder }, or pick a folder that has them.
+spelling-failed = The { $language } dictionary couldn't be loaded: { $error }
+
+## Slow mode
+
+slow-mode-seconds = { $seconds ->
+        [one] 1 second
+       *[other] { $seconds } seconds
+    }
+
+## Failures, with what went wrong
+
+failed-load-roles = Couldn't load roles: { $error }
+failed-create-channel = Couldn't create the channel: { $error }
+failed-remove-channel = Couldn't remove the channel: { $error }
+failed-create-role = Couldn't create the role: { $error }
+failed-change-role = Couldn't change the role: { $error }
+failed-send = Couldn't send that: { $error }
+failed-join-room = Couldn't join that room: { $error }
+failed-message-them = Couldn't message them: { $error }
+failed-change-topic = Couldn't change the topic: { $error }
+failed-invite = Couldn't invite them: { $error }
+failed-edit-message = Couldn't edit the message: { $error }
+failed-delete-message = Couldn't delete this message: { $error }
+failed-remove-reaction = Couldn't remove the reaction: { $error }
+failed-add-reaction = Couldn't add the reaction: { $error }
+failed-pin = Couldn't pin messages: { $error }
+failed-unpin = Couldn't unpin messages: { $error }
+failed-load-audit-log = Couldn't load the audit log: { $error }
+failed-send-report = Couldn't send the report: { $error }
+failed-ignore = Couldn't ignore them: { $error }
+failed-record-voice = Couldn't record a voice message: { $error }
+failed-play-voice = Couldn't play the voice message: { $error }
+failed-send-voice = Couldn't send the voice message: { $error }
+failed-measure-caches = Couldn't measure the caches: { $error }
+failed-clear-cache = Couldn't clear the cache: { $error }
+failed-clear-history = Couldn't clear local history: { $error }
+failed-export-settings = Couldn't export settings: { $error }
+failed-import-settings = Couldn't import settings: { $error }
+failed-purge = Couldn't remove the messages: { $error }
+failed-list-pushers = Couldn't list them: { $error }
+failed-remove-pusher = Couldn't remove it: { $error }
+failed-set-up-notifications = Couldn't set up notifications: { $error }
+failed-list-emails = Couldn't list them: { $error }
+failed-add-email = Couldn't add an email: { $error }
+failed-resend-email = Couldn't send the link again: { $error }
+failed-confirm-email = Couldn't add it: { $error }
+failed-remove-email = Couldn't remove it: { $error }
+failed-join = Couldn't join: { $error }
+failed-knock = Couldn't ask to join: { $error }
+failed-let-in = Couldn't let them in: { $error }
+failed-turn-away = Couldn't turn them away: { $error }
+failed-cancel-knock = Couldn't cancel the request: { $error }
+failed-load-stickers = Couldn't load stickers: { $error }
+failed-send-sticker = Couldn't send the sticker: { $error }
+failed-send-game-invite = Couldn't send the invite: { $error }
+failed-load-aliases = Couldn't load the aliases: { $error }
+failed-load-room-settings = Couldn't load the settings: { $error }
+failed-change-join-rule = Couldn't change who can join: { $error }
+failed-change-history-visibility = Couldn't change who can read the history: { $error }
+failed-change-guest-access = Couldn't change guest access: { $error }
+failed-enable-encryption = Couldn't enable encryption: { $error }
+failed-change-slow-mode = Couldn't change slow mode: { $error }
+failed-change-welcome = Couldn't change the welcome message: { $error }
+failed-add-alias = Couldn't add the alias: { $error }
+failed-remove-alias = Couldn't remove the alias: { $error }
+failed-change-main-alias = Couldn't change the main alias: { $error }
+failed-translate = Couldn't translate this: { $error }
+failed-forward = Couldn't forward messages: { $error }
+failed-run-checks = Couldn't run the checks: { $error }
+failed-sync-favorites = Couldn't sync your favorites: { $error }
+failed-load-context = Couldn't load that message: { $error }
+failed-trust-new-keys = Couldn't trust their new keys: { $error }
+failed-copy = Couldn't copy the message: { $error }
+failed-open-folder = Couldn't open the folder: { $error }
+failed-read-report = Couldn't read the report: { $error }
+failed-microphone = Couldn't record from the microphone
+
+## Done, in a toast
+
+command-needs-room = Open a room first
+topic-changed = Topic changed
+invited = Invited { $user }
+cache-cleared = Cache cleared
+history-cleared = Local history cleared
+report-sent = Report sent to the server's admins
+ignored = Ignored { $user }
+settings-exported = Settings exported
+settings-unchanged = These settings are already in use
+settings-imported = Settings imported
+delete-not-allowed = You don't have permission to delete this message
+hold-to-record = Hold the button to record
+
+## In encrypted rooms
+
+security-changed = { $name }'s security changed
+
+## A run of membership changes, folded into one row
+
+membership-joined =
+    { $count ->
+        [one] { $count } person joined
+       *[other] { $count } people joined
+    }
+membership-left =
+    { $count ->
+        [one] { $count } person left
+       *[other] { $count } people left
+    }
+membership-invited =
+    { $count ->
+        [one] { $count } person was invited
+       *[other] { $count } people were invited
+    }
+membership-removed =
+    { $count ->
+        [one] { $count } person was removed
+       *[other] { $count } people were removed
+    }
+list-separator = {", "}
+list-and = { $first } and { $last }
@@ -0,0 +1,206 @@
+# Le texte que l'interface compose dans le code, en français.
+
+## Saisie
+
+typing-one = { $name } écrit…
+typing-two = { $first } et { $second } écrivent…
+typing-many =
+    { $count ->
+        [one] { $count } personne écrit…
+       *[other] { $count } personnes écrivent…
+    }
+
+## Le serveur d'accueil sur l'écran de connexion
+
+latency-ms = { $ms } ms
+latency-s = { $seconds } s
+matrix-version = Matrix { $version }
+error-dns = Aucun serveur trouvé à cette adresse
+error-tls = La connexion sécurisée au serveur a échoué
+error-timeout = Le serveur a mis trop de temps à répondre
+error-not-matrix = Ce n’est pas un serveur Matrix
+error-unreachable = Impossible de joindre le serveur : { $reason }
+
+## Inscription
+
+username-available = Ce nom d’utilisateur est disponible
+username-taken = Ce nom d’utilisateur est déjà pris
+username-invalid = Ce nom d’utilisateur n’est pas autorisé sur ce serveur
+
+## Lu par les lecteurs d'écran à l'arrivée des messages
+
+announce-text = { $sender } : { $body }
+announce-emote = { $sender } { $body }
+announce-image = { $sender } a envoyé une image, { $name }
+announce-file = { $sender } a envoyé un fichier, { $name }
+announce-missed-call = Appel manqué de { $sender }
+announce-game-invite = { $sender } vous invite à jouer à { $game }
+announce-reaction = { $sender } a réagi avec { $body }
+timeline-reaction = { $sender } a réagi avec { $key }
+
+## Heures calmes, à côté du statut
+
+quiet-until = Heures calmes jusqu’à { $time }
+quiet-paused-until = Heures calmes suspendues jusqu’à { $time }
+
+## Transfert de messages
+
+forward-title =
+    { $count ->
+        [one] Transférer un message vers…
+       *[other] Transférer { $count } messages vers…
+    }
+forwarded-from = Transféré de la part de { $sender }
+forwarded-to =
+    { $count ->
+        [one] Message transféré vers { $room }
+       *[other] { $count } messages transférés vers { $room }
+    }
+
+## Messages programmés
+
+scheduled-sent-late = Programmé pour { $time }, envoyé en retard car GameChat était fermé
+
+## Journal d’audit d’un salon
+
+audit-joined = { $actor } a rejoint le salon
+audit-left = { $actor } est parti
+audit-invited = { $actor } a invité { $target }
+audit-kicked = { $actor } a exclu { $target }
+audit-banned = { $actor } a banni { $target }
+audit-unbanned = { $actor } a débanni { $target }
+audit-knocked = { $actor } a demandé à rejoindre
+audit-membership = { $actor } a changé l’adhésion de { $target }
+audit-power-levels = { $actor } a changé les niveaux de pouvoir
+audit-name = { $actor } a renommé le salon
+audit-topic = { $actor } a changé le sujet
+audit-avatar = { $actor } a changé l’avatar du salon
+audit-server-acl = { $actor } a changé l’ACL des serveurs
+audit-reason = Raison : { $reason }
+audit-unset = (aucun)
+
+## Spell check
+
+spelling-same-as-app = Comme l’application
+spelling-using = Orthographe vérifiée en { $language }.
+spelling-missing = Aucun dictionnaire { $language } trouvé. Placez ses fichiers .dic et .aff dans { $folder }, ou choisissez un dossier qui les contient.
+spelling-failed = Le dictionnaire { $language } n’a pas pu être chargé : { $error }
+
+## Mode lent
+
+slow-mode-seconds = { $seconds ->
+        [one] 1 seconde
+       *[other] { $seconds } secondes
+    }
+
+## Échecs, avec ce qui s’est mal passé
+
+failed-load-roles = Impossible de charger les rôles : { $error }
+failed-create-channel = Impossible de créer le salon : { $error }
+failed-remove-channel = Impossible de supprimer le salon : { $error }
+failed-create-role = Impossible de créer le rôle : { $error }
+failed-change-role = Impossible de modifier le rôle : { $error }
+failed-send = Impossible d’envoyer ceci : { $error }
+failed-join-room = Impossible de rejoindre ce salon : { $error }
+failed-message-them = Impossible de lui écrire : { $error }
+failed-change-topic = Impossible de changer le sujet : { $error }
+failed-invite = Impossible de l’inviter : { $error }
+failed-edit-message = Impossible de modifier le message : { $error }
+failed-delete-message = Impossible de supprimer ce message : { $error }
+failed-remove-reaction = Impossible de retirer la réaction : { $error }
+failed-add-reaction = Impossible d’ajouter la réaction : { $error }
+failed-pin = Impossible d’épingler des messages : { $error }
+failed-unpin = Impossible de désépingler des messages : { $error }
+failed-load-audit-log = Impossible de charger le journal d’audit : { $error }
+failed-send-report = Impossible d’envoyer le signalement : { $error }
+failed-ignore = Impossible de l’ignorer : { $error }
+failed-record-voice = Impossible d’enregistrer un message vocal : { $error }
+failed-play-voice = Impossible de lire le message vocal : { $error }
+failed-send-voice = Impossible d’envoyer le message vocal : { $error }
+failed-measure-caches = Impossible de mesurer les caches : { $error }
+failed-clear-cache = Impossible de vider le cache : { $error }
+failed-clear-history = Impossible d’effacer l’historique local : { $error }
+failed-export-settings = Impossible d’exporter les paramètres : { $error }
+failed-import-settings = Impossible d’importer les paramètres : { $error }
+failed-purge = Impossible de supprimer les messages : { $error }
+failed-list-pushers = Impossible de les lister : { $error }
+failed-remove-pusher = Impossible de le supprimer : { $error }
+failed-set-up-notifications = Impossible de configurer les notifications : { $error }
+failed-list-emails = Impossible de les lister : { $error }
+failed-add-email = Impossible d’ajouter une adresse e-mail : { $error }
+failed-resend-email = Impossible de renvoyer le lien : { $error }
+failed-confirm-email = Impossible de l’ajouter : { $error }
+failed-remove-email = Impossible de la supprimer : { $error }
+failed-join = Impossible de rejoindre : { $error }
+failed-knock = Impossible de demander à rejoindre : { $error }
+failed-let-in = Impossible de le laisser entrer : { $error }
+failed-turn-away = Impossible de refuser sa demande : { $error }
+failed-cancel-knock = Impossible d’annuler la demande : { $error }
+failed-load-stickers = Impossible de charger les stickers : { $error }
+failed-send-sticker = Impossible d’envoyer le sticker : { $error }
+failed-send-game-invite = Impossible d’envoyer l’invitation : { $error }
+failed-load-aliases = Impossible de charger les alias : { $error }
+failed-load-room-settings = Impossible de charger les paramètres : { $error }
+failed-change-join-rule = Impossible de changer qui peut rejoindre : { $error }
+failed-change-history-visibility = Impossible de changer qui peut lire l’historique : { $error }
+failed-change-guest-access = Impossible de changer l’accès des invités : { $error }
+failed-enable-encryption = Impossible d’activer le chiffrement : { $error }
+failed-change-slow-mode = Impossible de changer le mode lent : { $error }
+failed-change-welcome = Impossible de changer le message de bienvenue : { $error }
+failed-add-alias = Impossible d’ajouter l’alias : { $error }
+failed-remove-alias = Impossible de retirer l’alias : { $error }
+failed-change-main-alias = Impossible de changer l’alias principal : { $error }
+failed-translate = Impossible de traduire ceci : { $error }
+failed-forward = Impossible de transférer les messages : { $error }
+failed-run-checks = Impossible de lancer les vérifications : { $error }
+failed-sync-favorites = Impossible de synchroniser vos favoris : { $error }
+failed-load-context = Impossible de charger ce message : { $error }
+failed-trust-new-keys = Impossible d’accepter ses nouvelles clés : { $error }
+failed-copy = Impossible de copier le message : { $error }
+failed-open-folder = Impossible d’ouvrir le dossier : { $error }
+failed-read-report = Impossible de lire le rapport : { $error }
+failed-microphone = Impossible d’enregistrer depuis le micro
+
+## Fait, dans une notification
+
+command-needs-room = Ouvrez d’abord un salon
+topic-changed = Sujet modifié
+invited = { $user } a été invité
+cache-cleared = Cache vidé
+history-cleared = Historique local effacé
+report-sent = Signalement envoyé aux administrateurs du serveur
+ignored = { $user } est ignoré
+settings-exported = Paramètres exportés
+settings-unchanged = Ces paramètres sont déjà utilisés
+settings-imported = Paramètres importés
+delete-not-allowed = Vous n’avez pas la permission de supprimer ce message
+hold-to-record = Maintenez le bouton pour enregistrer
+
+## Dans les salons chiffrés
+
+security-changed = La sécurité de { $name } a changé
+
+## Une suite de changements d’adhésion, en une ligne
+
+membership-joined =
+    { $count ->
+        [one] { $count } personne a rejoint
+       *[other] { $count } personnes ont rejoint
+    }
+membership-left =
+    { $count ->
+        [one] { $count } personne est partie
+       *[other] { $count } personnes sont parties
+    }
+membership-invited =
+    { $count ->
+        [one] { $count } personne a été invitée
+       *[other] { $count } personnes ont été invitées
+    }
+membership-removed =
+    { $count ->
+        [one] { $count } personne a été retirée
+       *[other] { $count } personnes ont été retirées
+    }
+list-separator = {", "}
+list-and = { $first } et { $last }
@@ -0,0 +1,100 @@
+//! What screen readers and the keyboard get beyond the markup's labels and
+//! focus scopes: messages arriving in the open room are announced, and
+//! Escape closes the dialog on top.
+
+use crate::{history, i18n, AppWindow};
+use chat_core::{Message, MessageType};
+use fluent_bundle::FluentValue;
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{Key, NamedKey};
+use slint::SharedString;
+
+/// How `message` is read out as it arrives.
+pub fn announcement(message: &Message) -> String {
+    let sender = ("sender", FluentValue::from(message.sender_display()));
+    let body = ("body", FluentValue::from(message.content.as_str()));
+    let name = ("name", FluentValue::from(message.content.as_str()));
+    match &message.schema {
+        MessageType::Text => i18n::tr_args("announce-text", [sender, body]),
+        MessageType::Emote => i18n::tr_args("announce-emote", [sender, body]),
+        MessageType::Image => i18n::tr_args("announce-image", [sender, name]),
+        MessageType::File => i18n::tr_args("announce-file", [sender, name]),
+        MessageType::MissedCall => i18n::tr_args("announce-missed-call", [sender]),
+        MessageType::SystemNotice(_)
+        | MessageType::Membership(_)
+        | MessageType::MembershipSummary(_)
+        | MessageType::DisplayName
+        | MessageType::Avatar => message.content.clone(),
+        MessageType::Reaction => i18n::tr_args("announce-reaction", [sender, body]),
+        MessageType::GameInvite(invite) => {
+            let game = ("game", FluentValue::from(invite.game.as_str()));
+            i18n::tr_args("announce-game-invite", [sender, game])
+        }
+    }
+}
+
+/// Announce a message that came in through sync, when it is in the open
+/// room and someone else's.
+pub fn message_arrived(ui: &AppWindow, room_id: &str, message: &Message) {
+    if history::current_room().as_deref() != Some(room_id)
+        || message.sender == ui.get_current_user_id().as_str()
+    {
+        return;
+    }
+    ui.set_announcement(SharedString::from(announcement(message)));
+}
+
+/// Escape closes the dialog on top. Returns true when the key was used.
+pub fn key_pressed(ui: &AppWindow, event: &KeyEvent) -> bool {
+    event.state == ElementState::Pressed
+        && event.logical_key == Key::Named(NamedKey::Escape)
+        && ui.invoke_close_top_dialog()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::game_invite::GameInvite;
+
+    fn message(schema: MessageType, content: &str) -> Message {
+        Message {
+            id: "$1".to_string(),
+            sender: "@alice:example.org".to_string(),
+            sender_name: Some("Alice".to_string()),
+            content: content.to_string(),
+            schema,
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn test_announcement_says_who_and_what() {
+        assert_eq!(announcement(&message(MessageType::Text, "gg")), "Alice: gg");
+        assert_eq!(
+            announcement(&message(MessageType::Emote, "waves")),
+            "Alice waves"
+        );
+        assert_eq!(
+            announcement(&message(MessageType::Image, "clip.png")),
+            "Alice sent an image, clip.png"
+        );
+        assert_eq!(
+            announcement(&message(MessageType::MissedCall, "")),
+            "Missed call from Alice"
+        );
+        let invite = GameInvite {
+            game: "Valorant".to_string(),
+            connect: "ABCD-1234".to_string(),
+            party_size: None,
+            expires_ts: 1,
+        };
+        assert_eq!(
+            announcement(&message(MessageType::GameInvite(invite), "")),
+            "Alice invites you to play Valorant"
+        );
+        assert_eq!(
+            announcement(&message(MessageType::Reaction, "👍")),
+            "Alice reacted with 👍"
+        );
+    }
+}
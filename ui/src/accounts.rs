@@ -0,0 +1,90 @@
+//! The accounts signed in at once. One is shown; the others stay signed in
+//! with a lean sync, so their mentions and direct messages still notify and
+//! switching back to one needs no login.
+
+use network::cancel::CancelToken;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct Accounts {
+    /// User ids signed in, the shown one included, in the order they were
+    /// added.
+    held: Vec<String>,
+    /// Cancelled when the shown account stops being shown, ending the
+    /// streams that feed its rooms into the UI.
+    shown: CancelToken,
+    /// Accounts whose notifications are listened to, each until its token
+    /// is cancelled.
+    notifying: HashMap<String, CancelToken>,
+    /// A room to open once the account switched to has loaded.
+    opening: Option<String>,
+}
+
+thread_local! {
+    static ACCOUNTS: RefCell<Accounts> = RefCell::new(Accounts::default());
+}
+
+/// `user_id` is now shown, and signed in if it was not.
+pub fn show(user_id: &str) {
+    ACCOUNTS.with(|a| {
+        let mut accounts = a.borrow_mut();
+        accounts.shown.cancel();
+        accounts.shown = CancelToken::new();
+        if !accounts.held.iter().any(|id| id == user_id) {
+            accounts.held.push(user_id.to_string());
+        }
+    });
+}
+
+/// The shown account is being put away; stop feeding it to the UI.
+pub fn hide() {
+    ACCOUNTS.with(|a| a.borrow().shown.cancel());
+}
+
+/// Cancelled once the account shown now is switched away from or logged
+/// out. Streams started for it end with it.
+pub fn shown() -> CancelToken {
+    ACCOUNTS.with(|a| a.borrow().shown.clone())
+}
+
+/// `user_id` logged out; returns the account to show next, if any is left.
+pub fn signed_out(user_id: &str) -> Option<String> {
+    ACCOUNTS.with(|a| {
+        let mut accounts = a.borrow_mut();
+        accounts.held.retain(|id| id != user_id);
+        if let Some(notifying) = accounts.notifying.remove(user_id) {
+            notifying.cancel();
+        }
+        accounts.held.first().cloned()
+    })
+}
+
+pub fn is_held(user_id: &str) -> bool {
+    ACCOUNTS.with(|a| a.borrow().held.iter().any(|id| id == user_id))
+}
+
+/// Start listening to `user_id`'s notifications, until it signs out.
+/// `None` if they are listened to already.
+pub fn start_notifying(user_id: &str) -> Option<CancelToken> {
+    ACCOUNTS.with(|a| {
+        let mut accounts = a.borrow_mut();
+        if accounts.notifying.contains_key(user_id) {
+            return None;
+        }
+        let token = CancelToken::new();
+        accounts
+            .notifying
+            .insert(user_id.to_string(), token.clone());
+        Some(token)
+    })
+}
+
+/// Open `room_id` once the account being switched to has loaded its rooms.
+pub fn open_after_switch(room_id: Option<String>) {
+    ACCOUNTS.with(|a| a.borrow_mut().opening = room_id);
+}
+
+pub fn take_opening() -> Option<String> {
+    ACCOUNTS.with(|a| a.borrow_mut().opening.take())
+}
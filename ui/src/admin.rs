@@ -0,0 +1,232 @@
+//! The admin panel: channels of the active server and roles as power
+//! levels. Nothing is changed locally; the channel list and member roles
+//! follow from the sync events the server sends back, and refusals are
+//! shown in the panel.
+
+use crate::app_state::ClientHandle;
+use crate::member_list;
+use crate::rooms::SharedSidebar;
+use crate::{i18n, AppWindow, RoleData};
+use network::admin::{AdminError, ChannelRemoval, Role};
+use network::federation::FederationError;
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+
+/// Where the active server's admin actions apply.
+struct Target {
+    /// The active space; `None` on Home.
+    space_id: Option<String>,
+    /// Where roles are defined: the space, or the open room on Home.
+    roles_room: String,
+    /// Rooms a role assignment changes power levels in.
+    rooms: Vec<String>,
+}
+
+fn target(sidebar: &SharedSidebar) -> Option<Target> {
+    let guard = sidebar.lock().unwrap();
+    let sb = guard.as_ref()?;
+    match sb.active_space() {
+        Some(space) => {
+            let mut rooms = vec![space.id.clone()];
+            rooms.extend(sb.channels().iter().map(|r| r.id.clone()));
+            Some(Target {
+                space_id: Some(space.id.clone()),
+                roles_room: space.id.clone(),
+                rooms,
+            })
+        }
+        None => {
+            let room_id = sb.active_room()?.to_string();
+            Some(Target {
+                space_id: None,
+                roles_room: room_id.clone(),
+                rooms: vec![room_id],
+            })
+        }
+    }
+}
+
+thread_local! {
+    /// Roles of the active server, for looking up the level of a name.
+    static ROLES: RefCell<Vec<Role>> = const { RefCell::new(Vec::new()) };
+}
+
+fn role_color(level: i64) -> slint::Color {
+    match level {
+        100 => slint::Color::from_argb_u8(255, 237, 66, 69),
+        50 => slint::Color::from_argb_u8(255, 87, 242, 135),
+        0 => slint::Color::from_argb_u8(255, 148, 155, 164),
+        _ => slint::Color::from_argb_u8(255, 88, 101, 242),
+    }
+}
+
+/// Show `roles` in the panel.
+pub fn show_roles(ui: &AppWindow, roles: Vec<Role>) {
+    let data: Vec<RoleData> = roles
+        .iter()
+        .map(|r| RoleData {
+            name: SharedString::from(r.name.as_str()),
+            color: role_color(r.level),
+            level: r.level.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+        })
+        .collect();
+    let names: Vec<SharedString> = roles
+        .iter()
+        .map(|r| SharedString::from(r.name.as_str()))
+        .collect();
+    ui.set_roles(Rc::new(VecModel::from(data)).into());
+    ui.set_role_names(Rc::new(VecModel::from(names)).into());
+    ROLES.with(|r| *r.borrow_mut() = roles);
+}
+
+/// The panel was opened: load the active server's roles.
+pub fn open(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar) {
+    ui.set_admin_error(SharedString::default());
+    let Some(target) = target(sidebar) else {
+        ui.set_admin_can_unlist(false);
+        return;
+    };
+    ui.set_admin_can_unlist(target.space_id.is_some());
+    load_roles(ui, client, &target.roles_room);
+}
+
+fn load_roles(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    let reply = client.get_roles(room_id);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(roles) => show_roles(&ui, roles),
+                Err(e) => ui.set_admin_error(error_text(&e, "failed-load-roles")),
+            }
+        })
+        .ok();
+    });
+}
+
+/// How to show a failure: a refusal, by us or by the room's other servers,
+/// as is, anything else as the catalogs' message `failed` with the error.
+pub fn error_text(e: &anyhow::Error, failed: &str) -> SharedString {
+    if e.is::<AdminError>() || e.is::<FederationError>() {
+        return SharedString::from(e.to_string());
+    }
+    let error = i18n::error_text(e);
+    SharedString::from(i18n::tr_args(failed, [("error", error.into())]))
+}
+
+/// Run `reply`, showing a failure as the message `failed` in the panel. `then` runs on
+/// the UI thread afterwards either way.
+fn report<T: Send + 'static>(
+    ui: &AppWindow,
+    reply: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+    failed: &'static str,
+    then: impl FnOnce(&AppWindow) + Send + 'static,
+) {
+    ui.set_admin_error(SharedString::default());
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                tracing::warn!("{}: {}", failed, e);
+                ui.set_admin_error(error_text(&e, failed));
+            }
+            then(&ui);
+        })
+        .ok();
+    });
+}
+
+pub fn create_channel(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, name: &str) {
+    let name = name.trim();
+    if name.is_empty() {
+        return;
+    }
+    let Some(target) = target(sidebar) else {
+        return;
+    };
+    let reply = client.create_channel(target.space_id.as_deref(), name);
+    report(ui, reply, "failed-create-channel", |_| {});
+}
+
+/// Unlist `room_id` from the active space, or leave it if `leave`.
+pub fn remove_channel(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    room_id: &str,
+    leave: bool,
+) {
+    let Some(target) = target(sidebar) else {
+        return;
+    };
+    let removal = if leave {
+        ChannelRemoval::Leave
+    } else {
+        ChannelRemoval::FromSpace
+    };
+    let reply = client.remove_channel(target.space_id.as_deref(), room_id, removal);
+    report(ui, reply, "failed-remove-channel", |_| {});
+}
+
+pub fn create_role(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    name: &str,
+    level: i32,
+) {
+    let name = name.trim();
+    if name.is_empty() {
+        return;
+    }
+    let Some(target) = target(sidebar) else {
+        return;
+    };
+    let role = Role {
+        name: name.to_string(),
+        level: level.into(),
+    };
+    let reply = client.create_role(&target.roles_room, role);
+    let client = client.clone();
+    let roles_room = target.roles_room;
+    report(ui, reply, "failed-create-role", move |ui| {
+        load_roles(ui, &client, &roles_room)
+    });
+}
+
+/// Give `user_id` the power level of `role` in every room of the server.
+pub fn assign_role(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    user_id: &str,
+    role: &str,
+) {
+    let level = ROLES.with(|r| r.borrow().iter().find(|r| r.name == role).map(|r| r.level));
+    let (Some(target), Some(level)) = (target(sidebar), level) else {
+        return;
+    };
+    let replies: Vec<_> = target
+        .rooms
+        .iter()
+        .map(|room_id| client.set_power_level(room_id, user_id, level))
+        .collect();
+    let all = async move {
+        for reply in replies {
+            reply.await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    // A refused change leaves the member's role picker on the old role.
+    report(ui, all, "failed-change-role", member_list::render);
+}
@@ -0,0 +1,234 @@
+//! Animated GIFs and APNGs in the timeline. Frames are decoded off the UI
+//! thread when an image comes on screen and dropped when it goes, and only
+//! a few play at once: the images shown longest go back to their first
+//! frame for newer ones. The timeline's rows swap frames themselves, while
+//! on screen and the window has focus, unless motion is reduced.
+
+use crate::{attachment_view, history, AppWindow, DmPopout, Motion};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, DynamicImage, ImageFormat};
+use network::media::{THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use slint::{ComponentHandle, Image, ModelRc, Rgba8Pixel, SharedPixelBuffer, VecModel};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Cursor;
+use std::rc::Rc;
+
+/// How many images animate at once; the rest stay on their first frame.
+const MAX_PLAYING: usize = 8;
+
+/// Decoded frames an animation may take up, in bytes. Longer ones stay
+/// still.
+const MAX_ANIMATION_BYTES: usize = 48 * 1024 * 1024;
+
+/// Delays shorter than this play at `DEFAULT_DELAY_MS`, as browsers do.
+const MIN_DELAY_MS: u32 = 20;
+const DEFAULT_DELAY_MS: u32 = 100;
+
+type Frames = Vec<(SharedPixelBuffer<Rgba8Pixel>, u32)>;
+
+struct Playing {
+    frames: ModelRc<Image>,
+    /// Of each frame, in milliseconds.
+    delays: ModelRc<i32>,
+}
+
+#[derive(Default)]
+struct Animations {
+    /// By event id.
+    playing: HashMap<String, Playing>,
+    /// Playing event ids, the one shown longest first.
+    order: VecDeque<String>,
+    loading: HashSet<String>,
+    /// Images that turned out still, or too big to animate.
+    still: HashSet<String>,
+    reduce_motion: bool,
+}
+
+thread_local! {
+    static ANIMATIONS: RefCell<Animations> = RefCell::new(Animations::default());
+}
+
+fn frame_delay(numer: u32, denom: u32) -> u32 {
+    let ms = numer.checked_div(denom).unwrap_or(0);
+    if ms < MIN_DELAY_MS {
+        DEFAULT_DELAY_MS
+    } else {
+        ms
+    }
+}
+
+/// The frames of an animated GIF or APNG, scaled down like previews are.
+/// `None` for still images and ones too big to keep decoded.
+fn decode_frames(data: &[u8]) -> Option<Frames> {
+    let frames = match image::guess_format(data).ok()? {
+        ImageFormat::Gif => GifDecoder::new(Cursor::new(data)).ok()?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(data)).ok()?;
+            if !decoder.is_apng().ok()? {
+                return None;
+            }
+            decoder.apng().ok()?.into_frames()
+        }
+        _ => return None,
+    };
+    let mut decoded = Vec::new();
+    let mut total = 0;
+    for frame in frames {
+        let frame = frame.ok()?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let mut buffer = frame.into_buffer();
+        if buffer.width() > THUMBNAIL_WIDTH || buffer.height() > THUMBNAIL_HEIGHT {
+            buffer = DynamicImage::ImageRgba8(buffer)
+                .thumbnail(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT)
+                .into_rgba8();
+        }
+        total += buffer.as_raw().len();
+        if total > MAX_ANIMATION_BYTES {
+            return None;
+        }
+        decoded.push((
+            SharedPixelBuffer::clone_from_slice(buffer.as_raw(), buffer.width(), buffer.height()),
+            frame_delay(numer, denom),
+        ));
+    }
+    (decoded.len() > 1).then_some(decoded)
+}
+
+fn refresh(event_id: &str) {
+    if let Some(room_id) = history::room_of(event_id) {
+        history::for_each_timeline(&room_id, |timeline| timeline.refresh(event_id));
+    }
+}
+
+/// The frames of image message `event_id` and their delays, empty unless
+/// it is playing.
+pub fn frames(event_id: &str) -> (ModelRc<Image>, ModelRc<i32>) {
+    ANIMATIONS.with(|a| match a.borrow().playing.get(event_id) {
+        Some(playing) => (playing.frames.clone(), playing.delays.clone()),
+        None => Default::default(),
+    })
+}
+
+/// Animate image message `event_id`, which came on screen, once its
+/// preview is loaded.
+pub fn start(event_id: &str) {
+    let started = ANIMATIONS.with(|a| {
+        let mut state = a.borrow_mut();
+        if state.playing.contains_key(event_id) {
+            // Shown again: the last to go back to still.
+            state.order.retain(|id| id != event_id);
+            state.order.push_back(event_id.to_string());
+            return false;
+        }
+        !state.reduce_motion
+            && !state.still.contains(event_id)
+            && state.loading.insert(event_id.to_string())
+    });
+    if !started {
+        return;
+    }
+    let Some(data) = attachment_view::preview_bytes(event_id) else {
+        ANIMATIONS.with(|a| a.borrow_mut().loading.remove(event_id));
+        return;
+    };
+    let event_id = event_id.to_string();
+    tokio::spawn(async move {
+        let decoded = tokio::task::spawn_blocking(move || decode_frames(&data))
+            .await
+            .ok()
+            .flatten();
+        slint::invoke_from_event_loop(move || finish(&event_id, decoded)).ok();
+    });
+}
+
+fn finish(event_id: &str, decoded: Option<Frames>) {
+    let evicted = ANIMATIONS.with(|a| {
+        let mut state = a.borrow_mut();
+        // Dropped while decoding, e.g. scrolled away or motion reduced.
+        if !state.loading.remove(event_id) {
+            return None;
+        }
+        let Some(decoded) = decoded else {
+            state.still.insert(event_id.to_string());
+            return None;
+        };
+        let (images, delays): (Vec<Image>, Vec<i32>) = decoded
+            .into_iter()
+            .map(|(buffer, delay)| (Image::from_rgba8(buffer), delay as i32))
+            .unzip();
+        state.playing.insert(
+            event_id.to_string(),
+            Playing {
+                frames: Rc::new(VecModel::from(images)).into(),
+                delays: Rc::new(VecModel::from(delays)).into(),
+            },
+        );
+        state.order.push_back(event_id.to_string());
+        if state.order.len() > MAX_PLAYING {
+            let oldest = state.order.pop_front()?;
+            state.playing.remove(&oldest);
+            Some(oldest)
+        } else {
+            None
+        }
+    });
+    refresh(event_id);
+    if let Some(oldest) = evicted {
+        refresh(&oldest);
+    }
+}
+
+/// Stop animating `event_id`, which went off screen, and free its frames.
+pub fn stop(event_id: &str) {
+    let stopped = ANIMATIONS.with(|a| {
+        let mut state = a.borrow_mut();
+        state.loading.remove(event_id);
+        state.order.retain(|id| id != event_id);
+        state.playing.remove(event_id).is_some()
+    });
+    if stopped {
+        refresh(event_id);
+    }
+}
+
+/// Stop every animation, freeing their frames.
+fn stop_all() {
+    let stopped: Vec<String> = ANIMATIONS.with(|a| {
+        let mut state = a.borrow_mut();
+        state.loading.clear();
+        state.order.clear();
+        state.playing.drain().map(|(id, _)| id).collect()
+    });
+    for event_id in stopped {
+        refresh(&event_id);
+    }
+}
+
+/// Pause animations while the window is in the background.
+pub fn window_focused(ui: &AppWindow, focused: bool) {
+    ui.global::<Motion>().set_paused(!focused);
+}
+
+/// Show animated images still, or animate them again.
+pub fn set_reduce_motion(ui: &AppWindow, reduce: bool) {
+    ANIMATIONS.with(|a| a.borrow_mut().reduce_motion = reduce);
+    ui.global::<Motion>().set_reduce(reduce);
+    crate::popouts::for_each(|popout| popout.global::<Motion>().set_reduce(reduce));
+    if reduce {
+        stop_all();
+    }
+}
+
+/// Reduce motion in a newly opened popout if the main window does.
+pub fn prepare_popout(popout: &DmPopout) {
+    let reduce = ANIMATIONS.with(|a| a.borrow().reduce_motion);
+    popout.global::<Motion>().set_reduce(reduce);
+}
+
+/// Drop every animation, e.g. on logout.
+pub fn clear() {
+    stop_all();
+    ANIMATIONS.with(|a| a.borrow_mut().still.clear());
+}
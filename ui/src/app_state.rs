@@ -0,0 +1,6810 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chat_core::game_invite::GameInvite;
+use chat_core::link_preview::LinkPreview;
+use chat_core::mention::Mention;
+use chat_core::{Attachment, Message, Room, Space, UserStatus};
+use network::admin::{ChannelRemoval, Role};
+use network::audit::{AuditKind, StateHistoryPage};
+use network::cancel::CancelToken;
+use network::config::{PusherConfig, Retention};
+use network::connection::Connection;
+use network::details::EventDetails;
+use network::diagnostics::Report;
+use network::discovery::{DiscoveryError, ServerStatus};
+use network::encryption::RoomSecurity;
+use network::events::{ChatEvent, KnockRequest, NotificationEvent, TypingEvent, VoiceMember};
+use network::media::{Upload, UploadProgress};
+use network::media_cache::{CacheCategory, CacheUsage};
+use network::members::MemberList;
+use network::password_reset::PasswordReset;
+use network::peek::RoomPreview;
+use network::profile::Profile;
+use network::purge::{PurgeProgress, PurgeReport, PurgeWindow};
+use network::pusher::Pusher;
+use network::register::{Availability, ServerSupport};
+use network::report::Reported;
+use network::room_settings::{HistoryVisibility, JoinRule, RoomAliases, RoomSettings};
+use network::rooms::{ContextPage, MessagePage, NewerPage, Permissions};
+use network::roster::Friend;
+use network::search::SearchHit;
+use network::session::Session;
+use network::signaling::HangupReason;
+use network::startup;
+use network::stickers::{Sticker, StickerPack};
+use network::threepid::{EmailValidation, Threepid};
+use network::welcome::{Seen, Welcome};
+use network::MatrixClient;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+
+/// Number of messages fetched per history page.
+pub const HISTORY_PAGE_SIZE: u32 = 50;
+
+/// A media transfer, run outside the client task so a large file does not
+/// hold up other requests.
+pub type Detached<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+/// The client surface the UI drives. Implemented by `MatrixClient`, and by
+/// fakes in tests so the bridge can be exercised without a homeserver.
+#[async_trait]
+pub trait Backend: Sized + Send + Sync + 'static {
+    /// Connect and log in. Returns (client, user_id, display_name).
+    async fn login(
+        homeserver: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<(Self, String, String)>;
+    async fn restore(saved: &Session) -> Result<Self>;
+    /// Connect without logging in, for registration.
+    async fn connect(homeserver: &str) -> Result<Self>;
+    /// Register and log in. Returns (user_id, display_name).
+    async fn register(&mut self, username: &str, password: &str) -> Result<(String, String)>;
+    async fn check_username(&self, username: &str) -> Result<Availability>;
+    async fn server_support(&self) -> Result<ServerSupport>;
+    /// Time a few round trips to the server and ask what it runs.
+    async fn server_status(&self) -> Result<ServerStatus>;
+    /// Mail `email` a link to reset the password of its account.
+    async fn request_password_reset(&self, email: &str) -> Result<PasswordReset>;
+    async fn resend_password_reset(&self, reset: &PasswordReset) -> Result<PasswordReset>;
+    /// Submit the code from the reset mail instead of clicking its link.
+    async fn submit_reset_token(&self, reset: &PasswordReset, token: &str) -> Result<()>;
+    /// Set the new password once the reset link was clicked.
+    async fn complete_password_reset(
+        &self,
+        sid: &str,
+        client_secret: &str,
+        new_password: &str,
+    ) -> Result<()>;
+    /// Send a text message, returning its event id.
+    async fn send_message(
+        &self,
+        room_id: &str,
+        body: &str,
+        mentions: &[Mention],
+        txn_id: &str,
+    ) -> Result<String>;
+    async fn send_reply(
+        &self,
+        room_id: &str,
+        body: &str,
+        mentions: &[Mention],
+        reply_to: &str,
+        txn_id: &str,
+    ) -> Result<String>;
+    /// Send an emote, e.g. "waves" for "* alice waves", returning its
+    /// event id.
+    async fn send_emote(&self, room_id: &str, action: &str) -> Result<String>;
+    /// Send a sticker from a pack, returning its event id.
+    async fn send_sticker(&self, room_id: &str, sticker: &Sticker) -> Result<String>;
+    /// Invite the room to a game, returning the invite's event id.
+    async fn send_game_invite(&self, room_id: &str, invite: &GameInvite) -> Result<String>;
+    /// Replace the body of our message, returning the edit's event id.
+    async fn edit_message(&self, room_id: &str, event_id: &str, body: &str) -> Result<String>;
+    async fn delete_message(&self, room_id: &str, event_id: &str) -> Result<()>;
+    async fn react_to_message(&self, room_id: &str, event_id: &str, key: &str) -> Result<String>;
+    async fn report_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        score: Option<i64>,
+        reason: Option<&str>,
+    ) -> Result<Reported>;
+    async fn report_room(&self, room_id: &str, reason: Option<&str>) -> Result<Reported>;
+    async fn ignore_user(&self, user_id: &str) -> Result<()>;
+    async fn get_permissions(&self, room_id: &str) -> Result<Permissions>;
+    async fn get_pinned(&self, room_id: &str) -> Result<Vec<String>>;
+    async fn pin_message(&self, room_id: &str, event_id: &str, pin: bool) -> Result<()>;
+    async fn get_event(&self, room_id: &str, event_id: &str) -> Result<Message>;
+    async fn get_event_details(&self, room_id: &str, event_id: &str) -> Result<EventDetails>;
+    async fn logout(&mut self) -> Result<()>;
+    async fn start_sync(&mut self) -> Result<()>;
+    /// Sync only what notifications need while another account is shown.
+    async fn set_background(&mut self, background: bool);
+    fn subscribe(&self) -> broadcast::Receiver<ChatEvent>;
+    fn subscribe_notifications(&self) -> broadcast::Receiver<NotificationEvent>;
+    fn subscribe_typing(&self) -> broadcast::Receiver<TypingEvent>;
+    fn subscribe_connection(&self) -> Connection;
+    fn set_typing(&self, room_id: &str, typing: bool) -> Detached<()>;
+    /// Resolved `concurrency` at a time.
+    async fn get_spaces(&self, concurrency: usize) -> Result<Vec<Space>>;
+    async fn get_joined_rooms(&self, concurrency: usize) -> Result<Vec<Room>>;
+    async fn get_messages(&self, room_id: &str, from: Option<&str>) -> Result<MessagePage>;
+    async fn get_newer_messages(&self, room_id: &str, from: &str) -> Result<NewerPage>;
+    /// `event_id` and the messages around it.
+    async fn get_context(&self, room_id: &str, event_id: &str) -> Result<ContextPage>;
+    /// A page of the audit log: changes of `kinds`, going back from `from`.
+    async fn get_state_history(
+        &self,
+        room_id: &str,
+        kinds: &[AuditKind],
+        from: Option<&str>,
+    ) -> Result<StateHistoryPage>;
+    /// Mark read up to `event_id`, sent at `ts`.
+    fn mark_read(&self, room_id: &str, event_id: &str, ts: u64) -> Detached<()>;
+    /// The event our fully-read marker is on.
+    async fn fully_read(&self, room_id: &str) -> Result<Option<String>>;
+    /// Join by room id or alias, returning the room id.
+    async fn join_room(&self, id_or_alias: &str, via: &[String]) -> Result<String>;
+    /// Look into a room not joined, by room id or alias.
+    async fn preview_room(&self, id_or_alias: &str, via: &[String]) -> Result<RoomPreview>;
+    /// Ask to join by room id or alias, returning the room id.
+    async fn knock_on_room(
+        &self,
+        id_or_alias: &str,
+        via: &[String],
+        reason: Option<&str>,
+    ) -> Result<String>;
+    async fn retract_knock(&self, room_id: &str) -> Result<()>;
+    /// Knocks waiting in rooms we can let people into.
+    async fn knock_requests(&self) -> Result<Vec<KnockRequest>>;
+    async fn approve_knock(&self, room_id: &str, user_id: &str) -> Result<()>;
+    async fn deny_knock(&self, room_id: &str, user_id: &str) -> Result<()>;
+    async fn invite_user(&self, room_id: &str, user_id: &str) -> Result<()>;
+    /// Find or create the DM room with `user_id`, returning its id.
+    async fn open_dm(&self, user_id: &str) -> Result<String>;
+    /// The friends list, with requests either way.
+    async fn get_friends(&self) -> Result<Vec<Friend>>;
+    /// Ask `user_id` to be friends, or accept them if they asked first.
+    async fn add_friend(&self, user_id: &str) -> Result<()>;
+    async fn respond_to_friend(&self, user_id: &str, accept: bool) -> Result<()>;
+    async fn remove_friend(&self, user_id: &str) -> Result<()>;
+    fn set_presence(&self, status: UserStatus) -> Detached<()>;
+    /// Free text shown with our status; blank clears it.
+    fn set_status_message(&self, text: &str) -> Detached<()>;
+    /// Make the server's push rules alert on `keywords`, and no others.
+    async fn set_keywords(&self, keywords: &[String]) -> Result<()>;
+    /// Pushers on the account, ours and other apps'.
+    async fn list_pushers(&self) -> Result<Vec<Pusher>>;
+    /// Register the pushers `config` asks for, and remove ours it doesn't.
+    async fn register_pushers(&self, config: &PusherConfig) -> Result<()>;
+    async fn remove_pusher(&self, app_id: &str, pushkey: &str) -> Result<()>;
+    /// Email addresses and phone numbers on the account.
+    async fn get_3pids(&self) -> Result<Vec<Threepid>>;
+    /// Mail a link to `email` to prove it is ours.
+    async fn request_email_token(&self, email: &str) -> Result<EmailValidation>;
+    async fn resend_email_token(&self, validation: &EmailValidation) -> Result<EmailValidation>;
+    /// Add the validated address to the account, confirmed with `password`.
+    async fn add_3pid(&self, validation: &EmailValidation, password: &str) -> Result<()>;
+    async fn remove_3pid(&self, email: &str) -> Result<()>;
+    /// Joined members of a room; all of them if `full`, else those known.
+    async fn get_room_members(&self, room_id: &str, full: bool) -> Result<MemberList>;
+    async fn get_member_avatar(&self, room_id: &str, user_id: &str) -> Result<Option<Vec<u8>>>;
+    fn download_media(&self, source: &str) -> Detached<Vec<u8>>;
+    /// The inline rendition of an image attachment.
+    fn download_thumbnail(&self, attachment: &Attachment) -> Detached<Vec<u8>>;
+    /// The homeserver's preview of a link, `None` if it has nothing to show.
+    fn get_url_preview(&self, url: &str, ts: u64) -> Detached<Option<LinkPreview>>;
+    async fn upload_limit(&self) -> Result<u64>;
+    /// Send an image or file message under `txn_id`, returning its event id.
+    fn send_attachment(
+        &self,
+        room_id: &str,
+        upload: Upload,
+        txn_id: &str,
+        progress: watch::Sender<UploadProgress>,
+    ) -> Detached<String>;
+    /// Send a recorded voice clip under `txn_id`, returning its event id.
+    fn send_voice_message(
+        &self,
+        room_id: &str,
+        data: Vec<u8>,
+        duration: Duration,
+        txn_id: &str,
+    ) -> Detached<String>;
+    /// Send a message again in another room, returning the new event id.
+    fn forward_message(
+        &self,
+        src_room: &str,
+        event_id: &str,
+        dst_room: &str,
+        annotation: Option<&str>,
+    ) -> Detached<String>;
+    /// Remove `user_id`'s recent messages in `room_id`, banning them first
+    /// if `ban`, reporting on `progress` as the redactions go.
+    fn purge_user_messages(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        window: PurgeWindow,
+        ban: bool,
+        progress: watch::Sender<PurgeProgress>,
+    ) -> Detached<PurgeReport>;
+    async fn get_profile(&self) -> Result<Profile>;
+    async fn set_display_name(&mut self, name: &str) -> Result<()>;
+    async fn set_avatar(&self, data: Vec<u8>, mime_type: &str) -> Result<()>;
+    async fn set_bio(&self, bio: &str) -> Result<()>;
+    async fn get_favorites(&self) -> Result<Option<Vec<String>>>;
+    async fn set_favorites(&self, rooms: Vec<String>) -> Result<()>;
+    /// The sticker packs to offer in `room_id`.
+    async fn sticker_packs(&self, room_id: &str) -> Result<Vec<StickerPack>>;
+    /// Announce ourselves in `room_id`'s voice channel at `candidates`.
+    async fn join_voice(&self, room_id: &str, candidates: Vec<String>) -> Result<()>;
+    async fn leave_voice(&self, room_id: &str) -> Result<()>;
+    /// Where voice can ask which address it is seen at from outside.
+    async fn stun_servers(&self) -> Result<Vec<SocketAddr>>;
+    async fn voice_members(&self, room_id: &str) -> Result<Vec<VoiceMember>>;
+    async fn answer_call(&self, room_id: &str, call_id: &str) -> Result<()>;
+    async fn hangup_call(&self, room_id: &str, call_id: &str, reason: HangupReason) -> Result<()>;
+    /// Create a channel, listed in `space_id` if given, returning its id.
+    async fn create_channel(&self, space_id: Option<&str>, name: &str) -> Result<String>;
+    async fn remove_channel(
+        &self,
+        space_id: Option<&str>,
+        room_id: &str,
+        removal: ChannelRemoval,
+    ) -> Result<()>;
+    async fn get_roles(&self, room_id: &str) -> Result<Vec<Role>>;
+    async fn create_role(&self, room_id: &str, role: Role) -> Result<()>;
+    async fn set_power_level(&self, room_id: &str, user_id: &str, level: i64) -> Result<()>;
+    async fn get_room_settings(&self, room_id: &str) -> Result<RoomSettings>;
+    async fn set_join_rule(&self, room_id: &str, rule: JoinRule) -> Result<()>;
+    async fn set_history_visibility(
+        &self,
+        room_id: &str,
+        visibility: HistoryVisibility,
+    ) -> Result<()>;
+    async fn set_guest_access(&self, room_id: &str, allowed: bool) -> Result<()>;
+    async fn set_slow_mode(&self, room_id: &str, seconds: u64) -> Result<()>;
+    /// The room's welcome message, and what we did with it.
+    async fn get_welcome(&self, room_id: &str) -> Result<Option<(Welcome, Seen)>>;
+    async fn set_welcome(&self, room_id: &str, body: &str, must_accept: bool) -> Result<()>;
+    async fn mark_welcome(&self, room_id: &str, event_id: &str, seen: Seen) -> Result<()>;
+    async fn set_topic(&self, room_id: &str, topic: &str) -> Result<()>;
+    /// Turn on encryption for good, returning once messages sent after it
+    /// are encrypted.
+    async fn enable_encryption(&self, room_id: &str) -> Result<()>;
+    async fn get_room_aliases(&self, room_id: &str) -> Result<RoomAliases>;
+    /// Publish an alias on our server, returning it in full.
+    async fn add_room_alias(&self, room_id: &str, alias: &str) -> Result<String>;
+    async fn remove_room_alias(&self, alias: &str) -> Result<()>;
+    async fn set_canonical_alias(&self, room_id: &str, alias: &str) -> Result<()>;
+    /// Bytes cached on disk, by category.
+    async fn cache_size(&self) -> Result<CacheUsage>;
+    async fn clear_cache(&self, category: CacheCategory) -> Result<()>;
+    async fn prune_cache(&self, retention: Retention) -> Result<()>;
+    async fn clear_local_history(&self) -> Result<()>;
+    /// Messages loaded this session that match `query`, best first.
+    async fn search_local(&self, query: &str, room_id: Option<&str>) -> Result<Vec<SearchHit>>;
+    /// The homeserver's matches for `query`; encrypted rooms are left out.
+    async fn search_server(&self, query: &str, room_id: Option<&str>) -> Result<Vec<SearchHit>>;
+    /// Start indexing the latest messages of every room afresh.
+    async fn rebuild_search_index(&self) -> Result<()>;
+    /// Check the homeserver, session, sync, audio, UDP and disk.
+    async fn run_diagnostics(&self) -> Result<Report>;
+    /// Whether `room_id` is encrypted and who there has unverified devices.
+    async fn room_security(&self, room_id: &str) -> Result<RoomSecurity>;
+    /// Take `user_id`'s keys as they are now as the ones we verified.
+    async fn trust_new_keys(&self, user_id: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl Backend for MatrixClient {
+    async fn login(
+        homeserver: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<(Self, String, String)> {
+        let mut mc = MatrixClient::new(homeserver).await?;
+        let (user_id, display_name) = MatrixClient::login(&mut mc, username, password).await?;
+        Ok((mc, user_id, display_name))
+    }
+
+    async fn restore(saved: &Session) -> Result<Self> {
+        MatrixClient::restore_session(saved).await
+    }
+
+    async fn connect(homeserver: &str) -> Result<Self> {
+        MatrixClient::new(homeserver).await
+    }
+
+    async fn register(&mut self, username: &str, password: &str) -> Result<(String, String)> {
+        MatrixClient::register(self, username, password).await
+    }
+
+    async fn check_username(&self, username: &str) -> Result<Availability> {
+        MatrixClient::check_username(self, username).await
+    }
+
+    async fn server_support(&self) -> Result<ServerSupport> {
+        MatrixClient::server_support(self).await
+    }
+
+    async fn server_status(&self) -> Result<ServerStatus> {
+        MatrixClient::server_status(self).await
+    }
+
+    async fn request_password_reset(&self, email: &str) -> Result<PasswordReset> {
+        MatrixClient::request_password_reset(self, email).await
+    }
+
+    async fn resend_password_reset(&self, reset: &PasswordReset) -> Result<PasswordReset> {
+        MatrixClient::resend_password_reset(self, reset).await
+    }
+
+    async fn submit_reset_token(&self, reset: &PasswordReset, token: &str) -> Result<()> {
+        MatrixClient::submit_reset_token(self, reset, token).await
+    }
+
+    async fn complete_password_reset(
+        &self,
+        sid: &str,
+        client_secret: &str,
+        new_password: &str,
+    ) -> Result<()> {
+        MatrixClient::complete_password_reset(self, sid, client_secret, new_password).await
+    }
+
+    async fn send_message(
+        &self,
+        room_id: &str,
+        body: &str,
+        mentions: &[Mention],
+        txn_id: &str,
+    ) -> Result<String> {
+        MatrixClient::send_message(self, room_id, body, mentions, txn_id).await
+    }
+
+    async fn send_emote(&self, room_id: &str, action: &str) -> Result<String> {
+        MatrixClient::send_emote(self, room_id, action).await
+    }
+
+    async fn send_sticker(&self, room_id: &str, sticker: &Sticker) -> Result<String> {
+        MatrixClient::send_sticker(self, room_id, sticker).await
+    }
+
+    async fn send_game_invite(&self, room_id: &str, invite: &GameInvite) -> Result<String> {
+        MatrixClient::send_game_invite(self, room_id, invite).await
+    }
+
+    async fn send_reply(
+        &self,
+        room_id: &str,
+        body: &str,
+        mentions: &[Mention],
+        reply_to: &str,
+        txn_id: &str,
+    ) -> Result<String> {
+        MatrixClient::send_reply(self, room_id, body, mentions, reply_to, txn_id).await
+    }
+
+    async fn edit_message(&self, room_id: &str, event_id: &str, body: &str) -> Result<String> {
+        MatrixClient::edit_message(self, room_id, event_id, body).await
+    }
+
+    async fn delete_message(&self, room_id: &str, event_id: &str) -> Result<()> {
+        MatrixClient::delete_message(self, room_id, event_id).await
+    }
+
+    async fn react_to_message(&self, room_id: &str, event_id: &str, key: &str) -> Result<String> {
+        MatrixClient::react_to_message(self, room_id, event_id, key).await
+    }
+
+    async fn report_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        score: Option<i64>,
+        reason: Option<&str>,
+    ) -> Result<Reported> {
+        MatrixClient::report_message(self, room_id, event_id, score, reason).await
+    }
+
+    async fn report_room(&self, room_id: &str, reason: Option<&str>) -> Result<Reported> {
+        MatrixClient::report_room(self, room_id, reason).await
+    }
+
+    async fn ignore_user(&self, user_id: &str) -> Result<()> {
+        MatrixClient::ignore_user(self, user_id).await
+    }
+
+    async fn get_permissions(&self, room_id: &str) -> Result<Permissions> {
+        MatrixClient::get_permissions(self, room_id).await
+    }
+
+    async fn get_pinned(&self, room_id: &str) -> Result<Vec<String>> {
+        MatrixClient::get_pinned(self, room_id).await
+    }
+
+    async fn pin_message(&self, room_id: &str, event_id: &str, pin: bool) -> Result<()> {
+        MatrixClient::pin_message(self, room_id, event_id, pin).await
+    }
+
+    async fn get_event(&self, room_id: &str, event_id: &str) -> Result<Message> {
+        MatrixClient::get_event(self, room_id, event_id).await
+    }
+
+    async fn get_event_details(&self, room_id: &str, event_id: &str) -> Result<EventDetails> {
+        MatrixClient::get_event_details(self, room_id, event_id).await
+    }
+
+    async fn logout(&mut self) -> Result<()> {
+        MatrixClient::logout(self).await
+    }
+
+    async fn start_sync(&mut self) -> Result<()> {
+        MatrixClient::start_sync(self).await
+    }
+
+    async fn set_background(&mut self, background: bool) {
+        MatrixClient::set_background(self, background).await
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        MatrixClient::subscribe(self)
+    }
+
+    fn subscribe_notifications(&self) -> broadcast::Receiver<NotificationEvent> {
+        MatrixClient::subscribe_notifications(self)
+    }
+
+    fn subscribe_typing(&self) -> broadcast::Receiver<TypingEvent> {
+        MatrixClient::subscribe_typing(self)
+    }
+
+    fn subscribe_connection(&self) -> Connection {
+        MatrixClient::subscribe_connection(self)
+    }
+
+    fn set_typing(&self, room_id: &str, typing: bool) -> Detached<()> {
+        Box::pin(MatrixClient::set_typing(self, room_id, typing))
+    }
+
+    async fn get_spaces(&self, concurrency: usize) -> Result<Vec<Space>> {
+        MatrixClient::get_spaces(self, concurrency).await
+    }
+
+    async fn get_joined_rooms(&self, concurrency: usize) -> Result<Vec<Room>> {
+        MatrixClient::get_joined_rooms(self, concurrency).await
+    }
+
+    async fn get_messages(&self, room_id: &str, from: Option<&str>) -> Result<MessagePage> {
+        MatrixClient::get_messages(self, room_id, from, HISTORY_PAGE_SIZE).await
+    }
+
+    async fn get_newer_messages(&self, room_id: &str, from: &str) -> Result<NewerPage> {
+        MatrixClient::get_newer_messages(self, room_id, from, HISTORY_PAGE_SIZE).await
+    }
+
+    async fn get_context(&self, room_id: &str, event_id: &str) -> Result<ContextPage> {
+        MatrixClient::get_context(self, room_id, event_id, HISTORY_PAGE_SIZE).await
+    }
+
+    async fn get_state_history(
+        &self,
+        room_id: &str,
+        kinds: &[AuditKind],
+        from: Option<&str>,
+    ) -> Result<StateHistoryPage> {
+        MatrixClient::get_state_history(self, room_id, kinds, from, HISTORY_PAGE_SIZE).await
+    }
+
+    fn mark_read(&self, room_id: &str, event_id: &str, ts: u64) -> Detached<()> {
+        Box::pin(MatrixClient::mark_read(self, room_id, event_id, ts))
+    }
+
+    async fn fully_read(&self, room_id: &str) -> Result<Option<String>> {
+        MatrixClient::fully_read(self, room_id).await
+    }
+
+    async fn join_room(&self, id_or_alias: &str, via: &[String]) -> Result<String> {
+        MatrixClient::join_room(self, id_or_alias, via).await
+    }
+
+    async fn preview_room(&self, id_or_alias: &str, via: &[String]) -> Result<RoomPreview> {
+        MatrixClient::preview_room(self, id_or_alias, via, HISTORY_PAGE_SIZE).await
+    }
+
+    async fn knock_on_room(
+        &self,
+        id_or_alias: &str,
+        via: &[String],
+        reason: Option<&str>,
+    ) -> Result<String> {
+        MatrixClient::knock_on_room(self, id_or_alias, via, reason).await
+    }
+
+    async fn retract_knock(&self, room_id: &str) -> Result<()> {
+        MatrixClient::retract_knock(self, room_id).await
+    }
+
+    async fn knock_requests(&self) -> Result<Vec<KnockRequest>> {
+        MatrixClient::knock_requests(self).await
+    }
+
+    async fn approve_knock(&self, room_id: &str, user_id: &str) -> Result<()> {
+        MatrixClient::approve_knock(self, room_id, user_id).await
+    }
+
+    async fn deny_knock(&self, room_id: &str, user_id: &str) -> Result<()> {
+        MatrixClient::deny_knock(self, room_id, user_id).await
+    }
+
+    async fn invite_user(&self, room_id: &str, user_id: &str) -> Result<()> {
+        MatrixClient::invite_user(self, room_id, user_id).await
+    }
+
+    async fn open_dm(&self, user_id: &str) -> Result<String> {
+        MatrixClient::open_dm(self, user_id).await
+    }
+
+    async fn get_friends(&self) -> Result<Vec<Friend>> {
+        MatrixClient::get_friends(self).await
+    }
+
+    async fn add_friend(&self, user_id: &str) -> Result<()> {
+        MatrixClient::add_friend(self, user_id).await
+    }
+
+    async fn respond_to_friend(&self, user_id: &str, accept: bool) -> Result<()> {
+        MatrixClient::respond_to_friend(self, user_id, accept).await
+    }
+
+    async fn remove_friend(&self, user_id: &str) -> Result<()> {
+        MatrixClient::remove_friend(self, user_id).await
+    }
+
+    fn set_presence(&self, status: UserStatus) -> Detached<()> {
+        Box::pin(MatrixClient::set_presence(self, status))
+    }
+
+    fn set_status_message(&self, text: &str) -> Detached<()> {
+        Box::pin(MatrixClient::set_status_message(self, text))
+    }
+
+    async fn set_keywords(&self, keywords: &[String]) -> Result<()> {
+        MatrixClient::set_keywords(self, keywords).await
+    }
+
+    async fn list_pushers(&self) -> Result<Vec<Pusher>> {
+        MatrixClient::list_pushers(self).await
+    }
+
+    async fn register_pushers(&self, config: &PusherConfig) -> Result<()> {
+        MatrixClient::register_pushers(self, config).await
+    }
+
+    async fn remove_pusher(&self, app_id: &str, pushkey: &str) -> Result<()> {
+        MatrixClient::remove_pusher(self, app_id, pushkey).await
+    }
+
+    async fn get_3pids(&self) -> Result<Vec<Threepid>> {
+        MatrixClient::get_3pids(self).await
+    }
+
+    async fn request_email_token(&self, email: &str) -> Result<EmailValidation> {
+        MatrixClient::request_email_token(self, email).await
+    }
+
+    async fn resend_email_token(&self, validation: &EmailValidation) -> Result<EmailValidation> {
+        MatrixClient::resend_email_token(self, validation).await
+    }
+
+    async fn add_3pid(&self, validation: &EmailValidation, password: &str) -> Result<()> {
+        MatrixClient::add_3pid(self, validation, password).await
+    }
+
+    async fn remove_3pid(&self, email: &str) -> Result<()> {
+        MatrixClient::remove_3pid(self, email).await
+    }
+
+    async fn get_room_members(&self, room_id: &str, full: bool) -> Result<MemberList> {
+        MatrixClient::get_room_members(self, room_id, full).await
+    }
+
+    async fn get_member_avatar(&self, room_id: &str, user_id: &str) -> Result<Option<Vec<u8>>> {
+        MatrixClient::get_member_avatar(self, room_id, user_id).await
+    }
+
+    fn download_media(&self, source: &str) -> Detached<Vec<u8>> {
+        Box::pin(MatrixClient::download_media(self, source))
+    }
+
+    fn download_thumbnail(&self, attachment: &Attachment) -> Detached<Vec<u8>> {
+        Box::pin(MatrixClient::download_thumbnail(self, attachment))
+    }
+
+    fn get_url_preview(&self, url: &str, ts: u64) -> Detached<Option<LinkPreview>> {
+        Box::pin(MatrixClient::get_url_preview(self, url, ts))
+    }
+
+    async fn upload_limit(&self) -> Result<u64> {
+        MatrixClient::upload_limit(self).await
+    }
+
+    fn send_attachment(
+        &self,
+        room_id: &str,
+        upload: Upload,
+        txn_id: &str,
+        progress: watch::Sender<UploadProgress>,
+    ) -> Detached<String> {
+        Box::pin(MatrixClient::send_attachment(
+            self, room_id, upload, txn_id, progress,
+        ))
+    }
+
+    fn send_voice_message(
+        &self,
+        room_id: &str,
+        data: Vec<u8>,
+        duration: Duration,
+        txn_id: &str,
+    ) -> Detached<String> {
+        Box::pin(MatrixClient::send_voice_message(
+            self, room_id, data, duration, txn_id,
+        ))
+    }
+
+    fn forward_message(
+        &self,
+        src_room: &str,
+        event_id: &str,
+        dst_room: &str,
+        annotation: Option<&str>,
+    ) -> Detached<String> {
+        Box::pin(MatrixClient::forward_message(
+            self, src_room, event_id, dst_room, annotation,
+        ))
+    }
+
+    fn purge_user_messages(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        window: PurgeWindow,
+        ban: bool,
+        progress: watch::Sender<PurgeProgress>,
+    ) -> Detached<PurgeReport> {
+        Box::pin(MatrixClient::purge_user_messages(
+            self, room_id, user_id, window, ban, progress,
+        ))
+    }
+
+    async fn get_profile(&self) -> Result<Profile> {
+        MatrixClient::get_profile(self).await
+    }
+
+    async fn set_display_name(&mut self, name: &str) -> Result<()> {
+        MatrixClient::set_display_name(self, name).await
+    }
+
+    async fn set_avatar(&self, data: Vec<u8>, mime_type: &str) -> Result<()> {
+        MatrixClient::set_avatar(self, data, mime_type).await
+    }
+
+    async fn set_bio(&self, bio: &str) -> Result<()> {
+        MatrixClient::set_bio(self, bio).await
+    }
+
+    async fn get_favorites(&self) -> Result<Option<Vec<String>>> {
+        MatrixClient::get_favorites(self).await
+    }
+
+    async fn set_favorites(&self, rooms: Vec<String>) -> Result<()> {
+        MatrixClient::set_favorites(self, rooms).await
+    }
+
+    async fn sticker_packs(&self, room_id: &str) -> Result<Vec<StickerPack>> {
+        MatrixClient::sticker_packs(self, room_id).await
+    }
+
+    async fn join_voice(&self, room_id: &str, candidates: Vec<String>) -> Result<()> {
+        MatrixClient::join_voice(self, room_id, candidates).await
+    }
+
+    async fn leave_voice(&self, room_id: &str) -> Result<()> {
+        MatrixClient::leave_voice(self, room_id).await
+    }
+
+    async fn stun_servers(&self) -> Result<Vec<SocketAddr>> {
+        Ok(MatrixClient::stun_servers(self).await)
+    }
+
+    async fn voice_members(&self, room_id: &str) -> Result<Vec<VoiceMember>> {
+        MatrixClient::voice_members(self, room_id).await
+    }
+
+    async fn answer_call(&self, room_id: &str, call_id: &str) -> Result<()> {
+        MatrixClient::answer_call(self, room_id, call_id).await
+    }
+
+    async fn hangup_call(&self, room_id: &str, call_id: &str, reason: HangupReason) -> Result<()> {
+        MatrixClient::hangup_call(self, room_id, call_id, reason).await
+    }
+
+    async fn create_channel(&self, space_id: Option<&str>, name: &str) -> Result<String> {
+        MatrixClient::create_channel(self, space_id, name).await
+    }
+
+    async fn remove_channel(
+        &self,
+        space_id: Option<&str>,
+        room_id: &str,
+        removal: ChannelRemoval,
+    ) -> Result<()> {
+        MatrixClient::remove_channel(self, space_id, room_id, removal).await
+    }
+
+    async fn get_roles(&self, room_id: &str) -> Result<Vec<Role>> {
+        MatrixClient::get_roles(self, room_id).await
+    }
+
+    async fn create_role(&self, room_id: &str, role: Role) -> Result<()> {
+        MatrixClient::create_role(self, room_id, role).await
+    }
+
+    async fn set_power_level(&self, room_id: &str, user_id: &str, level: i64) -> Result<()> {
+        MatrixClient::set_power_level(self, room_id, user_id, level).await
+    }
+
+    async fn get_room_settings(&self, room_id: &str) -> Result<RoomSettings> {
+        MatrixClient::get_room_settings(self, room_id).await
+    }
+
+    async fn set_join_rule(&self, room_id: &str, rule: JoinRule) -> Result<()> {
+        MatrixClient::set_join_rule(self, room_id, rule).await
+    }
+
+    async fn set_history_visibility(
+        &self,
+        room_id: &str,
+        visibility: HistoryVisibility,
+    ) -> Result<()> {
+        MatrixClient::set_history_visibility(self, room_id, visibility).await
+    }
+
+    async fn set_guest_access(&self, room_id: &str, allowed: bool) -> Result<()> {
+        MatrixClient::set_guest_access(self, room_id, allowed).await
+    }
+
+    async fn set_slow_mode(&self, room_id: &str, seconds: u64) -> Result<()> {
+        MatrixClient::set_slow_mode(self, room_id, seconds).await
+    }
+
+    async fn get_welcome(&self, room_id: &str) -> Result<Option<(Welcome, Seen)>> {
+        MatrixClient::get_welcome(self, room_id).await
+    }
+
+    async fn set_welcome(&self, room_id: &str, body: &str, must_accept: bool) -> Result<()> {
+        MatrixClient::set_welcome(self, room_id, body, must_accept).await
+    }
+
+    async fn mark_welcome(&self, room_id: &str, event_id: &str, seen: Seen) -> Result<()> {
+        MatrixClient::mark_welcome(self, room_id, event_id, seen).await
+    }
+
+    async fn set_topic(&self, room_id: &str, topic: &str) -> Result<()> {
+        MatrixClient::set_topic(self, room_id, topic).await
+    }
+
+    async fn enable_encryption(&self, room_id: &str) -> Result<()> {
+        MatrixClient::enable_encryption(self, room_id).await
+    }
+
+    async fn get_room_aliases(&self, room_id: &str) -> Result<RoomAliases> {
+        MatrixClient::get_room_aliases(self, room_id).await
+    }
+
+    async fn add_room_alias(&self, room_id: &str, alias: &str) -> Result<String> {
+        MatrixClient::add_room_alias(self, room_id, alias).await
+    }
+
+    async fn remove_room_alias(&self, alias: &str) -> Result<()> {
+        MatrixClient::remove_room_alias(self, alias).await
+    }
+
+    async fn set_canonical_alias(&self, room_id: &str, alias: &str) -> Result<()> {
+        MatrixClient::set_canonical_alias(self, room_id, alias).await
+    }
+
+    async fn cache_size(&self) -> Result<CacheUsage> {
+        Ok(MatrixClient::cache_size(self))
+    }
+
+    async fn clear_cache(&self, category: CacheCategory) -> Result<()> {
+        MatrixClient::clear_cache(self, category).await
+    }
+
+    async fn prune_cache(&self, retention: Retention) -> Result<()> {
+        MatrixClient::prune_cache(self, retention).await
+    }
+
+    async fn clear_local_history(&self) -> Result<()> {
+        MatrixClient::clear_local_history(self).await
+    }
+
+    async fn search_local(&self, query: &str, room_id: Option<&str>) -> Result<Vec<SearchHit>> {
+        Ok(MatrixClient::search_local(self, query, room_id))
+    }
+
+    async fn search_server(&self, query: &str, room_id: Option<&str>) -> Result<Vec<SearchHit>> {
+        MatrixClient::search_server(self, query, room_id).await
+    }
+
+    async fn rebuild_search_index(&self) -> Result<()> {
+        MatrixClient::rebuild_search_index(self);
+        Ok(())
+    }
+
+    async fn run_diagnostics(&self) -> Result<Report> {
+        Ok(MatrixClient::run_diagnostics(self).await)
+    }
+
+    async fn room_security(&self, room_id: &str) -> Result<RoomSecurity> {
+        MatrixClient::room_security(self, room_id).await
+    }
+
+    async fn trust_new_keys(&self, user_id: &str) -> Result<()> {
+        MatrixClient::trust_new_keys(self, user_id).await
+    }
+}
+
+/// Joined spaces and rooms at load time, plus the event stream that keeps
+/// them current. The stream is subscribed before listing so nothing is missed.
+pub struct RoomList {
+    pub spaces: Vec<Space>,
+    pub rooms: Vec<Room>,
+    pub events: broadcast::Receiver<ChatEvent>,
+}
+
+enum Command {
+    Login {
+        homeserver: String,
+        username: String,
+        password: String,
+        reply: oneshot::Sender<Result<(String, String)>>,
+    },
+    Restore {
+        saved: Session,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SwitchAccount {
+        saved: Session,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Register {
+        homeserver: String,
+        username: String,
+        password: String,
+        reply: oneshot::Sender<Result<(String, String)>>,
+    },
+    CheckUsername {
+        homeserver: String,
+        username: String,
+        reply: oneshot::Sender<Result<Availability>>,
+    },
+    CheckServer {
+        homeserver: String,
+        reply: oneshot::Sender<Result<ServerSupport>>,
+    },
+    ServerStatus {
+        homeserver: String,
+        reply: oneshot::Sender<Result<ServerStatus>>,
+    },
+    RequestPasswordReset {
+        homeserver: String,
+        email: String,
+        reply: oneshot::Sender<Result<PasswordReset>>,
+    },
+    ResendPasswordReset {
+        homeserver: String,
+        reset: PasswordReset,
+        reply: oneshot::Sender<Result<PasswordReset>>,
+    },
+    SubmitResetToken {
+        homeserver: String,
+        reset: PasswordReset,
+        token: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    CompletePasswordReset {
+        homeserver: String,
+        reset: PasswordReset,
+        new_password: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SwitchRoom {
+        room_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SendMessage {
+        /// `None` for the active room.
+        room_id: Option<String>,
+        body: String,
+        mentions: Vec<Mention>,
+        reply_to: Option<String>,
+        txn_id: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    SendEmote {
+        room_id: String,
+        action: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    SendSticker {
+        room_id: String,
+        sticker: Sticker,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    SendGameInvite {
+        room_id: String,
+        invite: GameInvite,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    EditMessage {
+        room_id: String,
+        event_id: String,
+        body: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    DeleteMessage {
+        room_id: String,
+        event_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    React {
+        room_id: String,
+        event_id: String,
+        key: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    ForwardMessage {
+        src_room: String,
+        event_id: String,
+        dst_room: String,
+        annotation: Option<String>,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    PurgeUserMessages {
+        room_id: String,
+        user_id: String,
+        window: PurgeWindow,
+        ban: bool,
+        progress: watch::Sender<PurgeProgress>,
+        reply: oneshot::Sender<Result<PurgeReport>>,
+    },
+    ReportMessage {
+        room_id: String,
+        event_id: String,
+        score: Option<i64>,
+        reason: Option<String>,
+        reply: oneshot::Sender<Result<Reported>>,
+    },
+    ReportRoom {
+        room_id: String,
+        reason: Option<String>,
+        reply: oneshot::Sender<Result<Reported>>,
+    },
+    IgnoreUser {
+        user_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetPermissions {
+        room_id: String,
+        reply: oneshot::Sender<Result<Permissions>>,
+    },
+    GetPinned {
+        room_id: String,
+        reply: oneshot::Sender<Result<Vec<String>>>,
+    },
+    PinMessage {
+        room_id: String,
+        event_id: String,
+        pin: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetEvent {
+        room_id: String,
+        event_id: String,
+        reply: oneshot::Sender<Result<Message>>,
+    },
+    GetEventDetails {
+        room_id: String,
+        event_id: String,
+        reply: oneshot::Sender<Result<EventDetails>>,
+    },
+    Logout {
+        reply: oneshot::Sender<Result<()>>,
+    },
+    LoadRooms {
+        concurrency: usize,
+        reply: oneshot::Sender<Result<RoomList>>,
+    },
+    Notifications {
+        reply: oneshot::Sender<Result<broadcast::Receiver<NotificationEvent>>>,
+    },
+    Typing {
+        reply: oneshot::Sender<Result<broadcast::Receiver<TypingEvent>>>,
+    },
+    Connection {
+        reply: oneshot::Sender<Result<Connection>>,
+    },
+    SetTyping {
+        room_id: String,
+        typing: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    History {
+        room_id: String,
+        from: Option<String>,
+        cancel: CancelToken,
+        reply: oneshot::Sender<Result<MessagePage>>,
+    },
+    NewerHistory {
+        room_id: String,
+        from: String,
+        cancel: CancelToken,
+        reply: oneshot::Sender<Result<NewerPage>>,
+    },
+    Context {
+        room_id: String,
+        event_id: String,
+        cancel: CancelToken,
+        reply: oneshot::Sender<Result<ContextPage>>,
+    },
+    StateHistory {
+        room_id: String,
+        kinds: Vec<AuditKind>,
+        from: Option<String>,
+        reply: oneshot::Sender<Result<StateHistoryPage>>,
+    },
+    MarkRead {
+        room_id: String,
+        event_id: String,
+        ts: u64,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    FullyRead {
+        room_id: String,
+        reply: oneshot::Sender<Result<Option<String>>>,
+    },
+    JoinRoom {
+        id_or_alias: String,
+        via: Vec<String>,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    PreviewRoom {
+        id_or_alias: String,
+        via: Vec<String>,
+        reply: oneshot::Sender<Result<RoomPreview>>,
+    },
+    KnockOnRoom {
+        id_or_alias: String,
+        via: Vec<String>,
+        reason: Option<String>,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    RetractKnock {
+        room_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    KnockRequests {
+        reply: oneshot::Sender<Result<Vec<KnockRequest>>>,
+    },
+    AnswerKnock {
+        room_id: String,
+        user_id: String,
+        approve: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    OpenDm {
+        user_id: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    GetFriends {
+        reply: oneshot::Sender<Result<Vec<Friend>>>,
+    },
+    AddFriend {
+        user_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    RespondToFriend {
+        user_id: String,
+        accept: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    RemoveFriend {
+        user_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    InviteUser {
+        room_id: String,
+        user_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetPresence {
+        status: UserStatus,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetStatusMessage {
+        text: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetKeywords {
+        keywords: Vec<String>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    ListPushers {
+        reply: oneshot::Sender<Result<Vec<Pusher>>>,
+    },
+    RegisterPushers {
+        config: PusherConfig,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    RemovePusher {
+        app_id: String,
+        pushkey: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Get3pids {
+        reply: oneshot::Sender<Result<Vec<Threepid>>>,
+    },
+    RequestEmailToken {
+        email: String,
+        reply: oneshot::Sender<Result<EmailValidation>>,
+    },
+    ResendEmailToken {
+        validation: EmailValidation,
+        reply: oneshot::Sender<Result<EmailValidation>>,
+    },
+    Add3pid {
+        validation: EmailValidation,
+        password: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Remove3pid {
+        email: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetRoomMembers {
+        room_id: String,
+        full: bool,
+        reply: oneshot::Sender<Result<MemberList>>,
+    },
+    GetMemberAvatar {
+        room_id: String,
+        user_id: String,
+        reply: oneshot::Sender<Result<Option<Vec<u8>>>>,
+    },
+    DownloadMedia {
+        source: String,
+        reply: oneshot::Sender<Result<Vec<u8>>>,
+    },
+    DownloadThumbnail {
+        attachment: Attachment,
+        reply: oneshot::Sender<Result<Vec<u8>>>,
+    },
+    GetUrlPreview {
+        url: String,
+        ts: u64,
+        reply: oneshot::Sender<Result<Option<LinkPreview>>>,
+    },
+    UploadLimit {
+        reply: oneshot::Sender<Result<u64>>,
+    },
+    SendAttachment {
+        room_id: String,
+        upload: Upload,
+        txn_id: String,
+        progress: watch::Sender<UploadProgress>,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    SendVoiceMessage {
+        room_id: String,
+        data: Vec<u8>,
+        duration: Duration,
+        txn_id: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    GetProfile {
+        reply: oneshot::Sender<Result<Profile>>,
+    },
+    SetDisplayName {
+        name: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetAvatar {
+        data: Vec<u8>,
+        mime_type: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetBio {
+        bio: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetFavorites {
+        reply: oneshot::Sender<Result<Option<Vec<String>>>>,
+    },
+    SetFavorites {
+        rooms: Vec<String>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    StickerPacks {
+        room_id: String,
+        reply: oneshot::Sender<Result<Vec<StickerPack>>>,
+    },
+    JoinVoice {
+        room_id: String,
+        candidates: Vec<String>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    LeaveVoice {
+        room_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    StunServers {
+        reply: oneshot::Sender<Result<Vec<SocketAddr>>>,
+    },
+    VoiceMembers {
+        room_id: String,
+        reply: oneshot::Sender<Result<Vec<VoiceMember>>>,
+    },
+    AnswerCall {
+        room_id: String,
+        call_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    HangupCall {
+        room_id: String,
+        call_id: String,
+        reason: HangupReason,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    CreateChannel {
+        space_id: Option<String>,
+        name: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    RemoveChannel {
+        space_id: Option<String>,
+        room_id: String,
+        removal: ChannelRemoval,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetRoles {
+        room_id: String,
+        reply: oneshot::Sender<Result<Vec<Role>>>,
+    },
+    CreateRole {
+        room_id: String,
+        role: Role,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetPowerLevel {
+        room_id: String,
+        user_id: String,
+        level: i64,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetRoomSettings {
+        room_id: String,
+        reply: oneshot::Sender<Result<RoomSettings>>,
+    },
+    SetJoinRule {
+        room_id: String,
+        rule: JoinRule,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetHistoryVisibility {
+        room_id: String,
+        visibility: HistoryVisibility,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetGuestAccess {
+        room_id: String,
+        allowed: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetSlowMode {
+        room_id: String,
+        seconds: u64,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetWelcome {
+        room_id: String,
+        reply: oneshot::Sender<Result<Option<(Welcome, Seen)>>>,
+    },
+    SetWelcome {
+        room_id: String,
+        body: String,
+        must_accept: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    MarkWelcome {
+        room_id: String,
+        event_id: String,
+        seen: Seen,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetTopic {
+        room_id: String,
+        topic: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    EnableEncryption {
+        room_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    GetRoomAliases {
+        room_id: String,
+        reply: oneshot::Sender<Result<RoomAliases>>,
+    },
+    AddRoomAlias {
+        room_id: String,
+        alias: String,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    RemoveRoomAlias {
+        alias: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetCanonicalAlias {
+        room_id: String,
+        alias: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    CacheSize {
+        reply: oneshot::Sender<Result<CacheUsage>>,
+    },
+    ClearCache {
+        category: CacheCategory,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    PruneCache {
+        retention: Retention,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    ClearLocalHistory {
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SearchLocal {
+        query: String,
+        room_id: Option<String>,
+        reply: oneshot::Sender<Result<Vec<SearchHit>>>,
+    },
+    SearchServer {
+        query: String,
+        room_id: Option<String>,
+        cancel: CancelToken,
+        reply: oneshot::Sender<Result<Vec<SearchHit>>>,
+    },
+    RebuildSearchIndex {
+        reply: oneshot::Sender<Result<()>>,
+    },
+    RunDiagnostics {
+        reply: oneshot::Sender<Result<Report>>,
+    },
+    RoomSecurity {
+        room_id: String,
+        reply: oneshot::Sender<Result<RoomSecurity>>,
+    },
+    TrustNewKeys {
+        user_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Shutdown {
+        reply: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// Handle to the task that owns the logged-in client.
+///
+/// Commands are processed strictly in the order they are issued, so anything
+/// sent after `login` resolves is guaranteed to see the client it installed.
+#[derive(Clone)]
+pub struct ClientHandle {
+    tx: mpsc::UnboundedSender<Command>,
+    /// Set by `close`: new commands are refused.
+    closed: Arc<AtomicBool>,
+}
+
+impl ClientHandle {
+    /// Spawn the owning task on the current tokio runtime.
+    pub fn spawn() -> Self {
+        Self::spawn_with::<MatrixClient>()
+    }
+
+    /// Spawn the owning task with `B` in place of `MatrixClient`, e.g. to
+    /// replay a recording or in tests.
+    pub fn spawn_with<B: Backend>() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run::<B>(rx));
+        Self {
+            tx,
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Log in with username/password. Returns (user_id, display_name).
+    pub fn login(
+        &self,
+        homeserver: &str,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Output = Result<(String, String)>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::Login {
+                homeserver: homeserver.to_string(),
+                username: username.to_string(),
+                password: password.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Restore a saved session and make it the active client.
+    pub fn restore(&self, saved: Session) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::Restore { saved, reply }, rx)
+    }
+
+    /// Show `saved`'s account, keeping the one shown until now signed in in
+    /// the background. An account already held is picked up where it was;
+    /// any other is restored.
+    pub fn switch_account(
+        &self,
+        saved: Session,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::SwitchAccount { saved, reply }, rx)
+    }
+
+    /// Register a new account and make it the active client, like `login`.
+    pub fn register(
+        &self,
+        homeserver: &str,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Output = Result<(String, String)>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::Register {
+                homeserver: homeserver.to_string(),
+                username: username.to_string(),
+                password: password.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Check whether `username` is free on `homeserver`.
+    pub fn check_username(
+        &self,
+        homeserver: &str,
+        username: &str,
+    ) -> impl Future<Output = Result<Availability>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::CheckUsername {
+                homeserver: homeserver.to_string(),
+                username: username.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Find `homeserver` and what it supports, before signing in to it.
+    pub fn check_server(
+        &self,
+        homeserver: &str,
+    ) -> impl Future<Output = Result<ServerSupport>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let homeserver = homeserver.to_string();
+        self.dispatch(Command::CheckServer { homeserver, reply }, rx)
+    }
+
+    /// How quickly `homeserver` answers and what it runs. Failing to reach
+    /// it is a [`DiscoveryError`].
+    pub fn server_status(
+        &self,
+        homeserver: &str,
+    ) -> impl Future<Output = Result<ServerStatus>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let homeserver = homeserver.to_string();
+        self.dispatch(Command::ServerStatus { homeserver, reply }, rx)
+    }
+
+    /// Have `homeserver` mail `email` a link to reset the password of the
+    /// account it is on, before signing in.
+    pub fn request_password_reset(
+        &self,
+        homeserver: &str,
+        email: &str,
+    ) -> impl Future<Output = Result<PasswordReset>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::RequestPasswordReset {
+                homeserver: homeserver.to_string(),
+                email: email.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn resend_password_reset(
+        &self,
+        homeserver: &str,
+        reset: PasswordReset,
+    ) -> impl Future<Output = Result<PasswordReset>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let homeserver = homeserver.to_string();
+        self.dispatch(
+            Command::ResendPasswordReset {
+                homeserver,
+                reset,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Submit the code from the reset mail, on servers that mail one.
+    pub fn submit_reset_token(
+        &self,
+        homeserver: &str,
+        reset: PasswordReset,
+        token: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SubmitResetToken {
+                homeserver: homeserver.to_string(),
+                reset,
+                token: token.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Set `new_password` once the reset was validated. Fails with
+    /// `ResetError::NotValidated` until then, so it can be tried again.
+    pub fn complete_password_reset(
+        &self,
+        homeserver: &str,
+        reset: PasswordReset,
+        new_password: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::CompletePasswordReset {
+                homeserver: homeserver.to_string(),
+                reset,
+                new_password: new_password.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Set the room subsequent `send_message` calls are delivered to.
+    pub fn switch_room(&self, room_id: &str) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::SwitchRoom { room_id, reply }, rx)
+    }
+
+    /// Send a text message to the active room, returning its event id.
+    ///
+    /// Sends issued before login are held, in order, until a client is
+    /// installed; they fail if the session is logged out first. The app
+    /// sends with `send_to`, which names the room.
+    #[cfg(test)]
+    pub fn send_message(
+        &self,
+        body: &str,
+        txn_id: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        self.send(body, &[], None, txn_id)
+    }
+
+    /// Like `send_message`, mentioning `mentions`, as a reply to `reply_to`
+    /// if given.
+    #[cfg(test)]
+    pub fn send(
+        &self,
+        body: &str,
+        mentions: &[Mention],
+        reply_to: Option<&str>,
+        txn_id: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SendMessage {
+                room_id: None,
+                body: body.to_string(),
+                mentions: mentions.to_vec(),
+                reply_to: reply_to.map(str::to_owned),
+                txn_id: txn_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Like `send`, to `room_id` rather than the active room.
+    pub fn send_to(
+        &self,
+        room_id: &str,
+        body: &str,
+        mentions: &[Mention],
+        reply_to: Option<&str>,
+        txn_id: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SendMessage {
+                room_id: Some(room_id.to_string()),
+                body: body.to_string(),
+                mentions: mentions.to_vec(),
+                reply_to: reply_to.map(str::to_owned),
+                txn_id: txn_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Send `action` into `room_id` as an emote.
+    pub fn send_emote(
+        &self,
+        room_id: &str,
+        action: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SendEmote {
+                room_id: room_id.to_string(),
+                action: action.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Send `sticker` into `room_id`.
+    pub fn send_sticker(
+        &self,
+        room_id: &str,
+        sticker: Sticker,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SendSticker {
+                room_id,
+                sticker,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Invite `room_id` to a game.
+    pub fn send_game_invite(
+        &self,
+        room_id: &str,
+        invite: GameInvite,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SendGameInvite {
+                room_id,
+                invite,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn edit_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        body: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::EditMessage {
+                room_id: room_id.to_string(),
+                event_id: event_id.to_string(),
+                body: body.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Delete a message or take back a reaction.
+    pub fn delete_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::DeleteMessage {
+                room_id: room_id.to_string(),
+                event_id: event_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn react_to_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::React {
+                room_id: room_id.to_string(),
+                event_id: event_id.to_string(),
+                key: key.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Send `event_id` from `src_room` again in `dst_room`, with
+    /// `annotation` above text.
+    pub fn forward_message(
+        &self,
+        src_room: &str,
+        event_id: &str,
+        dst_room: &str,
+        annotation: Option<&str>,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::ForwardMessage {
+                src_room: src_room.to_string(),
+                event_id: event_id.to_string(),
+                dst_room: dst_room.to_string(),
+                annotation: annotation.map(str::to_string),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Remove `user_id`'s messages in `room_id` within `window`, banning
+    /// them first if `ban`, reporting progress on the returned receiver.
+    pub fn purge_user_messages(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        window: PurgeWindow,
+        ban: bool,
+    ) -> (
+        impl Future<Output = Result<PurgeReport>> + Send + 'static,
+        watch::Receiver<PurgeProgress>,
+    ) {
+        let (reply, rx) = oneshot::channel();
+        let (progress, progress_rx) = watch::channel(PurgeProgress::default());
+        let purged = self.dispatch(
+            Command::PurgeUserMessages {
+                room_id: room_id.to_string(),
+                user_id: user_id.to_string(),
+                window,
+                ban,
+                progress,
+                reply,
+            },
+            rx,
+        );
+        (purged, progress_rx)
+    }
+
+    /// Report `event_id` to the homeserver's admins.
+    pub fn report_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        score: Option<i64>,
+        reason: Option<&str>,
+    ) -> impl Future<Output = Result<Reported>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::ReportMessage {
+                room_id: room_id.to_string(),
+                event_id: event_id.to_string(),
+                score,
+                reason: reason.map(str::to_string),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Report `room_id` itself to the homeserver's admins.
+    pub fn report_room(
+        &self,
+        room_id: &str,
+        reason: Option<&str>,
+    ) -> impl Future<Output = Result<Reported>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::ReportRoom {
+                room_id: room_id.to_string(),
+                reason: reason.map(str::to_string),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Stop receiving anything from `user_id`.
+    pub fn ignore_user(&self, user_id: &str) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let user_id = user_id.to_string();
+        self.dispatch(Command::IgnoreUser { user_id, reply }, rx)
+    }
+
+    /// What we may do with messages in `room_id`.
+    pub fn get_permissions(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Permissions>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::GetPermissions { room_id, reply }, rx)
+    }
+
+    /// The messages pinned in `room_id`, oldest pin first.
+    pub fn get_pinned(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Vec<String>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::GetPinned { room_id, reply }, rx)
+    }
+
+    /// Pin message `event_id` in `room_id`, or unpin it.
+    pub fn pin_message(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        pin: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.dispatch(
+            Command::PinMessage {
+                room_id,
+                event_id,
+                pin,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Message `event_id` of `room_id`, loaded on its own.
+    pub fn get_event(
+        &self,
+        room_id: &str,
+        event_id: &str,
+    ) -> impl Future<Output = Result<Message>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.dispatch(
+            Command::GetEvent {
+                room_id,
+                event_id,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// The exact times and encryption of message `event_id` of `room_id`.
+    pub fn get_event_details(
+        &self,
+        room_id: &str,
+        event_id: &str,
+    ) -> impl Future<Output = Result<EventDetails>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.dispatch(
+            Command::GetEventDetails {
+                room_id,
+                event_id,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn logout(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::Logout { reply }, rx)
+    }
+
+    /// Start syncing (if needed) and list joined spaces and rooms,
+    /// resolving `concurrency` of them at a time.
+    pub fn load_rooms(
+        &self,
+        concurrency: usize,
+    ) -> impl Future<Output = Result<RoomList>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::LoadRooms { concurrency, reply }, rx)
+    }
+
+    /// Subscribe to the logged-in client's notification stream.
+    pub fn notifications(
+        &self,
+    ) -> impl Future<Output = Result<broadcast::Receiver<NotificationEvent>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::Notifications { reply }, rx)
+    }
+
+    /// Subscribe to the logged-in client's typing notifications.
+    pub fn typing(
+        &self,
+    ) -> impl Future<Output = Result<broadcast::Receiver<TypingEvent>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::Typing { reply }, rx)
+    }
+
+    /// Follow whether the logged-in client reaches the homeserver.
+    pub fn connection(&self) -> impl Future<Output = Result<Connection>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::Connection { reply }, rx)
+    }
+
+    pub fn set_typing(
+        &self,
+        room_id: &str,
+        typing: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SetTyping {
+                room_id,
+                typing,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Fetch a page of history for `room_id`, ending at `from` if given.
+    /// Cancelling `cancel` abandons the request (queued or in flight) so it
+    /// no longer holds up later commands.
+    pub fn history(
+        &self,
+        room_id: &str,
+        from: Option<String>,
+        cancel: CancelToken,
+    ) -> impl Future<Output = Result<MessagePage>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::History {
+                room_id,
+                from,
+                cancel,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Fetch the page of `room_id` after `from`, going forwards. Cancelled
+    /// as `history` is.
+    pub fn newer_history(
+        &self,
+        room_id: &str,
+        from: String,
+        cancel: CancelToken,
+    ) -> impl Future<Output = Result<NewerPage>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::NewerHistory {
+                room_id,
+                from,
+                cancel,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Fetch a page of `room_id`'s audit log, changes of `kinds` going back
+    /// from `from`, or from the latest.
+    pub fn state_history(
+        &self,
+        room_id: &str,
+        kinds: Vec<AuditKind>,
+        from: Option<String>,
+    ) -> impl Future<Output = Result<StateHistoryPage>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::StateHistory {
+                room_id,
+                kinds,
+                from,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Fetch `event_id` of `room_id` and the messages around it. Cancelled
+    /// as `history` is.
+    pub fn context(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        cancel: CancelToken,
+    ) -> impl Future<Output = Result<ContextPage>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.dispatch(
+            Command::Context {
+                room_id,
+                event_id,
+                cancel,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Mark `room_id` as read up to `event_id`, sent at `ts`.
+    pub fn mark_read(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        ts: u64,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.dispatch(
+            Command::MarkRead {
+                room_id,
+                event_id,
+                ts,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// The event our fully-read marker in `room_id` is on, if any.
+    pub fn fully_read(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Option<String>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::FullyRead { room_id, reply }, rx)
+    }
+
+    /// Join a room by id or alias, returning its room id.
+    pub fn join_room(
+        &self,
+        id_or_alias: &str,
+        via: Vec<String>,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let id_or_alias = id_or_alias.to_string();
+        self.dispatch(
+            Command::JoinRoom {
+                id_or_alias,
+                via,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Look into a room not joined, by room id or alias: its latest page
+    /// of messages if anyone may read them, otherwise its directory entry.
+    pub fn preview_room(
+        &self,
+        id_or_alias: &str,
+        via: Vec<String>,
+    ) -> impl Future<Output = Result<RoomPreview>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let id_or_alias = id_or_alias.to_string();
+        self.dispatch(
+            Command::PreviewRoom {
+                id_or_alias,
+                via,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Ask to join a room by id or alias, returning its room id.
+    pub fn knock_on_room(
+        &self,
+        id_or_alias: &str,
+        via: Vec<String>,
+        reason: Option<&str>,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let id_or_alias = id_or_alias.to_string();
+        let reason = reason.map(str::to_string);
+        self.dispatch(
+            Command::KnockOnRoom {
+                id_or_alias,
+                via,
+                reason,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn retract_knock(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::RetractKnock { room_id, reply }, rx)
+    }
+
+    /// Knocks waiting in rooms we can let people into.
+    pub fn knock_requests(
+        &self,
+    ) -> impl Future<Output = Result<Vec<KnockRequest>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::KnockRequests { reply }, rx)
+    }
+
+    /// Let `user_id`'s knock on `room_id` in if `approve`, or turn it away.
+    pub fn answer_knock(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        approve: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let user_id = user_id.to_string();
+        self.dispatch(
+            Command::AnswerKnock {
+                room_id,
+                user_id,
+                approve,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Find or create the direct message room with `user_id`.
+    pub fn open_dm(&self, user_id: &str) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let user_id = user_id.to_string();
+        self.dispatch(Command::OpenDm { user_id, reply }, rx)
+    }
+
+    pub fn get_friends(&self) -> impl Future<Output = Result<Vec<Friend>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::GetFriends { reply }, rx)
+    }
+
+    /// Ask `user_id` to be friends, in the direct chat with them.
+    pub fn add_friend(&self, user_id: &str) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let user_id = user_id.to_string();
+        self.dispatch(Command::AddFriend { user_id, reply }, rx)
+    }
+
+    /// Accept or decline `user_id`'s friend request.
+    pub fn respond_to_friend(
+        &self,
+        user_id: &str,
+        accept: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let user_id = user_id.to_string();
+        self.dispatch(
+            Command::RespondToFriend {
+                user_id,
+                accept,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn remove_friend(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let user_id = user_id.to_string();
+        self.dispatch(Command::RemoveFriend { user_id, reply }, rx)
+    }
+
+    pub fn invite_user(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::InviteUser {
+                room_id: room_id.to_string(),
+                user_id: user_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn set_presence(
+        &self,
+        status: UserStatus,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::SetPresence { status, reply }, rx)
+    }
+
+    pub fn set_status_message(
+        &self,
+        text: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let text = text.to_string();
+        self.dispatch(Command::SetStatusMessage { text, reply }, rx)
+    }
+
+    /// Mirror notification keywords into the account's push rules.
+    pub fn set_keywords(
+        &self,
+        keywords: Vec<String>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::SetKeywords { keywords, reply }, rx)
+    }
+
+    pub fn list_pushers(&self) -> impl Future<Output = Result<Vec<Pusher>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::ListPushers { reply }, rx)
+    }
+
+    /// Bring the account's pushers from this app in line with `config`.
+    /// Nothing is sent when they already are, so this runs on every sign in.
+    pub fn register_pushers(
+        &self,
+        config: PusherConfig,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::RegisterPushers { config, reply }, rx)
+    }
+
+    pub fn remove_pusher(
+        &self,
+        app_id: &str,
+        pushkey: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let app_id = app_id.to_string();
+        let pushkey = pushkey.to_string();
+        self.dispatch(
+            Command::RemovePusher {
+                app_id,
+                pushkey,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn get_3pids(&self) -> impl Future<Output = Result<Vec<Threepid>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::Get3pids { reply }, rx)
+    }
+
+    /// Have the homeserver mail a link to `email`. The address is added
+    /// with `add_3pid` once the link was clicked.
+    pub fn request_email_token(
+        &self,
+        email: &str,
+    ) -> impl Future<Output = Result<EmailValidation>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let email = email.to_string();
+        self.dispatch(Command::RequestEmailToken { email, reply }, rx)
+    }
+
+    /// Mail the link again. Either mail's link works.
+    pub fn resend_email_token(
+        &self,
+        validation: EmailValidation,
+    ) -> impl Future<Output = Result<EmailValidation>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::ResendEmailToken { validation, reply }, rx)
+    }
+
+    pub fn add_3pid(
+        &self,
+        validation: EmailValidation,
+        password: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let password = password.to_string();
+        self.dispatch(
+            Command::Add3pid {
+                validation,
+                password,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn remove_3pid(&self, email: &str) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let email = email.to_string();
+        self.dispatch(Command::Remove3pid { email, reply }, rx)
+    }
+
+    /// Joined members of `room_id`. Only those already known unless `full`,
+    /// which can take a while in large rooms.
+    pub fn get_room_members(
+        &self,
+        room_id: &str,
+        full: bool,
+    ) -> impl Future<Output = Result<MemberList>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::GetRoomMembers {
+                room_id,
+                full,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn get_member_avatar(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::GetMemberAvatar {
+                room_id: room_id.to_string(),
+                user_id: user_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// The whole content of an attachment.
+    pub fn download_media(
+        &self,
+        source: &str,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let source = source.to_string();
+        self.dispatch(Command::DownloadMedia { source, reply }, rx)
+    }
+
+    /// The rendition of an image attachment shown in the timeline.
+    pub fn download_thumbnail(
+        &self,
+        attachment: &Attachment,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let attachment = attachment.clone();
+        self.dispatch(Command::DownloadThumbnail { attachment, reply }, rx)
+    }
+
+    /// The preview card of `url`, linked in a message sent at `ts` (ms).
+    pub fn get_url_preview(
+        &self,
+        url: &str,
+        ts: u64,
+    ) -> impl Future<Output = Result<Option<LinkPreview>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let url = url.to_string();
+        self.dispatch(Command::GetUrlPreview { url, ts, reply }, rx)
+    }
+
+    /// Largest upload the homeserver accepts, in bytes.
+    pub fn upload_limit(&self) -> impl Future<Output = Result<u64>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::UploadLimit { reply }, rx)
+    }
+
+    /// Send `upload` to `room_id` under `txn_id`, reporting progress on the
+    /// returned receiver. Resolves to the event id.
+    pub fn send_attachment(
+        &self,
+        room_id: &str,
+        upload: Upload,
+        txn_id: &str,
+    ) -> (
+        impl Future<Output = Result<String>> + Send + 'static,
+        watch::Receiver<UploadProgress>,
+    ) {
+        let (reply, rx) = oneshot::channel();
+        let (progress, progress_rx) = watch::channel(UploadProgress::default());
+        let sent = self.dispatch(
+            Command::SendAttachment {
+                room_id: room_id.to_string(),
+                upload,
+                txn_id: txn_id.to_string(),
+                progress,
+                reply,
+            },
+            rx,
+        );
+        (sent, progress_rx)
+    }
+
+    /// Send a recorded voice clip, the WAV file `data` lasting `duration`,
+    /// to `room_id` under `txn_id`. Resolves to the event id.
+    pub fn send_voice_message(
+        &self,
+        room_id: &str,
+        data: Vec<u8>,
+        duration: Duration,
+        txn_id: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SendVoiceMessage {
+                room_id: room_id.to_string(),
+                data,
+                duration,
+                txn_id: txn_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Load our display name, avatar thumbnail and bio.
+    pub fn get_profile(&self) -> impl Future<Output = Result<Profile>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::GetProfile { reply }, rx)
+    }
+
+    pub fn set_display_name(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let name = name.to_string();
+        self.dispatch(Command::SetDisplayName { name, reply }, rx)
+    }
+
+    /// Upload an encoded image as our avatar.
+    pub fn set_avatar(
+        &self,
+        data: Vec<u8>,
+        mime_type: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let mime_type = mime_type.to_string();
+        self.dispatch(
+            Command::SetAvatar {
+                data,
+                mime_type,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn set_bio(&self, bio: &str) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let bio = bio.to_string();
+        self.dispatch(Command::SetBio { bio, reply }, rx)
+    }
+
+    /// The pinned rooms as account data has them; `None` if never stored.
+    pub fn get_favorites(
+        &self,
+    ) -> impl Future<Output = Result<Option<Vec<String>>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::GetFavorites { reply }, rx)
+    }
+
+    /// The sticker packs to offer in `room_id`.
+    pub fn sticker_packs(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Vec<StickerPack>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::StickerPacks { room_id, reply }, rx)
+    }
+
+    pub fn set_favorites(
+        &self,
+        rooms: Vec<String>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::SetFavorites { rooms, reply }, rx)
+    }
+
+    pub fn join_voice(
+        &self,
+        room_id: &str,
+        candidates: Vec<String>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::JoinVoice {
+                room_id: room_id.to_string(),
+                candidates,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn leave_voice(&self, room_id: &str) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::LeaveVoice { room_id, reply }, rx)
+    }
+
+    /// The homeserver's STUN servers, for voice to find its public address.
+    pub fn stun_servers(&self) -> impl Future<Output = Result<Vec<SocketAddr>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::StunServers { reply }, rx)
+    }
+
+    pub fn voice_members(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Vec<VoiceMember>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::VoiceMembers { room_id, reply }, rx)
+    }
+
+    pub fn answer_call(
+        &self,
+        room_id: &str,
+        call_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::AnswerCall {
+                room_id: room_id.to_string(),
+                call_id: call_id.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn hangup_call(
+        &self,
+        room_id: &str,
+        call_id: &str,
+        reason: HangupReason,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::HangupCall {
+                room_id: room_id.to_string(),
+                call_id: call_id.to_string(),
+                reason,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Create a channel named `name`, in `space_id` if given. Resolves to
+    /// its room id; the channel list updates from sync.
+    pub fn create_channel(
+        &self,
+        space_id: Option<&str>,
+        name: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::CreateChannel {
+                space_id: space_id.map(str::to_owned),
+                name: name.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn remove_channel(
+        &self,
+        space_id: Option<&str>,
+        room_id: &str,
+        removal: ChannelRemoval,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::RemoveChannel {
+                space_id: space_id.map(str::to_owned),
+                room_id: room_id.to_string(),
+                removal,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Roles defined in `room_id`, highest power level first.
+    pub fn get_roles(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Vec<Role>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::GetRoles { room_id, reply }, rx)
+    }
+
+    pub fn create_role(
+        &self,
+        room_id: &str,
+        role: Role,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::CreateRole {
+                room_id,
+                role,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn set_power_level(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        level: i64,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SetPowerLevel {
+                room_id: room_id.to_string(),
+                user_id: user_id.to_string(),
+                level,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Who can join and read `room_id`, and which of that we may change.
+    pub fn get_room_settings(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<RoomSettings>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::GetRoomSettings { room_id, reply }, rx)
+    }
+
+    pub fn set_join_rule(
+        &self,
+        room_id: &str,
+        rule: JoinRule,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SetJoinRule {
+                room_id,
+                rule,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn set_history_visibility(
+        &self,
+        room_id: &str,
+        visibility: HistoryVisibility,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SetHistoryVisibility {
+                room_id,
+                visibility,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn set_guest_access(
+        &self,
+        room_id: &str,
+        allowed: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SetGuestAccess {
+                room_id,
+                allowed,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Set `room_id`'s slow mode to `seconds` between messages, 0 for off.
+    pub fn set_slow_mode(
+        &self,
+        room_id: &str,
+        seconds: u64,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(
+            Command::SetSlowMode {
+                room_id,
+                seconds,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn get_welcome(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<Option<(Welcome, Seen)>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::GetWelcome { room_id, reply }, rx)
+    }
+
+    /// Set `room_id`'s welcome message, or remove it when `body` is empty.
+    pub fn set_welcome(
+        &self,
+        room_id: &str,
+        body: &str,
+        must_accept: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let body = body.to_string();
+        self.dispatch(
+            Command::SetWelcome {
+                room_id,
+                body,
+                must_accept,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Note that we dismissed or accepted welcome `event_id` of `room_id`.
+    pub fn mark_welcome(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        seen: Seen,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let event_id = event_id.to_string();
+        self.dispatch(
+            Command::MarkWelcome {
+                room_id,
+                event_id,
+                seen,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn set_topic(
+        &self,
+        room_id: &str,
+        topic: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(
+            Command::SetTopic {
+                room_id: room_id.to_string(),
+                topic: topic.to_string(),
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Encrypt `room_id` for good. Sends queued after this one are
+    /// encrypted once it succeeds.
+    pub fn enable_encryption(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::EnableEncryption { room_id, reply }, rx)
+    }
+
+    /// The aliases of `room_id`, the main one first.
+    pub fn get_room_aliases(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<RoomAliases>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::GetRoomAliases { room_id, reply }, rx)
+    }
+
+    /// Publish `alias` for `room_id`, which may leave out the `#` and our
+    /// server, returning it in full.
+    pub fn add_room_alias(
+        &self,
+        room_id: &str,
+        alias: &str,
+    ) -> impl Future<Output = Result<String>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let alias = alias.to_string();
+        self.dispatch(
+            Command::AddRoomAlias {
+                room_id,
+                alias,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    pub fn remove_room_alias(
+        &self,
+        alias: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let alias = alias.to_string();
+        self.dispatch(Command::RemoveRoomAlias { alias, reply }, rx)
+    }
+
+    pub fn set_canonical_alias(
+        &self,
+        room_id: &str,
+        alias: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        let alias = alias.to_string();
+        self.dispatch(
+            Command::SetCanonicalAlias {
+                room_id,
+                alias,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Bytes cached on disk, by category.
+    pub fn cache_size(&self) -> impl Future<Output = Result<CacheUsage>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::CacheSize { reply }, rx)
+    }
+
+    /// Delete what is cached of `category`, but for downloads still going.
+    pub fn clear_cache(
+        &self,
+        category: CacheCategory,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::ClearCache { category, reply }, rx)
+    }
+
+    /// Forget what is cached longer than `retention` keeps, and keep to it.
+    pub fn prune_cache(
+        &self,
+        retention: Retention,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::PruneCache { retention, reply }, rx)
+    }
+
+    /// Delete what this machine keeps of what was seen and sent, leaving the
+    /// server alone.
+    pub fn clear_local_history(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::ClearLocalHistory { reply }, rx)
+    }
+
+    /// Messages loaded this session that match `query`, in `room_id` if
+    /// given, best first.
+    pub fn search_local(
+        &self,
+        query: &str,
+        room_id: Option<String>,
+    ) -> impl Future<Output = Result<Vec<SearchHit>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let query = query.to_string();
+        self.dispatch(
+            Command::SearchLocal {
+                query,
+                room_id,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// The homeserver's matches for `query`, in `room_id` if given.
+    /// Cancelling `cancel` abandons the search, as for `history`.
+    pub fn search_server(
+        &self,
+        query: &str,
+        room_id: Option<String>,
+        cancel: CancelToken,
+    ) -> impl Future<Output = Result<Vec<SearchHit>>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let query = query.to_string();
+        self.dispatch(
+            Command::SearchServer {
+                query,
+                room_id,
+                cancel,
+                reply,
+            },
+            rx,
+        )
+    }
+
+    /// Start indexing the latest messages of every room afresh.
+    pub fn rebuild_search_index(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::RebuildSearchIndex { reply }, rx)
+    }
+
+    /// Run every health check, for the diagnostics panel. Takes a few
+    /// seconds.
+    pub fn run_diagnostics(&self) -> impl Future<Output = Result<Report>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        self.dispatch(Command::RunDiagnostics { reply }, rx)
+    }
+
+    /// Whether `room_id` is encrypted and who there has unverified devices.
+    pub fn room_security(
+        &self,
+        room_id: &str,
+    ) -> impl Future<Output = Result<RoomSecurity>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let room_id = room_id.to_string();
+        self.dispatch(Command::RoomSecurity { room_id, reply }, rx)
+    }
+
+    /// Take `user_id`'s keys as they are now as the ones we verified. How
+    /// far they are trusted with them comes as `SenderTrustChanged`.
+    pub fn trust_new_keys(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let user_id = user_id.to_string();
+        self.dispatch(Command::TrustNewKeys { user_id, reply }, rx)
+    }
+
+    /// Refuse commands from here on, as the app quits. Those already issued
+    /// still run, and `shutdown` still goes through.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    /// Drop the client, stopping its sync loop, and end the task once the
+    /// commands before it have run. Unlike `logout` the session stays valid
+    /// for the next start.
+    pub fn shutdown(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let (reply, rx) = oneshot::channel();
+        let queued = self.tx.send(Command::Shutdown { reply }).is_ok();
+        Self::reply(queued, rx)
+    }
+
+    /// Queue `cmd` immediately and return a future for its reply. Queuing
+    /// happens at call time, not on first poll, so the order of calls is the
+    /// order of execution even when the futures are awaited on other tasks.
+    fn dispatch<T: Send + 'static>(
+        &self,
+        cmd: Command,
+        rx: oneshot::Receiver<Result<T>>,
+    ) -> impl Future<Output = Result<T>> + Send + 'static {
+        let queued = !self.closed.load(Ordering::SeqCst) && self.tx.send(cmd).is_ok();
+        Self::reply(queued, rx)
+    }
+
+    async fn reply<T>(queued: bool, rx: oneshot::Receiver<Result<T>>) -> Result<T> {
+        if !queued {
+            return Err(anyhow!("Client task has shut down"));
+        }
+        rx.await
+            .map_err(|_| anyhow!("Client task dropped the request"))?
+    }
+}
+
+/// A send waiting for a client, addressed to the room active when it was issued.
+struct QueuedSend {
+    room_id: String,
+    body: String,
+    mentions: Vec<Mention>,
+    reply_to: Option<String>,
+    txn_id: String,
+    reply: oneshot::Sender<Result<String>>,
+}
+
+async fn send<B: Backend>(
+    mc: &B,
+    room_id: &str,
+    body: &str,
+    mentions: &[Mention],
+    reply_to: Option<&str>,
+    txn_id: &str,
+) -> Result<String> {
+    match reply_to {
+        Some(reply_to) => {
+            mc.send_reply(room_id, body, mentions, reply_to, txn_id)
+                .await
+        }
+        None => mc.send_message(room_id, body, mentions, txn_id).await,
+    }
+}
+
+async fn flush<B: Backend>(mc: &B, queued: &mut Vec<QueuedSend>) {
+    for pending in queued.drain(..) {
+        let result = send(
+            mc,
+            &pending.room_id,
+            &pending.body,
+            &pending.mentions,
+            pending.reply_to.as_deref(),
+            &pending.txn_id,
+        )
+        .await;
+        let _ = pending.reply.send(result);
+    }
+}
+
+/// The unauthenticated connection to `homeserver`, reusing the last one if
+/// it was for the same server.
+async fn guest_client<'a, B: Backend>(
+    guest: &'a mut Option<(String, B)>,
+    homeserver: &str,
+) -> Result<&'a mut B> {
+    if !matches!(guest, Some((server, _)) if server == homeserver) {
+        *guest = Some((homeserver.to_string(), B::connect(homeserver).await?));
+    }
+    Ok(&mut guest.as_mut().unwrap().1)
+}
+
+/// Make `mc`, signed in as `user_id`, the active client. The client it
+/// replaces stays signed in, syncing in the background.
+async fn show<B: Backend>(
+    client: &mut Option<B>,
+    shown: &mut Option<String>,
+    idle: &mut Vec<(String, B)>,
+    mc: B,
+    user_id: String,
+) {
+    idle.retain(|(id, _)| *id != user_id);
+    if let (Some(mut previous), Some(previous_id)) = (client.take(), shown.take()) {
+        if previous_id != user_id {
+            previous.set_background(true).await;
+            idle.push((previous_id, previous));
+        }
+    }
+    *client = Some(mc);
+    *shown = Some(user_id);
+}
+
+async fn run<B: Backend>(mut rx: mpsc::UnboundedReceiver<Command>) {
+    let mut client: Option<B> = None;
+    // Who `client` is signed in as, and the other accounts signed in.
+    let mut shown: Option<String> = None;
+    let mut idle: Vec<(String, B)> = Vec::new();
+    // Connection used for username checks and registration.
+    let mut guest: Option<(String, B)> = None;
+    let mut active_room: Option<String> = None;
+    let mut queued: Vec<QueuedSend> = Vec::new();
+
+    while let Some(cmd) = rx.recv().await {
+        match cmd {
+            Command::Login {
+                homeserver,
+                username,
+                password,
+                reply,
+            } => {
+                let result = match B::login(&homeserver, &username, &password).await {
+                    Ok((mc, user_id, display_name)) => {
+                        show(&mut client, &mut shown, &mut idle, mc, user_id.clone()).await;
+                        Ok((user_id, display_name))
+                    }
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+                if let Some(mc) = &client {
+                    flush(mc, &mut queued).await;
+                }
+            }
+            Command::Restore { saved, reply } => {
+                let result = match B::restore(&saved).await {
+                    Ok(mc) => {
+                        show(&mut client, &mut shown, &mut idle, mc, saved.user_id).await;
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+                if let Some(mc) = &client {
+                    flush(mc, &mut queued).await;
+                }
+            }
+            Command::SwitchAccount { saved, reply } => {
+                if shown.as_deref() == Some(saved.user_id.as_str()) {
+                    let _ = reply.send(Ok(()));
+                    continue;
+                }
+                let held = idle
+                    .iter()
+                    .position(|(id, _)| *id == saved.user_id)
+                    .map(|i| idle.remove(i).1);
+                let result = match held {
+                    Some(mut mc) => {
+                        mc.set_background(false).await;
+                        Ok(mc)
+                    }
+                    None => B::restore(&saved).await,
+                };
+                let result = match result {
+                    Ok(mc) => {
+                        active_room = None;
+                        show(&mut client, &mut shown, &mut idle, mc, saved.user_id).await;
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Register {
+                homeserver,
+                username,
+                password,
+                reply,
+            } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.register(&username, &password).await,
+                    Err(e) => Err(e),
+                };
+                if let (Ok((user_id, _)), Some((_, mc))) = (&result, guest.take()) {
+                    show(&mut client, &mut shown, &mut idle, mc, user_id.clone()).await;
+                }
+                let _ = reply.send(result);
+                if let Some(mc) = &client {
+                    flush(mc, &mut queued).await;
+                }
+            }
+            Command::CheckUsername {
+                homeserver,
+                username,
+                reply,
+            } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.check_username(&username).await,
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::CheckServer { homeserver, reply } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.server_support().await,
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ServerStatus { homeserver, reply } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.server_status().await,
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result.map_err(|e| DiscoveryError::classify(&e).into()));
+            }
+            Command::RequestPasswordReset {
+                homeserver,
+                email,
+                reply,
+            } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.request_password_reset(&email).await,
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ResendPasswordReset {
+                homeserver,
+                reset,
+                reply,
+            } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.resend_password_reset(&reset).await,
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SubmitResetToken {
+                homeserver,
+                reset,
+                token,
+                reply,
+            } => {
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => mc.submit_reset_token(&reset, &token).await,
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::CompletePasswordReset {
+                homeserver,
+                reset,
+                new_password,
+                reply,
+            } => {
+                let validation = &reset.validation;
+                let result = match guest_client(&mut guest, &homeserver).await {
+                    Ok(mc) => {
+                        mc.complete_password_reset(
+                            &validation.sid,
+                            &validation.client_secret,
+                            &new_password,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SwitchRoom { room_id, reply } => {
+                active_room = Some(room_id);
+                let _ = reply.send(Ok(()));
+            }
+            Command::SendMessage {
+                room_id,
+                body,
+                mentions,
+                reply_to,
+                txn_id,
+                reply,
+            } => {
+                let Some(room_id) = room_id.or_else(|| active_room.clone()) else {
+                    let _ = reply.send(Err(anyhow!("No room selected")));
+                    continue;
+                };
+                match &client {
+                    Some(mc) => {
+                        let result =
+                            send(mc, &room_id, &body, &mentions, reply_to.as_deref(), &txn_id)
+                                .await;
+                        let _ = reply.send(result);
+                    }
+                    None => queued.push(QueuedSend {
+                        room_id,
+                        body,
+                        mentions,
+                        reply_to,
+                        txn_id,
+                        reply,
+                    }),
+                }
+            }
+            Command::SendEmote {
+                room_id,
+                action,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.send_emote(&room_id, &action).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SendSticker {
+                room_id,
+                sticker,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.send_sticker(&room_id, &sticker).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SendGameInvite {
+                room_id,
+                invite,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.send_game_invite(&room_id, &invite).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::EditMessage {
+                room_id,
+                event_id,
+                body,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.edit_message(&room_id, &event_id, &body).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::DeleteMessage {
+                room_id,
+                event_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.delete_message(&room_id, &event_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::React {
+                room_id,
+                event_id,
+                key,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.react_to_message(&room_id, &event_id, &key).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ForwardMessage {
+                src_room,
+                event_id,
+                dst_room,
+                annotation,
+                reply,
+            } => match &client {
+                Some(mc) => detach(
+                    mc.forward_message(&src_room, &event_id, &dst_room, annotation.as_deref()),
+                    reply,
+                ),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::PurgeUserMessages {
+                room_id,
+                user_id,
+                window,
+                ban,
+                progress,
+                reply,
+            } => match &client {
+                Some(mc) => detach(
+                    mc.purge_user_messages(&room_id, &user_id, window, ban, progress),
+                    reply,
+                ),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::ReportMessage {
+                room_id,
+                event_id,
+                score,
+                reason,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => {
+                        mc.report_message(&room_id, &event_id, score, reason.as_deref())
+                            .await
+                    }
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ReportRoom {
+                room_id,
+                reason,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.report_room(&room_id, reason.as_deref()).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::IgnoreUser { user_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.ignore_user(&user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetPermissions { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_permissions(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetPinned { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_pinned(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::PinMessage {
+                room_id,
+                event_id,
+                pin,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.pin_message(&room_id, &event_id, pin).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetEvent {
+                room_id,
+                event_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.get_event(&room_id, &event_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetEventDetails {
+                room_id,
+                event_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.get_event_details(&room_id, &event_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Logout { reply } => {
+                let result = match client.take() {
+                    Some(mut mc) => mc.logout().await,
+                    None => Ok(()),
+                };
+                shown = None;
+                active_room = None;
+                for send in queued.drain(..) {
+                    let _ = send.reply.send(Err(anyhow!("Logged out")));
+                }
+                let _ = reply.send(result);
+            }
+            Command::LoadRooms { concurrency, reply } => {
+                let result = match client.as_mut() {
+                    Some(mc) => load_rooms(mc, concurrency).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Notifications { reply } => {
+                let result = match &client {
+                    Some(mc) => Ok(mc.subscribe_notifications()),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Typing { reply } => {
+                let result = match &client {
+                    Some(mc) => Ok(mc.subscribe_typing()),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Connection { reply } => {
+                let result = match &client {
+                    Some(mc) => Ok(mc.subscribe_connection()),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetTyping {
+                room_id,
+                typing,
+                reply,
+            } => match &client {
+                Some(mc) => detach(mc.set_typing(&room_id, typing), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::History {
+                room_id,
+                from,
+                cancel,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => cancel
+                        .run(mc.get_messages(&room_id, from.as_deref()))
+                        .await
+                        .unwrap_or_else(|cancelled| Err(cancelled.into())),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::NewerHistory {
+                room_id,
+                from,
+                cancel,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => cancel
+                        .run(mc.get_newer_messages(&room_id, &from))
+                        .await
+                        .unwrap_or_else(|cancelled| Err(cancelled.into())),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::StateHistory {
+                room_id,
+                kinds,
+                from,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => {
+                        mc.get_state_history(&room_id, &kinds, from.as_deref())
+                            .await
+                    }
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Context {
+                room_id,
+                event_id,
+                cancel,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => cancel
+                        .run(mc.get_context(&room_id, &event_id))
+                        .await
+                        .unwrap_or_else(|cancelled| Err(cancelled.into())),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::MarkRead {
+                room_id,
+                event_id,
+                ts,
+                reply,
+            } => match &client {
+                Some(mc) => detach(mc.mark_read(&room_id, &event_id, ts), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::FullyRead { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.fully_read(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::JoinRoom {
+                id_or_alias,
+                via,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.join_room(&id_or_alias, &via).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::PreviewRoom {
+                id_or_alias,
+                via,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.preview_room(&id_or_alias, &via).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::KnockOnRoom {
+                id_or_alias,
+                via,
+                reason,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => {
+                        mc.knock_on_room(&id_or_alias, &via, reason.as_deref())
+                            .await
+                    }
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RetractKnock { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.retract_knock(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::KnockRequests { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.knock_requests().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::AnswerKnock {
+                room_id,
+                user_id,
+                approve,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) if approve => mc.approve_knock(&room_id, &user_id).await,
+                    Some(mc) => mc.deny_knock(&room_id, &user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::OpenDm { user_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.open_dm(&user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetFriends { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_friends().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::AddFriend { user_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.add_friend(&user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RespondToFriend {
+                user_id,
+                accept,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.respond_to_friend(&user_id, accept).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RemoveFriend { user_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.remove_friend(&user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::InviteUser {
+                room_id,
+                user_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.invite_user(&room_id, &user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetPresence { status, reply } => match &client {
+                Some(mc) => detach(mc.set_presence(status), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::SetStatusMessage { text, reply } => match &client {
+                Some(mc) => detach(mc.set_status_message(&text), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::SetKeywords { keywords, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.set_keywords(&keywords).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ListPushers { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.list_pushers().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RegisterPushers { config, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.register_pushers(&config).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RemovePusher {
+                app_id,
+                pushkey,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.remove_pusher(&app_id, &pushkey).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Get3pids { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_3pids().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RequestEmailToken { email, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.request_email_token(&email).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ResendEmailToken { validation, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.resend_email_token(&validation).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Add3pid {
+                validation,
+                password,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.add_3pid(&validation, &password).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Remove3pid { email, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.remove_3pid(&email).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetRoomMembers {
+                room_id,
+                full,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.get_room_members(&room_id, full).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetMemberAvatar {
+                room_id,
+                user_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.get_member_avatar(&room_id, &user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::DownloadMedia { source, reply } => match &client {
+                Some(mc) => detach(mc.download_media(&source), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::DownloadThumbnail { attachment, reply } => match &client {
+                Some(mc) => detach(mc.download_thumbnail(&attachment), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::GetUrlPreview { url, ts, reply } => match &client {
+                Some(mc) => detach(mc.get_url_preview(&url, ts), reply),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::UploadLimit { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.upload_limit().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SendAttachment {
+                room_id,
+                upload,
+                txn_id,
+                progress,
+                reply,
+            } => match &client {
+                Some(mc) => detach(
+                    mc.send_attachment(&room_id, upload, &txn_id, progress),
+                    reply,
+                ),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::SendVoiceMessage {
+                room_id,
+                data,
+                duration,
+                txn_id,
+                reply,
+            } => match &client {
+                Some(mc) => detach(
+                    mc.send_voice_message(&room_id, data, duration, &txn_id),
+                    reply,
+                ),
+                None => {
+                    let _ = reply.send(Err(anyhow!("Not logged in")));
+                }
+            },
+            Command::GetProfile { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_profile().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetDisplayName { name, reply } => {
+                let result = match client.as_mut() {
+                    Some(mc) => mc.set_display_name(&name).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetAvatar {
+                data,
+                mime_type,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_avatar(data, &mime_type).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetBio { bio, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.set_bio(&bio).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetFavorites { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_favorites().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::StickerPacks { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.sticker_packs(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetFavorites { rooms, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.set_favorites(rooms).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::JoinVoice {
+                room_id,
+                candidates,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.join_voice(&room_id, candidates).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::LeaveVoice { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.leave_voice(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::StunServers { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.stun_servers().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::VoiceMembers { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.voice_members(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::AnswerCall {
+                room_id,
+                call_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.answer_call(&room_id, &call_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::HangupCall {
+                room_id,
+                call_id,
+                reason,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.hangup_call(&room_id, &call_id, reason).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::CreateChannel {
+                space_id,
+                name,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.create_channel(space_id.as_deref(), &name).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RemoveChannel {
+                space_id,
+                room_id,
+                removal,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => {
+                        mc.remove_channel(space_id.as_deref(), &room_id, removal)
+                            .await
+                    }
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetRoles { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_roles(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::CreateRole {
+                room_id,
+                role,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.create_role(&room_id, role).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetPowerLevel {
+                room_id,
+                user_id,
+                level,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_power_level(&room_id, &user_id, level).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetRoomSettings { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_room_settings(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetJoinRule {
+                room_id,
+                rule,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_join_rule(&room_id, rule).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetHistoryVisibility {
+                room_id,
+                visibility,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_history_visibility(&room_id, visibility).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetGuestAccess {
+                room_id,
+                allowed,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_guest_access(&room_id, allowed).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetSlowMode {
+                room_id,
+                seconds,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_slow_mode(&room_id, seconds).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetWelcome { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_welcome(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetWelcome {
+                room_id,
+                body,
+                must_accept,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_welcome(&room_id, &body, must_accept).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::MarkWelcome {
+                room_id,
+                event_id,
+                seen,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.mark_welcome(&room_id, &event_id, seen).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetTopic {
+                room_id,
+                topic,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_topic(&room_id, &topic).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::EnableEncryption { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.enable_encryption(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::GetRoomAliases { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.get_room_aliases(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::AddRoomAlias {
+                room_id,
+                alias,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.add_room_alias(&room_id, &alias).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RemoveRoomAlias { alias, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.remove_room_alias(&alias).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SetCanonicalAlias {
+                room_id,
+                alias,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.set_canonical_alias(&room_id, &alias).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::CacheSize { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.cache_size().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ClearCache { category, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.clear_cache(category).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::PruneCache { retention, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.prune_cache(retention).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::ClearLocalHistory { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.clear_local_history().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SearchLocal {
+                query,
+                room_id,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => mc.search_local(&query, room_id.as_deref()).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SearchServer {
+                query,
+                room_id,
+                cancel,
+                reply,
+            } => {
+                let result = match &client {
+                    Some(mc) => cancel
+                        .run(mc.search_server(&query, room_id.as_deref()))
+                        .await
+                        .unwrap_or_else(|cancelled| Err(cancelled.into())),
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RebuildSearchIndex { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.rebuild_search_index().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RunDiagnostics { reply } => {
+                let result = match &client {
+                    Some(mc) => mc.run_diagnostics().await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::RoomSecurity { room_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.room_security(&room_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::TrustNewKeys { user_id, reply } => {
+                let result = match &client {
+                    Some(mc) => mc.trust_new_keys(&user_id).await,
+                    None => Err(anyhow!("Not logged in")),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Shutdown { reply } => {
+                drop(client.take());
+                idle.clear();
+                for send in queued.drain(..) {
+                    let _ = send.reply.send(Err(anyhow!("Shutting down")));
+                }
+                let _ = reply.send(Ok(()));
+                break;
+            }
+        }
+    }
+}
+
+/// Finish `request` on its own task and reply with its result.
+fn detach<T: Send + 'static>(request: Detached<T>, reply: oneshot::Sender<Result<T>>) {
+    tokio::spawn(async move {
+        let _ = reply.send(request.await);
+    });
+}
+
+async fn load_rooms<B: Backend>(mc: &mut B, concurrency: usize) -> Result<RoomList> {
+    let _span = startup::Span::new("load rooms");
+    mc.start_sync().await?;
+    let events = mc.subscribe();
+    let (spaces, rooms) =
+        tokio::try_join!(mc.get_spaces(concurrency), mc.get_joined_rooms(concurrency))?;
+    Ok(RoomList {
+        spaces,
+        rooms,
+        events,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_echo::{SendState, Timeline};
+    use chat_core::{Member, MemberRole, Message};
+    use network::admin::AdminError;
+    use network::audit::{AuditEntry, Change};
+    use network::config::GatewayConfig;
+    use network::connection::{ConnectionState, Retry};
+    use network::events::TypingUser;
+    use network::knock::KnockError;
+    use network::password_reset::ResetError;
+    use network::pins;
+    use network::pusher::{PusherError, PusherKind, EMAIL_APP_ID};
+    use network::register::{RegisterError, Registration};
+    use network::room_settings::{parse_alias, AliasError, SettingsPermissions};
+    use network::roster::{FriendState, HandshakeError, Roster};
+    use network::search::{merge_hits, SearchIndex, MAX_RESULTS};
+    use network::threepid::{Medium, ThreepidError};
+    use network::welcome::WelcomeSeenContent;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const MISSING_ROOM: &str = "!missing:example.org";
+    /// History requests for this room never complete on their own.
+    const SLOW_ROOM: &str = "!slow:example.org";
+    const TAKEN_USERNAME: &str = "bob";
+    const UNKNOWN_HOMESERVER: &str = "nowhere.invalid";
+    const MISSING_MEDIA: &str = "missing";
+    const UPLOAD_LIMIT: u64 = 16;
+    /// A space we may not add channels to or define roles in.
+    const LOCKED_SPACE: &str = "!locked:example.org";
+    /// A push gateway the server won't post to.
+    const LOCKED_GATEWAY: &str = "https://locked.example.org/notify";
+    /// On another account already.
+    const TAKEN_EMAIL: &str = "bob@example.org";
+    /// Its link is never clicked.
+    const UNREAD_EMAIL: &str = "unread@example.org";
+    /// The code mailed for password resets.
+    const RESET_CODE: &str = "123456";
+
+    struct FakeClient {
+        user_id: String,
+        events: broadcast::Sender<ChatEvent>,
+        notifications: broadcast::Sender<NotificationEvent>,
+        /// Our own typing notices, as the server would echo them.
+        typing: broadcast::Sender<TypingEvent>,
+        connection: watch::Sender<ConnectionState>,
+        profile: Mutex<Profile>,
+        /// The pinned rooms' account data, once stored.
+        favorites: Mutex<Option<Vec<String>>>,
+        /// Pinned messages by room id.
+        pinned: Mutex<HashMap<String, Vec<String>>>,
+        /// Voice channel members by room id.
+        voice: Mutex<HashMap<String, Vec<VoiceMember>>>,
+        /// Custom roles by room id.
+        roles: Mutex<HashMap<String, Vec<Role>>>,
+        /// Rooms we turned encryption on in.
+        encrypted: Mutex<Vec<String>>,
+        /// Published aliases, with the room each is for.
+        aliases: Mutex<Vec<(String, String)>>,
+        pushers: Mutex<Vec<Pusher>>,
+        threepids: Mutex<Vec<Threepid>>,
+        /// Reset sessions whose link was clicked, or code typed in.
+        validated_resets: Mutex<Vec<String>>,
+        cache: Mutex<CacheUsage>,
+        /// What we sent, searchable.
+        search: Mutex<SearchIndex>,
+        /// Welcome messages by room id.
+        welcomes: Mutex<HashMap<String, Welcome>>,
+        welcomes_seen: Mutex<WelcomeSeenContent>,
+        roster: Mutex<Roster>,
+    }
+
+    impl FakeClient {
+        fn new(user_id: String) -> Self {
+            let (events, _) = broadcast::channel(16);
+            let (notifications, _) = broadcast::channel(16);
+            let (typing, _) = broadcast::channel(16);
+            let (connection, _) = watch::channel(ConnectionState::Connected);
+            Self {
+                user_id,
+                events,
+                notifications,
+                typing,
+                connection,
+                profile: Mutex::default(),
+                favorites: Mutex::default(),
+                pinned: Mutex::default(),
+                voice: Mutex::default(),
+                roles: Mutex::default(),
+                encrypted: Mutex::default(),
+                aliases: Mutex::default(),
+                // Another app's, on the user's phone.
+                pushers: Mutex::new(vec![Pusher {
+                    app_id: "im.vector.app.android".to_string(),
+                    pushkey: "phone-token".to_string(),
+                    kind: PusherKind::Http {
+                        url: "https://push.example.org/_matrix/push/v1/notify".to_string(),
+                    },
+                    app_display_name: "Element".to_string(),
+                    device_display_name: "Phone".to_string(),
+                }]),
+                threepids: Mutex::default(),
+                validated_resets: Mutex::default(),
+                cache: Mutex::new(CacheUsage {
+                    media: 300,
+                    avatars: 20,
+                    timeline: 5,
+                }),
+                search: Mutex::default(),
+                welcomes: Mutex::default(),
+                welcomes_seen: Mutex::default(),
+                roster: Mutex::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Backend for FakeClient {
+        async fn login(
+            _homeserver: &str,
+            username: &str,
+            _password: &str,
+        ) -> Result<(Self, String, String)> {
+            let user_id = format!("@{}:example.org", username);
+            Ok((
+                FakeClient::new(user_id.clone()),
+                user_id,
+                username.to_string(),
+            ))
+        }
+
+        async fn restore(saved: &Session) -> Result<Self> {
+            Ok(FakeClient::new(saved.user_id.clone()))
+        }
+
+        async fn connect(homeserver: &str) -> Result<Self> {
+            if homeserver == UNKNOWN_HOMESERVER {
+                return Err(anyhow!("dns error: failed to lookup address information"));
+            }
+            Ok(FakeClient::new(String::new()))
+        }
+
+        async fn register(&mut self, username: &str, _password: &str) -> Result<(String, String)> {
+            if username == TAKEN_USERNAME {
+                return Err(RegisterError::UsernameTaken.into());
+            }
+            self.user_id = format!("@{}:example.org", username);
+            Ok((self.user_id.clone(), username.to_string()))
+        }
+
+        async fn check_username(&self, username: &str) -> Result<Availability> {
+            Ok(match username {
+                TAKEN_USERNAME => Availability::Taken,
+                _ => Availability::Available,
+            })
+        }
+
+        async fn server_support(&self) -> Result<ServerSupport> {
+            Ok(ServerSupport {
+                homeserver: "https://example.org/".to_string(),
+                password_login: true,
+                registration: Registration::InApp,
+            })
+        }
+
+        async fn server_status(&self) -> Result<ServerStatus> {
+            Ok(ServerStatus {
+                homeserver: "https://example.org/".to_string(),
+                latency: std::time::Duration::from_millis(87),
+                version: Some("v1.9".to_string()),
+                server: None,
+            })
+        }
+
+        async fn request_password_reset(&self, email: &str) -> Result<PasswordReset> {
+            // Only alice's address is on an account.
+            let sid = match email {
+                "alice@example.org" => "reset-alice",
+                _ => "",
+            };
+            Ok(PasswordReset {
+                validation: EmailValidation {
+                    email: email.to_string(),
+                    client_secret: "secret".to_string(),
+                    sid: sid.to_string(),
+                    send_attempt: 1,
+                },
+                submit_url: Some("https://example.org/submit_token".to_string()),
+            })
+        }
+
+        async fn resend_password_reset(&self, reset: &PasswordReset) -> Result<PasswordReset> {
+            let mut reset = reset.clone();
+            reset.validation.send_attempt += 1;
+            Ok(reset)
+        }
+
+        async fn submit_reset_token(&self, reset: &PasswordReset, token: &str) -> Result<()> {
+            let sid = &reset.validation.sid;
+            if sid.is_empty() || token != RESET_CODE {
+                return Err(ResetError::WrongCode.into());
+            }
+            self.validated_resets.lock().unwrap().push(sid.clone());
+            Ok(())
+        }
+
+        async fn complete_password_reset(
+            &self,
+            sid: &str,
+            _client_secret: &str,
+            new_password: &str,
+        ) -> Result<()> {
+            if !self
+                .validated_resets
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|s| s == sid)
+            {
+                return Err(ResetError::NotValidated.into());
+            }
+            if new_password.len() < 8 {
+                return Err(ResetError::WeakPassword.into());
+            }
+            Ok(())
+        }
+
+        async fn send_message(
+            &self,
+            room_id: &str,
+            body: &str,
+            _mentions: &[Mention],
+            txn_id: &str,
+        ) -> Result<String> {
+            assert!(!self.user_id.is_empty());
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            let event_id = format!("$event-{}", txn_id);
+            let message = Message {
+                id: event_id.clone(),
+                sender: self.user_id.clone(),
+                ..outgoing(body)
+            };
+            self.search.lock().unwrap().insert(room_id, &message);
+            Ok(event_id)
+        }
+
+        async fn send_emote(&self, room_id: &str, _action: &str) -> Result<String> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok("$emote".to_string())
+        }
+
+        async fn send_sticker(&self, room_id: &str, sticker: &Sticker) -> Result<String> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok(format!("$sticker-{}", sticker.shortcode))
+        }
+
+        async fn send_game_invite(&self, room_id: &str, invite: &GameInvite) -> Result<String> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            invite.validate(chat_core::time::now_ms())?;
+            Ok(format!("$invite-{}", invite.game))
+        }
+
+        async fn send_reply(
+            &self,
+            room_id: &str,
+            _body: &str,
+            _mentions: &[Mention],
+            reply_to: &str,
+            txn_id: &str,
+        ) -> Result<String> {
+            assert!(!self.user_id.is_empty());
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok(format!("$reply-{}-to-{}", txn_id, reply_to))
+        }
+
+        async fn edit_message(
+            &self,
+            _room_id: &str,
+            event_id: &str,
+            _body: &str,
+        ) -> Result<String> {
+            Ok(format!("$edit-{}", event_id))
+        }
+
+        async fn delete_message(&self, room_id: &str, _event_id: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("delete this message").into());
+            }
+            Ok(())
+        }
+
+        async fn react_to_message(
+            &self,
+            _room_id: &str,
+            event_id: &str,
+            key: &str,
+        ) -> Result<String> {
+            Ok(format!("$react-{}-{}", event_id, key))
+        }
+
+        async fn report_message(
+            &self,
+            room_id: &str,
+            _event_id: &str,
+            _score: Option<i64>,
+            _reason: Option<&str>,
+        ) -> Result<Reported> {
+            Ok(match room_id {
+                LOCKED_SPACE => Reported::Unsupported,
+                _ => Reported::Sent,
+            })
+        }
+
+        async fn report_room(&self, room_id: &str, _reason: Option<&str>) -> Result<Reported> {
+            Ok(match room_id {
+                LOCKED_SPACE => Reported::Unsupported,
+                _ => Reported::Sent,
+            })
+        }
+
+        async fn ignore_user(&self, _user_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn get_permissions(&self, room_id: &str) -> Result<Permissions> {
+            Ok(match room_id {
+                LOCKED_SPACE => Permissions {
+                    send: false,
+                    react: false,
+                    redact_others: false,
+                    topic: false,
+                    pin: false,
+                    slow_mode_secs: 0,
+                },
+                _ => Permissions {
+                    pin: true,
+                    ..Permissions::default()
+                },
+            })
+        }
+
+        async fn get_pinned(&self, room_id: &str) -> Result<Vec<String>> {
+            Ok(self
+                .pinned
+                .lock()
+                .unwrap()
+                .get(room_id)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn pin_message(&self, room_id: &str, event_id: &str, pin: bool) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("pin messages").into());
+            }
+            let mut rooms = self.pinned.lock().unwrap();
+            let pinned = rooms.entry(room_id.to_string()).or_default();
+            if let Some(changed) = pins::with_pin(pinned, event_id, pin) {
+                *pinned = changed;
+                let _ = self.events.send(ChatEvent::PinsChanged {
+                    room_id: room_id.to_string(),
+                    pinned: pinned.clone(),
+                });
+            }
+            Ok(())
+        }
+
+        async fn get_event(&self, room_id: &str, event_id: &str) -> Result<Message> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok(Message {
+                id: event_id.to_string(),
+                ..outgoing("found it")
+            })
+        }
+
+        async fn get_event_details(&self, room_id: &str, event_id: &str) -> Result<EventDetails> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok(EventDetails {
+                event_id: event_id.to_string(),
+                sender: "@alice:example.org".to_string(),
+                sent_at: 1_710_504_000_000,
+                edited_at: Some(1_710_504_060_000),
+                encryption: None,
+            })
+        }
+
+        async fn logout(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn start_sync(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn set_background(&mut self, _background: bool) {}
+
+        fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+            self.events.subscribe()
+        }
+
+        fn subscribe_notifications(&self) -> broadcast::Receiver<NotificationEvent> {
+            self.notifications.subscribe()
+        }
+
+        fn subscribe_typing(&self) -> broadcast::Receiver<TypingEvent> {
+            self.typing.subscribe()
+        }
+
+        fn subscribe_connection(&self) -> Connection {
+            Connection {
+                state: self.connection.subscribe(),
+                retry: Retry::default(),
+            }
+        }
+
+        fn set_typing(&self, room_id: &str, typing: bool) -> Detached<()> {
+            let users = if typing {
+                vec![TypingUser {
+                    user_id: self.user_id.clone(),
+                    display_name: None,
+                }]
+            } else {
+                Vec::new()
+            };
+            let _ = self.typing.send(TypingEvent {
+                room_id: room_id.to_string(),
+                users,
+            });
+            Box::pin(async { Ok(()) })
+        }
+
+        async fn get_spaces(&self, _concurrency: usize) -> Result<Vec<Space>> {
+            Ok(Vec::new())
+        }
+
+        async fn get_joined_rooms(&self, _concurrency: usize) -> Result<Vec<Room>> {
+            Ok(Vec::new())
+        }
+
+        async fn get_messages(&self, room_id: &str, _from: Option<&str>) -> Result<MessagePage> {
+            if room_id == SLOW_ROOM {
+                std::future::pending::<()>().await;
+            }
+            Ok(MessagePage::default())
+        }
+
+        async fn get_newer_messages(&self, room_id: &str, _from: &str) -> Result<NewerPage> {
+            if room_id == SLOW_ROOM {
+                std::future::pending::<()>().await;
+            }
+            Ok(NewerPage::default())
+        }
+
+        async fn get_state_history(
+            &self,
+            room_id: &str,
+            kinds: &[AuditKind],
+            from: Option<&str>,
+        ) -> Result<StateHistoryPage> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            // Two pages of renames, newest first.
+            let (names, next) = match from {
+                None => (["Raids", "Lobby"], Some("older".to_string())),
+                Some(_) => (["Lobby", "General"], None),
+            };
+            let entries = names
+                .windows(2)
+                .filter(|_| kinds.contains(&AuditKind::Name))
+                .map(|pair| AuditEntry {
+                    event_id: format!("${}", pair[0]),
+                    kind: AuditKind::Name,
+                    actor: "@mod:example.org".to_string(),
+                    actor_name: None,
+                    target: None,
+                    target_name: None,
+                    membership: None,
+                    changes: vec![Change {
+                        field: "name".to_string(),
+                        old: Some(pair[1].to_string()),
+                        new: Some(pair[0].to_string()),
+                    }],
+                    reason: None,
+                    timestamp: 0,
+                })
+                .collect();
+            Ok(StateHistoryPage { entries, next })
+        }
+
+        async fn get_context(&self, room_id: &str, event_id: &str) -> Result<ContextPage> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok(ContextPage {
+                messages: vec![Message {
+                    id: event_id.to_string(),
+                    ..outgoing("found it")
+                }],
+                prev_batch: Some("before".to_string()),
+                next_batch: Some("after".to_string()),
+            })
+        }
+
+        fn mark_read(&self, _room_id: &str, _event_id: &str, _ts: u64) -> Detached<()> {
+            Box::pin(async { Ok(()) })
+        }
+
+        async fn fully_read(&self, _room_id: &str) -> Result<Option<String>> {
+            Ok(Some("$read".to_string()))
+        }
+
+        async fn join_room(&self, id_or_alias: &str, _via: &[String]) -> Result<String> {
+            Ok(id_or_alias.replacen('#', "!", 1))
+        }
+
+        async fn preview_room(&self, id_or_alias: &str, _via: &[String]) -> Result<RoomPreview> {
+            Ok(RoomPreview {
+                room_id: id_or_alias.replacen('#', "!", 1),
+                name: Some("lobby".to_string()),
+                member_count: 3,
+                peekable: true,
+                page: MessagePage {
+                    messages: vec![outgoing("peeked")],
+                    prev_batch: Some("before".to_string()),
+                },
+                ..Default::default()
+            })
+        }
+
+        async fn knock_on_room(
+            &self,
+            id_or_alias: &str,
+            _via: &[String],
+            _reason: Option<&str>,
+        ) -> Result<String> {
+            let room_id = id_or_alias.replacen('#', "!", 1);
+            if room_id == LOCKED_SPACE {
+                return Err(KnockError::Unsupported.into());
+            }
+            Ok(room_id)
+        }
+
+        async fn retract_knock(&self, _room_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn knock_requests(&self) -> Result<Vec<KnockRequest>> {
+            Ok(vec![KnockRequest {
+                room_id: "!raids:example.org".to_string(),
+                room_name: "raids".to_string(),
+                user_id: "@bob:example.org".to_string(),
+                display_name: None,
+                reason: Some("Saw you at the tournament".to_string()),
+            }])
+        }
+
+        async fn approve_knock(&self, room_id: &str, _user_id: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("let people in").into());
+            }
+            Ok(())
+        }
+
+        async fn deny_knock(&self, room_id: &str, _user_id: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("turn people away").into());
+            }
+            Ok(())
+        }
+
+        async fn invite_user(&self, room_id: &str, _user_id: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("invite people").into());
+            }
+            Ok(())
+        }
+
+        async fn open_dm(&self, user_id: &str) -> Result<String> {
+            Ok(format!("!dm-{}", user_id))
+        }
+
+        async fn get_friends(&self) -> Result<Vec<Friend>> {
+            let roster = self.roster.lock().unwrap();
+            Ok(roster
+                .entries()
+                .map(|(user_id, entry)| Friend {
+                    user_id: user_id.to_string(),
+                    display_name: None,
+                    state: entry.state,
+                    room_id: entry.room_id.clone(),
+                    status: UserStatus::Offline,
+                    status_message: None,
+                })
+                .collect())
+        }
+
+        async fn add_friend(&self, user_id: &str) -> Result<()> {
+            if user_id == self.user_id {
+                return Err(HandshakeError::FromSelf.into());
+            }
+            let room_id = self.open_dm(user_id).await?;
+            let mut roster = self.roster.lock().unwrap();
+            let request_id = format!("r{}", roster.friends.len());
+            roster.request(user_id, &room_id, &request_id, 100);
+            Ok(())
+        }
+
+        async fn respond_to_friend(&self, user_id: &str, accept: bool) -> Result<()> {
+            let mut roster = self.roster.lock().unwrap();
+            if accept {
+                roster.accept(user_id, 200);
+            } else {
+                roster.decline(user_id, 200);
+            }
+            Ok(())
+        }
+
+        async fn remove_friend(&self, user_id: &str) -> Result<()> {
+            self.roster.lock().unwrap().remove(user_id, 300);
+            let _ = self.events.send(ChatEvent::RosterChanged);
+            Ok(())
+        }
+
+        fn set_presence(&self, _status: UserStatus) -> Detached<()> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn set_status_message(&self, _text: &str) -> Detached<()> {
+            Box::pin(async { Ok(()) })
+        }
+
+        async fn set_keywords(&self, _keywords: &[String]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn list_pushers(&self) -> Result<Vec<Pusher>> {
+            Ok(self.pushers.lock().unwrap().clone())
+        }
+
+        async fn register_pushers(&self, config: &PusherConfig) -> Result<()> {
+            if let Some(gateway) = &config.gateway {
+                if gateway.url == LOCKED_GATEWAY {
+                    return Err(PusherError::Disabled.into());
+                }
+            }
+            let mut pushers = self.pushers.lock().unwrap();
+            pushers.retain(|p| p.app_display_name != "gamechat");
+            if let Some(email) = &config.email {
+                pushers.push(Pusher {
+                    app_id: EMAIL_APP_ID.to_string(),
+                    pushkey: email.clone(),
+                    kind: PusherKind::Email,
+                    app_display_name: "gamechat".to_string(),
+                    device_display_name: "DEVICE".to_string(),
+                });
+            }
+            Ok(())
+        }
+
+        async fn remove_pusher(&self, app_id: &str, pushkey: &str) -> Result<()> {
+            let mut pushers = self.pushers.lock().unwrap();
+            pushers.retain(|p| p.app_id != app_id || p.pushkey != pushkey);
+            Ok(())
+        }
+
+        async fn get_3pids(&self) -> Result<Vec<Threepid>> {
+            Ok(self.threepids.lock().unwrap().clone())
+        }
+
+        async fn request_email_token(&self, email: &str) -> Result<EmailValidation> {
+            if email == TAKEN_EMAIL {
+                return Err(ThreepidError::InUse.into());
+            }
+            Ok(EmailValidation {
+                email: email.to_string(),
+                client_secret: "secret".to_string(),
+                sid: format!("sid-{}", email),
+                send_attempt: 1,
+            })
+        }
+
+        async fn resend_email_token(
+            &self,
+            validation: &EmailValidation,
+        ) -> Result<EmailValidation> {
+            Ok(EmailValidation {
+                send_attempt: validation.send_attempt + 1,
+                ..validation.clone()
+            })
+        }
+
+        async fn add_3pid(&self, validation: &EmailValidation, password: &str) -> Result<()> {
+            if password != "hunter2" {
+                return Err(ThreepidError::WrongPassword.into());
+            }
+            if validation.email == UNREAD_EMAIL {
+                return Err(ThreepidError::NotValidated.into());
+            }
+            self.threepids.lock().unwrap().push(Threepid {
+                medium: Medium::Email,
+                address: validation.email.clone(),
+            });
+            Ok(())
+        }
+
+        async fn remove_3pid(&self, email: &str) -> Result<()> {
+            let mut threepids = self.threepids.lock().unwrap();
+            threepids.retain(|t| t.medium != Medium::Email || t.address != email);
+            Ok(())
+        }
+
+        async fn get_room_members(&self, _room_id: &str, full: bool) -> Result<MemberList> {
+            let own = Member {
+                user_id: self.user_id.clone(),
+                display_name: None,
+                avatar_url: None,
+                role: MemberRole::Admin,
+                status: UserStatus::Online,
+                status_message: None,
+            };
+            let other = Member {
+                user_id: "@bob:example.org".to_string(),
+                role: MemberRole::Member,
+                ..own.clone()
+            };
+            // Without `full` only we are known, as with lazy loading.
+            let members = if full { vec![own, other] } else { vec![own] };
+            Ok(MemberList { members, total: 2 })
+        }
+
+        async fn get_member_avatar(
+            &self,
+            _room_id: &str,
+            _user_id: &str,
+        ) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn download_media(&self, source: &str) -> Detached<Vec<u8>> {
+            let result = match source {
+                MISSING_MEDIA => Err(anyhow!("Not found")),
+                _ => Ok(source.as_bytes().to_vec()),
+            };
+            Box::pin(async move { result })
+        }
+
+        fn download_thumbnail(&self, attachment: &Attachment) -> Detached<Vec<u8>> {
+            let thumbnail = attachment.thumbnail_source.as_ref();
+            let source = thumbnail.unwrap_or(&attachment.source);
+            self.download_media(source)
+        }
+
+        fn get_url_preview(&self, url: &str, _ts: u64) -> Detached<Option<LinkPreview>> {
+            let preview = (url != MISSING_MEDIA).then(|| LinkPreview {
+                url: url.to_string(),
+                title: Some(url.to_string()),
+                description: None,
+                site_name: None,
+                image: None,
+            });
+            Box::pin(async move { Ok(preview) })
+        }
+
+        async fn upload_limit(&self) -> Result<u64> {
+            Ok(UPLOAD_LIMIT)
+        }
+
+        fn send_attachment(
+            &self,
+            _room_id: &str,
+            upload: Upload,
+            _txn_id: &str,
+            progress: watch::Sender<UploadProgress>,
+        ) -> Detached<String> {
+            let total = upload.data.len() as u64;
+            let result = if total > UPLOAD_LIMIT {
+                Err(anyhow!("Too large"))
+            } else {
+                let _ = progress.send(UploadProgress { sent: total, total });
+                Ok(format!("$upload-{}", upload.name))
+            };
+            Box::pin(async move { result })
+        }
+
+        fn send_voice_message(
+            &self,
+            _room_id: &str,
+            data: Vec<u8>,
+            duration: Duration,
+            txn_id: &str,
+        ) -> Detached<String> {
+            let result = if data.len() as u64 > UPLOAD_LIMIT {
+                Err(anyhow!("Too large"))
+            } else {
+                Ok(format!("$voice-{}-{}ms", txn_id, duration.as_millis()))
+            };
+            Box::pin(async move { result })
+        }
+
+        fn forward_message(
+            &self,
+            _src_room: &str,
+            event_id: &str,
+            dst_room: &str,
+            annotation: Option<&str>,
+        ) -> Detached<String> {
+            let result = if dst_room == MISSING_ROOM {
+                Err(anyhow!("Room not found"))
+            } else {
+                Ok(format!(
+                    "$forward-{}{}",
+                    event_id,
+                    if annotation.is_some() {
+                        "-annotated"
+                    } else {
+                        ""
+                    }
+                ))
+            };
+            Box::pin(async move { result })
+        }
+
+        fn purge_user_messages(
+            &self,
+            room_id: &str,
+            user_id: &str,
+            window: PurgeWindow,
+            ban: bool,
+            progress: watch::Sender<PurgeProgress>,
+        ) -> Detached<PurgeReport> {
+            if room_id == LOCKED_SPACE {
+                return Box::pin(async { Err(AdminError::Forbidden("ban members").into()) });
+            }
+            // One message a minute from `user_id`, the last undeletable.
+            let total = (window.max_age.as_secs() / 60).min(window.max_events as u64) as usize;
+            for done in 0..=total {
+                let _ = progress.send(PurgeProgress { done, total });
+            }
+            let report = PurgeReport {
+                banned: ban,
+                found: total,
+                redacted: total.saturating_sub(1),
+                failed: vec![(format!("$last-of-{}", user_id), "Forbidden".to_string())],
+            };
+            Box::pin(async move { Ok(report) })
+        }
+
+        async fn get_profile(&self) -> Result<Profile> {
+            Ok(self.profile.lock().unwrap().clone())
+        }
+
+        async fn set_display_name(&mut self, name: &str) -> Result<()> {
+            self.profile.get_mut().unwrap().display_name = Some(name.to_string());
+            Ok(())
+        }
+
+        async fn set_avatar(&self, data: Vec<u8>, mime_type: &str) -> Result<()> {
+            if !mime_type.starts_with("image/") {
+                return Err(anyhow!("Not an image"));
+            }
+            self.profile.lock().unwrap().avatar = Some(data);
+            Ok(())
+        }
+
+        async fn set_bio(&self, bio: &str) -> Result<()> {
+            self.profile.lock().unwrap().bio = bio.to_string();
+            Ok(())
+        }
+
+        async fn get_favorites(&self) -> Result<Option<Vec<String>>> {
+            Ok(self.favorites.lock().unwrap().clone())
+        }
+
+        async fn set_favorites(&self, rooms: Vec<String>) -> Result<()> {
+            *self.favorites.lock().unwrap() = Some(rooms);
+            Ok(())
+        }
+
+        async fn sticker_packs(&self, room_id: &str) -> Result<Vec<StickerPack>> {
+            if room_id == MISSING_ROOM {
+                return Err(anyhow!("Room not found"));
+            }
+            Ok(vec![StickerPack {
+                name: "Cats".to_string(),
+                avatar_url: None,
+                stickers: vec![sticker("cat_happy")],
+            }])
+        }
+
+        async fn join_voice(&self, room_id: &str, candidates: Vec<String>) -> Result<()> {
+            self.leave_voice(room_id).await?;
+            self.voice
+                .lock()
+                .unwrap()
+                .entry(room_id.to_string())
+                .or_default()
+                .push(VoiceMember {
+                    user_id: self.user_id.clone(),
+                    display_name: None,
+                    candidates,
+                });
+            Ok(())
+        }
+
+        async fn leave_voice(&self, room_id: &str) -> Result<()> {
+            if let Some(members) = self.voice.lock().unwrap().get_mut(room_id) {
+                members.retain(|m| m.user_id != self.user_id);
+            }
+            Ok(())
+        }
+
+        async fn stun_servers(&self) -> Result<Vec<SocketAddr>> {
+            Ok(Vec::new())
+        }
+
+        async fn voice_members(&self, room_id: &str) -> Result<Vec<VoiceMember>> {
+            Ok(self
+                .voice
+                .lock()
+                .unwrap()
+                .get(room_id)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn answer_call(&self, _room_id: &str, _call_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn hangup_call(
+            &self,
+            _room_id: &str,
+            _call_id: &str,
+            _reason: HangupReason,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn create_channel(&self, space_id: Option<&str>, name: &str) -> Result<String> {
+            if space_id == Some(LOCKED_SPACE) {
+                return Err(AdminError::Forbidden("add channels to this server").into());
+            }
+            Ok(format!("!{}:example.org", name))
+        }
+
+        async fn remove_channel(
+            &self,
+            space_id: Option<&str>,
+            _room_id: &str,
+            removal: ChannelRemoval,
+        ) -> Result<()> {
+            if removal == ChannelRemoval::FromSpace && space_id.is_none() {
+                return Err(anyhow!("Channel is not in a server"));
+            }
+            Ok(())
+        }
+
+        async fn get_roles(&self, room_id: &str) -> Result<Vec<Role>> {
+            let custom = self.roles.lock().unwrap().get(room_id).cloned();
+            Ok(network::admin::with_builtin_roles(
+                custom.unwrap_or_default(),
+            ))
+        }
+
+        async fn create_role(&self, room_id: &str, role: Role) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("create roles").into());
+            }
+            self.roles
+                .lock()
+                .unwrap()
+                .entry(room_id.to_string())
+                .or_default()
+                .push(role);
+            Ok(())
+        }
+
+        async fn set_power_level(&self, room_id: &str, _user_id: &str, _level: i64) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change roles").into());
+            }
+            Ok(())
+        }
+
+        async fn get_room_settings(&self, room_id: &str) -> Result<RoomSettings> {
+            let encrypted = self.encrypted.lock().unwrap().iter().any(|r| r == room_id);
+            let admin = room_id != LOCKED_SPACE;
+            Ok(RoomSettings {
+                join_rule: JoinRule::Invite,
+                history_visibility: HistoryVisibility::Shared,
+                guest_access: false,
+                encrypted,
+                slow_mode_secs: 0,
+                welcome: self.welcomes.lock().unwrap().get(room_id).cloned(),
+                can_change: SettingsPermissions {
+                    join_rule: admin,
+                    history_visibility: admin,
+                    guest_access: admin,
+                    encryption: admin && !encrypted,
+                    slow_mode: admin,
+                    welcome: admin,
+                },
+            })
+        }
+
+        async fn set_join_rule(&self, room_id: &str, _rule: JoinRule) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change who can join").into());
+            }
+            Ok(())
+        }
+
+        async fn set_history_visibility(
+            &self,
+            room_id: &str,
+            _visibility: HistoryVisibility,
+        ) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change who can read the history").into());
+            }
+            Ok(())
+        }
+
+        async fn set_guest_access(&self, room_id: &str, _allowed: bool) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change guest access").into());
+            }
+            Ok(())
+        }
+
+        async fn set_slow_mode(&self, room_id: &str, _seconds: u64) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change slow mode").into());
+            }
+            Ok(())
+        }
+
+        async fn get_welcome(&self, room_id: &str) -> Result<Option<(Welcome, Seen)>> {
+            let Some(welcome) = self.welcomes.lock().unwrap().get(room_id).cloned() else {
+                return Ok(None);
+            };
+            let seen = self.welcomes_seen.lock().unwrap();
+            let seen = seen.seen(room_id, &welcome.event_id);
+            Ok(Some((welcome, seen)))
+        }
+
+        async fn set_welcome(&self, room_id: &str, body: &str, must_accept: bool) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change the welcome message").into());
+            }
+            let mut welcomes = self.welcomes.lock().unwrap();
+            if body.trim().is_empty() {
+                welcomes.remove(room_id);
+            } else {
+                let event_id = format!("$welcome{}", welcomes.len());
+                self.welcomes_seen
+                    .lock()
+                    .unwrap()
+                    .mark(room_id, &event_id, Seen::Accepted);
+                let welcome = Welcome {
+                    event_id,
+                    body: body.trim().to_string(),
+                    formatted: None,
+                    must_accept,
+                };
+                welcomes.insert(room_id.to_string(), welcome);
+            }
+            let _ = self.events.send(ChatEvent::WelcomeChanged {
+                room_id: room_id.to_string(),
+            });
+            Ok(())
+        }
+
+        async fn mark_welcome(&self, room_id: &str, event_id: &str, seen: Seen) -> Result<()> {
+            self.welcomes_seen
+                .lock()
+                .unwrap()
+                .mark(room_id, event_id, seen);
+            Ok(())
+        }
+
+        async fn set_topic(&self, room_id: &str, topic: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change the topic").into());
+            }
+            let _ = self.events.send(ChatEvent::RoomTopicChanged {
+                room_id: room_id.to_string(),
+                topic: Some(topic.to_string()),
+                sender: self.user_id.clone(),
+                sender_name: None,
+            });
+            Ok(())
+        }
+
+        async fn enable_encryption(&self, room_id: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("enable encryption").into());
+            }
+            self.encrypted.lock().unwrap().push(room_id.to_string());
+            let _ = self.events.send(ChatEvent::RoomEncrypted {
+                room_id: room_id.to_string(),
+            });
+            Ok(())
+        }
+
+        async fn get_room_aliases(&self, room_id: &str) -> Result<RoomAliases> {
+            let aliases = self.aliases.lock().unwrap();
+            Ok(RoomAliases {
+                canonical: None,
+                aliases: aliases
+                    .iter()
+                    .filter(|(_, room)| room == room_id)
+                    .map(|(alias, _)| alias.clone())
+                    .collect(),
+                can_set_canonical: room_id != LOCKED_SPACE,
+            })
+        }
+
+        async fn add_room_alias(&self, room_id: &str, alias: &str) -> Result<String> {
+            let alias = parse_alias(alias, "example.org")?;
+            let mut aliases = self.aliases.lock().unwrap();
+            if aliases.iter().any(|(taken, _)| *taken == alias) {
+                return Err(AliasError::Taken.into());
+            }
+            aliases.push((alias.clone(), room_id.to_string()));
+            Ok(alias)
+        }
+
+        async fn remove_room_alias(&self, alias: &str) -> Result<()> {
+            self.aliases.lock().unwrap().retain(|(a, _)| a != alias);
+            Ok(())
+        }
+
+        async fn set_canonical_alias(&self, room_id: &str, alias: &str) -> Result<()> {
+            if room_id == LOCKED_SPACE {
+                return Err(AdminError::Forbidden("change the main alias").into());
+            }
+            let _ = self.events.send(ChatEvent::RoomAliasChanged {
+                room_id: room_id.to_string(),
+                alias: Some(alias.to_string()),
+            });
+            Ok(())
+        }
+
+        async fn cache_size(&self) -> Result<CacheUsage> {
+            Ok(*self.cache.lock().unwrap())
+        }
+
+        async fn clear_cache(&self, category: CacheCategory) -> Result<()> {
+            let mut cache = self.cache.lock().unwrap();
+            match category {
+                CacheCategory::Media => cache.media = 0,
+                CacheCategory::Avatars => cache.avatars = 0,
+                CacheCategory::Timeline => cache.timeline = 0,
+            }
+            Ok(())
+        }
+
+        async fn prune_cache(&self, retention: Retention) -> Result<()> {
+            // Everything fake was cached long ago.
+            if retention != Retention::Forever {
+                *self.cache.lock().unwrap() = CacheUsage::default();
+            }
+            Ok(())
+        }
+
+        async fn clear_local_history(&self) -> Result<()> {
+            *self.cache.lock().unwrap() = CacheUsage::default();
+            self.search.lock().unwrap().clear();
+            Ok(())
+        }
+
+        async fn search_local(&self, query: &str, room_id: Option<&str>) -> Result<Vec<SearchHit>> {
+            Ok(self
+                .search
+                .lock()
+                .unwrap()
+                .search(query, room_id, MAX_RESULTS))
+        }
+
+        /// The server finds what we sent too.
+        async fn search_server(
+            &self,
+            query: &str,
+            room_id: Option<&str>,
+        ) -> Result<Vec<SearchHit>> {
+            self.search_local(query, room_id).await
+        }
+
+        /// The fake server keeps no history to index afresh.
+        async fn rebuild_search_index(&self) -> Result<()> {
+            self.search.lock().unwrap().clear();
+            Ok(())
+        }
+
+        async fn run_diagnostics(&self) -> Result<Report> {
+            Ok(Report::default())
+        }
+
+        async fn room_security(&self, room_id: &str) -> Result<RoomSecurity> {
+            if !self.encrypted.lock().unwrap().iter().any(|r| r == room_id) {
+                return Ok(RoomSecurity::default());
+            }
+            Ok(RoomSecurity {
+                encrypted: true,
+                unverified_users: vec!["@bob:example.org".to_string()],
+            })
+        }
+
+        async fn trust_new_keys(&self, _user_id: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_immediately_after_login() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let (user_id, _) = handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(user_id, "@alice:example.org");
+
+        handle.switch_room("!room:example.org").await.unwrap();
+        assert_eq!(
+            handle.send_message("hello", "t1").await.unwrap(),
+            "$event-t1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commands_queued_before_login_completes() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let login = handle.login("https://example.org", "bob", "hunter2");
+        let switch = handle.switch_room("!room:example.org");
+        let send = handle.send_message("hello", "t1");
+
+        // Await out of order: execution order follows call order regardless.
+        assert!(send.await.is_ok());
+        assert!(switch.await.is_ok());
+        assert!(login.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_before_login_is_queued() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle.switch_room("!room:example.org").await.unwrap();
+        let first = handle.send_message("one", "t1");
+        let second = handle.send_message("two", "t2");
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(first.await.unwrap(), "$event-t1");
+        assert_eq!(second.await.unwrap(), "$event-t2");
+    }
+
+    #[tokio::test]
+    async fn test_queued_reply_keeps_its_target() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle.switch_room("!room:example.org").await.unwrap();
+        let reply = handle.send("me too", &[], Some("$1"), "t1");
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(reply.await.unwrap(), "$reply-t1-to-$1");
+    }
+
+    #[tokio::test]
+    async fn test_send_without_room_fails() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        assert!(handle.send_message("hello", "t1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_to_needs_no_active_room() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let sent = handle.send_to("!dm:example.org", "hello", &[], None, "t1");
+        assert_eq!(sent.await.unwrap(), "$event-t1");
+    }
+
+    #[tokio::test]
+    async fn test_logout_clears_client() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle.switch_room("!room:example.org").await.unwrap();
+        let queued = handle.send_message("hello", "t1");
+        handle.logout().await.unwrap();
+        assert!(queued.await.is_err());
+
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        handle.logout().await.unwrap();
+        assert!(handle
+            .history("!room:example.org", None, CancelToken::new())
+            .await
+            .is_err());
+        assert!(handle.notifications().await.is_err());
+        assert!(handle.open_dm("@bob:example.org").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_switching_accounts_keeps_both_signed_in() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        handle.set_display_name("Alice").await.unwrap();
+        handle
+            .login("https://example.org", "bob", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(handle.get_profile().await.unwrap().display_name, None);
+
+        let saved = |user_id: &str| Session {
+            user_id: user_id.to_string(),
+            display_name: String::new(),
+            homeserver: "https://example.org".to_string(),
+            access_token: String::new(),
+            device_id: String::new(),
+            details: Default::default(),
+        };
+        // Alice's client was kept, not restored afresh.
+        handle
+            .switch_account(saved("@alice:example.org"))
+            .await
+            .unwrap();
+        let profile = handle.get_profile().await.unwrap();
+        assert_eq!(profile.display_name.as_deref(), Some("Alice"));
+
+        // Logging Alice out leaves Bob to switch back to.
+        handle.logout().await.unwrap();
+        assert!(handle.get_profile().await.is_err());
+        handle
+            .switch_account(saved("@bob:example.org"))
+            .await
+            .unwrap();
+        let members = handle.get_room_members("!room:example.org", false);
+        assert_eq!(
+            members.await.unwrap().members[0].user_id,
+            "@bob:example.org"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_history_frees_the_queue() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let cancel = CancelToken::new();
+        let stale = handle.history(SLOW_ROOM, None, cancel.clone());
+        let fresh = handle.history("!room:example.org", None, CancelToken::new());
+        cancel.cancel();
+
+        assert!(fresh.await.is_ok());
+        assert!(stale.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_context_and_newer_history() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle
+            .context(room, "$old", CancelToken::new())
+            .await
+            .is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let page = handle
+            .context(room, "$old", CancelToken::new())
+            .await
+            .unwrap();
+        assert_eq!(page.messages[0].id, "$old");
+        assert!(handle
+            .context(MISSING_ROOM, "$old", CancelToken::new())
+            .await
+            .is_err());
+
+        // Catching up forwards is cancelled like going back.
+        let cancel = CancelToken::new();
+        let stale = handle.newer_history(SLOW_ROOM, "after".to_string(), cancel.clone());
+        let fresh = handle.newer_history(room, "after".to_string(), CancelToken::new());
+        cancel.cancel();
+        assert!(stale.await.is_err());
+        assert!(fresh.await.unwrap().next_batch.is_none());
+    }
+
+    fn outgoing(body: &str) -> Message {
+        Message {
+            sender: "@alice:example.org".to_string(),
+            content: body.to_string(),
+            ..Message::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_echo_reconciled_with_send_reply() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let mut timeline = Timeline::default();
+
+        handle.switch_room("!room:example.org").await.unwrap();
+        timeline.push_local("t1", outgoing("hello"));
+        let event_id = handle.send_message("hello", "t1").await.unwrap();
+        timeline.mark_sent("t1", &event_id);
+
+        handle.switch_room(MISSING_ROOM).await.unwrap();
+        timeline.push_local("t2", outgoing("lost"));
+        assert!(handle.send_message("lost", "t2").await.is_err());
+        timeline.mark_failed("t2");
+
+        let states: Vec<_> = timeline.entries().iter().map(|e| e.state).collect();
+        assert_eq!(states, vec![SendState::Sent, SendState::Failed]);
+        assert_eq!(timeline.entries()[0].message.id, "$event-t1");
+
+        // The remote echo of the delivered message does not duplicate it.
+        let mut echo = outgoing("hello");
+        echo.id = event_id;
+        timeline.push_remote(echo, Some("t1"));
+        assert_eq!(timeline.entries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_check_server_needs_no_session() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let support = handle.check_server("example.org").await.unwrap();
+        assert!(support.password_login);
+        assert_eq!(support.registration, Registration::InApp);
+    }
+
+    #[tokio::test]
+    async fn test_server_status_names_failures() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let status = handle.server_status("example.org").await.unwrap();
+        assert_eq!(status.latency.as_millis(), 87);
+        let err = handle.server_status(UNKNOWN_HOMESERVER).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DiscoveryError>(),
+            Some(&DiscoveryError::Dns)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_installs_client() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let homeserver = "https://example.org";
+        assert_eq!(
+            handle.check_username(homeserver, "bob").await.unwrap(),
+            Availability::Taken
+        );
+        let err = handle
+            .register(homeserver, "bob", "hunter2")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RegisterError>(),
+            Some(&RegisterError::UsernameTaken)
+        );
+        assert!(handle.notifications().await.is_err());
+
+        let (user_id, _) = handle
+            .register(homeserver, "alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(user_id, "@alice:example.org");
+        handle.switch_room("!room:example.org").await.unwrap();
+        assert!(handle.send_message("hello", "t1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_password_reset() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let homeserver = "https://example.org";
+
+        // An address on no account looks mailed, and never validates.
+        let unknown = handle
+            .request_password_reset(homeserver, "nobody@example.org")
+            .await
+            .unwrap();
+        let err = handle
+            .complete_password_reset(homeserver, unknown, "correct horse")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ResetError>(),
+            Some(&ResetError::NotValidated)
+        );
+
+        let reset = handle
+            .request_password_reset(homeserver, "alice@example.org")
+            .await
+            .unwrap();
+        let reset = handle
+            .resend_password_reset(homeserver, reset)
+            .await
+            .unwrap();
+        assert_eq!(reset.validation.send_attempt, 2);
+        let err = handle
+            .complete_password_reset(homeserver, reset.clone(), "correct horse")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ResetError>(),
+            Some(&ResetError::NotValidated)
+        );
+        let err = handle
+            .submit_reset_token(homeserver, reset.clone(), "000000")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ResetError>(),
+            Some(&ResetError::WrongCode)
+        );
+        handle
+            .submit_reset_token(homeserver, reset.clone(), RESET_CODE)
+            .await
+            .unwrap();
+        handle
+            .complete_password_reset(homeserver, reset, "correct horse")
+            .await
+            .unwrap();
+        // Resetting signs nobody in.
+        assert!(handle.notifications().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_profile_changes_are_read_back() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle.get_profile().await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let display_name = handle.set_display_name("Alice");
+        let avatar = handle.set_avatar(vec![1, 2, 3], "text/plain");
+        let bio = handle.set_bio("Support main");
+        display_name.await.unwrap();
+        assert!(avatar.await.is_err());
+        bio.await.unwrap();
+
+        let profile = handle.get_profile().await.unwrap();
+        assert_eq!(profile.display_name.as_deref(), Some("Alice"));
+        assert_eq!(profile.avatar, None);
+        assert_eq!(profile.bio, "Support main");
+    }
+
+    #[tokio::test]
+    async fn test_favorites_are_read_back() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(handle.get_favorites().await.unwrap(), None);
+
+        let rooms = vec![
+            "!raids:example.org".to_string(),
+            "!lobby:example.org".to_string(),
+        ];
+        handle.set_favorites(rooms.clone()).await.unwrap();
+        assert_eq!(handle.get_favorites().await.unwrap(), Some(rooms));
+    }
+
+    #[tokio::test]
+    async fn test_full_member_list_is_fetched_on_request() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let room = "!room:example.org";
+
+        let known = handle.get_room_members(room, false).await.unwrap();
+        assert_eq!(known.members.len(), 1);
+        assert_eq!(known.total, 2);
+        let all = handle.get_room_members(room, true).await.unwrap();
+        assert_eq!(all.members.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_voice_announcement_is_withdrawn_on_leave() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let room = "!room:example.org";
+
+        let join = handle.join_voice(room, vec!["192.0.2.1:4000".to_string()]);
+        let members = handle.voice_members(room);
+        join.await.unwrap();
+        let members = members.await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].user_id, "@alice:example.org");
+        assert_eq!(members[0].name(), "alice");
+
+        // Re-announcing replaces rather than duplicates.
+        handle.join_voice(room, vec![]).await.unwrap();
+        assert_eq!(handle.voice_members(room).await.unwrap().len(), 1);
+
+        handle.leave_voice(room).await.unwrap();
+        assert!(handle.voice_members(room).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_refusals_are_reported() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let space = "!space:example.org";
+
+        let err = handle
+            .create_channel(Some(LOCKED_SPACE), "raids")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("add channels to this server"))
+        );
+        assert!(handle.create_channel(Some(space), "raids").await.is_ok());
+        assert!(handle
+            .remove_channel(None, "!raids:example.org", ChannelRemoval::FromSpace)
+            .await
+            .is_err());
+
+        let role = Role {
+            name: "Raid Lead".to_string(),
+            level: 75,
+        };
+        assert!(handle
+            .create_role(LOCKED_SPACE, role.clone())
+            .await
+            .is_err());
+        handle.create_role(space, role.clone()).await.unwrap();
+        let roles = handle.get_roles(space).await.unwrap();
+        assert_eq!(roles[1], role);
+        assert!(handle
+            .set_power_level(LOCKED_SPACE, "@bob:example.org", 75)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pins_change_and_are_announced() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.get_pinned(room).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let mut events = handle.load_rooms(1).await.unwrap().events;
+
+        assert!(handle.get_permissions(room).await.unwrap().pin);
+        handle.pin_message(room, "$a", true).await.unwrap();
+        handle.pin_message(room, "$b", true).await.unwrap();
+        handle.pin_message(room, "$a", false).await.unwrap();
+        assert_eq!(handle.get_pinned(room).await.unwrap(), ["$b"]);
+        let mut announced = Vec::new();
+        while let Ok(ChatEvent::PinsChanged { pinned, .. }) = events.try_recv() {
+            announced.push(pinned);
+        }
+        assert_eq!(announced, [vec!["$a"], vec!["$a", "$b"], vec!["$b"]]);
+
+        let err = handle
+            .pin_message(LOCKED_SPACE, "$a", true)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("pin messages"))
+        );
+        assert_eq!(handle.get_event(room, "$b").await.unwrap().id, "$b");
+        let details = handle.get_event_details(room, "$b").await.unwrap();
+        assert_eq!(details.event_id, "$b");
+        assert!(details.edited_at.is_some());
+        assert!(handle.get_event_details(MISSING_ROOM, "$b").await.is_err());
+    }
+
+    fn sticker(shortcode: &str) -> Sticker {
+        Sticker {
+            shortcode: shortcode.to_string(),
+            body: shortcode.to_string(),
+            url: format!("mxc://example.org/{}", shortcode),
+            mimetype: None,
+            size: None,
+            width: None,
+            height: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_history_pages() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let page = handle
+            .state_history(room, AuditKind::ALL.to_vec(), None)
+            .await
+            .unwrap();
+        assert_eq!(page.entries[0].changes[0].new.as_deref(), Some("Raids"));
+        let older = handle
+            .state_history(room, AuditKind::ALL.to_vec(), page.next)
+            .await
+            .unwrap();
+        assert_eq!(older.entries[0].changes[0].old.as_deref(), Some("General"));
+        assert_eq!(older.next, None);
+        let members = handle
+            .state_history(room, vec![AuditKind::Membership], None)
+            .await
+            .unwrap();
+        assert!(members.entries.is_empty());
+        assert!(handle
+            .state_history(MISSING_ROOM, AuditKind::ALL.to_vec(), None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stickers_are_offered_and_sent() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.sticker_packs(room).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let packs = handle.sticker_packs(room).await.unwrap();
+        assert_eq!(packs[0].name, "Cats");
+        let sticker = packs[0].stickers[0].clone();
+        assert_eq!(
+            handle.send_sticker(room, sticker.clone()).await.unwrap(),
+            "$sticker-cat_happy"
+        );
+        assert!(handle.sticker_packs(MISSING_ROOM).await.is_err());
+        assert!(handle.send_sticker(MISSING_ROOM, sticker).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_game_invites_are_checked_and_sent() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        let invite = GameInvite {
+            game: "Valorant".to_string(),
+            connect: "steam://connect/203.0.113.7:27015".to_string(),
+            party_size: Some(5),
+            expires_ts: chat_core::time::now_ms() + 30 * 60 * 1000,
+        };
+        assert!(handle.send_game_invite(room, invite.clone()).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handle.send_game_invite(room, invite.clone()).await.unwrap(),
+            "$invite-Valorant"
+        );
+        let expired = GameInvite {
+            expires_ts: 1,
+            ..invite.clone()
+        };
+        assert!(handle.send_game_invite(room, expired).await.is_err());
+        assert!(handle.send_game_invite(MISSING_ROOM, invite).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slash_command_calls() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.send_emote(room, "waves").await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(handle.send_emote(room, "waves").await.unwrap(), "$emote");
+        assert!(handle.send_emote(MISSING_ROOM, "waves").await.is_err());
+        handle.set_topic(room, "Raid night").await.unwrap();
+        handle.invite_user(room, "@bob:example.org").await.unwrap();
+        let err = handle
+            .invite_user(LOCKED_SPACE, "@bob:example.org")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("invite people"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_settings() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.get_room_settings(room).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let settings = handle.get_room_settings(room).await.unwrap();
+        assert!(!settings.encrypted && settings.can_change.encryption);
+        let locked = handle.get_room_settings(LOCKED_SPACE).await.unwrap();
+        assert!(!locked.can_change.join_rule);
+        handle.set_join_rule(room, JoinRule::Knock).await.unwrap();
+        let err = handle
+            .set_guest_access(LOCKED_SPACE, true)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("change guest access"))
+        );
+        handle.set_slow_mode(room, 30).await.unwrap();
+        assert!(handle.set_slow_mode(LOCKED_SPACE, 30).await.is_err());
+
+        handle.enable_encryption(room).await.unwrap();
+        let settings = handle.get_room_settings(room).await.unwrap();
+        assert!(settings.encrypted && !settings.can_change.encryption);
+    }
+
+    #[tokio::test]
+    async fn test_welcome_message() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.get_welcome(room).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(handle.get_welcome(room).await.unwrap(), None);
+
+        let mut events = handle.load_rooms(1).await.unwrap().events;
+        handle.set_welcome(room, "Be nice.", true).await.unwrap();
+        assert!(std::iter::from_fn(|| events.try_recv().ok())
+            .any(|event| matches!(event, ChatEvent::WelcomeChanged { .. })));
+        // Whoever sets it has accepted it.
+        let (welcome, seen) = handle.get_welcome(room).await.unwrap().unwrap();
+        assert_eq!(welcome.body, "Be nice.");
+        assert_eq!(seen, Seen::Accepted);
+        let settings = handle.get_room_settings(room).await.unwrap();
+        assert_eq!(settings.welcome, Some(welcome.clone()));
+
+        let event_id = &welcome.event_id;
+        handle
+            .mark_welcome(room, event_id, Seen::New)
+            .await
+            .unwrap();
+        let (_, seen) = handle.get_welcome(room).await.unwrap().unwrap();
+        assert!(welcome.shows(seen) && welcome.holds_composer(seen));
+        handle
+            .mark_welcome(room, event_id, Seen::Dismissed)
+            .await
+            .unwrap();
+        let (_, seen) = handle.get_welcome(room).await.unwrap().unwrap();
+        assert!(!welcome.shows(seen) && welcome.holds_composer(seen));
+        handle
+            .mark_welcome(room, event_id, Seen::Accepted)
+            .await
+            .unwrap();
+        let (_, seen) = handle.get_welcome(room).await.unwrap().unwrap();
+        assert!(!welcome.holds_composer(seen));
+
+        let err = handle
+            .set_welcome(LOCKED_SPACE, "Mine now", false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("change the welcome message"))
+        );
+        handle.set_welcome(room, " ", false).await.unwrap();
+        assert_eq!(handle.get_welcome(room).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_knocks() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let room_id = handle
+            .knock_on_room("#lobby:example.org", vec![], Some("Friend of Bob"))
+            .await
+            .unwrap();
+        assert_eq!(room_id, "!lobby:example.org");
+        let err = handle
+            .knock_on_room(LOCKED_SPACE, vec![], None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<KnockError>(),
+            Some(&KnockError::Unsupported)
+        );
+        handle.retract_knock(&room_id).await.unwrap();
+
+        let requests = handle.knock_requests().await.unwrap();
+        assert_eq!(requests[0].name(), "bob");
+        let request = &requests[0];
+        handle
+            .answer_knock(&request.room_id, &request.user_id, true)
+            .await
+            .unwrap();
+        let err = handle
+            .answer_knock(LOCKED_SPACE, &request.user_id, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("turn people away"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_preview_room() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle
+            .preview_room("#lobby:example.org", vec![])
+            .await
+            .is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let preview = handle
+            .preview_room("#lobby:example.org", vec![])
+            .await
+            .unwrap();
+        assert_eq!(preview.room_id, "!lobby:example.org");
+        assert!(preview.peekable);
+        assert_eq!(preview.page.messages[0].content, "peeked");
+    }
+
+    #[tokio::test]
+    async fn test_pushers() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let config = PusherConfig {
+            email: Some("alice@example.org".to_string()),
+            gateway: None,
+        };
+        handle.register_pushers(config.clone()).await.unwrap();
+        handle.register_pushers(config).await.unwrap();
+        let pushers = handle.list_pushers().await.unwrap();
+        assert_eq!(pushers.len(), 2);
+        assert_eq!(pushers[1].pushkey, "alice@example.org");
+
+        let locked = PusherConfig {
+            email: None,
+            gateway: Some(GatewayConfig {
+                url: LOCKED_GATEWAY.to_string(),
+                app_id: "org.example.companion".to_string(),
+            }),
+        };
+        let err = handle.register_pushers(locked).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PusherError>(),
+            Some(&PusherError::Disabled)
+        );
+
+        handle
+            .remove_pusher(EMAIL_APP_ID, "alice@example.org")
+            .await
+            .unwrap();
+        let pushers = handle.list_pushers().await.unwrap();
+        assert_eq!(pushers.len(), 1);
+        assert_eq!(pushers[0].app_display_name, "Element");
+    }
+
+    #[tokio::test]
+    async fn test_email_addresses() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let err = handle.request_email_token(TAKEN_EMAIL).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ThreepidError>(),
+            Some(&ThreepidError::InUse)
+        );
+
+        let validation = handle
+            .request_email_token("alice@example.org")
+            .await
+            .unwrap();
+        let again = handle.resend_email_token(validation.clone()).await.unwrap();
+        assert_eq!(again.sid, validation.sid);
+        assert_eq!(again.send_attempt, 2);
+        let err = handle.add_3pid(again.clone(), "wrong").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ThreepidError>(),
+            Some(&ThreepidError::WrongPassword)
+        );
+        handle.add_3pid(again, "hunter2").await.unwrap();
+
+        let unread = handle.request_email_token(UNREAD_EMAIL).await.unwrap();
+        let err = handle.add_3pid(unread, "hunter2").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ThreepidError>(),
+            Some(&ThreepidError::NotValidated)
+        );
+
+        let threepids = handle.get_3pids().await.unwrap();
+        assert_eq!(threepids.len(), 1);
+        assert_eq!(threepids[0].address, "alice@example.org");
+        handle.remove_3pid("alice@example.org").await.unwrap();
+        assert!(handle.get_3pids().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_room_aliases() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let room = "!room:example.org";
+
+        let alias = handle.add_room_alias(room, "lobby").await.unwrap();
+        assert_eq!(alias, "#lobby:example.org");
+        let err = handle
+            .add_room_alias("!other:example.org", "#lobby")
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<AliasError>(), Some(&AliasError::Taken));
+        let err = handle
+            .add_room_alias(room, "#lobby:matrix.org")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AliasError>(),
+            Some(&AliasError::OtherServer)
+        );
+        assert_eq!(
+            handle.get_room_aliases(room).await.unwrap().aliases,
+            vec![alias.clone()]
+        );
+
+        assert!(handle
+            .set_canonical_alias(LOCKED_SPACE, &alias)
+            .await
+            .is_err());
+        handle.remove_room_alias(&alias).await.unwrap();
+        assert!(handle
+            .get_room_aliases(room)
+            .await
+            .unwrap()
+            .aliases
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_message_actions() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.delete_message(room, "$1").await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handle.edit_message(room, "$1", "fixed").await.unwrap(),
+            "$edit-$1"
+        );
+        assert_eq!(
+            handle.react_to_message(room, "$1", "👍").await.unwrap(),
+            "$react-$1-👍"
+        );
+        assert_eq!(
+            handle
+                .forward_message(room, "$1", "!other:example.org", Some("Forwarded"))
+                .await
+                .unwrap(),
+            "$forward-$1-annotated"
+        );
+        assert!(handle
+            .forward_message(room, "$1", MISSING_ROOM, None)
+            .await
+            .is_err());
+        assert!(handle.delete_message(room, "$1").await.is_ok());
+        let err = handle.delete_message(LOCKED_SPACE, "$1").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("delete this message"))
+        );
+        assert!(!handle.get_permissions(LOCKED_SPACE).await.unwrap().react);
+
+        assert_eq!(
+            handle
+                .report_message(room, "$1", None, Some("spam"))
+                .await
+                .unwrap(),
+            Reported::Sent
+        );
+        assert_eq!(
+            handle.report_room(LOCKED_SPACE, None).await.unwrap(),
+            Reported::Unsupported
+        );
+        assert!(handle.ignore_user("@spammer:example.org").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_media_downloads() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle.download_media("mxc").await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(handle.download_media("mxc").await.unwrap(), b"mxc");
+        assert!(handle.download_media(MISSING_MEDIA).await.is_err());
+
+        let attachment = Attachment {
+            source: "full".to_string(),
+            thumbnail_source: Some("thumb".to_string()),
+            name: "cat.png".to_string(),
+            mimetype: Some("image/png".to_string()),
+            size: None,
+            width: None,
+            height: None,
+        };
+        assert_eq!(
+            handle.download_thumbnail(&attachment).await.unwrap(),
+            b"thumb"
+        );
+
+        let preview = handle.get_url_preview("https://a.org", 0).await.unwrap();
+        assert_eq!(preview.unwrap().title.as_deref(), Some("https://a.org"));
+        assert_eq!(
+            handle.get_url_preview(MISSING_MEDIA, 0).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_attachment_uploads() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        let (sent, _) = handle.send_attachment(room, Upload::new("a.png", vec![0; 4]), "t1");
+        assert!(sent.await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(handle.upload_limit().await.unwrap(), UPLOAD_LIMIT);
+        let (sent, progress) = handle.send_attachment(room, Upload::new("a.png", vec![0; 4]), "t2");
+        assert_eq!(sent.await.unwrap(), "$upload-a.png");
+        assert_eq!(*progress.borrow(), UploadProgress { sent: 4, total: 4 });
+
+        let too_large = Upload::new("big.bin", vec![0; UPLOAD_LIMIT as usize + 1]);
+        let (sent, _) = handle.send_attachment(room, too_large, "t3");
+        assert!(sent.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_voice_messages_send() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        let clip = Duration::from_millis(1500);
+        let sent = handle.send_voice_message(room, vec![0; 4], clip, "v1");
+        assert!(sent.await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let sent = handle.send_voice_message(room, vec![0; 4], clip, "v2");
+        assert_eq!(sent.await.unwrap(), "$voice-v2-1500ms");
+        let too_large = vec![0; UPLOAD_LIMIT as usize + 1];
+        let sent = handle.send_voice_message(room, too_large, clip, "v3");
+        assert!(sent.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_user_messages() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        let spammer = "@spammer:example.org";
+        let (purged, _) = handle.purge_user_messages(room, spammer, PurgeWindow::default(), true);
+        assert!(purged.await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let window = PurgeWindow {
+            max_age: Duration::from_secs(10 * 60),
+            max_events: 100,
+        };
+        let (purged, progress) = handle.purge_user_messages(room, spammer, window, true);
+        let report = purged.await.unwrap();
+        assert_eq!(
+            *progress.borrow(),
+            PurgeProgress {
+                done: 10,
+                total: 10
+            }
+        );
+        assert!(report.banned);
+        assert_eq!((report.found, report.redacted), (10, 9));
+        assert_eq!(report.failed[0].0, "$last-of-@spammer:example.org");
+
+        let (purged, _) = handle.purge_user_messages(LOCKED_SPACE, spammer, window, false);
+        let err = purged.await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AdminError>(),
+            Some(&AdminError::Forbidden("ban members"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_friends() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let bob = "@bob:example.org";
+        assert!(handle.get_friends().await.is_err());
+        assert!(handle.add_friend(bob).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        handle.add_friend(bob).await.unwrap();
+        let err = handle.add_friend("@alice:example.org").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::FromSelf)
+        );
+        let friends = handle.get_friends().await.unwrap();
+        assert_eq!(friends.len(), 1);
+        assert_eq!(friends[0].state, FriendState::Requested);
+        assert_eq!(friends[0].room_id, "!dm-@bob:example.org");
+        assert_eq!(friends[0].name(), "bob");
+        // Bob hasn't asked, so there is nothing to accept.
+        handle.respond_to_friend(bob, true).await.unwrap();
+        assert_eq!(
+            handle.get_friends().await.unwrap()[0].state,
+            FriendState::Requested
+        );
+
+        let mut events = handle.load_rooms(1).await.unwrap().events;
+        handle.remove_friend(bob).await.unwrap();
+        assert!(std::iter::from_fn(|| events.try_recv().ok())
+            .any(|event| matches!(event, ChatEvent::RosterChanged)));
+        assert!(handle.get_friends().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_typing_notices() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.typing().await.is_err());
+        assert!(handle.set_typing(room, true).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let mut typing = handle.typing().await.unwrap();
+        handle.set_typing(room, true).await.unwrap();
+        let event = typing.recv().await.unwrap();
+        assert_eq!(event.room_id, room);
+        assert_eq!(event.users.len(), 1);
+        assert_eq!(event.users[0].name(), "alice");
+        handle.set_typing(room, false).await.unwrap();
+        assert!(typing.recv().await.unwrap().users.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_state_ends_with_logout() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle.connection().await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let mut connection = handle.connection().await.unwrap();
+        assert!(connection.state.borrow().is_connected());
+        handle.logout().await.unwrap();
+        assert!(connection.state.changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_ends_the_client_task() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        handle.set_presence(UserStatus::Idle).await.unwrap();
+
+        handle.shutdown().await.unwrap();
+        assert!(handle.set_presence(UserStatus::Online).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_closed_handle_finishes_what_was_issued() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        let issued = handle.set_presence(UserStatus::Idle);
+        handle.close();
+
+        assert!(handle.set_presence(UserStatus::Online).await.is_err());
+        issued.await.unwrap();
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clear_cache_by_category() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle.cache_size().await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(handle.cache_size().await.unwrap().total(), 325);
+        handle.clear_cache(CacheCategory::Media).await.unwrap();
+        let usage = handle.cache_size().await.unwrap();
+        assert_eq!((usage.media, usage.avatars, usage.timeline), (0, 20, 5));
+    }
+
+    #[tokio::test]
+    async fn test_prune_and_clear_local_history() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle.clear_local_history().await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        handle.prune_cache(Retention::Forever).await.unwrap();
+        assert_eq!(handle.cache_size().await.unwrap().total(), 325);
+        handle.prune_cache(Retention::Days7).await.unwrap();
+        assert_eq!(handle.cache_size().await.unwrap().total(), 0);
+
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+        handle.clear_local_history().await.unwrap();
+        assert_eq!(handle.cache_size().await.unwrap().total(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_search_messages() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        assert!(handle.search_local("gg", None).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        let lobby = "!lobby:example.org";
+        handle.switch_room(lobby).await.unwrap();
+        handle.send_message("gg everyone", "t1").await.unwrap();
+        handle.switch_room("!raid:example.org").await.unwrap();
+        handle.send_message("gg", "t2").await.unwrap();
+
+        let local = handle.search_local("gg", None).await.unwrap();
+        assert_eq!(local.len(), 2);
+        let server = handle
+            .search_server("gg", Some(lobby.to_string()), CancelToken::new())
+            .await
+            .unwrap();
+        assert_eq!(server.len(), 1);
+        assert_eq!(server[0].room_id, lobby);
+        assert_eq!(merge_hits(local, server).len(), 2);
+
+        handle.clear_local_history().await.unwrap();
+        assert!(handle.search_local("gg", None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_room_security() {
+        let handle = ClientHandle::spawn_with::<FakeClient>();
+        let room = "!room:example.org";
+        assert!(handle.room_security(room).await.is_err());
+        handle
+            .login("https://example.org", "alice", "hunter2")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handle.room_security(room).await.unwrap(),
+            RoomSecurity::default()
+        );
+        handle.enable_encryption(room).await.unwrap();
+        let security = handle.room_security(room).await.unwrap();
+        assert!(security.encrypted && security.has_unverified_devices());
+    }
+}
@@ -0,0 +1,377 @@
+//! Images and files in the timeline: inline previews loaded as their rows
+//! are shown, images opened at full size, and files saved where the user
+//! picks.
+
+use crate::app_state::ClientHandle;
+use crate::attachments::{self, Downloads, Fetch};
+use crate::{animations, history, uploads, AppWindow, AttachmentData, ImageViewerData, MediaState};
+use chat_core::{format_size, Attachment, Message};
+use network::media::{UploadProgress, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use network::media_cache::{self, Pinned};
+use slint::{ComponentHandle, Image, Rgba8Pixel, SharedPixelBuffer, SharedString};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Largest edge of an image shown at full size; bigger ones are scaled down.
+const VIEWER_MAX_SIZE: u32 = 2048;
+
+struct Preview {
+    image: Image,
+    /// The downloaded bytes: for GIFs and APNGs the whole animation, of
+    /// which `image` is the first frame.
+    bytes: Vec<u8>,
+    /// Keeps the cached file while the preview is loaded; our own uploads
+    /// weren't downloaded, so have none.
+    _pinned: Option<Pinned<'static>>,
+}
+
+thread_local! {
+    /// Inline previews by event id.
+    static PREVIEWS: RefCell<Downloads<Preview>> = RefCell::new(Downloads::default());
+    /// Files saved to disk by event id, with where they went.
+    static SAVES: RefCell<Downloads<PathBuf>> = RefCell::new(Downloads::default());
+    /// Our uploads in flight by transaction id.
+    static UPLOADS: RefCell<HashMap<String, UploadProgress>> = RefCell::new(HashMap::new());
+    /// Keeps the cached file of the image open at full size.
+    static VIEWING: RefCell<Option<Pinned<'static>>> = const { RefCell::new(None) };
+}
+
+fn media_state<T>(fetch: Option<&Fetch<T>>) -> MediaState {
+    match fetch {
+        None => MediaState::Idle,
+        Some(Fetch::Loading) => MediaState::Loading,
+        Some(Fetch::Done(_)) => MediaState::Ready,
+        Some(Fetch::Failed) => MediaState::Failed,
+    }
+}
+
+/// How to show the attachment of `message`, if it has one.
+pub fn attachment_data(message: &Message) -> AttachmentData {
+    let Some(attachment) = &message.attachment else {
+        return AttachmentData::default();
+    };
+    let (preview, preview_state) = PREVIEWS.with(|p| {
+        let previews = p.borrow();
+        let fetch = previews.get(&message.id);
+        let image = match fetch {
+            Some(Fetch::Done(preview)) => preview.image.clone(),
+            _ => Image::default(),
+        };
+        (image, media_state(fetch))
+    });
+    let (frames, delays) = animations::frames(&message.id);
+    let (width, height) = match preview.size() {
+        size if size.width > 0 => attachments::fit(size.width, size.height),
+        _ => attachments::fit(
+            attachment.width.unwrap_or(THUMBNAIL_WIDTH),
+            attachment.height.unwrap_or(THUMBNAIL_HEIGHT),
+        ),
+    };
+    AttachmentData {
+        name: SharedString::from(attachment.name.as_str()),
+        size: attachment
+            .size
+            .map(|bytes| SharedString::from(format_size(bytes)))
+            .unwrap_or_default(),
+        preview,
+        preview_state,
+        animated: attachment.is_animated(),
+        frames,
+        delays,
+        width: width as f32,
+        height: height as f32,
+        save_state: SAVES.with(|s| media_state(s.borrow().get(&message.id))),
+        uploading: UPLOADS.with(|u| u.borrow().contains_key(&message.id)),
+        upload_progress: UPLOADS.with(|u| {
+            u.borrow()
+                .get(&message.id)
+                .map_or(0.0, |progress| uploads::fraction(*progress))
+        }),
+    }
+}
+
+/// The downloaded bytes of the loaded preview of `event_id`.
+pub fn preview_bytes(event_id: &str) -> Option<Vec<u8>> {
+    PREVIEWS.with(|p| match p.borrow().get(event_id) {
+        Some(Fetch::Done(preview)) => Some(preview.bytes.clone()),
+        _ => None,
+    })
+}
+
+/// The attachment of `event_id` in whichever cached room has it, with the
+/// room's id.
+fn find(event_id: &str) -> Option<(String, Attachment)> {
+    let room_id = history::room_of(event_id)?;
+    let attachment =
+        history::with_message(&room_id, event_id, |timeline| timeline.message(event_id))
+            .flatten()?
+            .attachment?;
+    Some((room_id, attachment))
+}
+
+fn refresh(room_id: &str, event_id: &str) {
+    history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+}
+
+/// Decode an image no larger than `max_width`x`max_height`; the first
+/// frame of an animation.
+fn decode(data: &[u8], max_width: u32, max_height: u32) -> Option<SharedPixelBuffer<Rgba8Pixel>> {
+    let image = image::load_from_memory(data).ok()?;
+    let image = if image.width() > max_width || image.height() > max_height {
+        image.thumbnail(max_width, max_height)
+    } else {
+        image
+    }
+    .into_rgba8();
+    Some(SharedPixelBuffer::clone_from_slice(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+    ))
+}
+
+/// Download `reply` and decode it off the UI thread.
+pub async fn load_image(
+    reply: impl std::future::Future<Output = anyhow::Result<Vec<u8>>>,
+    max_width: u32,
+    max_height: u32,
+) -> Option<(SharedPixelBuffer<Rgba8Pixel>, Vec<u8>)> {
+    let data = match reply.await {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!("Failed to download image: {}", e);
+            return None;
+        }
+    };
+    let decoded = tokio::task::spawn_blocking(move || {
+        let buffer = decode(&data, max_width, max_height);
+        buffer.map(|buffer| (buffer, data))
+    })
+    .await
+    .ok()
+    .flatten();
+    if decoded.is_none() {
+        tracing::warn!("Failed to decode image");
+    }
+    decoded
+}
+
+/// Load the inline preview of image message `event_id`, unless it is
+/// loaded or loading already.
+pub fn load_preview(client: &ClientHandle, event_id: &str) {
+    let Some((room_id, attachment)) = find(event_id) else {
+        return;
+    };
+    // Our own upload, not on the server yet.
+    if attachment.source.is_empty() {
+        return;
+    }
+    if !PREVIEWS.with(|p| p.borrow_mut().start(event_id)) {
+        return;
+    }
+    refresh(&room_id, event_id);
+
+    let reply = client.download_thumbnail(&attachment);
+    let key = media_cache::thumbnail_key(&attachment);
+    let event_id = event_id.to_string();
+    tokio::spawn(async move {
+        let loaded = load_image(reply, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT).await;
+        slint::invoke_from_event_loop(move || {
+            let preview = loaded.map(|(buffer, bytes)| Preview {
+                image: Image::from_rgba8(buffer),
+                bytes,
+                _pinned: Some(media_cache::shared().pin(&key)),
+            });
+            PREVIEWS.with(|p| p.borrow_mut().finish(&event_id, preview));
+            refresh(&room_id, &event_id);
+        })
+        .ok();
+    });
+}
+
+/// Show image message `event_id` at full size, with its preview until the
+/// full image has loaded.
+pub fn open_image(ui: &AppWindow, client: &ClientHandle, event_id: &str) {
+    let Some((_, attachment)) = find(event_id) else {
+        return;
+    };
+    let preview = PREVIEWS.with(|p| match p.borrow().get(event_id) {
+        Some(Fetch::Done(preview)) => preview.image.clone(),
+        _ => Image::default(),
+    });
+    ui.set_image_viewer(ImageViewerData {
+        id: SharedString::from(event_id),
+        name: SharedString::from(attachment.name.as_str()),
+        image: preview,
+        state: MediaState::Loading,
+    });
+    ui.set_show_image_viewer(true);
+    let pinned = media_cache::shared().pin(&media_cache::media_key(&attachment.source));
+    VIEWING.with(|v| *v.borrow_mut() = Some(pinned));
+
+    let reply = client.download_media(&attachment.source);
+    let event_id = event_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let loaded = load_image(reply, VIEWER_MAX_SIZE, VIEWER_MAX_SIZE).await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let mut viewer = ui.get_image_viewer();
+            if !ui.get_show_image_viewer() || viewer.id.as_str() != event_id {
+                return;
+            }
+            match loaded {
+                Some((buffer, _)) => {
+                    viewer.image = Image::from_rgba8(buffer);
+                    viewer.state = MediaState::Ready;
+                }
+                None => viewer.state = MediaState::Failed,
+            }
+            ui.set_image_viewer(viewer);
+        })
+        .ok();
+    });
+}
+
+/// The full-size image was closed; its file may be evicted again.
+pub fn close_image() {
+    VIEWING.with(|v| v.borrow_mut().take());
+}
+
+/// Ask where to save file message `event_id`, then download it there.
+pub fn save_file(client: &ClientHandle, event_id: &str) {
+    let Some((room_id, attachment)) = find(event_id) else {
+        return;
+    };
+    if SAVES.with(|s| matches!(s.borrow().get(event_id), Some(Fetch::Loading))) {
+        return;
+    }
+    // Created here, on the UI thread, as some platforms require.
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Save file")
+        .set_file_name(attachments::file_name(&attachment.name))
+        .save_file();
+    let client = client.clone();
+    let event_id = event_id.to_string();
+    tokio::spawn(async move {
+        let Some(file) = dialog.await else {
+            return;
+        };
+        let path = file.path().to_path_buf();
+        slint::invoke_from_event_loop(move || {
+            download_to(&client, room_id, event_id, &attachment.source, path)
+        })
+        .ok();
+    });
+}
+
+fn download_to(
+    client: &ClientHandle,
+    room_id: String,
+    event_id: String,
+    source: &str,
+    path: PathBuf,
+) {
+    let started = SAVES.with(|s| {
+        let mut saves = s.borrow_mut();
+        // Saving again, e.g. to another place.
+        if let Some(Fetch::Done(_)) = saves.get(&event_id) {
+            saves.forget(&event_id);
+        }
+        saves.start(&event_id)
+    });
+    if !started {
+        return;
+    }
+    refresh(&room_id, &event_id);
+
+    let reply = client.download_media(source);
+    tokio::spawn(async move {
+        let result = match reply.await {
+            Ok(data) => tokio::fs::write(&path, data).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        let saved = match result {
+            Ok(()) => Some(path),
+            Err(e) => {
+                tracing::warn!("Failed to save {}: {}", path.display(), e);
+                None
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            SAVES.with(|s| s.borrow_mut().finish(&event_id, saved));
+            refresh(&room_id, &event_id);
+        })
+        .ok();
+    });
+}
+
+/// Show the upload `txn_id` in `room_id` as in progress. Image uploads are
+/// previewed from `image`, their own bytes, rather than downloaded back.
+pub fn upload_started(room_id: &str, txn_id: &str, image: Option<Vec<u8>>) {
+    UPLOADS.with(|u| {
+        u.borrow_mut()
+            .insert(txn_id.to_string(), UploadProgress::default())
+    });
+    refresh(room_id, txn_id);
+    let Some(data) = image else {
+        return;
+    };
+    if !PREVIEWS.with(|p| p.borrow_mut().start(txn_id)) {
+        return;
+    }
+    let room_id = room_id.to_string();
+    let txn_id = txn_id.to_string();
+    tokio::spawn(async move {
+        let loaded = load_image(async { Ok(data) }, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT).await;
+        slint::invoke_from_event_loop(move || {
+            let preview = loaded.map(|(buffer, bytes)| Preview {
+                image: Image::from_rgba8(buffer),
+                bytes,
+                _pinned: None,
+            });
+            PREVIEWS.with(|p| p.borrow_mut().finish(&txn_id, preview));
+            refresh(&room_id, &txn_id);
+        })
+        .ok();
+    });
+}
+
+/// `progress` of the upload `txn_id`, unless it has finished already.
+pub fn upload_progress(room_id: &str, txn_id: &str, progress: UploadProgress) {
+    let updated = UPLOADS.with(|u| match u.borrow_mut().get_mut(txn_id) {
+        Some(current) => {
+            *current = progress;
+            true
+        }
+        None => false,
+    });
+    if updated {
+        refresh(room_id, txn_id);
+    }
+}
+
+/// The upload `txn_id` is done: sent as `event_id`, or failed. Its preview
+/// moves to the event id, which its row goes by from now on.
+pub fn upload_finished(room_id: &str, txn_id: &str, event_id: Option<&str>) {
+    UPLOADS.with(|u| u.borrow_mut().remove(txn_id));
+    refresh(room_id, txn_id);
+    if let Some(event_id) = event_id {
+        PREVIEWS.with(|p| p.borrow_mut().rename(txn_id, event_id));
+        refresh(room_id, event_id);
+    }
+}
+
+/// Drop loaded previews and download states, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    animations::clear();
+    PREVIEWS.with(|p| p.borrow_mut().clear());
+    SAVES.with(|s| s.borrow_mut().clear());
+    UPLOADS.with(|u| u.borrow_mut().clear());
+    close_image();
+    ui.set_show_image_viewer(false);
+    ui.set_image_viewer(ImageViewerData::default());
+}
@@ -0,0 +1,139 @@
+//! Bookkeeping for attachments shown in the timeline: the size images are
+//! laid out at, and which downloads are in flight, done or failed.
+
+use network::media::{THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use std::collections::HashMap;
+
+/// Size to show a `width`x`height` image at inline: scaled down to fit the
+/// thumbnail box, never up.
+pub fn fit(width: u32, height: u32) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (0, 0);
+    }
+    let scale = f64::min(
+        1.0,
+        f64::min(
+            THUMBNAIL_WIDTH as f64 / width as f64,
+            THUMBNAIL_HEIGHT as f64 / height as f64,
+        ),
+    );
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// A file name to suggest when saving `name`: its last path component, as
+/// senders can put anything in there.
+pub fn file_name(name: &str) -> &str {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    match name {
+        "" | "." | ".." => "download",
+        name => name,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fetch<T> {
+    Loading,
+    Done(T),
+    Failed,
+}
+
+/// Downloads by event id. Each is started at most once, unless it failed.
+#[derive(Debug)]
+pub struct Downloads<T> {
+    entries: HashMap<String, Fetch<T>>,
+}
+
+impl<T> Default for Downloads<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Downloads<T> {
+    pub fn get(&self, event_id: &str) -> Option<&Fetch<T>> {
+        self.entries.get(event_id)
+    }
+
+    /// Mark `event_id` as loading. Returns false if it already is, or is
+    /// done; a failed download is started again.
+    pub fn start(&mut self, event_id: &str) -> bool {
+        match self.entries.get(event_id) {
+            Some(Fetch::Loading | Fetch::Done(_)) => false,
+            Some(Fetch::Failed) | None => {
+                self.entries.insert(event_id.to_string(), Fetch::Loading);
+                true
+            }
+        }
+    }
+
+    pub fn finish(&mut self, event_id: &str, result: Option<T>) {
+        let fetch = match result {
+            Some(value) => Fetch::Done(value),
+            None => Fetch::Failed,
+        };
+        self.entries.insert(event_id.to_string(), fetch);
+    }
+
+    /// Forget `event_id`, so the next `start` downloads it again.
+    pub fn forget(&mut self, event_id: &str) {
+        self.entries.remove(event_id);
+    }
+
+    /// File what is known under `from` under `to` instead, as when one of
+    /// our uploads gets its event id.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some(fetch) = self.entries.remove(from) {
+            self.entries.insert(to.to_string(), fetch);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images_fit_the_thumbnail_box() {
+        assert_eq!(fit(200, 100), (200, 100));
+        assert_eq!(fit(800, 400), (400, 200));
+        assert_eq!(fit(300, 900), (100, 300));
+        assert_eq!(fit(4000, 10), (400, 1));
+        assert_eq!(fit(0, 100), (0, 0));
+    }
+
+    #[test]
+    fn test_suggested_file_names() {
+        assert_eq!(file_name("report.pdf"), "report.pdf");
+        assert_eq!(file_name("../../.bashrc"), ".bashrc");
+        assert_eq!(file_name("C:\\Users\\me\\notes.txt"), "notes.txt");
+        assert_eq!(file_name("dir/"), "download");
+        assert_eq!(file_name(".."), "download");
+    }
+
+    #[test]
+    fn test_downloads_start_once_unless_failed() {
+        let mut downloads = Downloads::default();
+        assert!(downloads.start("$1"));
+        assert!(!downloads.start("$1"));
+        downloads.finish("$1", None);
+        assert_eq!(downloads.get("$1"), Some(&Fetch::Failed));
+        assert!(downloads.start("$1"));
+        downloads.finish("$1", Some(7));
+        assert!(!downloads.start("$1"));
+        assert_eq!(downloads.get("$1"), Some(&Fetch::Done(7)));
+        downloads.rename("$1", "$2");
+        assert_eq!(downloads.get("$1"), None);
+        assert_eq!(downloads.get("$2"), Some(&Fetch::Done(7)));
+        downloads.forget("$2");
+        assert_eq!(downloads.get("$2"), None);
+    }
+}
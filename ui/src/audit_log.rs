@@ -0,0 +1,222 @@
+//! The audit log, from a room's settings: who changed the room's state and
+//! how, newest first, a page of the room's history at a time. Which kinds
+//! of change to show is asked of the server, so changing it starts over;
+//! the filter by who made the change only narrows the pages loaded.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, i18n, AppWindow, AuditEntryData};
+use chat_core::time::format_timestamp;
+use fluent_bundle::FluentValue;
+use network::audit::{AuditEntry, AuditKind, Change, MembershipAction};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct AuditLog {
+    room_id: String,
+    /// The kinds of change asked for.
+    kinds: Vec<AuditKind>,
+    actor: String,
+    entries: Vec<AuditEntry>,
+    /// Where the next, older, page starts; `None` once at the start.
+    next: Option<String>,
+    loading: bool,
+    /// Counts restarts, so pages for an earlier room or filter are dropped.
+    generation: u64,
+}
+
+thread_local! {
+    static LOG: RefCell<AuditLog> = RefCell::new(AuditLog::default());
+}
+
+/// `user_id` with their display name, when they have one.
+fn who(user_id: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) if name != user_id => format!("{} ({})", name, user_id),
+        _ => user_id.to_string(),
+    }
+}
+
+fn summary(entry: &AuditEntry) -> String {
+    let actor = FluentValue::from(who(&entry.actor, entry.actor_name.as_deref()));
+    let target = entry.target.as_deref().unwrap_or_default();
+    let target = FluentValue::from(who(target, entry.target_name.as_deref()));
+    let id = match (entry.kind, entry.membership) {
+        (AuditKind::Membership, Some(action)) => match action {
+            MembershipAction::Joined => "audit-joined",
+            MembershipAction::Left => "audit-left",
+            MembershipAction::Invited => "audit-invited",
+            MembershipAction::Kicked => "audit-kicked",
+            MembershipAction::Banned => "audit-banned",
+            MembershipAction::Unbanned => "audit-unbanned",
+            MembershipAction::Knocked => "audit-knocked",
+        },
+        (AuditKind::Membership, None) => "audit-membership",
+        (AuditKind::PowerLevels, _) => "audit-power-levels",
+        (AuditKind::Name, _) => "audit-name",
+        (AuditKind::Topic, _) => "audit-topic",
+        (AuditKind::Avatar, _) => "audit-avatar",
+        (AuditKind::ServerAcl, _) => "audit-server-acl",
+    };
+    i18n::tr_args(id, [("actor", actor), ("target", target)])
+}
+
+/// A change as "field: old → new".
+fn change_line(change: &Change) -> String {
+    let value = |value: &Option<String>| value.clone().unwrap_or_else(|| i18n::tr("audit-unset"));
+    let (old, new) = (value(&change.old), value(&change.new));
+    format!("{}: {} → {}", change.field, old, new)
+}
+
+fn item(entry: &AuditEntry) -> AuditEntryData {
+    // The summary already says what a membership change did.
+    let details = match entry.kind {
+        AuditKind::Membership => String::new(),
+        _ => {
+            let lines: Vec<String> = entry.changes.iter().map(change_line).collect();
+            lines.join("\n")
+        }
+    };
+    let reason = match &entry.reason {
+        Some(reason) => {
+            let reason = FluentValue::from(reason.as_str());
+            i18n::tr_args("audit-reason", [("reason", reason)])
+        }
+        None => String::new(),
+    };
+    AuditEntryData {
+        summary: SharedString::from(summary(entry)),
+        details: SharedString::from(details),
+        reason: SharedString::from(reason),
+        time: SharedString::from(format_timestamp(entry.timestamp)),
+    }
+}
+
+fn render(ui: &AppWindow) {
+    let (items, has_more, loading) = LOG.with(|l| {
+        let log = l.borrow();
+        let items: Vec<AuditEntryData> = log
+            .entries
+            .iter()
+            .filter(|entry| entry.matches_actor(&log.actor))
+            .map(item)
+            .collect();
+        (items, log.next.is_some(), log.loading)
+    });
+    ui.set_audit_log_entries(Rc::new(VecModel::from(items)).into());
+    ui.set_audit_log_has_more(has_more);
+    ui.set_audit_log_loading(loading);
+}
+
+/// Load the page starting at `from`, or the newest without.
+fn load(ui: &AppWindow, client: &ClientHandle, from: Option<String>) {
+    let (room_id, kinds, generation) = LOG.with(|l| {
+        let mut log = l.borrow_mut();
+        log.loading = true;
+        (log.room_id.clone(), log.kinds.clone(), log.generation)
+    });
+    ui.set_audit_log_error(SharedString::default());
+    render(ui);
+
+    let reply = client.state_history(&room_id, kinds, from);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let current = LOG.with(|l| {
+                let mut log = l.borrow_mut();
+                if log.generation != generation {
+                    return false;
+                }
+                log.loading = false;
+                if let Ok(page) = &result {
+                    log.entries.extend(page.entries.iter().cloned());
+                    log.next = page.next.clone();
+                }
+                true
+            });
+            if !current {
+                return;
+            }
+            if let Err(e) = result {
+                tracing::warn!("Failed to load the audit log: {:#}", e);
+                ui.set_audit_log_error(admin::error_text(&e, "failed-load-audit-log"));
+            }
+            render(&ui);
+        })
+        .ok();
+    });
+}
+
+/// Start over with the newest page, for the room and kinds in `LOG`.
+fn restart(ui: &AppWindow, client: &ClientHandle) {
+    LOG.with(|l| {
+        let mut log = l.borrow_mut();
+        log.entries.clear();
+        log.next = None;
+        log.generation += 1;
+    });
+    load(ui, client, None);
+}
+
+/// Show the audit log of `room_id`, every kind of change, from anyone.
+pub fn open(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    LOG.with(|l| {
+        let mut log = l.borrow_mut();
+        log.room_id = room_id.to_string();
+        log.kinds = AuditKind::ALL.to_vec();
+        log.actor.clear();
+    });
+    ui.set_show_audit_log(true);
+    restart(ui, client);
+}
+
+/// Show only one kind of change: `index` in `AuditKind::ALL` plus one, or
+/// every kind for 0.
+pub fn set_kind(ui: &AppWindow, client: &ClientHandle, index: usize) {
+    let kinds = match index.checked_sub(1) {
+        Some(i) => AuditKind::ALL.get(i).into_iter().copied().collect(),
+        None => AuditKind::ALL.to_vec(),
+    };
+    LOG.with(|l| l.borrow_mut().kinds = kinds);
+    restart(ui, client);
+}
+
+/// Show only the changes made by those whose id or name has `query`.
+pub fn set_actor(ui: &AppWindow, query: &str) {
+    LOG.with(|l| l.borrow_mut().actor = query.to_string());
+    render(ui);
+}
+
+pub fn load_more(ui: &AppWindow, client: &ClientHandle) {
+    let next = LOG.with(|l| {
+        let log = l.borrow();
+        if log.loading {
+            None
+        } else {
+            log.next.clone()
+        }
+    });
+    if next.is_some() {
+        load(ui, client, next);
+    }
+}
+
+/// Forget the log on logout.
+pub fn clear(ui: &AppWindow) {
+    LOG.with(|l| {
+        let mut log = l.borrow_mut();
+        let generation = log.generation + 1;
+        *log = AuditLog {
+            generation,
+            ..AuditLog::default()
+        };
+    });
+    ui.set_show_audit_log(false);
+    ui.set_audit_log_entries(Rc::new(VecModel::default()).into());
+    ui.set_audit_log_error(SharedString::default());
+}
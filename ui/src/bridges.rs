@@ -0,0 +1,71 @@
+//! Messages a bridge's bot relays for others are shown as their author's,
+//! as `chat_core::bridge` reads them. A room where that gets it wrong can
+//! be set, from its channel menu, to show them as the bot sent them; those
+//! rooms are kept for each account.
+
+use crate::rooms::{self, SharedSidebar};
+use crate::{history, AppWindow};
+use network::config::{Config, ConfigManager};
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+#[derive(Default)]
+struct Bridges {
+    user_id: String,
+    /// Rooms showing relayed messages as the bot sent them.
+    off: HashSet<String>,
+}
+
+thread_local! {
+    static BRIDGES: RefCell<Bridges> = RefCell::new(Bridges::default());
+}
+
+/// Use the rooms `user_id` turned this off in, as they sign in.
+pub fn load(config: &Config, user_id: &str) {
+    BRIDGES.with(|b| {
+        *b.borrow_mut() = Bridges {
+            user_id: user_id.to_string(),
+            off: config
+                .account(user_id)
+                .unbridged_rooms
+                .into_iter()
+                .collect(),
+        }
+    });
+}
+
+/// Forget the account's rooms, e.g. on logout.
+pub fn clear() {
+    BRIDGES.with(|b| *b.borrow_mut() = Bridges::default());
+}
+
+/// Whether relayed messages in `room_id` are shown as their author's.
+pub fn is_on(room_id: &str) -> bool {
+    BRIDGES.with(|b| !b.borrow().off.contains(room_id))
+}
+
+/// Turn showing relayed messages as their author's on or off in `room_id`.
+pub fn toggle(ui: &AppWindow, sidebar: &SharedSidebar, room_id: &str) {
+    let on = !is_on(room_id);
+    let user_id = BRIDGES.with(|b| {
+        let mut state = b.borrow_mut();
+        if on {
+            state.off.remove(room_id);
+        } else {
+            state.off.insert(room_id.to_string());
+        }
+        state.user_id.clone()
+    });
+    history::for_each_timeline(room_id, |timeline| timeline.set_bridged(on));
+    rooms::refresh_sidebar(ui, sidebar);
+
+    let mut config = ConfigManager::load();
+    let account = config.accounts.entry(user_id).or_default();
+    account.unbridged_rooms.retain(|id| id != room_id);
+    if !on {
+        account.unbridged_rooms.push(room_id.to_string());
+    }
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the bridged rooms: {:#}", e);
+    }
+}
@@ -0,0 +1,167 @@
+//! Syntax highlighting for code blocks in messages. syntect is far too slow
+//! to run each time a row is drawn, so a block is highlighted once, off the
+//! UI thread, and kept by event id; until then it shows plain.
+
+use crate::history;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Code blocks kept highlighted; the oldest go first.
+const MAX_CACHED: usize = 200;
+
+/// Longer code shows plain: highlighting it would take too long.
+const MAX_HIGHLIGHTED_LEN: usize = 64 * 1024;
+
+/// A piece of a line in one color; `None` is the default text color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub color: Option<[u8; 3]>,
+}
+
+pub type Line = Vec<Token>;
+
+/// A code block by event id and its row in the message.
+type Key = (String, usize);
+
+struct Highlighted {
+    /// The code highlighted, so an edit to it is highlighted again.
+    text: String,
+    lines: Vec<Line>,
+}
+
+#[derive(Default)]
+struct Cache {
+    blocks: HashMap<Key, Highlighted>,
+    order: VecDeque<Key>,
+    pending: HashSet<Key>,
+}
+
+thread_local! {
+    static CACHE: RefCell<Cache> = RefCell::new(Cache::default());
+}
+
+fn untabbed(text: &str) -> String {
+    text.replace('\t', "    ")
+}
+
+/// `text` one token a line, uncolored.
+fn plain(text: &str) -> Vec<Line> {
+    text.split('\n')
+        .map(|line| {
+            vec![Token {
+                text: untabbed(line.trim_end_matches('\r')),
+                color: None,
+            }]
+        })
+        .collect()
+}
+
+/// `text` highlighted as `language`, or as whatever its first line says it
+/// is, e.g. a shebang. `None` if neither is known.
+fn highlight(language: Option<&str>, text: &str) -> Option<Vec<Line>> {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+    let theme = THEME.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove("base16-ocean.dark")
+            .unwrap_or_default()
+    });
+    let syntax = language
+        .and_then(|language| syntaxes.find_syntax_by_token(language))
+        .or_else(|| syntaxes.find_syntax_by_first_line(text))?;
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(text) {
+        let ranges = highlighter.highlight_line(line, syntaxes).ok()?;
+        let mut tokens: Line = ranges
+            .into_iter()
+            .map(|(style, piece)| Token {
+                text: untabbed(piece.trim_end_matches(['\n', '\r'])),
+                color: Some([style.foreground.r, style.foreground.g, style.foreground.b]),
+            })
+            .filter(|token| !token.text.is_empty())
+            .collect();
+        if tokens.is_empty() {
+            // Keep blank lines their height.
+            tokens.push(Token {
+                text: String::new(),
+                color: None,
+            });
+        }
+        lines.push(tokens);
+    }
+    Some(lines)
+}
+
+fn store(key: Key, text: String, lines: Vec<Line>) {
+    CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        cache.pending.remove(&key);
+        let highlighted = Highlighted { text, lines };
+        if cache.blocks.insert(key.clone(), highlighted).is_none() {
+            cache.order.push_back(key);
+        }
+        while cache.order.len() > MAX_CACHED {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.blocks.remove(&oldest);
+            }
+        }
+    });
+}
+
+/// The lines of code block `index` of message `event_id`: highlighted if
+/// that is done, plain otherwise. Highlighting starts on first asking, and
+/// the message's row is redrawn once it is done.
+pub fn lines(event_id: &str, index: usize, language: Option<&str>, text: &str) -> Vec<Line> {
+    let key = (event_id.to_string(), index);
+    let (cached, start) = CACHE.with(|c| {
+        let cache = &mut *c.borrow_mut();
+        match cache.blocks.get(&key).filter(|done| done.text == text) {
+            Some(done) => (Some(done.lines.clone()), false),
+            None => (
+                None,
+                text.len() <= MAX_HIGHLIGHTED_LEN && cache.pending.insert(key.clone()),
+            ),
+        }
+    });
+    if let Some(lines) = cached {
+        return lines;
+    }
+    if start {
+        let language = language.map(str::to_owned);
+        let text = text.to_string();
+        tokio::spawn(async move {
+            let job = {
+                let text = text.clone();
+                tokio::task::spawn_blocking(move || highlight(language.as_deref(), &text))
+            };
+            let lines = match job.await {
+                Ok(lines) => lines,
+                Err(e) => {
+                    tracing::warn!("Failed to highlight code: {}", e);
+                    None
+                }
+            };
+            slint::invoke_from_event_loop(move || {
+                // Unknown languages are kept plain, not tried again.
+                let lines = lines.unwrap_or_else(|| plain(&text));
+                let event_id = key.0.clone();
+                store(key, text, lines);
+                if let Some(room_id) = history::room_of(&event_id) {
+                    history::for_each_timeline(&room_id, |timeline| timeline.refresh(&event_id));
+                }
+            })
+            .ok();
+        });
+    }
+    plain(text)
+}
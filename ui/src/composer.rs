@@ -0,0 +1,13 @@
+//! Changing the composer's text from code, e.g. to insert an emoji or a
+//! mention, so the mentions already in it keep up.
+
+use crate::{mention_completion, AppWindow};
+use slint::SharedString;
+
+/// Put `text` into the composer, focused with the caret at byte offset
+/// `caret`.
+pub fn set_text(ui: &AppWindow, text: String, caret: usize) {
+    mention_completion::text_set(&text, caret);
+    ui.set_composer_text(SharedString::from(text));
+    ui.set_composer_caret_request(caret.min(i32::MAX as usize) as i32);
+}
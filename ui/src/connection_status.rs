@@ -0,0 +1,122 @@
+//! The reconnect banner: shown while the sync loop can't reach the
+//! homeserver, with messages sent meanwhile queued until it can again.
+
+use crate::app_state::ClientHandle;
+use crate::{accounts, popouts, rooms, AppWindow};
+use network::connection::{ConnectionState, Retry};
+use slint::ComponentHandle;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+/// How long the green "Connected" banner stays up after reconnecting.
+const CONNECTED_FLASH: Duration = Duration::from_secs(2);
+
+thread_local! {
+    static CONNECTED: Cell<bool> = const { Cell::new(true) };
+    /// Messages held back while offline, as (room id, transaction id), in
+    /// the order they were sent.
+    static QUEUED: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+    static RETRY: RefCell<Option<Retry>> = const { RefCell::new(None) };
+    static FLASH_TIMER: slint::Timer = slint::Timer::default();
+}
+
+pub fn is_connected() -> bool {
+    CONNECTED.with(Cell::get)
+}
+
+/// Send `txn_id` in `room_id` once reconnected.
+pub fn queue(room_id: &str, txn_id: &str) {
+    QUEUED.with(|q| {
+        q.borrow_mut()
+            .push((room_id.to_string(), txn_id.to_string()))
+    });
+}
+
+/// Stop waiting to send `txn_id`, e.g. as it was deleted.
+pub fn unqueue(txn_id: &str) {
+    QUEUED.with(|q| q.borrow_mut().retain(|(_, queued)| queued != txn_id));
+}
+
+/// Try reaching the homeserver now rather than after the backoff.
+pub fn retry_now() {
+    RETRY.with(|r| {
+        if let Some(retry) = &*r.borrow() {
+            retry.now();
+        }
+    });
+}
+
+fn show(ui: &AppWindow, client: &ClientHandle, state: ConnectionState) {
+    let was_connected = CONNECTED.with(|c| c.replace(state.is_connected()));
+    ui.set_reconnecting(!state.is_connected());
+    popouts::set_offline(!state.is_connected());
+    ui.set_reconnect_attempt(state.attempt().min(i32::MAX as u32) as i32);
+    if !state.is_connected() {
+        FLASH_TIMER.with(slint::Timer::stop);
+        ui.set_connected_flash(false);
+        return;
+    }
+    if was_connected {
+        return;
+    }
+
+    ui.set_connected_flash(true);
+    let ui_handle = ui.as_weak();
+    FLASH_TIMER.with(|timer| {
+        timer.start(slint::TimerMode::SingleShot, CONNECTED_FLASH, move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_connected_flash(false);
+            }
+        })
+    });
+    for (room_id, txn_id) in QUEUED.with(|q| std::mem::take(&mut *q.borrow_mut())) {
+        rooms::resend(client, room_id, &txn_id);
+    }
+}
+
+/// Follow the logged-in client's connection until its stream closes on
+/// logout.
+pub fn start(ui: &AppWindow, client: &ClientHandle) {
+    let subscription = client.connection();
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let shown = accounts::shown();
+
+    tokio::spawn(async move {
+        let connection = match subscription.await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!("Failed to follow the connection state: {}", e);
+                return;
+            }
+        };
+        let mut state = connection.state;
+        let retry = connection.retry;
+        slint::invoke_from_event_loop(move || RETRY.with(|r| *r.borrow_mut() = Some(retry))).ok();
+        loop {
+            let current = *state.borrow_and_update();
+            let ui_handle = ui_handle.clone();
+            let client = client.clone();
+            let shown_ui = shown.clone();
+            slint::invoke_from_event_loop(move || match ui_handle.upgrade() {
+                Some(ui) if !shown_ui.is_cancelled() => show(&ui, &client, current),
+                _ => {}
+            })
+            .ok();
+            if !matches!(shown.run(state.changed()).await, Ok(Ok(()))) {
+                break;
+            }
+        }
+    });
+}
+
+/// Forget the connection and drop queued messages, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    CONNECTED.with(|c| c.set(true));
+    QUEUED.with(|q| q.borrow_mut().clear());
+    RETRY.with(|r| r.borrow_mut().take());
+    FLASH_TIMER.with(slint::Timer::stop);
+    ui.set_reconnecting(false);
+    ui.set_reconnect_attempt(0);
+    ui.set_connected_flash(false);
+}
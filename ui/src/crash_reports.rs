@@ -0,0 +1,99 @@
+//! The crash reports saved since the app last ran, offered on startup: their
+//! folder can be opened, or the newest copied for a bug report. Once the
+//! dialog is closed they aren't offered again.
+
+use crate::{admin, i18n, links, message_actions, toast, AppWindow, CrashReportData};
+use network::crash;
+use slint::SharedString;
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+
+thread_local! {
+    /// The reports offered, oldest first.
+    static PENDING: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+fn data(pending: &[PathBuf]) -> CrashReportData {
+    let file = pending
+        .last()
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    CrashReportData {
+        count: pending.len() as i32,
+        file: SharedString::from(file),
+        copied: false,
+    }
+}
+
+/// Offer the reports not yet shown, if there are any.
+pub fn check(ui: &AppWindow) {
+    let Ok(dir) = crash::crashes_dir() else {
+        return;
+    };
+    let pending = crash::pending(&dir);
+    if pending.is_empty() {
+        return;
+    }
+    ui.set_crash_report(data(&pending));
+    ui.set_show_crash_report(true);
+    PENDING.with(|p| *p.borrow_mut() = pending);
+}
+
+pub fn open_folder(ui: &AppWindow) {
+    let result = crash::crashes_dir().and_then(|dir| links::open_external(&dir.to_string_lossy()));
+    if let Err(e) = result {
+        tracing::warn!("Failed to open the crashes folder: {:#}", e);
+        toast::show(ui, admin::error_text(&e, "failed-open-folder"));
+    }
+}
+
+/// Copy the newest report.
+pub fn copy(ui: &AppWindow) {
+    let Some(path) = PENDING.with(|p| p.borrow().last().cloned()) else {
+        return;
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => {
+            message_actions::copy(ui, &text);
+            let mut data = ui.get_crash_report();
+            data.copied = true;
+            ui.set_crash_report(data);
+        }
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", path.display(), e);
+            toast::show(
+                ui,
+                i18n::tr_args("failed-read-report", [("error", e.to_string().into())]),
+            );
+        }
+    }
+}
+
+/// Close the dialog, keeping the reports but no longer offering them.
+pub fn dismiss(ui: &AppWindow) {
+    for path in PENDING.with(|p| p.take()) {
+        if let Err(e) = crash::mark_seen(&path) {
+            tracing::warn!("{:#}", e);
+        }
+    }
+    ui.set_show_crash_report(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_names_the_newest() {
+        let pending = [
+            PathBuf::from("/x/crashes/crash-1.txt"),
+            PathBuf::from("/x/crashes/crash-2.txt"),
+        ];
+        let shown = data(&pending);
+        assert_eq!(shown.count, 2);
+        assert_eq!(shown.file, "crash-2.txt");
+        assert!(!shown.copied);
+    }
+}
@@ -0,0 +1,213 @@
+//! Mock servers, channels and messages for screenshots (`--demo`).
+
+use crate::{admin, local_echo, member_list, rooms};
+use crate::{AppWindow, ChannelData, ServerData, VoiceUserData};
+use chat_core::{time, Member, MemberRole, Message, MessageType, UserStatus};
+use slint::{ComponentHandle, Model, SharedString, VecModel};
+use std::rc::Rc;
+
+fn channels(names: &[&str]) -> Rc<VecModel<ChannelData>> {
+    Rc::new(VecModel::from(
+        names
+            .iter()
+            .map(|n| ChannelData {
+                id: SharedString::from(*n),
+                name: SharedString::from(*n),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn voice_users(names: &[&str]) -> Rc<VecModel<VoiceUserData>> {
+    Rc::new(VecModel::from(
+        names
+            .iter()
+            .map(|n| VoiceUserData {
+                name: SharedString::from(*n),
+                speaking: false,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn member(name: &str, role: MemberRole, status: UserStatus) -> Member {
+    Member {
+        user_id: format!("@{}:gamechat.demo", name.to_lowercase()),
+        display_name: Some(name.to_string()),
+        avatar_url: None,
+        role,
+        status,
+        status_message: None,
+    }
+}
+
+/// Mock history as `(sender, body)` pairs, one minute apart and ending now.
+fn messages(lines: &[(&str, &str)]) -> Vec<Message> {
+    let now = time::now_ms();
+    let count = lines.len() as u64;
+    lines
+        .iter()
+        .zip(0..)
+        .map(|((sender, body), i)| Message {
+            id: format!("$demo{}", i),
+            sender: format!("@{}:gamechat.demo", sender.to_lowercase()),
+            sender_name: Some(sender.to_string()),
+            content: body.to_string(),
+            schema: MessageType::Text,
+            timestamp: now - (count - i) * 60_000,
+            reply_to: None,
+            edited: false,
+            reactions: Vec::new(),
+            attachment: None,
+            mentions: Vec::new(),
+            formatted: None,
+            trust: None,
+        })
+        .collect()
+}
+
+/// Populate the window with mock data and install the mock navigation handlers.
+pub fn install(ui: &AppWindow) {
+    ui.set_logged_in(true);
+    ui.set_current_user_id("@demo:gamechat.demo".into());
+    ui.set_current_display_name("Demo".into());
+    ui.set_servers(
+        Rc::new(VecModel::from(vec![
+            ServerData {
+                id: "dm".into(),
+                name: "DM".into(),
+                color: slint::Color::from_rgb_u8(0x58, 0x65, 0xf2),
+                online: true,
+                ..Default::default()
+            },
+            ServerData {
+                id: "rust".into(),
+                name: "R".into(),
+                color: slint::Color::from_rgb_u8(0xe6, 0x4a, 0x19),
+                online: false,
+                unread_count: 4,
+                highlight_count: 2,
+            },
+            ServerData {
+                id: "matrix".into(),
+                name: "M".into(),
+                color: slint::Color::from_rgb_u8(0x00, 0xbf, 0xa5),
+                online: true,
+                unread_count: 1,
+                highlight_count: 0,
+            },
+        ]))
+        .into(),
+    );
+    ui.set_channels(channels(&["general", "random", "announcements"]).into());
+    ui.set_active_channel("general".into());
+    ui.set_active_channel_name("general".into());
+    rooms::set_messages(ui, messages(&[("GameChat", "Welcome to GameChat!")]));
+    ui.set_voice_channel_name("Lounge".into());
+    ui.set_voice_users(voice_users(&["xGamer42", "PixelKnight"]).into());
+    admin::show_roles(ui, network::admin::with_builtin_roles(Vec::new()));
+    member_list::show_members(
+        ui,
+        vec![
+            member("Demo", MemberRole::Admin, UserStatus::Online),
+            member("xGamer42", MemberRole::Moderator, UserStatus::Online),
+            member("PixelKnight", MemberRole::Member, UserStatus::Idle),
+            member("NightOwl", MemberRole::Member, UserStatus::Offline),
+        ],
+    );
+
+    // No peers: joining just lists us first.
+    let ui_handle = ui.as_weak();
+    ui.on_toggle_voice(move |active| {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        let users = ui.get_voice_users();
+        let mut names: Vec<SharedString> = users.iter().map(|u| u.name).collect();
+        names.retain(|n| n.as_str() != "Demo");
+        if active {
+            names.insert(0, "Demo".into());
+        }
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        ui.set_voice_users(voice_users(&names).into());
+    });
+
+    // No homeserver: echo sends as delivered straight away.
+    let ui_handle = ui.as_weak();
+    ui.on_send_message(move |text, reply_to| {
+        let text = text.trim();
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+        let txn_id = local_echo::new_txn_id();
+        let mut message = rooms::outgoing_message(&ui, text);
+        message.reply_to = Some(reply_to.to_string()).filter(|id| !id.is_empty());
+        rooms::with_timeline(&ui, |timeline| {
+            timeline.push_local(&txn_id, message);
+            timeline.mark_sent(&txn_id, &txn_id);
+        });
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_channel_selected(move |id| {
+        let new_history = match id.as_str() {
+            "general" => vec![
+                ("GameChat", "Welcome to #general!"),
+                ("xGamer42", "anyone up for a match tonight?"),
+                ("PixelKnight", "I'm in"),
+                ("PixelKnight", "after 8 though"),
+            ],
+            "random" => vec![
+                ("GameChat", "This is #random."),
+                ("GameChat", "Post memes here."),
+            ],
+            "announcements" => vec![("GameChat", "New version 0.1 released!")],
+            _ => vec![("GameChat", "Channel joined.")],
+        };
+
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.set_active_channel_name(id);
+            rooms::set_messages(&ui, messages(&new_history));
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_server_selected(move |index| {
+        let (new_channels, welcome_msg, voice_ch_name, users) = match index {
+            0 => (
+                vec!["general", "random", "announcements"],
+                "Welcome to Direct Messages!",
+                "Lounge",
+                vec!["xGamer42"],
+            ),
+            1 => (
+                vec!["rust-general", "cargo", "help"],
+                "Welcome to the Rust Server!",
+                "Rustacean Voice",
+                vec!["PixelKnight", "ferris_bot"],
+            ),
+            2 => (
+                vec!["matrix-dev", "synapse", "dendrite"],
+                "Welcome to Matrix HQ!",
+                "Dev Chat",
+                vec!["matrix_admin", "alice"],
+            ),
+            _ => (vec!["general"], "Welcome!", "General Voice", vec![]),
+        };
+
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.set_channels(channels(&new_channels).into());
+            rooms::set_messages(&ui, messages(&[("GameChat", welcome_msg)]));
+            ui.set_active_channel(SharedString::from(new_channels[0]));
+            ui.set_active_channel_name(SharedString::from(new_channels[0]));
+
+            ui.set_voice_channel_name(SharedString::from(voice_ch_name));
+            ui.set_voice_users(voice_users(&users).into());
+            ui.set_voice_active(false);
+        }
+    });
+}
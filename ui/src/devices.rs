@@ -0,0 +1,130 @@
+//! Audio device choices for the settings screen. A saved device that is no
+//! longer present stays selected, marked unavailable, so the choice is kept
+//! (and the default used) until the user picks another.
+
+use network::config::CueOutput;
+
+/// The entry standing for the system default device.
+pub const DEFAULT_DEVICE: &str = "Default";
+
+const UNAVAILABLE_PREFIX: &str = "(unavailable) ";
+
+/// Entries for where cues play besides a device of their own.
+pub const SAME_AS_VOICE: &str = "Same as voice";
+pub const CUES_OFF: &str = "Off";
+
+/// The entries to offer and the one selected, given the devices present
+/// and the saved device name.
+pub fn choices(available: &[String], saved: Option<&str>) -> (Vec<String>, String) {
+    let mut choices = vec![DEFAULT_DEVICE.to_string()];
+    let selected = match saved {
+        Some(name) if available.iter().any(|d| d == name) => name.to_string(),
+        Some(name) => {
+            let label = format!("{}{}", UNAVAILABLE_PREFIX, name);
+            choices.push(label.clone());
+            label
+        }
+        None => DEFAULT_DEVICE.to_string(),
+    };
+    choices.extend(available.iter().cloned());
+    (choices, selected)
+}
+
+/// The device name to save for a selected entry; `None` for the default.
+pub fn from_choice(choice: &str) -> Option<String> {
+    if choice == DEFAULT_DEVICE || choice.is_empty() {
+        return None;
+    }
+    Some(
+        choice
+            .strip_prefix(UNAVAILABLE_PREFIX)
+            .unwrap_or(choice)
+            .to_string(),
+    )
+}
+
+/// Like `choices`, for the device cues play on, led by the entries for
+/// playing them with voice and not at all.
+pub fn cue_choices(available: &[String], saved: &CueOutput) -> (Vec<String>, String) {
+    let device = match saved {
+        CueOutput::Device(name) => name.as_deref(),
+        _ => None,
+    };
+    let (devices, device) = choices(available, device);
+    let selected = match saved {
+        CueOutput::Voice => SAME_AS_VOICE.to_string(),
+        CueOutput::Off => CUES_OFF.to_string(),
+        CueOutput::Device(_) => device,
+    };
+    let choices = [SAME_AS_VOICE, CUES_OFF]
+        .map(String::from)
+        .into_iter()
+        .chain(devices)
+        .collect();
+    (choices, selected)
+}
+
+/// Where cues play for a selected entry.
+pub fn cue_from_choice(choice: &str) -> CueOutput {
+    match choice {
+        SAME_AS_VOICE | "" => CueOutput::Voice,
+        CUES_OFF => CueOutput::Off,
+        device => CueOutput::Device(from_choice(device)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_missing_device_is_kept_as_unavailable() {
+        let available = names(&["Built-in Mic", "USB Mic"]);
+
+        let (list, selected) = choices(&available, Some("USB Mic"));
+        assert_eq!(list, names(&["Default", "Built-in Mic", "USB Mic"]));
+        assert_eq!(selected, "USB Mic");
+
+        let (list, selected) = choices(&available, Some("Headset"));
+        assert_eq!(
+            list,
+            names(&[
+                "Default",
+                "(unavailable) Headset",
+                "Built-in Mic",
+                "USB Mic"
+            ])
+        );
+        assert_eq!(selected, "(unavailable) Headset");
+        assert_eq!(from_choice(&selected).as_deref(), Some("Headset"));
+
+        let (_, selected) = choices(&available, None);
+        assert_eq!(from_choice(&selected), None);
+    }
+
+    #[test]
+    fn test_cue_choices_round_trip() {
+        let available = names(&["Speakers", "Headset"]);
+        let (list, selected) = cue_choices(&available, &CueOutput::Voice);
+        assert_eq!(
+            list,
+            names(&["Same as voice", "Off", "Default", "Speakers", "Headset"])
+        );
+        assert_eq!(cue_from_choice(&selected), CueOutput::Voice);
+
+        for saved in [
+            CueOutput::Off,
+            CueOutput::Device(None),
+            CueOutput::Device(Some("Speakers".to_string())),
+            CueOutput::Device(Some("HDMI".to_string())),
+        ] {
+            let (list, selected) = cue_choices(&available, &saved);
+            assert!(list.contains(&selected));
+            assert_eq!(cue_from_choice(&selected), saved);
+        }
+    }
+}
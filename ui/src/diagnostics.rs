@@ -0,0 +1,87 @@
+//! The diagnostics section of the settings: health checks run on demand,
+//! and their report copied for a bug report with tokens taken out.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, message_actions, AppWindow, CheckStatus, DiagnosticCheck, DiagnosticsData};
+use network::crash;
+use network::diagnostics::{Report, Status};
+use slint::{ComponentHandle, ModelRc, SharedString, VecModel};
+use std::cell::RefCell;
+
+thread_local! {
+    /// The last checks run, for copying.
+    static REPORT: RefCell<Option<Report>> = const { RefCell::new(None) };
+}
+
+fn check_status(status: Status) -> CheckStatus {
+    match status {
+        Status::Ok => CheckStatus::Ok,
+        Status::Warn => CheckStatus::Warn,
+        Status::Fail => CheckStatus::Fail,
+    }
+}
+
+fn show(ui: &AppWindow, report: &Report) {
+    let checks: Vec<DiagnosticCheck> = report
+        .checks
+        .iter()
+        .map(|check| DiagnosticCheck {
+            name: SharedString::from(check.name.as_str()),
+            status: check_status(check.status),
+            message: SharedString::from(check.message.as_str()),
+        })
+        .collect();
+    ui.set_diagnostics(DiagnosticsData {
+        running: false,
+        checks: ModelRc::new(VecModel::from(checks)),
+        status: SharedString::default(),
+    });
+}
+
+/// Run every check, showing them once all are done.
+pub fn run(ui: &AppWindow, client: &ClientHandle) {
+    ui.set_diagnostics(DiagnosticsData {
+        running: true,
+        ..ui.get_diagnostics()
+    });
+    let reply = client.run_diagnostics();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(report) => {
+                    show(&ui, &report);
+                    // A crash report ends with the last checks run.
+                    crash::set_diagnostics(report.to_text());
+                    REPORT.with(|r| *r.borrow_mut() = Some(report));
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to run diagnostics: {}", e);
+                    ui.set_diagnostics(DiagnosticsData {
+                        running: false,
+                        status: admin::error_text(&e, "failed-run-checks"),
+                        ..ui.get_diagnostics()
+                    });
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Copy the last checks as plain text.
+pub fn copy(ui: &AppWindow) {
+    if let Some(text) = REPORT.with(|r| r.borrow().as_ref().map(Report::to_text)) {
+        message_actions::copy(ui, &text);
+    }
+}
+
+/// Forget the last checks, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    REPORT.with(|r| *r.borrow_mut() = None);
+    ui.set_diagnostics(DiagnosticsData::default());
+}
@@ -0,0 +1,272 @@
+//! Email addresses in the settings: those on the account, each removable,
+//! and adding one. The homeserver mails the address a link; the settings
+//! wait for it to be clicked, with the mail sent again on request, and the
+//! address is then added with the user's password to confirm.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, toast, AppWindow, EmailSettings, EmailStage};
+use network::threepid::{EmailValidation, Medium, ThreepidError};
+use slint::{ComponentHandle, ModelRc, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Emails {
+    addresses: Vec<String>,
+    /// The address being added, once its link was mailed.
+    validation: Option<EmailValidation>,
+}
+
+thread_local! {
+    static EMAILS: RefCell<Emails> = RefCell::new(Emails::default());
+}
+
+fn error_text(e: &anyhow::Error, failed: &str) -> SharedString {
+    match e.downcast_ref::<ThreepidError>() {
+        Some(e) => SharedString::from(e.to_string()),
+        None => admin::error_text(e, failed),
+    }
+}
+
+fn show(ui: &AppWindow, stage: EmailStage, status: impl Into<SharedString>) {
+    let (addresses, pending) = EMAILS.with(|e| {
+        let state = e.borrow();
+        let addresses: Vec<SharedString> = state
+            .addresses
+            .iter()
+            .map(|a| SharedString::from(a.as_str()))
+            .collect();
+        let pending = state.validation.as_ref().map(|v| v.email.clone());
+        (addresses, pending)
+    });
+    let mut settings = ui.get_email_settings();
+    settings.addresses = ModelRc::from(Rc::new(VecModel::from(addresses)));
+    settings.stage = stage;
+    if let Some(pending) = pending {
+        settings.pending = SharedString::from(pending);
+    }
+    settings.status = status.into();
+    ui.set_email_settings(settings);
+}
+
+fn load_addresses(ui: &AppWindow, client: &ClientHandle) {
+    let reply = client.get_3pids();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(threepids) => {
+                    EMAILS.with(|e| {
+                        e.borrow_mut().addresses = threepids
+                            .into_iter()
+                            .filter(|t| t.medium == Medium::Email)
+                            .map(|t| t.address)
+                            .collect()
+                    });
+                    let settings = ui.get_email_settings();
+                    show(&ui, settings.stage, settings.status);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to list email addresses: {}", e);
+                    let stage = ui.get_email_settings().stage;
+                    show(&ui, stage, error_text(&e, "failed-list-emails"));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// The settings dialog opened: list the account's addresses. One being
+/// added stays waiting for its link.
+pub fn open_settings(ui: &AppWindow, client: &ClientHandle) {
+    let stage = match EMAILS.with(|e| e.borrow().validation.is_some()) {
+        true => EmailStage::Waiting,
+        false => EmailStage::Idle,
+    };
+    show(ui, stage, "");
+    if ui.get_logged_in() {
+        load_addresses(ui, client);
+    }
+}
+
+/// Forget the addresses and any being added, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    EMAILS.with(|e| *e.borrow_mut() = Emails::default());
+    ui.set_email_settings(EmailSettings::default());
+}
+
+/// Have a link mailed to `address`, to add it once clicked.
+pub fn add(ui: &AppWindow, client: &ClientHandle, address: &str) {
+    let address = address.trim();
+    if address.is_empty() {
+        return;
+    }
+    let reply = client.request_email_token(address);
+    let address = SharedString::from(address);
+    let mut settings = ui.get_email_settings();
+    settings.stage = EmailStage::Sending;
+    settings.pending = address.clone();
+    settings.status = SharedString::new();
+    ui.set_email_settings(settings);
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            // Signed out while the mail was on its way.
+            let settings = ui.get_email_settings();
+            if settings.stage != EmailStage::Sending || settings.pending != address {
+                return;
+            }
+            match result {
+                Ok(validation) => {
+                    EMAILS.with(|e| e.borrow_mut().validation = Some(validation));
+                    show(&ui, EmailStage::Waiting, "");
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to mail a link: {}", e);
+                    show(&ui, EmailStage::Idle, error_text(&e, "failed-add-email"));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Mail the link again, e.g. when the first didn't arrive.
+pub fn resend(ui: &AppWindow, client: &ClientHandle) {
+    let Some(validation) = EMAILS.with(|e| e.borrow().validation.clone()) else {
+        return;
+    };
+    let reply = client.resend_email_token(validation);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let Some(pending) = EMAILS.with(|e| e.borrow().validation.clone()) else {
+                return;
+            };
+            match result {
+                Ok(validation) if validation.sid == pending.sid => {
+                    let email = validation.email.clone();
+                    EMAILS.with(|e| e.borrow_mut().validation = Some(validation));
+                    show(
+                        &ui,
+                        EmailStage::Waiting,
+                        format!("Sent another link to {}", email),
+                    );
+                }
+                // Another address is being added by now.
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Failed to mail the link again: {}", e);
+                    show(
+                        &ui,
+                        EmailStage::Waiting,
+                        error_text(&e, "failed-resend-email"),
+                    );
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Stop adding the address. Clicking its link no longer adds it.
+pub fn cancel(ui: &AppWindow) {
+    EMAILS.with(|e| e.borrow_mut().validation = None);
+    show(ui, EmailStage::Idle, "");
+}
+
+/// Add the address whose link was clicked, confirmed with `password`.
+pub fn confirm(ui: &AppWindow, client: &ClientHandle, password: &str) {
+    let Some(validation) = EMAILS.with(|e| e.borrow().validation.clone()) else {
+        return;
+    };
+    let reply = client.add_3pid(validation.clone(), password);
+    show(ui, EmailStage::Finishing, "");
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let current = EMAILS.with(|e| {
+                e.borrow()
+                    .validation
+                    .as_ref()
+                    .is_some_and(|v| v.sid == validation.sid)
+            });
+            match result {
+                Ok(()) => {
+                    toast::show(&ui, format!("Added {}", validation.email));
+                    let adding_another = EMAILS.with(|e| {
+                        let mut state = e.borrow_mut();
+                        if !state.addresses.contains(&validation.email) {
+                            state.addresses.push(validation.email.clone());
+                        }
+                        if current {
+                            state.validation = None;
+                        }
+                        state.validation.is_some()
+                    });
+                    let stage = match adding_another {
+                        true => ui.get_email_settings().stage,
+                        false => EmailStage::Idle,
+                    };
+                    show(&ui, stage, "");
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to add {}: {}", validation.email, e);
+                    if current {
+                        show(
+                            &ui,
+                            EmailStage::Waiting,
+                            error_text(&e, "failed-confirm-email"),
+                        );
+                    }
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Take the address at `row` off the account.
+pub fn remove(ui: &AppWindow, client: &ClientHandle, row: i32) {
+    let Some(address) = EMAILS.with(|e| e.borrow().addresses.get(row as usize).cloned()) else {
+        return;
+    };
+    let reply = client.remove_3pid(&address);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to remove {}: {}", address, e);
+                toast::show(&ui, error_text(&e, "failed-remove-email"));
+                return;
+            }
+            EMAILS.with(|e| e.borrow_mut().addresses.retain(|a| *a != address));
+            let settings = ui.get_email_settings();
+            show(&ui, settings.stage, settings.status);
+        })
+        .ok();
+    });
+}
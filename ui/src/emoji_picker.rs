@@ -0,0 +1,275 @@
+//! Emoji in the composer: the picker, which also picks reactions, and
+//! completions for a `:shortcode` typed at the caret. The skin tone and how
+//! often each emoji was used are kept in the settings.
+
+use crate::app_state::ClientHandle;
+use crate::{composer, message_actions, AppWindow, EmojiCategoryData, EmojiData};
+use chat_core::emoji::{self, Category, Emoji, EmojiUsage, SkinTone};
+use network::config::{Config, ConfigManager};
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{Key, NamedKey};
+use slint::{SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Completions offered at once.
+const MAX_COMPLETIONS: usize = 8;
+
+/// The picker's first tab, before the categories.
+const RECENT_TAB: usize = 0;
+
+/// A `:shortcode` being completed: the composer text it was typed in and
+/// the byte range it covers.
+struct Typed {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Default)]
+struct Emojis {
+    usage: EmojiUsage,
+    tone: SkinTone,
+    query: String,
+    tab: usize,
+    typed: Option<Typed>,
+    completions: Vec<&'static Emoji>,
+    selected: usize,
+}
+
+thread_local! {
+    static EMOJIS: RefCell<Emojis> = RefCell::new(Emojis::default());
+}
+
+fn data(emoji: &Emoji, tone: SkinTone) -> EmojiData {
+    EmojiData {
+        emoji: SharedString::from(tone.apply(emoji)),
+        shortcode: SharedString::from(emoji.shortcode),
+    }
+}
+
+fn model(emoji: &[&'static Emoji], tone: SkinTone) -> slint::ModelRc<EmojiData> {
+    let rows: Vec<EmojiData> = emoji.iter().map(|e| data(e, tone)).collect();
+    Rc::new(VecModel::from(rows)).into()
+}
+
+/// Take the skin tone and usage from the settings, and set up the picker's
+/// tabs and tone selector.
+pub fn load(ui: &AppWindow, config: &Config) {
+    EMOJIS.with(|e| {
+        let mut emojis = e.borrow_mut();
+        emojis.usage = config.emoji_usage.clone();
+        emojis.tone = config.skin_tone;
+    });
+    let mut tabs = vec![EmojiCategoryData {
+        icon: SharedString::from("🕘"),
+        label: SharedString::from("Recently Used"),
+    }];
+    tabs.extend(Category::ALL.iter().map(|category| EmojiCategoryData {
+        icon: SharedString::from(category.icon()),
+        label: SharedString::from(category.label()),
+    }));
+    ui.set_emoji_categories(Rc::new(VecModel::from(tabs)).into());
+    let swatches: Vec<SharedString> = SkinTone::ALL
+        .iter()
+        .map(|tone| SharedString::from(tone.swatch()))
+        .collect();
+    ui.set_emoji_tones(Rc::new(VecModel::from(swatches)).into());
+    render_picker(ui);
+}
+
+fn save() {
+    let (usage, tone) = EMOJIS.with(|e| {
+        let emojis = e.borrow();
+        (emojis.usage.clone(), emojis.tone)
+    });
+    let config = Config {
+        emoji_usage: usage,
+        skin_tone: tone,
+        ..ConfigManager::load()
+    };
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save emoji usage: {:#}", e);
+    }
+}
+
+fn render_picker(ui: &AppWindow) {
+    let (items, tab, tone) = EMOJIS.with(|e| {
+        let emojis = e.borrow();
+        let items: Vec<&'static Emoji> = if !emojis.query.is_empty() {
+            let mut found = emoji::search(&emojis.query);
+            emojis.usage.rank(&mut found);
+            found
+        } else if emojis.tab == RECENT_TAB {
+            emojis.usage.recent()
+        } else {
+            emoji::in_category(Category::ALL[emojis.tab - 1]).collect()
+        };
+        (items, emojis.tab, emojis.tone)
+    });
+    ui.set_emoji_picker_items(model(&items, tone));
+    ui.set_emoji_category(tab as i32);
+    ui.set_emoji_tone(SkinTone::ALL.iter().position(|t| *t == tone).unwrap_or(0) as i32);
+}
+
+/// The picker opened: start on the recently used emoji, if there are any.
+pub fn open(ui: &AppWindow) {
+    EMOJIS.with(|e| {
+        let mut emojis = e.borrow_mut();
+        emojis.query.clear();
+        emojis.tab = if emojis.usage.recent().is_empty() {
+            RECENT_TAB + 1
+        } else {
+            RECENT_TAB
+        };
+    });
+    render_picker(ui);
+}
+
+pub fn search(ui: &AppWindow, query: &str) {
+    EMOJIS.with(|e| e.borrow_mut().query = query.trim().to_string());
+    render_picker(ui);
+}
+
+pub fn pick_category(ui: &AppWindow, index: usize) {
+    if index > Category::ALL.len() {
+        return;
+    }
+    EMOJIS.with(|e| e.borrow_mut().tab = index);
+    render_picker(ui);
+}
+
+pub fn pick_tone(ui: &AppWindow, index: usize) {
+    let Some(tone) = SkinTone::ALL.get(index).copied() else {
+        return;
+    };
+    EMOJIS.with(|e| e.borrow_mut().tone = tone);
+    render_picker(ui);
+    render_completions(ui);
+    save();
+}
+
+/// Count a use of `emoji` and return it in the chosen skin tone.
+fn use_emoji(emoji: &'static Emoji) -> String {
+    let toned = EMOJIS.with(|e| {
+        let mut emojis = e.borrow_mut();
+        emojis.usage.record(emoji);
+        emojis.tone.apply(emoji)
+    });
+    save();
+    toned
+}
+
+/// An emoji was picked: insert it at the composer's caret, or react with it
+/// to the message `target`.
+pub fn choose(ui: &AppWindow, client: &ClientHandle, shortcode: &str, target: &str) {
+    let Some(emoji) = emoji::find(shortcode) else {
+        return;
+    };
+    let toned = use_emoji(emoji);
+    if !target.is_empty() {
+        message_actions::react(ui, client, target, &toned);
+        return;
+    }
+    let text = ui.get_composer_text();
+    let caret = usize::try_from(ui.get_composer_caret()).unwrap_or(0);
+    let (text, caret) = emoji::replace_range(&text, caret, caret, &toned);
+    composer::set_text(ui, text, caret);
+}
+
+fn render_completions(ui: &AppWindow) {
+    let (completions, selected, tone) = EMOJIS.with(|e| {
+        let emojis = e.borrow();
+        (emojis.completions.clone(), emojis.selected, emojis.tone)
+    });
+    ui.set_emoji_completions(model(&completions, tone));
+    ui.set_emoji_completion_index(selected as i32);
+}
+
+/// The composer changed: offer completions for a `:shortcode` just before
+/// the caret.
+pub fn composer_edited(ui: &AppWindow, text: &str, caret: usize) {
+    EMOJIS.with(|e| {
+        let mut emojis = e.borrow_mut();
+        emojis.selected = 0;
+        let Some(typed) = emoji::completion_query(text, caret) else {
+            emojis.typed = None;
+            emojis.completions.clear();
+            return;
+        };
+        let mut found = emoji::search(typed.query);
+        emojis.usage.rank(&mut found);
+        found.truncate(MAX_COMPLETIONS);
+        emojis.completions = found;
+        emojis.typed = Some(Typed {
+            text: text.to_string(),
+            start: typed.start,
+            end: caret,
+        });
+    });
+    render_completions(ui);
+}
+
+/// Hide the completions until the composer is edited again.
+pub fn dismiss(ui: &AppWindow) {
+    EMOJIS.with(|e| {
+        let mut emojis = e.borrow_mut();
+        emojis.typed = None;
+        emojis.completions.clear();
+    });
+    render_completions(ui);
+}
+
+/// Replace the `:shortcode` typed with completion `index`.
+pub fn accept(ui: &AppWindow, index: usize) {
+    let chosen = EMOJIS.with(|e| {
+        let emojis = e.borrow();
+        let emoji = emojis.completions.get(index).copied()?;
+        let typed = emojis.typed.as_ref()?;
+        Some((emoji, typed.text.clone(), typed.start, typed.end))
+    });
+    dismiss(ui);
+    let Some((emoji, text, start, end)) = chosen else {
+        return;
+    };
+    // The composer changed since; the range may no longer be the shortcode.
+    if ui.get_composer_text() != text.as_str() {
+        return;
+    }
+    let toned = use_emoji(emoji);
+    let (text, caret) = emoji::replace_range(&text, start, end, &toned);
+    composer::set_text(ui, text, caret);
+}
+
+fn move_selection(ui: &AppWindow, down: bool) {
+    EMOJIS.with(|e| {
+        let mut emojis = e.borrow_mut();
+        let count = emojis.completions.len();
+        emojis.selected = if down {
+            (emojis.selected + 1) % count
+        } else {
+            (emojis.selected + count - 1) % count
+        };
+    });
+    render_completions(ui);
+}
+
+/// Arrows move through the completions, Tab or Enter takes one and Escape
+/// hides them. Returns true when the key was used.
+pub fn key_pressed(ui: &AppWindow, event: &KeyEvent) -> bool {
+    let showing = EMOJIS.with(|e| !e.borrow().completions.is_empty());
+    if !showing || event.state != ElementState::Pressed || !ui.get_composer_focused() {
+        return false;
+    }
+    match &event.logical_key {
+        Key::Named(NamedKey::ArrowUp) => move_selection(ui, false),
+        Key::Named(NamedKey::ArrowDown) => move_selection(ui, true),
+        Key::Named(NamedKey::Tab | NamedKey::Enter) => {
+            let selected = EMOJIS.with(|e| e.borrow().selected);
+            accept(ui, selected);
+        }
+        Key::Named(NamedKey::Escape) => dismiss(ui),
+        _ => return false,
+    }
+    true
+}
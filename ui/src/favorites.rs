@@ -0,0 +1,176 @@
+//! Rooms pinned to the favorites strip at the top of the channel list, in
+//! the order the user put them with the channel menu's Move Up and Move
+//! Down. The order is kept in account data, so it follows the account to
+//! other devices, and in the account's settings, so it shows at once and
+//! holds when the server can't be reached. Rooms we leave drop out of it.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{admin, toast, AppWindow};
+use chat_core::favorites::Favorites;
+use network::config::{Config, ConfigManager};
+use network::events::ChatEvent;
+use slint::ComponentHandle;
+use std::cell::RefCell;
+
+#[derive(Default)]
+struct State {
+    user_id: String,
+    favorites: Favorites,
+}
+
+thread_local! {
+    static FAVORITES: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Pin the rooms `user_id` pinned on this machine as they sign in, then
+/// the ones in their account data once it answers. Account data that was
+/// never stored is filled in from this machine's.
+pub fn load(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    config: &Config,
+    user_id: &str,
+) {
+    let local = config.account(user_id).favorites;
+    FAVORITES.with(|f| {
+        *f.borrow_mut() = State {
+            user_id: user_id.to_string(),
+            favorites: Favorites::new(local.clone()),
+        }
+    });
+    let reply = client.get_favorites();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    let ui_handle = ui.as_weak();
+    let user_id = user_id.to_string();
+    tokio::spawn(async move {
+        let stored = match reply.await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => {
+                if !local.is_empty() {
+                    if let Err(e) = client.set_favorites(local).await {
+                        tracing::warn!("Failed to store favorites in account data: {}", e);
+                    }
+                }
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load favorites, using this machine's: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let current = FAVORITES.with(|f| {
+                let mut state = f.borrow_mut();
+                // Signed out or switched away while it loaded.
+                if state.user_id != user_id {
+                    return false;
+                }
+                state.favorites = Favorites::new(stored);
+                true
+            });
+            if current {
+                save_locally();
+                rooms::refresh_sidebar(&ui, &sidebar);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Forget the account's favorites, e.g. on logout.
+pub fn clear() {
+    FAVORITES.with(|f| *f.borrow_mut() = State::default());
+}
+
+/// The pinned rooms, top first.
+pub fn rooms() -> Vec<String> {
+    FAVORITES.with(|f| f.borrow().favorites.rooms().to_vec())
+}
+
+pub fn contains(room_id: &str) -> bool {
+    FAVORITES.with(|f| f.borrow().favorites.contains(room_id))
+}
+
+/// Pin `room_id`, or unpin it if it already is.
+pub fn toggle(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, room_id: &str) {
+    change(ui, client, sidebar, |favorites| {
+        favorites.remove(room_id) || favorites.add(room_id)
+    });
+}
+
+/// Move the pinned `room_id` one place up or down the strip.
+pub fn move_room(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    room_id: &str,
+    up: bool,
+) {
+    change(ui, client, sidebar, |favorites| {
+        if up {
+            favorites.move_up(room_id)
+        } else {
+            favorites.move_down(room_id)
+        }
+    });
+}
+
+/// Unpin a room once we have left or forgotten it.
+pub fn room_event(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    event: &ChatEvent,
+) {
+    if let ChatEvent::RoomLeft { room_id } = event {
+        change(ui, client, sidebar, |favorites| favorites.remove(room_id));
+    }
+}
+
+/// Apply `f`, and if it changed anything show the strip again and keep the
+/// new order here and in account data.
+fn change(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    f: impl FnOnce(&mut Favorites) -> bool,
+) {
+    if !FAVORITES.with(|state| f(&mut state.borrow_mut().favorites)) {
+        return;
+    }
+    rooms::refresh_sidebar(ui, sidebar);
+    save_locally();
+
+    let reply = client.set_favorites(rooms());
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Err(e) = reply.await else {
+            return;
+        };
+        tracing::warn!("Failed to store favorites in account data: {}", e);
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                toast::show(&ui, admin::error_text(&e, "failed-sync-favorites"));
+            }
+        })
+        .ok();
+    });
+}
+
+fn save_locally() {
+    let (user_id, rooms) = FAVORITES.with(|f| {
+        let state = f.borrow();
+        (state.user_id.clone(), state.favorites.rooms().to_vec())
+    });
+    let mut config = ConfigManager::load();
+    config.accounts.entry(user_id).or_default().favorites = rooms;
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save favorites: {:#}", e);
+    }
+}
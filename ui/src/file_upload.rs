@@ -0,0 +1,452 @@
+//! Uploading files: dropped on the window or pasted into the composer,
+//! reviewed in the upload prompt, then sent to the open room with their
+//! progress shown on their timeline rows. Images are compressed as they are
+//! added, so the prompt can show what will be sent.
+
+use crate::app_state::ClientHandle;
+use crate::image_compression::{self, Compressed, Compression};
+use crate::uploads::{Batch, PendingFile};
+use crate::{attachment_view, history, local_echo, rooms, toast, AppWindow, UploadFileData};
+use chat_core::{Attachment, MessageType};
+use network::config::UploadConfig;
+use network::media::{self, Upload};
+use slint::winit_030::winit::event::{ElementState, KeyEvent, WindowEvent};
+use slint::winit_030::winit::keyboard::{Key, ModifiersState};
+use slint::{ComponentHandle, Image, SharedString, VecModel};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Name of a pasted image in the upload prompt and the message.
+const PASTED_IMAGE_NAME: &str = "image.png";
+
+thread_local! {
+    static BATCH: RefCell<Batch> = RefCell::new(Batch::default());
+    /// Uploads not confirmed by the server, by transaction id, kept to send
+    /// again if they fail.
+    static UNSENT: RefCell<HashMap<String, (String, Upload)>> = RefCell::new(HashMap::new());
+    /// Images of the batch compressed so far, by path.
+    static COMPRESSED: RefCell<HashMap<PathBuf, Compressed>> = RefCell::new(HashMap::new());
+    /// `None` while images are sent as they are.
+    static COMPRESSION: Cell<Option<Compression>> = const { Cell::new(None) };
+}
+
+/// Apply the upload settings to files added from now on.
+pub fn configure(config: &UploadConfig) {
+    let compression = config.compress_images.then_some(Compression {
+        max_dimension: config.max_dimension,
+        quality: config.quality,
+    });
+    COMPRESSION.with(|c| c.set(compression));
+}
+
+fn is_image(name: &str) -> bool {
+    media::mimetype(name).starts_with("image/")
+}
+
+/// Delete `file` if we wrote it.
+fn discard(file: &PendingFile) {
+    if file.temporary {
+        if let Err(e) = std::fs::remove_file(&file.path) {
+            tracing::warn!("Failed to delete {}: {}", file.path.display(), e);
+        }
+    }
+}
+
+/// Show the upload prompt with the files of the batch, or hide it once the
+/// batch is empty.
+fn render(ui: &AppWindow) {
+    let (files, ready) = BATCH.with(|b| {
+        let batch = b.borrow();
+        let files: Vec<UploadFileData> = batch
+            .files()
+            .iter()
+            .map(|file| UploadFileData {
+                name: SharedString::from(file.name.as_str()),
+                size: SharedString::from(file.size_label()),
+                error: SharedString::from(file.problem(batch.limit()).unwrap_or_default()),
+                preview: if is_image(&file.name) {
+                    Image::load_from_path(&file.path).unwrap_or_default()
+                } else {
+                    Image::default()
+                },
+                compressed: file.compressed_size.is_some(),
+                original: file.send_original,
+            })
+            .collect();
+        (files, batch.sendable())
+    });
+    ui.set_show_upload_prompt(!files.is_empty());
+    ui.set_upload_files(Rc::new(VecModel::from(files)).into());
+    ui.set_upload_ready(ready);
+}
+
+/// Ask the server for its upload limit, unless it is known already, and
+/// check the batch against it.
+fn load_limit(ui: &AppWindow, client: &ClientHandle) {
+    if BATCH.with(|b| b.borrow().limit().is_some()) {
+        return;
+    }
+    let reply = client.upload_limit();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let limit = match reply.await {
+            Ok(limit) => limit,
+            // The server still refuses what is too large.
+            Err(e) => {
+                tracing::warn!("Failed to load the upload limit: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            BATCH.with(|b| b.borrow_mut().set_limit(limit));
+            if let Some(ui) = ui_handle.upgrade() {
+                render(&ui);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Add `file` to the batch and show it in the upload prompt.
+fn add(ui: &AppWindow, client: &ClientHandle, file: PendingFile) {
+    if history::current_room().is_none() {
+        discard(&file);
+        return;
+    }
+    if !BATCH.with(|b| b.borrow_mut().add(file.clone())) {
+        discard(&file);
+        return;
+    }
+    render(ui);
+    load_limit(ui, client);
+    if is_image(&file.name) {
+        compress(ui, file);
+    }
+}
+
+/// Compress image `file` off the UI thread, if images are, and show the
+/// size it comes down to.
+fn compress(ui: &AppWindow, file: PendingFile) {
+    let Some(compression) = COMPRESSION.with(Cell::get) else {
+        return;
+    };
+    let ui_handle = ui.as_weak();
+    tokio::task::spawn_blocking(move || {
+        let compressed = std::fs::read(&file.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| image_compression::compress(&file.name, &data, compression));
+        slint::invoke_from_event_loop(move || {
+            let compressed = match compressed {
+                Ok(Some(compressed)) => compressed,
+                // Sent as it is.
+                Ok(None) => return,
+                Err(e) => {
+                    tracing::warn!("Failed to compress {}: {:#}", file.path.display(), e);
+                    return;
+                }
+            };
+            let size = compressed.data.len() as u64;
+            if BATCH.with(|b| b.borrow_mut().set_compressed(&file.path, size)) {
+                COMPRESSED.with(|c| c.borrow_mut().insert(file.path, compressed));
+                if let Some(ui) = ui_handle.upgrade() {
+                    render(&ui);
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// A file was dropped on the window.
+pub fn add_file(ui: &AppWindow, client: &ClientHandle, path: PathBuf) {
+    let size = match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        Ok(_) => {
+            toast::show(ui, "Folders can't be uploaded.");
+            return;
+        }
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", path.display(), e);
+            toast::show(ui, format!("Couldn't read {}.", path.display()));
+            return;
+        }
+    };
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "file".to_string());
+    add(ui, client, PendingFile::new(path, name, size, false));
+}
+
+/// Paste the image on the clipboard, if there is one, into a temporary file
+/// queued for upload. Returns false when there is none, so the composer
+/// pastes text as usual.
+pub fn paste_image(ui: &AppWindow, client: &ClientHandle) -> bool {
+    if history::current_room().is_none() {
+        return false;
+    }
+    let copied = match arboard::Clipboard::new().and_then(|mut c| c.get_image()) {
+        Ok(copied) => copied,
+        Err(_) => return false,
+    };
+    let (width, height) = (copied.width as u32, copied.height as u32);
+    let pixels = copied.bytes.into_owned();
+    let path = std::env::temp_dir().join(format!("gamechat-{}.png", local_echo::new_txn_id()));
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::task::spawn_blocking(move || {
+        let written = image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Invalid clipboard image"))
+            .and_then(|image| {
+                image
+                    .save_with_format(&path, image::ImageFormat::Png)
+                    .map_err(Into::into)
+            })
+            .and_then(|()| Ok(std::fs::metadata(&path)?.len()));
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                let _ = std::fs::remove_file(&path);
+                return;
+            };
+            match written {
+                Ok(size) => {
+                    let file = PendingFile::new(path, PASTED_IMAGE_NAME.to_string(), size, true);
+                    add(&ui, &client, file);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to paste image: {}", e);
+                    let _ = std::fs::remove_file(&path);
+                    toast::show(&ui, "Couldn't paste the image.");
+                }
+            }
+        })
+        .ok();
+    });
+    true
+}
+
+/// Take file `index` out of the batch.
+pub fn remove(ui: &AppWindow, index: usize) {
+    if let Some(file) = BATCH.with(|b| b.borrow_mut().remove(index)) {
+        COMPRESSED.with(|c| c.borrow_mut().remove(&file.path));
+        discard(&file);
+    }
+    render(ui);
+}
+
+/// Send file `index` as it is, or compressed again.
+pub fn toggle_original(ui: &AppWindow, index: usize) {
+    BATCH.with(|b| b.borrow_mut().toggle_original(index));
+    render(ui);
+}
+
+/// Close the upload prompt without sending anything.
+pub fn cancel(ui: &AppWindow) {
+    for file in BATCH.with(|b| b.borrow_mut().take()) {
+        discard(&file);
+    }
+    COMPRESSED.with(|c| c.borrow_mut().clear());
+    render(ui);
+}
+
+/// Send the batch to the open room, each file as its own message, followed
+/// by `caption` if there is one.
+pub fn confirm(ui: &AppWindow, client: &ClientHandle, caption: &str) {
+    let Some(room_id) = history::current_room() else {
+        cancel(ui);
+        return;
+    };
+    if !BATCH.with(|b| b.borrow().sendable()) {
+        return;
+    }
+    for file in BATCH.with(|b| b.borrow_mut().take()) {
+        let compressed = COMPRESSED
+            .with(|c| c.borrow_mut().remove(&file.path))
+            .filter(|_| !file.send_original);
+        let name = compressed.as_ref().map_or(&file.name, |c| &c.name).clone();
+        let txn_id = local_echo::new_txn_id();
+        let mut message = rooms::outgoing_message(ui, &name);
+        message.schema = if is_image(&name) {
+            MessageType::Image
+        } else {
+            MessageType::File
+        };
+        message.attachment = Some(Attachment {
+            source: String::new(),
+            thumbnail_source: None,
+            name: name.clone(),
+            mimetype: Some(media::mimetype(&name).to_string()),
+            size: Some(file.upload_size()),
+            width: None,
+            height: None,
+        });
+        history::with_room(&room_id, |timeline| timeline.push_local(&txn_id, message));
+        match compressed {
+            Some(compressed) => {
+                discard(&file);
+                let upload = Upload::new(&compressed.name, compressed.data);
+                send(client, room_id.clone(), txn_id, upload);
+            }
+            // Not compressed yet, or sent as it is.
+            None => {
+                let compression = COMPRESSION
+                    .with(Cell::get)
+                    .filter(|_| !file.send_original && is_image(&file.name));
+                read(client, room_id.clone(), txn_id, file, compression);
+            }
+        }
+    }
+    render(ui);
+
+    let caption = caption.trim();
+    if !caption.is_empty() {
+        rooms::send_message(ui, client, caption, Vec::new(), None);
+    }
+}
+
+/// Read `file`, deleting it if it was ours, then send it compressed with
+/// `compression`, or else as it is but without its location.
+fn read(
+    client: &ClientHandle,
+    room_id: String,
+    txn_id: String,
+    file: PendingFile,
+    compression: Option<Compression>,
+) {
+    let client = client.clone();
+    tokio::task::spawn_blocking(move || {
+        let upload = std::fs::read(&file.path).map(|data| prepare(&file.name, data, compression));
+        if file.temporary {
+            let _ = std::fs::remove_file(&file.path);
+        }
+        slint::invoke_from_event_loop(move || match upload {
+            Ok(upload) => send(&client, room_id, txn_id, upload),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", file.path.display(), e);
+                history::with_room(&room_id, |timeline| timeline.mark_failed(&txn_id));
+            }
+        })
+        .ok();
+    });
+}
+
+/// The upload of `data`, named `name`: compressed with `compression` when
+/// that makes it smaller, and otherwise stripped of its location.
+fn prepare(name: &str, data: Vec<u8>, compression: Option<Compression>) -> Upload {
+    let compressed = compression.and_then(|compression| {
+        image_compression::compress(name, &data, compression).unwrap_or_else(|e| {
+            tracing::warn!("Failed to compress {}: {:#}", name, e);
+            None
+        })
+    });
+    match compressed {
+        Some(compressed) => Upload::new(&compressed.name, compressed.data),
+        None if is_image(name) => Upload::new(name, image_compression::strip_location(&data)),
+        None => Upload::new(name, data),
+    }
+}
+
+fn send(client: &ClientHandle, room_id: String, txn_id: String, upload: Upload) {
+    let image = upload.is_image().then(|| upload.data.clone());
+    attachment_view::upload_started(&room_id, &txn_id, image);
+    UNSENT.with(|u| {
+        u.borrow_mut()
+            .insert(txn_id.clone(), (room_id.clone(), upload.clone()))
+    });
+    let (reply, mut progress) = client.send_attachment(&room_id, upload, &txn_id);
+
+    let (progress_room, progress_txn) = (room_id.clone(), txn_id.clone());
+    tokio::spawn(async move {
+        while progress.changed().await.is_ok() {
+            let current = *progress.borrow_and_update();
+            let (room_id, txn_id) = (progress_room.clone(), progress_txn.clone());
+            slint::invoke_from_event_loop(move || {
+                attachment_view::upload_progress(&room_id, &txn_id, current)
+            })
+            .ok();
+        }
+    });
+
+    tokio::spawn(async move {
+        let result = reply.await;
+        if let Err(e) = &result {
+            tracing::error!("Upload failed: {}", e);
+        }
+        slint::invoke_from_event_loop(move || {
+            attachment_view::upload_finished(&room_id, &txn_id, result.as_deref().ok());
+            history::with_room(&room_id, |timeline| match &result {
+                Ok(event_id) => timeline.mark_sent(&txn_id, event_id),
+                Err(_) => timeline.mark_failed(&txn_id),
+            });
+            match &result {
+                Ok(_) => {
+                    UNSENT.with(|u| u.borrow_mut().remove(&txn_id));
+                }
+                Err(e) => rooms::send_failed(&room_id, e),
+            }
+        })
+        .ok();
+    });
+}
+
+/// Send the failed upload `txn_id` again. The timeline has put it back into
+/// sending already.
+pub fn retry(client: &ClientHandle, txn_id: &str) {
+    match UNSENT.with(|u| u.borrow().get(txn_id).cloned()) {
+        Some((room_id, upload)) => send(client, room_id, txn_id.to_string(), upload),
+        // Its file could not be read; there is nothing to send.
+        None => {
+            if let Some(room_id) = history::current_room() {
+                history::with_room(&room_id, |timeline| timeline.mark_failed(txn_id));
+            }
+        }
+    }
+}
+
+/// Forget the failed upload `txn_id`, as it was deleted.
+pub fn discard_failed(txn_id: &str) {
+    UNSENT.with(|u| u.borrow_mut().remove(txn_id));
+}
+
+/// Files dragged over and dropped on the window.
+pub fn window_event(ui: &AppWindow, client: &ClientHandle, event: &WindowEvent) {
+    let can_upload = ui.get_logged_in() && history::current_room().is_some();
+    match event {
+        WindowEvent::HoveredFile(_) => ui.set_file_hovering(can_upload),
+        WindowEvent::HoveredFileCancelled => ui.set_file_hovering(false),
+        WindowEvent::DroppedFile(path) => {
+            ui.set_file_hovering(false);
+            if can_upload {
+                add_file(ui, client, path.clone());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Paste an image with the paste shortcut while the composer has focus.
+/// Returns true when one was pasted, so the composer does not paste too.
+pub fn key_pressed(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    event: &KeyEvent,
+    modifiers: ModifiersState,
+) -> bool {
+    let shortcut = if cfg!(target_os = "macos") {
+        modifiers.super_key()
+    } else {
+        modifiers.control_key()
+    };
+    let paste = event.state == ElementState::Pressed
+        && shortcut
+        && matches!(&event.logical_key, Key::Character(c) if c.eq_ignore_ascii_case("v"));
+    paste && ui.get_composer_focused() && paste_image(ui, client)
+}
+
+/// Drop the batch and failed uploads, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    cancel(ui);
+    UNSENT.with(|u| u.borrow_mut().clear());
+}
@@ -0,0 +1,284 @@
+//! The forgot-password wizard off the login screen: mail a reset link to
+//! the address on the account, then set the new password once it is
+//! clicked. The new password is tried again every few seconds until then,
+//! so clicking the link is all that is left to do; servers that mail a
+//! code take it typed in instead.
+
+use crate::app_state::ClientHandle;
+use crate::{AppWindow, ResetStage};
+use network::password_reset::{PasswordReset, ResetError};
+use slint::{ComponentHandle, SharedString, Weak};
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// How long to wait before trying the new password again, while the link
+/// isn't clicked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Wizard {
+    homeserver: String,
+    reset: Option<PasswordReset>,
+    /// Chosen, waiting for the link.
+    password: Option<String>,
+    /// Bumped whenever the wizard moves on, so answers and retries from
+    /// before are dropped.
+    generation: u64,
+}
+
+thread_local! {
+    static WIZARD: RefCell<Wizard> = RefCell::new(Wizard::default());
+}
+
+fn generation() -> u64 {
+    WIZARD.with(|w| w.borrow().generation)
+}
+
+/// Drop answers and retries from before, keeping where the wizard is.
+fn next_generation() -> u64 {
+    WIZARD.with(|w| {
+        let mut wizard = w.borrow_mut();
+        wizard.generation += 1;
+        wizard.generation
+    })
+}
+
+/// Start over at the first step, dropping anything in flight.
+fn restart() -> u64 {
+    WIZARD.with(|w| {
+        let mut wizard = w.borrow_mut();
+        let generation = wizard.generation + 1;
+        *wizard = Wizard {
+            generation,
+            ..Wizard::default()
+        };
+        generation
+    })
+}
+
+fn show_stage(ui: &AppWindow, stage: ResetStage) {
+    ui.set_reset_stage(stage);
+    ui.set_reset_checking(false);
+    ui.set_reset_status(SharedString::new());
+    ui.set_reset_error(SharedString::new());
+}
+
+/// "Forgot password?" was clicked on the login screen.
+pub fn open(ui: &AppWindow) {
+    restart();
+    show_stage(ui, ResetStage::Email);
+    ui.set_reset_email(SharedString::new());
+    ui.set_reset_has_code(false);
+    ui.set_reset_mode(true);
+}
+
+/// Back to the login screen.
+pub fn close(ui: &AppWindow) {
+    restart();
+    ui.set_reset_mode(false);
+}
+
+/// Have `homeserver` mail a reset link to `email`.
+pub fn send_link(ui: &AppWindow, client: &ClientHandle, email: &str, homeserver: &str) {
+    let email = email.trim();
+    if email.is_empty() {
+        ui.set_reset_error(SharedString::from(
+            "Enter the email address on your account",
+        ));
+        return;
+    }
+    let gen_at_start = restart();
+    WIZARD.with(|w| w.borrow_mut().homeserver = homeserver.to_string());
+    show_stage(ui, ResetStage::Sending);
+
+    let reply = client.request_password_reset(homeserver, email);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if generation() != gen_at_start {
+                return;
+            }
+            match result {
+                Ok(reset) => {
+                    ui.set_reset_email(SharedString::from(reset.validation.email.as_str()));
+                    ui.set_reset_has_code(reset.submit_url.is_some());
+                    WIZARD.with(|w| w.borrow_mut().reset = Some(reset));
+                    show_stage(&ui, ResetStage::Waiting);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to ask for a password reset: {:#}", e);
+                    show_stage(&ui, ResetStage::Email);
+                    ui.set_reset_error(SharedString::from(e.to_string()));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Mail the link again.
+pub fn resend(ui: &AppWindow, client: &ClientHandle) {
+    let Some((homeserver, reset)) = WIZARD.with(|w| {
+        let wizard = w.borrow();
+        Some((wizard.homeserver.clone(), wizard.reset.clone()?))
+    }) else {
+        return;
+    };
+    let gen_at_start = generation();
+    let reply = client.resend_password_reset(&homeserver, reset);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if generation() != gen_at_start {
+                return;
+            }
+            match result {
+                Ok(reset) => {
+                    WIZARD.with(|w| w.borrow_mut().reset = Some(reset));
+                    ui.set_reset_error(SharedString::new());
+                    if !ui.get_reset_checking() {
+                        ui.set_reset_status(SharedString::from("Sent it again."));
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to send the reset link again: {:#}", e);
+                    ui.set_reset_error(SharedString::from(e.to_string()));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Submit the code from the mail, in place of clicking the link.
+pub fn submit_code(ui: &AppWindow, client: &ClientHandle, code: &str) {
+    let code = code.trim();
+    let Some((homeserver, reset)) = WIZARD.with(|w| {
+        let wizard = w.borrow();
+        Some((wizard.homeserver.clone(), wizard.reset.clone()?))
+    }) else {
+        return;
+    };
+    if code.is_empty() {
+        return;
+    }
+    let gen_at_start = generation();
+    let reply = client.submit_reset_token(&homeserver, reset, code);
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if generation() != gen_at_start {
+                return;
+            }
+            match result {
+                Ok(()) => {
+                    ui.set_reset_error(SharedString::new());
+                    // Set it now rather than at the next try.
+                    if WIZARD.with(|w| w.borrow().password.is_some()) {
+                        try_password(ui.as_weak(), client, next_generation());
+                    } else {
+                        ui.set_reset_status(SharedString::from(
+                            "Code accepted. Choose your new password.",
+                        ));
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to submit the reset code: {:#}", e);
+                    ui.set_reset_error(SharedString::from(e.to_string()));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Set `password` as the new password, now or once the link is clicked.
+pub fn set_password(ui: &AppWindow, client: &ClientHandle, password: &str, confirm: &str) {
+    let error = if password.is_empty() {
+        "Choose a password"
+    } else if password != confirm {
+        "Passwords do not match"
+    } else {
+        ""
+    };
+    ui.set_reset_error(SharedString::from(error));
+    if !error.is_empty() {
+        return;
+    }
+    WIZARD.with(|w| w.borrow_mut().password = Some(password.to_string()));
+    try_password(ui.as_weak(), client.clone(), next_generation());
+}
+
+/// Try the chosen password, and again every `CHECK_INTERVAL` while the
+/// link isn't clicked.
+fn try_password(ui_handle: Weak<AppWindow>, client: ClientHandle, gen_at_start: u64) {
+    let Some((homeserver, reset, password)) = WIZARD.with(|w| {
+        let wizard = w.borrow();
+        Some((
+            wizard.homeserver.clone(),
+            wizard.reset.clone()?,
+            wizard.password.clone()?,
+        ))
+    }) else {
+        return;
+    };
+    if let Some(ui) = ui_handle.upgrade() {
+        ui.set_reset_checking(true);
+    }
+
+    let reply = client.complete_password_reset(&homeserver, reset, &password);
+    tokio::spawn(async move {
+        let result = reply.await;
+        let not_clicked = result
+            .as_ref()
+            .is_err_and(|e| e.downcast_ref::<ResetError>() == Some(&ResetError::NotValidated));
+        if not_clicked {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if generation() != gen_at_start {
+                return;
+            }
+            match result {
+                Ok(()) => {
+                    WIZARD.with(|w| {
+                        let mut wizard = w.borrow_mut();
+                        wizard.reset = None;
+                        wizard.password = None;
+                    });
+                    show_stage(&ui, ResetStage::Done);
+                }
+                Err(_) if not_clicked => {
+                    ui.set_reset_status(SharedString::from(
+                        "Waiting for you to click the link in the email...",
+                    ));
+                    try_password(ui.as_weak(), client, gen_at_start);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to reset the password: {:#}", e);
+                    WIZARD.with(|w| w.borrow_mut().password = None);
+                    ui.set_reset_checking(false);
+                    ui.set_reset_status(SharedString::new());
+                    ui.set_reset_error(SharedString::from(e.to_string()));
+                }
+            }
+        })
+        .ok();
+    });
+}
@@ -0,0 +1,180 @@
+//! Forwarding messages of the open room to another: one from its context
+//! menu, or a run of consecutive ones, selected from the first to the last.
+//! The destination is picked in the quick switcher, and the messages go
+//! there one at a time, in the order they were sent.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, i18n, quick_switcher, toast, AppWindow};
+use fluent_bundle::FluentValue;
+use slint::ComponentHandle;
+use std::cell::RefCell;
+
+#[derive(Default)]
+struct Forwarding {
+    /// The room the selection is in.
+    room_id: String,
+    /// Where the run being selected starts.
+    anchor: Option<String>,
+    /// The messages selected, in timeline order.
+    selected: Vec<String>,
+    /// What goes, and from where, once a destination is picked.
+    pending: Option<(String, Vec<String>)>,
+}
+
+thread_local! {
+    static FORWARDING: RefCell<Forwarding> = RefCell::new(Forwarding::default());
+}
+
+/// Whether message `event_id` is selected to forward.
+pub fn is_selected(event_id: &str) -> bool {
+    FORWARDING.with(|f| f.borrow().selected.iter().any(|id| id == event_id))
+}
+
+/// Redraw the rows of `event_ids` in `room_id` and count the selection.
+fn show(ui: &AppWindow, room_id: &str, event_ids: &[String]) {
+    for event_id in event_ids {
+        history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+    }
+    let count = FORWARDING.with(|f| f.borrow().selected.len());
+    ui.set_selected_count(count as i32);
+}
+
+/// Start selecting a run at `event_id`, or with one started, take in every
+/// message from its start to `event_id`. Selecting the start again alone
+/// lets go of it.
+pub fn select(ui: &AppWindow, event_id: &str) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let (anchor, restart) = FORWARDING.with(|f| {
+        let state = f.borrow();
+        match &state.anchor {
+            Some(anchor) if state.room_id == room_id => {
+                let alone = state.selected.len() == 1 && anchor == event_id;
+                (anchor.clone(), alone)
+            }
+            _ => (event_id.to_string(), false),
+        }
+    });
+    if restart {
+        cancel(ui);
+        return;
+    }
+    let selected = history::with_message(&room_id, event_id, |timeline| {
+        timeline.delivered_between(&anchor, event_id)
+    })
+    .unwrap_or_default();
+    let old = FORWARDING.with(|f| {
+        let mut state = f.borrow_mut();
+        let old = std::mem::take(&mut state.selected);
+        state.room_id = room_id.clone();
+        state.anchor = Some(anchor);
+        state.selected = selected;
+        if state.selected.is_empty() {
+            state.anchor = None;
+        }
+        old
+    });
+    let changed: Vec<String> = old
+        .into_iter()
+        .chain(FORWARDING.with(|f| f.borrow().selected.clone()))
+        .collect();
+    show(ui, &room_id, &changed);
+}
+
+/// Let go of the selection, e.g. as another room opens.
+pub fn cancel(ui: &AppWindow) {
+    let (room_id, old) = FORWARDING.with(|f| {
+        let mut state = f.borrow_mut();
+        state.anchor = None;
+        (state.room_id.clone(), std::mem::take(&mut state.selected))
+    });
+    show(ui, &room_id, &old);
+}
+
+/// Pick where to forward `event_ids` of `room_id` to.
+fn pick_destination(ui: &AppWindow, room_id: String, event_ids: Vec<String>) {
+    if event_ids.is_empty() {
+        return;
+    }
+    let title = i18n::tr_args("forward-title", [("count", event_ids.len().into())]);
+    FORWARDING.with(|f| f.borrow_mut().pending = Some((room_id, event_ids)));
+    quick_switcher::open_forwarding(ui, &title);
+}
+
+/// Forward the open room's message `event_id`, from its context menu.
+pub fn forward_message(ui: &AppWindow, event_id: &str) {
+    if let Some(room_id) = history::current_room() {
+        pick_destination(ui, room_id, vec![event_id.to_string()]);
+    }
+}
+
+/// Forward the messages selected.
+pub fn forward_selection(ui: &AppWindow) {
+    let (room_id, selected) = FORWARDING.with(|f| {
+        let state = f.borrow();
+        (state.room_id.clone(), state.selected.clone())
+    });
+    pick_destination(ui, room_id, selected);
+}
+
+/// What goes above forwarded text of `event_id`: who sent it.
+fn annotation(room_id: &str, event_id: &str) -> Option<String> {
+    history::with_message(room_id, event_id, |timeline| {
+        let message = timeline.message(event_id)?;
+        let sender = FluentValue::from(message.sender_display().to_string());
+        Some(i18n::tr_args("forwarded-from", [("sender", sender)]))
+    })
+    .flatten()
+}
+
+/// Send what is waiting to be forwarded to `room_id`, named `name`. A
+/// failure stops the rest, so what arrives keeps its order.
+pub fn send(ui: &AppWindow, client: &ClientHandle, room_id: &str, name: &str) {
+    let Some((src_room, event_ids)) = FORWARDING.with(|f| f.borrow_mut().pending.take()) else {
+        return;
+    };
+    let annotate = ui.get_switcher_annotate();
+    let forwards: Vec<(String, Option<String>)> = event_ids
+        .into_iter()
+        .map(|event_id| {
+            let annotation = annotate.then(|| annotation(&src_room, &event_id)).flatten();
+            (event_id, annotation)
+        })
+        .collect();
+    cancel(ui);
+
+    let count = forwards.len();
+    let dst_room = room_id.to_string();
+    let name = name.to_string();
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let mut result = Ok(());
+        for (event_id, annotation) in forwards {
+            let forwarded = client
+                .forward_message(&src_room, &event_id, &dst_room, annotation.as_deref())
+                .await;
+            if let Err(e) = forwarded {
+                result = Err(e);
+                break;
+            }
+        }
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(()) => {
+                    let args = [("count", count.into()), ("room", name.into())];
+                    toast::show(&ui, i18n::tr_args("forwarded-to", args));
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to forward messages: {}", e);
+                    toast::show(&ui, admin::error_text(&e, "failed-forward"));
+                }
+            }
+        })
+        .ok();
+    });
+}
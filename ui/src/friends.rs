@@ -0,0 +1,267 @@
+//! The friends panel: friends with their presence and what they are up to,
+//! requests either way, and adding someone by their user id. The list is
+//! loaded as the panel opens and again whenever it changes, here or on
+//! another device, so the count of requests on the sidebar button stays
+//! current; presence and voice changes only redraw it.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{profile, quick_switcher, voice_channel, AppWindow, FriendData, FriendKind};
+use chat_core::rich_presence;
+use chat_core::UserStatus;
+use network::roster::{Friend, FriendState};
+use slint::{ComponentHandle, ModelRc, SharedString, VecModel};
+use std::cell::RefCell;
+
+thread_local! {
+    /// As last loaded, with presence kept current since.
+    static FRIENDS: RefCell<Vec<Friend>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Requests to answer first, then friends who are around, then the rest,
+/// then our own requests; by name within each.
+fn sort(friends: &mut [Friend]) {
+    friends.sort_by_cached_key(|friend| {
+        let group = match (friend.state, friend.status) {
+            (FriendState::Incoming, _) => 0,
+            (FriendState::Accepted, UserStatus::Offline) => 2,
+            (FriendState::Accepted, _) => 1,
+            _ => 3,
+        };
+        (group, friend.name().to_lowercase())
+    });
+}
+
+/// What a friend is up to: in a voice channel, shown by the name it has
+/// here, or playing a game. A status message that names no game is shown
+/// as it is instead.
+fn activity(voice_channel: Option<&str>, status_message: Option<&str>) -> String {
+    if let Some(channel) = voice_channel {
+        return format!("In voice: {}", channel);
+    }
+    match status_message.and_then(rich_presence::game_in) {
+        Some(game) => format!("Playing {}", game),
+        None => String::new(),
+    }
+}
+
+fn row(friend: &Friend) -> FriendData {
+    let voice = voice_channel::room_of(&friend.user_id)
+        .map(|room_id| quick_switcher::room_name(&room_id).unwrap_or(room_id));
+    let activity = activity(voice.as_deref(), friend.status_message.as_deref());
+    let status_message = match friend.status_message.as_deref() {
+        Some(message) if rich_presence::game_in(message).is_none() => message,
+        _ => "",
+    };
+    FriendData {
+        user_id: SharedString::from(friend.user_id.as_str()),
+        name: SharedString::from(friend.name()),
+        kind: match friend.state {
+            FriendState::Incoming => FriendKind::Incoming,
+            FriendState::Requested | FriendState::Removed => FriendKind::Outgoing,
+            FriendState::Accepted => FriendKind::Friend,
+        },
+        status: SharedString::from(friend.status.label()),
+        activity: SharedString::from(activity),
+        status_message: SharedString::from(status_message),
+        initials: profile::avatar_initials(
+            friend.display_name.as_deref().unwrap_or_default(),
+            &friend.user_id,
+        ),
+        avatar_color: profile::avatar_color(&friend.user_id),
+    }
+}
+
+fn render(ui: &AppWindow) {
+    let (rows, requests) = FRIENDS.with(|f| {
+        let friends = f.borrow();
+        let rows: Vec<FriendData> = friends.iter().map(row).collect();
+        let requests = friends
+            .iter()
+            .filter(|friend| friend.state == FriendState::Incoming)
+            .count();
+        (rows, requests)
+    });
+    ui.set_friends(ModelRc::new(VecModel::from(rows)));
+    ui.set_friend_requests(i32::try_from(requests).unwrap_or(i32::MAX));
+}
+
+/// Load the list and show it.
+pub fn load(ui: &AppWindow, client: &ClientHandle) {
+    let reply = client.get_friends();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            ui.set_friends_loading(false);
+            match result {
+                Ok(mut friends) => {
+                    sort(&mut friends);
+                    FRIENDS.with(|f| *f.borrow_mut() = friends);
+                    render(&ui);
+                }
+                Err(e) => tracing::warn!("Failed to load friends: {}", e),
+            }
+        })
+        .ok();
+    });
+}
+
+pub fn open(ui: &AppWindow, client: &ClientHandle) {
+    ui.set_friends_error(SharedString::default());
+    ui.set_friends_loading(FRIENDS.with(|f| f.borrow().is_empty()));
+    load(ui, client);
+}
+
+/// Wait for the change `reply` answers, then load the list again, or say
+/// why it failed.
+fn change(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    reply: impl std::future::Future<Output = anyhow::Result<()>> + Send + 'static,
+) {
+    ui.set_friends_error(SharedString::default());
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                ui.set_friends_error(SharedString::from(e.to_string()));
+            }
+            load(&ui, &client);
+        })
+        .ok();
+    });
+}
+
+/// Ask `user_id` to be friends, as typed in the panel.
+pub fn add(ui: &AppWindow, client: &ClientHandle, user_id: &str) {
+    let user_id = user_id.trim();
+    if !user_id.starts_with('@') || !user_id.contains(':') {
+        ui.set_friends_error(SharedString::from(
+            "Enter a full user id, e.g. @friend:example.org",
+        ));
+        return;
+    }
+    change(ui, client, client.add_friend(user_id));
+}
+
+pub fn respond(ui: &AppWindow, client: &ClientHandle, user_id: &str, accept: bool) {
+    change(ui, client, client.respond_to_friend(user_id, accept));
+}
+
+pub fn remove(ui: &AppWindow, client: &ClientHandle, user_id: &str) {
+    change(ui, client, client.remove_friend(user_id));
+}
+
+fn room_of(user_id: &str) -> Option<String> {
+    FRIENDS.with(|f| {
+        f.borrow()
+            .iter()
+            .find(|friend| friend.user_id == user_id)
+            .map(|friend| friend.room_id.clone())
+    })
+}
+
+/// Open the direct chat with `user_id`.
+pub fn message(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, user_id: &str) {
+    let Some(room_id) = room_of(user_id) else {
+        return;
+    };
+    ui.set_show_friends(false);
+    rooms::reveal_room(ui, client, sidebar, &room_id);
+}
+
+/// Open the direct chat with `user_id` and join its voice channel.
+pub fn call(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, user_id: &str) {
+    let Some(room_id) = room_of(user_id) else {
+        return;
+    };
+    ui.set_show_friends(false);
+    rooms::reveal_room(ui, client, sidebar, &room_id);
+    voice_channel::join_call(ui, client, &room_id);
+}
+
+/// A user's presence changed: redraw if they are on the list.
+pub fn presence_changed(ui: &AppWindow, user_id: &str, status: UserStatus, message: Option<&str>) {
+    let listed = FRIENDS.with(|f| {
+        let mut friends = f.borrow_mut();
+        let Some(friend) = friends.iter_mut().find(|friend| friend.user_id == user_id) else {
+            return false;
+        };
+        friend.status = status;
+        friend.status_message = message.map(str::to_string);
+        sort(&mut friends);
+        true
+    });
+    if listed {
+        render(ui);
+    }
+}
+
+/// Someone joined or left a voice channel: redraw, as they may be a
+/// friend.
+pub fn voice_changed(ui: &AppWindow, user_id: &str) {
+    let listed = FRIENDS.with(|f| f.borrow().iter().any(|friend| friend.user_id == user_id));
+    if listed {
+        render(ui);
+    }
+}
+
+/// Forget the list and close the panel, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    FRIENDS.with(|f| f.borrow_mut().clear());
+    ui.set_show_friends(false);
+    ui.set_friends(ModelRc::default());
+    ui.set_friends_error(SharedString::default());
+    ui.set_friend_requests(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn friend(name: &str, state: FriendState, status: UserStatus) -> Friend {
+        let user_id = format!("@{}:example.org", name);
+        Friend {
+            room_id: format!("!dm-{}", user_id),
+            user_id,
+            display_name: None,
+            state,
+            status,
+            status_message: None,
+        }
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut friends = vec![
+            friend("zed", FriendState::Requested, UserStatus::Online),
+            friend("yan", FriendState::Accepted, UserStatus::Offline),
+            friend("bob", FriendState::Accepted, UserStatus::Idle),
+            friend("Ann", FriendState::Accepted, UserStatus::Online),
+            friend("eve", FriendState::Incoming, UserStatus::Offline),
+        ];
+        sort(&mut friends);
+        let order: Vec<&str> = friends.iter().map(Friend::name).collect();
+        assert_eq!(order, ["eve", "Ann", "bob", "yan", "zed"]);
+    }
+
+    #[test]
+    fn test_activity() {
+        assert_eq!(
+            activity(Some("Lobby"), Some("Playing Valorant")),
+            "In voice: Lobby"
+        );
+        assert_eq!(activity(None, Some("Playing Valorant")), "Playing Valorant");
+        assert_eq!(activity(None, Some("Back at 5")), "");
+        assert_eq!(activity(None, None), "");
+    }
+}
@@ -0,0 +1,278 @@
+//! Game invites: sent from a small dialog to the open room, and shown in
+//! the timeline as a card whose Join button copies where to connect, or
+//! opens it when it is a link of a scheme the settings allow, after asking.
+//! Cards turn to expired as their time runs out: a timer set for the next
+//! expiry among those drawn redraws the timelines.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, message_actions, rich_presence, toast, AppWindow, GameInviteData};
+use chat_core::game_invite::{self, GameInvite, Join};
+use chat_core::{schedule, time, Message, MessageType};
+use network::config::GameInviteConfig;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// How long an invite stays open, by index in the dialog's choices.
+const OPEN_FOR_MINUTES: [u64; 5] = [15, 30, 60, 120, 240];
+
+#[derive(Default)]
+struct GameInvites {
+    config: GameInviteConfig,
+    /// The room the dialog invites.
+    room_id: String,
+    /// When the expiry timer fires, in ms since the Unix epoch.
+    next_expiry: Option<u64>,
+}
+
+thread_local! {
+    static GAME_INVITES: RefCell<GameInvites> = RefCell::new(GameInvites::default());
+    static EXPIRY_TIMER: slint::Timer = slint::Timer::default();
+}
+
+/// Use `config` from now on, redrawing the timelines if it changed.
+pub fn configure(config: &GameInviteConfig) {
+    let changed = GAME_INVITES.with(|g| {
+        let mut state = g.borrow_mut();
+        let changed = state.config != *config;
+        state.config = config.clone();
+        changed
+    });
+    if changed {
+        history::redraw();
+    }
+}
+
+/// Redraw the timelines at `expires_ts`, unless a redraw comes sooner.
+fn expire_at(expires_ts: u64, now: u64) {
+    let sooner = GAME_INVITES.with(|g| {
+        let mut state = g.borrow_mut();
+        if state.next_expiry.is_some_and(|next| next <= expires_ts) {
+            return false;
+        }
+        state.next_expiry = Some(expires_ts);
+        true
+    });
+    if !sooner {
+        return;
+    }
+    let wait = Duration::from_millis(expires_ts.saturating_sub(now));
+    EXPIRY_TIMER.with(|timer| {
+        timer.start(slint::TimerMode::SingleShot, wait, || {
+            GAME_INVITES.with(|g| g.borrow_mut().next_expiry = None);
+            history::redraw();
+        })
+    });
+}
+
+/// The card of `invite` as of `now`.
+fn card(invite: &GameInvite, now: u64, launch_schemes: &[String]) -> GameInviteData {
+    let expired = invite.is_expired(now);
+    GameInviteData {
+        game: SharedString::from(invite.game.trim()),
+        connect: SharedString::from(invite.connect.as_str()),
+        party: match invite.party_size {
+            Some(size) => SharedString::from(format!("Party of {}", size)),
+            None => SharedString::default(),
+        },
+        expires: if expired {
+            SharedString::from("Expired")
+        } else {
+            SharedString::from(format!(
+                "Open until {}",
+                schedule::format(invite.expires_ts)
+            ))
+        },
+        expired,
+        launch: matches!(
+            game_invite::join_action(&invite.connect, launch_schemes),
+            Join::Launch(_)
+        ),
+    }
+}
+
+/// The card of `message`, if it is an invite.
+pub fn invite_data(message: &Message) -> GameInviteData {
+    let MessageType::GameInvite(invite) = &message.schema else {
+        return GameInviteData::default();
+    };
+    let now = time::now_ms();
+    if !invite.is_expired(now) {
+        expire_at(invite.expires_ts, now);
+    }
+    GAME_INVITES.with(|g| card(invite, now, &g.borrow().config.launch_schemes))
+}
+
+/// Open the dialog to invite the open room, naming the game being played
+/// if none is typed in yet.
+pub fn open(ui: &AppWindow) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    GAME_INVITES.with(|g| g.borrow_mut().room_id = room_id);
+    if ui.get_game_invite_game().is_empty() {
+        if let Some(game) = rich_presence::game() {
+            ui.set_game_invite_game(SharedString::from(game));
+        }
+    }
+    ui.set_game_invite_error(SharedString::default());
+    ui.set_game_invite_sending(false);
+    ui.set_show_game_invite(true);
+}
+
+/// The invite the dialog describes, sent at `now`: `party` is empty for
+/// any size, and `open_for` the index of how long it stays open.
+fn invite(game: &str, connect: &str, party: &str, open_for: usize, now: u64) -> GameInvite {
+    let party = party.trim();
+    let minutes = OPEN_FOR_MINUTES[open_for.min(OPEN_FOR_MINUTES.len() - 1)];
+    GameInvite {
+        game: game.trim().to_string(),
+        connect: connect.trim().to_string(),
+        // Not a number, so out of range.
+        party_size: (!party.is_empty()).then(|| party.parse().unwrap_or(0)),
+        expires_ts: now + minutes * 60 * 1000,
+    }
+}
+
+/// Send the invite the dialog describes, closing it once sent.
+pub fn send(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    game: &str,
+    connect: &str,
+    party: &str,
+    open_for: usize,
+) {
+    let now = time::now_ms();
+    let invite = invite(game, connect, party, open_for, now);
+    if let Err(e) = invite.validate(now) {
+        ui.set_game_invite_error(SharedString::from(e.to_string()));
+        return;
+    }
+    let room_id = GAME_INVITES.with(|g| g.borrow().room_id.clone());
+    ui.set_game_invite_error(SharedString::default());
+    ui.set_game_invite_sending(true);
+    let reply = client.send_game_invite(&room_id, invite);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            ui.set_game_invite_sending(false);
+            match result {
+                Ok(_) => ui.set_show_game_invite(false),
+                Err(e) => {
+                    tracing::warn!("Failed to send the game invite: {:#}", e);
+                    ui.set_game_invite_error(admin::error_text(&e, "failed-send-game-invite"));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// What the prompt before launching `url` says.
+fn launch_prompt(url: &str) -> String {
+    let scheme = url.split_once(':').map_or(url, |(scheme, _)| scheme);
+    format!(
+        "This invite will open the application for {} links with the address below. \
+         Only join invites from people you trust.",
+        scheme
+    )
+}
+
+/// Join an invite to `connect`: ask before launching a link, or copy it.
+pub fn join(ui: &AppWindow, connect: &str) {
+    let launch_schemes = GAME_INVITES.with(|g| g.borrow().config.launch_schemes.clone());
+    match game_invite::join_action(connect, &launch_schemes) {
+        Join::Launch(url) => {
+            ui.set_link_prompt_url(SharedString::from(url.as_str()));
+            ui.set_link_prompt_message(SharedString::from(launch_prompt(&url)));
+            ui.set_link_prompt_can_open(true);
+            ui.set_show_link_prompt(true);
+        }
+        Join::Copy(text) => {
+            message_actions::copy(ui, &text);
+            toast::show(
+                ui,
+                "Copied where to connect. Paste it into the game to join.",
+            );
+        }
+    }
+}
+
+/// Close the dialog and forget what it was for, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    GAME_INVITES.with(|g| g.borrow_mut().room_id.clear());
+    ui.set_show_game_invite(false);
+    ui.set_game_invite_game(SharedString::default());
+    ui.set_game_invite_error(SharedString::default());
+    ui.set_game_invite_sending(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1000;
+    const NOW: u64 = 1_710_504_000_000;
+
+    #[test]
+    fn test_invite_from_the_dialog() {
+        let sent = invite(" Valorant ", " ABCD-1234 ", "", 1, NOW);
+        assert_eq!(sent.game, "Valorant");
+        assert_eq!(sent.connect, "ABCD-1234");
+        assert_eq!(sent.party_size, None);
+        assert_eq!(sent.expires_ts, NOW + 30 * MINUTE);
+        assert_eq!(sent.validate(NOW), Ok(()));
+
+        assert_eq!(invite("x", "y", "5", 4, NOW).party_size, Some(5));
+        assert_eq!(invite("x", "y", "", 9, NOW).expires_ts, NOW + 240 * MINUTE);
+        let junk = invite("x", "y", "lots", 0, NOW);
+        assert_eq!(
+            junk.validate(NOW),
+            Err(game_invite::InviteError::BadPartySize)
+        );
+    }
+
+    #[test]
+    fn test_card_expires_and_says_how_to_join() {
+        let steam = game_invite::default_launch_schemes();
+        let invite = GameInvite {
+            game: "Valorant".to_string(),
+            connect: "steam://connect/203.0.113.7:27015".to_string(),
+            party_size: Some(5),
+            expires_ts: NOW + 30 * MINUTE,
+        };
+        let open = card(&invite, NOW, &steam);
+        assert_eq!(open.game, "Valorant");
+        assert_eq!(open.party, "Party of 5");
+        assert!(open.expires.starts_with("Open until "));
+        assert!(!open.expired);
+        assert!(open.launch);
+
+        let expired = card(&invite, NOW + 30 * MINUTE, &steam);
+        assert_eq!(expired.expires, "Expired");
+        assert!(expired.expired);
+
+        let copied = GameInvite {
+            connect: "203.0.113.7:27015".to_string(),
+            party_size: None,
+            ..invite
+        };
+        let copied = card(&copied, NOW, &steam);
+        assert!(!copied.launch);
+        assert_eq!(copied.party, "");
+    }
+
+    #[test]
+    fn test_launch_prompt_names_the_scheme() {
+        assert_eq!(
+            launch_prompt("steam://connect/203.0.113.7:27015"),
+            "This invite will open the application for steam links with the address below. \
+             Only join invites from people you trust."
+        );
+    }
+}
@@ -0,0 +1,800 @@
+//! Per-room timelines kept on the UI thread, so switching back to a room
+//! shows its history (and scroll position) instantly while the latest page
+//! is fetched in the background. Jumping to a message that is not loaded
+//! shows the messages around it apart from the latest ones, until paging
+//! down from there reaches them. Rows let go from the top of a long
+//! timeline come back before older pages are fetched.
+
+use crate::app_state::ClientHandle;
+use crate::local_echo::Merge;
+use crate::timeline::TimelineModel;
+use crate::timeline_window::REVEAL_STEP;
+use crate::{admin, bridges, sender_trust, toast, translations, AppWindow};
+use chat_core::Message;
+use network::cancel::CancelToken;
+use network::config::TimelineConfig;
+use network::rooms::MessagePage;
+use slint::{ComponentHandle, ModelRc, SharedString};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long the message jumped to stays lit up.
+const JUMP_HIGHLIGHT: Duration = Duration::from_secs(2);
+
+struct RoomView {
+    model: Rc<TimelineModel>,
+    viewport_y: f32,
+    at_bottom: bool,
+    /// Token for the next older page; `None` until the latest page arrives.
+    prev_batch: Option<String>,
+    /// The oldest message of the room is loaded.
+    at_start: bool,
+    /// The in-flight backward pagination, at most one per room.
+    paginating: Option<CancelToken>,
+    /// Older messages jumped to, shown instead of `model` while set.
+    jump: Option<Jump>,
+}
+
+/// The messages around one jumped to, held apart from the latest ones.
+struct Jump {
+    model: Rc<TimelineModel>,
+    /// Token for the page before the oldest of them.
+    prev_batch: Option<String>,
+    /// Token for the page after the newest; `None` once the latest message
+    /// is reached.
+    next_batch: Option<String>,
+    /// The in-flight forward pagination.
+    paginating: Option<CancelToken>,
+}
+
+#[derive(Default)]
+struct Views {
+    rooms: HashMap<String, RoomView>,
+    /// The room whose timeline is on screen.
+    current: Option<String>,
+    /// The latest-page fetch for `current`, cancelled on the next switch.
+    loading: Option<CancelToken>,
+    /// The fetch of the messages around one jumped to in `current`.
+    jumping: Option<CancelToken>,
+}
+
+thread_local! {
+    static VIEWS: RefCell<Views> = RefCell::new(Views::default());
+    /// What each room's timeline shows, and most rows a room keeps while
+    /// scrolled to the bottom.
+    static CONFIG: RefCell<TimelineConfig> = RefCell::new(TimelineConfig::default());
+}
+
+/// Use the timeline settings for every room: its filter, and the window
+/// size. Rooms whose filter changed show their rows afresh.
+pub fn configure(config: &TimelineConfig) {
+    CONFIG.with(|c| *c.borrow_mut() = config.clone());
+    let rooms: Vec<String> = VIEWS.with(|v| v.borrow().rooms.keys().cloned().collect());
+    for room_id in rooms {
+        let filter = config.for_room(&room_id);
+        for_each_timeline(&room_id, |model| {
+            model.set_window_limit(config.window);
+            model.set_filter(filter);
+        });
+    }
+}
+
+/// The room whose timeline is on screen.
+pub fn current_room() -> Option<String> {
+    VIEWS.with(|v| v.borrow().current.clone())
+}
+
+/// Run `f` on a room's cached timeline, if it has been opened.
+pub fn with_room<R>(room_id: &str, f: impl FnOnce(&TimelineModel) -> R) -> Option<R> {
+    model(room_id).map(|m| f(&m))
+}
+
+fn model(room_id: &str) -> Option<Rc<TimelineModel>> {
+    VIEWS.with(|v| v.borrow().rooms.get(room_id).map(|r| r.model.clone()))
+}
+
+fn with_view<R>(room_id: &str, f: impl FnOnce(&mut RoomView) -> R) -> Option<R> {
+    VIEWS.with(|v| v.borrow_mut().rooms.get_mut(room_id).map(f))
+}
+
+/// Every timeline of `room_id`: the latest messages, and those jumped to.
+fn models(room_id: &str) -> Vec<Rc<TimelineModel>> {
+    VIEWS.with(|v| {
+        let views = v.borrow();
+        let Some(view) = views.rooms.get(room_id) else {
+            return Vec::new();
+        };
+        let mut models = vec![view.model.clone()];
+        models.extend(view.jump.as_ref().map(|jump| jump.model.clone()));
+        models
+    })
+}
+
+/// Run `f` on each timeline of `room_id`, e.g. to apply an edit to a
+/// message jumped to as well as to the latest ones.
+pub fn for_each_timeline(room_id: &str, f: impl Fn(&TimelineModel)) {
+    for model in models(room_id) {
+        f(&model);
+    }
+}
+
+/// Run `f` on the timeline of `room_id` holding message `event_id`.
+pub fn with_message<R>(
+    room_id: &str,
+    event_id: &str,
+    f: impl FnOnce(&TimelineModel) -> R,
+) -> Option<R> {
+    models(room_id)
+        .into_iter()
+        .find(|model| model.message(event_id).is_some())
+        .map(|model| f(&model))
+}
+
+/// The cached room holding message `event_id`, whichever window shows it.
+pub fn room_of(event_id: &str) -> Option<String> {
+    VIEWS.with(|v| {
+        v.borrow()
+            .rooms
+            .iter()
+            .find(|(_, view)| {
+                view.model.message(event_id).is_some()
+                    || view
+                        .jump
+                        .as_ref()
+                        .is_some_and(|jump| jump.model.message(event_id).is_some())
+            })
+            .map(|(room_id, _)| room_id.clone())
+    })
+}
+
+impl RoomView {
+    fn new(own_user_id: &str, room_id: &str) -> Self {
+        let model = Rc::new(TimelineModel::new(own_user_id, Vec::new()));
+        let (window, filter) = CONFIG.with(|c| {
+            let config = c.borrow();
+            (config.window, config.for_room(room_id))
+        });
+        model.set_window_limit(window);
+        model.set_filter(filter);
+        model.set_bridged(bridges::is_on(room_id));
+        model.set_auto_translate(translations::auto_from(room_id).is_some());
+        model.set_encrypted(sender_trust::is_encrypted(room_id));
+        Self {
+            model,
+            viewport_y: 0.0,
+            at_bottom: true,
+            prev_batch: None,
+            at_start: false,
+            paginating: None,
+            jump: None,
+        }
+    }
+
+    /// Whether the first message of the room is on screen.
+    fn at_start(&self) -> bool {
+        self.at_start && self.model.hidden() == 0
+    }
+
+    /// Stop showing messages jumped to, abandoning fetches for the
+    /// timeline on screen.
+    fn drop_jump(&mut self) {
+        if let Some(paginating) = self.paginating.take() {
+            paginating.cancel();
+        }
+        if let Some(paginating) = self.jump.take().and_then(|jump| jump.paginating) {
+            paginating.cancel();
+        }
+    }
+}
+
+/// Redraw every cached timeline, e.g. when the keywords changed.
+pub fn redraw() {
+    let models: Vec<Rc<TimelineModel>> =
+        VIEWS.with(|v| v.borrow().rooms.values().map(|r| r.model.clone()).collect());
+    for model in models {
+        model.redraw();
+    }
+}
+
+/// Add a message from sync to its room's timeline, if that room is cached.
+pub fn push_remote(room_id: &str, message: Message, txn_id: Option<&str>) {
+    with_room(room_id, |timeline| timeline.push_remote(message, txn_id));
+}
+
+/// Stop showing the current room: abandon its pending fetch, remember where
+/// it was scrolled to, and mark it read up to its newest message.
+pub fn leave_room(ui: &AppWindow, client: &ClientHandle) {
+    let read_up_to = VIEWS.with(|v| {
+        let mut views = v.borrow_mut();
+        if let Some(loading) = views.loading.take() {
+            loading.cancel();
+        }
+        if let Some(jumping) = views.jumping.take() {
+            jumping.cancel();
+        }
+        let room_id = views.current.take()?;
+        let view = views.rooms.get_mut(&room_id)?;
+        if let Some(paginating) = view.paginating.take() {
+            paginating.cancel();
+        }
+        // Coming back shows the latest messages, not those jumped to.
+        if view.jump.is_some() {
+            view.drop_jump();
+            view.at_bottom = true;
+        } else {
+            view.viewport_y = ui.get_messages_viewport_y();
+            view.at_bottom = ui.get_messages_at_bottom();
+        }
+        view.model.on_failed_changed(None);
+        view.model.set_unread_after(None);
+        Some((room_id, view.model.latest_event()?))
+    });
+
+    if let Some((room_id, (event_id, ts))) = read_up_to {
+        send_read_receipt(client, room_id, &event_id, ts);
+    }
+}
+
+fn send_read_receipt(client: &ClientHandle, room_id: String, event_id: &str, ts: u64) {
+    let reply = client.mark_read(&room_id, event_id, ts);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to mark {} as read: {}", room_id, e);
+        }
+    });
+}
+
+/// Put the "new messages" divider where `room_id` was read up to, asked for
+/// before opening it marks it read any further.
+fn show_unread_marker(client: &ClientHandle, room_id: &str) {
+    let marker = client.fully_read(room_id);
+    let room_id = room_id.to_string();
+    tokio::spawn(async move {
+        let marker = match marker.await {
+            Ok(marker) => marker,
+            Err(e) => {
+                tracing::warn!("Failed to read the fully-read marker of {}: {}", room_id, e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            if current_room().as_deref() != Some(room_id.as_str()) {
+                return;
+            }
+            if let Some(model) = model(&room_id) {
+                model.set_unread_after(marker);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Show `room_id`: its cached timeline at once if we have one, then the
+/// latest page from the server merged in and marked read.
+pub fn open_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    if current_room().as_deref() == Some(room_id) {
+        return;
+    }
+    leave_room(ui, client);
+
+    let own_user_id = ui.get_current_user_id();
+    let (timeline, viewport_y, at_bottom, at_start) = VIEWS.with(|v| {
+        let mut views = v.borrow_mut();
+        views.current = Some(room_id.to_string());
+        let view = views
+            .rooms
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomView::new(own_user_id.as_str(), room_id));
+        (
+            view.model.clone(),
+            view.viewport_y,
+            view.at_bottom,
+            view.at_start(),
+        )
+    });
+    let ui_handle = ui.as_weak();
+    timeline.on_failed_changed(Some(Box::new(move |count| {
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.set_failed_sends(count as i32);
+        }
+    })));
+    ui.set_messages(ModelRc::from(timeline));
+    ui.set_messages_loading_older(false);
+    ui.set_messages_loading_newer(false);
+    ui.set_messages_detached(false);
+    ui.set_messages_jump_id(SharedString::new());
+    ui.set_messages_at_start(at_start);
+    ui.set_messages_at_bottom(at_bottom);
+    if !at_bottom {
+        ui.set_messages_viewport_y(viewport_y);
+    }
+
+    let cancel = CancelToken::new();
+    VIEWS.with(|v| v.borrow_mut().loading = Some(cancel.clone()));
+
+    show_unread_marker(client, room_id);
+    let switch = client.switch_room(room_id);
+    let history = client.history(room_id, None, cancel.clone());
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let room_id = room_id.to_string();
+    tokio::spawn(async move {
+        let _ = switch.await;
+        let page = match history.await {
+            Ok(page) => page,
+            Err(_) if cancel.is_cancelled() => return,
+            Err(e) => {
+                tracing::error!("Failed to load history: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            // The user may have moved on while this was in flight.
+            if cancel.is_cancelled() {
+                return;
+            }
+            let Some(model) = model(&room_id) else {
+                return;
+            };
+            let merge = model.merge_latest(page.messages);
+            if let Some((event_id, ts)) = model.latest_event() {
+                send_read_receipt(&client, room_id.clone(), &event_id, ts);
+            }
+            if merge != Merge::Replaced {
+                return;
+            }
+            // Fresh history (or a gap): paginate from this page from now on.
+            let at_start = page.prev_batch.is_none();
+            with_view(&room_id, |view| {
+                if let Some(paginating) = view.paginating.take() {
+                    paginating.cancel();
+                }
+                view.prev_batch = page.prev_batch;
+                view.at_start = at_start;
+            });
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_messages_loading_older(false);
+                ui.set_messages_at_start(at_start);
+            }
+        })
+        .ok();
+    });
+}
+
+/// The timeline of `room_id` for a window other than the main one. A room
+/// not opened before is cached from now on and its latest page fetched;
+/// either way sync keeps the one model up to date for every window.
+pub fn shared_model(ui: &AppWindow, client: &ClientHandle, room_id: &str) -> Rc<TimelineModel> {
+    let own_user_id = ui.get_current_user_id();
+    let (timeline, created) = VIEWS.with(|v| {
+        let mut views = v.borrow_mut();
+        if let Some(view) = views.rooms.get(room_id) {
+            return (view.model.clone(), false);
+        }
+        let view = RoomView::new(own_user_id.as_str(), room_id);
+        let timeline = view.model.clone();
+        views.rooms.insert(room_id.to_string(), view);
+        (timeline, true)
+    });
+    if !created {
+        return timeline;
+    }
+
+    let history = client.history(room_id, None, CancelToken::new());
+    let room_id = room_id.to_string();
+    tokio::spawn(async move {
+        let page = match history.await {
+            Ok(page) => page,
+            Err(e) => {
+                tracing::error!("Failed to load history: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(model) = model(&room_id) else {
+                return;
+            };
+            if model.merge_latest(page.messages) != Merge::Replaced {
+                return;
+            }
+            let at_start = page.prev_batch.is_none();
+            with_view(&room_id, |view| {
+                if view.paginating.is_none() {
+                    view.prev_batch = page.prev_batch;
+                    view.at_start = at_start;
+                }
+            });
+        })
+        .ok();
+    });
+    timeline
+}
+
+/// Start `room_id`'s timeline from `page`, fetched while it was previewed,
+/// so opening it after joining shows those messages at once and pages
+/// back from them. A room already cached is left as it is.
+pub fn seed(ui: &AppWindow, room_id: &str, page: MessagePage) {
+    let own_user_id = ui.get_current_user_id();
+    VIEWS.with(|v| {
+        let mut views = v.borrow_mut();
+        if views.rooms.contains_key(room_id) {
+            return;
+        }
+        let mut view = RoomView::new(own_user_id.as_str(), room_id);
+        view.model.merge_latest(page.messages);
+        view.at_start = page.prev_batch.is_none();
+        view.prev_batch = page.prev_batch;
+        views.rooms.insert(room_id.to_string(), view);
+    });
+}
+
+/// Fetch the page before the oldest loaded message of the open room and
+/// insert it at the top. Does nothing while a fetch for the room is in
+/// flight, before the latest page has arrived, or at the start of the room.
+pub fn load_older(ui: &AppWindow, client: &ClientHandle) {
+    let Some(room_id) = current_room() else {
+        return;
+    };
+    if reveal_older(ui, &room_id) {
+        return;
+    }
+    let cancel = CancelToken::new();
+    let from = with_view(&room_id, |view| {
+        if view.paginating.is_some() {
+            return None;
+        }
+        let from = match &view.jump {
+            Some(jump) => jump.prev_batch.clone()?,
+            None if view.at_start => return None,
+            None => view.prev_batch.clone()?,
+        };
+        view.paginating = Some(cancel.clone());
+        Some(from)
+    })
+    .flatten();
+    let Some(from) = from else {
+        return;
+    };
+    ui.set_messages_loading_older(true);
+
+    let history = client.history(&room_id, Some(from), cancel.clone());
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = history.await;
+        slint::invoke_from_event_loop(move || {
+            if cancel.is_cancelled() {
+                return;
+            }
+            let Some(model) = with_view(&room_id, |view| {
+                view.paginating = None;
+                shown(view)
+            }) else {
+                return;
+            };
+            let page = match result {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::error!("Failed to load older messages: {}", e);
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_messages_loading_older(false);
+                    }
+                    return;
+                }
+            };
+
+            let is_current = current_room().as_deref() == Some(room_id.as_str());
+            let ui = ui_handle.upgrade().filter(|_| is_current);
+            if let Some(ui) = &ui {
+                // Keep the rows on screen in place as content grows above them.
+                ui.set_messages_keep_scroll_offset(true);
+            }
+            model.prepend(page.messages);
+
+            let at_start = page.prev_batch.is_none();
+            with_view(&room_id, |view| match &mut view.jump {
+                Some(jump) => jump.prev_batch = page.prev_batch,
+                None => {
+                    view.prev_batch = page.prev_batch;
+                    view.at_start = at_start;
+                }
+            });
+            if let Some(ui) = ui {
+                ui.set_messages_loading_older(false);
+                ui.set_messages_at_start(at_start);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Bring back rows let go from the top of the latest messages, keeping
+/// those on screen in place. Returns whether there were any.
+fn reveal_older(ui: &AppWindow, room_id: &str) -> bool {
+    let Some(model) = latest_shown(room_id).filter(|model| model.hidden() > 0) else {
+        return false;
+    };
+    ui.set_messages_keep_scroll_offset(true);
+    model.reveal(REVEAL_STEP);
+    let at_start = with_view(room_id, |view| view.at_start()).unwrap_or(false);
+    ui.set_messages_at_start(at_start);
+    true
+}
+
+/// Let go of the oldest rows of the open room past the window size, now
+/// the view sits at the bottom.
+pub fn trim(ui: &AppWindow) {
+    let Some(room_id) = current_room() else {
+        return;
+    };
+    if latest_shown(&room_id).is_some_and(|model| model.trim() > 0) {
+        ui.set_messages_at_start(false);
+    }
+}
+
+/// The latest messages of `room_id`, unless others jumped to are shown.
+fn latest_shown(room_id: &str) -> Option<Rc<TimelineModel>> {
+    with_view(room_id, |view| {
+        view.jump.is_none().then(|| view.model.clone())
+    })
+    .flatten()
+}
+
+/// The timeline on screen for a room: the messages jumped to, if any.
+fn shown(view: &RoomView) -> Rc<TimelineModel> {
+    match &view.jump {
+        Some(jump) => jump.model.clone(),
+        None => view.model.clone(),
+    }
+}
+
+/// Light up message `event_id` and scroll it into view, then let it fade.
+fn highlight(ui: &AppWindow, event_id: &str) {
+    ui.set_messages_at_bottom(false);
+    // Set afresh so jumping to the same message again scrolls to it again.
+    ui.set_messages_jump_id(SharedString::new());
+    ui.set_messages_jump_id(SharedString::from(event_id));
+    let ui_handle = ui.as_weak();
+    let event_id = event_id.to_string();
+    slint::Timer::single_shot(JUMP_HIGHLIGHT, move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            if ui.get_messages_jump_id() == event_id.as_str() {
+                ui.set_messages_jump_id(SharedString::new());
+            }
+        }
+    });
+}
+
+/// Show message `event_id` of the open room, lit up for a moment. If it is
+/// not loaded, the messages around it are fetched and shown apart from the
+/// latest ones, to page through in both directions.
+pub fn jump_to(ui: &AppWindow, client: &ClientHandle, event_id: &str) {
+    let Some(room_id) = current_room() else {
+        return;
+    };
+    let Some((in_latest, in_jump)) = with_view(&room_id, |view| {
+        (
+            view.model.message(event_id).is_some(),
+            view.jump
+                .as_ref()
+                .is_some_and(|jump| jump.model.message(event_id).is_some()),
+        )
+    }) else {
+        return;
+    };
+    if in_jump {
+        highlight(ui, event_id);
+        return;
+    }
+    if in_latest {
+        jump_to_present(ui);
+        if let Some(model) = model(&room_id) {
+            model.reveal_to(event_id);
+        }
+        highlight(ui, event_id);
+        return;
+    }
+
+    let cancel = CancelToken::new();
+    VIEWS.with(|v| {
+        if let Some(jumping) = v.borrow_mut().jumping.replace(cancel.clone()) {
+            jumping.cancel();
+        }
+    });
+    let context = client.context(&room_id, event_id, cancel.clone());
+    let ui_handle = ui.as_weak();
+    let event_id = event_id.to_string();
+    tokio::spawn(async move {
+        let result = context.await;
+        slint::invoke_from_event_loop(move || {
+            if cancel.is_cancelled() {
+                return;
+            }
+            VIEWS.with(|v| v.borrow_mut().jumping = None);
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let page = match result {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::error!("Failed to load the context of {}: {}", event_id, e);
+                    toast::show(&ui, admin::error_text(&e, "failed-load-context"));
+                    return;
+                }
+            };
+            // Sync may have brought it in meanwhile.
+            let jumped = with_view(&room_id, |view| {
+                if view.model.message(&event_id).is_some() {
+                    return None;
+                }
+                view.drop_jump();
+                let model = Rc::new(TimelineModel::new(view.model.own_user_id(), page.messages));
+                model.set_bridged(view.model.bridged());
+                model.set_auto_translate(view.model.auto_translate());
+                model.set_encrypted(view.model.encrypted());
+                model.set_filter(view.model.filter());
+                view.jump = Some(Jump {
+                    model: model.clone(),
+                    prev_batch: page.prev_batch.clone(),
+                    next_batch: page.next_batch,
+                    paginating: None,
+                });
+                Some(model)
+            });
+            match jumped {
+                Some(Some(model)) => {
+                    ui.set_messages(ModelRc::from(model));
+                    ui.set_messages_detached(true);
+                    ui.set_messages_loading_older(false);
+                    ui.set_messages_loading_newer(false);
+                    ui.set_messages_at_start(page.prev_batch.is_none());
+                }
+                Some(None) => jump_to_present(&ui),
+                None => return,
+            }
+            highlight(&ui, &event_id);
+        })
+        .ok();
+    });
+}
+
+/// Fetch the page after the newest message jumped to and add it below.
+/// Once the pages reach the latest messages the two join, and the view
+/// carries on as the room's timeline without moving.
+pub fn load_newer(ui: &AppWindow, client: &ClientHandle) {
+    let Some(room_id) = current_room() else {
+        return;
+    };
+    let cancel = CancelToken::new();
+    let from = with_view(&room_id, |view| {
+        let jump = view.jump.as_mut()?;
+        if jump.paginating.is_some() {
+            return None;
+        }
+        let Some(from) = jump.next_batch.clone() else {
+            return Some(None);
+        };
+        jump.paginating = Some(cancel.clone());
+        Some(Some(from))
+    })
+    .flatten();
+    let from = match from {
+        Some(Some(from)) => from,
+        // Nothing newer to fetch: join up with what we have.
+        Some(None) => {
+            join_present(ui, &room_id);
+            return;
+        }
+        None => return,
+    };
+    ui.set_messages_loading_newer(true);
+
+    let history = client.newer_history(&room_id, from, cancel.clone());
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = history.await;
+        slint::invoke_from_event_loop(move || {
+            if cancel.is_cancelled() {
+                return;
+            }
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            ui.set_messages_loading_newer(false);
+            let page = match result {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::error!("Failed to load newer messages: {}", e);
+                    with_view(&room_id, |view| {
+                        if let Some(jump) = &mut view.jump {
+                            jump.paginating = None;
+                        }
+                    });
+                    return;
+                }
+            };
+            let Some(Some((latest, jumped))) = with_view(&room_id, |view| {
+                let jump = view.jump.as_mut()?;
+                jump.paginating = None;
+                jump.next_batch = page.next_batch.clone();
+                Some((view.model.clone(), jump.model.clone()))
+            }) else {
+                return;
+            };
+            let reached = latest.reaches(&page.messages, page.next_batch.as_deref());
+            for message in page.messages {
+                jumped.push_remote(message, None);
+            }
+            if reached {
+                join_present(&ui, &room_id);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Fold the messages jumped to into the latest ones they now reach, and
+/// show those in their place, scrolled where the view was.
+fn join_present(ui: &AppWindow, room_id: &str) {
+    let Some(Some((latest, jump))) = with_view(room_id, |view| {
+        let jump = view.jump.take()?;
+        if let Some(paginating) = view.paginating.take() {
+            paginating.cancel();
+        }
+        Some((view.model.clone(), jump))
+    }) else {
+        return;
+    };
+    if latest.join(&jump.model) {
+        let at_start = jump.prev_batch.is_none();
+        with_view(room_id, |view| {
+            view.prev_batch = jump.prev_batch;
+            view.at_start = at_start;
+        });
+    }
+
+    let viewport_y = ui.get_messages_viewport_y();
+    let at_start = with_view(room_id, |view| view.at_start()).unwrap_or(false);
+    ui.set_messages(ModelRc::from(latest));
+    ui.set_messages_detached(false);
+    ui.set_messages_loading_older(false);
+    ui.set_messages_loading_newer(false);
+    ui.set_messages_at_start(at_start);
+    // The rows above are the same ones, so the same offset shows them.
+    ui.set_messages_viewport_y(viewport_y);
+}
+
+/// Leave the messages jumped to for the latest ones of the open room.
+pub fn jump_to_present(ui: &AppWindow) {
+    let Some(room_id) = current_room() else {
+        return;
+    };
+    let Some(Some((model, at_start))) = with_view(&room_id, |view| {
+        view.jump.as_ref()?;
+        view.drop_jump();
+        Some((view.model.clone(), view.at_start()))
+    }) else {
+        return;
+    };
+    ui.set_messages(ModelRc::from(model));
+    ui.set_messages_detached(false);
+    ui.set_messages_loading_older(false);
+    ui.set_messages_loading_newer(false);
+    ui.set_messages_at_start(at_start);
+    ui.set_messages_at_bottom(true);
+}
+
+/// Forget every cached room, e.g. on logout.
+pub fn clear() {
+    VIEWS.with(|v| {
+        let mut views = v.borrow_mut();
+        if let Some(loading) = views.loading.take() {
+            loading.cancel();
+        }
+        if let Some(jumping) = views.jumping.take() {
+            jumping.cancel();
+        }
+        *views = Views::default();
+    });
+}
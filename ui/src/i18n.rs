@@ -0,0 +1,265 @@
+//! The language the interface is in. The markup's strings are translated by
+//! Slint from the bundled `lang/<code>/LC_MESSAGES/ui.po`; the ones made
+//! here come from the Fluent catalogs in `lang/<code>/ui.ftl`. Anything a
+//! language lacks is shown in English.
+
+use chat_core::time::{self, TimestampFormat};
+use chat_core::MembershipSummary;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use network::discovery::DiscoveryError;
+use std::sync::{OnceLock, RwLock};
+
+/// A language the interface comes in.
+pub struct Language {
+    /// As Slint and Fluent know it, e.g. "fr".
+    pub code: &'static str,
+    /// In itself, for the language picker.
+    pub name: &'static str,
+    catalog: &'static str,
+    timestamps: TimestampFormat,
+}
+
+/// English first: it is the default and what's missing elsewhere falls
+/// back to.
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        code: "en",
+        name: "English",
+        catalog: include_str!("../lang/en/ui.ftl"),
+        timestamps: TimestampFormat::ENGLISH,
+    },
+    Language {
+        code: "fr",
+        name: "Français",
+        catalog: include_str!("../lang/fr/ui.ftl"),
+        timestamps: TimestampFormat {
+            today: "Aujourd’hui à %H:%M",
+            yesterday: "Hier à %H:%M",
+            older: "%d/%m/%Y %H:%M",
+            time_of_day: "%H:%M",
+            day_today: "Aujourd’hui",
+            day_yesterday: "Hier",
+            // Day and month names would come out in English.
+            day_older: "%d/%m/%Y",
+        },
+    },
+];
+
+/// Index into [`LANGUAGES`] of the one in use.
+static CURRENT: RwLock<usize> = RwLock::new(0);
+static BUNDLES: OnceLock<Vec<FluentBundle<FluentResource>>> = OnceLock::new();
+
+fn bundle(language: &Language) -> FluentBundle<FluentResource> {
+    let id = language
+        .code
+        .parse()
+        .expect("language codes in LANGUAGES are valid");
+    let mut bundle = FluentBundle::new_concurrent(vec![id]);
+    // The text goes into labels, not running text needing bidi marks.
+    bundle.set_use_isolating(false);
+    let resource = FluentResource::try_new(language.catalog.to_string()).unwrap_or_else(
+        |(resource, errors)| {
+            tracing::error!("Errors in the {} catalog: {:?}", language.code, errors);
+            resource
+        },
+    );
+    if let Err(errors) = bundle.add_resource(resource) {
+        tracing::error!("Errors in the {} catalog: {:?}", language.code, errors);
+    }
+    bundle
+}
+
+fn bundles() -> &'static [FluentBundle<FluentResource>] {
+    BUNDLES.get_or_init(|| LANGUAGES.iter().map(bundle).collect())
+}
+
+/// Where `code`, e.g. from the config, is in [`LANGUAGES`].
+pub fn index_of(code: &str) -> Option<usize> {
+    LANGUAGES.iter().position(|language| language.code == code)
+}
+
+/// The language in use.
+pub fn current() -> &'static Language {
+    &LANGUAGES[*CURRENT.read().unwrap_or_else(|e| e.into_inner())]
+}
+
+/// Use the language at `index` for text made from now on, timestamps
+/// included. The markup is switched separately, by Slint.
+pub fn set(index: usize) {
+    let index = index.min(LANGUAGES.len() - 1);
+    *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = index;
+    time::set_format(LANGUAGES[index].timestamps);
+}
+
+/// The message `id` in the language at `index`, or in English when that
+/// lacks it. An unknown id comes back as is.
+fn format_in(index: usize, id: &str, args: Option<&FluentArgs>) -> String {
+    let bundles = bundles();
+    for bundle in [&bundles[index], &bundles[0]] {
+        let Some(pattern) = bundle.get_message(id).and_then(|m| m.value()) else {
+            continue;
+        };
+        let mut errors = Vec::new();
+        let text = bundle.format_pattern(pattern, args, &mut errors);
+        if !errors.is_empty() {
+            tracing::error!("Errors formatting {}: {:?}", id, errors);
+        }
+        return text.into_owned();
+    }
+    id.to_string()
+}
+
+fn current_index() -> usize {
+    *CURRENT.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The message `id` in the language in use.
+pub fn tr(id: &str) -> String {
+    format_in(current_index(), id, None)
+}
+
+/// The message `id` in the language in use, filled in from `args`.
+/// Numbers pick the plural form.
+pub fn tr_args<'a>(id: &str, args: impl IntoIterator<Item = (&'a str, FluentValue<'a>)>) -> String {
+    let args = args.into_iter().collect::<FluentArgs>();
+    format_in(current_index(), id, Some(&args))
+}
+
+/// Why a homeserver could not be reached, in the language in use.
+pub fn discovery_error(e: &DiscoveryError) -> String {
+    match e {
+        DiscoveryError::Dns => tr("error-dns"),
+        DiscoveryError::Tls => tr("error-tls"),
+        DiscoveryError::Timeout => tr("error-timeout"),
+        DiscoveryError::NotMatrix => tr("error-not-matrix"),
+        DiscoveryError::Unreachable(reason) => {
+            tr_args("error-unreachable", [("reason", reason.as_str().into())])
+        }
+    }
+}
+
+/// A run of membership changes, e.g. "3 people joined and 1 person left",
+/// in the language in use.
+pub fn membership_summary(summary: &MembershipSummary) -> String {
+    membership_summary_in(current_index(), summary)
+}
+
+fn membership_summary_in(index: usize, summary: &MembershipSummary) -> String {
+    let format = |id, args: &[(&str, FluentValue)]| {
+        let args = args.iter().cloned().collect::<FluentArgs>();
+        format_in(index, id, Some(&args))
+    };
+    let parts: Vec<String> = [
+        ("membership-joined", summary.joined),
+        ("membership-left", summary.left),
+        ("membership-invited", summary.invited),
+        ("membership-removed", summary.removed),
+    ]
+    .into_iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(id, count)| format(id, &[("count", count.into())]))
+    .collect();
+    match parts.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => {
+            let first = rest.join(&format_in(index, "list-separator", None));
+            format(
+                "list-and",
+                &[("first", first.into()), ("last", last.as_str().into())],
+            )
+        }
+        None => String::new(),
+    }
+}
+
+/// How to show `e`: translated when it is one the catalogs know, as is
+/// otherwise.
+pub fn error_text(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<DiscoveryError>() {
+        Some(e) => discovery_error(e),
+        None => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRENCH: usize = 1;
+
+    fn typing_many(index: usize, count: usize) -> String {
+        let args = [("count", FluentValue::from(count))]
+            .into_iter()
+            .collect::<FluentArgs>();
+        format_in(index, "typing-many", Some(&args))
+    }
+
+    #[test]
+    fn test_catalogs_translate_and_fall_back() {
+        assert_eq!(index_of("fr"), Some(FRENCH));
+        assert_eq!(index_of("tlh"), None);
+        assert_eq!(
+            format_in(0, "error-dns", None),
+            "No server found at this address"
+        );
+        assert_eq!(
+            format_in(FRENCH, "error-dns", None),
+            "Aucun serveur trouvé à cette adresse"
+        );
+        assert_eq!(
+            format_in(FRENCH, "no-such-message", None),
+            "no-such-message"
+        );
+        assert_eq!(typing_many(0, 3), "3 people are typing…");
+        assert_eq!(typing_many(FRENCH, 3), "3 personnes écrivent…");
+    }
+
+    #[test]
+    fn test_membership_summary_picks_plurals() {
+        let summary = |joined, left, invited, removed| MembershipSummary {
+            joined,
+            left,
+            invited,
+            removed,
+        };
+        assert_eq!(
+            membership_summary_in(0, &summary(5, 0, 0, 0)),
+            "5 people joined"
+        );
+        assert_eq!(
+            membership_summary_in(0, &summary(1, 1, 0, 0)),
+            "1 person joined and 1 person left"
+        );
+        assert_eq!(
+            membership_summary_in(0, &summary(1, 0, 2, 1)),
+            "1 person joined, 2 people were invited and 1 person was removed"
+        );
+        assert_eq!(
+            membership_summary_in(FRENCH, &summary(3, 0, 1, 0)),
+            "3 personnes ont rejoint et 1 personne a été invitée"
+        );
+    }
+
+    #[test]
+    fn test_every_language_has_every_message() {
+        let ids: Vec<&str> = LANGUAGES[0]
+            .catalog
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_lowercase()))
+            .filter_map(|line| line.split_once(" ="))
+            .map(|(id, _)| id)
+            .collect();
+        assert!(ids.contains(&"typing-many"));
+        for (index, language) in LANGUAGES.iter().enumerate().skip(1) {
+            for id in &ids {
+                assert!(
+                    bundles()[index].has_message(id),
+                    "{} lacks {}",
+                    language.code,
+                    id
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Going Idle by itself: the time since the last keyboard or mouse input
+//! anywhere on the system, not just in our window, is checked every
+//! `POLL_INTERVAL`. After the minutes set in the settings the shown account
+//! goes from Online to Idle, and back to Online on the next input.
+
+use crate::app_state::ClientHandle;
+use crate::{profile, AppWindow};
+use chat_core::idle::AutoAway;
+use chat_core::UserStatus;
+use network::cancel::CancelToken;
+use network::config::IdleConfig;
+use slint::ComponentHandle;
+use std::cell::RefCell;
+use std::time::Duration;
+use user_idle::UserIdle;
+
+/// How often the idle time is checked, and so how late going away or
+/// coming back can be.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Default)]
+struct Idle {
+    /// Ends the poll loop; set while signed in with a threshold.
+    polling: Option<CancelToken>,
+    away: AutoAway,
+    threshold: Duration,
+    mute_sounds: bool,
+}
+
+thread_local! {
+    static IDLE: RefCell<Idle> = RefCell::new(Idle::default());
+}
+
+/// Start watching for input as an account signs in, or again with new
+/// settings.
+pub fn start(ui: &AppWindow, client: &ClientHandle, config: &IdleConfig) {
+    let threshold = Duration::from_secs(u64::from(config.after_minutes) * 60);
+    let token = (!threshold.is_zero()).then(CancelToken::new);
+    IDLE.with(|i| {
+        let mut state = i.borrow_mut();
+        if let Some(old) = std::mem::replace(&mut state.polling, token.clone()) {
+            old.cancel();
+        }
+        state.threshold = threshold;
+        state.mute_sounds = config.mute_sounds;
+    });
+    let Some(token) = token else {
+        return;
+    };
+
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        while token.run(interval.tick()).await.is_ok() {
+            let idle = match tokio::task::spawn_blocking(UserIdle::get_time).await {
+                Ok(Ok(idle)) => Duration::from_secs(idle.as_seconds()),
+                Ok(Err(e)) => {
+                    tracing::warn!("Failed to read the idle time: {:?}", e);
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to read the idle time: {}", e);
+                    return;
+                }
+            };
+            if token.is_cancelled() {
+                break;
+            }
+            let ui_handle = ui_handle.clone();
+            let client = client.clone();
+            slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    check(&ui, &client, idle);
+                }
+            })
+            .ok();
+        }
+    });
+}
+
+/// Stop watching, e.g. as the account signs out.
+pub fn stop() {
+    IDLE.with(|i| {
+        let mut state = i.borrow_mut();
+        if let Some(token) = state.polling.take() {
+            token.cancel();
+        }
+        state.away.reset();
+    });
+}
+
+/// Input has been idle for `idle`: go away or come back if it is time.
+fn check(ui: &AppWindow, client: &ClientHandle, idle: Duration) {
+    let Some(current) = UserStatus::from_label(&ui.get_current_profile().status) else {
+        return;
+    };
+    let change = IDLE.with(|i| {
+        let mut state = i.borrow_mut();
+        let threshold = state.threshold;
+        state
+            .polling
+            .is_some()
+            .then(|| state.away.update(current, idle, threshold))
+            .flatten()
+    });
+    if let Some(status) = change {
+        profile::set_status(ui, client, status);
+    }
+}
+
+/// Whether notifications should stay silent: away by ourselves, with
+/// sounds muted for that in the settings.
+pub fn mute_sounds() -> bool {
+    IDLE.with(|i| {
+        let state = i.borrow();
+        state.mute_sounds && state.away.is_away()
+    })
+}
+
+/// The settings were saved: apply `config` to the account shown.
+pub fn save(ui: &AppWindow, client: &ClientHandle, config: &IdleConfig) {
+    if !ui.get_logged_in() {
+        return;
+    }
+    start(ui, client, config);
+    // Turned off while away: come back now.
+    let was_away = IDLE.with(|i| {
+        let mut state = i.borrow_mut();
+        let was_away = state.polling.is_none() && state.away.is_away();
+        if was_away {
+            state.away.reset();
+        }
+        was_away
+    });
+    if was_away {
+        profile::set_status(ui, client, UserStatus::Online);
+    }
+}
@@ -0,0 +1,353 @@
+//! Images made ready to upload: turned upright, scaled down and encoded
+//! again to spare bandwidth, and never carrying where they were taken. The
+//! original can still be sent, minus its location.
+
+use anyhow::Result;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+use std::path::Path;
+
+/// The Exif tag pointing at the GPS directory.
+const GPS_IFD_TAG: u16 = 0x8825;
+
+/// How images are compressed, from the settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compression {
+    /// Longest side, in pixels.
+    pub max_dimension: u32,
+    /// JPEG quality, from 1 to 100.
+    pub quality: u8,
+}
+
+/// An image encoded again, to send instead of the original.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compressed {
+    /// The original name with the new format's extension.
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Still images we can encode again; animations would lose their frames.
+fn compressible(data: &[u8]) -> bool {
+    match image::guess_format(data) {
+        Ok(ImageFormat::Jpeg) => true,
+        Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(data))
+            .and_then(|decoder| decoder.is_apng())
+            .is_ok_and(|apng| !apng),
+        Ok(ImageFormat::WebP) => {
+            WebPDecoder::new(Cursor::new(data)).is_ok_and(|decoder| !decoder.has_animation())
+        }
+        _ => false,
+    }
+}
+
+fn is_transparent(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel[3] < 255)
+}
+
+/// Image `data`, named `name`, upright and no larger than `compression`
+/// allows, as a JPEG or, to keep its transparency, a lossless WebP (the
+/// only kind the image crate writes). Encoding drops every bit of
+/// metadata, location included. `None` for animations, and for images
+/// that already fit and come out no smaller.
+pub fn compress(name: &str, data: &[u8], compression: Compression) -> Result<Option<Compressed>> {
+    if !compressible(data) {
+        return Ok(None);
+    }
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let max = compression.max_dimension.max(1);
+    let scaled = image.width() > max || image.height() > max;
+    if scaled {
+        image = image.resize(max, max, FilterType::Lanczos3);
+    }
+
+    let mut encoded = Vec::new();
+    let extension = if is_transparent(&image) {
+        image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut encoded))?;
+        "webp"
+    } else {
+        let quality = compression.quality.clamp(1, 100);
+        image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))?;
+        "jpg"
+    };
+    if !scaled && encoded.len() >= data.len() {
+        return Ok(None);
+    }
+    Ok(Some(Compressed {
+        name: Path::new(name)
+            .with_extension(extension)
+            .to_string_lossy()
+            .into_owned(),
+        data: encoded,
+    }))
+}
+
+/// `data` without where it was taken, for sending an original: a JPEG's
+/// GPS tags are blanked and its XMP dropped, leaving the rest of its Exif
+/// data, orientation included. Other formats are returned as they are.
+pub fn strip_location(data: &[u8]) -> Vec<u8> {
+    strip_jpeg_location(data).unwrap_or_else(|| data.to_vec())
+}
+
+/// `None` when `data` is not a JPEG we can read.
+fn strip_jpeg_location(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // Fill bytes before a marker.
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a segment.
+            0x01 | 0xD0..=0xD7 => {
+                stripped.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            // Start of scan or end of image: no metadata follows.
+            0xDA | 0xD9 => {
+                stripped.extend_from_slice(&data[pos..]);
+                return Some(stripped);
+            }
+            _ => {}
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if length < 2 {
+            return None;
+        }
+        let end = pos + 2 + length;
+        let segment = data.get(pos..end)?;
+        let payload = &segment[4..];
+        if marker == 0xE1 && payload.starts_with(b"http://ns.adobe.com/") {
+            // XMP, which may repeat the location.
+        } else if marker == 0xE1 && payload.starts_with(b"Exif\0\0") {
+            let mut segment = segment.to_vec();
+            // Exif data we can't make sense of is dropped whole.
+            if clear_gps(&mut segment[10..]).is_some() {
+                stripped.extend_from_slice(&segment);
+            }
+        } else {
+            stripped.extend_from_slice(segment);
+        }
+        pos = end;
+    }
+}
+
+/// Size of one value of an Exif field type.
+fn type_size(field_type: u16) -> usize {
+    match field_type {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+/// Blank the GPS directory of Exif data `tiff`, values and all, leaving it
+/// empty. `None` when `tiff` is malformed.
+fn clear_gps(tiff: &mut [u8]) -> Option<()> {
+    let big_endian = match tiff.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let u16_at = |tiff: &[u8], at: usize| -> Option<u16> {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |tiff: &[u8], at: usize| -> Option<usize> {
+        let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        } as usize)
+    };
+
+    let ifd0 = u32_at(tiff, 4)?;
+    let count = u16_at(tiff, ifd0)? as usize;
+    let pointer = (0..count)
+        .map(|i| ifd0 + 2 + i * 12)
+        .find(|&entry| u16_at(tiff, entry) == Some(GPS_IFD_TAG));
+    let Some(pointer) = pointer else {
+        return Some(());
+    };
+    let gps = u32_at(tiff, pointer + 8)?;
+    let gps_count = u16_at(tiff, gps)? as usize;
+    let entries_end = gps + 2 + gps_count * 12;
+    if entries_end > tiff.len() {
+        return None;
+    }
+    for i in 0..gps_count {
+        let entry = gps + 2 + i * 12;
+        let size = type_size(u16_at(tiff, entry + 2)?).checked_mul(u32_at(tiff, entry + 4)?)?;
+        // Values of up to four bytes are in the entry itself.
+        if size > 4 {
+            let offset = u32_at(tiff, entry + 8)?;
+            tiff.get_mut(offset..offset.checked_add(size)?)?.fill(0);
+        }
+    }
+    // No entries, and no directory after it.
+    let end = (entries_end + 4).min(tiff.len());
+    tiff[gps..end].fill(0);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::metadata::Orientation;
+    use image::{ImageEncoder, RgbImage, RgbaImage};
+
+    /// A latitude no JPEG data would contain by chance.
+    const LATITUDE: [u8; 4] = [0x78, 0x56, 0x34, 0x12];
+
+    /// An APP1 Exif segment with `orientation` and a GPS latitude.
+    fn exif_segment(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        // IFD0: orientation, and the GPS directory at 38.
+        tiff.extend(2u16.to_le_bytes());
+        for (tag, field_type, value) in
+            [(0x0112u16, 3u16, orientation as u32), (GPS_IFD_TAG, 4, 38)]
+        {
+            tiff.extend(tag.to_le_bytes());
+            tiff.extend(field_type.to_le_bytes());
+            tiff.extend(1u32.to_le_bytes());
+            tiff.extend(value.to_le_bytes());
+        }
+        tiff.extend(0u32.to_le_bytes());
+        // GPS: the latitude, three rationals at 56.
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(5u16.to_le_bytes());
+        tiff.extend(3u32.to_le_bytes());
+        tiff.extend(56u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        for _ in 0..3 {
+            tiff.extend(LATITUDE);
+            tiff.extend(1u32.to_le_bytes());
+        }
+
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend(((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        segment.extend(b"Exif\0\0");
+        segment.extend(tiff);
+        segment
+    }
+
+    fn jpeg(width: u32, height: u32, segments: &[Vec<u8>]) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, 128]));
+        let mut data = Vec::new();
+        image
+            .write_with_encoder(JpegEncoder::new(&mut data))
+            .unwrap();
+        for segment in segments.iter().rev() {
+            data.splice(2..2, segment.iter().copied());
+        }
+        data
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_originals_lose_their_location_only() {
+        let mut xmp = vec![0xFF, 0xE1, 0, 40];
+        xmp.extend(b"http://ns.adobe.com/xap/1.0/\0");
+        xmp.resize(42, b' ');
+        let original = jpeg(8, 8, &[exif_segment(6), xmp]);
+        assert!(contains(&original, &LATITUDE));
+
+        let stripped = strip_location(&original);
+        assert!(!contains(&stripped, &LATITUDE));
+        assert!(!contains(&stripped, b"ns.adobe.com"));
+        assert_eq!(stripped.len(), original.len() - 42);
+        let mut decoder = ImageReader::new(Cursor::new(&stripped))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap();
+        assert_eq!(decoder.orientation().unwrap(), Orientation::Rotate90);
+
+        let png = b"\x89PNG\r\n\x1a\n".to_vec();
+        assert_eq!(strip_location(&png), png);
+    }
+
+    #[test]
+    fn test_malformed_exif_is_dropped() {
+        let mut exif = exif_segment(1);
+        // The GPS directory points past the end.
+        exif[10 + 30..10 + 34].copy_from_slice(&1000u32.to_le_bytes());
+        let original = jpeg(8, 8, &[exif]);
+        let stripped = strip_location(&original);
+        assert!(!contains(&stripped, b"Exif"));
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn test_compressed_images_are_upright_and_scaled() {
+        let compression = Compression {
+            max_dimension: 16,
+            quality: 80,
+        };
+        let photo = jpeg(64, 32, &[exif_segment(6)]);
+        let compressed = compress("photo.jpeg", &photo, compression)
+            .unwrap()
+            .unwrap();
+        assert_eq!(compressed.name, "photo.jpg");
+        assert!(!contains(&compressed.data, b"Exif"));
+        let image = image::load_from_memory(&compressed.data).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 16));
+
+        let mut png = Vec::new();
+        let transparent = RgbaImage::from_pixel(32, 32, image::Rgba([255, 0, 0, 100]));
+        image::codecs::png::PngEncoder::new(&mut png)
+            .write_image(&transparent, 32, 32, image::ExtendedColorType::Rgba8)
+            .unwrap();
+        let compressed = compress("shot.png", &png, compression).unwrap().unwrap();
+        assert_eq!(compressed.name, "shot.webp");
+        assert_eq!(
+            image::guess_format(&compressed.data).unwrap(),
+            ImageFormat::WebP
+        );
+
+        // Already small, and bigger at a higher quality.
+        let finest = Compression {
+            max_dimension: 2048,
+            quality: 100,
+        };
+        assert_eq!(compress("a.jpg", &jpeg(8, 8, &[]), finest).unwrap(), None);
+        assert_eq!(compress("a.gif", b"GIF89a", compression).unwrap(), None);
+    }
+}
@@ -0,0 +1,206 @@
+//! Calls ringing us: the banner saying who is calling, the ringtone, and
+//! answering by joining the room's voice channel. A call left to ring out
+//! is hung up as missed, which puts it in the room's timeline.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{profile, voice_channel, AppWindow, IncomingCallData};
+use chat_core::time;
+use network::events::CallInvite;
+use network::signaling::HangupReason;
+use network::voice::{Cue, VoiceManager};
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::Duration;
+
+struct Ringing {
+    room_id: String,
+    call_id: String,
+    caller: String,
+    /// Fires when the call rings out.
+    _expiry: slint::Timer,
+}
+
+#[derive(Default)]
+struct Calls {
+    voice: Option<Arc<VoiceManager>>,
+    ringing: Option<Ringing>,
+}
+
+thread_local! {
+    static CALLS: RefCell<Calls> = RefCell::new(Calls::default());
+}
+
+/// Ring through `voice` from now on.
+pub fn start(voice: Arc<VoiceManager>) {
+    CALLS.with(|c| c.borrow_mut().voice = Some(voice));
+}
+
+/// Stop ringing and hide the banner, returning the call that rang.
+fn stop(ui: &AppWindow) -> Option<Ringing> {
+    let (voice, ringing) = CALLS.with(|c| {
+        let mut calls = c.borrow_mut();
+        (calls.voice.clone(), calls.ringing.take())
+    });
+    if ringing.is_some() {
+        if let Some(voice) = voice {
+            voice.stop_cue();
+        }
+    }
+    ui.set_show_incoming_call(false);
+    ringing
+}
+
+fn hang_up(client: &ClientHandle, call: &Ringing, reason: HangupReason) {
+    let reply = client.hangup_call(&call.room_id, &call.call_id, reason);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to hang up: {}", e);
+        }
+    });
+}
+
+/// Ring for `invite`. One call rings at a time; others are left to ring
+/// out.
+pub fn incoming(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    invite: CallInvite,
+) {
+    if CALLS.with(|c| c.borrow().ringing.is_some()) {
+        return;
+    }
+    let rings_for = Duration::from_millis(invite.expires_ts.saturating_sub(time::now_ms()));
+    let expiry = slint::Timer::default();
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    expiry.start(slint::TimerMode::SingleShot, rings_for, move || {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        if let Some(call) = stop(&ui) {
+            hang_up(&client_clone, &call, HangupReason::Missed);
+        }
+    });
+    let voice = CALLS.with(|c| {
+        let mut calls = c.borrow_mut();
+        calls.ringing = Some(Ringing {
+            room_id: invite.room_id.clone(),
+            call_id: invite.call_id.clone(),
+            caller: invite.caller.clone(),
+            _expiry: expiry,
+        });
+        calls.voice.clone()
+    });
+    if let Some(voice) = voice {
+        voice.play_cue(Cue::Ringtone);
+    }
+
+    let room_name = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(&invite.room_id).map(|room| room.name.clone()))
+        .unwrap_or_default();
+    ui.set_incoming_call(IncomingCallData {
+        call_id: SharedString::from(invite.call_id.as_str()),
+        name: SharedString::from(invite.caller_name()),
+        room: SharedString::from(room_name),
+        avatar: Default::default(),
+        busy_channel: SharedString::from(voice_channel::joined_name().unwrap_or_default()),
+    });
+    ui.set_show_incoming_call(true);
+
+    let reply = client.get_member_avatar(&invite.room_id, &invite.caller);
+    let call_id = invite.call_id;
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let data = match reply.await {
+            Ok(Some(data)) => data,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!("Failed to load avatar: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let mut call = ui.get_incoming_call();
+            if call.call_id.as_str() != call_id {
+                return;
+            }
+            call.avatar = profile::decode_avatar(&data).unwrap_or_default();
+            ui.set_incoming_call(call);
+        })
+        .ok();
+    });
+}
+
+/// Answer: open the call's room and join its voice channel, leaving any
+/// other.
+pub fn accept(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar) {
+    let Some(call) = stop(ui) else {
+        return;
+    };
+    let reply = client.answer_call(&call.room_id, &call.call_id);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to answer call: {}", e);
+        }
+    });
+    rooms::reveal_room(ui, client, sidebar, &call.room_id);
+    voice_channel::join_call(ui, client, &call.room_id);
+}
+
+pub fn decline(ui: &AppWindow, client: &ClientHandle) {
+    if let Some(call) = stop(ui) {
+        hang_up(client, &call, HangupReason::Declined);
+    }
+}
+
+fn is_ringing(call_id: &str) -> bool {
+    CALLS.with(|c| {
+        c.borrow()
+            .ringing
+            .as_ref()
+            .is_some_and(|r| r.call_id == call_id)
+    })
+}
+
+/// `user_id` answered call `call_id`; if that was us on another device, it
+/// stops ringing here.
+pub fn answered(ui: &AppWindow, call_id: &str, user_id: &str) {
+    if is_ringing(call_id) && user_id == ui.get_current_user_id().as_str() {
+        stop(ui);
+    }
+}
+
+/// `user_id` hung up call `call_id`. The caller giving up while it rings
+/// means we missed it; us hanging up on another device just stops it.
+pub fn hung_up(ui: &AppWindow, client: &ClientHandle, call_id: &str, user_id: &str) {
+    if !is_ringing(call_id) {
+        return;
+    }
+    let by_caller = CALLS.with(|c| {
+        c.borrow()
+            .ringing
+            .as_ref()
+            .is_some_and(|r| r.caller == user_id)
+    });
+    if by_caller {
+        if let Some(call) = stop(ui) {
+            hang_up(client, &call, HangupReason::Missed);
+        }
+    } else if user_id == ui.get_current_user_id().as_str() {
+        stop(ui);
+    }
+}
+
+/// Stop ringing on logout.
+pub fn clear(ui: &AppWindow) {
+    stop(ui);
+}
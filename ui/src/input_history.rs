@@ -0,0 +1,159 @@
+//! Up and Down in the composer bring back the messages we sent to the open
+//! room, newest first; Escape puts back what was typed before. What we sent
+//! is kept per account across launches, and forgotten on logout. Under
+//! `Retention::SessionOnly` it is kept in memory only.
+
+use crate::{composer, history, AppWindow};
+use chat_core::input_history::{InputHistory, Recall};
+use network::composer::ComposerStore;
+use network::config::Retention;
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{Key, NamedKey};
+use std::cell::RefCell;
+
+#[derive(Default)]
+struct Inputs {
+    user_id: String,
+    sent: InputHistory,
+    /// The walk in progress, with the room it is through.
+    recall: Option<(String, Recall)>,
+    /// Whether what is sent is saved for the next launch.
+    on_disk: bool,
+}
+
+thread_local! {
+    static INPUTS: RefCell<Inputs> = RefCell::new(Inputs::default());
+}
+
+/// Bring back what `user_id` sent before, as they sign in.
+pub fn load(user_id: &str, retention: Retention) {
+    let sent = ComposerStore::load(user_id);
+    INPUTS.with(|i| {
+        *i.borrow_mut() = Inputs {
+            user_id: user_id.to_string(),
+            sent,
+            recall: None,
+            on_disk: true,
+        }
+    });
+    set_retention(retention);
+}
+
+/// The retention setting changed. Going to `SessionOnly` deletes what was
+/// saved, but what is in memory stays until sign-out.
+pub fn set_retention(retention: Retention) {
+    let user_id = INPUTS.with(|i| {
+        let mut inputs = i.borrow_mut();
+        inputs.on_disk = retention.on_disk();
+        inputs.user_id.clone()
+    });
+    if !retention.on_disk() && !user_id.is_empty() {
+        forget(&user_id);
+    }
+}
+
+/// The local history was cleared: forget what was sent this session too.
+/// A walk in progress keeps the draft it started from.
+pub fn clear_sent() {
+    INPUTS.with(|i| i.borrow_mut().sent = InputHistory::default());
+}
+
+/// We sent `body` to `room_id`.
+pub fn sent(room_id: &str, body: &str) {
+    let saved = INPUTS.with(|i| {
+        let mut inputs = i.borrow_mut();
+        inputs.recall = None;
+        inputs.sent.push(room_id, body);
+        (inputs.on_disk && !inputs.user_id.is_empty())
+            .then(|| (inputs.user_id.clone(), inputs.sent.clone()))
+    });
+    if let Some((user_id, sent)) = saved {
+        if let Err(e) = ComposerStore::save(&user_id, &sent) {
+            tracing::warn!("Failed to save sent messages: {:#}", e);
+        }
+    }
+}
+
+/// The user typed: what is in the composer is theirs now.
+pub fn edited() {
+    INPUTS.with(|i| i.borrow_mut().recall = None);
+}
+
+/// Put the account away, keeping what it sent for when it is back.
+pub fn clear() {
+    INPUTS.with(|i| *i.borrow_mut() = Inputs::default());
+}
+
+/// `user_id` logged out: forget what they sent.
+pub fn forget(user_id: &str) {
+    if let Err(e) = ComposerStore::delete(user_id) {
+        tracing::warn!("Failed to forget sent messages: {:#}", e);
+    }
+}
+
+fn show(ui: &AppWindow, text: String) {
+    let caret = text.len();
+    composer::set_text(ui, text, caret);
+}
+
+/// Up from an empty composer, or with the caret at its start, walks back;
+/// Down walks forward and Escape stops. Returns true when the key was used.
+pub fn key_pressed(ui: &AppWindow, event: &KeyEvent) -> bool {
+    if event.state != ElementState::Pressed
+        || !ui.get_composer_focused()
+        || !ui.get_composer_edit_id().is_empty()
+    {
+        return false;
+    }
+    let Some(room_id) = history::current_room() else {
+        return false;
+    };
+    let shown = INPUTS.with(|i| {
+        let mut inputs = i.borrow_mut();
+        let Inputs { sent, recall, .. } = &mut *inputs;
+        // A walk through a room left since is over.
+        if recall.as_ref().is_some_and(|(room, _)| *room != room_id) {
+            *recall = None;
+        }
+        let sent = sent.sent(&room_id);
+        match (&event.logical_key, recall.as_mut()) {
+            (Key::Named(NamedKey::ArrowUp), Some((_, walk))) => {
+                // At the oldest already: stay there.
+                Some(walk.older(sent).map(str::to_owned))
+            }
+            (Key::Named(NamedKey::ArrowUp), None) => {
+                let text = ui.get_composer_text();
+                if !text.is_empty() && ui.get_composer_caret() != 0 {
+                    return None;
+                }
+                let mut walk = Recall::start(&text);
+                let older = walk.older(sent)?.to_string();
+                *recall = Some((room_id.clone(), walk));
+                Some(Some(older))
+            }
+            (Key::Named(NamedKey::ArrowDown), Some((_, walk))) => {
+                let newer = walk.newer(sent).map(str::to_owned);
+                if newer.is_none() {
+                    let draft = walk.draft().to_string();
+                    *recall = None;
+                    return Some(Some(draft));
+                }
+                Some(newer)
+            }
+            (Key::Named(NamedKey::Escape), Some((_, walk))) => {
+                let draft = walk.draft().to_string();
+                *recall = None;
+                Some(Some(draft))
+            }
+            _ => None,
+        }
+    });
+    match shown {
+        Some(Some(text)) => {
+            show(ui, text);
+            true
+        }
+        Some(None) => true,
+        None => false,
+    }
+}
@@ -0,0 +1,165 @@
+//! Notification keywords of the signed-in account: messages from others
+//! that contain one notify as a mention does, with a chime if the keyword
+//! asks for it, and are tinted in the timeline. The settings' keyword list
+//! edits them, and saving mirrors them into the server's push rules.
+
+use crate::app_state::ClientHandle;
+use crate::{history, AppWindow, KeywordData};
+use chat_core::keywords::{self, Keyword};
+use network::config::{Config, ConfigManager};
+use network::voice::VoiceManager;
+use slint::{SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Keywords {
+    voice: Option<Arc<VoiceManager>>,
+    user_id: String,
+    keywords: Vec<Keyword>,
+    /// The settings dialog's copy, applied when it is saved.
+    draft: Vec<Keyword>,
+}
+
+thread_local! {
+    static KEYWORDS: RefCell<Keywords> = RefCell::new(Keywords::default());
+}
+
+/// Play keyword chimes through `voice` from now on.
+pub fn start(voice: Arc<VoiceManager>) {
+    KEYWORDS.with(|k| k.borrow_mut().voice = Some(voice));
+}
+
+/// Use the keywords `user_id` saved, as they sign in.
+pub fn load(config: &Config, user_id: &str) {
+    KEYWORDS.with(|k| {
+        let mut state = k.borrow_mut();
+        state.user_id = user_id.to_string();
+        state.keywords = config.account(user_id).keywords;
+    });
+}
+
+/// No keywords while signed out.
+pub fn clear() {
+    KEYWORDS.with(|k| {
+        let mut state = k.borrow_mut();
+        state.user_id.clear();
+        state.keywords.clear();
+    });
+}
+
+/// The keyword of `user_id` in `body`, if any. Accounts in the background
+/// have theirs read from the config.
+pub fn find(user_id: &str, body: &str) -> Option<Keyword> {
+    let shown = KEYWORDS.with(|k| {
+        let state = k.borrow();
+        (state.user_id == user_id).then(|| keywords::find(&state.keywords, body).cloned())
+    });
+    shown.unwrap_or_else(|| {
+        keywords::find(&ConfigManager::load().account(user_id).keywords, body).cloned()
+    })
+}
+
+/// Whether `body` has one of the shown account's keywords.
+pub fn hit(body: &str) -> bool {
+    KEYWORDS.with(|k| keywords::find(&k.borrow().keywords, body).is_some())
+}
+
+pub fn chime() {
+    if let Some(voice) = KEYWORDS.with(|k| k.borrow().voice.clone()) {
+        voice.play_chime();
+    }
+}
+
+/// The settings dialog opened: edit a copy of the keywords.
+pub fn open_settings(ui: &AppWindow) {
+    KEYWORDS.with(|k| {
+        let mut state = k.borrow_mut();
+        state.draft = state.keywords.clone();
+    });
+    render(ui);
+}
+
+fn render(ui: &AppWindow) {
+    let rows: Vec<KeywordData> = KEYWORDS.with(|k| {
+        k.borrow()
+            .draft
+            .iter()
+            .map(|keyword| KeywordData {
+                text: SharedString::from(keyword.text.as_str()),
+                sound: keyword.sound,
+            })
+            .collect()
+    });
+    ui.set_keywords(Rc::new(VecModel::from(rows)).into());
+}
+
+/// Add what was typed, unless it is blank or already listed.
+pub fn add(ui: &AppWindow, text: &str) {
+    let Some(keyword) = Keyword::new(text) else {
+        return;
+    };
+    KEYWORDS.with(|k| {
+        let draft = &mut k.borrow_mut().draft;
+        let known = draft
+            .iter()
+            .any(|k| k.text.to_lowercase() == keyword.text.to_lowercase());
+        if !known {
+            draft.push(keyword);
+        }
+    });
+    render(ui);
+}
+
+pub fn remove(ui: &AppWindow, row: i32) {
+    KEYWORDS.with(|k| {
+        let draft = &mut k.borrow_mut().draft;
+        if let Some(row) = usize::try_from(row).ok().filter(|&row| row < draft.len()) {
+            draft.remove(row);
+        }
+    });
+    render(ui);
+}
+
+pub fn toggle_sound(ui: &AppWindow, row: i32) {
+    KEYWORDS.with(|k| {
+        let draft = &mut k.borrow_mut().draft;
+        if let Some(keyword) = usize::try_from(row).ok().and_then(|row| draft.get_mut(row)) {
+            keyword.sound = !keyword.sound;
+        }
+    });
+    render(ui);
+}
+
+/// The settings were saved: keep the dialog's keywords for `user_id` in
+/// `config` and use them. The server's push rules are updated to match
+/// when the words changed; if that fails they still work in this app.
+pub fn save(config: &mut Config, user_id: &str, client: &ClientHandle) {
+    let (draft, changed) = KEYWORDS.with(|k| {
+        let mut state = k.borrow_mut();
+        let texts = |list: &[Keyword]| list.iter().map(|k| k.text.clone()).collect::<Vec<_>>();
+        let changed = texts(&state.draft) != texts(&state.keywords);
+        state.keywords = state.draft.clone();
+        (state.draft.clone(), changed)
+    });
+    config
+        .accounts
+        .entry(user_id.to_string())
+        .or_default()
+        .keywords = draft.clone();
+    history::redraw();
+    if changed {
+        update_push_rules(client, &draft);
+    }
+}
+
+/// Have the homeserver notify on `keywords`, e.g. on other devices.
+pub fn update_push_rules(client: &ClientHandle, keywords: &[Keyword]) {
+    let reply = client.set_keywords(keywords.iter().map(|k| k.text.clone()).collect());
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to update keyword push rules: {:#}", e);
+        }
+    });
+}
@@ -0,0 +1,152 @@
+//! Knocks: those on rooms we can let people into, and our own waiting for
+//! an answer, kept up with sync events.
+
+use network::config::PendingKnock;
+use network::events::{ChatEvent, KnockRequest};
+
+/// What a sync event meant for the knocks.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Unchanged,
+    Changed,
+    /// Our knock was let in with an invite: join the room.
+    LetIn(PendingKnock),
+    /// Our knock was turned away.
+    TurnedAway(PendingKnock),
+}
+
+#[derive(Debug, Default)]
+pub struct KnockList {
+    /// Knocks on rooms we can let people into, oldest first.
+    pub incoming: Vec<KnockRequest>,
+    /// Our own knocks.
+    pub pending: Vec<PendingKnock>,
+}
+
+impl KnockList {
+    pub fn new(pending: Vec<PendingKnock>) -> Self {
+        Self {
+            incoming: Vec::new(),
+            pending,
+        }
+    }
+
+    /// Knocks again replace the earlier one, e.g. with a new reason.
+    pub fn add_incoming(&mut self, request: KnockRequest) {
+        self.remove_incoming(&request.room_id, &request.user_id);
+        self.incoming.push(request);
+    }
+
+    pub fn remove_incoming(&mut self, room_id: &str, user_id: &str) -> bool {
+        let before = self.incoming.len();
+        self.incoming
+            .retain(|r| r.room_id != room_id || r.user_id != user_id);
+        self.incoming.len() != before
+    }
+
+    pub fn add_pending(&mut self, knock: PendingKnock) {
+        self.take_pending(&knock.room_id);
+        self.pending.push(knock);
+    }
+
+    pub fn take_pending(&mut self, room_id: &str) -> Option<PendingKnock> {
+        let index = self.pending.iter().position(|k| k.room_id == room_id)?;
+        Some(self.pending.remove(index))
+    }
+
+    pub fn apply(&mut self, event: &ChatEvent) -> Outcome {
+        match event {
+            ChatEvent::Knocked(request) => {
+                self.add_incoming(request.clone());
+                Outcome::Changed
+            }
+            ChatEvent::KnockResolved { room_id, user_id } => {
+                match self.remove_incoming(room_id, user_id) {
+                    true => Outcome::Changed,
+                    false => Outcome::Unchanged,
+                }
+            }
+            ChatEvent::Invited { room_id } => match self.take_pending(room_id) {
+                Some(knock) => Outcome::LetIn(knock),
+                None => Outcome::Unchanged,
+            },
+            // Let in from another device.
+            ChatEvent::RoomJoined(room) => match self.take_pending(&room.id) {
+                Some(_) => Outcome::Changed,
+                None => Outcome::Unchanged,
+            },
+            ChatEvent::RoomLeft { room_id } => {
+                let before = self.incoming.len();
+                self.incoming.retain(|r| r.room_id != *room_id);
+                match self.take_pending(room_id) {
+                    Some(knock) => Outcome::TurnedAway(knock),
+                    None if self.incoming.len() != before => Outcome::Changed,
+                    None => Outcome::Unchanged,
+                }
+            }
+            _ => Outcome::Unchanged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(room_id: &str, user_id: &str, reason: &str) -> KnockRequest {
+        KnockRequest {
+            room_id: room_id.to_string(),
+            room_name: "raids".to_string(),
+            user_id: user_id.to_string(),
+            display_name: None,
+            reason: Some(reason.to_string()),
+        }
+    }
+
+    fn pending(room_id: &str) -> PendingKnock {
+        PendingKnock {
+            room_id: room_id.to_string(),
+            address: "#lobby:x.org".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_incoming_knocks() {
+        let mut knocks = KnockList::default();
+        knocks.apply(&ChatEvent::Knocked(request("!raids", "@bob", "hi")));
+        knocks.apply(&ChatEvent::Knocked(request("!raids", "@bob", "hi again")));
+        knocks.apply(&ChatEvent::Knocked(request("!raids", "@eve", "let me in")));
+        assert_eq!(knocks.incoming.len(), 2);
+        assert_eq!(knocks.incoming[0].reason.as_deref(), Some("hi again"));
+
+        let resolved = ChatEvent::KnockResolved {
+            room_id: "!raids".to_string(),
+            user_id: "@eve".to_string(),
+        };
+        assert_eq!(knocks.apply(&resolved), Outcome::Changed);
+        assert_eq!(knocks.apply(&resolved), Outcome::Unchanged);
+
+        // Leaving the room leaves its knocks to others.
+        let left = ChatEvent::RoomLeft {
+            room_id: "!raids".to_string(),
+        };
+        assert_eq!(knocks.apply(&left), Outcome::Changed);
+        assert!(knocks.incoming.is_empty());
+    }
+
+    #[test]
+    fn test_our_knocks_are_answered() {
+        let mut knocks = KnockList::new(vec![pending("!lobby"), pending("!arena")]);
+        let invited = ChatEvent::Invited {
+            room_id: "!lobby".to_string(),
+        };
+        assert_eq!(knocks.apply(&invited), Outcome::LetIn(pending("!lobby")));
+        assert_eq!(knocks.apply(&invited), Outcome::Unchanged);
+
+        let left = ChatEvent::RoomLeft {
+            room_id: "!arena".to_string(),
+        };
+        assert_eq!(knocks.apply(&left), Outcome::TurnedAway(pending("!arena")));
+        assert!(knocks.pending.is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+//! Knocks in the channel list: requests to join rooms we can let people
+//! into, each with its reason and buttons to let them in or turn them away,
+//! and our own knocks waiting for an answer, which can be taken back. Our
+//! knocks are kept for each account, since sync doesn't list them. Being
+//! let in joins the room.
+
+use crate::app_state::ClientHandle;
+use crate::knock_list::{KnockList, Outcome};
+use crate::{admin, toast, AppWindow, KnockPromptData, KnockRequestData, PendingKnockData};
+use network::config::{Config, ConfigManager, PendingKnock};
+use network::events::ChatEvent;
+use network::knock::KnockError;
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Knocks {
+    user_id: String,
+    list: KnockList,
+    /// The room the knock prompt is open for, and the servers to ask
+    /// through.
+    prompt: Option<(String, Vec<String>)>,
+}
+
+thread_local! {
+    static KNOCKS: RefCell<Knocks> = RefCell::new(Knocks::default());
+}
+
+fn render(ui: &AppWindow) {
+    let (incoming, pending) = KNOCKS.with(|k| {
+        let state = k.borrow();
+        let incoming: Vec<KnockRequestData> = state
+            .list
+            .incoming
+            .iter()
+            .map(|request| KnockRequestData {
+                room_name: SharedString::from(request.room_name.as_str()),
+                name: SharedString::from(request.name()),
+                reason: SharedString::from(request.reason.as_deref().unwrap_or_default()),
+            })
+            .collect();
+        let pending: Vec<PendingKnockData> = state
+            .list
+            .pending
+            .iter()
+            .map(|knock| PendingKnockData {
+                address: SharedString::from(knock.address.as_str()),
+            })
+            .collect();
+        (incoming, pending)
+    });
+    ui.set_knock_requests(Rc::new(VecModel::from(incoming)).into());
+    ui.set_pending_knocks(Rc::new(VecModel::from(pending)).into());
+}
+
+fn save() {
+    let (user_id, pending) = KNOCKS.with(|k| {
+        let state = k.borrow();
+        (state.user_id.clone(), state.list.pending.clone())
+    });
+    let mut config = ConfigManager::load();
+    config.accounts.entry(user_id).or_default().knocks = pending;
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the pending knocks: {:#}", e);
+    }
+}
+
+/// Use the knocks `user_id` made, as they sign in.
+pub fn load(ui: &AppWindow, config: &Config, user_id: &str) {
+    KNOCKS.with(|k| {
+        *k.borrow_mut() = Knocks {
+            user_id: user_id.to_string(),
+            list: KnockList::new(config.account(user_id).knocks),
+            prompt: None,
+        }
+    });
+    render(ui);
+}
+
+/// Forget the account's knocks and close the prompt, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    ui.set_show_knock_prompt(false);
+    KNOCKS.with(|k| *k.borrow_mut() = Knocks::default());
+    render(ui);
+}
+
+/// List the knocks waiting in rooms we can let people into, once the rooms
+/// are loaded. Later ones come with sync.
+pub fn load_requests(ui: &AppWindow, client: &ClientHandle) {
+    let reply = client.knock_requests();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(requests) => {
+                    KNOCKS.with(|k| {
+                        let mut state = k.borrow_mut();
+                        for request in requests {
+                            state.list.add_incoming(request);
+                        }
+                    });
+                    render(&ui);
+                }
+                Err(e) => tracing::warn!("Failed to load requests to join: {}", e),
+            }
+        })
+        .ok();
+    });
+}
+
+/// Keep the knocks up with a sync event. Being let in joins the room.
+pub fn room_event(ui: &AppWindow, client: &ClientHandle, event: &ChatEvent) {
+    let (outcome, ours) = KNOCKS.with(|k| {
+        let list = &mut k.borrow_mut().list;
+        let pending = list.pending.len();
+        (list.apply(event), list.pending.len() != pending)
+    });
+    match outcome {
+        Outcome::Unchanged => return,
+        Outcome::Changed => {}
+        Outcome::LetIn(knock) => {
+            toast::show(ui, format!("You were let into {}", knock.address));
+            let reply = client.join_room(&knock.room_id, Vec::new());
+            let ui_handle = ui.as_weak();
+            tokio::spawn(async move {
+                let Err(e) = reply.await else {
+                    return;
+                };
+                tracing::warn!("Failed to join {}: {}", knock.address, e);
+                slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        toast::show(&ui, admin::error_text(&e, "failed-join"));
+                    }
+                })
+                .ok();
+            });
+        }
+        Outcome::TurnedAway(knock) => {
+            toast::show(
+                ui,
+                format!("Your request to join {} was declined", knock.address),
+            );
+        }
+    }
+    if ours {
+        save();
+    }
+    render(ui);
+}
+
+/// Offer to knock on `id_or_alias`, e.g. when a link to it couldn't be
+/// joined.
+pub fn prompt(ui: &AppWindow, id_or_alias: &str, via: Vec<String>) {
+    KNOCKS.with(|k| k.borrow_mut().prompt = Some((id_or_alias.to_string(), via)));
+    ui.set_knock_prompt(KnockPromptData {
+        address: SharedString::from(id_or_alias),
+        sending: false,
+        error: SharedString::new(),
+    });
+    ui.set_show_knock_prompt(true);
+}
+
+/// Knock on the room the prompt is open for, with `reason` if one was
+/// given.
+pub fn submit(ui: &AppWindow, client: &ClientHandle, reason: &str) {
+    let Some((address, via)) = KNOCKS.with(|k| k.borrow().prompt.clone()) else {
+        return;
+    };
+    let reason = reason.trim();
+    let reply = client.knock_on_room(&address, via, (!reason.is_empty()).then_some(reason));
+    let mut data = ui.get_knock_prompt();
+    data.sending = true;
+    data.error = SharedString::new();
+    ui.set_knock_prompt(data);
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let open = ui.get_show_knock_prompt()
+                && KNOCKS.with(|k| {
+                    k.borrow()
+                        .prompt
+                        .as_ref()
+                        .is_some_and(|(a, _)| *a == address)
+                });
+            match result {
+                Ok(room_id) => {
+                    KNOCKS.with(|k| {
+                        k.borrow_mut().list.add_pending(PendingKnock {
+                            room_id,
+                            address: address.clone(),
+                        })
+                    });
+                    save();
+                    render(&ui);
+                    toast::show(&ui, format!("Asked to join {}", address));
+                    if open {
+                        ui.set_show_knock_prompt(false);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to knock on {}: {}", address, e);
+                    if !open {
+                        return;
+                    }
+                    let mut data = ui.get_knock_prompt();
+                    data.sending = false;
+                    data.error = match e.downcast_ref::<KnockError>() {
+                        Some(e) => SharedString::from(e.to_string()),
+                        None => admin::error_text(&e, "failed-knock"),
+                    };
+                    ui.set_knock_prompt(data);
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Let in, or turn away, the knock at `index` in the requests list.
+pub fn answer(ui: &AppWindow, client: &ClientHandle, index: i32, approve: bool) {
+    let Some(request) = KNOCKS.with(|k| k.borrow().list.incoming.get(index as usize).cloned())
+    else {
+        return;
+    };
+    let reply = client.answer_knock(&request.room_id, &request.user_id, approve);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                // Sync would say so too, a moment later.
+                Ok(()) => {
+                    KNOCKS.with(|k| {
+                        k.borrow_mut()
+                            .list
+                            .remove_incoming(&request.room_id, &request.user_id)
+                    });
+                    render(&ui);
+                }
+                Err(e) => {
+                    let failed = match approve {
+                        true => "failed-let-in",
+                        false => "failed-turn-away",
+                    };
+                    tracing::warn!("{}: {}", failed, e);
+                    toast::show(&ui, admin::error_text(&e, failed));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Take back our knock at `index` in the pending list.
+pub fn retract(ui: &AppWindow, client: &ClientHandle, index: i32) {
+    let Some(knock) = KNOCKS.with(|k| k.borrow().list.pending.get(index as usize).cloned()) else {
+        return;
+    };
+    let reply = client.retract_knock(&knock.room_id);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(()) => {
+                    KNOCKS.with(|k| k.borrow_mut().list.take_pending(&knock.room_id));
+                    save();
+                    render(&ui);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to take back the knock on {}: {}", knock.address, e);
+                    toast::show(&ui, admin::error_text(&e, "failed-cancel-knock"));
+                }
+            }
+        })
+        .ok();
+    });
+}
@@ -0,0 +1,188 @@
+//! Preview cards for the first link of a message, asked of the homeserver
+//! as their rows are shown so the linked site never sees us. Encrypted
+//! rooms get none unless the settings allow it, and a preview that fails
+//! to load is simply not shown.
+
+use crate::app_state::ClientHandle;
+use crate::attachments::{Downloads, Fetch};
+use crate::rooms::SharedSidebar;
+use crate::{attachment_view, history, LinkPreviewData, MediaState};
+use chat_core::link_preview::{self, LinkPreview};
+use chat_core::{Message, MessageType};
+use network::config::LinkPreviewConfig;
+use slint::{Image, SharedString};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+/// Largest edge of a card's image as decoded; it is shown smaller.
+const IMAGE_SIZE: u32 = 160;
+
+struct Card {
+    preview: LinkPreview,
+    image: Image,
+}
+
+#[derive(Default)]
+struct LinkPreviews {
+    config: LinkPreviewConfig,
+    /// By URL, so a link sent twice is asked for once. `None` when there
+    /// is nothing to show.
+    cards: Downloads<Option<Card>>,
+    /// Rows to redraw when a card arrives, by URL: (room id, event id).
+    waiting: HashMap<String, Vec<(String, String)>>,
+    /// Messages whose link is not previewed, as their room is encrypted.
+    skipped: HashSet<String>,
+}
+
+thread_local! {
+    static LINK_PREVIEWS: RefCell<LinkPreviews> = RefCell::new(LinkPreviews::default());
+}
+
+/// Use `config` from now on, redrawing the timelines if it changed.
+pub fn configure(config: &LinkPreviewConfig) {
+    let changed = LINK_PREVIEWS.with(|l| {
+        let mut state = l.borrow_mut();
+        let changed = state.config != *config;
+        state.config = config.clone();
+        if changed {
+            state.skipped.clear();
+        }
+        changed
+    });
+    if changed {
+        history::redraw();
+    }
+}
+
+/// The card of `message`, if it has a link to preview.
+pub fn preview_data(message: &Message) -> LinkPreviewData {
+    if message.schema != MessageType::Text {
+        return LinkPreviewData::default();
+    }
+    let Some(url) = link_preview::first_url(&message.content) else {
+        return LinkPreviewData::default();
+    };
+    LINK_PREVIEWS.with(|l| {
+        let state = l.borrow();
+        if !state.config.enabled || state.skipped.contains(&message.id) {
+            return LinkPreviewData::default();
+        }
+        let mut data = LinkPreviewData {
+            url: SharedString::from(url),
+            ..Default::default()
+        };
+        match state.cards.get(url) {
+            None => data.state = MediaState::Idle,
+            Some(Fetch::Loading) => data.state = MediaState::Loading,
+            Some(Fetch::Done(None) | Fetch::Failed) => data.state = MediaState::Failed,
+            Some(Fetch::Done(Some(card))) => {
+                let text =
+                    |text: &Option<String>| SharedString::from(text.as_deref().unwrap_or(""));
+                data.state = MediaState::Ready;
+                data.title = text(&card.preview.title);
+                data.description = text(&card.preview.description);
+                data.site_name = text(&card.preview.site_name);
+                data.image = card.image.clone();
+            }
+        }
+        data
+    })
+}
+
+fn refresh(room_id: &str, event_id: &str) {
+    history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+}
+
+/// Load the card of message `event_id`'s first link, unless it is loaded
+/// or loading already.
+pub fn load(client: &ClientHandle, sidebar: &SharedSidebar, event_id: &str) {
+    let Some(room_id) = history::room_of(event_id) else {
+        return;
+    };
+    let Some(message) =
+        history::with_message(&room_id, event_id, |timeline| timeline.message(event_id)).flatten()
+    else {
+        return;
+    };
+    let Some(url) = link_preview::first_url(&message.content).map(str::to_string) else {
+        return;
+    };
+    // A room we know nothing of yet is taken to be encrypted.
+    let encrypted = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(&room_id))
+        .is_none_or(|room| room.encrypted);
+
+    let started = LINK_PREVIEWS.with(|l| {
+        let mut state = l.borrow_mut();
+        if !state.config.enabled || (encrypted && !state.config.encrypted_rooms) {
+            state.skipped.insert(event_id.to_string());
+            return None;
+        }
+        if let Some(Fetch::Done(_)) = state.cards.get(&url) {
+            return Some(false);
+        }
+        state
+            .waiting
+            .entry(url.clone())
+            .or_default()
+            .push((room_id.clone(), event_id.to_string()));
+        Some(state.cards.start(&url))
+    });
+    match started {
+        None => {
+            refresh(&room_id, event_id);
+            return;
+        }
+        Some(false) => return,
+        Some(true) => refresh(&room_id, event_id),
+    }
+
+    let reply = client.get_url_preview(&url, message.timestamp);
+    let client = client.clone();
+    tokio::spawn(async move {
+        let preview = match reply.await {
+            Ok(preview) => preview,
+            Err(e) => {
+                tracing::warn!("No preview of {}: {}", url, e);
+                None
+            }
+        };
+        let image = match preview.as_ref().and_then(|p| p.image.as_ref()) {
+            Some(image) => {
+                let reply = client.download_thumbnail(image);
+                attachment_view::load_image(reply, IMAGE_SIZE, IMAGE_SIZE).await
+            }
+            None => None,
+        };
+        slint::invoke_from_event_loop(move || {
+            let card = preview.map(|preview| Card {
+                preview,
+                image: image
+                    .map(|(buffer, _)| Image::from_rgba8(buffer))
+                    .unwrap_or_default(),
+            });
+            let rows = LINK_PREVIEWS.with(|l| {
+                let mut state = l.borrow_mut();
+                state.cards.finish(&url, Some(card));
+                state.waiting.remove(&url).unwrap_or_default()
+            });
+            for (room_id, event_id) in rows {
+                refresh(&room_id, &event_id);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Drop every card, e.g. on logout.
+pub fn clear() {
+    LINK_PREVIEWS.with(|l| {
+        let mut state = l.borrow_mut();
+        state.cards.clear();
+        state.waiting.clear();
+        state.skipped.clear();
+    });
+}
@@ -0,0 +1,111 @@
+//! Classifying and opening URLs. Web links go to the system browser,
+//! matrix.to permalinks and `matrix:` URIs are handled in-app, and anything
+//! else needs the user's confirmation first.
+
+use anyhow::{Context, Result};
+use chat_core::permalink::{self, Permalink};
+
+const MATRIX_TO_PREFIXES: [&str; 2] = ["https://matrix.to/#/", "http://matrix.to/#/"];
+
+/// What opening a URL should do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Link {
+    /// http(s), opened in the browser.
+    Web(String),
+    /// A matrix.to permalink or `matrix:` URI, handled in-app.
+    Matrix(Permalink),
+    /// mailto: links are refused.
+    Mail,
+    /// Any other scheme; opened only once the user confirms.
+    Other(String),
+    Invalid,
+}
+
+pub fn classify(url: &str) -> Link {
+    let url = url.trim();
+    if let Some(link) = permalink::parse(url) {
+        return Link::Matrix(link);
+    }
+    // Ours, but broken.
+    if MATRIX_TO_PREFIXES
+        .iter()
+        .any(|prefix| url.starts_with(prefix))
+    {
+        return Link::Invalid;
+    }
+
+    let Some((scheme, _)) = url.split_once(':') else {
+        return Link::Invalid;
+    };
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if !valid_scheme {
+        return Link::Invalid;
+    }
+    match scheme.to_ascii_lowercase().as_str() {
+        "http" | "https" => Link::Web(url.to_string()),
+        "mailto" => Link::Mail,
+        "matrix" => Link::Invalid,
+        _ => Link::Other(url.to_string()),
+    }
+}
+
+/// Open `url` with the system's default handler, without waiting for it.
+pub fn open_external(url: &str) -> Result<()> {
+    open::that_detached(url).with_context(|| format!("Failed to open {}", url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_schemes() {
+        assert_eq!(
+            classify("https://example.org/a?b=c"),
+            Link::Web("https://example.org/a?b=c".to_string())
+        );
+        assert_eq!(
+            classify("HTTP://example.org"),
+            Link::Web("HTTP://example.org".to_string())
+        );
+        assert_eq!(classify("mailto:bob@example.org"), Link::Mail);
+        assert_eq!(
+            classify("steam://run/440"),
+            Link::Other("steam://run/440".to_string())
+        );
+        assert_eq!(classify("not a link"), Link::Invalid);
+        assert_eq!(classify("1http://x"), Link::Invalid);
+    }
+
+    #[test]
+    fn test_matrix_to_links() {
+        assert_eq!(
+            classify("https://matrix.to/#/%23lobby:example.org?via=example.org&via=other.org"),
+            Link::Matrix(Permalink::Room {
+                id_or_alias: "#lobby:example.org".to_string(),
+                via: vec!["example.org".to_string(), "other.org".to_string()],
+            })
+        );
+        assert_eq!(
+            classify("https://matrix.to/#/!abc:example.org/$event:example.org"),
+            Link::Matrix(Permalink::Event {
+                id_or_alias: "!abc:example.org".to_string(),
+                event_id: "$event:example.org".to_string(),
+                via: Vec::new(),
+            })
+        );
+        assert_eq!(
+            classify("https://matrix.to/#/@bob:example.org"),
+            Link::Matrix(Permalink::User("@bob:example.org".to_string()))
+        );
+        assert_eq!(
+            classify("matrix:u/bob:example.org?action=chat"),
+            Link::Matrix(Permalink::User("@bob:example.org".to_string()))
+        );
+        assert_eq!(classify("https://matrix.to/#/nonsense"), Link::Invalid);
+        assert_eq!(classify("matrix:nonsense"), Link::Invalid);
+    }
+}
@@ -0,0 +1,870 @@
+//! Local echo for outgoing messages. A sent message is shown at once as
+//! `Sending` and reconciled by transaction id, from either the send reply or
+//! its remote echo in sync, whichever arrives first.
+
+use chat_core::{time, MembershipSummary, Message, MessageType, Reaction, SenderTrust};
+use network::timeline::{
+    Direction, EventKind, TimelineBuffer, TimelineDiff, TimelineFilter, TimelineItem,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SendState {
+    /// Held back while the homeserver can't be reached.
+    Queued,
+    Sending,
+    Sent,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub message: Message,
+    pub state: SendState,
+    /// Set for messages sent from this session.
+    pub txn_id: Option<String>,
+}
+
+impl Entry {
+    fn delivered(message: Message) -> Self {
+        Self {
+            message,
+            state: SendState::Sent,
+            txn_id: None,
+        }
+    }
+}
+
+impl TimelineItem for Entry {
+    fn event_id(&self) -> &str {
+        &self.message.id
+    }
+
+    fn kind(&self) -> EventKind {
+        self.message.kind()
+    }
+
+    fn summarize(run: &[Self], summary: MembershipSummary) -> Self {
+        let messages: Vec<Message> = run.iter().map(|e| e.message.clone()).collect();
+        Self::delivered(Message::summarize(&messages, summary))
+    }
+}
+
+/// Changes to a timeline's rows, for its model to repeat in order.
+pub type Diffs = Vec<TimelineDiff<Entry>>;
+
+/// How a freshly fetched page of latest history was folded into a timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Merge {
+    Unchanged,
+    /// The page overlapped what we had, and its new messages went in
+    /// beside those they follow.
+    Merged,
+    /// The page did not overlap what we had, so it replaced it.
+    Replaced,
+}
+
+/// A fresh transaction id, unique within this process.
+pub fn new_txn_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "gc{}.{}",
+        time::now_ms(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// An id for a row made up locally, e.g. a `SystemNotice`. Event ids start
+/// with "$", so it never clashes with one.
+pub fn new_notice_id() -> String {
+    format!("~{}", new_txn_id())
+}
+
+/// Messages of one room, oldest first, including unconfirmed local echoes.
+/// They are held in a `TimelineBuffer`, which holds each once wherever sync
+/// and pagination overlap, and says which of them the rows show.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    buffer: TimelineBuffer<Entry>,
+}
+
+impl Timeline {
+    /// A timeline of already delivered messages.
+    pub fn new(messages: Vec<Message>) -> Self {
+        let mut timeline = Self::default();
+        timeline.buffer.merge(
+            messages.into_iter().map(Entry::delivered).collect(),
+            Direction::Newer,
+        );
+        timeline
+    }
+
+    /// The entries shown, a row each: those the filter lets through, with
+    /// runs of membership changes folded into one.
+    pub fn entries(&self) -> &[Entry] {
+        self.buffer.rows()
+    }
+
+    pub fn filter(&self) -> TimelineFilter {
+        self.buffer.filter()
+    }
+
+    /// Show what `filter` lets through.
+    pub fn set_filter(&mut self, filter: TimelineFilter) -> Diffs {
+        self.buffer.set_filter(filter)
+    }
+
+    /// The newest message the server has confirmed. System notices are not
+    /// events, so they are skipped.
+    pub fn latest_event(&self) -> Option<&Message> {
+        self.buffer
+            .live_items()
+            .iter()
+            .rev()
+            .find(|e| {
+                e.state == SendState::Sent
+                    && !matches!(e.message.schema, MessageType::SystemNotice(_))
+            })
+            .map(|e| &e.message)
+    }
+
+    /// The newest text message `sender` sent that the server confirmed, the
+    /// one an edit shortcut picks.
+    pub fn last_editable(&self, sender: &str) -> Option<&Message> {
+        self.buffer
+            .live_items()
+            .iter()
+            .rev()
+            .find(|e| {
+                e.state == SendState::Sent
+                    && e.message.sender == sender
+                    && e.message.schema == MessageType::Text
+            })
+            .map(|e| &e.message)
+    }
+
+    /// Fold the latest page of history (oldest first) into a cached timeline.
+    /// Unconfirmed local echoes are kept at the end either way.
+    pub fn merge_latest(&mut self, page: Vec<Message>) -> (Merge, Diffs) {
+        if page.is_empty() {
+            return (Merge::Unchanged, Vec::new());
+        }
+        let page: Vec<Entry> = page.into_iter().map(Entry::delivered).collect();
+        if page
+            .iter()
+            .any(|e| self.buffer.live_contains(&e.message.id))
+        {
+            let diffs = self.buffer.merge(page, Direction::Newer);
+            let merge = if diffs.is_empty() {
+                Merge::Unchanged
+            } else {
+                Merge::Merged
+            };
+            return (merge, diffs);
+        }
+
+        let pending: Vec<Entry> = self
+            .buffer
+            .live_items()
+            .iter()
+            .filter(|e| e.state != SendState::Sent)
+            .cloned()
+            .collect();
+        let mut diffs = self.buffer.clear();
+        diffs.extend(self.buffer.merge(page, Direction::Newer));
+        for entry in pending {
+            diffs.extend(self.buffer.push_live(entry));
+        }
+        (Merge::Replaced, diffs)
+    }
+
+    /// Insert an older page (oldest first) above the messages shown,
+    /// skipping those already held.
+    pub fn prepend(&mut self, older: Vec<Message>) -> Diffs {
+        let older = older.into_iter().map(Entry::delivered).collect();
+        self.buffer.paginate(older, Direction::Older)
+    }
+
+    /// Whether `page`, fetched forwards from messages jumped to, reaches
+    /// the latest ones held here: it shares one with them, or nothing
+    /// comes after it (`next_batch` is `None`).
+    pub fn reaches(&self, page: &[Message], next_batch: Option<&str>) -> bool {
+        next_batch.is_none() || page.iter().any(|m| self.buffer.live_contains(&m.id))
+    }
+
+    /// Fold in `jumped`, the messages jumped to, once they reach these:
+    /// those up to the first message both hold go at the top, and the rest
+    /// are held here already. Returns whether any went at the top, so
+    /// paging back carries on from where `jumped` would have.
+    pub fn join(&mut self, jumped: &Timeline) -> (bool, Diffs) {
+        let older: Vec<Message> = jumped
+            .buffer
+            .items()
+            .iter()
+            .filter(|e| e.state == SendState::Sent)
+            .map(|e| e.message.clone())
+            .take_while(|m| !self.buffer.live_contains(&m.id))
+            .collect();
+        if older.is_empty() {
+            return (false, Vec::new());
+        }
+        (true, self.prepend(older))
+    }
+
+    /// The id of the entry sent under `txn_id`: the transaction id itself
+    /// until the server confirms it.
+    fn find_txn(&self, txn_id: &str) -> Option<String> {
+        self.buffer
+            .live_items()
+            .iter()
+            .rev()
+            .find(|e| e.txn_id.as_deref() == Some(txn_id))
+            .map(|e| e.message.id.clone())
+    }
+
+    /// Show an outgoing message before the server has confirmed it. Its id is
+    /// the transaction id until the event id is known.
+    pub fn push_local(&mut self, txn_id: &str, message: Message) -> Diffs {
+        self.push_pending(txn_id, message, SendState::Sending)
+    }
+
+    /// Show an outgoing message that will be sent once the homeserver can be
+    /// reached again.
+    pub fn push_queued(&mut self, txn_id: &str, message: Message) -> Diffs {
+        self.push_pending(txn_id, message, SendState::Queued)
+    }
+
+    fn push_pending(&mut self, txn_id: &str, mut message: Message, state: SendState) -> Diffs {
+        message.id = txn_id.to_string();
+        self.buffer.push_live(Entry {
+            message,
+            state,
+            txn_id: Some(txn_id.to_string()),
+        })
+    }
+
+    /// Add a message from sync. The remote echo of one of our own replaces
+    /// its local entry instead of appending a duplicate.
+    pub fn push_remote(&mut self, message: Message, txn_id: Option<&str>) -> Diffs {
+        if let Some(id) = txn_id.and_then(|t| self.find_txn(t)) {
+            let event_id = message.id.clone();
+            return self.confirm(&id, &event_id, Some(message));
+        }
+        if self.buffer.contains(&message.id) {
+            return Vec::new();
+        }
+        self.buffer.push_live(Entry::delivered(message))
+    }
+
+    /// The server has entry `id` as `event_id`, and sent it back as
+    /// `message` if it did. A copy of the event already held, from a page
+    /// fetched meanwhile, stays where the page put it, and the entry goes.
+    fn confirm(&mut self, id: &str, event_id: &str, message: Option<Message>) -> Diffs {
+        let settle = |entry: &mut Entry| {
+            if let Some(message) = message {
+                entry.message = message;
+            }
+            entry.message.id = event_id.to_string();
+            entry.state = SendState::Sent;
+        };
+        if id == event_id || !self.buffer.contains(event_id) {
+            return self.buffer.update(id, settle);
+        }
+        let txn_id = self.buffer.get(id).and_then(|e| e.txn_id.clone());
+        let mut diffs = self.buffer.remove(id);
+        diffs.extend(self.buffer.update(event_id, |entry| {
+            settle(entry);
+            entry.txn_id = txn_id;
+        }));
+        diffs
+    }
+
+    /// The server accepted the message sent under `txn_id`.
+    pub fn mark_sent(&mut self, txn_id: &str, event_id: &str) -> Diffs {
+        match self.find_txn(txn_id) {
+            Some(id) => self.confirm(&id, event_id, None),
+            None => Vec::new(),
+        }
+    }
+
+    /// Put the entry sent under `txn_id` from state `from` into state `to`.
+    /// Returns its message, or `None` if it is in another state.
+    fn set_state(
+        &mut self,
+        txn_id: &str,
+        from: &[SendState],
+        to: SendState,
+    ) -> Option<(Message, Diffs)> {
+        let id = self.find_txn(txn_id)?;
+        let entry = self.buffer.get(&id)?;
+        if !from.contains(&entry.state) {
+            return None;
+        }
+        let message = entry.message.clone();
+        let diffs = self.buffer.update(&id, |entry| entry.state = to);
+        Some((message, diffs))
+    }
+
+    /// Sending `txn_id` failed. A remote echo that already arrived wins.
+    pub fn mark_failed(&mut self, txn_id: &str) -> Diffs {
+        self.set_state(txn_id, &[SendState::Sending], SendState::Failed)
+            .map(|(_, diffs)| diffs)
+            .unwrap_or_default()
+    }
+
+    /// Put a failed or queued message into `Sending`, returning it to send
+    /// under its transaction id.
+    pub fn retry(&mut self, txn_id: &str) -> Option<(Message, Diffs)> {
+        self.set_state(
+            txn_id,
+            &[SendState::Failed, SendState::Queued],
+            SendState::Sending,
+        )
+    }
+
+    /// Hold a failed message back until the homeserver can be reached, as
+    /// retrying it while offline would only fail again.
+    pub fn requeue(&mut self, txn_id: &str) -> Diffs {
+        self.set_state(txn_id, &[SendState::Failed], SendState::Queued)
+            .map(|(_, diffs)| diffs)
+            .unwrap_or_default()
+    }
+
+    /// Drop a failed or queued message without sending it. `None` if there
+    /// was none.
+    pub fn discard(&mut self, txn_id: &str) -> Option<Diffs> {
+        let id = self.find_txn(txn_id)?;
+        if !matches!(
+            self.buffer.get(&id)?.state,
+            SendState::Failed | SendState::Queued
+        ) {
+            return None;
+        }
+        Some(self.buffer.remove(&id))
+    }
+
+    /// Transaction ids of the messages that failed to send, oldest first.
+    pub fn failed(&self) -> Vec<&str> {
+        self.buffer
+            .live_items()
+            .iter()
+            .filter(|e| e.state == SendState::Failed)
+            .filter_map(|e| e.txn_id.as_deref())
+            .collect()
+    }
+
+    /// The row showing the message `event_id`.
+    pub fn find(&self, event_id: &str) -> Option<usize> {
+        self.buffer.row(event_id)
+    }
+
+    /// The message `event_id`, shown or not.
+    pub fn message(&self, event_id: &str) -> Option<&Message> {
+        self.buffer.get(event_id).map(|e| &e.message)
+    }
+
+    /// `sender` replaced the body of `event_id`. Edits by anyone but the
+    /// message's sender are ignored.
+    pub fn edit(
+        &mut self,
+        event_id: &str,
+        sender: &str,
+        body: &str,
+        formatted: Option<&str>,
+    ) -> Diffs {
+        if self.message(event_id).is_none_or(|m| m.sender != sender) {
+            return Vec::new();
+        }
+        self.buffer.update(event_id, |entry| {
+            let message = &mut entry.message;
+            message.content = body.to_string();
+            message.formatted = formatted.map(str::to_owned);
+            message.edited = true;
+        })
+    }
+
+    /// Trust `sender`'s encrypted messages as far as `trust` says for the
+    /// device each came from.
+    pub fn set_trust(
+        &mut self,
+        sender: &str,
+        trust: impl Fn(Option<&str>) -> SenderTrust,
+    ) -> Diffs {
+        self.buffer.update_each(|entry| {
+            if entry.message.sender != sender {
+                return false;
+            }
+            let Some(message_trust) = &mut entry.message.trust else {
+                return false;
+            };
+            let now = trust(message_trust.device_id.as_deref());
+            std::mem::replace(&mut message_trust.trust, now) != now
+        })
+    }
+
+    /// Add a reaction to `message_id`. One we already have is ignored, so
+    /// the send reply and its remote echo can both report it.
+    pub fn react(&mut self, message_id: &str, reaction: Reaction) -> Diffs {
+        let Some(message) = self.message(message_id) else {
+            return Vec::new();
+        };
+        if message
+            .reactions
+            .iter()
+            .any(|r| r.event_id == reaction.event_id)
+        {
+            return Vec::new();
+        }
+        self.buffer
+            .update(message_id, |entry| entry.message.reactions.push(reaction))
+    }
+
+    /// The event id of `user_id`'s `key` reaction to `message_id`, if any.
+    pub fn own_reaction(&self, message_id: &str, key: &str, user_id: &str) -> Option<&str> {
+        self.message(message_id)?
+            .reactions
+            .iter()
+            .find(|r| r.key == key && r.sender == user_id)
+            .map(|r| r.event_id.as_str())
+    }
+
+    /// `event_id` was deleted: drop the message, or the reaction it was
+    /// along with its row.
+    pub fn redact(&mut self, event_id: &str) -> Diffs {
+        let mut diffs = self.buffer.remove(event_id);
+        diffs.extend(self.buffer.update_each(|entry| {
+            let reactions = &mut entry.message.reactions;
+            let before = reactions.len();
+            reactions.retain(|r| r.event_id != event_id);
+            reactions.len() != before
+        }));
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::{MessageTrust, Notice};
+
+    fn message(id: &str, sender: &str, body: &str) -> Message {
+        Message {
+            id: id.to_string(),
+            sender: sender.to_string(),
+            content: body.to_string(),
+            ..Message::default()
+        }
+    }
+
+    fn latest_id(timeline: &Timeline) -> Option<&str> {
+        timeline.latest_event().map(|m| m.id.as_str())
+    }
+
+    #[test]
+    fn test_remote_echo_replaces_local_entry() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "hi")]);
+        let txn = new_txn_id();
+        assert!(matches!(
+            timeline.push_local(&txn, message("", "@me:x", "hello"))[..],
+            [TimelineDiff::InsertAt(1, _)]
+        ));
+        assert_eq!(timeline.entries()[1].state, SendState::Sending);
+
+        let diffs = timeline.push_remote(message("$2", "@me:x", "hello"), Some(&txn));
+        assert!(matches!(diffs[..], [TimelineDiff::Update(1, _)]));
+        assert_eq!(timeline.entries().len(), 2);
+        assert_eq!(timeline.entries()[1].message.id, "$2");
+        assert_eq!(timeline.entries()[1].state, SendState::Sent);
+
+        // The send reply arriving afterwards only confirms the id.
+        timeline.mark_sent(&txn, "$2");
+        assert!(timeline.mark_failed(&txn).is_empty());
+        assert_eq!(timeline.entries()[1].state, SendState::Sent);
+    }
+
+    #[test]
+    fn test_trust_is_set_per_device() {
+        let encrypted = |id: &str, sender: &str, device_id: &str| Message {
+            trust: Some(MessageTrust {
+                device_id: Some(device_id.to_string()),
+                trust: SenderTrust::Unverified,
+            }),
+            ..message(id, sender, "hi")
+        };
+        let mut timeline = Timeline::new(vec![
+            encrypted("$1", "@bob:x", "PHONE"),
+            encrypted("$2", "@bob:x", "LAPTOP"),
+            message("$3", "@bob:x", "in the clear"),
+            encrypted("$4", "@eve:x", "PHONE"),
+        ]);
+        let phone_only = |device_id: Option<&str>| match device_id {
+            Some("PHONE") => SenderTrust::Verified,
+            _ => SenderTrust::Unverified,
+        };
+        assert!(!timeline.set_trust("@bob:x", phone_only).is_empty());
+        let trust: Vec<_> = timeline
+            .entries()
+            .iter()
+            .map(|e| e.message.trust.as_ref().map(|t| t.trust))
+            .collect();
+        assert_eq!(
+            trust,
+            [
+                Some(SenderTrust::Verified),
+                Some(SenderTrust::Unverified),
+                None,
+                Some(SenderTrust::Unverified)
+            ]
+        );
+        // Nothing left to change.
+        assert!(timeline.set_trust("@bob:x", phone_only).is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_remote_is_ignored() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "hi")]);
+        assert!(timeline
+            .push_remote(message("$1", "@bob:x", "hi"), None)
+            .is_empty());
+        assert!(matches!(
+            timeline.push_remote(message("$2", "@bob:x", "again"), None)[..],
+            [TimelineDiff::InsertAt(1, _)]
+        ));
+    }
+
+    #[test]
+    fn test_failed_send_can_be_retried() {
+        let mut timeline = Timeline::default();
+        timeline.push_local("t1", message("", "@me:x", "hello"));
+        assert!(timeline.retry("t1").is_none());
+
+        assert!(matches!(
+            timeline.mark_failed("t1")[..],
+            [TimelineDiff::Update(0, _)]
+        ));
+        assert_eq!(timeline.entries()[0].state, SendState::Failed);
+
+        let (message, _) = timeline.retry("t1").unwrap();
+        assert_eq!(message.content, "hello");
+        assert_eq!(timeline.entries()[0].state, SendState::Sending);
+    }
+
+    #[test]
+    fn test_queued_send_goes_out_later() {
+        let mut timeline = Timeline::default();
+        assert!(matches!(
+            timeline.push_queued("t1", message("", "@me:x", "hello"))[..],
+            [TimelineDiff::InsertAt(0, _)]
+        ));
+        assert_eq!(timeline.entries()[0].state, SendState::Queued);
+        assert!(timeline.mark_failed("t1").is_empty());
+        assert_eq!(latest_id(&timeline), None);
+
+        let (message, _) = timeline.retry("t1").unwrap();
+        assert_eq!(message.id, "t1");
+        assert_eq!(timeline.entries()[0].state, SendState::Sending);
+        timeline.mark_sent("t1", "$1");
+        assert_eq!(timeline.entries()[0].state, SendState::Sent);
+    }
+
+    #[test]
+    fn test_failed_send_can_be_requeued_or_discarded() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "hi")]);
+        timeline.push_local("t1", message("", "@me:x", "one"));
+        timeline.push_local("t2", message("", "@me:x", "two"));
+        timeline.push_local("t3", message("", "@me:x", "three"));
+        // Only failed messages go back to the queue.
+        assert!(timeline.requeue("t1").is_empty());
+        for txn in ["t1", "t2", "t3"] {
+            timeline.mark_failed(txn);
+        }
+        assert_eq!(timeline.failed(), vec!["t1", "t2", "t3"]);
+
+        assert!(matches!(
+            timeline.requeue("t2")[..],
+            [TimelineDiff::Update(2, _)]
+        ));
+        assert_eq!(timeline.entries()[2].state, SendState::Queued);
+        assert_eq!(timeline.failed(), vec!["t1", "t3"]);
+        // Still under its transaction id, so the remote echo dedups.
+        let (message, _) = timeline.retry("t2").unwrap();
+        assert_eq!(message.id, "t2");
+
+        assert_eq!(timeline.discard("t2"), None);
+        assert!(matches!(
+            timeline.discard("t1").as_deref(),
+            Some([TimelineDiff::Remove(1)])
+        ));
+        assert_eq!(timeline.failed(), vec!["t3"]);
+        assert_eq!(timeline.entries().len(), 3);
+        assert_eq!(timeline.discard("$1"), None);
+    }
+
+    #[test]
+    fn test_merge_latest_appends_after_overlap() {
+        let mut timeline = Timeline::new(vec![
+            message("$1", "@bob:x", "a"),
+            message("$2", "@bob:x", "b"),
+        ]);
+        let (merge, diffs) = timeline.merge_latest(vec![
+            message("$2", "@bob:x", "b"),
+            message("$3", "@bob:x", "c"),
+        ]);
+        assert_eq!(merge, Merge::Merged);
+        assert!(matches!(diffs[..], [TimelineDiff::InsertAt(2, _)]));
+        assert_eq!(latest_id(&timeline), Some("$3"));
+        assert_eq!(
+            timeline.merge_latest(vec![message("$3", "@bob:x", "c")]).0,
+            Merge::Unchanged
+        );
+    }
+
+    #[test]
+    fn test_merge_latest_replaces_across_gap() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "a")]);
+        timeline.push_local("t1", message("", "@me:x", "pending"));
+
+        let (merge, _) = timeline.merge_latest(vec![message("$9", "@bob:x", "z")]);
+        assert_eq!(merge, Merge::Replaced);
+        let ids: Vec<_> = timeline
+            .entries()
+            .iter()
+            .map(|e| e.message.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["$9", "t1"]);
+        assert_eq!(latest_id(&timeline), Some("$9"));
+    }
+
+    #[test]
+    fn test_system_notices_are_never_read_up_to() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "a")]);
+        let mut notice = message(&new_notice_id(), "@bob:x", "bob joined voice");
+        notice.schema = MessageType::SystemNotice(Notice::Voice);
+        assert!(notice.id.starts_with('~'));
+        let notice_id = notice.id.clone();
+        assert!(matches!(
+            timeline.push_remote(notice, None)[..],
+            [TimelineDiff::InsertAt(1, _)]
+        ));
+        assert_eq!(latest_id(&timeline), Some("$1"));
+
+        // $2 follows $1 in the page, so it goes above the notice.
+        let (merge, diffs) = timeline.merge_latest(vec![
+            message("$1", "@bob:x", "a"),
+            message("$2", "@bob:x", "b"),
+        ]);
+        assert_eq!(merge, Merge::Merged);
+        assert!(matches!(diffs[..], [TimelineDiff::InsertAt(1, _)]));
+        assert_eq!(timeline.find(&notice_id), Some(2));
+        assert_eq!(latest_id(&timeline), Some("$2"));
+    }
+
+    #[test]
+    fn test_prepend_skips_known_messages() {
+        let mut timeline = Timeline::new(vec![
+            message("$3", "@bob:x", "c"),
+            message("$4", "@bob:x", "d"),
+        ]);
+        let diffs = timeline.prepend(vec![
+            message("$1", "@bob:x", "a"),
+            message("$2", "@bob:x", "b"),
+            message("$3", "@bob:x", "c"),
+        ]);
+        assert_eq!(diffs.len(), 2);
+        let ids: Vec<_> = timeline
+            .entries()
+            .iter()
+            .map(|e| e.message.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["$1", "$2", "$3", "$4"]);
+    }
+
+    #[test]
+    fn test_pages_forward_from_a_jump_reach_the_latest() {
+        let timeline = Timeline::new(vec![
+            message("$5", "@bob:x", "e"),
+            message("$6", "@bob:x", "f"),
+        ]);
+        let page = vec![message("$3", "@bob:x", "c"), message("$4", "@bob:x", "d")];
+        assert!(!timeline.reaches(&page, Some("t1")));
+        // Nothing after it, so it is the newest there is.
+        assert!(timeline.reaches(&page, None));
+        let page = vec![message("$4", "@bob:x", "d"), message("$5", "@bob:x", "e")];
+        assert!(timeline.reaches(&page, Some("t2")));
+    }
+
+    #[test]
+    fn test_join_adds_the_older_messages_jumped_to() {
+        let ids = |timeline: &Timeline| -> Vec<String> {
+            timeline
+                .entries()
+                .iter()
+                .map(|e| e.message.id.clone())
+                .collect()
+        };
+
+        // Overlapping: those before the first shared message go on top.
+        let mut latest = Timeline::new(vec![
+            message("$3", "@bob:x", "c"),
+            message("$4", "@bob:x", "d"),
+        ]);
+        let jumped = Timeline::new(vec![
+            message("$1", "@bob:x", "a"),
+            message("$2", "@bob:x", "b"),
+            message("$3", "@bob:x", "c"),
+        ]);
+        let (joined, diffs) = latest.join(&jumped);
+        assert!(joined);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(ids(&latest), vec!["$1", "$2", "$3", "$4"]);
+
+        // Nothing older than the latest: unchanged.
+        let jumped = Timeline::new(vec![
+            message("$2", "@bob:x", "b"),
+            message("$3", "@bob:x", "c"),
+        ]);
+        let (joined, diffs) = latest.join(&jumped);
+        assert!(!joined);
+        assert!(diffs.is_empty());
+
+        // Paging ran out before any overlap: all of them go on top, and
+        // our own unsent messages stay out.
+        let mut latest = Timeline::new(vec![message("$9", "@bob:x", "z")]);
+        let mut jumped = Timeline::new(vec![
+            message("$1", "@bob:x", "a"),
+            message("$2", "@bob:x", "b"),
+        ]);
+        jumped.push_local("t1", message("", "@me:x", "pending"));
+        let (joined, _) = latest.join(&jumped);
+        assert!(joined);
+        assert_eq!(ids(&latest), vec!["$1", "$2", "$9"]);
+    }
+
+    #[test]
+    fn test_edits_only_by_the_sender() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "helo")]);
+        assert!(timeline.edit("$1", "@eve:x", "pwned", None).is_empty());
+        assert!(matches!(
+            timeline.edit("$1", "@bob:x", "hello", Some("<b>hello</b>"))[..],
+            [TimelineDiff::Update(0, _)]
+        ));
+        let edited = timeline.message("$1").unwrap();
+        assert_eq!(edited.content, "hello");
+        assert_eq!(edited.formatted.as_deref(), Some("<b>hello</b>"));
+        assert!(edited.edited);
+        assert!(timeline.edit("$9", "@bob:x", "gone", None).is_empty());
+    }
+
+    #[test]
+    fn test_last_editable_skips_unsent_and_others() {
+        let mut image = message("$3", "@bob:x", "cat.png");
+        image.schema = MessageType::Image;
+        let mut timeline = Timeline::new(vec![
+            message("$1", "@bob:x", "first"),
+            message("$2", "@eve:x", "hi"),
+            image,
+        ]);
+        timeline.push_local("t1", message("", "@bob:x", "pending"));
+        assert_eq!(timeline.last_editable("@bob:x").unwrap().id, "$1");
+        assert_eq!(timeline.last_editable("@eve:x").unwrap().id, "$2");
+        assert!(timeline.last_editable("@amy:x").is_none());
+    }
+
+    #[test]
+    fn test_reactions_and_redactions() {
+        let mut timeline = Timeline::new(vec![
+            message("$1", "@bob:x", "gg"),
+            message("$2", "@bob:x", "again?"),
+        ]);
+        let reaction = Reaction {
+            key: "👍".to_string(),
+            sender: "@me:x".to_string(),
+            event_id: "$r1".to_string(),
+        };
+        assert!(matches!(
+            timeline.react("$1", reaction.clone())[..],
+            [TimelineDiff::Update(0, _)]
+        ));
+        assert!(timeline.react("$1", reaction).is_empty());
+        assert_eq!(timeline.own_reaction("$1", "👍", "@me:x"), Some("$r1"));
+        assert_eq!(timeline.own_reaction("$1", "👍", "@bob:x"), None);
+
+        // Redacting a reaction keeps the message.
+        assert!(matches!(
+            timeline.redact("$r1")[..],
+            [TimelineDiff::Update(0, _)]
+        ));
+        assert!(timeline.message("$1").unwrap().reactions.is_empty());
+
+        assert!(matches!(
+            timeline.redact("$1")[..],
+            [TimelineDiff::Remove(0)]
+        ));
+        assert_eq!(timeline.entries().len(), 1);
+        assert!(timeline.redact("$1").is_empty());
+    }
+
+    #[test]
+    fn test_reaction_rows_follow_the_filter() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "gg")]);
+        let row = Message {
+            schema: MessageType::Reaction,
+            reply_to: Some("$1".to_string()),
+            ..message("$r1", "@me:x", "👍")
+        };
+        // Hidden by default, but held.
+        assert!(timeline.push_remote(row, None).is_empty());
+        timeline.react(
+            "$1",
+            Reaction {
+                key: "👍".to_string(),
+                sender: "@me:x".to_string(),
+                event_id: "$r1".to_string(),
+            },
+        );
+        assert_eq!(timeline.entries().len(), 1);
+
+        let as_rows = TimelineFilter {
+            reactions_as_rows: true,
+            ..TimelineFilter::default()
+        };
+        assert!(matches!(
+            timeline.set_filter(as_rows)[..],
+            [TimelineDiff::Reset(_)]
+        ));
+        assert_eq!(timeline.find("$r1"), Some(1));
+
+        // Redacting the reaction takes both away.
+        timeline.redact("$r1");
+        assert_eq!(timeline.entries().len(), 1);
+        assert!(timeline.message("$1").unwrap().reactions.is_empty());
+    }
+
+    #[test]
+    fn test_page_copy_of_a_pending_message_is_kept() {
+        let mut timeline = Timeline::new(vec![message("$1", "@bob:x", "hi")]);
+        timeline.push_local("t1", message("", "@me:x", "hello"));
+        // A page fetched before the send reply already has the event.
+        timeline.merge_latest(vec![
+            message("$1", "@bob:x", "hi"),
+            message("$2", "@me:x", "hello"),
+            message("$3", "@bob:x", "o/"),
+        ]);
+        assert_eq!(timeline.entries().len(), 4);
+
+        timeline.mark_sent("t1", "$2");
+        let ids: Vec<_> = timeline
+            .entries()
+            .iter()
+            .map(|e| e.message.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["$1", "$2", "$3"]);
+        assert_eq!(timeline.entries()[1].txn_id.as_deref(), Some("t1"));
+        // The remote echo arriving afterwards adds nothing.
+        assert!(timeline
+            .push_remote(message("$2", "@me:x", "hello"), Some("t1"))
+            .iter()
+            .all(|diff| matches!(diff, TimelineDiff::Update(1, _))));
+        assert_eq!(timeline.entries().len(), 3);
+    }
+}
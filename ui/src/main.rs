@@ -1,63 +1,182 @@
+mod accessibility;
+mod accounts;
+mod admin;
+mod animations;
+mod app_state;
+mod attachment_view;
+mod attachments;
+mod audit_log;
+mod bridges;
+mod code_highlight;
+mod composer;
+mod connection_status;
+mod crash_reports;
+mod demo;
+mod devices;
+mod diagnostics;
+mod emails;
+mod emoji_picker;
+mod favorites;
+mod file_upload;
+mod forgot_password;
+mod forwarding;
+mod friends;
+mod game_invites;
+mod history;
+mod i18n;
+mod idle;
+mod image_compression;
+mod incoming_call;
+mod input_history;
+mod keywords;
+mod knock_list;
+mod knocks;
+mod link_previews;
+mod links;
+mod local_echo;
+mod member_index;
+mod member_list;
+mod members;
+mod mention_completion;
+mod message_actions;
+mod message_details;
+mod message_search;
+mod notifications;
+mod onboarding;
+mod overlay;
+mod permalinks;
+mod pins;
+mod popouts;
+mod profile;
+mod purge;
+mod pushers;
+mod quick_switcher;
+mod quiet_hours;
+mod register;
+mod replay;
+mod reports;
+mod rich_presence;
+mod rich_text;
+mod room_preview;
+mod room_security;
+mod room_settings;
+mod room_sounds;
+mod room_topic;
+mod rooms;
+mod saved_sessions;
+mod scheduled;
+mod send_guard;
+mod send_queue;
+mod sender_trust;
+mod server_status;
+mod settings_transfer;
+mod shortcuts;
+mod shutdown;
+mod sidebar;
+mod slash_commands;
+mod spelling;
+mod stickers;
+mod storage;
+mod switcher_index;
+mod timeline;
+mod timeline_window;
+mod toast;
+mod translations;
+mod tray;
+mod typing;
+mod typing_indicator;
+mod unsent;
+mod uploads;
+mod voice_channel;
+mod voice_clips;
+mod welcome;
+mod window_events;
+mod window_state;
+
+use app_state::ClientHandle;
+use chat_core::commands::{self, Command};
+use chat_core::keybindings::Action;
+use chat_core::quiet_hours::Schedule;
+use chat_core::UserStatus;
+use links::Link;
+use network::config::{
+    AudioConfig, CacheConfig, Config, ConfigManager, EncryptionConfig, IdleConfig,
+    LinkPreviewConfig, OverlayConfig, SendConfig, SpellingConfig, TimelineConfig,
+    TranslationConfig, UploadConfig,
+};
 use network::session::SessionManager;
-use network::MatrixClient;
+use network::voice::VoiceManager;
+use notifications::Notifier;
+use rooms::SharedSidebar;
+use tray::TrayCommand;
 
+use slint::winit_030::{winit, WinitWindowAccessor};
 use slint::{ComponentHandle, Model, ModelRc, SharedString, VecModel};
+use std::panic::AssertUnwindSafe;
 use std::rc::Rc;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
 
 slint::include_modules!();
 
+const REGISTER_URL: &str = "https://app.element.io/#/register";
+
+/// How long typing must pause before the username is checked.
+const USERNAME_CHECK_DELAY: Duration = Duration::from_millis(400);
+/// How often quitting checks whether the voice loop's thread has ended.
+const VOICE_STOP_POLL: Duration = Duration::from_millis(20);
+
 #[tokio::main]
 async fn main() -> Result<(), slint::PlatformError> {
+    network::crash::install(env!("CARGO_PKG_VERSION"));
     println!("Starting application...");
+    let args: Vec<String> = std::env::args().collect();
+    let demo_mode = args.iter().any(|a| a == "--demo");
+    // A recording to play back instead of signing in.
+    let replay_path = args
+        .iter()
+        .position(|a| a == "--replay")
+        .and_then(|i| args.get(i + 1).cloned());
+    if args.iter().any(|a| a == "--record-bodies") {
+        replay::enable_recording(false);
+    } else if args.iter().any(|a| a == "--record") {
+        replay::enable_recording(true);
+    }
 
     println!("Initializing AppWindow...");
     let ui = AppWindow::new()?;
     println!("AppWindow initialized.");
 
     // Load saved profiles for login screen
-    let saved_sessions = SessionManager::get_remembered_profiles();
-    if !saved_sessions.is_empty() {
-        let profiles: Vec<SavedProfile> = saved_sessions
-            .iter()
-            .map(|s| SavedProfile {
-                user_id: SharedString::from(s.user_id.as_str()),
-                display_name: SharedString::from(s.display_name.as_str()),
-                homeserver: SharedString::from(s.homeserver.as_str()),
-            })
-            .collect();
-        let profiles_model = VecModel::from(profiles);
-        ui.set_saved_profiles(Rc::new(profiles_model).into());
+    refresh_saved_profiles(&ui);
+    if replay_path.is_none() {
+        saved_sessions::validate(&ui, refresh_saved_profiles);
     }
+    onboarding::start(&ui);
+    crash_reports::check(&ui);
 
     // Initialize message model
-    let messages = Rc::new(VecModel::from(vec![SharedString::from(
-        "Welcome to GameChat!",
-    )]));
-    ui.set_messages(ModelRc::from(messages.clone()));
+    rooms::set_messages(&ui, Vec::new());
 
-    // Shared client state
-    let client: Arc<Mutex<Option<MatrixClient>>> = Arc::new(Mutex::new(None));
+    // Shared client state, owned by a single task so commands stay ordered
+    let client = match replay_path {
+        Some(_) => ClientHandle::spawn_with::<replay::ReplayClient>(),
+        None => ClientHandle::spawn(),
+    };
+    let sidebar: SharedSidebar = Arc::new(Mutex::new(None));
 
     // --- Login callback ---
     let ui_handle = ui.as_weak();
     let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
     ui.on_login(move |username, password, homeserver| {
         let ui_handle = ui_handle.clone();
-        let client_clone = client_clone.clone();
         let password = password.to_string();
         let homeserver = homeserver.to_string();
 
-        // Normalize username: strip @ prefix and :server suffix, lowercase
-        let username = username.to_string();
-        let username = username.trim().to_lowercase();
-        let username = username.strip_prefix('@').unwrap_or(&username).to_string();
-        let username = if let Some(pos) = username.find(':') {
-            username[..pos].to_string()
-        } else {
-            username
-        };
+        let username = register::normalize_username(&username);
 
         // Set loading state
         if let Some(ui) = ui_handle.upgrade() {
@@ -65,56 +184,23 @@ async fn main() -> Result<(), slint::PlatformError> {
             ui.set_login_error(SharedString::from(""));
         }
 
+        let reply = client_clone.login(&homeserver, &username, &password);
+        let client_clone = client_clone.clone();
+        let sidebar_clone = sidebar_clone.clone();
         tokio::spawn(async move {
-            let result = async {
-                let mut mc = MatrixClient::new(&homeserver).await?;
-                let (user_id, display_name) = mc.login(&username, &password).await?;
-                Ok::<(MatrixClient, String, String), anyhow::Error>((mc, user_id, display_name))
-            }
-            .await;
+            let result = reply.await;
 
             slint::invoke_from_event_loop(move || {
                 if let Some(ui) = ui_handle.upgrade() {
                     ui.set_login_loading(false);
                     match result {
-                        Ok((mc, user_id, display_name)) => {
-                            // Store client
-                            let client_clone2 = client_clone.clone();
-                            tokio::spawn(async move {
-                                let mut guard = client_clone2.lock().await;
-                                *guard = Some(mc);
-                            });
-
-                            // Update UI
-                            ui.set_logged_in(true);
-                            ui.set_current_user_id(SharedString::from(user_id.as_str()));
-                            ui.set_current_display_name(SharedString::from(display_name.as_str()));
-
-                            // Update profile
-                            ui.set_current_profile(UserProfileData {
-                                username: SharedString::from(display_name.as_str()),
-                                status: SharedString::from("Online"),
-                                bio: SharedString::from(""),
-                                avatar_color: slint::Color::from_argb_u8(255, 114, 137, 218),
-                            });
-
-                            // Refresh saved profiles
-                            let saved = SessionManager::get_remembered_profiles();
-                            let profiles: Vec<SavedProfile> = saved
-                                .iter()
-                                .map(|s| SavedProfile {
-                                    user_id: SharedString::from(s.user_id.as_str()),
-                                    display_name: SharedString::from(s.display_name.as_str()),
-                                    homeserver: SharedString::from(s.homeserver.as_str()),
-                                })
-                                .collect();
-                            ui.set_saved_profiles(Rc::new(VecModel::from(profiles)).into());
-
+                        Ok((user_id, display_name)) => {
+                            enter_app(&ui, &client_clone, &sidebar_clone, &user_id, &display_name);
                             println!("Logged in as {}", user_id);
                         }
                         Err(e) => {
-                            ui.set_login_error(SharedString::from(format!("{}", e)));
-                            eprintln!("Login failed: {}", e);
+                            ui.set_login_error(SharedString::from(i18n::error_text(&e)));
+                            tracing::warn!("Login failed: {}", e);
                         }
                     }
                 }
@@ -123,20 +209,245 @@ async fn main() -> Result<(), slint::PlatformError> {
         });
     });
 
+    // --- Register ---
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_register(move |username, password, confirm, homeserver| {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        let username = register::normalize_username(&username);
+        let errors = register::validate(&username, &password, &confirm);
+        set_register_errors(&ui, &errors);
+        if !errors.is_empty() {
+            return;
+        }
+        ui.set_register_loading(true);
+
+        let reply = client_clone.register(&homeserver, &username, &password);
+        let ui_handle = ui_handle.clone();
+        let client_clone = client_clone.clone();
+        let sidebar_clone = sidebar_clone.clone();
+        tokio::spawn(async move {
+            let result = reply.await;
+
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                ui.set_register_loading(false);
+                match result {
+                    Ok((user_id, display_name)) => {
+                        ui.set_register_mode(false);
+                        ui.set_register_username_status(SharedString::from(""));
+                        enter_app(&ui, &client_clone, &sidebar_clone, &user_id, &display_name);
+                        println!("Registered as {}", user_id);
+                    }
+                    Err(e) => {
+                        set_register_errors(&ui, &register::from_error(&e));
+                        tracing::warn!("Registration failed: {:#}", e);
+                    }
+                }
+            })
+            .ok();
+        });
+    });
+
+    // Check availability once typing pauses; only the latest check is shown.
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let username_checks = Arc::new(AtomicU64::new(0));
+    ui.on_check_username(move |username, homeserver| {
+        let generation = username_checks.fetch_add(1, Ordering::SeqCst) + 1;
+        let username = register::normalize_username(&username);
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.set_register_username_status(SharedString::from(""));
+        }
+        if username.is_empty() {
+            return;
+        }
+
+        let ui_handle = ui_handle.clone();
+        let client_clone = client_clone.clone();
+        let username_checks = username_checks.clone();
+        let homeserver = homeserver.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(USERNAME_CHECK_DELAY).await;
+            if username_checks.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            let result = client_clone.check_username(&homeserver, &username).await;
+            slint::invoke_from_event_loop(move || {
+                if username_checks.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                match result {
+                    Ok(availability) => {
+                        let (text, available) = register::availability_text(availability);
+                        ui.set_register_username_status(SharedString::from(text));
+                        ui.set_register_username_available(available);
+                    }
+                    // Not worth interrupting the form; registering will say.
+                    Err(e) => tracing::warn!("Username check failed: {:#}", e),
+                }
+            })
+            .ok();
+        });
+    });
+
+    // Say how the homeserver is doing before anyone signs in to it.
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_check_login_server(move |homeserver, now| {
+        if let Some(ui) = ui_handle.upgrade() {
+            server_status::check(&ui, &client_clone, &homeserver, now);
+        }
+    });
+
+    // --- Forgot password ---
+    let ui_handle = ui.as_weak();
+    ui.on_forgot_password(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            forgot_password::open(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_send_reset_link(move |email, homeserver| {
+        if let Some(ui) = ui_handle.upgrade() {
+            forgot_password::send_link(&ui, &client_clone, &email, &homeserver);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_resend_reset_link(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            forgot_password::resend(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_submit_reset_code(move |code| {
+        if let Some(ui) = ui_handle.upgrade() {
+            forgot_password::submit_code(&ui, &client_clone, &code);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_reset_password(move |password, confirm| {
+        if let Some(ui) = ui_handle.upgrade() {
+            forgot_password::set_password(&ui, &client_clone, &password, &confirm);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_cancel_reset(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            forgot_password::close(&ui);
+        }
+    });
+
     // --- Open Register (browser) ---
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
     ui.on_open_register(move || {
         println!("Opening Element.io registration in browser...");
-        let _ = std::process::Command::new("cmd")
-            .args(["/C", "start", "https://app.element.io/#/register"])
-            .spawn();
+        if let Some(ui) = ui_handle.upgrade() {
+            open_link(&ui, &client_clone, &sidebar_clone, REGISTER_URL);
+        }
+    });
+
+    // --- First run ---
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_check_server(move |server| {
+        if let Some(ui) = ui_handle.upgrade() {
+            onboarding::check_server(&ui, &client_clone, &server);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_finish_onboarding(move |choice| {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        if onboarding::finish(&ui, choice) {
+            open_link(&ui, &client_clone, &sidebar_clone, REGISTER_URL);
+        }
+    });
+
+    // --- Links ---
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_open_link(move |url| {
+        if let Some(ui) = ui_handle.upgrade() {
+            open_link(&ui, &client_clone, &sidebar_clone, &url);
+        }
+    });
+
+    ui.on_open_link_confirmed(move |url| {
+        if let Err(e) = links::open_external(&url) {
+            tracing::warn!("{:#}", e);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_join_linked_room(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            permalinks::join(&ui, &client_clone, &sidebar_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_copy_message_link(move |event_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            permalinks::copy_message_link(&ui, &event_id);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let sidebar_clone = sidebar.clone();
+    ui.on_copy_room_link(move |room_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            permalinks::copy_room_link(&ui, &sidebar_clone, &room_id);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_copy_user_link(move |user_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            permalinks::copy_user_link(&ui, &user_id);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_trust_new_keys(move |user_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            sender_trust::trust_new_keys(&ui, &client_clone, &user_id);
+        }
     });
 
     // --- Quick login (saved profile) ---
     let ui_handle = ui.as_weak();
     let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
     ui.on_quick_login(move |index| {
         let ui_handle = ui_handle.clone();
-        let client_clone = client_clone.clone();
         let sessions = SessionManager::get_remembered_profiles();
         let idx = index as usize;
 
@@ -147,39 +458,46 @@ async fn main() -> Result<(), slint::PlatformError> {
         let saved = sessions[idx].clone();
 
         if let Some(ui) = ui_handle.upgrade() {
+            // Signed in already, while adding an account.
+            if accounts::is_held(&saved.user_id) {
+                switch_account(&ui, &client_clone, &sidebar_clone, &saved.user_id, None);
+                return;
+            }
+            // Found signed out at startup: no use trying the token.
+            if saved_sessions::is_expired(&saved.user_id) {
+                ui.set_login_homeserver(SharedString::from(saved.homeserver.as_str()));
+                ui.set_login_error(SharedString::from(format!(
+                    "The session of {} expired. Please log in again.",
+                    saved.user_id
+                )));
+                let _ = SessionManager::delete_session(&saved.user_id);
+                refresh_saved_profiles(&ui);
+                return;
+            }
             ui.set_login_loading(true);
             ui.set_login_error(SharedString::from(""));
         }
 
+        let reply = client_clone.restore(saved.clone());
+        let client_clone = client_clone.clone();
+        let sidebar_clone = sidebar_clone.clone();
         tokio::spawn(async move {
-            let result = MatrixClient::restore_session(&saved).await;
+            let result = reply.await;
 
             slint::invoke_from_event_loop(move || {
                 if let Some(ui) = ui_handle.upgrade() {
                     ui.set_login_loading(false);
                     match result {
-                        Ok(mc) => {
-                            let user_id = saved.user_id.clone();
-                            let display_name = saved.display_name.clone();
-
-                            let client_clone2 = client_clone.clone();
-                            tokio::spawn(async move {
-                                let mut guard = client_clone2.lock().await;
-                                *guard = Some(mc);
-                            });
-
-                            ui.set_logged_in(true);
-                            ui.set_current_user_id(SharedString::from(user_id.as_str()));
-                            ui.set_current_display_name(SharedString::from(display_name.as_str()));
-
-                            ui.set_current_profile(UserProfileData {
-                                username: SharedString::from(display_name.as_str()),
-                                status: SharedString::from("Online"),
-                                bio: SharedString::from(""),
-                                avatar_color: slint::Color::from_argb_u8(255, 114, 137, 218),
-                            });
-
-                            println!("Restored session for {}", user_id);
+                        Ok(()) => {
+                            saved_sessions::signed_in(&saved.user_id);
+                            enter_app(
+                                &ui,
+                                &client_clone,
+                                &sidebar_clone,
+                                &saved.user_id,
+                                &saved.display_name,
+                            );
+                            println!("Restored session for {}", saved.user_id);
                         }
                         Err(e) => {
                             ui.set_login_error(SharedString::from(format!(
@@ -188,7 +506,7 @@ async fn main() -> Result<(), slint::PlatformError> {
                             )));
                             // Remove invalid session
                             let _ = SessionManager::delete_session(&saved.user_id);
-                            eprintln!("Session restore failed: {}", e);
+                            tracing::warn!("Session restore failed: {}", e);
                         }
                     }
                 }
@@ -200,273 +518,2392 @@ async fn main() -> Result<(), slint::PlatformError> {
     // --- Logout callback ---
     let ui_handle = ui.as_weak();
     let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
     ui.on_logout(move || {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        let user_id = ui.get_current_user_id().to_string();
+        // Queued first, so our voice announcement is withdrawn while we
+        // are still logged in.
+        leave_account(&ui, &client_clone, &sidebar_clone);
+        input_history::forget(&user_id);
+        unsent::forget(&user_id);
+        let reply = client_clone.logout();
         let ui_handle = ui_handle.clone();
         let client_clone = client_clone.clone();
-
+        let sidebar_clone = sidebar_clone.clone();
         tokio::spawn(async move {
-            let mut guard = client_clone.lock().await;
-            if let Some(ref mut mc) = *guard {
-                let _ = mc.logout().await;
+            if let Err(e) = reply.await {
+                tracing::warn!("Logout failed: {}", e);
             }
-            *guard = None;
 
             slint::invoke_from_event_loop(move || {
-                if let Some(ui) = ui_handle.upgrade() {
-                    ui.set_logged_in(false);
-                    ui.set_current_user_id(SharedString::from(""));
-                    ui.set_current_display_name(SharedString::from(""));
-
-                    // Refresh saved profiles
-                    let saved = SessionManager::get_remembered_profiles();
-                    let profiles: Vec<SavedProfile> = saved
-                        .iter()
-                        .map(|s| SavedProfile {
-                            user_id: SharedString::from(s.user_id.as_str()),
-                            display_name: SharedString::from(s.display_name.as_str()),
-                            homeserver: SharedString::from(s.homeserver.as_str()),
-                        })
-                        .collect();
-                    ui.set_saved_profiles(Rc::new(VecModel::from(profiles)).into());
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                ui.set_current_user_id(SharedString::from(""));
+                ui.set_current_display_name(SharedString::from(""));
+                // Other accounts signed in carry on; show the next of them.
+                match accounts::signed_out(&user_id) {
+                    Some(next) => switch_account(&ui, &client_clone, &sidebar_clone, &next, None),
+                    None => show_login(&ui),
                 }
             })
             .ok();
         });
     });
 
-    // --- Send message ---
+    // --- Accounts ---
     let ui_handle = ui.as_weak();
-    let messages_clone = messages.clone();
-    ui.on_send_message(move |text| {
-        let text = text.to_string();
-        messages_clone.push(SharedString::from(format!("Me: {}", text)));
-
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_switch_account(move |user_id| {
         if let Some(ui) = ui_handle.upgrade() {
-            ui.set_messages(ModelRc::from(messages_clone.clone()));
+            switch_account(&ui, &client_clone, &sidebar_clone, &user_id, None);
         }
     });
 
-    // --- Channel selected ---
+    // The account shown is put away while the login screen is up, and
+    // comes back if adding another is given up.
     let ui_handle = ui.as_weak();
-    ui.on_channel_selected(move |id| {
-        let id = id.to_string();
-        println!("Switched to channel: {}", id);
-
-        let new_history = match id.as_str() {
-            "general" => vec!["Welcome to #general!"],
-            "random" => vec!["This is #random.", "Post memes here."],
-            "announcements" => vec!["New version 0.1 released!"],
-            _ => vec!["Channel joined."],
-        };
-
-        let new_model = VecModel::from(
-            new_history
-                .into_iter()
-                .map(SharedString::from)
-                .collect::<Vec<_>>(),
-        );
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_add_account(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            leave_account(&ui, &client_clone, &sidebar_clone);
+            ui.set_login_error(SharedString::from(""));
+            ui.set_adding_account(true);
+            ui.set_logged_in(false);
+        }
+    });
 
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_cancel_add_account(move || {
         if let Some(ui) = ui_handle.upgrade() {
-            ui.set_messages(Rc::new(new_model).into());
+            let user_id = ui.get_current_user_id();
+            let display_name = ui.get_current_display_name();
+            enter_app(&ui, &client_clone, &sidebar_clone, &user_id, &display_name);
         }
     });
 
-    // --- Server selected ---
+    // --- Send message ---
     let ui_handle = ui.as_weak();
-    ui.on_server_selected(move |index| {
-        println!("Switched to server index: {}", index);
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_send_message(move |text, reply_to| {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
+        };
+        let mentions = mention_completion::take(&ui, &text);
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let command = match commands::parse(text) {
+            Ok(command) => command,
+            Err(e) => {
+                slash_commands::show_help(&ui, text, &e);
+                return;
+            }
+        };
+        let outgoing = send_guard::Outgoing {
+            text: text.to_string(),
+            command,
+            mentions,
+            reply_to: Some(reply_to.to_string()).filter(|id| !id.is_empty()),
+        };
+        if let Some(outgoing) = send_guard::check(&ui, &sidebar_clone, outgoing) {
+            send_outgoing(&ui, &client_clone, &sidebar_clone, outgoing);
+        }
+    });
 
-        let (new_channels, welcome_msg, voice_ch_name, voice_users) = match index {
-            0 => (
-                vec!["general", "random", "announcements"],
-                "Welcome to Direct Messages!",
-                "Lounge",
-                vec!["xGamer42"],
-            ),
-            1 => (
-                vec!["rust-general", "cargo", "help"],
-                "Welcome to the Rust Server!",
-                "Rustacean Voice",
-                vec!["PixelKnight", "ferris_bot"],
-            ),
-            2 => (
-                vec!["matrix-dev", "synapse", "dendrite"],
-                "Welcome to Matrix HQ!",
-                "Dev Chat",
-                vec!["matrix_admin", "alice"],
-            ),
-            _ => (vec!["general"], "Welcome!", "General Voice", vec![]),
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    ui.on_confirm_send(move || {
+        let Some(ui) = ui_handle.upgrade() else {
+            return;
         };
+        if let Some(outgoing) = send_guard::confirm(&ui) {
+            send_outgoing(&ui, &client_clone, &sidebar_clone, outgoing);
+        }
+    });
 
+    let ui_handle = ui.as_weak();
+    ui.on_cancel_send(move || {
         if let Some(ui) = ui_handle.upgrade() {
-            let channels_model = VecModel::from(
-                new_channels
-                    .into_iter()
-                    .map(SharedString::from)
-                    .collect::<Vec<_>>(),
-            );
-            ui.set_channels(Rc::new(channels_model).into());
-
-            let msgs_model = VecModel::from(vec![SharedString::from(welcome_msg)]);
-            ui.set_messages(Rc::new(msgs_model).into());
-
-            ui.set_active_channel("general".into());
+            send_guard::cancel(&ui);
+        }
+    });
 
-            ui.set_voice_channel_name(SharedString::from(voice_ch_name));
-            let users_model = VecModel::from(
-                voice_users
-                    .into_iter()
-                    .map(SharedString::from)
-                    .collect::<Vec<_>>(),
-            );
-            ui.set_voice_users(Rc::new(users_model).into());
+    let ui_handle = ui.as_weak();
+    ui.on_send_later(move |text, reply_to, when| {
+        if let Some(ui) = ui_handle.upgrade() {
+            scheduled::send_later(&ui, &text, &reply_to, &when);
+        }
+    });
 
-            ui.set_voice_active(false);
+    let ui_handle = ui.as_weak();
+    ui.on_cancel_scheduled(move |txn_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            scheduled::cancel(&ui, &txn_id);
         }
     });
 
-    // --- Voice Manager ---
-    let voice_manager = match network::voice::VoiceManager::new("0.0.0.0:0").await {
-        Ok(vm) => Arc::new(vm),
-        Err(e) => {
-            eprintln!("Failed to init voice: {}", e);
-            Arc::new(
-                network::voice::VoiceManager::new("0.0.0.0:0")
-                    .await
-                    .unwrap(),
-            )
+    let ui_handle = ui.as_weak();
+    ui.on_edit_scheduled(move |txn_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            scheduled::edit(&ui, &txn_id);
         }
-    };
+    });
 
-    // Mock users already in voice channel (visible even before you join)
-    let initial_voice_users = Rc::new(VecModel::from(vec![
-        SharedString::from("xGamer42"),
-        SharedString::from("PixelKnight"),
-    ]));
-    ui.set_voice_users(initial_voice_users.clone().into());
+    let ui_handle = ui.as_weak();
+    ui.on_start_clip(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            voice_clips::start_recording(&ui);
+        }
+    });
 
-    let vm_clone = voice_manager.clone();
-    let voice_users_model = initial_voice_users.clone();
-    ui.on_toggle_voice(move |active| {
-        println!("Voice toggled: {}", active);
-        if active {
-            if let Err(e) = vm_clone.start_audio_loop() {
-                eprintln!("Failed to start audio: {}", e);
-            }
-            voice_users_model.insert(0, SharedString::from("You"));
-        } else {
-            vm_clone.stop();
-            if voice_users_model.row_count() > 0 {
-                voice_users_model.remove(0);
-            }
+    let ui_handle = ui.as_weak();
+    ui.on_stop_clip(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            voice_clips::stop_recording(&ui);
         }
     });
 
-    // --- Audio Devices ---
-    let input_devices = network::voice::VoiceManager::get_input_devices();
-    let output_devices = network::voice::VoiceManager::get_output_devices();
+    let ui_handle = ui.as_weak();
+    ui.on_preview_clip(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            voice_clips::toggle_preview(&ui);
+        }
+    });
 
-    let input_model = VecModel::from(
-        input_devices
-            .into_iter()
-            .map(SharedString::from)
-            .collect::<Vec<_>>(),
-    );
-    let output_model = VecModel::from(
-        output_devices
-            .into_iter()
-            .map(SharedString::from)
-            .collect::<Vec<_>>(),
-    );
-    ui.set_input_devices(Rc::new(input_model).into());
-    ui.set_output_devices(Rc::new(output_model).into());
+    let ui_handle = ui.as_weak();
+    ui.on_discard_clip(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            voice_clips::discard(&ui);
+        }
+    });
 
-    ui.on_save_settings(move |input, output| {
-        println!("Settings saved! Input: {}, Output: {}", input, output);
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_send_clip(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            voice_clips::send(&ui, &client_clone);
+        }
     });
 
-    // --- Profile Save ---
-    ui.on_save_profile(move |data| {
-        println!("Profile saved: {} — {}", data.username, data.status);
+    let client_clone = client.clone();
+    ui.on_retry_send(move |txn_id| {
+        rooms::retry_message(&client_clone, &txn_id);
     });
 
-    // --- Initial Roles/Members (mock data) ---
-    let roles_model = Rc::new(VecModel::from(vec![
-        RoleData {
-            name: SharedString::from("Admin"),
-            color: slint::Color::from_argb_u8(255, 237, 66, 69),
-        },
-        RoleData {
-            name: SharedString::from("Moderator"),
-            color: slint::Color::from_argb_u8(255, 87, 242, 135),
-        },
-        RoleData {
-            name: SharedString::from("Member"),
-            color: slint::Color::from_argb_u8(255, 148, 155, 164),
-        },
-    ]));
-    ui.set_roles(roles_model.clone().into());
+    ui.on_discard_send(move |txn_id| {
+        rooms::discard_message(&txn_id);
+    });
 
-    let members_model = Rc::new(VecModel::from(vec![
-        MemberData {
-            username: SharedString::from("You"),
-            role: SharedString::from("Admin"),
-        },
-        MemberData {
-            username: SharedString::from("xGamer42"),
-            role: SharedString::from("Moderator"),
-        },
-        MemberData {
-            username: SharedString::from("PixelKnight"),
-            role: SharedString::from("Member"),
-        },
-    ]));
-    ui.set_members(members_model.clone().into());
+    let client_clone = client.clone();
+    ui.on_retry_all_sends(move || {
+        rooms::retry_all(&client_clone);
+    });
 
-    // --- Admin: Create Channel ---
     let ui_handle = ui.as_weak();
-    ui.on_create_channel(move |name| {
-        let name = name.to_string();
-        println!("Creating channel: {}", name);
+    let client_clone = client.clone();
+    ui.on_review_unverified(move || {
         if let Some(ui) = ui_handle.upgrade() {
-            let current: ModelRc<SharedString> = ui.get_channels();
-            let mut channels: Vec<SharedString> = (0..current.row_count())
-                .map(|i| current.row_data(i).unwrap())
-                .collect();
-            channels.push(SharedString::from(name));
-            ui.set_channels(Rc::new(VecModel::from(channels)).into());
+            room_security::review(&ui, &client_clone);
         }
     });
 
-    // --- Admin: Delete Channel ---
+    // --- Message actions ---
     let ui_handle = ui.as_weak();
-    ui.on_delete_channel(move |name| {
-        let name = name.to_string();
-        println!("Deleting channel: {}", name);
+    ui.on_copy_text(move |text| {
         if let Some(ui) = ui_handle.upgrade() {
-            let current: ModelRc<SharedString> = ui.get_channels();
-            let channels: Vec<SharedString> = (0..current.row_count())
-                .map(|i| current.row_data(i).unwrap())
-                .filter(|c| c.as_str() != name)
-                .collect();
-            ui.set_channels(Rc::new(VecModel::from(channels)).into());
+            message_actions::copy(&ui, &text);
         }
     });
 
-    // --- Admin: Create Role ---
-    let roles_clone = roles_model.clone();
-    ui.on_create_role(move |name| {
-        println!("Creating role: {}", name);
-        roles_clone.push(RoleData {
-            name: name.clone(),
-            color: slint::Color::from_argb_u8(255, 88, 101, 242),
+    if !demo_mode {
+        let client_clone = client.clone();
+        ui.on_load_attachment(move |event_id| {
+            attachment_view::load_preview(&client_clone, &event_id);
         });
-    });
 
-    // --- Admin: Assign Role ---
-    ui.on_assign_role(move |user, role| {
-        println!("Assigning role '{}' to '{}'", role, user);
-    });
+        ui.on_start_animation(|event_id| animations::start(&event_id));
+        ui.on_stop_animation(|event_id| animations::stop(&event_id));
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_open_image(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                attachment_view::open_image(&ui, &client_clone, &event_id);
+            }
+        });
+        ui.on_close_image(attachment_view::close_image);
+
+        let client_clone = client.clone();
+        ui.on_save_attachment(move |event_id| {
+            attachment_view::save_file(&client_clone, &event_id);
+        });
+
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_load_link_preview(move |event_id| {
+            link_previews::load(&client_clone, &sidebar_clone, &event_id);
+        });
+
+        ui.on_toggle_translation(|event_id| translations::toggle(&event_id));
+        ui.on_load_translation(|event_id| translations::load(&event_id));
 
-    ui.run()
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_composer_edited(move |text, caret| {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            // Editing a sent message is not typing a new one.
+            if ui.get_composer_edit_id().is_empty() {
+                typing_indicator::composer_changed(&client_clone, &text);
+            }
+            input_history::edited();
+            slash_commands::composer_edited(&ui);
+            let caret = usize::try_from(caret).unwrap_or(0);
+            emoji_picker::composer_edited(&ui, &text, caret);
+            mention_completion::composer_edited(&ui, &client_clone, &text, caret);
+        });
+
+        ui.on_retry_connection(connection_status::retry_now);
+
+        // --- Emoji ---
+        let ui_handle = ui.as_weak();
+        ui.on_accept_completion(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                emoji_picker::accept(&ui, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_accept_mention(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                mention_completion::accept(&ui, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_emoji_picker_opened(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                emoji_picker::open(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_emoji_search(move |query| {
+            if let Some(ui) = ui_handle.upgrade() {
+                emoji_picker::search(&ui, &query);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_emoji_pick_category(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                emoji_picker::pick_category(&ui, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_emoji_pick_tone(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                emoji_picker::pick_tone(&ui, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_emoji_chosen(move |shortcode, target| {
+            if let Some(ui) = ui_handle.upgrade() {
+                emoji_picker::choose(&ui, &client_clone, &shortcode, &target);
+            }
+        });
+
+        // --- Stickers ---
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_sticker_picker_opened(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                stickers::open(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_sticker_pick_pack(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                stickers::pick_pack(&ui, &client_clone, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_sticker_chosen(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                stickers::choose(&ui, &client_clone, index as usize);
+            }
+        });
+
+        // --- Spell check ---
+        let ui_handle = ui.as_weak();
+        ui.on_composer_changed(move |text| {
+            if let Some(ui) = ui_handle.upgrade() {
+                spelling::composer_changed(&ui, &text);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_misspelling_menu(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                spelling::menu(&ui, index);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_replace_misspelling(move |suggestion| {
+            if let Some(ui) = ui_handle.upgrade() {
+                spelling::replace(&ui, &suggestion);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_add_to_dictionary(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                spelling::add_word(&ui);
+            }
+        });
+
+        // --- File uploads ---
+
+        let ui_handle = ui.as_weak();
+        ui.on_remove_upload(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                file_upload::remove(&ui, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_upload_original(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                file_upload::toggle_original(&ui, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_cancel_upload(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                file_upload::cancel(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_confirm_upload(move |caption| {
+            if let Some(ui) = ui_handle.upgrade() {
+                file_upload::confirm(&ui, &client_clone, &caption);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_edit_message(move |event_id, body| {
+            if let Some(ui) = ui_handle.upgrade() {
+                // Edits go out as plain text.
+                mention_completion::take(&ui, &body);
+                message_actions::edit(&ui, &client_clone, &event_id, &body);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_delete_message(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_actions::delete(&ui, &client_clone, &event_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_react(move |event_id, key| {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_actions::react(&ui, &client_clone, &event_id, &key);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_report_message(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                reports::open_message(&ui, &event_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_open_message_details(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_details::open(&ui, &client_clone, &event_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_open_purge(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                purge::open(&ui, &event_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_start_purge(move |window, ban| {
+            if let Some(ui) = ui_handle.upgrade() {
+                purge::start(&ui, &client_clone, window, ban);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_open_friends(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::open(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_add_friend(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::add(&ui, &client_clone, &user_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_accept_friend(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::respond(&ui, &client_clone, &user_id, true);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_decline_friend(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::respond(&ui, &client_clone, &user_id, false);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_remove_friend(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::remove(&ui, &client_clone, &user_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_message_friend(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::message(&ui, &client_clone, &sidebar_clone, &user_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_call_friend(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                friends::call(&ui, &client_clone, &sidebar_clone, &user_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_open_game_invite(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                game_invites::open(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_send_game_invite(move |game, connect, party, open_for| {
+            if let Some(ui) = ui_handle.upgrade() {
+                let open_for = usize::try_from(open_for).unwrap_or(0);
+                game_invites::send(&ui, &client_clone, &game, &connect, &party, open_for);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_join_game_invite(move |connect| {
+            if let Some(ui) = ui_handle.upgrade() {
+                game_invites::join(&ui, &connect);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_forward_message(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                forwarding::forward_message(&ui, &event_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_select_message(move |event_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                forwarding::select(&ui, &event_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_forward_selection(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                forwarding::forward_selection(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_cancel_selection(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                forwarding::cancel(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_unsent(move |row, checked| {
+            if let Some(ui) = ui_handle.upgrade() {
+                unsent::toggle(&ui, row as usize, checked);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_send_unsent(move |all| {
+            if let Some(ui) = ui_handle.upgrade() {
+                unsent::send(&ui, &client_clone, all);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_discard_unsent(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                unsent::discard_all(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let sidebar_clone = sidebar.clone();
+        ui.on_report_room(move |room_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                reports::open_room(&ui, &sidebar_clone, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let sidebar_clone = sidebar.clone();
+        ui.on_toggle_bridged(move |room_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                bridges::toggle(&ui, &sidebar_clone, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_toggle_favorite(move |room_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                favorites::toggle(&ui, &client_clone, &sidebar_clone, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_move_favorite(move |room_id, up| {
+            if let Some(ui) = ui_handle.upgrade() {
+                favorites::move_room(&ui, &client_clone, &sidebar_clone, &room_id, up);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_open_favorite(move |room_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                rooms::reveal_room(&ui, &client_clone, &sidebar_clone, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_submit_report(move |reason| {
+            if let Some(ui) = ui_handle.upgrade() {
+                reports::submit(&ui, &client_clone, &reason);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_ignore_reported_sender(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                reports::ignore_sender(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_open_room_settings(move |room_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::open(&ui, &client_clone, &sidebar_clone, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_join_rule(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_join_rule(&ui, &client_clone, index);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_history_visibility(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_history_visibility(&ui, &client_clone, index);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_guest_access(move |allowed| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_guest_access(&ui, &client_clone, allowed);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_slow_mode(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_slow_mode(&ui, &client_clone, index);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_welcome(move |body, must_accept| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_welcome(&ui, &client_clone, &body, must_accept);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_open_welcome(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                welcome::open(&ui);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_accept_welcome(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                welcome::accept(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_dismiss_welcome(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                welcome::dismiss(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_enable_encryption(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::enable_encryption(&ui, &client_clone, &sidebar_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_add_room_alias(move |alias| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::add_alias(&ui, &client_clone, &alias);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_remove_room_alias(move |alias| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::remove_alias(&ui, &client_clone, &alias);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_main_room_alias(move |alias| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_main_alias(&ui, &client_clone, &alias);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_set_room_timeline_filter(move |own, filter| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_timeline_filter(&ui, own, &filter);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_set_room_translate_from(move |language| {
+            if let Some(ui) = ui_handle.upgrade() {
+                room_settings::set_translate_from(&ui, &language);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_set_room_notification_sound(move |index| {
+            if let (Some(ui), Some(room_id)) = (ui_handle.upgrade(), room_settings::room_id()) {
+                room_sounds::select(&ui, &room_id, index);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_preview_room_notification_sound(move || {
+            if let (Some(ui), Some(room_id)) = (ui_handle.upgrade(), room_settings::room_id()) {
+                room_sounds::preview(&ui, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_open_audit_log(move || {
+            if let (Some(ui), Some(room_id)) = (ui_handle.upgrade(), room_settings::room_id()) {
+                audit_log::open(&ui, &client_clone, &room_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_set_audit_log_kind(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                audit_log::set_kind(&ui, &client_clone, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        ui.on_set_audit_log_actor(move |query| {
+            if let Some(ui) = ui_handle.upgrade() {
+                audit_log::set_actor(&ui, &query);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_load_more_audit_log(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                audit_log::load_more(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_approve_knock(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                knocks::answer(&ui, &client_clone, index, true);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_deny_knock(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                knocks::answer(&ui, &client_clone, index, false);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_retract_knock(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                knocks::retract(&ui, &client_clone, index);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_submit_knock(move |reason| {
+            if let Some(ui) = ui_handle.upgrade() {
+                knocks::submit(&ui, &client_clone, &reason);
+            }
+        });
+    }
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_load_older_messages(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            history::load_older(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_messages_scrolled_to_bottom(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            history::trim(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_load_newer_messages(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            history::load_newer(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_jump_to_message(move |event_id| {
+        if let Some(ui) = ui_handle.upgrade() {
+            history::jump_to(&ui, &client_clone, &event_id);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_jump_to_present(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            history::jump_to_present(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_set_topic(move |topic| {
+        if let Some(ui) = ui_handle.upgrade() {
+            room_topic::set(&ui, &client_clone, &topic);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_open_pins(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            pins::open(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_pin_message(move |event_id, pin| {
+        if let Some(ui) = ui_handle.upgrade() {
+            pins::set(&ui, &client_clone, &event_id, pin);
+        }
+    });
+
+    if demo_mode {
+        demo::install(&ui);
+    } else {
+        // --- Channel selected ---
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_channel_selected(move |id| {
+            let id = id.to_string();
+            println!("Switched to channel: {}", id);
+
+            if let Some(ui) = ui_handle.upgrade() {
+                rooms::select_room(&ui, &client_clone, &sidebar_clone, &id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_pop_out(move |id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                popouts::open(&ui, &client_clone, &sidebar_clone, &id);
+            }
+        });
+
+        // --- Member list ---
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_show_all_members(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                member_list::show_all(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_open_member(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                member_list::open_member(&ui, &client_clone, &user_id);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_message_member(move |user_id| {
+            if let Some(ui) = ui_handle.upgrade() {
+                member_list::message(&ui, &client_clone, &sidebar_clone, &user_id);
+            }
+        });
+
+        // --- Server selected ---
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_server_selected(move |index| {
+            println!("Switched to server index: {}", index);
+
+            let first_room = {
+                let mut guard = sidebar_clone.lock().unwrap();
+                let Some(sb) = guard.as_mut() else {
+                    return;
+                };
+                sb.set_active_server(index.max(0) as usize);
+                let first = sb
+                    .channels()
+                    .first()
+                    .map(|r| (r.id.clone(), r.name.clone()));
+                sb.set_active_room(first.as_ref().map(|(id, _)| id.clone()));
+                if let Some((room_id, _)) = &first {
+                    sb.clear_unread(room_id);
+                }
+                first
+            };
+
+            if let Some(ui) = ui_handle.upgrade() {
+                rooms::refresh_sidebar(&ui, &sidebar_clone);
+                match first_room {
+                    Some((room_id, name)) => {
+                        rooms::show_room(&ui, &client_clone, &room_id, &name);
+                    }
+                    None => {
+                        history::leave_room(&ui, &client_clone);
+                        typing_indicator::leave_room(&ui, &client_clone);
+                        member_list::clear(&ui);
+                        room_security::clear(&ui);
+                        rooms::set_messages(&ui, Vec::new());
+                    }
+                }
+            }
+        });
+    }
+
+    // --- Voice Manager ---
+    let voice_manager = match network::voice::VoiceManager::new("0.0.0.0:0").await {
+        Ok(vm) => Arc::new(vm),
+        Err(e) => {
+            tracing::warn!("Failed to init voice: {}", e);
+            Arc::new(
+                network::voice::VoiceManager::new("0.0.0.0:0")
+                    .await
+                    .unwrap(),
+            )
+        }
+    };
+
+    voice_channel::start(&ui, voice_manager.clone());
+    overlay::start(&client);
+    incoming_call::start(voice_manager.clone());
+    if !demo_mode {
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_toggle_voice(move |active| {
+            println!("Voice toggled: {}", active);
+            if let Some(ui) = ui_handle.upgrade() {
+                voice_channel::toggle(&ui, &client_clone, active);
+            }
+        });
+    }
+
+    // --- Settings ---
+    let config = ConfigManager::load();
+    let languages: Vec<SharedString> = i18n::LANGUAGES
+        .iter()
+        .map(|language| SharedString::from(language.name))
+        .collect();
+    ui.set_languages(Rc::new(VecModel::from(languages)).into());
+    apply_settings(&ui, &voice_manager, &config);
+
+    let vm_clone = voice_manager.clone();
+    let client_clone = client.clone();
+    settings_transfer::start(move |ui, previous, config| {
+        apply_settings(ui, &vm_clone, config);
+        apply_account_settings(ui, &client_clone, previous, config);
+    });
+
+    let ui_handle = ui.as_weak();
+    let vm_clone = voice_manager.clone();
+    let client_clone = client.clone();
+    ui.on_save_settings(
+        move |audio,
+              previews,
+              to_tray,
+              show_game,
+              away,
+              cards,
+              still,
+              uploads,
+              push,
+              strict,
+              filter,
+              keep,
+              quiet,
+              confirm_above,
+              translation,
+              spell_check,
+              strip| {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            // Keep what is saved from elsewhere, like emoji usage.
+            let saved = ConfigManager::load();
+            let retention = storage::retention(keep);
+            let retention_changed = retention != saved.cache.retention;
+            let mut config = Config {
+                audio: AudioConfig {
+                    input_device: devices::from_choice(&audio.input_device),
+                    output_device: devices::from_choice(&audio.output_device),
+                    cue_output: devices::cue_from_choice(&audio.cue_device),
+                    input_gain: audio.input_gain,
+                    vad_threshold: audio.vad_threshold,
+                    noise_suppression: audio.noise_suppression,
+                    frame_ms: u32::try_from(audio.frame_ms).unwrap_or_default(),
+                    jitter_ms: u32::try_from(audio.jitter_ms).unwrap_or_default(),
+                    max_clip_secs: u32::try_from(audio.max_clip_minutes)
+                        .unwrap_or(1)
+                        .saturating_mul(60),
+                },
+                notification_previews: previews,
+                close_to_tray: to_tray,
+                idle: IdleConfig {
+                    after_minutes: u32::try_from(away.after_minutes).unwrap_or(0),
+                    mute_sounds: away.mute_sounds,
+                },
+                quiet_hours: quiet_hours_schedule(&quiet, &saved.quiet_hours),
+                link_previews: LinkPreviewConfig {
+                    enabled: cards.enabled,
+                    encrypted_rooms: cards.encrypted_rooms,
+                },
+                reduce_motion: still,
+                uploads: UploadConfig {
+                    compress_images: uploads.compress_images,
+                    max_dimension: u32::try_from(uploads.max_dimension).unwrap_or(1),
+                    quality: u8::try_from(uploads.quality.clamp(1, 100)).unwrap_or(100),
+                },
+                encryption: EncryptionConfig {
+                    never_send_to_unverified: strict,
+                },
+                sending: SendConfig {
+                    confirm_above_members: u64::try_from(confirm_above).unwrap_or(0),
+                },
+                // Rooms with their own filter keep it.
+                timeline: TimelineConfig {
+                    filter: room_settings::timeline_filter(&filter),
+                    ..saved.timeline
+                },
+                cache: CacheConfig {
+                    retention,
+                    ..saved.cache
+                },
+                // Rooms set to always translate stay so.
+                translation: TranslationConfig {
+                    url: translation.url.trim().to_string(),
+                    api_key: translation.api_key.to_string(),
+                    target: translation.target.trim().to_string(),
+                    ..saved.translation
+                },
+                spelling: SpellingConfig {
+                    enabled: spell_check.enabled,
+                    language: spelling::language_at(spell_check.language),
+                    dictionary_dir: spell_check.dictionary_dir.trim().to_string(),
+                },
+                // Where it was dragged to stays.
+                overlay: OverlayConfig {
+                    show_on_voice: strip.show_on_voice,
+                    opacity: strip.opacity,
+                    click_through: strip.click_through,
+                    ..saved.overlay
+                },
+                ..saved
+            };
+            vm_clone.apply_config(&config.audio);
+            show_audio_settings(&ui, &config.audio);
+            let user_id = ui.get_current_user_id();
+            shortcuts::save(&mut config, &user_id);
+            keywords::save(&mut config, &user_id, &client_clone);
+            pushers::save(&ui, &client_clone, &mut config, &user_id, &push);
+            rich_presence::save(&ui, &client_clone, &mut config, show_game);
+            idle::save(&ui, &client_clone, &config.idle);
+            show_idle_settings(&ui, &config.idle);
+            quiet_hours::save(&ui, &client_clone, &config.quiet_hours);
+            show_quiet_hours_settings(&ui, &config.quiet_hours);
+            link_previews::configure(&config.link_previews);
+            animations::set_reduce_motion(&ui, config.reduce_motion);
+            file_upload::configure(&config.uploads);
+            send_guard::configure(&config.sending);
+            translations::configure(&config.translation);
+            show_translation_settings(&ui, &config.translation);
+            history::configure(&config.timeline);
+            spelling::configure(&ui, &config.spelling);
+            overlay::configure(&config.overlay);
+            if let Err(e) = ConfigManager::save(&config) {
+                tracing::warn!("Failed to save settings: {:#}", e);
+            }
+            if retention_changed && ui.get_logged_in() {
+                storage::set_retention(&client_clone, retention);
+            }
+        },
+    );
+
+    let ui_handle = ui.as_weak();
+    ui.on_set_language(move |index| {
+        let index = usize::try_from(index).unwrap_or(0);
+        let Some(language) = i18n::LANGUAGES.get(index) else {
+            return;
+        };
+        if let Some(ui) = ui_handle.upgrade() {
+            apply_language(&ui, index);
+        }
+        let mut config = ConfigManager::load();
+        config.language = Some(language.code.to_string());
+        if let Err(e) = ConfigManager::save(&config) {
+            tracing::warn!("Failed to save the language: {:#}", e);
+        }
+    });
+
+    // Mic meter: poll the level while the test runs.
+    let ui_handle = ui.as_weak();
+    let vm_clone = voice_manager.clone();
+    let meter_timer = Rc::new(slint::Timer::default());
+    ui.on_test_mic(move |on| {
+        if !on {
+            vm_clone.stop_mic_test();
+            meter_timer.stop();
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_mic_level(0.0);
+            }
+            return;
+        }
+        vm_clone.start_mic_test();
+        let ui_handle = ui_handle.clone();
+        let vm_clone = vm_clone.clone();
+        meter_timer.start(
+            slint::TimerMode::Repeated,
+            Duration::from_millis(50),
+            move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    ui.set_mic_level(vm_clone.input_level());
+                }
+            },
+        );
+    });
+
+    let vm_clone = voice_manager.clone();
+    ui.on_play_test_sound(move || vm_clone.play_test_sound());
+
+    // --- Shortcuts ---
+    shortcuts::start(voice_manager.clone());
+    keywords::start(voice_manager.clone());
+    room_sounds::start(voice_manager.clone());
+    voice_clips::start(voice_manager.clone());
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_open_settings(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            let config = ConfigManager::load();
+            shortcuts::open_settings(&ui);
+            keywords::open_settings(&ui);
+            pushers::open_settings(&ui, &client_clone, &config);
+            emails::open_settings(&ui, &client_clone);
+            rich_presence::open_settings(&ui, &config);
+            storage::open_settings(&ui, &client_clone, &config);
+            send_queue::open_settings(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_capture_keybinding(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            shortcuts::capture(&ui, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_clear_keybinding(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            shortcuts::unbind(&ui, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_add_keyword(move |text| {
+        if let Some(ui) = ui_handle.upgrade() {
+            keywords::add(&ui, &text);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_remove_keyword(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            keywords::remove(&ui, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_toggle_keyword_sound(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            keywords::toggle_sound(&ui, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_remove_pusher(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            pushers::remove(&ui, &client_clone, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_add_email(move |address| {
+        if let Some(ui) = ui_handle.upgrade() {
+            emails::add(&ui, &client_clone, &address);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_resend_email(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            emails::resend(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_cancel_email(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            emails::cancel(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_confirm_email(move |password| {
+        if let Some(ui) = ui_handle.upgrade() {
+            emails::confirm(&ui, &client_clone, &password);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_remove_email(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            emails::remove(&ui, &client_clone, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_add_game(move |name, executable| {
+        if let Some(ui) = ui_handle.upgrade() {
+            rich_presence::add_game(&ui, &name, &executable);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_remove_game(move |row| {
+        if let Some(ui) = ui_handle.upgrade() {
+            rich_presence::remove_game(&ui, row);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_clear_cache(move |category| {
+        if let Some(ui) = ui_handle.upgrade() {
+            storage::clear(&ui, &client_clone, category);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_clear_local_history(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            storage::clear_history(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_run_diagnostics(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            diagnostics::run(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_copy_diagnostics(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            diagnostics::copy(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_open_crash_folder(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            crash_reports::open_folder(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_copy_crash_report(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            crash_reports::copy(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_dismiss_crash_report(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            crash_reports::dismiss(&ui);
+        }
+    });
+
+    ui.on_cancel_pending(|id| send_queue::cancel(&id));
+
+    let client_clone = client.clone();
+    ui.on_retry_pending(move |id| send_queue::retry_now(&client_clone, &id));
+
+    let client_clone = client.clone();
+    ui.on_flush_pending(move || send_queue::flush(&client_clone));
+
+    let ui_handle = ui.as_weak();
+    ui.on_export_settings(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            settings_transfer::export(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_import_settings(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            settings_transfer::import(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_toggle_import_conflict(move |row, checked| {
+        if let Some(ui) = ui_handle.upgrade() {
+            settings_transfer::toggle(&ui, row as usize, checked);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_confirm_import(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            settings_transfer::confirm(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_cancel_import(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            settings_transfer::cancel(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_switcher_search(move |query| {
+        if let Some(ui) = ui_handle.upgrade() {
+            quick_switcher::search(&ui, &query);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_message_search_close(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            message_search::close(&ui);
+        }
+    });
+
+    if !demo_mode {
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_switcher_choose(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                quick_switcher::choose(&ui, &client_clone, &sidebar_clone, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_message_search(move |query| {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_search::search(&ui, &client_clone, &sidebar_clone, &query);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_message_search_choose(move |index| {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_search::choose(&ui, &client_clone, &sidebar_clone, index as usize);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_message_search_set_this_room(move |this_room| {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_search::set_this_room(&ui, &client_clone, &sidebar_clone, this_room);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_message_search_rebuild(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                message_search::rebuild(&ui, &client_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_accept_call(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                incoming_call::accept(&ui, &client_clone, &sidebar_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        ui.on_decline_call(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                incoming_call::decline(&ui, &client_clone);
+            }
+        });
+
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        let vm_clone = voice_manager.clone();
+        window_events::watch(&ui, &client, move |ui, action, pressed| {
+            handle_shortcut(
+                ui,
+                &client_clone,
+                &sidebar_clone,
+                &vm_clone,
+                action,
+                pressed,
+            )
+        });
+    }
+
+    // --- Profile ---
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_open_profile(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            profile::open(&ui, &client_clone);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_pick_avatar(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            profile::pick_avatar(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.on_cancel_profile_edit(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            profile::cancel_edit(&ui);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    ui.on_save_profile(move |data| {
+        if let Some(ui) = ui_handle.upgrade() {
+            profile::save(&ui, &client_clone, data);
+        }
+    });
+
+    // --- System Tray ---
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let vm_clone = voice_manager.clone();
+    let tray_running = tray::start(move |command| {
+        let ui_handle = ui_handle.clone();
+        let client = client_clone.clone();
+        let voice = vm_clone.clone();
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                handle_tray_command(&ui, &client, &voice, command);
+            }
+        })
+        .ok();
+    });
+
+    let ui_handle = ui.as_weak();
+    ui.window().on_close_requested(move || {
+        let Some(ui) = ui_handle.upgrade() else {
+            return slint::CloseRequestResponse::HideWindow;
+        };
+        let to_tray = ui.get_close_to_tray();
+        if to_tray && tray_running {
+            window_state::save(&ui);
+        } else {
+            request_quit(&ui);
+        }
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    let ui_handle = ui.as_weak();
+    shutdown::on_ctrl_c(move || {
+        let ui_handle = ui_handle.clone();
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                request_quit(&ui);
+            }
+        })
+        .ok();
+    });
+
+    // --- Admin ---
+    if !demo_mode {
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_open_admin(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                admin::open(&ui, &client_clone, &sidebar_clone);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_create_channel(move |name| {
+            println!("Creating channel: {}", name);
+            if let Some(ui) = ui_handle.upgrade() {
+                admin::create_channel(&ui, &client_clone, &sidebar_clone, &name);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_remove_channel(move |id, leave| {
+            println!("Removing channel: {}", id);
+            if let Some(ui) = ui_handle.upgrade() {
+                admin::remove_channel(&ui, &client_clone, &sidebar_clone, &id, leave);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_create_role(move |name, level| {
+            println!("Creating role: {}", name);
+            if let Some(ui) = ui_handle.upgrade() {
+                admin::create_role(&ui, &client_clone, &sidebar_clone, &name, level);
+            }
+        });
+
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        ui.on_assign_role(move |user_id, role| {
+            println!("Assigning role '{}' to '{}'", role, user_id);
+            if let Some(ui) = ui_handle.upgrade() {
+                admin::assign_role(&ui, &client_clone, &sidebar_clone, &user_id, &role);
+            }
+        });
+    }
+
+    // --- Replay (--replay <recording>) ---
+    if let Some(path) = replay_path {
+        let reply = client.login(&path, "", "");
+        let ui_handle = ui.as_weak();
+        let client_clone = client.clone();
+        let sidebar_clone = sidebar.clone();
+        tokio::spawn(async move {
+            let result = reply.await;
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                match result {
+                    Ok((user_id, display_name)) => {
+                        enter_app(&ui, &client_clone, &sidebar_clone, &user_id, &display_name);
+                        println!("Replaying {} as {}", path, user_id);
+                    }
+                    Err(e) => {
+                        ui.set_login_error(SharedString::from(format!("{}", e)));
+                        tracing::warn!("Replay failed: {:#}", e);
+                    }
+                }
+            })
+            .ok();
+        });
+    }
+
+    // Hidden to the tray, the window no longer keeps the loop alive; it runs
+    // until Quit or a close that is not to the tray.
+    ui.show()?;
+    // A panic in a callback unwinds out of the loop. Its report is written
+    // by then; quitting still winds the client down before exiting.
+    let ran = std::panic::catch_unwind(AssertUnwindSafe(slint::run_event_loop_until_quit));
+    let crashed = ran.is_err();
+    if let Ok(result) = ran {
+        result?;
+    }
+    ui.hide()?;
+
+    voice_channel::clear(&ui, &client);
+    typing_indicator::clear(&ui, &client);
+    // Deletes pasted images the user never sent.
+    file_upload::clear(&ui);
+    let quitting = Quitting {
+        client: &client,
+        voice: &voice_manager,
+    };
+    shutdown::run(&quitting, shutdown::STOP_TIMEOUT).await;
+    if crashed {
+        std::process::exit(101);
+    }
+    Ok(())
+}
+
+/// End the event loop, and with it the app, remembering the window first.
+fn request_quit(ui: &AppWindow) {
+    window_state::save(ui);
+    slint::quit_event_loop().ok();
+}
+
+/// What quitting the app winds down.
+struct Quitting<'a> {
+    client: &'a ClientHandle,
+    voice: &'a VoiceManager,
+}
+
+#[async_trait::async_trait(?Send)]
+impl shutdown::Parts for Quitting<'_> {
+    fn close(&self) {
+        self.client.close();
+    }
+
+    async fn stop_sync(&self) {
+        if let Err(e) = self.client.shutdown().await {
+            tracing::warn!("Client shutdown failed: {}", e);
+        }
+    }
+
+    async fn stop_voice(&self) {
+        self.voice.stop();
+        while !self.voice.is_stopped() {
+            tokio::time::sleep(VOICE_STOP_POLL).await;
+        }
+    }
+
+    /// Settings are saved as they change and the window as quitting was
+    /// asked for. The SDK keeps its state, the sync token with it, in
+    /// memory only, so there is none of it to write.
+    fn flush(&self) {
+        unsent::flush();
+    }
+}
+
+/// Fill the settings dialog from `audio`, listing devices that are present
+/// and any saved one that is not.
+fn show_audio_settings(ui: &AppWindow, audio: &AudioConfig) {
+    let (inputs, input) = devices::choices(
+        &VoiceManager::get_input_devices(),
+        audio.input_device.as_deref(),
+    );
+    let (outputs, output) = devices::choices(
+        &VoiceManager::get_output_devices(),
+        audio.output_device.as_deref(),
+    );
+    let model = |names: Vec<String>| -> ModelRc<SharedString> {
+        Rc::new(VecModel::from(
+            names
+                .into_iter()
+                .map(SharedString::from)
+                .collect::<Vec<_>>(),
+        ))
+        .into()
+    };
+    let (cue_outputs, cue_output) =
+        devices::cue_choices(&VoiceManager::get_output_devices(), &audio.cue_output);
+    ui.set_input_devices(model(inputs));
+    ui.set_output_devices(model(outputs));
+    ui.set_cue_devices(model(cue_outputs));
+    ui.set_audio_settings(AudioSettings {
+        input_device: SharedString::from(input),
+        output_device: SharedString::from(output),
+        cue_device: SharedString::from(cue_output),
+        input_gain: audio.input_gain,
+        vad_threshold: audio.vad_threshold,
+        noise_suppression: audio.noise_suppression,
+        frame_ms: i32::try_from(audio.frame_ms).unwrap_or(i32::MAX),
+        jitter_ms: i32::try_from(audio.jitter_ms).unwrap_or(i32::MAX),
+        max_clip_minutes: i32::try_from(audio.max_clip_secs.div_ceil(60)).unwrap_or(i32::MAX),
+    });
+}
+
+fn show_link_preview_settings(ui: &AppWindow, config: &LinkPreviewConfig) {
+    ui.set_link_preview_settings(LinkPreviewSettings {
+        enabled: config.enabled,
+        encrypted_rooms: config.encrypted_rooms,
+    });
+}
+
+fn show_overlay_settings(ui: &AppWindow, config: &OverlayConfig) {
+    ui.set_overlay_settings(OverlaySettings {
+        show_on_voice: config.show_on_voice,
+        opacity: config.opacity,
+        click_through: config.click_through,
+    });
+}
+
+fn show_translation_settings(ui: &AppWindow, config: &TranslationConfig) {
+    ui.set_translation_settings(TranslationSettings {
+        url: SharedString::from(config.url.as_str()),
+        api_key: SharedString::from(config.api_key.as_str()),
+        target: SharedString::from(config.target.as_str()),
+    });
+}
+
+fn show_upload_settings(ui: &AppWindow, config: &UploadConfig) {
+    ui.set_upload_settings(UploadSettings {
+        compress_images: config.compress_images,
+        max_dimension: i32::try_from(config.max_dimension).unwrap_or(i32::MAX),
+        quality: i32::from(config.quality),
+    });
+}
+
+/// Put the settings not tied to an account into effect and show them, as
+/// at startup or after an import.
+fn apply_settings(ui: &AppWindow, voice: &VoiceManager, config: &Config) {
+    let language = config.language.as_deref().and_then(i18n::index_of);
+    apply_language(ui, language.unwrap_or(0));
+    voice.apply_config(&config.audio);
+    show_audio_settings(ui, &config.audio);
+    ui.set_notification_previews(config.notification_previews);
+    ui.set_close_to_tray(config.close_to_tray);
+    ui.set_reduce_motion(config.reduce_motion);
+    ui.set_never_send_unverified(config.encryption.never_send_to_unverified);
+    show_send_settings(ui, &config.sending);
+    send_guard::configure(&config.sending);
+    ui.set_timeline_filter(room_settings::timeline_filter_data(&config.timeline.filter));
+    history::configure(&config.timeline);
+    animations::set_reduce_motion(ui, config.reduce_motion);
+    show_idle_settings(ui, &config.idle);
+    show_quiet_hours_settings(ui, &config.quiet_hours);
+    show_link_preview_settings(ui, &config.link_previews);
+    link_previews::configure(&config.link_previews);
+    game_invites::configure(&config.game_invites);
+    show_overlay_settings(ui, &config.overlay);
+    overlay::configure(&config.overlay);
+    show_translation_settings(ui, &config.translation);
+    translations::configure(&config.translation);
+    spelling::configure(ui, &config.spelling);
+    show_upload_settings(ui, &config.uploads);
+    file_upload::configure(&config.uploads);
+    emoji_picker::load(ui, config);
+}
+
+/// Put imported settings that replaced `previous` into effect for the
+/// signed-in account, as signing in would.
+fn apply_account_settings(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    previous: &Config,
+    config: &Config,
+) {
+    if !ui.get_logged_in() {
+        return;
+    }
+    let user_id = ui.get_current_user_id();
+    shortcuts::load(config, &user_id);
+    keywords::load(config, &user_id);
+    room_sounds::load(config, &user_id);
+    spelling::load(config, &user_id);
+    let account = config.account(&user_id);
+    if account.keywords != previous.account(&user_id).keywords {
+        keywords::update_push_rules(client, &account.keywords);
+    }
+    reports::load(config, &user_id);
+    bridges::load(config, &user_id);
+    profile::restore_status_message(ui, client, config, &user_id);
+    rich_presence::start(ui, client, &config.rich_presence);
+    idle::save(ui, client, &config.idle);
+    quiet_hours::save(ui, client, &config.quiet_hours);
+    if config.cache.retention != previous.cache.retention {
+        storage::set_retention(client, config.cache.retention);
+    }
+    history::redraw();
+}
+
+/// Switch the interface to the language at `index` in `i18n::LANGUAGES`:
+/// the markup through Slint, text made in code and timestamps through the
+/// catalogs, and the timelines redrawn in it.
+fn apply_language(ui: &AppWindow, index: usize) {
+    i18n::set(index);
+    let language = i18n::current();
+    if let Err(e) = slint::select_bundled_translation(language.code) {
+        tracing::warn!("No bundled {} translation: {}", language.code, e);
+    }
+    ui.set_language(index as i32);
+    spelling::language_changed(ui);
+    history::redraw();
+}
+
+fn show_send_settings(ui: &AppWindow, sending: &SendConfig) {
+    ui.set_confirm_above_members(i32::try_from(sending.confirm_above_members).unwrap_or(i32::MAX));
+}
+
+fn show_idle_settings(ui: &AppWindow, idle: &IdleConfig) {
+    ui.set_idle_settings(IdleSettings {
+        after_minutes: i32::try_from(idle.after_minutes).unwrap_or(i32::MAX),
+        mute_sounds: idle.mute_sounds,
+    });
+}
+
+fn show_quiet_hours_settings(ui: &AppWindow, schedule: &Schedule) {
+    ui.set_quiet_hours_settings(QuietHoursSettings {
+        enabled: schedule.enabled,
+        start: SharedString::from(chat_core::quiet_hours::format_time(schedule.start)),
+        end: SharedString::from(chat_core::quiet_hours::format_time(schedule.end)),
+        days: Rc::new(VecModel::from(schedule.days.to_vec())).into(),
+    });
+}
+
+/// The schedule `settings` describe. Times that don't read as "HH:MM" keep
+/// what `saved` has.
+fn quiet_hours_schedule(settings: &QuietHoursSettings, saved: &Schedule) -> Schedule {
+    let mut days = saved.days;
+    for (day, on) in days.iter_mut().zip(settings.days.iter()) {
+        *day = on;
+    }
+    Schedule {
+        enabled: settings.enabled,
+        start: chat_core::quiet_hours::parse_time(&settings.start).unwrap_or(saved.start),
+        end: chat_core::quiet_hours::parse_time(&settings.end).unwrap_or(saved.end),
+        days,
+    }
+}
+
+/// Switch from the login or register screen to the app for a signed-in user.
+fn enter_app(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    user_id: &str,
+    display_name: &str,
+) {
+    ui.set_logged_in(true);
+    ui.set_adding_account(false);
+    ui.set_current_user_id(SharedString::from(user_id));
+    ui.set_current_display_name(SharedString::from(display_name));
+    ui.set_current_profile(UserProfileData {
+        username: SharedString::from(display_name),
+        status: SharedString::from("Online"),
+        status_message: SharedString::from(""),
+        bio: SharedString::from(""),
+        avatar_color: profile::avatar_color(user_id),
+        initials: profile::avatar_initials(display_name, user_id),
+        avatar: slint::Image::default(),
+    });
+
+    accounts::show(user_id);
+    window_state::restore(ui, user_id);
+    let config = ConfigManager::load();
+    shortcuts::load(&config, user_id);
+    keywords::load(&config, user_id);
+    room_sounds::load(&config, user_id);
+    spelling::load(&config, user_id);
+    reports::load(&config, user_id);
+    bridges::load(&config, user_id);
+    favorites::load(ui, client, sidebar, &config, user_id);
+    knocks::load(ui, &config, user_id);
+    profile::restore_status_message(ui, client, &config, user_id);
+    pushers::register(client, &config, user_id);
+    rich_presence::start(ui, client, &config.rich_presence);
+    idle::start(ui, client, &config.idle);
+    quiet_hours::start(ui, client, &config.quiet_hours);
+    input_history::load(user_id, config.cache.retention);
+    unsent::load(
+        ui,
+        user_id,
+        config.cache.retention,
+        config.startup.unsent_max_age_hours,
+    );
+    storage::start(client, config.cache.retention);
+    rooms::start(ui, client, sidebar, config.startup.room_concurrency);
+    scheduled::start(ui, client);
+    friends::load(ui, client);
+    start_notifications(ui, client, sidebar, user_id, display_name);
+    typing_indicator::start(ui, client);
+    connection_status::start(ui, client);
+    refresh_saved_profiles(ui);
+}
+
+/// Put away everything shown of the current account, to log it out or
+/// show another. Voice is single-account, so this leaves any call.
+fn leave_account(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar) {
+    window_state::save(ui);
+    accounts::hide();
+    popouts::clear();
+    voice_channel::clear(ui, client);
+    overlay::clear();
+    member_list::clear(ui);
+    room_security::clear(ui);
+    sender_trust::clear();
+    room_preview::close(ui);
+    typing_indicator::clear(ui, client);
+    connection_status::clear(ui);
+    send_queue::clear(ui);
+
+    *sidebar.lock().unwrap() = None;
+    rooms::refresh_sidebar(ui, sidebar);
+    history::clear();
+    attachment_view::clear(ui);
+    link_previews::clear();
+    file_upload::clear(ui);
+    emoji_picker::dismiss(ui);
+    mention_completion::clear(ui);
+    quick_switcher::clear(ui);
+    forwarding::cancel(ui);
+    message_search::clear(ui);
+    incoming_call::clear(ui);
+    shortcuts::clear();
+    keywords::clear();
+    room_sounds::clear();
+    reports::clear(ui);
+    bridges::clear();
+    favorites::clear();
+    translations::clear();
+    spelling::clear(ui);
+    pins::clear(ui);
+    send_guard::clear(ui);
+    welcome::clear(ui);
+    message_details::clear(ui);
+    purge::clear(ui);
+    friends::clear(ui);
+    game_invites::clear(ui);
+    diagnostics::clear(ui);
+    room_settings::clear(ui);
+    audit_log::clear(ui);
+    knocks::clear(ui);
+    emails::clear(ui);
+    rich_presence::stop();
+    idle::stop();
+    quiet_hours::stop(ui);
+    storage::stop();
+    input_history::clear();
+    unsent::clear(ui);
+    scheduled::clear(ui);
+    voice_clips::clear(ui);
+    rooms::set_messages(ui, Vec::new());
+}
+
+/// Back to the login screen once no account is signed in.
+fn show_login(ui: &AppWindow) {
+    ui.set_logged_in(false);
+    ui.set_adding_account(false);
+    refresh_saved_profiles(ui);
+}
+
+/// Show the saved account `user_id` in place of the current one, opening
+/// `room_id` in it once its rooms are loaded. The current account stays
+/// signed in, in the background.
+fn switch_account(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    user_id: &str,
+    room_id: Option<String>,
+) {
+    let current = ui.get_current_user_id();
+    if ui.get_logged_in() && current.as_str() == user_id {
+        if let Some(room_id) = room_id {
+            rooms::reveal_room(ui, client, sidebar, &room_id);
+        }
+        return;
+    }
+    let Some(saved) = SessionManager::get_remembered_profiles()
+        .into_iter()
+        .find(|s| s.user_id == user_id)
+    else {
+        toast::show(ui, "That account is no longer saved");
+        return;
+    };
+    // Where to go back to if the switch fails.
+    let previous = Some((current, ui.get_current_display_name()))
+        .filter(|(user_id, _)| !user_id.is_empty() && accounts::is_held(user_id));
+
+    leave_account(ui, client, sidebar);
+    let reply = client.switch_account(saved.clone());
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(()) => {
+                    accounts::open_after_switch(room_id);
+                    enter_app(&ui, &client, &sidebar, &saved.user_id, &saved.display_name);
+                    println!("Switched to {}", saved.user_id);
+                }
+                Err(e) => {
+                    tracing::warn!("Account switch failed: {}", e);
+                    toast::show(
+                        &ui,
+                        format!("Could not switch to {}: {}", saved.display_name, e),
+                    );
+                    match previous {
+                        Some((user_id, display_name)) => {
+                            enter_app(&ui, &client, &sidebar, &user_id, &display_name)
+                        }
+                        None => show_login(&ui),
+                    }
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// List the saved accounts on the login screen and, but for the one shown,
+/// in the account switcher.
+fn refresh_saved_profiles(ui: &AppWindow) {
+    let profiles: Vec<SavedProfile> = SessionManager::get_remembered_profiles()
+        .iter()
+        .map(|s| SavedProfile {
+            user_id: SharedString::from(s.user_id.as_str()),
+            display_name: SharedString::from(s.display_name.as_str()),
+            homeserver: SharedString::from(s.homeserver.as_str()),
+            signed_in: accounts::is_held(&s.user_id),
+            initials: profile::avatar_initials(&s.display_name, &s.user_id),
+            avatar_color: profile::avatar_color(&s.user_id),
+            session_text: SharedString::from(saved_sessions::card_text(&s.user_id, &s.details)),
+            expired: saved_sessions::is_expired(&s.user_id),
+        })
+        .collect();
+    let current = ui.get_current_user_id();
+    let others: Vec<SavedProfile> = profiles
+        .iter()
+        .filter(|p| p.user_id != current)
+        .cloned()
+        .collect();
+    ui.set_saved_profiles(Rc::new(VecModel::from(profiles)).into());
+    ui.set_other_accounts(Rc::new(VecModel::from(others)).into());
+}
+
+fn set_register_errors(ui: &AppWindow, errors: &register::FormErrors) {
+    ui.set_register_errors(RegisterErrors {
+        username: SharedString::from(errors.username.as_str()),
+        password: SharedString::from(errors.password.as_str()),
+        confirm: SharedString::from(errors.confirm.as_str()),
+        form: SharedString::from(errors.form.as_str()),
+        needs_browser: errors.needs_browser,
+    });
+}
+
+fn handle_tray_command(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    voice: &VoiceManager,
+    command: TrayCommand,
+) {
+    match command {
+        TrayCommand::SetStatus(status) => profile::set_status(ui, client, status),
+        TrayCommand::ToggleMute => toggle_mute(voice),
+        TrayCommand::ToggleDeafen => toggle_deafen(voice),
+        TrayCommand::Show => {
+            if let Err(e) = ui.show() {
+                tracing::warn!("Failed to show window: {}", e);
+            }
+            focus_window(ui);
+        }
+        TrayCommand::Quit => request_quit(ui),
+    }
+}
+
+fn toggle_mute(voice: &VoiceManager) {
+    voice.set_muted(!voice.is_muted());
+    tray::update(|state| state.muted = voice.is_muted());
+}
+
+fn toggle_deafen(voice: &VoiceManager) {
+    voice.set_deafened(!voice.is_deafened());
+    tray::update(|state| state.deafened = voice.is_deafened());
+}
+
+/// Run the action bound to keys the window saw; `pressed` is false only
+/// for push-to-talk's release. Returns false to leave the keys to the
+/// widgets, e.g. the up arrow in a composer with text in it.
+fn handle_shortcut(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    voice: &VoiceManager,
+    action: Action,
+    pressed: bool,
+) -> bool {
+    match action {
+        // Where the global hotkey is unavailable, or our window has it.
+        Action::PushToTalk => voice.set_ptt_pressed(pressed),
+        Action::ToggleMute => toggle_mute(voice),
+        Action::ToggleDeafen => toggle_deafen(voice),
+        Action::NextUnread | Action::PreviousUnread => {
+            let next = sidebar
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|sb| sb.next_unread(action == Action::NextUnread))
+                .map(|room| room.id.clone());
+            if let Some(room_id) = next {
+                rooms::reveal_room(ui, client, sidebar, &room_id);
+            }
+        }
+        Action::EditLastMessage => {
+            let composing = ui.get_composer_focused()
+                && ui.get_composer_text().is_empty()
+                && ui.get_composer_edit_id().is_empty();
+            return composing && message_actions::edit_last(ui);
+        }
+        Action::QuickSwitcher => {
+            if ui.get_show_switcher() {
+                ui.set_show_switcher(false);
+            } else {
+                quick_switcher::open(ui);
+            }
+        }
+        Action::SearchMessages => {
+            if ui.get_show_message_search() {
+                message_search::close(ui);
+            } else {
+                message_search::open(ui);
+            }
+        }
+        // Where the global hotkey has it, it toggles already.
+        Action::ToggleOverlay => {
+            if !shortcuts::overlay_key_is_global() {
+                overlay::toggle(ui);
+            }
+        }
+    }
+    true
+}
+
+/// Raise desktop notifications for mentions and DMs to `user_id`, whether
+/// or not it is the account shown, until it logs out.
+fn start_notifications(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    user_id: &str,
+    display_name: &str,
+) {
+    let Some(signed_in) = accounts::start_notifying(user_id) else {
+        return;
+    };
+    let subscription = client.notifications();
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    let user_id = user_id.to_string();
+    let display_name = display_name.to_string();
+
+    tokio::spawn(async move {
+        let mut events = match subscription.await {
+            Ok(events) => events,
+            Err(e) => {
+                tracing::warn!("Failed to subscribe to notifications: {}", e);
+                return;
+            }
+        };
+        let notifier = Arc::new(Mutex::new(Notifier::default()));
+
+        loop {
+            let event = match signed_in.run(events.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            };
+
+            let ui_handle = ui_handle.clone();
+            let client = client.clone();
+            let sidebar = sidebar.clone();
+            let notifier = notifier.clone();
+            let user_id = user_id.clone();
+            let display_name = display_name.clone();
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                // A keyword alerts like a mention, whether or not the
+                // server's push rules know it.
+                let mut event = event;
+                let keyword = keywords::find(&user_id, &event.body);
+                event.is_mention |= keyword.is_some();
+                let shown = ui.get_logged_in() && ui.get_current_user_id().as_str() == user_id;
+                let context = notifications::Context {
+                    window_focused: shown && window_focused(&ui),
+                    do_not_disturb: UserStatus::from_label(&ui.get_current_profile().status)
+                        == Some(UserStatus::DoNotDisturb)
+                        || quiet_hours::is_silencing(),
+                    // Only the shown account's rooms are at hand.
+                    room_muted: shown
+                        && sidebar
+                            .lock()
+                            .unwrap()
+                            .as_ref()
+                            .and_then(|sb| sb.room(&event.room_id))
+                            .is_some_and(|room| room.muted),
+                };
+                if !notifier
+                    .lock()
+                    .unwrap()
+                    .should_show(&event, context, Instant::now())
+                {
+                    return;
+                }
+
+                request_attention(&ui);
+                let silent = idle::mute_sounds();
+                let chimed = keyword.is_some_and(|k| k.sound) && !silent;
+                if chimed {
+                    keywords::chime();
+                }
+                // The room's own sound, if it has one, stands in for the
+                // system's.
+                let played = !silent && !chimed && room_sounds::play(&user_id, &event.room_id);
+                let mut summary = notifications::summary(&event, ui.get_notification_previews());
+                if !shown {
+                    summary = notifications::for_account(&summary, &display_name);
+                }
+                let room_id = event.room_id;
+                notifications::show(summary, silent || played, move || {
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_handle.upgrade() {
+                            focus_window(&ui);
+                            switch_account(&ui, &client, &sidebar, &user_id, Some(room_id));
+                        }
+                    })
+                    .ok();
+                });
+            })
+            .ok();
+        }
+    });
+}
+
+/// Whether the window has keyboard focus. Assumed focused on backends that
+/// cannot tell, so notifications are never shown over the app.
+fn window_focused(ui: &AppWindow) -> bool {
+    ui.window()
+        .with_winit_window(|window| window.has_focus())
+        .unwrap_or(true)
+}
+
+/// Flash the taskbar entry (or bounce the dock icon) until the window is
+/// focused, where the windowing system supports it.
+fn request_attention(ui: &AppWindow) {
+    ui.window().with_winit_window(|window| {
+        window.request_user_attention(Some(winit::window::UserAttentionType::Informational))
+    });
+}
+
+fn focus_window(ui: &AppWindow) {
+    ui.window().set_minimized(false);
+    ui.window()
+        .with_winit_window(|window| window.focus_window());
+}
+
+/// Open `url` the way its kind calls for: matrix.to links join the room or
+/// open the DM in-app, web links go to the browser, other schemes ask first
+/// and mailto: is refused.
+/// Send what was written in the composer, or run the command it holds.
+fn send_outgoing(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    outgoing: send_guard::Outgoing,
+) {
+    typing_indicator::message_sent(client);
+    let reply_to = outgoing.reply_to.as_deref();
+    match outgoing.command {
+        Command::Say(body) => {
+            rooms::send_message(ui, client, &body, outgoing.mentions, reply_to);
+            send_guard::sent(ui);
+        }
+        Command::Me(action) => {
+            slash_commands::me(ui, client, &action);
+            send_guard::sent(ui);
+        }
+        Command::Join(room) => slash_commands::join(ui, client, sidebar, &room),
+        Command::Msg { user_id, text } => slash_commands::msg(ui, client, sidebar, &user_id, text),
+        Command::Topic(topic) => slash_commands::topic(ui, client, &topic),
+        Command::Invite(user_id) => slash_commands::invite(ui, client, &user_id),
+    }
+}
+
+fn open_link(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, url: &str) {
+    let prompt = |message: &str, can_open: bool| {
+        ui.set_link_prompt_url(SharedString::from(url));
+        ui.set_link_prompt_message(SharedString::from(message));
+        ui.set_link_prompt_can_open(can_open);
+        ui.set_show_link_prompt(true);
+    };
+
+    match links::classify(url) {
+        Link::Web(url) => {
+            if let Err(e) = links::open_external(&url) {
+                tracing::warn!("{:#}", e);
+            }
+        }
+        Link::Other(_) => prompt("This link will be opened by another application.", true),
+        Link::Mail => prompt("Email links are not supported.", false),
+        Link::Invalid => prompt("This doesn't look like a valid link.", false),
+        Link::Matrix(link) => permalinks::open(ui, client, sidebar, link),
+    }
 }
@@ -0,0 +1,180 @@
+//! Finding room members as an @-mention is typed. Rooms can have thousands
+//! of members, so names and user ids are indexed once, by every suffix, and
+//! a keystroke's substring search is a binary search rather than a scan.
+
+use crate::members;
+use chat_core::{Member, UserStatus};
+use std::collections::HashSet;
+
+#[derive(Debug)]
+pub struct MemberIndex {
+    members: Vec<Member>,
+    /// Lowercased display names and user ids, with the member each is of.
+    keys: Vec<(String, usize)>,
+    /// Every suffix of every key as (key, byte offset), sorted by text, so
+    /// the suffixes starting with a query are one run.
+    suffixes: Vec<(u32, u32)>,
+    /// Members online first, then by name.
+    order: Vec<usize>,
+    /// Each member's place in `order`.
+    rank: Vec<usize>,
+}
+
+impl MemberIndex {
+    pub fn new(members: Vec<Member>) -> Self {
+        let mut keys = Vec::with_capacity(members.len() * 2);
+        for (i, member) in members.iter().enumerate() {
+            if let Some(name) = &member.display_name {
+                keys.push((name.to_lowercase(), i));
+            }
+            keys.push((member.user_id.to_lowercase(), i));
+        }
+        let mut suffixes: Vec<(u32, u32)> = keys
+            .iter()
+            .enumerate()
+            .flat_map(|(k, (key, _))| {
+                key.char_indices()
+                    .map(move |(offset, _)| (k as u32, offset as u32))
+            })
+            .collect();
+        suffixes.sort_unstable_by(|a, b| suffix(&keys, *a).cmp(suffix(&keys, *b)));
+
+        let mut index = Self {
+            members,
+            keys,
+            suffixes,
+            order: Vec::new(),
+            rank: Vec::new(),
+        };
+        index.sort();
+        index
+    }
+
+    fn sort(&mut self) {
+        let members = &self.members;
+        let mut order: Vec<usize> = (0..members.len()).collect();
+        order.sort_by_cached_key(|&i| {
+            let member = &members[i];
+            (
+                members::status_rank(member.status),
+                member.name().to_lowercase(),
+                member.user_id.clone(),
+            )
+        });
+        self.rank = vec![0; order.len()];
+        for (place, &i) in order.iter().enumerate() {
+            self.rank[i] = place;
+        }
+        self.order = order;
+    }
+
+    /// Up to `limit` members whose name or user id contains `query`, in any
+    /// case, online members first. An empty query matches everyone.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&Member> {
+        let query = query.to_lowercase();
+        if query.is_empty() {
+            return self
+                .order
+                .iter()
+                .take(limit)
+                .map(|&i| &self.members[i])
+                .collect();
+        }
+        let first = self
+            .suffixes
+            .partition_point(|&s| suffix(&self.keys, s) < query.as_str());
+        let matched: HashSet<usize> = self.suffixes[first..]
+            .iter()
+            .take_while(|&&s| suffix(&self.keys, s).starts_with(&query))
+            .map(|&(k, _)| self.keys[k as usize].1)
+            .collect();
+        let mut found: Vec<usize> = matched.into_iter().collect();
+        found.sort_unstable_by_key(|&i| self.rank[i]);
+        found
+            .into_iter()
+            .take(limit)
+            .map(|i| &self.members[i])
+            .collect()
+    }
+
+    /// Record a presence change, if `user_id` is a member.
+    pub fn set_status(&mut self, user_id: &str, status: UserStatus, message: Option<&str>) {
+        if members::set_status(&mut self.members, user_id, status, message) {
+            self.sort();
+        }
+    }
+}
+
+fn suffix(keys: &[(String, usize)], (k, offset): (u32, u32)) -> &str {
+    &keys[k as usize].0[offset as usize..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::MemberRole;
+
+    fn member(user_id: &str, name: Option<&str>, status: UserStatus) -> Member {
+        Member {
+            user_id: user_id.to_string(),
+            display_name: name.map(str::to_string),
+            avatar_url: None,
+            role: MemberRole::Member,
+            status,
+            status_message: None,
+        }
+    }
+
+    fn ids(members: &[&Member]) -> Vec<String> {
+        members.iter().map(|m| m.user_id.clone()).collect()
+    }
+
+    fn index() -> MemberIndex {
+        MemberIndex::new(vec![
+            member("@alice:x.org", Some("Alice"), UserStatus::Offline),
+            member("@bob:x.org", Some("Bob Malice"), UserStatus::Online),
+            member("@carol:x.org", None, UserStatus::Idle),
+            member("@dave:y.org", Some("Dave"), UserStatus::Online),
+        ])
+    }
+
+    #[test]
+    fn test_search_matches_names_and_ids_online_first() {
+        let index = index();
+        assert_eq!(
+            ids(&index.search("LIC", 10)),
+            ["@bob:x.org", "@alice:x.org"]
+        );
+        assert_eq!(ids(&index.search("aro", 10)), ["@carol:x.org"]);
+        assert_eq!(ids(&index.search("y.org", 10)), ["@dave:y.org"]);
+        assert_eq!(index.search("x.org", 2).len(), 2);
+        assert!(index.search("zed", 10).is_empty());
+        assert_eq!(
+            ids(&index.search("", 10)),
+            ["@bob:x.org", "@dave:y.org", "@carol:x.org", "@alice:x.org"]
+        );
+    }
+
+    #[test]
+    fn test_presence_changes_order() {
+        let mut index = index();
+        index.set_status("@alice:x.org", UserStatus::Online, None);
+        assert_eq!(
+            ids(&index.search("lic", 10)),
+            ["@alice:x.org", "@bob:x.org"]
+        );
+    }
+
+    #[test]
+    fn test_large_rooms() {
+        let members: Vec<Member> = (0..5000)
+            .map(|i| {
+                let name = format!("Player {}", i);
+                member(&format!("@p{}:x.org", i), Some(&name), UserStatus::Offline)
+            })
+            .collect();
+        let index = MemberIndex::new(members);
+        assert_eq!(ids(&index.search("layer 4999", 5)), ["@p4999:x.org"]);
+        assert_eq!(index.search("p12", 200).len(), 111);
+    }
+}
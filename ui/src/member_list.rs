@@ -0,0 +1,300 @@
+//! The member list of the open room, kept current from membership, power
+//! level and presence events, and the popup shown for a member.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{members, profile, room_security, sender_trust};
+use crate::{AppWindow, MemberData, MemberGroupData, MemberPopupData, RoomMemberData};
+use chat_core::permalink;
+use chat_core::{Member, UserStatus};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct MemberView {
+    /// The room whose members are listed.
+    room_id: Option<String>,
+    /// Sorted with `members::sort`.
+    members: Vec<Member>,
+    total: u64,
+    /// The user asked to see everyone; refreshes fetch the full list too.
+    show_all: bool,
+}
+
+thread_local! {
+    static VIEW: RefCell<MemberView> = RefCell::new(MemberView::default());
+}
+
+fn is_current(room_id: &str) -> bool {
+    VIEW.with(|v| v.borrow().room_id.as_deref() == Some(room_id))
+}
+
+fn member_data(member: &Member) -> RoomMemberData {
+    RoomMemberData {
+        user_id: SharedString::from(member.user_id.as_str()),
+        name: SharedString::from(member.name()),
+        status: SharedString::from(member.status.label()),
+        status_message: SharedString::from(member.status_message.as_deref().unwrap_or_default()),
+        initials: profile::avatar_initials(
+            member.display_name.as_deref().unwrap_or_default(),
+            &member.user_id,
+        ),
+        avatar_color: profile::avatar_color(&member.user_id),
+    }
+}
+
+/// Rebuild the member list and the admin panel's members from the view.
+pub fn render(ui: &AppWindow) {
+    VIEW.with(|v| {
+        let view = v.borrow();
+        let shown = members::page(&view.members, view.show_all);
+        let mut offset = 0;
+        let groups: Vec<MemberGroupData> = members::groups(shown)
+            .into_iter()
+            .map(|(role, run)| {
+                let group = MemberGroupData {
+                    title: SharedString::from(format!(
+                        "{} — {}",
+                        role.label().to_uppercase(),
+                        run.len()
+                    )),
+                    members: Rc::new(VecModel::from(
+                        run.iter().map(member_data).collect::<Vec<_>>(),
+                    ))
+                    .into(),
+                    offset,
+                };
+                offset += run.len() as i32;
+                group
+            })
+            .collect();
+        ui.set_member_groups(Rc::new(VecModel::from(groups)).into());
+        let hidden = view.total.saturating_sub(shown.len() as u64);
+        ui.set_member_list_hidden(hidden.min(i32::MAX as u64) as i32);
+        ui.set_member_list_total(view.total.min(i32::MAX as u64) as i32);
+
+        let admin_members: Vec<MemberData> = view
+            .members
+            .iter()
+            .map(|m| MemberData {
+                user_id: SharedString::from(m.user_id.as_str()),
+                username: SharedString::from(m.name()),
+                role: SharedString::from(m.role.label()),
+            })
+            .collect();
+        ui.set_members(Rc::new(VecModel::from(admin_members)).into());
+    });
+}
+
+/// List the members of the room just opened.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    VIEW.with(|v| {
+        *v.borrow_mut() = MemberView {
+            room_id: Some(room_id.to_string()),
+            ..Default::default()
+        }
+    });
+    ui.set_member_list_loading(false);
+    render(ui);
+    fetch(ui, client, room_id, false);
+}
+
+/// Load the room's members and show them if it is still open.
+fn fetch(ui: &AppWindow, client: &ClientHandle, room_id: &str, full: bool) {
+    let reply = client.get_room_members(room_id, full);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if !is_current(&room_id) {
+                return;
+            }
+            if full {
+                ui.set_member_list_loading(false);
+            }
+            let list = match result {
+                Ok(list) => list,
+                Err(e) => {
+                    tracing::warn!("Failed to load members: {}", e);
+                    return;
+                }
+            };
+            VIEW.with(|v| {
+                let mut view = v.borrow_mut();
+                view.members = list.members;
+                members::sort(&mut view.members);
+                // Lazy loading can know of members the count does not
+                // include yet, e.g. ones who just joined.
+                view.total = list.total.max(view.members.len() as u64);
+            });
+            render(&ui);
+        })
+        .ok();
+    });
+}
+
+/// Show a fixed list with no room behind it, for the demo.
+pub fn show_members(ui: &AppWindow, mut members: Vec<Member>) {
+    members::sort(&mut members);
+    VIEW.with(|v| {
+        *v.borrow_mut() = MemberView {
+            total: members.len() as u64,
+            members,
+            ..Default::default()
+        }
+    });
+    render(ui);
+}
+
+/// Fetch the full member list and show every member.
+pub fn show_all(ui: &AppWindow, client: &ClientHandle) {
+    let Some(room_id) = VIEW.with(|v| {
+        let mut view = v.borrow_mut();
+        view.show_all = true;
+        view.room_id.clone()
+    }) else {
+        return;
+    };
+    ui.set_member_list_loading(true);
+    render(ui);
+    fetch(ui, client, &room_id, true);
+}
+
+/// Servers for a link to `room_id` to join it through, from its members
+/// if it is the open room.
+pub fn via(room_id: &str) -> Vec<String> {
+    VIEW.with(|v| {
+        let view = v.borrow();
+        let members = if view.room_id.as_deref() == Some(room_id) {
+            view.members.as_slice()
+        } else {
+            &[]
+        };
+        permalink::via_servers(room_id, members)
+    })
+}
+
+/// Someone joined or left `room_id`, or their role or name changed.
+pub fn members_changed(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    if !is_current(room_id) {
+        return;
+    }
+    let full = VIEW.with(|v| v.borrow().show_all);
+    fetch(ui, client, room_id, full);
+}
+
+pub fn presence_changed(ui: &AppWindow, user_id: &str, status: UserStatus, message: Option<&str>) {
+    let listed = VIEW.with(|v| {
+        let mut view = v.borrow_mut();
+        let listed = members::set_status(&mut view.members, user_id, status, message);
+        if listed {
+            members::sort(&mut view.members);
+        }
+        listed
+    });
+    if listed {
+        render(ui);
+    }
+}
+
+/// How far `user_id` is trusted changed: update their popup if it is open.
+pub fn trust_changed(ui: &AppWindow, user_id: &str) {
+    let mut popup = ui.get_member_popup();
+    if !ui.get_show_member_popup() || popup.user_id.as_str() != user_id {
+        return;
+    }
+    popup.keys_changed = sender_trust::keys_changed(user_id);
+    ui.set_member_popup(popup);
+}
+
+/// Show the popup for a listed member and load their avatar.
+pub fn open_member(ui: &AppWindow, client: &ClientHandle, user_id: &str) {
+    let (room_id, member) = VIEW.with(|v| {
+        let view = v.borrow();
+        let member = view.members.iter().find(|m| m.user_id == user_id).cloned();
+        (view.room_id.clone(), member)
+    });
+    let (Some(room_id), Some(member)) = (room_id, member) else {
+        return;
+    };
+    ui.set_member_popup(MemberPopupData {
+        user_id: SharedString::from(member.user_id.as_str()),
+        name: SharedString::from(member.name()),
+        role: SharedString::from(member.role.label()),
+        status: SharedString::from(member.status.label()),
+        status_message: SharedString::from(member.status_message.as_deref().unwrap_or_default()),
+        avatar: Default::default(),
+        initials: profile::avatar_initials(
+            member.display_name.as_deref().unwrap_or_default(),
+            &member.user_id,
+        ),
+        avatar_color: profile::avatar_color(&member.user_id),
+        unverified: room_security::is_unverified(&member.user_id),
+        keys_changed: sender_trust::keys_changed(&member.user_id),
+    });
+    ui.set_show_member_popup(true);
+    if member.avatar_url.is_none() {
+        return;
+    }
+
+    let reply = client.get_member_avatar(&room_id, user_id);
+    let user_id = user_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let data = match reply.await {
+            Ok(Some(data)) => data,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!("Failed to load avatar: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let mut popup = ui.get_member_popup();
+            if popup.user_id.as_str() != user_id {
+                return;
+            }
+            popup.avatar = profile::decode_avatar(&data).unwrap_or_default();
+            ui.set_member_popup(popup);
+        })
+        .ok();
+    });
+}
+
+/// Open the direct message room with `user_id`, creating it if needed.
+pub fn message(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, user_id: &str) {
+    ui.set_show_member_popup(false);
+    let reply = client.open_dm(user_id);
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(room_id) => rooms::reveal_room(&ui, &client, &sidebar, &room_id),
+                Err(e) => tracing::warn!("Failed to open direct message: {}", e),
+            }
+        })
+        .ok();
+    });
+}
+
+/// Forget the listed members, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    VIEW.with(|v| *v.borrow_mut() = MemberView::default());
+    ui.set_show_member_popup(false);
+    ui.set_member_list_loading(false);
+    render(ui);
+}
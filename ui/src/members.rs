@@ -0,0 +1,145 @@
+//! Ordering for the member list: grouped by role, online members first
+//! within a group, then by name. Large rooms show a first page of members
+//! until the user asks for all of them.
+
+use chat_core::{Member, MemberRole, UserStatus};
+use std::cmp::Ordering;
+
+/// Members shown before the list offers to show everyone.
+pub const MEMBER_PAGE_SIZE: usize = 200;
+
+/// Online first, offline last.
+pub fn status_rank(status: UserStatus) -> u8 {
+    match status {
+        UserStatus::Online => 0,
+        UserStatus::Idle => 1,
+        UserStatus::DoNotDisturb => 2,
+        UserStatus::Offline => 3,
+    }
+}
+
+fn compare(a: &Member, b: &Member) -> Ordering {
+    a.role
+        .cmp(&b.role)
+        .then_with(|| status_rank(a.status).cmp(&status_rank(b.status)))
+        .then_with(|| a.name().to_lowercase().cmp(&b.name().to_lowercase()))
+        .then_with(|| a.user_id.cmp(&b.user_id))
+}
+
+pub fn sort(members: &mut [Member]) {
+    members.sort_by(compare);
+}
+
+/// The members to show, given sorted `members`: the first page unless
+/// `show_all`.
+pub fn page(members: &[Member], show_all: bool) -> &[Member] {
+    if show_all {
+        members
+    } else {
+        &members[..members.len().min(MEMBER_PAGE_SIZE)]
+    }
+}
+
+/// Split sorted members into runs sharing a role.
+pub fn groups(members: &[Member]) -> Vec<(MemberRole, &[Member])> {
+    members
+        .chunk_by(|a, b| a.role == b.role)
+        .map(|run| (run[0].role, run))
+        .collect()
+}
+
+/// Record a presence change. Returns whether `user_id` is listed; the list
+/// then needs sorting again.
+pub fn set_status(
+    members: &mut [Member],
+    user_id: &str,
+    status: UserStatus,
+    message: Option<&str>,
+) -> bool {
+    match members.iter_mut().find(|m| m.user_id == user_id) {
+        Some(member) => {
+            member.status = status;
+            member.status_message = message.map(str::to_owned);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, role: MemberRole, status: UserStatus) -> Member {
+        Member {
+            user_id: format!("@{}:example.org", name.to_lowercase()),
+            display_name: Some(name.to_string()),
+            avatar_url: None,
+            role,
+            status,
+            status_message: None,
+        }
+    }
+
+    fn names(members: &[Member]) -> Vec<&str> {
+        members.iter().map(Member::name).collect()
+    }
+
+    #[test]
+    fn test_grouped_by_role_online_first() {
+        let mut members = vec![
+            member("zed", MemberRole::Member, UserStatus::Online),
+            member("Amy", MemberRole::Member, UserStatus::Offline),
+            member("bob", MemberRole::Member, UserStatus::Online),
+            member("Root", MemberRole::Admin, UserStatus::Offline),
+            member("mod", MemberRole::Moderator, UserStatus::Idle),
+        ];
+        sort(&mut members);
+        assert_eq!(names(&members), ["Root", "mod", "bob", "zed", "Amy"]);
+
+        let groups = groups(&members);
+        let roles: Vec<_> = groups
+            .iter()
+            .map(|(role, run)| (*role, run.len()))
+            .collect();
+        assert_eq!(
+            roles,
+            [
+                (MemberRole::Admin, 1),
+                (MemberRole::Moderator, 1),
+                (MemberRole::Member, 3)
+            ]
+        );
+
+        assert!(set_status(
+            &mut members,
+            "@amy:example.org",
+            UserStatus::Online,
+            Some("Playing Dota 2")
+        ));
+        assert!(!set_status(
+            &mut members,
+            "@carol:example.org",
+            UserStatus::Online,
+            None
+        ));
+        sort(&mut members);
+        assert_eq!(names(&members), ["Root", "mod", "Amy", "bob", "zed"]);
+    }
+
+    #[test]
+    fn test_large_rooms_show_a_first_page() {
+        let members: Vec<_> = (0..MEMBER_PAGE_SIZE + 5)
+            .map(|i| {
+                member(
+                    &format!("user{}", i),
+                    MemberRole::Member,
+                    UserStatus::Online,
+                )
+            })
+            .collect();
+        assert_eq!(page(&members, false).len(), MEMBER_PAGE_SIZE);
+        assert_eq!(page(&members, true).len(), MEMBER_PAGE_SIZE + 5);
+        assert_eq!(page(&members[..3], false).len(), 3);
+    }
+}
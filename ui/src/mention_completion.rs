@@ -0,0 +1,272 @@
+//! @-mentions in the composer: the open room's members offered as an
+//! `@name` is typed, and the pills that keep each completed mention on its
+//! name until the message is sent with them.
+
+use crate::app_state::ClientHandle;
+use crate::member_index::MemberIndex;
+use crate::{composer, history, profile, AppWindow, RoomMemberData};
+use chat_core::mention::{self, Mention, Pills};
+use chat_core::{Member, UserStatus};
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{Key, NamedKey};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Members offered at once.
+const MAX_COMPLETIONS: usize = 8;
+
+#[derive(Default)]
+struct Mentions {
+    /// The room whose members are indexed, and the index once loaded.
+    room_id: Option<String>,
+    index: Option<MemberIndex>,
+    /// The composer as last seen, to tell where it was edited.
+    text: String,
+    caret: usize,
+    pills: Pills,
+    /// Byte range of the `@query` being completed.
+    typed: Option<(usize, usize)>,
+    completions: Vec<Member>,
+    selected: usize,
+}
+
+thread_local! {
+    static MENTIONS: RefCell<Mentions> = RefCell::new(Mentions::default());
+}
+
+fn render(ui: &AppWindow) {
+    let (rows, selected) = MENTIONS.with(|m| {
+        let mentions = m.borrow();
+        let rows: Vec<RoomMemberData> = mentions
+            .completions
+            .iter()
+            .map(|member| RoomMemberData {
+                user_id: SharedString::from(member.user_id.as_str()),
+                name: SharedString::from(member.name()),
+                status: SharedString::from(member.status.label()),
+                status_message: SharedString::from(
+                    member.status_message.as_deref().unwrap_or_default(),
+                ),
+                initials: profile::avatar_initials(
+                    member.display_name.as_deref().unwrap_or_default(),
+                    &member.user_id,
+                ),
+                avatar_color: profile::avatar_color(&member.user_id),
+            })
+            .collect();
+        (rows, mentions.selected)
+    });
+    ui.set_mention_completions(Rc::new(VecModel::from(rows)).into());
+    ui.set_mention_completion_index(selected as i32);
+}
+
+/// Offer members for the `@query` before the caret, loading the open room's
+/// members first if they are not indexed yet.
+fn complete(ui: &AppWindow, client: &ClientHandle) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let load = MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        let mentions = &mut *mentions;
+        mentions.selected = 0;
+        mentions.completions.clear();
+        let pills = mentions.pills.mentions();
+        let typed = mention::query(&mentions.text, mentions.caret)
+            // A pill's own '@' does not start a new mention.
+            .filter(|(start, _)| !pills.iter().any(|m| m.start <= *start && *start < m.end))
+            .map(|(start, query)| (start, query.to_string()));
+        let Some((start, query)) = typed else {
+            mentions.typed = None;
+            return false;
+        };
+        mentions.typed = Some((start, mentions.caret));
+        if mentions.room_id.as_deref() != Some(room_id.as_str()) {
+            mentions.room_id = Some(room_id.clone());
+            mentions.index = None;
+            return true;
+        }
+        if let Some(index) = &mentions.index {
+            let found = index.search(&query, MAX_COMPLETIONS);
+            mentions.completions = found.into_iter().cloned().collect();
+        }
+        false
+    });
+    render(ui);
+    if load {
+        fetch(ui, client, room_id);
+    }
+}
+
+/// Index every member of `room_id`, off the UI thread, then complete
+/// whatever has been typed meanwhile.
+fn fetch(ui: &AppWindow, client: &ClientHandle, room_id: String) {
+    let reply = client.get_room_members(&room_id, true);
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let index = match reply.await {
+            Ok(list) => MemberIndex::new(list.members),
+            Err(e) => {
+                tracing::warn!("Failed to load members to mention: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let current = MENTIONS.with(|m| {
+                let mut mentions = m.borrow_mut();
+                let current = mentions.room_id.as_deref() == Some(room_id.as_str());
+                if current {
+                    mentions.index = Some(index);
+                }
+                current
+            });
+            if let Some(ui) = ui_handle.upgrade().filter(|_| current) {
+                complete(&ui, &client);
+            }
+        })
+        .ok();
+    });
+}
+
+/// The composer changed: move pills along with the text around them, and
+/// offer members for an `@query` before the caret.
+pub fn composer_edited(ui: &AppWindow, client: &ClientHandle, text: &str, caret: usize) {
+    text_set(text, caret);
+    complete(ui, client);
+}
+
+/// The composer text was replaced from code, e.g. by an emoji.
+pub fn text_set(text: &str, caret: usize) {
+    MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        let old = std::mem::replace(&mut mentions.text, text.to_string());
+        mentions.pills.edited(&old, text);
+        mentions.caret = caret;
+    });
+}
+
+/// Hide the member list until the composer is edited again.
+pub fn dismiss(ui: &AppWindow) {
+    MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        mentions.typed = None;
+        mentions.completions.clear();
+    });
+    render(ui);
+}
+
+/// Replace the `@query` typed with a pill for member `index`.
+pub fn accept(ui: &AppWindow, index: usize) {
+    let chosen = MENTIONS.with(|m| {
+        let mentions = m.borrow();
+        let member = mentions.completions.get(index)?.clone();
+        let (start, end) = mentions.typed?;
+        Some((member, mentions.text.clone(), start, end))
+    });
+    dismiss(ui);
+    let Some((member, text, start, end)) = chosen else {
+        return;
+    };
+    // The composer changed since; the range may no longer be the query.
+    if ui.get_composer_text() != text.as_str() {
+        return;
+    }
+    let name = format!("@{}", member.name());
+    let text = format!("{}{} {}", &text[..start], name, &text[end..]);
+    composer::set_text(ui, text, start + name.len() + 1);
+    MENTIONS.with(|m| {
+        m.borrow_mut().pills.insert(Mention {
+            user_id: member.user_id,
+            start,
+            end: start + name.len(),
+        })
+    });
+}
+
+fn move_selection(ui: &AppWindow, down: bool) {
+    MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        let count = mentions.completions.len();
+        mentions.selected = if down {
+            (mentions.selected + 1) % count
+        } else {
+            (mentions.selected + count - 1) % count
+        };
+    });
+    render(ui);
+}
+
+/// Arrows move through the members, Tab or Enter takes one and Escape
+/// hides them. Returns true when the key was used.
+pub fn key_pressed(ui: &AppWindow, event: &KeyEvent) -> bool {
+    let showing = MENTIONS.with(|m| !m.borrow().completions.is_empty());
+    if !showing || event.state != ElementState::Pressed || !ui.get_composer_focused() {
+        return false;
+    }
+    match &event.logical_key {
+        Key::Named(NamedKey::ArrowUp) => move_selection(ui, false),
+        Key::Named(NamedKey::ArrowDown) => move_selection(ui, true),
+        Key::Named(NamedKey::Tab | NamedKey::Enter) => {
+            let selected = MENTIONS.with(|m| m.borrow().selected);
+            accept(ui, selected);
+        }
+        Key::Named(NamedKey::Escape) => dismiss(ui),
+        _ => return false,
+    }
+    true
+}
+
+/// The mentions in `text`, which is being sent; the composer starts over
+/// empty.
+pub fn take(ui: &AppWindow, text: &str) -> Vec<Mention> {
+    let taken = MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        mentions.text.clear();
+        mentions.caret = 0;
+        mentions.pills.take(text)
+    });
+    dismiss(ui);
+    taken
+}
+
+/// A message taken to be sent was put back in the composer as `text`,
+/// with the mentions it was taken with.
+pub fn restore(text: &str, taken: Vec<Mention>) {
+    MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        mentions.text = text.to_string();
+        mentions.caret = text.len();
+        mentions.pills.clear();
+        for mention in taken {
+            mentions.pills.insert(mention);
+        }
+    });
+}
+
+/// Members of `room_id` joined, left or were renamed: index them afresh
+/// when next mentioned.
+pub fn members_changed(room_id: &str) {
+    MENTIONS.with(|m| {
+        let mut mentions = m.borrow_mut();
+        if mentions.room_id.as_deref() == Some(room_id) {
+            mentions.room_id = None;
+            mentions.index = None;
+        }
+    });
+}
+
+pub fn presence_changed(user_id: &str, status: UserStatus, message: Option<&str>) {
+    MENTIONS.with(|m| {
+        if let Some(index) = &mut m.borrow_mut().index {
+            index.set_status(user_id, status, message);
+        }
+    });
+}
+
+/// Forget the indexed members and the draft's pills, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    MENTIONS.with(|m| *m.borrow_mut() = Mentions::default());
+    render(ui);
+}
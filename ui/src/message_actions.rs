@@ -0,0 +1,214 @@
+//! Context menu actions on messages of the open room: reply, edit, delete,
+//! react and copy. The menu only offers what ownership and our power level
+//! allow, and each action checks again here; failures are shown as a toast.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, composer, history, i18n, send_guard, toast, AppWindow};
+use chat_core::Reaction;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::future::Future;
+
+thread_local! {
+    /// Kept open: on X11 the copied text is served by its owner, and goes
+    /// away with it.
+    static CLIPBOARD: RefCell<Option<arboard::Clipboard>> = const { RefCell::new(None) };
+}
+
+/// Reset the composer for the room just opened and load what we may do there.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    ui.set_composer_reply_id(SharedString::default());
+    ui.set_composer_reply_sender(SharedString::default());
+    ui.set_composer_edit_id(SharedString::default());
+    send_guard::show_wait(ui, room_id);
+    load_permissions(ui, client, room_id);
+}
+
+fn load_permissions(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    let reply = client.get_permissions(room_id);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if history::current_room().as_deref() != Some(room_id.as_str()) {
+                return;
+            }
+            match result {
+                Ok(permissions) => {
+                    ui.set_can_send_messages(permissions.send);
+                    ui.set_can_react(permissions.react);
+                    ui.set_can_redact_others(permissions.redact_others);
+                    ui.set_can_edit_topic(permissions.topic);
+                    ui.set_can_pin(permissions.pin);
+                    send_guard::set_slow_mode(&ui, &room_id, permissions.slow_mode_secs);
+                }
+                Err(e) => tracing::warn!("Failed to load permissions: {}", e),
+            }
+        })
+        .ok();
+    });
+}
+
+/// Power levels may have changed in `room_id`.
+pub fn members_changed(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    if history::current_room().as_deref() == Some(room_id) {
+        load_permissions(ui, client, room_id);
+    }
+}
+
+/// Run `reply` for the open room, toasting a failure as the message
+/// `failed`. `then`
+/// runs on the UI thread with the result if it succeeded.
+fn run<T: Send + 'static>(
+    ui: &AppWindow,
+    reply: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+    failed: &'static str,
+    then: impl FnOnce(T) + Send + 'static,
+) {
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || match result {
+            Ok(value) => then(value),
+            Err(e) => {
+                tracing::warn!("{}: {}", failed, e);
+                if let Some(ui) = ui_handle.upgrade() {
+                    toast::show(&ui, admin::error_text(&e, failed));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Whether the open room's message `event_id` was sent by us.
+fn is_own(room_id: &str, event_id: &str) -> bool {
+    history::with_message(room_id, event_id, |timeline| {
+        timeline
+            .message(event_id)
+            .is_some_and(|m| m.sender == timeline.own_user_id())
+    })
+    .unwrap_or(false)
+}
+
+/// Replace the body of our message `event_id`.
+pub fn edit(ui: &AppWindow, client: &ClientHandle, event_id: &str, body: &str) {
+    let body = body.trim();
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    if body.is_empty() || !is_own(&room_id, event_id) {
+        return;
+    }
+    let unchanged = history::with_message(&room_id, event_id, |timeline| {
+        timeline
+            .message(event_id)
+            .is_some_and(|m| m.content == body)
+    });
+    if unchanged == Some(true) {
+        return;
+    }
+
+    let reply = client.edit_message(&room_id, event_id, body);
+    let own_user_id = ui.get_current_user_id().to_string();
+    let event_id = event_id.to_string();
+    let body = body.to_string();
+    run(ui, reply, "failed-edit-message", move |_| {
+        history::for_each_timeline(&room_id, |timeline| {
+            timeline.edit(&event_id, &own_user_id, &body, None)
+        });
+    });
+}
+
+/// Start editing our newest message in the open room, as its menu would.
+/// Returns false if there is none.
+pub fn edit_last(ui: &AppWindow) -> bool {
+    let Some(room_id) = history::current_room() else {
+        return false;
+    };
+    let Some(message) = history::with_room(&room_id, |timeline| timeline.last_editable()).flatten()
+    else {
+        return false;
+    };
+    ui.set_composer_reply_id(SharedString::default());
+    ui.set_composer_reply_sender(SharedString::default());
+    ui.set_composer_edit_id(SharedString::from(message.id.as_str()));
+    let caret = message.content.len();
+    composer::set_text(ui, message.content, caret);
+    true
+}
+
+/// Delete `event_id` after the user confirmed it.
+pub fn delete(ui: &AppWindow, client: &ClientHandle, event_id: &str) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    if !is_own(&room_id, event_id) && !ui.get_can_redact_others() {
+        toast::show(ui, i18n::tr("delete-not-allowed"));
+        return;
+    }
+    let reply = client.delete_message(&room_id, event_id);
+    let event_id = event_id.to_string();
+    run(ui, reply, "failed-delete-message", move |_| {
+        history::for_each_timeline(&room_id, |timeline| timeline.redact(&event_id));
+    });
+}
+
+/// React to `event_id` with `key`, or take our reaction back if we had one.
+pub fn react(ui: &AppWindow, client: &ClientHandle, event_id: &str, key: &str) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    if !ui.get_can_react() {
+        return;
+    }
+    let own = history::with_message(&room_id, event_id, |timeline| {
+        timeline.own_reaction(event_id, key)
+    })
+    .flatten();
+    if let Some(reaction_id) = own {
+        let reply = client.delete_message(&room_id, &reaction_id);
+        run(ui, reply, "failed-remove-reaction", move |_| {
+            history::for_each_timeline(&room_id, |timeline| timeline.redact(&reaction_id));
+        });
+        return;
+    }
+
+    let reply = client.react_to_message(&room_id, event_id, key);
+    let reaction = Reaction {
+        key: key.to_string(),
+        sender: ui.get_current_user_id().to_string(),
+        event_id: String::new(),
+    };
+    let event_id = event_id.to_string();
+    run(ui, reply, "failed-add-reaction", move |reaction_id| {
+        let reaction = Reaction {
+            event_id: reaction_id,
+            ..reaction
+        };
+        history::for_each_timeline(&room_id, |timeline| {
+            timeline.react(&event_id, reaction.clone())
+        });
+    });
+}
+
+pub fn copy(ui: &AppWindow, text: &str) {
+    let result = CLIPBOARD.with(|clipboard| {
+        let mut clipboard = clipboard.borrow_mut();
+        if clipboard.is_none() {
+            *clipboard = Some(arboard::Clipboard::new()?);
+        }
+        clipboard.as_mut().unwrap().set_text(text)
+    });
+    if let Err(e) = result {
+        tracing::warn!("Failed to copy: {}", e);
+        toast::show(
+            ui,
+            i18n::tr_args("failed-copy", [("error", e.to_string().into())]),
+        );
+    }
+}
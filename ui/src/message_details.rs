@@ -0,0 +1,111 @@
+//! The details of one message, opened from its menu or its timestamp:
+//! exact sent time in the local time zone, event id, sender MXID, when it
+//! was edited and how it was encrypted. What the timeline holds shows at
+//! once; the edit time and encryption come from the server after.
+
+use crate::app_state::ClientHandle;
+use crate::{history, AppWindow, MessageDetailsData, SenderVerification};
+use chat_core::{time, Message};
+use network::details::EventDetails;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+
+thread_local! {
+    /// The event whose details are open, so a late answer for another
+    /// one is dropped.
+    static OPEN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// What the timeline knows of `message`, while the rest loads.
+fn cached(message: &Message) -> MessageDetailsData {
+    MessageDetailsData {
+        event_id: SharedString::from(message.id.as_str()),
+        sender: SharedString::from(message.sender_display()),
+        sender_id: SharedString::from(message.sender.as_str()),
+        sent: SharedString::from(time::format_exact(message.timestamp)),
+        edited: message.edited,
+        loading: true,
+        ..MessageDetailsData::default()
+    }
+}
+
+/// Fill in `data` with what the server said of the event.
+fn apply(data: &mut MessageDetailsData, details: &EventDetails) {
+    data.event_id = SharedString::from(details.event_id.as_str());
+    data.sender_id = SharedString::from(details.sender.as_str());
+    if data.sender.is_empty() {
+        data.sender = SharedString::from(chat_core::localpart(&details.sender));
+    }
+    data.sent = SharedString::from(time::format_exact(details.sent_at));
+    if let Some(edited_at) = details.edited_at {
+        data.edited = true;
+        data.edited_at = SharedString::from(time::format_exact(edited_at));
+    }
+    let Some(encryption) = &details.encryption else {
+        data.encrypted = false;
+        return;
+    };
+    data.encrypted = true;
+    data.algorithm = SharedString::from(encryption.algorithm.as_str());
+    data.session_id = SharedString::from(encryption.session_id.as_deref().unwrap_or_default());
+    data.device_id = SharedString::from(encryption.sender_device.as_deref().unwrap_or_default());
+    data.verification = match encryption.verified {
+        Some(true) => SenderVerification::Verified,
+        Some(false) => SenderVerification::Unverified,
+        None => SenderVerification::Unknown,
+    };
+}
+
+/// Show the details of message `event_id`, as far as known, and ask the
+/// server for the rest.
+pub fn open(ui: &AppWindow, client: &ClientHandle, event_id: &str) {
+    let Some(room_id) = history::room_of(event_id).or_else(history::current_room) else {
+        return;
+    };
+    let message =
+        history::with_message(&room_id, event_id, |timeline| timeline.message(event_id)).flatten();
+    let data = match &message {
+        Some(message) => cached(message),
+        None => MessageDetailsData {
+            event_id: SharedString::from(event_id),
+            loading: true,
+            ..MessageDetailsData::default()
+        },
+    };
+    OPEN.with(|o| *o.borrow_mut() = Some(event_id.to_string()));
+    ui.set_message_details(data);
+    ui.set_show_message_details(true);
+
+    let reply = client.get_event_details(&room_id, event_id);
+    let event_id = event_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if OPEN.with(|o| o.borrow().as_deref() != Some(event_id.as_str())) {
+                return;
+            }
+            let mut data = ui.get_message_details();
+            data.loading = false;
+            match result {
+                Ok(details) => apply(&mut data, &details),
+                Err(e) => {
+                    tracing::warn!("Failed to load the message details: {:#}", e);
+                    data.error =
+                        SharedString::from("Couldn't ask the server; only what was loaded shows.");
+                }
+            }
+            ui.set_message_details(data);
+        })
+        .ok();
+    });
+}
+
+/// Close the details, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    OPEN.with(|o| *o.borrow_mut() = None);
+    ui.set_show_message_details(false);
+}
@@ -0,0 +1,265 @@
+//! Message search: an overlay that finds messages by their words. Messages
+//! loaded this session answer as you type; once typing pauses the
+//! homeserver is asked too, and what it finds beyond them is added below.
+//! Picking a result opens the room it is in at that message.
+
+use crate::app_state::ClientHandle;
+use crate::history;
+use crate::rooms::{self, SharedSidebar};
+use crate::{AppWindow, MessageSearchItemData};
+use chat_core::time::format_timestamp;
+use network::cancel::CancelToken;
+use network::search::{merge_hits, SearchHit};
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{Key, NamedKey};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long typing must pause before the homeserver is asked.
+const SERVER_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+struct Search {
+    query: String,
+    /// Only the room open when searching.
+    this_room: bool,
+    results: Vec<SearchHit>,
+    selected: usize,
+    /// Counts searches, so answers to an earlier one are dropped.
+    generation: u64,
+    /// The server search under way.
+    cancel: Option<CancelToken>,
+}
+
+thread_local! {
+    static SEARCH: RefCell<Search> = RefCell::new(Search::default());
+}
+
+/// Forget the last search on logout.
+pub fn clear(ui: &AppWindow) {
+    let search = SEARCH.with(|s| std::mem::take(&mut *s.borrow_mut()));
+    if let Some(cancel) = search.cancel {
+        cancel.cancel();
+    }
+    ui.set_show_message_search(false);
+    ui.set_message_search_this_room(false);
+    ui.set_message_search_items(Rc::new(VecModel::default()).into());
+    ui.set_message_search_status(SharedString::new());
+}
+
+/// Open the search afresh, keeping whether it was for this room only.
+pub fn open(ui: &AppWindow) {
+    SEARCH.with(|s| {
+        let mut search = s.borrow_mut();
+        search.query.clear();
+        search.results.clear();
+        search.selected = 0;
+    });
+    ui.set_message_search_items(Rc::new(VecModel::default()).into());
+    ui.set_message_search_index(0);
+    ui.set_message_search_status(SharedString::new());
+    ui.set_show_message_search(true);
+}
+
+/// Close the overlay, abandoning a server search still going.
+pub fn close(ui: &AppWindow) {
+    SEARCH.with(|s| {
+        if let Some(cancel) = s.borrow_mut().cancel.take() {
+            cancel.cancel();
+        }
+    });
+    ui.set_show_message_search(false);
+}
+
+fn item(hit: &SearchHit, sidebar: &SharedSidebar) -> MessageSearchItemData {
+    let room = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(&hit.room_id).map(|room| room.name.clone()))
+        .unwrap_or_else(|| hit.room_id.clone());
+    MessageSearchItemData {
+        room: SharedString::from(room),
+        sender: SharedString::from(hit.message.sender_display()),
+        body: SharedString::from(hit.message.content.replace('\n', " ")),
+        time: SharedString::from(format_timestamp(hit.message.timestamp)),
+    }
+}
+
+fn show(ui: &AppWindow, sidebar: &SharedSidebar, results: Vec<SearchHit>) {
+    let items: Vec<MessageSearchItemData> = results.iter().map(|hit| item(hit, sidebar)).collect();
+    SEARCH.with(|s| {
+        let mut search = s.borrow_mut();
+        search.results = results;
+        search.selected = 0;
+    });
+    ui.set_message_search_items(Rc::new(VecModel::from(items)).into());
+    ui.set_message_search_index(0);
+}
+
+fn is_current(generation: u64) -> bool {
+    SEARCH.with(|s| s.borrow().generation == generation)
+}
+
+/// Search for `query`: what was loaded here at once, then the server's.
+pub fn search(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, query: &str) {
+    let (generation, cancel, room_id) = SEARCH.with(|s| {
+        let mut search = s.borrow_mut();
+        if let Some(cancel) = search.cancel.take() {
+            cancel.cancel();
+        }
+        search.query = query.to_string();
+        search.generation += 1;
+        let cancel = CancelToken::new();
+        search.cancel = Some(cancel.clone());
+        let room_id = if search.this_room {
+            history::current_room()
+        } else {
+            None
+        };
+        (search.generation, cancel, room_id)
+    });
+    if query.trim().is_empty() {
+        show(ui, sidebar, Vec::new());
+        ui.set_message_search_status(SharedString::new());
+        return;
+    }
+
+    let local = client.search_local(query, room_id.clone());
+    let client = client.clone();
+    let query = query.to_string();
+    let ui_handle = ui.as_weak();
+    let sidebar = sidebar.clone();
+    tokio::spawn(async move {
+        let local = local.await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to search messages: {}", e);
+            Vec::new()
+        });
+        {
+            let ui_handle = ui_handle.clone();
+            let sidebar = sidebar.clone();
+            let local = local.clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                if !is_current(generation) {
+                    return;
+                }
+                show(&ui, &sidebar, local);
+                ui.set_message_search_status(SharedString::from("Searching the server…"));
+            });
+        }
+
+        if cancel.run(tokio::time::sleep(SERVER_DELAY)).await.is_err() {
+            return;
+        }
+        let server = client.search_server(&query, room_id, cancel).await;
+        let _ = slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if !is_current(generation) {
+                return;
+            }
+            match server {
+                Ok(server) => {
+                    let selected = SEARCH.with(|s| s.borrow().selected);
+                    show(&ui, &sidebar, merge_hits(local, server));
+                    SEARCH.with(|s| s.borrow_mut().selected = selected);
+                    ui.set_message_search_index(selected as i32);
+                    ui.set_message_search_status(SharedString::new());
+                }
+                Err(e) => {
+                    tracing::warn!("Server search failed: {}", e);
+                    ui.set_message_search_status(SharedString::from(
+                        "The server could not search; showing messages loaded here",
+                    ));
+                }
+            }
+        });
+    });
+}
+
+/// Search only the open room, or everywhere, again for the same words.
+pub fn set_this_room(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    this_room: bool,
+) {
+    let query = SEARCH.with(|s| {
+        let mut search = s.borrow_mut();
+        search.this_room = this_room;
+        search.query.clone()
+    });
+    ui.set_message_search_this_room(this_room);
+    search(ui, client, sidebar, &query);
+}
+
+/// Index the latest messages of every room afresh, for when results seem
+/// to be missing some.
+pub fn rebuild(ui: &AppWindow, client: &ClientHandle) {
+    let reply = client.rebuild_search_index();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        let _ = slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let status = match result {
+                Ok(()) => "Indexing the latest messages of every room…".to_string(),
+                Err(e) => format!("Could not rebuild the index: {}", e),
+            };
+            ui.set_message_search_status(SharedString::from(status));
+        });
+    });
+}
+
+/// Open the room of result `index` and jump to the message.
+pub fn choose(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, index: usize) {
+    let hit = SEARCH.with(|s| s.borrow().results.get(index).cloned());
+    close(ui);
+    if let Some(hit) = hit {
+        rooms::reveal_room(ui, client, sidebar, &hit.room_id);
+        history::jump_to(ui, client, &hit.message.id);
+    }
+}
+
+fn move_selection(ui: &AppWindow, down: bool) {
+    let selected = SEARCH.with(|s| {
+        let mut search = s.borrow_mut();
+        let count = search.results.len();
+        if count > 0 {
+            search.selected = if down {
+                (search.selected + 1) % count
+            } else {
+                (search.selected + count - 1) % count
+            };
+        }
+        search.selected
+    });
+    ui.set_message_search_index(selected as i32);
+}
+
+/// While the search is open, arrows move through the results, Enter opens
+/// one and Escape closes it. Returns true when the key was used.
+pub fn key_pressed(ui: &AppWindow, event: &KeyEvent) -> bool {
+    if !ui.get_show_message_search() || event.state != ElementState::Pressed {
+        return false;
+    }
+    match &event.logical_key {
+        Key::Named(NamedKey::ArrowUp) => move_selection(ui, false),
+        Key::Named(NamedKey::ArrowDown) => move_selection(ui, true),
+        Key::Named(NamedKey::Enter) => {
+            let selected = SEARCH.with(|s| s.borrow().selected);
+            ui.invoke_message_search_choose(selected as i32);
+        }
+        Key::Named(NamedKey::Escape) => close(ui),
+        _ => return false,
+    }
+    true
+}
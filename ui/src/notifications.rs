@@ -0,0 +1,200 @@
+//! Desktop notifications for mentions and direct messages. Which events
+//! become a notification is decided here; raising the window and flashing
+//! the taskbar is left to the caller.
+
+use network::events::NotificationEvent;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// At most one notification per room within this window.
+pub const COALESCE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Longest message preview shown, in characters.
+const PREVIEW_CHARS: usize = 100;
+
+/// UI state a notification is checked against when it arrives.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Context {
+    pub window_focused: bool,
+    pub do_not_disturb: bool,
+    pub room_muted: bool,
+}
+
+/// Filters and rate-limits incoming notification events.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    last_shown: HashMap<String, Instant>,
+}
+
+impl Notifier {
+    /// Whether `event` should be shown now. Only mentions and direct
+    /// messages qualify, and never while the window is focused, the user is
+    /// on Do Not Disturb, or the room is muted. Bursts in one room collapse
+    /// into the first notification.
+    pub fn should_show(
+        &mut self,
+        event: &NotificationEvent,
+        context: Context,
+        now: Instant,
+    ) -> bool {
+        if !(event.is_mention || event.is_direct)
+            || context.window_focused
+            || context.do_not_disturb
+            || context.room_muted
+        {
+            return false;
+        }
+        if let Some(last) = self.last_shown.get(&event.room_id) {
+            if now.saturating_duration_since(*last) < COALESCE_WINDOW {
+                return false;
+            }
+        }
+        self.last_shown.insert(event.room_id.clone(), now);
+        true
+    }
+}
+
+/// "sender — preview (room)", without the room for direct messages. With
+/// previews off the message text is replaced by a generic line.
+pub fn summary(event: &NotificationEvent, show_preview: bool) -> String {
+    let preview = if show_preview {
+        preview(&event.body)
+    } else if event.is_mention {
+        "mentioned you".to_string()
+    } else {
+        "sent you a message".to_string()
+    };
+    if event.is_direct {
+        format!("{} — {}", event.sender_name, preview)
+    } else {
+        format!("{} — {} ({})", event.sender_name, preview, event.room_name)
+    }
+}
+
+/// `summary` for an account other than the one shown, saying which.
+pub fn for_account(summary: &str, account: &str) -> String {
+    format!("{} · to {}", summary, account)
+}
+
+/// First line of `body`, cut to `PREVIEW_CHARS`.
+fn preview(body: &str) -> String {
+    let line = body.lines().next().unwrap_or_default().trim();
+    match line.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// Show a native notification. `on_click` runs on a background thread when
+/// the user activates it, where the platform reports that (XDG only). A
+/// `silent` one asks the notification server to play no sound (XDG only).
+pub fn show(summary: String, silent: bool, on_click: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(move || {
+        let mut notification = notify_rust::Notification::new();
+        notification.appname("GameChat").summary(&summary);
+
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            if silent {
+                notification.hint(notify_rust::Hint::SuppressSound(true));
+            }
+            notification.action("default", "Open");
+            match notification.show() {
+                Ok(handle) => handle.wait_for_action(|action| {
+                    if action == "default" {
+                        on_click();
+                    }
+                }),
+                Err(e) => tracing::warn!("Failed to show notification: {}", e),
+            }
+        }
+
+        #[cfg(not(all(unix, not(target_os = "macos"))))]
+        {
+            let _ = (on_click, silent);
+            if let Err(e) = notification.show() {
+                tracing::warn!("Failed to show notification: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(room_id: &str, is_direct: bool, is_mention: bool) -> NotificationEvent {
+        NotificationEvent {
+            room_id: room_id.to_string(),
+            room_name: "general".to_string(),
+            sender_name: "Bob".to_string(),
+            body: "hey @alice, ready?\nsecond line".to_string(),
+            is_direct,
+            is_mention,
+        }
+    }
+
+    #[test]
+    fn test_bursts_are_coalesced_per_room() {
+        let mut notifier = Notifier::default();
+        let start = Instant::now();
+        let mention = event("!a:x", false, true);
+
+        assert!(notifier.should_show(&mention, Context::default(), start));
+        let soon = start + Duration::from_secs(1);
+        assert!(!notifier.should_show(&mention, Context::default(), soon));
+        assert!(notifier.should_show(&event("!b:x", true, false), Context::default(), soon));
+
+        let later = start + COALESCE_WINDOW;
+        assert!(notifier.should_show(&mention, Context::default(), later));
+    }
+
+    #[test]
+    fn test_only_unattended_mentions_and_dms_notify() {
+        let mut notifier = Notifier::default();
+        let now = Instant::now();
+        assert!(!notifier.should_show(&event("!a:x", false, false), Context::default(), now));
+
+        let dm = event("!dm:x", true, false);
+        for context in [
+            Context {
+                window_focused: true,
+                ..Default::default()
+            },
+            Context {
+                do_not_disturb: true,
+                ..Default::default()
+            },
+            Context {
+                room_muted: true,
+                ..Default::default()
+            },
+        ] {
+            assert!(!notifier.should_show(&dm, context, now));
+        }
+        // Suppressed events do not start the coalescing window.
+        assert!(notifier.should_show(&dm, Context::default(), now));
+    }
+
+    #[test]
+    fn test_summary_hides_preview_when_disabled() {
+        let mention = event("!a:x", false, true);
+        assert_eq!(
+            summary(&mention, true),
+            "Bob — hey @alice, ready? (general)"
+        );
+        assert_eq!(summary(&mention, false), "Bob — mentioned you (general)");
+        assert_eq!(
+            summary(&event("!dm:x", true, false), false),
+            "Bob — sent you a message"
+        );
+
+        assert_eq!(
+            for_account(&summary(&mention, false), "Alt"),
+            "Bob — mentioned you (general) · to Alt"
+        );
+
+        let long = "x".repeat(PREVIEW_CHARS + 5);
+        assert_eq!(preview(&long).chars().count(), PREVIEW_CHARS + 1);
+    }
+}
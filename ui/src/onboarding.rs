@@ -0,0 +1,114 @@
+//! The first run: a word on what a homeserver is, picking one and checking
+//! it answers, then on to logging in or registering there. The server
+//! picked is remembered for the login screen, and once anything is saved
+//! the flow does not come back.
+
+use crate::app_state::ClientHandle;
+use crate::{AppWindow, OnboardingChoice, ServerRegistration, ServerSupportData};
+use network::config::{Config, ConfigManager, LoginPreferences};
+use network::register::Registration;
+use network::session::SessionManager;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+
+/// Offered to people who do not know a server yet.
+const SUGGESTED_HOMESERVER: &str = "matrix.org";
+
+thread_local! {
+    /// The server that last checked out, as typed.
+    static CHECKED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Nothing was ever saved: no session, no settings.
+fn is_first_run() -> bool {
+    SessionManager::get_remembered_profiles().is_empty() && !ConfigManager::exists()
+}
+
+/// Show the onboarding on the first run; otherwise start the login screen
+/// on the server picked back then.
+pub fn start(ui: &AppWindow) {
+    if let Some(homeserver) = ConfigManager::load().login.homeserver {
+        ui.set_login_homeserver(SharedString::from(homeserver));
+    }
+    if is_first_run() {
+        ui.set_onboarding_suggested_server(SharedString::from(SUGGESTED_HOMESERVER));
+        ui.set_onboarding_step(0);
+        ui.set_show_onboarding(true);
+    }
+}
+
+fn registration(registration: Registration) -> ServerRegistration {
+    match registration {
+        Registration::InApp => ServerRegistration::InApp,
+        Registration::NeedsBrowser => ServerRegistration::NeedsBrowser,
+        Registration::Disabled => ServerRegistration::Disabled,
+    }
+}
+
+/// Look `server` up through its discovery file and ask what it supports.
+pub fn check_server(ui: &AppWindow, client: &ClientHandle, server: &str) {
+    let server = server.trim().to_string();
+    if server.is_empty() {
+        ui.set_onboarding_error(SharedString::from("Enter a homeserver"));
+        return;
+    }
+    ui.set_onboarding_error(SharedString::from(""));
+    ui.set_onboarding_checking(true);
+
+    let reply = client.check_server(&server);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            ui.set_onboarding_checking(false);
+            match result {
+                Ok(support) => {
+                    ui.set_onboarding_support(ServerSupportData {
+                        homeserver: SharedString::from(support.homeserver),
+                        password_login: support.password_login,
+                        registration: registration(support.registration),
+                    });
+                    CHECKED.with(|c| *c.borrow_mut() = Some(server));
+                    ui.set_onboarding_step(2);
+                }
+                Err(e) => {
+                    tracing::warn!("Homeserver check failed: {:#}", e);
+                    ui.set_onboarding_error(SharedString::from(format!(
+                        "Could not reach a Matrix server at {}",
+                        server
+                    )));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Leave the onboarding for the login or register screen. Returns true
+/// when registering has to continue in the browser.
+pub fn finish(ui: &AppWindow, choice: OnboardingChoice) -> bool {
+    let homeserver = match choice {
+        OnboardingChoice::Skip => None,
+        _ => CHECKED.with(|c| c.borrow_mut().take()),
+    };
+    // Saving marks the first run as done, skipped or not.
+    let config = Config {
+        login: LoginPreferences {
+            homeserver: homeserver.clone(),
+        },
+        ..ConfigManager::load()
+    };
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save login preferences: {:#}", e);
+    }
+
+    if let Some(homeserver) = homeserver {
+        ui.set_login_homeserver(SharedString::from(homeserver));
+    }
+    ui.set_register_mode(choice == OnboardingChoice::Register);
+    ui.set_show_onboarding(false);
+    choice == OnboardingChoice::RegisterInBrowser
+}
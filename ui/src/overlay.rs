@@ -0,0 +1,245 @@
+//! The overlay: a slim window kept above a game in borderless windowed
+//! mode, showing the voice channel the sidebar shows, who in it is
+//! speaking, and the latest messages of its room. It shares the main
+//! window's models, so sync and voice events update both at once, and
+//! showing or hiding it leaves the main window as it was.
+
+use crate::app_state::ClientHandle;
+use crate::{history, AppWindow, Overlay};
+use network::config::{ConfigManager, OverlayConfig, OverlayPosition};
+use slint::winit_030::WinitWindowAccessor;
+use slint::{ComponentHandle, ModelRc, PhysicalPosition};
+use std::cell::RefCell;
+
+/// The faintest it can be set to and still be read over a game.
+const MIN_OPACITY: f32 = 0.2;
+
+#[derive(Default)]
+struct OverlayState {
+    client: Option<ClientHandle>,
+    config: OverlayConfig,
+    window: Option<Overlay>,
+    /// The room of the voice channel the sidebar shows.
+    room_id: Option<String>,
+    /// The room whose messages the window was given.
+    showing: Option<String>,
+}
+
+thread_local! {
+    static OVERLAY: RefCell<OverlayState> = RefCell::new(OverlayState::default());
+}
+
+/// Fetch rooms' messages through `client` from now on.
+pub fn start(client: &ClientHandle) {
+    OVERLAY.with(|o| o.borrow_mut().client = Some(client.clone()));
+}
+
+/// How opaque to draw it for the `opacity` set; out of range is brought
+/// back in, and what isn't a number is the default.
+fn strength(opacity: f32) -> f32 {
+    if opacity.is_nan() {
+        return OverlayConfig::default().opacity;
+    }
+    opacity.clamp(MIN_OPACITY, 1.0)
+}
+
+/// Where a window at `from` goes when dragged by `dx`, `dy` logical pixels
+/// at `scale` physical pixels to one.
+fn dragged_to(from: PhysicalPosition, dx: f32, dy: f32, scale: f32) -> PhysicalPosition {
+    PhysicalPosition::new(
+        from.x + (dx * scale).round() as i32,
+        from.y + (dy * scale).round() as i32,
+    )
+}
+
+fn window() -> Option<Overlay> {
+    OVERLAY
+        .with(|o| o.borrow().window.as_ref().map(|w| w.as_weak()))
+        .and_then(|w| w.upgrade())
+}
+
+/// Draw `overlay` as `config` says, letting clicks through if it says so.
+/// It has to be shown for clicks to go through.
+fn apply(overlay: &Overlay, config: &OverlayConfig) {
+    overlay.set_strength(strength(config.opacity));
+    let hittest = !config.click_through;
+    let result = overlay
+        .window()
+        .with_winit_window(|window| window.set_cursor_hittest(hittest));
+    if let Some(Err(e)) = result {
+        tracing::warn!("Failed to let clicks through the overlay: {}", e);
+    }
+}
+
+/// Use `config` from now on, redrawing the overlay if it is shown.
+pub fn configure(config: &OverlayConfig) {
+    OVERLAY.with(|o| o.borrow_mut().config = config.clone());
+    if let Some(overlay) = window() {
+        apply(&overlay, config);
+    }
+}
+
+/// Remember where it was moved to, for next time.
+fn save_position(position: PhysicalPosition) {
+    let position = OverlayPosition {
+        x: position.x,
+        y: position.y,
+    };
+    OVERLAY.with(|o| o.borrow_mut().config.position = Some(position));
+    let mut config = ConfigManager::load();
+    if config.overlay.position == Some(position) {
+        return;
+    }
+    config.overlay.position = Some(position);
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the overlay's position: {:#}", e);
+    }
+}
+
+pub fn is_shown() -> bool {
+    OVERLAY.with(|o| o.borrow().window.is_some())
+}
+
+/// Show it where it was last left, signed in only.
+pub fn show(ui: &AppWindow) {
+    if !ui.get_logged_in() || is_shown() {
+        return;
+    }
+    let overlay = match Overlay::new() {
+        Ok(overlay) => overlay,
+        Err(e) => {
+            tracing::warn!("Failed to open the overlay: {}", e);
+            return;
+        }
+    };
+    let config = OVERLAY.with(|o| o.borrow().config.clone());
+    if let Some(position) = config.position {
+        overlay
+            .window()
+            .set_position(PhysicalPosition::new(position.x, position.y));
+    }
+
+    let overlay_handle = overlay.as_weak();
+    overlay.on_dragged(move |dx, dy| {
+        if let Some(overlay) = overlay_handle.upgrade() {
+            let window = overlay.window();
+            window.set_position(dragged_to(window.position(), dx, dy, window.scale_factor()));
+        }
+    });
+
+    let overlay_handle = overlay.as_weak();
+    overlay.on_drag_ended(move || {
+        if let Some(overlay) = overlay_handle.upgrade() {
+            save_position(overlay.window().position());
+        }
+    });
+
+    // Dropped once the close is done with, not from inside its handler.
+    overlay.on_dismiss(|| {
+        slint::invoke_from_event_loop(hide).ok();
+    });
+    overlay.window().on_close_requested(|| {
+        slint::invoke_from_event_loop(hide).ok();
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    if let Err(e) = overlay.show() {
+        tracing::warn!("Failed to show the overlay: {}", e);
+        return;
+    }
+    apply(&overlay, &config);
+    let room_id = OVERLAY.with(|o| {
+        let mut state = o.borrow_mut();
+        state.window = Some(overlay);
+        state.showing = None;
+        state.room_id.clone()
+    });
+    refresh(ui, room_id.as_deref());
+}
+
+pub fn hide() {
+    let overlay = OVERLAY.with(|o| {
+        let mut state = o.borrow_mut();
+        state.showing = None;
+        state.window.take()
+    });
+    if let Some(overlay) = overlay {
+        overlay.hide().ok();
+    }
+}
+
+/// Show it if it is hidden, otherwise hide it.
+pub fn toggle(ui: &AppWindow) {
+    if is_shown() {
+        hide();
+    } else {
+        show(ui);
+    }
+}
+
+/// We joined a voice channel: show it if the settings say so.
+pub fn joined_voice(ui: &AppWindow) {
+    if OVERLAY.with(|o| o.borrow().config.show_on_voice) {
+        show(ui);
+    }
+}
+
+/// Show the voice channel of `room_id` as the sidebar shows it, with the
+/// room's messages. Called whenever the sidebar's voice section changes.
+pub fn refresh(ui: &AppWindow, room_id: Option<&str>) {
+    let (client, changed) = OVERLAY.with(|o| {
+        let mut state = o.borrow_mut();
+        state.room_id = room_id.map(str::to_string);
+        let changed = state.window.is_some() && state.showing != state.room_id;
+        if changed {
+            state.showing = state.room_id.clone();
+        }
+        (state.client.clone(), changed)
+    });
+    let Some(overlay) = window() else {
+        return;
+    };
+    overlay.set_channel_name(ui.get_voice_channel_name());
+    overlay.set_in_voice(ui.get_voice_active());
+    overlay.set_voice_users(ui.get_voice_users());
+    if !changed {
+        return;
+    }
+    let messages = match (room_id, client) {
+        (Some(room_id), Some(client)) => ModelRc::from(history::shared_model(ui, &client, room_id)),
+        _ => ModelRc::default(),
+    };
+    overlay.set_messages(messages);
+}
+
+/// Hide it and forget the room, e.g. on logout.
+pub fn clear() {
+    hide();
+    OVERLAY.with(|o| o.borrow_mut().room_id = None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strength_stays_readable() {
+        assert_eq!(strength(0.5), 0.5);
+        assert_eq!(strength(0.0), MIN_OPACITY);
+        assert_eq!(strength(3.0), 1.0);
+        assert_eq!(strength(f32::NAN), OverlayConfig::default().opacity);
+    }
+
+    #[test]
+    fn test_dragged_to_scales_to_physical_pixels() {
+        let from = PhysicalPosition::new(100, 50);
+        assert_eq!(
+            dragged_to(from, 10.0, -5.0, 1.0),
+            PhysicalPosition::new(110, 45)
+        );
+        assert_eq!(
+            dragged_to(from, 10.0, -5.0, 1.5),
+            PhysicalPosition::new(115, 42)
+        );
+    }
+}
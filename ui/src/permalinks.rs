@@ -0,0 +1,171 @@
+//! Links to messages, rooms and users: copied as matrix.to links from their
+//! menus, and opened in-app when clicked. A link to a room we are in opens
+//! it, to a message there jumps to it, and to anywhere else previews the
+//! room until it is joined; a user's link opens a direct message with them.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{history, knocks, member_list, message_actions, room_preview, toast, AppWindow};
+use chat_core::permalink::Permalink;
+use network::admin::AdminError;
+use network::federation::FederationError;
+use slint::ComponentHandle;
+use std::cell::RefCell;
+
+/// A room not joined yet that a link was clicked for, with the servers to
+/// join through and the message it pointed at, if any.
+struct Joining {
+    id_or_alias: String,
+    via: Vec<String>,
+    event_id: Option<String>,
+}
+
+thread_local! {
+    static JOINING: RefCell<Option<Joining>> = const { RefCell::new(None) };
+}
+
+/// Copy a link to message `event_id`, by room id as aliases can change.
+pub fn copy_message_link(ui: &AppWindow, event_id: &str) {
+    let Some(room_id) = history::room_of(event_id).or_else(history::current_room) else {
+        return;
+    };
+    let link = Permalink::Event {
+        via: member_list::via(&room_id),
+        id_or_alias: room_id,
+        event_id: event_id.to_string(),
+    };
+    message_actions::copy(ui, &link.matrix_to());
+}
+
+/// Copy a link to `room_id`: its alias when it has one.
+pub fn copy_room_link(ui: &AppWindow, sidebar: &SharedSidebar, room_id: &str) {
+    let alias = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(room_id)?.alias.clone());
+    let link = match alias {
+        Some(alias) => Permalink::Room {
+            id_or_alias: alias,
+            via: Vec::new(),
+        },
+        None => Permalink::Room {
+            id_or_alias: room_id.to_string(),
+            via: member_list::via(room_id),
+        },
+    };
+    message_actions::copy(ui, &link.matrix_to());
+}
+
+pub fn copy_user_link(ui: &AppWindow, user_id: &str) {
+    message_actions::copy(ui, &Permalink::User(user_id.to_string()).matrix_to());
+}
+
+/// Open `link`, clicked in a message or a topic.
+pub fn open(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, link: Permalink) {
+    let (id_or_alias, via, event_id) = match link {
+        Permalink::User(user_id) => {
+            let reply = client.open_dm(&user_id);
+            opened(ui, client, sidebar, reply, None);
+            return;
+        }
+        Permalink::Room { id_or_alias, via } => (id_or_alias, via, None),
+        Permalink::Event {
+            id_or_alias,
+            event_id,
+            via,
+        } => (id_or_alias, via, Some(event_id)),
+    };
+    let joined = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.find(&id_or_alias).map(|room| room.id.clone()));
+    if let Some(room_id) = joined {
+        show(ui, client, sidebar, &room_id, event_id.as_deref());
+        return;
+    }
+
+    room_preview::open(ui, client, &id_or_alias, via.clone());
+    JOINING.with(|j| {
+        *j.borrow_mut() = Some(Joining {
+            id_or_alias,
+            via,
+            event_id,
+        })
+    });
+}
+
+/// Join the room previewed, then open it.
+pub fn join(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar) {
+    let Some(joining) = JOINING.with(|j| j.borrow_mut().take()) else {
+        return;
+    };
+    room_preview::joining(ui);
+    let reply = client.join_room(&joining.id_or_alias, joining.via.clone());
+    opened(ui, client, sidebar, reply, Some(joining));
+}
+
+/// Open `room_id`, and jump to `event_id` in it if given.
+fn show(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    room_id: &str,
+    event_id: Option<&str>,
+) {
+    rooms::reveal_room(ui, client, sidebar, room_id);
+    if let Some(event_id) = event_id {
+        history::jump_to(ui, client, event_id);
+    }
+}
+
+/// Open the room `reply` joins or makes, from its preview if there was
+/// one. A room that can't be joined outright may take a knock.
+fn opened(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    reply: impl std::future::Future<Output = anyhow::Result<String>> + Send + 'static,
+    joining: Option<Joining>,
+) {
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match (result, joining) {
+                (Ok(room_id), joining) => {
+                    if joining.is_some() {
+                        room_preview::joined(&ui, &room_id);
+                    }
+                    let event_id = joining.and_then(|j| j.event_id);
+                    show(&ui, &client, &sidebar, &room_id, event_id.as_deref());
+                }
+                (Err(e), Some(joining))
+                    if matches!(e.downcast_ref(), Some(AdminError::Forbidden(_))) =>
+                {
+                    room_preview::close(&ui);
+                    knocks::prompt(&ui, &joining.id_or_alias, joining.via)
+                }
+                (Err(e), joining) => {
+                    // Kept, so the preview's button can try again.
+                    if let Some(joining) = joining {
+                        JOINING.with(|j| *j.borrow_mut() = Some(joining));
+                        room_preview::join_failed(&ui);
+                    }
+                    if e.is::<FederationError>() {
+                        toast::show(&ui, e.to_string());
+                    } else {
+                        tracing::warn!("Failed to open Matrix link: {}", e);
+                    }
+                }
+            }
+        })
+        .ok();
+    });
+}
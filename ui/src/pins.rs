@@ -0,0 +1,253 @@
+//! Messages pinned in the open room: their count in the header, lit up for
+//! a moment as new ones arrive from sync, and a dropdown listing them to
+//! jump to. The dropdown opens at once with what is loaded and fetches the
+//! rest one by one. Pinning and unpinning is offered to those whose power
+//! level allows it; the list follows once sync brings the change back.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, timeline, toast, AppWindow, MediaState, PinnedData};
+use chat_core::{time, Message};
+use slint::{ComponentHandle, SharedString, Timer, TimerMode, VecModel};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long the header's pin count stays lit up after a new pin.
+const NEW_PIN_FLASH: Duration = Duration::from_secs(3);
+
+enum Entry {
+    Loading,
+    Loaded(Box<Message>),
+    Failed,
+}
+
+#[derive(Default)]
+struct Pins {
+    room_id: String,
+    /// Oldest pin first.
+    pinned: Vec<String>,
+    /// Pinned messages fetched on their own, as they weren't loaded.
+    fetched: HashMap<String, Entry>,
+}
+
+thread_local! {
+    static PINS: RefCell<Pins> = RefCell::new(Pins::default());
+    static FLASH_TIMER: Timer = Timer::default();
+}
+
+/// Whether message `event_id` of the open room is pinned.
+pub fn is_pinned(event_id: &str) -> bool {
+    PINS.with(|p| p.borrow().pinned.iter().any(|id| id == event_id))
+}
+
+/// Load the pins of the room just opened.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    PINS.with(|p| {
+        *p.borrow_mut() = Pins {
+            room_id: room_id.to_string(),
+            ..Pins::default()
+        }
+    });
+    FLASH_TIMER.with(Timer::stop);
+    ui.set_pins_flash(false);
+    ui.set_pins_open(false);
+    show(ui);
+
+    let reply = client.get_pinned(room_id);
+    let room_id = room_id.to_string();
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(pinned) => {
+                    set_pinned(&ui, &client, &room_id, pinned);
+                }
+                Err(e) => tracing::warn!("Failed to load pinned messages: {}", e),
+            }
+        })
+        .ok();
+    });
+}
+
+/// Sync brought `room_id`'s pins. The header lights up if some are new.
+pub fn changed(ui: &AppWindow, client: &ClientHandle, room_id: &str, pinned: Vec<String>) {
+    if !set_pinned(ui, client, room_id, pinned) {
+        return;
+    }
+    ui.set_pins_flash(true);
+    let ui_handle = ui.as_weak();
+    FLASH_TIMER.with(|timer| {
+        timer.start(TimerMode::SingleShot, NEW_PIN_FLASH, move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_pins_flash(false);
+            }
+        })
+    });
+}
+
+/// Take `pinned` as the pins of `room_id`, if it is still open. Returns
+/// whether any of them is new.
+fn set_pinned(ui: &AppWindow, client: &ClientHandle, room_id: &str, pinned: Vec<String>) -> bool {
+    let Some(old) = PINS.with(|p| {
+        let mut state = p.borrow_mut();
+        (state.room_id == room_id).then(|| std::mem::replace(&mut state.pinned, pinned.clone()))
+    }) else {
+        return false;
+    };
+    // Their rows offer Pin or Unpin, which changed.
+    for event_id in pinned.iter().filter(|id| !old.contains(id)) {
+        history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+    }
+    for event_id in old.iter().filter(|id| !pinned.contains(id)) {
+        history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+    }
+    show(ui);
+    if ui.get_pins_open() {
+        fetch_missing(ui, client);
+    }
+    pinned.iter().any(|id| !old.contains(id))
+}
+
+/// Open the dropdown: list the pins, newest first, and fetch the ones that
+/// aren't loaded.
+pub fn open(ui: &AppWindow, client: &ClientHandle) {
+    ui.set_pins_open(true);
+    show(ui);
+    fetch_missing(ui, client);
+}
+
+/// Fetch the pinned messages that aren't loaded, or failed to before.
+fn fetch_missing(ui: &AppWindow, client: &ClientHandle) {
+    let (room_id, missing) = PINS.with(|p| {
+        let mut state = p.borrow_mut();
+        let room_id = state.room_id.clone();
+        let missing: Vec<String> = state
+            .pinned
+            .iter()
+            .filter(|id| {
+                loaded(&room_id, id).is_none()
+                    && matches!(state.fetched.get(*id), None | Some(Entry::Failed))
+            })
+            .cloned()
+            .collect();
+        for event_id in &missing {
+            state.fetched.insert(event_id.clone(), Entry::Loading);
+        }
+        (room_id, missing)
+    });
+    for event_id in missing {
+        fetch(ui, client, &room_id, event_id);
+    }
+}
+
+fn fetch(ui: &AppWindow, client: &ClientHandle, room_id: &str, event_id: String) {
+    let reply = client.get_event(room_id, &event_id);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let entry = match result {
+                Ok(message) => Entry::Loaded(Box::new(message)),
+                Err(e) => {
+                    tracing::warn!("Failed to load pinned message {}: {}", event_id, e);
+                    Entry::Failed
+                }
+            };
+            let current = PINS.with(|p| {
+                let mut state = p.borrow_mut();
+                // Another room was opened meanwhile.
+                let current = state.room_id == room_id;
+                if current {
+                    state.fetched.insert(event_id, entry);
+                }
+                current
+            });
+            if let Some(ui) = ui_handle.upgrade().filter(|_| current) {
+                show(&ui);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Message `event_id` of `room_id`, if its timeline has it.
+fn loaded(room_id: &str, event_id: &str) -> Option<Message> {
+    history::with_message(room_id, event_id, |timeline| timeline.message(event_id)).flatten()
+}
+
+/// Show the pin count, and the list if the dropdown is open.
+fn show(ui: &AppWindow) {
+    let rows: Vec<PinnedData> = PINS.with(|p| {
+        let state = p.borrow();
+        ui.set_pinned_count(state.pinned.len().min(i32::MAX as usize) as i32);
+        if !ui.get_pins_open() {
+            return Vec::new();
+        }
+        state
+            .pinned
+            .iter()
+            .rev()
+            .map(|event_id| match loaded(&state.room_id, event_id) {
+                Some(message) => row(event_id, Some(&Entry::Loaded(Box::new(message)))),
+                None => row(event_id, state.fetched.get(event_id)),
+            })
+            .collect()
+    });
+    ui.set_pinned_messages(Rc::new(VecModel::from(rows)).into());
+}
+
+fn row(event_id: &str, entry: Option<&Entry>) -> PinnedData {
+    let mut data = PinnedData {
+        id: SharedString::from(event_id),
+        ..Default::default()
+    };
+    match entry {
+        Some(Entry::Loaded(message)) => {
+            data.state = MediaState::Ready;
+            data.sender = SharedString::from(message.sender_display());
+            data.timestamp = SharedString::from(time::format_timestamp(message.timestamp));
+            data.blocks = timeline::blocks(message);
+        }
+        Some(Entry::Failed) => data.state = MediaState::Failed,
+        Some(Entry::Loading) | None => data.state = MediaState::Loading,
+    }
+    data
+}
+
+/// Pin message `event_id` of the open room, or unpin it.
+pub fn set(ui: &AppWindow, client: &ClientHandle, event_id: &str, pin: bool) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let reply = client.pin_message(&room_id, event_id, pin);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Err(e) = reply.await else {
+            return;
+        };
+        let failed = if pin { "failed-pin" } else { "failed-unpin" };
+        tracing::warn!("{}: {}", failed, e);
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                toast::show(&ui, admin::error_text(&e, failed));
+            }
+        })
+        .ok();
+    });
+}
+
+/// Forget the pins, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    PINS.with(|p| *p.borrow_mut() = Pins::default());
+    FLASH_TIMER.with(Timer::stop);
+    ui.set_pins_flash(false);
+    ui.set_pins_open(false);
+    show(ui);
+}
@@ -0,0 +1,147 @@
+//! Direct messages popped out into small windows of their own, to keep a
+//! conversation in view over a game. A popout shows the room's cached
+//! timeline model, the one the main window shows, so sync updates every
+//! window at once. Closing a popout drops only its window.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{
+    animations, attachment_view, connection_status, history, link_previews, translations,
+    AppWindow, DmPopout,
+};
+use slint::{ComponentHandle, ModelRc, SharedString};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    /// By room id.
+    static POPOUTS: RefCell<HashMap<String, DmPopout>> = RefCell::new(HashMap::new());
+}
+
+/// Pop `room_id` out, or bring its popout forward if it is open already.
+pub fn open(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, room_id: &str) {
+    if let Some(popout) = POPOUTS.with(|p| p.borrow().get(room_id).map(|w| w.as_weak())) {
+        if let Some(popout) = popout.upgrade() {
+            if let Err(e) = popout.show() {
+                tracing::warn!("Failed to show popout: {}", e);
+            }
+        }
+        return;
+    }
+    let name = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(room_id).map(|room| room.name.clone()))
+        .unwrap_or_default();
+    let popout = match DmPopout::new() {
+        Ok(popout) => popout,
+        Err(e) => {
+            tracing::warn!("Failed to open popout: {}", e);
+            return;
+        }
+    };
+    popout.set_name(SharedString::from(name));
+    popout.set_offline(!connection_status::is_connected());
+    popout.set_messages(ModelRc::from(history::shared_model(ui, client, room_id)));
+    animations::prepare_popout(&popout);
+
+    let ui_handle = ui.as_weak();
+    let client_clone = client.clone();
+    let room = room_id.to_string();
+    popout.on_send_message(move |body| {
+        if let Some(ui) = ui_handle.upgrade() {
+            rooms::send_to(&ui, &client_clone, &room, &body, Vec::new(), None);
+        }
+    });
+
+    let client_clone = client.clone();
+    let room = room_id.to_string();
+    popout.on_retry_send(move |txn_id| rooms::retry(&client_clone, room.clone(), &txn_id));
+
+    let room = room_id.to_string();
+    popout.on_discard_send(move |txn_id| rooms::discard(&room, &txn_id));
+
+    let client_clone = client.clone();
+    popout.on_load_attachment(move |event_id| {
+        attachment_view::load_preview(&client_clone, &event_id);
+    });
+
+    let client_clone = client.clone();
+    popout.on_save_attachment(move |event_id| {
+        attachment_view::save_file(&client_clone, &event_id);
+    });
+
+    let client_clone = client.clone();
+    let sidebar_clone = sidebar.clone();
+    popout.on_load_link_preview(move |event_id| {
+        link_previews::load(&client_clone, &sidebar_clone, &event_id);
+    });
+
+    popout.on_toggle_translation(|event_id| translations::toggle(&event_id));
+    popout.on_load_translation(|event_id| translations::load(&event_id));
+    popout.on_start_animation(|event_id| animations::start(&event_id));
+    popout.on_stop_animation(|event_id| animations::stop(&event_id));
+
+    // Opened as from the main window, where matrix.to links are handled.
+    let ui_handle = ui.as_weak();
+    popout.on_open_link(move |url| {
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.invoke_open_link(url);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    popout.on_join_game_invite(move |connect| {
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.invoke_join_game_invite(connect);
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    popout.on_copy_text(move |text| {
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.invoke_copy_text(text);
+        }
+    });
+
+    // Dropped once the close is done with, not from inside its handler.
+    let room = room_id.to_string();
+    popout.window().on_close_requested(move || {
+        let room = room.clone();
+        slint::invoke_from_event_loop(move || {
+            POPOUTS.with(|p| p.borrow_mut().remove(&room));
+        })
+        .ok();
+        slint::CloseRequestResponse::HideWindow
+    });
+
+    if let Err(e) = popout.show() {
+        tracing::warn!("Failed to show popout: {}", e);
+        return;
+    }
+    POPOUTS.with(|p| p.borrow_mut().insert(room_id.to_string(), popout));
+}
+
+/// Mirror the connection state, as the main window's composer does.
+pub fn set_offline(offline: bool) {
+    POPOUTS.with(|p| {
+        for popout in p.borrow().values() {
+            popout.set_offline(offline);
+        }
+    });
+}
+
+/// Run `f` on each open popout.
+pub fn for_each(f: impl Fn(&DmPopout)) {
+    POPOUTS.with(|p| p.borrow().values().for_each(f));
+}
+
+/// Close every popout, e.g. on logout or switching accounts: the rooms
+/// they show are the old account's.
+pub fn clear() {
+    let popouts: Vec<DmPopout> = POPOUTS.with(|p| p.borrow_mut().drain().map(|(_, w)| w).collect());
+    for popout in popouts {
+        popout.hide().ok();
+    }
+}
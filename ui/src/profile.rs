@@ -0,0 +1,299 @@
+//! The profile dialog: loading our profile from the server, picking a new
+//! avatar, and saving each changed field with its own error.
+
+use crate::app_state::ClientHandle;
+use crate::{rich_presence, tray, AppWindow, ProfileErrors, UserProfileData};
+use chat_core::UserStatus;
+use network::config::{Config, ConfigManager};
+use network::profile::{avatar_mime, AVATAR_THUMBNAIL_SIZE, MAX_AVATAR_BYTES};
+use slint::{ComponentHandle, Image, Rgba8Pixel, SharedPixelBuffer, SharedString};
+use std::cell::RefCell;
+
+/// An avatar picked in the dialog but not uploaded yet.
+struct PendingAvatar {
+    data: Vec<u8>,
+    mime_type: &'static str,
+}
+
+thread_local! {
+    static PENDING_AVATAR: RefCell<Option<PendingAvatar>> = const { RefCell::new(None) };
+}
+
+/// Decode an encoded image into a thumbnail Slint can show.
+pub fn decode_avatar(data: &[u8]) -> Option<Image> {
+    let thumbnail = image::load_from_memory(data)
+        .ok()?
+        .thumbnail(AVATAR_THUMBNAIL_SIZE, AVATAR_THUMBNAIL_SIZE)
+        .into_rgba8();
+    let buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(
+        thumbnail.as_raw(),
+        thumbnail.width(),
+        thumbnail.height(),
+    );
+    Some(Image::from_rgba8(buffer))
+}
+
+/// The background of `user_id`'s generated avatar.
+pub fn avatar_color(user_id: &str) -> slint::Color {
+    let (r, g, b) = chat_core::avatar::color(user_id);
+    slint::Color::from_rgb_u8(r, g, b)
+}
+
+/// The letters on `user_id`'s generated avatar.
+pub fn avatar_initials(display_name: &str, user_id: &str) -> SharedString {
+    SharedString::from(chat_core::avatar::initials(display_name, user_id))
+}
+
+fn discard_pending(ui: &AppWindow) {
+    PENDING_AVATAR.with(|p| *p.borrow_mut() = None);
+    ui.set_profile_avatar_preview(Image::default());
+}
+
+/// Reset the dialog and fill it with the profile as the server has it.
+pub fn open(ui: &AppWindow, client: &ClientHandle) {
+    discard_pending(ui);
+    ui.set_profile_edit_mode(false);
+    ui.set_profile_errors(ProfileErrors::default());
+
+    let reply = client.get_profile();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let profile = match reply.await {
+            Ok(profile) => profile,
+            Err(e) => {
+                tracing::warn!("Failed to load profile: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let mut current = ui.get_current_profile();
+            if let Some(name) = profile.display_name {
+                ui.set_current_display_name(SharedString::from(name.as_str()));
+                current.initials = avatar_initials(&name, &ui.get_current_user_id());
+                current.username = SharedString::from(name);
+            }
+            current.avatar = profile
+                .avatar
+                .as_deref()
+                .and_then(decode_avatar)
+                .unwrap_or_default();
+            current.bio = SharedString::from(profile.bio);
+            ui.set_current_profile(current);
+        })
+        .ok();
+    });
+}
+
+/// Let the user choose an image file and preview it as the new avatar.
+pub fn pick_avatar(ui: &AppWindow) {
+    // Created here, on the UI thread, as some platforms require.
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Choose an avatar")
+        .add_filter("Images", &["png", "jpg", "jpeg", "gif", "webp"])
+        .pick_file();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Some(file) = dialog.await else {
+            return;
+        };
+        let file_name = file.file_name();
+        let data = file.read().await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let mut errors = ui.get_profile_errors();
+            errors.avatar = match avatar_mime(&file_name) {
+                None => SharedString::from("Choose a PNG, JPEG, GIF or WebP image"),
+                Some(_) if data.len() > MAX_AVATAR_BYTES => {
+                    SharedString::from("Avatars must be under 5 MB")
+                }
+                Some(mime_type) => match decode_avatar(&data) {
+                    Some(preview) => {
+                        ui.set_profile_avatar_preview(preview);
+                        PENDING_AVATAR
+                            .with(|p| *p.borrow_mut() = Some(PendingAvatar { data, mime_type }));
+                        SharedString::new()
+                    }
+                    None => SharedString::from("This image could not be read"),
+                },
+            };
+            ui.set_profile_errors(errors);
+        })
+        .ok();
+    });
+}
+
+/// Leave edit mode without saving.
+pub fn cancel_edit(ui: &AppWindow) {
+    discard_pending(ui);
+    ui.set_profile_errors(ProfileErrors::default());
+}
+
+/// Show `status` in the profile and tray and publish it as our presence.
+pub fn set_status(ui: &AppWindow, client: &ClientHandle, status: UserStatus) {
+    let mut profile = ui.get_current_profile();
+    profile.status = SharedString::from(status.label());
+    ui.set_current_profile(profile);
+    tray::update(|state| state.status = status);
+
+    let reply = client.set_presence(status);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to set presence: {}", e);
+        }
+    });
+}
+
+/// Save the fields of `data` that differ from the current profile. Fields
+/// that saved are shown at once; the dialog stays in edit mode, with an
+/// error next to each field that failed, until everything has saved.
+pub fn save(ui: &AppWindow, client: &ClientHandle, data: UserProfileData) {
+    let current = ui.get_current_profile();
+    let name = data.username.trim().to_string();
+    if name.is_empty() {
+        ui.set_profile_errors(ProfileErrors {
+            display_name: SharedString::from("Display name can't be empty"),
+            ..Default::default()
+        });
+        return;
+    }
+    let status = UserStatus::from_label(&data.status);
+    let status_message = data.status_message.trim().to_string();
+    let bio = data.bio.trim().to_string();
+
+    let set_name = (name != current.username.as_str()).then(|| client.set_display_name(&name));
+    let set_avatar = PENDING_AVATAR.with(|p| {
+        p.borrow()
+            .as_ref()
+            .map(|avatar| client.set_avatar(avatar.data.clone(), avatar.mime_type))
+    });
+    let set_status = status
+        .filter(|s| s.label() != current.status.as_str())
+        .map(|s| client.set_presence(s));
+    // While a game is shown in its place, ours is kept for when it exits.
+    let set_status_message = (status_message != current.status_message.as_str())
+        .then(|| client.set_status_message(&rich_presence::published(&status_message)));
+    let set_bio = (bio != current.bio.as_str()).then(|| client.set_bio(&bio));
+
+    ui.set_profile_saving(true);
+    ui.set_profile_errors(ProfileErrors::default());
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let name_result = match set_name {
+            Some(reply) => Some(reply.await),
+            None => None,
+        };
+        let avatar_result = match set_avatar {
+            Some(reply) => Some(reply.await),
+            None => None,
+        };
+        let status_result = match set_status {
+            Some(reply) => Some(reply.await),
+            None => None,
+        };
+        let status_message_result = match set_status_message {
+            Some(reply) => Some(reply.await),
+            None => None,
+        };
+        let bio_result = match set_bio {
+            Some(reply) => Some(reply.await),
+            None => None,
+        };
+
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            ui.set_profile_saving(false);
+            let mut profile = ui.get_current_profile();
+            let mut errors = ProfileErrors::default();
+
+            match name_result {
+                Some(Ok(())) => {
+                    // New local echoes pick this up as the sender name.
+                    ui.set_current_display_name(SharedString::from(name.as_str()));
+                    profile.initials = avatar_initials(&name, &ui.get_current_user_id());
+                    profile.username = SharedString::from(name);
+                }
+                Some(Err(e)) => errors.display_name = SharedString::from(e.to_string()),
+                None => {}
+            }
+            match avatar_result {
+                Some(Ok(())) => {
+                    profile.avatar = ui.get_profile_avatar_preview();
+                    discard_pending(&ui);
+                }
+                Some(Err(e)) => errors.avatar = SharedString::from(e.to_string()),
+                None => {}
+            }
+            match (status_result, status) {
+                (Some(Ok(())), Some(status)) => {
+                    profile.status = SharedString::from(status.label());
+                    tray::update(|state| state.status = status);
+                }
+                (Some(Err(e)), _) => errors.status = SharedString::from(e.to_string()),
+                _ => {}
+            }
+            match status_message_result {
+                Some(Ok(())) => {
+                    remember_status_message(&ui.get_current_user_id(), &status_message);
+                    profile.status_message = SharedString::from(status_message);
+                }
+                Some(Err(e)) => errors.status_message = SharedString::from(e.to_string()),
+                None => {}
+            }
+            match bio_result {
+                Some(Ok(())) => profile.bio = SharedString::from(bio),
+                Some(Err(e)) => errors.bio = SharedString::from(e.to_string()),
+                None => {}
+            }
+
+            ui.set_current_profile(profile);
+            if errors == ProfileErrors::default() {
+                ui.set_profile_edit_mode(false);
+            } else {
+                ui.set_profile_errors(errors);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Keep our status message for `user_id` to publish again next sign-in.
+fn remember_status_message(user_id: &str, message: &str) {
+    let mut config = ConfigManager::load();
+    config
+        .accounts
+        .entry(user_id.to_string())
+        .or_default()
+        .status_message = (!message.is_empty()).then(|| message.to_string());
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the status message: {:#}", e);
+    }
+}
+
+/// Publish the status message `user_id` kept, as they sign in.
+pub fn restore_status_message(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    config: &Config,
+    user_id: &str,
+) {
+    let Some(message) = config.account(user_id).status_message else {
+        return;
+    };
+    let mut profile = ui.get_current_profile();
+    profile.status_message = SharedString::from(message.as_str());
+    ui.set_current_profile(profile);
+
+    let reply = client.set_status_message(&message);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to publish the status message: {}", e);
+        }
+    });
+}
@@ -0,0 +1,196 @@
+//! Removing a spammer's recent messages in one go, from the menu of one of
+//! them: pick how far back to go and whether to ban them first, then watch
+//! the redactions count up. The timeline drops the messages as the
+//! redactions come back through sync.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, toast, AppWindow, PurgeData, PurgeStage};
+use network::purge::{PurgeReport, PurgeWindow};
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::time::Duration;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Whose messages go, and where.
+#[derive(Debug, Clone, PartialEq)]
+struct Target {
+    room_id: String,
+    user_id: String,
+}
+
+thread_local! {
+    /// Set while the dialog is open or a purge runs.
+    static TARGET: RefCell<Option<Target>> = const { RefCell::new(None) };
+}
+
+/// The window picked in the dialog: the last hour, 6 hours or 24 hours,
+/// each with a cap on how many messages are looked at.
+fn window(index: i32) -> PurgeWindow {
+    let (hours, max_events) = match index {
+        1 => (6, 500),
+        2 => (24, 1000),
+        _ => (1, 100),
+    };
+    PurgeWindow {
+        max_age: HOUR * hours,
+        max_events,
+    }
+}
+
+/// What came of it, in a sentence.
+fn summary(report: &PurgeReport, sender: &str) -> String {
+    let removed = match report.found {
+        0 => format!("{} sent nothing in that time.", sender),
+        found => format!("Removed {} of {} messages.", report.redacted, found),
+    };
+    if report.banned {
+        format!("Banned {}. {}", sender, removed)
+    } else {
+        removed
+    }
+}
+
+/// Offer to remove the recent messages of whoever sent `event_id`.
+pub fn open(ui: &AppWindow, event_id: &str) {
+    let Some(room_id) = history::room_of(event_id) else {
+        return;
+    };
+    let Some(message) =
+        history::with_message(&room_id, event_id, |timeline| timeline.message(event_id)).flatten()
+    else {
+        return;
+    };
+    if message.sender == ui.get_current_user_id().as_str() {
+        return;
+    }
+    // One purge at a time; the dialog shows how the running one goes.
+    let running = ui.get_purge().stage == PurgeStage::Running;
+    if !running {
+        TARGET.with(|t| {
+            *t.borrow_mut() = Some(Target {
+                room_id,
+                user_id: message.sender.clone(),
+            })
+        });
+        ui.set_purge(PurgeData {
+            stage: PurgeStage::Choose,
+            sender: SharedString::from(message.sender_display()),
+            ..PurgeData::default()
+        });
+    }
+    ui.set_show_purge(true);
+}
+
+/// Start removing, going back as far as choice `window_index` says.
+pub fn start(ui: &AppWindow, client: &ClientHandle, window_index: i32, ban: bool) {
+    let Some(target) = TARGET.with(|t| t.borrow().clone()) else {
+        return;
+    };
+    let (reply, mut progress) =
+        client.purge_user_messages(&target.room_id, &target.user_id, window(window_index), ban);
+    let mut data = ui.get_purge();
+    data.stage = PurgeStage::Running;
+    data.error = SharedString::default();
+    ui.set_purge(data);
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        while progress.changed().await.is_ok() {
+            let current = *progress.borrow_and_update();
+            let ui_handle = ui_handle.clone();
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                let mut data = ui.get_purge();
+                if data.stage != PurgeStage::Running {
+                    return;
+                }
+                data.done = current.done as i32;
+                data.total = current.total as i32;
+                ui.set_purge(data);
+            })
+            .ok();
+        }
+    });
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if TARGET.with(|t| t.borrow().as_ref() != Some(&target)) {
+                return;
+            }
+            let mut data = ui.get_purge();
+            data.stage = PurgeStage::Done;
+            match result {
+                Ok(report) => {
+                    data.summary = SharedString::from(summary(&report, &data.sender));
+                    if !report.failed.is_empty() {
+                        for (event_id, reason) in &report.failed {
+                            tracing::warn!("Failed to remove {}: {}", event_id, reason);
+                        }
+                        data.error = SharedString::from(format!(
+                            "{} couldn't be removed; try again later.",
+                            report.failed.len()
+                        ));
+                    }
+                }
+                Err(e) => {
+                    data.error = admin::error_text(&e, "failed-purge");
+                }
+            }
+            // Closed while it ran: say how it went anyway.
+            if !ui.get_show_purge() {
+                let text = format!("{} {}", data.summary, data.error);
+                toast::show(&ui, text.trim());
+            }
+            ui.set_purge(data);
+        })
+        .ok();
+    });
+}
+
+/// Close the dialog and forget its target, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    TARGET.with(|t| *t.borrow_mut() = None);
+    ui.set_show_purge(false);
+    ui.set_purge(PurgeData::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        assert_eq!(window(0).max_age, HOUR);
+        assert_eq!(window(1).max_events, 500);
+        assert_eq!(window(2).max_age, HOUR * 24);
+        assert_eq!(window(7), window(0));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut report = PurgeReport {
+            banned: true,
+            found: 20,
+            redacted: 18,
+            failed: Vec::new(),
+        };
+        assert_eq!(
+            summary(&report, "Spammer"),
+            "Banned Spammer. Removed 18 of 20 messages."
+        );
+        report.banned = false;
+        report.found = 0;
+        assert_eq!(
+            summary(&report, "Spammer"),
+            "Spammer sent nothing in that time."
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Pushers in the notification settings: the homeserver mailing us of
+//! mentions, or posting them to a push gateway, while the app is closed.
+//! The account's choice is kept in the config and registered again as it
+//! signs in; the settings also list other apps' pushers so stale ones can
+//! be removed.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, toast, AppWindow, PushSettings, PusherData};
+use network::config::{Config, ConfigManager, GatewayConfig, PusherConfig};
+use network::pusher::{Pusher, PusherError, PusherKind};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+thread_local! {
+    /// The pushers the settings list, as last loaded.
+    static LISTED: RefCell<Vec<Pusher>> = const { RefCell::new(Vec::new()) };
+}
+
+fn error_text(e: &anyhow::Error, failed: &str) -> SharedString {
+    match e.downcast_ref::<PusherError>() {
+        Some(e) => SharedString::from(e.to_string()),
+        None => admin::error_text(e, failed),
+    }
+}
+
+/// Register the pushers `user_id` asked for, as they sign in. Nothing is
+/// asked of the server when there are none.
+pub fn register(client: &ClientHandle, config: &Config, user_id: &str) {
+    let pushers = config.account(user_id).pushers;
+    if pushers == PusherConfig::default() {
+        return;
+    }
+    let reply = client.register_pushers(pushers);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to register pushers: {:#}", e);
+        }
+    });
+}
+
+fn show_settings(ui: &AppWindow, pushers: &PusherConfig) {
+    let gateway = pushers.gateway.clone().unwrap_or_default();
+    ui.set_push_settings(PushSettings {
+        email: SharedString::from(pushers.email.as_deref().unwrap_or_default()),
+        gateway_url: SharedString::from(gateway.url.as_str()),
+        gateway_app_id: SharedString::from(gateway.app_id.as_str()),
+    });
+}
+
+fn render(ui: &AppWindow) {
+    let rows: Vec<PusherData> = LISTED.with(|l| {
+        l.borrow()
+            .iter()
+            .map(|pusher| {
+                let (label, detail) = match &pusher.kind {
+                    PusherKind::Email => (format!("Email to {}", pusher.pushkey), String::new()),
+                    PusherKind::Http { url } => (
+                        format!(
+                            "{} on {}",
+                            pusher.app_display_name, pusher.device_display_name
+                        ),
+                        url.clone(),
+                    ),
+                    PusherKind::Other => (pusher.app_display_name.clone(), String::new()),
+                };
+                PusherData {
+                    label: SharedString::from(label),
+                    detail: SharedString::from(detail),
+                }
+            })
+            .collect()
+    });
+    ui.set_pushers(Rc::new(VecModel::from(rows)).into());
+}
+
+/// The settings dialog opened: show the account's choice and list the
+/// pushers on the account.
+pub fn open_settings(ui: &AppWindow, client: &ClientHandle, config: &Config) {
+    show_settings(ui, &config.account(&ui.get_current_user_id()).pushers);
+    LISTED.with(|l| l.borrow_mut().clear());
+    render(ui);
+    ui.set_pushers_status(SharedString::new());
+    if !ui.get_logged_in() {
+        return;
+    }
+
+    let reply = client.list_pushers();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(pushers) => {
+                    LISTED.with(|l| *l.borrow_mut() = pushers);
+                    render(&ui);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to list pushers: {}", e);
+                    ui.set_pushers_status(error_text(&e, "failed-list-pushers"));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Remove the listed pusher at `row`. One the account asked for is
+/// dropped from its settings too, so it isn't registered again.
+pub fn remove(ui: &AppWindow, client: &ClientHandle, row: i32) {
+    let Some(pusher) = LISTED.with(|l| l.borrow().get(row as usize).cloned()) else {
+        return;
+    };
+    let reply = client.remove_pusher(&pusher.app_id, &pusher.pushkey);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to remove the pusher: {}", e);
+                toast::show(&ui, error_text(&e, "failed-remove-pusher"));
+                return;
+            }
+            LISTED.with(|l| {
+                l.borrow_mut()
+                    .retain(|p| p.app_id != pusher.app_id || p.pushkey != pusher.pushkey)
+            });
+            render(&ui);
+
+            let mut push = ui.get_push_settings();
+            match &pusher.kind {
+                PusherKind::Email if push.email.trim() == pusher.pushkey.as_str() => {
+                    push.email = SharedString::new();
+                }
+                PusherKind::Http { url }
+                    if push.gateway_url.trim() == url.as_str()
+                        && push.gateway_app_id.trim() == pusher.app_id.as_str() =>
+                {
+                    push.gateway_url = SharedString::new();
+                    push.gateway_app_id = SharedString::new();
+                }
+                _ => return,
+            }
+            let mut config = ConfigManager::load();
+            let user_id = ui.get_current_user_id().to_string();
+            config.accounts.entry(user_id).or_default().pushers = from_settings(&push);
+            if let Err(e) = ConfigManager::save(&config) {
+                tracing::warn!("Failed to save the pushers: {:#}", e);
+            }
+            ui.set_push_settings(push);
+        })
+        .ok();
+    });
+}
+
+fn from_settings(push: &PushSettings) -> PusherConfig {
+    let email = push.email.trim();
+    let gateway = GatewayConfig {
+        url: push.gateway_url.trim().to_string(),
+        app_id: push.gateway_app_id.trim().to_string(),
+    };
+    PusherConfig {
+        email: (!email.is_empty()).then(|| email.to_string()),
+        gateway: (!gateway.url.is_empty() || !gateway.app_id.is_empty()).then_some(gateway),
+    }
+}
+
+/// The settings were saved: keep the pushers asked for in `push` for
+/// `user_id` in `config`, and register them if they changed.
+pub fn save(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    config: &mut Config,
+    user_id: &str,
+    push: &PushSettings,
+) {
+    let pushers = from_settings(push);
+    let account = config.accounts.entry(user_id.to_string()).or_default();
+    if account.pushers == pushers {
+        return;
+    }
+    account.pushers = pushers.clone();
+    if !ui.get_logged_in() {
+        return;
+    }
+
+    let reply = client.register_pushers(pushers);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to register pushers: {}", e);
+                toast::show(&ui, error_text(&e, "failed-set-up-notifications"));
+            }
+        })
+        .ok();
+    });
+}
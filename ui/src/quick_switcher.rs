@@ -0,0 +1,161 @@
+//! The quick switcher: a finder over the joined rooms and direct chats, run
+//! from the keyboard. Picking a room opens it as clicking it in the sidebar
+//! would, or, when opened to forward messages, sends them there.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::switcher_index::SwitcherIndex;
+use crate::{forwarding, AppWindow, SwitcherItemData};
+use chat_core::{Room, RoomType};
+use network::events::ChatEvent;
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{Key, NamedKey};
+use slint::{Model, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Results shown at once; typing more narrows them.
+const MAX_RESULTS: usize = 10;
+
+#[derive(Default)]
+struct Switcher {
+    index: SwitcherIndex,
+    /// The ids of the rooms listed.
+    results: Vec<String>,
+    selected: usize,
+    /// Picking where to forward messages to, rather than a room to open.
+    forwarding: bool,
+}
+
+thread_local! {
+    static SWITCHER: RefCell<Switcher> = RefCell::new(Switcher::default());
+}
+
+/// Offer the rooms of a freshly loaded room list.
+pub fn load(index: SwitcherIndex) {
+    SWITCHER.with(|s| s.borrow_mut().index = index);
+}
+
+/// Keep the rooms offered up to date.
+pub fn room_event(event: &ChatEvent) {
+    SWITCHER.with(|s| s.borrow_mut().index.apply(event));
+}
+
+/// Forget the rooms on logout.
+pub fn clear(ui: &AppWindow) {
+    SWITCHER.with(|s| *s.borrow_mut() = Switcher::default());
+    ui.set_show_switcher(false);
+}
+
+/// What `room_id` is called, if it is joined.
+pub fn room_name(room_id: &str) -> Option<String> {
+    SWITCHER.with(|s| s.borrow().index.room(room_id).map(|r| r.name.clone()))
+}
+
+fn item(room: &Room) -> SwitcherItemData {
+    let detail = match room.room_type {
+        RoomType::Direct => room.direct_targets.first().map(String::as_str),
+        _ => room.alias.as_deref(),
+    };
+    SwitcherItemData {
+        icon: SharedString::from(if room.room_type == RoomType::Direct {
+            "@"
+        } else {
+            "#"
+        }),
+        name: SharedString::from(room.name.as_str()),
+        detail: SharedString::from(detail.unwrap_or_default()),
+        unread: room.unread.notifications > 0,
+        mentioned: room.unread.highlights > 0,
+    }
+}
+
+fn render(ui: &AppWindow, query: &str) {
+    let items: Vec<SwitcherItemData> = SWITCHER.with(|s| {
+        let mut switcher = s.borrow_mut();
+        let found = switcher.index.search(query, MAX_RESULTS);
+        let items = found.iter().map(|&room| item(room)).collect();
+        let results = found.into_iter().map(|room| room.id.clone()).collect();
+        switcher.results = results;
+        switcher.selected = 0;
+        items
+    });
+    ui.set_switcher_items(Rc::new(VecModel::from(items)).into());
+    ui.set_switcher_index(0);
+}
+
+/// Open the switcher on the most recently active rooms.
+pub fn open(ui: &AppWindow) {
+    SWITCHER.with(|s| s.borrow_mut().forwarding = false);
+    ui.set_switcher_title(SharedString::default());
+    render(ui, "");
+    ui.set_show_switcher(true);
+}
+
+/// Open the switcher to pick where to forward messages to, under `title`.
+pub fn open_forwarding(ui: &AppWindow, title: &str) {
+    SWITCHER.with(|s| s.borrow_mut().forwarding = true);
+    ui.set_switcher_title(SharedString::from(title));
+    render(ui, "");
+    ui.set_show_switcher(true);
+}
+
+pub fn search(ui: &AppWindow, query: &str) {
+    render(ui, query);
+}
+
+/// Open result `index`, or forward to it.
+pub fn choose(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, index: usize) {
+    let (room_id, forwarding) = SWITCHER.with(|s| {
+        let switcher = s.borrow();
+        (switcher.results.get(index).cloned(), switcher.forwarding)
+    });
+    ui.set_show_switcher(false);
+    match room_id {
+        Some(room_id) if forwarding => {
+            let name = ui
+                .get_switcher_items()
+                .row_data(index)
+                .map(|item| item.name)
+                .unwrap_or_default();
+            forwarding::send(ui, client, &room_id, &name);
+        }
+        Some(room_id) => rooms::reveal_room(ui, client, sidebar, &room_id),
+        None => {}
+    }
+}
+
+fn move_selection(ui: &AppWindow, down: bool) {
+    let selected = SWITCHER.with(|s| {
+        let mut switcher = s.borrow_mut();
+        let count = switcher.results.len();
+        if count > 0 {
+            switcher.selected = if down {
+                (switcher.selected + 1) % count
+            } else {
+                (switcher.selected + count - 1) % count
+            };
+        }
+        switcher.selected
+    });
+    ui.set_switcher_index(selected as i32);
+}
+
+/// While the switcher is open, arrows move through the results, Enter
+/// opens one and Escape closes it. Returns true when the key was used.
+pub fn key_pressed(ui: &AppWindow, event: &KeyEvent) -> bool {
+    if !ui.get_show_switcher() || event.state != ElementState::Pressed {
+        return false;
+    }
+    match &event.logical_key {
+        Key::Named(NamedKey::ArrowUp) => move_selection(ui, false),
+        Key::Named(NamedKey::ArrowDown) => move_selection(ui, true),
+        Key::Named(NamedKey::Enter) => {
+            let selected = SWITCHER.with(|s| s.borrow().selected);
+            ui.invoke_switcher_choose(selected as i32);
+        }
+        Key::Named(NamedKey::Escape) => ui.set_show_switcher(false),
+        _ => return false,
+    }
+    true
+}
@@ -0,0 +1,139 @@
+//! Quiet hours: the clock is checked against the schedule in the settings
+//! every `POLL_INTERVAL`. As a window starts the shown account goes Do Not
+//! Disturb and notifications stay silent; as it ends the status from before
+//! comes back. Reading the wall clock each time, rather than sleeping until
+//! the next boundary, keeps this right after the machine wakes or its clock
+//! or time zone changes.
+
+use crate::app_state::ClientHandle;
+use crate::{i18n, profile, tray, AppWindow};
+use chat_core::quiet_hours::{self, QuietHours, Schedule, Transition};
+use chat_core::UserStatus;
+use network::cancel::CancelToken;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// How often the clock is checked, and so how late a window can start or
+/// end.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Quiet {
+    /// Ends the poll loop; set while signed in with quiet hours on.
+    polling: Option<CancelToken>,
+    schedule: Schedule,
+    hours: QuietHours,
+}
+
+thread_local! {
+    static QUIET: RefCell<Quiet> = RefCell::new(Quiet::default());
+}
+
+/// Start following `schedule` as an account signs in, or again once it was
+/// changed.
+pub fn start(ui: &AppWindow, client: &ClientHandle, schedule: &Schedule) {
+    let token = schedule.enabled.then(CancelToken::new);
+    QUIET.with(|q| {
+        let mut state = q.borrow_mut();
+        if let Some(old) = std::mem::replace(&mut state.polling, token.clone()) {
+            old.cancel();
+        }
+        state.schedule = *schedule;
+    });
+    // Settle a change of schedule at once rather than on the next tick.
+    check(ui, client);
+    let Some(token) = token else {
+        return;
+    };
+
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        // The first tick is immediate, and the check above covered it.
+        interval.tick().await;
+        while token.run(interval.tick()).await.is_ok() {
+            let ui_handle = ui_handle.clone();
+            let client = client.clone();
+            slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    check(&ui, &client);
+                }
+            })
+            .ok();
+        }
+    });
+}
+
+/// Stop following the schedule, e.g. as the account signs out. The status
+/// from before a window is not put back: the account is going away.
+pub fn stop(ui: &AppWindow) {
+    QUIET.with(|q| {
+        let mut state = q.borrow_mut();
+        if let Some(token) = state.polling.take() {
+            token.cancel();
+        }
+        state.hours = QuietHours::default();
+    });
+    show(ui, None);
+}
+
+/// Whether notifications should stay silent for quiet hours.
+pub fn is_silencing() -> bool {
+    QUIET.with(|q| q.borrow().hours.is_silencing())
+}
+
+/// Compare the clock with the schedule, and act on what changed.
+fn check(ui: &AppWindow, client: &ClientHandle) {
+    let Some(current) = UserStatus::from_label(&ui.get_current_profile().status) else {
+        return;
+    };
+    let now = quiet_hours::local_now();
+    let (transition, next) = QUIET.with(|q| {
+        let mut state = q.borrow_mut();
+        let quiet = state.polling.is_some() && state.schedule.is_quiet(now);
+        let transition = state.hours.update(current, quiet);
+        (transition, state.schedule.next_boundary(now))
+    });
+    if let Some(transition) = transition {
+        apply(ui, client, transition);
+    }
+    let until = next.map(|next| next.format("%H:%M").to_string());
+    show(ui, until.as_deref());
+}
+
+/// Switch presence as `transition` says.
+fn apply(ui: &AppWindow, client: &ClientHandle, transition: Transition) {
+    match transition {
+        Transition::Started(Some(status)) | Transition::Ended(Some(status)) => {
+            profile::set_status(ui, client, status);
+        }
+        Transition::Started(None) | Transition::Overridden | Transition::Ended(None) => {}
+    }
+}
+
+/// Show in the tray and beside the status whether quiet hours are on, and
+/// until when.
+fn show(ui: &AppWindow, until: Option<&str>) {
+    let (quiet, silencing) = QUIET.with(|q| {
+        let state = q.borrow();
+        (state.hours.is_quiet(), state.hours.is_silencing())
+    });
+    tray::update(|state| state.quiet_hours = silencing);
+    let note = match until {
+        Some(until) if silencing => i18n::tr_args("quiet-until", [("time", until.into())]),
+        Some(until) if quiet => i18n::tr_args("quiet-paused-until", [("time", until.into())]),
+        _ => String::new(),
+    };
+    ui.set_quiet_hours_note(SharedString::from(note));
+}
+
+/// The settings were saved: follow the new schedule. Turned off during a
+/// window, the status from before it comes back now.
+pub fn save(ui: &AppWindow, client: &ClientHandle, schedule: &Schedule) {
+    if !ui.get_logged_in() {
+        return;
+    }
+    start(ui, client, schedule);
+}
@@ -0,0 +1,115 @@
+//! Validation for the register form, and which field each error belongs to.
+
+use crate::i18n;
+use network::register::{Availability, RegisterError};
+
+/// Errors shown on the register form, empty where a field is fine.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormErrors {
+    pub username: String,
+    pub password: String,
+    pub confirm: String,
+    /// Errors about the server rather than a field.
+    pub form: String,
+    /// The server needs registration to be finished in a browser.
+    pub needs_browser: bool,
+}
+
+impl FormErrors {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Reduce what the user typed to a bare localpart: no leading `@`, no
+/// `:server` suffix, lowercase.
+pub fn normalize_username(input: &str) -> String {
+    let username = input.trim().to_lowercase();
+    let username = username.strip_prefix('@').unwrap_or(&username);
+    username.split(':').next().unwrap_or_default().to_string()
+}
+
+/// Problems we can spot before asking the server.
+pub fn validate(username: &str, password: &str, confirm: &str) -> FormErrors {
+    let mut errors = FormErrors::default();
+    if username.is_empty() {
+        errors.username = "Choose a username".to_string();
+    }
+    if password.is_empty() {
+        errors.password = "Choose a password".to_string();
+    } else if password != confirm {
+        errors.confirm = "Passwords do not match".to_string();
+    }
+    errors
+}
+
+/// Place a failed registration on the field it concerns.
+pub fn from_error(error: &anyhow::Error) -> FormErrors {
+    let mut errors = FormErrors::default();
+    match error.downcast_ref::<RegisterError>() {
+        Some(e @ (RegisterError::UsernameTaken | RegisterError::InvalidUsername)) => {
+            errors.username = e.to_string();
+        }
+        Some(e @ RegisterError::WeakPassword) => errors.password = e.to_string(),
+        Some(e @ RegisterError::Disabled) => errors.form = e.to_string(),
+        Some(e @ RegisterError::NeedsBrowser) => {
+            errors.form = e.to_string();
+            errors.needs_browser = true;
+        }
+        None => errors.form = error.to_string(),
+    }
+    errors
+}
+
+/// Status line shown under the username while typing, and whether it is
+/// good news.
+pub fn availability_text(availability: Availability) -> (String, bool) {
+    match availability {
+        Availability::Available => (i18n::tr("username-available"), true),
+        Availability::Taken => (i18n::tr("username-taken"), false),
+        Availability::Invalid => (i18n::tr("username-invalid"), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_username() {
+        assert_eq!(normalize_username(" @Alice:matrix.org "), "alice");
+        assert_eq!(normalize_username("bob"), "bob");
+    }
+
+    #[test]
+    fn test_validate_places_errors_on_fields() {
+        assert!(validate("alice", "hunter2", "hunter2").is_empty());
+
+        let errors = validate("alice", "hunter2", "hunter3");
+        assert_eq!(errors.confirm, "Passwords do not match");
+        assert!(errors.password.is_empty());
+
+        let errors = validate("", "", "");
+        assert!(!errors.username.is_empty());
+        assert!(!errors.password.is_empty());
+        assert!(errors.confirm.is_empty());
+    }
+
+    #[test]
+    fn test_server_errors_land_on_their_field() {
+        let errors = from_error(&RegisterError::WeakPassword.into());
+        assert_eq!(errors.password, "This password is too weak");
+        assert!(errors.username.is_empty() && errors.form.is_empty());
+
+        let errors = from_error(&RegisterError::UsernameTaken.into());
+        assert!(!errors.username.is_empty());
+
+        let errors = from_error(&RegisterError::NeedsBrowser.into());
+        assert!(errors.needs_browser);
+        assert!(!errors.form.is_empty());
+
+        let errors = from_error(&anyhow::anyhow!("Connection refused"));
+        assert_eq!(errors.form, "Connection refused");
+        assert!(!errors.needs_browser);
+    }
+}
@@ -0,0 +1,911 @@
+//! Recording the event stream for bug reports (`--record`, or
+//! `--record-bodies` to keep message bodies), and driving the app from a
+//! recording instead of a homeserver (`--replay <file>`). A replay plays
+//! the events back as they came, with the rooms as they were loaded;
+//! anything that would change the account is refused.
+
+use crate::app_state::{Backend, Detached};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chat_core::game_invite::GameInvite;
+use chat_core::link_preview::LinkPreview;
+use chat_core::mention::Mention;
+use chat_core::{Attachment, Message, Room, Space, UserStatus};
+use network::admin::{ChannelRemoval, Role};
+use network::audit::{AuditKind, StateHistoryPage};
+use network::cancel::CancelToken;
+use network::config::{PusherConfig, Retention};
+use network::connection::{Connection, ConnectionState, Retry};
+use network::details::EventDetails;
+use network::diagnostics::Report;
+use network::discovery::ServerStatus;
+use network::encryption::RoomSecurity;
+use network::events::{ChatEvent, KnockRequest, NotificationEvent, TypingEvent, VoiceMember};
+use network::media::{Upload, UploadProgress};
+use network::media_cache::{CacheCategory, CacheUsage};
+use network::members::MemberList;
+use network::password_reset::PasswordReset;
+use network::peek::RoomPreview;
+use network::profile::Profile;
+use network::purge::{PurgeProgress, PurgeReport, PurgeWindow};
+use network::pusher::Pusher;
+use network::recorder::{self, Header, Recorder, Recording};
+use network::register::{Availability, ServerSupport};
+use network::report::Reported;
+use network::room_settings::{HistoryVisibility, JoinRule, RoomAliases, RoomSettings};
+use network::rooms::{ContextPage, MessagePage, NewerPage, Permissions};
+use network::roster::Friend;
+use network::search::SearchHit;
+use network::session::Session;
+use network::signaling::HangupReason;
+use network::stickers::{Sticker, StickerPack};
+use network::threepid::{EmailValidation, Threepid};
+use network::welcome::{Seen, Welcome};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::Instant;
+
+/// Whether sessions are recorded, and with message bodies left out or not.
+/// Set once, from the command line.
+static RECORDING: OnceLock<bool> = OnceLock::new();
+
+/// Record every session from now on, leaving message bodies out if
+/// `redacted`.
+pub fn enable_recording(redacted: bool) {
+    RECORDING.set(redacted).ok();
+}
+
+/// Record `events` until the account shown now is put away, if recording
+/// is on.
+pub fn record(
+    shown: &CancelToken,
+    user_id: &str,
+    display_name: &str,
+    spaces: &[Space],
+    rooms: &[Room],
+    mut events: broadcast::Receiver<ChatEvent>,
+) {
+    let Some(&redacted) = RECORDING.get() else {
+        return;
+    };
+    let header = Header {
+        user_id: user_id.to_string(),
+        display_name: display_name.to_string(),
+        spaces: spaces.to_vec(),
+        rooms: rooms.to_vec(),
+        redacted,
+    };
+    let (mut recorder, path) = match Recorder::start(&header) {
+        Ok(started) => started,
+        Err(e) => {
+            tracing::warn!("Failed to start recording: {:#}", e);
+            return;
+        }
+    };
+    println!("Recording events to {}", path.display());
+
+    let shown = shown.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match shown.run(events.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    tracing::warn!("Recording skipped {} events", skipped);
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            };
+            if let Err(e) = recorder.record(&event) {
+                tracing::warn!("Failed to record an event: {:#}", e);
+                return;
+            }
+        }
+        if let Err(e) = recorder.finish() {
+            tracing::warn!("Failed to finish the recording: {:#}", e);
+        }
+    });
+}
+
+fn replaying() -> anyhow::Error {
+    anyhow!("Not available while replaying a recording")
+}
+
+/// A client that plays a recording back. Events start playing once the
+/// stream is first subscribed to, so none are missed.
+pub struct ReplayClient {
+    recording: Arc<Recording>,
+    events: broadcast::Sender<ChatEvent>,
+    notifications: broadcast::Sender<NotificationEvent>,
+    typing: broadcast::Sender<TypingEvent>,
+    connection: watch::Sender<ConnectionState>,
+    playing: AtomicBool,
+    /// How many of the recording's events were played so far.
+    played: Arc<AtomicUsize>,
+}
+
+impl ReplayClient {
+    pub fn open(path: &Path) -> Result<Self> {
+        let recording = recorder::read(path)?;
+        // Room for the whole recording, so a slow reader never skips.
+        let (events, _) = broadcast::channel(recording.events.len().max(16));
+        let (notifications, _) = broadcast::channel(16);
+        let (typing, _) = broadcast::channel(16);
+        let (connection, _) = watch::channel(ConnectionState::Connected);
+        Ok(Self {
+            recording: Arc::new(recording),
+            events,
+            notifications,
+            typing,
+            connection,
+            playing: AtomicBool::new(false),
+            played: Arc::default(),
+        })
+    }
+
+    fn play(&self) {
+        let recording = self.recording.clone();
+        let events = self.events.clone();
+        let played = self.played.clone();
+        tokio::spawn(async move {
+            let start = Instant::now();
+            for (count, recorded) in recording.events.iter().enumerate() {
+                tokio::time::sleep_until(start + Duration::from_millis(recorded.at_ms)).await;
+                played.store(count + 1, Ordering::SeqCst);
+                let _ = events.send(recorded.event.clone());
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Backend for ReplayClient {
+    /// Replays the recording at `homeserver`, a file path.
+    async fn login(
+        homeserver: &str,
+        _username: &str,
+        _password: &str,
+    ) -> Result<(Self, String, String)> {
+        let client = ReplayClient::open(Path::new(homeserver))?;
+        let header = &client.recording.header;
+        let (user_id, display_name) = (header.user_id.clone(), header.display_name.clone());
+        Ok((client, user_id, display_name))
+    }
+
+    async fn restore(_saved: &Session) -> Result<Self> {
+        Err(replaying())
+    }
+
+    async fn connect(_homeserver: &str) -> Result<Self> {
+        Err(replaying())
+    }
+
+    async fn register(&mut self, _username: &str, _password: &str) -> Result<(String, String)> {
+        Err(replaying())
+    }
+
+    async fn check_username(&self, _username: &str) -> Result<Availability> {
+        Err(replaying())
+    }
+
+    async fn server_support(&self) -> Result<ServerSupport> {
+        Err(replaying())
+    }
+
+    async fn server_status(&self) -> Result<ServerStatus> {
+        Err(replaying())
+    }
+
+    async fn request_password_reset(&self, _email: &str) -> Result<PasswordReset> {
+        Err(replaying())
+    }
+
+    async fn resend_password_reset(&self, _reset: &PasswordReset) -> Result<PasswordReset> {
+        Err(replaying())
+    }
+
+    async fn submit_reset_token(&self, _reset: &PasswordReset, _token: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn complete_password_reset(
+        &self,
+        _sid: &str,
+        _client_secret: &str,
+        _new_password: &str,
+    ) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn send_message(
+        &self,
+        _room_id: &str,
+        _body: &str,
+        _mentions: &[Mention],
+        _txn_id: &str,
+    ) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn send_reply(
+        &self,
+        _room_id: &str,
+        _body: &str,
+        _mentions: &[Mention],
+        _reply_to: &str,
+        _txn_id: &str,
+    ) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn send_emote(&self, _room_id: &str, _action: &str) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn send_sticker(&self, _room_id: &str, _sticker: &Sticker) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn send_game_invite(&self, _room_id: &str, _invite: &GameInvite) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn edit_message(&self, _room_id: &str, _event_id: &str, _body: &str) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn delete_message(&self, _room_id: &str, _event_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn react_to_message(
+        &self,
+        _room_id: &str,
+        _event_id: &str,
+        _key: &str,
+    ) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn report_message(
+        &self,
+        _room_id: &str,
+        _event_id: &str,
+        _score: Option<i64>,
+        _reason: Option<&str>,
+    ) -> Result<Reported> {
+        Err(replaying())
+    }
+
+    async fn report_room(&self, _room_id: &str, _reason: Option<&str>) -> Result<Reported> {
+        Err(replaying())
+    }
+
+    async fn ignore_user(&self, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_permissions(&self, _room_id: &str) -> Result<Permissions> {
+        Ok(Permissions {
+            send: false,
+            react: false,
+            redact_others: false,
+            topic: false,
+            pin: false,
+            slow_mode_secs: 0,
+        })
+    }
+
+    async fn get_pinned(&self, _room_id: &str) -> Result<Vec<String>> {
+        Err(replaying())
+    }
+
+    async fn pin_message(&self, _room_id: &str, _event_id: &str, _pin: bool) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_event(&self, _room_id: &str, _event_id: &str) -> Result<Message> {
+        Err(replaying())
+    }
+
+    async fn get_event_details(&self, _room_id: &str, _event_id: &str) -> Result<EventDetails> {
+        Err(replaying())
+    }
+
+    async fn logout(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn start_sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_background(&mut self, _background: bool) {}
+
+    fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        let events = self.events.subscribe();
+        if !self.playing.swap(true, Ordering::SeqCst) {
+            self.play();
+        }
+        events
+    }
+
+    fn subscribe_notifications(&self) -> broadcast::Receiver<NotificationEvent> {
+        self.notifications.subscribe()
+    }
+
+    fn subscribe_typing(&self) -> broadcast::Receiver<TypingEvent> {
+        self.typing.subscribe()
+    }
+
+    fn subscribe_connection(&self) -> Connection {
+        Connection {
+            state: self.connection.subscribe(),
+            retry: Retry::default(),
+        }
+    }
+
+    fn set_typing(&self, _room_id: &str, _typing: bool) -> Detached<()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    async fn get_spaces(&self, _concurrency: usize) -> Result<Vec<Space>> {
+        Ok(self.recording.header.spaces.clone())
+    }
+
+    async fn get_joined_rooms(&self, _concurrency: usize) -> Result<Vec<Room>> {
+        Ok(self.recording.header.rooms.clone())
+    }
+
+    /// The room's messages played so far, as first sent. There is no older
+    /// history.
+    async fn get_messages(&self, room_id: &str, from: Option<&str>) -> Result<MessagePage> {
+        if from.is_some() {
+            return Ok(MessagePage::default());
+        }
+        let played = self.played.load(Ordering::SeqCst);
+        let messages = self.recording.events[..played]
+            .iter()
+            .filter_map(|recorded| match &recorded.event {
+                ChatEvent::Message {
+                    room_id: room,
+                    message,
+                    ..
+                } if room == room_id => Some(Message::clone(message)),
+                _ => None,
+            })
+            .collect();
+        Ok(MessagePage {
+            messages,
+            prev_batch: None,
+        })
+    }
+
+    /// The first page holds everything played, so there is never more.
+    async fn get_newer_messages(&self, _room_id: &str, _from: &str) -> Result<NewerPage> {
+        Ok(NewerPage::default())
+    }
+
+    async fn get_context(&self, _room_id: &str, _event_id: &str) -> Result<ContextPage> {
+        Err(replaying())
+    }
+
+    /// Recordings hold no state history.
+    async fn get_state_history(
+        &self,
+        _room_id: &str,
+        _kinds: &[AuditKind],
+        _from: Option<&str>,
+    ) -> Result<StateHistoryPage> {
+        Ok(StateHistoryPage::default())
+    }
+
+    fn mark_read(&self, _room_id: &str, _event_id: &str, _ts: u64) -> Detached<()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    async fn fully_read(&self, _room_id: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn join_room(&self, _id_or_alias: &str, _via: &[String]) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn preview_room(&self, _id_or_alias: &str, _via: &[String]) -> Result<RoomPreview> {
+        Err(replaying())
+    }
+
+    async fn knock_on_room(
+        &self,
+        _id_or_alias: &str,
+        _via: &[String],
+        _reason: Option<&str>,
+    ) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn retract_knock(&self, _room_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn knock_requests(&self) -> Result<Vec<KnockRequest>> {
+        Ok(Vec::new())
+    }
+
+    async fn approve_knock(&self, _room_id: &str, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn deny_knock(&self, _room_id: &str, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn invite_user(&self, _room_id: &str, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn open_dm(&self, _user_id: &str) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn get_friends(&self) -> Result<Vec<Friend>> {
+        Ok(Vec::new())
+    }
+
+    async fn add_friend(&self, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn respond_to_friend(&self, _user_id: &str, _accept: bool) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn remove_friend(&self, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    fn set_presence(&self, _status: UserStatus) -> Detached<()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn set_status_message(&self, _text: &str) -> Detached<()> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    async fn set_keywords(&self, _keywords: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    async fn list_pushers(&self) -> Result<Vec<Pusher>> {
+        Ok(Vec::new())
+    }
+
+    async fn register_pushers(&self, _config: &PusherConfig) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove_pusher(&self, _app_id: &str, _pushkey: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_3pids(&self) -> Result<Vec<Threepid>> {
+        Ok(Vec::new())
+    }
+
+    async fn request_email_token(&self, _email: &str) -> Result<EmailValidation> {
+        Err(replaying())
+    }
+
+    async fn resend_email_token(&self, _validation: &EmailValidation) -> Result<EmailValidation> {
+        Err(replaying())
+    }
+
+    async fn add_3pid(&self, _validation: &EmailValidation, _password: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn remove_3pid(&self, _email: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_room_members(&self, _room_id: &str, _full: bool) -> Result<MemberList> {
+        Ok(MemberList::default())
+    }
+
+    async fn get_member_avatar(&self, _room_id: &str, _user_id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn download_media(&self, _source: &str) -> Detached<Vec<u8>> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    fn download_thumbnail(&self, _attachment: &Attachment) -> Detached<Vec<u8>> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    fn get_url_preview(&self, _url: &str, _ts: u64) -> Detached<Option<LinkPreview>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    async fn upload_limit(&self) -> Result<u64> {
+        Err(replaying())
+    }
+
+    fn send_attachment(
+        &self,
+        _room_id: &str,
+        _upload: Upload,
+        _txn_id: &str,
+        _progress: watch::Sender<UploadProgress>,
+    ) -> Detached<String> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    fn send_voice_message(
+        &self,
+        _room_id: &str,
+        _data: Vec<u8>,
+        _duration: Duration,
+        _txn_id: &str,
+    ) -> Detached<String> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    fn forward_message(
+        &self,
+        _src_room: &str,
+        _event_id: &str,
+        _dst_room: &str,
+        _annotation: Option<&str>,
+    ) -> Detached<String> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    fn purge_user_messages(
+        &self,
+        _room_id: &str,
+        _user_id: &str,
+        _window: PurgeWindow,
+        _ban: bool,
+        _progress: watch::Sender<PurgeProgress>,
+    ) -> Detached<PurgeReport> {
+        Box::pin(async { Err(replaying()) })
+    }
+
+    async fn get_profile(&self) -> Result<Profile> {
+        Ok(Profile {
+            display_name: Some(self.recording.header.display_name.clone()),
+            ..Profile::default()
+        })
+    }
+
+    async fn set_display_name(&mut self, _name: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_avatar(&self, _data: Vec<u8>, _mime_type: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_bio(&self, _bio: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_favorites(&self) -> Result<Option<Vec<String>>> {
+        Err(replaying())
+    }
+
+    async fn set_favorites(&self, _rooms: Vec<String>) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn sticker_packs(&self, _room_id: &str) -> Result<Vec<StickerPack>> {
+        Err(replaying())
+    }
+
+    async fn join_voice(&self, _room_id: &str, _candidates: Vec<String>) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn leave_voice(&self, _room_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stun_servers(&self) -> Result<Vec<SocketAddr>> {
+        Ok(Vec::new())
+    }
+
+    async fn voice_members(&self, _room_id: &str) -> Result<Vec<VoiceMember>> {
+        Ok(Vec::new())
+    }
+
+    async fn answer_call(&self, _room_id: &str, _call_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn hangup_call(
+        &self,
+        _room_id: &str,
+        _call_id: &str,
+        _reason: HangupReason,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_channel(&self, _space_id: Option<&str>, _name: &str) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn remove_channel(
+        &self,
+        _space_id: Option<&str>,
+        _room_id: &str,
+        _removal: ChannelRemoval,
+    ) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_roles(&self, _room_id: &str) -> Result<Vec<Role>> {
+        Ok(network::admin::with_builtin_roles(Vec::new()))
+    }
+
+    async fn create_role(&self, _room_id: &str, _role: Role) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_power_level(&self, _room_id: &str, _user_id: &str, _level: i64) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_room_settings(&self, _room_id: &str) -> Result<RoomSettings> {
+        Err(replaying())
+    }
+
+    async fn set_join_rule(&self, _room_id: &str, _rule: JoinRule) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_history_visibility(
+        &self,
+        _room_id: &str,
+        _visibility: HistoryVisibility,
+    ) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_guest_access(&self, _room_id: &str, _allowed: bool) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_slow_mode(&self, _room_id: &str, _seconds: u64) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_welcome(&self, _room_id: &str) -> Result<Option<(Welcome, Seen)>> {
+        Ok(None)
+    }
+
+    async fn set_welcome(&self, _room_id: &str, _body: &str, _must_accept: bool) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn mark_welcome(&self, _room_id: &str, _event_id: &str, _seen: Seen) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_topic(&self, _room_id: &str, _topic: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn enable_encryption(&self, _room_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn get_room_aliases(&self, _room_id: &str) -> Result<RoomAliases> {
+        Err(replaying())
+    }
+
+    async fn add_room_alias(&self, _room_id: &str, _alias: &str) -> Result<String> {
+        Err(replaying())
+    }
+
+    async fn remove_room_alias(&self, _alias: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn set_canonical_alias(&self, _room_id: &str, _alias: &str) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn cache_size(&self) -> Result<CacheUsage> {
+        Err(replaying())
+    }
+
+    async fn clear_cache(&self, _category: CacheCategory) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn prune_cache(&self, _retention: Retention) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn clear_local_history(&self) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn search_local(&self, _query: &str, _room_id: Option<&str>) -> Result<Vec<SearchHit>> {
+        Err(replaying())
+    }
+
+    async fn search_server(&self, _query: &str, _room_id: Option<&str>) -> Result<Vec<SearchHit>> {
+        Err(replaying())
+    }
+
+    async fn rebuild_search_index(&self) -> Result<()> {
+        Err(replaying())
+    }
+
+    async fn run_diagnostics(&self) -> Result<Report> {
+        Err(replaying())
+    }
+
+    async fn room_security(&self, _room_id: &str) -> Result<RoomSecurity> {
+        Ok(RoomSecurity::default())
+    }
+
+    async fn trust_new_keys(&self, _user_id: &str) -> Result<()> {
+        Err(replaying())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_state::ClientHandle;
+    use crate::local_echo::Timeline;
+    use crate::sidebar::Sidebar;
+    use chat_core::{Message, RoomType};
+    use std::collections::HashMap;
+
+    fn room(id: &str, name: &str) -> Room {
+        Room {
+            id: id.to_string(),
+            name: name.to_string(),
+            topic: None,
+            room_type: RoomType::Group,
+            avatar_url: None,
+            last_activity: 0,
+            unread: Default::default(),
+            muted: false,
+            alias: None,
+            direct_targets: Vec::new(),
+            encrypted: false,
+            member_count: 0,
+        }
+    }
+
+    fn message(room_id: &str, id: &str, body: &str) -> ChatEvent {
+        ChatEvent::Message {
+            room_id: room_id.to_string(),
+            message: Box::new(Message {
+                id: id.to_string(),
+                sender: "@bob:example.org".to_string(),
+                content: body.to_string(),
+                ..Message::default()
+            }),
+            transaction_id: None,
+        }
+    }
+
+    /// Record a session, replay it through the client handle, and fold the
+    /// events into the sidebar and timelines as the app does.
+    #[tokio::test]
+    async fn test_replay_recorded_session() {
+        let path =
+            std::env::temp_dir().join(format!("replay-{}.jsonl.gz", chat_core::time::now_ms()));
+        let header = Header {
+            user_id: "@me:example.org".to_string(),
+            display_name: "me".to_string(),
+            spaces: Vec::new(),
+            rooms: vec![room("!lobby:x", "lobby"), room("!raids:x", "raids")],
+            redacted: false,
+        };
+        let session = [
+            message("!lobby:x", "$1", "hi"),
+            message("!raids:x", "$2", "tonight?"),
+            // Sync delivering the same event twice.
+            message("!lobby:x", "$1", "hi"),
+            message("!lobby:x", "$3", "typo"),
+            ChatEvent::MessageEdited {
+                room_id: "!lobby:x".to_string(),
+                event_id: "$3".to_string(),
+                sender: "@bob:example.org".to_string(),
+                body: "fixed".to_string(),
+                formatted: None,
+            },
+            message("!lobby:x", "$4", "oops"),
+            ChatEvent::Redacted {
+                room_id: "!lobby:x".to_string(),
+                event_id: "$4".to_string(),
+            },
+            ChatEvent::RoomRenamed {
+                room_id: "!raids:x".to_string(),
+                name: "raid night".to_string(),
+            },
+        ];
+        let mut recorder = Recorder::create(&path, &header).unwrap();
+        for event in &session {
+            recorder.record(event).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let handle = ClientHandle::spawn_with::<ReplayClient>();
+        let (user_id, _) = handle.login(path.to_str().unwrap(), "", "").await.unwrap();
+        assert_eq!(user_id, "@me:example.org");
+        let list = handle
+            .load_rooms(network::startup::DEFAULT_CONCURRENCY)
+            .await
+            .unwrap();
+        let mut events = list.events;
+        let mut sidebar = Sidebar::new(list.spaces, list.rooms);
+        let mut timelines: HashMap<String, Timeline> = HashMap::new();
+        for _ in 0..session.len() {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            sidebar.apply(&event);
+            match event {
+                ChatEvent::Message {
+                    room_id,
+                    message,
+                    transaction_id,
+                } => {
+                    let timeline = timelines.entry(room_id).or_default();
+                    timeline.push_remote(*message, transaction_id.as_deref());
+                }
+                ChatEvent::MessageEdited {
+                    room_id,
+                    event_id,
+                    sender,
+                    body,
+                    formatted,
+                } => {
+                    let timeline = timelines.entry(room_id).or_default();
+                    timeline.edit(&event_id, &sender, &body, formatted.as_deref());
+                }
+                ChatEvent::Redacted { room_id, event_id } => {
+                    timelines.entry(room_id).or_default().redact(&event_id);
+                }
+                _ => {}
+            }
+        }
+        std::fs::remove_file(&path).ok();
+
+        let bodies = |room_id: &str| -> Vec<String> {
+            timelines[room_id]
+                .entries()
+                .iter()
+                .map(|e| e.message.content.clone())
+                .collect()
+        };
+        assert_eq!(bodies("!lobby:x"), vec!["hi", "fixed"]);
+        assert_eq!(bodies("!raids:x"), vec!["tonight?"]);
+        assert_eq!(sidebar.room("!raids:x").unwrap().name, "raid night");
+
+        // History as played, and the account is read-only.
+        let page = handle
+            .history("!raids:x", None, CancelToken::new())
+            .await
+            .unwrap();
+        assert_eq!(page.messages.len(), 1);
+        handle.switch_room("!lobby:x").await.unwrap();
+        assert!(handle.send_message("hello", "txn").await.is_err());
+    }
+}
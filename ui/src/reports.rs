@@ -0,0 +1,235 @@
+//! Reporting messages and rooms to the homeserver's admins, from the menu
+//! of a message or a channel. Once a message is reported, or when the
+//! server takes no reports, ignoring its sender is offered instead.
+//! Reported messages are kept for each account so their menu says so.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::SharedSidebar;
+use crate::{admin, history, i18n, toast, AppWindow, ReportData, ReportStage};
+use network::config::{Config, ConfigManager};
+use network::report::Reported;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+/// What the report dialog is about.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Message {
+        room_id: String,
+        event_id: String,
+        sender_id: String,
+    },
+    Room {
+        room_id: String,
+    },
+}
+
+#[derive(Default)]
+struct Reports {
+    user_id: String,
+    /// Event ids of the messages this account reported.
+    reported: HashSet<String>,
+    /// Set while the report dialog is open.
+    target: Option<Target>,
+}
+
+thread_local! {
+    static REPORTS: RefCell<Reports> = RefCell::new(Reports::default());
+}
+
+/// Use the reports `user_id` made, as they sign in.
+pub fn load(config: &Config, user_id: &str) {
+    REPORTS.with(|r| {
+        let mut state = r.borrow_mut();
+        state.user_id = user_id.to_string();
+        state.reported = config.account(user_id).reported.into_iter().collect();
+    });
+}
+
+/// Forget the account's reports and close the dialog, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    ui.set_show_report(false);
+    REPORTS.with(|r| *r.borrow_mut() = Reports::default());
+}
+
+/// Whether we reported message `event_id`.
+pub fn is_reported(event_id: &str) -> bool {
+    REPORTS.with(|r| r.borrow().reported.contains(event_id))
+}
+
+fn remember(event_id: &str) {
+    let user_id = REPORTS.with(|r| {
+        let mut state = r.borrow_mut();
+        state.reported.insert(event_id.to_string());
+        state.user_id.clone()
+    });
+    let mut config = ConfigManager::load();
+    let account = config.accounts.entry(user_id).or_default();
+    account.reported.push(event_id.to_string());
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the reported message: {:#}", e);
+    }
+}
+
+fn open(ui: &AppWindow, target: Target, title: &str, sender: &str) {
+    REPORTS.with(|r| r.borrow_mut().target = Some(target));
+    ui.set_report(ReportData {
+        stage: ReportStage::Reason,
+        title: SharedString::from(title),
+        sender: SharedString::from(sender),
+        sending: false,
+    });
+    ui.set_show_report(true);
+}
+
+/// Ask why message `event_id` is reported. Our own messages and ones
+/// reported already are not.
+pub fn open_message(ui: &AppWindow, event_id: &str) {
+    let Some(room_id) = history::room_of(event_id) else {
+        return;
+    };
+    let Some(message) =
+        history::with_message(&room_id, event_id, |timeline| timeline.message(event_id)).flatten()
+    else {
+        return;
+    };
+    if message.sender == ui.get_current_user_id().as_str() || is_reported(event_id) {
+        return;
+    }
+    let target = Target::Message {
+        room_id,
+        event_id: event_id.to_string(),
+        sender_id: message.sender.clone(),
+    };
+    open(ui, target, "REPORT MESSAGE", message.sender_display());
+}
+
+/// Ask why room `room_id` is reported, e.g. as spam.
+pub fn open_room(ui: &AppWindow, sidebar: &SharedSidebar, room_id: &str) {
+    let name = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(room_id))
+        .map(|room| room.name.clone())
+        .unwrap_or_default();
+    let target = Target::Room {
+        room_id: room_id.to_string(),
+    };
+    open(ui, target, &format!("REPORT #{}", name), "");
+}
+
+fn set_sending(ui: &AppWindow, sending: bool) {
+    let mut report = ui.get_report();
+    report.sending = sending;
+    ui.set_report(report);
+}
+
+/// Whether the dialog is still open for `target`, and not for another
+/// report opened since.
+fn still_open(ui: &AppWindow, target: &Target) -> bool {
+    ui.get_show_report() && REPORTS.with(|r| r.borrow().target.as_ref() == Some(target))
+}
+
+/// Send the report the dialog is open for, with `reason` if one was given.
+pub fn submit(ui: &AppWindow, client: &ClientHandle, reason: &str) {
+    let Some(target) = REPORTS.with(|r| r.borrow().target.clone()) else {
+        return;
+    };
+    let reason = reason.trim();
+    let reason = (!reason.is_empty()).then(|| reason.to_string());
+    set_sending(ui, true);
+
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let reason = reason.as_deref();
+        let result = match &target {
+            Target::Message {
+                room_id, event_id, ..
+            } => client.report_message(room_id, event_id, None, reason).await,
+            Target::Room { room_id } => client.report_room(room_id, reason).await,
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let sent = matches!(result, Ok(Reported::Sent));
+            if let Target::Message {
+                room_id, event_id, ..
+            } = &target
+            {
+                if sent {
+                    remember(event_id);
+                    history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+                }
+            }
+            if !still_open(&ui, &target) {
+                return;
+            }
+            let mut report = ui.get_report();
+            report.sending = false;
+            match result {
+                Ok(Reported::Sent) => {
+                    toast::show(&ui, i18n::tr("report-sent"));
+                    // A room has no sender to ignore: nothing more to ask.
+                    if matches!(target, Target::Room { .. }) {
+                        ui.set_show_report(false);
+                    }
+                    report.stage = ReportStage::Sent;
+                }
+                Ok(Reported::Unsupported) => report.stage = ReportStage::Unsupported,
+                Err(e) => {
+                    tracing::warn!("Failed to send the report: {}", e);
+                    toast::show(&ui, admin::error_text(&e, "failed-send-report"));
+                }
+            }
+            ui.set_report(report);
+        })
+        .ok();
+    });
+}
+
+/// Ignore the sender of the message the dialog is open for.
+pub fn ignore_sender(ui: &AppWindow, client: &ClientHandle) {
+    let Some(target) = REPORTS.with(|r| r.borrow().target.clone()) else {
+        return;
+    };
+    let Target::Message { sender_id, .. } = &target else {
+        return;
+    };
+    let reply = client.ignore_user(sender_id);
+    let sender = ui.get_report().sender;
+    set_sending(ui, true);
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let open = still_open(&ui, &target);
+            match result {
+                Ok(()) => {
+                    toast::show(
+                        &ui,
+                        i18n::tr_args("ignored", [("user", sender.as_str().into())]),
+                    );
+                    if open {
+                        ui.set_show_report(false);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to ignore {}: {}", sender, e);
+                    toast::show(&ui, admin::error_text(&e, "failed-ignore"));
+                    if open {
+                        set_sending(&ui, false);
+                    }
+                }
+            }
+        })
+        .ok();
+    });
+}
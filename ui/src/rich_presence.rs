@@ -0,0 +1,202 @@
+//! Rich presence: while enabled, the running processes are checked every
+//! `POLL_INTERVAL` for a game from the settings' list, and "Playing …" is
+//! published as our status message until it exits, when our own message is
+//! put back. Off by default, and nothing is scanned while off.
+
+use crate::app_state::ClientHandle;
+use crate::{AppWindow, GameData};
+use chat_core::rich_presence::{self, Game};
+use network::cancel::CancelToken;
+use network::config::{Config, RichPresenceConfig};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use sysinfo::System;
+
+/// How often the running processes are checked.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct RichPresence {
+    /// Ends the poll loop; set while enabled and signed in.
+    polling: Option<CancelToken>,
+    /// The game published as our status message.
+    playing: Option<Game>,
+    /// The settings dialog's copy of the game list, applied when saved.
+    draft: Vec<Game>,
+}
+
+thread_local! {
+    static RICH_PRESENCE: RefCell<RichPresence> = RefCell::new(RichPresence::default());
+}
+
+/// The executable names of the running processes.
+fn running(system: &mut System) -> Vec<String> {
+    system.refresh_processes();
+    system
+        .processes()
+        .values()
+        .map(|process| process.name().to_string())
+        .collect()
+}
+
+/// Start looking for games, if enabled, as an account signs in.
+pub fn start(ui: &AppWindow, client: &ClientHandle, config: &RichPresenceConfig) {
+    stop();
+    if !config.enabled || config.games.is_empty() {
+        return;
+    }
+    let token = CancelToken::new();
+    RICH_PRESENCE.with(|r| r.borrow_mut().polling = Some(token.clone()));
+
+    let games = config.games.clone();
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut system = System::new();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut last: Option<Game> = None;
+        while token.run(interval.tick()).await.is_ok() {
+            let games = games.clone();
+            let scan = tokio::task::spawn_blocking(move || {
+                let found = rich_presence::detect(&games, &running(&mut system)).cloned();
+                (system, found)
+            });
+            let found;
+            (system, found) = match scan.await {
+                Ok(scanned) => scanned,
+                Err(e) => {
+                    tracing::warn!("Failed to look for running games: {}", e);
+                    return;
+                }
+            };
+            if found == last || token.is_cancelled() {
+                continue;
+            }
+            last = found.clone();
+            let ui_handle = ui_handle.clone();
+            let client = client.clone();
+            slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    playing(&ui, &client, found);
+                }
+            })
+            .ok();
+        }
+    });
+}
+
+/// Stop looking, e.g. as the account signs out. What was published stays
+/// until the next status change.
+pub fn stop() {
+    RICH_PRESENCE.with(|r| {
+        let mut state = r.borrow_mut();
+        if let Some(token) = state.polling.take() {
+            token.cancel();
+        }
+        state.playing = None;
+    });
+}
+
+/// The status message to publish for our own `message`: the game being
+/// played, if any, takes its place.
+pub fn published(message: &str) -> String {
+    RICH_PRESENCE.with(|r| {
+        r.borrow()
+            .playing
+            .as_ref()
+            .map_or_else(|| message.to_string(), Game::status_message)
+    })
+}
+
+/// The name of the game being played, if one was found running.
+pub fn game() -> Option<String> {
+    RICH_PRESENCE.with(|r| r.borrow().playing.as_ref().map(|game| game.name.clone()))
+}
+
+/// `game` started, or the one played exited.
+fn playing(ui: &AppWindow, client: &ClientHandle, game: Option<Game>) {
+    let polling = RICH_PRESENCE.with(|r| {
+        let mut state = r.borrow_mut();
+        state.playing = game;
+        state.polling.is_some()
+    });
+    if !polling {
+        return;
+    }
+    let text = published(&ui.get_current_profile().status_message);
+    let reply = client.set_status_message(&text);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to publish the game played: {}", e);
+        }
+    });
+}
+
+/// The settings dialog opened: edit a copy of the game list.
+pub fn open_settings(ui: &AppWindow, config: &Config) {
+    RICH_PRESENCE.with(|r| r.borrow_mut().draft = config.rich_presence.games.clone());
+    ui.set_rich_presence_enabled(config.rich_presence.enabled);
+    render(ui);
+}
+
+fn render(ui: &AppWindow) {
+    let rows: Vec<GameData> = RICH_PRESENCE.with(|r| {
+        r.borrow()
+            .draft
+            .iter()
+            .map(|game| GameData {
+                name: SharedString::from(game.name.as_str()),
+                executable: SharedString::from(game.executable.as_str()),
+            })
+            .collect()
+    });
+    ui.set_games(Rc::new(VecModel::from(rows)).into());
+}
+
+pub fn add_game(ui: &AppWindow, name: &str, executable: &str) {
+    let (name, executable) = (name.trim(), executable.trim());
+    if name.is_empty() || executable.is_empty() {
+        return;
+    }
+    RICH_PRESENCE.with(|r| {
+        let draft = &mut r.borrow_mut().draft;
+        draft.retain(|game| !rich_presence::runs(&game.executable, executable));
+        draft.push(Game::new(name, executable));
+    });
+    render(ui);
+}
+
+pub fn remove_game(ui: &AppWindow, row: i32) {
+    RICH_PRESENCE.with(|r| {
+        let draft = &mut r.borrow_mut().draft;
+        if let Some(row) = usize::try_from(row).ok().filter(|&row| row < draft.len()) {
+            draft.remove(row);
+        }
+    });
+    render(ui);
+}
+
+/// The settings were saved: keep the dialog's choices in `config` and
+/// start or stop looking to match. Turning it off while a game is shown
+/// puts our own message back.
+pub fn save(ui: &AppWindow, client: &ClientHandle, config: &mut Config, enabled: bool) {
+    let (games, was_playing) = RICH_PRESENCE.with(|r| {
+        let state = r.borrow();
+        (state.draft.clone(), state.playing.is_some())
+    });
+    config.rich_presence = RichPresenceConfig { enabled, games };
+    if !ui.get_logged_in() {
+        return;
+    }
+    start(ui, client, &config.rich_presence);
+    if was_playing && !enabled {
+        let reply = client.set_status_message(&ui.get_current_profile().status_message);
+        tokio::spawn(async move {
+            if let Err(e) = reply.await {
+                tracing::warn!("Failed to restore the status message: {}", e);
+            }
+        });
+    }
+}
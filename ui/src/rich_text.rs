@@ -0,0 +1,423 @@
+//! Formatted message bodies as rows the timeline draws one below the other.
+//! Slint has no styled runs within a text, so a row takes the styling its
+//! whole text shares: a paragraph all in bold is bold, one with a single
+//! bold word is not.
+
+use chat_core::html::{self, Block, Span};
+use chat_core::{link_preview, Message};
+
+/// What a row draws as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RowKind {
+    #[default]
+    Text,
+    Heading,
+    Code,
+    Rule,
+}
+
+/// One row of a formatted message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Row {
+    pub kind: RowKind,
+    pub text: String,
+    /// The text with its spoilers blotted out, shown until clicked; `None`
+    /// when it has none.
+    pub masked: Option<String>,
+    /// How many quotes it is in.
+    pub quote_depth: usize,
+    /// How many lists it is in.
+    pub indent: usize,
+    /// "•" or "3." before the first row of a list item.
+    pub marker: String,
+    /// Heading level, 1 to 6.
+    pub level: u8,
+    pub bold: bool,
+    pub italic: bool,
+    /// Monospace, for code.
+    pub code: bool,
+    /// The language a code block is in, as its sender named it.
+    pub language: Option<String>,
+    /// Red, green and blue, set by the sender.
+    pub color: Option<[u8; 3]>,
+    /// The one link in the row, opened by clicking it.
+    pub link: Option<String>,
+    /// The whole row is that link.
+    pub all_link: bool,
+}
+
+/// The rows of `message`'s formatted body, or none when it adds nothing to
+/// the plain one, e.g. when only a mention is linked. Plain bodies with
+/// ``` fenced code in them get rows too.
+pub fn rows(message: &Message) -> Vec<Row> {
+    let blocks = match &message.formatted {
+        Some(formatted) => html::parse(formatted),
+        None => fenced(&message.content),
+    };
+    if !blocks.iter().any(|b| matches!(b, Block::Code { .. })) && message.formatted.is_none() {
+        return Vec::new();
+    }
+    if let [Block::Paragraph(spans)] = blocks.as_slice() {
+        let plain = spans
+            .iter()
+            .all(|span| span.style == html::Style::default() && span.color.is_none());
+        if plain && html::plain_text(spans).trim() == message.content.trim() {
+            return Vec::new();
+        }
+    }
+    let mut rows = Vec::new();
+    flatten(&blocks, 0, 0, &mut rows);
+    rows
+}
+
+fn flatten(blocks: &[Block], quote_depth: usize, indent: usize, rows: &mut Vec<Row>) {
+    for block in blocks {
+        match block {
+            Block::Paragraph(spans) => rows.push(text_row(spans, quote_depth, indent)),
+            Block::Heading { level, spans } => rows.push(Row {
+                kind: RowKind::Heading,
+                level: *level,
+                bold: true,
+                ..text_row(spans, quote_depth, indent)
+            }),
+            Block::Quote(inner) => flatten(inner, quote_depth + 1, indent, rows),
+            Block::Code { language, text } => rows.push(Row {
+                kind: RowKind::Code,
+                text: text.clone(),
+                code: true,
+                language: language.clone(),
+                quote_depth,
+                indent,
+                ..Row::default()
+            }),
+            Block::List { start, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    let first = rows.len();
+                    flatten(item, quote_depth, indent + 1, rows);
+                    if let Some(row) = rows.get_mut(first) {
+                        row.marker = match start {
+                            Some(start) => format!("{}.", u64::from(*start) + i as u64),
+                            None => "•".to_string(),
+                        };
+                    }
+                }
+            }
+            Block::Rule => rows.push(Row {
+                kind: RowKind::Rule,
+                quote_depth,
+                indent,
+                ..Row::default()
+            }),
+        }
+    }
+}
+
+/// The rows of a room topic, which is plain text: one per line, linking
+/// to the first web link in it. Nothing else in it is taken as a link.
+pub fn topic_rows(topic: &str) -> Vec<Row> {
+    topic
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| text_row(&linked_spans(line), 0, 0))
+        .collect()
+}
+
+/// The rows of a room's welcome message: its formatted body when it has
+/// one, else the plain body as a topic is shown.
+pub fn welcome_rows(body: &str, formatted: Option<&str>) -> Vec<Row> {
+    let Some(formatted) = formatted else {
+        return topic_rows(body);
+    };
+    let mut rows = Vec::new();
+    flatten(&html::parse(formatted), 0, 0, &mut rows);
+    rows
+}
+
+/// `line` as spans, its first web link one of its own.
+fn linked_spans(line: &str) -> Vec<Span> {
+    let span = |text: &str, link: Option<&str>| Span {
+        text: text.to_string(),
+        style: html::Style::default(),
+        link: link.map(str::to_owned),
+        color: None,
+    };
+    let Some(url) = link_preview::first_url(line) else {
+        return vec![span(line, None)];
+    };
+    // `url` is a slice of `line`.
+    let start = url.as_ptr() as usize - line.as_ptr() as usize;
+    let end = start + url.len();
+    [
+        span(&line[..start], None),
+        span(url, Some(url)),
+        span(&line[end..], None),
+    ]
+    .into_iter()
+    .filter(|span| !span.text.is_empty())
+    .collect()
+}
+
+/// A plain body as paragraphs and the code blocks fenced in ```, the
+/// language named after the opening fence. A fence never closed runs to
+/// the end.
+fn fenced(body: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut text: Vec<&str> = Vec::new();
+    // The language and lines of the code block we are in.
+    let mut code: Option<(Option<String>, Vec<&str>)> = None;
+    let paragraph = |text: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        let joined = text.join("\n");
+        text.clear();
+        let trimmed = joined.trim_matches('\n');
+        if !trimmed.trim().is_empty() {
+            blocks.push(Block::Paragraph(vec![Span {
+                text: trimmed.to_string(),
+                style: html::Style::default(),
+                link: None,
+                color: None,
+            }]));
+        }
+    };
+    for line in body.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut code, fence) {
+            (None, Some(language)) => {
+                paragraph(&mut text, &mut blocks);
+                let language = language.trim();
+                code = Some((
+                    (!language.is_empty()).then(|| language.to_string()),
+                    Vec::new(),
+                ));
+            }
+            (Some(_), Some(rest)) if rest.trim().is_empty() => {
+                let (language, lines) = code.take().unwrap();
+                blocks.push(Block::Code {
+                    language,
+                    text: lines.join("\n"),
+                });
+            }
+            (Some((_, lines)), _) => lines.push(line),
+            (None, None) => text.push(line),
+        }
+    }
+    if let Some((language, lines)) = code {
+        blocks.push(Block::Code {
+            language,
+            text: lines.join("\n"),
+        });
+    }
+    paragraph(&mut text, &mut blocks);
+    blocks
+}
+
+/// "#rrggbb" as red, green and blue.
+fn rgb(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// The value every span with text in it shares, if they all do.
+fn shared<T: PartialEq + Clone>(spans: &[&Span], value: impl Fn(&Span) -> T) -> Option<T> {
+    let first = value(spans.first()?);
+    spans
+        .iter()
+        .all(|span| value(span) == first)
+        .then_some(first)
+}
+
+fn text_row(spans: &[Span], quote_depth: usize, indent: usize) -> Row {
+    let text = html::plain_text(spans);
+    let visible: Vec<&Span> = spans
+        .iter()
+        .filter(|span| !span.text.trim().is_empty())
+        .collect();
+    let masked = spans.iter().any(|span| span.style.spoiler).then(|| {
+        spans
+            .iter()
+            .map(|span| {
+                if !span.style.spoiler {
+                    return span.text.clone();
+                }
+                span.text
+                    .chars()
+                    .map(|c| if c.is_whitespace() { c } else { '█' })
+                    .collect()
+            })
+            .collect()
+    });
+    let mut links = spans.iter().filter_map(|span| span.link.as_ref());
+    let link = links.next().cloned();
+    let one_link = links.all(|other| Some(other) == link.as_ref());
+    let link = link.filter(|_| one_link);
+    Row {
+        kind: RowKind::Text,
+        masked,
+        quote_depth,
+        indent,
+        bold: shared(&visible, |span| span.style.bold) == Some(true),
+        italic: shared(&visible, |span| span.style.italic) == Some(true),
+        code: shared(&visible, |span| span.style.code) == Some(true),
+        color: shared(&visible, |span| span.color.clone())
+            .flatten()
+            .and_then(|color| rgb(&color)),
+        all_link: link.is_some() && shared(&visible, |span| span.link.clone()).is_some(),
+        link,
+        text,
+        ..Row::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(body: &str, formatted: &str) -> Message {
+        Message {
+            id: "$1".to_string(),
+            sender: "@bob:x".to_string(),
+            content: body.to_string(),
+            formatted: Some(formatted.to_string()),
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn test_plain_bodies_need_no_rows() {
+        assert!(rows(&message("hi", "hi")).is_empty());
+        let mention = "<a href=\"https://matrix.to/#/@ann:x\">Ann</a>: hi";
+        assert!(rows(&message("Ann: hi", mention)).is_empty());
+        let mut unformatted = message("hi", "");
+        unformatted.formatted = None;
+        assert!(rows(&unformatted).is_empty());
+
+        let bold = rows(&message("hi", "<b>hi</b>"));
+        assert_eq!(bold.len(), 1);
+        assert!(bold[0].bold);
+    }
+
+    #[test]
+    fn test_topic_rows_link_web_links_only() {
+        let rows = topic_rows(
+            "Raid nights: https://x.org/raids.\n  https://x.org  \n\njavascript:alert(1)",
+        );
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].text, "Raid nights: https://x.org/raids.");
+        assert_eq!(rows[0].link.as_deref(), Some("https://x.org/raids"));
+        assert!(!rows[0].all_link);
+        assert!(rows[1].all_link);
+        assert_eq!(rows[2].link, None);
+        assert!(topic_rows(" \n").is_empty());
+    }
+
+    #[test]
+    fn test_welcome_rows_use_the_formatting_if_any() {
+        let plain = welcome_rows("Be nice.\nRules: https://x.org/rules", None);
+        assert_eq!(plain.len(), 2);
+        assert_eq!(plain[1].link.as_deref(), Some("https://x.org/rules"));
+
+        let formatted = welcome_rows(
+            "Rules",
+            Some("<h2>Rules</h2><ol><li>Be nice</li><li>No spam</li></ol>"),
+        );
+        assert_eq!(formatted.len(), 3);
+        assert_eq!(formatted[0].kind, RowKind::Heading);
+        assert_eq!(formatted[2].marker, "2.");
+        assert_eq!(formatted[2].text, "No spam");
+    }
+
+    #[test]
+    fn test_row_styles_are_shared_by_all_its_text() {
+        let rows = rows(&message(
+            "",
+            "<p><b>all <i>bold</i></b></p><p>one <b>word</b></p>\
+             <p><font color=\"#ff8000\">orange</font></p>\
+             <p><a href=\"https://x.org\">site</a></p><p>see <a href=\"https://x.org\">x</a></p>",
+        ));
+        assert!(rows[0].bold && !rows[0].italic);
+        assert!(!rows[1].bold);
+        assert_eq!(rows[2].color, Some([0xff, 0x80, 0x00]));
+        assert!(rows[3].all_link);
+        assert_eq!(rows[4].link.as_deref(), Some("https://x.org"));
+        assert!(!rows[4].all_link);
+    }
+
+    #[test]
+    fn test_rows_with_two_links_open_neither() {
+        let rows = rows(&message(
+            "",
+            "<a href=\"https://a.org\">a</a> <a href=\"https://b.org\">b</a>",
+        ));
+        assert_eq!(rows[0].link, None);
+    }
+
+    #[test]
+    fn test_spoilers_are_masked() {
+        let rows = rows(&message("", "the <span data-mx-spoiler>hero dies</span>!"));
+        assert_eq!(rows[0].text, "the hero dies!");
+        assert_eq!(rows[0].masked.as_deref(), Some("the ████ ████!"));
+    }
+
+    #[test]
+    fn test_fenced_code_in_plain_bodies() {
+        let mut plain = message("try\n```rust\nfn main() {\n\n}\n```\nthen ```x``` ok", "");
+        plain.formatted = None;
+        let summary: Vec<_> = rows(&plain)
+            .into_iter()
+            .map(|r| (r.kind, r.text, r.language))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (RowKind::Text, "try".to_string(), None),
+                (
+                    RowKind::Code,
+                    "fn main() {\n\n}".to_string(),
+                    Some("rust".to_string())
+                ),
+                (RowKind::Text, "then ```x``` ok".to_string(), None),
+            ]
+        );
+
+        // Never closed: the rest is code.
+        plain.content = "```\nlet x".to_string();
+        assert_eq!(rows(&plain)[0].text, "let x");
+        assert_eq!(rows(&plain)[0].language, None);
+
+        plain.content = "no code, just ``` in passing".to_string();
+        assert!(rows(&plain).is_empty());
+    }
+
+    #[test]
+    fn test_lists_and_quotes() {
+        let rows = rows(&message(
+            "",
+            "<blockquote>said<ol start=\"9\"><li>nine</li><li>ten<ul><li>sub</li></ul></li></ol>\
+             </blockquote><hr><pre><code>x = 1</code></pre>",
+        ));
+        let summary: Vec<_> = rows
+            .iter()
+            .map(|r| {
+                (
+                    r.kind,
+                    r.text.as_str(),
+                    r.quote_depth,
+                    r.indent,
+                    r.marker.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (RowKind::Text, "said", 1, 0, ""),
+                (RowKind::Text, "nine", 1, 1, "9."),
+                (RowKind::Text, "ten", 1, 1, "10."),
+                (RowKind::Text, "sub", 1, 2, "•"),
+                (RowKind::Rule, "", 0, 0, ""),
+                (RowKind::Code, "x = 1", 0, 0, ""),
+            ]
+        );
+    }
+}
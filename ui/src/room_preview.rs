@@ -0,0 +1,137 @@
+//! A read-only look into a room a link points at, before joining it. Rooms
+//! whose history anyone can read show their latest messages; others show
+//! their name, topic and member count from the room directory. Joining from
+//! the preview hands its messages to the room's timeline, so the room opens
+//! with them rather than waiting for a fetch.
+
+use crate::app_state::ClientHandle;
+use crate::timeline::TimelineModel;
+use crate::{history, AppWindow, RoomPreviewData};
+use network::peek::RoomPreview;
+use slint::{ComponentHandle, ModelRc, SharedString};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+thread_local! {
+    /// The preview on screen, once fetched.
+    static PREVIEW: RefCell<Option<RoomPreview>> = const { RefCell::new(None) };
+}
+
+fn data(preview: &RoomPreview, address: &str) -> RoomPreviewData {
+    let address = preview.alias.as_deref().unwrap_or(address);
+    RoomPreviewData {
+        name: SharedString::from(preview.name.as_deref().unwrap_or(address)),
+        topic: SharedString::from(preview.topic.as_deref().unwrap_or_default()),
+        address: SharedString::from(address),
+        member_count: i32::try_from(preview.member_count).unwrap_or(i32::MAX),
+        peekable: preview.peekable,
+        loading: false,
+        joining: false,
+        error: SharedString::new(),
+    }
+}
+
+/// Look into `id_or_alias` through the `via` servers, showing it as soon
+/// as it is known.
+pub fn open(ui: &AppWindow, client: &ClientHandle, id_or_alias: &str, via: Vec<String>) {
+    PREVIEW.with(|p| *p.borrow_mut() = None);
+    ui.set_room_preview(RoomPreviewData {
+        name: SharedString::from(id_or_alias),
+        address: SharedString::from(id_or_alias),
+        loading: true,
+        ..Default::default()
+    });
+    ui.set_room_preview_messages(ModelRc::default());
+    ui.set_show_room_preview(true);
+
+    let reply = client.preview_room(id_or_alias, via);
+    let ui_handle = ui.as_weak();
+    let address = id_or_alias.to_string();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            // Closed, or another link opened since.
+            if !ui.get_show_room_preview() || ui.get_room_preview().address != address.as_str() {
+                return;
+            }
+            let preview = match result {
+                Ok(preview) => preview,
+                Err(e) => {
+                    tracing::warn!("Failed to preview {}: {:#}", address, e);
+                    let mut data = ui.get_room_preview();
+                    data.loading = false;
+                    data.error = SharedString::from(
+                        "This room can't be previewed, but you can still try to join it.",
+                    );
+                    ui.set_room_preview(data);
+                    return;
+                }
+            };
+            let own_user_id = ui.get_current_user_id();
+            let model = TimelineModel::new(own_user_id.as_str(), preview.page.messages.clone());
+            ui.set_room_preview_messages(ModelRc::from(Rc::new(model)));
+            let mut data = data(&preview, &address);
+            data.joining = ui.get_room_preview().joining;
+            ui.set_room_preview(data);
+            PREVIEW.with(|p| *p.borrow_mut() = Some(preview));
+        })
+        .ok();
+    });
+}
+
+/// The join asked for from the preview is on its way.
+pub fn joining(ui: &AppWindow) {
+    let mut data = ui.get_room_preview();
+    data.joining = true;
+    ui.set_room_preview(data);
+}
+
+/// The join failed; the preview stays up to try again.
+pub fn join_failed(ui: &AppWindow) {
+    let mut data = ui.get_room_preview();
+    data.joining = false;
+    ui.set_room_preview(data);
+}
+
+/// `room_id` was joined: start its timeline from the messages previewed,
+/// and put the preview away.
+pub fn joined(ui: &AppWindow, room_id: &str) {
+    if let Some(preview) = PREVIEW.with(|p| p.borrow_mut().take()) {
+        if preview.peekable && preview.room_id == room_id {
+            history::seed(ui, room_id, preview.page);
+        }
+    }
+    close(ui);
+}
+
+pub fn close(ui: &AppWindow) {
+    PREVIEW.with(|p| *p.borrow_mut() = None);
+    ui.set_show_room_preview(false);
+    ui.set_room_preview_messages(ModelRc::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_falls_back_to_the_address() {
+        let mut preview = RoomPreview {
+            member_count: 12,
+            ..Default::default()
+        };
+        let shown = data(&preview, "!raid:x.org");
+        assert_eq!(shown.name, "!raid:x.org");
+        assert_eq!(shown.address, "!raid:x.org");
+        assert_eq!(shown.member_count, 12);
+
+        preview.alias = Some("#raid:x.org".to_string());
+        preview.name = Some("Raid night".to_string());
+        let shown = data(&preview, "!raid:x.org");
+        assert_eq!(shown.name, "Raid night");
+        assert_eq!(shown.address, "#raid:x.org");
+    }
+}
@@ -0,0 +1,148 @@
+//! The padlock in the open room's header, and the warning shown the first
+//! time we send into a room where someone has devices they haven't
+//! verified. Device lists don't come through sync in this build, so the
+//! open room is checked again every minute as well as when its members or
+//! encryption change.
+
+use crate::app_state::ClientHandle;
+use crate::{history, member_list, sender_trust, AppWindow, RoomSecurityState};
+use network::encryption::RoomSecurity;
+use slint::{ComponentHandle, Timer, TimerMode};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::time::Duration;
+
+const REFRESH_EVERY: Duration = Duration::from_secs(60);
+
+thread_local! {
+    /// The open room and what we last learned of it.
+    static SHOWN: RefCell<Option<(String, RoomSecurity)>> = const { RefCell::new(None) };
+    /// Rooms warned about this session, which aren't warned about again.
+    static WARNED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static REFRESH: Timer = Timer::default();
+}
+
+fn state(security: &RoomSecurity) -> RoomSecurityState {
+    if !security.encrypted {
+        RoomSecurityState::Unencrypted
+    } else if security.has_unverified_devices() {
+        RoomSecurityState::Unverified
+    } else {
+        RoomSecurityState::Encrypted
+    }
+}
+
+fn set(ui: &AppWindow, room_id: &str, security: RoomSecurity) {
+    ui.set_room_security(state(&security));
+    sender_trust::room_checked(room_id, &security);
+    SHOWN.with(|shown| *shown.borrow_mut() = Some((room_id.to_string(), security)));
+}
+
+fn load(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    let reply = client.room_security(room_id);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if history::current_room().as_deref() != Some(room_id.as_str()) {
+                return;
+            }
+            match result {
+                Ok(security) => set(&ui, &room_id, security),
+                Err(e) => tracing::warn!("Failed to check room encryption: {}", e),
+            }
+        })
+        .ok();
+    });
+}
+
+/// Show what the sidebar knows of the room just opened, then check its
+/// devices, and keep checking while it stays open.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    ui.set_show_unverified_warning(false);
+    let encrypted = ui.get_active_channel_encrypted();
+    set(
+        ui,
+        room_id,
+        RoomSecurity {
+            encrypted,
+            ..Default::default()
+        },
+    );
+    load(ui, client, room_id);
+
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    REFRESH.with(|timer| {
+        timer.start(TimerMode::Repeated, REFRESH_EVERY, move || {
+            let (Some(ui), Some(room_id)) = (ui_handle.upgrade(), history::current_room()) else {
+                return;
+            };
+            load(&ui, &client, &room_id);
+        })
+    });
+}
+
+/// Members or the encryption of `room_id` may have changed.
+pub fn room_changed(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    if history::current_room().as_deref() == Some(room_id) {
+        load(ui, client, room_id);
+    }
+}
+
+/// Whether `user_id` has unverified devices in the open room.
+pub fn is_unverified(user_id: &str) -> bool {
+    SHOWN.with(|shown| {
+        shown
+            .borrow()
+            .as_ref()
+            .is_some_and(|(_, security)| security.unverified_users.iter().any(|u| u == user_id))
+    })
+}
+
+/// About to send into `room_id`: warn, once per room, if it has unverified
+/// devices.
+pub fn sending(ui: &AppWindow, room_id: &str) {
+    let unverified = SHOWN.with(|shown| {
+        shown
+            .borrow()
+            .as_ref()
+            .is_some_and(|(id, security)| id == room_id && security.has_unverified_devices())
+    });
+    if unverified && WARNED.with(|warned| warned.borrow_mut().insert(room_id.to_string())) {
+        ui.set_show_unverified_warning(true);
+    }
+}
+
+/// A send into `room_id` was refused over unverified devices.
+pub fn refused(ui: &AppWindow, room_id: &str) {
+    if history::current_room().as_deref() == Some(room_id) {
+        ui.set_show_unverified_warning(true);
+    }
+}
+
+/// Open the first member with unverified devices, to review who they are.
+pub fn review(ui: &AppWindow, client: &ClientHandle) {
+    ui.set_show_unverified_warning(false);
+    let first = SHOWN.with(|shown| {
+        shown
+            .borrow()
+            .as_ref()
+            .and_then(|(_, security)| security.unverified_users.first().cloned())
+    });
+    if let Some(user_id) = first {
+        member_list::open_member(ui, client, &user_id);
+    }
+}
+
+/// The room was left, or we logged out.
+pub fn clear(ui: &AppWindow) {
+    REFRESH.with(Timer::stop);
+    SHOWN.with(|shown| *shown.borrow_mut() = None);
+    ui.set_room_security(RoomSecurityState::Unencrypted);
+    ui.set_show_unverified_warning(false);
+}
@@ -0,0 +1,550 @@
+//! The room settings dialog, from the menu of a channel: who can join, who
+//! can read the history, guest access, encryption, slow mode, the welcome
+//! message and aliases. Each control applies on its own and shows whether
+//! that worked next to it; a refused change puts the control back.
+//! Controls our power level doesn't cover are off. The room's timeline
+//! filter, the language its messages are translated from and its
+//! notification sound are kept on this device, in the config.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{
+    admin, history, i18n, room_sounds, translations, AliasData, AppWindow, RoomSettingsData,
+    SettingStatus, TimelineFilterData,
+};
+use fluent_bundle::FluentValue;
+use network::config::ConfigManager;
+use network::room_settings::{AliasError, HistoryVisibility, JoinRule};
+use network::room_settings::{RoomAliases, RoomSettings};
+use network::timeline::TimelineFilter;
+use network::welcome::Welcome;
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+
+const JOIN_RULES: [JoinRule; 4] = [
+    JoinRule::Public,
+    JoinRule::Invite,
+    JoinRule::Knock,
+    JoinRule::Other,
+];
+
+const HISTORY: [HistoryVisibility; 4] = [
+    HistoryVisibility::WorldReadable,
+    HistoryVisibility::Shared,
+    HistoryVisibility::Invited,
+    HistoryVisibility::Joined,
+];
+
+/// The slow modes offered, in seconds, as the dialog lists them. One set
+/// elsewhere to another time is shown after them.
+const SLOW_MODES: [u64; 14] = [
+    0, 5, 10, 15, 30, 60, 120, 300, 600, 900, 1800, 3600, 7200, 21600,
+];
+
+/// Which control a change came from, for where to show how it went.
+#[derive(Clone, Copy)]
+enum Field {
+    JoinRule,
+    History,
+    GuestAccess,
+    Encryption,
+    SlowMode,
+    Welcome,
+    Aliases,
+}
+
+/// The room the dialog is open for, and its settings as last applied.
+struct Open {
+    room_id: String,
+    /// `None` until loaded.
+    settings: Option<RoomSettings>,
+    aliases: RoomAliases,
+}
+
+thread_local! {
+    static OPEN: RefCell<Option<Open>> = const { RefCell::new(None) };
+}
+
+fn index_of<T: PartialEq>(options: &[T], value: &T) -> i32 {
+    options.iter().position(|o| o == value).unwrap_or(0) as i32
+}
+
+fn show(ui: &AppWindow, settings: &RoomSettings) {
+    let mut data = ui.get_room_settings();
+    data.loading = false;
+    data.join_rule = index_of(&JOIN_RULES, &settings.join_rule);
+    data.history = index_of(&HISTORY, &settings.history_visibility);
+    data.guest_access = settings.guest_access;
+    data.encrypted = settings.encrypted;
+    data.can_join_rule = settings.can_change.join_rule;
+    data.can_history = settings.can_change.history_visibility;
+    data.can_guest_access = settings.can_change.guest_access;
+    data.can_encrypt = settings.can_change.encryption;
+    let secs = settings.slow_mode_secs;
+    let preset = SLOW_MODES.iter().position(|&s| s == secs);
+    data.slow_mode = preset.unwrap_or(SLOW_MODES.len()) as i32;
+    let seconds = FluentValue::from(secs);
+    data.slow_mode_other =
+        SharedString::from(i18n::tr_args("slow-mode-seconds", [("seconds", seconds)]));
+    data.can_slow_mode = settings.can_change.slow_mode;
+    let welcome = settings.welcome.as_ref();
+    data.welcome = SharedString::from(welcome.map_or("", |w| w.body.as_str()));
+    data.welcome_must_accept = welcome.is_some_and(|w| w.must_accept);
+    data.can_welcome = settings.can_change.welcome;
+    ui.set_room_settings(data);
+}
+
+fn set_status(ui: &AppWindow, field: Field, status: SettingStatus) {
+    let mut data = ui.get_room_settings();
+    match field {
+        Field::JoinRule => data.join_rule_status = status,
+        Field::History => data.history_status = status,
+        Field::GuestAccess => data.guest_access_status = status,
+        Field::Encryption => data.encryption_status = status,
+        Field::SlowMode => data.slow_mode_status = status,
+        Field::Welcome => data.welcome_status = status,
+        Field::Aliases => data.aliases_status = status,
+    }
+    ui.set_room_settings(data);
+}
+
+fn show_aliases(ui: &AppWindow, aliases: &RoomAliases) {
+    let rows: Vec<AliasData> = aliases
+        .aliases
+        .iter()
+        .map(|alias| AliasData {
+            alias: SharedString::from(alias.as_str()),
+            main: aliases.canonical.as_ref() == Some(alias),
+        })
+        .collect();
+    let mut data = ui.get_room_settings();
+    data.aliases = Rc::new(VecModel::from(rows)).into();
+    data.can_set_main_alias = aliases.can_set_canonical;
+    ui.set_room_settings(data);
+}
+
+/// `filter` as the checkboxes show it.
+pub fn timeline_filter_data(filter: &TimelineFilter) -> TimelineFilterData {
+    TimelineFilterData {
+        membership: filter.membership,
+        group_membership: filter.group_membership,
+        display_names: filter.display_names,
+        avatars: filter.avatars,
+        reactions_as_rows: filter.reactions_as_rows,
+        voice: filter.voice,
+        topic: filter.topic,
+    }
+}
+
+/// The filter the checkboxes say.
+pub fn timeline_filter(data: &TimelineFilterData) -> TimelineFilter {
+    TimelineFilter {
+        membership: data.membership,
+        group_membership: data.group_membership,
+        display_names: data.display_names,
+        avatars: data.avatars,
+        reactions_as_rows: data.reactions_as_rows,
+        voice: data.voice,
+        topic: data.topic,
+    }
+}
+
+fn status(text: &str, failed: bool) -> SettingStatus {
+    SettingStatus {
+        text: SharedString::from(text),
+        failed,
+    }
+}
+
+/// Whether the dialog is still open for `room_id`, and not for another
+/// room opened since.
+fn still_open(ui: &AppWindow, room_id: &str) -> bool {
+    ui.get_show_room_settings()
+        && OPEN.with(|o| {
+            o.borrow()
+                .as_ref()
+                .is_some_and(|open| open.room_id == room_id)
+        })
+}
+
+/// How to show a failure: an alias we turned down or a refusal as is,
+/// anything else as the message `failed` with the error.
+fn error_text(e: &anyhow::Error, failed: &str) -> SharedString {
+    match e.downcast_ref::<AliasError>() {
+        Some(e) => SharedString::from(e.to_string()),
+        None => admin::error_text(e, failed),
+    }
+}
+
+/// Open the dialog for `room_id` and load its settings and aliases.
+pub fn open(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, room_id: &str) {
+    let name = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(room_id))
+        .map(|room| room.name.clone())
+        .unwrap_or_default();
+    OPEN.with(|o| {
+        *o.borrow_mut() = Some(Open {
+            room_id: room_id.to_string(),
+            settings: None,
+            aliases: RoomAliases::default(),
+        })
+    });
+    let timeline = ConfigManager::load().timeline;
+    let (sound, sound_file) = room_sounds::shown(room_id);
+    ui.set_room_settings(RoomSettingsData {
+        name: SharedString::from(name.as_str()),
+        loading: true,
+        own_timeline_filter: timeline.rooms.contains_key(room_id),
+        timeline_filter: timeline_filter_data(&timeline.for_room(room_id)),
+        translate_from: SharedString::from(translations::auto_from(room_id).unwrap_or_default()),
+        can_translate: translations::is_available(),
+        sound,
+        sound_file,
+        ..Default::default()
+    });
+    ui.set_show_room_settings(true);
+
+    let reply = client.get_room_settings(room_id);
+    let aliases = client.get_room_aliases(room_id);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let (result, aliases) = tokio::join!(reply, aliases);
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if !still_open(&ui, &room_id) {
+                return;
+            }
+            match aliases {
+                Ok(aliases) => {
+                    show_aliases(&ui, &aliases);
+                    OPEN.with(|o| {
+                        if let Some(open) = o.borrow_mut().as_mut() {
+                            open.aliases = aliases;
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load room aliases: {}", e);
+                    let text = error_text(&e, "failed-load-aliases");
+                    set_status(&ui, Field::Aliases, status(&text, true));
+                }
+            }
+            match result {
+                Ok(settings) => {
+                    show(&ui, &settings);
+                    OPEN.with(|o| {
+                        if let Some(open) = o.borrow_mut().as_mut() {
+                            open.settings = Some(settings);
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load room settings: {}", e);
+                    let mut data = ui.get_room_settings();
+                    data.loading = false;
+                    data.error = admin::error_text(&e, "failed-load-room-settings");
+                    ui.set_room_settings(data);
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Close the dialog, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    ui.set_show_room_settings(false);
+    OPEN.with(|o| *o.borrow_mut() = None);
+}
+
+/// The room the dialog is open for.
+pub fn room_id() -> Option<String> {
+    OPEN.with(|o| o.borrow().as_ref().map(|open| open.room_id.clone()))
+}
+
+/// Give the room its own timeline filter, or go back to the default. Only
+/// this device's config changes, so there's nothing to wait for.
+pub fn set_timeline_filter(ui: &AppWindow, own: bool, data: &TimelineFilterData) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    let mut config = ConfigManager::load();
+    if own {
+        config
+            .timeline
+            .rooms
+            .insert(room_id.clone(), timeline_filter(data));
+    } else {
+        config.timeline.rooms.remove(&room_id);
+    }
+    let mut shown = ui.get_room_settings();
+    shown.own_timeline_filter = own;
+    shown.timeline_filter = timeline_filter_data(&config.timeline.for_room(&room_id));
+    ui.set_room_settings(shown);
+    history::configure(&config.timeline);
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the timeline filter: {:#}", e);
+    }
+}
+
+/// Translate others' messages in the room from `language` as they are
+/// shown, or stop when it is empty. Kept on this device, like the filter.
+pub fn set_translate_from(ui: &AppWindow, language: &str) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    let language = language.trim();
+    translations::set_auto(&room_id, language);
+    let mut config = ConfigManager::load();
+    if language.is_empty() {
+        config.translation.rooms.remove(&room_id);
+    } else {
+        config
+            .translation
+            .rooms
+            .insert(room_id, language.to_string());
+    }
+    let mut shown = ui.get_room_settings();
+    shown.translate_from = SharedString::from(language);
+    ui.set_room_settings(shown);
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the room's translation: {:#}", e);
+    }
+}
+
+/// Send a change to `field`, then keep `change` in what the dialog shows or
+/// put the control back if it failed.
+fn apply<T: Send + 'static>(
+    ui: &AppWindow,
+    field: Field,
+    reply: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+    failed: &'static str,
+    change: impl FnOnce(&AppWindow, &mut Open, T) + Send + 'static,
+) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    set_status(ui, field, status("Saving...", false));
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if !still_open(&ui, &room_id) {
+                return;
+            }
+            let shown = OPEN.with(|o| {
+                let mut open = o.borrow_mut();
+                let open = open.as_mut()?;
+                match result {
+                    Ok(value) => {
+                        change(&ui, open, value);
+                        set_status(&ui, field, status("Saved", false));
+                    }
+                    Err(e) => {
+                        tracing::warn!("{}: {}", failed, e);
+                        set_status(&ui, field, status(&error_text(&e, failed), true));
+                    }
+                }
+                Some((open.settings.clone(), open.aliases.clone()))
+            });
+            let Some((settings, aliases)) = shown else {
+                return;
+            };
+            if let Some(settings) = settings {
+                show(&ui, &settings);
+            }
+            show_aliases(&ui, &aliases);
+        })
+        .ok();
+    });
+}
+
+/// Make `change` to the loaded settings once applied.
+fn in_settings(
+    change: impl FnOnce(&mut RoomSettings) + Send + 'static,
+) -> impl FnOnce(&AppWindow, &mut Open, ()) + Send + 'static {
+    move |_, open, ()| {
+        if let Some(settings) = &mut open.settings {
+            change(settings);
+        }
+    }
+}
+
+pub fn set_join_rule(ui: &AppWindow, client: &ClientHandle, index: i32) {
+    let Some(&rule) = JOIN_RULES.get(index as usize) else {
+        return;
+    };
+    let Some(room_id) = room_id().filter(|_| rule != JoinRule::Other) else {
+        return;
+    };
+    let reply = client.set_join_rule(&room_id, rule);
+    apply(
+        ui,
+        Field::JoinRule,
+        reply,
+        "failed-change-join-rule",
+        in_settings(move |s| s.join_rule = rule),
+    );
+}
+
+pub fn set_history_visibility(ui: &AppWindow, client: &ClientHandle, index: i32) {
+    let (Some(&visibility), Some(room_id)) = (HISTORY.get(index as usize), room_id()) else {
+        return;
+    };
+    let reply = client.set_history_visibility(&room_id, visibility);
+    apply(
+        ui,
+        Field::History,
+        reply,
+        "failed-change-history-visibility",
+        in_settings(move |s| s.history_visibility = visibility),
+    );
+}
+
+pub fn set_guest_access(ui: &AppWindow, client: &ClientHandle, allowed: bool) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    let reply = client.set_guest_access(&room_id, allowed);
+    apply(
+        ui,
+        Field::GuestAccess,
+        reply,
+        "failed-change-guest-access",
+        in_settings(move |s| s.guest_access = allowed),
+    );
+}
+
+/// Turn encryption on. The composer switches over as soon as the server
+/// agrees, without waiting for sync to say so.
+pub fn enable_encryption(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    let reply = client.enable_encryption(&room_id);
+    let sidebar = sidebar.clone();
+    apply(
+        ui,
+        Field::Encryption,
+        reply,
+        "failed-enable-encryption",
+        move |ui, open, ()| {
+            if let Some(settings) = &mut open.settings {
+                settings.encrypted = true;
+                settings.can_change.encryption = false;
+            }
+            let patches = match sidebar.lock().unwrap().as_mut() {
+                Some(sb) => sb.set_encrypted(&room_id),
+                None => Vec::new(),
+            };
+            rooms::apply_patches(ui, &sidebar, patches);
+        },
+    );
+}
+
+/// Set the slow mode to the choice at `index`; a time set elsewhere stays.
+pub fn set_slow_mode(ui: &AppWindow, client: &ClientHandle, index: i32) {
+    let (Some(&seconds), Some(room_id)) = (SLOW_MODES.get(index as usize), room_id()) else {
+        return;
+    };
+    let reply = client.set_slow_mode(&room_id, seconds);
+    apply(
+        ui,
+        Field::SlowMode,
+        reply,
+        "failed-change-slow-mode",
+        in_settings(move |s| s.slow_mode_secs = seconds),
+    );
+}
+
+/// Set the welcome message to `body`, or remove it if empty. Formatting
+/// set elsewhere goes, as the dialog edits plain text.
+pub fn set_welcome(ui: &AppWindow, client: &ClientHandle, body: &str, must_accept: bool) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    let reply = client.set_welcome(&room_id, body, must_accept);
+    let body = body.trim().to_string();
+    apply(
+        ui,
+        Field::Welcome,
+        reply,
+        "failed-change-welcome",
+        in_settings(move |s| {
+            s.welcome = (!body.is_empty()).then(|| Welcome {
+                body,
+                must_accept,
+                ..Welcome::default()
+            });
+        }),
+    );
+}
+
+/// Publish `alias`, as typed, for the room.
+pub fn add_alias(ui: &AppWindow, client: &ClientHandle, alias: &str) {
+    let Some(room_id) = room_id().filter(|_| !alias.trim().is_empty()) else {
+        return;
+    };
+    let reply = client.add_room_alias(&room_id, alias);
+    apply(
+        ui,
+        Field::Aliases,
+        reply,
+        "failed-add-alias",
+        |_, open, alias| {
+            if !open.aliases.aliases.contains(&alias) {
+                open.aliases.aliases.push(alias);
+            }
+        },
+    );
+}
+
+pub fn remove_alias(ui: &AppWindow, client: &ClientHandle, alias: &str) {
+    let reply = client.remove_room_alias(alias);
+    let alias = alias.to_string();
+    apply(
+        ui,
+        Field::Aliases,
+        reply,
+        "failed-remove-alias",
+        move |_, open, ()| {
+            let aliases = &mut open.aliases;
+            aliases.aliases.retain(|a| *a != alias);
+            if aliases.canonical.as_ref() == Some(&alias) {
+                aliases.canonical = None;
+            }
+        },
+    );
+}
+
+/// Make `alias` the one the room is shown with, in the channel header too.
+pub fn set_main_alias(ui: &AppWindow, client: &ClientHandle, alias: &str) {
+    let Some(room_id) = room_id() else {
+        return;
+    };
+    let reply = client.set_canonical_alias(&room_id, alias);
+    let alias = alias.to_string();
+    apply(
+        ui,
+        Field::Aliases,
+        reply,
+        "failed-change-main-alias",
+        move |_, open, ()| {
+            let aliases = &mut open.aliases;
+            aliases.aliases.retain(|a| *a != alias);
+            aliases.aliases.insert(0, alias.clone());
+            aliases.canonical = Some(alias);
+        },
+    );
+}
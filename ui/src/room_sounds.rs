@@ -0,0 +1,290 @@
+//! Rooms' own notification sounds, chosen in the room settings: one of the
+//! tones built in, or a short WAV or Ogg file of the user's, played through
+//! the cue device in place of the system's sound. They are kept per
+//! account. A file that has gone missing or can't be played is reported
+//! once, and the room notifies with the system's sound until it is fixed.
+
+use crate::{room_settings, AppWindow, SettingStatus};
+use network::config::{Config, ConfigManager};
+use network::cue_sound::{self, RoomSound, Sound, Tone};
+use network::voice::VoiceManager;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The dialog's list: the system's sound, the tones, then a file.
+const CUSTOM: i32 = Tone::ALL.len() as i32 + 1;
+
+#[derive(Default)]
+struct RoomSounds {
+    voice: Option<Arc<VoiceManager>>,
+    user_id: String,
+    sounds: BTreeMap<String, RoomSound>,
+    /// Files read so far, `None` for those that can't be played, so a
+    /// broken one is only read and reported once.
+    files: HashMap<PathBuf, Option<Sound>>,
+}
+
+thread_local! {
+    static ROOM_SOUNDS: RefCell<RoomSounds> = RefCell::new(RoomSounds::default());
+}
+
+/// Play sounds through `voice` from now on.
+pub fn start(voice: Arc<VoiceManager>) {
+    ROOM_SOUNDS.with(|r| r.borrow_mut().voice = Some(voice));
+}
+
+/// Use the sounds `user_id` chose, as they sign in. Files are read again,
+/// in case they were fixed in the meantime.
+pub fn load(config: &Config, user_id: &str) {
+    ROOM_SOUNDS.with(|r| {
+        let mut state = r.borrow_mut();
+        state.user_id = user_id.to_string();
+        state.sounds = config.account(user_id).room_sounds;
+        state.files.clear();
+    });
+}
+
+/// No sounds while signed out.
+pub fn clear() {
+    ROOM_SOUNDS.with(|r| {
+        let mut state = r.borrow_mut();
+        state.user_id.clear();
+        state.sounds.clear();
+        state.files.clear();
+    });
+}
+
+/// The sound `user_id` chose for `room_id`, if any. Accounts in the
+/// background have theirs read from the config.
+fn chosen(user_id: &str, room_id: &str) -> Option<RoomSound> {
+    let shown = ROOM_SOUNDS.with(|r| {
+        let state = r.borrow();
+        (state.user_id == user_id).then(|| state.sounds.get(room_id).cloned())
+    });
+    shown.unwrap_or_else(|| {
+        ConfigManager::load()
+            .account(user_id)
+            .room_sounds
+            .get(room_id)
+            .cloned()
+    })
+}
+
+/// The file at `path`, read the first time it is asked for.
+fn file(path: &Path) -> Option<Sound> {
+    if let Some(known) = ROOM_SOUNDS.with(|r| r.borrow().files.get(path).cloned()) {
+        return known;
+    }
+    let sound = match cue_sound::load(path) {
+        Ok(sound) => Some(sound),
+        Err(e) => {
+            tracing::warn!("Using the default notification sound: {:#}", e);
+            None
+        }
+    };
+    ROOM_SOUNDS.with(|r| {
+        r.borrow_mut()
+            .files
+            .insert(path.to_path_buf(), sound.clone())
+    });
+    sound
+}
+
+fn sound(choice: &RoomSound) -> Option<Sound> {
+    match choice {
+        RoomSound::Tone(tone) => Some(tone.sound()),
+        RoomSound::File(path) => file(path),
+    }
+}
+
+fn play_sound(sound: &Sound) {
+    if let Some(voice) = ROOM_SOUNDS.with(|r| r.borrow().voice.clone()) {
+        voice.play(sound);
+    }
+}
+
+/// Play the sound `user_id` chose for `room_id`, as it notifies. Returns
+/// whether there was one to play; if not, the system's sound is left on.
+pub fn play(user_id: &str, room_id: &str) -> bool {
+    let Some(sound) = chosen(user_id, room_id).as_ref().and_then(sound) else {
+        return false;
+    };
+    play_sound(&sound);
+    true
+}
+
+/// Where `choice` is in the dialog's list.
+fn index(choice: Option<&RoomSound>) -> i32 {
+    match choice {
+        None => 0,
+        Some(RoomSound::Tone(tone)) => {
+            Tone::ALL.iter().position(|t| t == tone).unwrap_or(0) as i32 + 1
+        }
+        Some(RoomSound::File(_)) => CUSTOM,
+    }
+}
+
+/// The tone at `index` in the dialog's list; `None` for the system's sound.
+fn tone_at(index: i32) -> Option<Tone> {
+    let at = usize::try_from(index).ok()?.checked_sub(1)?;
+    Tone::ALL.get(at).copied()
+}
+
+fn file_name(choice: Option<&RoomSound>) -> SharedString {
+    match choice {
+        Some(RoomSound::File(path)) => path
+            .file_name()
+            .map(|name| SharedString::from(name.to_string_lossy().as_ref()))
+            .unwrap_or_default(),
+        _ => SharedString::new(),
+    }
+}
+
+fn status(text: &str, failed: bool) -> SettingStatus {
+    SettingStatus {
+        text: SharedString::from(text),
+        failed,
+    }
+}
+
+/// What the room settings show for `room_id`: where its sound is in the
+/// list, and the file's name if it is one.
+pub fn shown(room_id: &str) -> (i32, SharedString) {
+    ROOM_SOUNDS.with(|r| {
+        let choice = r.borrow().sounds.get(room_id).cloned();
+        (index(choice.as_ref()), file_name(choice.as_ref()))
+    })
+}
+
+/// Keep `choice` for `room_id`, or go back to the system's sound.
+fn set(ui: &AppWindow, room_id: &str, choice: Option<RoomSound>, note: SettingStatus) {
+    let user_id = ROOM_SOUNDS.with(|r| {
+        let mut state = r.borrow_mut();
+        match &choice {
+            Some(choice) => state.sounds.insert(room_id.to_string(), choice.clone()),
+            None => state.sounds.remove(room_id),
+        };
+        state.user_id.clone()
+    });
+    // The dialog may have been opened for another room while a file was
+    // picked.
+    if room_settings::room_id().as_deref() == Some(room_id) {
+        let mut data = ui.get_room_settings();
+        data.sound = index(choice.as_ref());
+        data.sound_file = file_name(choice.as_ref());
+        data.sound_status = note;
+        ui.set_room_settings(data);
+    }
+
+    let mut config = ConfigManager::load();
+    let sounds = &mut config.accounts.entry(user_id).or_default().room_sounds;
+    match choice {
+        Some(choice) => sounds.insert(room_id.to_string(), choice),
+        None => sounds.remove(room_id),
+    };
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save the room's notification sound: {:#}", e);
+    }
+}
+
+/// The list in the room settings changed to `index`: a tone or the
+/// system's sound is kept at once, a file is asked for first.
+pub fn select(ui: &AppWindow, room_id: &str, index: i32) {
+    if index == CUSTOM {
+        pick_file(ui, room_id);
+        return;
+    }
+    let choice = tone_at(index).map(RoomSound::Tone);
+    if let Some(sound) = choice.as_ref().and_then(sound) {
+        play_sound(&sound);
+    }
+    set(ui, room_id, choice, status("", false));
+}
+
+/// Let the user choose a file, and keep it if it can be played. Otherwise
+/// the room keeps the sound it had.
+fn pick_file(ui: &AppWindow, room_id: &str) {
+    // Created here, on the UI thread, as some platforms require.
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Choose a notification sound")
+        .add_filter("Sounds", &["wav", "ogg"])
+        .pick_file();
+    let ui_handle = ui.as_weak();
+    let room_id = room_id.to_string();
+    tokio::spawn(async move {
+        let loaded = dialog.await.map(|file| {
+            let path = file.path().to_path_buf();
+            (cue_sound::load(&path), path)
+        });
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let (note, path) = match loaded {
+                // Cancelled: show the sound it had again.
+                None => (status("", false), None),
+                Some((Err(e), _)) => (status(&format!("{:#}", e), true), None),
+                Some((Ok(sound), path)) => {
+                    play_sound(&sound);
+                    ROOM_SOUNDS.with(|r| r.borrow_mut().files.insert(path.clone(), Some(sound)));
+                    (status("", false), Some(path))
+                }
+            };
+            let choice = match path {
+                Some(path) => Some(RoomSound::File(path)),
+                None => ROOM_SOUNDS.with(|r| r.borrow().sounds.get(&room_id).cloned()),
+            };
+            set(&ui, &room_id, choice, note);
+        })
+        .ok();
+    });
+}
+
+/// Play the sound the room settings show for `room_id`, or say why not.
+pub fn preview(ui: &AppWindow, room_id: &str) {
+    let choice = ROOM_SOUNDS.with(|r| r.borrow().sounds.get(room_id).cloned());
+    let Some(choice) = choice else {
+        return;
+    };
+    match sound(&choice) {
+        Some(sound) => play_sound(&sound),
+        None => {
+            let mut data = ui.get_room_settings();
+            data.sound_status = status(
+                "This file can't be played; the default sound is used instead",
+                true,
+            );
+            ui.set_room_settings(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_index_round_trips() {
+        assert_eq!(index(None), 0);
+        assert_eq!(tone_at(0), None);
+        for tone in Tone::ALL {
+            let at = index(Some(&RoomSound::Tone(tone)));
+            assert_eq!(tone_at(at), Some(tone));
+        }
+        let file = RoomSound::File(PathBuf::from("/sounds/horn.ogg"));
+        assert_eq!(index(Some(&file)), CUSTOM);
+        assert_eq!(tone_at(CUSTOM), None);
+        assert_eq!(file_name(Some(&file)), "horn.ogg");
+    }
+
+    #[test]
+    fn test_missing_file_falls_back_once() {
+        let path = PathBuf::from("/nonexistent/horn.ogg");
+        assert_eq!(file(&path), None);
+        let known = ROOM_SOUNDS.with(|r| r.borrow().files.get(&path).cloned());
+        assert_eq!(known, Some(None));
+    }
+}
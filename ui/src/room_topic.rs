@@ -0,0 +1,77 @@
+//! The open room's topic: a line of it beside the room's name, opened in
+//! full with its links on a click, and changed there by those allowed to.
+//! Changes from sync show at once, and as a notice in the timeline unless
+//! the room's filter hides it.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, local_echo, rich_text, timeline, toast, AppWindow, TextBlockData};
+use chat_core::{time, Message, MessageType, Notice};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::rc::Rc;
+
+/// Show `topic` as the open room's, or none.
+pub fn show(ui: &AppWindow, topic: Option<&str>) {
+    let topic = topic.unwrap_or_default();
+    // The header has room for one line of it.
+    let line = topic.split_whitespace().collect::<Vec<_>>().join(" ");
+    let blocks: Vec<TextBlockData> = rich_text::topic_rows(topic)
+        .into_iter()
+        .map(|row| timeline::block_data(row, Rc::new(VecModel::default()).into()))
+        .collect();
+    ui.set_active_channel_topic(SharedString::from(line));
+    ui.set_active_channel_topic_text(SharedString::from(topic));
+    ui.set_active_channel_topic_blocks(Rc::new(VecModel::from(blocks)).into());
+}
+
+/// Note in `room_id`'s timeline that `sender` changed its topic to `topic`.
+/// The notice has a local id and is never read up to; the room's filter
+/// decides whether it shows.
+pub fn changed(room_id: &str, topic: Option<&str>, sender: &str, sender_name: Option<String>) {
+    let mut message = Message {
+        id: local_echo::new_notice_id(),
+        sender: sender.to_string(),
+        sender_name,
+        content: String::new(),
+        schema: MessageType::SystemNotice(Notice::Topic),
+        timestamp: time::now_ms(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    };
+    message.content = match topic {
+        Some(topic) => format!(
+            "{} changed the topic to \"{}\"",
+            message.sender_display(),
+            topic.split_whitespace().collect::<Vec<_>>().join(" ")
+        ),
+        None => format!("{} removed the topic", message.sender_display()),
+    };
+    history::push_remote(room_id, message, None);
+}
+
+/// Change the open room's topic to `topic`; an empty one removes it. The
+/// header follows once sync brings the change back.
+pub fn set(ui: &AppWindow, client: &ClientHandle, topic: &str) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let reply = client.set_topic(&room_id, topic.trim());
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Err(e) = result else {
+                return;
+            };
+            tracing::warn!("Failed to change the topic: {}", e);
+            if let Some(ui) = ui_handle.upgrade() {
+                toast::show(&ui, admin::error_text(&e, "failed-change-topic"));
+            }
+        })
+        .ok();
+    });
+}
@@ -0,0 +1,811 @@
+//! Keeps the server rail, channel list and timeline models in step with the
+//! joined rooms reported by the client.
+
+use crate::app_state::{ClientHandle, RoomList};
+use crate::send_queue::{self, PendingState};
+use crate::sidebar::{Patch, Sidebar, HOME_SERVER_ID};
+use crate::switcher_index::SwitcherIndex;
+use crate::timeline::TimelineModel;
+use crate::{
+    accessibility, accounts, connection_status, file_upload, forwarding, friends, history,
+    incoming_call, input_history, voice_channel, voice_clips,
+};
+use crate::{bridges, favorites, pins, stickers, AppWindow, ChannelData, ServerData};
+use crate::{knocks, local_echo, member_list, mention_completion, message_actions, quick_switcher};
+use crate::{replay, room_security, room_topic, scheduled, send_guard, sender_trust, toast, tray};
+use crate::{typing_indicator, unsent, welcome};
+use chat_core::mention::Mention;
+use chat_core::outbox::Unsent;
+use chat_core::{time, Message, MessageType, Room, RoomType, Space, UnreadCounts};
+use network::encryption::UnverifiedDevices;
+use network::events::ChatEvent;
+use network::federation::FederationError;
+use network::startup;
+use slint::{ComponentHandle, Model, SharedString, VecModel, Weak};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Sidebar state shared between the UI thread and the event task.
+/// `None` until the room list has been loaded after login.
+pub type SharedSidebar = Arc<Mutex<Option<Sidebar>>>;
+
+thread_local! {
+    /// The window, to say why a send failed after the user has moved on.
+    static WINDOW: RefCell<Option<Weak<AppWindow>>> = const { RefCell::new(None) };
+}
+
+const SERVER_COLORS: [(u8, u8, u8); 5] = [
+    (0xe6, 0x4a, 0x19),
+    (0x00, 0xbf, 0xa5),
+    (0xf5, 0x9e, 0x0b),
+    (0xeb, 0x45, 0x9e),
+    (0x57, 0xf2, 0x87),
+];
+
+/// Badge counts as model ints.
+fn badge(count: u64) -> i32 {
+    count.min(i32::MAX as u64) as i32
+}
+
+fn set_server_unread(server: &mut ServerData, unread: UnreadCounts) {
+    server.unread_count = badge(unread.notifications);
+    server.highlight_count = badge(unread.highlights);
+}
+
+fn home_server(unread: UnreadCounts) -> ServerData {
+    let mut server = ServerData {
+        id: SharedString::from(HOME_SERVER_ID),
+        name: SharedString::from("DM"),
+        color: slint::Color::from_rgb_u8(0x58, 0x65, 0xf2),
+        online: true,
+        ..Default::default()
+    };
+    set_server_unread(&mut server, unread);
+    server
+}
+
+/// Rail icons show the first letter of the space name.
+fn rail_label(name: &str) -> SharedString {
+    let initial: String = name
+        .chars()
+        .next()
+        .map(|c| c.to_uppercase().collect())
+        .unwrap_or_default();
+    SharedString::from(initial)
+}
+
+/// Rail entry for the space at `index` in `Sidebar::spaces`.
+fn server_data(index: usize, space: &Space, unread: UnreadCounts) -> ServerData {
+    let (r, g, b) = SERVER_COLORS[index % SERVER_COLORS.len()];
+    let mut server = ServerData {
+        id: SharedString::from(space.id.as_str()),
+        name: rail_label(&space.name),
+        color: slint::Color::from_rgb_u8(r, g, b),
+        online: true,
+        ..Default::default()
+    };
+    set_server_unread(&mut server, unread);
+    server
+}
+
+fn channel_data(room: &Room) -> ChannelData {
+    ChannelData {
+        id: SharedString::from(room.id.as_str()),
+        name: SharedString::from(room.name.as_str()),
+        unread_count: badge(room.unread.notifications),
+        highlight_count: badge(room.unread.highlights),
+        muted: room.muted,
+        direct: room.room_type == RoomType::Direct,
+        // Known on the UI thread only; see `local_state`.
+        unbridged: false,
+        favorite: false,
+    }
+}
+
+/// `channel` saying whether its relayed messages are shown as sent, and
+/// whether it is pinned.
+fn local_state(channel: ChannelData) -> ChannelData {
+    ChannelData {
+        unbridged: !bridges::is_on(&channel.id),
+        favorite: favorites::contains(&channel.id),
+        ..channel
+    }
+}
+
+/// The favorites strip: pinned rooms in their order, leaving out any not
+/// loaded yet.
+fn set_favorites(ui: &AppWindow, sb: Option<&Sidebar>) {
+    let rows: Vec<ChannelData> = sb.map_or_else(Vec::new, |sb| {
+        favorites::rooms()
+            .iter()
+            .filter_map(|room_id| sb.room(room_id))
+            .map(|room| local_state(channel_data(room)))
+            .collect()
+    });
+    ui.set_favorites(Rc::new(VecModel::from(rows)).into());
+}
+
+/// Replace the timeline with `messages` (oldest first).
+pub fn set_messages(ui: &AppWindow, messages: Vec<Message>) {
+    let model = TimelineModel::new(ui.get_current_user_id().as_str(), messages);
+    ui.set_messages(Rc::new(model).into());
+}
+
+/// Run `f` on the timeline currently shown.
+pub fn with_timeline<R>(ui: &AppWindow, f: impl FnOnce(&TimelineModel) -> R) -> Option<R> {
+    let model = ui.get_messages();
+    model.as_any().downcast_ref::<TimelineModel>().map(f)
+}
+
+/// An outgoing text message from the logged-in user, not yet sent.
+pub fn outgoing_message(ui: &AppWindow, body: &str) -> Message {
+    Message {
+        id: String::new(),
+        sender: ui.get_current_user_id().to_string(),
+        sender_name: Some(ui.get_current_display_name().to_string()),
+        content: body.to_string(),
+        schema: MessageType::Text,
+        timestamp: time::now_ms(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    }
+}
+
+/// Show `body` as a local echo in the open room and send it, mentioning
+/// `mentions`, as a reply to `reply_to` if given. While the homeserver can't
+/// be reached the message is queued instead, and sent on reconnecting.
+pub fn send_message(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    body: &str,
+    mentions: Vec<Mention>,
+    reply_to: Option<&str>,
+) {
+    let Some(room_id) = history::current_room() else {
+        tracing::warn!("No room selected");
+        return;
+    };
+    send_to(ui, client, &room_id, body, mentions, reply_to);
+}
+
+/// Like `send_message`, to `room_id` whether or not it is the open room,
+/// e.g. from a popout.
+pub fn send_to(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    room_id: &str,
+    body: &str,
+    mentions: Vec<Mention>,
+    reply_to: Option<&str>,
+) {
+    let room_id = room_id.to_string();
+    input_history::sent(&room_id, body);
+    let txn_id = local_echo::new_txn_id();
+    let mut message = outgoing_message(ui, body);
+    message.reply_to = reply_to.map(str::to_owned);
+    message.mentions = mentions;
+    unsent::written(&room_id, &txn_id, &message);
+    send_as(ui, client, room_id, txn_id, message);
+}
+
+/// Send a message from the outbox as if it had just been written: one an
+/// earlier run left unsent, or one scheduled and now due. It goes under
+/// its transaction id, so that the homeserver drops it if it did arrive
+/// after all.
+pub fn send_unsent(ui: &AppWindow, client: &ClientHandle, unsent: &Unsent) {
+    // The room may not have been opened yet; its echo needs a timeline.
+    history::shared_model(ui, client, &unsent.room_id);
+    let mut message = outgoing_message(ui, &unsent.body);
+    message.reply_to = unsent.reply_to.clone();
+    message.mentions = unsent.mentions.clone();
+    send_as(
+        ui,
+        client,
+        unsent.room_id.clone(),
+        unsent.txn_id.clone(),
+        message,
+    );
+}
+
+/// Show `message` as a local echo in `room_id` and send it as `txn_id`,
+/// or queue it while offline.
+fn send_as(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    room_id: String,
+    txn_id: String,
+    message: Message,
+) {
+    room_security::sending(ui, &room_id);
+    if !connection_status::is_connected() {
+        send_queue::queued(&room_id, &txn_id, &message.content, PendingState::Offline);
+        history::with_room(&room_id, |timeline| timeline.push_queued(&txn_id, message));
+        connection_status::queue(&room_id, &txn_id);
+        return;
+    }
+    deliver(client, room_id.clone(), txn_id.clone(), &message);
+    history::with_room(&room_id, |timeline| timeline.push_local(&txn_id, message));
+}
+
+/// Resend a message in the open room that failed, under its original
+/// transaction id.
+pub fn retry_message(client: &ClientHandle, txn_id: &str) {
+    if let Some(room_id) = history::current_room() {
+        retry(client, room_id, txn_id);
+    }
+}
+
+/// Retry every message in the open room that failed, oldest first.
+pub fn retry_all(client: &ClientHandle) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let failed = history::with_room(&room_id, |timeline| timeline.failed()).unwrap_or_default();
+    for txn_id in failed {
+        retry(client, room_id.clone(), &txn_id);
+    }
+}
+
+/// Resend a failed message in `room_id`, or while offline queue it again.
+pub fn retry(client: &ClientHandle, room_id: String, txn_id: &str) {
+    if connection_status::is_connected() {
+        resend(client, room_id, txn_id);
+    } else {
+        history::with_room(&room_id, |timeline| timeline.requeue(txn_id));
+        connection_status::queue(&room_id, txn_id);
+        send_queue::set_state(txn_id, PendingState::Offline);
+    }
+}
+
+/// Drop a failed or queued message in the open room without sending it.
+pub fn discard_message(txn_id: &str) {
+    if let Some(room_id) = history::current_room() {
+        discard(&room_id, txn_id);
+    }
+}
+
+/// Drop a failed or queued message in `room_id` without sending it.
+pub fn discard(room_id: &str, txn_id: &str) {
+    if history::with_room(room_id, |timeline| timeline.discard(txn_id)) == Some(true) {
+        connection_status::unqueue(txn_id);
+        file_upload::discard_failed(txn_id);
+    }
+    unsent::done(txn_id);
+    send_queue::done(txn_id);
+}
+
+/// Drop a message in `room_id` that hasn't reached the server: failed,
+/// queued, or waiting for its slow mode turn.
+pub fn cancel(room_id: &str, txn_id: &str) {
+    history::with_room(room_id, |timeline| timeline.mark_failed(txn_id));
+    discard(room_id, txn_id);
+}
+
+/// Send a failed or queued message in `room_id` under its transaction id.
+pub fn resend(client: &ClientHandle, room_id: String, txn_id: &str) {
+    let Some(message) = history::with_room(&room_id, |timeline| timeline.retry(txn_id)).flatten()
+    else {
+        return;
+    };
+    if message.attachment.is_some() {
+        file_upload::retry(client, txn_id);
+        return;
+    }
+    deliver(client, room_id, txn_id.to_string(), &message);
+}
+
+/// Send once the room's slow mode allows, unless cancelled meanwhile, and
+/// reconcile the echo in `room_id`'s timeline, even if the user has
+/// switched rooms by the time the reply arrives.
+fn deliver(client: &ClientHandle, room_id: String, txn_id: String, message: &Message) {
+    send_queue::queued(&room_id, &txn_id, &message.content, PendingState::Waiting);
+    let (room, message, sender) = (room_id.clone(), message.clone(), client.clone());
+    send_guard::when_ready(client, &room, move || {
+        if send_queue::is_pending(&txn_id) {
+            send_now(&sender, room_id, txn_id, &message)
+        }
+    });
+}
+
+fn send_now(client: &ClientHandle, room_id: String, txn_id: String, message: &Message) {
+    let reply = client.send_to(
+        &room_id,
+        &message.content,
+        &message.mentions,
+        message.reply_to.as_deref(),
+        &txn_id,
+    );
+    send_queue::set_state(&txn_id, PendingState::Sending);
+    tokio::spawn(async move {
+        let result = reply.await;
+        if let Err(e) = &result {
+            tracing::error!("Send failed: {}", e);
+        }
+        slint::invoke_from_event_loop(move || match result {
+            Ok(event_id) => {
+                history::with_room(&room_id, |timeline| timeline.mark_sent(&txn_id, &event_id));
+                unsent::done(&txn_id);
+                send_queue::done(&txn_id);
+            }
+            Err(e) => {
+                history::with_room(&room_id, |timeline| timeline.mark_failed(&txn_id));
+                send_queue::set_state(&txn_id, PendingState::Failed);
+                send_failed(&room_id, &e);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Say why a send into `room_id` failed, where the timeline only shows
+/// that it did: it was refused over unverified devices, or by the room's
+/// other servers.
+pub fn send_failed(room_id: &str, e: &anyhow::Error) {
+    let unverified = e.is::<UnverifiedDevices>();
+    if !unverified && !e.is::<FederationError>() {
+        return;
+    }
+    let Some(ui) = WINDOW.with(|window| window.borrow().as_ref().and_then(Weak::upgrade)) else {
+        return;
+    };
+    toast::show(&ui, e.to_string());
+    if unverified {
+        room_security::refused(&ui, room_id);
+    }
+}
+
+/// The sidebar's models as plain rows, which can be built off the UI thread
+/// and shown in one go.
+struct SidebarRows {
+    servers: Vec<ServerData>,
+    active_server: usize,
+    channels: Vec<ChannelData>,
+    active: Option<Room>,
+    mentions: bool,
+}
+
+impl SidebarRows {
+    fn new(sb: &Sidebar) -> Self {
+        let mut servers = vec![home_server(sb.server_unread(0))];
+        servers.extend(
+            sb.spaces()
+                .iter()
+                .enumerate()
+                .map(|(i, s)| server_data(i, s, sb.server_unread(i + 1))),
+        );
+        Self {
+            servers,
+            active_server: sb.active_server(),
+            channels: sb.channels().into_iter().map(channel_data).collect(),
+            active: sb.active_room().and_then(|id| sb.room(id)).cloned(),
+            mentions: sb.total_unread().highlights > 0,
+        }
+    }
+}
+
+/// Rebuild every sidebar model from the current state.
+pub fn refresh_sidebar(ui: &AppWindow, sidebar: &SharedSidebar) {
+    let rows = {
+        let guard = sidebar.lock().unwrap();
+        set_favorites(ui, guard.as_ref());
+        guard.as_ref().map(SidebarRows::new)
+    };
+    match rows {
+        Some(rows) => show_sidebar(ui, rows),
+        None => {
+            ui.set_servers(Rc::new(VecModel::<ServerData>::default()).into());
+            ui.set_channels(Rc::new(VecModel::<ChannelData>::default()).into());
+            tray::update(|state| state.mentions = false);
+        }
+    }
+}
+
+/// Replace every sidebar model with `rows`.
+fn show_sidebar(ui: &AppWindow, rows: SidebarRows) {
+    tray::update(|state| state.mentions = rows.mentions);
+    ui.set_servers(Rc::new(VecModel::from(rows.servers)).into());
+    ui.set_active_server_index(rows.active_server as i32);
+    let channels: Vec<ChannelData> = rows.channels.into_iter().map(local_state).collect();
+    ui.set_channels(Rc::new(VecModel::from(channels)).into());
+    show_active_channel(ui, rows.active.as_ref());
+}
+
+fn show_active_channel(ui: &AppWindow, active: Option<&Room>) {
+    ui.set_active_channel(SharedString::from(
+        active.map(|r| r.id.as_str()).unwrap_or(""),
+    ));
+    ui.set_active_channel_name(SharedString::from(
+        active.map(|r| r.name.as_str()).unwrap_or(""),
+    ));
+    ui.set_active_channel_encrypted(active.is_some_and(|r| r.encrypted));
+    ui.set_active_channel_alias(SharedString::from(
+        active.and_then(|r| r.alias.as_deref()).unwrap_or(""),
+    ));
+    room_topic::show(ui, active.and_then(|r| r.topic.as_deref()));
+}
+
+fn set_channels(ui: &AppWindow, sb: &Sidebar) {
+    let channels: Vec<ChannelData> = sb
+        .channels()
+        .into_iter()
+        .map(|room| local_state(channel_data(room)))
+        .collect();
+    ui.set_channels(Rc::new(VecModel::from(channels)).into());
+    show_active_channel(ui, sb.active_room().and_then(|id| sb.room(id)));
+}
+
+/// Apply incremental patches so row state (selection, scroll) survives.
+pub fn apply_patches(ui: &AppWindow, sidebar: &SharedSidebar, patches: Vec<Patch>) {
+    let servers_model = ui.get_servers();
+    let channels_model = ui.get_channels();
+    let servers = servers_model
+        .as_any()
+        .downcast_ref::<VecModel<ServerData>>();
+    let channels = channels_model
+        .as_any()
+        .downcast_ref::<VecModel<ChannelData>>();
+    let (Some(servers), Some(channels)) = (servers, channels) else {
+        refresh_sidebar(ui, sidebar);
+        return;
+    };
+
+    let guard = sidebar.lock().unwrap();
+    let Some(sb) = guard.as_ref() else {
+        return;
+    };
+    for patch in patches {
+        match patch {
+            Patch::ServerInserted { index, space } => {
+                servers.insert(
+                    index,
+                    server_data(index - 1, &space, sb.server_unread(index)),
+                );
+            }
+            Patch::ServerRemoved { index } => {
+                servers.remove(index);
+            }
+            Patch::ServerRenamed { index, name } => {
+                if let Some(mut row) = servers.row_data(index) {
+                    row.name = rail_label(&name);
+                    servers.set_row_data(index, row);
+                }
+            }
+            Patch::ChannelInserted { index, room } => {
+                channels.insert(index, local_state(channel_data(&room)));
+            }
+            Patch::ChannelRemoved { index } => {
+                channels.remove(index);
+            }
+            Patch::ChannelRenamed { index, name } => {
+                if let Some(mut row) = channels.row_data(index) {
+                    if row.id.as_str() == ui.get_active_channel().as_str() {
+                        ui.set_active_channel_name(SharedString::from(name.as_str()));
+                    }
+                    row.name = SharedString::from(name);
+                    channels.set_row_data(index, row);
+                }
+            }
+            Patch::ChannelUnread { index, unread } => {
+                if let Some(mut row) = channels.row_data(index) {
+                    row.unread_count = badge(unread.notifications);
+                    row.highlight_count = badge(unread.highlights);
+                    channels.set_row_data(index, row);
+                }
+            }
+            Patch::ServerUnread { index, unread } => {
+                if let Some(mut row) = servers.row_data(index) {
+                    set_server_unread(&mut row, unread);
+                    servers.set_row_data(index, row);
+                }
+            }
+            Patch::RoomEncrypted { room_id } => {
+                if room_id == ui.get_active_channel().as_str() {
+                    ui.set_active_channel_encrypted(true);
+                }
+            }
+            Patch::RoomAliasChanged { room_id, alias } => {
+                if room_id == ui.get_active_channel().as_str() {
+                    let alias = alias.as_deref().unwrap_or("");
+                    ui.set_active_channel_alias(SharedString::from(alias));
+                }
+            }
+            Patch::RoomTopicChanged { room_id, topic } => {
+                if room_id == ui.get_active_channel().as_str() {
+                    room_topic::show(ui, topic.as_deref());
+                }
+            }
+            Patch::ChannelsReset => {
+                set_channels(ui, sb);
+                // Rooms may have moved between servers.
+                for index in 0..servers.row_count() {
+                    if let Some(mut row) = servers.row_data(index) {
+                        set_server_unread(&mut row, sb.server_unread(index));
+                        servers.set_row_data(index, row);
+                    }
+                }
+            }
+        }
+    }
+    // Pinned rooms may be anywhere; the strip is small enough to rebuild.
+    set_favorites(ui, Some(sb));
+    ui.set_active_server_index(sb.active_server() as i32);
+    tray::update(|state| state.mentions = sb.total_unread().highlights > 0);
+}
+
+/// Open `room_id` from the channel list: select it, clear its badge and load
+/// its timeline, members and voice channel. The server is told via the read
+/// receipt sent on open.
+pub fn select_room(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, room_id: &str) {
+    let (name, patches) = {
+        let mut guard = sidebar.lock().unwrap();
+        let Some(sb) = guard.as_mut() else {
+            return;
+        };
+        sb.set_active_room(Some(room_id.to_string()));
+        let room = sb.room(room_id);
+        let name = room.map(|r| r.name.clone()).unwrap_or_default();
+        ui.set_active_channel(SharedString::from(room_id));
+        ui.set_active_channel_name(SharedString::from(name.as_str()));
+        ui.set_active_channel_encrypted(room.is_some_and(|r| r.encrypted));
+        ui.set_active_channel_alias(SharedString::from(
+            room.and_then(|r| r.alias.as_deref()).unwrap_or(""),
+        ));
+        room_topic::show(ui, room.and_then(|r| r.topic.as_deref()));
+        (name, sb.clear_unread(room_id))
+    };
+    apply_patches(ui, sidebar, patches);
+    show_room(ui, client, room_id, &name);
+}
+
+/// Load the timeline, members, voice channel and composer state of
+/// `room_id`, already made the active room.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str, name: &str) {
+    forwarding::cancel(ui);
+    history::open_room(ui, client, room_id);
+    voice_channel::show_room(ui, client, room_id, name);
+    member_list::show_room(ui, client, room_id);
+    message_actions::show_room(ui, client, room_id);
+    pins::show_room(ui, client, room_id);
+    scheduled::show_room(ui);
+    voice_clips::show_room(ui);
+    typing_indicator::show_room(ui, client, room_id);
+    room_security::show_room(ui, client, room_id);
+    welcome::show_room(ui, client, room_id);
+}
+
+/// Open `room_id` from outside the channel list, e.g. a notification click,
+/// switching to the server it belongs to first.
+pub fn reveal_room(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, room_id: &str) {
+    let switched = {
+        let mut guard = sidebar.lock().unwrap();
+        let Some(sb) = guard.as_mut() else {
+            return;
+        };
+        let server = sb.server_of(room_id);
+        let switched = server != sb.active_server();
+        sb.set_active_server(server);
+        switched
+    };
+    if switched {
+        refresh_sidebar(ui, sidebar);
+    }
+    select_room(ui, client, sidebar, room_id);
+}
+
+/// Load joined rooms after login, `concurrency` at a time, select the most
+/// recently active one, and keep the models patched from the event stream
+/// until it closes or the account is switched away from. The rows are built
+/// here; the UI thread only swaps them in.
+pub fn start(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, concurrency: usize) {
+    WINDOW.with(|window| *window.borrow_mut() = Some(ui.as_weak()));
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    let reply = client.load_rooms(concurrency);
+    let shown = accounts::shown();
+    let user_id = ui.get_current_user_id().to_string();
+    let display_name = ui.get_current_display_name().to_string();
+
+    tokio::spawn(async move {
+        let RoomList {
+            spaces,
+            rooms,
+            mut events,
+        } = match shown.run(reply).await {
+            Ok(Ok(list)) => list,
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to load rooms: {}", e);
+                return;
+            }
+            Err(_) => return,
+        };
+        replay::record(
+            &shown,
+            &user_id,
+            &display_name,
+            &spaces,
+            &rooms,
+            events.resubscribe(),
+        );
+
+        let (switcher, rows, initial_room) = {
+            let _span = startup::Span::new("sidebar");
+            let switcher = SwitcherIndex::new(rooms.clone());
+            let mut sb = Sidebar::new(spaces, rooms);
+            let initial = sb.most_recent_room().map(|r| r.id.clone());
+            if let Some(room_id) = &initial {
+                sb.set_active_server(sb.server_of(room_id));
+                sb.set_active_room(Some(room_id.clone()));
+            }
+            let rows = SidebarRows::new(&sb);
+            *sidebar.lock().unwrap() = Some(sb);
+            (switcher, rows, initial)
+        };
+
+        let sidebar_ui = sidebar.clone();
+        let client_ui = client.clone();
+        let handle = ui_handle.clone();
+        let shown_ui = shown.clone();
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = handle.upgrade() else {
+                return;
+            };
+            if shown_ui.is_cancelled() {
+                return;
+            }
+            quick_switcher::load(switcher);
+            knocks::load_requests(&ui, &client_ui);
+            show_sidebar(&ui, rows);
+            set_favorites(&ui, sidebar_ui.lock().unwrap().as_ref());
+            if let Some(room_id) = accounts::take_opening() {
+                reveal_room(&ui, &client_ui, &sidebar_ui, &room_id);
+            } else if let Some(room_id) = initial_room {
+                select_room(&ui, &client_ui, &sidebar_ui, &room_id);
+            }
+        })
+        .ok();
+
+        loop {
+            let event = match shown.run(events.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    tracing::warn!("Event stream lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            };
+
+            let patches = {
+                let mut guard = sidebar.lock().unwrap();
+                let Some(sb) = guard.as_mut() else {
+                    break; // logged out
+                };
+                sb.apply(&event)
+            };
+
+            let sidebar_ui = sidebar.clone();
+            let client_ui = client.clone();
+            let handle = ui_handle.clone();
+            let shown = shown.clone();
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = handle.upgrade() else {
+                    return;
+                };
+                if shown.is_cancelled() {
+                    return;
+                }
+                if !patches.is_empty() {
+                    apply_patches(&ui, &sidebar_ui, patches);
+                }
+                quick_switcher::room_event(&event);
+                knocks::room_event(&ui, &client_ui, &event);
+                favorites::room_event(&ui, &client_ui, &sidebar_ui, &event);
+                match event {
+                    ChatEvent::Message {
+                        room_id,
+                        message,
+                        transaction_id,
+                    } => {
+                        accessibility::message_arrived(&ui, &room_id, &message);
+                        history::push_remote(&room_id, *message, transaction_id.as_deref());
+                    }
+                    ChatEvent::MessageEdited {
+                        room_id,
+                        event_id,
+                        sender,
+                        body,
+                        formatted,
+                    } => {
+                        history::for_each_timeline(&room_id, |timeline| {
+                            timeline.edit(&event_id, &sender, &body, formatted.as_deref())
+                        });
+                    }
+                    ChatEvent::Reacted {
+                        room_id,
+                        message_id,
+                        reaction,
+                    } => {
+                        history::for_each_timeline(&room_id, |timeline| {
+                            timeline.react(&message_id, reaction.clone())
+                        });
+                    }
+                    ChatEvent::Redacted { room_id, event_id } => {
+                        history::for_each_timeline(&room_id, |timeline| timeline.redact(&event_id));
+                    }
+                    ChatEvent::VoiceMembership { room_id, member } => {
+                        let user_id = member.user_id.clone();
+                        voice_channel::member_changed(&ui, &room_id, member);
+                        friends::voice_changed(&ui, &user_id);
+                    }
+                    ChatEvent::MembersChanged { room_id } => {
+                        member_list::members_changed(&ui, &client_ui, &room_id);
+                        message_actions::members_changed(&ui, &client_ui, &room_id);
+                        room_security::room_changed(&ui, &client_ui, &room_id);
+                        mention_completion::members_changed(&room_id);
+                    }
+                    ChatEvent::StickerPacksChanged { .. } => {
+                        stickers::packs_changed(&ui, &client_ui);
+                    }
+                    ChatEvent::WelcomeChanged { room_id } => {
+                        welcome::changed(&ui, &client_ui, &room_id)
+                    }
+                    ChatEvent::PresenceChanged {
+                        user_id,
+                        status,
+                        message,
+                    } => {
+                        let message = message.as_deref();
+                        member_list::presence_changed(&ui, &user_id, status, message);
+                        mention_completion::presence_changed(&user_id, status, message);
+                        friends::presence_changed(&ui, &user_id, status, message);
+                    }
+                    ChatEvent::RosterChanged => friends::load(&ui, &client_ui),
+                    ChatEvent::RoomEncrypted { room_id } => {
+                        room_security::room_changed(&ui, &client_ui, &room_id)
+                    }
+                    ChatEvent::SenderTrustChanged {
+                        user_id,
+                        display_name,
+                        devices,
+                        keys_changed,
+                        rooms,
+                    } => sender_trust::changed(
+                        &ui,
+                        &user_id,
+                        display_name,
+                        &devices,
+                        keys_changed,
+                        &rooms,
+                    ),
+                    ChatEvent::RoomTopicChanged {
+                        room_id,
+                        topic,
+                        sender,
+                        sender_name,
+                    } => room_topic::changed(&room_id, topic.as_deref(), &sender, sender_name),
+                    ChatEvent::PinsChanged { room_id, pinned } => {
+                        pins::changed(&ui, &client_ui, &room_id, pinned)
+                    }
+                    ChatEvent::IncomingCall(invite) => {
+                        incoming_call::incoming(&ui, &client_ui, &sidebar_ui, invite)
+                    }
+                    ChatEvent::CallAnswered {
+                        call_id, user_id, ..
+                    } => incoming_call::answered(&ui, &call_id, &user_id),
+                    ChatEvent::CallHungUp {
+                        call_id, user_id, ..
+                    } => incoming_call::hung_up(&ui, &client_ui, &call_id, &user_id),
+                    _ => {}
+                }
+            })
+            .ok();
+        }
+    });
+}
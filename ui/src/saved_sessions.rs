@@ -0,0 +1,100 @@
+//! What the saved profile cards on the login screen say of their sessions:
+//! the device, whether it is verified, and whether its token still works.
+//! Each saved session is checked with its homeserver once at startup, all
+//! at once and briefly; one whose server can't be reached shows as unknown,
+//! never as expired.
+
+use crate::AppWindow;
+use chat_core::time::format_timestamp;
+use network::session::{DeviceDetails, SessionManager, SessionValidity};
+use slint::ComponentHandle;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    /// What the startup check made of each session, by user id. Missing
+    /// until it answers.
+    static CHECKED: RefCell<Option<HashMap<String, SessionValidity>>> =
+        const { RefCell::new(None) };
+}
+
+/// Check the saved sessions in the background, then `refresh` the cards.
+pub fn validate(ui: &AppWindow, refresh: fn(&AppWindow)) {
+    let sessions = SessionManager::get_remembered_profiles();
+    if sessions.is_empty() {
+        CHECKED.with(|checked| *checked.borrow_mut() = Some(HashMap::new()));
+        return;
+    }
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let results = network::session::validate_sessions(&sessions).await;
+        slint::invoke_from_event_loop(move || {
+            let checked = sessions
+                .into_iter()
+                .map(|session| session.user_id)
+                .zip(results)
+                .collect();
+            CHECKED.with(|c| *c.borrow_mut() = Some(checked));
+            if let Some(ui) = ui_handle.upgrade() {
+                refresh(&ui);
+            }
+        })
+        .ok();
+    });
+}
+
+fn validity(user_id: &str) -> Option<SessionValidity> {
+    CHECKED.with(|checked| {
+        checked.borrow().as_ref().map(|checked| {
+            checked
+                .get(user_id)
+                .copied()
+                .unwrap_or(SessionValidity::Unknown)
+        })
+    })
+}
+
+/// Whether the startup check found `user_id`'s session signed out.
+pub fn is_expired(user_id: &str) -> bool {
+    validity(user_id) == Some(SessionValidity::Expired)
+}
+
+/// `user_id` just signed in with its session, which works then.
+pub fn signed_in(user_id: &str) {
+    CHECKED.with(|checked| {
+        if let Some(checked) = checked.borrow_mut().as_mut() {
+            checked.insert(user_id.to_string(), SessionValidity::Valid);
+        }
+    });
+}
+
+/// The line under a saved profile's name, e.g. "Laptop · Verified ·
+/// Checked Today at 14:02".
+pub fn card_text(user_id: &str, details: &DeviceDetails) -> String {
+    let validity = validity(user_id);
+    if validity == Some(SessionValidity::Expired) {
+        return "Session expired — sign in again".to_string();
+    }
+    let mut parts = Vec::new();
+    if let Some(name) = &details.device_name {
+        parts.push(name.clone());
+    }
+    match details.verified {
+        Some(true) => parts.push("Verified".to_string()),
+        Some(false) => parts.push("Not verified".to_string()),
+        None => {}
+    }
+    let checked = details
+        .validated_at
+        .map(|secs| format_timestamp(secs.saturating_mul(1000)));
+    parts.push(match (validity, checked) {
+        (None, _) => "Checking…".to_string(),
+        (Some(SessionValidity::Unknown), Some(at)) => {
+            format!("Status unknown, last checked {}", at)
+        }
+        (Some(SessionValidity::Unknown), None) => "Status unknown".to_string(),
+        (_, Some(at)) => format!("Checked {}", at),
+        (_, None) => "Session valid".to_string(),
+    });
+    parts.join(" · ")
+}
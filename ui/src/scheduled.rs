@@ -0,0 +1,176 @@
+//! Messages scheduled from the composer to go out later. They wait in the
+//! outbox with what else is unsent, so they outlive the app, and a timer
+//! sends each once it is due; what came due while the app was closed goes
+//! on the next sign-in, marked as sent late. Every tick reads the clock
+//! afresh, so a clock or time zone change moves nothing but "now". The
+//! open room's are listed above the composer, to cancel or take back.
+
+use crate::app_state::ClientHandle;
+use crate::{history, i18n, local_echo, mention_completion, quick_switcher, rooms, unsent};
+use crate::{AppWindow, ScheduledData};
+use chat_core::commands::{self, Command};
+use chat_core::outbox::Unsent;
+use chat_core::{schedule, time};
+use slint::{ComponentHandle, SharedString, Timer, TimerMode, VecModel};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How often to look for messages that are due.
+const TICK: Duration = Duration::from_secs(15);
+
+thread_local! {
+    /// When messages that went out late were due, by transaction id.
+    static LATE: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    static TICKER: Timer = Timer::default();
+}
+
+/// Send what is due, now and from then on, as the account is shown.
+pub fn start(ui: &AppWindow, client: &ClientHandle) {
+    send_due(ui, client);
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    TICKER.with(|timer| {
+        timer.start(TimerMode::Repeated, TICK, move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                send_due(&ui, &client);
+            }
+        });
+    });
+    show(ui);
+}
+
+fn send_due(ui: &AppWindow, client: &ClientHandle) {
+    let now = time::now_ms();
+    let due = unsent::take_due(now);
+    if due.is_empty() {
+        return;
+    }
+    for message in &due {
+        if let Some(send_at) = message.send_at.filter(|at| schedule::is_late(*at, now)) {
+            LATE.with(|l| l.borrow_mut().insert(message.txn_id.clone(), send_at));
+        }
+        rooms::send_unsent(ui, client, message);
+    }
+    show(ui);
+}
+
+/// Schedule the composer's `text` for the open room at `when`, as a reply
+/// to `reply_to` if it isn't empty. What is wrong is shown instead.
+pub fn send_later(ui: &AppWindow, text: &str, reply_to: &str, when: &str) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let Some(send_at) = schedule::parse(when) else {
+        ui.set_schedule_error(SharedString::from(
+            "Say when, like 30m, 18:30 or tomorrow 9:00",
+        ));
+        return;
+    };
+    let body = match commands::parse(text.trim()) {
+        Ok(Command::Say(body)) if !body.is_empty() => body,
+        Ok(_) => {
+            ui.set_schedule_error(SharedString::from("Only messages can be sent later"));
+            return;
+        }
+        Err(e) => {
+            ui.set_schedule_error(SharedString::from(e.to_string()));
+            return;
+        }
+    };
+    unsent::schedule(Unsent {
+        room_name: quick_switcher::room_name(&room_id).unwrap_or_default(),
+        room_id,
+        txn_id: local_echo::new_txn_id(),
+        body,
+        mentions: mention_completion::take(ui, text),
+        reply_to: Some(reply_to.to_string()).filter(|id| !id.is_empty()),
+        timestamp: time::now_ms(),
+        send_at: Some(send_at),
+    });
+    ui.set_composer_text(SharedString::default());
+    ui.set_composer_reply_id(SharedString::default());
+    close(ui);
+    show(ui);
+}
+
+/// Put away the composer's scheduling row.
+pub fn close(ui: &AppWindow) {
+    ui.set_schedule_open(false);
+    ui.set_schedule_when(SharedString::default());
+    ui.set_schedule_error(SharedString::default());
+}
+
+/// Don't send scheduled `txn_id`.
+pub fn cancel(ui: &AppWindow, txn_id: &str) {
+    unsent::unschedule(txn_id);
+    show(ui);
+}
+
+/// Take scheduled `txn_id` back into the composer, with its time, to
+/// change and schedule again or send now.
+pub fn edit(ui: &AppWindow, txn_id: &str) {
+    let Some(message) = unsent::unschedule(txn_id) else {
+        return;
+    };
+    // Sent as text, so one that looks like a command is escaped again.
+    let text = if message.body.starts_with('/') {
+        format!("/{}", message.body)
+    } else {
+        message.body
+    };
+    ui.set_composer_text(SharedString::from(text.as_str()));
+    mention_completion::restore(&text, message.mentions);
+    if let Some(reply_to) = &message.reply_to {
+        ui.set_composer_reply_id(SharedString::from(reply_to.as_str()));
+    }
+    ui.set_composer_caret_request(i32::try_from(text.len()).unwrap_or(0));
+    let when = message.send_at.map(schedule::format).unwrap_or_default();
+    ui.set_schedule_when(SharedString::from(when));
+    ui.set_schedule_error(SharedString::default());
+    ui.set_schedule_open(true);
+    show(ui);
+}
+
+/// List the scheduled messages of the room just opened, with the
+/// scheduling row put away.
+pub fn show_room(ui: &AppWindow) {
+    close(ui);
+    show(ui);
+}
+
+/// List the open room's scheduled messages above the composer.
+fn show(ui: &AppWindow) {
+    let scheduled = history::current_room()
+        .map(|room_id| unsent::scheduled(&room_id))
+        .unwrap_or_default();
+    let rows: Vec<ScheduledData> = scheduled
+        .iter()
+        .map(|message| ScheduledData {
+            txn_id: SharedString::from(message.txn_id.as_str()),
+            body: SharedString::from(message.body.as_str()),
+            when: SharedString::from(time::format_timestamp(message.send_at.unwrap_or(0))),
+        })
+        .collect();
+    ui.set_scheduled_messages(Rc::new(VecModel::from(rows)).into());
+}
+
+/// What the row of our message sent as `txn_id` says of its delivery:
+/// when it was due, if it went out late.
+pub fn late_note(txn_id: Option<&str>) -> SharedString {
+    let Some(send_at) = txn_id.and_then(|id| LATE.with(|l| l.borrow().get(id).copied())) else {
+        return SharedString::default();
+    };
+    let due = time::format_timestamp(send_at);
+    SharedString::from(i18n::tr_args("scheduled-sent-late", [("time", due.into())]))
+}
+
+/// Stop sending, e.g. on logout. What is scheduled stays in the outbox
+/// for the next sign-in.
+pub fn clear(ui: &AppWindow) {
+    TICKER.with(Timer::stop);
+    LATE.with(|l| l.borrow_mut().clear());
+    close(ui);
+    ui.set_scheduled_messages(Rc::new(VecModel::<ScheduledData>::default()).into());
+}
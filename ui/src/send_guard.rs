@@ -0,0 +1,237 @@
+//! What `chat_core::send_guard` holds back, for the open room: a message
+//! to a room over the configured size waits for confirmation, after each
+//! message to a room in slow mode the composer counts down the wait, and
+//! none go before the room's welcome message is accepted if it asks.
+//! Messages that were queued, in any room, go out a slow mode apart.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::SharedSidebar;
+use crate::{history, mention_completion, welcome, AppWindow};
+use chat_core::commands::Command;
+use chat_core::mention::Mention;
+use chat_core::send_guard::{self, Cooldowns};
+use chat_core::time;
+use network::config::SendConfig;
+use slint::{ComponentHandle, SharedString, Timer, TimerMode};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A message from the composer, as parsed.
+pub struct Outgoing {
+    pub text: String,
+    pub command: Command,
+    pub mentions: Vec<Mention>,
+    pub reply_to: Option<String>,
+}
+
+#[derive(Default)]
+struct Guard {
+    confirm_above: u64,
+    /// Each room's slow mode in seconds, as its permissions were loaded.
+    slow_mode: HashMap<String, u64>,
+    cooldowns: Cooldowns,
+    /// Waiting for confirmation.
+    pending: Option<Outgoing>,
+    /// Sends to rooms whose slow mode is being loaded, in order, by room.
+    unpaced: HashMap<String, Vec<Box<dyn FnOnce()>>>,
+}
+
+thread_local! {
+    static GUARD: RefCell<Guard> = RefCell::new(Guard::default());
+    static COUNTDOWN: Timer = Timer::default();
+}
+
+pub fn configure(config: &SendConfig) {
+    GUARD.with(|g| g.borrow_mut().confirm_above = config.confirm_above_members);
+}
+
+/// Forget slow modes and waits, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    GUARD.with(|g| {
+        let mut guard = g.borrow_mut();
+        guard.slow_mode.clear();
+        guard.cooldowns = Cooldowns::default();
+        guard.pending = None;
+        guard.unpaced.clear();
+    });
+    COUNTDOWN.with(Timer::stop);
+    ui.set_show_send_confirm(false);
+    ui.set_slow_mode_secs(0);
+    ui.set_send_wait(0);
+}
+
+/// Let `outgoing` through to the open room, or hold it back: during a
+/// slow mode wait it goes back in the composer, and to a room with more
+/// members than the setting it waits for `confirm` or `cancel`. Only
+/// messages are held; other commands always go through.
+pub fn check(ui: &AppWindow, sidebar: &SharedSidebar, outgoing: Outgoing) -> Option<Outgoing> {
+    if !matches!(outgoing.command, Command::Say(_) | Command::Me(_)) {
+        return Some(outgoing);
+    }
+    let Some(room_id) = history::current_room() else {
+        return Some(outgoing);
+    };
+    if remaining(&room_id) > 0 {
+        restore(ui, &outgoing);
+        show_wait(ui, &room_id);
+        return None;
+    }
+    if welcome::holds_composer(&room_id) {
+        restore(ui, &outgoing);
+        welcome::open(ui);
+        return None;
+    }
+    let threshold = GUARD.with(|g| g.borrow().confirm_above);
+    let room = sidebar
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|sb| sb.room(&room_id).cloned());
+    match room {
+        Some(room) if send_guard::needs_confirmation(&room, threshold) => {
+            ui.set_send_confirm_room(SharedString::from(room.name));
+            ui.set_send_confirm_members(i32::try_from(room.member_count).unwrap_or(i32::MAX));
+            ui.set_send_confirm_body(SharedString::from(outgoing.text.as_str()));
+            ui.set_show_send_confirm(true);
+            GUARD.with(|g| g.borrow_mut().pending = Some(outgoing));
+            None
+        }
+        _ => Some(outgoing),
+    }
+}
+
+/// Send the message waiting for confirmation: it is given back.
+pub fn confirm(ui: &AppWindow) -> Option<Outgoing> {
+    ui.set_show_send_confirm(false);
+    GUARD.with(|g| g.borrow_mut().pending.take())
+}
+
+/// Don't send the message waiting for confirmation; it goes back in the
+/// composer.
+pub fn cancel(ui: &AppWindow) {
+    ui.set_show_send_confirm(false);
+    if let Some(outgoing) = GUARD.with(|g| g.borrow_mut().pending.take()) {
+        restore(ui, &outgoing);
+    }
+}
+
+/// Put `outgoing` back in the composer, mentions and all.
+fn restore(ui: &AppWindow, outgoing: &Outgoing) {
+    ui.set_composer_text(SharedString::from(outgoing.text.as_str()));
+    mention_completion::restore(&outgoing.text, outgoing.mentions.clone());
+    if let Some(reply_to) = &outgoing.reply_to {
+        ui.set_composer_reply_id(SharedString::from(reply_to.as_str()));
+    }
+    ui.set_composer_caret_request(i32::try_from(outgoing.text.len()).unwrap_or(0));
+}
+
+fn remaining(room_id: &str) -> u64 {
+    GUARD.with(|g| g.borrow().cooldowns.remaining_secs(room_id, time::now_ms()))
+}
+
+/// `room_id`'s slow mode, as its permissions were loaded.
+pub fn set_slow_mode(ui: &AppWindow, room_id: &str, secs: u64) {
+    GUARD.with(|g| g.borrow_mut().slow_mode.insert(room_id.to_string(), secs));
+    show_wait(ui, room_id);
+}
+
+/// Run `send`, which sends a message to `room_id`, once the room's slow
+/// mode lets it through: right away, or a wait after the one before, so
+/// that a queue sent on reconnecting doesn't go out all at once. A room
+/// not opened yet has its slow mode loaded first.
+pub fn when_ready(client: &ClientHandle, room_id: &str, send: impl FnOnce() + 'static) {
+    let secs = GUARD.with(|g| g.borrow().slow_mode.get(room_id).copied());
+    let Some(secs) = secs else {
+        let first = GUARD.with(|g| {
+            let mut guard = g.borrow_mut();
+            let unpaced = guard.unpaced.entry(room_id.to_string()).or_default();
+            unpaced.push(Box::new(send));
+            unpaced.len() == 1
+        });
+        if first {
+            load_slow_mode(client, room_id);
+        }
+        return;
+    };
+    let wait = GUARD.with(|g| g.borrow_mut().cooldowns.book(room_id, time::now_ms(), secs));
+    if wait == 0 {
+        send();
+    } else {
+        Timer::single_shot(Duration::from_millis(wait), send);
+    }
+}
+
+/// Learn `room_id`'s slow mode, then pace what waited for it. If it can't
+/// be loaded they go unpaced rather than not at all.
+fn load_slow_mode(client: &ClientHandle, room_id: &str) {
+    let reply = client.get_permissions(room_id);
+    let client = client.clone();
+    let room_id = room_id.to_string();
+    tokio::spawn(async move {
+        let secs = match reply.await {
+            Ok(permissions) => Some(permissions.slow_mode_secs),
+            Err(e) => {
+                tracing::warn!("Failed to load the slow mode of {}: {:#}", room_id, e);
+                None
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let unpaced = GUARD.with(|g| {
+                let mut guard = g.borrow_mut();
+                if let Some(secs) = secs {
+                    guard.slow_mode.entry(room_id.clone()).or_insert(secs);
+                }
+                guard.unpaced.remove(&room_id).unwrap_or_default()
+            });
+            for send in unpaced {
+                match secs {
+                    Some(_) => when_ready(&client, &room_id, send),
+                    None => send(),
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// A message just went to the open room: its slow mode's wait starts.
+pub fn sent(ui: &AppWindow) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    GUARD.with(|g| {
+        let mut guard = g.borrow_mut();
+        let secs = guard.slow_mode.get(&room_id).copied().unwrap_or(0);
+        guard.cooldowns.sent(&room_id, time::now_ms(), secs);
+    });
+    show_wait(ui, &room_id);
+}
+
+/// Show `room_id`'s wait in the composer as it is opened, counting down
+/// each second until it is over.
+pub fn show_wait(ui: &AppWindow, room_id: &str) {
+    let secs = GUARD.with(|g| g.borrow().slow_mode.get(room_id).copied().unwrap_or(0));
+    ui.set_slow_mode_secs(i32::try_from(secs).unwrap_or(i32::MAX));
+    let wait = remaining(room_id);
+    ui.set_send_wait(i32::try_from(wait).unwrap_or(i32::MAX));
+    if wait == 0 {
+        COUNTDOWN.with(Timer::stop);
+        GUARD.with(|g| g.borrow_mut().cooldowns.expire(time::now_ms()));
+        return;
+    }
+    let ui_handle = ui.as_weak();
+    COUNTDOWN.with(|timer| {
+        timer.start(TimerMode::Repeated, Duration::from_secs(1), move || {
+            let (Some(ui), Some(room_id)) = (ui_handle.upgrade(), history::current_room()) else {
+                COUNTDOWN.with(Timer::stop);
+                return;
+            };
+            let wait = remaining(&room_id);
+            ui.set_send_wait(i32::try_from(wait).unwrap_or(i32::MAX));
+            if wait == 0 {
+                COUNTDOWN.with(Timer::stop);
+            }
+        });
+    });
+}
@@ -0,0 +1,277 @@
+//! The messages on their way out, in every room, for the queue in the
+//! settings: held back while offline, waiting for a slow mode turn, being
+//! sent or failed. Each is known by the transaction id it goes under,
+//! which a retry keeps, so cancelling one reaches the right message
+//! however often it was tried. The panel follows the queue as it changes.
+
+use crate::app_state::ClientHandle;
+use crate::{
+    connection_status, quick_switcher, rooms, AppWindow, OutgoingState, PendingMessageData,
+};
+use chat_core::time;
+use slint::{ComponentHandle, ModelRc, SharedString, Timer, TimerMode, VecModel, Weak};
+use std::cell::RefCell;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PendingState {
+    /// Held back while the homeserver can't be reached.
+    Offline,
+    /// Waiting for its turn in a room in slow mode.
+    Waiting,
+    Sending,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingMessage {
+    /// The transaction id it is sent under.
+    pub id: String,
+    pub room_id: String,
+    /// The first line of the body.
+    pub preview: String,
+    pub state: PendingState,
+    /// When it was first sent, in milliseconds since the Unix epoch.
+    pub queued_at: u64,
+    /// How often it was sent again after failing.
+    pub retries: u32,
+}
+
+/// Pending messages, oldest first.
+#[derive(Debug, Default)]
+struct Queue {
+    messages: Vec<PendingMessage>,
+}
+
+impl Queue {
+    fn find(&self, id: &str) -> Option<usize> {
+        self.messages.iter().position(|m| m.id == id)
+    }
+
+    /// Take in `body`, sent to `room_id` as `id` at `now_ms`. A message
+    /// already queued under `id` only changes state.
+    fn push(&mut self, room_id: &str, id: &str, body: &str, state: PendingState, now_ms: u64) {
+        if self.set_state(id, state) {
+            return;
+        }
+        self.messages.push(PendingMessage {
+            id: id.to_string(),
+            room_id: room_id.to_string(),
+            preview: body.lines().next().unwrap_or_default().to_string(),
+            state,
+            queued_at: now_ms,
+            retries: 0,
+        });
+    }
+
+    /// Move `id` to `state`, counting a retry when it leaves `Failed`.
+    /// False if it isn't queued.
+    fn set_state(&mut self, id: &str, state: PendingState) -> bool {
+        let Some(row) = self.find(id) else {
+            return false;
+        };
+        let message = &mut self.messages[row];
+        if message.state == PendingState::Failed && state != PendingState::Failed {
+            message.retries += 1;
+        }
+        message.state = state;
+        true
+    }
+
+    fn remove(&mut self, id: &str) -> Option<PendingMessage> {
+        self.find(id).map(|row| self.messages.remove(row))
+    }
+
+    fn get(&self, id: &str) -> Option<&PendingMessage> {
+        self.find(id).map(|row| &self.messages[row])
+    }
+}
+
+thread_local! {
+    static QUEUE: RefCell<Queue> = RefCell::new(Queue::default());
+    /// Set once the settings were opened, to follow the queue there.
+    static WINDOW: RefCell<Option<Weak<AppWindow>>> = const { RefCell::new(None) };
+    /// Ticks the ages while the settings are open.
+    static TICK: Timer = Timer::default();
+}
+
+/// Every message not yet confirmed, oldest first.
+pub fn get_pending_messages() -> Vec<PendingMessage> {
+    QUEUE.with(|q| q.borrow().messages.clone())
+}
+
+/// `body` went out to `room_id` as `txn_id`, or is held back as `state`
+/// says.
+pub fn queued(room_id: &str, txn_id: &str, body: &str, state: PendingState) {
+    QUEUE.with(|q| {
+        q.borrow_mut()
+            .push(room_id, txn_id, body, state, time::now_ms())
+    });
+    refresh();
+}
+
+pub fn set_state(txn_id: &str, state: PendingState) {
+    if QUEUE.with(|q| q.borrow_mut().set_state(txn_id, state)) {
+        refresh();
+    }
+}
+
+/// `txn_id` was confirmed or dropped.
+pub fn done(txn_id: &str) {
+    if QUEUE.with(|q| q.borrow_mut().remove(txn_id)).is_some() {
+        refresh();
+    }
+}
+
+/// Whether `txn_id` is still to be sent, and not cancelled while it
+/// waited for its turn.
+pub fn is_pending(txn_id: &str) -> bool {
+    QUEUE.with(|q| q.borrow().get(txn_id).is_some())
+}
+
+/// Drop message `id` without sending it. One already on its way to the
+/// server can't be called back.
+pub fn cancel(id: &str) {
+    let Some(message) = QUEUE.with(|q| q.borrow().get(id).cloned()) else {
+        return;
+    };
+    if message.state == PendingState::Sending {
+        return;
+    }
+    rooms::cancel(&message.room_id, id);
+}
+
+/// Send message `id` again now if it failed, or try reaching the
+/// homeserver now if it is held back while offline.
+pub fn retry_now(client: &ClientHandle, id: &str) {
+    let Some(message) = QUEUE.with(|q| q.borrow().get(id).cloned()) else {
+        return;
+    };
+    match message.state {
+        PendingState::Failed => rooms::retry(client, message.room_id, id),
+        PendingState::Offline => connection_status::retry_now(),
+        PendingState::Waiting | PendingState::Sending => {}
+    }
+}
+
+/// Retry every failed message, and reach for the homeserver if any are
+/// held back.
+pub fn flush(client: &ClientHandle) {
+    let pending = get_pending_messages();
+    if pending.iter().any(|m| m.state == PendingState::Offline) {
+        connection_status::retry_now();
+    }
+    for message in pending {
+        if message.state == PendingState::Failed {
+            rooms::retry(client, message.room_id, &message.id);
+        }
+    }
+}
+
+/// How long ago `queued_at` was, e.g. "42 s" or "3 min".
+fn age(queued_at: u64, now_ms: u64) -> String {
+    let secs = now_ms.saturating_sub(queued_at) / 1000;
+    match secs {
+        0..=59 => format!("{} s", secs),
+        60..=3599 => format!("{} min", secs / 60),
+        _ => format!("{} h", secs / 3600),
+    }
+}
+
+fn row(message: &PendingMessage, now_ms: u64) -> PendingMessageData {
+    let room =
+        quick_switcher::room_name(&message.room_id).unwrap_or_else(|| message.room_id.clone());
+    PendingMessageData {
+        id: SharedString::from(message.id.as_str()),
+        room: SharedString::from(room),
+        preview: SharedString::from(message.preview.as_str()),
+        state: match message.state {
+            PendingState::Offline => OutgoingState::Offline,
+            PendingState::Waiting => OutgoingState::Waiting,
+            PendingState::Sending => OutgoingState::Sending,
+            PendingState::Failed => OutgoingState::Failed,
+        },
+        age: SharedString::from(age(message.queued_at, now_ms)),
+        retries: i32::try_from(message.retries).unwrap_or(i32::MAX),
+    }
+}
+
+fn show(ui: &AppWindow) {
+    let now_ms = time::now_ms();
+    let rows: Vec<PendingMessageData> =
+        QUEUE.with(|q| q.borrow().messages.iter().map(|m| row(m, now_ms)).collect());
+    ui.set_pending_messages(ModelRc::new(VecModel::from(rows)));
+}
+
+/// Show the queue again, if the settings are open.
+fn refresh() {
+    let Some(ui) = WINDOW.with(|window| window.borrow().as_ref().and_then(Weak::upgrade)) else {
+        return;
+    };
+    if ui.get_show_settings() {
+        show(&ui);
+    }
+}
+
+/// Show the queue as the settings open, and keep the ages current until
+/// they close.
+pub fn open_settings(ui: &AppWindow) {
+    WINDOW.with(|window| *window.borrow_mut() = Some(ui.as_weak()));
+    show(ui);
+    let ui_handle = ui.as_weak();
+    TICK.with(|timer| {
+        timer.start(
+            TimerMode::Repeated,
+            Duration::from_secs(1),
+            move || match ui_handle.upgrade() {
+                Some(ui) if ui.get_show_settings() => show(&ui),
+                _ => TICK.with(Timer::stop),
+            },
+        )
+    });
+}
+
+/// Forget the queue, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    QUEUE.with(|q| *q.borrow_mut() = Queue::default());
+    TICK.with(Timer::stop);
+    ui.set_pending_messages(ModelRc::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "!room:example.org";
+
+    #[test]
+    fn test_retries_keep_the_id() {
+        let mut queue = Queue::default();
+        queue.push(ROOM, "t1", "hello\nworld", PendingState::Waiting, 1_000);
+        queue.push(ROOM, "t2", "again", PendingState::Offline, 2_000);
+        assert_eq!(queue.get("t1").unwrap().preview, "hello");
+
+        queue.set_state("t1", PendingState::Sending);
+        queue.set_state("t1", PendingState::Failed);
+        queue.set_state("t1", PendingState::Sending);
+        queue.set_state("t1", PendingState::Failed);
+        // Sent again under the same id, e.g. by a retry: still one message.
+        queue.push(ROOM, "t1", "hello\nworld", PendingState::Waiting, 9_000);
+        let retried = queue.get("t1").unwrap();
+        assert_eq!(retried.retries, 2);
+        assert_eq!(retried.queued_at, 1_000);
+        assert_eq!(queue.messages.len(), 2);
+
+        assert_eq!(queue.remove("t1").unwrap().id, "t1");
+        assert_eq!(queue.get("t2").unwrap().state, PendingState::Offline);
+        assert!(!queue.set_state("t1", PendingState::Sending));
+    }
+
+    #[test]
+    fn test_ages() {
+        assert_eq!(age(1_000, 43_500), "42 s");
+        assert_eq!(age(0, 3 * 60_000 + 5_000), "3 min");
+        assert_eq!(age(0, 2 * 3_600_000), "2 h");
+        assert_eq!(age(5_000, 1_000), "0 s");
+    }
+}
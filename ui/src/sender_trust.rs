@@ -0,0 +1,199 @@
+//! The shield by each sender's name in encrypted rooms: green for messages
+//! from a verified device of a verified user, grey when not, red when the
+//! sender's keys changed since we verified them. Each message comes with
+//! its trust as it was decrypted; when the network learns more of someone's
+//! devices, the messages of theirs we hold are brought up to date, and a
+//! change of keys also leaves a notice in the rooms we share with them.
+//! Their profile can take the new keys as theirs, once checked some other
+//! way.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, i18n, local_echo, member_list, toast, AppWindow, SenderShield};
+use chat_core::{time, Message, MessageType, Notice, SenderTrust};
+use network::encryption::RoomSecurity;
+use slint::ComponentHandle;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Default)]
+struct Trust {
+    /// Users whose keys changed since we verified them.
+    keys_changed: HashSet<String>,
+    /// Rooms known to be encrypted, whose messages carry a shield.
+    encrypted: HashSet<String>,
+}
+
+thread_local! {
+    static TRUST: RefCell<Trust> = RefCell::new(Trust::default());
+}
+
+/// Whether messages of `room_id` carry a shield.
+pub fn is_encrypted(room_id: &str) -> bool {
+    TRUST.with(|t| t.borrow().encrypted.contains(room_id))
+}
+
+fn set_encrypted(room_id: &str) {
+    let added = TRUST.with(|t| t.borrow_mut().encrypted.insert(room_id.to_string()));
+    if added {
+        history::for_each_timeline(room_id, |timeline| timeline.set_encrypted(true));
+    }
+}
+
+/// What the shield by `message`'s sender shows, in an encrypted room.
+pub fn shield(message: &Message) -> SenderShield {
+    if message.schema.is_notice() {
+        return SenderShield::None;
+    }
+    match message.trust.as_ref().map(|t| t.trust) {
+        Some(SenderTrust::Verified) => SenderShield::Verified,
+        Some(SenderTrust::KeysChanged) => SenderShield::KeysChanged,
+        // Sent in the clear, or not yet echoed back.
+        Some(SenderTrust::Unverified) | None => SenderShield::Unverified,
+    }
+}
+
+/// Whether `user_id`'s keys changed since we verified them.
+pub fn keys_changed(user_id: &str) -> bool {
+    TRUST.with(|t| t.borrow().keys_changed.contains(user_id))
+}
+
+/// The open room `room_id` was checked.
+pub fn room_checked(room_id: &str, security: &RoomSecurity) {
+    if security.encrypted {
+        set_encrypted(room_id);
+    }
+}
+
+/// "Alice's security changed", in `room_id`'s timeline. The notice has a
+/// local id and is never read up to.
+fn notice(room_id: &str, user_id: &str, display_name: Option<String>) {
+    let mut message = Message {
+        id: local_echo::new_notice_id(),
+        sender: user_id.to_string(),
+        sender_name: display_name,
+        content: String::new(),
+        schema: MessageType::SystemNotice(Notice::Security),
+        timestamp: time::now_ms(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    };
+    message.content = i18n::tr_args(
+        "security-changed",
+        [("name", message.sender_display().into())],
+    );
+    history::push_remote(room_id, message, None);
+}
+
+/// What the network knows of `user_id` changed: `devices` says how far
+/// messages from each of their devices can be trusted, unless their keys
+/// changed since we verified them. Bring their messages in `rooms`, the
+/// encrypted rooms we share with them, up to date, and leave a notice
+/// there if their keys just changed.
+pub fn changed(
+    ui: &AppWindow,
+    user_id: &str,
+    display_name: Option<String>,
+    devices: &BTreeMap<String, SenderTrust>,
+    keys_changed: bool,
+    rooms: &[String],
+) {
+    let newly_changed = TRUST.with(|t| {
+        let users = &mut t.borrow_mut().keys_changed;
+        if keys_changed {
+            users.insert(user_id.to_string())
+        } else {
+            users.remove(user_id);
+            false
+        }
+    });
+    let trust = |device_id: Option<&str>| {
+        if keys_changed {
+            return SenderTrust::KeysChanged;
+        }
+        device_id
+            .and_then(|device_id| devices.get(device_id))
+            .copied()
+            .unwrap_or_default()
+    };
+    for room_id in rooms {
+        set_encrypted(room_id);
+        if newly_changed {
+            notice(room_id, user_id, display_name.clone());
+        }
+        history::for_each_timeline(room_id, |timeline| timeline.set_trust(user_id, trust));
+    }
+    member_list::trust_changed(ui, user_id);
+}
+
+/// Take `user_id`'s new keys as theirs. The network forgets the ones we
+/// verified them with and tells how far they are trusted now.
+pub fn trust_new_keys(ui: &AppWindow, client: &ClientHandle, user_id: &str) {
+    let reply = client.trust_new_keys(user_id);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Err(e) = reply.await else {
+            return;
+        };
+        tracing::warn!("Failed to trust new keys: {:#}", e);
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                toast::show(&ui, admin::error_text(&e, "failed-trust-new-keys"));
+            }
+        })
+        .ok();
+    });
+}
+
+/// Forget everyone's trust, e.g. on logout.
+pub fn clear() {
+    TRUST.with(|t| *t.borrow_mut() = Trust::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::MessageTrust;
+
+    fn message(schema: MessageType, trust: Option<SenderTrust>) -> Message {
+        Message {
+            id: "$a".to_string(),
+            sender: "@bob:x".to_string(),
+            content: "gg".to_string(),
+            schema,
+            trust: trust.map(|trust| MessageTrust {
+                device_id: Some("BOBPHONE".to_string()),
+                trust,
+            }),
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn test_shield_follows_the_messages_trust() {
+        let shield_of = |trust| shield(&message(MessageType::Text, trust));
+        assert_eq!(
+            shield_of(Some(SenderTrust::Verified)),
+            SenderShield::Verified
+        );
+        assert_eq!(
+            shield_of(Some(SenderTrust::Unverified)),
+            SenderShield::Unverified
+        );
+        assert_eq!(
+            shield_of(Some(SenderTrust::KeysChanged)),
+            SenderShield::KeysChanged
+        );
+        // Sent in the clear, or ours before its echo.
+        assert_eq!(shield_of(None), SenderShield::Unverified);
+        // Notices aren't anyone's to vouch for.
+        assert_eq!(
+            shield(&message(MessageType::SystemNotice(Notice::Security), None)),
+            SenderShield::None
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! The login screen's line on the homeserver: whether it answers, how
+//! quickly and what it runs, or why it can't be reached. It is checked when
+//! the screen opens, once typing in the homeserver field pauses, and on
+//! asking again.
+
+use crate::app_state::ClientHandle;
+use crate::{i18n, AppWindow, ServerStatusData};
+use network::discovery::{DiscoveryError, ServerStatus};
+use slint::{ComponentHandle, SharedString};
+use std::cell::Cell;
+use std::time::Duration;
+
+/// How long typing must pause before the server is checked again.
+const CHECK_DELAY: Duration = Duration::from_millis(600);
+
+thread_local! {
+    /// Counts checks, so answers to an earlier one are dropped.
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// "87 ms", or seconds once it's that slow.
+pub fn latency_text(latency: Duration) -> String {
+    if latency < Duration::from_secs(1) {
+        i18n::tr_args(
+            "latency-ms",
+            [("ms", latency.as_millis().to_string().into())],
+        )
+    } else {
+        let seconds = format!("{:.1}", latency.as_secs_f64());
+        i18n::tr_args("latency-s", [("seconds", seconds.into())])
+    }
+}
+
+/// The software and Matrix version, as far as the server said.
+pub fn details_text(status: &ServerStatus) -> String {
+    let version = status
+        .version
+        .as_ref()
+        .map(|version| i18n::tr_args("matrix-version", [("version", version.as_str().into())]));
+    [status.server.clone(), version]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+/// Check `homeserver`, at once or, while it is being typed, after a pause.
+pub fn check(ui: &AppWindow, client: &ClientHandle, homeserver: &str, now: bool) {
+    let generation = GENERATION.with(|g| {
+        g.set(g.get() + 1);
+        g.get()
+    });
+    let homeserver = homeserver.trim().to_string();
+    if homeserver.is_empty() {
+        ui.set_login_server_status(ServerStatusData::default());
+        return;
+    }
+    ui.set_login_server_status(ServerStatusData {
+        checking: true,
+        ..ui.get_login_server_status()
+    });
+
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        if !now {
+            tokio::time::sleep(CHECK_DELAY).await;
+        }
+        let result = client.server_status(&homeserver).await;
+        slint::invoke_from_event_loop(move || {
+            if GENERATION.with(Cell::get) != generation {
+                return;
+            }
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let data = match result {
+                Ok(status) => ServerStatusData {
+                    checking: false,
+                    reachable: true,
+                    latency: SharedString::from(latency_text(status.latency)),
+                    details: SharedString::from(details_text(&status)),
+                    error: SharedString::new(),
+                },
+                Err(e) => {
+                    tracing::warn!("Homeserver status check failed: {:#}", e);
+                    ServerStatusData {
+                        error: SharedString::from(i18n::discovery_error(
+                            &DiscoveryError::classify(&e),
+                        )),
+                        ..ServerStatusData::default()
+                    }
+                }
+            };
+            ui.set_login_server_status(data);
+        })
+        .ok();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_and_details_text() {
+        assert_eq!(latency_text(Duration::from_millis(87)), "87 ms");
+        assert_eq!(latency_text(Duration::from_millis(1400)), "1.4 s");
+
+        let mut status = ServerStatus {
+            homeserver: "https://example.org/".to_string(),
+            latency: Duration::from_millis(87),
+            version: Some("v1.9".to_string()),
+            server: Some("Synapse 1.98.0".to_string()),
+        };
+        assert_eq!(details_text(&status), "Synapse 1.98.0 · Matrix v1.9");
+        status.server = None;
+        assert_eq!(details_text(&status), "Matrix v1.9");
+        status.version = None;
+        assert_eq!(details_text(&status), "");
+    }
+}
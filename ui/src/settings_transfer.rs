@@ -0,0 +1,217 @@
+//! Exporting the settings to a file and importing them from one, from the
+//! settings dialog. An import that would overwrite settings changed here
+//! first lists those, ticked, for the user to pick which to take; the rest
+//! is saved and put into effect at once, and the settings dialog closes so
+//! its drafts don't write the old values back.
+
+use crate::{admin, i18n, toast, AppWindow, ImportConflictData};
+use network::config::{Config, ConfigManager};
+use network::settings_transfer::{self, Section};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Name an export is offered under.
+const FILE_NAME: &str = "GameChat settings.json";
+
+/// An import waiting on the user to settle its conflicts.
+struct Pending {
+    imported: Config,
+    conflicts: Vec<Section>,
+    taken: Vec<bool>,
+}
+
+/// Puts imported settings into effect, given those they replace.
+type Apply = Rc<dyn Fn(&AppWindow, &Config, &Config)>;
+
+#[derive(Default)]
+struct Transfer {
+    apply: Option<Apply>,
+    pending: Option<Pending>,
+}
+
+thread_local! {
+    static TRANSFER: RefCell<Transfer> = RefCell::new(Transfer::default());
+}
+
+/// Put imported settings into effect through `apply`.
+pub fn start(apply: impl Fn(&AppWindow, &Config, &Config) + 'static) {
+    TRANSFER.with(|t| t.borrow_mut().apply = Some(Rc::new(apply)));
+}
+
+/// Let the user choose where to save the settings, and write them there.
+pub fn export(ui: &AppWindow) {
+    let data = match settings_transfer::export(&ConfigManager::load()) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!("Failed to export settings: {:#}", e);
+            toast::show(ui, admin::error_text(&e, "failed-export-settings"));
+            return;
+        }
+    };
+    // Created here, on the UI thread, as some platforms require.
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Export settings")
+        .set_file_name(FILE_NAME)
+        .add_filter("Settings", &["json"])
+        .save_file();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Some(file) = dialog.await else {
+            return;
+        };
+        let result = tokio::fs::write(file.path(), data)
+            .await
+            .map_err(anyhow::Error::from);
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(()) => toast::show(&ui, i18n::tr("settings-exported")),
+                Err(e) => {
+                    tracing::warn!("Failed to export settings: {:#}", e);
+                    toast::show(&ui, admin::error_text(&e, "failed-export-settings"));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// Let the user choose a settings file, and import it.
+pub fn import(ui: &AppWindow) {
+    // Created here, on the UI thread, as some platforms require.
+    let dialog = rfd::AsyncFileDialog::new()
+        .set_title("Import settings")
+        .add_filter("Settings", &["json"])
+        .pick_file();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Some(file) = dialog.await else {
+            return;
+        };
+        let data = file.read().await;
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                read(&ui, &data);
+            }
+        })
+        .ok();
+    });
+}
+
+fn read(ui: &AppWindow, data: &[u8]) {
+    let plan = String::from_utf8(data.to_vec())
+        .map_err(anyhow::Error::from)
+        .and_then(|data| settings_transfer::parse(&data))
+        .and_then(|imported| {
+            let plan = settings_transfer::plan(&ConfigManager::load(), &imported)?;
+            Ok((imported, plan))
+        });
+    let (imported, plan) = match plan {
+        Ok(plan) => plan,
+        Err(e) => {
+            tracing::warn!("Failed to import settings: {:#}", e);
+            toast::show(ui, admin::error_text(&e, "failed-import-settings"));
+            return;
+        }
+    };
+    if plan.conflicts.is_empty() {
+        if plan.changes.is_empty() {
+            toast::show(ui, i18n::tr("settings-unchanged"));
+        } else {
+            finish(ui, &imported, &[]);
+        }
+        return;
+    }
+    let taken = vec![true; plan.conflicts.len()];
+    TRANSFER.with(|t| {
+        t.borrow_mut().pending = Some(Pending {
+            imported,
+            conflicts: plan.conflicts,
+            taken,
+        })
+    });
+    show(ui);
+    ui.set_show_import_conflicts(true);
+}
+
+/// Tick or untick the conflict at `row`.
+pub fn toggle(ui: &AppWindow, row: usize, checked: bool) {
+    TRANSFER.with(|t| {
+        if let Some(taken) = t
+            .borrow_mut()
+            .pending
+            .as_mut()
+            .and_then(|p| p.taken.get_mut(row))
+        {
+            *taken = checked;
+        }
+    });
+    show(ui);
+}
+
+/// Import, taking the ticked conflicts and keeping ours for the rest.
+pub fn confirm(ui: &AppWindow) {
+    ui.set_show_import_conflicts(false);
+    let Some(pending) = TRANSFER.with(|t| t.borrow_mut().pending.take()) else {
+        return;
+    };
+    let taken: Vec<String> = pending
+        .conflicts
+        .into_iter()
+        .zip(pending.taken)
+        .filter(|(_, taken)| *taken)
+        .map(|(section, _)| section.key)
+        .collect();
+    finish(ui, &pending.imported, &taken);
+}
+
+/// Drop the import without changing anything.
+pub fn cancel(ui: &AppWindow) {
+    ui.set_show_import_conflicts(false);
+    TRANSFER.with(|t| t.borrow_mut().pending = None);
+}
+
+/// Merge `imported` into the saved settings, save and apply them.
+fn finish(ui: &AppWindow, imported: &Config, taken: &[String]) {
+    let current = ConfigManager::load();
+    let merged = match settings_transfer::merge(&current, imported, taken) {
+        Ok(merged) => merged,
+        Err(e) => {
+            tracing::warn!("Failed to import settings: {:#}", e);
+            toast::show(ui, admin::error_text(&e, "failed-import-settings"));
+            return;
+        }
+    };
+    if let Err(e) = ConfigManager::save(&merged) {
+        tracing::warn!("Failed to save imported settings: {:#}", e);
+        toast::show(ui, admin::error_text(&e, "failed-import-settings"));
+        return;
+    }
+    ui.set_show_settings(false);
+    if let Some(apply) = TRANSFER.with(|t| t.borrow().apply.clone()) {
+        apply(ui, &current, &merged);
+    }
+    toast::show(ui, i18n::tr("settings-imported"));
+}
+
+fn show(ui: &AppWindow) {
+    let rows: Vec<ImportConflictData> = TRANSFER.with(|t| {
+        let transfer = t.borrow();
+        let Some(pending) = transfer.pending.as_ref() else {
+            return Vec::new();
+        };
+        pending
+            .conflicts
+            .iter()
+            .zip(&pending.taken)
+            .map(|(section, taken)| ImportConflictData {
+                label: SharedString::from(section.label.as_str()),
+                checked: *taken,
+            })
+            .collect()
+    });
+    ui.set_import_conflicts(Rc::new(VecModel::from(rows)).into());
+}
@@ -0,0 +1,221 @@
+//! Keyboard shortcuts of the signed-in account: keys the window sees are
+//! turned into the actions they are bound to, for `handle_shortcut` to run,
+//! and the settings' keybindings section captures new keys.
+
+use crate::{AppWindow, KeybindingData};
+use chat_core::keybindings::{Action, Keybindings, Shortcut};
+use network::config::Config;
+use network::voice::VoiceManager;
+use slint::winit_030::winit::event::{ElementState, KeyEvent};
+use slint::winit_030::winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
+use slint::{SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Shortcuts {
+    voice: Option<Arc<VoiceManager>>,
+    bindings: Keybindings,
+    /// The settings dialog's copy, applied when it is saved.
+    draft: Keybindings,
+    /// The action the dialog is waiting for keys for.
+    capturing: Option<Action>,
+    /// The overlay's key is a global hotkey, so the window leaves it be.
+    overlay_global: bool,
+}
+
+thread_local! {
+    static SHORTCUTS: RefCell<Shortcuts> = RefCell::new(Shortcuts::default());
+}
+
+/// Hand push-to-talk and overlay keys to `voice` from now on.
+pub fn start(voice: Arc<VoiceManager>) {
+    SHORTCUTS.with(|s| s.borrow_mut().voice = Some(voice));
+}
+
+fn apply(bindings: Keybindings) {
+    let ptt = bindings.get(Action::PushToTalk);
+    let overlay = bindings.get(Action::ToggleOverlay);
+    let voice = SHORTCUTS.with(|s| {
+        let mut shortcuts = s.borrow_mut();
+        shortcuts.bindings = bindings;
+        shortcuts.voice.clone()
+    });
+    let Some(voice) = voice else {
+        return;
+    };
+    voice.set_ptt_key(ptt.as_ref());
+    let overlay_global = voice.set_overlay_key(overlay.as_ref());
+    SHORTCUTS.with(|s| s.borrow_mut().overlay_global = overlay_global);
+}
+
+/// Whether the overlay's key works wherever the focus is, rather than
+/// only in the window.
+pub fn overlay_key_is_global() -> bool {
+    SHORTCUTS.with(|s| s.borrow().overlay_global)
+}
+
+/// Use the bindings `user_id` saved, as they sign in.
+pub fn load(config: &Config, user_id: &str) {
+    apply(config.account(user_id).keybindings);
+}
+
+/// Back to the defaults on logout, with push-to-talk released and the
+/// overlay's key given back to other apps.
+pub fn clear() {
+    let mut bindings = Keybindings::default();
+    bindings.set(Action::ToggleOverlay, None);
+    apply(bindings);
+}
+
+/// The settings dialog opened: edit a copy of the bindings.
+pub fn open_settings(ui: &AppWindow) {
+    SHORTCUTS.with(|s| {
+        let mut shortcuts = s.borrow_mut();
+        shortcuts.draft = shortcuts.bindings.clone();
+        shortcuts.capturing = None;
+    });
+    render(ui);
+}
+
+fn render(ui: &AppWindow) {
+    let (rows, capturing) = SHORTCUTS.with(|s| {
+        let shortcuts = s.borrow();
+        let draft = &shortcuts.draft;
+        let rows: Vec<KeybindingData> = Action::ALL
+            .iter()
+            .map(|&action| {
+                let conflicts: Vec<&str> = draft
+                    .conflicts(action)
+                    .into_iter()
+                    .map(Action::label)
+                    .collect();
+                KeybindingData {
+                    action: SharedString::from(action.label()),
+                    keys: SharedString::from(
+                        draft.get(action).map(|s| s.label()).unwrap_or_default(),
+                    ),
+                    conflict: SharedString::from(conflicts.join(", ")),
+                }
+            })
+            .collect();
+        let capturing = shortcuts
+            .capturing
+            .and_then(|action| Action::ALL.iter().position(|&a| a == action));
+        (rows, capturing)
+    });
+    ui.set_keybindings(Rc::new(VecModel::from(rows)).into());
+    ui.set_capturing_keybinding(capturing.map_or(-1, |row| row as i32));
+}
+
+fn action_at(row: i32) -> Option<Action> {
+    usize::try_from(row)
+        .ok()
+        .and_then(|row| Action::ALL.get(row).copied())
+}
+
+/// Wait for the keys to bind row `row` to; -1 stops waiting.
+pub fn capture(ui: &AppWindow, row: i32) {
+    SHORTCUTS.with(|s| s.borrow_mut().capturing = action_at(row));
+    render(ui);
+}
+
+pub fn unbind(ui: &AppWindow, row: i32) {
+    let Some(action) = action_at(row) else {
+        return;
+    };
+    SHORTCUTS.with(|s| {
+        let mut shortcuts = s.borrow_mut();
+        shortcuts.draft.set(action, None);
+        shortcuts.capturing = None;
+    });
+    render(ui);
+}
+
+/// The settings were saved: keep the dialog's bindings for `user_id` in
+/// `config`, and use them.
+pub fn save(config: &mut Config, user_id: &str) {
+    let draft = SHORTCUTS.with(|s| s.borrow().draft.clone());
+    config
+        .accounts
+        .entry(user_id.to_string())
+        .or_default()
+        .keybindings = draft.clone();
+    apply(draft);
+}
+
+/// The keys of `event` as a binding; `None` for a modifier on its own.
+fn shortcut(event: &KeyEvent, modifiers: ModifiersState) -> Option<Shortcut> {
+    let PhysicalKey::Code(code) = event.physical_key else {
+        return None;
+    };
+    if matches!(
+        code,
+        KeyCode::ShiftLeft
+            | KeyCode::ShiftRight
+            | KeyCode::ControlLeft
+            | KeyCode::ControlRight
+            | KeyCode::AltLeft
+            | KeyCode::AltRight
+            | KeyCode::SuperLeft
+            | KeyCode::SuperRight
+    ) {
+        return None;
+    }
+    Some(Shortcut {
+        ctrl: modifiers.control_key(),
+        alt: modifiers.alt_key(),
+        shift: modifiers.shift_key(),
+        meta: modifiers.super_key(),
+        // Named as in the W3C `code` values.
+        key: format!("{:?}", code),
+    })
+}
+
+/// While the dialog waits for keys, the next ones pressed are bound; Escape
+/// gives up. Returns true when the key was used.
+pub fn capture_key(ui: &AppWindow, event: &KeyEvent, modifiers: ModifiersState) -> bool {
+    let Some(action) = SHORTCUTS.with(|s| s.borrow().capturing) else {
+        return false;
+    };
+    if !ui.get_show_settings() {
+        return false;
+    }
+    if event.state != ElementState::Pressed {
+        return true;
+    }
+    if event.physical_key != PhysicalKey::Code(KeyCode::Escape) {
+        let Some(shortcut) = shortcut(event, modifiers) else {
+            // Wait for the key to go with the modifiers.
+            return true;
+        };
+        SHORTCUTS.with(|s| s.borrow_mut().draft.set(action, Some(shortcut)));
+    }
+    SHORTCUTS.with(|s| s.borrow_mut().capturing = None);
+    render(ui);
+    true
+}
+
+/// The action `event` presses or releases, with whether it is pressed.
+/// Only push-to-talk is released, by its key whatever modifiers are still
+/// held.
+pub fn key_event(
+    ui: &AppWindow,
+    event: &KeyEvent,
+    modifiers: ModifiersState,
+) -> Option<(Action, bool)> {
+    if !ui.get_logged_in() || event.repeat {
+        return None;
+    }
+    let shortcut = shortcut(event, modifiers)?;
+    SHORTCUTS.with(|s| {
+        let shortcuts = s.borrow();
+        let bindings = &shortcuts.bindings;
+        if event.state == ElementState::Released {
+            let ptt = bindings.get(Action::PushToTalk)?;
+            return (ptt.key == shortcut.key).then_some((Action::PushToTalk, false));
+        }
+        bindings.action(&shortcut).map(|action| (action, true))
+    })
+}
@@ -0,0 +1,110 @@
+//! Quitting in order, however it was asked for: the window closing, the
+//! tray's Quit or Ctrl+C. New commands are refused first; then sync and
+//! the voice loop are stopped together, given `STOP_TIMEOUT` between them;
+//! then what is kept between launches is written, whether or not they
+//! stopped in time.
+
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// How long sync and voice get to stop before quitting goes on without them.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What quitting winds down.
+#[async_trait(?Send)]
+pub trait Parts {
+    /// Refuse new commands; those already issued still run.
+    fn close(&self);
+    /// End the sync loop once what was issued before has run.
+    async fn stop_sync(&self);
+    /// End the voice loop's thread.
+    async fn stop_voice(&self);
+    /// Write the send queue, sent messages and settings.
+    fn flush(&self);
+}
+
+/// Wind `parts` down, giving sync and voice `timeout` to stop. Returns
+/// whether they did.
+pub async fn run(parts: &impl Parts, timeout: Duration) -> bool {
+    parts.close();
+    let stopped = tokio::time::timeout(timeout, async {
+        tokio::join!(parts.stop_sync(), parts.stop_voice());
+    })
+    .await
+    .is_ok();
+    if !stopped {
+        tracing::info!("Quitting without waiting longer for sync and voice to stop");
+    }
+    parts.flush();
+    stopped
+}
+
+/// Quit on Ctrl+C as the tray's Quit does, with `quit`; a second Ctrl+C
+/// while quitting ends the process at once.
+pub fn on_ctrl_c(quit: impl Fn() + Send + 'static) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        quit();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records what was called, in order; voice can be made to hang.
+    #[derive(Default)]
+    struct FakeParts {
+        calls: RefCell<Vec<&'static str>>,
+        voice_hangs: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl Parts for FakeParts {
+        fn close(&self) {
+            self.calls.borrow_mut().push("close");
+        }
+
+        async fn stop_sync(&self) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.calls.borrow_mut().push("sync stopped");
+        }
+
+        async fn stop_voice(&self) {
+            if self.voice_hangs {
+                std::future::pending::<()>().await;
+            }
+            self.calls.borrow_mut().push("voice stopped");
+        }
+
+        fn flush(&self) {
+            self.calls.borrow_mut().push("flush");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parts_stop_before_flushing() {
+        let parts = FakeParts::default();
+        assert!(run(&parts, STOP_TIMEOUT).await);
+        assert_eq!(
+            *parts.calls.borrow(),
+            ["close", "voice stopped", "sync stopped", "flush"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flushes_after_timeout() {
+        let parts = FakeParts {
+            voice_hangs: true,
+            ..Default::default()
+        };
+        assert!(!run(&parts, Duration::from_millis(50)).await);
+        assert_eq!(*parts.calls.borrow(), ["close", "sync stopped", "flush"]);
+    }
+}
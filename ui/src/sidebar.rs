@@ -0,0 +1,713 @@
+use chat_core::{Room, Space, UnreadCounts};
+use network::events::ChatEvent;
+
+/// Rail id for the pseudo-server holding DMs and rooms outside any space.
+pub const HOME_SERVER_ID: &str = "home";
+
+/// An incremental change to the server rail or channel list models.
+/// Indices refer to the models as they are *after* the previous patch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    ServerInserted {
+        index: usize,
+        space: Space,
+    },
+    ServerRemoved {
+        index: usize,
+    },
+    ServerRenamed {
+        index: usize,
+        name: String,
+    },
+    ChannelInserted {
+        index: usize,
+        room: Room,
+    },
+    ChannelRemoved {
+        index: usize,
+    },
+    ChannelRenamed {
+        index: usize,
+        name: String,
+    },
+    ChannelUnread {
+        index: usize,
+        unread: UnreadCounts,
+    },
+    /// A server's aggregated badge changed.
+    ServerUnread {
+        index: usize,
+        unread: UnreadCounts,
+    },
+    /// The visible channel set changed wholesale (e.g. space children edited).
+    ChannelsReset,
+    /// A room's messages are end-to-end encrypted from now on.
+    RoomEncrypted {
+        room_id: String,
+    },
+    /// A room's main alias changed.
+    RoomAliasChanged {
+        room_id: String,
+        alias: Option<String>,
+    },
+    RoomTopicChanged {
+        room_id: String,
+        topic: Option<String>,
+    },
+}
+
+/// Sidebar state: joined spaces and rooms, plus the current selection.
+///
+/// Server index 0 is always Home; space `i` is shown at rail index `i + 1`.
+pub struct Sidebar {
+    spaces: Vec<Space>,
+    /// Non-space rooms, kept sorted by name so insert positions are stable.
+    rooms: Vec<Room>,
+    active_server: usize,
+    active_room: Option<String>,
+}
+
+impl Sidebar {
+    pub fn new(spaces: Vec<Space>, mut rooms: Vec<Room>) -> Self {
+        rooms.sort_by_key(sort_key);
+        Self {
+            spaces,
+            rooms,
+            active_server: 0,
+            active_room: None,
+        }
+    }
+
+    pub fn spaces(&self) -> &[Space] {
+        &self.spaces
+    }
+
+    pub fn active_server(&self) -> usize {
+        self.active_server
+    }
+
+    pub fn set_active_server(&mut self, index: usize) {
+        self.active_server = index.min(self.spaces.len());
+    }
+
+    /// The space shown as the active server; `None` on Home.
+    pub fn active_space(&self) -> Option<&Space> {
+        self.active_server
+            .checked_sub(1)
+            .and_then(|i| self.spaces.get(i))
+    }
+
+    pub fn active_room(&self) -> Option<&str> {
+        self.active_room.as_deref()
+    }
+
+    pub fn set_active_room(&mut self, room_id: Option<String>) {
+        self.active_room = room_id;
+    }
+
+    pub fn room(&self, room_id: &str) -> Option<&Room> {
+        self.rooms.iter().find(|r| r.id == room_id)
+    }
+
+    /// A joined room by its id or its canonical alias.
+    pub fn find(&self, id_or_alias: &str) -> Option<&Room> {
+        self.rooms
+            .iter()
+            .find(|r| r.id == id_or_alias || r.alias.as_deref() == Some(id_or_alias))
+    }
+
+    /// Rooms shown in the channel list for the active server.
+    pub fn channels(&self) -> Vec<&Room> {
+        self.rooms
+            .iter()
+            .filter(|r| self.server_of(&r.id) == self.active_server)
+            .collect()
+    }
+
+    /// Rail index of the server a room is listed under.
+    pub fn server_of(&self, room_id: &str) -> usize {
+        self.spaces
+            .iter()
+            .position(|s| s.children.iter().any(|c| c == room_id))
+            .map(|i| i + 1)
+            .unwrap_or(0)
+    }
+
+    /// Badge for a server: the sum over its rooms, leaving out muted ones.
+    pub fn server_unread(&self, index: usize) -> UnreadCounts {
+        self.rooms
+            .iter()
+            .filter(|r| !r.muted && self.server_of(&r.id) == index)
+            .map(|r| r.unread)
+            .sum()
+    }
+
+    /// Unread counters across all rooms, ignoring muted ones.
+    pub fn total_unread(&self) -> UnreadCounts {
+        self.rooms
+            .iter()
+            .filter(|r| !r.muted)
+            .map(|r| r.unread)
+            .sum()
+    }
+
+    /// Zero a room's counters locally, e.g. when it is opened and marked read.
+    pub fn clear_unread(&mut self, room_id: &str) -> Vec<Patch> {
+        self.set_unread(room_id, UnreadCounts::default())
+    }
+
+    /// The room with the most recent activity, if any.
+    pub fn most_recent_room(&self) -> Option<&Room> {
+        self.rooms.iter().max_by_key(|r| r.last_activity)
+    }
+
+    /// The next room with unread messages after the active one, going down
+    /// the rail and each server's channels (or up, if not `forward`), and
+    /// wrapping around. Muted rooms are skipped.
+    pub fn next_unread(&self, forward: bool) -> Option<&Room> {
+        let mut order: Vec<&Room> = self.rooms.iter().collect();
+        // Stable, so each server's rooms stay in channel list order.
+        order.sort_by_key(|r| self.server_of(&r.id));
+        if !forward {
+            order.reverse();
+        }
+        let start = self
+            .active_room
+            .as_deref()
+            .and_then(|id| order.iter().position(|r| r.id == id))
+            .map_or(0, |i| i + 1);
+        let (before, after) = order.split_at(start.min(order.len()));
+        after
+            .iter()
+            .chain(before)
+            .find(|r| {
+                !r.muted && r.unread.notifications > 0 && Some(r.id.as_str()) != self.active_room()
+            })
+            .copied()
+    }
+
+    fn channel_index(&self, room_id: &str) -> Option<usize> {
+        self.channels().iter().position(|r| r.id == room_id)
+    }
+
+    /// Fold a sync event into the state, returning the model patches to apply.
+    pub fn apply(&mut self, event: &ChatEvent) -> Vec<Patch> {
+        match event {
+            ChatEvent::RoomJoined(room) => self.upsert_room(room.clone()),
+            ChatEvent::SpaceJoined(space) => self.upsert_space(space.clone()),
+            ChatEvent::RoomLeft { room_id } => self.remove(room_id),
+            ChatEvent::RoomRenamed { room_id, name } => self.rename(room_id, name),
+            ChatEvent::RoomEncrypted { room_id } => self.set_encrypted(room_id),
+            ChatEvent::RoomAliasChanged { room_id, alias } => self.set_alias(room_id, alias),
+            ChatEvent::RoomTopicChanged { room_id, topic, .. } => self.set_topic(room_id, topic),
+            ChatEvent::SpaceChildrenChanged { space_id, children } => {
+                let Some(pos) = self.spaces.iter().position(|s| &s.id == space_id) else {
+                    return Vec::new();
+                };
+                let affects_view = self.active_server == 0 || self.active_server == pos + 1;
+                self.spaces[pos].children = children.clone();
+                if affects_view {
+                    vec![Patch::ChannelsReset]
+                } else {
+                    Vec::new()
+                }
+            }
+            ChatEvent::UnreadChanged { room_id, unread } => self.set_unread(room_id, *unread),
+            ChatEvent::Message {
+                room_id, message, ..
+            } => {
+                if let Some(room) = self.rooms.iter_mut().find(|r| &r.id == room_id) {
+                    room.last_activity = room.last_activity.max(message.timestamp);
+                }
+                Vec::new()
+            }
+            ChatEvent::MessageEdited { .. }
+            | ChatEvent::SenderTrustChanged { .. }
+            | ChatEvent::PinsChanged { .. }
+            | ChatEvent::Reacted { .. }
+            | ChatEvent::Redacted { .. }
+            | ChatEvent::MembersChanged { .. }
+            | ChatEvent::StickerPacksChanged { .. }
+            | ChatEvent::WelcomeChanged { .. }
+            | ChatEvent::PresenceChanged { .. }
+            | ChatEvent::RosterChanged
+            | ChatEvent::VoiceMembership { .. }
+            | ChatEvent::Invited { .. }
+            | ChatEvent::Knocked(_)
+            | ChatEvent::KnockResolved { .. }
+            | ChatEvent::IncomingCall(_)
+            | ChatEvent::CallAnswered { .. }
+            | ChatEvent::CallHungUp { .. } => Vec::new(),
+        }
+    }
+
+    /// Mark `room_id` encrypted, e.g. as soon as we turn encryption on
+    /// rather than when sync brings it back.
+    pub fn set_encrypted(&mut self, room_id: &str) -> Vec<Patch> {
+        match self.rooms.iter_mut().find(|r| r.id == room_id) {
+            Some(room) if !room.encrypted => {
+                room.encrypted = true;
+                vec![Patch::RoomEncrypted {
+                    room_id: room_id.to_string(),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_alias(&mut self, room_id: &str, alias: &Option<String>) -> Vec<Patch> {
+        match self.rooms.iter_mut().find(|r| r.id == room_id) {
+            Some(room) if room.alias != *alias => {
+                room.alias = alias.clone();
+                vec![Patch::RoomAliasChanged {
+                    room_id: room_id.to_string(),
+                    alias: alias.clone(),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_topic(&mut self, room_id: &str, topic: &Option<String>) -> Vec<Patch> {
+        match self.rooms.iter_mut().find(|r| r.id == room_id) {
+            Some(room) if room.topic != *topic => {
+                room.topic = topic.clone();
+                vec![Patch::RoomTopicChanged {
+                    room_id: room_id.to_string(),
+                    topic: topic.clone(),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_unread(&mut self, room_id: &str, unread: UnreadCounts) -> Vec<Patch> {
+        match self.rooms.iter_mut().find(|r| r.id == room_id) {
+            Some(room) if room.unread != unread => room.unread = unread,
+            _ => return Vec::new(),
+        }
+        let mut patches = Vec::new();
+        if let Some(index) = self.channel_index(room_id) {
+            patches.push(Patch::ChannelUnread { index, unread });
+        }
+        let server = self.server_of(room_id);
+        patches.push(Patch::ServerUnread {
+            index: server,
+            unread: self.server_unread(server),
+        });
+        patches
+    }
+
+    fn upsert_room(&mut self, room: Room) -> Vec<Patch> {
+        if let Some(existing) = self.rooms.iter_mut().find(|r| r.id == room.id) {
+            let renamed = existing.name != room.name;
+            let last_activity = existing.last_activity.max(room.last_activity);
+            *existing = Room {
+                last_activity,
+                ..room.clone()
+            };
+            if !renamed {
+                return Vec::new();
+            }
+            return self.resort_renamed(&room.id, &room.name);
+        }
+
+        let pos = self
+            .rooms
+            .binary_search_by_key(&sort_key(&room), sort_key)
+            .unwrap_or_else(|p| p);
+        let id = room.id.clone();
+        self.rooms.insert(pos, room.clone());
+        match self.channel_index(&id) {
+            Some(index) => vec![Patch::ChannelInserted { index, room }],
+            None => Vec::new(),
+        }
+    }
+
+    fn upsert_space(&mut self, space: Space) -> Vec<Patch> {
+        if let Some(pos) = self.spaces.iter().position(|s| s.id == space.id) {
+            let mut patches = Vec::new();
+            if self.spaces[pos].name != space.name {
+                patches.push(Patch::ServerRenamed {
+                    index: pos + 1,
+                    name: space.name.clone(),
+                });
+            }
+            let children_changed = self.spaces[pos].children != space.children;
+            self.spaces[pos] = space;
+            if children_changed && (self.active_server == 0 || self.active_server == pos + 1) {
+                patches.push(Patch::ChannelsReset);
+            }
+            return patches;
+        }
+
+        self.spaces.push(space.clone());
+        let mut patches = vec![Patch::ServerInserted {
+            index: self.spaces.len(),
+            space,
+        }];
+        // Its children may have been listed under Home until now.
+        if self.active_server == 0 {
+            patches.push(Patch::ChannelsReset);
+        }
+        patches
+    }
+
+    fn remove(&mut self, room_id: &str) -> Vec<Patch> {
+        if let Some(pos) = self.spaces.iter().position(|s| s.id == room_id) {
+            self.spaces.remove(pos);
+            let index = pos + 1;
+            let mut patches = vec![Patch::ServerRemoved { index }];
+            if self.active_server == index {
+                self.active_server = 0;
+                patches.push(Patch::ChannelsReset);
+            } else if self.active_server > index {
+                self.active_server -= 1;
+            } else if self.active_server == 0 {
+                patches.push(Patch::ChannelsReset);
+            }
+            return patches;
+        }
+
+        let visible = self.channel_index(room_id);
+        self.rooms.retain(|r| r.id != room_id);
+        if self.active_room.as_deref() == Some(room_id) {
+            self.active_room = None;
+        }
+        match visible {
+            Some(index) => vec![Patch::ChannelRemoved { index }],
+            None => Vec::new(),
+        }
+    }
+
+    fn rename(&mut self, room_id: &str, name: &str) -> Vec<Patch> {
+        if let Some(pos) = self.spaces.iter().position(|s| s.id == room_id) {
+            self.spaces[pos].name = name.to_string();
+            return vec![Patch::ServerRenamed {
+                index: pos + 1,
+                name: name.to_string(),
+            }];
+        }
+        match self.rooms.iter_mut().find(|r| r.id == room_id) {
+            Some(room) if room.name != name => {
+                room.name = name.to_string();
+                self.resort_renamed(room_id, name)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Re-sort after a rename. Renames in place when the position is
+    /// unchanged so the row (and any selection on it) is kept.
+    fn resort_renamed(&mut self, room_id: &str, name: &str) -> Vec<Patch> {
+        let before = self.channel_index(room_id);
+        self.rooms.sort_by_key(sort_key);
+        let after = self.channel_index(room_id);
+        match (before, after) {
+            (Some(b), Some(a)) if a == b => vec![Patch::ChannelRenamed {
+                index: a,
+                name: name.to_string(),
+            }],
+            (Some(b), Some(a)) => vec![
+                Patch::ChannelRemoved { index: b },
+                Patch::ChannelInserted {
+                    index: a,
+                    room: self.channels()[a].clone(),
+                },
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn sort_key(room: &Room) -> (String, String) {
+    (room.name.to_lowercase(), room.id.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::RoomType;
+
+    fn room(id: &str, name: &str, last_activity: u64) -> Room {
+        Room {
+            id: id.to_string(),
+            name: name.to_string(),
+            topic: None,
+            room_type: RoomType::Group,
+            avatar_url: None,
+            last_activity,
+            unread: UnreadCounts::default(),
+            muted: false,
+            alias: None,
+            direct_targets: Vec::new(),
+            encrypted: false,
+            member_count: 0,
+        }
+    }
+
+    fn space(id: &str, name: &str, children: &[&str]) -> Space {
+        Space {
+            id: id.to_string(),
+            name: name.to_string(),
+            avatar_url: None,
+            children: children.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    fn names(sidebar: &Sidebar) -> Vec<&str> {
+        sidebar.channels().iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_home_excludes_space_children() {
+        let sidebar = Sidebar::new(
+            vec![space("!s", "Clan", &["!b"])],
+            vec![room("!a", "alpha", 1), room("!b", "bravo", 2)],
+        );
+        assert_eq!(names(&sidebar), vec!["alpha"]);
+        assert_eq!(sidebar.server_of("!b"), 1);
+        assert_eq!(sidebar.most_recent_room().unwrap().id, "!b");
+    }
+
+    #[test]
+    fn test_join_inserts_sorted() {
+        let mut sidebar = Sidebar::new(
+            vec![],
+            vec![room("!a", "alpha", 0), room("!c", "charlie", 0)],
+        );
+        let patches = sidebar.apply(&ChatEvent::RoomJoined(room("!b", "bravo", 0)));
+        assert_eq!(
+            patches,
+            vec![Patch::ChannelInserted {
+                index: 1,
+                room: room("!b", "bravo", 0)
+            }]
+        );
+        assert_eq!(names(&sidebar), vec!["alpha", "bravo", "charlie"]);
+    }
+
+    #[test]
+    fn test_rejoin_is_not_duplicated() {
+        let mut sidebar = Sidebar::new(vec![], vec![room("!a", "alpha", 5)]);
+        let patches = sidebar.apply(&ChatEvent::RoomJoined(room("!a", "alpha", 0)));
+        assert!(patches.is_empty());
+        assert_eq!(sidebar.channels().len(), 1);
+        assert_eq!(sidebar.room("!a").unwrap().last_activity, 5);
+    }
+
+    #[test]
+    fn test_leave_removes_row_and_selection() {
+        let mut sidebar =
+            Sidebar::new(vec![], vec![room("!a", "alpha", 0), room("!b", "bravo", 0)]);
+        sidebar.set_active_room(Some("!b".to_string()));
+        let patches = sidebar.apply(&ChatEvent::RoomLeft {
+            room_id: "!b".to_string(),
+        });
+        assert_eq!(patches, vec![Patch::ChannelRemoved { index: 1 }]);
+        assert_eq!(sidebar.active_room(), None);
+    }
+
+    #[test]
+    fn test_encryption_is_patched_once() {
+        let mut sidebar = Sidebar::new(vec![], vec![room("!a", "alpha", 0)]);
+        assert_eq!(
+            sidebar.set_encrypted("!a"),
+            vec![Patch::RoomEncrypted {
+                room_id: "!a".to_string()
+            }]
+        );
+        assert!(sidebar.room("!a").unwrap().encrypted);
+        // Sync bringing the event back changes nothing.
+        let patches = sidebar.apply(&ChatEvent::RoomEncrypted {
+            room_id: "!a".to_string(),
+        });
+        assert!(patches.is_empty());
+        assert!(sidebar.set_encrypted("!b").is_empty());
+    }
+
+    #[test]
+    fn test_alias_change_is_patched() {
+        let mut sidebar = Sidebar::new(vec![], vec![room("!a", "alpha", 0)]);
+        let event = ChatEvent::RoomAliasChanged {
+            room_id: "!a".to_string(),
+            alias: Some("#alpha:x.org".to_string()),
+        };
+        assert_eq!(
+            sidebar.apply(&event),
+            vec![Patch::RoomAliasChanged {
+                room_id: "!a".to_string(),
+                alias: Some("#alpha:x.org".to_string()),
+            }]
+        );
+        assert_eq!(
+            sidebar.room("!a").unwrap().alias.as_deref(),
+            Some("#alpha:x.org")
+        );
+        assert_eq!(sidebar.find("#alpha:x.org").unwrap().id, "!a");
+        assert!(sidebar.find("#beta:x.org").is_none());
+        assert!(sidebar.apply(&event).is_empty());
+    }
+
+    #[test]
+    fn test_topic_change_is_patched() {
+        let mut sidebar = Sidebar::new(vec![], vec![room("!a", "alpha", 0)]);
+        let event = |topic: Option<&str>| ChatEvent::RoomTopicChanged {
+            room_id: "!a".to_string(),
+            topic: topic.map(str::to_owned),
+            sender: "@bob:x.org".to_string(),
+            sender_name: None,
+        };
+        assert_eq!(
+            sidebar.apply(&event(Some("Raids on Friday"))),
+            vec![Patch::RoomTopicChanged {
+                room_id: "!a".to_string(),
+                topic: Some("Raids on Friday".to_string()),
+            }]
+        );
+        assert!(sidebar.apply(&event(Some("Raids on Friday"))).is_empty());
+        assert_eq!(sidebar.apply(&event(None)).len(), 1);
+        assert_eq!(sidebar.room("!a").unwrap().topic, None);
+    }
+
+    #[test]
+    fn test_rename_in_place_and_reorder() {
+        let mut sidebar =
+            Sidebar::new(vec![], vec![room("!a", "alpha", 0), room("!b", "bravo", 0)]);
+        let patches = sidebar.apply(&ChatEvent::RoomRenamed {
+            room_id: "!a".to_string(),
+            name: "apple".to_string(),
+        });
+        assert_eq!(
+            patches,
+            vec![Patch::ChannelRenamed {
+                index: 0,
+                name: "apple".to_string()
+            }]
+        );
+
+        let patches = sidebar.apply(&ChatEvent::RoomRenamed {
+            room_id: "!a".to_string(),
+            name: "zulu".to_string(),
+        });
+        assert_eq!(patches[0], Patch::ChannelRemoved { index: 0 });
+        assert!(matches!(
+            patches[1],
+            Patch::ChannelInserted { index: 1, .. }
+        ));
+        assert_eq!(names(&sidebar), vec!["bravo", "zulu"]);
+    }
+
+    #[test]
+    fn test_space_removal_adjusts_selection() {
+        let mut sidebar = Sidebar::new(
+            vec![space("!s1", "One", &[]), space("!s2", "Two", &[])],
+            vec![],
+        );
+        sidebar.set_active_server(2);
+        let patches = sidebar.apply(&ChatEvent::RoomLeft {
+            room_id: "!s1".to_string(),
+        });
+        assert_eq!(patches, vec![Patch::ServerRemoved { index: 1 }]);
+        assert_eq!(sidebar.active_server(), 1);
+        assert_eq!(sidebar.active_space().unwrap().id, "!s2");
+        sidebar.set_active_server(0);
+        assert!(sidebar.active_space().is_none());
+    }
+
+    fn unread(notifications: u64, highlights: u64) -> UnreadCounts {
+        UnreadCounts {
+            notifications,
+            highlights,
+        }
+    }
+
+    #[test]
+    fn test_unread_bubbles_up_without_muted_rooms() {
+        let mut muted = room("!m", "memes", 0);
+        muted.muted = true;
+        let mut sidebar = Sidebar::new(
+            vec![space("!s", "Clan", &["!a", "!m"])],
+            vec![room("!a", "alpha", 0), muted],
+        );
+        sidebar.set_active_server(1);
+
+        let patches = sidebar.apply(&ChatEvent::UnreadChanged {
+            room_id: "!a".to_string(),
+            unread: unread(3, 1),
+        });
+        assert_eq!(
+            patches,
+            vec![
+                Patch::ChannelUnread {
+                    index: 0,
+                    unread: unread(3, 1)
+                },
+                Patch::ServerUnread {
+                    index: 1,
+                    unread: unread(3, 1)
+                },
+            ]
+        );
+
+        // The muted room keeps its own count but adds nothing to the space.
+        let patches = sidebar.apply(&ChatEvent::UnreadChanged {
+            room_id: "!m".to_string(),
+            unread: unread(5, 0),
+        });
+        assert_eq!(
+            patches[0],
+            Patch::ChannelUnread {
+                index: 1,
+                unread: unread(5, 0)
+            }
+        );
+        assert_eq!(sidebar.server_unread(1), unread(3, 1));
+        assert_eq!(sidebar.server_unread(0), UnreadCounts::default());
+        assert_eq!(sidebar.total_unread(), unread(3, 1));
+    }
+
+    #[test]
+    fn test_clear_unread() {
+        let mut sidebar = Sidebar::new(vec![], vec![room("!a", "alpha", 0)]);
+        sidebar.apply(&ChatEvent::UnreadChanged {
+            room_id: "!a".to_string(),
+            unread: unread(2, 0),
+        });
+        assert_eq!(sidebar.server_unread(0), unread(2, 0));
+
+        let patches = sidebar.clear_unread("!a");
+        assert!(patches.contains(&Patch::ServerUnread {
+            index: 0,
+            unread: UnreadCounts::default()
+        }));
+        assert!(sidebar.clear_unread("!a").is_empty());
+    }
+
+    #[test]
+    fn test_next_unread_goes_down_the_rail_and_wraps() {
+        let mut muted = room("!m", "memes", 0);
+        muted.muted = true;
+        muted.unread = unread(4, 0);
+        let mut rooms = vec![room("!a", "alpha", 0), room("!b", "beta", 0), muted];
+        rooms.push(room("!c", "clan chat", 0));
+        for room in &mut rooms {
+            if room.id != "!a" {
+                room.unread = unread(1, 0);
+            }
+        }
+        let mut sidebar = Sidebar::new(vec![space("!s", "Clan", &["!c", "!m"])], rooms);
+        let next = |sidebar: &Sidebar, forward| sidebar.next_unread(forward).map(|r| r.id.clone());
+
+        assert_eq!(next(&sidebar, true).as_deref(), Some("!b"));
+        sidebar.set_active_room(Some("!b".to_string()));
+        assert_eq!(next(&sidebar, true).as_deref(), Some("!c"));
+        assert_eq!(next(&sidebar, false).as_deref(), Some("!c"));
+        sidebar.set_active_room(Some("!c".to_string()));
+        assert_eq!(next(&sidebar, true).as_deref(), Some("!b"));
+
+        sidebar.clear_unread("!b");
+        sidebar.clear_unread("!c");
+        assert_eq!(next(&sidebar, true), None);
+    }
+}
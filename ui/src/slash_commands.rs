@@ -0,0 +1,162 @@
+//! Running the slash commands typed into the composer, once
+//! `chat_core::commands` has parsed them. Failures show as toasts; a
+//! command that doesn't parse is put back in the composer with help above
+//! it, rather than sent as text.
+
+use crate::app_state::ClientHandle;
+use crate::rooms::{self, SharedSidebar};
+use crate::{admin, composer, history, i18n, knocks, toast, AppWindow};
+use chat_core::commands::{CommandError, USAGE};
+use network::admin::AdminError;
+use slint::{ComponentHandle, SharedString};
+use std::future::Future;
+
+/// Run `reply`, then `done` with its result on the UI thread, or toast a
+/// failure as the message `failed`.
+fn run<T: Send + 'static>(
+    ui: &AppWindow,
+    reply: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+    failed: &'static str,
+    done: impl FnOnce(&AppWindow, T) + Send + 'static,
+) {
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(value) => done(&ui, value),
+                Err(e) => {
+                    tracing::warn!("{}: {}", failed, e);
+                    toast::show(&ui, admin::error_text(&e, failed));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// The open room, or a toast saying a command needs one.
+fn open_room(ui: &AppWindow) -> Option<String> {
+    let room_id = history::current_room();
+    if room_id.is_none() {
+        toast::show(ui, i18n::tr("command-needs-room"));
+    }
+    room_id
+}
+
+/// `/me`: send `action` as an emote into the open room.
+pub fn me(ui: &AppWindow, client: &ClientHandle, action: &str) {
+    let Some(room_id) = open_room(ui) else {
+        return;
+    };
+    let reply = client.send_emote(&room_id, action);
+    run(ui, reply, "failed-send", |_, _| {});
+}
+
+/// `/join`: join a room by alias or id and open it. Rooms we may only
+/// knock on offer to.
+pub fn join(ui: &AppWindow, client: &ClientHandle, sidebar: &SharedSidebar, room: &str) {
+    let reply = client.join_room(room, Vec::new());
+    let room = room.to_string();
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(room_id) => rooms::reveal_room(&ui, &client, &sidebar, &room_id),
+                Err(e)
+                    if matches!(
+                        e.downcast_ref::<AdminError>(),
+                        Some(AdminError::Forbidden(_))
+                    ) =>
+                {
+                    knocks::prompt(&ui, &room, Vec::new())
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to join {}: {}", room, e);
+                    toast::show(&ui, admin::error_text(&e, "failed-join-room"));
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// `/msg`: send `text` to `user_id` in our DM with them, started if there
+/// is none, and open it.
+pub fn msg(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    sidebar: &SharedSidebar,
+    user_id: &str,
+    text: String,
+) {
+    let reply = client.open_dm(user_id);
+    let client = client.clone();
+    let sidebar = sidebar.clone();
+    run(ui, reply, "failed-message-them", move |ui, room_id| {
+        rooms::send_to(ui, &client, &room_id, &text, Vec::new(), None);
+        rooms::reveal_room(ui, &client, &sidebar, &room_id);
+    });
+}
+
+/// `/topic`: change the open room's topic.
+pub fn topic(ui: &AppWindow, client: &ClientHandle, topic: &str) {
+    let Some(room_id) = open_room(ui) else {
+        return;
+    };
+    let reply = client.set_topic(&room_id, topic);
+    run(ui, reply, "failed-change-topic", |ui, ()| {
+        toast::show(ui, i18n::tr("topic-changed"))
+    });
+}
+
+/// `/invite`: invite `user_id` into the open room.
+pub fn invite(ui: &AppWindow, client: &ClientHandle, user_id: &str) {
+    let Some(room_id) = open_room(ui) else {
+        return;
+    };
+    let reply = client.invite_user(&room_id, user_id);
+    let user_id = user_id.to_string();
+    run(ui, reply, "failed-invite", move |ui, ()| {
+        toast::show(ui, i18n::tr_args("invited", [("user", user_id.into())]))
+    });
+}
+
+/// `text` didn't parse: put it back in the composer and say why.
+pub fn show_help(ui: &AppWindow, text: &str, error: &CommandError) {
+    let mut help = error.to_string();
+    if let CommandError::Unknown(_) = error {
+        let names: Vec<&str> = USAGE
+            .iter()
+            .filter_map(|usage| usage.split_whitespace().next())
+            .collect();
+        help = format!("{} Commands: {}", help, names.join(", "));
+    }
+    ui.set_command_help(SharedString::from(help));
+    // The composer clears itself once the send callback returns.
+    let text = text.to_string();
+    let ui_handle = ui.as_weak();
+    slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            let caret = text.len();
+            composer::set_text(&ui, text, caret);
+        }
+    })
+    .ok();
+}
+
+/// The composer was edited, which dismisses the help.
+pub fn composer_edited(ui: &AppWindow) {
+    if !ui.get_command_help().is_empty() {
+        ui.set_command_help(SharedString::default());
+    }
+}
@@ -0,0 +1,389 @@
+//! Spell checking in the composer: misspelled words are underlined a
+//! moment after typing stops, and right-clicking one offers the
+//! dictionary's suggestions and to add it to the account's own words. The
+//! dictionary is the settings' language or the interface's, loaded off the
+//! UI thread whenever either changes.
+
+use crate::{composer, i18n, AppWindow, MisspellingData, SpellingSettings};
+use fluent_bundle::FluentValue;
+use network::config::{Config, ConfigManager, SpellingConfig};
+use network::spelling::{self, SpellChecker};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long typing must pause before the composer is checked again.
+const CHECK_DELAY: Duration = Duration::from_millis(300);
+
+#[derive(Default)]
+struct Spelling {
+    /// `None` until the settings are put into effect at startup.
+    config: Option<SpellingConfig>,
+    /// The languages of the dictionaries found, as offered in the settings.
+    languages: Vec<String>,
+    checker: Option<Arc<SpellChecker>>,
+    user_id: String,
+    /// The signed-in account's own words.
+    words: Vec<String>,
+    /// The composer's text, and its misspelled words as byte ranges.
+    text: String,
+    misspelled: Vec<Range<usize>>,
+    /// The misspelled word whose menu is open.
+    menu: Option<Range<usize>>,
+    /// Counts edits, so checks of earlier text are dropped.
+    generation: u64,
+    /// Counts dictionary loads, so one for earlier settings is dropped.
+    loads: u64,
+}
+
+thread_local! {
+    static SPELLING: RefCell<Spelling> = RefCell::new(Spelling::default());
+}
+
+/// The language the settings ask for, or the interface's.
+fn wanted_language(config: &SpellingConfig) -> String {
+    match config.language.trim() {
+        "" => i18n::current().code.to_string(),
+        language => language.to_string(),
+    }
+}
+
+fn render(ui: &AppWindow) {
+    let items: Vec<MisspellingData> = SPELLING.with(|s| {
+        let state = s.borrow();
+        state
+            .misspelled
+            .iter()
+            .map(|word| MisspellingData {
+                before: SharedString::from(&state.text[..word.start]),
+                word: SharedString::from(&state.text[word.clone()]),
+            })
+            .collect()
+    });
+    ui.set_misspellings(Rc::new(VecModel::from(items)).into());
+}
+
+fn show_settings(ui: &AppWindow) {
+    let (config, languages) = SPELLING.with(|s| {
+        let state = s.borrow();
+        (
+            state.config.clone().unwrap_or_default(),
+            state.languages.clone(),
+        )
+    });
+    let index = languages.iter().position(|l| *l == config.language);
+    let mut choices = vec![SharedString::from(i18n::tr("spelling-same-as-app"))];
+    choices.extend(languages.iter().map(SharedString::from));
+    ui.set_spelling_languages(Rc::new(VecModel::from(choices)).into());
+    ui.set_spelling_settings(SpellingSettings {
+        enabled: config.enabled,
+        language: index.map_or(0, |i| i as i32 + 1),
+        dictionary_dir: SharedString::from(config.dictionary_dir.as_str()),
+    });
+}
+
+/// The language at `index` in the settings' list, or "" for the first,
+/// the interface's.
+pub fn language_at(index: i32) -> String {
+    let index = usize::try_from(index).ok().and_then(|i| i.checked_sub(1));
+    SPELLING.with(|s| {
+        let state = s.borrow();
+        index
+            .and_then(|i| state.languages.get(i).cloned())
+            .unwrap_or_default()
+    })
+}
+
+/// Look for dictionaries again and load the one the settings call for.
+fn reload(ui: &AppWindow) {
+    let Some((config, words, loads)) = SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        state.loads += 1;
+        let config = state.config.clone()?;
+        Some((config, state.words.clone(), state.loads))
+    }) else {
+        return;
+    };
+    let language = wanted_language(&config);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let job = tokio::task::spawn_blocking(move || {
+            let found = spelling::find(&spelling::search_dirs(&config.dictionary_dir));
+            let file = spelling::pick(&found, &language).filter(|_| config.enabled);
+            let checker = file.map(|file| {
+                let checker = SpellChecker::load(file)?;
+                checker.set_personal(&words);
+                anyhow::Ok(Arc::new(checker))
+            });
+            let mut languages: Vec<String> = found.into_iter().map(|d| d.language).collect();
+            // Keep a language picked before, though its dictionary went.
+            if !config.language.is_empty() && !languages.contains(&config.language) {
+                languages.push(config.language.clone());
+            }
+            (language, languages, checker, config.enabled)
+        });
+        let (language, languages, checker, enabled) = match job.await {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                tracing::warn!("Failed to load the dictionary: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let name = FluentValue::from(language.clone());
+            let status = match checker {
+                _ if !enabled => String::new(),
+                Some(Ok(ref checker)) => {
+                    let name = FluentValue::from(checker.language().to_string());
+                    i18n::tr_args("spelling-using", [("language", name)])
+                }
+                Some(Err(ref e)) => {
+                    tracing::warn!("Failed to load the {} dictionary: {:#}", language, e);
+                    let error = FluentValue::from(format!("{:#}", e));
+                    i18n::tr_args("spelling-failed", [("language", name), ("error", error)])
+                }
+                None => {
+                    let folder = spelling::own_dir()
+                        .map(|dir| dir.display().to_string())
+                        .unwrap_or_default();
+                    let folder = FluentValue::from(folder);
+                    i18n::tr_args("spelling-missing", [("language", name), ("folder", folder)])
+                }
+            };
+            let current = SPELLING.with(|s| {
+                let mut state = s.borrow_mut();
+                if state.loads != loads {
+                    return false;
+                }
+                state.languages = languages;
+                state.checker = checker.and_then(Result::ok);
+                true
+            });
+            if !current {
+                return;
+            }
+            ui.set_spelling_status(SharedString::from(status));
+            show_settings(&ui);
+            let text = SPELLING.with(|s| s.borrow().text.clone());
+            check(&ui, text, Duration::ZERO);
+        })
+        .ok();
+    });
+}
+
+/// Put `config` into effect, as at startup or when the settings are saved.
+pub fn configure(ui: &AppWindow, config: &SpellingConfig) {
+    SPELLING.with(|s| s.borrow_mut().config = Some(config.clone()));
+    show_settings(ui);
+    reload(ui);
+}
+
+/// The interface's language changed, which the dictionary may follow.
+pub fn language_changed(ui: &AppWindow) {
+    let follows = SPELLING.with(|s| {
+        let state = s.borrow();
+        state.config.as_ref().is_some_and(|c| c.language.is_empty())
+    });
+    if follows {
+        reload(ui);
+    } else {
+        show_settings(ui);
+    }
+}
+
+/// Check `text` after `delay`, unless the composer changes meanwhile.
+fn check(ui: &AppWindow, text: String, delay: Duration) {
+    let (checker, generation) = SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        state.generation += 1;
+        (state.checker.clone(), state.generation)
+    });
+    let Some(checker) = checker else {
+        SPELLING.with(|s| s.borrow_mut().misspelled.clear());
+        render(ui);
+        return;
+    };
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let job = {
+            let text = text.clone();
+            tokio::task::spawn_blocking(move || checker.misspelled(&text))
+        };
+        let misspelled = match job.await {
+            Ok(misspelled) => misspelled,
+            Err(e) => {
+                tracing::warn!("Failed to check spelling: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let current = SPELLING.with(|s| {
+                let mut state = s.borrow_mut();
+                if state.generation != generation || state.text != text {
+                    return false;
+                }
+                state.misspelled = misspelled;
+                true
+            });
+            if current {
+                render(&ui);
+            }
+        })
+        .ok();
+    });
+}
+
+/// The composer's text changed, by typing or from code. Underlines ahead
+/// of the change stay put until it is checked again.
+pub fn composer_changed(ui: &AppWindow, text: &str) {
+    SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        let same = state
+            .text
+            .bytes()
+            .zip(text.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        state.misspelled.retain(|word| word.end < same);
+        state.text = text.to_string();
+        state.menu = None;
+    });
+    ui.set_spelling_menu_open(false);
+    render(ui);
+    check(ui, text.to_string(), CHECK_DELAY);
+}
+
+/// Open the menu of the misspelled word at `index`, its suggestions coming
+/// in once found.
+pub fn menu(ui: &AppWindow, index: i32) {
+    let Some((word, range, checker)) = SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        let range = state.misspelled.get(usize::try_from(index).ok()?)?.clone();
+        state.menu = Some(range.clone());
+        let word = state.text[range.clone()].to_string();
+        Some((word, range, state.checker.clone()?))
+    }) else {
+        return;
+    };
+    ui.set_spelling_word(SharedString::from(word.as_str()));
+    ui.set_spelling_suggestions(Rc::new(VecModel::<SharedString>::default()).into());
+    ui.set_spelling_menu_open(true);
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let job = tokio::task::spawn_blocking(move || checker.suggest(&word));
+        let suggestions = match job.await {
+            Ok(suggestions) => suggestions,
+            Err(e) => {
+                tracing::warn!("Failed to find spelling suggestions: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if SPELLING.with(|s| s.borrow().menu.as_ref() != Some(&range)) {
+                return;
+            }
+            let items: Vec<SharedString> = suggestions.iter().map(SharedString::from).collect();
+            ui.set_spelling_suggestions(Rc::new(VecModel::from(items)).into());
+        })
+        .ok();
+    });
+}
+
+/// Put `suggestion` in place of the word whose menu was open.
+pub fn replace(ui: &AppWindow, suggestion: &str) {
+    let Some((text, range)) = SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        let range = state.menu.take()?;
+        Some((state.text.clone(), range))
+    }) else {
+        return;
+    };
+    if ui.get_composer_text() != text.as_str() {
+        return;
+    }
+    let mut replaced = text;
+    replaced.replace_range(range.clone(), suggestion);
+    composer::set_text(ui, replaced, range.start + suggestion.len());
+}
+
+/// Take the word whose menu was open as correct from now on, for the
+/// signed-in account.
+pub fn add_word(ui: &AppWindow) {
+    let Some((word, user_id, checker)) = SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        let range = state.menu.take()?;
+        let word = state.text[range].to_lowercase();
+        if !state.words.contains(&word) {
+            state.words.push(word.clone());
+        }
+        Some((word, state.user_id.clone(), state.checker.clone()))
+    }) else {
+        return;
+    };
+    if let Some(checker) = checker {
+        checker.add(&word);
+    }
+    if !user_id.is_empty() {
+        let mut config = ConfigManager::load();
+        let dictionary = &mut config.accounts.entry(user_id).or_default().dictionary;
+        if !dictionary.contains(&word) {
+            dictionary.push(word.clone());
+            if let Err(e) = ConfigManager::save(&config) {
+                tracing::warn!("Failed to save the spelling dictionary: {:#}", e);
+            }
+        }
+    }
+    SPELLING.with(|s| {
+        let state = &mut *s.borrow_mut();
+        let text = &state.text;
+        state
+            .misspelled
+            .retain(|w| text[w.clone()].to_lowercase() != word);
+    });
+    render(ui);
+}
+
+/// Use the words `user_id` added, as they sign in.
+pub fn load(config: &Config, user_id: &str) {
+    let words = config.account(user_id).dictionary;
+    SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        state.user_id = user_id.to_string();
+        if let Some(checker) = &state.checker {
+            checker.set_personal(&words);
+        }
+        state.words = words;
+    });
+}
+
+/// Forget the account's words and the composer's underlines on logout.
+pub fn clear(ui: &AppWindow) {
+    SPELLING.with(|s| {
+        let mut state = s.borrow_mut();
+        state.user_id.clear();
+        state.words.clear();
+        if let Some(checker) = &state.checker {
+            checker.set_personal(&[]);
+        }
+        state.text.clear();
+        state.misspelled.clear();
+        state.menu = None;
+        state.generation += 1;
+    });
+    ui.set_spelling_menu_open(false);
+    render(ui);
+}
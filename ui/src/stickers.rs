@@ -0,0 +1,245 @@
+//! The sticker picker: the image packs of the user and of the open room,
+//! a tab each, with the stickers of the chosen pack as thumbnails fetched
+//! through the media cache. A picked sticker is sent as is, and shows in
+//! the timeline once synced back. Packs are read again when the account
+//! data or room state holding them changes.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, attachment_view, history, toast, AppWindow, StickerData, StickerPackData};
+use network::media;
+use network::stickers::{Sticker, StickerPack};
+use slint::{ComponentHandle, Image, SharedString, VecModel};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Largest edge thumbnails are decoded at: twice the picker's cells, for
+/// high density screens.
+const THUMBNAIL_SIZE: u32 = 160;
+
+#[derive(Default)]
+struct Stickers {
+    /// The room the packs were read for.
+    room_id: String,
+    packs: Vec<StickerPack>,
+    pack: usize,
+    loading: bool,
+    /// Thumbnails by `mxc://` URI, and those asked for, loaded or not, so a
+    /// broken image isn't fetched over and over.
+    images: HashMap<String, Image>,
+    requested: HashSet<String>,
+}
+
+thread_local! {
+    static STICKERS: RefCell<Stickers> = RefCell::new(Stickers::default());
+}
+
+/// The thumbnail of `url`, and whether it has loaded.
+fn image(url: Option<&str>) -> (Image, bool) {
+    let loaded = url.and_then(|url| STICKERS.with(|s| s.borrow().images.get(url).cloned()));
+    match loaded {
+        Some(image) => (image, true),
+        None => (Image::default(), false),
+    }
+}
+
+/// The image of `pack`'s tab, as a sticker to fetch like the others.
+fn icon(pack: &StickerPack) -> Option<Sticker> {
+    match &pack.avatar_url {
+        Some(url) => Some(Sticker {
+            shortcode: String::new(),
+            body: pack.name.clone(),
+            url: url.clone(),
+            mimetype: None,
+            size: None,
+            width: None,
+            height: None,
+        }),
+        None => pack.stickers.first().cloned(),
+    }
+}
+
+/// Fetch the packs' icons and the chosen pack's thumbnails not asked for
+/// yet.
+fn load_thumbnails(ui: &AppWindow, client: &ClientHandle) {
+    let wanted: Vec<Sticker> = STICKERS.with(|s| {
+        let mut stickers = s.borrow_mut();
+        let mut wanted: Vec<Sticker> = stickers.packs.iter().filter_map(icon).collect();
+        if let Some(pack) = stickers.packs.get(stickers.pack) {
+            wanted.extend(pack.stickers.iter().cloned());
+        }
+        wanted.retain(|sticker| stickers.requested.insert(sticker.url.clone()));
+        wanted
+    });
+    for sticker in wanted {
+        let reply = client.download_thumbnail(&media::sticker_attachment(&sticker));
+        let ui_handle = ui.as_weak();
+        tokio::spawn(async move {
+            let loaded = attachment_view::load_image(reply, THUMBNAIL_SIZE, THUMBNAIL_SIZE).await;
+            slint::invoke_from_event_loop(move || {
+                let Some((buffer, _)) = loaded else {
+                    return;
+                };
+                STICKERS.with(|s| {
+                    let image = Image::from_rgba8(buffer);
+                    s.borrow_mut().images.insert(sticker.url, image);
+                });
+                if let Some(ui) = ui_handle.upgrade() {
+                    render(&ui);
+                }
+            })
+            .ok();
+        });
+    }
+}
+
+fn render(ui: &AppWindow) {
+    let (packs, items, pack, loading) = STICKERS.with(|s| {
+        let stickers = s.borrow();
+        let packs: Vec<_> = stickers
+            .packs
+            .iter()
+            .map(|pack| (pack.name.clone(), pack.icon_url().map(str::to_string)))
+            .collect();
+        let items: Vec<_> = stickers
+            .packs
+            .get(stickers.pack)
+            .map(|pack| pack.stickers.clone())
+            .unwrap_or_default();
+        (packs, items, stickers.pack, stickers.loading)
+    });
+    let packs: Vec<StickerPackData> = packs
+        .into_iter()
+        .map(|(name, icon)| {
+            let (icon, has_icon) = image(icon.as_deref());
+            StickerPackData {
+                name: SharedString::from(name),
+                icon,
+                has_icon,
+            }
+        })
+        .collect();
+    let items: Vec<StickerData> = items
+        .into_iter()
+        .map(|sticker| {
+            let (image, loaded) = image(Some(&sticker.url));
+            StickerData {
+                body: SharedString::from(sticker.body),
+                image,
+                loaded,
+            }
+        })
+        .collect();
+    ui.set_sticker_packs(Rc::new(VecModel::from(packs)).into());
+    ui.set_sticker_items(Rc::new(VecModel::from(items)).into());
+    ui.set_sticker_pack(pack as i32);
+    ui.set_stickers_loading(loading);
+}
+
+/// Read the packs offered in `room_id` again.
+fn load(ui: &AppWindow, client: &ClientHandle, room_id: String) {
+    STICKERS.with(|s| {
+        let mut stickers = s.borrow_mut();
+        if stickers.room_id != room_id {
+            stickers.packs.clear();
+            stickers.pack = 0;
+        }
+        stickers.room_id = room_id.clone();
+        stickers.loading = true;
+    });
+    render(ui);
+
+    let reply = client.sticker_packs(&room_id);
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            let current = STICKERS.with(|s| {
+                let mut stickers = s.borrow_mut();
+                if stickers.room_id != room_id {
+                    return false;
+                }
+                stickers.loading = false;
+                if let Ok(packs) = &result {
+                    stickers.pack = stickers.pack.min(packs.len().saturating_sub(1));
+                    stickers.packs = packs.clone();
+                }
+                true
+            });
+            if !current {
+                return;
+            }
+            if let Err(e) = result {
+                tracing::warn!("Failed to read sticker packs: {:#}", e);
+                toast::show(&ui, admin::error_text(&e, "failed-load-stickers"));
+            }
+            render(&ui);
+            load_thumbnails(&ui, &client);
+        })
+        .ok();
+    });
+}
+
+/// The picker opened: read the open room's packs.
+pub fn open(ui: &AppWindow, client: &ClientHandle) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    load(ui, client, room_id);
+}
+
+pub fn pick_pack(ui: &AppWindow, client: &ClientHandle, index: usize) {
+    let known = STICKERS.with(|s| {
+        let mut stickers = s.borrow_mut();
+        let known = index < stickers.packs.len();
+        if known {
+            stickers.pack = index;
+        }
+        known
+    });
+    if known {
+        render(ui);
+        load_thumbnails(ui, client);
+    }
+}
+
+/// Send the chosen pack's sticker at `index` to the open room.
+pub fn choose(ui: &AppWindow, client: &ClientHandle, index: usize) {
+    let picked = STICKERS.with(|s| {
+        let stickers = s.borrow();
+        let sticker = stickers.packs.get(stickers.pack)?.stickers.get(index)?;
+        Some((stickers.room_id.clone(), sticker.clone()))
+    });
+    let Some((room_id, sticker)) = picked else {
+        return;
+    };
+    let reply = client.send_sticker(&room_id, sticker);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to send the sticker: {:#}", e);
+                toast::show(&ui, admin::error_text(&e, "failed-send-sticker"));
+            }
+        })
+        .ok();
+    });
+}
+
+/// Sticker packs changed somewhere: read them again if they were read
+/// before. Any room's may be among those offered, once the user enabled
+/// them everywhere.
+pub fn packs_changed(ui: &AppWindow, client: &ClientHandle) {
+    let room_id = STICKERS.with(|s| s.borrow().room_id.clone());
+    if !room_id.is_empty() {
+        load(ui, client, room_id);
+    }
+}
@@ -0,0 +1,185 @@
+//! The storage section of the settings: how much disk each cache takes,
+//! clearing one, and how long what was seen is kept. While signed in, what
+//! outlived the retention setting is pruned every `PRUNE_INTERVAL`.
+
+use crate::app_state::ClientHandle;
+use crate::{
+    admin, i18n, input_history, toast, unsent, AppWindow, StorageCategory, StorageSettings,
+};
+use chat_core::format_size;
+use network::cancel::CancelToken;
+use network::config::{Config, Retention};
+use network::media_cache::{CacheCategory, CacheUsage};
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// How often what outlived the retention setting is deleted.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The retention choices, in the order the settings list them.
+const RETENTIONS: [Retention; 4] = [
+    Retention::Forever,
+    Retention::Days30,
+    Retention::Days7,
+    Retention::SessionOnly,
+];
+
+thread_local! {
+    /// Ends the pruning loop; set while signed in.
+    static PRUNING: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// `retention` as the settings list shows it.
+fn retention_index(retention: Retention) -> i32 {
+    RETENTIONS.iter().position(|r| *r == retention).unwrap_or(0) as i32
+}
+
+/// The retention picked at `index` in the settings list.
+pub fn retention(index: i32) -> Retention {
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| RETENTIONS.get(i).copied())
+        .unwrap_or_default()
+}
+
+fn category(category: StorageCategory) -> CacheCategory {
+    match category {
+        StorageCategory::Media => CacheCategory::Media,
+        StorageCategory::Avatars => CacheCategory::Avatars,
+        StorageCategory::Timeline => CacheCategory::Timeline,
+    }
+}
+
+fn show(ui: &AppWindow, usage: CacheUsage) {
+    let mut settings = ui.get_storage_settings();
+    settings.media = SharedString::from(format_size(usage.media));
+    settings.avatars = SharedString::from(format_size(usage.avatars));
+    settings.timeline = SharedString::from(format_size(usage.timeline));
+    settings.status = SharedString::default();
+    ui.set_storage_settings(settings);
+}
+
+fn load_usage(ui: &AppWindow, client: &ClientHandle) {
+    let reply = client.cache_size();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(usage) => show(&ui, usage),
+                Err(e) => {
+                    tracing::warn!("Failed to measure the caches: {}", e);
+                    ui.set_storage_settings(StorageSettings {
+                        status: admin::error_text(&e, "failed-measure-caches"),
+                        ..ui.get_storage_settings()
+                    });
+                }
+            }
+        })
+        .ok();
+    });
+}
+
+/// The settings dialog opened: measure the caches.
+pub fn open_settings(ui: &AppWindow, client: &ClientHandle, config: &Config) {
+    ui.set_storage_settings(StorageSettings {
+        limit: SharedString::from(format_size(config.cache.max_bytes())),
+        retention: retention_index(config.cache.retention),
+        ..Default::default()
+    });
+    if ui.get_logged_in() {
+        load_usage(ui, client);
+    }
+}
+
+/// Delete what is cached of `which`, then measure again.
+pub fn clear(ui: &AppWindow, client: &ClientHandle, which: StorageCategory) {
+    let reply = client.clear_cache(category(which));
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(()) => toast::show(&ui, i18n::tr("cache-cleared")),
+                Err(e) => {
+                    tracing::warn!("Failed to clear the cache: {}", e);
+                    toast::show(&ui, admin::error_text(&e, "failed-clear-cache"));
+                }
+            }
+            load_usage(&ui, &client);
+        })
+        .ok();
+    });
+}
+
+/// Keep the caches to `retention` as an account signs in, or again when the
+/// setting changed: prune now and every `PRUNE_INTERVAL`. Messages waiting
+/// to be sent and drafts are in memory only, so pruning never reaches them.
+pub fn start(client: &ClientHandle, retention: Retention) {
+    let token = CancelToken::new();
+    PRUNING.with(|p| {
+        if let Some(old) = p.borrow_mut().replace(token.clone()) {
+            old.cancel();
+        }
+    });
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        while token.run(interval.tick()).await.is_ok() {
+            if let Err(e) = client.prune_cache(retention).await {
+                tracing::warn!("Failed to prune the caches: {}", e);
+            }
+        }
+    });
+}
+
+pub fn stop() {
+    PRUNING.with(|p| {
+        if let Some(token) = p.borrow_mut().take() {
+            token.cancel();
+        }
+    });
+}
+
+/// The retention setting was saved as `retention`.
+pub fn set_retention(client: &ClientHandle, retention: Retention) {
+    input_history::set_retention(retention);
+    unsent::set_retention(retention);
+    start(client, retention);
+}
+
+/// Delete everything kept on this machine of what was seen and sent, then
+/// measure again. The server keeps it all.
+pub fn clear_history(ui: &AppWindow, client: &ClientHandle) {
+    let reply = client.clear_local_history();
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match result {
+                Ok(()) => {
+                    input_history::clear_sent();
+                    toast::show(&ui, i18n::tr("history-cleared"));
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to clear local history: {}", e);
+                    toast::show(&ui, admin::error_text(&e, "failed-clear-history"));
+                }
+            }
+            load_usage(&ui, &client);
+        })
+        .ok();
+    });
+}
@@ -0,0 +1,238 @@
+//! The rooms the quick switcher offers. It is kept up to date from the room
+//! list's events, each room's names prepared for fuzzy matching as they
+//! arrive, so opening the switcher costs nothing however many rooms there
+//! are.
+
+use chat_core::fuzzy::{self, Target};
+use chat_core::{localpart, Room, RoomType};
+use network::events::ChatEvent;
+use std::cmp::Reverse;
+
+#[derive(Debug)]
+struct Entry {
+    room: Room,
+    /// The name, alias, and for a direct chat who it is with.
+    targets: Vec<Target>,
+}
+
+impl Entry {
+    fn new(room: Room) -> Self {
+        let mut targets = vec![Target::new(&room.name)];
+        if let Some(alias) = &room.alias {
+            targets.push(Target::new(alias));
+        }
+        for user_id in &room.direct_targets {
+            targets.push(Target::new(localpart(user_id)));
+        }
+        Self { room, targets }
+    }
+
+    fn score(&self, query: &[char]) -> Option<i32> {
+        self.targets.iter().filter_map(|t| t.score(query)).max()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SwitcherIndex {
+    entries: Vec<Entry>,
+}
+
+impl SwitcherIndex {
+    /// Index the joined rooms; spaces are left out.
+    pub fn new(rooms: Vec<Room>) -> Self {
+        let mut index = Self::default();
+        for room in rooms {
+            index.upsert(room);
+        }
+        index
+    }
+
+    fn upsert(&mut self, room: Room) {
+        if room.room_type == RoomType::Space {
+            return;
+        }
+        match self.entries.iter_mut().find(|e| e.room.id == room.id) {
+            Some(entry) => {
+                let last_activity = entry.room.last_activity.max(room.last_activity);
+                *entry = Entry::new(Room {
+                    last_activity,
+                    ..room
+                });
+            }
+            None => self.entries.push(Entry::new(room)),
+        }
+    }
+
+    fn room_mut(&mut self, room_id: &str) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|e| e.room.id == room_id)
+    }
+
+    /// Keep up with a room list event.
+    pub fn apply(&mut self, event: &ChatEvent) {
+        match event {
+            ChatEvent::RoomJoined(room) => self.upsert(room.clone()),
+            ChatEvent::RoomLeft { room_id } => self.entries.retain(|e| e.room.id != *room_id),
+            ChatEvent::RoomRenamed { room_id, name } => {
+                if let Some(entry) = self.room_mut(room_id) {
+                    let room = Room {
+                        name: name.clone(),
+                        ..entry.room.clone()
+                    };
+                    *entry = Entry::new(room);
+                }
+            }
+            ChatEvent::RoomAliasChanged { room_id, alias } => {
+                if let Some(entry) = self.room_mut(room_id) {
+                    let room = Room {
+                        alias: alias.clone(),
+                        ..entry.room.clone()
+                    };
+                    *entry = Entry::new(room);
+                }
+            }
+            ChatEvent::UnreadChanged { room_id, unread } => {
+                if let Some(entry) = self.room_mut(room_id) {
+                    entry.room.unread = *unread;
+                }
+            }
+            ChatEvent::Message {
+                room_id, message, ..
+            } => {
+                if let Some(entry) = self.room_mut(room_id) {
+                    entry.room.last_activity = entry.room.last_activity.max(message.timestamp);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The room `room_id`, if it is offered.
+    pub fn room(&self, room_id: &str) -> Option<&Room> {
+        self.entries
+            .iter()
+            .find(|e| e.room.id == room_id)
+            .map(|e| &e.room)
+    }
+
+    /// Up to `limit` rooms matching `query`, best first. Equal matches, and
+    /// everything for an empty query, go most recently active first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&Room> {
+        let query = fuzzy::query(query);
+        let mut found: Vec<(i32, &Room)> = self
+            .entries
+            .iter()
+            .filter_map(|e| Some((e.score(&query)?, &e.room)))
+            .collect();
+        found.sort_by_key(|&(score, room)| {
+            (
+                Reverse(score),
+                Reverse(room.last_activity),
+                room.name.to_lowercase(),
+            )
+        });
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(_, room)| room)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::{time, Message, MessageType, UnreadCounts};
+
+    fn room(id: &str, name: &str, room_type: RoomType, last_activity: u64) -> Room {
+        Room {
+            id: id.to_string(),
+            name: name.to_string(),
+            topic: None,
+            room_type,
+            avatar_url: None,
+            last_activity,
+            unread: UnreadCounts::default(),
+            muted: false,
+            alias: None,
+            direct_targets: Vec::new(),
+            encrypted: false,
+            member_count: 0,
+        }
+    }
+
+    fn ids(rooms: &[&Room]) -> Vec<String> {
+        rooms.iter().map(|r| r.id.clone()).collect()
+    }
+
+    fn index() -> SwitcherIndex {
+        let mut lobby = room("!lobby", "Lobby", RoomType::Public, 30);
+        lobby.alias = Some("#general:x.org".to_string());
+        let mut dm = room("!dm", "Alice", RoomType::Direct, 20);
+        dm.direct_targets = vec!["@wonderland:x.org".to_string()];
+        SwitcherIndex::new(vec![
+            lobby,
+            dm,
+            room("!raid", "Raid Night", RoomType::Group, 10),
+            room("!space", "Guild", RoomType::Space, 40),
+        ])
+    }
+
+    #[test]
+    fn test_search() {
+        let index = index();
+        assert_eq!(ids(&index.search("", 10)), ["!lobby", "!dm", "!raid"]);
+        assert_eq!(ids(&index.search("", 2)), ["!lobby", "!dm"]);
+        assert_eq!(ids(&index.search("raid n", 10)), ["!raid"]);
+        assert_eq!(ids(&index.search("general", 10)), ["!lobby"]);
+        assert_eq!(ids(&index.search("wonder", 10)), ["!dm"]);
+        assert!(index.search("guild", 10).is_empty());
+    }
+
+    #[test]
+    fn test_follows_room_events() {
+        let mut index = index();
+        index.apply(&ChatEvent::RoomRenamed {
+            room_id: "!raid".to_string(),
+            name: "Strategy".to_string(),
+        });
+        index.apply(&ChatEvent::RoomLeft {
+            room_id: "!dm".to_string(),
+        });
+        index.apply(&ChatEvent::RoomAliasChanged {
+            room_id: "!lobby".to_string(),
+            alias: Some("#hangout:x.org".to_string()),
+        });
+        index.apply(&ChatEvent::RoomJoined(room(
+            "!new",
+            "Newcomers",
+            RoomType::Group,
+            5,
+        )));
+        assert!(index.search("raid", 10).is_empty());
+        assert_eq!(ids(&index.search("strat", 10)), ["!raid"]);
+        assert_eq!(ids(&index.search("hangout", 10)), ["!lobby"]);
+        assert!(index.search("general", 10).is_empty());
+        assert_eq!(ids(&index.search("", 10)), ["!lobby", "!raid", "!new"]);
+
+        index.apply(&ChatEvent::Message {
+            room_id: "!new".to_string(),
+            message: Box::new(Message {
+                id: "$1".to_string(),
+                sender: "@bob:x.org".to_string(),
+                sender_name: None,
+                content: "hi".to_string(),
+                schema: MessageType::Text,
+                timestamp: time::now_ms(),
+                reply_to: None,
+                edited: false,
+                reactions: Vec::new(),
+                attachment: None,
+                mentions: Vec::new(),
+                formatted: None,
+                trust: None,
+            }),
+            transaction_id: None,
+        });
+        assert_eq!(ids(&index.search("", 1)), ["!new"]);
+    }
+}
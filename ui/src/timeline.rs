@@ -0,0 +1,665 @@
+//! Slint model for the timeline: structured messages with grouping and
+//! delivery state. Only a window of the newest rows is shown; see
+//! `timeline_window`.
+
+use crate::local_echo::{Diffs, Merge, SendState, Timeline};
+use crate::rich_text::{self, Row, RowKind};
+use crate::timeline_window::{RowChange, Window};
+use crate::{
+    attachment_view, code_highlight, forwarding, game_invites, i18n, keywords, link_previews, pins,
+    profile, reports, scheduled, sender_trust, translations, CodeLineData, CodeTokenData,
+    DeliveryState, MessageData, MessageKind, ReactionData, SenderShield, TextBlockData,
+    TextBlockKind,
+};
+use chat_core::{bridge, time, Message, MessageType, Reaction, SenderTrust};
+use fluent_bundle::FluentValue;
+use network::timeline::TimelineFilter;
+use slint::{Model, ModelNotify, ModelTracker, SharedString, VecModel};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Told how many messages failed to send.
+pub type FailedListener = Box<dyn Fn(usize)>;
+
+/// Slint model over a room's `Timeline`. Rows are derived on demand so the
+/// `compact` flag always reflects the row above.
+pub struct TimelineModel {
+    own_user_id: String,
+    timeline: RefCell<Timeline>,
+    notify: ModelNotify,
+    /// Told how many messages failed to send whenever that changes, while
+    /// the room is on screen.
+    failed_listener: RefCell<Option<FailedListener>>,
+    failed_count: Cell<usize>,
+    /// Whether messages a bridge's bot relayed are shown as their author's.
+    bridged: Cell<bool>,
+    /// Whether the room translates others' messages as they are shown.
+    auto_translate: Cell<bool>,
+    /// Whether the room is encrypted, so its messages carry a shield.
+    encrypted: Cell<bool>,
+    /// The rows of `timeline` shown.
+    window: Cell<Window>,
+    /// Our fully-read marker: the "new messages" divider goes below it.
+    unread_after: RefCell<Option<String>>,
+}
+
+impl TimelineModel {
+    /// A timeline of already delivered messages.
+    pub fn new(own_user_id: &str, messages: Vec<Message>) -> Self {
+        Self {
+            own_user_id: own_user_id.to_string(),
+            timeline: RefCell::new(Timeline::new(messages)),
+            notify: ModelNotify::default(),
+            failed_listener: RefCell::new(None),
+            failed_count: Cell::new(0),
+            bridged: Cell::new(true),
+            auto_translate: Cell::new(false),
+            encrypted: Cell::new(false),
+            window: Cell::new(Window::default()),
+            unread_after: RefCell::new(None),
+        }
+    }
+
+    /// Keep at most `limit` rows when trimmed.
+    pub fn set_window_limit(&self, limit: usize) {
+        let mut window = self.window.get();
+        window.set_limit(limit);
+        self.window.set(window);
+    }
+
+    /// How many of the oldest messages are let go from the rows.
+    pub fn hidden(&self) -> usize {
+        self.window.get().hidden()
+    }
+
+    /// Let go of the oldest rows past the limit, returning how many went.
+    /// Only done while the view sits at the bottom, far from them.
+    pub fn trim(&self) -> usize {
+        let mut window = self.window.get();
+        let count = window.trim(self.timeline.borrow().entries().len());
+        self.window.set(window);
+        if count > 0 {
+            self.notify.row_removed(0, count);
+        }
+        count
+    }
+
+    /// Bring back up to `count` of the rows let go, returning how many came.
+    pub fn reveal(&self, count: usize) -> usize {
+        let mut window = self.window.get();
+        let count = window.reveal(count);
+        self.window.set(window);
+        if count > 0 {
+            self.notify.row_added(0, count);
+        }
+        count
+    }
+
+    /// Bring back the rows let go down from message `event_id`.
+    pub fn reveal_to(&self, event_id: &str) {
+        let Some(entry) = self.timeline.borrow().find(event_id) else {
+            return;
+        };
+        let mut window = self.window.get();
+        let count = window.reveal_to(entry);
+        self.window.set(window);
+        if count > 0 {
+            self.notify.row_added(0, count);
+        }
+    }
+
+    pub fn filter(&self) -> TimelineFilter {
+        self.timeline.borrow().filter()
+    }
+
+    /// Show what `filter` lets through, redrawing the rows if it changed.
+    pub fn set_filter(&self, filter: TimelineFilter) {
+        let diffs = self.timeline.borrow_mut().set_filter(filter);
+        self.apply(diffs);
+    }
+
+    pub fn bridged(&self) -> bool {
+        self.bridged.get()
+    }
+
+    /// Show relayed messages as their author's, or as the bridge sent them.
+    pub fn set_bridged(&self, bridged: bool) {
+        if self.bridged.replace(bridged) != bridged {
+            self.notify.reset();
+        }
+    }
+
+    pub fn auto_translate(&self) -> bool {
+        self.auto_translate.get()
+    }
+
+    /// Translate others' messages as they are shown, or only when asked.
+    pub fn set_auto_translate(&self, on: bool) {
+        if self.auto_translate.replace(on) != on {
+            self.notify.reset();
+        }
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.encrypted.get()
+    }
+
+    /// Show the shield of each message's sender, or none.
+    pub fn set_encrypted(&self, encrypted: bool) {
+        if self.encrypted.replace(encrypted) != encrypted {
+            self.notify.reset();
+        }
+    }
+
+    /// Put the "new messages" divider below message `event_id`, or take it
+    /// away. Only the rows around it change.
+    pub fn set_unread_after(&self, event_id: Option<String>) {
+        let old = self.unread_after.replace(event_id.clone());
+        if old == event_id {
+            return;
+        }
+        for id in [old, event_id].into_iter().flatten() {
+            if let Some(row) = self.row_after(&id) {
+                self.notify.row_changed(row);
+            }
+        }
+    }
+
+    /// The row below message `event_id`, when it is shown.
+    fn row_after(&self, event_id: &str) -> Option<usize> {
+        let entry = self.timeline.borrow().find(event_id)?;
+        let row = (entry + 1).checked_sub(self.hidden())?;
+        (row < self.row_count()).then_some(row)
+    }
+
+    /// `message` as it is shown.
+    fn shown<'a>(&self, message: &'a Message) -> Cow<'a, Message> {
+        if let MessageType::MembershipSummary(summary) = &message.schema {
+            return Cow::Owned(Message {
+                content: i18n::membership_summary(summary),
+                ..message.clone()
+            });
+        }
+        if message.schema == MessageType::Reaction {
+            let sender = ("sender", FluentValue::from(message.sender_display()));
+            let key = ("key", FluentValue::from(message.content.as_str()));
+            return Cow::Owned(Message {
+                content: i18n::tr_args("timeline-reaction", [sender, key]),
+                ..message.clone()
+            });
+        }
+        let relayed = self.bridged.get().then(|| bridge::attribute(message));
+        match relayed.flatten() {
+            Some(relayed) => Cow::Owned(relayed),
+            None => Cow::Borrowed(message),
+        }
+    }
+
+    /// Repeat `diffs`, made to the timeline's rows, on the rows shown.
+    fn apply(&self, diffs: Diffs) {
+        for diff in &diffs {
+            match RowChange::of(diff) {
+                Some(change) => self.notify_row(change),
+                None => self.reset(),
+            }
+        }
+        self.report_failed();
+    }
+
+    /// Show every row afresh, bringing back any let go.
+    fn reset(&self) {
+        let mut window = self.window.get();
+        window.reveal(usize::MAX);
+        self.window.set(window);
+        self.notify.reset();
+    }
+
+    fn notify_row(&self, change: RowChange) {
+        let mut window = self.window.get();
+        let shown = window.change(change);
+        self.window.set(window);
+        match shown {
+            Some(RowChange::Added(row)) => {
+                self.notify.row_added(row, 1);
+                // The row below may start or continue a group now.
+                if row + 1 < self.row_count() {
+                    self.notify.row_changed(row + 1);
+                }
+            }
+            Some(RowChange::Changed(row)) => {
+                self.notify.row_changed(row);
+                // Grouping of the next row depends on this one.
+                if row + 1 < self.row_count() {
+                    self.notify.row_changed(row + 1);
+                }
+            }
+            Some(RowChange::Removed(row)) => {
+                self.notify.row_removed(row, 1);
+                // The row that moved up may start or continue a group now.
+                if row < self.row_count() {
+                    self.notify.row_changed(row);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn report_failed(&self) {
+        let count = self.timeline.borrow().failed().len();
+        if self.failed_count.replace(count) != count {
+            if let Some(listener) = &*self.failed_listener.borrow() {
+                listener(count);
+            }
+        }
+    }
+
+    /// Call `listener` with how many messages failed to send, now and
+    /// whenever that changes; `None` stops.
+    pub fn on_failed_changed(&self, listener: Option<FailedListener>) {
+        if let Some(listener) = &listener {
+            listener(self.failed_count.get());
+        }
+        *self.failed_listener.borrow_mut() = listener;
+    }
+
+    pub fn own_user_id(&self) -> &str {
+        &self.own_user_id
+    }
+
+    pub fn message(&self, event_id: &str) -> Option<Message> {
+        self.timeline.borrow().message(event_id).cloned()
+    }
+
+    pub fn edit(&self, event_id: &str, sender: &str, body: &str, formatted: Option<&str>) {
+        let diffs = self
+            .timeline
+            .borrow_mut()
+            .edit(event_id, sender, body, formatted);
+        self.apply(diffs);
+    }
+
+    pub fn react(&self, message_id: &str, reaction: Reaction) {
+        let diffs = self.timeline.borrow_mut().react(message_id, reaction);
+        self.apply(diffs);
+    }
+
+    /// Trust `sender`'s messages anew, redrawing those that changed.
+    pub fn set_trust(&self, sender: &str, trust: impl Fn(Option<&str>) -> SenderTrust) {
+        let diffs = self.timeline.borrow_mut().set_trust(sender, trust);
+        self.apply(diffs);
+    }
+
+    /// The event id of our own `key` reaction to `message_id`, if any.
+    pub fn own_reaction(&self, message_id: &str, key: &str) -> Option<String> {
+        self.timeline
+            .borrow()
+            .own_reaction(message_id, key, &self.own_user_id)
+            .map(str::to_owned)
+    }
+
+    /// The ids of the messages from `first` to `last`, either way round and
+    /// both included, in timeline order. Ones not yet sent, and notices, are
+    /// left out; so is everything when either end isn't here.
+    pub fn delivered_between(&self, first: &str, last: &str) -> Vec<String> {
+        let timeline = self.timeline.borrow();
+        let (Some(first), Some(last)) = (timeline.find(first), timeline.find(last)) else {
+            return Vec::new();
+        };
+        timeline.entries()[first.min(last)..=first.max(last)]
+            .iter()
+            .filter(|e| e.state == SendState::Sent && !e.message.schema.is_notice())
+            .map(|e| e.message.id.clone())
+            .collect()
+    }
+
+    /// Redraw every row, e.g. when what is highlighted changed.
+    pub fn redraw(&self) {
+        self.notify.reset();
+    }
+
+    /// Redraw the row of `event_id`, e.g. once its attachment has loaded.
+    pub fn refresh(&self, event_id: &str) {
+        let row = self.timeline.borrow().find(event_id);
+        if let Some(row) = row {
+            self.notify_row(RowChange::Changed(row));
+        }
+    }
+
+    pub fn redact(&self, event_id: &str) {
+        let diffs = self.timeline.borrow_mut().redact(event_id);
+        self.apply(diffs);
+    }
+
+    pub fn push_local(&self, txn_id: &str, message: Message) {
+        let diffs = self.timeline.borrow_mut().push_local(txn_id, message);
+        self.apply(diffs);
+    }
+
+    pub fn push_queued(&self, txn_id: &str, message: Message) {
+        let diffs = self.timeline.borrow_mut().push_queued(txn_id, message);
+        self.apply(diffs);
+    }
+
+    pub fn push_remote(&self, message: Message, txn_id: Option<&str>) {
+        let diffs = self.timeline.borrow_mut().push_remote(message, txn_id);
+        self.apply(diffs);
+    }
+
+    pub fn mark_sent(&self, txn_id: &str, event_id: &str) {
+        let diffs = self.timeline.borrow_mut().mark_sent(txn_id, event_id);
+        self.apply(diffs);
+    }
+
+    pub fn mark_failed(&self, txn_id: &str) {
+        let diffs = self.timeline.borrow_mut().mark_failed(txn_id);
+        self.apply(diffs);
+    }
+
+    /// Our newest message that can still be edited.
+    pub fn last_editable(&self) -> Option<Message> {
+        self.timeline
+            .borrow()
+            .last_editable(&self.own_user_id)
+            .cloned()
+    }
+
+    /// Whether `page`, fetched forwards from the messages jumped to, reaches
+    /// the latest ones of this timeline.
+    pub fn reaches(&self, page: &[Message], next_batch: Option<&str>) -> bool {
+        self.timeline.borrow().reaches(page, next_batch)
+    }
+
+    /// Add the messages of `jumped` older than any held here at the top,
+    /// bringing back any rows let go. Returns whether there were any.
+    pub fn join(&self, jumped: &TimelineModel) -> bool {
+        self.reveal(usize::MAX);
+        let (joined, diffs) = self.timeline.borrow_mut().join(&jumped.timeline.borrow());
+        self.apply(diffs);
+        joined
+    }
+
+    /// Id and timestamp of the newest confirmed event.
+    pub fn latest_event(&self) -> Option<(String, u64)> {
+        let timeline = self.timeline.borrow();
+        timeline.latest_event().map(|m| (m.id.clone(), m.timestamp))
+    }
+
+    pub fn merge_latest(&self, page: Vec<Message>) -> Merge {
+        let (merge, diffs) = self.timeline.borrow_mut().merge_latest(page);
+        if merge == Merge::Replaced {
+            self.reset();
+            self.report_failed();
+        } else {
+            self.apply(diffs);
+        }
+        merge
+    }
+
+    /// Insert older history at the top, bringing back any rows let go.
+    pub fn prepend(&self, older: Vec<Message>) {
+        self.reveal(usize::MAX);
+        let diffs = self.timeline.borrow_mut().prepend(older);
+        self.apply(diffs);
+    }
+
+    /// Mark a failed or queued message as sending; returns the message to
+    /// send.
+    pub fn retry(&self, txn_id: &str) -> Option<Message> {
+        let (message, diffs) = self.timeline.borrow_mut().retry(txn_id)?;
+        self.apply(diffs);
+        Some(message)
+    }
+
+    pub fn requeue(&self, txn_id: &str) {
+        let diffs = self.timeline.borrow_mut().requeue(txn_id);
+        self.apply(diffs);
+    }
+
+    /// Drop a failed or queued message; returns whether there was one.
+    pub fn discard(&self, txn_id: &str) -> bool {
+        let diffs = self.timeline.borrow_mut().discard(txn_id);
+        let found = diffs.is_some();
+        self.apply(diffs.unwrap_or_default());
+        found
+    }
+
+    /// Transaction ids of the messages that failed to send, oldest first.
+    pub fn failed(&self) -> Vec<String> {
+        let timeline = self.timeline.borrow();
+        timeline.failed().into_iter().map(str::to_owned).collect()
+    }
+}
+
+fn delivery_state(state: SendState) -> DeliveryState {
+    match state {
+        SendState::Queued => DeliveryState::Queued,
+        SendState::Sending => DeliveryState::Sending,
+        SendState::Sent => DeliveryState::Sent,
+        SendState::Failed => DeliveryState::Failed,
+    }
+}
+
+fn message_kind(schema: &MessageType) -> MessageKind {
+    match schema {
+        MessageType::Image => MessageKind::Image,
+        MessageType::File => MessageKind::File,
+        MessageType::Text => MessageKind::Text,
+        MessageType::Emote => MessageKind::Emote,
+        MessageType::MissedCall => MessageKind::MissedCall,
+        MessageType::SystemNotice(_)
+        | MessageType::Membership(_)
+        | MessageType::MembershipSummary(_)
+        | MessageType::DisplayName
+        | MessageType::Avatar
+        | MessageType::Reaction => MessageKind::SystemNotice,
+        MessageType::GameInvite(_) => MessageKind::GameInvite,
+    }
+}
+
+/// Sender and body of the message `message` replies to, if it is loaded.
+fn reply_preview(message: &Message, timeline: &Timeline) -> (SharedString, SharedString) {
+    let Some(reply_to) = &message.reply_to else {
+        return Default::default();
+    };
+    match timeline.message(reply_to) {
+        Some(replied) => (
+            SharedString::from(replied.sender_display()),
+            SharedString::from(replied.content.as_str()),
+        ),
+        None => (
+            SharedString::default(),
+            SharedString::from("Reply to an earlier message"),
+        ),
+    }
+}
+
+fn reactions(message: &Message, own_user_id: &str) -> slint::ModelRc<ReactionData> {
+    let data: Vec<ReactionData> = message
+        .reaction_counts(own_user_id)
+        .into_iter()
+        .map(|(key, count, own)| ReactionData {
+            key: SharedString::from(key),
+            count: count.min(i32::MAX as usize) as i32,
+            own,
+        })
+        .collect();
+    Rc::new(VecModel::from(data)).into()
+}
+
+fn color(rgb: Option<[u8; 3]>) -> slint::Color {
+    rgb.map(|[r, g, b]| slint::Color::from_rgb_u8(r, g, b))
+        .unwrap_or_default()
+}
+
+/// The lines of code row `index` of `message`, highlighted once that is
+/// done.
+fn code_lines(message: &Message, index: usize, row: &Row) -> slint::ModelRc<CodeLineData> {
+    if row.kind != RowKind::Code {
+        return Default::default();
+    }
+    let lines: Vec<CodeLineData> =
+        code_highlight::lines(&message.id, index, row.language.as_deref(), &row.text)
+            .into_iter()
+            .map(|line| {
+                let tokens: Vec<CodeTokenData> = line
+                    .into_iter()
+                    .map(|token| CodeTokenData {
+                        text: SharedString::from(token.text),
+                        color: color(token.color),
+                        colored: token.color.is_some(),
+                    })
+                    .collect();
+                CodeLineData {
+                    tokens: Rc::new(VecModel::from(tokens)).into(),
+                }
+            })
+            .collect();
+    Rc::new(VecModel::from(lines)).into()
+}
+
+/// `row` as `RichText` draws it, with the highlighted `lines` of a code
+/// block.
+pub fn block_data(row: Row, lines: slint::ModelRc<CodeLineData>) -> TextBlockData {
+    TextBlockData {
+        lines,
+        kind: match row.kind {
+            RowKind::Text => TextBlockKind::Text,
+            RowKind::Heading => TextBlockKind::Heading,
+            RowKind::Code => TextBlockKind::Code,
+            RowKind::Rule => TextBlockKind::Rule,
+        },
+        text: SharedString::from(row.text),
+        masked: SharedString::from(row.masked.unwrap_or_default()),
+        quote_depth: row.quote_depth.min(i32::MAX as usize) as i32,
+        indent: row.indent.min(i32::MAX as usize) as i32,
+        marker: SharedString::from(row.marker),
+        level: row.level.into(),
+        bold: row.bold,
+        italic: row.italic,
+        code: row.code,
+        language: SharedString::from(row.language.unwrap_or_default()),
+        color: color(row.color),
+        colored: row.color.is_some(),
+        link: SharedString::from(row.link.unwrap_or_default()),
+        all_link: row.all_link,
+    }
+}
+
+pub fn blocks(message: &Message) -> slint::ModelRc<TextBlockData> {
+    let data: Vec<TextBlockData> = rich_text::rows(message)
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let lines = code_lines(message, index, &row);
+            block_data(row, lines)
+        })
+        .collect();
+    Rc::new(VecModel::from(data)).into()
+}
+
+fn message_data(
+    message: &Message,
+    prev: Option<&Message>,
+    state: DeliveryState,
+    txn_id: Option<&str>,
+    own_user_id: &str,
+    timeline: &Timeline,
+    auto_translate: bool,
+) -> MessageData {
+    // System notices are one muted line, without a header.
+    let notice = message.schema.is_notice();
+    // A bridge's bot relays for many authors; each gets a header.
+    let compact = notice
+        || prev.is_some_and(|p| {
+            message.continues(p)
+                && (message.sender_name == p.sender_name || !bridge::is_relay_bot(&message.sender))
+        });
+    let timestamp = if compact {
+        time::format_time_of_day(message.timestamp)
+    } else {
+        time::format_timestamp(message.timestamp)
+    };
+    let (reply_sender, reply_body) = reply_preview(message, timeline);
+    let is_own = message.sender == own_user_id;
+    // Date separators go between days, not between senders.
+    let day_break = !prev.is_some_and(|p| time::same_day(p.timestamp, message.timestamp));
+    MessageData {
+        id: SharedString::from(message.id.as_str()),
+        sender: SharedString::from(message.sender_display()),
+        sender_id: SharedString::from(message.sender.as_str()),
+        body: SharedString::from(message.content.as_str()),
+        blocks: blocks(message),
+        timestamp: SharedString::from(timestamp),
+        exact_timestamp: SharedString::from(time::format_exact(message.timestamp)),
+        is_own,
+        state,
+        delivery_note: scheduled::late_note(txn_id),
+        kind: message_kind(&message.schema),
+        compact,
+        avatar_initials: profile::avatar_initials(
+            message.sender_name.as_deref().unwrap_or_default(),
+            &message.sender,
+        ),
+        avatar_color: profile::avatar_color(&message.sender),
+        edited: message.edited,
+        reply_sender,
+        reply_body,
+        reply_id: SharedString::from(message.reply_to.as_deref().unwrap_or_default()),
+        reactions: reactions(message, own_user_id),
+        attachment: attachment_view::attachment_data(message),
+        highlighted: !is_own && !notice && keywords::hit(&message.content),
+        link_preview: link_previews::preview_data(message),
+        invite: game_invites::invite_data(message),
+        translation: translations::translation_data(message, auto_translate, is_own),
+        reported: reports::is_reported(&message.id),
+        pinned: pins::is_pinned(&message.id),
+        selected: forwarding::is_selected(&message.id),
+        shield: SenderShield::None,
+        day: SharedString::from(time::format_day(message.timestamp)),
+        day_break,
+        unread_break: false,
+    }
+}
+
+impl Model for TimelineModel {
+    type Data = MessageData;
+
+    fn row_count(&self) -> usize {
+        let len = self.timeline.borrow().entries().len();
+        self.window.get().rows(len)
+    }
+
+    fn row_data(&self, row: usize) -> Option<MessageData> {
+        let timeline = self.timeline.borrow();
+        let entries = timeline.entries();
+        // Rows let go still lead into the first shown, for grouping.
+        let at = self.window.get().entry(row);
+        let entry = entries.get(at)?;
+        let message = self.shown(&entry.message);
+        let prev = at.checked_sub(1).map(|i| self.shown(&entries[i].message));
+        let mut data = message_data(
+            &message,
+            prev.as_deref(),
+            delivery_state(entry.state),
+            entry.txn_id.as_deref(),
+            &self.own_user_id,
+            &timeline,
+            self.auto_translate.get(),
+        );
+        if self.encrypted.get() {
+            data.shield = sender_trust::shield(&message);
+        }
+        data.unread_break = prev.is_some()
+            && self.unread_after.borrow().as_deref() == Some(entries[at - 1].message.id.as_str());
+        Some(data)
+    }
+
+    fn model_tracker(&self) -> &dyn ModelTracker {
+        &self.notify
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
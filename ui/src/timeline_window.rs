@@ -0,0 +1,197 @@
+//! Which of a room's messages are on screen. Every row of the message list
+//! is a live widget, so a room left open all day would keep growing; while
+//! the view sits at the bottom, rows past the limit are let go from the top,
+//! and scrolling up brings them back from the `Timeline`, which keeps them
+//! all.
+
+use network::timeline::TimelineDiff;
+
+/// Which row a timeline update touched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowChange {
+    Added(usize),
+    Changed(usize),
+    Removed(usize),
+}
+
+impl RowChange {
+    /// The row `diff` touches, or `None` for a `Reset`, which touches all.
+    pub fn of<T>(diff: &TimelineDiff<T>) -> Option<Self> {
+        match diff {
+            TimelineDiff::InsertAt(row, _) => Some(Self::Added(*row)),
+            TimelineDiff::Update(row, _) => Some(Self::Changed(*row)),
+            TimelineDiff::Remove(row) => Some(Self::Removed(*row)),
+            TimelineDiff::Reset(_) => None,
+        }
+    }
+}
+
+/// Rows scrolling up to the top brings back at a time.
+pub const REVEAL_STEP: usize = 100;
+
+/// Fewest rows kept whatever the setting, so the list always overfills the
+/// view and scrolling up has somewhere to go.
+const MIN_LIMIT: usize = 100;
+
+/// The rows shown of a timeline's entries: all but the `hidden` oldest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    hidden: usize,
+    limit: usize,
+}
+
+impl Default for Window {
+    /// Everything shown, however much there is.
+    fn default() -> Self {
+        Self {
+            hidden: 0,
+            limit: usize::MAX,
+        }
+    }
+}
+
+impl Window {
+    /// How many of the oldest entries are let go.
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    /// Keep at most `limit` rows once trimmed.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(MIN_LIMIT);
+    }
+
+    /// How many rows show of `len` entries.
+    pub fn rows(&self, len: usize) -> usize {
+        len.saturating_sub(self.hidden)
+    }
+
+    /// The entry shown at `row`.
+    pub fn entry(&self, row: usize) -> usize {
+        self.hidden + row
+    }
+
+    /// `change` to the entries as a change to the rows shown, or `None` if
+    /// it was above them.
+    pub fn change(&mut self, change: RowChange) -> Option<RowChange> {
+        let hidden = self.hidden;
+        match change {
+            RowChange::Added(entry) if entry < hidden => {
+                self.hidden += 1;
+                None
+            }
+            RowChange::Removed(entry) if entry < hidden => {
+                self.hidden -= 1;
+                None
+            }
+            RowChange::Changed(entry) if entry < hidden => None,
+            RowChange::Added(entry) => Some(RowChange::Added(entry - hidden)),
+            RowChange::Changed(entry) => Some(RowChange::Changed(entry - hidden)),
+            RowChange::Removed(entry) => Some(RowChange::Removed(entry - hidden)),
+        }
+    }
+
+    /// Let go of rows from the top until at most the limit of `len` entries
+    /// show, returning how many went.
+    pub fn trim(&mut self, len: usize) -> usize {
+        let excess = self.rows(len).saturating_sub(self.limit);
+        self.hidden += excess;
+        excess
+    }
+
+    /// Bring back up to `count` rows above the top, returning how many came.
+    pub fn reveal(&mut self, count: usize) -> usize {
+        let count = count.min(self.hidden);
+        self.hidden -= count;
+        count
+    }
+
+    /// Bring back the rows down from `entry`, returning how many came.
+    pub fn reveal_to(&mut self, entry: usize) -> usize {
+        self.reveal(self.hidden.saturating_sub(entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_echo::Timeline;
+    use chat_core::Message;
+    use std::time::{Duration, Instant};
+
+    /// A frame at 60 Hz.
+    const FRAME_BUDGET: Duration = Duration::from_millis(16);
+
+    fn message(n: usize) -> Message {
+        Message {
+            id: format!("${}", n),
+            sender: "@bob:x".to_string(),
+            content: format!("message {}", n),
+            timestamp: n as u64,
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn test_changes_above_the_window_shift_it() {
+        let mut window = Window::default();
+        window.set_limit(0);
+        assert_eq!(window.trim(150), 50);
+        assert_eq!(window.rows(150), 100);
+        assert_eq!(window.entry(0), 50);
+
+        assert_eq!(window.change(RowChange::Changed(10)), None);
+        assert_eq!(window.change(RowChange::Removed(10)), None);
+        assert_eq!(window.hidden(), 49);
+        assert_eq!(
+            window.change(RowChange::Added(149)),
+            Some(RowChange::Added(100))
+        );
+        assert_eq!(
+            window.change(RowChange::Changed(49)),
+            Some(RowChange::Changed(0))
+        );
+
+        assert_eq!(window.reveal_to(40), 9);
+        assert_eq!(window.reveal(REVEAL_STEP), 40);
+        assert_eq!(window.reveal(REVEAL_STEP), 0);
+    }
+
+    #[test]
+    fn test_a_long_session_stays_bounded() {
+        let mut timeline = Timeline::default();
+        let mut window = Window::default();
+        window.set_limit(1000);
+        for n in 0..100_000 {
+            let diffs = timeline.push_remote(message(n), None);
+            let change = RowChange::of(&diffs[0]).unwrap();
+            assert_eq!(window.change(change), Some(RowChange::Added(n.min(1000))));
+            window.trim(timeline.entries().len());
+            assert!(window.rows(timeline.entries().len()) <= 1000);
+        }
+        assert_eq!(window.hidden(), 99_000);
+
+        // Scrolling all the way up and back down, reading each row brought
+        // back as the list would.
+        let len = timeline.entries().len();
+        let mut slowest = Duration::ZERO;
+        loop {
+            let started = Instant::now();
+            let count = window.reveal(REVEAL_STEP);
+            let read = (0..count)
+                .map(|row| &timeline.entries()[window.entry(row)].message)
+                .filter(|m| timeline.find(&m.id).is_some())
+                .count();
+            slowest = slowest.max(started.elapsed());
+            assert_eq!(read, count);
+            if count == 0 {
+                break;
+            }
+        }
+        assert_eq!(window.rows(len), 100_000);
+        let started = Instant::now();
+        assert_eq!(window.trim(len), 99_000);
+        slowest = slowest.max(started.elapsed());
+        assert!(slowest < FRAME_BUDGET, "slowest scroll took {:?}", slowest);
+    }
+}
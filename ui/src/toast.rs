@@ -0,0 +1,26 @@
+//! Short notices at the bottom of the window, for failures that have no
+//! better place to be shown.
+
+use crate::AppWindow;
+use slint::{ComponentHandle, SharedString};
+use std::time::Duration;
+
+/// How long a toast stays up.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+
+thread_local! {
+    static TIMER: slint::Timer = slint::Timer::default();
+}
+
+/// Show `text`, replacing any toast already up.
+pub fn show(ui: &AppWindow, text: impl Into<SharedString>) {
+    ui.set_toast_text(text.into());
+    let ui_handle = ui.as_weak();
+    TIMER.with(|timer| {
+        timer.start(slint::TimerMode::SingleShot, TOAST_DURATION, move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_toast_text(SharedString::default());
+            }
+        })
+    });
+}
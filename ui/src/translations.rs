@@ -0,0 +1,243 @@
+//! Messages translated inline, below the original, through the provider set
+//! up in the settings. A message is only sent to it when the user picks
+//! Translate from its menu, or when its room is set to always translate
+//! from a language; our own messages are never translated by themselves.
+//! A failure is shown on the message it was for.
+
+use crate::{admin, history, i18n, MediaState, TranslationData};
+use chat_core::{Message, MessageType};
+use network::config::TranslationConfig;
+use network::translate::{LibreTranslate, Translation, Translator};
+use slint::SharedString;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+enum Entry {
+    Loading,
+    Done(Translation),
+    Failed(SharedString),
+}
+
+#[derive(Default)]
+struct Translations {
+    config: TranslationConfig,
+    /// `None` when no server is set, or its address is wrong.
+    translator: Option<Arc<dyn Translator>>,
+    /// By event id.
+    entries: HashMap<String, Entry>,
+    /// Translations put away, which their room's automation leaves alone.
+    hidden: HashSet<String>,
+}
+
+thread_local! {
+    static TRANSLATIONS: RefCell<Translations> = RefCell::new(Translations::default());
+}
+
+/// Use `config` from now on. A different server or language forgets what
+/// was translated.
+pub fn configure(config: &TranslationConfig) {
+    let changed = TRANSLATIONS.with(|t| {
+        let mut state = t.borrow_mut();
+        let provider_changed = state.config.url != config.url
+            || state.config.api_key != config.api_key
+            || state.config.target != config.target;
+        if provider_changed || state.translator.is_none() {
+            state.translator = match LibreTranslate::new(config) {
+                Ok(translator) => Some(Arc::new(translator)),
+                Err(e) => {
+                    if !config.url.trim().is_empty() {
+                        tracing::warn!("Translation is off: {:#}", e);
+                    }
+                    None
+                }
+            };
+        }
+        if provider_changed {
+            state.entries.clear();
+            state.hidden.clear();
+        }
+        let changed = state.config != *config;
+        state.config = config.clone();
+        changed
+    });
+    if changed {
+        history::redraw();
+    }
+}
+
+/// Whether a translation server is set up.
+pub fn is_available() -> bool {
+    TRANSLATIONS.with(|t| t.borrow().translator.is_some())
+}
+
+/// The language `room_id`'s messages are translated from as they are
+/// shown, if it is set to.
+pub fn auto_from(room_id: &str) -> Option<String> {
+    TRANSLATIONS.with(|t| t.borrow().config.rooms.get(room_id).cloned())
+}
+
+/// What is shown of `message`'s translation. Rows of a room that always
+/// translates ask for one as they are shown, unless `own`.
+pub fn translation_data(message: &Message, auto: bool, own: bool) -> TranslationData {
+    if message.schema != MessageType::Text {
+        return TranslationData::default();
+    }
+    TRANSLATIONS.with(|t| {
+        let state = t.borrow();
+        if state.translator.is_none() {
+            return TranslationData::default();
+        }
+        let mut data = TranslationData {
+            available: true,
+            ..Default::default()
+        };
+        if state.hidden.contains(&message.id) {
+            return data;
+        }
+        match state.entries.get(&message.id) {
+            None => data.auto = auto && !own && !message.id.is_empty(),
+            Some(Entry::Loading) => data.state = MediaState::Loading,
+            Some(Entry::Failed(error)) => {
+                data.state = MediaState::Failed;
+                data.error = error.clone();
+            }
+            Some(Entry::Done(translation)) => {
+                data.state = MediaState::Ready;
+                data.text = SharedString::from(translation.text.as_str());
+                data.source_lang =
+                    SharedString::from(translation.source_lang.as_deref().unwrap_or(""));
+            }
+        }
+        data
+    })
+}
+
+fn refresh(room_id: &str, event_id: &str) {
+    history::for_each_timeline(room_id, |timeline| timeline.refresh(event_id));
+}
+
+/// The Translate menu action: show message `event_id`'s translation, or
+/// put it away if it is shown.
+pub fn toggle(event_id: &str) {
+    let Some(room_id) = history::room_of(event_id) else {
+        return;
+    };
+    let shown = TRANSLATIONS.with(|t| {
+        let mut state = t.borrow_mut();
+        let shown = state.entries.contains_key(event_id) && !state.hidden.contains(event_id);
+        if shown {
+            state.hidden.insert(event_id.to_string());
+        } else {
+            state.hidden.remove(event_id);
+        }
+        shown
+    });
+    if shown {
+        refresh(&room_id, event_id);
+        return;
+    }
+    // Tried again after a failure.
+    TRANSLATIONS.with(|t| {
+        let mut state = t.borrow_mut();
+        if matches!(state.entries.get(event_id), Some(Entry::Failed(_))) {
+            state.entries.remove(event_id);
+        }
+    });
+    start(&room_id, event_id, None);
+}
+
+/// A row of a room that always translates was shown: translate it from
+/// the room's language, if the room is still set to.
+pub fn load(event_id: &str) {
+    let Some(room_id) = history::room_of(event_id) else {
+        return;
+    };
+    let Some(source_lang) = auto_from(&room_id) else {
+        return;
+    };
+    let hidden = TRANSLATIONS.with(|t| t.borrow().hidden.contains(event_id));
+    if !hidden {
+        start(&room_id, event_id, Some(source_lang));
+    }
+}
+
+/// Ask for message `event_id`'s translation unless it is asked for already.
+fn start(room_id: &str, event_id: &str, source_lang: Option<String>) {
+    let Some(message) =
+        history::with_message(room_id, event_id, |timeline| timeline.message(event_id)).flatten()
+    else {
+        return;
+    };
+    let started = TRANSLATIONS.with(|t| {
+        let mut state = t.borrow_mut();
+        if state.entries.contains_key(event_id) {
+            return None;
+        }
+        let translator = state.translator.clone()?;
+        let target = match state.config.target.trim() {
+            "" => i18n::current().code.to_string(),
+            target => target.to_string(),
+        };
+        state.entries.insert(event_id.to_string(), Entry::Loading);
+        Some((translator, target))
+    });
+    refresh(room_id, event_id);
+    let Some((translator, target)) = started else {
+        return;
+    };
+
+    let room_id = room_id.to_string();
+    let event_id = event_id.to_string();
+    tokio::spawn(async move {
+        let result = translator
+            .translate(&message.content, source_lang.as_deref(), &target)
+            .await;
+        slint::invoke_from_event_loop(move || {
+            let entry = match result {
+                Ok(translation) => Entry::Done(translation),
+                Err(e) => {
+                    tracing::warn!("Failed to translate {}: {:#}", event_id, e);
+                    Entry::Failed(admin::error_text(&e, "failed-translate"))
+                }
+            };
+            let current = TRANSLATIONS.with(|t| {
+                let mut state = t.borrow_mut();
+                // Forgotten meanwhile, e.g. on logout or a new server.
+                let current = matches!(state.entries.get(&event_id), Some(Entry::Loading));
+                if current {
+                    state.entries.insert(event_id.clone(), entry);
+                }
+                current
+            });
+            if current {
+                refresh(&room_id, &event_id);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Always translate `room_id`'s messages from `source_lang`, or no longer
+/// when it is empty. The caller saves it.
+pub fn set_auto(room_id: &str, source_lang: &str) {
+    TRANSLATIONS.with(|t| {
+        let rooms = &mut t.borrow_mut().config.rooms;
+        if source_lang.is_empty() {
+            rooms.remove(room_id);
+        } else {
+            rooms.insert(room_id.to_string(), source_lang.to_string());
+        }
+    });
+    let on = !source_lang.is_empty();
+    history::for_each_timeline(room_id, |timeline| timeline.set_auto_translate(on));
+}
+
+/// Forget every translation, e.g. on logout.
+pub fn clear() {
+    TRANSLATIONS.with(|t| {
+        let mut state = t.borrow_mut();
+        state.entries.clear();
+        state.hidden.clear();
+    });
+}
@@ -0,0 +1,365 @@
+//! System tray icon with a status/voice menu. The icon is owned by a
+//! platform thread (a GTK thread on Linux, the UI thread elsewhere) and
+//! is updated by sending it a new `TrayState`.
+
+use chat_core::UserStatus;
+use std::sync::{mpsc, Mutex, OnceLock};
+use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+
+const ICON_SIZE: u32 = 32;
+const BASE_COLOR: [u8; 3] = [0x58, 0x65, 0xf2];
+const MENTION_COLOR: [u8; 3] = [0xed, 0x42, 0x45];
+const VOICE_COLOR: [u8; 3] = [0x3b, 0xa5, 0x5d];
+
+/// What the tray shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrayState {
+    pub status: UserStatus,
+    /// Unread mentions in any unmuted room.
+    pub mentions: bool,
+    pub in_voice: bool,
+    pub muted: bool,
+    pub deafened: bool,
+    /// Quiet hours are keeping notifications silent.
+    pub quiet_hours: bool,
+}
+
+impl Default for TrayState {
+    fn default() -> Self {
+        Self {
+            status: UserStatus::Online,
+            mentions: false,
+            in_voice: false,
+            muted: false,
+            deafened: false,
+            quiet_hours: false,
+        }
+    }
+}
+
+/// A tray menu entry (or icon click) the UI should act on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrayCommand {
+    SetStatus(UserStatus),
+    ToggleMute,
+    ToggleDeafen,
+    Show,
+    Quit,
+}
+
+impl TrayCommand {
+    fn from_id(id: &str) -> Option<Self> {
+        let command = match id {
+            "mute" => Self::ToggleMute,
+            "deafen" => Self::ToggleDeafen,
+            "show" => Self::Show,
+            "quit" => Self::Quit,
+            _ => {
+                let label = id.strip_prefix("status:")?;
+                Self::SetStatus(UserStatus::from_label(label)?)
+            }
+        };
+        Some(command)
+    }
+}
+
+fn status_id(status: UserStatus) -> String {
+    format!("status:{}", status.label())
+}
+
+struct Tray {
+    state: Mutex<TrayState>,
+    tx: mpsc::Sender<TrayState>,
+}
+
+static TRAY: OnceLock<Tray> = OnceLock::new();
+
+/// Create the tray icon. `on_command` runs on whichever thread the platform
+/// delivers menu events on. Returns false if the tray is unavailable.
+pub fn start(on_command: impl Fn(TrayCommand) + Send + Sync + 'static) -> bool {
+    let on_command = std::sync::Arc::new(on_command);
+    let on_menu = on_command.clone();
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+        if let Some(command) = TrayCommand::from_id(event.id.as_ref()) {
+            on_menu(command);
+        }
+    }));
+    TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+        if let TrayIconEvent::Click {
+            button: MouseButton::Left,
+            button_state: MouseButtonState::Up,
+            ..
+        } = event
+        {
+            on_command(TrayCommand::Show);
+        }
+    }));
+
+    let (tx, rx) = mpsc::channel();
+    if !platform::spawn(rx) {
+        return false;
+    }
+    TRAY.get_or_init(|| Tray {
+        state: Mutex::new(TrayState::default()),
+        tx,
+    });
+    true
+}
+
+/// Change the tray state; the icon is only redrawn if something changed.
+pub fn update(f: impl FnOnce(&mut TrayState)) {
+    let Some(tray) = TRAY.get() else {
+        return;
+    };
+    let mut state = tray.state.lock().unwrap();
+    let before = *state;
+    f(&mut state);
+    if *state != before {
+        let _ = tray.tx.send(*state);
+    }
+}
+
+/// The tray's widgets, living on the thread that created them.
+struct Widgets {
+    icon: TrayIcon,
+    statuses: Vec<(UserStatus, CheckMenuItem)>,
+    mute: CheckMenuItem,
+    deafen: CheckMenuItem,
+}
+
+impl Widgets {
+    fn build() -> anyhow::Result<Self> {
+        let statuses: Vec<_> = UserStatus::ALL
+            .into_iter()
+            .map(|status| {
+                let item = CheckMenuItem::with_id(
+                    status_id(status),
+                    status.label(),
+                    true,
+                    status == UserStatus::Online,
+                    None,
+                );
+                (status, item)
+            })
+            .collect();
+        let mute = CheckMenuItem::with_id("mute", "Mute", true, false, None);
+        let deafen = CheckMenuItem::with_id("deafen", "Deafen", true, false, None);
+        let show = MenuItem::with_id("show", "Show GameChat", true, None);
+        let quit = MenuItem::with_id("quit", "Quit", true, None);
+
+        let menu = Menu::new();
+        for (_, item) in &statuses {
+            menu.append(item)?;
+        }
+        menu.append_items(&[
+            &PredefinedMenuItem::separator(),
+            &mute,
+            &deafen,
+            &PredefinedMenuItem::separator(),
+            &show,
+            &quit,
+        ])?;
+
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip(tooltip(&TrayState::default()))
+            .with_icon(icon(&TrayState::default())?)
+            .build()?;
+        Ok(Self {
+            icon,
+            statuses,
+            mute,
+            deafen,
+        })
+    }
+
+    fn apply(&self, state: &TrayState) {
+        for (status, item) in &self.statuses {
+            item.set_checked(*status == state.status);
+        }
+        self.mute.set_checked(state.muted);
+        self.deafen.set_checked(state.deafened);
+        let _ = self.icon.set_tooltip(Some(tooltip(state)));
+        if let Ok(icon) = icon(state) {
+            let _ = self.icon.set_icon(Some(icon));
+        }
+    }
+
+    /// Apply the latest pending state, if any.
+    fn drain(&self, rx: &mpsc::Receiver<TrayState>) {
+        if let Some(state) = rx.try_iter().last() {
+            self.apply(&state);
+        }
+    }
+}
+
+fn tooltip(state: &TrayState) -> String {
+    let mut tooltip = format!("GameChat — {}", state.status.label());
+    if state.in_voice {
+        tooltip.push_str(" · In voice");
+    }
+    if state.quiet_hours {
+        tooltip.push_str(" · Quiet hours");
+    }
+    if state.mentions {
+        tooltip.push_str(" · Unread mentions");
+    }
+    tooltip
+}
+
+fn icon(state: &TrayState) -> Result<Icon, tray_icon::BadIcon> {
+    Icon::from_rgba(icon_rgba(state), ICON_SIZE, ICON_SIZE)
+}
+
+/// The app disc, with a red dot at the bottom right for unread mentions and
+/// a green one at the top right while in voice.
+fn icon_rgba(state: &TrayState) -> Vec<u8> {
+    let size = ICON_SIZE as f32;
+    let mut discs = vec![(size / 2.0, size / 2.0, size / 2.0 - 1.0, BASE_COLOR)];
+    if state.in_voice {
+        discs.push((size - 7.0, 7.0, 6.0, VOICE_COLOR));
+    }
+    if state.mentions {
+        discs.push((size - 7.0, size - 7.0, 6.0, MENTION_COLOR));
+    }
+
+    let mut rgba = vec![0; (ICON_SIZE * ICON_SIZE * 4) as usize];
+    for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+        let x = (i as u32 % ICON_SIZE) as f32 + 0.5;
+        let y = (i as u32 / ICON_SIZE) as f32 + 0.5;
+        // Later discs are drawn over earlier ones.
+        let hit = discs
+            .iter()
+            .rev()
+            .find(|(cx, cy, r, _)| (x - cx).powi(2) + (y - cy).powi(2) <= r * r);
+        if let Some((_, _, _, [r, g, b])) = hit {
+            pixel.copy_from_slice(&[*r, *g, *b, 0xff]);
+        }
+    }
+    rgba
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{TrayState, Widgets};
+    use gtk::glib;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// The tray needs a running GTK main loop on Linux, so it gets a thread
+    /// of its own.
+    pub fn spawn(rx: mpsc::Receiver<TrayState>) -> bool {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Err(e) = gtk::init() {
+                tracing::warn!("Failed to start tray: {}", e);
+                let _ = ready_tx.send(false);
+                return;
+            }
+            let widgets = match Widgets::build() {
+                Ok(widgets) => widgets,
+                Err(e) => {
+                    tracing::warn!("Failed to create tray icon: {}", e);
+                    let _ = ready_tx.send(false);
+                    return;
+                }
+            };
+            let _ = ready_tx.send(true);
+            glib::timeout_add_local(Duration::from_millis(200), move || {
+                widgets.drain(&rx);
+                glib::ControlFlow::Continue
+            });
+            gtk::main();
+        });
+        ready_rx.recv().unwrap_or(false)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::{TrayState, Widgets};
+    use std::cell::RefCell;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    thread_local! {
+        static WIDGETS: RefCell<Option<(Widgets, slint::Timer)>> = const { RefCell::new(None) };
+    }
+
+    /// Elsewhere the tray lives on the UI thread, whose event loop also
+    /// serves it. Must be called from that thread.
+    pub fn spawn(rx: mpsc::Receiver<TrayState>) -> bool {
+        let widgets = match Widgets::build() {
+            Ok(widgets) => widgets,
+            Err(e) => {
+                tracing::warn!("Failed to create tray icon: {}", e);
+                return false;
+            }
+        };
+        let timer = slint::Timer::default();
+        timer.start(
+            slint::TimerMode::Repeated,
+            Duration::from_millis(200),
+            move || {
+                WIDGETS.with(|w| {
+                    if let Some((widgets, _)) = w.borrow().as_ref() {
+                        widgets.drain(&rx);
+                    }
+                })
+            },
+        );
+        WIDGETS.with(|w| *w.borrow_mut() = Some((widgets, timer)));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(rgba: &[u8], x: u32, y: u32) -> &[u8] {
+        let i = ((y * ICON_SIZE + x) * 4) as usize;
+        &rgba[i..i + 4]
+    }
+
+    #[test]
+    fn test_menu_ids_round_trip() {
+        for status in UserStatus::ALL {
+            assert_eq!(
+                TrayCommand::from_id(&status_id(status)),
+                Some(TrayCommand::SetStatus(status))
+            );
+        }
+        assert_eq!(TrayCommand::from_id("quit"), Some(TrayCommand::Quit));
+        assert_eq!(TrayCommand::from_id("status:Away"), None);
+    }
+
+    #[test]
+    fn test_icon_marks_mentions_and_voice() {
+        let plain = icon_rgba(&TrayState::default());
+        assert_eq!(plain.len(), (ICON_SIZE * ICON_SIZE * 4) as usize);
+        assert_eq!(pixel(&plain, 0, 0)[3], 0);
+        assert_eq!(pixel(&plain, 16, 16), &[0x58, 0x65, 0xf2, 0xff]);
+
+        let busy = icon_rgba(&TrayState {
+            mentions: true,
+            in_voice: true,
+            ..Default::default()
+        });
+        assert_eq!(pixel(&busy, 25, 25), &[0xed, 0x42, 0x45, 0xff]);
+        assert_eq!(pixel(&busy, 25, 6), &[0x3b, 0xa5, 0x5d, 0xff]);
+        assert_eq!(pixel(&busy, 16, 16), pixel(&plain, 16, 16));
+    }
+
+    #[test]
+    fn test_tooltip_says_why_it_is_quiet() {
+        assert_eq!(tooltip(&TrayState::default()), "GameChat — Online");
+        let quiet = TrayState {
+            status: UserStatus::DoNotDisturb,
+            quiet_hours: true,
+            ..Default::default()
+        };
+        assert_eq!(tooltip(&quiet), "GameChat — Do Not Disturb · Quiet hours");
+    }
+}
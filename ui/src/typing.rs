@@ -0,0 +1,185 @@
+//! Typing notices: when to tell the server we are typing in the composer,
+//! and the "X is typing…" line shown for others.
+
+use crate::i18n;
+use network::events::TypingUser;
+use std::time::{Duration, Instant};
+
+/// Stop typing after this long without input.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The server forgets a typing notice after a few seconds; keep typing
+/// users announced by sending it again this often.
+const RESEND_AFTER: Duration = Duration::from_secs(3);
+
+/// A typing notice to send.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    pub room_id: String,
+    pub typing: bool,
+}
+
+/// Decides when composer input becomes a typing notice. At most one room
+/// is announced at a time: the one the composer belongs to.
+#[derive(Debug, Default)]
+pub struct TypingEmitter {
+    room_id: Option<String>,
+    last_input: Option<Instant>,
+    /// When we last told the server we are typing; `None` when we are not.
+    last_sent: Option<Instant>,
+}
+
+impl TypingEmitter {
+    pub fn is_typing(&self) -> bool {
+        self.last_sent.is_some()
+    }
+
+    fn stop(&mut self) -> Option<Notice> {
+        self.last_input = None;
+        let room_id = self.room_id.clone()?;
+        self.last_sent.take().map(|_| Notice {
+            room_id,
+            typing: false,
+        })
+    }
+
+    /// The composer now belongs to `room_id`; stops typing in the old room.
+    pub fn switch_room(&mut self, room_id: Option<&str>) -> Option<Notice> {
+        if self.room_id.as_deref() == room_id {
+            return None;
+        }
+        let notice = self.stop();
+        self.room_id = room_id.map(str::to_string);
+        notice
+    }
+
+    /// The composer text for `room_id` changed, to something or to nothing.
+    pub fn input(&mut self, room_id: &str, has_text: bool, now: Instant) -> Vec<Notice> {
+        let mut notices: Vec<Notice> = self.switch_room(Some(room_id)).into_iter().collect();
+        if !has_text {
+            notices.extend(self.stop());
+            return notices;
+        }
+        self.last_input = Some(now);
+        let due = self
+            .last_sent
+            .is_none_or(|sent| now.saturating_duration_since(sent) >= RESEND_AFTER);
+        if due {
+            self.last_sent = Some(now);
+            notices.push(Notice {
+                room_id: room_id.to_string(),
+                typing: true,
+            });
+        }
+        notices
+    }
+
+    /// The message was sent.
+    pub fn sent(&mut self) -> Option<Notice> {
+        self.stop()
+    }
+
+    /// Stop typing if there was no input for `IDLE_TIMEOUT`.
+    pub fn idle(&mut self, now: Instant) -> Option<Notice> {
+        let last_input = self.last_input?;
+        if now.saturating_duration_since(last_input) < IDLE_TIMEOUT {
+            return None;
+        }
+        self.stop()
+    }
+}
+
+/// The line shown under the timeline for `users` typing, leaving out
+/// `own_user_id`. Empty when nobody else is typing.
+pub fn typing_line(users: &[TypingUser], own_user_id: &str) -> String {
+    let names: Vec<&str> = users
+        .iter()
+        .filter(|u| u.user_id != own_user_id)
+        .map(TypingUser::name)
+        .collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [one] => i18n::tr_args("typing-one", [("name", (*one).into())]),
+        [first, second] => i18n::tr_args(
+            "typing-two",
+            [("first", (*first).into()), ("second", (*second).into())],
+        ),
+        _ => i18n::tr_args("typing-many", [("count", names.len().into())]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWN: &str = "@me:example.org";
+
+    fn user(name: &str) -> TypingUser {
+        TypingUser {
+            user_id: format!("@{}:example.org", name.to_lowercase()),
+            display_name: Some(name.to_string()),
+        }
+    }
+
+    fn notice(room_id: &str, typing: bool) -> Notice {
+        Notice {
+            room_id: room_id.to_string(),
+            typing,
+        }
+    }
+
+    #[test]
+    fn test_typing_line_leaves_us_out() {
+        assert_eq!(typing_line(&[], OWN), "");
+        assert_eq!(typing_line(&[user("Me")], OWN), "");
+        assert_eq!(
+            typing_line(&[user("Amy"), user("Me")], OWN),
+            "Amy is typing…"
+        );
+        assert_eq!(
+            typing_line(&[user("Amy"), user("Bob")], OWN),
+            "Amy and Bob are typing…"
+        );
+        assert_eq!(
+            typing_line(&[user("Amy"), user("Bob"), user("Cid")], OWN),
+            "3 people are typing…"
+        );
+    }
+
+    #[test]
+    fn test_emitter_debounces_and_stops() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut emitter = TypingEmitter::default();
+
+        assert_eq!(emitter.input("!a", true, at(0)), [notice("!a", true)]);
+        assert!(emitter.input("!a", true, at(1)).is_empty());
+        assert_eq!(emitter.input("!a", true, at(3)), [notice("!a", true)]);
+        assert_eq!(emitter.idle(at(7)), None);
+        assert_eq!(emitter.idle(at(8)), Some(notice("!a", false)));
+        assert!(!emitter.is_typing());
+        assert_eq!(emitter.idle(at(9)), None);
+
+        emitter.input("!a", true, at(10));
+        assert_eq!(emitter.sent(), Some(notice("!a", false)));
+        assert_eq!(emitter.sent(), None);
+        assert!(emitter.input("!a", false, at(11)).is_empty());
+
+        emitter.input("!a", true, at(12));
+        assert_eq!(emitter.input("!a", false, at(13)), [notice("!a", false)]);
+    }
+
+    #[test]
+    fn test_switching_rooms_stops_the_old_one() {
+        let now = Instant::now();
+        let mut emitter = TypingEmitter::default();
+        emitter.input("!a", true, now);
+        assert_eq!(
+            emitter.input("!b", true, now),
+            [notice("!a", false), notice("!b", true)]
+        );
+        assert_eq!(emitter.switch_room(Some("!b")), None);
+        assert_eq!(emitter.switch_room(Some("!c")), Some(notice("!b", false)));
+        assert_eq!(emitter.switch_room(None), None);
+    }
+}
@@ -0,0 +1,157 @@
+//! Typing notices for the open room: ours are sent as the composer changes,
+//! and everyone else's are shown under the timeline.
+
+use crate::app_state::ClientHandle;
+use crate::typing::{self, Notice, TypingEmitter};
+use crate::{accounts, history, AppWindow};
+use network::events::TypingUser;
+use slint::{ComponentHandle, SharedString};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+/// How often to check whether the user stopped typing.
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+
+thread_local! {
+    static EMITTER: RefCell<TypingEmitter> = RefCell::new(TypingEmitter::default());
+    /// Who is typing, by room id, as last reported by the server.
+    static TYPING: RefCell<HashMap<String, Vec<TypingUser>>> = RefCell::new(HashMap::new());
+    static IDLE_TIMER: slint::Timer = slint::Timer::default();
+}
+
+fn send(client: &ClientHandle, notices: impl IntoIterator<Item = Notice>) {
+    for notice in notices {
+        let reply = client.set_typing(&notice.room_id, notice.typing);
+        tokio::spawn(async move {
+            if let Err(e) = reply.await {
+                tracing::warn!("Failed to send typing notice: {}", e);
+            }
+        });
+    }
+}
+
+/// Show who is typing in the open room.
+fn render(ui: &AppWindow) {
+    let text = history::current_room()
+        .and_then(|room_id| {
+            TYPING.with(|t| {
+                t.borrow()
+                    .get(&room_id)
+                    .map(|users| typing::typing_line(users, &ui.get_current_user_id()))
+            })
+        })
+        .unwrap_or_default();
+    ui.set_typing_text(SharedString::from(text));
+}
+
+/// The composer text changed.
+pub fn composer_changed(client: &ClientHandle, text: &str) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    let has_text = !text.trim().is_empty();
+    let (notices, typing) = EMITTER.with(|e| {
+        let mut emitter = e.borrow_mut();
+        let notices = emitter.input(&room_id, has_text, Instant::now());
+        (notices, emitter.is_typing())
+    });
+    send(client, notices);
+    if typing {
+        watch_idle(client);
+    }
+}
+
+fn watch_idle(client: &ClientHandle) {
+    let client = client.clone();
+    IDLE_TIMER.with(|timer| {
+        if timer.running() {
+            return;
+        }
+        timer.start(slint::TimerMode::Repeated, IDLE_CHECK, move || {
+            let (notice, typing) = EMITTER.with(|e| {
+                let mut emitter = e.borrow_mut();
+                (emitter.idle(Instant::now()), emitter.is_typing())
+            });
+            send(&client, notice);
+            if !typing {
+                IDLE_TIMER.with(slint::Timer::stop);
+            }
+        });
+    });
+}
+
+/// Our message went out; we are no longer typing.
+pub fn message_sent(client: &ClientHandle) {
+    let notice = EMITTER.with(|e| e.borrow_mut().sent());
+    send(client, notice);
+}
+
+/// The room just opened; stop typing in the one left.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    let notice = EMITTER.with(|e| e.borrow_mut().switch_room(Some(room_id)));
+    send(client, notice);
+    render(ui);
+}
+
+/// No room is open any more; stop typing in the one left.
+pub fn leave_room(ui: &AppWindow, client: &ClientHandle) {
+    let notice = EMITTER.with(|e| e.borrow_mut().switch_room(None));
+    send(client, notice);
+    ui.set_typing_text(SharedString::default());
+}
+
+/// Follow the logged-in client's typing notices until its stream closes on
+/// logout.
+pub fn start(ui: &AppWindow, client: &ClientHandle) {
+    let subscription = client.typing();
+    let ui_handle = ui.as_weak();
+    let shown = accounts::shown();
+
+    tokio::spawn(async move {
+        let mut events = match subscription.await {
+            Ok(events) => events,
+            Err(e) => {
+                tracing::warn!("Failed to subscribe to typing notices: {}", e);
+                return;
+            }
+        };
+        loop {
+            let event = match shown.run(events.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            };
+            let ui_handle = ui_handle.clone();
+            let shown = shown.clone();
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                if shown.is_cancelled() {
+                    return;
+                }
+                TYPING.with(|t| {
+                    let mut typing = t.borrow_mut();
+                    if event.users.is_empty() {
+                        typing.remove(&event.room_id);
+                    } else {
+                        typing.insert(event.room_id, event.users);
+                    }
+                });
+                render(&ui);
+            })
+            .ok();
+        }
+    });
+}
+
+/// Stop typing and forget who else is, e.g. on logout.
+pub fn clear(ui: &AppWindow, client: &ClientHandle) {
+    let notice = EMITTER.with(|e| std::mem::take(&mut *e.borrow_mut()).sent());
+    send(client, notice);
+    TYPING.with(|t| t.borrow_mut().clear());
+    IDLE_TIMER.with(slint::Timer::stop);
+    ui.set_typing_text(SharedString::default());
+}
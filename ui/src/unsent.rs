@@ -0,0 +1,284 @@
+//! Messages written but not yet delivered are kept on disk until they are,
+//! so that what a crash left behind is offered again on the next sign-in:
+//! grouped by room, to send through the queue as if just written, under
+//! the same transaction ids so that none arrives twice, or to discard.
+//! Those older than `unsent_max_age_hours` start out unticked. Attachments
+//! are not kept; under `Retention::SessionOnly` nothing is. Messages
+//! scheduled for later are kept here as well, but left to `scheduled`.
+
+use crate::app_state::ClientHandle;
+use crate::{quick_switcher, rooms, AppWindow, UnsentRowData};
+use chat_core::outbox::{self, Unsent};
+use chat_core::{time, Message};
+use network::config::Retention;
+use network::outbox::OutboxStore;
+use slint::{Model, SharedString, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Outbox {
+    user_id: String,
+    /// Written and not yet delivered, oldest first, and scheduled.
+    waiting: Vec<Unsent>,
+    /// Whether `waiting` is saved for the next launch.
+    on_disk: bool,
+    /// What the dialog offers, by row: a message and whether it is ticked,
+    /// or `None` for a room's heading.
+    offered: Vec<Option<(Unsent, bool)>>,
+}
+
+thread_local! {
+    static OUTBOX: RefCell<Outbox> = RefCell::new(Outbox::default());
+}
+
+fn save() {
+    let saved = OUTBOX.with(|o| {
+        let outbox = o.borrow();
+        (outbox.on_disk && !outbox.user_id.is_empty())
+            .then(|| (outbox.user_id.clone(), outbox.waiting.clone()))
+    });
+    if let Some((user_id, waiting)) = saved {
+        if let Err(e) = OutboxStore::save(&user_id, &waiting) {
+            tracing::warn!("Failed to save unsent messages: {:#}", e);
+        }
+    }
+}
+
+/// Write what is waiting once more as the app quits, in case a save along
+/// the way failed.
+pub fn flush() {
+    save();
+}
+
+/// As `user_id` signs in, offer what they left unsent.
+pub fn load(ui: &AppWindow, user_id: &str, retention: Retention, max_age_hours: u64) {
+    let waiting = if retention.on_disk() {
+        OutboxStore::load(user_id)
+    } else {
+        Vec::new()
+    };
+    let now = time::now_ms();
+    let mut offered = Vec::new();
+    let mut rows = Vec::new();
+    let unscheduled: Vec<Unsent> = waiting
+        .iter()
+        .filter(|m| m.send_at.is_none())
+        .cloned()
+        .collect();
+    for group in outbox::by_room(&unscheduled) {
+        let room = if group.room_name.is_empty() {
+            group.room_id
+        } else {
+            group.room_name
+        };
+        offered.push(None);
+        rows.push(UnsentRowData {
+            room: SharedString::from(room),
+            ..Default::default()
+        });
+        for message in group.messages {
+            let checked = !message.is_stale(now, max_age_hours);
+            offered.push(Some((message.clone(), checked)));
+            rows.push(UnsentRowData {
+                room: SharedString::default(),
+                body: SharedString::from(message.body.as_str()),
+                written: SharedString::from(time::format_timestamp(message.timestamp)),
+                checked,
+            });
+        }
+    }
+    let show = !rows.is_empty();
+    OUTBOX.with(|o| {
+        *o.borrow_mut() = Outbox {
+            user_id: user_id.to_string(),
+            waiting,
+            on_disk: retention.on_disk(),
+            offered,
+        }
+    });
+    ui.set_unsent_rows(Rc::new(VecModel::from(rows)).into());
+    show_checked_count(ui);
+    ui.set_show_unsent(show);
+}
+
+/// The retention setting changed: going to `SessionOnly` deletes what was
+/// saved, and coming back saves what is waiting.
+pub fn set_retention(retention: Retention) {
+    let user_id = OUTBOX.with(|o| {
+        let mut outbox = o.borrow_mut();
+        outbox.on_disk = retention.on_disk();
+        outbox.user_id.clone()
+    });
+    if user_id.is_empty() {
+        return;
+    }
+    if retention.on_disk() {
+        save();
+    } else {
+        forget(&user_id);
+    }
+}
+
+/// `message` was written to `room_id` and is on its way as `txn_id`.
+pub fn written(room_id: &str, txn_id: &str, message: &Message) {
+    let unsent = Unsent {
+        room_id: room_id.to_string(),
+        room_name: quick_switcher::room_name(room_id).unwrap_or_default(),
+        txn_id: txn_id.to_string(),
+        body: message.content.clone(),
+        mentions: message.mentions.clone(),
+        reply_to: message.reply_to.clone(),
+        timestamp: message.timestamp,
+        send_at: None,
+    };
+    OUTBOX.with(|o| o.borrow_mut().waiting.push(unsent));
+    save();
+}
+
+/// Keep `unsent` until its `send_at`, across launches.
+pub fn schedule(unsent: Unsent) {
+    OUTBOX.with(|o| o.borrow_mut().waiting.push(unsent));
+    save();
+}
+
+/// The messages scheduled in `room_id`, soonest first.
+pub fn scheduled(room_id: &str) -> Vec<Unsent> {
+    let mut scheduled: Vec<Unsent> = OUTBOX.with(|o| {
+        o.borrow()
+            .waiting
+            .iter()
+            .filter(|m| m.room_id == room_id && m.send_at.is_some())
+            .cloned()
+            .collect()
+    });
+    scheduled.sort_by_key(|m| m.send_at);
+    scheduled
+}
+
+/// Take the scheduled messages due by `now` off the schedule; from here on
+/// they wait like any other. They are given back as they were scheduled.
+pub fn take_due(now: u64) -> Vec<Unsent> {
+    let due: Vec<Unsent> = OUTBOX.with(|o| {
+        let mut outbox = o.borrow_mut();
+        let mut due = Vec::new();
+        for message in &mut outbox.waiting {
+            if message.send_at.is_some() && !message.is_scheduled_after(now) {
+                due.push(message.clone());
+                message.send_at = None;
+            }
+        }
+        due
+    });
+    if !due.is_empty() {
+        save();
+    }
+    due
+}
+
+/// Take scheduled `txn_id` off the schedule without sending it.
+pub fn unschedule(txn_id: &str) -> Option<Unsent> {
+    let removed = OUTBOX.with(|o| {
+        let waiting = &mut o.borrow_mut().waiting;
+        let index = waiting
+            .iter()
+            .position(|m| m.txn_id == txn_id && m.send_at.is_some())?;
+        Some(waiting.remove(index))
+    });
+    if removed.is_some() {
+        save();
+    }
+    removed
+}
+
+/// `txn_id` was delivered, or discarded.
+pub fn done(txn_id: &str) {
+    let removed = OUTBOX.with(|o| {
+        let waiting = &mut o.borrow_mut().waiting;
+        let before = waiting.len();
+        waiting.retain(|m| m.txn_id != txn_id);
+        waiting.len() != before
+    });
+    if removed {
+        save();
+    }
+}
+
+fn show_checked_count(ui: &AppWindow) {
+    let count = OUTBOX.with(|o| {
+        let outbox = o.borrow();
+        outbox
+            .offered
+            .iter()
+            .filter(|m| matches!(m, Some((_, true))))
+            .count()
+    });
+    ui.set_unsent_checked(count as i32);
+}
+
+/// Row `row` of the dialog was ticked or unticked.
+pub fn toggle(ui: &AppWindow, row: usize, checked: bool) {
+    let toggled = OUTBOX.with(|o| match o.borrow_mut().offered.get_mut(row) {
+        Some(Some((_, ticked))) => {
+            *ticked = checked;
+            true
+        }
+        _ => false,
+    });
+    if !toggled {
+        return;
+    }
+    let rows = ui.get_unsent_rows();
+    if let Some(mut data) = rows.row_data(row) {
+        data.checked = checked;
+        rows.set_row_data(row, data);
+    }
+    show_checked_count(ui);
+}
+
+/// Take what the dialog offers out of it: the messages, and whether each
+/// is ticked.
+fn take_offered(ui: &AppWindow) -> Vec<(Unsent, bool)> {
+    ui.set_show_unsent(false);
+    ui.set_unsent_rows(Rc::new(VecModel::<UnsentRowData>::default()).into());
+    let offered = OUTBOX.with(|o| std::mem::take(&mut o.borrow_mut().offered));
+    offered.into_iter().flatten().collect()
+}
+
+/// Send every message offered, or with `all` false only those ticked; the
+/// rest are discarded.
+pub fn send(ui: &AppWindow, client: &ClientHandle, all: bool) {
+    let offered = take_offered(ui);
+    for (message, checked) in &offered {
+        if !all && !checked {
+            done(&message.txn_id);
+        }
+    }
+    for (message, checked) in offered {
+        if all || checked {
+            rooms::send_unsent(ui, client, &message);
+        }
+    }
+}
+
+/// Discard every message offered.
+pub fn discard_all(ui: &AppWindow) {
+    for (message, _) in take_offered(ui) {
+        done(&message.txn_id);
+    }
+}
+
+/// Put away what was offered, e.g. as another account is shown. The
+/// messages stay on disk for the next sign-in.
+pub fn clear(ui: &AppWindow) {
+    OUTBOX.with(|o| *o.borrow_mut() = Outbox::default());
+    ui.set_show_unsent(false);
+    ui.set_unsent_rows(Rc::new(VecModel::<UnsentRowData>::default()).into());
+}
+
+/// Delete what `user_id` left unsent, as they log out.
+pub fn forget(user_id: &str) {
+    if let Err(e) = OutboxStore::delete(user_id) {
+        tracing::warn!("Failed to delete unsent messages: {:#}", e);
+    }
+}
@@ -0,0 +1,216 @@
+//! Files waiting to be uploaded: dropped on the window or pasted into the
+//! composer, checked against the server's size limit, and sent together once
+//! the user confirms. Images are sent compressed unless the user asks for
+//! the original.
+
+use chat_core::format_size;
+use network::media::UploadProgress;
+use std::path::{Path, PathBuf};
+
+/// A file picked for upload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingFile {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    /// Written by us, from a pasted image; deleted once sent or cancelled.
+    pub temporary: bool,
+    /// The size of the image compressed, once that is done; `None` for
+    /// files that aren't compressed.
+    pub compressed_size: Option<u64>,
+    /// Send the image as it is, though it was compressed.
+    pub send_original: bool,
+}
+
+impl PendingFile {
+    pub fn new(path: PathBuf, name: String, size: u64, temporary: bool) -> Self {
+        Self {
+            path,
+            name,
+            size,
+            temporary,
+            compressed_size: None,
+            send_original: false,
+        }
+    }
+
+    /// Whether it will be sent compressed.
+    pub fn compressed(&self) -> bool {
+        self.compressed_size.is_some() && !self.send_original
+    }
+
+    /// How many bytes will be sent.
+    pub fn upload_size(&self) -> u64 {
+        match self.compressed_size {
+            Some(size) if !self.send_original => size,
+            _ => self.size,
+        }
+    }
+
+    /// The size to show, e.g. "4.2 MB → 480 KB" for a compressed image.
+    pub fn size_label(&self) -> String {
+        if self.compressed() {
+            format!(
+                "{} → {}",
+                format_size(self.size),
+                format_size(self.upload_size())
+            )
+        } else {
+            format_size(self.size)
+        }
+    }
+
+    /// Why the file can't be sent, given the server's upload `limit`.
+    pub fn problem(&self, limit: Option<u64>) -> Option<String> {
+        if self.size == 0 {
+            return Some("This file is empty.".to_string());
+        }
+        match limit {
+            Some(limit) if self.upload_size() > limit => Some(format!(
+                "Too large: the server accepts up to {}.",
+                format_size(limit)
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// The files of one upload prompt.
+#[derive(Debug, Default)]
+pub struct Batch {
+    files: Vec<PendingFile>,
+    /// The server's upload limit, once known.
+    limit: Option<u64>,
+}
+
+impl Batch {
+    pub fn files(&self) -> &[PendingFile] {
+        &self.files
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = Some(limit);
+    }
+
+    /// Add `file`, unless the same path is in the batch already; returns
+    /// whether it was added.
+    pub fn add(&mut self, file: PendingFile) -> bool {
+        if self.files.iter().any(|f| f.path == file.path) {
+            return false;
+        }
+        self.files.push(file);
+        true
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<PendingFile> {
+        (index < self.files.len()).then(|| self.files.remove(index))
+    }
+
+    /// Record that the image at `path` compresses to `size` bytes; returns
+    /// false if it has left the batch.
+    pub fn set_compressed(&mut self, path: &Path, size: u64) -> bool {
+        match self.files.iter_mut().find(|f| f.path == path) {
+            Some(file) => {
+                file.compressed_size = Some(size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Switch file `index` between being sent compressed and as it is.
+    pub fn toggle_original(&mut self, index: usize) {
+        if let Some(file) = self.files.get_mut(index) {
+            file.send_original = !file.send_original;
+        }
+    }
+
+    /// Every file can be sent: none is empty or over the limit.
+    pub fn sendable(&self) -> bool {
+        !self.files.is_empty() && self.files.iter().all(|f| f.problem(self.limit).is_none())
+    }
+
+    /// Empty the batch, returning its files.
+    pub fn take(&mut self) -> Vec<PendingFile> {
+        std::mem::take(&mut self.files)
+    }
+}
+
+/// How far along an upload is, from 0 to 1.
+pub fn fraction(progress: UploadProgress) -> f32 {
+    if progress.total == 0 {
+        return 0.0;
+    }
+    (progress.sent as f64 / progress.total as f64).min(1.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> PendingFile {
+        PendingFile::new(PathBuf::from(path), path.to_string(), size, false)
+    }
+
+    #[test]
+    fn test_oversized_and_empty_files_are_rejected() {
+        assert_eq!(file("a", 10).problem(None), None);
+        assert_eq!(file("a", 10).problem(Some(10)), None);
+        assert_eq!(
+            file("a", 11).problem(Some(10)).as_deref(),
+            Some("Too large: the server accepts up to 10 B.")
+        );
+        assert!(file("a", 0).problem(None).is_some());
+    }
+
+    #[test]
+    fn test_batch_collects_files_once() {
+        let mut batch = Batch::default();
+        assert!(!batch.sendable());
+        assert!(batch.add(file("a", 10)));
+        assert!(batch.add(file("b", 20)));
+        assert!(!batch.add(file("a", 10)));
+        assert!(batch.sendable());
+
+        batch.set_limit(15);
+        assert!(!batch.sendable());
+        assert_eq!(batch.remove(1), Some(file("b", 20)));
+        assert_eq!(batch.remove(1), None);
+        assert!(batch.sendable());
+
+        assert_eq!(batch.take(), [file("a", 10)]);
+        assert!(batch.files().is_empty());
+        assert_eq!(batch.limit(), Some(15));
+    }
+
+    #[test]
+    fn test_compressed_images_show_both_sizes() {
+        let mut batch = Batch::default();
+        batch.add(file("a.jpg", 4_404_019));
+        batch.set_limit(1_000_000);
+        assert!(!batch.sendable());
+        assert_eq!(batch.files()[0].size_label(), "4.2 MB");
+
+        assert!(batch.set_compressed(Path::new("a.jpg"), 491_520));
+        assert!(!batch.set_compressed(Path::new("b.jpg"), 1));
+        assert_eq!(batch.files()[0].size_label(), "4.2 MB → 480.0 KB");
+        assert!(batch.sendable());
+
+        batch.toggle_original(0);
+        assert_eq!(batch.files()[0].upload_size(), 4_404_019);
+        assert!(!batch.files()[0].compressed());
+        assert!(!batch.sendable());
+    }
+
+    #[test]
+    fn test_progress_fraction() {
+        let progress = |sent, total| UploadProgress { sent, total };
+        assert_eq!(fraction(progress(0, 0)), 0.0);
+        assert_eq!(fraction(progress(50, 200)), 0.25);
+        assert_eq!(fraction(progress(300, 200)), 1.0);
+    }
+}
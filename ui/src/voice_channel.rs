@@ -0,0 +1,466 @@
+//! The voice channel of the open room: who is in it, who is speaking, and
+//! joining or leaving it. Membership comes from room state, so people
+//! already in a channel show up without us joining it.
+
+use crate::app_state::ClientHandle;
+use crate::{history, local_echo, overlay, tray, AppWindow, VoiceUserData};
+use chat_core::{time, Message, MessageType, Notice};
+use network::events::VoiceMember;
+use network::signaling::MEMBERSHIP_TTL_MS;
+use network::voice::{VoiceEvent, VoiceManager};
+use slint::{ComponentHandle, SharedString, VecModel};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Re-announce well before our membership lapses.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(MEMBERSHIP_TTL_MS / 2);
+/// How often the latency estimate is refreshed during a call.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A room's voice channel: its id and the name it is shown under.
+#[derive(Clone)]
+struct Channel {
+    room_id: String,
+    name: String,
+}
+
+#[derive(Default)]
+struct Channels {
+    manager: Option<Arc<VoiceManager>>,
+    /// The open room, whose channel is shown while we are not in a call.
+    viewed: Option<Channel>,
+    /// The channel we are in.
+    joined: Option<Channel>,
+    /// Members of every channel we have heard about, by room id.
+    members: HashMap<String, Vec<VoiceMember>>,
+    speaking: HashSet<String>,
+    /// Re-announces our membership while joined.
+    announce: Option<slint::Timer>,
+    /// Announces it at once, as when our address changes.
+    announce_now: Option<Rc<dyn Fn()>>,
+    /// Refreshes the latency estimate while joined.
+    stats: Option<slint::Timer>,
+}
+
+impl Channels {
+    /// The channel shown in the sidebar.
+    fn shown(&self) -> Option<&Channel> {
+        self.joined.as_ref().or(self.viewed.as_ref())
+    }
+
+    /// Hand the joined channel's other members to the voice manager.
+    fn connect_peers(&self, local_user: &str) {
+        let (Some(manager), Some(joined)) = (&self.manager, &self.joined) else {
+            return;
+        };
+        let peers = self
+            .members
+            .get(&joined.room_id)
+            .into_iter()
+            .flatten()
+            .filter(|m| m.user_id != local_user)
+            .map(|m| {
+                let addrs = m.candidates.iter().filter_map(|c| c.parse().ok());
+                (m.user_id.clone(), addrs.collect::<Vec<SocketAddr>>())
+            })
+            .collect();
+        manager.set_peers(peers);
+    }
+}
+
+thread_local! {
+    static CHANNELS: RefCell<Channels> = RefCell::new(Channels::default());
+}
+
+/// Show the speaking indicators reported by `manager` until the app exits.
+pub fn start(ui: &AppWindow, manager: Arc<VoiceManager>) {
+    let mut events = manager.subscribe();
+    CHANNELS.with(|c| c.borrow_mut().manager = Some(manager));
+
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            let ui_handle = ui_handle.clone();
+            slint::invoke_from_event_loop(move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                match event {
+                    VoiceEvent::Speaking { user_id, speaking } => {
+                        CHANNELS.with(|c| {
+                            let mut channels = c.borrow_mut();
+                            if speaking {
+                                channels.speaking.insert(user_id);
+                            } else {
+                                channels.speaking.remove(&user_id);
+                            }
+                        });
+                        refresh(&ui);
+                    }
+                    // Peers find us again through our announced addresses,
+                    // so announce the new ones now rather than at the next
+                    // refresh, and again once through in case the first went
+                    // out before the move.
+                    VoiceEvent::Reconnecting | VoiceEvent::Reconnected => {
+                        ui.set_voice_reconnecting(event == VoiceEvent::Reconnecting);
+                        let announce = CHANNELS.with(|c| c.borrow().announce_now.clone());
+                        if let Some(announce) = announce {
+                            announce();
+                        }
+                    }
+                    VoiceEvent::OverlayKey => overlay::toggle(&ui),
+                }
+            })
+            .ok();
+        }
+    });
+}
+
+/// Rebuild the sidebar's voice section from the shown channel, and the
+/// overlay with it.
+fn refresh(ui: &AppWindow) {
+    let (room_id, name, users) = CHANNELS.with(|c| {
+        let channels = c.borrow();
+        let Some(shown) = channels.shown() else {
+            return (None, String::new(), Vec::new());
+        };
+        let users = channels
+            .members
+            .get(&shown.room_id)
+            .into_iter()
+            .flatten()
+            .map(|m| VoiceUserData {
+                name: SharedString::from(m.name()),
+                speaking: channels.speaking.contains(&m.user_id),
+            })
+            .collect();
+        (Some(shown.room_id.clone()), shown.name.clone(), users)
+    });
+    ui.set_voice_channel_name(SharedString::from(name));
+    ui.set_voice_users(Rc::new(VecModel::from(users)).into());
+    overlay::refresh(ui, room_id.as_deref());
+}
+
+/// Show the voice channel of the room just opened, fetching who is in it.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str, name: &str) {
+    CHANNELS.with(|c| {
+        c.borrow_mut().viewed = Some(Channel {
+            room_id: room_id.to_string(),
+            name: name.to_string(),
+        })
+    });
+    refresh(ui);
+
+    let reply = client.voice_members(room_id);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let members = match reply.await {
+            Ok(members) => members,
+            Err(e) => {
+                tracing::warn!("Failed to load voice members: {}", e);
+                return;
+            }
+        };
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            CHANNELS.with(|c| {
+                let mut channels = c.borrow_mut();
+                channels.members.insert(room_id, members);
+                channels.connect_peers(ui.get_current_user_id().as_str());
+            });
+            refresh(&ui);
+        })
+        .ok();
+    });
+}
+
+/// A "joined voice" or "left voice" row for `room_id`'s timeline, if it is
+/// cached. Its filter decides whether the row shows.
+fn notice(room_id: &str, member: &VoiceMember, joined: bool) {
+    history::push_remote(room_id, notice_message(member, joined), None);
+}
+
+/// The row saying `member` joined or left voice. Made up here rather than
+/// sent, so it has a local id and is never read up to.
+fn notice_message(member: &VoiceMember, joined: bool) -> Message {
+    Message {
+        id: local_echo::new_notice_id(),
+        sender: member.user_id.clone(),
+        sender_name: member.display_name.clone(),
+        content: format!(
+            "{} {} voice",
+            member.name(),
+            if joined { "joined" } else { "left" }
+        ),
+        schema: MessageType::SystemNotice(Notice::Voice),
+        timestamp: time::now_ms(),
+        reply_to: None,
+        edited: false,
+        reactions: Vec::new(),
+        attachment: None,
+        mentions: Vec::new(),
+        formatted: None,
+        trust: None,
+    }
+}
+
+/// Apply a membership change from sync.
+pub fn member_changed(ui: &AppWindow, room_id: &str, member: VoiceMember) {
+    let change = CHANNELS.with(|c| {
+        let mut channels = c.borrow_mut();
+        let members = channels.members.entry(room_id.to_string()).or_default();
+        let change = match members.iter().position(|m| m.user_id == member.user_id) {
+            Some(i) if member.candidates.is_empty() => Some((members.remove(i), false)),
+            Some(i) => {
+                members[i] = member;
+                None
+            }
+            None if member.candidates.is_empty() => None,
+            None => {
+                members.push(member.clone());
+                Some((member, true))
+            }
+        };
+        if channels
+            .joined
+            .as_ref()
+            .is_some_and(|j| j.room_id == room_id)
+        {
+            channels.connect_peers(ui.get_current_user_id().as_str());
+        }
+        change
+    });
+    if let Some((member, joined)) = change {
+        notice(room_id, &member, joined);
+    }
+    refresh(ui);
+}
+
+/// Join the open room's voice channel, or leave the one we are in.
+pub fn toggle(ui: &AppWindow, client: &ClientHandle, active: bool) {
+    if active {
+        join(ui, client);
+    } else {
+        leave(ui, client);
+    }
+}
+
+fn join(ui: &AppWindow, client: &ClientHandle) {
+    let (manager, channel) = CHANNELS.with(|c| {
+        let channels = c.borrow();
+        (channels.manager.clone(), channels.viewed.clone())
+    });
+    let (Some(manager), Some(channel)) = (manager, channel) else {
+        ui.set_voice_active(false);
+        return;
+    };
+
+    let user_id = ui.get_current_user_id().to_string();
+    manager.set_local_user(&user_id);
+    if let Err(e) = manager.start_audio_loop() {
+        tracing::warn!("Failed to start audio: {}", e);
+        ui.set_voice_active(false);
+        return;
+    }
+    tray::update(|state| state.in_voice = true);
+
+    // The public address found with these goes out with the next
+    // announcement.
+    let servers = client.stun_servers();
+    let manager_clone = manager.clone();
+    tokio::spawn(async move {
+        match servers.await {
+            Ok(servers) => manager_clone.set_stun_servers(servers),
+            Err(e) => tracing::warn!("Failed to get STUN servers: {}", e),
+        }
+    });
+
+    let client_clone = client.clone();
+    let manager_clone = manager.clone();
+    let room_id = channel.room_id.clone();
+    let announce_now: Rc<dyn Fn()> = Rc::new(move || {
+        let reply = client_clone.join_voice(&room_id, manager_clone.local_candidates());
+        tokio::spawn(async move {
+            if let Err(e) = reply.await {
+                tracing::warn!("Failed to refresh voice membership: {}", e);
+            }
+        });
+    });
+    let announce = slint::Timer::default();
+    let refresh_announcement = announce_now.clone();
+    announce.start(slint::TimerMode::Repeated, ANNOUNCE_INTERVAL, move || {
+        refresh_announcement()
+    });
+    let stats = slint::Timer::default();
+    let ui_handle = ui.as_weak();
+    let manager_clone = manager.clone();
+    stats.start(slint::TimerMode::Repeated, STATS_INTERVAL, move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            let latency = manager_clone.latency().map(|l| l.to_string());
+            ui.set_voice_latency(SharedString::from(latency.unwrap_or_default()));
+        }
+    });
+    CHANNELS.with(|c| {
+        let mut channels = c.borrow_mut();
+        channels.joined = Some(channel.clone());
+        channels.announce = Some(announce);
+        channels.announce_now = Some(announce_now);
+        channels.stats = Some(stats);
+        channels.connect_peers(&user_id);
+    });
+    refresh(ui);
+    overlay::joined_voice(ui);
+
+    let reply = client.join_voice(&channel.room_id, manager.local_candidates());
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    tokio::spawn(async move {
+        let Err(e) = reply.await else {
+            return;
+        };
+        tracing::warn!("Failed to join voice: {}", e);
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_voice_active(false);
+                leave(&ui, &client);
+            }
+        })
+        .ok();
+    });
+}
+
+/// The name of the channel we are in, if any.
+pub fn joined_name() -> Option<String> {
+    CHANNELS.with(|c| c.borrow().joined.as_ref().map(|j| j.name.clone()))
+}
+
+/// The room whose voice channel `user_id` is in, of those we have heard
+/// about.
+pub fn room_of(user_id: &str) -> Option<String> {
+    CHANNELS.with(|c| {
+        c.borrow()
+            .members
+            .iter()
+            .find(|(_, members)| members.iter().any(|m| m.user_id == user_id))
+            .map(|(room_id, _)| room_id.clone())
+    })
+}
+
+/// Join `room_id`'s channel to answer a call, leaving the one we are in.
+/// The room has to be the one open.
+pub fn join_call(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    let (joined, viewed) = CHANNELS.with(|c| {
+        let channels = c.borrow();
+        let id = |channel: &Option<Channel>| channel.as_ref().map(|c| c.room_id.clone());
+        (id(&channels.joined), id(&channels.viewed))
+    });
+    if joined.as_deref() == Some(room_id) {
+        return;
+    }
+    if viewed.as_deref() != Some(room_id) {
+        tracing::warn!("Can't join the call: its room is not open");
+        return;
+    }
+    if joined.is_some() {
+        leave(ui, client);
+    }
+    ui.set_voice_active(true);
+    join(ui, client);
+}
+
+/// Hang up on everyone and withdraw our announcement.
+fn leave(ui: &AppWindow, client: &ClientHandle) {
+    let (manager, joined) = CHANNELS.with(|c| {
+        let mut channels = c.borrow_mut();
+        channels.announce = None;
+        channels.announce_now = None;
+        channels.stats = None;
+        (channels.manager.clone(), channels.joined.take())
+    });
+    if let Some(manager) = manager {
+        manager.stop();
+    }
+    ui.set_voice_latency(SharedString::default());
+    ui.set_voice_reconnecting(false);
+    tray::update(|state| state.in_voice = false);
+    refresh(ui);
+
+    let Some(joined) = joined else {
+        return;
+    };
+    let reply = client.leave_voice(&joined.room_id);
+    tokio::spawn(async move {
+        if let Err(e) = reply.await {
+            tracing::warn!("Failed to leave voice: {}", e);
+        }
+    });
+}
+
+/// Leave any call and forget every channel, e.g. on logout.
+pub fn clear(ui: &AppWindow, client: &ClientHandle) {
+    ui.set_voice_active(false);
+    leave(ui, client);
+    CHANNELS.with(|c| {
+        let mut channels = c.borrow_mut();
+        channels.viewed = None;
+        channels.members.clear();
+        channels.speaking.clear();
+    });
+    refresh(ui);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_echo::Timeline;
+    use network::timeline::TimelineFilter;
+
+    #[test]
+    fn test_filter_hides_and_restores_voice_notices() {
+        let mut timeline = Timeline::new(vec![Message {
+            id: "$1".to_string(),
+            sender: "@bob:x".to_string(),
+            content: "anyone up for a game?".to_string(),
+            ..Message::default()
+        }]);
+        let member = VoiceMember {
+            user_id: "@xgamer42:x".to_string(),
+            display_name: Some("xGamer42".to_string()),
+            candidates: Vec::new(),
+        };
+        let joined = notice_message(&member, true);
+        assert_eq!(joined.content, "xGamer42 joined voice");
+        let id = joined.id.clone();
+        timeline.push_remote(joined, None);
+        assert_eq!(timeline.find(&id), Some(1));
+
+        let no_voice = TimelineFilter {
+            voice: false,
+            ..TimelineFilter::default()
+        };
+        timeline.set_filter(no_voice);
+        assert_eq!(timeline.find(&id), None);
+        assert_eq!(timeline.entries().len(), 1);
+        // Held while hidden, so one made meanwhile comes back too.
+        timeline.push_remote(notice_message(&member, false), None);
+        assert_eq!(timeline.entries().len(), 1);
+
+        timeline.set_filter(TimelineFilter::default());
+        assert_eq!(timeline.find(&id), Some(1));
+        assert_eq!(timeline.entries().len(), 3);
+        assert_eq!(timeline.entries()[2].message.content, "xGamer42 left voice");
+    }
+}
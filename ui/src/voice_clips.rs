@@ -0,0 +1,288 @@
+//! Voice clips recorded from the composer. Holding the mic button records
+//! from the chosen input device, during a call or not, with the time and
+//! level shown as it goes; letting go, or reaching the longest clip the
+//! settings allow, leaves the clip above the composer of the room it was
+//! recorded in, to play back, throw away or send as a voice message. Its
+//! file is removed once sent or discarded.
+
+use crate::app_state::ClientHandle;
+use crate::{admin, history, i18n, local_echo, toast, AppWindow};
+use chat_core::time;
+use network::config::ConfigManager;
+use network::voice::VoiceManager;
+use network::voice_clip::RecordedClip;
+use slint::{ComponentHandle, SharedString, Timer, TimerMode};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often the time and level are updated while recording.
+const METER_INTERVAL: Duration = Duration::from_millis(50);
+/// Shorter than this, the button was clicked rather than held.
+const SHORTEST_CLIP: Duration = Duration::from_millis(300);
+
+struct Recorded {
+    clip: RecordedClip,
+    sending: bool,
+}
+
+#[derive(Default)]
+struct Clips {
+    voice: Option<Arc<VoiceManager>>,
+    /// The room a clip is being recorded for.
+    recording: Option<String>,
+    /// Clips waiting to be sent, by room.
+    recorded: HashMap<String, Recorded>,
+    /// The room whose clip is playing back.
+    playing: Option<String>,
+}
+
+thread_local! {
+    static CLIPS: RefCell<Clips> = RefCell::new(Clips::default());
+    static METER: Timer = Timer::default();
+    static PREVIEW: Timer = Timer::default();
+}
+
+/// Record through `voice` from now on.
+pub fn start(voice: Arc<VoiceManager>) {
+    CLIPS.with(|c| c.borrow_mut().voice = Some(voice));
+}
+
+fn voice() -> Option<Arc<VoiceManager>> {
+    CLIPS.with(|c| c.borrow().voice.clone())
+}
+
+/// The mic button went down: record a clip for the open room.
+pub fn start_recording(ui: &AppWindow) {
+    let (Some(voice), Some(room_id)) = (voice(), history::current_room()) else {
+        return;
+    };
+    let busy = CLIPS.with(|c| {
+        let clips = c.borrow();
+        clips.recording.is_some() || clips.recorded.contains_key(&room_id)
+    });
+    if busy {
+        return;
+    }
+    let max = Duration::from_secs(u64::from(ConfigManager::load().audio.max_clip_secs));
+    if let Err(e) = voice.start_clip(max) {
+        tracing::warn!("Failed to record a voice clip: {:#}", e);
+        toast::show(ui, admin::error_text(&e, "failed-record-voice"));
+        return;
+    }
+    CLIPS.with(|c| c.borrow_mut().recording = Some(room_id));
+    ui.set_clip_recording(true);
+    ui.set_clip_level(0.0);
+    ui.set_clip_time(SharedString::from(time::format_length(Duration::ZERO)));
+
+    let ui_handle = ui.as_weak();
+    METER.with(|timer| {
+        timer.start(TimerMode::Repeated, METER_INTERVAL, move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            match voice.clip_status() {
+                Some(status) if status.full => stop_recording(&ui),
+                Some(status) => {
+                    ui.set_clip_level(status.level);
+                    ui.set_clip_time(SharedString::from(time::format_length(status.elapsed)));
+                }
+                // The microphone couldn't be opened.
+                None => {
+                    stop_recording(&ui);
+                    toast::show(&ui, i18n::tr("failed-microphone"));
+                }
+            }
+        });
+    });
+}
+
+/// The mic button went up, or the clip is as long as allowed: keep what
+/// was recorded for its room.
+pub fn stop_recording(ui: &AppWindow) {
+    let Some(room_id) = CLIPS.with(|c| c.borrow_mut().recording.take()) else {
+        return;
+    };
+    METER.with(Timer::stop);
+    ui.set_clip_recording(false);
+    let Some(voice) = voice() else {
+        return;
+    };
+    match voice.finish_clip() {
+        Ok(clip) if clip.duration < SHORTEST_CLIP => {
+            clip.remove();
+            toast::show(ui, i18n::tr("hold-to-record"));
+        }
+        Ok(clip) => CLIPS.with(|c| {
+            let recorded = Recorded {
+                clip,
+                sending: false,
+            };
+            c.borrow_mut().recorded.insert(room_id, recorded);
+        }),
+        Err(e) => tracing::warn!("Failed to record a voice clip: {:#}", e),
+    }
+    show(ui);
+}
+
+/// Play the open room's clip back, or stop it playing.
+pub fn toggle_preview(ui: &AppWindow) {
+    let (Some(voice), Some(room_id)) = (voice(), history::current_room()) else {
+        return;
+    };
+    if stop_preview(&voice) {
+        show(ui);
+        return;
+    }
+    let Some(clip) = CLIPS.with(|c| c.borrow().recorded.get(&room_id).map(|r| r.clip.clone()))
+    else {
+        return;
+    };
+    if let Err(e) = voice.play_clip(&clip) {
+        tracing::warn!("Failed to play the voice clip: {:#}", e);
+        toast::show(ui, admin::error_text(&e, "failed-play-voice"));
+        return;
+    }
+    CLIPS.with(|c| c.borrow_mut().playing = Some(room_id));
+    let ui_handle = ui.as_weak();
+    PREVIEW.with(|timer| {
+        timer.start(TimerMode::SingleShot, clip.duration, move || {
+            CLIPS.with(|c| c.borrow_mut().playing = None);
+            if let Some(ui) = ui_handle.upgrade() {
+                show(&ui);
+            }
+        })
+    });
+    show(ui);
+}
+
+/// Stop the clip playing back, if one is. Returns whether one was.
+fn stop_preview(voice: &VoiceManager) -> bool {
+    if CLIPS.with(|c| c.borrow_mut().playing.take()).is_none() {
+        return false;
+    }
+    voice.stop_clip_preview();
+    PREVIEW.with(Timer::stop);
+    true
+}
+
+/// Throw away the open room's clip.
+pub fn discard(ui: &AppWindow) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    if let Some(voice) = voice() {
+        stop_preview(&voice);
+    }
+    let removed = CLIPS.with(|c| {
+        let mut clips = c.borrow_mut();
+        match clips.recorded.get(&room_id) {
+            Some(recorded) if !recorded.sending => clips.recorded.remove(&room_id),
+            _ => None,
+        }
+    });
+    if let Some(recorded) = removed {
+        recorded.clip.remove();
+    }
+    show(ui);
+}
+
+/// Send the open room's clip as a voice message. It stays, to try again,
+/// if that fails.
+pub fn send(ui: &AppWindow, client: &ClientHandle) {
+    let Some(room_id) = history::current_room() else {
+        return;
+    };
+    if let Some(voice) = voice() {
+        stop_preview(&voice);
+    }
+    let clip = CLIPS.with(|c| {
+        let mut clips = c.borrow_mut();
+        let recorded = clips.recorded.get_mut(&room_id).filter(|r| !r.sending)?;
+        recorded.sending = true;
+        Some(recorded.clip.clone())
+    });
+    let Some(clip) = clip else {
+        return;
+    };
+    show(ui);
+
+    let client = client.clone();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = match tokio::fs::read(&clip.path).await {
+            Ok(data) => {
+                let txn_id = local_echo::new_txn_id();
+                client
+                    .send_voice_message(&room_id, data, clip.duration, &txn_id)
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        slint::invoke_from_event_loop(move || {
+            let sent = result.is_ok();
+            CLIPS.with(|c| {
+                let mut clips = c.borrow_mut();
+                if sent {
+                    clips.recorded.remove(&room_id);
+                } else if let Some(recorded) = clips.recorded.get_mut(&room_id) {
+                    recorded.sending = false;
+                }
+            });
+            if sent {
+                clip.remove();
+            }
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to send the voice message: {:#}", e);
+                toast::show(&ui, admin::error_text(&e, "failed-send-voice"));
+            }
+            show(&ui);
+        })
+        .ok();
+    });
+}
+
+/// Show the clip of the room just opened, if it has one.
+pub fn show_room(ui: &AppWindow) {
+    show(ui);
+}
+
+/// Show the open room's clip above the composer.
+fn show(ui: &AppWindow) {
+    let room_id = history::current_room().unwrap_or_default();
+    CLIPS.with(|c| {
+        let clips = c.borrow();
+        let recorded = clips.recorded.get(&room_id);
+        ui.set_clip_ready(recorded.is_some());
+        ui.set_clip_sending(recorded.is_some_and(|r| r.sending));
+        ui.set_clip_playing(clips.playing.as_deref() == Some(room_id.as_str()));
+        if let Some(recorded) = recorded {
+            ui.set_clip_time(SharedString::from(time::format_length(
+                recorded.clip.duration,
+            )));
+        }
+    });
+}
+
+/// Stop recording and throw every clip away, e.g. on logout.
+pub fn clear(ui: &AppWindow) {
+    METER.with(Timer::stop);
+    let recorded = CLIPS.with(|c| {
+        let mut clips = c.borrow_mut();
+        clips.recording = None;
+        std::mem::take(&mut clips.recorded)
+    });
+    if let Some(voice) = voice() {
+        voice.discard_clip();
+        stop_preview(&voice);
+    }
+    for recorded in recorded.into_values() {
+        recorded.clip.remove();
+    }
+    ui.set_clip_recording(false);
+    show(ui);
+}
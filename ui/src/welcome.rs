@@ -0,0 +1,170 @@
+//! The open room's welcome message: shown over the timeline the first time
+//! the room is opened, and again whenever it changes. Closing it is noted
+//! in account data, as is accepting it; a room that asks for that keeps
+//! the composer off until then, with a way back to the message.
+
+use crate::app_state::ClientHandle;
+use crate::{history, rich_text, timeline, toast, AppWindow, TextBlockData};
+use network::welcome::{Seen, Welcome};
+use slint::{ComponentHandle, VecModel};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Open {
+    room_id: String,
+    welcome: Option<Welcome>,
+    seen: Seen,
+}
+
+thread_local! {
+    static OPEN: RefCell<Open> = RefCell::new(Open::default());
+}
+
+/// Show the open room's welcome as loaded; the panel closes if it has none.
+fn render(ui: &AppWindow) {
+    let Some((blocks, must_accept, held)) = OPEN.with(|o| {
+        let open = o.borrow();
+        let welcome = open.welcome.as_ref()?;
+        let blocks: Vec<TextBlockData> =
+            rich_text::welcome_rows(&welcome.body, welcome.formatted.as_deref())
+                .into_iter()
+                .map(|row| timeline::block_data(row, Rc::new(VecModel::default()).into()))
+                .collect();
+        let must_accept = welcome.must_accept && open.seen != Seen::Accepted;
+        Some((blocks, must_accept, welcome.holds_composer(open.seen)))
+    }) else {
+        ui.set_show_welcome(false);
+        ui.set_welcome_blocks(Rc::new(VecModel::default()).into());
+        ui.set_welcome_must_accept(false);
+        ui.set_welcome_pending(false);
+        return;
+    };
+    ui.set_welcome_blocks(Rc::new(VecModel::from(blocks)).into());
+    ui.set_welcome_must_accept(must_accept);
+    ui.set_welcome_pending(held);
+}
+
+/// Load the welcome of `room_id`, showing it if it is new to us.
+fn load(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    let reply = client.get_welcome(room_id);
+    let room_id = room_id.to_string();
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let result = reply.await;
+        slint::invoke_from_event_loop(move || {
+            let Some(ui) = ui_handle.upgrade() else {
+                return;
+            };
+            if history::current_room().as_deref() != Some(room_id.as_str()) {
+                return;
+            }
+            let (welcome, seen) = match result {
+                Ok(Some((welcome, seen))) => (Some(welcome), seen),
+                Ok(None) => (None, Seen::New),
+                Err(e) => {
+                    tracing::warn!("Failed to load the welcome message: {:#}", e);
+                    return;
+                }
+            };
+            let shows = welcome.as_ref().is_some_and(|w| w.shows(seen));
+            OPEN.with(|o| {
+                let mut open = o.borrow_mut();
+                open.welcome = welcome;
+                open.seen = seen;
+            });
+            render(&ui);
+            if shows {
+                ui.set_show_welcome(true);
+            }
+        })
+        .ok();
+    });
+}
+
+/// Forget the last room's welcome and load that of the room just opened.
+pub fn show_room(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    OPEN.with(|o| {
+        *o.borrow_mut() = Open {
+            room_id: room_id.to_string(),
+            ..Open::default()
+        }
+    });
+    ui.set_show_welcome(false);
+    render(ui);
+    load(ui, client, room_id);
+}
+
+/// Sync brought a new welcome for `room_id`.
+pub fn changed(ui: &AppWindow, client: &ClientHandle, room_id: &str) {
+    if history::current_room().as_deref() == Some(room_id) {
+        load(ui, client, room_id);
+    }
+}
+
+/// Whether the composer of `room_id` waits for its welcome to be accepted.
+pub fn holds_composer(room_id: &str) -> bool {
+    OPEN.with(|o| {
+        let open = o.borrow();
+        open.room_id == room_id
+            && open
+                .welcome
+                .as_ref()
+                .is_some_and(|w| w.holds_composer(open.seen))
+    })
+}
+
+/// Show the open room's welcome again.
+pub fn open(ui: &AppWindow) {
+    if OPEN.with(|o| o.borrow().welcome.is_some()) {
+        ui.set_show_welcome(true);
+    }
+}
+
+/// Close the welcome, accepting it or not, and note that for our other
+/// devices. Having accepted it before, closing it changes nothing.
+fn close(ui: &AppWindow, client: &ClientHandle, seen: Seen) {
+    ui.set_show_welcome(false);
+    let Some((room_id, event_id)) = OPEN.with(|o| {
+        let mut open = o.borrow_mut();
+        if open.seen == Seen::Accepted || open.seen == seen {
+            return None;
+        }
+        open.seen = seen;
+        let event_id = open.welcome.as_ref()?.event_id.clone();
+        Some((open.room_id.clone(), event_id))
+    }) else {
+        return;
+    };
+    render(ui);
+    let reply = client.mark_welcome(&room_id, &event_id, seen);
+    let ui_handle = ui.as_weak();
+    tokio::spawn(async move {
+        let Err(e) = reply.await else {
+            return;
+        };
+        tracing::warn!("Failed to save the welcome message as read: {:#}", e);
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                toast::show(&ui, "Couldn't save that you read the welcome message.");
+            }
+        })
+        .ok();
+    });
+}
+
+pub fn dismiss(ui: &AppWindow, client: &ClientHandle) {
+    close(ui, client, Seen::Dismissed);
+}
+
+/// Accept the welcome: the composer is on again at once.
+pub fn accept(ui: &AppWindow, client: &ClientHandle) {
+    close(ui, client, Seen::Accepted);
+}
+
+/// Forget the welcome on logout.
+pub fn clear(ui: &AppWindow) {
+    OPEN.with(|o| *o.borrow_mut() = Open::default());
+    ui.set_show_welcome(false);
+    render(ui);
+}
@@ -0,0 +1,63 @@
+//! The window's raw events, for what has to be seen before the widgets get
+//! it: dropped files, pasted images, keys that drive the quick switcher,
+//! message search, popups over the composer and message recall, keyboard
+//! shortcuts, Escape for dialogs, and focus, which pauses animations.
+//! Winit allows one hook per window, so it is shared here.
+
+use crate::app_state::ClientHandle;
+use crate::AppWindow;
+use crate::{accessibility, animations, emoji_picker, file_upload, input_history};
+use crate::{mention_completion, message_search, quick_switcher, shortcuts};
+use chat_core::keybindings::Action;
+use slint::winit_030::winit::event::WindowEvent;
+use slint::winit_030::winit::keyboard::ModifiersState;
+use slint::winit_030::{EventResult, WinitWindowAccessor};
+use slint::ComponentHandle;
+
+/// `on_shortcut` runs bound actions as their keys go down (and push-to-talk
+/// as its key comes up), returning whether it used the key.
+pub fn watch(
+    ui: &AppWindow,
+    client: &ClientHandle,
+    on_shortcut: impl Fn(&AppWindow, Action, bool) -> bool + 'static,
+) {
+    let ui_handle = ui.as_weak();
+    let client = client.clone();
+    let mut modifiers = ModifiersState::default();
+    ui.window().on_winit_window_event(move |_, event| {
+        let Some(ui) = ui_handle.upgrade() else {
+            return EventResult::Propagate;
+        };
+        let taken = match event {
+            WindowEvent::ModifiersChanged(changed) => {
+                modifiers = changed.state();
+                false
+            }
+            WindowEvent::Focused(focused) => {
+                animations::window_focused(&ui, *focused);
+                false
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                shortcuts::capture_key(&ui, event, modifiers)
+                    || quick_switcher::key_pressed(&ui, event)
+                    || message_search::key_pressed(&ui, event)
+                    || emoji_picker::key_pressed(&ui, event)
+                    || mention_completion::key_pressed(&ui, event)
+                    || accessibility::key_pressed(&ui, event)
+                    || shortcuts::key_event(&ui, event, modifiers)
+                        .is_some_and(|(action, pressed)| on_shortcut(&ui, action, pressed))
+                    || input_history::key_pressed(&ui, event)
+                    || file_upload::key_pressed(&ui, &client, event, modifiers)
+            }
+            _ => {
+                file_upload::window_event(&ui, &client, event);
+                false
+            }
+        };
+        if taken {
+            EventResult::PreventDefault
+        } else {
+            EventResult::Propagate
+        }
+    });
+}
@@ -0,0 +1,57 @@
+//! The main window's position, size and maximized state, kept for each
+//! account so it opens where that account left it.
+
+use crate::AppWindow;
+use network::config::{ConfigManager, WindowGeometry};
+use slint::{ComponentHandle, PhysicalPosition, PhysicalSize};
+
+/// Put the window back where `user_id` left it, if it was ever saved.
+pub fn restore(ui: &AppWindow, user_id: &str) {
+    let Some(geometry) = ConfigManager::load().account(user_id).window else {
+        return;
+    };
+    let window = ui.window();
+    window.set_maximized(false);
+    window.set_position(PhysicalPosition::new(geometry.x, geometry.y));
+    window.set_size(PhysicalSize::new(geometry.width, geometry.height));
+    window.set_maximized(geometry.maximized);
+}
+
+/// Remember the window for the account shown. A maximized window keeps
+/// the normal geometry saved before; a minimized one is not saved.
+pub fn save(ui: &AppWindow) {
+    let user_id = ui.get_current_user_id();
+    let window = ui.window();
+    if !ui.get_logged_in() || user_id.is_empty() || window.is_minimized() {
+        return;
+    }
+    let mut config = ConfigManager::load();
+    let account = config.accounts.entry(user_id.to_string()).or_default();
+    let geometry = if window.is_maximized() {
+        WindowGeometry {
+            maximized: true,
+            ..account.window.unwrap_or_default()
+        }
+    } else {
+        let position = window.position();
+        let size = window.size();
+        WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            maximized: false,
+        }
+    };
+    // Maximized before it was ever saved: no normal size to go back to.
+    if geometry.width == 0 || geometry.height == 0 {
+        return;
+    }
+    if account.window == Some(geometry) {
+        return;
+    }
+    account.window = Some(geometry);
+    if let Err(e) = ConfigManager::save(&config) {
+        tracing::warn!("Failed to save window state: {:#}", e);
+    }
+}